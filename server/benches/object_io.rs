@@ -0,0 +1,126 @@
+//! Single-object GET and PUT of a 100 MB payload through the storage service, on the
+//! in-memory store so only the service's own buffering is measured.
+//!
+//! Run with `cargo bench --bench object_io`. Besides criterion's timings, the bytes allocated
+//! by one operation are printed for each case; a copy of the payload shows up as another
+//! 100 MB.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::error::PayloadError;
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use flatbuffers::FlatBufferBuilder;
+use futures::stream;
+use warp_drive::service::storage_service::{StorageMode, StorageService};
+use warp_drive::service::user_context::UserContext;
+use warp_drive::storage::mock_store::MockBinaryStore;
+use warp_drive::util::flatbuffer_store_generated::store::{FileData, FileDataArgs, FileDataList, FileDataListArgs};
+use warp_drive::util::payload::read_body_sized;
+
+const OBJECT_BYTES: usize = 100 * 1024 * 1024;
+
+/// Size of the body chunks a client upload arrives in
+const BODY_CHUNK: usize = 64 * 1024;
+
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Print the bytes `op` allocates, in MB.
+fn report_allocations<T>(name: &str, op: impl FnOnce() -> T) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let out = op();
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+    drop(out);
+    println!("{}: {:.1} MB allocated", name, allocated as f64 / (1024.0 * 1024.0));
+}
+
+fn native_payload(data: &[u8]) -> Vec<u8> {
+    let mut builder = FlatBufferBuilder::new();
+    let data = builder.create_vector(data);
+    let file = FileData::create(&mut builder, &FileDataArgs { data: Some(data) });
+    let files = builder.create_vector(&[file]);
+    let list = FileDataList::create(&mut builder, &FileDataListArgs { files: Some(files) });
+    builder.finish(list, None);
+    builder.finished_data().to_vec()
+}
+
+/// `body` as the chunk stream of an upload
+fn body_stream(body: &Bytes) -> impl futures::Stream<Item = Result<Bytes, PayloadError>> + Unpin {
+    let chunks: Vec<_> = (0..body.len()).step_by(BODY_CHUNK)
+        .map(|start| Ok(body.slice(start..(start + BODY_CHUNK).min(body.len()))))
+        .collect();
+    stream::iter(chunks)
+}
+
+fn setup() -> (StorageService, Arc<MockBinaryStore>, UserContext) {
+    // Reads look up freed ranges in the metadata database
+    let root = std::env::temp_dir().join(format!("warpdrive-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::env::set_var("DB_FILE", root.join("metadata.sqlite"));
+    let store = Arc::new(MockBinaryStore::new());
+    let service = StorageService::with_store(store.clone());
+    (service, store, UserContext::with_bucket("bench_user".to_string(), "bench".to_string()))
+}
+
+fn bench_get(c: &mut Criterion) {
+    let (service, _store, context) = setup();
+    let data = vec![0x5a; OBJECT_BYTES];
+    let native = service.write_object(&context, &native_payload(&data), StorageMode::Native).unwrap();
+    let raw = service.write_object(&context, &data, StorageMode::RAW).unwrap();
+    drop(data);
+
+    report_allocations("get/native", || service.read_object(&context, &native, StorageMode::Native).unwrap());
+    report_allocations("get/raw", || service.read_object(&context, &raw, StorageMode::RAW).unwrap());
+
+    let mut group = c.benchmark_group("get");
+    group.sample_size(10).measurement_time(Duration::from_secs(10)).throughput(Throughput::Bytes(OBJECT_BYTES as u64));
+    group.bench_function("native_100mb", |b| b.iter(|| service.read_object(&context, &native, StorageMode::Native).unwrap()));
+    group.bench_function("raw_100mb", |b| b.iter(|| service.read_object(&context, &raw, StorageMode::RAW).unwrap()));
+    group.finish();
+}
+
+fn bench_put(c: &mut Criterion) {
+    let (service, store, context) = setup();
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+    let native = Bytes::from(native_payload(&vec![0x5a; OBJECT_BYTES]));
+    let put = |body: &Bytes, mode: StorageMode| {
+        let body = runtime.block_on(read_body_sized(body_stream(body), body.len(), usize::MAX, Duration::from_secs(30))).unwrap();
+        service.write_object(&context, &body, mode).unwrap()
+    };
+
+    report_allocations("put/native", || put(&native, StorageMode::Native));
+    store.clear();
+
+    let mut group = c.benchmark_group("put");
+    group.sample_size(10).measurement_time(Duration::from_secs(10)).throughput(Throughput::Bytes(OBJECT_BYTES as u64));
+    // The store is emptied between runs so memory stays at one object
+    group.bench_function("native_100mb", |b| b.iter_batched(|| store.clear(), |_| put(&native, StorageMode::Native), BatchSize::PerIteration));
+    group.finish();
+}
+
+criterion_group!(benches, bench_get, bench_put);
+criterion_main!(benches);
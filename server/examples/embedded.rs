@@ -0,0 +1,62 @@
+//! warpdrive embedded in another actix application
+//!
+//! The application serves its own routes next to every warpdrive route, with object metadata
+//! and chunks kept in memory by the mock backends and no background workers:
+//!
+//! ```text
+//! cargo run --example embedded
+//! curl http://127.0.0.1:8090/edge/status
+//! curl -X POST -H 'User: edge' --data-binary @payload.fb http://127.0.0.1:8090/v2/put/hello
+//! ```
+//!
+//! Bucket settings and other state outside the metadata store still go to the SQLite
+//! database at `DB_FILE`.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::{web, App, HttpResponse};
+use std::io;
+use std::sync::Arc;
+
+use warp_drive::app_state::{routes, AppState, AppStateBuilder};
+use warp_drive::config::HttpConfig;
+use warp_drive::metadata::mock_store::MockMetadataStore;
+use warp_drive::server;
+use warp_drive::startup::StartupError;
+use warp_drive::storage::mock_store::MockBinaryStore;
+
+/// warpdrive on in-memory backends, listening on localhost only
+pub fn state() -> Result<AppState, StartupError> {
+    AppStateBuilder::new()
+        .metadata_store(Arc::new(MockMetadataStore::new()))
+        .storage(Arc::new(MockBinaryStore::new()))
+        .http_config(HttpConfig { host: "127.0.0.1".to_string(), port: 8090, ..HttpConfig::default() })
+        .background_workers(false)
+        .build()
+}
+
+/// The application's own routes, then warpdrive's
+pub fn app() -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .route("/edge/status", web::get().to(status))
+        .configure(routes)
+}
+
+async fn status() -> HttpResponse {
+    HttpResponse::Ok().body("edge node up")
+}
+
+#[actix_web::main]
+async fn main() -> io::Result<()> {
+    let state = state().map_err(io::Error::other)?;
+    println!("Serving on {}:{}", state.http.host, state.http.port);
+    server::http_server(app, &state.http)?.await
+}
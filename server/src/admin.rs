@@ -0,0 +1,949 @@
+//admin.rs
+//! Operator-only endpoints under /admin.
+//!
+//! Requests must carry `Authorization: Bearer <token>`: the configured
+//! `WARPDRIVE_ADMIN_SECRET_KEY`, which may do anything, or an admin token, which may only use
+//! the endpoints of its scopes and, when restricted to a user, only on that user's resources
+//! (see [`crate::service::admin_tokens`]).
+
+use actix_web::{web, HttpRequest, HttpResponse, Error};
+use bytes::Bytes;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use log::{info, warn};
+use std::time::Duration;
+
+use crate::logging;
+use crate::metrics;
+use crate::service::access_tracking;
+use crate::metadata::config::ShadowConfig;
+use crate::metadata::shadow_store;
+use crate::metadata::sqlite_store::{BucketLimits, JobRecord, SQLiteMetadataStore};
+use crate::service::admin_tokens::{self, AdminGrant, Scope};
+use crate::service::bucket_keys::{self, EncryptionConfig};
+use crate::service::change_log;
+use crate::service::compaction::{self, CompactionConfig};
+use crate::service::db_maintenance::{run_maintenance, DbMaintenanceConfig};
+use crate::service::deletion_worker::DeletionWorker;
+use crate::service::fsck::check_bucket;
+use crate::service::gc::collect_orphans;
+use crate::service::inflight;
+use crate::service::inventory::{default_name, run_inventory, InventoryFormat, InventoryTarget};
+use crate::service::jobs::{self, JobState};
+use crate::service::checksums::{self, ChecksumBackfillConfig};
+use crate::service::stats;
+use crate::service::maintenance::{self, MaintenanceMode};
+use crate::service::metadata_service::MetadataService;
+use crate::service::native_auth;
+use crate::service::error::native_error;
+use crate::service::repair_service;
+use crate::service::tiering::{check_migration, migrate_bucket};
+use crate::service::user_health::check_user;
+use crate::storage::instrumented_store;
+use crate::storage::replicated_store;
+use crate::storage::tiered_store::{self, Tier};
+
+fn dry_run_param(req: &HttpRequest) -> bool {
+    flag_param(req, "dry_run")
+}
+
+fn flag_param(req: &HttpRequest, name: &str) -> bool {
+    web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|q| q.get(name).cloned())
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(false)
+}
+
+#[actix_web::post("/admin/gc/{user}/{bucket}")]
+async fn gc(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Gc, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    let dry_run = dry_run_param(&req);
+    info!("orphan GC requested for user: {}, bucket: {}, dry_run: {}", user, bucket, dry_run);
+    let (job_user, job_bucket) = (user.clone(), bucket.clone());
+    let job = jobs::enqueue("gc", &user, &bucket, serde_json::json!({ "dry_run": dry_run }), move |job| async move {
+        collect_orphans(&job_user, &job_bucket, dry_run, &job).await
+    })?;
+    Ok(accepted(job))
+}
+
+/// Check `user`/`bucket` against storage; 200 with the report whether or not it is clean.
+#[actix_web::get("/admin/fsck/{user}/{bucket}")]
+async fn fsck(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Gc, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    if !MetadataService::new(&user)?.bucket_exists(&bucket)? {
+        return Ok(no_such_bucket(&user, &bucket));
+    }
+    info!("fsck requested for user: {}, bucket: {}", user, bucket);
+    let report = web::block(move || check_bucket(&user, &bucket).map_err(|e| e.to_string()))
+        .await?
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Free `user`/`bucket`'s queued deletions and unlink emptied segments, as a job.
+#[actix_web::post("/admin/compact/{user}/{bucket}")]
+async fn compact(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Gc, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    info!("compaction requested for user: {}, bucket: {}", user, bucket);
+    let (job_user, job_bucket) = (user.clone(), bucket.clone());
+    let job = jobs::enqueue("compact", &user, &bucket, serde_json::json!({}), move |job| async move {
+        DeletionWorker::new().compact_bucket(&job_user, &job_bucket, &job).await
+    })?;
+    Ok(accepted(job))
+}
+
+/// Fill in the chunk checksums missing from `?user=&bucket=`, as a job that continues where an
+/// interrupted one stopped; `?verify_only=true` only reports what it would change.
+#[actix_web::post("/admin/checksums/backfill")]
+async fn backfill_checksums(
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let (Some(user), Some(bucket)) = (query.get("user").cloned(), query.get("bucket").cloned()) else {
+        return Err(ErrorBadRequest("Missing user or bucket query parameter"));
+    };
+    admin.require(Scope::Gc, Some(&user))?;
+    if !MetadataService::new(&user)?.bucket_exists(&bucket)? {
+        return Ok(no_such_bucket(&user, &bucket));
+    }
+    let verify_only = flag_param(&req, "verify_only");
+    info!("checksum backfill requested for user: {}, bucket: {}, verify_only: {}", user, bucket, verify_only);
+    let (job_user, job_bucket) = (user.clone(), bucket.clone());
+    let config = ChecksumBackfillConfig::from_env();
+    let job = jobs::enqueue("checksum_backfill", &user, &bucket, serde_json::json!({ "verify_only": verify_only }), move |job| async move {
+        checksums::backfill(&job_user, &job_bucket, verify_only, &config, &job).await
+    })?;
+    Ok(accepted(job))
+}
+
+/// What compacting `user`/`bucket` would do, without touching it; `?threshold=` (0 to 1)
+/// overrides `COMPACTION_FREE_RATIO`.
+#[actix_web::get("/admin/compaction/plan/{user}/{bucket}")]
+async fn compaction_plan(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Gc, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let threshold = match query.get("threshold") {
+        Some(v) => v.parse::<f64>().ok().filter(|t| (0.0..=1.0).contains(t))
+            .ok_or_else(|| ErrorBadRequest("Invalid threshold query parameter: expected a ratio from 0 to 1"))?,
+        None => CompactionConfig::from_env().free_ratio,
+    };
+    if !MetadataService::new(&user)?.bucket_exists(&bucket)? {
+        return Ok(no_such_bucket(&user, &bucket));
+    }
+    let plan = web::block(move || compaction::plan_bucket(&user, &bucket, threshold).map_err(|e| e.to_string()))
+        .await?
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(plan))
+}
+
+/// Deep check of `user`'s data path through `?bucket=`: 200 with each check's result and
+/// timing when all pass, 503 with the same report when one fails.
+#[actix_web::get("/admin/health/user/{user}")]
+async fn user_health(
+    path: web::Path<String>,
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let user = path.into_inner();
+    admin.require(Scope::Stats, Some(&user))?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let bucket = match query.get("bucket") {
+        Some(bucket) if !bucket.is_empty() => bucket.clone(),
+        _ => return Err(ErrorBadRequest("Missing bucket query parameter")),
+    };
+    let (user_name, bucket_name) = (user.clone(), bucket.clone());
+    Ok(match web::block(move || check_user(&user_name, &bucket_name)).await? {
+        Some(report) if report.healthy => HttpResponse::Ok().json(report),
+        Some(report) => HttpResponse::ServiceUnavailable().json(report),
+        None => no_such_bucket(&user, &bucket),
+    })
+}
+
+/// 202 with the queued job; poll its Location for progress and the operation's report
+fn accepted(job: JobRecord) -> HttpResponse {
+    HttpResponse::Accepted()
+        .insert_header(("Location", format!("/admin/jobs/{}", job.id)))
+        .json(job)
+}
+
+fn no_such_job(id: i64) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "NoSuchJob",
+        "message": "The specified job does not exist",
+        "id": id,
+    }))
+}
+
+/// Newest jobs first; `?state=running` filters, `?limit=N` (default 100) caps the list.
+#[actix_web::get("/admin/jobs")]
+async fn list_jobs(admin: AdminGrant, req: HttpRequest) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Stats, None)?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let state = match query.get("state") {
+        Some(v) => Some(v.parse::<JobState>().map_err(ErrorBadRequest)?),
+        None => None,
+    };
+    let limit = match query.get("limit") {
+        Some(v) => v.parse::<usize>().map_err(|_| ErrorBadRequest("Invalid limit query parameter"))?,
+        None => jobs::DEFAULT_LIST_LIMIT,
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "jobs": jobs::list(state, limit)? })))
+}
+
+#[actix_web::get("/admin/jobs/{id}")]
+async fn get_job(path: web::Path<i64>, admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require_scope(Scope::Stats)?;
+    let id = path.into_inner();
+    Ok(match jobs::get(id)? {
+        Some(job) => {
+            admin.require(Scope::Stats, Some(&job.user))?;
+            HttpResponse::Ok().json(job)
+        }
+        None => no_such_job(id),
+    })
+}
+
+/// Ask a queued or running job to stop; 409 once it has finished.
+#[actix_web::post("/admin/jobs/{id}/cancel")]
+async fn cancel_job(path: web::Path<i64>, admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require_scope(Scope::Gc)?;
+    let id = path.into_inner();
+    if let Some(job) = jobs::get(id)? {
+        admin.require(Scope::Gc, Some(&job.user))?;
+    }
+    Ok(match jobs::cancel(id)? {
+        Some(job) => HttpResponse::Accepted().json(job),
+        None => no_such_job(id),
+    })
+}
+
+#[actix_web::get("/admin/stats")]
+async fn server_stats(admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Stats, None)?;
+    let stats = web::block(|| stats::collect().map_err(|e| e.to_string()))
+        .await?
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Storage operations and bytes by operation and traffic since the process started, write
+/// amplification and the busiest users; `?top=N` (default `IO_STATS_TOP_USERS`) users.
+#[actix_web::get("/admin/stats/io")]
+async fn io_stats(admin: AdminGrant, req: HttpRequest) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Stats, None)?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let top = match query.get("top") {
+        Some(v) => v.parse::<usize>().map_err(|_| ErrorBadRequest("Invalid top query parameter"))?,
+        None => instrumented_store::top_users_from_env(),
+    };
+    Ok(HttpResponse::Ok().json(instrumented_store::snapshot(top)))
+}
+
+/// Requests still being handled, oldest first; `?older_than_ms=N` (default 0) lists only
+/// those running at least that long.
+#[actix_web::get("/admin/inflight")]
+async fn list_inflight(admin: AdminGrant, req: HttpRequest) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Stats, None)?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let older_than = match query.get("older_than_ms") {
+        Some(v) => v.parse::<u64>().map_err(|_| ErrorBadRequest("Invalid older_than_ms query parameter"))?,
+        None => 0,
+    };
+    let requests = inflight::list(Duration::from_millis(older_than), req.resource_map());
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "requests": requests })))
+}
+
+/// Keys read most since they entered the access tracker's memory; `?limit=N` (default 100).
+/// Empty unless `TRACK_ACCESS=true`.
+#[actix_web::get("/admin/access/top")]
+async fn access_top(admin: AdminGrant, req: HttpRequest) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Stats, None)?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let limit = match query.get("limit") {
+        Some(v) => v.parse::<usize>().map_err(|_| ErrorBadRequest("Invalid limit query parameter"))?,
+        None => access_tracking::DEFAULT_TOP_LIMIT,
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "enabled": access_tracking::enabled(),
+        "tracked_keys": access_tracking::tracked_keys(),
+        "keys": access_tracking::top(limit),
+    })))
+}
+
+#[actix_web::get("/admin/replication/status")]
+async fn replication_status(admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Stats, None)?;
+    Ok(HttpResponse::Ok().json(replicated_store::status()))
+}
+
+/// Divergences the metadata shadow backend has recorded: counts by operation and the most
+/// recent ones; `?limit=N` (default 100) caps the list.
+#[actix_web::get("/admin/shadow/report")]
+async fn shadow_report(admin: AdminGrant, req: HttpRequest) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Stats, None)?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let limit = match query.get("limit") {
+        Some(v) => v.parse::<usize>().map_err(|_| ErrorBadRequest("Invalid limit query parameter"))?,
+        None => shadow_store::DEFAULT_REPORT_LIMIT,
+    };
+    let db = SQLiteMetadataStore::new();
+    let by_op = db.shadow_divergence_counts()?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "backend": ShadowConfig::from_env().backend.map(|backend| backend.as_str()),
+        "total": by_op.iter().map(|(_, count)| count).sum::<u64>(),
+        "by_op": by_op.into_iter().collect::<std::collections::BTreeMap<_, _>>(),
+        "dropped": metrics::counter_value("warpdrive_shadow_dropped_total", &[]),
+        "recent": db.recent_shadow_divergences(limit)?,
+    })))
+}
+
+/// Run a database maintenance pass (vacuum when over the threshold, then WAL checkpoint) now.
+#[actix_web::post("/admin/db/checkpoint")]
+async fn db_checkpoint(admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, None)?;
+    let config = DbMaintenanceConfig::from_env();
+    let report = web::block(move || run_maintenance(&config).map_err(|e| e.to_string()))
+        .await?
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[actix_web::get("/admin/maintenance")]
+async fn get_maintenance(admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, None)?;
+    let state = maintenance::current();
+    let effective = state.effective_mode(chrono::Utc::now());
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "mode": effective,
+        "changed_at": state.changed_at,
+        "changed_by": state.changed_by,
+        "until": state.until.filter(|_| effective == MaintenanceMode::ReadOnly),
+    })))
+}
+
+/// Switch the server to `?mode=read_only|normal`; `?duration_secs=N` bounds a read-only period.
+#[actix_web::post("/admin/maintenance")]
+async fn set_maintenance(admin: AdminGrant, req: HttpRequest) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, None)?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let mode = query.get("mode")
+        .ok_or_else(|| ErrorBadRequest("Missing mode query parameter"))?
+        .parse::<MaintenanceMode>()
+        .map_err(ErrorBadRequest)?;
+    let until = match query.get("duration_secs") {
+        Some(v) => {
+            let secs = v.parse::<u32>().ok().filter(|secs| *secs > 0)
+                .ok_or_else(|| ErrorBadRequest("Invalid duration_secs query parameter"))?;
+            Some(chrono::Utc::now() + chrono::Duration::seconds(secs.into()))
+        }
+        None => None,
+    };
+    let request_id = logging::request_id(&req);
+    let previous = maintenance::current();
+    let state = maintenance::set(mode, until, &request_id)?;
+    change_log::record(&admin.actor(), "maintenance", "server",
+        Some(serde_json::json!({ "mode": previous.effective_mode(chrono::Utc::now()), "until": previous.until })),
+        Some(serde_json::json!({ "mode": state.mode, "until": state.until })));
+    info!("maintenance mode set to {} by request {} (until {:?})", mode.as_str(), request_id, state.until);
+    Ok(HttpResponse::Ok().insert_header(("X-Request-Id", request_id)).json(state))
+}
+
+/// Schema migrations and configuration changes, oldest first. `?since=` (RFC 3339) skips
+/// earlier ones, `?limit=N` (default 1000) caps the list.
+#[actix_web::get("/admin/changes")]
+async fn list_changes(admin: AdminGrant, req: HttpRequest) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, None)?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let since = match query.get("since") {
+        Some(v) => Some(chrono::DateTime::parse_from_rfc3339(v)
+            .map_err(|_| ErrorBadRequest("Invalid since query parameter (expected RFC 3339)"))?
+            .with_timezone(&chrono::Utc)),
+        None => None,
+    };
+    let limit = match query.get("limit") {
+        Some(v) => v.parse::<usize>().map_err(|_| ErrorBadRequest("Invalid limit query parameter"))?,
+        None => change_log::DEFAULT_LIST_LIMIT,
+    };
+    let changes = web::block(move || change_log::list(since, limit).map_err(|e| e.to_string()))
+        .await?
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(changes))
+}
+
+/// The global log level
+#[actix_web::get("/admin/log_level")]
+async fn get_log_level(admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, None)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "level": logging::level().as_str().to_lowercase() })))
+}
+
+/// Change the global log level to `?level=error|warn|info|debug|trace|off` until restart.
+#[actix_web::post("/admin/log_level")]
+async fn set_log_level(admin: AdminGrant, req: HttpRequest) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, None)?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let level = query.get("level")
+        .ok_or_else(|| ErrorBadRequest("Missing level query parameter"))?
+        .parse::<log::LevelFilter>()
+        .map_err(|_| ErrorBadRequest("Invalid level (expected off, error, warn, info, debug or trace)"))?;
+    let previous = logging::level();
+    logging::set_level(level);
+    change_log::record(&admin.actor(), "log_level", "server",
+        Some(serde_json::json!(previous.as_str().to_lowercase())),
+        Some(serde_json::json!(level.as_str().to_lowercase())));
+    warn!("log level changed from {} to {}", previous, level);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "level": level.as_str().to_lowercase() })))
+}
+
+/// Log `{user}`'s requests at debug and capture them for `?ttl=` seconds (default 300).
+#[actix_web::post("/admin/trace_user/{user}")]
+async fn trace_user(
+    user: web::Path<String>,
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, Some(&user))?;
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let ttl = match query.get("ttl") {
+        Some(v) => v.parse::<u64>().ok()
+            .map(std::time::Duration::from_secs)
+            .filter(|ttl| !ttl.is_zero() && *ttl <= logging::MAX_TRACE_TTL)
+            .ok_or_else(|| ErrorBadRequest(format!(
+                "Invalid ttl (1 to {} seconds)", logging::MAX_TRACE_TTL.as_secs())))?,
+        None => std::time::Duration::from_secs(300),
+    };
+    logging::start_trace(&user, ttl);
+    info!("tracing requests of user {} for {}s", user, ttl.as_secs());
+    Ok(HttpResponse::Ok().json(logging::trace_snapshot(&user)))
+}
+
+/// Stop tracing `{user}` and discard the captured lines.
+#[actix_web::delete("/admin/trace_user/{user}")]
+async fn untrace_user(
+    user: web::Path<String>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, Some(&user))?;
+    if !logging::stop_trace(&user) {
+        return Ok(no_trace(&user));
+    }
+    info!("stopped tracing requests of user {}", user);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Lines captured for `{user}`, oldest first; kept after the trace lapses.
+#[actix_web::get("/admin/trace_user/{user}/logs")]
+async fn trace_user_logs(
+    user: web::Path<String>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, Some(&user))?;
+    match logging::trace_snapshot(&user) {
+        Some(snapshot) => Ok(HttpResponse::Ok().json(snapshot)),
+        None => Ok(no_trace(&user)),
+    }
+}
+
+fn no_trace(user: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "NoSuchTrace",
+        "message": "This user has not been traced",
+        "user": user,
+    }))
+}
+
+/// Move a bucket to `?to=hot|cold` as a job; `?max_keys=N` moves at most N keys per job.
+#[actix_web::post("/admin/tier/{user}/{bucket}")]
+async fn tier(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Gc, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let to = query.get("to")
+        .ok_or_else(|| ErrorBadRequest("Missing to query parameter"))?
+        .parse::<Tier>()
+        .map_err(ErrorBadRequest)?;
+    let max_keys = match query.get("max_keys") {
+        Some(v) => Some(v.parse::<usize>().map_err(|_| ErrorBadRequest("Invalid max_keys query parameter"))?),
+        None => None,
+    };
+    check_migration(&user, &bucket, to)?;
+    let from = tiered_store::placement(&user, &bucket)?.tier;
+    info!("tier migration requested for user: {}, bucket: {}, to: {}", user, bucket, to.as_str());
+    let (job_user, job_bucket) = (user.clone(), bucket.clone());
+    let params = serde_json::json!({ "to": to.as_str(), "max_keys": max_keys });
+    let job = jobs::enqueue("tier", &user, &bucket, params, move |job| async move {
+        migrate_bucket(&job_user, &job_bucket, to, max_keys, &job).await
+    })?;
+    change_log::record(&admin.actor(), "tier", &format!("{}/{}", user, bucket),
+        Some(serde_json::json!(from.as_str())),
+        Some(serde_json::json!(to.as_str())));
+    Ok(accepted(job))
+}
+
+/// Partial update of a bucket's access flags; omitted flags keep their value
+#[derive(Debug, serde::Deserialize)]
+struct BucketAccessUpdate {
+    public_read: Option<bool>,
+    read_only: Option<bool>,
+}
+
+fn no_such_bucket(user: &str, bucket: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": "NoSuchBucket",
+        "message": "The specified bucket does not exist",
+        "user": user,
+        "bucket": bucket,
+    }))
+}
+
+#[actix_web::get("/admin/buckets/{user}/{bucket}/access")]
+async fn get_bucket_access(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Quota, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    match MetadataService::new(&user)?.get_bucket_access(&bucket)? {
+        Some(access) => Ok(HttpResponse::Ok().json(access)),
+        None => Ok(no_such_bucket(&user, &bucket)),
+    }
+}
+
+#[actix_web::put("/admin/buckets/{user}/{bucket}/access")]
+async fn set_bucket_access(
+    path: web::Path<(String, String)>,
+    update: web::Json<BucketAccessUpdate>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Quota, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    let db = MetadataService::new(&user)?;
+    let Some(mut access) = db.get_bucket_access(&bucket)? else {
+        return Ok(no_such_bucket(&user, &bucket));
+    };
+    let previous = access;
+    access.public_read = update.public_read.unwrap_or(access.public_read);
+    access.read_only = update.read_only.unwrap_or(access.read_only);
+    if !db.set_bucket_access(&bucket, &access)? {
+        return Ok(no_such_bucket(&user, &bucket));
+    }
+    change_log::record(&admin.actor(), "bucket_access", &format!("{}/{}", user, bucket),
+        serde_json::to_value(previous).ok(), serde_json::to_value(access).ok());
+    info!("bucket access of user: {}, bucket: {} set to public_read: {}, read_only: {}",
+          user, bucket, access.public_read, access.read_only);
+    Ok(HttpResponse::Ok().json(access))
+}
+
+/// Partial update of a bucket's quotas; omitted limits keep their value, null removes one
+#[derive(Debug, serde::Deserialize)]
+struct BucketLimitsUpdate {
+    #[serde(default, deserialize_with = "some_or_null")]
+    max_objects: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "some_or_null")]
+    max_bytes: Option<Option<u64>>,
+}
+
+/// A present field, even null, as `Some`; `#[serde(default)]` leaves absent ones `None`
+fn some_or_null<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Option<u64>>, D::Error> {
+    <Option<u64> as serde::Deserialize>::deserialize(deserializer).map(Some)
+}
+
+/// Limits of a bucket with its current usage
+fn bucket_limits_json(db: &MetadataService, bucket: &str, limits: &BucketLimits) -> Result<serde_json::Value, Error> {
+    let (objects, bytes) = db.bucket_object_stats(bucket)?;
+    Ok(serde_json::json!({
+        "max_objects": limits.max_objects,
+        "max_bytes": limits.max_bytes,
+        "objects": objects,
+        "bytes": bytes,
+    }))
+}
+
+#[actix_web::get("/admin/buckets/{user}/{bucket}/limits")]
+async fn get_bucket_limits(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Quota, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    let db = MetadataService::new(&user)?;
+    match db.get_bucket_limits(&bucket)? {
+        Some(limits) => Ok(HttpResponse::Ok().json(bucket_limits_json(&db, &bucket, &limits)?)),
+        None => Ok(no_such_bucket(&user, &bucket)),
+    }
+}
+
+#[actix_web::put("/admin/buckets/{user}/{bucket}/limits")]
+async fn set_bucket_limits(
+    path: web::Path<(String, String)>,
+    update: web::Json<BucketLimitsUpdate>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Quota, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    let db = MetadataService::new(&user)?;
+    let Some(mut limits) = db.get_bucket_limits(&bucket)? else {
+        return Ok(no_such_bucket(&user, &bucket));
+    };
+    let previous = limits;
+    limits.max_objects = update.max_objects.unwrap_or(limits.max_objects);
+    limits.max_bytes = update.max_bytes.unwrap_or(limits.max_bytes);
+    if !db.set_bucket_limits(&bucket, &limits)? {
+        return Ok(no_such_bucket(&user, &bucket));
+    }
+    change_log::record(&admin.actor(), "bucket_limits", &format!("{}/{}", user, bucket),
+        serde_json::to_value(previous).ok(), serde_json::to_value(limits).ok());
+    info!("bucket limits of user: {}, bucket: {} set to max_objects: {:?}, max_bytes: {:?}",
+          user, bucket, limits.max_objects, limits.max_bytes);
+    Ok(HttpResponse::Ok().json(bucket_limits_json(&db, &bucket, &limits)?))
+}
+
+/// Last-access lifecycle rule of a bucket; null is no rule
+#[derive(Debug, serde::Deserialize)]
+struct BucketLifecycleUpdate {
+    expire_unread_days: Option<u32>,
+}
+
+#[actix_web::get("/admin/buckets/{user}/{bucket}/lifecycle")]
+async fn get_bucket_lifecycle(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Quota, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    match access_tracking::expire_unread_days(&user, &bucket)? {
+        Some(days) => Ok(HttpResponse::Ok().json(serde_json::json!({ "expire_unread_days": days }))),
+        None => Ok(no_such_bucket(&user, &bucket)),
+    }
+}
+
+/// Expire the bucket's objects once neither written nor read for `expire_unread_days` days.
+/// Setting a rule needs `TRACK_ACCESS=true`; clearing one does not.
+#[actix_web::put("/admin/buckets/{user}/{bucket}/lifecycle")]
+async fn set_bucket_lifecycle(
+    path: web::Path<(String, String)>,
+    update: web::Json<BucketLifecycleUpdate>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Quota, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    let days = update.expire_unread_days;
+    if days == Some(0) {
+        return Err(ErrorBadRequest("expire_unread_days must be at least 1"));
+    }
+    if days.is_some() && !access_tracking::enabled() {
+        return Err(ErrorBadRequest("Last-access rules need read tracking: set TRACK_ACCESS=true"));
+    }
+    let Some(previous) = access_tracking::expire_unread_days(&user, &bucket)? else {
+        return Ok(no_such_bucket(&user, &bucket));
+    };
+    if !access_tracking::set_expire_unread_days(&user, &bucket, days)? {
+        return Ok(no_such_bucket(&user, &bucket));
+    }
+    change_log::record(&admin.actor(), "bucket_lifecycle", &format!("{}/{}", user, bucket),
+        Some(serde_json::json!({ "expire_unread_days": previous })), Some(serde_json::json!({ "expire_unread_days": days })));
+    info!("lifecycle of user: {}, bucket: {} set to expire_unread_days: {:?}", user, bucket, days);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "expire_unread_days": days })))
+}
+
+/// Issue a native API key for `user`. The key is only returned in this response.
+#[actix_web::post("/admin/users/{user}/keys")]
+async fn create_api_key(
+    user: web::Path<String>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Keys, Some(&user))?;
+    let user = user.into_inner();
+    let issued = native_auth::issue_key(&user)?;
+    info!("issued API key {} for user: {}", issued.key_id, user);
+    Ok(HttpResponse::Created().json(issued))
+}
+
+#[actix_web::delete("/admin/users/{user}/keys/{key_id}")]
+async fn revoke_api_key(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Keys, Some(&path.0))?;
+    let (user, key_id) = path.into_inner();
+    if !native_auth::revoke_key(&user, &key_id)? {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "NoSuchKey",
+            "message": "No active API key with this id for the user",
+            "key_id": key_id,
+        })));
+    }
+    info!("revoked API key {} of user: {}", key_id, user);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "user": user, "key_id": key_id, "revoked": true })))
+}
+
+fn encryption_off() -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": "EncryptionNotConfigured",
+        "message": "Set ENCRYPTION_MASTER_KEY to manage bucket keys",
+    }))
+}
+
+/// Add a key version to `?user=&bucket=`; later writes of the bucket are encrypted with it
+/// while earlier chunks keep theirs.
+#[actix_web::post("/admin/keys/rotate")]
+async fn rotate_bucket_key(
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let (Some(user), Some(bucket)) = (query.get("user"), query.get("bucket")) else {
+        return Err(ErrorBadRequest("Missing user or bucket query parameter"));
+    };
+    admin.require(Scope::Keys, Some(user))?;
+    if EncryptionConfig::from_env().master_key.is_none() {
+        return Ok(encryption_off());
+    }
+    let version = bucket_keys::rotate(user, bucket)?;
+    change_log::record(&admin.actor(), "rotate_bucket_key", &format!("{}/{}", user, bucket),
+        None, Some(serde_json::json!({ "version": version })));
+    info!("rotated key of user: {}, bucket: {} to version {}", user, bucket, version);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "user": user, "bucket": bucket, "version": version })))
+}
+
+/// Wrap every bucket key with the current master key after ENCRYPTION_MASTER_KEY changed;
+/// the old key must be in ENCRYPTION_PREVIOUS_MASTER_KEY. 409 lists the keys left behind.
+#[actix_web::post("/admin/keys/rewrap")]
+async fn rewrap_bucket_keys(admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Keys, None)?;
+    if EncryptionConfig::from_env().master_key.is_none() {
+        return Ok(encryption_off());
+    }
+    let report = bucket_keys::rewrap()?;
+    change_log::record(&admin.actor(), "rewrap_bucket_keys", "server",
+        None, Some(serde_json::json!({ "master_key_id": report.master_key_id, "rewrapped": report.rewrapped })));
+    if !report.failed.is_empty() {
+        warn!("rewrap left {} bucket keys under another master key", report.failed.len());
+        return Ok(HttpResponse::Conflict().json(report));
+    }
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct NewAdminToken {
+    #[serde(default)]
+    description: String,
+    scopes: Vec<String>,
+    user: Option<String>,
+}
+
+/// Issue an admin token from `{"scopes": [...], "user": ..., "description": ...}`. A token
+/// can only hand out scopes it holds, and a user-restricted one only for its own user. The
+/// token is only returned in this response.
+#[actix_web::post("/admin/tokens")]
+async fn create_admin_token(
+    admin: AdminGrant,
+    body: web::Json<NewAdminToken>,
+) -> Result<HttpResponse, Error> {
+    let body = body.into_inner();
+    admin.require(Scope::Tokens, body.user.as_deref())?;
+    let scopes = body.scopes.iter()
+        .map(|s| s.parse::<Scope>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ErrorBadRequest)?;
+    for scope in &scopes {
+        admin.require_scope(*scope)?;
+    }
+    let issued = admin_tokens::issue_token(&body.description, &scopes, body.user.as_deref())?;
+    info!("issued admin token {} with scopes {:?} for user {:?} by {:?}",
+          issued.token_id, body.scopes, issued.user, admin.token_id);
+    Ok(HttpResponse::Created().json(issued))
+}
+
+/// Every admin token, or those of the caller's user for a user-restricted token
+#[actix_web::get("/admin/tokens")]
+async fn list_admin_tokens(admin: AdminGrant) -> Result<HttpResponse, Error> {
+    admin.require_scope(Scope::Tokens)?;
+    let tokens: Vec<_> = admin_tokens::list_tokens()?
+        .into_iter()
+        .filter(|token| admin.user.is_none() || token.user == admin.user)
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "tokens": tokens })))
+}
+
+#[actix_web::delete("/admin/tokens/{token_id}")]
+async fn disable_admin_token(
+    token_id: web::Path<String>,
+    admin: AdminGrant,
+) -> Result<HttpResponse, Error> {
+    admin.require_scope(Scope::Tokens)?;
+    let token_id = token_id.into_inner();
+    let token = admin_tokens::list_tokens()?.into_iter().find(|token| token.token_id == token_id && !token.disabled);
+    let Some(token) = token else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "NoSuchToken",
+            "message": "No enabled admin token with this id",
+            "token_id": token_id,
+        })));
+    };
+    admin.require(Scope::Tokens, token.user.as_deref())?;
+    admin_tokens::disable_token(&token_id)?;
+    info!("disabled admin token {} by {:?}", token_id, admin.token_id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "token_id": token_id, "disabled": true })))
+}
+
+/// Rows written per chunk of a bucket dump
+const DUMP_PAGE_ROWS: usize = 100;
+
+fn dump_enabled() -> bool {
+    std::env::var("ADMIN_DUMP_ENABLED")
+        .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+        .unwrap_or(false)
+}
+
+/// Raw metadata rows for debugging, answered only when `ADMIN_DUMP_ENABLED` is set.
+/// With `?key=` returns every row of that key, newest first, as one JSON document; otherwise
+/// streams the bucket as NDJSON, one row per line, reading it a page at a time.
+#[actix_web::get("/admin/dump/{user}/{bucket}")]
+async fn dump(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, Some(&path.0))?;
+    if !dump_enabled() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let (user, bucket) = path.into_inner();
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let db = MetadataService::new(&user)?;
+    if !db.bucket_exists(&bucket)? {
+        return Ok(no_such_bucket(&user, &bucket));
+    }
+
+    if let Some(key) = query.get("key") {
+        let versions = db.full_record(&bucket, key)?;
+        let state = db.object_state(&bucket, key)?;
+        if versions.is_empty() && state.is_none() {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "NoSuchKey",
+                "message": "No metadata rows or pending deletions for this key",
+                "key": key,
+            })));
+        }
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "user": user,
+            "bucket": bucket,
+            "key": key,
+            "state": state,
+            "versions": versions,
+        })));
+    }
+
+    info!("metadata dump requested for user: {}, bucket: {}", user, bucket);
+    let pages = futures::stream::try_unfold(Some(0i64), move |after| {
+        let (user, bucket) = (user.clone(), bucket.clone());
+        async move {
+            let Some(after) = after else { return Ok(None) };
+            let page = MetadataService::new(&user)?.full_records_page(&bucket, after, DUMP_PAGE_ROWS)?;
+            let Some(last) = page.last().map(|r| r.row_id) else { return Ok(None) };
+            let mut lines = Vec::new();
+            for record in &page {
+                serde_json::to_writer(&mut lines, record).map_err(ErrorInternalServerError)?;
+                lines.push(b'\n');
+            }
+            let next = if page.len() < DUMP_PAGE_ROWS { None } else { Some(last) };
+            Ok::<_, Error>(Some((Bytes::from(lines), next)))
+        }
+    });
+    Ok(HttpResponse::Ok().content_type("application/x-ndjson").streaming(pages))
+}
+
+/// Inventory `user`/`bucket` as a job: `?format=csv|ndjson` (default csv), written under
+/// `INVENTORY_DIRECTORY`, or as object `?target_key=` (default a timestamped name) of the
+/// user's bucket `?target_bucket=`.
+#[actix_web::post("/admin/inventory/{user}/{bucket}")]
+async fn inventory(
+    path: web::Path<(String, String)>,
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    admin.require(Scope::Maintenance, Some(&path.0))?;
+    let (user, bucket) = path.into_inner();
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(ErrorBadRequest)?;
+    let format: InventoryFormat = query.get("format").map(|f| f.parse()).transpose().map_err(ErrorBadRequest)?
+        .unwrap_or(InventoryFormat::Csv);
+    let db = MetadataService::new(&user)?;
+    if !db.bucket_exists(&bucket)? {
+        return Ok(no_such_bucket(&user, &bucket));
+    }
+    let target = match query.get("target_bucket") {
+        Some(target_bucket) => {
+            if !db.bucket_exists(target_bucket)? {
+                return Ok(no_such_bucket(&user, target_bucket));
+            }
+            let key = query.get("target_key").cloned()
+                .unwrap_or_else(|| format!("inventory/{}", default_name(&user, &bucket, format)));
+            InventoryTarget::Object { bucket: target_bucket.clone(), key }
+        }
+        None => InventoryTarget::Directory,
+    };
+    info!("inventory requested for user: {}, bucket: {}, format: {}", user, bucket, format.as_str());
+    let params = serde_json::json!({
+        "format": format,
+        "target_bucket": query.get("target_bucket"),
+        "target_key": query.get("target_key"),
+    });
+    let (job_user, job_bucket) = (user.clone(), bucket.clone());
+    let job = jobs::enqueue("inventory", &user, &bucket, params, move |job| async move {
+        run_inventory(&job_user, &job_bucket, format, target, &job).await
+    })?;
+    Ok(accepted(job))
+}
+
+/// Rewrite one chunk of `key` (User/Bucket headers as for the native API) from the request body.
+#[actix_web::put("/repair/{key}")]
+async fn repair(
+    key: web::Path<String>,
+    payload: web::Payload,
+    admin: AdminGrant,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let user = req.headers().get("User").and_then(|h| h.to_str().ok()).unwrap_or_default();
+    admin.require(Scope::Gc, Some(user))?;
+    repair_service(key.into_inner(), payload, req).await.map_err(native_error)
+}
@@ -1,66 +1,512 @@
 //api.rs
 use actix_web::{web, HttpRequest, HttpResponse,Error };
 use log::info;
+use std::collections::HashMap;
 
-use crate::service::{get_service, put_service ,append_service , delete_service, update_key_service,update_service};
+use crate::service::{get_service, download_service, get_batch_service, put_service, put_batch_service, txn_service,append_service , delete_service, touch_service, lease_service, release_lease_service, update_key_service,update_service, copy_service, move_service, clone_service,
+    create_bucket_service, head_bucket_service, delete_bucket_service, put_tags_service, get_tags_service, list_service,
+    put_grant_service, list_grants_service, delete_grant_service, list_buckets_service, usage_service,
+    create_upload_service, put_upload_part_service, list_upload_parts_service, complete_upload_service, abort_upload_service};
+use crate::service::error::{native_error, ServiceError};
+use crate::service::api_version::ApiVersion;
+use crate::service::capabilities::Capabilities;
+use crate::service::{config_generation, deletion_spill, maintenance};
+use crate::service::metadata_service::MetadataService;
+use crate::config;
+use crate::startup::{HEALTH_BUCKET, SYSTEM_USER};
 
-#[actix_web::post("/put/{key}")]
+/// Refuse an empty key: key routes match the rest of the path, so `/put/` arrives here with
+/// `""` while `/put/dir/` is the directory marker `dir/`.
+fn require_key(key: &str) -> Result<(), Error> {
+    if key.is_empty() {
+        return Err(ServiceError::InvalidRequest("The object key must not be empty".to_string()).into());
+    }
+    Ok(())
+}
+
+#[actix_web::post("/put/{key:.*}")]
 async fn put(
     key: web::Path<String>,
     payload: web::Payload,
     req: HttpRequest,
+    version: ApiVersion,
 ) -> Result<HttpResponse, Error> {
-    info!("Uploading data with key: {}", key);
-    put_service(key.into_inner(), payload, req).await
+    version.finish(async move {
+        require_key(&key)?;
+        info!("Uploading data with key: {}", key);
+        put_service(key.into_inner(), payload, req).await.map_err(native_error)
+    }.await)
 }
 
-#[actix_web::get("/get/{key}")]
+#[actix_web::get("/get/{key:.*}")]
 async fn get(
     key: web::Path<String>,
     req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("checking key and retrieving : {}", key);
+        get_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::get("/download/{key:.*}")]
+async fn download(
+    key: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("downloading key: {}", key);
+        download_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::post("/get_batch")]
+async fn get_batch(
+    payload: web::Payload,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        info!("retrieving batch of keys");
+        get_batch_service(payload, req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::post("/put_batch")]
+async fn put_batch(
+    payload: web::Payload,
+    req: HttpRequest,
+    version: ApiVersion,
 ) -> Result<HttpResponse, Error> {
-    info!("checking key and retrieving : {}", key);
-    get_service(key.into_inner(), req).await
+    version.finish(async move {
+        info!("uploading batch of objects");
+        put_batch_service(payload, req).await.map_err(native_error)
+    }.await)
 }
 
+#[actix_web::post("/txn")]
+async fn txn(
+    payload: web::Payload,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        info!("applying transaction");
+        txn_service(payload, req).await.map_err(native_error)
+    }.await)
+}
 
-#[actix_web::post("/append/{key}")]
+#[actix_web::post("/append/{key:.*}")]
 async fn append(
     key: web::Path<String>,
     payload: web::Payload,
     req: HttpRequest,
+    version: ApiVersion,
 ) -> Result<HttpResponse, Error> {
-    info!("appending data with key: {}", key);
-    append_service(key.into_inner(), payload, req).await
+    version.finish(async move {
+        require_key(&key)?;
+        info!("appending data with key: {}", key);
+        append_service(key.into_inner(), payload, req).await.map_err(native_error)
+    }.await)
 }
 
-#[actix_web::delete("/delete/{key}")]
+#[actix_web::delete("/delete/{key:.*}")]
 async fn delete(
     key: web::Path<String>,
     req: HttpRequest,
+    version: ApiVersion,
 ) -> Result<HttpResponse, Error> {
-    info!("deleting data with key: {}", key);
-    delete_service(key.into_inner(), req).await
+    version.finish(async move {
+        require_key(&key)?;
+        info!("deleting data with key: {}", key);
+        delete_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
 }
 
 
-#[actix_web::put("/update_key/{old_key}/{new_key}")]
+#[actix_web::post("/touch/{key:.*}")]
+async fn touch(
+    key: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("touching key: {}", key);
+        touch_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+
+#[actix_web::post("/lease/{key:.*}")]
+async fn lease(
+    key: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("leasing key: {}", key);
+        lease_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::delete("/lease/{key:.*}")]
+async fn release_lease(
+    key: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("releasing lease on key: {}", key);
+        release_lease_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+
+#[actix_web::put("/update_key/{old_key:[^/]*}/{new_key:[^/]*}")]
 async fn update_key(
     path: web::Path<(String, String)>,
     req: HttpRequest,
+    version: ApiVersion,
 ) -> Result<HttpResponse, Error> {
-    let (old_key, new_key) = path.into_inner();
-    info!("updating old key with key: {}", new_key);
-    update_key_service(old_key, new_key, req).await
+    version.finish(async move {
+        let (old_key, new_key) = path.into_inner();
+        require_key(&old_key)?;
+        require_key(&new_key)?;
+        info!("updating old key with key: {}", new_key);
+        update_key_service(old_key, new_key, req).await.map_err(native_error)
+    }.await)
 }
 
 
-#[actix_web::post("/update/{key}")]
+#[actix_web::post("/update/{key:.*}")]
 async fn update(
     key: web::Path<String>,
     payload: web::Payload,
     req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("Uploading data with key: {}", key);
+        update_service(key.into_inner(), payload, req).await.map_err(native_error)
+    }.await)
+}
+
+
+#[actix_web::put("/move/{key:.*}")]
+async fn move_key(
+    key: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("moving key: {} between buckets", key);
+        move_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::post("/copy/{src_key:[^/]*}/{dst_key:[^/]*}")]
+async fn copy(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        let (src_key, dst_key) = path.into_inner();
+        require_key(&src_key)?;
+        require_key(&dst_key)?;
+        info!("copying key: {} to key: {}", src_key, dst_key);
+        copy_service(src_key, dst_key, req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::post("/clone/{src_key:[^/]*}/{dst_key:[^/]*}")]
+async fn clone(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        let (src_key, dst_key) = path.into_inner();
+        require_key(&src_key)?;
+        require_key(&dst_key)?;
+        info!("cloning key: {} to key: {}", src_key, dst_key);
+        clone_service(src_key, dst_key, req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::put("/tag/{key:.*}")]
+async fn put_tags(
+    key: web::Path<String>,
+    payload: web::Payload,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("setting tags on key: {}", key);
+        put_tags_service(key.into_inner(), payload, req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::get("/tag/{key:.*}")]
+async fn get_tags(
+    key: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("reading tags on key: {}", key);
+        get_tags_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::get("/list")]
+async fn list(
+    query: web::Query<HashMap<String, String>>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        info!("listing keys");
+        list_service(query.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::put("/bucket/{bucket}")]
+async fn create_bucket(
+    bucket: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        info!("Creating bucket: {}", bucket);
+        create_bucket_service(bucket.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::head("/bucket/{bucket}")]
+async fn head_bucket(
+    bucket: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        head_bucket_service(bucket.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::delete("/bucket/{bucket}")]
+async fn delete_bucket(
+    bucket: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        info!("Deleting bucket: {}", bucket);
+        delete_bucket_service(bucket.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::put("/grants/{bucket}/{grantee}")]
+async fn put_grant(
+    path: web::Path<(String, String)>,
+    query: web::Query<HashMap<String, String>>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        let (bucket, grantee) = path.into_inner();
+        info!("Granting bucket: {} to {}", bucket, grantee);
+        put_grant_service(bucket, grantee, query.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::get("/grants/{bucket}")]
+async fn list_grants(
+    bucket: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        list_grants_service(bucket.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::delete("/grants/{bucket}/{grantee}")]
+async fn delete_grant(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        let (bucket, grantee) = path.into_inner();
+        info!("Revoking bucket: {} from {}", bucket, grantee);
+        delete_grant_service(bucket, grantee, req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::get("/buckets")]
+async fn list_buckets(req: HttpRequest, version: ApiVersion) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        list_buckets_service(req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::get("/usage")]
+async fn usage(req: HttpRequest, version: ApiVersion) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        usage_service(req).await.map_err(native_error)
+    }.await)
+}
+
+// Upload session routes take the session id from the end of the path, so the key may hold
+// slashes; a session segment is `ups-` and hex (see `upload_sessions`).
+
+#[actix_web::put("/upload/{key:.*}/{session:ups-[0-9a-f]+}/{part_number:[0-9]+}")]
+async fn put_upload_part(
+    path: web::Path<(String, String, String)>,
+    payload: web::Payload,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        let (key, session, part_number) = path.into_inner();
+        require_key(&key)?;
+        let part_number = part_number.parse::<i32>()
+            .map_err(|_| ServiceError::InvalidRequest(format!("Invalid part number: {}", part_number)))?;
+        info!("receiving part {} of upload session {} for key: {}", part_number, session, key);
+        put_upload_part_service(key, session, part_number, payload, req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::post("/upload/{key:.*}/{session:ups-[0-9a-f]+}/complete")]
+async fn complete_upload(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    version: ApiVersion,
 ) -> Result<HttpResponse, Error> {
-    info!("Uploading data with key: {}", key);
-    update_service(key.into_inner(), payload, req).await
-}
\ No newline at end of file
+    version.finish(async move {
+        let (key, session) = path.into_inner();
+        require_key(&key)?;
+        info!("completing upload session {} for key: {}", session, key);
+        complete_upload_service(key, session, req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::get("/upload/{key:.*}/{session:ups-[0-9a-f]+}")]
+async fn list_upload_parts(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        let (key, session) = path.into_inner();
+        require_key(&key)?;
+        list_upload_parts_service(key, session, req).await.map_err(native_error)
+    }.await)
+}
+
+#[actix_web::delete("/upload/{key:.*}/{session:ups-[0-9a-f]+}")]
+async fn abort_upload(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        let (key, session) = path.into_inner();
+        require_key(&key)?;
+        info!("aborting upload session {} for key: {}", session, key);
+        abort_upload_service(key, session, req).await.map_err(native_error)
+    }.await)
+}
+
+/// Registered after the session routes, which would otherwise match as part of a key
+#[actix_web::post("/upload/{key:.*}")]
+async fn create_upload(
+    key: web::Path<String>,
+    req: HttpRequest,
+    version: ApiVersion,
+) -> Result<HttpResponse, Error> {
+    version.finish(async move {
+        require_key(&key)?;
+        info!("opening upload session for key: {}", key);
+        create_upload_service(key.into_inner(), req).await.map_err(native_error)
+    }.await)
+}
+
+/// The native API routes, mounted under `/v1`, `/v2` and unprefixed (see
+/// [`crate::service::api_version`])
+pub fn native_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(put)
+        .service(get)
+        .service(download)
+        .service(get_batch)
+        .service(put_batch)
+        .service(txn)
+        .service(append)
+        .service(delete)
+        .service(lease)
+        .service(release_lease)
+        .service(update_key)
+        .service(move_key)
+        .service(update)
+        .service(touch)
+        .service(copy)
+        .service(clone)
+        .service(put_tags)
+        .service(get_tags)
+        .service(list)
+        .service(create_bucket)
+        .service(head_bucket)
+        .service(delete_bucket)
+        .service(put_grant)
+        .service(list_grants)
+        .service(delete_grant)
+        .service(list_buckets)
+        .service(usage)
+        .service(put_upload_part)
+        .service(complete_upload)
+        .service(list_upload_parts)
+        .service(abort_upload)
+        .service(create_upload);
+}
+
+#[actix_web::get("/capabilities")]
+async fn capabilities(req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok().json(Capabilities::of(&req))
+}
+
+/// Liveness: the process is up and answering; reports its role, the configuration
+/// generation its caches were last validated against and the deletion spill file
+#[actix_web::get("/healthz")]
+async fn healthz() -> HttpResponse {
+    config_generation::revalidate();
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+        "role": config::role(),
+        "config_generation": config_generation::observed(),
+        "deletion_spill": deletion_spill::status(),
+    }))
+}
+
+/// Readiness: the metadata database answers queries. `read_only` is true on a replica and
+/// while the server is in read-only maintenance mode.
+#[actix_web::get("/readyz")]
+async fn readyz() -> HttpResponse {
+    let role = config::role();
+    let read_only = config::is_replica() || maintenance::is_read_only();
+    match MetadataService::new(SYSTEM_USER).and_then(|db| db.bucket_exists(HEALTH_BUCKET)) {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "status": "ready", "role": role, "read_only": read_only })),
+        Err(e) => HttpResponse::ServiceUnavailable().json(serde_json::json!({ "status": "unavailable", "role": role, "error": e.to_string() })),
+    }
+}
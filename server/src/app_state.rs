@@ -0,0 +1,166 @@
+//! Putting the server together, for `main` and for applications embedding it
+//!
+//! An [`AppStateBuilder`] installs the metadata store and storage backend (the ones
+//! `METADATA_BACKEND` and `STORAGE_BACKEND` name unless others are supplied), runs startup
+//! initialization and starts the background workers; initialization and workers can each be
+//! turned off. [`routes`] registers every native, admin and S3 route on any actix `App`, and
+//! [`crate::server::app`] is the same routes behind the server's middleware stack.
+//!
+//! Stores and configuration are process-wide: one state is built per process, before the
+//! first request, from within the actix runtime that will serve it.
+
+use log::{error, info};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+use crate::config::{self, HttpConfig};
+use crate::metadata::MetadataStorage;
+use crate::service::access_tracking::start_access_flusher;
+use crate::service::admin_tokens::{self, IssuedToken};
+use crate::service::db_maintenance::start_db_maintenance;
+use crate::service::deletion_worker::start_deletion_worker;
+use crate::service::inflight;
+use crate::service::jobs;
+use crate::service::metadata_service;
+use crate::startup::{self, StartupError};
+use crate::storage::config as storage_config;
+use crate::storage::durability::start_fsync_worker;
+use crate::storage::replicated_store::start_metadata_replication;
+use crate::storage::Storage;
+
+pub use crate::server::routes;
+
+/// A server ready to serve requests
+pub struct AppState {
+    pub http: HttpConfig,
+    /// Object metadata store every service and worker uses
+    pub metadata: Arc<dyn MetadataStorage>,
+    /// Admin token issued on the first start, to be shown once
+    pub bootstrap_token: Option<IssuedToken>,
+    /// Background workers; they run until the runtime stops
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl AppState {
+    /// Background workers started by [`AppStateBuilder::build`]
+    pub fn worker_count(&self) -> usize {
+        self.workers.len()
+    }
+}
+
+/// How to build an [`AppState`]; [`AppStateBuilder::new`] does what `main` does
+pub struct AppStateBuilder {
+    metadata: Option<Arc<dyn MetadataStorage>>,
+    storage: Option<Arc<dyn Storage>>,
+    http: Option<HttpConfig>,
+    initialize: bool,
+    force_adopt: bool,
+    background_workers: bool,
+}
+
+impl Default for AppStateBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AppStateBuilder {
+    /// The configured stores, startup initialization and every background worker
+    pub fn new() -> Self {
+        Self { metadata: None, storage: None, http: None, initialize: true, force_adopt: false, background_workers: true }
+    }
+
+    /// Keep object metadata in `store` instead of the configured backend; see
+    /// [`metadata_service::install_store`] for what stays in SQLite.
+    pub fn metadata_store(mut self, store: Arc<dyn MetadataStorage>) -> Self {
+        self.metadata = Some(store);
+        self
+    }
+
+    /// Keep chunks in `backend` instead of the configured backend
+    pub fn storage(mut self, backend: Arc<dyn Storage>) -> Self {
+        self.storage = Some(backend);
+        self
+    }
+
+    /// Serve with `config` instead of [`HttpConfig::from_env`]
+    pub fn http_config(mut self, config: HttpConfig) -> Self {
+        self.http = Some(config);
+        self
+    }
+
+    /// Whether to run [`startup::initialize`], recover admin jobs and issue the bootstrap
+    /// admin token (default on)
+    pub fn initialize(mut self, enabled: bool) -> Self {
+        self.initialize = enabled;
+        self
+    }
+
+    /// Whether initialization makes the database and storage agree on an instance id instead
+    /// of failing when they differ (`serve --force-adopt`, default off); see
+    /// [`crate::service::instance_identity`]
+    pub fn force_adopt(mut self, enabled: bool) -> Self {
+        self.force_adopt = enabled;
+        self
+    }
+
+    /// Whether to start the deletion worker, replication, database maintenance, fsync, access
+    /// flushing and the in-flight watchdog (default on)
+    pub fn background_workers(mut self, enabled: bool) -> Self {
+        self.background_workers = enabled;
+        self
+    }
+
+    /// Install the stores, then initialize and start workers as configured. Fails when
+    /// initialization does, or when stores are supplied after the process already opened its
+    /// own.
+    pub fn build(self) -> Result<AppState, StartupError> {
+        if let Some(store) = self.metadata {
+            metadata_service::install_store(store).map_err(|_| StartupError {
+                step: "metadata",
+                message: "a metadata store is already in use".to_string(),
+            })?;
+        }
+        let metadata = metadata_service::store();
+        if let Some(backend) = self.storage {
+            storage_config::install_backend(backend).map_err(|_| StartupError {
+                step: "storage",
+                message: "a storage backend is already installed".to_string(),
+            })?;
+        }
+
+        // A replica serves a snapshot: nothing at startup or in the background may write to it
+        let replica = config::is_replica();
+        let mut bootstrap_token = None;
+        if self.initialize {
+            startup::initialize_with(self.force_adopt)?;
+            if !replica {
+                // Admin jobs of the previous process will not finish
+                if let Err(e) = jobs::recover() {
+                    error!("Failed to recover admin jobs: {}", e);
+                }
+                // The first start issues a token with every scope; only its hash is kept
+                match admin_tokens::bootstrap() {
+                    Ok(issued) => bootstrap_token = issued,
+                    Err(e) => error!("Failed to issue the bootstrap admin token: {}", e),
+                }
+            }
+        }
+
+        let mut workers = Vec::new();
+        if self.background_workers {
+            if !replica {
+                workers.push(start_deletion_worker(Arc::clone(&metadata)));
+                info!("Deletion worker started in background");
+                workers.extend(start_metadata_replication());
+                workers.extend(start_db_maintenance());
+                workers.extend(start_fsync_worker());
+                workers.extend(start_access_flusher());
+            }
+            // Only reads the in-flight registry, so replicas run it too
+            workers.push(inflight::start_watchdog());
+        }
+
+        Ok(AppState { http: self.http.unwrap_or_else(HttpConfig::from_env), metadata, bootstrap_token, workers })
+    }
+}
@@ -0,0 +1,242 @@
+//! Command line of the server binary
+//!
+//! With no subcommand, or `serve`, the binary runs the HTTP server. The other subcommands
+//! run one admin task against the configured database and storage and exit, for cron jobs
+//! and containers. They call the same functions as the matching `/admin` endpoints, print
+//! one JSON document on stdout and log to stderr (`RUST_LOG`, warnings by default).
+//!
+//! `self-test` is the exception: it runs against scratch stores of its own and prints a
+//! pass/fail entry per step.
+//!
+//! Exit status: 0 on success, 1 when the task failed (stdout then holds `{"error": ...}`,
+//! or for `self-test` the report with the failing step), 2 for usage errors and 4 when
+//! `fsck` or the verification of `migrate-layout` found problems, or `import-dir` could not
+//! import some files.
+
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::service::deletion_worker::DeletionWorker;
+use crate::service::jobs::JobHandle;
+use crate::service::import::{self, ImportOptions};
+use crate::service::{export, fsck, gc, layout_migration, legacy_migration, self_test, size_stats, stats};
+use crate::storage::layout::StorageLayout;
+use crate::startup;
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILED: i32 = 1;
+/// `fsck` (or `migrate-layout`'s check) completed and found inconsistencies, or `import-dir`
+/// completed with files it could not import
+pub const EXIT_PROBLEMS: i32 = 4;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "WarpDrive object storage server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (the default)
+    Serve {
+        /// Make the metadata database and storage directories agree on the database's
+        /// instance id instead of refusing to start when they differ
+        #[arg(long)]
+        force_adopt: bool,
+    },
+    /// Check a bucket's metadata against its storage
+    Fsck {
+        #[arg(long)]
+        user: String,
+        #[arg(long)]
+        bucket: String,
+    },
+    /// Queue a bucket's orphaned chunks for deletion
+    Gc {
+        #[arg(long)]
+        user: String,
+        #[arg(long)]
+        bucket: String,
+        /// Report the orphans without queueing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Free a bucket's queued deletions and unlink emptied segment files
+    Compact {
+        #[arg(long)]
+        user: String,
+        #[arg(long)]
+        bucket: String,
+    },
+    /// Write a bucket's live objects and their metadata to a tar archive
+    Export {
+        #[arg(long)]
+        user: String,
+        #[arg(long)]
+        bucket: String,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Create an object from each regular file under a directory, keyed by its relative path
+    ImportDir {
+        #[arg(long)]
+        user: String,
+        #[arg(long)]
+        bucket: String,
+        #[arg(long)]
+        path: PathBuf,
+        /// Prepended to every key
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// Files imported at once
+        #[arg(long, default_value_t = import::DEFAULT_CONCURRENCY)]
+        concurrency: usize,
+        /// Replace live keys instead of skipping them
+        #[arg(long)]
+        overwrite: bool,
+        /// Manifest of imported keys, for resuming (default: `<path>.warpdrive-import`)
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+    /// Objects and bytes per bucket, and the deletion backlog
+    Stats,
+    /// Distributions of a bucket's object sizes, chunks per object and chunk sizes
+    AnalyzeSizes {
+        #[arg(long)]
+        user: String,
+        #[arg(long)]
+        bucket: String,
+    },
+    /// Move a user's bucket files to another storage layout and verify them
+    MigrateLayout {
+        #[arg(long)]
+        user: String,
+        /// flat or hashed (default: STORAGE_LAYOUT)
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Copy a user's chunks out of the pre-bucket `{user}.bin` into their buckets' files
+    MigrateLegacy {
+        #[arg(long)]
+        user: String,
+    },
+    /// Put, read, append, rename and delete objects in a scratch database and storage
+    SelfTest {
+        /// Directory for the scratch stores (default: a new one under the temp directory,
+        /// removed afterwards)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Serve { .. } => "serve",
+            Command::Fsck { .. } => "fsck",
+            Command::Gc { .. } => "gc",
+            Command::Compact { .. } => "compact",
+            Command::Export { .. } => "export",
+            Command::ImportDir { .. } => "import-dir",
+            Command::Stats => "stats",
+            Command::AnalyzeSizes { .. } => "analyze-sizes",
+            Command::MigrateLayout { .. } => "migrate-layout",
+            Command::MigrateLegacy { .. } => "migrate-legacy",
+            Command::SelfTest { .. } => "self-test",
+        }
+    }
+}
+
+fn to_json(value: impl serde::Serialize) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+/// Run the self-test in `dir`, or in a scratch directory that is removed afterwards
+async fn run_self_test(dir: Option<&PathBuf>) -> Result<(Value, i32), String> {
+    let scratch = std::env::temp_dir().join(format!("warpdrive-self-test-{}", std::process::id()));
+    let report = self_test::run(dir.unwrap_or(&scratch)).await;
+    if dir.is_none() {
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+    let code = if report.passed { EXIT_OK } else { EXIT_FAILED };
+    Ok((to_json(report)?, code))
+}
+
+async fn execute(command: &Command) -> Result<(Value, i32), String> {
+    // The self-test brings up its own stores instead of the configured ones
+    if let Command::SelfTest { dir } = command {
+        return run_self_test(dir.as_ref()).await;
+    }
+    startup::initialize().map_err(|e| e.to_string())?;
+    match command {
+        Command::Serve { .. } => Err("serve is not a single-shot command".to_string()),
+        Command::Fsck { user, bucket } => {
+            let report = fsck::check_bucket(user, bucket).map_err(|e| e.to_string())?;
+            let code = if report.is_clean() { EXIT_OK } else { EXIT_PROBLEMS };
+            Ok((to_json(report)?, code))
+        }
+        Command::Gc { user, bucket, dry_run } => {
+            let report = gc::collect_orphans(user, bucket, *dry_run, &JobHandle::detached()).await.map_err(|e| e.to_string())?;
+            Ok((to_json(report)?, EXIT_OK))
+        }
+        Command::Compact { user, bucket } => {
+            let report = DeletionWorker::new().compact_bucket(user, bucket, &JobHandle::detached()).await.map_err(|e| e.to_string())?;
+            Ok((to_json(report)?, EXIT_OK))
+        }
+        Command::Export { user, bucket, out } => {
+            let file = File::create(out).map_err(|e| format!("cannot create {}: {}", out.display(), e))?;
+            match export::export_bucket(user, bucket, BufWriter::new(file)) {
+                Ok(report) => Ok((to_json(report)?, EXIT_OK)),
+                Err(e) => {
+                    let _ = std::fs::remove_file(out);
+                    Err(e.to_string())
+                }
+            }
+        }
+        Command::ImportDir { user, bucket, path, prefix, concurrency, overwrite, manifest } => {
+            let options = ImportOptions {
+                prefix: prefix.clone(),
+                concurrency: *concurrency,
+                overwrite: *overwrite,
+                manifest: manifest.clone(),
+            };
+            let report = import::import_dir(user, bucket, path, &options).await.map_err(|e| e.to_string())?;
+            let code = if report.failed.is_empty() { EXIT_OK } else { EXIT_PROBLEMS };
+            Ok((to_json(report)?, code))
+        }
+        Command::Stats => Ok((to_json(stats::collect().map_err(|e| e.to_string())?)?, EXIT_OK)),
+        Command::AnalyzeSizes { user, bucket } => {
+            Ok((to_json(size_stats::analyze(user, bucket).map_err(|e| e.to_string())?)?, EXIT_OK))
+        }
+        Command::MigrateLayout { user, to } => {
+            let to = match to {
+                Some(to) => to.parse::<StorageLayout>()?,
+                None => StorageLayout::from_env(),
+            };
+            let report = layout_migration::migrate_user(user, to).await.map_err(|e| e.to_string())?;
+            let code = if report.is_clean() { EXIT_OK } else { EXIT_PROBLEMS };
+            Ok((to_json(report)?, code))
+        }
+        Command::MigrateLegacy { user } => {
+            let report = legacy_migration::migrate_user(user).await.map_err(|e| e.to_string())?;
+            Ok((to_json(report)?, EXIT_OK))
+        }
+        Command::SelfTest { .. } => unreachable!("handled before startup"),
+    }
+}
+
+/// Run a single-shot command, print its JSON result and return the exit status.
+pub async fn run(command: Command) -> i32 {
+    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).try_init();
+    let (output, code) = match execute(&command).await {
+        Ok(result) => result,
+        Err(error) => (json!({ "command": command.name(), "error": error }), EXIT_FAILED),
+    };
+    // A closed stdout (`| head`) must not turn a finished task into a panic
+    let _ = writeln!(std::io::stdout(), "{}", serde_json::to_string_pretty(&output).unwrap_or_else(|_| output.to_string()));
+    code
+}
@@ -0,0 +1,209 @@
+//! Server-wide configuration
+//!
+//! `SERVER_ROLE` picks what the process is for. A `primary` (the default) serves reads and
+//! writes. A `replica` serves reads from a snapshot of a primary's metadata and storage (or
+//! its replication target): mutating requests fail with 501 `NotImplemented`, the background
+//! workers that write are not started, the metadata database is opened read-only and the
+//! storage backend refuses writes. `SERVER_PORT` (default 9710) lets a replica listen next to
+//! its primary.
+//!
+//! [`HttpConfig`] tunes the HTTP server: worker threads, keep-alive, client timeouts and
+//! connection limits, each left at actix's default when unset, and TLS. With
+//! `TLS_CERT_FILE`/`TLS_KEY_FILE` the server speaks HTTPS and offers HTTP/2 through ALPN;
+//! without them `HTTP_H2C=true` accepts HTTP/2 with prior knowledge next to HTTP/1.1.
+
+use actix_web::{Error, HttpResponse};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::s3::handlers::xml_escape;
+use crate::service::api_version;
+use crate::service::maintenance::is_s3_request;
+
+pub const DEFAULT_PORT: u16 = 9710;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerRole {
+    #[default]
+    Primary,
+    Replica,
+}
+
+impl ServerRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerRole::Primary => "primary",
+            ServerRole::Replica => "replica",
+        }
+    }
+
+    /// Read `SERVER_ROLE`; unset or unknown values mean primary.
+    pub fn from_env() -> Self {
+        match env::var("SERVER_ROLE") {
+            Ok(value) => value.parse().unwrap_or_else(|e: String| {
+                warn!("{}; running as primary", e);
+                ServerRole::Primary
+            }),
+            Err(_) => ServerRole::Primary,
+        }
+    }
+}
+
+impl std::str::FromStr for ServerRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "primary" => Ok(ServerRole::Primary),
+            "replica" => Ok(ServerRole::Replica),
+            _ => Err(format!("Unknown server role: {} (expected primary or replica)", s)),
+        }
+    }
+}
+
+static ROLE: OnceLock<ServerRole> = OnceLock::new();
+
+/// Role of this process, read from the environment on first use
+pub fn role() -> ServerRole {
+    *ROLE.get_or_init(ServerRole::from_env)
+}
+
+pub fn is_replica() -> bool {
+    role() == ServerRole::Replica
+}
+
+/// Port to listen on: `SERVER_PORT`, or [`DEFAULT_PORT`]
+pub fn port() -> u16 {
+    env_number("SERVER_PORT").unwrap_or(DEFAULT_PORT)
+}
+
+fn env_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|v| v.trim().parse().ok())
+}
+
+/// HTTP server tuning; `None` keeps actix's default
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpConfig {
+    /// `SERVER_HOST`, default `0.0.0.0`
+    pub host: String,
+    pub port: u16,
+    /// `HTTP_WORKERS`; actix starts one per physical core
+    pub workers: Option<usize>,
+    /// `HTTP_KEEP_ALIVE_SECS`, 0 to close every connection after its response; actix keeps
+    /// idle connections 5s
+    pub keep_alive: Option<Duration>,
+    /// `HTTP_CLIENT_REQUEST_TIMEOUT_MS`, time to receive a request's headers (0 for none);
+    /// actix allows 5s
+    pub client_request_timeout: Option<Duration>,
+    /// `HTTP_CLIENT_DISCONNECT_TIMEOUT_MS`, time for a client to close a connection the
+    /// server is done with; actix allows 1s
+    pub client_disconnect_timeout: Option<Duration>,
+    /// `HTTP_MAX_CONNECTIONS`, per worker; actix accepts 25k
+    pub max_connections: Option<usize>,
+    /// `HTTP_H2C`: plaintext HTTP/2 with prior knowledge
+    pub h2c: bool,
+    /// `TLS_CERT_FILE`, PEM certificate chain
+    pub tls_cert: Option<PathBuf>,
+    /// `TLS_KEY_FILE`, PEM private key
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: DEFAULT_PORT,
+            workers: None,
+            keep_alive: None,
+            client_request_timeout: None,
+            client_disconnect_timeout: None,
+            max_connections: None,
+            h2c: false,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn from_env() -> Self {
+        let path = |name| env::var(name).ok().filter(|v| !v.trim().is_empty()).map(|v| PathBuf::from(v.trim()));
+        Self {
+            host: env::var("SERVER_HOST").ok().filter(|v| !v.trim().is_empty())
+                .map(|v| v.trim().to_string())
+                .unwrap_or_else(|| Self::default().host),
+            port: port(),
+            workers: env_number("HTTP_WORKERS").filter(|n| *n > 0),
+            keep_alive: env_number("HTTP_KEEP_ALIVE_SECS").map(Duration::from_secs),
+            client_request_timeout: env_number("HTTP_CLIENT_REQUEST_TIMEOUT_MS").map(Duration::from_millis),
+            client_disconnect_timeout: env_number("HTTP_CLIENT_DISCONNECT_TIMEOUT_MS").map(Duration::from_millis),
+            max_connections: env_number("HTTP_MAX_CONNECTIONS").filter(|n| *n > 0),
+            h2c: env::var("HTTP_H2C").map(|v| v.eq_ignore_ascii_case("true") || v == "1").unwrap_or(false),
+            tls_cert: path("TLS_CERT_FILE"),
+            tls_key: path("TLS_KEY_FILE"),
+        }
+    }
+
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert.is_some() || self.tls_key.is_some()
+    }
+}
+
+/// Answer every mutating request with 501 on a replica; use with `middleware::from_fn` inside
+/// the virtual-host rewrite. `POST /get_batch` is a read.
+pub async fn replica_gate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let mutating = matches!(*req.method(), Method::PUT | Method::POST | Method::DELETE | Method::PATCH);
+    if !is_replica() || !mutating || api_version::unversioned_path(req.path()) == "/get_batch" {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+
+    let message = "This server is a read-only replica";
+    let mut resp = HttpResponse::NotImplemented();
+    let resp = if is_s3_request(&req) {
+        resp.content_type("application/xml")
+            .insert_header(("x-amz-request-id", "warpdrive"))
+            .body(format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <Error>\n\
+                   <Code>NotImplemented</Code>\n\
+                   <Message>{}</Message>\n\
+                   <Resource>{}</Resource>\n\
+                   <RequestId>warpdrive</RequestId>\n\
+                 </Error>",
+                message, xml_escape(req.path()),
+            ))
+    } else {
+        resp.json(json!({
+            "error": "NotImplemented",
+            "message": message,
+            "role": role(),
+        }))
+    };
+    Ok(req.into_response(resp).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_role_from_str() {
+        assert_eq!("primary".parse::<ServerRole>().unwrap(), ServerRole::Primary);
+        assert_eq!("Replica".parse::<ServerRole>().unwrap(), ServerRole::Replica);
+        assert!("standby".parse::<ServerRole>().is_err());
+        assert_eq!(ServerRole::default(), ServerRole::Primary);
+    }
+}
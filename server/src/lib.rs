@@ -1,8 +1,17 @@
 // src/lib.rs
 
+pub mod config;
 pub mod storage;
 pub mod metadata;
 pub mod util;
 pub mod api;
+pub mod admin;
 pub mod service;
 pub mod s3;
+pub mod metrics;
+pub mod logging;
+pub mod startup;
+pub mod cli;
+pub mod ui;
+pub mod server;
+pub mod app_state;
@@ -0,0 +1,254 @@
+//! Runtime log verbosity and per-user request tracing
+//!
+//! The server installs [`RuntimeLogger`] in front of log4rs. It owns the global level, which
+//! starts at the root level of `server_log.yaml` and can be changed at runtime with
+//! `POST /admin/log_level?level=debug`; log4rs itself passes everything it is handed.
+//!
+//! The [`request_scope`] middleware gives every request an id (the caller's `X-Request-Id`
+//! or a new one), returned in `X-Request-Id` and prefixed to every line logged while the
+//! request is handled. Once a handler knows the user it calls [`bind_user`]. A user put under
+//! trace with `POST /admin/trace_user/{user}?ttl=300` has all of their requests logged at
+//! debug whatever the global level, and those lines are also kept in a per-user ring buffer
+//! served by `GET /admin/trace_user/{user}/logs`. The trace lapses after its TTL; the
+//! buffer is kept until the user is traced again or `DELETE /admin/trace_user/{user}`.
+//! Work moved off the request's task (`web::block`, background threads) is not attributed.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest};
+use lazy_static::lazy_static;
+use log::{debug, Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Lines kept per traced user; older lines are dropped first
+pub const TRACE_BUFFER_LINES: usize = 2000;
+
+/// Longest trace one call may request
+pub const MAX_TRACE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+static LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+
+lazy_static! {
+    static ref TRACES: Mutex<HashMap<String, UserTrace>> = Mutex::new(HashMap::new());
+}
+
+tokio::task_local! {
+    static REQUEST: RequestScope;
+}
+
+/// The request being handled on the current task
+struct RequestScope {
+    id: String,
+    /// User and trace expiry, once the user is known
+    user: RefCell<Option<(String, Option<Instant>)>>,
+}
+
+impl RequestScope {
+    /// The traced user, while the trace lasts
+    fn traced_user(&self) -> Option<String> {
+        match &*self.user.borrow() {
+            Some((user, Some(until))) if Instant::now() < *until => Some(user.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// One captured log line
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceLine {
+    pub time: String,
+    pub level: String,
+    pub request_id: String,
+    pub target: String,
+    pub message: String,
+}
+
+struct UserTrace {
+    until: Instant,
+    lines: VecDeque<TraceLine>,
+    dropped: u64,
+}
+
+/// A user's trace as reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceSnapshot {
+    pub user: String,
+    pub active: bool,
+    pub expires_in_secs: u64,
+    /// Lines pushed out of the full buffer
+    pub dropped: u64,
+    pub lines: Vec<TraceLine>,
+}
+
+/// log4rs with a runtime-adjustable level and request attribution
+pub struct RuntimeLogger {
+    inner: log4rs::Logger,
+}
+
+impl Log for RuntimeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= level()
+            || (metadata.level() <= Level::Debug && REQUEST.try_with(|r| r.traced_user().is_some()).unwrap_or(false))
+    }
+
+    fn log(&self, record: &Record) {
+        let in_request = REQUEST.try_with(|request| {
+            let traced = match request.traced_user() {
+                Some(user) if record.level() <= Level::Debug => {
+                    capture(&user, &request.id, record);
+                    true
+                }
+                _ => false,
+            };
+            if traced || record.level() <= level() {
+                self.inner.log(&Record::builder()
+                    .args(format_args!("[{}] {}", request.id, record.args()))
+                    .level(record.level())
+                    .target(record.target())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build());
+            }
+        });
+        if in_request.is_err() && record.level() <= level() {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install the logger configured by a log4rs config file.
+pub fn init_file(path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    install(log4rs::config::load_config_file(path, Default::default())?)?;
+    Ok(())
+}
+
+/// Install the logger for `config`; its root level becomes the initial global level.
+pub fn install(mut config: log4rs::Config) -> Result<(), log::SetLoggerError> {
+    let initial = config.root().level();
+    config.root_mut().set_level(LevelFilter::Trace);
+    log::set_boxed_logger(Box::new(RuntimeLogger { inner: log4rs::Logger::new(config) }))?;
+    set_level(initial);
+    Ok(())
+}
+
+/// The global level
+pub fn level() -> LevelFilter {
+    LevelFilter::iter().nth(LEVEL.load(Ordering::Relaxed)).unwrap_or(LevelFilter::Info)
+}
+
+pub fn set_level(level: LevelFilter) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    refresh_max_level();
+}
+
+/// Let records through the `log` macros while the level or any live trace wants them.
+fn refresh_max_level() {
+    let now = Instant::now();
+    let tracing = TRACES.lock().unwrap_or_else(|e| e.into_inner()).values().any(|t| t.until > now);
+    let wanted = if tracing { level().max(LevelFilter::Debug) } else { level() };
+    log::set_max_level(wanted);
+}
+
+fn capture(user: &str, request_id: &str, record: &Record) {
+    let mut traces = TRACES.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(trace) = traces.get_mut(user) else { return };
+    if trace.lines.len() >= TRACE_BUFFER_LINES {
+        trace.lines.pop_front();
+        trace.dropped += 1;
+    }
+    trace.lines.push_back(TraceLine {
+        time: chrono::Utc::now().to_rfc3339(),
+        level: record.level().to_string(),
+        request_id: request_id.to_string(),
+        target: record.target().to_string(),
+        message: record.args().to_string(),
+    });
+}
+
+/// Trace `user`'s requests for `ttl`, starting a fresh buffer.
+pub fn start_trace(user: &str, ttl: Duration) {
+    let trace = UserTrace { until: Instant::now() + ttl, lines: VecDeque::new(), dropped: 0 };
+    TRACES.lock().unwrap_or_else(|e| e.into_inner()).insert(user.to_string(), trace);
+    refresh_max_level();
+}
+
+/// Stop tracing `user` and drop the buffer; false if there was none.
+pub fn stop_trace(user: &str) -> bool {
+    let removed = TRACES.lock().unwrap_or_else(|e| e.into_inner()).remove(user).is_some();
+    refresh_max_level();
+    removed
+}
+
+/// `user`'s trace and captured lines, if the user has been traced
+pub fn trace_snapshot(user: &str) -> Option<TraceSnapshot> {
+    let traces = TRACES.lock().unwrap_or_else(|e| e.into_inner());
+    let trace = traces.get(user)?;
+    let left = trace.until.saturating_duration_since(Instant::now());
+    Some(TraceSnapshot {
+        user: user.to_string(),
+        active: !left.is_zero(),
+        expires_in_secs: left.as_secs(),
+        dropped: trace.dropped,
+        lines: trace.lines.iter().cloned().collect(),
+    })
+}
+
+/// Attribute the current request to `user`, in the log and in the in-flight registry; called
+/// once the user is authenticated.
+pub fn bind_user(user: &str) {
+    crate::service::inflight::bind_user(user);
+    let until = TRACES.lock().unwrap_or_else(|e| e.into_inner()).get(user).map(|t| t.until);
+    let _ = REQUEST.try_with(|request| *request.user.borrow_mut() = Some((user.to_string(), until)));
+}
+
+/// The request id of the current request, else the caller's `X-Request-Id`, else a new one
+pub fn request_id(req: &HttpRequest) -> String {
+    if let Ok(id) = REQUEST.try_with(|request| request.id.clone()) {
+        return id;
+    }
+    if let Some(id) = req.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()).filter(|v| !v.is_empty()) {
+        return id.to_string();
+    }
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).expect("OS random number generator unavailable");
+    format!("req-{}", hex::encode(bytes))
+}
+
+/// Run the request under its id; use with `middleware::from_fn`. Returns the id in
+/// `X-Request-Id`.
+pub async fn request_scope(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = request_id(req.request());
+    let scope = RequestScope { id: id.clone(), user: RefCell::new(None) };
+    REQUEST.scope(scope, async move {
+        let (method, path) = (req.method().clone(), req.path().to_string());
+        let mut res = next.call(req).await;
+        match &mut res {
+            Ok(res) => {
+                debug!("{} {} -> {}", method, path, res.status());
+                if let Ok(value) = HeaderValue::from_str(&id) {
+                    res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+            }
+            Err(e) => debug!("{} {} failed: {}", method, path, e),
+        }
+        res
+    }).await
+}
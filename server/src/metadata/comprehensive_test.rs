@@ -2,9 +2,10 @@
 
 #[cfg(test)]
 mod integration_tests {
-    use crate::metadata::{Metadata, config::{MetadataConfig, MetadataBackend}};
+    use crate::metadata::{Metadata, MetadataStorage, DataChunk, config::{MetadataConfig, MetadataBackend}};
+    use actix_web::http::StatusCode;
     use crate::service::metadata_service::MetadataService;
-    use crate::util::serializer::{serialize_offset_size, deserialize_offset_size};
+    use crate::util::serializer::{encode_extents, decode_extents};
     use std::env;
 
     #[test]
@@ -25,7 +26,7 @@ mod integration_tests {
             
             // Create test data (simulating what comes from storage layer)
             let offset_size_list = vec![(100, 200), (300, 400), (500, 600)];
-            let serialized_data = serialize_offset_size(&offset_size_list)
+            let serialized_data = encode_extents(&offset_size_list)
                 .expect("Failed to serialize data");
             
             // Store the data
@@ -44,13 +45,13 @@ mod integration_tests {
             assert_eq!(serialized_data, retrieved_data);
             
             // Deserialize and verify structure
-            let retrieved_offset_size_list = deserialize_offset_size(&retrieved_data)
+            let retrieved_offset_size_list = decode_extents(&retrieved_data)
                 .expect("Failed to deserialize retrieved data");
             assert_eq!(offset_size_list, retrieved_offset_size_list);
             
             // Test update operation
             let new_offset_size_list = vec![(1000, 2000)];
-            let new_serialized_data = serialize_offset_size(&new_offset_size_list)
+            let new_serialized_data = encode_extents(&new_offset_size_list)
                 .expect("Failed to serialize new data");
             
             service.update_metadata("default", &key, &new_serialized_data)
@@ -59,7 +60,7 @@ mod integration_tests {
             let updated_data = service.read_metadata("default", &key)
                 .expect("Failed to retrieve updated data");
             
-            let updated_offset_size_list = deserialize_offset_size(&updated_data)
+            let updated_offset_size_list = decode_extents(&updated_data)
                 .expect("Failed to deserialize updated data");
             assert_eq!(new_offset_size_list, updated_offset_size_list);
             
@@ -180,4 +181,126 @@ mod integration_tests {
         
         println!("✓ Metadata portability test passed");
     }
-}
\ No newline at end of file
+
+    /// Put contract every backend must satisfy; run against each so they cannot drift apart.
+    fn assert_put_contract(store: &dyn MetadataStorage, user_id: &str) {
+        let key = "put_contract_object";
+        let _ = store.delete_metadata(user_id, "default", key);
+
+        let first = Metadata::from_offset_size_list(vec![(0, 10), (10, 5)]);
+        store.put_metadata(user_id, "default", key, &first).expect("First put failed");
+
+        let err = store.put_metadata(user_id, "default", key, &Metadata::from_offset_size_list(vec![(20, 1)]))
+            .expect_err("Duplicate put must fail");
+        assert_eq!(err.as_response_error().status_code(), StatusCode::CONFLICT);
+        assert_eq!(store.get_metadata(user_id, "default", key).unwrap().to_offset_size_list(), vec![(0, 10), (10, 5)]);
+
+        let previous = store.put_metadata_overwrite(user_id, "default", key, &Metadata::from_offset_size_list(vec![(30, 7)]))
+            .expect("Overwrite failed");
+        assert_eq!(previous, vec![DataChunk::from_extent((0, 10)), DataChunk::from_extent((10, 5))]);
+        assert_eq!(store.get_metadata(user_id, "default", key).unwrap().to_offset_size_list(), vec![(30, 7)]);
+
+        store.delete_metadata(user_id, "default", key).unwrap();
+        let previous = store.put_metadata_overwrite(user_id, "default", key, &first).expect("Overwrite of missing key failed");
+        assert!(previous.is_empty());
+        store.delete_metadata(user_id, "default", key).unwrap();
+
+        // A deleted key can be put again
+        store.put_metadata(user_id, "default", key, &first).expect("Put after delete failed");
+        store.delete_metadata(user_id, "default", key).unwrap();
+    }
+
+    #[test]
+    fn test_put_contract_sqlite() {
+        let store = MetadataConfig { backend: MetadataBackend::SQLite }.create_store();
+        assert_put_contract(store.as_ref(), "put_contract_user_sqlite");
+    }
+
+    #[test]
+    fn test_put_contract_mock() {
+        let store = MetadataConfig { backend: MetadataBackend::Mock }.create_store();
+        assert_put_contract(store.as_ref(), "put_contract_user_mock");
+    }
+
+    /// Deletion queue lifecycle every backend must satisfy: queue, list pending, record
+    /// failures, complete (recording free ranges), mark processed, replay spilled deletions,
+    /// forget. The queue is shared by every user, so only the counts scoped to `user_id`'s
+    /// bucket are exact.
+    fn assert_deletion_queue_contract(store: &dyn MetadataStorage, user_id: &str) {
+        let bucket = "deletion_queue_contract";
+        let queued = store.queue_deletion(user_id, bucket, "first", &[(0, 100), (100, 50)]).expect("Queue failed");
+        assert_eq!((queued.chunks, queued.bytes), (2, 150));
+        store.queue_deletion(user_id, bucket, "second", &[(1000, 10)]).expect("Queue failed");
+
+        assert_eq!(store.pending_deletion_count_for_bucket(user_id, bucket).unwrap(), 2);
+        assert!(store.pending_deletion_count().unwrap() >= 2);
+        assert!(store.pending_deletion_bytes().unwrap() >= 160);
+        assert!(store.deletion_pending(user_id, bucket, "first").unwrap());
+        assert!(!store.deletion_pending(user_id, bucket, "never-queued").unwrap());
+        let events = store.get_pending_deletions_for_bucket(user_id, bucket, 10).unwrap();
+        let keys: Vec<(&str, &[(u64, u64)])> = events.iter().map(|e| (e.key.as_str(), e.offset_size_list.as_slice())).collect();
+        assert_eq!(keys, vec![("first", &[(0, 100), (100, 50)][..]), ("second", &[(1000, 10)][..])]);
+        assert_eq!(store.get_pending_deletions_for_bucket(user_id, bucket, 1).unwrap().len(), 1);
+        let ids: Vec<i64> = events.iter().map(|e| e.id).collect();
+        let all = store.get_pending_deletions(i32::MAX).unwrap();
+        assert!(ids.iter().all(|id| all.iter().any(|e| e.id == *id && e.user_id == user_id)));
+
+        // Failed attempts are counted on the events, which stay pending
+        assert!(events.iter().all(|e| (e.attempts, e.last_error.as_deref()) == (0, None)));
+        store.record_deletion_failure(&ids[..1], "disk on fire").unwrap();
+        store.record_deletion_failure(&ids[..1], "disk still on fire").unwrap();
+        let failed: Vec<(u32, Option<String>)> = store.get_pending_deletions_for_bucket(user_id, bucket, 10).unwrap()
+            .into_iter().map(|e| (e.attempts, e.last_error)).collect();
+        assert_eq!(failed, vec![(2, Some("disk still on fire".to_string())), (0, None)]);
+        assert_eq!(store.pending_deletion_count_for_bucket(user_id, bucket).unwrap(), 2);
+
+        // Queued ranges stay live until they are freed
+        let mut live = store.live_extents(user_id, bucket).unwrap();
+        live.sort_unstable();
+        assert_eq!(live, vec![(0, 100), (100, 50), (1000, 10)]);
+        assert!(store.free_ranges_overlapping(user_id, bucket, &[(0, 150)]).unwrap().is_empty());
+
+        store.complete_deletions(user_id, bucket, &[(0, 150)], &ids[..1]).expect("Complete failed");
+        assert!(!store.deletion_pending(user_id, bucket, "first").unwrap());
+        assert_eq!(store.pending_deletion_count_for_bucket(user_id, bucket).unwrap(), 1);
+        assert_eq!(store.free_ranges(user_id, bucket).unwrap(), vec![(0, 150)]);
+        assert_eq!(store.live_extents(user_id, bucket).unwrap(), vec![(1000, 10)]);
+        // Recording a free range again changes nothing; a touching one is merged
+        store.complete_deletions(user_id, bucket, &[(0, 150)], &[]).unwrap();
+        assert_eq!(store.free_ranges(user_id, bucket).unwrap(), vec![(0, 150)]);
+        store.complete_deletions(user_id, bucket, &[(150, 50)], &[]).unwrap();
+        assert_eq!(store.free_ranges(user_id, bucket).unwrap(), vec![(0, 200)]);
+        assert_eq!(store.free_ranges_overlapping(user_id, bucket, &[(120, 10), (5000, 1)]).unwrap(), vec![(0, 200)]);
+
+        store.mark_deletion_processed(ids[1]).unwrap();
+        assert_eq!(store.pending_deletion_count_for_bucket(user_id, bucket).unwrap(), 0);
+        assert!(store.live_extents(user_id, bucket).unwrap().is_empty());
+
+        // A spilled deletion replayed twice is queued once; SQLite keeps the hashes it has seen,
+        // so each run replays a new one
+        let hash = format!("{}-{:?}", user_id, std::time::SystemTime::now());
+        let replayed = store.queue_spilled_deletion(&hash, user_id, bucket, "spilled", &[(2000, 20)]).unwrap();
+        assert_eq!(replayed.map(|s| (s.chunks, s.bytes)), Some((1, 20)));
+        assert!(store.queue_spilled_deletion(&hash, user_id, bucket, "spilled", &[(2000, 20)]).unwrap().is_none());
+        let spilled = store.get_pending_deletions_for_bucket(user_id, bucket, 10).unwrap();
+        assert_eq!(spilled.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(), vec!["spilled"]);
+        store.mark_deletion_processed(spilled[0].id).unwrap();
+
+        store.forget_free_ranges(user_id, bucket, 0).unwrap();
+        assert!(store.free_ranges(user_id, bucket).unwrap().is_empty());
+        // Nothing was processed a century ago
+        assert_eq!(store.cleanup_old_deletions(36_500, 10).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_deletion_queue_contract_sqlite() {
+        let store = MetadataConfig { backend: MetadataBackend::SQLite }.create_store();
+        assert_deletion_queue_contract(store.as_ref(), "deletion_queue_user_sqlite");
+    }
+
+    #[test]
+    fn test_deletion_queue_contract_mock() {
+        let store = MetadataConfig { backend: MetadataBackend::Mock }.create_store();
+        assert_deletion_queue_contract(store.as_ref(), "deletion_queue_user_mock");
+    }
+}
@@ -1,23 +1,21 @@
 //! Configuration for metadata storage backends
 
 use crate::metadata::{MetadataStorage, sqlite_store::SQLiteMetadataStore, mock_store::MockMetadataStore};
+use crate::metadata::shadow_store::{self, ShadowingStore};
+use crate::storage::durability::FsyncPolicy;
 use std::sync::Arc;
 use std::env;
+use std::time::Duration;
 use log::{info, warn};
 
 /// Available metadata storage backends
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub enum MetadataBackend {
+    #[default]
     SQLite,
     Mock,
 }
 
-impl Default for MetadataBackend {
-    fn default() -> Self {
-        MetadataBackend::SQLite
-    }
-}
-
 impl std::str::FromStr for MetadataBackend {
     type Err = String;
     
@@ -30,20 +28,21 @@ impl std::str::FromStr for MetadataBackend {
     }
 }
 
+impl MetadataBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetadataBackend::SQLite => "sqlite",
+            MetadataBackend::Mock => "mock",
+        }
+    }
+}
+
 /// Configuration for metadata storage
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct MetadataConfig {
     pub backend: MetadataBackend,
 }
 
-impl Default for MetadataConfig {
-    fn default() -> Self {
-        Self {
-            backend: MetadataBackend::default(),
-        }
-    }
-}
-
 impl MetadataConfig {
     /// Create a new metadata configuration from environment variables
     pub fn from_env() -> Self {
@@ -69,9 +68,22 @@ impl MetadataConfig {
         Self { backend }
     }
     
-    /// Create a metadata storage instance based on the configuration
+    /// Create a metadata storage instance based on the configuration, shadowed by the
+    /// backend `METADATA_SHADOW_BACKEND` names, if any
     pub fn create_store(&self) -> Arc<dyn MetadataStorage> {
-        match self.backend {
+        let store = Self::open(&self.backend);
+        let shadowing = ShadowConfig::from_env();
+        match shadowing.backend_for(&self.backend) {
+            Some(shadow) => {
+                info!("Shadowing the {} metadata store with {}", self.backend.as_str(), shadow.as_str());
+                Arc::new(ShadowingStore::new(store, Self::open(&shadow), shadowing.queue))
+            }
+            None => store,
+        }
+    }
+
+    fn open(backend: &MetadataBackend) -> Arc<dyn MetadataStorage> {
+        match backend {
             MetadataBackend::SQLite => {
                 info!("Creating SQLite metadata store");
                 Arc::new(SQLiteMetadataStore::new())
@@ -84,6 +96,140 @@ impl MetadataConfig {
     }
 }
 
+/// A second backend replaying metadata traffic, for trying one out before migrating to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowConfig {
+    /// `METADATA_SHADOW_BACKEND`; None when unset or not a known backend
+    pub backend: Option<MetadataBackend>,
+    /// `METADATA_SHADOW_QUEUE`: calls waiting to be replayed before more are dropped
+    pub queue: usize,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self { backend: None, queue: shadow_store::DEFAULT_QUEUE }
+    }
+}
+
+impl ShadowConfig {
+    pub fn from_env() -> Self {
+        let backend = env::var("METADATA_SHADOW_BACKEND").ok().filter(|v| !v.trim().is_empty()).and_then(|v| {
+            v.trim().parse::<MetadataBackend>()
+                .map_err(|e| warn!("{}; metadata shadowing is off", e))
+                .ok()
+        });
+        let queue = env::var("METADATA_SHADOW_QUEUE")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(shadow_store::DEFAULT_QUEUE);
+        Self { backend, queue }
+    }
+
+    /// The backend to shadow `primary` with. Two SQLite stores would share one database,
+    /// so that pair is refused.
+    pub fn backend_for(&self, primary: &MetadataBackend) -> Option<MetadataBackend> {
+        match &self.backend {
+            Some(MetadataBackend::SQLite) if *primary == MetadataBackend::SQLite => {
+                warn!("METADATA_SHADOW_BACKEND=sqlite would shadow SQLite with the same database; metadata shadowing is off");
+                None
+            }
+            backend => backend.clone(),
+        }
+    }
+}
+
+/// Connection tuning for the SQLite backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqliteConfig {
+    /// How long SQLite retries on a locked database before returning SQLITE_BUSY.
+    pub busy_timeout: Duration,
+    /// Operations slower than this are logged with their name and user/bucket.
+    pub slow_query_threshold: Duration,
+    /// `PRAGMA synchronous` value, derived from `FSYNC_POLICY`.
+    pub synchronous: &'static str,
+    /// `PRAGMA mmap_size` in bytes; None leaves SQLite's default (no memory-mapped I/O).
+    pub mmap_size: Option<u64>,
+    /// `PRAGMA cache_size`: pages when positive, KiB when negative; None leaves SQLite's default.
+    pub cache_size: Option<i64>,
+    /// `PRAGMA temp_store=MEMORY`, keeping temporary tables and sort spills off disk.
+    pub temp_store_memory: bool,
+    /// Read the object key index at startup so the first lookups find it in the page cache.
+    pub warmup: bool,
+    /// Time after which the warm-up stops, whether or not it reached the end of the index.
+    pub warmup_budget: Duration,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_millis(5000),
+            slow_query_threshold: Duration::from_millis(100),
+            synchronous: FsyncPolicy::default().sqlite_synchronous(),
+            mmap_size: None,
+            cache_size: None,
+            temp_store_memory: false,
+            warmup: false,
+            warmup_budget: Duration::from_millis(5000),
+        }
+    }
+}
+
+impl SqliteConfig {
+    /// Read `SQLITE_BUSY_TIMEOUT_MS` and `SQLITE_SLOW_QUERY_MS`, falling back to defaults, and
+    /// `synchronous` from the fsync policy. The performance settings are off unless set:
+    /// `SQLITE_MMAP_SIZE` (bytes), `SQLITE_CACHE_SIZE` (as `PRAGMA cache_size`),
+    /// `SQLITE_TEMP_STORE_MEMORY=true`, and `SQLITE_WARMUP=true` with `SQLITE_WARMUP_MAX_MS`
+    /// (default 5000).
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let millis = |name: &str| env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok()).map(Duration::from_millis);
+        let flag = |name: &str| env::var(name).map(|v| v.trim().eq_ignore_ascii_case("true")).unwrap_or(false);
+        Self {
+            busy_timeout: millis("SQLITE_BUSY_TIMEOUT_MS").unwrap_or(defaults.busy_timeout),
+            slow_query_threshold: millis("SQLITE_SLOW_QUERY_MS").unwrap_or(defaults.slow_query_threshold),
+            synchronous: FsyncPolicy::from_env().sqlite_synchronous(),
+            mmap_size: env::var("SQLITE_MMAP_SIZE").ok().and_then(|v| v.trim().parse().ok()),
+            cache_size: env::var("SQLITE_CACHE_SIZE").ok().and_then(|v| v.trim().parse().ok()).filter(|n| *n != 0),
+            temp_store_memory: flag("SQLITE_TEMP_STORE_MEMORY"),
+            warmup: flag("SQLITE_WARMUP"),
+            warmup_budget: millis("SQLITE_WARMUP_MAX_MS").unwrap_or(defaults.warmup_budget),
+        }
+    }
+}
+
+/// Group commit of `put_metadata` inserts
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupCommitConfig {
+    /// How long the committer collects rows before writing them; zero disables group commit.
+    pub interval: Duration,
+    /// Rows that trigger a write before the interval is up.
+    pub max_rows: usize,
+}
+
+impl Default for GroupCommitConfig {
+    fn default() -> Self {
+        Self { interval: Duration::ZERO, max_rows: 256 }
+    }
+}
+
+impl GroupCommitConfig {
+    /// Read `METADATA_GROUP_COMMIT_MS` (default 0, off) and `METADATA_GROUP_COMMIT_ROWS`
+    /// (default 256).
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok());
+        Self {
+            interval: parse("METADATA_GROUP_COMMIT_MS").map(Duration::from_millis).unwrap_or(defaults.interval),
+            max_rows: parse("METADATA_GROUP_COMMIT_ROWS").filter(|n| *n > 0).map(|n| n as usize).unwrap_or(defaults.max_rows),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,6 +269,24 @@ mod tests {
         assert_eq!(config.backend, MetadataBackend::SQLite);
     }
     
+    #[test]
+    fn test_shadow_backend_for() {
+        let shadow = |backend| ShadowConfig { backend, ..ShadowConfig::default() };
+        assert_eq!(ShadowConfig::default().backend_for(&MetadataBackend::SQLite), None);
+        assert_eq!(shadow(Some(MetadataBackend::Mock)).backend_for(&MetadataBackend::SQLite), Some(MetadataBackend::Mock));
+        assert_eq!(shadow(Some(MetadataBackend::SQLite)).backend_for(&MetadataBackend::Mock), Some(MetadataBackend::SQLite));
+        assert_eq!(shadow(Some(MetadataBackend::SQLite)).backend_for(&MetadataBackend::SQLite), None);
+    }
+
+    #[test]
+    fn test_sqlite_config_default() {
+        let config = SqliteConfig::default();
+        assert_eq!(config.busy_timeout, Duration::from_millis(5000));
+        assert_eq!(config.slow_query_threshold, Duration::from_millis(100));
+        // Performance pragmas are left to SQLite unless configured
+        assert_eq!((config.mmap_size, config.cache_size, config.temp_store_memory, config.warmup), (None, None, false, false));
+    }
+
     #[test]
     fn test_create_store() {
         // Test SQLite store creation
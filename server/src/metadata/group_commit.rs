@@ -0,0 +1,155 @@
+//! Group commit of metadata inserts
+//!
+//! With `METADATA_GROUP_COMMIT_MS` above zero, `put_metadata` hands its row to a committer
+//! thread instead of running its own transaction. The committer collects rows for up to that
+//! long, or until `METADATA_GROUP_COMMIT_ROWS` are waiting, and inserts them in one
+//! transaction, so one sync covers the whole batch. Each writer blocks until its batch has
+//! committed, so a returned `Ok` is as durable as without group commit.
+//!
+//! Every row runs under its own savepoint: a row that fails, such as a key that already
+//! exists, is rolled back alone and only its writer sees the error. If the batch transaction
+//! itself fails, its rows are retried one transaction each.
+
+use actix_web::Error;
+use actix_web::error::ErrorInternalServerError;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use rusqlite::Connection;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::metadata::config::GroupCommitConfig;
+use crate::metadata::sqlite_store::{db_error_in, insert_new, timed_conn};
+use crate::metadata::{Metadata, MetadataError};
+use crate::metrics;
+
+lazy_static! {
+    static ref CONFIG: GroupCommitConfig = GroupCommitConfig::from_env();
+}
+
+static QUEUE: OnceLock<Sender<PendingRow>> = OnceLock::new();
+
+/// Why one row of a batch was not inserted
+enum RowError {
+    /// A live row already holds the key
+    Exists,
+    Sqlite(rusqlite::Error),
+}
+
+type Outcome = Result<(), RowError>;
+
+/// A `put_metadata` call waiting for its batch
+struct PendingRow {
+    user: String,
+    bucket: String,
+    key: String,
+    offset_size_bytes: Vec<u8>,
+    metadata: Metadata,
+    reply: SyncSender<Outcome>,
+}
+
+/// Whether `put_metadata` goes through the committer
+pub(super) fn enabled() -> bool {
+    CONFIG.enabled()
+}
+
+fn queue() -> &'static Sender<PendingRow> {
+    QUEUE.get_or_init(|| {
+        let (sender, receiver) = channel();
+        let config = CONFIG.clone();
+        std::thread::Builder::new()
+            .name("metadata-group-commit".to_string())
+            .spawn(move || commit_loop(receiver, &config))
+            .expect("Failed to spawn metadata group commit thread");
+        info!("Metadata group commit enabled: interval={}ms max_rows={}",
+              CONFIG.interval.as_millis(), CONFIG.max_rows);
+        sender
+    })
+}
+
+/// Queue a new-object insert and block until its batch has committed.
+pub(super) fn submit(user_id: &str, bucket: &str, object_id: &str, offset_size_bytes: Vec<u8>, metadata: &Metadata) -> Result<(), Error> {
+    let (reply, outcome) = sync_channel(1);
+    let row = PendingRow {
+        user: user_id.to_string(),
+        bucket: bucket.to_string(),
+        key: object_id.to_string(),
+        offset_size_bytes,
+        metadata: metadata.clone(),
+        reply,
+    };
+    queue().send(row).map_err(|_| ErrorInternalServerError("Metadata group commit thread is not running"))?;
+    match outcome.recv() {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(RowError::Exists)) => Err(MetadataError::already_exists(bucket, object_id)),
+        Ok(Err(RowError::Sqlite(e))) => Err(db_error_in("put_metadata", e)),
+        Err(_) => Err(ErrorInternalServerError("Metadata group commit thread stopped before committing")),
+    }
+}
+
+fn commit_loop(receiver: Receiver<PendingRow>, config: &GroupCommitConfig) {
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + config.interval;
+        let mut batch = vec![first];
+        while batch.len() < config.max_rows {
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else { break };
+            match receiver.recv_timeout(left) {
+                Ok(row) => batch.push(row),
+                Err(_) => break,
+            }
+        }
+        commit_batch(batch);
+    }
+}
+
+fn commit_batch(batch: Vec<PendingRow>) {
+    metrics::inc_counter("warpdrive_metadata_group_commits_total", &[]);
+    metrics::inc_counter_by("warpdrive_metadata_group_commit_rows_total", &[], batch.len() as u64);
+    let outcomes = match write_batch(&batch) {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            warn!("Group commit of {} metadata rows failed ({}); retrying them one by one", batch.len(), e);
+            metrics::inc_counter("warpdrive_metadata_group_commit_retries_total", &[]);
+            batch.iter().map(write_single).collect()
+        }
+    };
+    for (row, outcome) in batch.into_iter().zip(outcomes) {
+        // The writer only goes away if its thread died
+        let _ = row.reply.send(outcome);
+    }
+}
+
+fn insert_row(conn: &Connection, row: &PendingRow) -> Outcome {
+    match insert_new(conn, &row.user, &row.bucket, &row.key, &row.offset_size_bytes, &row.metadata) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(RowError::Exists),
+        Err(e) => Err(RowError::Sqlite(e)),
+    }
+}
+
+/// Insert the batch in one transaction, one savepoint per row.
+fn write_batch(batch: &[PendingRow]) -> rusqlite::Result<Vec<Outcome>> {
+    let conn = timed_conn("put_metadata_batch", "*", "*");
+    let tx = conn.unchecked_transaction()?;
+    let mut outcomes = Vec::with_capacity(batch.len());
+    for row in batch {
+        tx.execute_batch("SAVEPOINT put_row")?;
+        let outcome = insert_row(&tx, row);
+        if outcome.is_ok() {
+            tx.execute_batch("RELEASE put_row")?;
+        } else {
+            tx.execute_batch("ROLLBACK TO put_row; RELEASE put_row")?;
+        }
+        outcomes.push(outcome);
+    }
+    tx.commit()?;
+    Ok(outcomes)
+}
+
+fn write_single(row: &PendingRow) -> Outcome {
+    let conn = timed_conn("put_metadata", &row.user, &row.bucket);
+    let tx = conn.unchecked_transaction().map_err(RowError::Sqlite)?;
+    insert_row(&tx, row)?;
+    tx.commit().map_err(RowError::Sqlite)
+}
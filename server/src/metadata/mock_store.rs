@@ -1,15 +1,43 @@
 //! Mock implementation of MetadataStorage trait for testing
 
-use crate::metadata::{MetadataStorage, Metadata, ObjectId, BucketStats};
+use crate::metadata::{MetadataStorage, MetadataError, Metadata, DataChunk, ObjectId, ObjectRecord, BucketStats, DeletionEvent, DeletionSummary};
+use crate::storage::segment;
 use actix_web::Error;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
+
+/// In-memory metadata: user_id -> bucket -> key -> metadata
+type UserBucketObjects = HashMap<String, HashMap<String, HashMap<String, Metadata>>>;
+/// Merged free ranges by (user, bucket)
+type FreeRanges = HashMap<(String, String), Vec<(u64, u64)>>;
+/// Last generation by (user, bucket, key)
+type Generations = HashMap<(String, String, String), u64>;
+
+/// A queued deletion and when it was processed
+struct QueuedDeletion {
+    event: DeletionEvent,
+    bytes: u64,
+    processed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct DeletionQueue {
+    events: Vec<QueuedDeletion>,
+    next_id: i64,
+    /// Hashes of the spilled deletions replayed so far
+    replayed: HashSet<String>,
+}
 
 /// Mock implementation of MetadataStorage for testing
 pub struct MockMetadataStore {
-    data: Arc<Mutex<HashMap<String, HashMap<String, HashMap<String, Metadata>>>>>,
+    data: Arc<Mutex<UserBucketObjects>>,
     buckets: Arc<Mutex<HashMap<String, HashSet<String>>>>,
+    deletions: Arc<Mutex<DeletionQueue>>,
+    /// (user_id, bucket) -> free ranges by offset
+    free: Arc<Mutex<FreeRanges>>,
+    /// Last generation of every key written, as object_generations in SQLite
+    generations: Arc<Mutex<Generations>>,
 }
 
 impl MockMetadataStore {
@@ -17,12 +45,35 @@ impl MockMetadataStore {
         Self {
             data: Arc::new(Mutex::new(HashMap::new())),
             buckets: Arc::new(Mutex::new(HashMap::new())),
+            deletions: Arc::new(Mutex::new(DeletionQueue::default())),
+            free: Arc::new(Mutex::new(HashMap::new())),
+            generations: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     pub fn clear(&self) {
         self.data.lock().unwrap().clear();
         self.buckets.lock().unwrap().clear();
+        *self.deletions.lock().unwrap() = DeletionQueue::default();
+        self.free.lock().unwrap().clear();
+        self.generations.lock().unwrap().clear();
+    }
+
+    /// Record a write of `key` and return its generation: one past the last one, and at
+    /// least `at_least`
+    fn next_generation(&self, user_id: &str, bucket: &str, key: &str, at_least: u64) -> u64 {
+        let mut generations = self.generations.lock().unwrap();
+        let last = generations.entry((user_id.to_string(), bucket.to_string(), key.to_string())).or_insert(0);
+        *last = (*last + 1).max(at_least);
+        *last
+    }
+
+    /// Unprocessed events matching `filter`, oldest first
+    fn pending(&self, filter: impl Fn(&DeletionEvent) -> bool) -> Vec<DeletionEvent> {
+        self.deletions.lock().unwrap().events.iter()
+            .filter(|d| d.processed_at.is_none() && filter(&d.event))
+            .map(|d| d.event.clone())
+            .collect()
     }
 
     pub fn user_count(&self) -> usize {
@@ -35,6 +86,25 @@ impl MockMetadataStore {
             .map(|u| u.values().map(|b| b.len()).sum())
             .unwrap_or(0)
     }
+
+    /// Rows of a bucket numbered from 1 in key order, standing in for store row ids
+    fn records(&self, user_id: &str, bucket: &str) -> Vec<ObjectRecord> {
+        let data = self.data.lock().unwrap();
+        let mut objects: Vec<(&String, &Metadata)> = data
+            .get(user_id)
+            .and_then(|u| u.get(bucket))
+            .map(|b| b.iter().collect())
+            .unwrap_or_default();
+        objects.sort_by(|a, b| a.0.cmp(b.0));
+        objects.into_iter().enumerate().map(|(i, (key, metadata))| ObjectRecord {
+            row_id: i as i64 + 1,
+            key: key.clone(),
+            is_latest: true,
+            parts_manifest: None,
+            created_at: None,
+            metadata: metadata.clone(),
+        }).collect()
+    }
 }
 
 impl Default for MockMetadataStore {
@@ -49,12 +119,23 @@ impl MetadataStorage for MockMetadataStore {
             .entry(bucket.to_string()).or_default();
 
         if bucket_data.contains_key(object_id) {
-            return Err(actix_web::error::ErrorBadRequest("Key already exists"));
+            return Err(MetadataError::already_exists(bucket, object_id));
         }
-        bucket_data.insert(object_id.to_string(), metadata.clone());
+        let generation = self.next_generation(user_id, bucket, object_id, 1);
+        bucket_data.insert(object_id.to_string(), Metadata { generation, ..metadata.clone() });
         Ok(())
     }
 
+    fn put_metadata_overwrite(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<Vec<DataChunk>, Error> {
+        let mut data = self.data.lock().unwrap();
+        let generation = self.next_generation(user_id, bucket, object_id, 1);
+        let previous = data
+            .entry(user_id.to_string()).or_default()
+            .entry(bucket.to_string()).or_default()
+            .insert(object_id.to_string(), Metadata { generation, ..metadata.clone() });
+        Ok(previous.map(|m| m.chunks).unwrap_or_default())
+    }
+
     fn get_metadata(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<Metadata, Error> {
         let data = self.data.lock().unwrap();
         data.get(user_id)
@@ -108,7 +189,11 @@ impl MetadataStorage for MockMetadataStore {
             .and_then(|u| u.get_mut(bucket))
             .and_then(|b| b.get_mut(object_id));
         match entry {
-            Some(e) => { *e = metadata.clone(); Ok(()) }
+            Some(e) => {
+                let generation = self.next_generation(user_id, bucket, object_id, e.generation + 1);
+                *e = Metadata { generation, ..metadata.clone() };
+                Ok(())
+            }
             None => Err(actix_web::error::ErrorNotFound(format!(
                 "No data found for key: {}, The key does not exist", object_id
             ))),
@@ -123,8 +208,9 @@ impl MetadataStorage for MockMetadataStore {
             .and_then(|b| b.remove(old_object_id));
         match metadata {
             Some(m) => {
+                let generation = self.next_generation(user_id, bucket, new_object_id, m.generation);
                 data.get_mut(user_id).unwrap().get_mut(bucket).unwrap()
-                    .insert(new_object_id.to_string(), m);
+                    .insert(new_object_id.to_string(), Metadata { generation, ..m });
                 Ok(())
             }
             None => Err(actix_web::error::ErrorNotFound(format!(
@@ -133,8 +219,18 @@ impl MetadataStorage for MockMetadataStore {
         }
     }
 
-    fn queue_deletion(&self, _user_id: &str, _bucket: &str, _key: &str, _offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
-        Ok(())
+    fn touch_metadata(&self, user_id: &str, bucket: &str, object_id: &str, last_modified: &str) -> Result<(), Error> {
+        let mut data = self.data.lock().unwrap();
+        let entry = data
+            .get_mut(user_id)
+            .and_then(|u| u.get_mut(bucket))
+            .and_then(|b| b.get_mut(object_id));
+        match entry {
+            Some(e) => { e.last_modified = Some(last_modified.to_string()); Ok(()) }
+            None => Err(actix_web::error::ErrorNotFound(format!(
+                "No data found for key: {}, The key does not exist", object_id
+            ))),
+        }
     }
 
     fn list_buckets_with_stats(&self, user_id: &str) -> Result<Vec<BucketStats>, Error> {
@@ -202,6 +298,153 @@ impl MetadataStorage for MockMetadataStore {
             .unwrap_or((0, 0));
         Ok((count, bytes))
     }
+
+    fn get_full_record(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<Vec<ObjectRecord>, Error> {
+        Ok(self.records(user_id, bucket).into_iter().filter(|r| r.key == object_id).collect())
+    }
+
+    fn list_full_records(&self, user_id: &str, bucket: &str, after_row_id: i64, limit: usize) -> Result<Vec<ObjectRecord>, Error> {
+        Ok(self.records(user_id, bucket).into_iter().filter(|r| r.row_id > after_row_id).take(limit).collect())
+    }
+
+    // No clones share ranges here, so every non-empty range is queued
+    fn queue_deletion(&self, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<DeletionSummary, Error> {
+        let ranges: Vec<(u64, u64)> = offset_size_list.iter().copied().filter(|(_, size)| *size > 0).collect();
+        let summary = DeletionSummary { chunks: ranges.len(), bytes: ranges.iter().map(|(_, size)| size).sum() };
+        if summary.queued() {
+            let mut queue = self.deletions.lock().unwrap();
+            queue.next_id += 1;
+            let event = DeletionEvent {
+                id: queue.next_id,
+                user_id: user_id.to_string(),
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+                offset_size_list: ranges,
+                created_at: Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                attempts: 0,
+                last_error: None,
+            };
+            queue.events.push(QueuedDeletion { event, bytes: summary.bytes, processed_at: None });
+        }
+        Ok(summary)
+    }
+
+    fn queue_spilled_deletion(&self, hash: &str, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<Option<DeletionSummary>, Error> {
+        if !self.deletions.lock().unwrap().replayed.insert(hash.to_string()) {
+            return Ok(None);
+        }
+        self.queue_deletion(user_id, bucket, key, offset_size_list).map(Some)
+    }
+
+    fn get_pending_deletions(&self, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        Ok(self.pending(|_| true).into_iter().take(limit.max(0) as usize).collect())
+    }
+
+    fn get_pending_deletions_for_bucket(&self, user_id: &str, bucket: &str, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        let events = self.pending(|e| e.user_id == user_id && e.bucket == bucket);
+        Ok(events.into_iter().take(limit.max(0) as usize).collect())
+    }
+
+    fn deletion_pending(&self, user_id: &str, bucket: &str, key: &str) -> Result<bool, Error> {
+        Ok(!self.pending(|e| e.user_id == user_id && e.bucket == bucket && e.key == key).is_empty())
+    }
+
+    fn pending_deletion_count(&self) -> Result<u64, Error> {
+        Ok(self.pending(|_| true).len() as u64)
+    }
+
+    fn pending_deletion_count_for_bucket(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        Ok(self.pending(|e| e.user_id == user_id && e.bucket == bucket).len() as u64)
+    }
+
+    fn pending_deletion_bytes(&self) -> Result<u64, Error> {
+        Ok(self.deletions.lock().unwrap().events.iter().filter(|d| d.processed_at.is_none()).map(|d| d.bytes).sum())
+    }
+
+    fn mark_deletion_processed(&self, id: i64) -> Result<(), Error> {
+        let mut queue = self.deletions.lock().unwrap();
+        if let Some(deletion) = queue.events.iter_mut().find(|d| d.event.id == id) {
+            deletion.processed_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    fn record_deletion_failure(&self, ids: &[i64], error: &str) -> Result<(), Error> {
+        let mut queue = self.deletions.lock().unwrap();
+        for deletion in queue.events.iter_mut().filter(|d| d.processed_at.is_none() && ids.contains(&d.event.id)) {
+            deletion.event.attempts += 1;
+            deletion.event.last_error = Some(error.to_string());
+        }
+        Ok(())
+    }
+
+    fn complete_deletions(&self, user_id: &str, bucket: &str, freed: &[(u64, u64)], ids: &[i64]) -> Result<(), Error> {
+        // Both locks are held so the ranges and the events change together
+        let mut queue = self.deletions.lock().unwrap();
+        let mut free = self.free.lock().unwrap();
+        let ranges = free.entry((user_id.to_string(), bucket.to_string())).or_default();
+        ranges.extend(freed.iter().copied().filter(|(_, size)| *size > 0));
+        *ranges = merge_within_segments(ranges);
+        let now = Utc::now();
+        for deletion in queue.events.iter_mut().filter(|d| ids.contains(&d.event.id)) {
+            deletion.processed_at = Some(now);
+        }
+        Ok(())
+    }
+
+    fn cleanup_old_deletions(&self, retention_days: u32, _batch: usize) -> Result<usize, Error> {
+        let cutoff = Utc::now() - Duration::days(retention_days as i64);
+        let mut queue = self.deletions.lock().unwrap();
+        let before = queue.events.len();
+        queue.events.retain(|d| d.processed_at.is_none_or(|at| at >= cutoff));
+        Ok(before - queue.events.len())
+    }
+
+    fn free_ranges(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        Ok(self.free.lock().unwrap().get(&(user_id.to_string(), bucket.to_string())).cloned().unwrap_or_default())
+    }
+
+    fn free_ranges_overlapping(&self, user_id: &str, bucket: &str, ranges: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error> {
+        let free = self.free_ranges(user_id, bucket)?;
+        Ok(free.into_iter()
+            .filter(|&(start, size)| ranges.iter().any(|&(offset, len)| start < offset + len && start + size > offset))
+            .collect())
+    }
+
+    fn forget_free_ranges(&self, user_id: &str, bucket: &str, segment: u32) -> Result<(), Error> {
+        if let Some(ranges) = self.free.lock().unwrap().get_mut(&(user_id.to_string(), bucket.to_string())) {
+            ranges.retain(|(offset, _)| segment::segment_of(*offset) != segment);
+        }
+        Ok(())
+    }
+
+    fn live_extents(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        let mut extents: Vec<(u64, u64)> = self.data.lock().unwrap()
+            .get(user_id)
+            .and_then(|u| u.get(bucket))
+            .map(|b| b.values().flat_map(|m| m.to_offset_size_list()).collect())
+            .unwrap_or_default();
+        for event in self.pending(|e| e.user_id == user_id && e.bucket == bucket) {
+            extents.extend(event.offset_size_list);
+        }
+        Ok(extents)
+    }
+}
+
+/// Sort `ranges` and merge the ones that overlap or touch within one segment
+fn merge_within_segments(ranges: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(sorted.len());
+    for (offset, size) in sorted {
+        match merged.last_mut() {
+            Some((start, len)) if offset <= *start + *len && segment::segment_of(offset) == segment::segment_of(*start) => {
+                *len = (*start + *len).max(offset + size) - *start;
+            }
+            _ => merged.push((offset, size)),
+        }
+    }
+    merged
 }
 
 #[cfg(test)]
@@ -268,4 +511,19 @@ mod tests {
         store.delete_bucket(user_id, "my-bucket").unwrap();
         assert!(!store.bucket_exists(user_id, "my-bucket").unwrap());
     }
+
+    #[test]
+    fn test_mock_cleanup_drops_only_processed_events() {
+        let store = MockMetadataStore::new();
+        store.queue_deletion("cleanup_user", "b", "done", &[(0, 10)]).unwrap();
+        store.queue_deletion("cleanup_user", "b", "waiting", &[(10, 10)]).unwrap();
+        store.queue_deletion("cleanup_user", "b", "inline", &[]).unwrap();
+        let done = store.get_pending_deletions(10).unwrap()[0].id;
+        store.mark_deletion_processed(done).unwrap();
+
+        assert_eq!(store.cleanup_old_deletions(1, 10).unwrap(), 0);
+        assert_eq!(store.cleanup_old_deletions(0, 10).unwrap(), 1);
+        let pending = store.get_pending_deletions(10).unwrap();
+        assert_eq!(pending.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(), vec!["waiting"]);
+    }
 }
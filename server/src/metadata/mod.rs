@@ -3,21 +3,119 @@
 pub mod sqlite_store;
 pub mod mock_store;
 pub mod config;
+pub mod shadow_store;
+mod group_commit;
 
 #[cfg(test)]
 mod comprehensive_test;
 
-use actix_web::Error;
+use actix_web::{Error, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::storage::segment;
+use crate::util::compression::Codec;
+
+/// Typed failures of the metadata contract, shared by every backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataError {
+    /// `put_metadata` found a live object under the key; maps to 409 Conflict.
+    AlreadyExists { bucket: String, key: String },
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataError::AlreadyExists { bucket, key } => {
+                write!(f, "Key already exists: {} in bucket: {}", key, bucket)
+            }
+        }
+    }
+}
+
+impl ResponseError for MetadataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            MetadataError::AlreadyExists { .. } => StatusCode::CONFLICT,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+impl MetadataError {
+    pub fn already_exists(bucket: &str, key: &str) -> Error {
+        MetadataError::AlreadyExists { bucket: bucket.to_string(), key: key.to_string() }.into()
+    }
+}
 
 /// Represents the location and properties of stored data chunks
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct DataChunk {
+    /// Position inside the segment file
     pub offset: u64,
     pub size: u64,
+    /// Segment file holding the chunk; 0, the original `{bucket}.bin`, for legacy data
+    #[serde(default)]
+    pub segment: u32,
+    /// CRC32C of the stored bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<u32>,
+    /// Coding of the stored bytes; `None` when they are stored as written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<Codec>,
+    /// Size before `encoding` was applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_size: Option<u64>,
+}
+
+impl DataChunk {
+    /// Chunk at a storage `(offset, size)` extent, whose offset carries the segment
+    pub fn from_extent((offset, size): (u64, u64)) -> Self {
+        Self {
+            offset: segment::offset_in_segment(offset),
+            size,
+            segment: segment::segment_of(offset),
+            checksum: None,
+            encoding: None,
+            raw_size: None,
+        }
+    }
+
+    /// Storage `(offset, size)` extent of the chunk
+    pub fn extent(&self) -> (u64, u64) {
+        (segment::address(self.segment, self.offset), self.size)
+    }
+
+    /// The same bytes copied to storage extent `to`, keeping checksum and encoding
+    pub fn relocated(&self, to: (u64, u64)) -> Self {
+        Self { checksum: self.checksum, encoding: self.encoding, raw_size: self.raw_size, ..Self::from_extent(to) }
+    }
+}
+
+/// Optional identity attached to one file of a v2 native payload
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileAttrs {
+    pub name: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl FileAttrs {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.content_type.is_none()
+    }
 }
 
+/// Property holding the file lengths of an inline object with more than one file
+const INLINE_FILES_PROPERTY: &str = "inline.files";
+
+/// Prefix of the properties holding a native object's `X-Warp-Meta-*` metadata
+const META_PROPERTY_PREFIX: &str = "meta.";
+
 /// Full metadata for a stored S3 object
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Metadata {
@@ -30,7 +128,8 @@ pub struct Metadata {
     pub size: u64,
     /// MIME type stored on PUT.
     pub content_type: Option<String>,
-    /// RFC 2616 date string set on PUT, e.g. `"Thu, 19 Jun 2026 00:00:00 GMT"`.
+    /// Time of the last write or touch as stored, see [`last_modified_now`]; headers carry it
+    /// through [`http_date`].
     pub last_modified: Option<String>,
     /// `x-amz-meta-*` headers stored as `{"key": "value"}` (header name without prefix).
     pub user_metadata: HashMap<String, String>,
@@ -51,16 +150,30 @@ pub struct Metadata {
     pub checksum_value: Option<String>,
     /// Checksum type: "COMPOSITE" or "FULL_OBJECT" (empty for non-checksum objects).
     pub checksum_type: Option<String>,
+    /// Time the object expires, in the form of [`last_modified_now`]; set on write with
+    /// `X-Warp-Expires` / `X-Warp-Expire-At` (see `service::expiry`).
+    pub expire_at: Option<String>,
+    /// Data of a small object kept in the row itself instead of the bucket file; such an
+    /// object has no chunks (see `service::inline_objects`). The data of several native files
+    /// is concatenated, with their lengths in the `inline.files` property.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<Vec<u8>>,
+    /// Writes of the key so far, assigned by the store (see `service::validators`); 0 until
+    /// the object is stored.
+    #[serde(default)]
+    pub generation: u64,
 }
 
 impl Metadata {
     /// Create from an offset-size list (old-API path); S3 fields default to empty/None.
     pub fn from_offset_size_list(offset_size_list: Vec<(u64, u64)>) -> Self {
-        let size: u64 = offset_size_list.iter().map(|(_, s)| s).sum();
-        let chunks = offset_size_list
-            .into_iter()
-            .map(|(offset, size)| DataChunk { offset, size })
-            .collect();
+        Self::from_chunks(offset_size_list.into_iter().map(DataChunk::from_extent).collect())
+    }
+
+    /// Create from decoded chunks, sized by their bytes before any encoding; S3 fields
+    /// default to empty/None.
+    pub fn from_chunks(chunks: Vec<DataChunk>) -> Self {
+        let size: u64 = chunks.iter().map(|c| c.raw_size.unwrap_or(c.size)).sum();
         Self {
             chunks,
             properties: HashMap::new(),
@@ -77,14 +190,114 @@ impl Metadata {
             checksum_algorithm: None,
             checksum_value: None,
             checksum_type: None,
+            expire_at: None,
+            inline_data: None,
+            generation: 0,
         }
     }
 
+    /// Create for data stored inline, sized by its bytes; S3 fields default to empty/None.
+    pub fn inline(data: Vec<u8>) -> Self {
+        Self { size: data.len() as u64, inline_data: Some(data), ..Self::from_chunks(Vec::new()) }
+    }
+
+    /// Create for native files stored inline, one after another.
+    pub fn inline_files_of(files: &[&[u8]]) -> Self {
+        let mut metadata = Self::inline(files.concat());
+        metadata.set_inline_lengths(files.iter().map(|f| f.len()).collect());
+        metadata
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.inline_data.is_some()
+    }
+
+    /// The files of an inline object, split at the recorded lengths; empty for other objects.
+    pub fn inline_files(&self) -> Vec<&[u8]> {
+        let Some(data) = &self.inline_data else {
+            return Vec::new();
+        };
+        let lengths: Vec<usize> = self.properties.get(INLINE_FILES_PROPERTY)
+            .and_then(|json| serde_json::from_str(json).ok())
+            .filter(|lengths: &Vec<usize>| lengths.iter().sum::<usize>() == data.len())
+            .unwrap_or_else(|| vec![data.len()]);
+        let mut rest = data.as_slice();
+        lengths.into_iter().map(|len| {
+            let (file, tail) = rest.split_at(len);
+            rest = tail;
+            file
+        }).collect()
+    }
+
+    /// Record the file lengths of `inline_data`; a single file needs no record.
+    pub fn set_inline_lengths(&mut self, lengths: Vec<usize>) {
+        match lengths.len() {
+            0 | 1 => self.properties.remove(INLINE_FILES_PROPERTY),
+            _ => self.properties.insert(INLINE_FILES_PROPERTY.to_string(), serde_json::to_string(&lengths).unwrap_or_default()),
+        };
+    }
+
     pub fn to_offset_size_list(&self) -> Vec<(u64, u64)> {
-        self.chunks.iter().map(|c| (c.offset, c.size)).collect()
+        self.chunks.iter().map(DataChunk::extent).collect()
+    }
+
+    /// Store per-chunk file attributes as `chunk.{i}.name` / `chunk.{i}.content_type` properties,
+    /// replacing any previously recorded ones.
+    pub fn set_file_attrs(&mut self, files: &[FileAttrs]) {
+        self.properties.retain(|k, _| !k.starts_with("chunk."));
+        for (i, attrs) in files.iter().enumerate() {
+            if let Some(name) = &attrs.name {
+                self.properties.insert(format!("chunk.{}.name", i), name.clone());
+            }
+            if let Some(content_type) = &attrs.content_type {
+                self.properties.insert(format!("chunk.{}.content_type", i), content_type.clone());
+            }
+        }
+    }
+
+    /// Store `entries` (from `X-Warp-Meta-*` headers) as `meta.{name}` properties, replacing
+    /// any previously recorded ones.
+    pub fn set_meta_properties(&mut self, entries: &HashMap<String, String>) {
+        self.properties.retain(|k, _| !k.starts_with(META_PROPERTY_PREFIX));
+        for (name, value) in entries {
+            self.properties.insert(format!("{}{}", META_PROPERTY_PREFIX, name), value.clone());
+        }
+    }
+
+    /// The `X-Warp-Meta-*` metadata recorded with [`Self::set_meta_properties`], by name.
+    pub fn meta_properties(&self) -> BTreeMap<String, String> {
+        self.properties.iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix(META_PROPERTY_PREFIX)?.to_string(), v.clone())))
+            .collect()
+    }
+
+    /// Per-chunk file attributes, one entry per chunk (empty for chunks written as v1); each
+    /// inline file counts as a chunk.
+    pub fn file_attrs(&self) -> Vec<FileAttrs> {
+        let count = if self.is_inline() { self.inline_files().len() } else { self.chunks.len() };
+        (0..count)
+            .map(|i| FileAttrs {
+                name: self.properties.get(&format!("chunk.{}.name", i)).cloned(),
+                content_type: self.properties.get(&format!("chunk.{}.content_type", i)).cloned(),
+            })
+            .collect()
     }
 }
 
+/// The current time as stored in `last_modified`: ISO 8601 in UTC with milliseconds, e.g.
+/// `2026-07-01T12:00:00.123Z`, as S3 listings show it.
+pub fn last_modified_now() -> String {
+    chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+/// A stored `last_modified` in the RFC 7231 form of the `Last-Modified` header
+/// (`Thu, 01 Jul 2026 12:00:00 GMT`); values that do not parse are passed through.
+pub fn http_date(last_modified: &str) -> String {
+    chrono::DateTime::parse_from_rfc3339(last_modified)
+        .map(|dt| dt.with_timezone(&chrono::Utc).format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+        .unwrap_or_else(|_| last_modified.to_string())
+}
+
 /// Per-bucket info for list-buckets
 #[derive(Debug, Clone)]
 pub struct BucketStats {
@@ -94,19 +307,73 @@ pub struct BucketStats {
     pub total_size: u64,
 }
 
+/// Storage a delete handed to the deletion worker: the ranges no other object still
+/// references, and their total size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DeletionSummary {
+    pub chunks: usize,
+    pub bytes: u64,
+}
+
+impl DeletionSummary {
+    /// Whether anything was queued; inline, empty and fully shared objects queue nothing
+    pub fn queued(&self) -> bool {
+        self.chunks > 0
+    }
+}
+
+/// Ranges queued for the deletion worker by one delete or overwrite
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeletionEvent {
+    pub id: i64,
+    pub user_id: String,
+    pub bucket: String,
+    pub key: String,
+    pub offset_size_list: Vec<(u64, u64)>,
+    pub created_at: String,
+    /// Passes that failed to free the event; it stays pending and is retried
+    pub attempts: u32,
+    /// Error of the last failed pass
+    pub last_error: Option<String>,
+}
+
+/// One stored row of an object with its internal columns, for debugging dumps
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectRecord {
+    /// Store row id; rows of a bucket are paged in this order
+    pub row_id: i64,
+    pub key: String,
+    /// False for noncurrent versions
+    pub is_latest: bool,
+    /// JSON part list of a multipart object
+    pub parts_manifest: Option<String>,
+    /// When the row was written, in the form of [`last_modified_now`]; `None` for rows older
+    /// than the column
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
 pub type ObjectId = String;
 pub type UserId = String;
 
 /// Trait defining the metadata storage interface
 pub trait MetadataStorage: Send + Sync {
+    /// Create a new object; fails with [`MetadataError::AlreadyExists`] if the key is live.
     fn put_metadata(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<(), Error>;
+    /// Atomically create or replace an object, returning the chunks of the replaced
+    /// (non-versioned) row so the caller can queue them for deletion.
+    fn put_metadata_overwrite(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<Vec<DataChunk>, Error>;
     fn get_metadata(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<Metadata, Error>;
     fn delete_metadata(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<(), Error>;
     fn list_objects(&self, user_id: &str, bucket: &str) -> Result<Vec<ObjectId>, Error>;
     fn object_exists(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<bool, Error>;
     fn update_metadata(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<(), Error>;
     fn update_object_id(&self, user_id: &str, bucket: &str, old_object_id: &str, new_object_id: &str) -> Result<(), Error>;
-    fn queue_deletion(&self, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error>;
+    /// Set the `last_modified` of the live object to `last_modified` without touching its data;
+    /// 404 when the key is not live.
+    fn touch_metadata(&self, user_id: &str, bucket: &str, object_id: &str, last_modified: &str) -> Result<(), Error>;
     fn list_buckets_with_stats(&self, user_id: &str) -> Result<Vec<BucketStats>, Error>;
 
     // Bucket lifecycle
@@ -117,6 +384,49 @@ pub trait MetadataStorage: Send + Sync {
 
     /// Returns (object_count, total_bytes) for a single bucket.
     fn bucket_object_stats(&self, user_id: &str, bucket: &str) -> Result<(u64, u64), Error>;
+
+    // Debug reads
+    /// Every row of `object_id`, delete markers and noncurrent versions included, newest first.
+    fn get_full_record(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<Vec<ObjectRecord>, Error>;
+    /// Up to `limit` rows of the bucket with a row id above `after_row_id`, in row id order.
+    fn list_full_records(&self, user_id: &str, bucket: &str, after_row_id: i64, limit: usize) -> Result<Vec<ObjectRecord>, Error>;
+
+    // Deletion queue: the deletion worker frees what deletes queue here
+    /// Drop one reference to each range and queue the ranges nobody references any more.
+    fn queue_deletion(&self, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<DeletionSummary, Error>;
+    /// [`Self::queue_deletion`] for a deletion replayed from the spill file, identified by
+    /// `hash`; None when that deletion was replayed before.
+    fn queue_spilled_deletion(&self, hash: &str, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<Option<DeletionSummary>, Error>;
+    /// Up to `limit` unprocessed events of every user, oldest first.
+    fn get_pending_deletions(&self, limit: i32) -> Result<Vec<DeletionEvent>, Error>;
+    fn get_pending_deletions_for_bucket(&self, user_id: &str, bucket: &str, limit: i32) -> Result<Vec<DeletionEvent>, Error>;
+    /// Whether `key` has a deletion queued that the worker has not processed yet.
+    fn deletion_pending(&self, user_id: &str, bucket: &str, key: &str) -> Result<bool, Error>;
+    fn pending_deletion_count(&self) -> Result<u64, Error>;
+    fn pending_deletion_count_for_bucket(&self, user_id: &str, bucket: &str) -> Result<u64, Error>;
+    /// Total size of the ranges of unprocessed events.
+    fn pending_deletion_bytes(&self) -> Result<u64, Error>;
+    fn mark_deletion_processed(&self, id: i64) -> Result<(), Error>;
+    /// Count a failed attempt at freeing each unprocessed event of `ids` and keep `error` as
+    /// its last error; the events stay pending.
+    fn record_deletion_failure(&self, ids: &[i64], error: &str) -> Result<(), Error>;
+    /// Record `freed` as free and mark the events `ids` processed, atomically. Recording a
+    /// range already free changes nothing.
+    fn complete_deletions(&self, user_id: &str, bucket: &str, freed: &[(u64, u64)], ids: &[i64]) -> Result<(), Error>;
+    /// Drop events processed more than `retention_days` ago, `batch` at a time; returns how
+    /// many were dropped.
+    fn cleanup_old_deletions(&self, retention_days: u32, batch: usize) -> Result<usize, Error>;
+
+    // Free space of bucket files
+    /// Every recorded free range of a bucket, by offset.
+    fn free_ranges(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error>;
+    /// Recorded free ranges overlapping any of `ranges`, by offset.
+    fn free_ranges_overlapping(&self, user_id: &str, bucket: &str, ranges: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error>;
+    /// Drop the free ranges of an unlinked segment file.
+    fn forget_free_ranges(&self, user_id: &str, bucket: &str, segment: u32) -> Result<(), Error>;
+    /// Extents whose bytes must stay readable: those of stored objects and uploaded parts
+    /// and of unprocessed deletion events. A sealed segment with none can be unlinked.
+    fn live_extents(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error>;
 }
 
 #[cfg(test)]
@@ -132,12 +442,40 @@ mod tests {
         assert_eq!(metadata.to_offset_size_list(), offset_size_list);
     }
 
+    #[test]
+    fn test_file_attrs_round_trip() {
+        let mut metadata = Metadata::from_offset_size_list(vec![(0, 4), (4, 4), (8, 4)]);
+        metadata.set_file_attrs(&[
+            FileAttrs { name: Some("a.txt".to_string()), content_type: Some("text/plain".to_string()) },
+            FileAttrs::default(),
+            FileAttrs { name: Some("c.bin".to_string()), content_type: None },
+        ]);
+        let attrs = metadata.file_attrs();
+        assert_eq!(attrs.len(), 3);
+        assert_eq!(attrs[0].name.as_deref(), Some("a.txt"));
+        assert!(attrs[1].is_empty());
+        assert_eq!(attrs[2].name.as_deref(), Some("c.bin"));
+
+        metadata.set_file_attrs(&[]);
+        assert!(metadata.file_attrs().iter().all(FileAttrs::is_empty));
+    }
+
     #[test]
     fn test_data_chunk_equality() {
-        let chunk1 = DataChunk { offset: 100, size: 200 };
-        let chunk2 = DataChunk { offset: 100, size: 200 };
-        let chunk3 = DataChunk { offset: 100, size: 300 };
+        let chunk1 = DataChunk::from_extent((100, 200));
+        let chunk2 = DataChunk::from_extent((100, 200));
+        let chunk3 = DataChunk::from_extent((100, 300));
         assert_eq!(chunk1, chunk2);
         assert_ne!(chunk1, chunk3);
+        assert_ne!(chunk1, DataChunk { checksum: Some(1), ..chunk2 });
+    }
+
+    #[test]
+    fn test_relocated_chunk_keeps_attributes() {
+        let chunk = DataChunk { checksum: Some(7), encoding: Some(Codec::Zstd), raw_size: Some(900), ..DataChunk::from_extent((100, 200)) };
+        let moved = chunk.relocated((segment::address(2, 64), 200));
+        assert_eq!((moved.segment, moved.offset, moved.size), (2, 64, 200));
+        assert_eq!((moved.checksum, moved.encoding, moved.raw_size), (Some(7), Some(Codec::Zstd), Some(900)));
+        assert_eq!(Metadata::from_chunks(vec![moved, DataChunk::from_extent((0, 10))]).size, 910);
     }
 }
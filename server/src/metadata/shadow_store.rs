@@ -0,0 +1,327 @@
+//! Shadowing a metadata backend for migration testing
+//!
+//! `ShadowingStore` serves every call from the primary backend and replays it against a
+//! shadow backend on a background thread, so a candidate backend sees production traffic
+//! without being able to change a response. Writes reach the primary first and are then
+//! replayed in the same order; reads are replayed too and their results compared with the
+//! primary's: existence, chunk lists and sizes, and key and bucket listings, a failure on
+//! one side only counting as a difference as well. Every difference is logged, counted in
+//! `warpdrive_shadow_divergence_total` and stored in the `shadow_divergence` table, which
+//! `GET /admin/shadow/report` summarizes.
+//!
+//! Only object and bucket operations are shadowed. The deletion queue and free ranges are
+//! keyed by the primary's row ids, so they stay with the primary, and neither backend sees
+//! what is written around the trait (compaction, tiering and other maintenance go straight
+//! to SQLite). A shadow started empty reports every object written before it.
+//!
+//! Replays wait in a queue of `METADATA_SHADOW_QUEUE` calls; when it is full the call is not
+//! replayed and `warpdrive_shadow_dropped_total` counts it, rather than slowing the request.
+
+use actix_web::Error;
+use log::{error, warn};
+use std::fmt::Debug;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metadata::{BucketStats, DataChunk, DeletionEvent, DeletionSummary, Metadata, MetadataStorage, ObjectId, ObjectRecord};
+use crate::metrics;
+
+/// Calls waiting for the shadow when `METADATA_SHADOW_QUEUE` is unset
+pub const DEFAULT_QUEUE: usize = 10_000;
+
+/// Divergences `GET /admin/shadow/report` lists when no `limit` is given
+pub const DEFAULT_REPORT_LIMIT: usize = 100;
+
+type Replay = Box<dyn FnOnce(&dyn MetadataStorage) + Send>;
+
+/// What one call returned, reduced to what both backends must agree on: the compared value,
+/// or the status of the error
+type Outcome<V> = Result<V, u16>;
+
+fn outcome<T, V>(result: &Result<T, Error>, project: fn(&T) -> V) -> Outcome<V> {
+    result.as_ref().map(project).map_err(|e| e.as_response_error().status_code().as_u16())
+}
+
+fn chunk_list(metadata: &Metadata) -> (u64, Vec<DataChunk>) {
+    (metadata.size, metadata.chunks.clone())
+}
+
+// Takes `&Vec` to fit `fn(&T) -> V` for `T = Vec<String>`
+#[allow(clippy::ptr_arg)]
+fn sorted(names: &Vec<String>) -> Vec<String> {
+    let mut names = names.clone();
+    names.sort();
+    names
+}
+
+fn same<T: Clone>(value: &T) -> T {
+    value.clone()
+}
+
+/// The call a difference was found on
+struct Call {
+    op: &'static str,
+    user_id: String,
+    bucket: String,
+    key: Option<String>,
+}
+
+pub struct ShadowingStore {
+    primary: Arc<dyn MetadataStorage>,
+    replays: SyncSender<Replay>,
+}
+
+impl ShadowingStore {
+    /// Serve from `primary` and replay into `shadow`, at most `queue` calls behind
+    pub fn new(primary: Arc<dyn MetadataStorage>, shadow: Arc<dyn MetadataStorage>, queue: usize) -> Self {
+        let (replays, pending) = mpsc::sync_channel(queue.max(1));
+        std::thread::Builder::new()
+            .name("metadata-shadow".to_string())
+            .spawn(move || replay_all(shadow, pending))
+            .expect("failed to start the metadata shadow thread");
+        Self { primary, replays }
+    }
+
+    /// Block until every call made so far has been replayed
+    pub fn flush(&self) {
+        let (done, finished) = mpsc::channel();
+        let marker: Replay = Box::new(move |_| {
+            let _ = done.send(());
+        });
+        if self.replays.send(marker).is_ok() {
+            let _ = finished.recv();
+        }
+    }
+
+    /// Replay a call against the shadow and record where its outcome differs from `primary`'s
+    fn shadow<T: 'static, V, F>(&self, call: Call, primary: &Result<T, Error>, project: fn(&T) -> V, replay: F)
+    where
+        V: PartialEq + Debug + Send + 'static,
+        F: FnOnce(&dyn MetadataStorage) -> Result<T, Error> + Send + 'static,
+    {
+        let expected = outcome(primary, project);
+        let job: Replay = Box::new(move |shadow| {
+            let actual = outcome(&replay(shadow), project);
+            if actual != expected {
+                record(&call, &format!("{:?}", expected), &format!("{:?}", actual));
+            }
+        });
+        match self.replays.try_send(job) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => metrics::inc_counter("warpdrive_shadow_dropped_total", &[]),
+            Err(TrySendError::Disconnected(_)) => warn!("Metadata shadow thread is gone; call not replayed"),
+        }
+    }
+}
+
+fn replay_all(shadow: Arc<dyn MetadataStorage>, pending: Receiver<Replay>) {
+    for replay in pending {
+        replay(shadow.as_ref());
+    }
+}
+
+fn record(call: &Call, primary: &str, shadow: &str) {
+    warn!("Metadata shadow diverged on {} for user: {} bucket: {} key: {:?}: primary {}, shadow {}",
+          call.op, call.user_id, call.bucket, call.key, primary, shadow);
+    metrics::inc_counter("warpdrive_shadow_divergence_total", &[("op", call.op)]);
+    let stored = SQLiteMetadataStore::new()
+        .record_shadow_divergence(call.op, &call.user_id, &call.bucket, call.key.as_deref(), primary, shadow);
+    if let Err(e) = stored {
+        error!("Failed to record a metadata shadow divergence: {}", e);
+    }
+}
+
+fn call(op: &'static str, user_id: &str, bucket: &str, key: Option<&str>) -> Call {
+    Call { op, user_id: user_id.to_string(), bucket: bucket.to_string(), key: key.map(str::to_string) }
+}
+
+impl MetadataStorage for ShadowingStore {
+    fn put_metadata(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<(), Error> {
+        let result = self.primary.put_metadata(user_id, bucket, object_id, metadata);
+        let (u, b, k, m) = (user_id.to_string(), bucket.to_string(), object_id.to_string(), metadata.clone());
+        self.shadow(call("put_metadata", user_id, bucket, Some(object_id)), &result, same,
+                    move |shadow| shadow.put_metadata(&u, &b, &k, &m));
+        result
+    }
+
+    fn put_metadata_overwrite(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<Vec<DataChunk>, Error> {
+        let result = self.primary.put_metadata_overwrite(user_id, bucket, object_id, metadata);
+        let (u, b, k, m) = (user_id.to_string(), bucket.to_string(), object_id.to_string(), metadata.clone());
+        self.shadow(call("put_metadata_overwrite", user_id, bucket, Some(object_id)), &result, same,
+                    move |shadow| shadow.put_metadata_overwrite(&u, &b, &k, &m));
+        result
+    }
+
+    fn get_metadata(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<Metadata, Error> {
+        let result = self.primary.get_metadata(user_id, bucket, object_id);
+        let (u, b, k) = (user_id.to_string(), bucket.to_string(), object_id.to_string());
+        self.shadow(call("get_metadata", user_id, bucket, Some(object_id)), &result, chunk_list,
+                    move |shadow| shadow.get_metadata(&u, &b, &k));
+        result
+    }
+
+    fn delete_metadata(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<(), Error> {
+        let result = self.primary.delete_metadata(user_id, bucket, object_id);
+        let (u, b, k) = (user_id.to_string(), bucket.to_string(), object_id.to_string());
+        self.shadow(call("delete_metadata", user_id, bucket, Some(object_id)), &result, same,
+                    move |shadow| shadow.delete_metadata(&u, &b, &k));
+        result
+    }
+
+    fn list_objects(&self, user_id: &str, bucket: &str) -> Result<Vec<ObjectId>, Error> {
+        let result = self.primary.list_objects(user_id, bucket);
+        let (u, b) = (user_id.to_string(), bucket.to_string());
+        self.shadow(call("list_objects", user_id, bucket, None), &result, sorted,
+                    move |shadow| shadow.list_objects(&u, &b));
+        result
+    }
+
+    fn object_exists(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<bool, Error> {
+        let result = self.primary.object_exists(user_id, bucket, object_id);
+        let (u, b, k) = (user_id.to_string(), bucket.to_string(), object_id.to_string());
+        self.shadow(call("object_exists", user_id, bucket, Some(object_id)), &result, same,
+                    move |shadow| shadow.object_exists(&u, &b, &k));
+        result
+    }
+
+    fn update_metadata(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<(), Error> {
+        let result = self.primary.update_metadata(user_id, bucket, object_id, metadata);
+        let (u, b, k, m) = (user_id.to_string(), bucket.to_string(), object_id.to_string(), metadata.clone());
+        self.shadow(call("update_metadata", user_id, bucket, Some(object_id)), &result, same,
+                    move |shadow| shadow.update_metadata(&u, &b, &k, &m));
+        result
+    }
+
+    fn update_object_id(&self, user_id: &str, bucket: &str, old_object_id: &str, new_object_id: &str) -> Result<(), Error> {
+        let result = self.primary.update_object_id(user_id, bucket, old_object_id, new_object_id);
+        let (u, b, old, new) = (user_id.to_string(), bucket.to_string(), old_object_id.to_string(), new_object_id.to_string());
+        self.shadow(call("update_object_id", user_id, bucket, Some(old_object_id)), &result, same,
+                    move |shadow| shadow.update_object_id(&u, &b, &old, &new));
+        result
+    }
+
+    fn touch_metadata(&self, user_id: &str, bucket: &str, object_id: &str, last_modified: &str) -> Result<(), Error> {
+        let result = self.primary.touch_metadata(user_id, bucket, object_id, last_modified);
+        let (u, b, k, at) = (user_id.to_string(), bucket.to_string(), object_id.to_string(), last_modified.to_string());
+        self.shadow(call("touch_metadata", user_id, bucket, Some(object_id)), &result, same,
+                    move |shadow| shadow.touch_metadata(&u, &b, &k, &at));
+        result
+    }
+
+    fn list_buckets_with_stats(&self, user_id: &str) -> Result<Vec<BucketStats>, Error> {
+        self.primary.list_buckets_with_stats(user_id)
+    }
+
+    fn create_bucket(&self, user_id: &str, bucket: &str) -> Result<(), Error> {
+        let result = self.primary.create_bucket(user_id, bucket);
+        let (u, b) = (user_id.to_string(), bucket.to_string());
+        self.shadow(call("create_bucket", user_id, bucket, None), &result, same,
+                    move |shadow| shadow.create_bucket(&u, &b));
+        result
+    }
+
+    fn delete_bucket(&self, user_id: &str, bucket: &str) -> Result<(), Error> {
+        let result = self.primary.delete_bucket(user_id, bucket);
+        let (u, b) = (user_id.to_string(), bucket.to_string());
+        self.shadow(call("delete_bucket", user_id, bucket, None), &result, same,
+                    move |shadow| shadow.delete_bucket(&u, &b));
+        result
+    }
+
+    fn bucket_exists(&self, user_id: &str, bucket: &str) -> Result<bool, Error> {
+        let result = self.primary.bucket_exists(user_id, bucket);
+        let (u, b) = (user_id.to_string(), bucket.to_string());
+        self.shadow(call("bucket_exists", user_id, bucket, None), &result, same,
+                    move |shadow| shadow.bucket_exists(&u, &b));
+        result
+    }
+
+    fn list_all_buckets_for_user(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        let result = self.primary.list_all_buckets_for_user(user_id);
+        let u = user_id.to_string();
+        self.shadow(call("list_all_buckets_for_user", user_id, "", None), &result, sorted,
+                    move |shadow| shadow.list_all_buckets_for_user(&u));
+        result
+    }
+
+    fn bucket_object_stats(&self, user_id: &str, bucket: &str) -> Result<(u64, u64), Error> {
+        let result = self.primary.bucket_object_stats(user_id, bucket);
+        let (u, b) = (user_id.to_string(), bucket.to_string());
+        self.shadow(call("bucket_object_stats", user_id, bucket, None), &result, same,
+                    move |shadow| shadow.bucket_object_stats(&u, &b));
+        result
+    }
+
+    fn get_full_record(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<Vec<ObjectRecord>, Error> {
+        self.primary.get_full_record(user_id, bucket, object_id)
+    }
+
+    fn list_full_records(&self, user_id: &str, bucket: &str, after_row_id: i64, limit: usize) -> Result<Vec<ObjectRecord>, Error> {
+        self.primary.list_full_records(user_id, bucket, after_row_id, limit)
+    }
+
+    fn queue_deletion(&self, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<DeletionSummary, Error> {
+        self.primary.queue_deletion(user_id, bucket, key, offset_size_list)
+    }
+
+    fn queue_spilled_deletion(&self, hash: &str, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<Option<DeletionSummary>, Error> {
+        self.primary.queue_spilled_deletion(hash, user_id, bucket, key, offset_size_list)
+    }
+
+    fn get_pending_deletions(&self, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        self.primary.get_pending_deletions(limit)
+    }
+
+    fn get_pending_deletions_for_bucket(&self, user_id: &str, bucket: &str, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        self.primary.get_pending_deletions_for_bucket(user_id, bucket, limit)
+    }
+
+    fn deletion_pending(&self, user_id: &str, bucket: &str, key: &str) -> Result<bool, Error> {
+        self.primary.deletion_pending(user_id, bucket, key)
+    }
+
+    fn pending_deletion_count(&self) -> Result<u64, Error> {
+        self.primary.pending_deletion_count()
+    }
+
+    fn pending_deletion_count_for_bucket(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.primary.pending_deletion_count_for_bucket(user_id, bucket)
+    }
+
+    fn pending_deletion_bytes(&self) -> Result<u64, Error> {
+        self.primary.pending_deletion_bytes()
+    }
+
+    fn mark_deletion_processed(&self, id: i64) -> Result<(), Error> {
+        self.primary.mark_deletion_processed(id)
+    }
+
+    fn record_deletion_failure(&self, ids: &[i64], error: &str) -> Result<(), Error> {
+        self.primary.record_deletion_failure(ids, error)
+    }
+
+    fn complete_deletions(&self, user_id: &str, bucket: &str, freed: &[(u64, u64)], ids: &[i64]) -> Result<(), Error> {
+        self.primary.complete_deletions(user_id, bucket, freed, ids)
+    }
+
+    fn cleanup_old_deletions(&self, retention_days: u32, batch: usize) -> Result<usize, Error> {
+        self.primary.cleanup_old_deletions(retention_days, batch)
+    }
+
+    fn free_ranges(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.primary.free_ranges(user_id, bucket)
+    }
+
+    fn free_ranges_overlapping(&self, user_id: &str, bucket: &str, ranges: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error> {
+        self.primary.free_ranges_overlapping(user_id, bucket, ranges)
+    }
+
+    fn forget_free_ranges(&self, user_id: &str, bucket: &str, segment: u32) -> Result<(), Error> {
+        self.primary.forget_free_ranges(user_id, bucket, segment)
+    }
+
+    fn live_extents(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.primary.live_extents(user_id, bucket)
+    }
+}
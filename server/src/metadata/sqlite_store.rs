@@ -1,14 +1,22 @@
 //! SQLite implementation of MetadataStorage trait
 
-use crate::metadata::{MetadataStorage, Metadata, ObjectId, BucketStats};
-use crate::util::serializer::serialize_offset_size;
-use std::sync::Mutex;
+use crate::metadata::{MetadataStorage, MetadataError, Metadata, DataChunk, ObjectId, ObjectRecord, BucketStats, DeletionEvent, DeletionSummary};
+use crate::metadata::config::SqliteConfig;
+use crate::metadata::group_commit;
+use crate::metrics;
+use crate::storage::segment;
+use crate::util::serializer::{decode_chunks, encode_chunks, encode_extents};
+use std::cell::Cell;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
 use std::sync::atomic::{AtomicU64, Ordering};
-use rusqlite::{params, Connection};
-use std::sync::Arc;
+use rusqlite::{params, Connection, DatabaseName, ErrorCode, OpenFlags, OptionalExtension};
 use log::{warn, info, error};
-use actix_web::Error;
+use actix_web::{Error, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::path::{Path, PathBuf};
 
@@ -35,6 +43,15 @@ pub struct DeleteSpecificResult {
     pub version_id: String,
 }
 
+/// (version_id, old_extents_to_gc) returned by a versioning-aware PUT.
+pub type PutObjectOutcome = (Option<String>, Vec<(u64, u64)>);
+
+/// Bucket default retention: (mode, days, years).
+pub type ObjectLockConfig = (String, Option<i64>, Option<i64>);
+
+/// (old extent, new extent) of a chunk copied out of a legacy file.
+pub type ExtentMove = ((u64, u64), (u64, u64));
+
 /// One entry in a ListObjectVersions response.
 pub struct VersionRow {
     pub key: String,
@@ -46,6 +63,14 @@ pub struct VersionRow {
     pub is_latest: bool,
 }
 
+fn default_db_path() -> PathBuf {
+    #[cfg(test)]
+    let base = crate::util::scratch::root().join("metadata");
+    #[cfg(not(test))]
+    let base = PathBuf::from("metadata");
+    base.join("metadata.sqlite")
+}
+
 fn get_db_path() -> PathBuf {
     match env::var("DB_FILE") {
         Ok(path) => {
@@ -54,235 +79,1119 @@ fn get_db_path() -> PathBuf {
         }
         Err(_) => {
             warn!("Metadata database location not defined in environment");
-            let default_path = Path::new("metadata").join("metadata.sqlite");
-            if let Some(parent) = default_path.parent() {
-                std::fs::create_dir_all(parent).expect("Failed to create metadata directory");
-            }
+            let default_path = default_db_path();
             info!("Using default database path: {}", default_path.display());
             default_path
         }
     }
 }
 
+/// Open a connection with WAL journaling, the configured busy timeout and `synchronous` level.
+pub fn open_connection(path: &Path, config: &SqliteConfig) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(config.busy_timeout)?;
+    // Only takes effect on a new file, and only before journal_mode is switched to WAL
+    conn.execute_batch("PRAGMA auto_vacuum=INCREMENTAL")?;
+    let journal_mode: String = conn.query_row("PRAGMA journal_mode=WAL", [], |row| row.get(0))?;
+    if !journal_mode.eq_ignore_ascii_case("wal") {
+        warn!("SQLite journal_mode is {} (WAL not available for {})", journal_mode, path.display());
+    }
+    conn.pragma_update(None, "synchronous", config.synchronous)?;
+    apply_performance_pragmas(&conn, config)?;
+    Ok(conn)
+}
+
+/// Apply the optional `mmap_size`, `cache_size` and `temp_store` settings; unset ones keep
+/// SQLite's defaults.
+fn apply_performance_pragmas(conn: &Connection, config: &SqliteConfig) -> rusqlite::Result<()> {
+    if let Some(bytes) = config.mmap_size {
+        conn.pragma_update(None, "mmap_size", bytes as i64)?;
+    }
+    if let Some(size) = config.cache_size {
+        conn.pragma_update(None, "cache_size", size)?;
+    }
+    if config.temp_store_memory {
+        conn.pragma_update(None, "temp_store", "MEMORY")?;
+    }
+    Ok(())
+}
+
+/// Outcome of [`warm_up`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WarmupReport {
+    /// Index entries read
+    pub rows: u64,
+    pub elapsed: Duration,
+    /// Whether the whole index was read within the budget
+    pub complete: bool,
+}
+
+/// Read the `objects` key index (`UNIQUE(user, bucket, key, version_id)`) from end to end so
+/// its pages are in the page cache, stopping once `budget` has passed. The query only needs
+/// the index, so no table pages are read.
+pub fn warm_up(conn: &Connection, budget: Duration) -> rusqlite::Result<WarmupReport> {
+    let start = Instant::now();
+    let mut stmt = conn.prepare(
+        "SELECT user, bucket, key, version_id FROM objects ORDER BY user, bucket, key, version_id",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut count = 0;
+    while rows.next()?.is_some() {
+        count += 1;
+        if start.elapsed() >= budget {
+            return Ok(WarmupReport { rows: count, elapsed: start.elapsed(), complete: false });
+        }
+    }
+    Ok(WarmupReport { rows: count, elapsed: start.elapsed(), complete: true })
+}
+
+/// [`warm_up`] the metadata database when `SQLITE_WARMUP=true`; None when it is off.
+pub fn warm_up_database() -> Result<Option<WarmupReport>, String> {
+    if !SQLITE_CONFIG.warmup {
+        return Ok(None);
+    }
+    let conn = timed_conn("warm_up", "", "");
+    warm_up(&conn, SQLITE_CONFIG.warmup_budget)
+        .map(Some)
+        .map_err(|e| format!("Failed to warm up the metadata database: {}", e))
+}
+
+/// Open a replica's connection. `SQLITE_OPEN_READ_ONLY` makes any write fail, so a stray
+/// write cannot touch the snapshot. WAL needs a writable `-shm` file; when the directory is on
+/// a read-only mount and the snapshot has none, the file cannot change under us and is opened
+/// as immutable.
+pub fn open_read_only_connection(path: &Path, config: &SqliteConfig) -> rusqlite::Result<Connection> {
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let shm = PathBuf::from(format!("{}-shm", path.display()));
+    let immutable = !shm.exists() && !is_writable(dir);
+    let uri = format!("file:{}?mode=ro{}", path.display(), if immutable { "&immutable=1" } else { "" });
+    let conn = Connection::open_with_flags(uri, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    conn.busy_timeout(config.busy_timeout)?;
+    apply_performance_pragmas(&conn, config)?;
+    // Fail now on a missing or unreadable file rather than on the first request
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0))?;
+    Ok(conn)
+}
+
+/// Whether this process may create files in `dir` (false on a read-only mount)
+fn is_writable(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(c_path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else { return false };
+    // SAFETY: `c_path` is a valid NUL-terminated string for the duration of the call.
+    unsafe { libc::access(c_path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Returned when the database stayed locked past `busy_timeout`; maps to 503 TryAgain.
+#[derive(Debug)]
+pub struct MetadataBusyError {
+    pub op: &'static str,
+    /// `DatabaseBusy` or `DatabaseLocked`
+    pub code: ErrorCode,
+}
+
+impl std::fmt::Display for MetadataBusyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metadata store is busy ({}), please retry", self.op)
+    }
+}
+
+impl ResponseError for MetadataBusyError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type("application/xml")
+            .insert_header(("Retry-After", "1"))
+            .body(format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <Error>\n\
+                   <Code>TryAgain</Code>\n\
+                   <Message>{}</Message>\n\
+                 </Error>",
+                self
+            ))
+    }
+}
+
+thread_local! {
+    // Operation currently holding the connection on this thread, for error labelling.
+    static CURRENT_OP: Cell<&'static str> = const { Cell::new("unknown") };
+}
+
+/// Convert a rusqlite error, surfacing SQLITE_BUSY / SQLITE_LOCKED as [`MetadataBusyError`].
+pub fn db_error(e: rusqlite::Error) -> Error {
+    let op = CURRENT_OP.with(|c| c.get());
+    match e.sqlite_error_code() {
+        Some(code @ (ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)) => {
+            warn!("SQLite busy during {}: {}", op, e);
+            metrics::inc_counter("warpdrive_sqlite_busy_errors_total", &[("op", op)]);
+            MetadataBusyError { op, code }.into()
+        }
+        _ => actix_web::error::ErrorInternalServerError(e),
+    }
+}
+
+/// [`db_error`] for an error `op` hit on another thread, such as the group committer's
+pub(super) fn db_error_in(op: &'static str, e: rusqlite::Error) -> Error {
+    CURRENT_OP.with(|c| c.set(op));
+    let err = db_error(e);
+    CURRENT_OP.with(|c| c.set("unknown"));
+    err
+}
+
+/// Connection guard that times the operation (including lock wait) and logs slow ones.
+pub(super) struct TimedConn<'a> {
+    conn: MutexGuard<'static, Connection>,
+    op: &'static str,
+    user: &'a str,
+    bucket: &'a str,
+    start: Instant,
+}
+
+impl Deref for TimedConn<'_> {
+    type Target = Connection;
+    fn deref(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl DerefMut for TimedConn<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+}
+
+impl Drop for TimedConn<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        metrics::observe_histogram("warpdrive_sqlite_op_duration_seconds", &[("op", self.op)], elapsed.as_secs_f64());
+        if elapsed >= SQLITE_CONFIG.slow_query_threshold {
+            metrics::inc_counter("warpdrive_sqlite_slow_ops_total", &[("op", self.op)]);
+            warn!("Slow SQLite operation {} took {}ms (user: {}, bucket: {})",
+                self.op, elapsed.as_millis(), self.user, self.bucket);
+        }
+        CURRENT_OP.with(|c| c.set("unknown"));
+    }
+}
+
+pub(super) fn timed_conn<'a>(op: &'static str, user: &'a str, bucket: &'a str) -> TimedConn<'a> {
+    let start = Instant::now();
+    let conn = db_conn().lock().unwrap();
+    CURRENT_OP.with(|c| c.set(op));
+    TimedConn { conn, op, user, bucket, start }
+}
+
 lazy_static! {
-    static ref DB_CONN: Arc<Mutex<Connection>> = {
-        let db_path = get_db_path();
-        let conn = Connection::open(&db_path).expect("Failed to open the database");
+    static ref SQLITE_CONFIG: SqliteConfig = SqliteConfig::from_env();
+}
 
-        conn.execute_batch("PRAGMA journal_mode=WAL;").ok();
+static DB_CONN: OnceLock<Mutex<Connection>> = OnceLock::new();
 
-        // Object metadata table — one row per (user, bucket, key, version_id).
-        // version_id='' means versioning is disabled for that bucket.
-        // version_id='null' means the null-version in a suspended-versioning bucket.
-        // is_latest=1 marks the current visible version for a key.
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS objects (
-                id                 INTEGER PRIMARY KEY AUTOINCREMENT,
-                user               TEXT NOT NULL,
-                bucket             TEXT NOT NULL,
-                key                TEXT NOT NULL,
-                version_id         TEXT NOT NULL DEFAULT '',
-                is_delete_marker   INTEGER NOT NULL DEFAULT 0,
-                is_latest          INTEGER NOT NULL DEFAULT 1,
-                offset_size_list   BLOB,
-                etag               TEXT,
-                size               INTEGER NOT NULL DEFAULT 0,
-                content_type       TEXT,
-                last_modified      TEXT,
-                user_metadata      TEXT,
-                cache_control      TEXT,
-                expires            TEXT,
-                content_encoding   TEXT,
-                parts_manifest     TEXT,
-                checksum_algorithm TEXT NOT NULL DEFAULT '',
-                checksum_value     TEXT NOT NULL DEFAULT '',
-                checksum_type      TEXT NOT NULL DEFAULT '',
-                UNIQUE(user, bucket, key, version_id)
-            )",
-            [],
-        ).expect("Failed to create objects table");
+fn db_conn() -> &'static Mutex<Connection> {
+    DB_CONN.get_or_init(|| Mutex::new(open_database().unwrap_or_else(|e| panic!("{}", e))))
+}
+
+/// Open the metadata database, creating and migrating the schema. A replica opens an
+/// existing database read-only and leaves its schema alone.
+fn open_database() -> Result<Connection, String> {
+    let db_path = get_db_path();
+    if crate::config::is_replica() {
+        return open_read_only_connection(&db_path, &SQLITE_CONFIG)
+            .map_err(|e| format!("Failed to open the database {} read-only: {}", db_path.display(), e));
+    }
+    if let Some(parent) = db_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create metadata directory {}: {}", parent.display(), e))?;
+    }
+    let conn = open_connection(&db_path, &SQLITE_CONFIG)
+        .map_err(|e| format!("Failed to open the database {}: {}", db_path.display(), e))?;
+
+    // Append-only audit of schema migrations and configuration changes
+    // (`service::change_log`); created first so the migrations below can be recorded.
+    // before_value and after_value are JSON.
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS change_log (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            changed_at   TEXT NOT NULL,
+            actor        TEXT NOT NULL,
+            kind         TEXT NOT NULL,
+            subject      TEXT NOT NULL,
+            before_value TEXT,
+            after_value  TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_change_log_changed_at ON change_log (changed_at);",
+    ).map_err(|e| format!("Failed to create change_log table: {}", e))?;
+
+    // Object metadata table — one row per (user, bucket, key, version_id).
+    // version_id='' means versioning is disabled for that bucket.
+    // version_id='null' means the null-version in a suspended-versioning bucket.
+    // is_latest=1 marks the current visible version for a key.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS objects (
+            id                 INTEGER PRIMARY KEY AUTOINCREMENT,
+            user               TEXT NOT NULL,
+            bucket             TEXT NOT NULL,
+            key                TEXT NOT NULL,
+            version_id         TEXT NOT NULL DEFAULT '',
+            is_delete_marker   INTEGER NOT NULL DEFAULT 0,
+            is_latest          INTEGER NOT NULL DEFAULT 1,
+            offset_size_list   BLOB,
+            etag               TEXT,
+            size               INTEGER NOT NULL DEFAULT 0,
+            content_type       TEXT,
+            last_modified      TEXT,
+            user_metadata      TEXT,
+            cache_control      TEXT,
+            expires            TEXT,
+            content_encoding   TEXT,
+            parts_manifest     TEXT,
+            checksum_algorithm TEXT NOT NULL DEFAULT '',
+            checksum_value     TEXT NOT NULL DEFAULT '',
+            checksum_type      TEXT NOT NULL DEFAULT '',
+            properties         TEXT,
+            expire_at          TEXT,
+            UNIQUE(user, bucket, key, version_id)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create objects table: {}", e))?;
+
+    // Databases created before the properties column existed
+    add_column(&conn, "objects", "properties", "TEXT")?;
+
+    // Per-object expiry (`service::expiry`), stored like last_modified so the sweep can range
+    // over the index
+    add_column(&conn, "objects", "expire_at", "TEXT")?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_objects_expire_at ON objects(expire_at) WHERE expire_at IS NOT NULL",
+        [],
+    ).map_err(|e| format!("Failed to create expire_at index: {}", e))?;
+
+    // Data of small objects kept in the row (`service::inline_objects`)
+    add_column(&conn, "objects", "inline_data", "BLOB")?;
+
+    // Native writes used to store no last-modified time; such rows get the time of the upgrade
+    // rather than a different made-up time on every read
+    let backfill = "UPDATE objects SET last_modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE last_modified IS NULL";
+    let backfilled = conn.execute(backfill, [])
+        .map_err(|e| format!("Failed to backfill last_modified: {}", e))?;
+    if backfilled > 0 {
+        record_migration(&conn, "objects.last_modified", serde_json::json!({ "sql": backfill, "rows": backfilled }))?;
+    }
 
-        // Multipart upload tracking tables
+    // Time each row was written, kept through appends, touches and renames (which update the
+    // row) for inventories; rows from before the column existed have none. Every insert path
+    // gets it from the trigger rather than its own column list.
+    add_column(&conn, "objects", "created_at", "TEXT")?;
+    // Stamps are compared as text (`service::as_of`), so they are all UTC with milliseconds;
+    // the trigger used to copy last_modified as written, which older rows had to the second
+    conn.execute_batch(
+        "DROP TRIGGER IF EXISTS objects_created_at;
+         CREATE TRIGGER objects_created_at AFTER INSERT ON objects
+         WHEN NEW.created_at IS NULL
+         BEGIN
+             UPDATE objects SET created_at = COALESCE(
+                 strftime('%Y-%m-%dT%H:%M:%fZ', NEW.last_modified), NEW.last_modified, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+             WHERE id = NEW.id;
+         END",
+    ).map_err(|e| format!("Failed to create objects_created_at trigger: {}", e))?;
+    // Rows stamped by the old trigger are rewritten once; the recorded migration marks it done,
+    // as the statement scans the whole table
+    if !migration_recorded(&conn, "objects.created_at.utc")? {
+        let normalize = "UPDATE objects SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', created_at)
+             WHERE created_at NOT GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9].[0-9][0-9][0-9]Z'
+               AND strftime('%Y-%m-%dT%H:%M:%fZ', created_at) IS NOT NULL";
+        let normalized = conn.execute(normalize, [])
+            .map_err(|e| format!("Failed to normalize created_at: {}", e))?;
+        record_migration(&conn, "objects.created_at.utc", serde_json::json!({ "sql": normalize, "rows": normalized }))?;
+    }
+    // Reads as of a time (`service::as_of`) take the newest row of a key created by then
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_objects_key_created_at ON objects(user, bucket, key, created_at)",
+        [],
+    ).map_err(|e| format!("Failed to create created_at index: {}", e))?;
+
+    // Generation of each key (`service::validators`): 1 on the first write, one more on every
+    // later write of the row's data or metadata, carried over by renames and moves. The last
+    // generation of every key is kept in object_generations, so a key deleted and written
+    // again never hands out a generation it had before. Like created_at it is set by
+    // triggers, so every insert path gets it; chunk relocations only touch
+    // offset_size_list and keep it.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS object_generations (
+            user_id    TEXT NOT NULL,
+            bucket     TEXT NOT NULL,
+            key        TEXT NOT NULL,
+            generation INTEGER NOT NULL,
+            PRIMARY KEY (user_id, bucket, key)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create object_generations table: {}", e))?;
+    if add_column(&conn, "objects", "generation", "INTEGER NOT NULL DEFAULT 0")? {
         conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS multipart_uploads (
-                upload_id          TEXT NOT NULL PRIMARY KEY,
-                user_id            TEXT NOT NULL,
-                bucket             TEXT NOT NULL,
-                key                TEXT NOT NULL,
-                content_type       TEXT,
-                metadata_json      TEXT NOT NULL DEFAULT '{}',
-                initiated_at       TEXT NOT NULL,
-                status             TEXT NOT NULL DEFAULT 'in_progress',
-                final_etag         TEXT,
-                tagging                TEXT DEFAULT '',
-                checksum_algorithm     TEXT NOT NULL DEFAULT '',
-                checksum_type          TEXT NOT NULL DEFAULT '',
-                object_lock_mode       TEXT NOT NULL DEFAULT '',
-                object_lock_retain_until TEXT NOT NULL DEFAULT '',
-                object_lock_legal_hold TEXT NOT NULL DEFAULT ''
-            );
-            CREATE TABLE IF NOT EXISTS multipart_parts (
-                upload_id       TEXT NOT NULL,
-                part_number     INTEGER NOT NULL,
-                etag            TEXT NOT NULL,
-                size            INTEGER NOT NULL,
-                extents_blob    BLOB NOT NULL,
-                checksum_value  TEXT NOT NULL DEFAULT '',
-                PRIMARY KEY (upload_id, part_number)
-            );"
-        ).expect("Failed to create multipart tables");
-
-        // Deletion WAL — extent ranges queued for background GC
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS deletion_queue (
-                id               INTEGER PRIMARY KEY AUTOINCREMENT,
-                user_id          TEXT NOT NULL,
-                bucket           TEXT NOT NULL,
-                key              TEXT NOT NULL,
-                offset_size_list BLOB NOT NULL,
-                created_at       DATETIME DEFAULT CURRENT_TIMESTAMP,
-                processed        BOOLEAN DEFAULT FALSE
-            )",
-            [],
-        ).expect("Failed to create deletion_queue table");
+            "UPDATE objects SET generation = 1;
+             INSERT OR IGNORE INTO object_generations (user_id, bucket, key, generation)
+                 SELECT user, bucket, key, 1 FROM objects GROUP BY user, bucket, key;",
+        ).map_err(|e| format!("Failed to backfill generations: {}", e))?;
+    }
+    let latest = "UPDATE objects SET generation = (
+                      SELECT generation FROM object_generations
+                      WHERE user_id = NEW.user AND bucket = NEW.bucket AND key = NEW.key)
+                  WHERE id = NEW.id;";
+    conn.execute_batch(&format!(
+        "CREATE TRIGGER IF NOT EXISTS objects_generation_insert AFTER INSERT ON objects
+         BEGIN
+             INSERT INTO object_generations (user_id, bucket, key, generation)
+                 VALUES (NEW.user, NEW.bucket, NEW.key, 1)
+                 ON CONFLICT (user_id, bucket, key) DO UPDATE SET generation = generation + 1;
+             {latest}
+         END;
+         CREATE TRIGGER IF NOT EXISTS objects_generation_update
+         AFTER UPDATE OF etag, size, content_type, user_metadata, properties, inline_data ON objects
+         BEGIN
+             INSERT INTO object_generations (user_id, bucket, key, generation)
+                 VALUES (NEW.user, NEW.bucket, NEW.key, OLD.generation + 1)
+                 ON CONFLICT (user_id, bucket, key) DO UPDATE SET generation = generation + 1;
+             {latest}
+         END;
+         CREATE TRIGGER IF NOT EXISTS objects_generation_rename AFTER UPDATE OF key, bucket ON objects
+         BEGIN
+             INSERT INTO object_generations (user_id, bucket, key, generation)
+                 VALUES (NEW.user, NEW.bucket, NEW.key, OLD.generation)
+                 ON CONFLICT (user_id, bucket, key) DO UPDATE SET generation = MAX(generation + 1, excluded.generation);
+             {latest}
+         END;",
+        latest = latest,
+    )).map_err(|e| format!("Failed to create generation triggers: {}", e))?;
+
+    // Multipart upload tracking tables
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS multipart_uploads (
+            upload_id          TEXT NOT NULL PRIMARY KEY,
+            user_id            TEXT NOT NULL,
+            bucket             TEXT NOT NULL,
+            key                TEXT NOT NULL,
+            content_type       TEXT,
+            metadata_json      TEXT NOT NULL DEFAULT '{}',
+            initiated_at       TEXT NOT NULL,
+            status             TEXT NOT NULL DEFAULT 'in_progress',
+            final_etag         TEXT,
+            tagging                TEXT DEFAULT '',
+            checksum_algorithm     TEXT NOT NULL DEFAULT '',
+            checksum_type          TEXT NOT NULL DEFAULT '',
+            object_lock_mode       TEXT NOT NULL DEFAULT '',
+            object_lock_retain_until TEXT NOT NULL DEFAULT '',
+            object_lock_legal_hold TEXT NOT NULL DEFAULT ''
+        );
+        CREATE TABLE IF NOT EXISTS multipart_parts (
+            upload_id       TEXT NOT NULL,
+            part_number     INTEGER NOT NULL,
+            etag            TEXT NOT NULL,
+            size            INTEGER NOT NULL,
+            extents_blob    BLOB NOT NULL,
+            checksum_value  TEXT NOT NULL DEFAULT '',
+            PRIMARY KEY (upload_id, part_number)
+        );"
+    ).map_err(|e| format!("Failed to create multipart tables: {}", e))?;
+    // Counts a user's in-progress uploads against MULTIPART_MAX_UPLOADS_PER_USER
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_multipart_uploads_user ON multipart_uploads (user_id, status)",
+        [],
+    ).map_err(|e| format!("Failed to create multipart_uploads index: {}", e))?;
+
+    // Deletion WAL — extent ranges queued for background GC
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS deletion_queue (
+            id               INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id          TEXT NOT NULL,
+            bucket           TEXT NOT NULL,
+            key              TEXT NOT NULL,
+            offset_size_list BLOB NOT NULL,
+            created_at       DATETIME DEFAULT CURRENT_TIMESTAMP,
+            processed        BOOLEAN DEFAULT FALSE
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create deletion_queue table: {}", e))?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_deletion_queue_key ON deletion_queue (user_id, bucket, key)",
+        [],
+    ).map_err(|e| format!("Failed to create deletion_queue index: {}", e))?;
+    // Databases created before processing times were recorded; their rows age by created_at
+    add_column(&conn, "deletion_queue", "processed_at", "DATETIME")?;
+    // Total size of each event's ranges, summed for the pending-bytes gauge; pending events
+    // queued before the column existed are sized from their ranges once
+    if add_column(&conn, "deletion_queue", "bytes", "INTEGER")? {
+        backfill_deletion_bytes(&conn)?;
+    }
+    // Failed attempts at freeing each event and the error of the last one
+    add_column(&conn, "deletion_queue", "attempts", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(&conn, "deletion_queue", "last_error", "TEXT")?;
+    // Serves the worker's oldest-pending scan and retention cleanup
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_deletion_queue_processed ON deletion_queue (processed, created_at)",
+        [],
+    ).map_err(|e| format!("Failed to create deletion_queue index: {}", e))?;
+
+    // Deletions replayed from the spill file (see `service::deletion_spill`), by the hash of
+    // their line, so a line replayed twice queues its ranges once
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS spilled_deletions (
+            hash        TEXT PRIMARY KEY,
+            replayed_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create spilled_deletions table: {}", e))?;
+
+    // Operations the S3 mirror has not applied to its remote yet (`storage::mirrored_store`),
+    // by remote, so those queued when the process stopped or given up after their attempts
+    // are replayed at the next start
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS mirror_pending (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            remote     TEXT NOT NULL,
+            user_id    TEXT NOT NULL,
+            bucket     TEXT NOT NULL,
+            kind       TEXT NOT NULL,
+            ranges     BLOB NOT NULL,
+            attempts   INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_mirror_pending_remote ON mirror_pending (remote, id);",
+    ).map_err(|e| format!("Failed to create mirror_pending table: {}", e))?;
+
+    // Ranges referenced by more than one object row (metadata-only clones). A range without a
+    // row has exactly one reference; `refs` is always at least 2.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chunk_refs (
+            user_id TEXT NOT NULL,
+            bucket  TEXT NOT NULL,
+            offset  INTEGER NOT NULL,
+            size    INTEGER NOT NULL,
+            refs    INTEGER NOT NULL,
+            PRIMARY KEY (user_id, bucket, offset, size)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create chunk_refs table: {}", e))?;
+
+    // SHA-256 of stored chunks, for deduplicated writes (`DEDUP_ENABLED`). A range is listed
+    // once, under the digest of the write that stored it, and only while it is referenced;
+    // its reference count is the one in `chunk_refs`.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chunk_index (
+            user_id TEXT NOT NULL,
+            bucket  TEXT NOT NULL,
+            digest  BLOB NOT NULL,
+            offset  INTEGER NOT NULL,
+            size    INTEGER NOT NULL,
+            PRIMARY KEY (user_id, bucket, digest)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create chunk_index table: {}", e))?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_chunk_index_range ON chunk_index (user_id, bucket, offset, size)",
+        [],
+    ).map_err(|e| format!("Failed to create chunk_index index: {}", e))?;
+
+    // Ranges the deletion worker has already freed, merged so no two rows of a segment
+    // overlap or touch. Lets a range queued twice be skipped instead of freed again.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS free_ranges (
+            user_id TEXT NOT NULL,
+            bucket  TEXT NOT NULL,
+            offset  INTEGER NOT NULL,
+            size    INTEGER NOT NULL,
+            PRIMARY KEY (user_id, bucket, offset)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create free_ranges table: {}", e))?;
+
+    // Bucket registry — tracks created buckets (including empty ones)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS buckets (
+            user                TEXT NOT NULL,
+            name                TEXT NOT NULL,
+            created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S.000Z', 'now')),
+            versioning_state    TEXT NOT NULL DEFAULT 'disabled',
+            location            TEXT DEFAULT '',
+            object_lock_enabled INTEGER NOT NULL DEFAULT 0,
+            public_read         INTEGER NOT NULL DEFAULT 0,
+            read_only           INTEGER NOT NULL DEFAULT 0,
+            max_objects         INTEGER,
+            max_bytes           INTEGER,
+            expire_unread_days  INTEGER,
+            PRIMARY KEY (user, name)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create buckets table: {}", e))?;
+
+    // Databases created before the bucket access flags existed
+    for column in ["public_read", "read_only"] {
+        add_column(&conn, "buckets", column, "INTEGER NOT NULL DEFAULT 0")?;
+    }
 
-        // Bucket registry — tracks created buckets (including empty ones)
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS buckets (
-                user                TEXT NOT NULL,
-                name                TEXT NOT NULL,
-                created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S.000Z', 'now')),
-                versioning_state    TEXT NOT NULL DEFAULT 'disabled',
-                location            TEXT DEFAULT '',
-                object_lock_enabled INTEGER NOT NULL DEFAULT 0,
-                PRIMARY KEY (user, name)
-            )",
-            [],
-        ).expect("Failed to create buckets table");
+    // ... and before per-bucket quotas (NULL is unlimited)
+    for column in ["max_objects", "max_bytes"] {
+        add_column(&conn, "buckets", column, "INTEGER")?;
+    }
 
-        // Object lock — bucket-level default retention configuration
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS object_lock_config (
-                bucket TEXT PRIMARY KEY,
-                mode   TEXT NOT NULL,
-                days   INTEGER,
-                years  INTEGER
-            )",
-            [],
-        ).expect("Failed to create object_lock_config table");
+    // ... and before last-access lifecycle rules (NULL is no rule)
+    add_column(&conn, "buckets", "expire_unread_days", "INTEGER")?;
+
+    // Last read of each key, flushed in batches by `service::access_tracking`; rows go with
+    // the key when it expires
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS access_times (
+            user_id          TEXT NOT NULL,
+            bucket           TEXT NOT NULL,
+            key              TEXT NOT NULL,
+            last_accessed_at TEXT NOT NULL,
+            reads            INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (user_id, bucket, key)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create access_times table: {}", e))?;
+
+    // Object lock — bucket-level default retention configuration
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS object_lock_config (
+            bucket TEXT PRIMARY KEY,
+            mode   TEXT NOT NULL,
+            days   INTEGER,
+            years  INTEGER
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create object_lock_config table: {}", e))?;
+
+    // Object lock — per-version retention and legal hold
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS object_lock (
+            bucket            TEXT NOT NULL,
+            key               TEXT NOT NULL,
+            version_id        TEXT NOT NULL,
+            mode              TEXT,
+            retain_until_date TEXT,
+            legal_hold        TEXT NOT NULL DEFAULT 'OFF',
+            PRIMARY KEY (bucket, key, version_id)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create object_lock table: {}", e))?;
+
+    // CORS configuration per bucket
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS bucket_cors (
+            bucket  TEXT PRIMARY KEY,
+            cors_xml TEXT NOT NULL
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create bucket_cors table: {}", e))?;
+
+    // Object tags: replace-all semantics (DELETE + INSERT per PUT ?tagging)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS object_tags (
+            user_id   TEXT NOT NULL,
+            bucket    TEXT NOT NULL,
+            key       TEXT NOT NULL,
+            tag_key   TEXT NOT NULL,
+            tag_value TEXT NOT NULL DEFAULT '',
+            PRIMARY KEY (user_id, bucket, key, tag_key)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create object_tags table: {}", e))?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_object_tags_tag ON object_tags (user_id, bucket, tag_key, tag_value)",
+        [],
+    ).map_err(|e| format!("Failed to create object_tags index: {}", e))?;
+
+    // Bucket tags
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS bucket_tags (
+            bucket    TEXT NOT NULL,
+            tag_key   TEXT NOT NULL,
+            tag_value TEXT NOT NULL DEFAULT '',
+            PRIMARY KEY (bucket, tag_key)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create bucket_tags table: {}", e))?;
+
+    // Storage tier of each bucket for the tiered backend. While a bucket moves between
+    // tiers, target_tier is set and offsets >= boundary already live in the target tier.
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS bucket_tiers (
+            user_id     TEXT NOT NULL,
+            bucket      TEXT NOT NULL,
+            tier        TEXT NOT NULL,
+            target_tier TEXT,
+            boundary    INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (user_id, bucket)
+        );
+        CREATE TABLE IF NOT EXISTS tier_migration_keys (
+            user_id TEXT NOT NULL,
+            bucket  TEXT NOT NULL,
+            key     TEXT NOT NULL,
+            PRIMARY KEY (user_id, bucket, key)
+        );",
+    ).map_err(|e| format!("Failed to create tiering tables: {}", e))?;
+
+    // Storage layout of each user's bucket files (`storage::layout`) where it differs from
+    // STORAGE_LAYOUT or is being migrated (target set). The row with an empty user_id holds
+    // the STORAGE_LAYOUT the storage was last started with.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS storage_layouts (
+            user_id TEXT NOT NULL PRIMARY KEY,
+            layout  TEXT NOT NULL,
+            target  TEXT
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create storage_layouts table: {}", e))?;
+
+    // Facts about the deployment itself by name, such as the `instance_id` it shares with
+    // its storage directories (`service::instance_identity`)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS meta_info (
+            name  TEXT NOT NULL PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create meta_info table: {}", e))?;
+
+    // Users found with a `{user}.bin` from before buckets (`service::legacy_migration`):
+    // when their rows were pointed at it and when a migration retired it
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS legacy_files (
+            user_id    TEXT NOT NULL PRIMARY KEY,
+            marked_at  TEXT NOT NULL,
+            rows       INTEGER NOT NULL,
+            retired_at TEXT
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create legacy_files table: {}", e))?;
+
+    // Native API keys; only the SHA-256 of each key is stored
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS api_keys (
+            key_hash   TEXT NOT NULL PRIMARY KEY,
+            key_id     TEXT NOT NULL UNIQUE,
+            user_id    TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            disabled   INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create api_keys table: {}", e))?;
+
+    // Admin API tokens; only the SHA-256 of each token is stored. `scopes` is comma-separated
+    // and `user_id`, when set, restricts the token to that user's resources.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS admin_tokens (
+            token_hash  TEXT NOT NULL PRIMARY KEY,
+            token_id    TEXT NOT NULL UNIQUE,
+            description TEXT NOT NULL DEFAULT '',
+            scopes      TEXT NOT NULL,
+            user_id     TEXT,
+            created_at  TEXT NOT NULL,
+            disabled    INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create admin_tokens table: {}", e))?;
+
+    // Data encryption keys of each bucket (`service::bucket_keys`), wrapped by the master key
+    // whose fingerprint is `master_key_id`; writes use the highest version. Every encrypted
+    // chunk records the version and IV it was written with.
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS bucket_keys (
+            user_id       TEXT NOT NULL,
+            bucket        TEXT NOT NULL,
+            version       INTEGER NOT NULL,
+            wrapped_key   BLOB NOT NULL,
+            master_key_id TEXT NOT NULL,
+            created_at    TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S.000Z', 'now')),
+            PRIMARY KEY (user_id, bucket, version)
+        );
+        CREATE TABLE IF NOT EXISTS chunk_keys (
+            user_id     TEXT NOT NULL,
+            bucket      TEXT NOT NULL,
+            offset      INTEGER NOT NULL,
+            size        INTEGER NOT NULL,
+            key_version INTEGER NOT NULL,
+            iv          BLOB NOT NULL,
+            PRIMARY KEY (user_id, bucket, offset)
+        );",
+    ).map_err(|e| format!("Failed to create encryption key tables: {}", e))?;
+
+    // Exclusive write leases on keys (`service::leases`); expires_at is RFC 3339
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS leases (
+            user_id    TEXT NOT NULL,
+            bucket     TEXT NOT NULL,
+            key        TEXT NOT NULL,
+            token      TEXT NOT NULL,
+            expires_at TEXT NOT NULL,
+            PRIMARY KEY (user_id, bucket, key)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create leases table: {}", e))?;
+
+    // Server-wide maintenance mode; at most one row
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS maintenance (
+            id         INTEGER PRIMARY KEY CHECK (id = 1),
+            mode       TEXT NOT NULL,
+            changed_at TEXT NOT NULL,
+            changed_by TEXT NOT NULL,
+            until      TEXT
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create maintenance table: {}", e))?;
+
+    // Bumped in the same transaction as every change to buckets, bucket placement and
+    // maintenance mode, so processes sharing this database notice their caches are stale
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS config_generation (
+            id         INTEGER PRIMARY KEY CHECK (id = 1),
+            generation INTEGER NOT NULL
+        );
+        INSERT OR IGNORE INTO config_generation (id, generation) VALUES (1, 0);",
+    ).map_err(|e| format!("Failed to create config_generation table: {}", e))?;
+
+    // Admin jobs (`service::jobs`); params and result are JSON
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS jobs (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind        TEXT NOT NULL,
+            user_id     TEXT NOT NULL,
+            bucket      TEXT NOT NULL,
+            params      TEXT NOT NULL,
+            state       TEXT NOT NULL,
+            progress    REAL NOT NULL DEFAULT 0,
+            result      TEXT,
+            error       TEXT,
+            created_at  TEXT NOT NULL,
+            started_at  TEXT,
+            finished_at TEXT
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create jobs table: {}", e))?;
+
+    // Last key of each bucket whose chunks a checksum backfill (`service::checksums`) has
+    // finished; the row is dropped once the backfill reaches the end of the bucket
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS checksum_backfill (
+            user_id    TEXT NOT NULL,
+            bucket     TEXT NOT NULL,
+            after_key  TEXT NOT NULL,
+            updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+            PRIMARY KEY (user_id, bucket)
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create checksum_backfill table: {}", e))?;
+
+    // Calls on which the metadata shadow backend (`metadata::shadow_store`) answered
+    // differently from the primary, each side's outcome as text
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS shadow_divergence (
+            id             INTEGER PRIMARY KEY AUTOINCREMENT,
+            op             TEXT NOT NULL,
+            user_id        TEXT NOT NULL,
+            bucket         TEXT NOT NULL,
+            key            TEXT,
+            primary_result TEXT NOT NULL,
+            shadow_result  TEXT NOT NULL,
+            recorded_at    TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create shadow_divergence table: {}", e))?;
+
+    // Native bucket grants (`service::bucket_grants`): `grantee_user` may use `owner_user`'s
+    // `bucket` with `permission` read, write or admin. Rows go with the bucket.
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS bucket_grants (
+            owner_user   TEXT NOT NULL,
+            bucket       TEXT NOT NULL,
+            grantee_user TEXT NOT NULL,
+            permission   TEXT NOT NULL,
+            created_at   TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S.000Z', 'now')),
+            PRIMARY KEY (owner_user, bucket, grantee_user)
+        );
+        CREATE INDEX IF NOT EXISTS idx_bucket_grants_grantee ON bucket_grants(grantee_user);",
+    ).map_err(|e| format!("Failed to create bucket_grants table: {}", e))?;
 
-        // Object lock — per-version retention and legal hold
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS object_lock (
-                bucket            TEXT NOT NULL,
-                key               TEXT NOT NULL,
-                version_id        TEXT NOT NULL,
-                mode              TEXT,
-                retain_until_date TEXT,
-                legal_hold        TEXT NOT NULL DEFAULT 'OFF',
-                PRIMARY KEY (bucket, key, version_id)
-            )",
-            [],
-        ).expect("Failed to create object_lock table");
+    Ok(conn)
+}
 
-        // CORS configuration per bucket
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS bucket_cors (
-                bucket  TEXT PRIMARY KEY,
-                cors_xml TEXT NOT NULL
-            )",
-            [],
-        ).expect("Failed to create bucket_cors table");
+/// Set `bytes` of the pending deletion events queued before the column existed
+fn backfill_deletion_bytes(conn: &Connection) -> Result<(), String> {
+    let pending: Vec<(i64, Vec<u8>)> = conn
+        .prepare("SELECT id, offset_size_list FROM deletion_queue WHERE bytes IS NULL AND processed = FALSE")
+        .and_then(|mut stmt| stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect())
+        .map_err(|e| format!("Failed to read deletion_queue: {}", e))?;
+    for (id, blob) in pending {
+        let bytes: u64 = crate::util::serializer::decode_extents(&blob)
+            .map(|extents| extents.iter().map(|(_, size)| size).sum())
+            .unwrap_or_default();
+        conn.execute("UPDATE deletion_queue SET bytes = ?1 WHERE id = ?2", params![bytes as i64, id])
+            .map_err(|e| format!("Failed to backfill deletion_queue.bytes: {}", e))?;
+    }
+    Ok(())
+}
 
-        // Object tags: replace-all semantics (DELETE + INSERT per PUT ?tagging)
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS object_tags (
-                user_id   TEXT NOT NULL,
-                bucket    TEXT NOT NULL,
-                key       TEXT NOT NULL,
-                tag_key   TEXT NOT NULL,
-                tag_value TEXT NOT NULL DEFAULT '',
-                PRIMARY KEY (user_id, bucket, key, tag_key)
-            )",
-            [],
-        ).expect("Failed to create object_tags table");
+/// Add `column` to `table` of a database created before it existed, recording the migration.
+/// Returns whether the column was added.
+fn add_column(conn: &Connection, table: &str, column: &str, declaration: &str) -> Result<bool, String> {
+    let sql = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, declaration);
+    match conn.execute(&sql, []) {
+        Ok(_) => {
+            record_migration(conn, &format!("{}.{}", table, column), serde_json::json!({ "sql": sql }))?;
+            Ok(true)
+        }
+        Err(e) if e.to_string().contains("duplicate column") => Ok(false),
+        Err(e) => Err(format!("Failed to add {} column: {}", column, e)),
+    }
+}
 
-        // Bucket tags
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS bucket_tags (
-                bucket    TEXT NOT NULL,
-                tag_key   TEXT NOT NULL,
-                tag_value TEXT NOT NULL DEFAULT '',
-                PRIMARY KEY (bucket, tag_key)
-            )",
-            [],
-        ).expect("Failed to create bucket_tags table");
+fn record_migration(conn: &Connection, subject: &str, after: serde_json::Value) -> Result<(), String> {
+    info!("Schema migration applied to {}", subject);
+    insert_change(conn, &change_time(), "startup", "schema", subject, None, Some(&after))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to record migration of {}: {}", subject, e))
+}
 
-        Arc::new(Mutex::new(conn))
-    };
+/// Whether a migration of `subject` was recorded by an earlier start
+fn migration_recorded(conn: &Connection, subject: &str) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM change_log WHERE kind = 'schema' AND subject = ?1)",
+        params![subject],
+        |row| row.get(0),
+    ).map_err(|e| format!("Failed to look up migration of {}: {}", subject, e))
 }
 
+/// Time of a change log entry: RFC 3339 UTC with milliseconds, so entries order as text
+fn change_time() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn insert_change(
+    conn: &Connection,
+    changed_at: &str,
+    actor: &str,
+    kind: &str,
+    subject: &str,
+    before: Option<&serde_json::Value>,
+    after: Option<&serde_json::Value>,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO change_log (changed_at, actor, kind, subject, before_value, after_value)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![changed_at, actor, kind, subject, before.map(|v| v.to_string()), after.map(|v| v.to_string())],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Open the shared connection and run migrations now rather than on the first request.
+/// Returns the database path.
+pub fn initialize() -> Result<PathBuf, String> {
+    if DB_CONN.get().is_none() {
+        let conn = open_database()?;
+        // Lost a race with a lazy open: that connection is already migrated
+        let _ = DB_CONN.set(Mutex::new(conn));
+    }
+    Ok(get_db_path())
+}
+
+#[derive(Default)]
 pub struct SQLiteMetadataStore;
 
 impl SQLiteMetadataStore {
     pub fn new() -> Self { Self }
+
+    /// Whether `bucket` holds any live object, whether or not it is registered
+    pub fn bucket_has_objects(&self, user_id: &str, bucket: &str) -> Result<bool, Error> {
+        let conn = timed_conn("bucket_has_objects", user_id, bucket);
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND is_latest = 1 AND is_delete_marker = 0)",
+            params![user_id, bucket],
+            |row| row.get(0),
+        ).map_err(db_error)
+    }
+}
+
+/// `chunks` copied to the storage extents `to`, one per chunk, keeping their attributes
+fn relocate(chunks: &[DataChunk], to: &[(u64, u64)]) -> Vec<DataChunk> {
+    chunks.iter().zip(to).map(|(chunk, &extent)| chunk.relocated(extent)).collect()
+}
+
+/// Non-versioned write: DELETE the existing version_id='' row, demote any other versions,
+/// then INSERT the new row as latest.
+fn replace_unversioned(conn: &Connection, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<(), Error> {
+    let offset_size_bytes = encode_chunks(&metadata.chunks)?;
+    insert_unversioned(conn, user_id, bucket, object_id, &offset_size_bytes, metadata).map_err(|e| {
+        error!("put_metadata failed user={} bucket={} key={}: {}", user_id, bucket, object_id, e);
+        db_error(e)
+    })
+}
+
+/// [`replace_unversioned`] with the extents already serialized.
+fn insert_unversioned(conn: &Connection, user_id: &str, bucket: &str, object_id: &str, offset_size_bytes: &[u8], metadata: &Metadata) -> rusqlite::Result<()> {
+    let user_metadata_json = serde_json::to_string(&metadata.user_metadata)
+        .unwrap_or_else(|_| "{}".to_string());
+
+    let properties_json = serde_json::to_string(&metadata.properties)
+        .unwrap_or_else(|_| "{}".to_string());
+
+    conn.execute(
+        "DELETE FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND version_id = ''",
+        params![user_id, bucket, object_id],
+    )?;
+    // Also clear is_latest on all existing versions so the non-versioned row becomes latest.
+    conn.execute(
+        "UPDATE objects SET is_latest = 0 WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1",
+        params![user_id, bucket, object_id],
+    )?;
+    conn.execute(
+        "INSERT INTO objects
+            (user, bucket, key, version_id, is_latest, is_delete_marker,
+             offset_size_list, etag, size, content_type, last_modified,
+             user_metadata, cache_control, expires, content_encoding, properties, expire_at,
+             inline_data)
+         VALUES (?1, ?2, ?3, '', 1, 0, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            user_id, bucket, object_id,
+            offset_size_bytes,
+            metadata.etag,
+            metadata.size as i64,
+            metadata.content_type,
+            metadata.last_modified,
+            user_metadata_json,
+            metadata.cache_control,
+            metadata.expires,
+            metadata.content_encoding,
+            properties_json,
+            metadata.expire_at,
+            metadata.inline_data,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Insert `object_id` unless a live row holds the key; `Ok(false)` when one does.
+pub(super) fn insert_new(conn: &Connection, user_id: &str, bucket: &str, object_id: &str, offset_size_bytes: &[u8], metadata: &Metadata) -> rusqlite::Result<bool> {
+    let live: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM objects
+         WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0",
+        params![user_id, bucket, object_id],
+        |row| row.get(0),
+    )?;
+    if live > 0 {
+        return Ok(false);
+    }
+    insert_unversioned(conn, user_id, bucket, object_id, offset_size_bytes, metadata)?;
+    Ok(true)
+}
+
+/// Replace the unversioned row of `object_id`, returning the chunks it referenced, or
+/// `None` when there was no such row.
+fn overwrite_unversioned(conn: &Connection, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<Option<Vec<DataChunk>>, Error> {
+    let previous: Option<Option<Vec<u8>>> = conn.query_row(
+        "SELECT offset_size_list FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND version_id = ''",
+        params![user_id, bucket, object_id],
+        |row| row.get(0),
+    ).optional().map_err(db_error)?;
+    let previous = match previous {
+        Some(Some(bytes)) => Some(decode_chunks(&bytes)?),
+        Some(None) => Some(Vec::new()),
+        None => None,
+    };
+    replace_unversioned(conn, user_id, bucket, object_id, metadata)?;
+    Ok(previous)
+}
+
+/// Rows matching `filter` (a WHERE clause over `?1` user and `?2` bucket) with every column,
+/// in the order and up to the count `tail` gives.
+fn query_records(conn: &Connection, filter: &str, tail: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<ObjectRecord>, Error> {
+    let sql = format!(
+        "SELECT id, key, is_latest, parts_manifest, offset_size_list, etag, size, content_type,
+                last_modified, user_metadata, cache_control, expires, content_encoding, version_id,
+                is_delete_marker, checksum_algorithm, checksum_value, checksum_type, properties, expire_at,
+                inline_data, created_at, generation
+         FROM objects WHERE {} {}",
+        filter, tail
+    );
+    let mut stmt = conn.prepare(&sql).map_err(db_error)?;
+    let rows = stmt.query_map(params, |row| {
+        let non_empty = |s: String| if s.is_empty() { None } else { Some(s) };
+        let json_map = |s: Option<String>| s.as_deref().and_then(|s| serde_json::from_str(s).ok()).unwrap_or_default();
+        let mut metadata = Metadata::from_offset_size_list(vec![]);
+        metadata.etag = row.get(5)?;
+        metadata.size = row.get::<_, i64>(6)? as u64;
+        metadata.content_type = row.get(7)?;
+        metadata.last_modified = row.get(8)?;
+        metadata.user_metadata = json_map(row.get(9)?);
+        metadata.cache_control = row.get(10)?;
+        metadata.expires = row.get(11)?;
+        metadata.content_encoding = row.get(12)?;
+        metadata.version_id = non_empty(row.get(13)?);
+        metadata.is_delete_marker = row.get::<_, i64>(14)? != 0;
+        metadata.checksum_algorithm = non_empty(row.get(15)?);
+        metadata.checksum_value = non_empty(row.get(16)?);
+        metadata.checksum_type = non_empty(row.get(17)?);
+        metadata.properties = json_map(row.get(18)?);
+        metadata.expire_at = row.get(19)?;
+        metadata.inline_data = row.get(20)?;
+        metadata.generation = row.get::<_, i64>(22)? as u64;
+        let record = ObjectRecord {
+            row_id: row.get(0)?,
+            key: row.get(1)?,
+            is_latest: row.get::<_, i64>(2)? != 0,
+            parts_manifest: row.get(3)?,
+            created_at: row.get(21)?,
+            metadata,
+        };
+        Ok((record, row.get::<_, Option<Vec<u8>>>(4)?))
+    }).map_err(db_error)?;
+
+    let mut records = Vec::new();
+    for row in rows {
+        let (mut record, offset_size_bytes) = row.map_err(db_error)?;
+        if let Some(bytes) = offset_size_bytes {
+            record.metadata.chunks = decode_chunks(&bytes)?;
+        }
+        records.push(record);
+    }
+    Ok(records)
 }
 
 impl MetadataStorage for SQLiteMetadataStore {
     fn put_metadata(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<(), Error> {
-        // Non-versioned write: DELETE existing version_id='' row then INSERT new one.
-        let offset_size_list = metadata.to_offset_size_list();
-        let offset_size_bytes = serialize_offset_size(&offset_size_list)?;
-        let user_metadata_json = serde_json::to_string(&metadata.user_metadata)
-            .unwrap_or_else(|_| "{}".to_string());
-
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "DELETE FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND version_id = ''",
-            params![user_id, bucket, object_id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        // Also clear is_latest on all existing versions so the non-versioned row becomes latest.
-        conn.execute(
-            "UPDATE objects SET is_latest = 0 WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1",
-            params![user_id, bucket, object_id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        let result = conn.execute(
-            "INSERT INTO objects
-                (user, bucket, key, version_id, is_latest, is_delete_marker,
-                 offset_size_list, etag, size, content_type, last_modified,
-                 user_metadata, cache_control, expires, content_encoding)
-             VALUES (?1, ?2, ?3, '', 1, 0, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
-            params![
-                user_id, bucket, object_id,
-                offset_size_bytes,
-                metadata.etag,
-                metadata.size as i64,
-                metadata.content_type,
-                metadata.last_modified,
-                user_metadata_json,
-                metadata.cache_control,
-                metadata.expires,
-                metadata.content_encoding,
-            ],
-        );
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                error!("put_metadata failed user={} bucket={} key={}: {}", user_id, bucket, object_id, e);
-                Err(actix_web::error::ErrorInternalServerError(e))
-            }
+        let offset_size_bytes = encode_chunks(&metadata.chunks)?;
+        if group_commit::enabled() {
+            return group_commit::submit(user_id, bucket, object_id, offset_size_bytes, metadata);
+        }
+        let conn = timed_conn("put_metadata", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        if !insert_new(&tx, user_id, bucket, object_id, &offset_size_bytes, metadata).map_err(db_error)? {
+            return Err(MetadataError::already_exists(bucket, object_id));
         }
+        tx.commit().map_err(db_error)
+    }
+
+    fn put_metadata_overwrite(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<Vec<DataChunk>, Error> {
+        let conn = timed_conn("put_metadata_overwrite", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let previous = overwrite_unversioned(&tx, user_id, bucket, object_id, metadata)?;
+        tx.commit().map_err(db_error)?;
+        Ok(previous.unwrap_or_default())
     }
 
     fn get_metadata(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<Metadata, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("get_metadata", user_id, bucket);
         let mut stmt = conn.prepare(
             "SELECT offset_size_list, etag, size, content_type, last_modified, user_metadata,
                     cache_control, expires, content_encoding, version_id, is_delete_marker,
-                    checksum_algorithm, checksum_value, checksum_type
+                    checksum_algorithm, checksum_value, checksum_type, properties, expire_at, inline_data,
+                    generation
              FROM objects
              WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
 
         let row = stmt.query_row(params![user_id, bucket, object_id], |row| {
             Ok((
@@ -300,6 +1209,10 @@ impl MetadataStorage for SQLiteMetadataStore {
                 row.get::<_, String>(11)?,
                 row.get::<_, String>(12)?,
                 row.get::<_, String>(13)?,
+                row.get::<_, Option<String>>(14)?,
+                row.get::<_, Option<String>>(15)?,
+                row.get::<_, Option<Vec<u8>>>(16)?,
+                row.get::<_, i64>(17)?,
             ))
         }).map_err(|e| {
             warn!("get_metadata: not found user={} bucket={} key={}: {}", user_id, bucket, object_id, e);
@@ -310,7 +1223,7 @@ impl MetadataStorage for SQLiteMetadataStore {
 
         let (offset_size_bytes, etag, size, content_type, last_modified, user_metadata_json,
              cache_control, expires, content_encoding, version_id, is_delete_marker,
-             checksum_algorithm, checksum_value, checksum_type) = row;
+             checksum_algorithm, checksum_value, checksum_type, properties_json, expire_at, inline_data, generation) = row;
 
         if is_delete_marker != 0 {
             return Err(actix_web::error::ErrorNotFound(format!(
@@ -318,8 +1231,8 @@ impl MetadataStorage for SQLiteMetadataStore {
             )));
         }
 
-        let offset_size_list = if let Some(bytes) = offset_size_bytes {
-            crate::util::serializer::deserialize_offset_size(&bytes)?
+        let chunks = if let Some(bytes) = offset_size_bytes {
+            decode_chunks(&bytes)?
         } else {
             vec![]
         };
@@ -328,7 +1241,7 @@ impl MetadataStorage for SQLiteMetadataStore {
             .and_then(|s| serde_json::from_str(s).ok())
             .unwrap_or_default();
 
-        let mut metadata = Metadata::from_offset_size_list(offset_size_list);
+        let mut metadata = Metadata::from_chunks(chunks);
         metadata.etag = etag;
         metadata.size = size as u64;
         metadata.content_type = content_type;
@@ -341,56 +1254,69 @@ impl MetadataStorage for SQLiteMetadataStore {
         metadata.checksum_algorithm = if checksum_algorithm.is_empty() { None } else { Some(checksum_algorithm) };
         metadata.checksum_value = if checksum_value.is_empty() { None } else { Some(checksum_value) };
         metadata.checksum_type = if checksum_type.is_empty() { None } else { Some(checksum_type) };
+        metadata.properties = properties_json
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or_default();
+        metadata.expire_at = expire_at;
+        metadata.inline_data = inline_data;
+        metadata.generation = generation as u64;
         Ok(metadata)
     }
 
     fn delete_metadata(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<(), Error> {
         // Hard-delete all rows for this key (used by CompleteMultipartUpload overwrite and internal cleanup).
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("delete_metadata", user_id, bucket);
         conn.execute(
             "DELETE FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3",
             params![user_id, bucket, object_id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
+        conn.execute(
+            "DELETE FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+            params![user_id, bucket, object_id],
+        ).map_err(db_error)?;
         Ok(())
     }
 
     fn list_objects(&self, user_id: &str, bucket: &str) -> Result<Vec<ObjectId>, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("list_objects", user_id, bucket);
         let mut stmt = conn.prepare(
             "SELECT key FROM objects
              WHERE user = ?1 AND bucket = ?2 AND is_latest = 1 AND is_delete_marker = 0
              ORDER BY key",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
 
         let rows = stmt.query_map(params![user_id, bucket], |row| {
             row.get::<_, String>(0)
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
+        }).map_err(db_error)?;
 
         let mut objects = Vec::new();
         for row in rows {
-            objects.push(row.map_err(actix_web::error::ErrorInternalServerError)?);
+            objects.push(row.map_err(db_error)?);
         }
         Ok(objects)
     }
 
     fn object_exists(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<bool, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("object_exists", user_id, bucket);
         let count: i64 = conn.query_row(
             "SELECT COUNT(*) FROM objects
              WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0",
             params![user_id, bucket, object_id],
             |row| row.get(0),
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
         Ok(count > 0)
     }
 
     fn update_metadata(&self, user_id: &str, bucket: &str, object_id: &str, metadata: &Metadata) -> Result<(), Error> {
-        let offset_size_list = metadata.to_offset_size_list();
-        let offset_size_bytes = serialize_offset_size(&offset_size_list)?;
+        let offset_size_bytes = encode_chunks(&metadata.chunks)?;
         let user_metadata_json = serde_json::to_string(&metadata.user_metadata)
             .unwrap_or_else(|_| "{}".to_string());
 
-        let conn = DB_CONN.lock().unwrap();
+        let properties_json = serde_json::to_string(&metadata.properties)
+            .unwrap_or_else(|_| "{}".to_string());
+
+        let conn = timed_conn("update_metadata", user_id, bucket);
         conn.execute(
             "UPDATE objects SET
                 offset_size_list = ?1,
@@ -401,7 +1327,10 @@ impl MetadataStorage for SQLiteMetadataStore {
                 user_metadata    = ?6,
                 cache_control    = ?7,
                 expires          = ?8,
-                content_encoding = ?9
+                content_encoding = ?9,
+                properties       = ?13,
+                expire_at        = ?14,
+                inline_data      = ?15
              WHERE user = ?10 AND bucket = ?11 AND key = ?12 AND is_latest = 1",
             params![
                 offset_size_bytes,
@@ -411,26 +1340,44 @@ impl MetadataStorage for SQLiteMetadataStore {
                 metadata.cache_control, metadata.expires,
                 metadata.content_encoding,
                 user_id, bucket, object_id,
+                properties_json,
+                metadata.expire_at,
+                metadata.inline_data,
             ],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    fn touch_metadata(&self, user_id: &str, bucket: &str, object_id: &str, last_modified: &str) -> Result<(), Error> {
+        let conn = timed_conn("touch_metadata", user_id, bucket);
+        let changed = conn.execute(
+            "UPDATE objects SET last_modified = ?1
+             WHERE user = ?2 AND bucket = ?3 AND key = ?4 AND is_latest = 1 AND is_delete_marker = 0",
+            params![last_modified, user_id, bucket, object_id],
+        ).map_err(db_error)?;
+        if changed == 0 {
+            return Err(actix_web::error::ErrorNotFound(format!(
+                "No data found for key: {}, The key does not exist", object_id
+            )));
+        }
         Ok(())
     }
 
     fn update_object_id(&self, user_id: &str, bucket: &str, old_object_id: &str, new_object_id: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("update_object_id", user_id, bucket);
         conn.execute(
             "UPDATE objects SET key = ?1 WHERE user = ?2 AND bucket = ?3 AND key = ?4",
             params![new_object_id, user_id, bucket, old_object_id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
+        conn.execute(
+            "UPDATE object_tags SET key = ?1 WHERE user_id = ?2 AND bucket = ?3 AND key = ?4",
+            params![new_object_id, user_id, bucket, old_object_id],
+        ).map_err(db_error)?;
         Ok(())
     }
 
-    fn queue_deletion(&self, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
-        SQLiteMetadataStore::queue_deletion(self, user_id, bucket, key, offset_size_list)
-    }
-
     fn list_buckets_with_stats(&self, user_id: &str) -> Result<Vec<BucketStats>, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("list_buckets_with_stats", user_id, "");
         // LEFT JOIN so empty buckets still appear in the result
         let mut stmt = conn.prepare(
             "SELECT b.name,
@@ -443,7 +1390,7 @@ impl MetadataStorage for SQLiteMetadataStore {
              WHERE  b.user = ?1
              GROUP BY b.name
              ORDER BY b.name",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
 
         let rows = stmt.query_map(params![user_id], |row| {
             Ok(BucketStats {
@@ -452,369 +1399,1091 @@ impl MetadataStorage for SQLiteMetadataStore {
                 object_count: row.get::<_, i64>(2)? as u64,
                 total_size:   row.get::<_, i64>(3)? as u64,
             })
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
+        }).map_err(db_error)?;
 
         let mut stats = Vec::new();
         for row in rows {
-            stats.push(row.map_err(actix_web::error::ErrorInternalServerError)?);
+            stats.push(row.map_err(db_error)?);
         }
         Ok(stats)
     }
 
     fn create_bucket(&self, user_id: &str, bucket: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
+        let conn = timed_conn("create_bucket", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
             "INSERT OR IGNORE INTO buckets (user, name) VALUES (?1, ?2)",
             params![user_id, bucket],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
         Ok(())
     }
 
     fn delete_bucket(&self, user_id: &str, bucket: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
+        let conn = timed_conn("delete_bucket", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
             "DELETE FROM buckets WHERE user = ?1 AND name = ?2",
             params![user_id, bucket],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
+        tx.execute(
+            "DELETE FROM chunk_refs WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+        ).map_err(db_error)?;
+        tx.execute(
+            "DELETE FROM chunk_index WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+        ).map_err(db_error)?;
+        tx.execute(
+            "DELETE FROM free_ranges WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+        ).map_err(db_error)?;
+        tx.execute(
+            "DELETE FROM bucket_grants WHERE owner_user = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
         Ok(())
     }
 
     fn bucket_exists(&self, user_id: &str, bucket: &str) -> Result<bool, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("bucket_exists", user_id, bucket);
         let mut stmt = conn.prepare(
             "SELECT COUNT(*) FROM buckets WHERE user = ?1 AND name = ?2",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
         let count: i64 = stmt.query_row(params![user_id, bucket], |row| row.get(0))
-            .map_err(actix_web::error::ErrorInternalServerError)?;
+            .map_err(db_error)?;
         Ok(count > 0)
     }
 
     fn list_all_buckets_for_user(&self, user_id: &str) -> Result<Vec<String>, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("list_all_buckets_for_user", user_id, "");
         let mut stmt = conn.prepare(
             "SELECT name FROM buckets WHERE user = ?1 ORDER BY name",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
         let rows = stmt.query_map(params![user_id], |row| row.get::<_, String>(0))
-            .map_err(actix_web::error::ErrorInternalServerError)?;
+            .map_err(db_error)?;
         let mut names = Vec::new();
         for row in rows {
-            names.push(row.map_err(actix_web::error::ErrorInternalServerError)?);
+            names.push(row.map_err(db_error)?);
         }
         Ok(names)
     }
 
     fn bucket_object_stats(&self, user_id: &str, bucket: &str) -> Result<(u64, u64), Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("bucket_object_stats", user_id, bucket);
         let mut stmt = conn.prepare(
             "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM objects
              WHERE user = ?1 AND bucket = ?2 AND is_latest = 1 AND is_delete_marker = 0",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
         let (count, bytes): (i64, i64) = stmt.query_row(params![user_id, bucket], |row| {
             Ok((row.get(0)?, row.get(1)?))
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
+        }).map_err(db_error)?;
         Ok((count as u64, bytes as u64))
     }
-}
 
-/// Deletion queue — WAL for background storage GC
-impl SQLiteMetadataStore {
-    pub fn queue_deletion(&self, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
-        let offset_size_bytes = serialize_offset_size(&offset_size_list.to_vec())?;
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "INSERT INTO deletion_queue (user_id, bucket, key, offset_size_list) VALUES (?1, ?2, ?3, ?4)",
-            params![user_id, bucket, key, offset_size_bytes],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        info!("Queued deletion user={} bucket={} key={} chunks={}", user_id, bucket, key, offset_size_list.len());
-        Ok(())
+    fn get_full_record(&self, user_id: &str, bucket: &str, object_id: &str) -> Result<Vec<ObjectRecord>, Error> {
+        let conn = timed_conn("get_full_record", user_id, bucket);
+        query_records(&conn, "user = ?1 AND bucket = ?2 AND key = ?3", "ORDER BY id DESC",
+                      params![user_id, bucket, object_id])
     }
 
-    pub fn get_pending_deletions(&self, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, user_id, bucket, key, offset_size_list, created_at
-             FROM deletion_queue
-             WHERE processed = FALSE
-             ORDER BY created_at ASC
-             LIMIT ?1",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+    fn list_full_records(&self, user_id: &str, bucket: &str, after_row_id: i64, limit: usize) -> Result<Vec<ObjectRecord>, Error> {
+        let conn = timed_conn("list_full_records", user_id, bucket);
+        query_records(&conn, "user = ?1 AND bucket = ?2 AND id > ?3", "ORDER BY id ASC LIMIT ?4",
+                      params![user_id, bucket, after_row_id, limit as i64])
+    }
 
-        let rows = stmt.query_map(params![limit], |row| {
-            let offset_size_bytes: Vec<u8> = row.get(4)?;
-            let offset_size_list = crate::util::serializer::deserialize_offset_size(&offset_size_bytes)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(4, "BLOB".to_string(), rusqlite::types::Type::Blob))?;
-            Ok(DeletionEvent {
-                id: row.get(0)?,
-                user_id: row.get(1)?,
-                bucket: row.get(2)?,
-                key: row.get(3)?,
-                offset_size_list,
-                created_at: row.get(5)?,
-            })
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
+    // The deletion queue methods are inherent below; these forward to them
 
-        let mut events = Vec::new();
-        for row in rows {
-            events.push(row.map_err(actix_web::error::ErrorInternalServerError)?);
-        }
-        Ok(events)
+    fn queue_deletion(&self, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<DeletionSummary, Error> {
+        SQLiteMetadataStore::queue_deletion(self, user_id, bucket, key, offset_size_list)
     }
 
-    pub fn mark_deletion_processed(&self, id: i64) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "UPDATE deletion_queue SET processed = TRUE WHERE id = ?1",
-            params![id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+    fn queue_spilled_deletion(&self, hash: &str, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<Option<DeletionSummary>, Error> {
+        SQLiteMetadataStore::queue_spilled_deletion(self, hash, user_id, bucket, key, offset_size_list)
     }
 
-    pub fn cleanup_old_deletions(&self) -> Result<usize, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let count = conn.execute(
-            "DELETE FROM deletion_queue WHERE processed = TRUE AND created_at < datetime('now', '-7 days')",
-            [],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        info!("Cleaned up {} old deletion events", count);
-        Ok(count)
+    fn get_pending_deletions(&self, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        SQLiteMetadataStore::get_pending_deletions(self, limit)
     }
-}
 
-/// CORS and bucket location operations
-impl SQLiteMetadataStore {
-    pub fn set_bucket_cors(&self, bucket: &str, cors_xml: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "INSERT OR REPLACE INTO bucket_cors (bucket, cors_xml) VALUES (?1, ?2)",
-            params![bucket, cors_xml],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+    fn get_pending_deletions_for_bucket(&self, user_id: &str, bucket: &str, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        SQLiteMetadataStore::get_pending_deletions_for_bucket(self, user_id, bucket, limit)
     }
 
-    pub fn get_bucket_cors(&self, bucket: &str) -> Result<Option<String>, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT cors_xml FROM bucket_cors WHERE bucket = ?1")
-            .map_err(actix_web::error::ErrorInternalServerError)?;
-        let result = stmt.query_row(params![bucket], |row| row.get::<_, String>(0));
-        match result {
-            Ok(xml) => Ok(Some(xml)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
-        }
+    fn deletion_pending(&self, user_id: &str, bucket: &str, key: &str) -> Result<bool, Error> {
+        SQLiteMetadataStore::deletion_pending(self, user_id, bucket, key)
     }
 
-    pub fn delete_bucket_cors(&self, bucket: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute("DELETE FROM bucket_cors WHERE bucket = ?1", params![bucket])
-            .map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+    fn pending_deletion_count(&self) -> Result<u64, Error> {
+        SQLiteMetadataStore::pending_deletion_count(self)
     }
 
-    pub fn set_bucket_location(&self, user_id: &str, bucket: &str, location: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "UPDATE buckets SET location = ?1 WHERE user = ?2 AND name = ?3",
-            params![location, user_id, bucket],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+    fn pending_deletion_count_for_bucket(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        SQLiteMetadataStore::pending_deletion_count_for_bucket(self, user_id, bucket)
     }
 
-    pub fn get_bucket_location(&self, user_id: &str, bucket: &str) -> Result<String, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT location FROM buckets WHERE user = ?1 AND name = ?2")
-            .map_err(actix_web::error::ErrorInternalServerError)?;
-        let result = stmt.query_row(params![user_id, bucket], |row| row.get::<_, Option<String>>(0));
-        match result {
-            Ok(loc) => Ok(loc.unwrap_or_default()),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(String::new()),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
-        }
+    fn pending_deletion_bytes(&self) -> Result<u64, Error> {
+        SQLiteMetadataStore::pending_deletion_bytes(self)
     }
-}
 
-/// Object + bucket tagging operations
-impl SQLiteMetadataStore {
-    /// Replace all tags for an object (atomic delete-then-insert within one lock).
-    pub fn set_object_tags(&self, user_id: &str, bucket: &str, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "DELETE FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
-            params![user_id, bucket, key],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        for (k, v) in tags {
-            conn.execute(
-                "INSERT INTO object_tags (user_id, bucket, key, tag_key, tag_value) VALUES (?1, ?2, ?3, ?4, ?5)",
-                params![user_id, bucket, key, k, v],
-            ).map_err(actix_web::error::ErrorInternalServerError)?;
-        }
-        Ok(())
+    fn mark_deletion_processed(&self, id: i64) -> Result<(), Error> {
+        SQLiteMetadataStore::mark_deletion_processed(self, id)
     }
 
-    pub fn get_object_tags(&self, user_id: &str, bucket: &str, key: &str) -> Result<Vec<(String, String)>, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT tag_key, tag_value FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3 ORDER BY tag_key",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        let rows = stmt.query_map(params![user_id, bucket, key], |row| {
-            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
-        let mut tags = Vec::new();
-        for row in rows {
-            tags.push(row.map_err(actix_web::error::ErrorInternalServerError)?);
-        }
-        Ok(tags)
+    fn record_deletion_failure(&self, ids: &[i64], error: &str) -> Result<(), Error> {
+        SQLiteMetadataStore::record_deletion_failure(self, ids, error)
     }
 
-    pub fn delete_object_tags(&self, user_id: &str, bucket: &str, key: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
+    fn complete_deletions(&self, user_id: &str, bucket: &str, freed: &[(u64, u64)], ids: &[i64]) -> Result<(), Error> {
+        SQLiteMetadataStore::complete_deletions(self, user_id, bucket, freed, ids)
+    }
+
+    fn cleanup_old_deletions(&self, retention_days: u32, batch: usize) -> Result<usize, Error> {
+        SQLiteMetadataStore::cleanup_old_deletions(self, retention_days, batch)
+    }
+
+    fn free_ranges(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        SQLiteMetadataStore::free_ranges(self, user_id, bucket)
+    }
+
+    fn free_ranges_overlapping(&self, user_id: &str, bucket: &str, ranges: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error> {
+        SQLiteMetadataStore::free_ranges_overlapping(self, user_id, bucket, ranges)
+    }
+
+    fn forget_free_ranges(&self, user_id: &str, bucket: &str, segment: u32) -> Result<(), Error> {
+        SQLiteMetadataStore::forget_free_ranges(self, user_id, bucket, segment)
+    }
+
+    fn live_extents(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        SQLiteMetadataStore::live_extents(self, user_id, bucket)
+    }
+}
+
+/// Drop one reference to each of `offset_size_list` and queue the ranges nobody references
+/// any more, within the caller's transaction; a queued range leaves `chunk_index`, so no new
+/// write can come to share it. Returns what was queued.
+fn queue_unreferenced(conn: &Connection, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<DeletionSummary, Error> {
+    let mut unreferenced = Vec::with_capacity(offset_size_list.len());
+    for &(offset, size) in offset_size_list {
+        let refs: Option<i64> = if size == 0 { None } else {
+            conn.query_row(
+                "SELECT refs FROM chunk_refs WHERE user_id = ?1 AND bucket = ?2 AND offset = ?3 AND size = ?4",
+                params![user_id, bucket, offset as i64, size as i64],
+                |row| row.get(0),
+            ).optional().map_err(db_error)?
+        };
+        match refs {
+            Some(n) if n > 2 => {
+                conn.execute(
+                    "UPDATE chunk_refs SET refs = refs - 1 WHERE user_id = ?1 AND bucket = ?2 AND offset = ?3 AND size = ?4",
+                    params![user_id, bucket, offset as i64, size as i64],
+                ).map_err(db_error)?;
+            }
+            Some(_) => {
+                conn.execute(
+                    "DELETE FROM chunk_refs WHERE user_id = ?1 AND bucket = ?2 AND offset = ?3 AND size = ?4",
+                    params![user_id, bucket, offset as i64, size as i64],
+                ).map_err(db_error)?;
+            }
+            None => {
+                conn.execute(
+                    "DELETE FROM chunk_index WHERE user_id = ?1 AND bucket = ?2 AND offset = ?3 AND size = ?4",
+                    params![user_id, bucket, offset as i64, size as i64],
+                ).map_err(db_error)?;
+                unreferenced.push((offset, size));
+            }
+        }
+    }
+    let summary = DeletionSummary {
+        chunks: unreferenced.len(),
+        bytes: unreferenced.iter().map(|(_, size)| size).sum(),
+    };
+    if summary.queued() {
+        let offset_size_bytes = encode_extents(&unreferenced)?;
         conn.execute(
+            "INSERT INTO deletion_queue (user_id, bucket, key, offset_size_list, bytes) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user_id, bucket, key, offset_size_bytes, summary.bytes as i64],
+        ).map_err(db_error)?;
+    }
+    Ok(summary)
+}
+
+/// Deletion queue — WAL for background storage GC
+impl SQLiteMetadataStore {
+    /// Drop one reference to each range and queue the ranges nobody references any more.
+    /// Ranges shared with a clone only lose a count in `chunk_refs`.
+    pub fn queue_deletion(&self, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<DeletionSummary, Error> {
+        let conn = timed_conn("queue_deletion", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let queued = queue_unreferenced(&tx, user_id, bucket, key, offset_size_list)?;
+        tx.commit().map_err(db_error)?;
+        info!("Queued deletion user={} bucket={} key={} chunks={} bytes={} still_shared={}",
+              user_id, bucket, key, queued.chunks, queued.bytes, offset_size_list.len() - queued.chunks);
+        Ok(queued)
+    }
+
+    /// [`Self::queue_deletion`] for a deletion replayed from the spill file, identified by
+    /// `hash`. Returns None when that deletion was replayed before.
+    pub fn queue_spilled_deletion(&self, hash: &str, user_id: &str, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<Option<DeletionSummary>, Error> {
+        let conn = timed_conn("queue_spilled_deletion", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let first = tx.execute("INSERT OR IGNORE INTO spilled_deletions (hash) VALUES (?1)", params![hash]).map_err(db_error)? > 0;
+        let queued = match first {
+            true => Some(queue_unreferenced(&tx, user_id, bucket, key, offset_size_list)?),
+            false => None,
+        };
+        tx.commit().map_err(db_error)?;
+        Ok(queued)
+    }
+
+    /// Point `dst_key` at `src_key`'s chunks without copying any data. The new row copies the
+    /// source's latest row, minus per-key properties such as append IDs and with a fresh
+    /// last-modified time, and each shared range gains a reference in `chunk_refs`, all in one
+    /// transaction. A live destination is an error unless `overwrite`, in which case its
+    /// extents are returned for `queue_deletion`.
+    pub fn clone_metadata(&self, user_id: &str, bucket: &str, src_key: &str, dst_key: &str, overwrite: bool) -> Result<Vec<(u64, u64)>, Error> {
+        let conn = timed_conn("clone_metadata", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let src_bytes: Option<Vec<u8>> = tx.query_row(
+            "SELECT offset_size_list FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0",
+            params![user_id, bucket, src_key],
+            |row| row.get(0),
+        ).optional().map_err(db_error)?
+            .ok_or_else(|| actix_web::error::ErrorNotFound(format!(
+                "No data found for key: {} in bucket: {}, The key does not exist", src_key, bucket
+            )))?;
+        let src_list = match src_bytes {
+            Some(bytes) => crate::util::serializer::decode_extents(&bytes)?,
+            None => Vec::new(),
+        };
+
+        let previous: Option<Option<Vec<u8>>> = tx.query_row(
+            "SELECT offset_size_list FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0",
+            params![user_id, bucket, dst_key],
+            |row| row.get(0),
+        ).optional().map_err(db_error)?;
+        let replaced = match previous {
+            Some(_) if !overwrite => return Err(MetadataError::already_exists(bucket, dst_key)),
+            Some(Some(bytes)) => crate::util::serializer::decode_extents(&bytes)?,
+            _ => Vec::new(),
+        };
+
+        tx.execute(
+            "DELETE FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND version_id = ''",
+            params![user_id, bucket, dst_key],
+        ).map_err(db_error)?;
+        tx.execute(
+            "UPDATE objects SET is_latest = 0 WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1",
+            params![user_id, bucket, dst_key],
+        ).map_err(db_error)?;
+        let last_modified = crate::metadata::last_modified_now();
+        tx.execute(
+            "INSERT INTO objects
+                (user, bucket, key, version_id, is_latest, is_delete_marker,
+                 offset_size_list, etag, size, content_type, last_modified,
+                 user_metadata, cache_control, expires, content_encoding, parts_manifest,
+                 checksum_algorithm, checksum_value, checksum_type, properties, expire_at, inline_data)
+             SELECT user, bucket, ?4, '', 1, 0,
+                    offset_size_list, etag, size, content_type, ?5,
+                    user_metadata, cache_control, expires, content_encoding, parts_manifest,
+                    checksum_algorithm, checksum_value, checksum_type, NULL, expire_at, inline_data
+             FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0",
+            params![user_id, bucket, src_key, dst_key, last_modified],
+        ).map_err(db_error)?;
+        for &(offset, size) in src_list.iter().filter(|(_, size)| *size > 0) {
+            tx.execute(
+                "INSERT INTO chunk_refs (user_id, bucket, offset, size, refs) VALUES (?1, ?2, ?3, ?4, 2)
+                 ON CONFLICT (user_id, bucket, offset, size) DO UPDATE SET refs = refs + 1",
+                params![user_id, bucket, offset as i64, size as i64],
+            ).map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(replaced)
+    }
+
+    /// Move `key`'s row from `src_bucket` to `dst_bucket`, pointing it at `new_list`, the
+    /// extents its data was copied to in the destination; tags move with it, all in one
+    /// transaction. The source row must still reference `expected_list`, the extents that were
+    /// copied, so a write that lands during the copy fails the move rather than being lost. A
+    /// live destination key is an error. The caller queues `expected_list` for deletion.
+    pub fn move_object(
+        &self,
+        user_id: &str,
+        src_bucket: &str,
+        key: &str,
+        dst_bucket: &str,
+        expected_list: &[(u64, u64)],
+        new_list: &[(u64, u64)],
+    ) -> Result<(), Error> {
+        let conn = timed_conn("move_object", user_id, src_bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let dst_live: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0)",
+            params![user_id, dst_bucket, key],
+            |row| row.get(0),
+        ).map_err(db_error)?;
+        if dst_live {
+            return Err(MetadataError::already_exists(dst_bucket, key));
+        }
+        let current: Option<Option<Vec<u8>>> = tx.query_row(
+            "SELECT offset_size_list FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND version_id = '' AND is_latest = 1 AND is_delete_marker = 0",
+            params![user_id, src_bucket, key],
+            |row| row.get(0),
+        ).optional().map_err(db_error)?;
+        let current = current.ok_or_else(|| actix_web::error::ErrorNotFound(format!(
+            "No data found for key: {} in bucket: {}, The key does not exist", key, src_bucket
+        )))?;
+        let current_chunks = match current {
+            Some(bytes) => decode_chunks(&bytes)?,
+            None => Vec::new(),
+        };
+        if !current_chunks.iter().map(DataChunk::extent).eq(expected_list.iter().copied()) {
+            return Err(actix_web::error::ErrorConflict(format!(
+                "Key: {} in bucket: {} was modified during the move", key, src_bucket
+            )));
+        }
+
+        tx.execute(
+            "DELETE FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND version_id = ''",
+            params![user_id, dst_bucket, key],
+        ).map_err(db_error)?;
+        tx.execute(
+            "UPDATE objects SET bucket = ?4, offset_size_list = ?5
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND version_id = ''",
+            params![user_id, src_bucket, key, dst_bucket, encode_chunks(&relocate(&current_chunks, new_list))?],
+        ).map_err(db_error)?;
+        tx.execute(
             "DELETE FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
-            params![user_id, bucket, key],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+            params![user_id, dst_bucket, key],
+        ).map_err(db_error)?;
+        tx.execute(
+            "UPDATE object_tags SET bucket = ?4 WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+            params![user_id, src_bucket, key, dst_bucket],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
         Ok(())
     }
 
-    pub fn get_object_tag_count(&self, user_id: &str, bucket: &str, key: &str) -> Result<i64, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let count: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
-            params![user_id, bucket, key],
+    /// Rebuild `user`/`bucket`'s `chunk_refs` from the object rows, dropping counts left
+    /// behind by interrupted operations, and unlist ranges no row references from
+    /// `chunk_index`. Returns the number of shared ranges.
+    pub fn rebuild_chunk_refs(&self, user_id: &str, bucket: &str) -> Result<usize, Error> {
+        let conn = timed_conn("rebuild_chunk_refs", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let mut counts: std::collections::HashMap<(u64, u64), i64> = std::collections::HashMap::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT offset_size_list FROM objects
+                 WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL",
+            ).map_err(db_error)?;
+            let rows = stmt.query_map(params![user_id, bucket], |row| row.get::<_, Vec<u8>>(0))
+                .map_err(db_error)?;
+            for row in rows {
+                let extents = crate::util::serializer::decode_extents(&row.map_err(db_error)?)?;
+                for extent in extents.into_iter().filter(|(_, size)| *size > 0) {
+                    *counts.entry(extent).or_insert(0) += 1;
+                }
+            }
+        }
+        tx.execute(
+            "DELETE FROM chunk_refs WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+        ).map_err(db_error)?;
+        let indexed: Vec<(i64, i64)> = {
+            let mut stmt = tx.prepare(
+                "SELECT offset, size FROM chunk_index WHERE user_id = ?1 AND bucket = ?2",
+            ).map_err(db_error)?;
+            let rows = stmt.query_map(params![user_id, bucket], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(db_error)?;
+            rows.collect::<Result<_, _>>().map_err(db_error)?
+        };
+        for (offset, size) in indexed.into_iter().filter(|(offset, size)| !counts.contains_key(&(*offset as u64, *size as u64))) {
+            tx.execute(
+                "DELETE FROM chunk_index WHERE user_id = ?1 AND bucket = ?2 AND offset = ?3 AND size = ?4",
+                params![user_id, bucket, offset, size],
+            ).map_err(db_error)?;
+        }
+        let mut shared = 0;
+        for ((offset, size), refs) in counts.into_iter().filter(|(_, refs)| *refs > 1) {
+            tx.execute(
+                "INSERT INTO chunk_refs (user_id, bucket, offset, size, refs) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![user_id, bucket, offset as i64, size as i64, refs],
+            ).map_err(db_error)?;
+            shared += 1;
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(shared)
+    }
+
+    /// The stored range whose bytes hash to `digest`, if `user`/`bucket` has one of `size`
+    /// bytes; it gains a reference in `chunk_refs` in the same transaction, so a delete can
+    /// no longer free it under the caller.
+    pub fn share_indexed_chunk(&self, user_id: &str, bucket: &str, digest: &[u8], size: u64) -> Result<Option<(u64, u64)>, Error> {
+        let conn = timed_conn("share_indexed_chunk", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let extent: Option<(i64, i64)> = tx.query_row(
+            "SELECT offset, size FROM chunk_index WHERE user_id = ?1 AND bucket = ?2 AND digest = ?3 AND size = ?4",
+            params![user_id, bucket, digest, size as i64],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional().map_err(db_error)?;
+        let Some((offset, size)) = extent else { return Ok(None) };
+        tx.execute(
+            "INSERT INTO chunk_refs (user_id, bucket, offset, size, refs) VALUES (?1, ?2, ?3, ?4, 2)
+             ON CONFLICT (user_id, bucket, offset, size) DO UPDATE SET refs = refs + 1",
+            params![user_id, bucket, offset, size],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(Some((offset as u64, size as u64)))
+    }
+
+    /// List the just-written range `extent` under `digest`. A range already listed for the
+    /// digest (a concurrent identical write) is kept and `extent` stays unlisted.
+    pub fn index_chunk(&self, user_id: &str, bucket: &str, digest: &[u8], extent: (u64, u64)) -> Result<(), Error> {
+        let conn = timed_conn("index_chunk", user_id, bucket);
+        conn.execute(
+            "INSERT OR IGNORE INTO chunk_index (user_id, bucket, digest, offset, size) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user_id, bucket, digest, extent.0 as i64, extent.1 as i64],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Stop offering `extent` to deduplicated writes
+    pub fn unindex_chunk(&self, user_id: &str, bucket: &str, extent: (u64, u64)) -> Result<(), Error> {
+        let conn = timed_conn("unindex_chunk", user_id, bucket);
+        conn.execute(
+            "DELETE FROM chunk_index WHERE user_id = ?1 AND bucket = ?2 AND offset = ?3 AND size = ?4",
+            params![user_id, bucket, extent.0 as i64, extent.1 as i64],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Ranges listed in `chunk_index` and the bytes stored once but referenced by more than
+    /// one object row (by clones or deduplicated writes) beyond their first reference
+    pub fn sharing_stats(&self, user_id: &str, bucket: &str) -> Result<(u64, u64), Error> {
+        let conn = timed_conn("sharing_stats", user_id, bucket);
+        let indexed: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM chunk_index WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
             |row| row.get(0),
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(count)
+        ).map_err(db_error)?;
+        let shared: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(size * (refs - 1)), 0) FROM chunk_refs WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+            |row| row.get(0),
+        ).map_err(db_error)?;
+        Ok((indexed as u64, shared as u64))
     }
 
-    pub fn set_bucket_tags(&self, bucket: &str, tags: &[(String, String)]) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute("DELETE FROM bucket_tags WHERE bucket = ?1", params![bucket])
-            .map_err(actix_web::error::ErrorInternalServerError)?;
-        for (k, v) in tags {
-            conn.execute(
-                "INSERT INTO bucket_tags (bucket, tag_key, tag_value) VALUES (?1, ?2, ?3)",
-                params![bucket, k, v],
-            ).map_err(actix_web::error::ErrorInternalServerError)?;
+    pub fn get_pending_deletions(&self, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        let conn = timed_conn("get_pending_deletions", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT id, user_id, bucket, key, offset_size_list, created_at, attempts, last_error
+             FROM deletion_queue
+             WHERE processed = FALSE
+             ORDER BY created_at ASC
+             LIMIT ?1",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![limit], deletion_event).map_err(db_error)?;
+        let mut events = Vec::new();
+        for row in rows {
+            events.push(row.map_err(db_error)?);
         }
-        Ok(())
+        Ok(events)
     }
 
-    pub fn get_bucket_tags(&self, bucket: &str) -> Result<Vec<(String, String)>, Error> {
-        let conn = DB_CONN.lock().unwrap();
+    /// As [`Self::get_pending_deletions`], for one bucket only
+    pub fn get_pending_deletions_for_bucket(&self, user_id: &str, bucket: &str, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        let conn = timed_conn("get_pending_deletions_for_bucket", user_id, bucket);
         let mut stmt = conn.prepare(
-            "SELECT tag_key, tag_value FROM bucket_tags WHERE bucket = ?1 ORDER BY tag_key",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        let rows = stmt.query_map(params![bucket], |row| {
-            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
-        let mut tags = Vec::new();
+            "SELECT id, user_id, bucket, key, offset_size_list, created_at, attempts, last_error
+             FROM deletion_queue
+             WHERE processed = FALSE AND user_id = ?1 AND bucket = ?2
+             ORDER BY created_at ASC
+             LIMIT ?3",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, limit], deletion_event).map_err(db_error)?;
+        let mut events = Vec::new();
         for row in rows {
-            tags.push(row.map_err(actix_web::error::ErrorInternalServerError)?);
+            events.push(row.map_err(db_error)?);
         }
-        Ok(tags)
+        Ok(events)
     }
 
-    pub fn delete_bucket_tags(&self, bucket: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute("DELETE FROM bucket_tags WHERE bucket = ?1", params![bucket])
-            .map_err(actix_web::error::ErrorInternalServerError)?;
+    /// Every extent of the bucket in the deletion queue, pending or processed
+    pub fn queued_extents(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        let conn = timed_conn("queued_extents", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT offset_size_list FROM deletion_queue WHERE user_id = ?1 AND bucket = ?2",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket], |row| row.get::<_, Vec<u8>>(0))
+            .map_err(db_error)?;
+        let mut extents = Vec::new();
+        for row in rows {
+            extents.extend(crate::util::serializer::decode_extents(&row.map_err(db_error)?)?);
+        }
+        Ok(extents)
+    }
+
+    /// Whether `key` has a deletion queued that the worker has not processed yet
+    pub fn deletion_pending(&self, user_id: &str, bucket: &str, key: &str) -> Result<bool, Error> {
+        let conn = timed_conn("deletion_pending", user_id, bucket);
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM deletion_queue
+                           WHERE user_id = ?1 AND bucket = ?2 AND key = ?3 AND processed = FALSE)",
+            params![user_id, bucket, key],
+            |row| row.get(0),
+        ).map_err(db_error)
+    }
+
+    /// Health probe of a user's metadata: in one (otherwise empty) transaction, whether
+    /// `bucket` exists and the first key, by name, of an object whose data is in the bucket
+    /// file rather than the row
+    pub fn health_probe(&self, user_id: &str, bucket: &str) -> Result<(bool, Option<String>), Error> {
+        let conn = timed_conn("health_probe", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let exists: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM buckets WHERE user = ?1 AND name = ?2",
+            params![user_id, bucket],
+            |row| row.get(0),
+        ).map_err(db_error)?;
+        let key = tx.query_row(
+            "SELECT key FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND is_latest = 1 AND is_delete_marker = 0
+               AND size > 0 AND inline_data IS NULL
+             ORDER BY key LIMIT 1",
+            params![user_id, bucket],
+            |row| row.get::<_, String>(0),
+        ).optional().map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok((exists > 0, key))
+    }
+
+    /// Every recorded free range of a bucket, by offset
+    pub fn free_ranges(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        let conn = timed_conn("free_ranges", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT offset, size FROM free_ranges WHERE user_id = ?1 AND bucket = ?2 ORDER BY offset",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(
+            params![user_id, bucket],
+            |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+        ).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    /// Recorded free ranges overlapping any of `ranges`
+    pub fn free_ranges_overlapping(&self, user_id: &str, bucket: &str, ranges: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error> {
+        let conn = timed_conn("free_ranges_overlapping", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT offset, size FROM free_ranges
+             WHERE user_id = ?1 AND bucket = ?2 AND offset < ?4 AND offset + size > ?3
+             ORDER BY offset",
+        ).map_err(db_error)?;
+        let mut free = Vec::new();
+        for &(offset, size) in ranges {
+            let rows = stmt.query_map(
+                params![user_id, bucket, offset as i64, (offset + size) as i64],
+                |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+            ).map_err(db_error)?;
+            for row in rows {
+                free.push(row.map_err(db_error)?);
+            }
+        }
+        free.sort_unstable();
+        free.dedup();
+        Ok(free)
+    }
+
+    /// Record `freed` as free and mark the deletion events `ids` processed, in one
+    /// transaction. Each range is merged with the rows it overlaps or touches within its
+    /// segment, so recording a range twice leaves the table unchanged.
+    pub fn complete_deletions(&self, user_id: &str, bucket: &str, freed: &[(u64, u64)], ids: &[i64]) -> Result<(), Error> {
+        use crate::storage::segment;
+        let conn = timed_conn("complete_deletions", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        for &(offset, size) in freed.iter().filter(|(_, size)| *size > 0) {
+            let n = segment::segment_of(offset);
+            let (base, next) = (segment::address(n, 0), segment::address(n + 1, 0));
+            let (mut start, mut end) = (offset, offset + size);
+            let neighbours: Vec<(u64, u64)> = {
+                let mut stmt = tx.prepare(
+                    "SELECT offset, size FROM free_ranges
+                     WHERE user_id = ?1 AND bucket = ?2 AND offset >= ?3 AND offset <= ?5 AND offset + size >= ?4",
+                ).map_err(db_error)?;
+                let rows = stmt.query_map(
+                    params![user_id, bucket, base as i64, start as i64, end.min(next - 1) as i64],
+                    |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+                ).map_err(db_error)?;
+                rows.collect::<rusqlite::Result<_>>().map_err(db_error)?
+            };
+            for (other, other_size) in neighbours {
+                start = start.min(other);
+                end = end.max(other + other_size);
+                tx.execute(
+                    "DELETE FROM free_ranges WHERE user_id = ?1 AND bucket = ?2 AND offset = ?3",
+                    params![user_id, bucket, other as i64],
+                ).map_err(db_error)?;
+            }
+            tx.execute(
+                "INSERT INTO free_ranges (user_id, bucket, offset, size) VALUES (?1, ?2, ?3, ?4)",
+                params![user_id, bucket, start as i64, (end - start) as i64],
+            ).map_err(db_error)?;
+        }
+        for id in ids {
+            tx.execute(
+                "UPDATE deletion_queue SET processed = TRUE, processed_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                params![id],
+            ).map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    /// Drop the free ranges of a segment file that has been unlinked
+    pub fn forget_free_ranges(&self, user_id: &str, bucket: &str, segment: u32) -> Result<(), Error> {
+        use crate::storage::segment;
+        let conn = timed_conn("forget_free_ranges", user_id, bucket);
+        conn.execute(
+            "DELETE FROM free_ranges WHERE user_id = ?1 AND bucket = ?2 AND offset >= ?3 AND offset < ?4",
+            params![user_id, bucket, segment::address(segment, 0) as i64, segment::address(segment + 1, 0) as i64],
+        ).map_err(db_error)?;
         Ok(())
     }
 
-    pub fn set_multipart_tagging(&self, upload_id: &str, tagging: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
+    /// Count a failed attempt at freeing each unprocessed event of `ids` and keep `error` as
+    /// its last error
+    pub fn record_deletion_failure(&self, ids: &[i64], error: &str) -> Result<(), Error> {
+        let mut conn = timed_conn("record_deletion_failure", "", "");
+        let tx = conn.transaction().map_err(db_error)?;
+        for id in ids {
+            tx.execute(
+                "UPDATE deletion_queue SET attempts = attempts + 1, last_error = ?2 WHERE id = ?1 AND processed = FALSE",
+                params![id, error],
+            ).map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    pub fn mark_deletion_processed(&self, id: i64) -> Result<(), Error> {
+        let conn = timed_conn("mark_deletion_processed", "", "");
         conn.execute(
-            "UPDATE multipart_uploads SET tagging = ?1 WHERE upload_id = ?2",
-            params![tagging, upload_id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+            "UPDATE deletion_queue SET processed = TRUE, processed_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            params![id],
+        ).map_err(db_error)?;
         Ok(())
     }
 
-    pub fn get_multipart_tagging(&self, upload_id: &str) -> Result<String, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let result: rusqlite::Result<Option<String>> = conn.query_row(
-            "SELECT tagging FROM multipart_uploads WHERE upload_id = ?1",
-            params![upload_id],
+    /// Deletion events the worker has not processed yet
+    pub fn pending_deletion_count(&self) -> Result<u64, Error> {
+        let conn = timed_conn("pending_deletion_count", "", "");
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM deletion_queue WHERE processed = FALSE",
+            [],
             |row| row.get(0),
-        );
-        match result {
-            Ok(t) => Ok(t.unwrap_or_default()),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(String::new()),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+        ).map_err(db_error)?;
+        Ok(count as u64)
+    }
+
+    /// Total size of the ranges of unprocessed deletion events
+    pub fn pending_deletion_bytes(&self) -> Result<u64, Error> {
+        let conn = timed_conn("pending_deletion_bytes", "", "");
+        let bytes: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(bytes), 0) FROM deletion_queue WHERE processed = FALSE",
+            [],
+            |row| row.get(0),
+        ).map_err(db_error)?;
+        Ok(bytes as u64)
+    }
+
+    /// As [`Self::pending_deletion_count`], for one bucket only
+    pub fn pending_deletion_count_for_bucket(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        let conn = timed_conn("pending_deletion_count_for_bucket", user_id, bucket);
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM deletion_queue WHERE processed = FALSE AND user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+            |row| row.get(0),
+        ).map_err(db_error)?;
+        Ok(count as u64)
+    }
+
+    /// Drop processed events whose processing is more than `retention_days` old, at most
+    /// `batch` rows per statement; the connection is released between batches so a large
+    /// backlog does not hold the database. Returns the number of rows removed.
+    pub fn cleanup_old_deletions(&self, retention_days: u32, batch: usize) -> Result<usize, Error> {
+        let cutoff = format!("-{} days", retention_days);
+        let batch = batch.max(1);
+        let mut total = 0;
+        loop {
+            let removed = {
+                let conn = timed_conn("cleanup_old_deletions", "", "");
+                conn.execute(
+                    "DELETE FROM deletion_queue WHERE id IN (
+                         SELECT id FROM deletion_queue
+                         WHERE processed = TRUE AND COALESCE(processed_at, created_at) < datetime('now', ?1)
+                         LIMIT ?2)",
+                    params![cutoff, batch as i64],
+                ).map_err(db_error)?
+            };
+            if removed == 0 {
+                break;
+            }
+            metrics::inc_counter("warpdrive_deletion_cleanup_batches_total", &[]);
+            total += removed;
+            if removed < batch {
+                break;
+            }
+        }
+        if total > 0 {
+            info!("Cleaned up {} old deletion events", total);
+        }
+        Ok(total)
+    }
+
+    /// Every extent in a bucket file that is accounted for: object rows (all versions and
+    /// delete markers), multipart parts, and deletion-queue entries (pending or processed).
+    pub fn referenced_extents(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.collect_extents("referenced_extents", user_id, bucket,
+            "SELECT offset_size_list FROM deletion_queue WHERE user_id = ?1 AND bucket = ?2")
+    }
+
+    /// Extents whose bytes must stay readable: object rows, multipart parts, and deletion-queue
+    /// entries the worker has not freed yet. A sealed segment with none of these can be unlinked.
+    pub fn live_extents(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.collect_extents("live_extents", user_id, bucket,
+            "SELECT offset_size_list FROM deletion_queue WHERE user_id = ?1 AND bucket = ?2 AND processed = FALSE")
+    }
+
+    fn collect_extents(&self, op: &'static str, user_id: &str, bucket: &str, deletion_query: &str) -> Result<Vec<(u64, u64)>, Error> {
+        let conn = timed_conn(op, user_id, bucket);
+        let mut blobs: Vec<Vec<u8>> = Vec::new();
+        let queries = [
+            "SELECT offset_size_list FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL",
+            "SELECT p.extents_blob FROM multipart_parts p
+             JOIN multipart_uploads u ON u.upload_id = p.upload_id
+             WHERE u.user_id = ?1 AND u.bucket = ?2",
+            deletion_query,
+        ];
+        for sql in queries {
+            let mut stmt = conn.prepare(sql).map_err(db_error)?;
+            let rows = stmt.query_map(params![user_id, bucket], |row| row.get::<_, Vec<u8>>(0))
+                .map_err(db_error)?;
+            for row in rows {
+                blobs.push(row.map_err(db_error)?);
+            }
         }
+        drop(conn);
+
+        let mut extents = Vec::new();
+        for blob in blobs {
+            extents.extend(crate::util::serializer::decode_extents(&blob)?);
+        }
+        Ok(extents)
     }
 }
 
-/// Versioning operations
+/// CORS and bucket location operations
 impl SQLiteMetadataStore {
-    pub fn get_versioning_state(&self, bucket: &str) -> Result<String, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let result: rusqlite::Result<Option<String>> = conn.query_row(
-            "SELECT versioning_state FROM buckets WHERE name = ?1",
-            params![bucket],
-            |row| row.get(0),
-        );
-        match result {
-            Ok(s) => Ok(s.unwrap_or_else(|| "disabled".to_string())),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok("disabled".to_string()),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+    /// Every user owning at least one bucket
+    pub fn list_users(&self) -> Result<Vec<String>, Error> {
+        let conn = timed_conn("list_users", "", "");
+        let mut stmt = conn.prepare("SELECT DISTINCT user FROM buckets ORDER BY user").map_err(db_error)?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0)).map_err(db_error)?;
+        let mut users = Vec::new();
+        for row in rows {
+            users.push(row.map_err(db_error)?);
         }
+        Ok(users)
     }
 
-    pub fn set_versioning_state(&self, user_id: &str, bucket: &str, state: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
+    pub fn set_bucket_cors(&self, bucket: &str, cors_xml: &str) -> Result<(), Error> {
+        let conn = timed_conn("set_bucket_cors", "", bucket);
         conn.execute(
-            "UPDATE buckets SET versioning_state = ?1 WHERE user = ?2 AND name = ?3",
-            params![state, user_id, bucket],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+            "INSERT OR REPLACE INTO bucket_cors (bucket, cors_xml) VALUES (?1, ?2)",
+            params![bucket, cors_xml],
+        ).map_err(db_error)?;
         Ok(())
     }
 
-    /// Versioning-aware PUT. Returns Some(version_id) when versioning is enabled/suspended,
-    /// None when versioning is disabled (overwrites in place).
-    /// Write a new object version.
-    /// Returns (version_id, old_extents_to_gc).
-    /// - version_id: Some(vid) when versioning enabled/suspended, None when disabled.
-    /// - old_extents_to_gc: extents of the row that was replaced (caller should queue for GC).
-    pub fn put_object_v2(
-        &self, user_id: &str, bucket: &str, key: &str, metadata: &Metadata,
-    ) -> Result<(Option<String>, Vec<(u64, u64)>), Error> {
-        let versioning = self.get_versioning_state(bucket)?;
-        let offset_size_list = metadata.to_offset_size_list();
-        let offset_size_bytes = serialize_offset_size(&offset_size_list)?;
-        let user_metadata_json = serde_json::to_string(&metadata.user_metadata)
-            .unwrap_or_else(|_| "{}".to_string());
+    pub fn get_bucket_cors(&self, bucket: &str) -> Result<Option<String>, Error> {
+        let conn = timed_conn("get_bucket_cors", "", bucket);
+        let mut stmt = conn.prepare("SELECT cors_xml FROM bucket_cors WHERE bucket = ?1")
+            .map_err(db_error)?;
+        let result = stmt.query_row(params![bucket], |row| row.get::<_, String>(0));
+        match result {
+            Ok(xml) => Ok(Some(xml)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(db_error(e)),
+        }
+    }
 
-        let conn = DB_CONN.lock().unwrap();
+    pub fn delete_bucket_cors(&self, bucket: &str) -> Result<(), Error> {
+        let conn = timed_conn("delete_bucket_cors", "", bucket);
+        conn.execute("DELETE FROM bucket_cors WHERE bucket = ?1", params![bucket])
+            .map_err(db_error)?;
+        Ok(())
+    }
 
-        match versioning.as_str() {
-            "disabled" => {
-                // Read old extents before deleting so the caller can GC them.
-                let old_extents: Vec<(u64, u64)> = conn.query_row(
-                    "SELECT offset_size_list FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id=''",
-                    params![user_id, bucket, key],
-                    |row| row.get::<_, Option<Vec<u8>>>(0),
-                ).unwrap_or(None)
-                .and_then(|b| crate::util::serializer::deserialize_offset_size(&b).ok())
-                .unwrap_or_default();
+    pub fn set_bucket_location(&self, user_id: &str, bucket: &str, location: &str) -> Result<(), Error> {
+        let conn = timed_conn("set_bucket_location", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "UPDATE buckets SET location = ?1 WHERE user = ?2 AND name = ?3",
+            params![location, user_id, bucket],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
 
-                conn.execute(
-                    "DELETE FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id=''",
-                    params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+    pub fn get_bucket_location(&self, user_id: &str, bucket: &str) -> Result<String, Error> {
+        let conn = timed_conn("get_bucket_location", user_id, bucket);
+        let mut stmt = conn.prepare("SELECT location FROM buckets WHERE user = ?1 AND name = ?2")
+            .map_err(db_error)?;
+        let result = stmt.query_row(params![user_id, bucket], |row| row.get::<_, Option<String>>(0));
+        match result {
+            Ok(loc) => Ok(loc.unwrap_or_default()),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(String::new()),
+            Err(e) => Err(db_error(e)),
+        }
+    }
+}
+
+/// Object + bucket tagging operations
+impl SQLiteMetadataStore {
+    /// Replace all tags for an object (atomic delete-then-insert within one lock).
+    pub fn set_object_tags(&self, user_id: &str, bucket: &str, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        let conn = timed_conn("set_object_tags", user_id, bucket);
+        conn.execute(
+            "DELETE FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+            params![user_id, bucket, key],
+        ).map_err(db_error)?;
+        for (k, v) in tags {
+            conn.execute(
+                "INSERT INTO object_tags (user_id, bucket, key, tag_key, tag_value) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![user_id, bucket, key, k, v],
+            ).map_err(db_error)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_object_tags(&self, user_id: &str, bucket: &str, key: &str) -> Result<Vec<(String, String)>, Error> {
+        let conn = timed_conn("get_object_tags", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT tag_key, tag_value FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3 ORDER BY tag_key",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, key], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        }).map_err(db_error)?;
+        let mut tags = Vec::new();
+        for row in rows {
+            tags.push(row.map_err(db_error)?);
+        }
+        Ok(tags)
+    }
+
+    /// Tags of each of `keys` that has any, by key
+    pub fn get_tags_for_keys(&self, user_id: &str, bucket: &str, keys: &[&str]) -> Result<std::collections::HashMap<String, Vec<(String, String)>>, Error> {
+        let mut tags: std::collections::HashMap<String, Vec<(String, String)>> = std::collections::HashMap::new();
+        if keys.is_empty() {
+            return Ok(tags);
+        }
+        let conn = timed_conn("get_tags_for_keys", user_id, bucket);
+        let placeholders = vec!["?"; keys.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT key, tag_key, tag_value FROM object_tags WHERE user_id = ? AND bucket = ? AND key IN ({})
+             ORDER BY key, tag_key",
+            placeholders,
+        )).map_err(db_error)?;
+        let params = [user_id, bucket].into_iter().chain(keys.iter().copied());
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        }).map_err(db_error)?;
+        for row in rows {
+            let (key, tag_key, tag_value) = row.map_err(db_error)?;
+            tags.entry(key).or_default().push((tag_key, tag_value));
+        }
+        Ok(tags)
+    }
+
+    /// Keys of live objects in `bucket` tagged `tag_key=tag_value`, in key order
+    pub fn list_objects_with_tag(&self, user_id: &str, bucket: &str, tag_key: &str, tag_value: &str) -> Result<Vec<String>, Error> {
+        let conn = timed_conn("list_objects_with_tag", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT o.key FROM objects o
+             JOIN object_tags t ON t.user_id = o.user AND t.bucket = o.bucket AND t.key = o.key
+             WHERE o.user = ?1 AND o.bucket = ?2 AND o.is_latest = 1 AND o.is_delete_marker = 0
+               AND t.tag_key = ?3 AND t.tag_value = ?4
+             ORDER BY o.key",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, tag_key, tag_value], |row| row.get::<_, String>(0))
+            .map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+
+    pub fn delete_object_tags(&self, user_id: &str, bucket: &str, key: &str) -> Result<(), Error> {
+        let conn = timed_conn("delete_object_tags", user_id, bucket);
+        conn.execute(
+            "DELETE FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+            params![user_id, bucket, key],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn get_object_tag_count(&self, user_id: &str, bucket: &str, key: &str) -> Result<i64, Error> {
+        let conn = timed_conn("get_object_tag_count", user_id, bucket);
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+            params![user_id, bucket, key],
+            |row| row.get(0),
+        ).map_err(db_error)?;
+        Ok(count)
+    }
+
+    pub fn set_bucket_tags(&self, bucket: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        let conn = timed_conn("set_bucket_tags", "", bucket);
+        conn.execute("DELETE FROM bucket_tags WHERE bucket = ?1", params![bucket])
+            .map_err(db_error)?;
+        for (k, v) in tags {
+            conn.execute(
+                "INSERT INTO bucket_tags (bucket, tag_key, tag_value) VALUES (?1, ?2, ?3)",
+                params![bucket, k, v],
+            ).map_err(db_error)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_bucket_tags(&self, bucket: &str) -> Result<Vec<(String, String)>, Error> {
+        let conn = timed_conn("get_bucket_tags", "", bucket);
+        let mut stmt = conn.prepare(
+            "SELECT tag_key, tag_value FROM bucket_tags WHERE bucket = ?1 ORDER BY tag_key",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![bucket], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        }).map_err(db_error)?;
+        let mut tags = Vec::new();
+        for row in rows {
+            tags.push(row.map_err(db_error)?);
+        }
+        Ok(tags)
+    }
+
+    pub fn delete_bucket_tags(&self, bucket: &str) -> Result<(), Error> {
+        let conn = timed_conn("delete_bucket_tags", "", bucket);
+        conn.execute("DELETE FROM bucket_tags WHERE bucket = ?1", params![bucket])
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn set_multipart_tagging(&self, upload_id: &str, tagging: &str) -> Result<(), Error> {
+        let conn = timed_conn("set_multipart_tagging", "", "");
+        conn.execute(
+            "UPDATE multipart_uploads SET tagging = ?1 WHERE upload_id = ?2",
+            params![tagging, upload_id],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn get_multipart_tagging(&self, upload_id: &str) -> Result<String, Error> {
+        let conn = timed_conn("get_multipart_tagging", "", "");
+        let result: rusqlite::Result<Option<String>> = conn.query_row(
+            "SELECT tagging FROM multipart_uploads WHERE upload_id = ?1",
+            params![upload_id],
+            |row| row.get(0),
+        );
+        match result {
+            Ok(t) => Ok(t.unwrap_or_default()),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(String::new()),
+            Err(e) => Err(db_error(e)),
+        }
+    }
+}
+
+/// Versioning operations
+impl SQLiteMetadataStore {
+    pub fn get_versioning_state(&self, bucket: &str) -> Result<String, Error> {
+        let conn = timed_conn("get_versioning_state", "", bucket);
+        let result: rusqlite::Result<Option<String>> = conn.query_row(
+            "SELECT versioning_state FROM buckets WHERE name = ?1",
+            params![bucket],
+            |row| row.get(0),
+        );
+        match result {
+            Ok(s) => Ok(s.unwrap_or_else(|| "disabled".to_string())),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok("disabled".to_string()),
+            Err(e) => Err(db_error(e)),
+        }
+    }
+
+    pub fn set_versioning_state(&self, user_id: &str, bucket: &str, state: &str) -> Result<(), Error> {
+        let conn = timed_conn("set_versioning_state", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "UPDATE buckets SET versioning_state = ?1 WHERE user = ?2 AND name = ?3",
+            params![state, user_id, bucket],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Versioning-aware PUT. Returns Some(version_id) when versioning is enabled/suspended,
+    /// None when versioning is disabled (overwrites in place).
+    /// Write a new object version.
+    /// Returns (version_id, old_extents_to_gc).
+    /// - version_id: Some(vid) when versioning enabled/suspended, None when disabled.
+    /// - old_extents_to_gc: extents of the row that was replaced (caller should queue for GC).
+    pub fn put_object_v2(
+        &self, user_id: &str, bucket: &str, key: &str, metadata: &Metadata,
+    ) -> Result<PutObjectOutcome, Error> {
+        let versioning = self.get_versioning_state(bucket)?;
+        let offset_size_bytes = encode_chunks(&metadata.chunks)?;
+        let user_metadata_json = serde_json::to_string(&metadata.user_metadata)
+            .unwrap_or_else(|_| "{}".to_string());
+        let properties_json = serde_json::to_string(&metadata.properties)
+            .unwrap_or_else(|_| "{}".to_string());
+
+        let conn = timed_conn("put_object_v2", user_id, bucket);
+
+        match versioning.as_str() {
+            "disabled" => {
+                // Read old extents before deleting so the caller can GC them.
+                let old_extents: Vec<(u64, u64)> = conn.query_row(
+                    "SELECT offset_size_list FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id=''",
+                    params![user_id, bucket, key],
+                    |row| row.get::<_, Option<Vec<u8>>>(0),
+                ).unwrap_or(None)
+                .and_then(|b| crate::util::serializer::decode_extents(&b).ok())
+                .unwrap_or_default();
+
+                conn.execute(
+                    "DELETE FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id=''",
+                    params![user_id, bucket, key],
+                ).map_err(db_error)?;
                 conn.execute(
                     "INSERT INTO objects
                         (user,bucket,key,version_id,is_latest,is_delete_marker,
                          offset_size_list,etag,size,content_type,last_modified,
                          user_metadata,cache_control,expires,content_encoding,parts_manifest,
-                         checksum_algorithm,checksum_value,checksum_type)
-                     VALUES(?1,?2,?3,'',1,0,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16)",
+                         checksum_algorithm,checksum_value,checksum_type,properties,expire_at,inline_data)
+                     VALUES(?1,?2,?3,'',1,0,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19)",
                     params![user_id,bucket,key,offset_size_bytes,metadata.etag,
                             metadata.size as i64,metadata.content_type,metadata.last_modified,
                             user_metadata_json,metadata.cache_control,metadata.expires,
                             metadata.content_encoding,metadata.properties.get("parts_manifest"),
                             metadata.checksum_algorithm.as_deref().unwrap_or(""),
                             metadata.checksum_value.as_deref().unwrap_or(""),
-                            metadata.checksum_type.as_deref().unwrap_or("")],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                            metadata.checksum_type.as_deref().unwrap_or(""),
+                            properties_json,metadata.expire_at,metadata.inline_data],
+                ).map_err(db_error)?;
                 Ok((None, old_extents))
             }
             "enabled" => {
@@ -823,22 +2492,23 @@ impl SQLiteMetadataStore {
                 conn.execute(
                     "UPDATE objects SET is_latest=0 WHERE user=?1 AND bucket=?2 AND key=?3 AND is_latest=1",
                     params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
                 conn.execute(
                     "INSERT INTO objects
                         (user,bucket,key,version_id,is_latest,is_delete_marker,
                          offset_size_list,etag,size,content_type,last_modified,
                          user_metadata,cache_control,expires,content_encoding,parts_manifest,
-                         checksum_algorithm,checksum_value,checksum_type)
-                     VALUES(?1,?2,?3,?4,1,0,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17)",
+                         checksum_algorithm,checksum_value,checksum_type,properties,expire_at,inline_data)
+                     VALUES(?1,?2,?3,?4,1,0,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,?20)",
                     params![user_id,bucket,key,vid,offset_size_bytes,metadata.etag,
                             metadata.size as i64,metadata.content_type,metadata.last_modified,
                             user_metadata_json,metadata.cache_control,metadata.expires,
                             metadata.content_encoding,metadata.properties.get("parts_manifest"),
                             metadata.checksum_algorithm.as_deref().unwrap_or(""),
                             metadata.checksum_value.as_deref().unwrap_or(""),
-                            metadata.checksum_type.as_deref().unwrap_or("")],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                            metadata.checksum_type.as_deref().unwrap_or(""),
+                            properties_json,metadata.expire_at,metadata.inline_data],
+                ).map_err(db_error)?;
                 Ok((Some(vid), vec![]))
             }
             _ /* "suspended" */ => {
@@ -850,7 +2520,7 @@ impl SQLiteMetadataStore {
                         params![user_id, bucket, key, vid],
                         |row| row.get::<_, Option<Vec<u8>>>(0),
                     ).unwrap_or(None)
-                    .and_then(|b| crate::util::serializer::deserialize_offset_size(&b).ok())
+                    .and_then(|b| crate::util::serializer::decode_extents(&b).ok())
                     .unwrap_or_default()
                 };
                 let old_extents: Vec<(u64, u64)> = {
@@ -862,30 +2532,31 @@ impl SQLiteMetadataStore {
                 conn.execute(
                     "DELETE FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id='null'",
                     params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
                 conn.execute(
                     "DELETE FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id=''",
                     params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
                 conn.execute(
                     "UPDATE objects SET is_latest=0 WHERE user=?1 AND bucket=?2 AND key=?3 AND is_latest=1",
                     params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
                 conn.execute(
                     "INSERT INTO objects
                         (user,bucket,key,version_id,is_latest,is_delete_marker,
                          offset_size_list,etag,size,content_type,last_modified,
                          user_metadata,cache_control,expires,content_encoding,parts_manifest,
-                         checksum_algorithm,checksum_value,checksum_type)
-                     VALUES(?1,?2,?3,'null',1,0,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16)",
+                         checksum_algorithm,checksum_value,checksum_type,properties,expire_at,inline_data)
+                     VALUES(?1,?2,?3,'null',1,0,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19)",
                     params![user_id,bucket,key,offset_size_bytes,metadata.etag,
                             metadata.size as i64,metadata.content_type,metadata.last_modified,
                             user_metadata_json,metadata.cache_control,metadata.expires,
                             metadata.content_encoding,metadata.properties.get("parts_manifest"),
                             metadata.checksum_algorithm.as_deref().unwrap_or(""),
                             metadata.checksum_value.as_deref().unwrap_or(""),
-                            metadata.checksum_type.as_deref().unwrap_or("")],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                            metadata.checksum_type.as_deref().unwrap_or(""),
+                            properties_json,metadata.expire_at,metadata.inline_data],
+                ).map_err(db_error)?;
                 Ok((Some("null".to_string()), old_extents))
             }
         }
@@ -894,29 +2565,33 @@ impl SQLiteMetadataStore {
     /// Versioning-aware DELETE (no explicit versionId).
     pub fn delete_object_v2(&self, user_id: &str, bucket: &str, key: &str) -> Result<VersioningDeleteResult, Error> {
         let versioning = self.get_versioning_state(bucket)?;
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("delete_object_v2", user_id, bucket);
 
         match versioning.as_str() {
             "disabled" => {
                 conn.execute(
                     "DELETE FROM objects WHERE user=?1 AND bucket=?2 AND key=?3",
                     params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
+                conn.execute(
+                    "DELETE FROM object_tags WHERE user_id=?1 AND bucket=?2 AND key=?3",
+                    params![user_id, bucket, key],
+                ).map_err(db_error)?;
                 Ok(VersioningDeleteResult::Deleted)
             }
             "enabled" => {
                 let vid = generate_version_id();
-                let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S.000Z").to_string();
+                let now = crate::metadata::last_modified_now();
                 conn.execute(
                     "UPDATE objects SET is_latest=0 WHERE user=?1 AND bucket=?2 AND key=?3 AND is_latest=1",
                     params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
                 conn.execute(
                     "INSERT INTO objects
                         (user,bucket,key,version_id,is_latest,is_delete_marker,size,last_modified)
                      VALUES(?1,?2,?3,?4,1,1,0,?5)",
                     params![user_id, bucket, key, vid, now],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
                 Ok(VersioningDeleteResult::Marker { version_id: vid })
             }
             _ /* "suspended" */ => {
@@ -924,18 +2599,18 @@ impl SQLiteMetadataStore {
                 conn.execute(
                     "DELETE FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id='null'",
                     params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
                 conn.execute(
                     "UPDATE objects SET is_latest=0 WHERE user=?1 AND bucket=?2 AND key=?3 AND is_latest=1",
                     params![user_id, bucket, key],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
-                let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S.000Z").to_string();
+                ).map_err(db_error)?;
+                let now = crate::metadata::last_modified_now();
                 conn.execute(
                     "INSERT INTO objects
                         (user,bucket,key,version_id,is_latest,is_delete_marker,size,last_modified)
                      VALUES(?1,?2,?3,'null',1,1,0,?4)",
                     params![user_id, bucket, key, now],
-                ).map_err(actix_web::error::ErrorInternalServerError)?;
+                ).map_err(db_error)?;
                 Ok(VersioningDeleteResult::Marker { version_id: "null".to_string() })
             }
         }
@@ -944,7 +2619,7 @@ impl SQLiteMetadataStore {
     /// Permanently delete a specific version (DELETE ?versionId=x).
     /// Returns info about what was deleted.
     pub fn delete_specific_version(&self, user_id: &str, bucket: &str, key: &str, version_id: &str) -> Result<DeleteSpecificResult, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("delete_specific_version", user_id, bucket);
 
         // "null" from the client matches both '' (versioning never enabled) and 'null' (suspended null-version).
         let effective_vid: &str = if version_id == "null" {
@@ -968,7 +2643,7 @@ impl SQLiteMetadataStore {
             Err(rusqlite::Error::QueryReturnedNoRows) => {
                 return Ok(DeleteSpecificResult { found: false, was_delete_marker: false, version_id: version_id.to_string() });
             }
-            Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
+            Err(e) => return Err(db_error(e)),
             Ok(_) => {}
         }
         let (is_dm, was_latest) = row_result.unwrap();
@@ -977,7 +2652,7 @@ impl SQLiteMetadataStore {
         conn.execute(
             "DELETE FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id=?4",
             params![user_id, bucket, key, effective_vid],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
 
         // If the deleted row was is_latest, promote the most-recently-inserted remaining row.
         if was_latest != 0 {
@@ -986,8 +2661,14 @@ impl SQLiteMetadataStore {
                      SELECT id FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 ORDER BY id DESC LIMIT 1
                  )",
                 params![user_id, bucket, key],
-            ).map_err(actix_web::error::ErrorInternalServerError)?;
+            ).map_err(db_error)?;
         }
+        // Tags are kept per key, so they go with the last version
+        conn.execute(
+            "DELETE FROM object_tags WHERE user_id=?1 AND bucket=?2 AND key=?3
+               AND NOT EXISTS (SELECT 1 FROM objects WHERE user=?1 AND bucket=?2 AND key=?3)",
+            params![user_id, bucket, key],
+        ).map_err(db_error)?;
 
         Ok(DeleteSpecificResult {
             found: true,
@@ -998,7 +2679,7 @@ impl SQLiteMetadataStore {
 
     /// Returns the `last_modified` of the `is_latest=1` row for a key, including delete markers.
     pub fn get_latest_last_modified(&self, user_id: &str, bucket: &str, key: &str) -> Result<Option<String>, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("get_latest_last_modified", user_id, bucket);
         match conn.query_row(
             "SELECT last_modified FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND is_latest=1",
             params![user_id, bucket, key],
@@ -1006,13 +2687,13 @@ impl SQLiteMetadataStore {
         ) {
             Ok(lm) => Ok(lm),
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+            Err(e) => Err(db_error(e)),
         }
     }
 
     /// Fetch a specific version of an object.
     pub fn get_object_version(&self, user_id: &str, bucket: &str, key: &str, version_id: &str) -> Result<Metadata, Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("get_object_version", user_id, bucket);
         // "null" matches '' (versioning never enabled) or 'null' (suspended null-version).
         let effective_vid: &str = if version_id == "null" {
             let exists_empty: bool = conn.query_row(
@@ -1026,7 +2707,7 @@ impl SQLiteMetadataStore {
         let row = conn.query_row(
             "SELECT offset_size_list,etag,size,content_type,last_modified,user_metadata,
                     cache_control,expires,content_encoding,version_id,is_delete_marker,
-                    checksum_algorithm,checksum_value,checksum_type
+                    checksum_algorithm,checksum_value,checksum_type,expire_at,inline_data,generation
              FROM objects WHERE user=?1 AND bucket=?2 AND key=?3 AND version_id=?4",
             params![user_id, bucket, key, effective_vid],
             |row| Ok((
@@ -1044,21 +2725,24 @@ impl SQLiteMetadataStore {
                 row.get::<_, String>(11)?,
                 row.get::<_, String>(12)?,
                 row.get::<_, String>(13)?,
+                row.get::<_, Option<String>>(14)?,
+                row.get::<_, Option<Vec<u8>>>(15)?,
+                row.get::<_, i64>(16)?,
             )),
         ).map_err(|e| {
             if e == rusqlite::Error::QueryReturnedNoRows {
                 actix_web::error::ErrorNotFound("Version not found")
             } else {
-                actix_web::error::ErrorInternalServerError(e)
+                db_error(e)
             }
         })?;
 
         let (offset_size_bytes, etag, size, content_type, last_modified, user_metadata_json,
              cache_control, expires, content_encoding, vid, is_delete_marker,
-             checksum_algorithm, checksum_value, checksum_type) = row;
+             checksum_algorithm, checksum_value, checksum_type, expire_at, inline_data, generation) = row;
 
-        let offset_size_list = if let Some(bytes) = offset_size_bytes {
-            crate::util::serializer::deserialize_offset_size(&bytes)?
+        let chunks = if let Some(bytes) = offset_size_bytes {
+            decode_chunks(&bytes)?
         } else {
             vec![]
         };
@@ -1067,7 +2751,7 @@ impl SQLiteMetadataStore {
             .and_then(|s| serde_json::from_str(s).ok())
             .unwrap_or_default();
 
-        let mut metadata = Metadata::from_offset_size_list(offset_size_list);
+        let mut metadata = Metadata::from_chunks(chunks);
         metadata.etag = etag;
         metadata.size = size as u64;
         metadata.content_type = content_type;
@@ -1081,22 +2765,56 @@ impl SQLiteMetadataStore {
         metadata.checksum_algorithm = if checksum_algorithm.is_empty() { None } else { Some(checksum_algorithm) };
         metadata.checksum_value = if checksum_value.is_empty() { None } else { Some(checksum_value) };
         metadata.checksum_type = if checksum_type.is_empty() { None } else { Some(checksum_type) };
+        metadata.expire_at = expire_at;
+        metadata.inline_data = inline_data;
+        metadata.generation = generation as u64;
         Ok(metadata)
     }
 
+    /// Version id of the row of `key` that was latest at `as_of` (a stored timestamp), delete
+    /// markers included; None if no row was created by then. Rows from before `created_at`
+    /// was recorded never qualify.
+    pub fn version_as_of(&self, user_id: &str, bucket: &str, key: &str, as_of: &str) -> Result<Option<String>, Error> {
+        let conn = timed_conn("version_as_of", user_id, bucket);
+        conn.query_row(
+            "SELECT version_id FROM objects
+             WHERE user=?1 AND bucket=?2 AND key=?3 AND created_at<=?4
+             ORDER BY created_at DESC, id DESC LIMIT 1",
+            params![user_id, bucket, key, as_of],
+            |row| row.get::<_, String>(0),
+        ).optional().map_err(db_error)
+    }
+
+    /// (key, version id) of up to `limit` keys after `after` that were live at `as_of`: their
+    /// newest row created by then is not a delete marker. In key order.
+    pub fn versions_as_of(&self, user_id: &str, bucket: &str, as_of: &str, after: &str, limit: usize) -> Result<Vec<(String, String)>, Error> {
+        let conn = timed_conn("versions_as_of", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT key, version_id FROM objects o
+             WHERE user=?1 AND bucket=?2 AND key>?4 AND created_at<=?3 AND is_delete_marker=0
+               AND id = (SELECT id FROM objects i
+                         WHERE i.user=o.user AND i.bucket=o.bucket AND i.key=o.key AND i.created_at<=?3
+                         ORDER BY i.created_at DESC, i.id DESC LIMIT 1)
+             ORDER BY key LIMIT ?5",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, as_of, after, limit as i64], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(db_error)?;
+        rows.collect::<rusqlite::Result<Vec<_>>>().map_err(db_error)
+    }
+
     /// List all versions for a bucket (for GET ?versions).
     /// Returns (rows, is_truncated, next_key_marker, next_version_id_marker).
     pub fn list_object_versions_full(
         &self, user_id: &str, bucket: &str,
         prefix: &str, key_marker: &str, version_id_marker: &str, max_keys: usize,
     ) -> Result<(Vec<VersionRow>, bool, String, String), Error> {
-        let conn = DB_CONN.lock().unwrap();
+        let conn = timed_conn("list_object_versions_full", user_id, bucket);
         let mut stmt = conn.prepare(
             "SELECT key, version_id, is_delete_marker, etag, size, last_modified, is_latest
              FROM objects
              WHERE user=?1 AND bucket=?2
              ORDER BY key ASC, id DESC",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+        ).map_err(db_error)?;
 
         let rows = stmt.query_map(params![user_id, bucket], |row| {
             Ok(VersionRow {
@@ -1108,7 +2826,7 @@ impl SQLiteMetadataStore {
                 last_modified:     row.get::<_, Option<String>>(5)?.unwrap_or_default(),
                 is_latest:         row.get::<_, i64>(6)? != 0,
             })
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
+        }).map_err(db_error)?;
 
         let mut all: Vec<VersionRow> = rows
             .filter_map(|r| r.ok())
@@ -1140,14 +2858,20 @@ impl SQLiteMetadataStore {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct DeletionEvent {
-    pub id: i64,
-    pub user_id: String,
-    pub bucket: String,
-    pub key: String,
-    pub offset_size_list: Vec<(u64, u64)>,
-    pub created_at: String,
+fn deletion_event(row: &rusqlite::Row) -> rusqlite::Result<DeletionEvent> {
+    let offset_size_bytes: Vec<u8> = row.get(4)?;
+    let offset_size_list = crate::util::serializer::decode_extents(&offset_size_bytes)
+        .map_err(|_| rusqlite::Error::InvalidColumnType(4, "BLOB".to_string(), rusqlite::types::Type::Blob))?;
+    Ok(DeletionEvent {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        bucket: row.get(2)?,
+        key: row.get(3)?,
+        offset_size_list,
+        created_at: row.get(5)?,
+        attempts: row.get(6)?,
+        last_error: row.get(7)?,
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -1183,330 +2907,2248 @@ pub struct ObjectLockRow {
     pub legal_hold: String,
 }
 
+/// A `multipart_uploads` row selected in the column order of [`MultipartUploadRow`]
+fn multipart_upload_row(row: &rusqlite::Row) -> rusqlite::Result<MultipartUploadRow> {
+    Ok(MultipartUploadRow {
+        upload_id: row.get(0)?,
+        user_id: row.get(1)?,
+        bucket: row.get(2)?,
+        key: row.get(3)?,
+        content_type: row.get(4)?,
+        metadata_json: row.get::<_, Option<String>>(5)?.unwrap_or_else(|| "{}".to_string()),
+        initiated_at: row.get(6)?,
+        status: row.get::<_, String>(7)?,
+        final_etag: row.get(8)?,
+        checksum_algorithm: row.get::<_, Option<String>>(9)?.unwrap_or_default(),
+        checksum_type: row.get::<_, Option<String>>(10)?.unwrap_or_default(),
+        object_lock_mode: row.get::<_, Option<String>>(11)?.unwrap_or_default(),
+        object_lock_retain_until: row.get::<_, Option<String>>(12)?.unwrap_or_default(),
+        object_lock_legal_hold: row.get::<_, Option<String>>(13)?.unwrap_or_default(),
+    })
+}
+
 /// Multipart upload management
 impl SQLiteMetadataStore {
+    /// Record a new upload unless `user_id` already has `max_in_progress` uploads in progress
+    /// (no limit when None). Returns whether it was recorded.
+    #[allow(clippy::too_many_arguments)]
     pub fn create_multipart_upload(
         &self, upload_id: &str, user_id: &str, bucket: &str, key: &str,
         content_type: Option<&str>, metadata_json: &str, initiated_at: &str,
         checksum_algorithm: &str, checksum_type: &str,
         object_lock_mode: &str, object_lock_retain_until: &str, object_lock_legal_hold: &str,
-    ) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
+        max_in_progress: Option<u64>,
+    ) -> Result<bool, Error> {
+        let conn = timed_conn("create_multipart_upload", user_id, bucket);
+        // Counted and inserted in one statement, so concurrent initiations cannot pass the limit
+        let created = conn.execute(
             "INSERT OR IGNORE INTO multipart_uploads
              (upload_id, user_id, bucket, key, content_type, metadata_json, initiated_at,
               checksum_algorithm, checksum_type,
               object_lock_mode, object_lock_retain_until, object_lock_legal_hold)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+             SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12
+             WHERE ?13 IS NULL
+                OR (SELECT COUNT(*) FROM multipart_uploads WHERE user_id = ?2 AND status = 'in_progress') < ?13",
             params![upload_id, user_id, bucket, key, content_type, metadata_json, initiated_at,
                     checksum_algorithm, checksum_type,
-                    object_lock_mode, object_lock_retain_until, object_lock_legal_hold],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+                    object_lock_mode, object_lock_retain_until, object_lock_legal_hold,
+                    max_in_progress.map(|n| n as i64)],
+        ).map_err(db_error)?;
+        Ok(created > 0)
+    }
+
+    /// In-progress uploads of `user_id` by bucket
+    pub fn multipart_uploads_in_progress(&self, user_id: &str) -> Result<Vec<(String, u64)>, Error> {
+        let conn = timed_conn("multipart_uploads_in_progress", user_id, "");
+        let mut stmt = conn.prepare(
+            "SELECT bucket, COUNT(*) FROM multipart_uploads
+             WHERE user_id = ?1 AND status = 'in_progress' GROUP BY bucket ORDER BY bucket",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        Ok(rows)
+    }
+
+    /// In-progress uploads of every user
+    pub fn multipart_uploads_in_progress_total(&self) -> Result<u64, Error> {
+        let conn = timed_conn("multipart_uploads_in_progress_total", "", "");
+        conn.query_row(
+            "SELECT COUNT(*) FROM multipart_uploads WHERE status = 'in_progress'",
+            [],
+            |row| row.get::<_, i64>(0),
+        ).map(|n| n as u64).map_err(db_error)
+    }
+
+    /// Bytes of the parts of `upload_id` other than `except_part`, which an upload is replacing
+    pub fn multipart_part_bytes(&self, upload_id: &str, except_part: i32) -> Result<u64, Error> {
+        let conn = timed_conn("multipart_part_bytes", "", "");
+        conn.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM multipart_parts WHERE upload_id = ?1 AND part_number != ?2",
+            params![upload_id, except_part],
+            |row| row.get::<_, i64>(0),
+        ).map(|n| n as u64).map_err(db_error)
+    }
+
+    /// Bytes of the parts of `user_id`'s in-progress uploads to `bucket`, leaving out
+    /// `except_upload`, which is being completed
+    pub fn multipart_bucket_bytes(&self, user_id: &str, bucket: &str, except_upload: Option<&str>) -> Result<u64, Error> {
+        let conn = timed_conn("multipart_bucket_bytes", user_id, bucket);
+        conn.query_row(
+            "SELECT COALESCE(SUM(p.size), 0) FROM multipart_parts p
+             JOIN multipart_uploads u ON u.upload_id = p.upload_id
+             WHERE u.user_id = ?1 AND u.bucket = ?2 AND u.status = 'in_progress'
+               AND (?3 IS NULL OR u.upload_id != ?3)",
+            params![user_id, bucket, except_upload],
+            |row| row.get::<_, i64>(0),
+        ).map(|n| n as u64).map_err(db_error)
+    }
+
+    /// Bytes of the parts of `user_id`'s in-progress uploads by bucket
+    pub fn multipart_bytes_by_bucket(&self, user_id: &str) -> Result<Vec<(String, u64)>, Error> {
+        let conn = timed_conn("multipart_bytes_by_bucket", user_id, "");
+        let mut stmt = conn.prepare(
+            "SELECT u.bucket, SUM(p.size) FROM multipart_parts p
+             JOIN multipart_uploads u ON u.upload_id = p.upload_id
+             WHERE u.user_id = ?1 AND u.status = 'in_progress' GROUP BY u.bucket ORDER BY u.bucket",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        Ok(rows)
+    }
+
+    pub fn get_multipart_upload(&self, upload_id: &str) -> Result<Option<MultipartUploadRow>, Error> {
+        let conn = timed_conn("get_multipart_upload", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT upload_id, user_id, bucket, key, content_type, metadata_json,
+                    initiated_at, status, final_etag, checksum_algorithm, checksum_type,
+                    object_lock_mode, object_lock_retain_until, object_lock_legal_hold
+             FROM multipart_uploads WHERE upload_id = ?1",
+        ).map_err(db_error)?;
+        let result = stmt.query_row(params![upload_id], multipart_upload_row);
+        match result {
+            Ok(row) => Ok(Some(row)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(db_error(e)),
+        }
+    }
+
+    pub fn mark_multipart_completed(&self, upload_id: &str, final_etag: &str) -> Result<(), Error> {
+        let conn = timed_conn("mark_multipart_completed", "", "");
+        conn.execute(
+            "UPDATE multipart_uploads SET status = 'completed', final_etag = ?1 WHERE upload_id = ?2",
+            params![final_etag, upload_id],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn delete_multipart_upload(&self, upload_id: &str) -> Result<(), Error> {
+        let conn = timed_conn("delete_multipart_upload", "", "");
+        conn.execute(
+            "DELETE FROM multipart_uploads WHERE upload_id = ?1",
+            params![upload_id],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn delete_completed_uploads_for_key(&self, bucket: &str, key: &str) -> Result<(), Error> {
+        let conn = timed_conn("delete_completed_uploads_for_key", "", bucket);
+        conn.execute(
+            "DELETE FROM multipart_uploads WHERE bucket = ?1 AND key = ?2 AND status = 'completed'",
+            params![bucket, key],
+        ).map_err(db_error)?;
         Ok(())
     }
 
-    pub fn get_multipart_upload(&self, upload_id: &str) -> Result<Option<MultipartUploadRow>, Error> {
-        let conn = DB_CONN.lock().unwrap();
+    pub fn list_bucket_multipart_uploads(&self, bucket: &str) -> Result<Vec<MultipartUploadRow>, Error> {
+        let conn = timed_conn("list_bucket_multipart_uploads", "", bucket);
+        let mut stmt = conn.prepare(
+            "SELECT upload_id, user_id, bucket, key, content_type, metadata_json,
+                    initiated_at, status, final_etag, checksum_algorithm, checksum_type,
+                    object_lock_mode, object_lock_retain_until, object_lock_legal_hold
+             FROM multipart_uploads
+             WHERE bucket = ?1 AND status = 'in_progress'
+             ORDER BY key, initiated_at",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![bucket], multipart_upload_row).map_err(db_error)?;
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row.map_err(db_error)?);
+        }
+        Ok(result)
+    }
+
+    /// In-progress uploads with ids starting `prefix` initiated before `before`, oldest first
+    pub fn stale_multipart_uploads(&self, prefix: &str, before: &str, limit: usize) -> Result<Vec<MultipartUploadRow>, Error> {
+        let conn = timed_conn("stale_multipart_uploads", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT upload_id, user_id, bucket, key, content_type, metadata_json,
+                    initiated_at, status, final_etag, checksum_algorithm, checksum_type,
+                    object_lock_mode, object_lock_retain_until, object_lock_legal_hold
+             FROM multipart_uploads
+             WHERE status = 'in_progress' AND substr(upload_id, 1, length(?1)) = ?1 AND initiated_at < ?2
+             ORDER BY initiated_at LIMIT ?3",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![prefix, before, limit as i64], multipart_upload_row).map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+
+    /// Store part `part_number` of `upload_id`, replacing any part sent before under that
+    /// number. `admit` runs in the same transaction, with the bytes of the other parts of
+    /// the bucket's in-progress uploads (leaving out `except_upload`) and those of the part
+    /// being replaced; an error from it stores nothing.
+    #[allow(clippy::too_many_arguments)]
+    pub fn upsert_multipart_part(
+        &self, upload_id: &str, part_number: i32, etag: &str, size: u64, extents_blob: &[u8],
+        checksum_value: &str, except_upload: Option<&str>, admit: impl FnOnce(u64, u64) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut conn = timed_conn("upsert_multipart_part", "", "");
+        let tx = conn.transaction().map_err(db_error)?;
+        let other_parts = tx.query_row(
+            "SELECT COALESCE(SUM(p.size), 0) FROM multipart_parts p
+             JOIN multipart_uploads u ON u.upload_id = p.upload_id
+             JOIN multipart_uploads t ON t.upload_id = ?1
+             WHERE u.user_id = t.user_id AND u.bucket = t.bucket AND u.status = 'in_progress'
+               AND NOT (p.upload_id = ?1 AND p.part_number = ?2)
+               AND (?3 IS NULL OR u.upload_id != ?3)",
+            params![upload_id, part_number, except_upload],
+            |row| row.get::<_, i64>(0),
+        ).map_err(db_error)? as u64;
+        let replaced = tx.query_row(
+            "SELECT size FROM multipart_parts WHERE upload_id = ?1 AND part_number = ?2",
+            params![upload_id, part_number],
+            |row| row.get::<_, i64>(0),
+        ).optional().map_err(db_error)?.unwrap_or(0) as u64;
+        admit(other_parts, replaced)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO multipart_parts (upload_id, part_number, etag, size, extents_blob, checksum_value)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![upload_id, part_number, etag, size as i64, extents_blob, checksum_value],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)
+    }
+
+    pub fn list_multipart_parts(&self, upload_id: &str) -> Result<Vec<MultipartPartRow>, Error> {
+        let conn = timed_conn("list_multipart_parts", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT part_number, etag, size, extents_blob, checksum_value
+             FROM multipart_parts WHERE upload_id = ?1 ORDER BY part_number",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![upload_id], |row| {
+            Ok(MultipartPartRow {
+                part_number: row.get(0)?,
+                etag: row.get(1)?,
+                size: row.get::<_, i64>(2)? as u64,
+                extents_blob: row.get(3)?,
+                checksum_value: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+            })
+        }).map_err(db_error)?;
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row.map_err(db_error)?);
+        }
+        Ok(result)
+    }
+
+    pub fn delete_parts_for_upload(&self, upload_id: &str) -> Result<(), Error> {
+        let conn = timed_conn("delete_parts_for_upload", "", "");
+        conn.execute(
+            "DELETE FROM multipart_parts WHERE upload_id = ?1",
+            params![upload_id],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn get_parts_manifest(&self, user_id: &str, bucket: &str, key: &str) -> Result<Option<String>, Error> {
+        let conn = timed_conn("get_parts_manifest", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT parts_manifest FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3",
+        ).map_err(db_error)?;
+        let result = stmt.query_row(params![user_id, bucket, key], |row| {
+            row.get::<_, Option<String>>(0)
+        });
+        match result {
+            Ok(v) => Ok(v),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(db_error(e)),
+        }
+    }
+
+    pub fn set_parts_manifest(&self, user_id: &str, bucket: &str, key: &str, manifest: &str) -> Result<(), Error> {
+        let conn = timed_conn("set_parts_manifest", user_id, bucket);
+        conn.execute(
+            "UPDATE objects SET parts_manifest = ?1 WHERE user = ?2 AND bucket = ?3 AND key = ?4",
+            params![manifest, user_id, bucket, key],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    // --- Object Lock ---
+
+    pub fn get_bucket_object_lock_enabled(&self, bucket: &str) -> Result<bool, Error> {
+        let conn = timed_conn("get_bucket_object_lock_enabled", "", bucket);
+        let result: rusqlite::Result<i64> = conn.query_row(
+            "SELECT object_lock_enabled FROM buckets WHERE name = ?1",
+            params![bucket],
+            |row| row.get(0),
+        );
+        match result {
+            Ok(v) => Ok(v != 0),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+            Err(e) => Err(db_error(e)),
+        }
+    }
+
+    pub fn set_bucket_object_lock_enabled(&self, bucket: &str, enabled: bool) -> Result<(), Error> {
+        let conn = timed_conn("set_bucket_object_lock_enabled", "", bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "UPDATE buckets SET object_lock_enabled = ?1 WHERE name = ?2",
+            params![if enabled { 1i64 } else { 0i64 }, bucket],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn create_bucket_with_lock(&self, user_id: &str, bucket: &str, lock_enabled: bool) -> Result<(), Error> {
+        let conn = timed_conn("create_bucket_with_lock", user_id, bucket);
+        let lock_val = if lock_enabled { 1i64 } else { 0i64 };
+        let versioning = if lock_enabled { "enabled" } else { "disabled" };
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "INSERT OR IGNORE INTO buckets (user, name, object_lock_enabled, versioning_state) VALUES (?1, ?2, ?3, ?4)",
+            params![user_id, bucket, lock_val, versioning],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Returns None if no config set; Some((mode, days, years)) otherwise.
+    pub fn get_object_lock_config(&self, bucket: &str) -> Result<Option<ObjectLockConfig>, Error> {
+        let conn = timed_conn("get_object_lock_config", "", bucket);
+        let result = conn.query_row(
+            "SELECT mode, days, years FROM object_lock_config WHERE bucket = ?1",
+            params![bucket],
+            |row| Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+            )),
+        );
+        match result {
+            Ok(r) => Ok(Some(r)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(db_error(e)),
+        }
+    }
+
+    pub fn put_object_lock_config(&self, bucket: &str, mode: &str, days: Option<i64>, years: Option<i64>) -> Result<(), Error> {
+        let conn = timed_conn("put_object_lock_config", "", bucket);
+        conn.execute(
+            "INSERT OR REPLACE INTO object_lock_config (bucket, mode, days, years) VALUES (?1, ?2, ?3, ?4)",
+            params![bucket, mode, days, years],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn get_object_lock(&self, bucket: &str, key: &str, version_id: &str) -> Result<Option<ObjectLockRow>, Error> {
+        let conn = timed_conn("get_object_lock", "", bucket);
+        let result = conn.query_row(
+            "SELECT mode, retain_until_date, legal_hold FROM object_lock WHERE bucket = ?1 AND key = ?2 AND version_id = ?3",
+            params![bucket, key, version_id],
+            |row| Ok(ObjectLockRow {
+                mode: row.get(0)?,
+                retain_until_date: row.get(1)?,
+                legal_hold: row.get::<_, String>(2).unwrap_or_else(|_| "OFF".to_string()),
+            }),
+        );
+        match result {
+            Ok(r) => Ok(Some(r)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(db_error(e)),
+        }
+    }
+
+    pub fn put_object_lock(
+        &self, bucket: &str, key: &str, version_id: &str,
+        mode: Option<&str>, retain_until_date: Option<&str>, legal_hold: Option<&str>,
+    ) -> Result<(), Error> {
+        let conn = timed_conn("put_object_lock", "", bucket);
+        // INSERT uses COALESCE(?6, 'OFF') so a fresh row defaults legal_hold to 'OFF'.
+        // ON CONFLICT uses COALESCE(?6, legal_hold) so None (NULL) preserves the existing value.
+        conn.execute(
+            "INSERT INTO object_lock (bucket, key, version_id, mode, retain_until_date, legal_hold)
+             VALUES (?1, ?2, ?3, ?4, ?5, COALESCE(?6, 'OFF'))
+             ON CONFLICT(bucket, key, version_id) DO UPDATE SET
+               mode = COALESCE(?4, mode),
+               retain_until_date = COALESCE(?5, retain_until_date),
+               legal_hold = COALESCE(?6, legal_hold)",
+            params![bucket, key, version_id, mode, retain_until_date, legal_hold],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Forget the retention and legal hold of one version (the object it protected is gone).
+    pub fn delete_object_lock(&self, bucket: &str, key: &str, version_id: &str) -> Result<(), Error> {
+        let conn = timed_conn("delete_object_lock", "", bucket);
+        conn.execute(
+            "DELETE FROM object_lock WHERE bucket = ?1 AND key = ?2 AND version_id = ?3",
+            params![bucket, key, version_id],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    pub fn set_object_legal_hold(&self, bucket: &str, key: &str, version_id: &str, status: &str) -> Result<(), Error> {
+        let conn = timed_conn("set_object_legal_hold", "", bucket);
+        conn.execute(
+            "INSERT INTO object_lock (bucket, key, version_id, legal_hold)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(bucket, key, version_id) DO UPDATE SET legal_hold = ?4",
+            params![bucket, key, version_id, status],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Returns (blocked_by_retention, blocked_by_legal_hold).
+    /// bypass_governance: true allows overriding GOVERNANCE retention.
+    pub fn check_object_lock_protection(
+        &self, bucket: &str, key: &str, version_id: &str, bypass_governance: bool,
+    ) -> Result<(bool, bool), Error> {
+        let row = match self.get_object_lock(bucket, key, version_id)? {
+            Some(r) => r,
+            None => return Ok((false, false)),
+        };
+        let legal_hold_blocked = row.legal_hold == "ON";
+        let retention_blocked = match (&row.mode, &row.retain_until_date) {
+            (Some(mode), Some(until)) => {
+                let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+                let still_locked = until.as_str() > now.as_str();
+                if still_locked {
+                    match mode.as_str() {
+                        "COMPLIANCE" => true,
+                        "GOVERNANCE" => !bypass_governance,
+                        _ => false,
+                    }
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        };
+        Ok((retention_blocked, legal_hold_blocked))
+    }
+}
+
+impl SQLiteMetadataStore {
+    /// Point chunk `index` of the latest version of `key` at `new`, but only if it still
+    /// references `old`. Returns false when the object changed underneath the caller.
+    pub fn replace_chunk(
+        &self, user_id: &str, bucket: &str, key: &str, index: usize, old: (u64, u64), new: (u64, u64),
+    ) -> Result<bool, Error> {
+        let conn = timed_conn("replace_chunk", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let row: Option<(i64, Vec<u8>)> = tx.query_row(
+            "SELECT id, offset_size_list FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0",
+            params![user_id, bucket, key],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional().map_err(db_error)?;
+        let (id, bytes) = match row {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+        let mut chunks = decode_chunks(&bytes)?;
+        if chunks.get(index).map(DataChunk::extent) != Some(old) {
+            return Ok(false);
+        }
+        chunks[index] = chunks[index].relocated(new);
+        tx.execute(
+            "UPDATE objects SET offset_size_list = ?1 WHERE id = ?2",
+            params![encode_chunks(&chunks)?, id],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(true)
+    }
+
+    /// Copy the live database to `dest` with the online backup API. Runs on its own
+    /// connection, so in WAL mode writers keep going while pages are copied.
+    pub fn backup_to(&self, dest: &Path) -> Result<(), Error> {
+        db_conn();
+        let conn = open_connection(&get_db_path(), &SQLITE_CONFIG).map_err(db_error)?;
+        let journal_mode: String = conn
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .map_err(db_error)?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            return Err(actix_web::error::ErrorInternalServerError(format!(
+                "Metadata backup requires WAL mode (journal_mode={})", journal_mode
+            )));
+        }
+        let tmp = dest.with_extension("tmp");
+        conn.backup(DatabaseName::Main, &tmp, None).map_err(db_error)?;
+        std::fs::rename(&tmp, dest).map_err(actix_web::error::ErrorInternalServerError)?;
+        Ok(())
+    }
+}
+
+/// What [`SQLiteMetadataStore::put_metadata_batch`] did with one row
+#[derive(Debug)]
+pub enum BatchRowOutcome {
+    Created,
+    /// Replaced an existing object; its chunks are now unreferenced
+    Replaced(Vec<DataChunk>),
+    /// A live object holds the key and the batch does not overwrite
+    Exists,
+}
+
+impl SQLiteMetadataStore {
+    /// Insert many objects of one bucket in a single transaction, in order. Without
+    /// `overwrite` a key that is already live (including one earlier in the batch) is left
+    /// alone and reported as `Exists`; any database error rolls back the whole batch.
+    pub fn put_metadata_batch(
+        &self, user_id: &str, bucket: &str, rows: &[(String, Metadata)], overwrite: bool,
+    ) -> Result<Vec<BatchRowOutcome>, Error> {
+        let conn = timed_conn("put_metadata_batch", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let mut outcomes = Vec::with_capacity(rows.len());
+        for (key, metadata) in rows {
+            let outcome = if overwrite {
+                match overwrite_unversioned(&tx, user_id, bucket, key, metadata)? {
+                    Some(previous) => BatchRowOutcome::Replaced(previous),
+                    None => BatchRowOutcome::Created,
+                }
+            } else {
+                let offset_size_bytes = encode_chunks(&metadata.chunks)?;
+                match insert_new(&tx, user_id, bucket, key, &offset_size_bytes, metadata).map_err(db_error)? {
+                    true => BatchRowOutcome::Created,
+                    false => BatchRowOutcome::Exists,
+                }
+            };
+            outcomes.push(outcome);
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(outcomes)
+    }
+}
+
+/// One metadata change of a transaction (`POST /txn`)
+#[derive(Debug)]
+pub enum TxnMutation {
+    /// Create `key`; with `overwrite` an existing object is replaced and its chunks queued
+    Put { key: String, metadata: Box<Metadata>, overwrite: bool },
+    /// Remove every row of `key` and queue its chunks
+    Delete { key: String },
+    /// Move `key` to the free key `to`
+    Rename { key: String, to: String },
+}
+
+/// Why a transaction stopped at one of its mutations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxnConflict {
+    /// A live object holds the key being created or renamed to
+    Exists,
+    /// The key being deleted or renamed is not live
+    NotFound,
+}
+
+/// The latest live chunks of `key`, or `None` when it is not live
+fn live_extents_of(conn: &Connection, user_id: &str, bucket: &str, key: &str) -> Result<Option<Vec<(u64, u64)>>, Error> {
+    let row: Option<Option<Vec<u8>>> = conn.query_row(
+        "SELECT offset_size_list FROM objects
+         WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0",
+        params![user_id, bucket, key],
+        |row| row.get(0),
+    ).optional().map_err(db_error)?;
+    match row {
+        Some(Some(bytes)) => Ok(Some(crate::util::serializer::decode_extents(&bytes)?)),
+        Some(None) => Ok(Some(Vec::new())),
+        None => Ok(None),
+    }
+}
+
+impl SQLiteMetadataStore {
+    /// Apply `mutations` of one bucket in order in a single transaction. Either all of them
+    /// are committed, with the chunks of deleted and replaced objects queued for deletion, or
+    /// none is: the first one meeting a conflict rolls everything back and is returned with
+    /// its index.
+    pub fn apply_txn(&self, user_id: &str, bucket: &str, mutations: &[TxnMutation]) -> Result<Result<(), (usize, TxnConflict)>, Error> {
+        let conn = timed_conn("apply_txn", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        for (i, mutation) in mutations.iter().enumerate() {
+            match mutation {
+                TxnMutation::Put { key, metadata, overwrite: true } => {
+                    if let Some(previous) = overwrite_unversioned(&tx, user_id, bucket, key, metadata)? {
+                        let previous: Vec<_> = previous.iter().map(DataChunk::extent).collect();
+                        queue_unreferenced(&tx, user_id, bucket, key, &previous)?;
+                    }
+                }
+                TxnMutation::Put { key, metadata, overwrite: false } => {
+                    let offset_size_bytes = encode_chunks(&metadata.chunks)?;
+                    if !insert_new(&tx, user_id, bucket, key, &offset_size_bytes, metadata).map_err(db_error)? {
+                        return Ok(Err((i, TxnConflict::Exists)));
+                    }
+                }
+                TxnMutation::Delete { key } => {
+                    let Some(extents) = live_extents_of(&tx, user_id, bucket, key)? else {
+                        return Ok(Err((i, TxnConflict::NotFound)));
+                    };
+                    queue_unreferenced(&tx, user_id, bucket, key, &extents)?;
+                    tx.execute("DELETE FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3", params![user_id, bucket, key])
+                        .map_err(db_error)?;
+                    tx.execute("DELETE FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3", params![user_id, bucket, key])
+                        .map_err(db_error)?;
+                }
+                TxnMutation::Rename { key, to } => {
+                    if live_extents_of(&tx, user_id, bucket, key)?.is_none() {
+                        return Ok(Err((i, TxnConflict::NotFound)));
+                    }
+                    if live_extents_of(&tx, user_id, bucket, to)?.is_some() {
+                        return Ok(Err((i, TxnConflict::Exists)));
+                    }
+                    tx.execute("UPDATE objects SET key = ?1 WHERE user = ?2 AND bucket = ?3 AND key = ?4", params![to, user_id, bucket, key])
+                        .map_err(db_error)?;
+                    tx.execute("UPDATE object_tags SET key = ?1 WHERE user_id = ?2 AND bucket = ?3 AND key = ?4", params![to, user_id, bucket, key])
+                        .map_err(db_error)?;
+                }
+            }
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(Ok(()))
+    }
+}
+
+/// A user's pre-bucket `{user}.bin`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LegacyFileRecord {
+    pub marked_at: String,
+    /// Object rows pointed at the file when it was found
+    pub rows: u64,
+    pub retired_at: Option<String>,
+}
+
+/// Chunks of `blob` moved into the legacy namespace, or None when none was outside it
+fn to_legacy_segment(blob: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let chunks = decode_chunks(blob)?;
+    if chunks.iter().all(|chunk| chunk.segment == segment::LEGACY_SEGMENT) {
+        return Ok(None);
+    }
+    let moved: Vec<DataChunk> = chunks.into_iter().map(|chunk| DataChunk { segment: segment::LEGACY_SEGMENT, ..chunk }).collect();
+    encode_chunks(&moved).map(Some)
+}
+
+/// Pre-bucket storage
+impl SQLiteMetadataStore {
+    pub fn legacy_file(&self, user_id: &str) -> Result<Option<LegacyFileRecord>, Error> {
+        let conn = timed_conn("legacy_file", user_id, "");
+        conn.query_row(
+            "SELECT marked_at, rows, retired_at FROM legacy_files WHERE user_id = ?1",
+            params![user_id],
+            |row| Ok(LegacyFileRecord { marked_at: row.get(0)?, rows: row.get::<_, i64>(1)? as u64, retired_at: row.get(2)? }),
+        ).optional().map_err(db_error)
+    }
+
+    /// Buckets in which `user_id` has object rows, registered or not
+    pub fn object_buckets(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        let conn = timed_conn("object_buckets", user_id, "");
+        let mut stmt = conn.prepare("SELECT DISTINCT bucket FROM objects WHERE user = ?1 ORDER BY bucket").map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id], |row| row.get(0)).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    /// Point every chunk of `user_id`'s rows in `buckets`, and of their pending deletions, at
+    /// the user's legacy file and record the file, in one transaction. Returns the object
+    /// rows changed.
+    pub fn mark_legacy_rows(&self, user_id: &str, buckets: &[String]) -> Result<u64, Error> {
+        let conn = timed_conn("mark_legacy_rows", user_id, "");
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let mut marked = 0u64;
+        for bucket in buckets {
+            for (table, select, update) in [
+                ("objects",
+                 "SELECT id, offset_size_list FROM objects WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL",
+                 "UPDATE objects SET offset_size_list = ?1 WHERE id = ?2"),
+                ("deletion_queue",
+                 "SELECT id, offset_size_list FROM deletion_queue WHERE user_id = ?1 AND bucket = ?2 AND NOT processed",
+                 "UPDATE deletion_queue SET offset_size_list = ?1 WHERE id = ?2"),
+            ] {
+                let rows: Vec<(i64, Vec<u8>)> = {
+                    let mut stmt = tx.prepare(select).map_err(db_error)?;
+                    let rows = stmt.query_map(params![user_id, bucket], |row| Ok((row.get(0)?, row.get(1)?))).map_err(db_error)?;
+                    rows.collect::<rusqlite::Result<_>>().map_err(db_error)?
+                };
+                for (id, blob) in rows {
+                    let Some(moved) = to_legacy_segment(&blob)? else { continue };
+                    tx.execute(update, params![moved, id]).map_err(db_error)?;
+                    if table == "objects" {
+                        marked += 1;
+                    }
+                }
+            }
+        }
+        tx.execute(
+            "INSERT INTO legacy_files (user_id, marked_at, rows) VALUES (?1, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), ?2)",
+            params![user_id, marked as i64],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(marked)
+    }
+
+    /// Rows of `user_id` in `bucket` with at least one chunk in the legacy file
+    pub fn legacy_rows(&self, user_id: &str, bucket: &str) -> Result<Vec<ObjectExtentsRow>, Error> {
+        let conn = timed_conn("legacy_rows", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT id, offset_size_list FROM objects WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))
+            .map_err(db_error)?;
+        let rows: Vec<(i64, Vec<u8>)> = rows.collect::<rusqlite::Result<_>>().map_err(db_error)?;
+        drop(stmt);
+        drop(conn);
+
+        let mut result = Vec::new();
+        for (id, blob) in rows {
+            let extents = crate::util::serializer::decode_extents(&blob)?;
+            if extents.iter().any(|(offset, _)| segment::segment_of(*offset) == segment::LEGACY_SEGMENT) {
+                result.push(ObjectExtentsRow { id, extents, stored: blob });
+            }
+        }
+        Ok(result)
+    }
+
+    /// Swap in the copied chunk lists of legacy rows and move the clone reference counts and
+    /// dedup index entries of `moves` (old extent, new extent) along, in one transaction. Each row is only rewritten
+    /// if it still holds the list the copy was made from. Returns the rows rewritten.
+    pub fn commit_legacy_rows(
+        &self, user_id: &str, bucket: &str, rows: &[(ObjectExtentsRow, Vec<(u64, u64)>)], moves: &[ExtentMove],
+    ) -> Result<usize, Error> {
+        let conn = timed_conn("commit_legacy_rows", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let mut rewritten = 0;
+        for (old, new) in rows {
+            rewritten += tx.execute(
+                "UPDATE objects SET offset_size_list = ?1 WHERE id = ?2 AND offset_size_list = ?3",
+                params![encode_chunks(&relocate(&decode_chunks(&old.stored)?, new))?, old.id, old.stored],
+            ).map_err(db_error)?;
+        }
+        for ((old_offset, old_size), (new_offset, new_size)) in moves {
+            for table in ["chunk_refs", "chunk_index"] {
+                tx.execute(
+                    &format!("UPDATE {} SET offset = ?1, size = ?2 WHERE user_id = ?3 AND bucket = ?4 AND offset = ?5 AND size = ?6", table),
+                    params![*new_offset as i64, *new_size as i64, user_id, bucket, *old_offset as i64, *old_size as i64],
+                ).map_err(db_error)?;
+            }
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(rewritten)
+    }
+
+    /// Record that `user_id`'s legacy file no longer holds referenced data
+    pub fn retire_legacy_file(&self, user_id: &str) -> Result<(), Error> {
+        let conn = timed_conn("retire_legacy_file", user_id, "");
+        conn.execute(
+            "UPDATE legacy_files SET retired_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE user_id = ?1",
+            params![user_id],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+}
+
+/// Chunk list of one object row (any version)
+pub struct ObjectExtentsRow {
+    pub id: i64,
+    pub extents: Vec<(u64, u64)>,
+    /// The chunk list as stored, which a migration must find unchanged
+    pub(crate) stored: Vec<u8>,
+}
+
+pub struct BucketTierRow {
+    pub tier: String,
+    pub target_tier: Option<String>,
+    pub boundary: u64,
+}
+
+/// Storage tier placement and migration progress
+impl SQLiteMetadataStore {
+    pub fn get_bucket_tier(&self, user_id: &str, bucket: &str) -> Result<Option<BucketTierRow>, Error> {
+        let conn = timed_conn("get_bucket_tier", user_id, bucket);
+        conn.query_row(
+            "SELECT tier, target_tier, boundary FROM bucket_tiers WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+            |row| Ok(BucketTierRow {
+                tier: row.get(0)?,
+                target_tier: row.get(1)?,
+                boundary: row.get::<_, i64>(2)? as u64,
+            }),
+        ).optional().map_err(db_error)
+    }
+
+    /// Record the start (or restart) of a migration towards `target_tier`.
+    pub fn begin_tier_migration(&self, user_id: &str, bucket: &str, tier: &str, target_tier: &str, boundary: u64) -> Result<(), Error> {
+        let conn = timed_conn("begin_tier_migration", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "INSERT INTO bucket_tiers (user_id, bucket, tier, target_tier, boundary)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(user_id, bucket) DO UPDATE SET
+                tier = excluded.tier, target_tier = excluded.target_tier, boundary = excluded.boundary",
+            params![user_id, bucket, tier, target_tier, boundary as i64],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Keys with stored chunks that the current migration has not moved yet.
+    pub fn pending_tier_migration_keys(&self, user_id: &str, bucket: &str) -> Result<Vec<String>, Error> {
+        let conn = timed_conn("pending_tier_migration_keys", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT key FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL
+               AND key NOT IN (SELECT key FROM tier_migration_keys WHERE user_id = ?1 AND bucket = ?2)
+             ORDER BY key",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket], |row| row.get::<_, String>(0))
+            .map_err(db_error)?;
+        let mut keys = Vec::new();
+        for row in rows {
+            keys.push(row.map_err(db_error)?);
+        }
+        Ok(keys)
+    }
+
+    /// Number of live keys in `bucket`.
+    pub fn live_key_count(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        let conn = timed_conn("live_key_count", user_id, bucket);
+        conn.query_row(
+            "SELECT COUNT(*) FROM objects WHERE user = ?1 AND bucket = ?2 AND is_latest = 1 AND is_delete_marker = 0",
+            params![user_id, bucket],
+            |row| row.get::<_, i64>(0),
+        ).map(|n| n as u64).map_err(db_error)
+    }
+
+    /// The `limit` live keys after `after` (from the first key when None) in key order, one
+    /// page of a scan that holds the connection for a single query at a time.
+    pub fn live_keys_after(&self, user_id: &str, bucket: &str, after: Option<&str>, limit: usize) -> Result<Vec<String>, Error> {
+        let conn = timed_conn("live_keys_after", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT key FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND (?3 IS NULL OR key > ?3) AND is_latest = 1 AND is_delete_marker = 0
+             ORDER BY key LIMIT ?4",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, after, limit as i64], |row| row.get::<_, String>(0))
+            .map_err(db_error)?;
+        rows.collect::<rusqlite::Result<Vec<_>>>().map_err(db_error)
+    }
+
+    /// Latest live rows of the `limit` keys after `key` in key order.
+    pub fn latest_records_after(&self, user_id: &str, bucket: &str, key: &str, limit: usize) -> Result<Vec<ObjectRecord>, Error> {
+        let conn = timed_conn("latest_records_after", user_id, bucket);
+        query_records(&conn, "user = ?1 AND bucket = ?2 AND key > ?3 AND is_latest = 1 AND is_delete_marker = 0",
+                      "ORDER BY key ASC LIMIT ?4", params![user_id, bucket, key, limit as i64])
+    }
+
+    /// Chunk lists of every version of `key`, by row id.
+    pub fn object_extents_for_key(&self, user_id: &str, bucket: &str, key: &str) -> Result<Vec<ObjectExtentsRow>, Error> {
+        let conn = timed_conn("object_extents_for_key", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT id, offset_size_list FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND offset_size_list IS NOT NULL",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, key], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))
+            .map_err(db_error)?;
+        let mut blobs = Vec::new();
+        for row in rows {
+            blobs.push(row.map_err(db_error)?);
+        }
+        drop(stmt);
+        drop(conn);
+
+        let mut result = Vec::new();
+        for (id, blob) in blobs {
+            result.push(ObjectExtentsRow { id, extents: crate::util::serializer::decode_extents(&blob)?, stored: blob });
+        }
+        Ok(result)
+    }
+
+    /// Swap in the migrated chunk lists of `key` and mark it done, in one transaction. Each
+    /// row is only rewritten if it still holds the list the copy was made from.
+    pub fn commit_key_migration(
+        &self, user_id: &str, bucket: &str, key: &str, rows: &[(ObjectExtentsRow, Vec<(u64, u64)>)],
+    ) -> Result<(), Error> {
+        let conn = timed_conn("commit_key_migration", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        for (old, new) in rows {
+            tx.execute(
+                "UPDATE objects SET offset_size_list = ?1 WHERE id = ?2 AND offset_size_list = ?3",
+                params![encode_chunks(&relocate(&decode_chunks(&old.stored)?, new))?, old.id, old.stored],
+            ).map_err(db_error)?;
+            // Ranges left in the source tier must not be shared by new writes
+            for (offset, size) in old.extents.iter().filter(|extent| !new.contains(extent)) {
+                tx.execute(
+                    "DELETE FROM chunk_index WHERE user_id = ?1 AND bucket = ?2 AND offset = ?3 AND size = ?4",
+                    params![user_id, bucket, *offset as i64, *size as i64],
+                ).map_err(db_error)?;
+            }
+        }
+        tx.execute(
+            "INSERT OR IGNORE INTO tier_migration_keys (user_id, bucket, key) VALUES (?1, ?2, ?3)",
+            params![user_id, bucket, key],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Place the bucket on `tier` and drop the migration progress.
+    pub fn finish_tier_migration(&self, user_id: &str, bucket: &str, tier: &str) -> Result<(), Error> {
+        let conn = timed_conn("finish_tier_migration", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "INSERT INTO bucket_tiers (user_id, bucket, tier, target_tier, boundary)
+             VALUES (?1, ?2, ?3, NULL, 0)
+             ON CONFLICT(user_id, bucket) DO UPDATE SET tier = excluded.tier, target_tier = NULL, boundary = 0",
+            params![user_id, bucket, tier],
+        ).map_err(db_error)?;
+        tx.execute(
+            "DELETE FROM tier_migration_keys WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+}
+
+/// Moving live chunks during compaction
+impl SQLiteMetadataStore {
+    /// Every row of `bucket` that holds chunks, with its chunk list as stored
+    pub fn object_extents_in_bucket(&self, user_id: &str, bucket: &str) -> Result<Vec<ObjectExtentsRow>, Error> {
+        let conn = timed_conn("object_extents_in_bucket", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT id, offset_size_list FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))
+            .map_err(db_error)?;
+        let rows: Vec<(i64, Vec<u8>)> = rows.collect::<rusqlite::Result<_>>().map_err(db_error)?;
+        drop(stmt);
+        drop(conn);
+
+        let mut result = Vec::new();
+        for (id, blob) in rows {
+            result.push(ObjectExtentsRow { id, extents: crate::util::serializer::decode_extents(&blob)?, stored: blob });
+        }
+        Ok(result)
+    }
+
+    /// Publish a compaction pass in one transaction: swap in the rows' new chunk lists, move
+    /// the reference counts and dedup entries of `moves` (old extent, new extent) over and
+    /// queue the old extents for deletion. The pass is all or nothing: if any row no longer
+    /// holds the list its copies were made from, nothing is rewritten and the copies are
+    /// queued instead. Returns the number of rows rewritten.
+    pub fn commit_relocation(
+        &self, user_id: &str, bucket: &str, rows: &[(ObjectExtentsRow, Vec<(u64, u64)>)], moves: &[ExtentMove],
+    ) -> Result<usize, Error> {
+        let conn = timed_conn("commit_relocation", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let mut rewritten = 0;
+        for (old, new) in rows {
+            rewritten += tx.execute(
+                "UPDATE objects SET offset_size_list = ?1 WHERE id = ?2 AND offset_size_list = ?3",
+                params![encode_chunks(&relocate(&decode_chunks(&old.stored)?, new))?, old.id, old.stored],
+            ).map_err(db_error)?;
+        }
+        if rewritten < rows.len() {
+            tx.rollback().map_err(db_error)?;
+            let copies: Vec<(u64, u64)> = moves.iter().map(|(_, new)| *new).collect();
+            queue_relocated(&conn, user_id, bucket, &copies)?;
+            return Ok(0);
+        }
+        for ((old_offset, size), (new_offset, _)) in moves {
+            for table in ["chunk_refs", "chunk_index"] {
+                tx.execute(
+                    &format!("UPDATE {} SET offset = ?1 WHERE user_id = ?2 AND bucket = ?3 AND offset = ?4 AND size = ?5", table),
+                    params![*new_offset as i64, user_id, bucket, *old_offset as i64, *size as i64],
+                ).map_err(db_error)?;
+            }
+        }
+        let originals: Vec<(u64, u64)> = moves.iter().map(|(old, _)| *old).collect();
+        queue_relocated(&tx, user_id, bucket, &originals)?;
+        tx.commit().map_err(db_error)?;
+        Ok(rewritten)
+    }
+}
+
+/// Queue the extents a compaction pass left unreferenced. Keys are never empty, so the entry
+/// is never taken for a key's pending deletion.
+fn queue_relocated(conn: &Connection, user_id: &str, bucket: &str, extents: &[(u64, u64)]) -> Result<(), Error> {
+    if extents.is_empty() {
+        return Ok(());
+    }
+    let bytes: u64 = extents.iter().map(|(_, size)| size).sum();
+    conn.execute(
+        "INSERT INTO deletion_queue (user_id, bucket, key, offset_size_list, bytes) VALUES (?1, ?2, '', ?3, ?4)",
+        params![user_id, bucket, encode_extents(extents)?, bytes as i64],
+    ).map_err(db_error)?;
+    Ok(())
+}
+
+/// Deployment facts
+impl SQLiteMetadataStore {
+    pub fn get_meta_info(&self, name: &str) -> Result<Option<String>, Error> {
+        let conn = timed_conn("get_meta_info", "", "");
+        conn.query_row("SELECT value FROM meta_info WHERE name = ?1", params![name], |row| row.get(0))
+            .optional().map_err(db_error)
+    }
+
+    pub fn set_meta_info(&self, name: &str, value: &str) -> Result<(), Error> {
+        let conn = timed_conn("set_meta_info", "", "");
+        conn.execute(
+            "INSERT INTO meta_info (name, value) VALUES (?1, ?2)
+             ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+            params![name, value],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Whether any user has an object, of any state
+    pub fn has_objects(&self) -> Result<bool, Error> {
+        let conn = timed_conn("has_objects", "", "");
+        conn.query_row("SELECT EXISTS(SELECT 1 FROM objects)", [], |row| row.get(0)).map_err(db_error)
+    }
+}
+
+/// Storage layout of a user's bucket files
+impl SQLiteMetadataStore {
+    /// `(layout, target)` recorded for `user_id` ("" for the server-wide layout)
+    pub fn get_storage_layout(&self, user_id: &str) -> Result<Option<(String, Option<String>)>, Error> {
+        let conn = timed_conn("get_storage_layout", user_id, "");
+        conn.query_row(
+            "SELECT layout, target FROM storage_layouts WHERE user_id = ?1",
+            params![user_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional().map_err(db_error)
+    }
+
+    /// Record `layout` for `user_id`, moving towards `target` if given.
+    pub fn set_storage_layout(&self, user_id: &str, layout: &str, target: Option<&str>) -> Result<(), Error> {
+        let conn = timed_conn("set_storage_layout", user_id, "");
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "INSERT INTO storage_layouts (user_id, layout, target) VALUES (?1, ?2, ?3)
+             ON CONFLICT(user_id) DO UPDATE SET layout = excluded.layout, target = excluded.target",
+            params![user_id, layout, target],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Record `layout` for each of `users` that has none, and `server_layout` as the
+    /// server-wide layout, in one transaction. Returns how many users were pinned.
+    pub fn pin_storage_layouts(&self, users: &[String], layout: &str, server_layout: &str) -> Result<usize, Error> {
+        let conn = timed_conn("pin_storage_layouts", "", "");
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let mut pinned = 0;
+        for user in users.iter().filter(|u| !u.is_empty()) {
+            pinned += tx.execute(
+                "INSERT OR IGNORE INTO storage_layouts (user_id, layout, target) VALUES (?1, ?2, NULL)",
+                params![user, layout],
+            ).map_err(db_error)?;
+        }
+        tx.execute(
+            "INSERT INTO storage_layouts (user_id, layout, target) VALUES ('', ?1, NULL)
+             ON CONFLICT(user_id) DO UPDATE SET layout = excluded.layout",
+            params![server_layout],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(pinned)
+    }
+}
+
+/// Per-bucket S3 access flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BucketAccess {
+    /// GET/HEAD allowed without credentials
+    pub public_read: bool,
+    /// Mutating requests rejected even with valid credentials
+    pub read_only: bool,
+}
+
+/// Bucket access flags
+impl SQLiteMetadataStore {
+    /// Flags of `user_id`/`bucket`, or None when the bucket does not exist
+    pub fn get_bucket_access(&self, user_id: &str, bucket: &str) -> Result<Option<BucketAccess>, Error> {
+        let conn = timed_conn("get_bucket_access", user_id, bucket);
+        conn.query_row(
+            "SELECT public_read, read_only FROM buckets WHERE user = ?1 AND name = ?2",
+            params![user_id, bucket],
+            |row| Ok(BucketAccess {
+                public_read: row.get::<_, i64>(0)? != 0,
+                read_only: row.get::<_, i64>(1)? != 0,
+            }),
+        ).optional().map_err(db_error)
+    }
+
+    /// Returns false when the bucket does not exist.
+    pub fn set_bucket_access(&self, user_id: &str, bucket: &str, access: &BucketAccess) -> Result<bool, Error> {
+        let conn = timed_conn("set_bucket_access", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let changed = tx.execute(
+            "UPDATE buckets SET public_read = ?1, read_only = ?2 WHERE user = ?3 AND name = ?4",
+            params![access.public_read as i64, access.read_only as i64, user_id, bucket],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(changed > 0)
+    }
+
+    /// Owners of public-read buckets named `bucket`
+    pub fn public_read_owners(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        let conn = timed_conn("public_read_owners", "", bucket);
+        let mut stmt = conn.prepare(
+            "SELECT user FROM buckets WHERE name = ?1 AND public_read = 1 ORDER BY user",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![bucket], |row| row.get::<_, String>(0))
+            .map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+}
+
+/// Per-bucket quotas; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct BucketLimits {
+    pub max_objects: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl BucketLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_objects.is_none() && self.max_bytes.is_none()
+    }
+}
+
+/// Bucket quotas
+impl SQLiteMetadataStore {
+    /// Limits of `user_id`/`bucket`, or None when the bucket does not exist
+    pub fn get_bucket_limits(&self, user_id: &str, bucket: &str) -> Result<Option<BucketLimits>, Error> {
+        let conn = timed_conn("get_bucket_limits", user_id, bucket);
+        conn.query_row(
+            "SELECT max_objects, max_bytes FROM buckets WHERE user = ?1 AND name = ?2",
+            params![user_id, bucket],
+            |row| Ok(BucketLimits {
+                max_objects: row.get::<_, Option<i64>>(0)?.map(|v| v as u64),
+                max_bytes: row.get::<_, Option<i64>>(1)?.map(|v| v as u64),
+            }),
+        ).optional().map_err(db_error)
+    }
+
+    /// Returns false when the bucket does not exist.
+    pub fn set_bucket_limits(&self, user_id: &str, bucket: &str, limits: &BucketLimits) -> Result<bool, Error> {
+        let conn = timed_conn("set_bucket_limits", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let changed = tx.execute(
+            "UPDATE buckets SET max_objects = ?1, max_bytes = ?2 WHERE user = ?3 AND name = ?4",
+            params![limits.max_objects.map(|v| v as i64), limits.max_bytes.map(|v| v as i64), user_id, bucket],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(changed > 0)
+    }
+}
+
+/// Native API keys
+impl SQLiteMetadataStore {
+    pub fn insert_api_key(&self, key_hash: &str, key_id: &str, user_id: &str, created_at: &str) -> Result<(), Error> {
+        let conn = timed_conn("insert_api_key", user_id, "");
+        conn.execute(
+            "INSERT INTO api_keys (key_hash, key_id, user_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![key_hash, key_id, user_id, created_at],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// User an enabled key belongs to
+    pub fn api_key_user(&self, key_hash: &str) -> Result<Option<String>, Error> {
+        let conn = timed_conn("api_key_user", "", "");
+        conn.query_row(
+            "SELECT user_id FROM api_keys WHERE key_hash = ?1 AND disabled = 0",
+            params![key_hash],
+            |row| row.get(0),
+        ).optional().map_err(db_error)
+    }
+
+    /// Disable `key_id` of `user_id`. Returns whether an enabled key was revoked.
+    pub fn disable_api_key(&self, user_id: &str, key_id: &str) -> Result<bool, Error> {
+        let conn = timed_conn("disable_api_key", user_id, "");
+        let changed = conn.execute(
+            "UPDATE api_keys SET disabled = 1 WHERE user_id = ?1 AND key_id = ?2 AND disabled = 0",
+            params![user_id, key_id],
+        ).map_err(db_error)?;
+        Ok(changed > 0)
+    }
+}
+
+/// A native bucket grant
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BucketGrantRow {
+    pub owner: String,
+    pub bucket: String,
+    pub grantee: String,
+    /// `read`, `write` or `admin`
+    pub permission: String,
+    pub created_at: String,
+}
+
+fn bucket_grant_row(row: &rusqlite::Row) -> rusqlite::Result<BucketGrantRow> {
+    Ok(BucketGrantRow {
+        owner: row.get(0)?,
+        bucket: row.get(1)?,
+        grantee: row.get(2)?,
+        permission: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+/// Native bucket grants
+impl SQLiteMetadataStore {
+    /// Grant `grantee` `permission` on `owner`'s `bucket`, replacing any earlier grant
+    pub fn put_bucket_grant(&self, owner: &str, bucket: &str, grantee: &str, permission: &str) -> Result<(), Error> {
+        let conn = timed_conn("put_bucket_grant", owner, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "INSERT INTO bucket_grants (owner_user, bucket, grantee_user, permission) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (owner_user, bucket, grantee_user) DO UPDATE SET permission = excluded.permission",
+            params![owner, bucket, grantee, permission],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)
+    }
+
+    /// Returns whether there was a grant to revoke.
+    pub fn delete_bucket_grant(&self, owner: &str, bucket: &str, grantee: &str) -> Result<bool, Error> {
+        let conn = timed_conn("delete_bucket_grant", owner, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let changed = tx.execute(
+            "DELETE FROM bucket_grants WHERE owner_user = ?1 AND bucket = ?2 AND grantee_user = ?3",
+            params![owner, bucket, grantee],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(changed > 0)
+    }
+
+    /// Grants on `owner`'s `bucket`, by grantee
+    pub fn bucket_grants(&self, owner: &str, bucket: &str) -> Result<Vec<BucketGrantRow>, Error> {
+        let conn = timed_conn("bucket_grants", owner, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT owner_user, bucket, grantee_user, permission, created_at FROM bucket_grants
+             WHERE owner_user = ?1 AND bucket = ?2 ORDER BY grantee_user",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![owner, bucket], bucket_grant_row).map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+
+    /// Grants to `grantee`, by bucket and owner
+    pub fn grants_to(&self, grantee: &str) -> Result<Vec<BucketGrantRow>, Error> {
+        let conn = timed_conn("grants_to", grantee, "");
+        let mut stmt = conn.prepare(
+            "SELECT owner_user, bucket, grantee_user, permission, created_at FROM bucket_grants
+             WHERE grantee_user = ?1 ORDER BY bucket, owner_user",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![grantee], bucket_grant_row).map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+}
+
+/// An admin API token, without its hash
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AdminTokenRecord {
+    pub token_id: String,
+    pub description: String,
+    /// Comma-separated scope names
+    pub scopes: String,
+    pub user: Option<String>,
+    pub created_at: String,
+    pub disabled: bool,
+}
+
+fn admin_token_row(row: &rusqlite::Row) -> rusqlite::Result<AdminTokenRecord> {
+    Ok(AdminTokenRecord {
+        token_id: row.get(0)?,
+        description: row.get(1)?,
+        scopes: row.get(2)?,
+        user: row.get(3)?,
+        created_at: row.get(4)?,
+        disabled: row.get::<_, i64>(5)? != 0,
+    })
+}
+
+/// Admin API tokens
+impl SQLiteMetadataStore {
+    pub fn insert_admin_token(&self, token_hash: &str, record: &AdminTokenRecord) -> Result<(), Error> {
+        let conn = timed_conn("insert_admin_token", record.user.as_deref().unwrap_or(""), "");
+        conn.execute(
+            "INSERT INTO admin_tokens (token_hash, token_id, description, scopes, user_id, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![token_hash, record.token_id, record.description, record.scopes, record.user, record.created_at],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// The token with this hash, enabled or not
+    pub fn admin_token(&self, token_hash: &str) -> Result<Option<AdminTokenRecord>, Error> {
+        let conn = timed_conn("admin_token", "", "");
+        conn.query_row(
+            "SELECT token_id, description, scopes, user_id, created_at, disabled FROM admin_tokens WHERE token_hash = ?1",
+            params![token_hash],
+            admin_token_row,
+        ).optional().map_err(db_error)
+    }
+
+    /// Every token, oldest first
+    pub fn list_admin_tokens(&self) -> Result<Vec<AdminTokenRecord>, Error> {
+        let conn = timed_conn("list_admin_tokens", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT token_id, description, scopes, user_id, created_at, disabled FROM admin_tokens ORDER BY rowid",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map([], admin_token_row).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    pub fn admin_token_count(&self) -> Result<u64, Error> {
+        let conn = timed_conn("admin_token_count", "", "");
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM admin_tokens", [], |row| row.get(0)).map_err(db_error)?;
+        Ok(count as u64)
+    }
+
+    /// Disable `token_id`. Returns whether an enabled token was disabled.
+    pub fn disable_admin_token(&self, token_id: &str) -> Result<bool, Error> {
+        let conn = timed_conn("disable_admin_token", "", "");
+        let changed = conn.execute(
+            "UPDATE admin_tokens SET disabled = 1 WHERE token_id = ?1 AND disabled = 0",
+            params![token_id],
+        ).map_err(db_error)?;
+        Ok(changed > 0)
+    }
+}
+
+/// A wrapped data encryption key of a bucket
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketKeyRow {
+    pub user_id: String,
+    pub bucket: String,
+    pub version: u32,
+    pub wrapped_key: Vec<u8>,
+    pub master_key_id: String,
+}
+
+fn bucket_key_row(row: &rusqlite::Row) -> rusqlite::Result<BucketKeyRow> {
+    Ok(BucketKeyRow {
+        user_id: row.get(0)?,
+        bucket: row.get(1)?,
+        version: row.get::<_, i64>(2)? as u32,
+        wrapped_key: row.get(3)?,
+        master_key_id: row.get(4)?,
+    })
+}
+
+/// An encrypted chunk: the stored extent and the key version and IV it was written with
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkKeyRow {
+    pub offset: u64,
+    pub size: u64,
+    pub key_version: u32,
+    pub iv: Vec<u8>,
+}
+
+/// Condition on chunk_keys rows of `?1`/`?2` overlapping `[?3, ?4)`. Starting from the last
+/// chunk at or before `?3` keeps the scan on the primary key.
+const CHUNKS_OVERLAPPING: &str = "offset >= COALESCE((SELECT MAX(offset) FROM chunk_keys
+        WHERE user_id = ?1 AND bucket = ?2 AND offset <= ?3), 0)
+    AND offset < ?4 AND offset + size > ?3";
+
+/// Bucket encryption keys and the keys of encrypted chunks
+impl SQLiteMetadataStore {
+    /// Versions of the bucket's key, oldest first
+    pub fn bucket_keys(&self, user_id: &str, bucket: &str) -> Result<Vec<BucketKeyRow>, Error> {
+        let conn = timed_conn("bucket_keys", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT user_id, bucket, version, wrapped_key, master_key_id FROM bucket_keys
+             WHERE user_id = ?1 AND bucket = ?2 ORDER BY version",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket], bucket_key_row).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    /// Every key of every bucket
+    pub fn all_bucket_keys(&self) -> Result<Vec<BucketKeyRow>, Error> {
+        let conn = timed_conn("all_bucket_keys", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT user_id, bucket, version, wrapped_key, master_key_id FROM bucket_keys
+             ORDER BY user_id, bucket, version",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map([], bucket_key_row).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    pub fn has_bucket_keys(&self) -> Result<bool, Error> {
+        let conn = timed_conn("has_bucket_keys", "", "");
+        conn.query_row("SELECT EXISTS(SELECT 1 FROM bucket_keys)", [], |row| row.get(0)).map_err(db_error)
+    }
+
+    /// Store a new key version. Returns false when the version already exists, as when two
+    /// writers create the first key of a bucket at once.
+    pub fn insert_bucket_key(&self, key: &BucketKeyRow) -> Result<bool, Error> {
+        let conn = timed_conn("insert_bucket_key", &key.user_id, &key.bucket);
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO bucket_keys (user_id, bucket, version, wrapped_key, master_key_id)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![key.user_id, key.bucket, key.version as i64, key.wrapped_key, key.master_key_id],
+        ).map_err(db_error)?;
+        Ok(inserted > 0)
+    }
+
+    /// Replace the wrapping of `key`, provided it is still wrapped as read. Returns whether
+    /// the row was updated.
+    pub fn rewrap_bucket_key(&self, key: &BucketKeyRow, wrapped_key: &[u8], master_key_id: &str) -> Result<bool, Error> {
+        let conn = timed_conn("rewrap_bucket_key", &key.user_id, &key.bucket);
+        let changed = conn.execute(
+            "UPDATE bucket_keys SET wrapped_key = ?4, master_key_id = ?5
+             WHERE user_id = ?1 AND bucket = ?2 AND version = ?3 AND wrapped_key = ?6",
+            params![key.user_id, key.bucket, key.version as i64, wrapped_key, master_key_id, key.wrapped_key],
+        ).map_err(db_error)?;
+        Ok(changed > 0)
+    }
+
+    /// Record the chunk written at `chunk.offset`, replacing chunks that used to overlap it
+    pub fn record_chunk_key(&self, user_id: &str, bucket: &str, chunk: &ChunkKeyRow) -> Result<(), Error> {
+        let conn = timed_conn("record_chunk_key", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            &format!("DELETE FROM chunk_keys WHERE user_id = ?1 AND bucket = ?2 AND {}", CHUNKS_OVERLAPPING),
+            params![user_id, bucket, chunk.offset as i64, (chunk.offset + chunk.size) as i64],
+        ).map_err(db_error)?;
+        tx.execute(
+            "INSERT INTO chunk_keys (user_id, bucket, offset, size, key_version, iv) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![user_id, bucket, chunk.offset as i64, chunk.size as i64, chunk.key_version as i64, chunk.iv],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)
+    }
+
+    /// Encrypted chunks overlapping `[start, end)`, by offset
+    pub fn chunk_keys_in(&self, user_id: &str, bucket: &str, start: u64, end: u64) -> Result<Vec<ChunkKeyRow>, Error> {
+        let conn = timed_conn("chunk_keys_in", user_id, bucket);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT offset, size, key_version, iv FROM chunk_keys
+             WHERE user_id = ?1 AND bucket = ?2 AND {} ORDER BY offset",
+            CHUNKS_OVERLAPPING,
+        )).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, start as i64, end as i64], |row| Ok(ChunkKeyRow {
+            offset: row.get::<_, i64>(0)? as u64,
+            size: row.get::<_, i64>(1)? as u64,
+            key_version: row.get::<_, i64>(2)? as u32,
+            iv: row.get(3)?,
+        })).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    /// Drop the records of chunks starting inside the freed `ranges`
+    pub fn forget_chunk_keys(&self, user_id: &str, bucket: &str, ranges: &[(u64, u64)]) -> Result<(), Error> {
+        let conn = timed_conn("forget_chunk_keys", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        for &(offset, size) in ranges {
+            tx.execute(
+                "DELETE FROM chunk_keys WHERE user_id = ?1 AND bucket = ?2 AND offset >= ?3 AND offset < ?4",
+                params![user_id, bucket, offset as i64, (offset + size) as i64],
+            ).map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)
+    }
+}
+
+/// Chunk list of one object row (any version) with its key
+pub struct KeyChunksRow {
+    pub id: i64,
+    pub key: String,
+    pub chunks: Vec<DataChunk>,
+    /// The chunk list as stored, which an update must find unchanged
+    pub(crate) stored: Vec<u8>,
+}
+
+/// Backfilling chunk checksums
+impl SQLiteMetadataStore {
+    /// Keys of `bucket` holding chunks after `after` (all of them when None)
+    pub fn chunk_keys_after_count(&self, user_id: &str, bucket: &str, after: Option<&str>) -> Result<u64, Error> {
+        let conn = timed_conn("chunk_keys_after_count", user_id, bucket);
+        conn.query_row(
+            "SELECT COUNT(DISTINCT key) FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL AND (?3 IS NULL OR key > ?3)",
+            params![user_id, bucket, after],
+            |row| row.get::<_, i64>(0),
+        ).map(|n| n as u64).map_err(db_error)
+    }
+
+    /// Rows of every version of the next `keys` keys after `after` that hold chunks, in key order
+    pub fn chunk_rows_after(&self, user_id: &str, bucket: &str, after: Option<&str>, keys: usize) -> Result<Vec<KeyChunksRow>, Error> {
+        let conn = timed_conn("chunk_rows_after", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT id, key, offset_size_list FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL AND key IN (
+                 SELECT DISTINCT key FROM objects
+                 WHERE user = ?1 AND bucket = ?2 AND offset_size_list IS NOT NULL AND (?3 IS NULL OR key > ?3)
+                 ORDER BY key LIMIT ?4
+             )
+             ORDER BY key, id",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, after, keys as i64], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Vec<u8>>(2)?))
+        }).map_err(db_error)?;
+        let rows: Vec<(i64, String, Vec<u8>)> = rows.collect::<rusqlite::Result<_>>().map_err(db_error)?;
+        drop(stmt);
+        drop(conn);
+
+        let mut result = Vec::with_capacity(rows.len());
+        for (id, key, stored) in rows {
+            result.push(KeyChunksRow { id, key, chunks: decode_chunks(&stored)?, stored });
+        }
+        Ok(result)
+    }
+
+    /// Swap in chunk lists carrying checksums and record `after_key` as the last key done, in
+    /// one transaction. Each row is only rewritten if it still holds the list that was read.
+    /// Returns the rows rewritten.
+    pub fn commit_chunk_checksums(
+        &self, user_id: &str, bucket: &str, rows: &[(&KeyChunksRow, Vec<DataChunk>)], after_key: &str,
+    ) -> Result<usize, Error> {
+        let conn = timed_conn("commit_chunk_checksums", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let mut rewritten = 0;
+        for (old, chunks) in rows {
+            rewritten += tx.execute(
+                "UPDATE objects SET offset_size_list = ?1 WHERE id = ?2 AND offset_size_list = ?3",
+                params![encode_chunks(chunks)?, old.id, old.stored],
+            ).map_err(db_error)?;
+        }
+        tx.execute(
+            "INSERT INTO checksum_backfill (user_id, bucket, after_key) VALUES (?1, ?2, ?3)
+             ON CONFLICT(user_id, bucket) DO UPDATE SET after_key = excluded.after_key,
+                 updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')",
+            params![user_id, bucket, after_key],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(rewritten)
+    }
+
+    /// Last key an unfinished backfill of the bucket completed
+    pub fn checksum_backfill_cursor(&self, user_id: &str, bucket: &str) -> Result<Option<String>, Error> {
+        let conn = timed_conn("checksum_backfill_cursor", user_id, bucket);
+        conn.query_row(
+            "SELECT after_key FROM checksum_backfill WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+            |row| row.get(0),
+        ).optional().map_err(db_error)
+    }
+
+    /// Forget the backfill progress of the bucket once it reached the end
+    pub fn finish_checksum_backfill(&self, user_id: &str, bucket: &str) -> Result<(), Error> {
+        let conn = timed_conn("finish_checksum_backfill", user_id, bucket);
+        conn.execute(
+            "DELETE FROM checksum_backfill WHERE user_id = ?1 AND bucket = ?2",
+            params![user_id, bucket],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+}
+
+/// One call the metadata shadow backend answered differently
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShadowDivergence {
+    pub id: i64,
+    pub op: String,
+    pub user_id: String,
+    pub bucket: String,
+    pub key: Option<String>,
+    pub primary_result: String,
+    pub shadow_result: String,
+    pub recorded_at: String,
+}
+
+/// Metadata shadow divergences
+impl SQLiteMetadataStore {
+    pub fn record_shadow_divergence(
+        &self, op: &str, user_id: &str, bucket: &str, key: Option<&str>, primary_result: &str, shadow_result: &str,
+    ) -> Result<(), Error> {
+        let conn = timed_conn("record_shadow_divergence", user_id, bucket);
+        conn.execute(
+            "INSERT INTO shadow_divergence (op, user_id, bucket, key, primary_result, shadow_result)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![op, user_id, bucket, key, primary_result, shadow_result],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Divergences recorded so far, by operation
+    pub fn shadow_divergence_counts(&self) -> Result<Vec<(String, u64)>, Error> {
+        let conn = timed_conn("shadow_divergence_counts", "", "");
+        let mut stmt = conn
+            .prepare("SELECT op, COUNT(*) FROM shadow_divergence GROUP BY op ORDER BY op")
+            .map_err(db_error)?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        Ok(rows)
+    }
+
+    /// The `limit` most recent divergences, newest first
+    pub fn recent_shadow_divergences(&self, limit: usize) -> Result<Vec<ShadowDivergence>, Error> {
+        let conn = timed_conn("recent_shadow_divergences", "", "");
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, op, user_id, bucket, key, primary_result, shadow_result, recorded_at
+                 FROM shadow_divergence ORDER BY id DESC LIMIT ?1",
+            )
+            .map_err(db_error)?;
+        let rows = stmt.query_map(params![limit as i64], |row| Ok(ShadowDivergence {
+                id: row.get(0)?,
+                op: row.get(1)?,
+                user_id: row.get(2)?,
+                bucket: row.get(3)?,
+                key: row.get(4)?,
+                primary_result: row.get(5)?,
+                shadow_result: row.get(6)?,
+                recorded_at: row.get(7)?,
+            }))
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        Ok(rows)
+    }
+}
+
+/// A write lease on one key
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LeaseRecord {
+    pub token: String,
+    pub expires_at: String,
+}
+
+/// Write leases
+impl SQLiteMetadataStore {
+    /// The lease on `key`, expired or not
+    pub fn lease(&self, user_id: &str, bucket: &str, key: &str) -> Result<Option<LeaseRecord>, Error> {
+        let conn = timed_conn("lease", user_id, bucket);
+        conn.query_row(
+            "SELECT token, expires_at FROM leases WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+            params![user_id, bucket, key],
+            |row| Ok(LeaseRecord { token: row.get(0)?, expires_at: row.get(1)? }),
+        ).optional().map_err(db_error)
+    }
+
+    /// Set the lease on `key`, replacing any previous one
+    pub fn put_lease(&self, user_id: &str, bucket: &str, key: &str, lease: &LeaseRecord) -> Result<(), Error> {
+        let conn = timed_conn("put_lease", user_id, bucket);
+        conn.execute(
+            "INSERT OR REPLACE INTO leases (user_id, bucket, key, token, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user_id, bucket, key, lease.token, lease.expires_at],
+        ).map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Remove the lease on `key` if `token` holds it. Returns whether one was removed.
+    pub fn delete_lease(&self, user_id: &str, bucket: &str, key: &str, token: &str) -> Result<bool, Error> {
+        let conn = timed_conn("delete_lease", user_id, bucket);
+        let removed = conn.execute(
+            "DELETE FROM leases WHERE user_id = ?1 AND bucket = ?2 AND key = ?3 AND token = ?4",
+            params![user_id, bucket, key, token],
+        ).map_err(db_error)?;
+        Ok(removed > 0)
+    }
+}
+
+/// Last maintenance mode change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceRecord {
+    pub mode: String,
+    pub changed_at: String,
+    /// Request id of the admin call that made the change
+    pub changed_by: String,
+    /// RFC 3339 time the mode lapses back to normal, if bounded
+    pub until: Option<String>,
+}
+
+/// Object expiry
+impl SQLiteMetadataStore {
+    /// Version id of the latest live row of `key` when its expiry time is at or before `now`
+    /// (both as stored in `last_modified`)
+    pub fn expired_version(&self, user_id: &str, bucket: &str, key: &str, now: &str) -> Result<Option<String>, Error> {
+        let conn = timed_conn("expired_version", user_id, bucket);
+        conn.query_row(
+            "SELECT version_id FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0
+               AND expire_at IS NOT NULL AND expire_at <= ?4",
+            params![user_id, bucket, key, now],
+            |row| row.get(0),
+        ).optional().map_err(db_error)
+    }
+
+    /// Remove every row and tag of `key` and queue their chunks, in one transaction, if its
+    /// latest row still expires at or before `now`. Returns whether it did.
+    pub fn expire_object(&self, user_id: &str, bucket: &str, key: &str, now: &str) -> Result<bool, Error> {
+        let conn = timed_conn("expire_object", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let due = tx.query_row(
+            "SELECT 1 FROM objects
+             WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND is_latest = 1 AND is_delete_marker = 0
+               AND expire_at IS NOT NULL AND expire_at <= ?4",
+            params![user_id, bucket, key, now],
+            |_| Ok(()),
+        ).optional().map_err(db_error)?.is_some();
+        if !due {
+            return Ok(false);
+        }
+        remove_key(&tx, user_id, bucket, key)?;
+        tx.commit().map_err(db_error)?;
+        Ok(true)
+    }
+
+    /// Up to `limit` (user, bucket, key) whose latest row expires at or before `now`, soonest
+    /// first
+    pub fn due_expirations(&self, now: &str, limit: usize) -> Result<Vec<(String, String, String)>, Error> {
+        let conn = timed_conn("due_expirations", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT user, bucket, key FROM objects
+             WHERE expire_at IS NOT NULL AND expire_at <= ?1 AND is_latest = 1 AND is_delete_marker = 0
+             ORDER BY expire_at LIMIT ?2",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![now, limit as i64], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+}
+
+/// Remove every row, tag and access time of `key` and queue its chunks; call inside the
+/// transaction that decided the key goes.
+fn remove_key(conn: &Connection, user_id: &str, bucket: &str, key: &str) -> Result<(), Error> {
+    let mut stmt = conn.prepare(
+        "SELECT offset_size_list FROM objects
+         WHERE user = ?1 AND bucket = ?2 AND key = ?3 AND offset_size_list IS NOT NULL",
+    ).map_err(db_error)?;
+    let blobs = stmt.query_map(params![user_id, bucket, key], |row| row.get::<_, Vec<u8>>(0))
+        .map_err(db_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(db_error)?;
+    drop(stmt);
+    let mut extents = Vec::new();
+    for blob in blobs {
+        extents.extend(crate::util::serializer::decode_extents(&blob)?);
+    }
+    queue_unreferenced(conn, user_id, bucket, key, &extents)?;
+    conn.execute(
+        "DELETE FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3",
+        params![user_id, bucket, key],
+    ).map_err(db_error)?;
+    conn.execute(
+        "DELETE FROM object_tags WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+        params![user_id, bucket, key],
+    ).map_err(db_error)?;
+    conn.execute(
+        "DELETE FROM access_times WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+        params![user_id, bucket, key],
+    ).map_err(db_error)?;
+    Ok(())
+}
+
+/// One key's reads since the last flush of the access tracker
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessUpdate {
+    pub user_id: String,
+    pub bucket: String,
+    pub key: String,
+    /// As stored in `last_modified`
+    pub last_accessed_at: String,
+    pub reads: u64,
+}
+
+/// Access times and last-access lifecycle rules
+impl SQLiteMetadataStore {
+    /// Fold `updates` into `access_times` in one transaction: read counts add up and the
+    /// later access time wins.
+    pub fn record_accesses(&self, updates: &[AccessUpdate]) -> Result<(), Error> {
+        let conn = timed_conn("record_accesses", "", "");
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO access_times (user_id, bucket, key, last_accessed_at, reads)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (user_id, bucket, key) DO UPDATE SET
+                     last_accessed_at = MAX(last_accessed_at, excluded.last_accessed_at),
+                     reads = reads + excluded.reads",
+            ).map_err(db_error)?;
+            for update in updates {
+                stmt.execute(params![update.user_id, update.bucket, update.key, update.last_accessed_at, update.reads as i64])
+                    .map_err(db_error)?;
+            }
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    /// Recorded last access of each of `keys` that has one
+    pub fn access_times(&self, user_id: &str, bucket: &str, keys: &[&str]) -> Result<std::collections::HashMap<String, String>, Error> {
+        let conn = timed_conn("access_times", user_id, bucket);
+        let mut stmt = conn.prepare(
+            "SELECT last_accessed_at FROM access_times WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+        ).map_err(db_error)?;
+        let mut times = std::collections::HashMap::new();
+        for key in keys {
+            let time: Option<String> = stmt.query_row(params![user_id, bucket, key], |row| row.get(0))
+                .optional().map_err(db_error)?;
+            if let Some(time) = time {
+                times.insert(key.to_string(), time);
+            }
+        }
+        Ok(times)
+    }
+
+    /// Days after which unread objects of `user_id`/`bucket` expire, or None when the bucket
+    /// does not exist. The inner None is no rule.
+    pub fn get_expire_unread_days(&self, user_id: &str, bucket: &str) -> Result<Option<Option<u32>>, Error> {
+        let conn = timed_conn("get_expire_unread_days", user_id, bucket);
+        conn.query_row(
+            "SELECT expire_unread_days FROM buckets WHERE user = ?1 AND name = ?2",
+            params![user_id, bucket],
+            |row| row.get::<_, Option<i64>>(0).map(|days| days.map(|d| d as u32)),
+        ).optional().map_err(db_error)
+    }
+
+    /// Returns false when the bucket does not exist.
+    pub fn set_expire_unread_days(&self, user_id: &str, bucket: &str, days: Option<u32>) -> Result<bool, Error> {
+        let conn = timed_conn("set_expire_unread_days", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let changed = tx.execute(
+            "UPDATE buckets SET expire_unread_days = ?1 WHERE user = ?2 AND name = ?3",
+            params![days.map(i64::from), user_id, bucket],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(changed > 0)
+    }
+
+    /// (user, bucket, days) of every bucket with a last-access rule
+    pub fn expire_unread_rules(&self) -> Result<Vec<(String, String, u32)>, Error> {
+        let conn = timed_conn("expire_unread_rules", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT user, name, expire_unread_days FROM buckets WHERE expire_unread_days IS NOT NULL ORDER BY user, name",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as u32)))
+            .map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+
+    /// Up to `limit` (key, version id) of `bucket` neither written nor read since `cutoff`
+    /// (as stored in `last_modified`), in key order
+    pub fn unread_objects(&self, user_id: &str, bucket: &str, cutoff: &str, limit: usize) -> Result<Vec<(String, String)>, Error> {
+        let conn = timed_conn("unread_objects", user_id, bucket);
         let mut stmt = conn.prepare(
-            "SELECT upload_id, user_id, bucket, key, content_type, metadata_json,
-                    initiated_at, status, final_etag, checksum_algorithm, checksum_type,
-                    object_lock_mode, object_lock_retain_until, object_lock_legal_hold
-             FROM multipart_uploads WHERE upload_id = ?1",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        let result = stmt.query_row(params![upload_id], |row| {
-            Ok(MultipartUploadRow {
-                upload_id: row.get(0)?,
-                user_id: row.get(1)?,
-                bucket: row.get(2)?,
-                key: row.get(3)?,
-                content_type: row.get(4)?,
-                metadata_json: row.get::<_, Option<String>>(5)?.unwrap_or_else(|| "{}".to_string()),
-                initiated_at: row.get(6)?,
-                status: row.get::<_, String>(7)?,
-                final_etag: row.get(8)?,
-                checksum_algorithm: row.get::<_, Option<String>>(9)?.unwrap_or_default(),
-                checksum_type: row.get::<_, Option<String>>(10)?.unwrap_or_default(),
-                object_lock_mode: row.get::<_, Option<String>>(11)?.unwrap_or_default(),
-                object_lock_retain_until: row.get::<_, Option<String>>(12)?.unwrap_or_default(),
-                object_lock_legal_hold: row.get::<_, Option<String>>(13)?.unwrap_or_default(),
-            })
-        });
-        match result {
-            Ok(row) => Ok(Some(row)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+            "SELECT o.key, o.version_id FROM objects o
+             LEFT JOIN access_times a ON a.user_id = o.user AND a.bucket = o.bucket AND a.key = o.key
+             WHERE o.user = ?1 AND o.bucket = ?2 AND o.is_latest = 1 AND o.is_delete_marker = 0
+               AND o.last_modified < ?3 AND (a.last_accessed_at IS NULL OR a.last_accessed_at < ?3)
+             ORDER BY o.key LIMIT ?4",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![user_id, bucket, cutoff, limit as i64], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+
+    /// Remove `key` as [`expire_object`](Self::expire_object) does if it is still unread since
+    /// `cutoff`. Returns whether it did.
+    pub fn expire_unread_object(&self, user_id: &str, bucket: &str, key: &str, cutoff: &str) -> Result<bool, Error> {
+        let conn = timed_conn("expire_unread_object", user_id, bucket);
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        let unread = tx.query_row(
+            "SELECT 1 FROM objects o
+             LEFT JOIN access_times a ON a.user_id = o.user AND a.bucket = o.bucket AND a.key = o.key
+             WHERE o.user = ?1 AND o.bucket = ?2 AND o.key = ?3 AND o.is_latest = 1 AND o.is_delete_marker = 0
+               AND o.last_modified < ?4 AND (a.last_accessed_at IS NULL OR a.last_accessed_at < ?4)",
+            params![user_id, bucket, key, cutoff],
+            |_| Ok(()),
+        ).optional().map_err(db_error)?.is_some();
+        if !unread {
+            return Ok(false);
         }
+        remove_key(&tx, user_id, bucket, key)?;
+        tx.commit().map_err(db_error)?;
+        Ok(true)
     }
+}
 
-    pub fn mark_multipart_completed(&self, upload_id: &str, final_etag: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "UPDATE multipart_uploads SET status = 'completed', final_etag = ?1 WHERE upload_id = ?2",
-            params![final_etag, upload_id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+/// Bump the configuration generation; call inside the transaction making the change.
+fn bump_config_generation(conn: &Connection) -> Result<(), Error> {
+    conn.execute("UPDATE config_generation SET generation = generation + 1 WHERE id = 1", [])
+        .map_err(db_error)?;
+    Ok(())
+}
+
+/// Maintenance mode
+impl SQLiteMetadataStore {
+    pub fn get_maintenance(&self) -> Result<Option<MaintenanceRecord>, Error> {
+        let conn = timed_conn("get_maintenance", "", "");
+        conn.query_row(
+            "SELECT mode, changed_at, changed_by, until FROM maintenance WHERE id = 1",
+            [],
+            |row| Ok(MaintenanceRecord {
+                mode: row.get(0)?,
+                changed_at: row.get(1)?,
+                changed_by: row.get(2)?,
+                until: row.get(3)?,
+            }),
+        ).optional().map_err(db_error)
     }
 
-    pub fn delete_multipart_upload(&self, upload_id: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "DELETE FROM multipart_uploads WHERE upload_id = ?1",
-            params![upload_id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+    /// Current configuration generation (0 before any change)
+    pub fn config_generation(&self) -> Result<u64, Error> {
+        let conn = timed_conn("config_generation", "", "");
+        conn.query_row("SELECT generation FROM config_generation WHERE id = 1", [], |row| row.get::<_, i64>(0))
+            .optional()
+            .map(|g| g.unwrap_or(0) as u64)
+            .map_err(db_error)
+    }
+
+    pub fn set_maintenance(&self, record: &MaintenanceRecord) -> Result<(), Error> {
+        let conn = timed_conn("set_maintenance", "", "");
+        let tx = conn.unchecked_transaction().map_err(db_error)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO maintenance (id, mode, changed_at, changed_by, until)
+             VALUES (1, ?1, ?2, ?3, ?4)",
+            params![record.mode, record.changed_at, record.changed_by, record.until],
+        ).map_err(db_error)?;
+        bump_config_generation(&tx)?;
+        tx.commit().map_err(db_error)?;
         Ok(())
     }
+}
 
-    pub fn delete_completed_uploads_for_key(&self, bucket: &str, key: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
+/// Row of the `jobs` table
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobRecord {
+    pub id: i64,
+    pub kind: String,
+    pub user: String,
+    pub bucket: String,
+    pub params: serde_json::Value,
+    pub state: String,
+    /// Fraction done, 0 to 1
+    pub progress: f64,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+}
+
+const JOB_COLUMNS: &str =
+    "id, kind, user_id, bucket, params, state, progress, result, error, created_at, started_at, finished_at";
+
+fn job_record(row: &rusqlite::Row) -> rusqlite::Result<JobRecord> {
+    let json = |text: String| serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text));
+    Ok(JobRecord {
+        id: row.get(0)?,
+        kind: row.get(1)?,
+        user: row.get(2)?,
+        bucket: row.get(3)?,
+        params: json(row.get(4)?),
+        state: row.get(5)?,
+        progress: row.get(6)?,
+        result: row.get::<_, Option<String>>(7)?.map(json),
+        error: row.get(8)?,
+        created_at: row.get(9)?,
+        started_at: row.get(10)?,
+        finished_at: row.get(11)?,
+    })
+}
+
+/// Admin jobs
+impl SQLiteMetadataStore {
+    pub fn insert_job(&self, kind: &str, user_id: &str, bucket: &str, params: &serde_json::Value, state: &str, created_at: &str) -> Result<i64, Error> {
+        let conn = timed_conn("insert_job", user_id, bucket);
         conn.execute(
-            "DELETE FROM multipart_uploads WHERE bucket = ?1 AND key = ?2 AND status = 'completed'",
-            params![bucket, key],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+            "INSERT INTO jobs (kind, user_id, bucket, params, state, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![kind, user_id, bucket, params.to_string(), state, created_at],
+        ).map_err(db_error)?;
+        Ok(conn.last_insert_rowid())
     }
 
-    pub fn list_bucket_multipart_uploads(&self, bucket: &str) -> Result<Vec<MultipartUploadRow>, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT upload_id, user_id, bucket, key, content_type, metadata_json,
-                    initiated_at, status, final_etag, checksum_algorithm, checksum_type,
-                    object_lock_mode, object_lock_retain_until, object_lock_legal_hold
-             FROM multipart_uploads
-             WHERE bucket = ?1 AND status = 'in_progress'
-             ORDER BY key, initiated_at",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        let rows = stmt.query_map(params![bucket], |row| {
-            Ok(MultipartUploadRow {
-                upload_id: row.get(0)?,
-                user_id: row.get(1)?,
-                bucket: row.get(2)?,
-                key: row.get(3)?,
-                content_type: row.get(4)?,
-                metadata_json: row.get::<_, Option<String>>(5)?.unwrap_or_else(|| "{}".to_string()),
-                initiated_at: row.get(6)?,
-                status: row.get::<_, String>(7)?,
-                final_etag: row.get(8)?,
-                checksum_algorithm: row.get::<_, Option<String>>(9)?.unwrap_or_default(),
-                checksum_type: row.get::<_, Option<String>>(10)?.unwrap_or_default(),
-                object_lock_mode: row.get::<_, Option<String>>(11)?.unwrap_or_default(),
-                object_lock_retain_until: row.get::<_, Option<String>>(12)?.unwrap_or_default(),
-                object_lock_legal_hold: row.get::<_, Option<String>>(13)?.unwrap_or_default(),
-            })
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
-        let mut result = Vec::new();
-        for row in rows {
-            result.push(row.map_err(actix_web::error::ErrorInternalServerError)?);
-        }
-        Ok(result)
+    pub fn get_job(&self, id: i64) -> Result<Option<JobRecord>, Error> {
+        let conn = timed_conn("get_job", "", "");
+        conn.query_row(
+            &format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS),
+            params![id],
+            job_record,
+        ).optional().map_err(db_error)
     }
 
-    pub fn upsert_multipart_part(
-        &self, upload_id: &str, part_number: i32, etag: &str, size: u64, extents_blob: &[u8],
-        checksum_value: &str,
-    ) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
+    /// Newest first, optionally only those in `state`
+    pub fn list_jobs(&self, state: Option<&str>, limit: usize) -> Result<Vec<JobRecord>, Error> {
+        let conn = timed_conn("list_jobs", "", "");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE ?1 IS NULL OR state = ?1 ORDER BY id DESC LIMIT ?2",
+            JOB_COLUMNS,
+        )).map_err(db_error)?;
+        let rows = stmt.query_map(params![state, limit as i64], job_record).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    pub fn start_job(&self, id: i64, state: &str, started_at: &str) -> Result<(), Error> {
+        let conn = timed_conn("start_job", "", "");
         conn.execute(
-            "INSERT OR REPLACE INTO multipart_parts (upload_id, part_number, etag, size, extents_blob, checksum_value)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![upload_id, part_number, etag, size as i64, extents_blob, checksum_value],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+            "UPDATE jobs SET state = ?2, started_at = ?3 WHERE id = ?1",
+            params![id, state, started_at],
+        ).map_err(db_error)?;
         Ok(())
     }
 
-    pub fn list_multipart_parts(&self, upload_id: &str) -> Result<Vec<MultipartPartRow>, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT part_number, etag, size, extents_blob, checksum_value
-             FROM multipart_parts WHERE upload_id = ?1 ORDER BY part_number",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        let rows = stmt.query_map(params![upload_id], |row| {
-            Ok(MultipartPartRow {
-                part_number: row.get(0)?,
-                etag: row.get(1)?,
-                size: row.get::<_, i64>(2)? as u64,
-                extents_blob: row.get(3)?,
-                checksum_value: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
-            })
-        }).map_err(actix_web::error::ErrorInternalServerError)?;
-        let mut result = Vec::new();
-        for row in rows {
-            result.push(row.map_err(actix_web::error::ErrorInternalServerError)?);
-        }
-        Ok(result)
+    pub fn set_job_progress(&self, id: i64, progress: f64) -> Result<(), Error> {
+        let conn = timed_conn("set_job_progress", "", "");
+        conn.execute("UPDATE jobs SET progress = ?2 WHERE id = ?1", params![id, progress]).map_err(db_error)?;
+        Ok(())
     }
 
-    pub fn delete_parts_for_upload(&self, upload_id: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
+    pub fn finish_job(&self, id: i64, state: &str, progress: f64, result: Option<&serde_json::Value>, error: Option<&str>, finished_at: &str) -> Result<(), Error> {
+        let conn = timed_conn("finish_job", "", "");
         conn.execute(
-            "DELETE FROM multipart_parts WHERE upload_id = ?1",
-            params![upload_id],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+            "UPDATE jobs SET state = ?2, progress = ?3, result = ?4, error = ?5, finished_at = ?6 WHERE id = ?1",
+            params![id, state, progress, result.map(|r| r.to_string()), error, finished_at],
+        ).map_err(db_error)?;
         Ok(())
     }
 
-    pub fn get_parts_manifest(&self, user_id: &str, bucket: &str, key: &str) -> Result<Option<String>, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT parts_manifest FROM objects WHERE user = ?1 AND bucket = ?2 AND key = ?3",
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        let result = stmt.query_row(params![user_id, bucket, key], |row| {
-            row.get::<_, Option<String>>(0)
-        });
-        match result {
-            Ok(v) => Ok(v),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+    /// Move every job still in one of `unfinished` to `state` with `error`; returns how many
+    pub fn abandon_jobs(&self, unfinished: &[&str], state: &str, error: &str, finished_at: &str) -> Result<usize, Error> {
+        let conn = timed_conn("abandon_jobs", "", "");
+        let mut abandoned = 0;
+        for from in unfinished {
+            abandoned += conn.execute(
+                "UPDATE jobs SET state = ?2, error = ?3, finished_at = ?4 WHERE state = ?1",
+                params![from, state, error, finished_at],
+            ).map_err(db_error)?;
         }
+        Ok(abandoned)
     }
+}
 
-    pub fn set_parts_manifest(&self, user_id: &str, bucket: &str, key: &str, manifest: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "UPDATE objects SET parts_manifest = ?1 WHERE user = ?2 AND bucket = ?3 AND key = ?4",
-            params![manifest, user_id, bucket, key],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+/// Row of the `change_log` table
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangeRecord {
+    pub id: i64,
+    pub changed_at: String,
+    /// Admin token description, "admin secret", or "startup"
+    pub actor: String,
+    /// "schema", "config", "maintenance", "log_level", "bucket_access", "bucket_limits", "tier",
+    /// "storage_layout" or "instance"
+    pub kind: String,
+    /// What changed: a column, a bucket as `user/bucket`, or the server
+    pub subject: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+const CHANGE_COLUMNS: &str = "id, changed_at, actor, kind, subject, before_value, after_value";
+
+fn change_record(row: &rusqlite::Row) -> rusqlite::Result<ChangeRecord> {
+    let json = |text: Option<String>| text.map(|t| serde_json::from_str(&t).unwrap_or(serde_json::Value::String(t)));
+    Ok(ChangeRecord {
+        id: row.get(0)?,
+        changed_at: row.get(1)?,
+        actor: row.get(2)?,
+        kind: row.get(3)?,
+        subject: row.get(4)?,
+        before: json(row.get(5)?),
+        after: json(row.get(6)?),
+    })
+}
+
+/// Change log
+impl SQLiteMetadataStore {
+    pub fn insert_change(
+        &self,
+        actor: &str,
+        kind: &str,
+        subject: &str,
+        before: Option<&serde_json::Value>,
+        after: Option<&serde_json::Value>,
+    ) -> Result<i64, Error> {
+        let conn = timed_conn("insert_change", "", "");
+        insert_change(&conn, &change_time(), actor, kind, subject, before, after).map_err(db_error)
     }
 
-    // --- Object Lock ---
+    /// Oldest first, from `since` (as stored in `changed_at`) if given
+    pub fn list_changes(&self, since: Option<&str>, limit: usize) -> Result<Vec<ChangeRecord>, Error> {
+        let conn = timed_conn("list_changes", "", "");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM change_log WHERE ?1 IS NULL OR changed_at >= ?1 ORDER BY id LIMIT ?2",
+            CHANGE_COLUMNS,
+        )).map_err(db_error)?;
+        let rows = stmt.query_map(params![since, limit as i64], change_record).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
 
-    pub fn get_bucket_object_lock_enabled(&self, bucket: &str) -> Result<bool, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let result: rusqlite::Result<i64> = conn.query_row(
-            "SELECT object_lock_enabled FROM buckets WHERE name = ?1",
-            params![bucket],
-            |row| row.get(0),
-        );
-        match result {
-            Ok(v) => Ok(v != 0),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
-        }
+    /// Newest entry of `kind`
+    pub fn last_change(&self, kind: &str) -> Result<Option<ChangeRecord>, Error> {
+        let conn = timed_conn("last_change", "", "");
+        conn.query_row(
+            &format!("SELECT {} FROM change_log WHERE kind = ?1 ORDER BY id DESC LIMIT 1", CHANGE_COLUMNS),
+            params![kind],
+            change_record,
+        ).optional().map_err(db_error)
     }
+}
 
-    pub fn set_bucket_object_lock_enabled(&self, bucket: &str, enabled: bool) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
+/// An operation the S3 mirror has not applied to its remote yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorPendingRow {
+    pub id: i64,
+    pub user_id: String,
+    pub bucket: String,
+    /// `put` (one range, copied from the local store) or `delete`
+    pub kind: String,
+    pub ranges: Vec<(u64, u64)>,
+    /// Times the mirror gave up on it
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Mirror journal
+impl SQLiteMetadataStore {
+    /// Record an operation queued for `remote`; returns its id
+    pub fn insert_mirror_pending(&self, remote: &str, user_id: &str, bucket: &str, kind: &str, ranges: &[(u64, u64)]) -> Result<i64, Error> {
+        let ranges = crate::util::serializer::encode_extents(ranges)?;
+        let conn = timed_conn("insert_mirror_pending", user_id, bucket);
         conn.execute(
-            "UPDATE buckets SET object_lock_enabled = ?1 WHERE name = ?2",
-            params![if enabled { 1i64 } else { 0i64 }, bucket],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+            "INSERT INTO mirror_pending (remote, user_id, bucket, kind, ranges) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![remote, user_id, bucket, kind, ranges],
+        ).map_err(db_error)?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Forget an operation the remote applied
+    pub fn delete_mirror_pending(&self, id: i64) -> Result<(), Error> {
+        let conn = timed_conn("delete_mirror_pending", "", "");
+        conn.execute("DELETE FROM mirror_pending WHERE id = ?1", params![id]).map_err(db_error)?;
         Ok(())
     }
 
-    pub fn create_bucket_with_lock(&self, user_id: &str, bucket: &str, lock_enabled: bool) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let lock_val = if lock_enabled { 1i64 } else { 0i64 };
-        let versioning = if lock_enabled { "enabled" } else { "disabled" };
+    /// Count that the mirror gave up on an operation, which stays for the next start
+    pub fn record_mirror_failure(&self, id: i64, error: &str) -> Result<(), Error> {
+        let conn = timed_conn("record_mirror_failure", "", "");
         conn.execute(
-            "INSERT OR IGNORE INTO buckets (user, name, object_lock_enabled, versioning_state) VALUES (?1, ?2, ?3, ?4)",
-            params![user_id, bucket, lock_val, versioning],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
+            "UPDATE mirror_pending SET attempts = attempts + 1, last_error = ?2 WHERE id = ?1",
+            params![id, error],
+        ).map_err(db_error)?;
         Ok(())
     }
 
-    /// Returns None if no config set; Some((mode, days, years)) otherwise.
-    pub fn get_object_lock_config(&self, bucket: &str) -> Result<Option<(String, Option<i64>, Option<i64>)>, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let result = conn.query_row(
-            "SELECT mode, days, years FROM object_lock_config WHERE bucket = ?1",
-            params![bucket],
-            |row| Ok((
-                row.get::<_, String>(0)?,
-                row.get::<_, Option<i64>>(1)?,
-                row.get::<_, Option<i64>>(2)?,
-            )),
-        );
-        match result {
-            Ok(r) => Ok(Some(r)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+    /// Operations recorded for `remote`, in the order they were queued
+    pub fn list_mirror_pending(&self, remote: &str) -> Result<Vec<MirrorPendingRow>, Error> {
+        let conn = timed_conn("list_mirror_pending", "", "");
+        let mut stmt = conn.prepare(
+            "SELECT id, user_id, bucket, kind, ranges, attempts, last_error FROM mirror_pending WHERE remote = ?1 ORDER BY id",
+        ).map_err(db_error)?;
+        let rows = stmt.query_map(params![remote], |row| Ok((
+            MirrorPendingRow {
+                id: row.get(0)?,
+                user_id: row.get(1)?,
+                bucket: row.get(2)?,
+                kind: row.get(3)?,
+                ranges: Vec::new(),
+                attempts: row.get(5)?,
+                last_error: row.get(6)?,
+            },
+            row.get::<_, Vec<u8>>(4)?,
+        ))).map_err(db_error)?;
+        let mut pending = Vec::new();
+        for row in rows {
+            let (mut row, ranges) = row.map_err(db_error)?;
+            row.ranges = crate::util::serializer::decode_extents(&ranges)?;
+            pending.push(row);
         }
+        Ok(pending)
     }
+}
 
-    pub fn put_object_lock_config(&self, bucket: &str, mode: &str, days: Option<i64>, years: Option<i64>) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "INSERT OR REPLACE INTO object_lock_config (bucket, mode, days, years) VALUES (?1, ?2, ?3, ?4)",
-            params![bucket, mode, days, years],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
-    }
+/// Outcome of `PRAGMA wal_checkpoint(TRUNCATE)`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct CheckpointResult {
+    /// The checkpoint could not finish because of other readers or writers
+    pub busy: bool,
+    /// Frames in the WAL before the checkpoint
+    pub wal_frames: i64,
+    /// Frames copied back into the database file
+    pub checkpointed_frames: i64,
+}
 
-    pub fn get_object_lock(&self, bucket: &str, key: &str, version_id: &str) -> Result<Option<ObjectLockRow>, Error> {
-        let conn = DB_CONN.lock().unwrap();
-        let result = conn.query_row(
-            "SELECT mode, retain_until_date, legal_hold FROM object_lock WHERE bucket = ?1 AND key = ?2 AND version_id = ?3",
-            params![bucket, key, version_id],
-            |row| Ok(ObjectLockRow {
-                mode: row.get(0)?,
-                retain_until_date: row.get(1)?,
-                legal_hold: row.get::<_, String>(2).unwrap_or_else(|_| "OFF".to_string()),
-            }),
-        );
-        match result {
-            Ok(r) => Ok(Some(r)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+/// Page usage of the database file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PageStats {
+    pub page_size: i64,
+    pub page_count: i64,
+    pub freelist_count: i64,
+    /// Whether `PRAGMA incremental_vacuum` can reclaim free pages
+    pub incremental_vacuum: bool,
+}
+
+impl PageStats {
+    /// Share of the file that is free pages
+    pub fn freelist_ratio(&self) -> f64 {
+        if self.page_count == 0 {
+            return 0.0;
         }
+        self.freelist_count as f64 / self.page_count as f64
     }
+}
 
-    pub fn put_object_lock(
-        &self, bucket: &str, key: &str, version_id: &str,
-        mode: Option<&str>, retain_until_date: Option<&str>, legal_hold: Option<&str>,
-    ) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        // INSERT uses COALESCE(?6, 'OFF') so a fresh row defaults legal_hold to 'OFF'.
-        // ON CONFLICT uses COALESCE(?6, legal_hold) so None (NULL) preserves the existing value.
-        conn.execute(
-            "INSERT INTO object_lock (bucket, key, version_id, mode, retain_until_date, legal_hold)
-             VALUES (?1, ?2, ?3, ?4, ?5, COALESCE(?6, 'OFF'))
-             ON CONFLICT(bucket, key, version_id) DO UPDATE SET
-               mode = COALESCE(?4, mode),
-               retain_until_date = COALESCE(?5, retain_until_date),
-               legal_hold = COALESCE(?6, legal_hold)",
-            params![bucket, key, version_id, mode, retain_until_date, legal_hold],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+/// Database maintenance
+impl SQLiteMetadataStore {
+    pub fn wal_checkpoint(&self) -> Result<CheckpointResult, Error> {
+        let conn = timed_conn("wal_checkpoint", "", "");
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| Ok(CheckpointResult {
+            busy: row.get::<_, i64>(0)? != 0,
+            wal_frames: row.get(1)?,
+            checkpointed_frames: row.get(2)?,
+        })).map_err(db_error)
     }
 
-    pub fn set_object_legal_hold(&self, bucket: &str, key: &str, version_id: &str, status: &str) -> Result<(), Error> {
-        let conn = DB_CONN.lock().unwrap();
-        conn.execute(
-            "INSERT INTO object_lock (bucket, key, version_id, legal_hold)
-             VALUES (?1, ?2, ?3, ?4)
-             ON CONFLICT(bucket, key, version_id) DO UPDATE SET legal_hold = ?4",
-            params![bucket, key, version_id, status],
-        ).map_err(actix_web::error::ErrorInternalServerError)?;
-        Ok(())
+    pub fn page_stats(&self) -> Result<PageStats, Error> {
+        let conn = timed_conn("page_stats", "", "");
+        let pragma = |name: &str| conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get::<_, i64>(0));
+        Ok(PageStats {
+            page_size: pragma("page_size").map_err(db_error)?,
+            page_count: pragma("page_count").map_err(db_error)?,
+            freelist_count: pragma("freelist_count").map_err(db_error)?,
+            // 2 = INCREMENTAL
+            incremental_vacuum: pragma("auto_vacuum").map_err(db_error)? == 2,
+        })
     }
 
-    /// Returns (blocked_by_retention, blocked_by_legal_hold).
-    /// bypass_governance: true allows overriding GOVERNANCE retention.
-    pub fn check_object_lock_protection(
-        &self, bucket: &str, key: &str, version_id: &str, bypass_governance: bool,
-    ) -> Result<(bool, bool), Error> {
-        let row = match self.get_object_lock(bucket, key, version_id)? {
-            Some(r) => r,
-            None => return Ok((false, false)),
-        };
-        let legal_hold_blocked = row.legal_hold == "ON";
-        let retention_blocked = match (&row.mode, &row.retain_until_date) {
-            (Some(mode), Some(until)) => {
-                let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-                let still_locked = until.as_str() > now.as_str();
-                if still_locked {
-                    match mode.as_str() {
-                        "COMPLIANCE" => true,
-                        "GOVERNANCE" => !bypass_governance,
-                        _ => false,
-                    }
-                } else {
-                    false
-                }
-            }
-            _ => false,
-        };
-        Ok((retention_blocked, legal_hold_blocked))
+    /// Return up to `pages` free pages to the filesystem. Kept short so the connection is
+    /// released between steps.
+    pub fn incremental_vacuum(&self, pages: u32) -> Result<(), Error> {
+        let conn = timed_conn("incremental_vacuum", "", "");
+        conn.execute_batch(&format!("PRAGMA incremental_vacuum({})", pages)).map_err(db_error)
+    }
+
+    /// Sizes in bytes of the database file and its WAL (0 when absent)
+    pub fn file_sizes(&self) -> (u64, u64) {
+        let db_path = get_db_path();
+        let mut wal_path = db_path.clone().into_os_string();
+        wal_path.push("-wal");
+        let size = |path: &Path| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        (size(&db_path), size(Path::new(&wal_path)))
     }
 }
 
@@ -1546,6 +5188,35 @@ mod tests {
         assert!(!store.object_exists(user_id, "default", new_object_id).unwrap());
     }
 
+    #[test]
+    fn test_legacy_chunk_blob_read_and_upgraded_on_update() {
+        use crate::util::serializer::{self, VERSION_1, VERSION_2};
+        let store = SQLiteMetadataStore::new();
+        let (user_id, object_id) = ("test_user_legacy_blob", "legacy_blob_object");
+        let stored = || -> Vec<u8> {
+            timed_conn("test", user_id, "default").query_row(
+                "SELECT offset_size_list FROM objects WHERE user = ?1 AND key = ?2",
+                params![user_id, object_id], |row| row.get(0)).unwrap()
+        };
+
+        store.put_metadata(user_id, "default", object_id, &Metadata::from_offset_size_list(vec![(1, 1)])).unwrap();
+        // Rewrite the row as the pre-versioning serializer stored it: bincode of the extents
+        let legacy: Vec<u8> = [2u64, 0, 10, 10, 5].iter().flat_map(|v| v.to_le_bytes()).collect();
+        timed_conn("test", user_id, "default").execute(
+            "UPDATE objects SET offset_size_list = ?1 WHERE user = ?2 AND key = ?3",
+            params![legacy, user_id, object_id]).unwrap();
+        assert_eq!(serializer::version(&stored()), Some(VERSION_1));
+
+        let mut metadata = store.get_metadata(user_id, "default", object_id).unwrap();
+        assert_eq!(metadata.to_offset_size_list(), vec![(0, 10), (10, 5)]);
+
+        metadata.chunks[1].checksum = Some(42);
+        store.update_metadata(user_id, "default", object_id, &metadata).unwrap();
+        assert_eq!(serializer::version(&stored()), Some(VERSION_2));
+        assert_eq!(store.get_metadata(user_id, "default", object_id).unwrap().chunks, metadata.chunks);
+        store.delete_metadata(user_id, "default", object_id).unwrap();
+    }
+
     #[test]
     fn test_bucket_lifecycle() {
         let store = SQLiteMetadataStore::new();
@@ -1563,4 +5234,70 @@ mod tests {
         store.delete_bucket(user_id, bucket).unwrap();
         assert!(!store.bucket_exists(user_id, bucket).unwrap());
     }
+
+    #[test]
+    fn test_busy_database_maps_to_try_again() {
+        let dir = std::env::temp_dir().join(format!("warpdrive_busy_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("busy.sqlite");
+        let config = SqliteConfig { busy_timeout: std::time::Duration::from_millis(50), ..SqliteConfig::default() };
+
+        let setup = open_connection(&path, &config).unwrap();
+        setup.execute("CREATE TABLE IF NOT EXISTS t (v INTEGER)", []).unwrap();
+
+        // Hold an exclusive write lock from another thread until the contender has failed
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+        let holder_path = path.clone();
+        let holder = std::thread::spawn(move || {
+            let conn = Connection::open(&holder_path).unwrap();
+            conn.execute_batch("BEGIN EXCLUSIVE").unwrap();
+            locked_tx.send(()).unwrap();
+            done_rx.recv().unwrap();
+            conn.execute_batch("ROLLBACK").unwrap();
+        });
+        locked_rx.recv().unwrap();
+
+        let err = setup.execute("INSERT INTO t (v) VALUES (1)", []).map_err(db_error).unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(metrics::counter_value("warpdrive_sqlite_busy_errors_total", &[("op", "unknown")]) >= 1);
+
+        done_tx.send(()).unwrap();
+        holder.join().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_performance_pragmas_and_warm_up() {
+        let dir = std::env::temp_dir().join(format!("warpdrive_warmup_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("warmup.sqlite");
+        let config = SqliteConfig {
+            mmap_size: Some(64 << 20),
+            cache_size: Some(-8192),
+            temp_store_memory: true,
+            ..SqliteConfig::default()
+        };
+        let conn = open_connection(&path, &config).unwrap();
+        let pragma = |name: &str| conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!(pragma("mmap_size"), 64 << 20);
+        assert_eq!(pragma("cache_size"), -8192);
+        assert_eq!(pragma("temp_store"), 2);
+
+        conn.execute_batch(
+            "CREATE TABLE objects (id INTEGER PRIMARY KEY, user TEXT, bucket TEXT, key TEXT,
+                                   version_id TEXT, UNIQUE(user, bucket, key, version_id))",
+        ).unwrap();
+        for i in 0..100 {
+            conn.execute("INSERT INTO objects (user, bucket, key, version_id) VALUES ('u', 'b', ?1, '')",
+                params![format!("key-{:03}", i)]).unwrap();
+        }
+        let report = warm_up(&conn, Duration::from_secs(60)).unwrap();
+        assert_eq!((report.rows, report.complete), (100, true));
+        // Out of time after the first entry
+        let report = warm_up(&conn, Duration::ZERO).unwrap();
+        assert_eq!((report.rows, report.complete), (1, false));
+        drop(conn);
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
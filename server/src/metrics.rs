@@ -0,0 +1,282 @@
+//! Minimal in-process metrics registry exported in Prometheus text format at `GET /metrics`.
+//!
+//! Counters, gauges and histograms are keyed by name plus a fixed label set. Everything lives behind
+//! one mutex; the hot paths only touch it once per observation.
+//!
+//! Series about requests are labeled by the route template the request matched (see
+//! [`route_label`]), never by its path: a label per key would make a series per object.
+//! [`track_requests`] counts every request as `warpdrive_http_requests_total` and times it in
+//! `warpdrive_http_request_duration_seconds`.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest, HttpResponse};
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Latency buckets in seconds, for histograms observed with [`observe_histogram`].
+const HISTOGRAM_BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+type Labels = Vec<(String, String)>;
+
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<String, BTreeMap<Labels, u64>>,
+    gauges: BTreeMap<String, BTreeMap<Labels, f64>>,
+    histograms: BTreeMap<String, BTreeMap<Labels, Histogram>>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+fn owned_labels(labels: &[(&str, &str)]) -> Labels {
+    labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+fn format_labels(labels: &Labels, extra: Option<(&str, String)>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    if let Some((k, v)) = extra {
+        parts.push(format!("{}=\"{}\"", k, v));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+/// Add one to the counter `name` with the given labels.
+pub fn inc_counter(name: &str, labels: &[(&str, &str)]) {
+    inc_counter_by(name, labels, 1);
+}
+
+/// Add `amount` to the counter `name` with the given labels.
+pub fn inc_counter_by(name: &str, labels: &[(&str, &str)], amount: u64) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    *registry
+        .counters
+        .entry(name.to_string())
+        .or_default()
+        .entry(owned_labels(labels))
+        .or_default() += amount;
+}
+
+/// Set the gauge `name` with the given labels to `value`.
+pub fn set_gauge(name: &str, labels: &[(&str, &str)], value: f64) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry
+        .gauges
+        .entry(name.to_string())
+        .or_default()
+        .insert(owned_labels(labels), value);
+}
+
+/// Replace every series of the gauge `name` with `series`, dropping label sets not listed.
+pub fn replace_gauge_series(name: &str, series: &[(Vec<(&str, &str)>, f64)]) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.gauges.insert(
+        name.to_string(),
+        series.iter().map(|(labels, value)| (owned_labels(labels), *value)).collect(),
+    );
+}
+
+/// Record `value` (seconds) into the histogram `name` with the given labels.
+pub fn observe_histogram(name: &str, labels: &[(&str, &str)], value: f64) {
+    observe_histogram_in(name, labels, &HISTOGRAM_BUCKETS, value);
+}
+
+/// Record `value` into the histogram `name` with the given labels, bucketed by `bounds`
+/// (ascending upper bounds). A series keeps the bounds of its first observation.
+pub fn observe_histogram_in(name: &str, labels: &[(&str, &str)], bounds: &'static [f64], value: f64) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let histogram = registry
+        .histograms
+        .entry(name.to_string())
+        .or_default()
+        .entry(owned_labels(labels))
+        .or_insert_with(|| Histogram { bounds, buckets: vec![0; bounds.len()], count: 0, sum: 0.0 });
+    for (i, bound) in histogram.bounds.iter().enumerate() {
+        if value <= *bound {
+            histogram.buckets[i] += 1;
+        }
+    }
+    histogram.count += 1;
+    histogram.sum += value;
+}
+
+/// Current value of a counter, or 0 if it has never been incremented.
+pub fn counter_value(name: &str, labels: &[(&str, &str)]) -> u64 {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry
+        .counters
+        .get(name)
+        .and_then(|series| series.get(&owned_labels(labels)))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Label of a request that matched no route
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// The route template `pattern` with the regex of each segment dropped, e.g. `/put/{key}` for
+/// `/put/{key:.*}`; [`UNMATCHED_ROUTE`] without one.
+pub fn route_template(pattern: Option<&str>) -> String {
+    let Some(pattern) = pattern else { return UNMATCHED_ROUTE.to_string() };
+    let mut template = String::with_capacity(pattern.len());
+    // Depth of braces inside a segment's regex, e.g. `{id:[0-9]{4}}`
+    let (mut depth, mut in_regex) = (0, false);
+    for c in pattern.chars() {
+        match c {
+            '{' => {
+                depth += 1;
+                if depth > 1 { continue }
+            }
+            '}' => {
+                depth -= 1;
+                if depth > 0 { continue }
+                in_regex = false;
+            }
+            ':' if depth == 1 => in_regex = true,
+            _ => {}
+        }
+        if !in_regex {
+            template.push(c);
+        }
+    }
+    template
+}
+
+/// Label for the route `req` matched: its [`route_template`], or [`UNMATCHED_ROUTE`] before
+/// routing or when nothing matched
+pub fn route_label(req: &HttpRequest) -> String {
+    route_template(req.match_pattern().as_deref())
+}
+
+/// Method label; methods outside the standard set share `OTHER`
+fn method_label(method: &Method) -> &str {
+    match *method {
+        Method::GET | Method::HEAD | Method::POST | Method::PUT | Method::DELETE
+        | Method::OPTIONS | Method::PATCH => method.as_str(),
+        _ => "OTHER",
+    }
+}
+
+/// Count and time every request by method, route template and status; use with
+/// `middleware::from_fn`.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let method = method_label(req.method()).to_string();
+    let res = next.call(req).await;
+    let (route, status) = match &res {
+        Ok(res) => (route_label(res.request()), res.status().as_u16().to_string()),
+        Err(e) => (UNMATCHED_ROUTE.to_string(), e.as_response_error().status_code().as_u16().to_string()),
+    };
+    inc_counter("warpdrive_http_requests_total", &[("method", &method), ("route", &route), ("status", &status)]);
+    observe_histogram("warpdrive_http_request_duration_seconds", &[("method", &method), ("route", &route)],
+                      started.elapsed().as_secs_f64());
+    res
+}
+
+/// Render every registered metric in Prometheus text exposition format.
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = String::new();
+    for (name, series) in &registry.counters {
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (labels, value) in series {
+            let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+        }
+    }
+    for (name, series) in &registry.gauges {
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (labels, value) in series {
+            let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+        }
+    }
+    for (name, series) in &registry.histograms {
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (labels, h) in series {
+            for (i, bound) in h.bounds.iter().enumerate() {
+                let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, Some(("le", bound.to_string()))), h.buckets[i]);
+            }
+            let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, Some(("le", "+Inf".to_string()))), h.count);
+            let _ = writeln!(out, "{}_sum{} {}", name, format_labels(labels, None), h.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, format_labels(labels, None), h.count);
+        }
+    }
+    out
+}
+
+/// GET /metrics
+pub async fn metrics_handler() -> HttpResponse {
+    // The deletion backlog changes with every delete, not just on worker passes
+    crate::service::deletion_worker::export_pending_bytes();
+    crate::service::deletion_spill::export_metrics();
+    crate::service::multipart_limits::export_in_progress();
+    crate::storage::instrumented_store::export();
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_render() {
+        inc_counter("test_metrics_counter_total", &[("op", "a")]);
+        inc_counter("test_metrics_counter_total", &[("op", "a")]);
+        assert_eq!(counter_value("test_metrics_counter_total", &[("op", "a")]), 2);
+        let text = render();
+        assert!(text.contains("# TYPE test_metrics_counter_total counter"));
+        assert!(text.contains("test_metrics_counter_total{op=\"a\"} 2"));
+    }
+
+    #[test]
+    fn test_gauge_render() {
+        set_gauge("test_metrics_depth", &[], 3.0);
+        set_gauge("test_metrics_depth", &[], 1.0);
+        let text = render();
+        assert!(text.contains("# TYPE test_metrics_depth gauge"));
+        assert!(text.contains("test_metrics_depth 1\n"));
+    }
+
+    #[test]
+    fn test_histogram_render() {
+        observe_histogram("test_metrics_latency_seconds", &[("op", "b")], 0.02);
+        let text = render();
+        assert!(text.contains("test_metrics_latency_seconds_bucket{op=\"b\",le=\"0.01\"} 0"));
+        assert!(text.contains("test_metrics_latency_seconds_bucket{op=\"b\",le=\"0.025\"} 1"));
+        assert!(text.contains("test_metrics_latency_seconds_bucket{op=\"b\",le=\"+Inf\"} 1"));
+        assert!(text.contains("test_metrics_latency_seconds_count{op=\"b\"} 1"));
+    }
+
+    #[test]
+    fn test_route_template() {
+        assert_eq!(route_template(Some("/put/{key:.*}")), "/put/{key}");
+        assert_eq!(route_template(Some("/s3/{bucket}/{key:.+}")), "/s3/{bucket}/{key}");
+        assert_eq!(route_template(Some("/upload/{key:.*}/{session:ups-[0-9a-f]{32}}/complete")), "/upload/{key}/{session}/complete");
+        assert_eq!(route_template(Some("/metrics")), "/metrics");
+        assert_eq!(route_template(None), UNMATCHED_ROUTE);
+    }
+}
@@ -1,12 +1,16 @@
 // S3 Authentication module
-use actix_web::{HttpRequest, HttpResponse, Error, error::{ErrorBadRequest, ErrorForbidden, ErrorServiceUnavailable, ErrorUnauthorized}};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Error, error::{ErrorBadRequest, ErrorServiceUnavailable, ErrorUnauthorized}};
 use lazy_static::lazy_static;
 use log::{debug, warn};
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::RwLock;
 use std::time::{Duration, Instant};
 
+use crate::logging;
+use crate::s3::middleware::VirtualHostPath;
+use crate::service::{bucket_access, user_limits};
+
 lazy_static! {
     static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
         // Protect Actix workers from hanging indefinitely when Console is slow/unreachable.
@@ -39,16 +43,52 @@ struct S3CredentialsResponse {
     registered_buckets: Vec<String>,
 }
 
-/// S3 Authentication result
+/// Buckets a credential may use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BucketGrant {
+    /// The admin credential reaches every bucket
+    All,
+    /// Console-registered buckets of the key's owner (snapshot from the credential cache), or the
+    /// public bucket an anonymous request reads
+    Only(BTreeSet<String>),
+}
+
+impl BucketGrant {
+    pub fn allows(&self, bucket: &str) -> bool {
+        match self {
+            BucketGrant::All => true,
+            BucketGrant::Only(buckets) => buckets.contains(bucket),
+        }
+    }
+}
+
+/// What a request does to the bucket it is authorized for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum S3Operation {
+    Read,
+    Write,
+}
+
+impl S3Operation {
+    /// GET and HEAD read; every other method writes
+    pub fn of(method: &actix_web::http::Method) -> Self {
+        if method == actix_web::http::Method::GET || method == actix_web::http::Method::HEAD {
+            S3Operation::Read
+        } else {
+            S3Operation::Write
+        }
+    }
+}
+
+/// S3 Authentication result: who signed the request and which buckets the credential covers.
+/// The bucket a request works on always comes from its routed path; see [`authorize`].
 #[derive(Debug)]
 pub struct S3AuthResult {
     pub access_key: String,
     pub user_id: String,
-    pub bucket: String,
-    /// Snapshot of Console-registered buckets for this key (from credential cache at refresh time).
-    pub allowed_buckets: Vec<String>,
-    /// True for the hardcoded admin user — skips bucket membership checks so any bucket is reachable.
-    pub allow_all_buckets: bool,
+    pub buckets: BucketGrant,
+    /// Unsigned GET/HEAD of a public-read bucket, acting as its owner
+    pub anonymous: bool,
 }
 
 /// Returns (base_url, service_secret, cache_ttl_secs).
@@ -229,13 +269,13 @@ fn parse_authorization_header_full(auth_header: &str) -> Result<ParsedAuthHeader
     let credential_end = credential_part.find(',').unwrap_or(credential_part.len());
     let credential = credential_part[..credential_end].trim();
     let parts: Vec<&str> = credential.splitn(2, '/').collect();
-    let access_key = parts.get(0).ok_or_else(|| ErrorUnauthorized("Invalid Credential"))?.trim().to_string();
+    let access_key = parts.first().ok_or_else(|| ErrorUnauthorized("Invalid Credential"))?.trim().to_string();
     if access_key.is_empty() {
         return Err(ErrorUnauthorized("Invalid Credential: access key is empty"));
     }
     let scope = parts.get(1).ok_or_else(|| ErrorUnauthorized("Invalid Credential"))?;
     let scope_parts: Vec<&str> = scope.splitn(4, '/').collect();
-    let date = scope_parts.get(0).ok_or_else(|| ErrorUnauthorized("Invalid Credential"))?.to_string();
+    let date = scope_parts.first().ok_or_else(|| ErrorUnauthorized("Invalid Credential"))?.to_string();
     let region = scope_parts.get(1).unwrap_or(&"us-east-1").to_string();
     let service = scope_parts.get(2).unwrap_or(&"s3").to_string();
 
@@ -293,7 +333,8 @@ fn verify_sigv4(
         .unwrap_or(EMPTY_PAYLOAD_HASH);
 
     let method = req.method().as_str();
-    let path = req.path();
+    let signed_path = signed_path(req);
+    let path = signed_path.as_str();
     let query = req.query_string();
     let canonical_uri = path;
     let canonical_query_string = if query.is_empty() {
@@ -420,7 +461,7 @@ fn verify_sigv4(
             canonical_uri,
             canonical_query_string.len()
         );
-        return Err(ErrorUnauthorized("Signature does not match"));
+        return Err(s3_signature_mismatch());
     }
     Ok(())
 }
@@ -457,6 +498,14 @@ fn percent_encode_uri(s: &str) -> String {
     out
 }
 
+/// Path the client signed: the one it sent, even if a virtual-host rewrite changed it.
+fn signed_path(req: &HttpRequest) -> String {
+    match req.extensions().get::<VirtualHostPath>() {
+        Some(original) => original.0.clone(),
+        None => req.path().to_string(),
+    }
+}
+
 /// True only for AWS ListBuckets: GET/HEAD at the S3 root (no bucket in path).
 fn is_list_buckets_request(req: &HttpRequest) -> bool {
     let method = req.method();
@@ -468,20 +517,11 @@ fn is_list_buckets_request(req: &HttpRequest) -> bool {
     p == "/s3" || p == "/s3/" || p == "/"
 }
 
-/// Extract bucket from request path.
-/// Handles both the warpdrive-prefixed form (/s3/{bucket}/...) and the standard
-/// root form (/{bucket}/...) that Ceph s3-tests and boto3 clients use by default.
-fn extract_bucket_from_path(req: &HttpRequest) -> Result<String, Error> {
-    let path = req.path();
-    let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
-
-    // Skip the "s3" prefix if present (warpdrive path-style: /s3/{bucket}/...)
-    let bucket_idx = if parts.first().copied() == Some("s3") { 1 } else { 0 };
-
-    if parts.len() <= bucket_idx || parts[bucket_idx].is_empty() {
-        return Ok(String::new()); // list-buckets call
-    }
-    Ok(parts[bucket_idx].to_string())
+/// Bucket of the routed path: the `{bucket}` segment of both the warpdrive-prefixed form
+/// (/s3/{bucket}/...) and the root form (/{bucket}/...) that Ceph s3-tests and boto3 clients
+/// use by default. Empty for list-buckets and for requests that were not routed.
+pub fn routed_bucket(req: &HttpRequest) -> String {
+    req.match_info().get("bucket").unwrap_or_default().to_string()
 }
 
 // ---------------------------------------------------------------------------
@@ -517,7 +557,7 @@ fn parse_presigned_v4(query: &HashMap<String, String>) -> Result<ParsedPresigned
     let (access_key, scope) = credential.split_once('/')
         .ok_or_else(|| ErrorUnauthorized("Invalid X-Amz-Credential"))?;
     let scope_parts: Vec<&str> = scope.splitn(4, '/').collect();
-    let date = scope_parts.get(0).ok_or_else(|| ErrorUnauthorized("Invalid credential scope"))?.to_string();
+    let date = scope_parts.first().ok_or_else(|| ErrorUnauthorized("Invalid credential scope"))?.to_string();
     let region = scope_parts.get(1).unwrap_or(&"us-east-1").to_string();
     let service = scope_parts.get(2).unwrap_or(&"s3").to_string();
 
@@ -573,7 +613,8 @@ fn verify_sigv4_presigned(req: &HttpRequest, secret_key: &str, parsed: &ParsedPr
     type HmacSha256 = Hmac<Sha256>;
 
     let method = req.method().as_str();
-    let canonical_uri = req.path();
+    let signed_path = signed_path(req);
+    let canonical_uri = signed_path.as_str();
 
     // Canonical query string: all params except X-Amz-Signature, sorted
     let mut pairs: Vec<(String, String)> = req.query_string()
@@ -654,7 +695,7 @@ fn verify_sigv4_presigned(req: &HttpRequest, secret_key: &str, parsed: &ParsedPr
     })?;
     if mac.verify_slice(&provided_sig).is_err() {
         warn!("Presigned V4 signature mismatch (uri={}, query_len={})", canonical_uri, canonical_query_string.len());
-        return Err(ErrorUnauthorized("Signature does not match"));
+        return Err(s3_signature_mismatch());
     }
     Ok(())
 }
@@ -663,7 +704,6 @@ async fn authenticate_presigned_v4(req: &HttpRequest, query: &HashMap<String, St
     let parsed = parse_presigned_v4(query)?;
     check_presigned_expiry(&parsed.amz_date, parsed.expires)?;
 
-    let bucket = extract_bucket_from_path(req)?;
     let access_key = parsed.access_key.clone();
 
     // Admin bypass
@@ -672,13 +712,12 @@ async fn authenticate_presigned_v4(req: &HttpRequest, query: &HashMap<String, St
     if let (Some(ref aak), Some(ref ask)) = (&admin_access_key, &admin_secret_key) {
         if access_key == *aak {
             verify_sigv4_presigned(req, ask, &parsed)?;
-            debug!("Presigned V4 auth: admin bypass OK bucket={:?}", bucket);
+            debug!("Presigned V4 auth: admin bypass OK");
             return Ok(S3AuthResult {
                 access_key,
                 user_id: "admin".to_string(),
-                bucket,
-                allowed_buckets: vec![],
-                allow_all_buckets: true,
+                buckets: BucketGrant::All,
+                anonymous: false,
             });
         }
     }
@@ -687,37 +726,41 @@ async fn authenticate_presigned_v4(req: &HttpRequest, query: &HashMap<String, St
     let (base_url, service_secret, cache_ttl_secs) = auth_config_from_env();
     let base_url = base_url.ok_or_else(|| ErrorUnauthorized("No authentication method configured"))?;
     let service_secret = service_secret.ok_or_else(|| ErrorUnauthorized("WARPDRIVE_SERVICE_SECRET not set"))?;
-    let (owner_id, secret_key, _, _) =
+    let (owner_id, secret_key, allowed_buckets, _) =
         load_or_refresh_credential_bundle(&access_key, &base_url, &service_secret, cache_ttl_secs).await?;
 
     verify_sigv4_presigned(req, &secret_key, &parsed)?;
-    debug!("Presigned V4 auth: Console OK bucket={:?} user={}", bucket, owner_id);
+    debug!("Presigned V4 auth: Console OK user={}", owner_id);
     Ok(S3AuthResult {
         access_key,
         user_id: owner_id,
-        bucket,
-        allowed_buckets: vec![],
-        allow_all_buckets: false,
+        buckets: BucketGrant::Only(allowed_buckets.into_iter().collect()),
+        anonymous: false,
     })
 }
 
-/// Authenticate S3 request (async).
-///
-/// **Admin bypass:** if `WARPDRIVE_ADMIN_ACCESS_KEY` and `WARPDRIVE_ADMIN_SECRET_KEY` are set and
-/// the request's access key matches, SigV4 is verified against the admin secret without contacting
-/// Vitality Console. The returned result has `allow_all_buckets = true`.
-///
-/// **Console path:** requires `VITALITY_CONSOLE_URL` + `WARPDRIVE_SERVICE_SECRET`. Credential
-/// cache TTL is `S3_AUTH_CACHE_TTL_SECS` (default 300 s).
 fn s3_access_denied(message: &str) -> Error {
+    s3_forbidden("AccessDenied", message)
+}
+
+/// A signature computed with the wrong secret, or over a different request than was sent
+fn s3_signature_mismatch() -> Error {
+    s3_forbidden(
+        "SignatureDoesNotMatch",
+        "The request signature we calculated does not match the signature you provided. Check your key and signing method.",
+    )
+}
+
+/// 403 with an S3 error document, so SDKs surface `code`
+fn s3_forbidden(code: &str, message: &str) -> Error {
     let body = format!(
         "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
          <Error>\n\
-           <Code>AccessDenied</Code>\n\
+           <Code>{}</Code>\n\
            <Message>{}</Message>\n\
            <RequestId>warpdrive</RequestId>\n\
          </Error>",
-        message
+        code, message
     );
     let msg = message.to_string();
     actix_web::error::InternalError::from_response(
@@ -729,11 +772,77 @@ fn s3_access_denied(message: &str) -> Error {
     ).into()
 }
 
+/// Authenticate an S3 request and authorize it for the bucket of its routed path, with the
+/// operation its method implies. The request then counts against the user's concurrency limit
+/// (429 `SlowDown` when full). Handlers touching another bucket too, such as a copy source,
+/// [`authorize`] it themselves.
 pub async fn authenticate_s3_request(req: &HttpRequest) -> Result<S3AuthResult, Error> {
-    // Detect presigned requests before looking for Authorization header
+    let result = authenticate(req).await?;
+    let bucket = routed_bucket(req);
+    if !bucket.is_empty() {
+        authorize(&result, &bucket, S3Operation::of(req.method()))?;
+    }
+    logging::bind_user(&result.user_id);
+    user_limits::admit(req, &result.user_id, true).await?;
+    Ok(result)
+}
+
+/// Whether `auth` may perform `operation` on `bucket`: the bucket must be in the credential's
+/// grant, an anonymous request may only read, and a read-only bucket refuses writes. Denials
+/// are 403 `AccessDenied`.
+pub fn authorize(auth: &S3AuthResult, bucket: &str, operation: S3Operation) -> Result<(), Error> {
+    if !auth.buckets.allows(bucket) {
+        warn!("S3 auth: user {} denied bucket {:?}, not granted to the credential", auth.user_id, bucket);
+        return Err(s3_access_denied("Access Denied"));
+    }
+    if operation == S3Operation::Write {
+        if auth.anonymous {
+            return Err(s3_access_denied("Access Denied"));
+        }
+        if bucket_access::access(&auth.user_id, bucket)?.read_only {
+            warn!("S3 auth: write by {} rejected, bucket {:?} is read-only", auth.user_id, bucket);
+            return Err(s3_access_denied("Bucket is read-only"));
+        }
+    }
+    Ok(())
+}
+
+/// Authenticate S3 request (async), establishing only who is calling.
+///
+/// **Anonymous:** an unsigned GET/HEAD of a public-read bucket acts as the bucket's owner,
+/// granted that bucket alone.
+///
+/// **Admin bypass:** if `WARPDRIVE_ADMIN_ACCESS_KEY` and `WARPDRIVE_ADMIN_SECRET_KEY` are set and
+/// the request's access key matches, SigV4 is verified against the admin secret without contacting
+/// Vitality Console. The admin is granted every bucket.
+///
+/// **Console path:** requires `VITALITY_CONSOLE_URL` + `WARPDRIVE_SERVICE_SECRET`. The key is
+/// granted its owner's Console-registered buckets. Credential cache TTL is
+/// `S3_AUTH_CACHE_TTL_SECS` (default 300 s).
+pub async fn authenticate(req: &HttpRequest) -> Result<S3AuthResult, Error> {
     let query_map = parse_query_map(req);
+    let unsigned = !query_map.contains_key("X-Amz-Algorithm") && !req.headers().contains_key("Authorization");
+    if unsigned && S3Operation::of(req.method()) == S3Operation::Read {
+        let bucket = routed_bucket(req);
+        if !bucket.is_empty() {
+            if let Some(owner) = bucket_access::public_owner(&bucket)? {
+                debug!("S3 auth: anonymous read of public bucket {:?} as owner {}", bucket, owner);
+                return Ok(S3AuthResult {
+                    access_key: String::new(),
+                    user_id: owner,
+                    buckets: BucketGrant::Only(BTreeSet::from([bucket])),
+                    anonymous: true,
+                });
+            }
+        }
+    }
+    authenticate_signed_request(req, &query_map).await
+}
+
+async fn authenticate_signed_request(req: &HttpRequest, query_map: &HashMap<String, String>) -> Result<S3AuthResult, Error> {
+    // Detect presigned requests before looking for Authorization header
     if query_map.contains_key("X-Amz-Algorithm") {
-        return authenticate_presigned_v4(req, &query_map).await;
+        return authenticate_presigned_v4(req, query_map).await;
     }
 
     let auth_header = req
@@ -751,7 +860,7 @@ pub async fn authenticate_s3_request(req: &HttpRequest) -> Result<S3AuthResult,
 
     let parsed = parse_authorization_header_full(auth_header)?;
     let access_key = parsed.access_key.clone();
-    let bucket = extract_bucket_from_path(req)?;
+    let bucket = routed_bucket(req);
 
     if !is_list_buckets_request(req) && bucket.is_empty() {
         warn!(
@@ -780,9 +889,8 @@ pub async fn authenticate_s3_request(req: &HttpRequest) -> Result<S3AuthResult,
             return Ok(S3AuthResult {
                 access_key,
                 user_id: "admin".to_string(),
-                bucket,
-                allowed_buckets: vec![],
-                allow_all_buckets: true,
+                buckets: BucketGrant::All,
+                anonymous: false,
             });
         }
     }
@@ -815,19 +923,6 @@ pub async fn authenticate_s3_request(req: &HttpRequest) -> Result<S3AuthResult,
         allowed_buckets = ab;
     }
 
-    if !bucket.is_empty() && !allowed_buckets.contains(&bucket) {
-        warn!(
-            "S3 auth: FORBIDDEN path_bucket={:?} not in cached Console bucket set {:?}",
-            bucket, allowed_buckets
-        );
-        return Err(ErrorForbidden(
-            "Bucket is not registered for this account in Vitality Console",
-        ));
-    }
-
-    let mut allowed_buckets_vec: Vec<String> = allowed_buckets.iter().cloned().collect();
-    allowed_buckets_vec.sort();
-
     verify_sigv4(req, &secret_key, &parsed)?;
     debug!(
         "S3 auth: Console path OK request_path={} user={} path_bucket={:?}",
@@ -836,9 +931,8 @@ pub async fn authenticate_s3_request(req: &HttpRequest) -> Result<S3AuthResult,
     Ok(S3AuthResult {
         access_key,
         user_id: owner_id,
-        bucket,
-        allowed_buckets: allowed_buckets_vec,
-        allow_all_buckets: false,
+        buckets: BucketGrant::Only(allowed_buckets.into_iter().collect()),
+        anonymous: false,
     })
 }
 
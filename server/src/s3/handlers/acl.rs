@@ -1,7 +1,8 @@
-// ACL stub handlers + validate_bucket_name + validate_object_key.
+// ACL stub handlers + validate_bucket_name + validate_object_key + validate_key_present + validate_new_key_length.
 use actix_web::{HttpRequest, HttpResponse, Error, http::StatusCode};
 
 use super::common::*;
+use crate::service::key_limits::{is_safe_key, KeyLimits};
 
 pub(super) async fn s3_get_object_acl_stub(bucket: &str, key: &str, req: &HttpRequest) -> Result<HttpResponse, Error> {
     use crate::s3::auth::authenticate_s3_request;
@@ -87,13 +88,28 @@ pub(super) fn validate_bucket_name(bucket: &str) -> Result<(), HttpResponse> {
 
 /// Reject keys containing C0/C1 control characters.
 pub(super) fn validate_object_key(key: &str, bucket: &str) -> Result<(), HttpResponse> {
-    if key.chars().any(|c| {
-        let n = c as u32;
-        n < 0x20 || (n >= 0x7F && n <= 0x9F)
-    }) {
+    if !is_safe_key(key) {
         return Err(s3_error(StatusCode::BAD_REQUEST, "InvalidURI",
                             "Couldn't parse the specified URI.",
                             &format!("/{}/{}", bucket, key)));
     }
     Ok(())
 }
+
+/// Reject an empty key: `/{bucket}/` addresses the bucket, never an object named "".
+/// A key ending in `/` (a directory marker) is an ordinary key.
+pub(super) fn validate_key_present(key: &str, bucket: &str) -> Result<(), HttpResponse> {
+    if key.is_empty() {
+        return Err(s3_error(StatusCode::BAD_REQUEST, "InvalidRequest",
+                            "An object key must not be empty.",
+                            &format!("/{}/", bucket)));
+    }
+    Ok(())
+}
+
+/// Reject keys over `MAX_KEY_LENGTH` on the paths that create them.
+pub(super) fn validate_new_key_length(key: &str, bucket: &str) -> Result<(), HttpResponse> {
+    KeyLimits::from_env().check_new_key(key).map_err(|e| {
+        s3_error(StatusCode::BAD_REQUEST, "KeyTooLongError", &e.to_string(), &format!("/{}/{}", bucket, key))
+    })
+}
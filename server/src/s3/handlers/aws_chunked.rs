@@ -0,0 +1,245 @@
+//! `aws-chunked` request bodies
+//!
+//! Streaming uploads (`x-amz-content-sha256: STREAMING-...`, `Content-Encoding: aws-chunked`)
+//! wrap the payload in chunk framing:
+//!
+//! ```text
+//! <hex size>[;chunk-signature=<sig>]\r\n<data>\r\n ... 0[;chunk-signature=<sig>]\r\n
+//! [<trailer>:<value>\r\n ...]\r\n
+//! ```
+//!
+//! The decoder strips the framing so only payload bytes are stored, and collects trailers
+//! such as `x-amz-checksum-crc32` for verification. Chunk signatures are not verified; the
+//! seed request signature is checked by `authenticate_s3_request` as usual.
+
+use actix_web::{HttpRequest, HttpResponse, http::StatusCode};
+
+use super::checksum::ChecksumAlgorithm;
+use super::common::s3_error;
+
+/// Longest chunk header or trailer line accepted
+const MAX_LINE: usize = 4096;
+
+/// `(lowercased name, value)` pairs sent after the final chunk
+pub(super) type Trailers = Vec<(String, String)>;
+
+/// Whether the request body uses `aws-chunked` framing.
+pub(super) fn is_aws_chunked(req: &HttpRequest) -> bool {
+    let encoded = req.headers().get("content-encoding")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').any(|p| p.trim().eq_ignore_ascii_case("aws-chunked")))
+        .unwrap_or(false);
+    let streaming = req.headers().get("x-amz-content-sha256")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("STREAMING-"))
+        .unwrap_or(false);
+    encoded || streaming
+}
+
+/// Payload length announced by the client (`x-amz-decoded-content-length`)
+pub(super) fn decoded_content_length(req: &HttpRequest) -> Option<u64> {
+    req.headers().get("x-amz-decoded-content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum AwsChunkedError {
+    /// Framing could not be parsed
+    Malformed(&'static str),
+    /// Body ended early or does not match `x-amz-decoded-content-length`
+    Incomplete,
+}
+
+impl AwsChunkedError {
+    pub(super) fn to_s3_error(&self, resource: &str) -> HttpResponse {
+        match self {
+            AwsChunkedError::Malformed(msg) => s3_error(StatusCode::BAD_REQUEST, "InvalidRequest", msg, resource),
+            AwsChunkedError::Incomplete => s3_error(StatusCode::BAD_REQUEST, "IncompleteBody",
+                "You did not provide the number of bytes specified by the Content-Length HTTP header", resource),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    /// Reading `<hex size>[;ext]\r\n`
+    Header,
+    /// Payload bytes left in the current chunk
+    Data(u64),
+    /// Expecting the `\r\n` after chunk data
+    DataEnd,
+    /// Reading trailer lines after the final chunk
+    Trailers,
+    Done,
+}
+
+/// Incremental `aws-chunked` decoder; feed body pieces as they arrive.
+pub(super) struct AwsChunkedDecoder {
+    state: State,
+    line: Vec<u8>,
+    decoded: u64,
+    trailers: Trailers,
+}
+
+impl AwsChunkedDecoder {
+    pub(super) fn new() -> Self {
+        Self { state: State::Header, line: Vec::new(), decoded: 0, trailers: Vec::new() }
+    }
+
+    /// Decode the next piece of the body, returning the payload bytes it contained.
+    pub(super) fn feed(&mut self, mut input: &[u8]) -> Result<Vec<u8>, AwsChunkedError> {
+        let mut out = Vec::new();
+        while !input.is_empty() {
+            match self.state {
+                State::Data(remaining) => {
+                    let n = remaining.min(input.len() as u64) as usize;
+                    out.extend_from_slice(&input[..n]);
+                    self.decoded += n as u64;
+                    input = &input[n..];
+                    self.state = if remaining == n as u64 { State::DataEnd } else { State::Data(remaining - n as u64) };
+                }
+                State::Done => return Err(AwsChunkedError::Malformed("Unexpected data after the final chunk")),
+                _ => {
+                    let Some(line) = self.take_line(&mut input)? else { break };
+                    self.on_line(&line)?;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// End of body: check the framing was complete and, if given, the decoded length.
+    pub(super) fn finish(mut self, expected_len: Option<u64>) -> Result<Trailers, AwsChunkedError> {
+        // Some clients omit the blank line that closes an empty trailer section
+        if self.state == State::Trailers && self.line.is_empty() {
+            self.state = State::Done;
+        }
+        if self.state != State::Done || expected_len.is_some_and(|len| len != self.decoded) {
+            return Err(AwsChunkedError::Incomplete);
+        }
+        Ok(self.trailers)
+    }
+
+    /// Accumulate up to and including `\r\n`; returns the line without it once complete.
+    fn take_line(&mut self, input: &mut &[u8]) -> Result<Option<Vec<u8>>, AwsChunkedError> {
+        match input.iter().position(|b| *b == b'\n') {
+            Some(pos) => {
+                self.line.extend_from_slice(&input[..pos]);
+                *input = &input[pos + 1..];
+                if self.line.pop() != Some(b'\r') {
+                    return Err(AwsChunkedError::Malformed("Chunk framing lines must end with CRLF"));
+                }
+                Ok(Some(std::mem::take(&mut self.line)))
+            }
+            None => {
+                self.line.extend_from_slice(input);
+                *input = &[];
+                if self.line.len() > MAX_LINE {
+                    return Err(AwsChunkedError::Malformed("Chunk header is too long"));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn on_line(&mut self, line: &[u8]) -> Result<(), AwsChunkedError> {
+        match self.state {
+            State::Header => {
+                let line = std::str::from_utf8(line).map_err(|_| AwsChunkedError::Malformed("Invalid chunk header"))?;
+                let size = line.split(';').next().unwrap_or("").trim();
+                let size = u64::from_str_radix(size, 16).map_err(|_| AwsChunkedError::Malformed("Invalid chunk size"))?;
+                self.state = if size == 0 { State::Trailers } else { State::Data(size) };
+            }
+            State::DataEnd => {
+                if !line.is_empty() {
+                    return Err(AwsChunkedError::Malformed("Chunk data longer than its declared size"));
+                }
+                self.state = State::Header;
+            }
+            State::Trailers => {
+                if line.is_empty() {
+                    self.state = State::Done;
+                    return Ok(());
+                }
+                let line = std::str::from_utf8(line).map_err(|_| AwsChunkedError::Malformed("Invalid trailer"))?;
+                let (name, value) = line.split_once(':').ok_or(AwsChunkedError::Malformed("Invalid trailer"))?;
+                self.trailers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+            State::Data(_) | State::Done => unreachable!("no framing lines in this state"),
+        }
+        Ok(())
+    }
+}
+
+/// Decode a fully buffered `aws-chunked` body.
+pub(super) fn decode_body(req: &HttpRequest, body: &[u8]) -> Result<(Vec<u8>, Trailers), AwsChunkedError> {
+    let mut decoder = AwsChunkedDecoder::new();
+    let payload = decoder.feed(body)?;
+    let trailers = decoder.finish(decoded_content_length(req))?;
+    Ok((payload, trailers))
+}
+
+/// The `x-amz-checksum-*` value sent as a trailer, if any.
+pub(super) fn trailer_checksum(trailers: &[(String, String)]) -> Option<(ChecksumAlgorithm, String)> {
+    trailers.iter().find_map(|(name, value)| {
+        let algo = ChecksumAlgorithm::from_str(name.strip_prefix("x-amz-checksum-")?)?;
+        Some((algo, value.clone()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Signed streaming upload of "hello world!" in two chunks
+    const SIGNED_FIXTURE: &[u8] = b"6;chunk-signature=ad80c730a21e5b8d04586a2213dd63b9a0e99e0e2307b0ade35a65485a288648\r\n\
+hello \r\n\
+6;chunk-signature=0055627c9e194cb4542bae2aa5492e3c1575bbb81b612b7d234b86a503ef5497\r\n\
+world!\r\n\
+0;chunk-signature=b6c6ea8a5354eaf15b3cb7646744f4275b71ea724fed81ceb9323e279d449df9\r\n\
+\r\n";
+
+    // Unsigned streaming upload with a CRC32 trailer
+    const TRAILER_FIXTURE: &[u8] = b"c\r\nhello world!\r\n0\r\nx-amz-checksum-crc32:A7TCbQ==\r\n\r\n";
+
+    #[test]
+    fn test_decodes_signed_chunks() {
+        let mut decoder = AwsChunkedDecoder::new();
+        let payload = decoder.feed(SIGNED_FIXTURE).unwrap();
+        assert_eq!(payload, b"hello world!");
+        assert!(decoder.finish(Some(12)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decodes_byte_by_byte() {
+        let mut decoder = AwsChunkedDecoder::new();
+        let mut payload = Vec::new();
+        for b in TRAILER_FIXTURE {
+            payload.extend(decoder.feed(std::slice::from_ref(b)).unwrap());
+        }
+        assert_eq!(payload, b"hello world!");
+        let trailers = decoder.finish(None).unwrap();
+        let (algo, value) = trailer_checksum(&trailers).unwrap();
+        assert_eq!(algo, ChecksumAlgorithm::Crc32);
+        assert_eq!(value, "A7TCbQ==");
+        assert!(super::super::checksum::verify_checksum(&algo, &payload, &value));
+    }
+
+    #[test]
+    fn test_rejects_truncated_and_malformed_bodies() {
+        let mut decoder = AwsChunkedDecoder::new();
+        decoder.feed(&SIGNED_FIXTURE[..40]).unwrap();
+        assert_eq!(decoder.finish(None), Err(AwsChunkedError::Incomplete));
+
+        let mut decoder = AwsChunkedDecoder::new();
+        decoder.feed(SIGNED_FIXTURE).unwrap();
+        assert_eq!(decoder.finish(Some(13)), Err(AwsChunkedError::Incomplete));
+
+        let mut decoder = AwsChunkedDecoder::new();
+        assert!(matches!(decoder.feed(b"zz\r\nhello\r\n"), Err(AwsChunkedError::Malformed(_))));
+
+        let mut decoder = AwsChunkedDecoder::new();
+        assert!(matches!(decoder.feed(b"2\r\nhello\r\n"), Err(AwsChunkedError::Malformed(_))));
+    }
+}
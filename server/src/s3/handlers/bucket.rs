@@ -1,18 +1,21 @@
 // Bucket-level handlers: ListBuckets, CreateBucket, DeleteBucket, HeadBucket.
 use actix_web::{web, HttpRequest, HttpResponse, Error, http::StatusCode};
-use futures::StreamExt as _;
 use log::info;
 
 use std::collections::HashMap;
 
 use crate::s3::auth::authenticate_s3_request;
+use crate::service::bucket_registry::{BYTES_HEADER, OBJECT_COUNT_HEADER};
+use crate::service::capabilities::{Capabilities, FEATURES_HEADER};
 use crate::service::metadata_service::MetadataService;
+use crate::util::payload::{read_control_body, PayloadLimits};
 
 use super::common::*;
 use super::tagging::{s3_put_bucket_tagging_inner, s3_delete_bucket_tagging_inner};
 use super::versioning::s3_put_bucket_versioning_inner;
 use super::acl::{s3_put_acl_stub, validate_bucket_name};
 use super::object_lock::s3_put_bucket_object_lock_inner;
+use super::cors::{parse_cors_rules, validate_cors_rules};
 
 // ---------------------------------------------------------------------------
 // ListBuckets  GET /s3  or  GET /s3/
@@ -22,22 +25,16 @@ pub async fn s3_list_buckets_handler(
     query: web::Query<HashMap<String, String>>,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
-    let auth_result = authenticate_s3_request(&req).await?;
-    if !auth_result.bucket.is_empty() {
-        return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidRequest",
-                           "Unexpected bucket in path for list-buckets", "/"));
+    // Answered before authentication so clients can discover features without credentials
+    if query.contains_key("capabilities") {
+        return Ok(HttpResponse::Ok().json(Capabilities::of(&req)));
     }
+    let auth_result = authenticate_s3_request(&req).await?;
     info!("S3 ListBuckets: user={}", auth_result.user_id);
 
     let db = MetadataService::new(&auth_result.user_id)?;
     let all_stats = db.list_buckets_with_stats()?;
 
-    let allowed_set: Option<std::collections::HashSet<&str>> = if auth_result.allow_all_buckets {
-        None
-    } else {
-        Some(auth_result.allowed_buckets.iter().map(|s| s.as_str()).collect())
-    };
-
     let max_buckets: usize = query.get("max-buckets")
         .and_then(|s| s.parse().ok())
         .unwrap_or(usize::MAX);
@@ -49,9 +46,7 @@ pub async fn s3_list_buckets_handler(
     let mut truncated = false;
 
     for stat in &all_stats {
-        if let Some(ref allowed) = allowed_set {
-            if !allowed.contains(stat.name.as_str()) { continue; }
-        }
+        if !auth_result.buckets.allows(&stat.name) { continue; }
         if !after.is_empty() && stat.name.as_str() <= after {
             continue;
         }
@@ -97,7 +92,7 @@ pub async fn s3_list_buckets_handler(
 
 pub async fn s3_create_bucket_handler(
     path: web::Path<String>,
-    mut payload: web::Payload,
+    payload: web::Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let bucket = path.into_inner();
@@ -109,10 +104,7 @@ pub async fn s3_create_bucket_handler(
         return s3_put_acl_stub(&req).await;
     }
     if qmap.contains_key("tagging") || qmap.contains_key("versioning") || qmap.contains_key("object-lock") {
-        let mut body: Vec<u8> = Vec::new();
-        while let Some(chunk) = payload.next().await {
-            body.extend_from_slice(&chunk.map_err(actix_web::error::ErrorInternalServerError)?);
-        }
+        let body = read_control_body(payload, PayloadLimits::of(&req)).await?;
         if qmap.contains_key("tagging") {
             return s3_put_bucket_tagging_inner(&bucket, &body, &req).await;
         }
@@ -122,11 +114,7 @@ pub async fn s3_create_bucket_handler(
         return s3_put_bucket_versioning_inner(&bucket, &body, &req).await;
     }
 
-    let mut body_bytes: Vec<u8> = Vec::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.map_err(actix_web::error::ErrorInternalServerError)?;
-        body_bytes.extend_from_slice(&chunk);
-    }
+    let body_bytes = read_control_body(payload, PayloadLimits::of(&req)).await?;
     let body = String::from_utf8_lossy(&body_bytes);
 
     let query: HashMap<String, String> = web::Query::<HashMap<String, String>>::from_query(req.query_string())
@@ -136,6 +124,9 @@ pub async fn s3_create_bucket_handler(
         let auth_result = authenticate_s3_request(&req).await?;
         let db = MetadataService::new(&auth_result.user_id)?;
         if let Err(resp) = require_bucket(&db, &bucket) { return Ok(resp); }
+        if let Err((code, message)) = validate_cors_rules(&parse_cors_rules(&body)) {
+            return Ok(s3_error(StatusCode::BAD_REQUEST, code, &message, &bucket));
+        }
         db.set_bucket_cors(&bucket, body.trim())?;
         info!("S3 PutBucketCors: bucket={}", bucket);
         return Ok(HttpResponse::Ok()
@@ -227,11 +218,14 @@ pub async fn s3_head_bucket_handler(
     let mut resp = HttpResponse::Ok();
     resp.insert_header(("Content-Type", "application/xml"));
     resp.insert_header(("Content-Length", "0"));
+    resp.insert_header((FEATURES_HEADER, Capabilities::of(&req).enabled_features().join(",")));
 
     let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
         .unwrap_or_else(|_| web::Query(HashMap::new()));
-    if query.contains_key("read-stats") {
-        if let Ok((count, bytes)) = db.bucket_object_stats(&bucket) {
+    if let Ok((count, bytes)) = db.bucket_object_stats(&bucket) {
+        resp.insert_header((OBJECT_COUNT_HEADER, count.to_string()));
+        resp.insert_header((BYTES_HEADER, bytes.to_string()));
+        if query.contains_key("read-stats") {
             resp.insert_header(("x-rgw-object-count", count.to_string()));
             resp.insert_header(("x-rgw-bytes-used", bytes.to_string()));
         }
@@ -27,6 +27,10 @@ pub enum ChecksumAlgorithm {
 }
 
 impl ChecksumAlgorithm {
+    pub const ALL: [ChecksumAlgorithm; 5] = [
+        Self::Sha256, Self::Crc32, Self::Crc32c, Self::Sha1, Self::Crc64Nvme,
+    ];
+
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_uppercase().as_str() {
             "SHA256" => Some(Self::Sha256),
@@ -83,11 +87,19 @@ impl ChecksumAlgorithm {
 /// Parse checksum algorithm + value from request headers.
 /// Returns (algo, client_provided_value) if both are present.
 /// boto3/AWS SDK sends `x-amz-sdk-checksum-algorithm`; raw clients may send
-/// `x-amz-checksum-algorithm`. We accept both.
+/// `x-amz-checksum-algorithm`. We accept both. Without either, a lone
+/// `x-amz-checksum-{algo}` value header names the algorithm itself.
 pub fn parse_checksum_headers(req: &HttpRequest) -> Option<(ChecksumAlgorithm, String)> {
     let algo_str = req.headers().get("x-amz-sdk-checksum-algorithm")
         .or_else(|| req.headers().get("x-amz-checksum-algorithm"))
-        .and_then(|v| v.to_str().ok())?;
+        .and_then(|v| v.to_str().ok());
+    let Some(algo_str) = algo_str else {
+        return ChecksumAlgorithm::ALL.into_iter().find_map(|algo| {
+            let header_name = format!("x-amz-checksum-{}", algo.header_suffix());
+            let value = req.headers().get(header_name.as_str())?.to_str().ok()?.to_string();
+            Some((algo, value))
+        });
+    };
     let algo = ChecksumAlgorithm::from_str(algo_str)?;
     let header_name = format!("x-amz-checksum-{}", algo.header_suffix());
     let value = req.headers().get(header_name.as_str())
@@ -95,14 +107,6 @@ pub fn parse_checksum_headers(req: &HttpRequest) -> Option<(ChecksumAlgorithm, S
     Some((algo, value))
 }
 
-/// Extract only the algorithm name from request headers (without requiring a value header).
-pub fn parse_checksum_algorithm(req: &HttpRequest) -> Option<ChecksumAlgorithm> {
-    let algo_str = req.headers().get("x-amz-sdk-checksum-algorithm")
-        .or_else(|| req.headers().get("x-amz-checksum-algorithm"))
-        .and_then(|v| v.to_str().ok())?;
-    ChecksumAlgorithm::from_str(algo_str)
-}
-
 /// Compute checksum of data, return base64-encoded string.
 pub fn compute_checksum(algo: &ChecksumAlgorithm, data: &[u8]) -> String {
     match algo {
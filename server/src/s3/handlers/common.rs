@@ -1,5 +1,5 @@
 // Shared utilities, constants, and types used across handler submodules.
-use actix_web::{HttpRequest, HttpResponse, http::StatusCode};
+use actix_web::{Error, HttpRequest, HttpResponse, http::StatusCode};
 use actix_web::body::{BodySize, MessageBody};
 
 use bytes::Bytes;
@@ -7,6 +7,7 @@ use bytes::Bytes;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+use crate::service::error::ServiceError;
 use crate::service::metadata_service::MetadataService;
 
 pub(super) const S3_XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
@@ -22,13 +23,7 @@ impl MessageBody for HeadBody {
     }
 }
 
-pub(super) enum RangeResult {
-    None,
-    Valid(u64, u64),
-    Unsatisfiable,
-}
-
-pub(super) fn xml_escape(s: &str) -> String {
+pub(crate) fn xml_escape(s: &str) -> String {
     let mut out = String::with_capacity(s.len());
     for c in s.chars() {
         match c {
@@ -93,6 +88,31 @@ pub(super) fn s3_error(status: StatusCode, code: &str, message: &str, resource:
         .body(body)
 }
 
+/// S3 form of a write refused by a bucket quota (403 `QuotaExceeded`); other errors pass through.
+pub(super) fn s3_quota_error(e: Error, resource: &str) -> Error {
+    match e.as_error::<ServiceError>() {
+        Some(quota @ ServiceError::QuotaExceeded { .. }) => {
+            let message = quota.to_string();
+            let response = s3_error(StatusCode::FORBIDDEN, "QuotaExceeded", &message, resource);
+            actix_web::error::InternalError::from_response(message, response).into()
+        }
+        _ => e,
+    }
+}
+
+/// S3 form of a read of metadata pointing into freed space (500 `InternalError`); other
+/// errors pass through.
+pub(super) fn s3_read_error(e: Error, resource: &str) -> Error {
+    match e.as_error::<ServiceError>() {
+        Some(freed @ ServiceError::FreedRangeReference { .. }) => {
+            let message = freed.to_string();
+            let response = s3_error(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", &message, resource);
+            actix_web::error::InternalError::from_response(message, response).into()
+        }
+        _ => e,
+    }
+}
+
 /// Return 404 NoSuchBucket if the bucket is not registered for this user.
 pub(super) fn require_bucket(db: &MetadataService, bucket: &str) -> Result<(), HttpResponse> {
     match db.bucket_exists(bucket) {
@@ -120,94 +140,20 @@ pub(super) fn stream_slices(chunks: &[(u64, u64)]) -> Vec<(u64, u64)> {
     out
 }
 
-/// Parse `Range: bytes=X-Y`, `bytes=X-`, or `bytes=-N` (suffix).
-pub(super) fn parse_range_header(req: &HttpRequest, total: u64) -> RangeResult {
-    let hdr = match req.headers().get("range").and_then(|v| v.to_str().ok()) {
-        Some(h) => h.to_string(),
-        None => return RangeResult::None,
-    };
-    let bytes = match hdr.strip_prefix("bytes=") {
-        Some(b) => b.to_string(),
-        None => return RangeResult::Unsatisfiable,
-    };
-    if bytes.starts_with('-') {
-        let n: u64 = match bytes[1..].parse().ok() {
-            Some(n) => n,
-            None => return RangeResult::Unsatisfiable,
-        };
-        if n == 0 || total == 0 {
-            return RangeResult::Unsatisfiable;
-        }
-        let start = total.saturating_sub(n);
-        return RangeResult::Valid(start, total - 1);
-    }
-    let (start_s, end_s) = match bytes.split_once('-') {
-        Some(pair) => pair,
-        None => return RangeResult::Unsatisfiable,
-    };
-    let start: u64 = match start_s.parse().ok() {
-        Some(s) => s,
-        None => return RangeResult::Unsatisfiable,
-    };
-    let end: u64 = if end_s.is_empty() {
-        total.saturating_sub(1)
-    } else {
-        match end_s.parse::<u64>().ok() {
-            Some(e) => e,
-            None => return RangeResult::Unsatisfiable,
-        }
-    };
-    if total == 0 || start >= total {
-        return RangeResult::Unsatisfiable;
-    }
-    let end = end.min(total - 1);
-    if start > end {
-        return RangeResult::Unsatisfiable;
-    }
-    RangeResult::Valid(start, end)
-}
-
-/// Map a logical byte range onto storage extents.
+/// Map a logical byte range onto storage extents of at most S3_GET_STREAM_CHUNK.
 pub(super) fn range_slices(chunks: &[(u64, u64)], range_start: u64, range_end: u64) -> Vec<(u64, u64)> {
-    let mut out = Vec::new();
-    let mut logical = 0u64;
-    for &(storage_off, chunk_size) in chunks {
-        let chunk_end = logical + chunk_size;
-        if chunk_end <= range_start {
-            logical = chunk_end;
-            continue;
-        }
-        if logical >= range_end + 1 {
-            break;
-        }
-        let read_start = range_start.max(logical);
-        let read_end   = (range_end + 1).min(chunk_end);
-        let skip       = read_start - logical;
-        let read_size  = read_end - read_start;
-        let mut off = storage_off + skip;
-        let mut rem = read_size;
-        while rem > 0 {
-            let n = rem.min(S3_GET_STREAM_CHUNK);
-            out.push((off, n));
-            off += n;
-            rem -= n;
-        }
-        logical = chunk_end;
-    }
-    out
+    crate::util::byte_ranges::range_slices(chunks, range_start, range_end, S3_GET_STREAM_CHUNK)
 }
 
 /// Returns the current timestamp as the canonical last_modified string stored in metadata
-/// and emitted in S3 XML bodies. Format: ISO 8601 (`2026-07-01T12:00:00.000Z`).
+/// and emitted in S3 XML bodies. Format: ISO 8601 (`2026-07-01T12:00:00.123Z`).
 pub(super) fn last_modified_now() -> String {
-    chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S.000Z").to_string()
+    crate::metadata::last_modified_now()
 }
 
 /// Converts a stored last_modified value to the RFC 2616 format required by HTTP headers.
 pub(super) fn last_modified_for_header(s: &str) -> String {
-    chrono::DateTime::parse_from_rfc3339(s)
-        .map(|dt| dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
-        .unwrap_or_else(|_| s.to_string())
+    crate::metadata::http_date(s)
 }
 
 /// Compute MD5 ETag (double-quoted) from accumulated bytes.
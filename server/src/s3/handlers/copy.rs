@@ -5,13 +5,30 @@ use log::info;
 use std::collections::HashMap;
 
 use crate::metadata::Metadata;
-use crate::s3::auth::authenticate_s3_request;
+use crate::s3::auth::{authenticate_s3_request, authorize, S3Operation};
+use crate::service::inline_objects::InlineConfig;
 use crate::service::metadata_service::MetadataService;
 use crate::service::storage_service::{StorageService, StorageMode};
 use crate::service::user_context::UserContext;
-use crate::util::serializer::deserialize_offset_size;
+use crate::storage::bucket_lock;
+use crate::util::serializer::decode_extents;
 
 use super::common::*;
+use super::tagging::{parse_url_tags, validate_tags};
+
+/// Request header asking CopyObject for a metadata-only clone
+pub const COW_HEADER: &str = "x-warp-cow";
+
+fn copy_result_xml(etag: &str, last_modified: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <CopyObjectResult xmlns=\"{s3}\">\n\
+             <ETag>{etag}</ETag>\n\
+             <LastModified>{lm}</LastModified>\n\
+         </CopyObjectResult>",
+        s3 = S3_XMLNS, etag = xml_escape(etag), lm = last_modified,
+    )
+}
 
 // ---------------------------------------------------------------------------
 // CopyObject  PUT /s3/{dst_bucket}/{dst_key} with x-amz-copy-source header
@@ -40,11 +57,12 @@ pub async fn s3_copy_object_handler(
         (source_raw, None)
     };
     let (src_bucket, src_key_enc) = match source_path.splitn(2, '/').collect::<Vec<_>>().as_slice() {
-        [b, k] => (b.to_string(), k.to_string()),
+        [b, k] if !k.is_empty() => (b.to_string(), k.to_string()),
         _ => return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
                                 "Invalid x-amz-copy-source format (expected bucket/key)", &dst_bucket)),
     };
     let src_key = percent_decode(&src_key_enc);
+    authorize(&auth_result, &src_bucket, S3Operation::Read)?;
 
     info!("S3 CopyObject: {}/{} → {}/{}", src_bucket, src_key, dst_bucket, dst_key);
 
@@ -63,6 +81,24 @@ pub async fn s3_copy_object_handler(
                            &format!("/{}/{}", src_bucket, src_key)));
     }
 
+    // COPY keeps the source's tags; REPLACE takes the x-amz-tagging header (or none)
+    let tagging_directive = req.headers().get("x-amz-tagging-directive")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("COPY");
+    let replacement_tags = match tagging_directive {
+        "COPY" => None,
+        "REPLACE" => {
+            let tags = req.headers().get("x-amz-tagging")
+                .and_then(|v| v.to_str().ok())
+                .map(parse_url_tags)
+                .unwrap_or_default();
+            if let Err(resp) = validate_tags(&tags, &format!("/{}/{}", dst_bucket, dst_key)) { return Ok(resp); }
+            Some(tags)
+        }
+        _ => return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
+                                "Unknown tagging directive.", &dst_bucket)),
+    };
+
     let src_meta = if let Some(ref vid) = copy_source_version_id {
         match db.get_object_version(&src_bucket, &src_key, vid) {
             Ok(m) if !m.is_delete_marker => m,
@@ -78,6 +114,12 @@ pub async fn s3_copy_object_handler(
         db.get_object_full(&src_bucket, &src_key)?
     };
 
+    // Read before an existing destination (possibly the source itself) is replaced
+    let dst_tags = match replacement_tags {
+        Some(tags) => tags,
+        None => db.get_object_tags(&src_bucket, &src_key)?,
+    };
+
     let copy_if_match = req.headers().get("x-amz-copy-source-if-match")
         .and_then(|v| v.to_str().ok()).map(|s| s.trim().to_string());
     let copy_if_none_match = req.headers().get("x-amz-copy-source-if-none-match")
@@ -95,12 +137,37 @@ pub async fn s3_copy_object_handler(
         }
     }
 
+    // x-warp-cow: share the source's chunks instead of copying them. Only a same-bucket copy
+    // of the current version with its metadata kept qualifies; anything else is a full copy.
+    let cow = req.headers().get(COW_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    if cow && src_bucket == dst_bucket && src_key != dst_key && copy_source_version_id.is_none()
+        && directive_early == "COPY" && db.get_versioning_state(&dst_bucket)? == "disabled" {
+        let _write_guard = bucket_lock::begin_write(&auth_result.user_id, &dst_bucket).await;
+        let replaced = db.clone_metadata(&dst_bucket, &src_key, &dst_key, true)?;
+        if !replaced.is_empty() {
+            db.queue_deletion(&dst_bucket, &dst_key, &replaced).ok();
+        }
+        db.set_object_tags(&dst_bucket, &dst_key, &dst_tags)?;
+        let last_modified = db.get_object_full(&dst_bucket, &dst_key)?.last_modified.unwrap_or_default();
+        info!("S3 CopyObject cloned {}/{} → {}", src_bucket, src_key, dst_key);
+        return Ok(HttpResponse::Ok().content_type("application/xml").body(copy_result_xml(&src_etag_val, &last_modified)));
+    }
+
     let src_context = UserContext::with_bucket(auth_result.user_id.clone(), src_bucket.clone());
     let dst_context = UserContext::with_bucket(auth_result.user_id.clone(), dst_bucket.clone());
 
     let storage_service = StorageService::new();
-    let src_data = storage_service.read_object(&src_context, &src_meta.to_offset_size_list(), StorageMode::S3)?;
-    let new_offset_size_list = storage_service.write_object(&dst_context, &src_data, StorageMode::S3)?;
+    let src_data = storage_service.read_stored(&src_context, &src_meta, StorageMode::S3)?;
+    let _write_guard = bucket_lock::begin_write(&dst_context.user_id, &dst_context.bucket).await;
+    let inline = InlineConfig::from_env().fits(src_data.len() as u64);
+    let new_offset_size_list = if inline {
+        Vec::new()
+    } else {
+        storage_service.write_object(&dst_context, &src_data, StorageMode::S3)?
+    };
 
     let directive = req.headers().get("x-amz-metadata-directive")
         .and_then(|v| v.to_str().ok())
@@ -140,10 +207,12 @@ pub async fn s3_copy_object_handler(
         storage_service.delete_object(&dst_context, &dst_key)?;
     }
 
-    let new_offset_size_bytes = crate::util::serializer::serialize_offset_size(&new_offset_size_list)?;
-    let mut dst_meta = Metadata::from_offset_size_list(
-        deserialize_offset_size(&new_offset_size_bytes)?
-    );
+    let new_offset_size_bytes = crate::util::serializer::encode_extents(&new_offset_size_list)?;
+    let mut dst_meta = if inline {
+        Metadata::inline(src_data.to_vec())
+    } else {
+        Metadata::from_offset_size_list(decode_extents(&new_offset_size_bytes)?)
+    };
     dst_meta.etag = Some(etag.clone());
     dst_meta.size = src_data.len() as u64;
     dst_meta.content_type = Some(content_type);
@@ -151,19 +220,14 @@ pub async fn s3_copy_object_handler(
     dst_meta.last_modified = Some(last_modified.clone());
     dst_meta.user_metadata = user_metadata;
 
-    let (copy_vid, copy_old_extents) = db.put_object_full(&dst_bucket, &dst_key, dst_meta)?;
+    let (copy_vid, copy_old_extents) = db.put_object_full(&dst_bucket, &dst_key, dst_meta)
+        .map_err(|e| s3_quota_error(e, &format!("/{}/{}", dst_bucket, dst_key)))?;
     if !copy_old_extents.is_empty() {
         db.queue_deletion(&dst_bucket, &dst_key, &copy_old_extents).ok();
     }
+    db.set_object_tags(&dst_bucket, &dst_key, &dst_tags)?;
 
-    let xml = format!(
-        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
-         <CopyObjectResult xmlns=\"{s3}\">\n\
-             <ETag>{etag}</ETag>\n\
-             <LastModified>{lm}</LastModified>\n\
-         </CopyObjectResult>",
-        s3 = S3_XMLNS, etag = xml_escape(&etag), lm = last_modified,
-    );
+    let xml = copy_result_xml(&etag, &last_modified);
     let mut resp = HttpResponse::Ok();
     resp.content_type("application/xml");
     if let Some(vid) = copy_vid { if vid != "null" { resp.insert_header(("x-amz-version-id", vid)); } }
@@ -1,8 +1,17 @@
 // CORS types, helpers, and handlers.
 use actix_web::{HttpRequest, HttpResponse, Error, http::StatusCode};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
 
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
 use super::common::*;
 
+/// Methods a CORS rule may allow
+const CORS_METHODS: [&str; 5] = ["GET", "PUT", "POST", "DELETE", "HEAD"];
+
 pub(super) struct CorsRule {
     pub(super) allowed_origins: Vec<String>,
     pub(super) allowed_methods: Vec<String>,
@@ -24,6 +33,27 @@ pub(super) fn parse_cors_rules(xml: &str) -> Vec<CorsRule> {
     }).collect()
 }
 
+/// Check a PutBucketCors configuration; the S3 error code and message when it is rejected.
+pub(super) fn validate_cors_rules(rules: &[CorsRule]) -> Result<(), (&'static str, String)> {
+    if rules.is_empty() {
+        return Err(("MalformedXML", "The CORS configuration must contain at least one CORSRule.".to_string()));
+    }
+    for rule in rules {
+        if rule.allowed_origins.is_empty() || rule.allowed_methods.is_empty() {
+            return Err(("MalformedXML", "Each CORSRule must have an AllowedOrigin and an AllowedMethod.".to_string()));
+        }
+        if let Some(method) = rule.allowed_methods.iter().find(|m| !CORS_METHODS.contains(&m.as_str())) {
+            return Err(("InvalidRequest",
+                        format!("Found unsupported HTTP method in CORS config. Unsupported method is {}", method)));
+        }
+        if let Some(origin) = rule.allowed_origins.iter().find(|o| o.matches('*').count() > 1) {
+            return Err(("InvalidRequest",
+                        format!("AllowedOrigin \"{}\" can not have more than one wildcard.", origin)));
+        }
+    }
+    Ok(())
+}
+
 /// Glob-match an origin against an S3 AllowedOrigin pattern (single '*' wildcard).
 pub(super) fn origin_matches_pattern(origin: &str, pattern: &str) -> bool {
     if pattern == "*" { return true; }
@@ -69,6 +99,68 @@ pub(super) fn cors_allow_origin<'a>(origin: &'a str, matched_pattern: &str) -> &
     if matched_pattern == "*" { "*" } else { origin }
 }
 
+/// Access-Control-* headers `rule` grants to `origin`, on preflights and actual requests alike.
+fn cors_rule_headers(rule: &CorsRule, origin: &str) -> Vec<(HeaderName, String)> {
+    let matched_pattern = rule.allowed_origins.iter()
+        .find(|p| origin_matches_pattern(origin, p))
+        .map(|s| s.as_str())
+        .unwrap_or("");
+    let mut headers = vec![
+        (header::ACCESS_CONTROL_ALLOW_ORIGIN, cors_allow_origin(origin, matched_pattern).to_string()),
+        (header::ACCESS_CONTROL_ALLOW_METHODS, rule.allowed_methods.join(", ")),
+    ];
+    if let Some(max_age) = rule.max_age_seconds {
+        headers.push((header::ACCESS_CONTROL_MAX_AGE, max_age.to_string()));
+    }
+    if !rule.expose_headers.is_empty() {
+        headers.push((header::ACCESS_CONTROL_EXPOSE_HEADERS, rule.expose_headers.join(", ")));
+    }
+    headers
+}
+
+/// Bucket an S3 route was matched for; None for native and admin routes.
+fn routed_bucket(req: &HttpRequest) -> Option<String> {
+    let pattern = req.match_pattern()?;
+    let bucket_route = pattern.strip_prefix("/s3").unwrap_or(&pattern).starts_with("/{bucket}");
+    bucket_route.then(|| req.match_info().get("bucket").map(str::to_string)).flatten()
+}
+
+// ---------------------------------------------------------------------------
+// Actual (non-preflight) requests
+// ---------------------------------------------------------------------------
+
+/// Add the bucket's CORS headers to the response of an S3 request whose Origin and method
+/// match one of its rules; use with `middleware::from_fn`. The bucket is only known once the
+/// request is routed, so the rules are looked up for the response. Other requests, and
+/// buckets without a configuration, pass through untouched.
+pub async fn s3_bucket_cors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let origin = req.headers().get(header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let mut res = next.call(req).await?;
+    let Some(origin) = origin else { return Ok(res) };
+    if res.request().method() == Method::OPTIONS {
+        return Ok(res);
+    }
+    let Some(bucket) = routed_bucket(res.request()) else { return Ok(res) };
+    let Ok(Some(cors_xml)) = SQLiteMetadataStore::new().get_bucket_cors(&bucket) else { return Ok(res) };
+
+    let rules = parse_cors_rules(&cors_xml);
+    let method = res.request().method().to_string();
+    let Some(rule) = find_cors_match(&rules, &origin, &method, &[]) else { return Ok(res) };
+    let headers = res.headers_mut();
+    for (name, value) in cors_rule_headers(rule, &origin) {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    Ok(res)
+}
+
 // ---------------------------------------------------------------------------
 // GetBucketLocation  GET /s3/{bucket}?location
 // ---------------------------------------------------------------------------
@@ -145,7 +237,6 @@ pub async fn s3_cors_not_configured_handler(req: HttpRequest) -> Result<HttpResp
         .unwrap_or_default();
     let req_header_refs: Vec<&str> = request_headers.iter().map(|s| s.as_str()).collect();
 
-    use crate::metadata::sqlite_store::SQLiteMetadataStore;
     let cors_xml = SQLiteMetadataStore::new().get_bucket_cors(bucket)?;
 
     let cors_xml = match cors_xml {
@@ -162,27 +253,14 @@ pub async fn s3_cors_not_configured_handler(req: HttpRequest) -> Result<HttpResp
             .insert_header(("Content-Length", "0"))
             .body("")),
         Some(rule) => {
-            let matched_pattern = rule.allowed_origins.iter()
-                .find(|p| origin_matches_pattern(&origin, p))
-                .map(|s| s.as_str())
-                .unwrap_or("");
-            let allow_origin = cors_allow_origin(&origin, matched_pattern);
-            let allow_methods = rule.allowed_methods.join(", ");
-
             let mut resp = HttpResponse::Ok();
-            resp.insert_header(("Access-Control-Allow-Origin", allow_origin));
-            resp.insert_header(("Access-Control-Allow-Methods", allow_methods.as_str()));
+            for header in cors_rule_headers(rule, &origin) {
+                resp.insert_header(header);
+            }
             if !rule.allowed_headers.is_empty() {
                 resp.insert_header(("Access-Control-Allow-Headers",
                     rule.allowed_headers.join(", ").as_str()));
             }
-            if let Some(max_age) = rule.max_age_seconds {
-                resp.insert_header(("Access-Control-Max-Age", max_age.to_string().as_str()));
-            }
-            if !rule.expose_headers.is_empty() {
-                resp.insert_header(("Access-Control-Expose-Headers",
-                    rule.expose_headers.join(", ").as_str()));
-            }
             resp.insert_header(("Content-Length", "0"));
             Ok(resp.body(""))
         }
@@ -1,12 +1,13 @@
 // s3_list_objects_handler, s3_delete_objects_handler.
 use actix_web::{web, HttpRequest, HttpResponse, Error, http::StatusCode};
-use futures::StreamExt as _;
-use log::{info, warn};
+use log::info;
 
 use std::collections::HashMap;
 
 use crate::s3::auth::authenticate_s3_request;
 use crate::service::metadata_service::MetadataService;
+use crate::service::object_tags::parse_tag_filter;
+use crate::util::payload::{read_control_body, PayloadLimits};
 
 use super::common::*;
 use super::tagging::s3_get_bucket_tagging_inner;
@@ -20,6 +21,9 @@ use super::object_lock::s3_get_bucket_object_lock_inner;
 // ListObjects  GET /s3/{bucket}
 // ---------------------------------------------------------------------------
 
+/// ListObjectsV2 with `list-type=2`, ListObjects (V1) without it: V1 pages with `marker`,
+/// returns `NextMarker` only when truncated with a delimiter, and has no `KeyCount`. Both
+/// page through [`paginate`].
 pub async fn s3_list_objects_handler(
     path: web::Path<String>,
     query: web::Query<HashMap<String, String>>,
@@ -101,99 +105,56 @@ pub async fn s3_list_objects_handler(
     info!("S3 ListObjects{}: bucket={} prefix={:?} delim={:?} max_keys={} marker={:?}",
           if is_v2 { "V2" } else { "V1" }, bucket, prefix, delimiter, max_keys, effective_marker);
 
-    let all_keys = db.list_objects(&bucket)?;
-    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S.000Z").to_string();
+    // Non-standard `tag=key:value` filter, resolved with a join on the tag table
+    let all_keys = match query.get("tag") {
+        Some(filter) => match parse_tag_filter(filter) {
+            Some((tag_key, tag_value)) => db.list_objects_with_tag(&bucket, tag_key, tag_value)?,
+            None => return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
+                                       "tag filter must be of the form key:value", &bucket)),
+        },
+        None => db.list_objects(&bucket)?,
+    };
     let owner_id = auth_result.user_id.clone();
 
-    let mut contents_xml   = String::new();
-    let mut prefixes_xml   = String::new();
-    let mut last_common_prefix = String::new();
-    let mut last_key       = String::new();
-    let mut count          = 0usize;
-    let mut truncated      = false;
-
-    if max_keys > 0 {
-        'outer: for key in &all_keys {
-            let key = key.as_str();
-
-            if !effective_marker.is_empty() && key <= effective_marker {
-                continue;
-            }
-
-            if !key.starts_with(prefix) {
-                continue;
-            }
-
-            if !delimiter.is_empty() {
-                let after_prefix = &key[prefix.len()..];
-                if let Some(pos) = after_prefix.find(delimiter) {
-                    let group = format!("{}{}{}", prefix, &after_prefix[..pos], delimiter);
-
-                    if !effective_marker.is_empty() && group.as_str() <= effective_marker {
-                        continue;
-                    }
-
-                    if group == last_common_prefix {
-                        continue;
-                    }
-
-                    if count >= max_keys {
-                        truncated = true;
-                        break 'outer;
-                    }
-
-                    last_common_prefix = group.clone();
-                    last_key = group.clone();
-                    count += 1;
-
-                    let disp = if url_encode { s3_url_encode(&group) } else { xml_escape(&group) };
-                    prefixes_xml.push_str(&format!(
-                        "    <CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>\n", disp
-                    ));
-                    continue;
-                }
-            }
-
-            if count >= max_keys {
-                truncated = true;
-                break;
-            }
+    // Skip keys deleted between the listing and the lookup
+    let page = paginate(&all_keys, prefix, delimiter, effective_marker, max_keys,
+                        |key| db.get_object_full(&bucket, key).ok());
+
+    let mut contents_xml = String::new();
+    for (key, meta) in &page.contents {
+        let etag = meta.etag.clone().unwrap_or_default();
+        let disp_key = if url_encode { s3_url_encode(key) } else { xml_escape(key) };
+        let owner_xml = if !is_v2 || fetch_owner {
+            format!("      <Owner><ID>{id}</ID><DisplayName>{id}</DisplayName></Owner>\n",
+                    id = xml_escape(&owner_id))
+        } else {
+            String::new()
+        };
 
-            last_key = key.to_string();
-            count += 1;
-
-            let meta = db.get_object_full(&bucket, key).ok();
-            let size = meta.as_ref().map(|m| m.size).unwrap_or(0);
-            let etag = meta.as_ref().and_then(|m| m.etag.clone()).unwrap_or_default();
-            let lm   = meta.as_ref().and_then(|m| m.last_modified.clone())
-                           .unwrap_or_else(|| now.clone());
-
-            let disp_key = if url_encode { s3_url_encode(key) } else { xml_escape(key) };
-
-            let owner_xml = if !is_v2 || fetch_owner {
-                format!("      <Owner><ID>{id}</ID><DisplayName>{id}</DisplayName></Owner>\n",
-                        id = xml_escape(&owner_id))
-            } else {
-                String::new()
-            };
-
-            contents_xml.push_str(&format!(
-                "    <Contents>\n\
-                 \t<Key>{key}</Key>\n\
-                 \t<LastModified>{lm}</LastModified>\n\
-                 \t<ETag>&quot;{etag}&quot;</ETag>\n\
-                 \t<Size>{size}</Size>\n\
-                 \t<StorageClass>STANDARD</StorageClass>\n\
-                 {owner}\
-                 \t</Contents>\n",
-                key   = disp_key,
-                lm    = lm,
-                etag  = etag.trim_matches('"'),
-                size  = size,
-                owner = owner_xml,
-            ));
-        }
+        contents_xml.push_str(&format!(
+            "    <Contents>\n\
+             \t<Key>{key}</Key>\n\
+             \t<LastModified>{lm}</LastModified>\n\
+             \t<ETag>&quot;{etag}&quot;</ETag>\n\
+             \t<Size>{size}</Size>\n\
+             \t<StorageClass>STANDARD</StorageClass>\n\
+             {owner}\
+             \t</Contents>\n",
+            key   = disp_key,
+            lm    = meta.last_modified.as_deref().unwrap_or_default(),
+            etag  = etag.trim_matches('"'),
+            size  = meta.size,
+            owner = owner_xml,
+        ));
+    }
+    let mut prefixes_xml = String::new();
+    for group in &page.common_prefixes {
+        let disp = if url_encode { s3_url_encode(group) } else { xml_escape(group) };
+        prefixes_xml.push_str(&format!(
+            "    <CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>\n", disp
+        ));
     }
+    let (count, truncated, last_key) = (page.count(), page.truncated, page.last);
 
     let delimiter_xml = if !delimiter.is_empty() {
         format!("    <Delimiter>{}</Delimiter>\n", xml_escape(delimiter))
@@ -207,8 +168,7 @@ pub async fn s3_list_objects_handler(
     };
 
     let truncated_str  = if truncated { "true" } else { "false" };
-    let v1_prefix  = xml_escape(prefix);
-    let v2_prefix  = if url_encode { s3_url_encode(prefix) } else { xml_escape(prefix) };
+    let disp_prefix = if url_encode { s3_url_encode(prefix) } else { xml_escape(prefix) };
     let encoded_bucket = xml_escape(&bucket);
 
     let xml = if is_v2 {
@@ -248,7 +208,7 @@ pub async fn s3_list_objects_handler(
                  {contents}{prefixes}</ListBucketResult>",
             s3          = S3_XMLNS,
             bucket      = encoded_bucket,
-            prefix      = v2_prefix,
+            prefix      = disp_prefix,
             delimiter   = delimiter_xml,
             encoding    = encoding_xml,
             max_keys    = max_keys,
@@ -264,8 +224,10 @@ pub async fn s3_list_objects_handler(
         let marker_val = query.get("marker").map(|s| s.as_str()).unwrap_or("");
         let disp_marker = if url_encode { s3_url_encode(marker_val) } else { xml_escape(marker_val) };
 
-        let next_marker_xml = if truncated {
-            format!("    <NextMarker>{}</NextMarker>\n", xml_escape(&last_key))
+        // Without a delimiter clients continue from the last key they received
+        let next_marker_xml = if truncated && !delimiter.is_empty() {
+            let disp = if url_encode { s3_url_encode(&last_key) } else { xml_escape(&last_key) };
+            format!("    <NextMarker>{}</NextMarker>\n", disp)
         } else {
             String::new()
         };
@@ -283,7 +245,7 @@ pub async fn s3_list_objects_handler(
                  {contents}{prefixes}</ListBucketResult>",
             s3          = S3_XMLNS,
             bucket      = encoded_bucket,
-            prefix      = v1_prefix,
+            prefix      = disp_prefix,
             marker      = disp_marker,
             next_marker = next_marker_xml,
             delimiter   = delimiter_xml,
@@ -298,6 +260,80 @@ pub async fn s3_list_objects_handler(
     Ok(HttpResponse::Ok().content_type("application/xml").body(xml))
 }
 
+/// One page of a listing: objects and common prefixes in key order, `max_keys` in all
+#[derive(Debug)]
+pub struct ListPage<T> {
+    pub contents: Vec<(String, T)>,
+    pub common_prefixes: Vec<String>,
+    /// More entries follow
+    pub truncated: bool,
+    /// Last key or common prefix returned, where the next page starts after
+    pub last: String,
+}
+
+impl<T> ListPage<T> {
+    /// Entries on the page
+    pub fn count(&self) -> usize {
+        self.contents.len() + self.common_prefixes.len()
+    }
+}
+
+/// Page through the sorted `keys` after `marker` (V1 `marker`, V2 `continuation-token` or
+/// `start-after`), keeping those under `prefix` and rolling up everything past `delimiter`
+/// into common prefixes. `entry` looks up each object; a key it returns `None` for is left
+/// out without counting towards `max_keys`.
+pub fn paginate<T>(
+    keys: &[String],
+    prefix: &str,
+    delimiter: &str,
+    marker: &str,
+    max_keys: usize,
+    mut entry: impl FnMut(&str) -> Option<T>,
+) -> ListPage<T> {
+    let mut page = ListPage { contents: Vec::new(), common_prefixes: Vec::new(), truncated: false, last: String::new() };
+    if max_keys == 0 {
+        return page;
+    }
+    for key in keys {
+        let key = key.as_str();
+        if !marker.is_empty() && key <= marker {
+            continue;
+        }
+        if !key.starts_with(prefix) {
+            continue;
+        }
+
+        if !delimiter.is_empty() {
+            let after_prefix = &key[prefix.len()..];
+            if let Some(pos) = after_prefix.find(delimiter) {
+                let group = format!("{}{}{}", prefix, &after_prefix[..pos], delimiter);
+                if !marker.is_empty() && group.as_str() <= marker {
+                    continue;
+                }
+                if page.common_prefixes.last() == Some(&group) {
+                    continue;
+                }
+                if page.count() >= max_keys {
+                    page.truncated = true;
+                    break;
+                }
+                page.last = group.clone();
+                page.common_prefixes.push(group);
+                continue;
+            }
+        }
+
+        if page.count() >= max_keys {
+            page.truncated = true;
+            break;
+        }
+        let Some(value) = entry(key) else { continue };
+        page.last = key.to_string();
+        page.contents.push((key.to_string(), value));
+    }
+    page
+}
+
 // ---------------------------------------------------------------------------
 // DeleteObjects  POST /s3/{bucket}?delete
 // ---------------------------------------------------------------------------
@@ -305,7 +341,7 @@ pub async fn s3_list_objects_handler(
 pub async fn s3_delete_objects_handler(
     path: web::Path<String>,
     query: web::Query<HashMap<String, String>>,
-    mut payload: web::Payload,
+    payload: web::Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     if !query.contains_key("delete") {
@@ -318,14 +354,7 @@ pub async fn s3_delete_objects_handler(
 
     if let Err(resp) = require_bucket(&db, &bucket) { return Ok(resp); }
 
-    let mut body_bytes: Vec<u8> = Vec::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.map_err(|e| {
-            warn!("DeleteObjects: payload read error: {}", e);
-            actix_web::error::ErrorInternalServerError("Error reading payload")
-        })?;
-        body_bytes.extend_from_slice(&chunk);
-    }
+    let body_bytes = read_control_body(payload, PayloadLimits::of(&req)).await?;
     let body = String::from_utf8_lossy(&body_bytes);
 
     struct ObjReq {
@@ -364,7 +393,7 @@ pub async fn s3_delete_objects_handler(
     }
 
     let context = crate::service::user_context::UserContext::with_bucket(
-        auth_result.user_id.clone(), auth_result.bucket.clone()
+        auth_result.user_id.clone(), bucket.to_string()
     );
     let storage_service = crate::service::storage_service::StorageService::new();
 
@@ -1,22 +1,26 @@
 // All multipart handlers + GetObjectAttributes + GetPart + HeadPart + complete_multipart_xml_response.
 use actix_web::{web, HttpRequest, HttpResponse, Error, http::StatusCode};
 use bytes::Bytes;
-use futures::stream::{self, StreamExt as _};
-use log::{info, warn};
+use log::info;
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
 
 use serde_json;
 
-use crate::s3::auth::authenticate_s3_request;
+use crate::s3::auth::{authenticate_s3_request, authorize, S3Operation};
 use crate::service::metadata_service::MetadataService;
-use crate::service::storage_service::{StorageService, StorageMode};
+use crate::service::multipart_limits::{self, MultipartLimitConfig};
+use crate::service::size_stats::{self, Api, WriteType};
+use crate::service::storage_service::{read_ahead_chunks, StorageService, StorageMode};
 use crate::service::user_context::UserContext;
-use crate::storage::config::StorageConfig;
-use crate::util::serializer::deserialize_offset_size;
+use crate::service::upload_sessions;
+use crate::storage::bucket_lock;
+use crate::util::serializer::decode_extents;
+use crate::util::payload::{content_length, read_body, read_control_body, PayloadLimits};
 use crate::metadata::Metadata;
 
+use super::acl::{validate_key_present, validate_new_key_length};
+use super::aws_chunked::{is_aws_chunked, decode_body, trailer_checksum};
 use super::checksum::{ChecksumAlgorithm, compute_composite_checksum, verify_checksum};
 use super::common::*;
 use super::tagging::parse_url_tags;
@@ -42,7 +46,8 @@ pub(super) fn parse_complete_multipart_xml(body: &str) -> Vec<(i32, String)> {
     while let Some(idx) = rest.find("<Part>") {
         rest = &rest[idx + 6..];
         let part_num = extract_xml_tag(rest, "PartNumber").and_then(|s| s.trim().parse::<i32>().ok());
-        let etag = extract_xml_tag(rest, "ETag").map(|s| s.trim().to_string());
+        // SDKs escape the ETag's quotes as &quot;
+        let etag = extract_xml_tag(rest, "ETag").map(|s| xml_unescape(s.trim()));
         if let (Some(n), Some(e)) = (part_num, etag) {
             parts.push((n, e));
         }
@@ -50,6 +55,53 @@ pub(super) fn parse_complete_multipart_xml(body: &str) -> Vec<(i32, String)> {
     parts
 }
 
+/// Generate an upload ID: `mpu-` followed by 128 random bits in hex.
+fn new_upload_id() -> Result<String, Error> {
+    let mut id = [0u8; 16];
+    getrandom::getrandom(&mut id).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(format!("mpu-{}", hex::encode(id)))
+}
+
+/// Whether `upload_id` has the shape produced by `new_upload_id`.
+pub(super) fn is_valid_upload_id(upload_id: &str) -> bool {
+    upload_id.strip_prefix("mpu-")
+        .is_some_and(|h| h.len() == 32 && h.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)))
+}
+
+/// Look up `upload_id` for an operation on `bucket/key` by `user_id`. Malformed IDs and uploads
+/// initiated by another user or for another object are reported as missing.
+fn find_upload(db: &MetadataService, user_id: &str, upload_id: &str, bucket: &str, key: &str)
+    -> Result<Option<crate::metadata::sqlite_store::MultipartUploadRow>, Error>
+{
+    if !is_valid_upload_id(upload_id) {
+        return Ok(None);
+    }
+    Ok(db.get_multipart_upload(upload_id)?
+        .filter(|row| row.user_id == user_id && row.bucket == bucket && row.key == key))
+}
+
+fn no_such_upload(bucket: &str, key: &str) -> HttpResponse {
+    s3_error(StatusCode::NOT_FOUND, "NoSuchUpload",
+             "The specified upload does not exist", &format!("/{}/{}", bucket, key))
+}
+
+/// 400 `EntityTooLarge` when a part of `part_bytes` would take `upload_id` past
+/// `MULTIPART_MAX_UPLOAD_BYTES`
+fn check_upload_size(
+    db: &MetadataService, limits: &MultipartLimitConfig, upload_id: &str, part_number: i32, part_bytes: u64,
+    bucket: &str, key: &str,
+) -> Result<Option<HttpResponse>, Error> {
+    let other_parts = db.multipart_part_bytes(upload_id, part_number)?;
+    if !limits.upload_too_large(other_parts, part_bytes) {
+        return Ok(None);
+    }
+    multipart_limits::record_rejection("upload_bytes");
+    Ok(Some(s3_error(StatusCode::BAD_REQUEST, "EntityTooLarge",
+        &format!("Your proposed upload exceeds the maximum allowed size of {} bytes for one multipart upload",
+                 limits.max_upload_bytes),
+        &format!("/{}/{}", bucket, key))))
+}
+
 // ---------------------------------------------------------------------------
 // CreateMultipartUpload  POST /s3/{bucket}/{key}?uploads
 // ---------------------------------------------------------------------------
@@ -64,6 +116,7 @@ pub async fn s3_create_multipart_upload_handler(
         return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidRequest",
                            "Invalid multipart upload initiation request", &bucket));
     }
+    if let Err(resp) = validate_new_key_length(&key, &bucket) { return Ok(resp); }
 
     let auth_result = authenticate_s3_request(&req).await?;
     let db = MetadataService::new(&auth_result.user_id)?;
@@ -86,7 +139,7 @@ pub async fn s3_create_multipart_upload_handler(
         .collect();
     let metadata_json = serde_json::to_string(&user_metadata).unwrap_or_else(|_| "{}".to_string());
 
-    let upload_id = format!("mpu-{}", chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0));
+    let upload_id = new_upload_id()?;
     let initiated_at = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S.000Z").to_string();
 
     // Parse checksum algorithm and type from request headers
@@ -115,12 +168,21 @@ pub async fn s3_create_multipart_upload_handler(
     let mpu_legal_hold = req.headers().get("x-amz-object-lock-legal-hold")
         .and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
 
-    db.create_multipart_upload(
+    let limits = MultipartLimitConfig::from_env();
+    let created = db.create_multipart_upload(
         &upload_id, &bucket, &key,
         content_type.as_deref(), &metadata_json, &initiated_at,
         &mpu_checksum_algo, &mpu_checksum_type,
         &mpu_lock_mode, &mpu_lock_until, &mpu_legal_hold,
+        limits.max_uploads_per_user,
     )?;
+    if !created {
+        multipart_limits::record_rejection("uploads_per_user");
+        return Ok(s3_error(StatusCode::SERVICE_UNAVAILABLE, "ServiceUnavailable",
+            &format!("You have {} multipart uploads in progress, the most allowed; complete or abort one first",
+                     limits.max_uploads_per_user.unwrap_or_default()),
+            &format!("/{}/{}", bucket, key)));
+    }
 
     if let Some(tagging_str) = req.headers().get("x-amz-tagging").and_then(|v| v.to_str().ok()) {
         db.set_multipart_tagging(&upload_id, tagging_str)?;
@@ -156,7 +218,7 @@ pub async fn s3_create_multipart_upload_handler(
 pub async fn s3_upload_part_handler(
     path: web::Path<(String, String)>,
     query: web::Query<HashMap<String, String>>,
-    mut payload: web::Payload,
+    payload: web::Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let (bucket, key) = path.into_inner();
@@ -171,37 +233,50 @@ pub async fn s3_upload_part_handler(
     let auth_result = authenticate_s3_request(&req).await?;
     let db = MetadataService::new(&auth_result.user_id)?;
 
-    match db.get_multipart_upload(&upload_id)? {
+    match find_upload(&db, &auth_result.user_id, &upload_id, &bucket, &key)? {
         Some(row) if row.status == "in_progress" => {}
-        _ => return Ok(s3_error(StatusCode::NOT_FOUND, "NoSuchUpload",
-                                "The specified upload does not exist", &format!("/{}/{}", bucket, key))),
+        _ => return Ok(no_such_upload(&bucket, &key)),
     }
 
-    let mut body: Vec<u8> = Vec::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.map_err(|e| {
-            warn!("UploadPart: read error: {}", e);
-            actix_web::error::ErrorInternalServerError("Error reading payload")
-        })?;
-        body.extend_from_slice(&chunk);
+    let mut body = Vec::from(read_body(payload, content_length(&req), PayloadLimits::of(&req)).await?);
+    let mut trailers = Vec::new();
+    if is_aws_chunked(&req) {
+        match decode_body(&req, &body) {
+            Ok((decoded, decoded_trailers)) => (body, trailers) = (decoded, decoded_trailers),
+            Err(e) => return Ok(e.to_s3_error(&format!("/{}/{}", bucket, key))),
+        }
+    }
+    let trailer = trailer_checksum(&trailers);
+    let limits = MultipartLimitConfig::from_env();
+    if let Some(resp) = check_upload_size(&db, &limits, &upload_id, part_number, body.len() as u64, &bucket, &key)? {
+        return Ok(resp);
     }
+    db.check_part_quota(&bucket, &upload_id, part_number, body.len() as u64)
+        .map_err(|e| s3_quota_error(e, &format!("/{}/{}", bucket, key)))?;
 
-    let context = UserContext::with_bucket(auth_result.user_id.clone(), auth_result.bucket.clone());
+    let context = UserContext::with_bucket(auth_result.user_id.clone(), bucket.to_string());
     let storage_service = StorageService::new();
+    let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
     let offset_size_list = storage_service.write_object(&context, &body, StorageMode::S3)?;
-    let extents_blob = crate::util::serializer::serialize_offset_size(&offset_size_list)?;
+    let extents_blob = crate::util::serializer::encode_extents(&offset_size_list)?;
 
     let etag = format!("\"{}\"", hex::encode(md5::compute(&body).0));
 
     // Parse and verify per-part checksum
     let part_checksum_algo = req.headers().get("x-amz-sdk-checksum-algorithm")
         .or_else(|| req.headers().get("x-amz-checksum-algorithm"))
-        .and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| trailer.as_ref().map(|(algo, _)| algo.as_str()))
+        .unwrap_or("").to_string();
     let part_checksum_value = if !part_checksum_algo.is_empty() {
         if let Some(algo) = ChecksumAlgorithm::from_str(&part_checksum_algo) {
             let header_name = format!("x-amz-checksum-{}", algo.header_suffix());
+            let trailer_value = trailer.as_ref()
+                .filter(|(trailer_algo, _)| *trailer_algo == algo)
+                .map(|(_, value)| value.as_str());
             if let Some(client_value) = req.headers().get(header_name.as_str())
                 .and_then(|v| v.to_str().ok())
+                .or(trailer_value)
             {
                 if !verify_checksum(&algo, &body, client_value) {
                     return Ok(s3_error(StatusCode::BAD_REQUEST, "BadDigest",
@@ -219,7 +294,10 @@ pub async fn s3_upload_part_handler(
         String::new()
     };
 
-    db.upsert_multipart_part(&upload_id, part_number, &etag, body.len() as u64, &extents_blob, &part_checksum_value)?;
+    if let Err(e) = db.upsert_multipart_part(&bucket, &upload_id, part_number, &etag, body.len() as u64, &extents_blob, &part_checksum_value) {
+        db.queue_deletion(&bucket, &key, &offset_size_list)?;
+        return Err(s3_quota_error(e, &format!("/{}/{}", bucket, key)));
+    }
 
     info!("S3 UploadPart: bucket={} key={} part={} size={}", bucket, key, part_number, body.len());
     let mut part_resp = HttpResponse::Ok();
@@ -251,6 +329,10 @@ pub async fn s3_upload_part_copy_handler(
 
     let auth_result = authenticate_s3_request(&req).await?;
     let db = MetadataService::new(&auth_result.user_id)?;
+    match find_upload(&db, &auth_result.user_id, &upload_id, &bucket, &key)? {
+        Some(row) if row.status == "in_progress" => {}
+        _ => return Ok(no_such_upload(&bucket, &key)),
+    }
 
     let copy_source = match req.headers().get("x-amz-copy-source") {
         Some(h) => h.to_str().unwrap_or("").to_string(),
@@ -259,11 +341,12 @@ pub async fn s3_upload_part_copy_handler(
     };
     let source = copy_source.trim_start_matches('/');
     let (src_bucket, src_key_enc) = match source.splitn(2, '/').collect::<Vec<_>>().as_slice() {
-        [b, k] => (b.to_string(), k.to_string()),
+        [b, k] if !k.is_empty() => (b.to_string(), k.to_string()),
         _ => return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
                                 "Invalid x-amz-copy-source", &bucket)),
     };
     let src_key = percent_decode(&src_key_enc);
+    authorize(&auth_result, &src_bucket, S3Operation::Read)?;
 
     if !db.check_key(&src_bucket, &src_key)? {
         return Ok(s3_error(StatusCode::NOT_FOUND, "NoSuchKey",
@@ -276,7 +359,7 @@ pub async fn s3_upload_part_copy_handler(
     let copy_range_header = req.headers().get("x-amz-copy-source-range")
         .and_then(|v| v.to_str().ok()).map(|s| s.to_string());
 
-    let (read_extents, part_size) = if let Some(ref range_str) = copy_range_header {
+    let (range_start, read_extents, part_size) = if let Some(ref range_str) = copy_range_header {
         let bytes_part = match range_str.strip_prefix("bytes=") {
             Some(b) => b,
             None => return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
@@ -301,30 +384,37 @@ pub async fn s3_upload_part_copy_handler(
             return Ok(s3_error(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange",
                                "The x-amz-copy-source-range value is not valid", &bucket));
         }
-        (range_slices(&src_extents, start, end), end - start + 1)
+        (start, range_slices(&src_extents, start, end), end - start + 1)
     } else {
-        (range_slices(&src_extents, 0, src_size.saturating_sub(1)), src_size)
+        (0, range_slices(&src_extents, 0, src_size.saturating_sub(1)), src_size)
     };
 
+    let part_number_i32: i32 = part_number.parse()
+        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid partNumber"))?;
+    let limits = MultipartLimitConfig::from_env();
+    if let Some(resp) = check_upload_size(&db, &limits, &upload_id, part_number_i32, part_size, &bucket, &key)? {
+        return Ok(resp);
+    }
+    db.check_part_quota(&bucket, &upload_id, part_number_i32, part_size)
+        .map_err(|e| s3_quota_error(e, &format!("/{}/{}", bucket, key)))?;
+
     let storage_service = StorageService::new();
     let src_context = UserContext::with_bucket(auth_result.user_id.clone(), src_bucket.clone());
-    let part_bytes = storage_service.read_object(&src_context, &read_extents, StorageMode::S3)?;
+    let part_bytes = match &src_meta.inline_data {
+        Some(data) => Bytes::copy_from_slice(&data[range_start as usize..(range_start + part_size) as usize]),
+        None => storage_service.read_object(&src_context, &read_extents, StorageMode::S3)?,
+    };
 
-    let dst_context = UserContext::with_bucket(auth_result.user_id.clone(), auth_result.bucket.clone());
+    let dst_context = UserContext::with_bucket(auth_result.user_id.clone(), bucket.to_string());
+    let _write_guard = bucket_lock::begin_write(&dst_context.user_id, &dst_context.bucket).await;
     let offset_size_list = storage_service.write_object(&dst_context, &part_bytes, StorageMode::S3)?;
 
-    let part_number_i32: i32 = part_number.parse()
-        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid partNumber"))?;
-
-    match db.get_multipart_upload(&upload_id)? {
-        Some(row) if row.status == "in_progress" => {}
-        _ => return Ok(s3_error(StatusCode::NOT_FOUND, "NoSuchUpload",
-                                "The specified upload does not exist", &format!("/{}/{}", bucket, key))),
-    }
-
-    let extents_blob = crate::util::serializer::serialize_offset_size(&offset_size_list)?;
+    let extents_blob = crate::util::serializer::encode_extents(&offset_size_list)?;
     let etag = format!("\"{}\"", hex::encode(md5::compute(&part_bytes).0));
-    db.upsert_multipart_part(&upload_id, part_number_i32, &etag, part_size, &extents_blob, "")?;
+    if let Err(e) = db.upsert_multipart_part(&bucket, &upload_id, part_number_i32, &etag, part_size, &extents_blob, "") {
+        db.queue_deletion(&bucket, &key, &offset_size_list)?;
+        return Err(s3_quota_error(e, &format!("/{}/{}", bucket, key)));
+    }
 
     let last_modified = last_modified_now();
     let xml = format!(
@@ -347,7 +437,7 @@ pub async fn s3_upload_part_copy_handler(
 pub async fn s3_complete_multipart_upload_handler(
     path: web::Path<(String, String)>,
     query: web::Query<HashMap<String, String>>,
-    mut payload: web::Payload,
+    payload: web::Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let (bucket, key) = path.into_inner();
@@ -387,14 +477,7 @@ pub async fn s3_complete_multipart_upload_handler(
         }
     }
 
-    let mut body_bytes: Vec<u8> = Vec::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.map_err(|e| {
-            warn!("CompleteMultipartUpload: read error: {}", e);
-            actix_web::error::ErrorInternalServerError("Error reading payload")
-        })?;
-        body_bytes.extend_from_slice(&chunk);
-    }
+    let body_bytes = read_control_body(payload, PayloadLimits::of(&req)).await?;
     let body_str = String::from_utf8_lossy(&body_bytes);
 
     let raw_parts = parse_complete_multipart_xml(&body_str);
@@ -411,7 +494,7 @@ pub async fn s3_complete_multipart_upload_handler(
                            "The XML you provided was not well-formed or did not validate", &bucket));
     }
 
-    let upload_row = match db.get_multipart_upload(&upload_id)? {
+    let upload_row = match find_upload(&db, &auth_result.user_id, &upload_id, &bucket, &key)? {
         Some(row) if row.status == "completed" => {
             let etag = row.final_etag.unwrap_or_default();
             // For idempotent re-completion, look up stored checksum from current object metadata
@@ -427,10 +510,7 @@ pub async fn s3_complete_multipart_upload_handler(
             return Ok(idem_resp);
         }
         Some(row) if row.status == "in_progress" => row,
-        Some(_) | None => {
-            return Ok(s3_error(StatusCode::NOT_FOUND, "NoSuchUpload",
-                               "The specified upload does not exist", &format!("/{}/{}", bucket, key)));
-        }
+        Some(_) | None => return Ok(no_such_upload(&bucket, &key)),
     };
 
     let stored_parts = db.list_multipart_parts(&upload_id)?;
@@ -468,7 +548,7 @@ pub async fn s3_complete_multipart_upload_handler(
     let mut manifest: Vec<PartEntry> = Vec::new();
     for (part_num, _) in &requested_parts {
         let p = &stored_map[part_num];
-        let exts = deserialize_offset_size(&p.extents_blob)?;
+        let exts = decode_extents(&p.extents_blob)?;
         let ext_arr: Vec<[u64; 2]> = exts.iter().map(|&(o, s)| [o, s]).collect();
         final_extents.extend_from_slice(&exts);
         let part_cksum = if p.checksum_value.is_empty() { None } else { Some(p.checksum_value.clone()) };
@@ -549,7 +629,10 @@ pub async fn s3_complete_multipart_upload_handler(
     final_metadata.checksum_algorithm = final_checksum_algo.clone();
     final_metadata.checksum_value = final_checksum_value.clone();
     final_metadata.checksum_type = final_checksum_type.clone();
-    let (mpu_vid, mpu_old_extents) = db.put_object_full(&bucket, &key, final_metadata)?;
+    let part_sizes = size_stats::chunk_sizes(&final_metadata);
+    let (mpu_vid, mpu_old_extents) = db.completing(&upload_id).put_object_full(&bucket, &key, final_metadata)
+        .map_err(|e| s3_quota_error(e, &format!("/{}/{}", bucket, key)))?;
+    size_stats::record(Api::S3, WriteType::Multipart, total_size, &part_sizes);
     if !mpu_old_extents.is_empty() {
         db.queue_deletion(&bucket, &key, &mpu_old_extents).ok();
     }
@@ -650,16 +733,14 @@ pub async fn s3_abort_multipart_upload_handler(
     let db = MetadataService::new(&auth_result.user_id)?;
     if let Err(resp) = require_bucket(&db, &bucket) { return Ok(resp); }
 
-    match db.get_multipart_upload(&upload_id)? {
+    match find_upload(&db, &auth_result.user_id, &upload_id, &bucket, &key)? {
         Some(row) if row.status == "in_progress" => {}
-        _ => return Ok(s3_error(StatusCode::NOT_FOUND, "NoSuchUpload",
-                                "The specified upload does not exist",
-                                &format!("/{}/{}", bucket, key))),
+        _ => return Ok(no_such_upload(&bucket, &key)),
     }
 
     let parts = db.list_multipart_parts(&upload_id)?;
     for part in &parts {
-        let extents = deserialize_offset_size(&part.extents_blob)?;
+        let extents = decode_extents(&part.extents_blob)?;
         db.queue_deletion(&bucket, &key, &extents)?;
     }
     db.delete_parts_for_upload(&upload_id)?;
@@ -681,6 +762,7 @@ pub async fn s3_multipart_router(
     payload: web::Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
+    if let Err(resp) = validate_key_present(&path.1, &path.0) { return Ok(resp); }
     if query.contains_key("uploads") {
         s3_create_multipart_upload_handler(path, query, req).await
     } else if query.contains_key("uploadId") {
@@ -699,7 +781,10 @@ pub(super) async fn s3_list_multipart_uploads_handler(bucket: &str, req: &HttpRe
     let db = MetadataService::new(&auth_result.user_id)?;
     if let Err(resp) = require_bucket(&db, bucket) { return Ok(resp); }
 
-    let uploads = db.list_multipart_uploads_for_bucket(bucket)?;
+    // Native upload sessions share the table but are not S3 uploads
+    let uploads: Vec<_> = db.list_multipart_uploads_for_bucket(bucket)?.into_iter()
+        .filter(|upload| !upload_sessions::is_session_id(&upload.upload_id))
+        .collect();
 
     let mut uploads_xml = String::new();
     for upload in &uploads {
@@ -737,10 +822,82 @@ pub(super) async fn s3_list_multipart_uploads_handler(bucket: &str, req: &HttpRe
     Ok(HttpResponse::Ok().content_type("application/xml").body(xml))
 }
 
+// ---------------------------------------------------------------------------
+// ListParts  GET /s3/{bucket}/{key}?uploadId=ID
+// ---------------------------------------------------------------------------
+
+pub(super) async fn s3_list_parts_handler(bucket: &str, key: &str, upload_id: &str, req: &HttpRequest) -> Result<HttpResponse, Error> {
+    let auth_result = authenticate_s3_request(req).await?;
+    let db = MetadataService::new(&auth_result.user_id)?;
+    if let Err(resp) = require_bucket(&db, bucket) { return Ok(resp); }
+
+    match find_upload(&db, &auth_result.user_id, upload_id, bucket, key)? {
+        Some(row) if row.status == "in_progress" => {}
+        _ => return Ok(no_such_upload(bucket, key)),
+    }
+
+    let qmap: HashMap<String, String> = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|q| q.into_inner()).unwrap_or_default();
+    let marker: i32 = qmap.get("part-number-marker").and_then(|v| v.parse().ok()).unwrap_or(0);
+    let max_parts: usize = qmap.get("max-parts").and_then(|v| v.parse().ok()).unwrap_or(1000).min(1000);
+
+    let mut parts = db.list_multipart_parts(upload_id)?;
+    parts.sort_by_key(|p| p.part_number);
+    parts.retain(|p| p.part_number > marker);
+    let truncated = parts.len() > max_parts;
+    parts.truncate(max_parts);
+    let next_marker = parts.last().map_or(marker, |p| p.part_number);
+
+    let uid = xml_escape(&auth_result.user_id);
+    let mut parts_xml = String::new();
+    for part in &parts {
+        parts_xml.push_str(&format!(
+            "<Part><PartNumber>{n}</PartNumber><ETag>{etag}</ETag><Size>{size}</Size></Part>",
+            n = part.part_number,
+            etag = xml_escape(&part.etag),
+            size = part.size,
+        ));
+    }
+
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <ListPartsResult xmlns=\"{s3}\">\
+           <Bucket>{bucket}</Bucket>\
+           <Key>{key}</Key>\
+           <UploadId>{upid}</UploadId>\
+           <Initiator><ID>{uid}</ID><DisplayName>{uid}</DisplayName></Initiator>\
+           <Owner><ID>{uid}</ID><DisplayName>{uid}</DisplayName></Owner>\
+           <StorageClass>STANDARD</StorageClass>\
+           <PartNumberMarker>{marker}</PartNumberMarker>\
+           <NextPartNumberMarker>{next}</NextPartNumberMarker>\
+           <MaxParts>{max}</MaxParts>\
+           <IsTruncated>{truncated}</IsTruncated>\
+           {parts}\
+         </ListPartsResult>",
+        s3 = S3_XMLNS, bucket = xml_escape(bucket), key = xml_escape(key), upid = xml_escape(upload_id),
+        uid = uid, marker = marker, next = next_marker, max = max_parts, truncated = truncated, parts = parts_xml,
+    );
+    Ok(HttpResponse::Ok().content_type("application/xml").body(xml))
+}
+
 // ---------------------------------------------------------------------------
 // GetObjectAttributes  GET /s3/{bucket}/{key}?attributes
 // ---------------------------------------------------------------------------
 
+/// Attributes named in `x-amz-object-attributes` (comma-separated, possibly repeated).
+/// Names are kept as sent; unknown ones simply never match. Without the header every
+/// attribute is returned.
+fn requested_attributes(req: &HttpRequest) -> Option<HashSet<String>> {
+    let mut values = req.headers().get_all("x-amz-object-attributes").peekable();
+    values.peek()?;
+    Some(values
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect())
+}
+
 pub(super) async fn s3_get_object_attributes_handler(bucket: &str, key: &str, req: &HttpRequest) -> Result<HttpResponse, Error> {
     let auth_result = authenticate_s3_request(req).await?;
     let db = MetadataService::new(&auth_result.user_id)?;
@@ -752,6 +909,8 @@ pub(super) async fn s3_get_object_attributes_handler(bucket: &str, key: &str, re
     }
     let meta = db.get_object_full(bucket, key)?;
     let etag_raw = meta.etag.as_deref().map(normalize_etag).unwrap_or("").to_string();
+    let requested = requested_attributes(req);
+    let wants = |name: &str| requested.as_ref().is_none_or(|set| set.contains(name));
 
     let max_parts: usize = req.headers().get("x-amz-max-parts")
         .and_then(|v| v.to_str().ok()).and_then(|s| s.parse().ok()).unwrap_or(1000);
@@ -760,10 +919,11 @@ pub(super) async fn s3_get_object_attributes_handler(bucket: &str, key: &str, re
 
     // Pre-compute checksum algo for parts XML building
     let checksum_algo_for_parts = meta.checksum_algorithm.as_deref()
-        .and_then(|s| ChecksumAlgorithm::from_str(s));
+        .and_then(ChecksumAlgorithm::from_str);
 
     let mut object_parts_xml = String::new();
-    if let Some(manifest_json) = db.get_parts_manifest(bucket, key)? {
+    let manifest = if wants("ObjectParts") { db.get_parts_manifest(bucket, key)? } else { None };
+    if let Some(manifest_json) = manifest {
         if let Ok(parts) = serde_json::from_str::<Vec<PartEntry>>(&manifest_json) {
             let total = parts.len();
             let eligible: Vec<&PartEntry> = parts.iter().filter(|p| p.n > part_number_marker).collect();
@@ -809,7 +969,7 @@ pub(super) async fn s3_get_object_attributes_handler(bucket: &str, key: &str, re
 
     // Build checksum XML if object has a stored checksum
     let mut checksum_xml = String::new();
-    if let (Some(ref algo_str), Some(ref cksum_val)) = (&meta.checksum_algorithm, &meta.checksum_value) {
+    if let (true, Some(ref algo_str), Some(ref cksum_val)) = (wants("Checksum"), &meta.checksum_algorithm, &meta.checksum_value) {
         if let Some(algo) = ChecksumAlgorithm::from_str(algo_str) {
             let cksum_type_xml = if let Some(ref ct) = meta.checksum_type {
                 if !ct.is_empty() {
@@ -829,18 +989,25 @@ pub(super) async fn s3_get_object_attributes_handler(bucket: &str, key: &str, re
         }
     }
 
+    let etag_xml = if wants("ETag") {
+        format!("<ETag>{}</ETag>", xml_escape(&etag_raw))
+    } else { String::new() };
+    let storage_class_xml = if wants("StorageClass") {
+        "<StorageClass>STANDARD</StorageClass>".to_string()
+    } else { String::new() };
+    let size_xml = if wants("ObjectSize") {
+        format!("<ObjectSize>{}</ObjectSize>", meta.size)
+    } else { String::new() };
+
     let xml = format!(
         "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
          <GetObjectAttributesResponse xmlns=\"{s3}\">\
-           <ETag>{etag}</ETag>\
-           <StorageClass>STANDARD</StorageClass>\
-           <ObjectSize>{sz}</ObjectSize>\
-           {checksum}\
-           {parts}\
+           {etag}{storage_class}{sz}{checksum}{parts}\
          </GetObjectAttributesResponse>",
         s3 = S3_XMLNS,
-        etag = xml_escape(&etag_raw),
-        sz = meta.size,
+        etag = etag_xml,
+        storage_class = storage_class_xml,
+        sz = size_xml,
         checksum = checksum_xml,
         parts = object_parts_xml,
     );
@@ -880,22 +1047,12 @@ pub(super) async fn s3_get_part_handler(bucket: &str, key: &str, part_num: i32,
             let extents: Vec<(u64, u64)> = part.ext.iter().map(|e| (e[0], e[1])).collect();
             let part_size = part.sz;
 
-            let slices = Arc::new(stream_slices(&extents));
-            let store = StorageConfig::from_env().create_store();
-            let context = UserContext::with_bucket(auth_result.user_id.clone(), auth_result.bucket.clone());
-            let byte_stream = stream::try_unfold(0usize, move |idx| {
-                let slices = Arc::clone(&slices);
-                let ctx = context.clone();
-                let store = Arc::clone(&store);
-                async move {
-                    if idx >= slices.len() { return Ok::<Option<(Bytes, usize)>, Error>(None); }
-                    let (off, sz) = slices[idx];
-                    let chunk = web::block(move || store.read(&ctx.user_id, &ctx.bucket, off, sz).map_err(|e| e.to_string()))
-                        .await.map_err(actix_web::error::ErrorInternalServerError)?
-                        .map_err(actix_web::error::ErrorInternalServerError)?;
-                    Ok(Some((Bytes::from(chunk), idx + 1)))
-                }
-            });
+            let slices = stream_slices(&extents);
+            let context = UserContext::with_bucket(auth_result.user_id.clone(), bucket.to_string());
+            let storage = StorageService::new();
+            storage.check_readable(&context, &slices)
+                .map_err(|e| s3_read_error(e, &format!("/{}/{}", bucket, key)))?;
+            let byte_stream = storage.chunk_stream(&context, slices, read_ahead_chunks());
 
             let mut resp = HttpResponse::Ok();
             resp.content_type(content_type.as_str());
@@ -927,23 +1084,19 @@ pub(super) async fn s3_get_part_handler(bucket: &str, key: &str, part_num: i32,
     }
 
     let total_size = meta.size;
+    if let Some(data) = meta.inline_data {
+        return Ok(HttpResponse::Ok()
+            .content_type(content_type.as_str())
+            .insert_header(("ETag", etag))
+            .body(data));
+    }
     let extents = meta.to_offset_size_list();
-    let slices = Arc::new(stream_slices(&extents));
-    let store = StorageConfig::from_env().create_store();
-    let context = UserContext::with_bucket(auth_result.user_id.clone(), auth_result.bucket.clone());
-    let byte_stream = stream::try_unfold(0usize, move |idx| {
-        let slices = Arc::clone(&slices);
-        let ctx = context.clone();
-        let store = Arc::clone(&store);
-        async move {
-            if idx >= slices.len() { return Ok::<Option<(Bytes, usize)>, Error>(None); }
-            let (off, sz) = slices[idx];
-            let chunk = web::block(move || store.read(&ctx.user_id, &ctx.bucket, off, sz).map_err(|e| e.to_string()))
-                .await.map_err(actix_web::error::ErrorInternalServerError)?
-                .map_err(actix_web::error::ErrorInternalServerError)?;
-            Ok(Some((Bytes::from(chunk), idx + 1)))
-        }
-    });
+    let slices = stream_slices(&extents);
+    let context = UserContext::with_bucket(auth_result.user_id.clone(), bucket.to_string());
+    let storage = StorageService::new();
+    storage.check_readable(&context, &slices)
+        .map_err(|e| s3_read_error(e, &format!("/{}/{}", bucket, key)))?;
+    let byte_stream = storage.chunk_stream(&context, slices, read_ahead_chunks());
 
     let mut resp = HttpResponse::Ok();
     resp.content_type(content_type.as_str());
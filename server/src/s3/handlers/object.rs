@@ -1,37 +1,74 @@
 // PutObject, GetObject, HeadObject, DeleteObject handlers.
 use actix_web::{web, HttpRequest, HttpResponse, Error, http::StatusCode};
 use bytes::Bytes;
-use futures::stream::{self, StreamExt as _};
+use futures::{stream, StreamExt};
 use log::{debug, error, info, warn};
 
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::metadata::Metadata;
+use crate::metadata::{DeletionSummary, Metadata};
 use crate::s3::auth::{authenticate_s3_request, create_authenticated_request};
+use crate::service::access_tracking;
+use crate::service::expiry;
+use crate::service::idempotency::{self, IdempotencyConfig, IdempotencyRecord, IDEMPOTENCY_KEY_HEADER, REPLAYED_HEADER};
+use crate::service::inline_objects::InlineConfig;
 use crate::service::metadata_service::MetadataService;
-use crate::service::storage_service::StorageService;
+use crate::service::object_format::{ObjectFormat, OBJECT_FORMAT_HEADER};
+use crate::service::payload_digest::{self, Direction};
+use crate::service::response_fields::ResponseFields;
+use crate::service::size_stats::{self, Api, WriteType};
+use crate::service::storage_service::{read_ahead_chunks, StorageService};
 use crate::service::user_context::UserContext;
+use crate::service::validators::{self, GENERATION_HEADER};
 use crate::storage::config::StorageConfig;
+use crate::storage::bucket_lock;
+use crate::util::byte_ranges::{self, ByteRanges, Multipart, RangeConfig};
+use crate::util::compression::{accept_encoding, compress_stream, CompressionConfig};
+use crate::util::payload::{content_length, initial_capacity, read_control_body, next_chunk_limited, PayloadLimits};
 
+use super::aws_chunked::{is_aws_chunked, decoded_content_length, trailer_checksum, AwsChunkedDecoder};
 use super::checksum::{parse_checksum_headers, verify_checksum, ChecksumAlgorithm};
 use super::common::*;
 use super::tagging::{s3_put_object_tagging_inner, s3_get_object_tagging_inner, s3_delete_object_tagging_inner, parse_url_tags, validate_tags};
 use super::versioning::{s3_get_object_version_handler, s3_delete_specific_version_handler};
-use super::acl::{s3_put_acl_stub, s3_get_object_acl_stub, validate_object_key};
+use super::acl::{s3_put_acl_stub, s3_get_object_acl_stub, validate_object_key, validate_key_present, validate_new_key_length};
 use super::copy::s3_copy_object_handler;
-use super::multipart::{s3_upload_part_handler, s3_upload_part_copy_handler, s3_abort_multipart_upload_handler, s3_get_object_attributes_handler, s3_get_part_handler, s3_head_part_handler};
-use super::object_lock::{s3_put_object_retention_inner, s3_get_object_retention_inner, s3_put_object_legal_hold_inner, s3_get_object_legal_hold_inner, compute_retain_until};
+use super::multipart::{s3_upload_part_handler, s3_upload_part_copy_handler, s3_abort_multipart_upload_handler, s3_get_object_attributes_handler, s3_get_part_handler, s3_head_part_handler, s3_list_parts_handler};
+use super::object_lock::{s3_put_object_retention_inner, s3_get_object_retention_inner, s3_put_object_legal_hold_inner, s3_get_object_legal_hold_inner, compute_retain_until, parse_retain_until, bypass_governance, in_place_lock, retained_until, insert_object_lock_headers};
+
+/// Response header of DeleteObject: bytes queued for the deletion worker to reclaim
+pub const RECLAIMED_BYTES_HEADER: &str = "x-warp-reclaimed-bytes";
 
 // ---------------------------------------------------------------------------
 // PutObject  PUT /s3/{bucket}/{key}
 // ---------------------------------------------------------------------------
 
+/// The decoded body of a PutObject retry, to compare with the PUT it repeats; nothing of it
+/// is written
+async fn retried_body(mut payload: web::Payload, req: &HttpRequest, resource: &str) -> Result<Result<Vec<u8>, HttpResponse>, Error> {
+    let limits = PayloadLimits::of(req);
+    let mut received = 0usize;
+    let mut chunked = is_aws_chunked(req).then(AwsChunkedDecoder::new);
+    let mut body = Vec::new();
+    while let Some(chunk) = next_chunk_limited(&mut payload, &mut received, limits.max_bytes, limits.idle_timeout).await? {
+        match chunked.as_mut() {
+            Some(decoder) => match decoder.feed(&chunk) {
+                Ok(data) => body.extend_from_slice(&data),
+                Err(e) => return Ok(Err(e.to_s3_error(resource))),
+            },
+            None => body.extend_from_slice(&chunk),
+        }
+    }
+    Ok(Ok(body))
+}
+
 pub async fn s3_put_object_handler(
     path: web::Path<(String, String)>,
     mut payload: web::Payload,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
+    if let Err(resp) = validate_key_present(&path.1, &path.0) { return Ok(resp); }
     if let Ok(query) = web::Query::<HashMap<String, String>>::from_query(req.query_string()) {
         if req.headers().contains_key("x-amz-copy-source")
             && query.contains_key("partNumber")
@@ -44,10 +81,7 @@ pub async fn s3_put_object_handler(
         }
         if query.contains_key("tagging") {
             let (bucket, key) = path.into_inner();
-            let mut body: Vec<u8> = Vec::new();
-            while let Some(chunk) = payload.next().await {
-                body.extend_from_slice(&chunk.map_err(actix_web::error::ErrorInternalServerError)?);
-            }
+            let body = read_control_body(payload, PayloadLimits::of(&req)).await?;
             return s3_put_object_tagging_inner(&bucket, &key, &body, &req).await;
         }
         if query.contains_key("acl") {
@@ -55,16 +89,14 @@ pub async fn s3_put_object_handler(
         }
         if query.contains_key("retention") || query.contains_key("legal-hold") {
             let (bucket, key) = path.into_inner();
-            let mut body: Vec<u8> = Vec::new();
-            while let Some(chunk) = payload.next().await {
-                body.extend_from_slice(&chunk.map_err(actix_web::error::ErrorInternalServerError)?);
-            }
+            let body = read_control_body(payload, PayloadLimits::of(&req)).await?;
             if query.contains_key("retention") {
                 return s3_put_object_retention_inner(&bucket, &key, &body, &req).await;
             }
             return s3_put_object_legal_hold_inner(&bucket, &key, &body, &req).await;
         }
     }
+    if let Err(resp) = validate_new_key_length(&path.1, &path.0) { return Ok(resp); }
     if req.headers().contains_key("x-amz-copy-source") {
         return s3_copy_object_handler(path, req).await;
     }
@@ -75,11 +107,64 @@ pub async fn s3_put_object_handler(
 
     let db = MetadataService::new(&auth_result.user_id)?;
     if let Err(resp) = require_bucket(&db, &bucket) { return Ok(resp); }
+    // Refuse a new object in a full bucket before any of its data is written
+    db.check_quota(&bucket, &[(&key, 0)], true).map_err(|e| s3_quota_error(e, &format!("/{}/{}", bucket, key)))?;
+
+    // Object lock headers are checked, and a locked object refused, before any data is written
+    let lock_resource = format!("/{}/{}", bucket, key);
+    let lock_header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok()).map(|s| s.trim().to_string());
+    let obj_lock_mode = lock_header("x-amz-object-lock-mode");
+    let obj_lock_until = match lock_header("x-amz-object-lock-retain-until-date") {
+        Some(date) => match parse_retain_until(&date) {
+            Some(until) => Some(until),
+            None => return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
+                                       "x-amz-object-lock-retain-until-date must be an ISO 8601 date", &lock_resource)),
+        },
+        None => None,
+    };
+    let obj_legal_hold = lock_header("x-amz-object-lock-legal-hold");
+    if obj_lock_mode.is_some() != obj_lock_until.is_some() {
+        return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
+                           "x-amz-object-lock-mode and x-amz-object-lock-retain-until-date must be given together",
+                           &lock_resource));
+    }
+    if obj_lock_mode.as_deref().is_some_and(|m| m != "COMPLIANCE" && m != "GOVERNANCE") {
+        return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
+                           "x-amz-object-lock-mode must be COMPLIANCE or GOVERNANCE", &lock_resource));
+    }
+    if obj_legal_hold.as_deref().is_some_and(|h| h != "ON" && h != "OFF") {
+        return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
+                           "x-amz-object-lock-legal-hold must be ON or OFF", &lock_resource));
+    }
+    let expire_at = match expiry::requested(&req) {
+        Ok(expire_at) => expire_at,
+        Err(e) => return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", &e.to_string(), &lock_resource)),
+    };
+    // Without versioning the write replaces the current object, which its own lock protects
+    // whatever the bucket's object lock setting; its retention carries over unless extended.
+    let replaced_lock = in_place_lock(&db, &bucket, &key)?;
+    let mut carried_retention: Option<(String, String)> = None;
+    if let Some((ref vid, ref lock)) = replaced_lock {
+        let (ret_blocked, hold_blocked) = db.check_object_lock_protection(&bucket, &key, vid, bypass_governance(&req))?;
+        if ret_blocked || hold_blocked {
+            return Ok(s3_error(StatusCode::FORBIDDEN, "AccessDenied",
+                               "Object is locked and cannot be overwritten", &lock_resource));
+        }
+        if let (Some(until), Some(mode)) = (retained_until(lock), lock.mode.as_deref()) {
+            if obj_lock_until.as_deref().is_some_and(|new_until| new_until < until) {
+                return Ok(s3_error(StatusCode::FORBIDDEN, "AccessDenied",
+                                   "The retention of a locked object cannot be shortened", &lock_resource));
+            }
+            carried_retention = Some((mode.to_string(), until.to_string()));
+        }
+    }
 
     info!("S3 PutObject: bucket={} key={} user={}", bucket, key, auth_result.user_id);
 
-    let context = UserContext::with_bucket(auth_result.user_id.clone(), auth_result.bucket.clone());
+    let context = UserContext::with_bucket(auth_result.user_id.clone(), bucket.to_string());
 
+    let idempotency_key = req.headers().get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok()).map(str::to_string);
     let if_match_put = req.headers().get("if-match")
         .and_then(|v| v.to_str().ok()).map(|s| s.trim().to_string());
     let if_none_match_put = req.headers().get("if-none-match")
@@ -103,6 +188,24 @@ pub async fn s3_put_object_handler(
         }
         if let Some(ref inm) = if_none_match_put {
             if obj_exists && (inm == "*" || normalize_etag(inm) == normalize_etag(&cur_etag)) {
+                // A retry of the PUT that created the object succeeds with that PUT's ETag
+                let existing = db.get_object_full(&bucket, &key)?;
+                let record = idempotency_key.as_deref()
+                    .and_then(|id| idempotency::find(&existing, id, &IdempotencyConfig::from_env()));
+                if let Some(record) = record {
+                    let body = match retried_body(payload, &req, &resource).await? {
+                        Ok(body) => body,
+                        Err(resp) => return Ok(resp),
+                    };
+                    if record.digest == idempotency::digest(&body) {
+                        info!("S3 PutObject: idempotency key already created bucket={} key={}, skipping write", bucket, key);
+                        return Ok(HttpResponse::Ok()
+                            .insert_header(("ETag", cur_etag))
+                            .insert_header((REPLAYED_HEADER, "true"))
+                            .insert_header(("Content-Length", "0"))
+                            .body(""));
+                    }
+                }
                 return Ok(s3_precondition_failed(&resource));
             }
         }
@@ -149,23 +252,47 @@ pub async fn s3_put_object_handler(
         });
 
     let store = StorageConfig::from_env().create_store();
+    // Held until the metadata row below references every chunk we append
+    let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
     let mut offset_size_list: Vec<(u64, u64)> = Vec::new();
-    let mut body_buf: Vec<u8> = Vec::new();
-
-    while let Some(chunk_result) = payload.next().await {
-        let chunk = chunk_result.map_err(|e| {
-            warn!("PutObject: payload read error: {}", e);
-            actix_web::error::ErrorInternalServerError("Error reading payload")
-        })?;
+    let limits = PayloadLimits::of(&req);
+    // Sized from Content-Length up front so a large body is not copied on every regrowth
+    let expected = decoded_content_length(&req)
+        .or_else(|| content_length(&req))
+        .unwrap_or(0);
+    let mut body_buf: Vec<u8> = Vec::with_capacity(initial_capacity(expected, limits.max_bytes));
+    let mut received = 0usize;
+    let mut chunked = is_aws_chunked(&req).then(AwsChunkedDecoder::new);
+    let mut digest = payload_digest::sample(&req).map(|s| s.digest(&context.user_id, &bucket, &key, Direction::Upload));
+    let inline = InlineConfig::from_env();
+    // Bytes of body_buf already in storage; the rest is held back while it could still be
+    // stored inline
+    let mut written = 0usize;
+
+    while let Some(chunk) = next_chunk_limited(&mut payload, &mut received, limits.max_bytes, limits.idle_timeout).await? {
+        let chunk = match chunked.as_mut() {
+            Some(decoder) => match decoder.feed(&chunk) {
+                Ok(data) => Bytes::from(data),
+                Err(e) => return Ok(e.to_s3_error(&format!("/{}/{}", bucket, key))),
+            },
+            None => chunk,
+        };
         if chunk.is_empty() { continue; }
+        if let Some(digest) = digest.as_mut() {
+            digest.update(&chunk);
+        }
 
         body_buf.extend_from_slice(&chunk);
+        if inline.fits(body_buf.len() as u64) {
+            continue;
+        }
+        let chunk = if written + chunk.len() == body_buf.len() { chunk } else { Bytes::copy_from_slice(&body_buf[written..]) };
+        written = body_buf.len();
 
         let ctx = context.clone();
         let store_c = Arc::clone(&store);
-        let buf = chunk.to_vec();
         let pair = web::block(move || {
-            store_c.write(&ctx.user_id, &ctx.bucket, &buf).map_err(|e| e.to_string())
+            store_c.write(&ctx.user_id, &ctx.bucket, &chunk).map_err(|e| e.to_string())
         }).await
         .map_err(|e| {
             error!("PutObject: blocking write failed bucket={} key={}: {:?}", bucket, key, e);
@@ -178,6 +305,17 @@ pub async fn s3_put_object_handler(
         offset_size_list.push(pair);
     }
 
+    let trailers = match chunked {
+        Some(decoder) => match decoder.finish(decoded_content_length(&req)) {
+            Ok(trailers) => trailers,
+            Err(e) => return Ok(e.to_s3_error(&format!("/{}/{}", bucket, key))),
+        },
+        None => Vec::new(),
+    };
+    if let Some(digest) = digest {
+        digest.finish();
+    }
+
     let size = body_buf.len() as u64;
     let etag = md5_etag(&body_buf);
     let last_modified = last_modified_now();
@@ -193,7 +331,7 @@ pub async fn s3_put_object_handler(
         match base64::engine::general_purpose::STANDARD.decode(raw) {
             Ok(decoded) if decoded.len() == 16 => {
                 let body_md5 = md5::compute(&body_buf).0;
-                if decoded.as_slice() != &body_md5 {
+                if decoded.as_slice() != body_md5 {
                     return Ok(s3_error(StatusCode::BAD_REQUEST, "BadDigest",
                                        "The Content-MD5 you specified did not match what we received.",
                                        &format!("/{}/{}", bucket, key)));
@@ -208,7 +346,7 @@ pub async fn s3_put_object_handler(
     }
 
     // Checksum verification
-    let checksum_result = parse_checksum_headers(&req);
+    let checksum_result = parse_checksum_headers(&req).or_else(|| trailer_checksum(&trailers));
     if let Some((ref algo, ref client_value)) = checksum_result {
         if !verify_checksum(algo, &body_buf, client_value) {
             let resource = format!("/{}/{}", bucket, key);
@@ -218,7 +356,13 @@ pub async fn s3_put_object_handler(
         }
     }
 
-    let mut metadata = Metadata::from_offset_size_list(offset_size_list);
+    let idempotency_record = idempotency_key.as_deref()
+        .map(|id| IdempotencyRecord { size, ..IdempotencyRecord::new(id, &body_buf) });
+    let mut metadata = if written == 0 && inline.fits(size) {
+        Metadata::inline(std::mem::take(&mut body_buf))
+    } else {
+        Metadata::from_offset_size_list(offset_size_list)
+    };
     metadata.etag = Some(etag.clone());
     metadata.size = size;
     metadata.content_type = Some(content_type);
@@ -227,13 +371,20 @@ pub async fn s3_put_object_handler(
     metadata.cache_control = cache_control;
     metadata.expires = expires;
     metadata.content_encoding = content_encoding;
+    metadata.expire_at = expire_at.clone();
+    if let Some(ref record) = idempotency_record {
+        idempotency::remember(&mut metadata, record);
+    }
     if let Some((ref algo, ref value)) = checksum_result {
         metadata.checksum_algorithm = Some(algo.as_str().to_string());
         metadata.checksum_value = Some(value.clone());
         // For simple (non-multipart) objects, checksum_type is not set (leave None)
     }
 
-    let (version_id, old_extents) = db.put_object_full(&bucket, &key, metadata)?;
+    let chunk_sizes = size_stats::chunk_sizes(&metadata);
+    let (version_id, old_extents) = db.put_object_full(&bucket, &key, metadata)
+        .map_err(|e| s3_quota_error(e, &format!("/{}/{}", bucket, key)))?;
+    size_stats::record(Api::S3, WriteType::Put, size, &chunk_sizes);
     if !old_extents.is_empty() {
         db.queue_deletion(&bucket, &key, &old_extents).ok();
     }
@@ -245,21 +396,20 @@ pub async fn s3_put_object_handler(
         db.set_object_tags(&bucket, &key, &tags)?;
     }
 
-    // Apply object lock — from per-object headers or bucket default retention
-    let obj_lock_mode = req.headers().get("x-amz-object-lock-mode")
-        .and_then(|v| v.to_str().ok()).map(|s| s.to_string());
-    let obj_lock_until = req.headers().get("x-amz-object-lock-retain-until-date")
-        .and_then(|v| v.to_str().ok()).map(|s| s.to_string());
-    let obj_legal_hold = req.headers().get("x-amz-object-lock-legal-hold")
-        .and_then(|v| v.to_str().ok()).map(|s| s.to_string());
-
+    // Apply object lock — from per-object headers, the replaced object or bucket default retention
     let effective_vid = version_id.as_deref().unwrap_or("");
+    if let Some((ref replaced_vid, _)) = replaced_lock {
+        db.delete_object_lock(&bucket, &key, replaced_vid)?;
+    }
     let lock_mode: Option<String>;
     let lock_until: Option<String>;
 
     if obj_lock_mode.is_some() && obj_lock_until.is_some() {
         lock_mode = obj_lock_mode;
         lock_until = obj_lock_until;
+    } else if let Some((mode, until)) = carried_retention {
+        lock_mode = Some(mode);
+        lock_until = Some(until);
     } else if let Ok(Some((def_mode, def_days, def_years))) = db.get_object_lock_config(&bucket) {
         lock_mode = Some(def_mode);
         lock_until = Some(compute_retain_until(def_days, def_years));
@@ -269,11 +419,11 @@ pub async fn s3_put_object_handler(
     }
 
     if lock_mode.is_some() || obj_legal_hold.is_some() {
-        let _ = db.put_object_lock(
+        db.put_object_lock(
             &bucket, &key, effective_vid,
             lock_mode.as_deref(), lock_until.as_deref(),
             obj_legal_hold.as_deref(),
-        );
+        )?;
     }
 
     debug!("S3 PutObject OK: bucket={} key={} size={} etag={}", bucket, key, size, etag);
@@ -285,6 +435,7 @@ pub async fn s3_put_object_handler(
     }
     if let Some(ref m) = lock_mode { resp.insert_header(("x-amz-object-lock-mode", m.clone())); }
     if let Some(ref u) = lock_until { resp.insert_header(("x-amz-object-lock-retain-until-date", u.clone())); }
+    if let Some(ref at) = expire_at { resp.insert_header(("x-amz-expiration", expiry::s3_expiration(at))); }
     // Echo checksum header in response
     if let Some((ref algo, ref value)) = checksum_result {
         let header_name = format!("x-amz-checksum-{}", algo.header_suffix());
@@ -302,6 +453,7 @@ pub async fn s3_get_object_handler(
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let (bucket, key) = path.into_inner();
+    if let Err(resp) = validate_key_present(&key, &bucket) { return Ok(resp); }
 
     let qmap: HashMap<String, String> = web::Query::<HashMap<String, String>>::from_query(req.query_string())
         .map(|q| q.into_inner()).unwrap_or_default();
@@ -318,8 +470,11 @@ pub async fn s3_get_object_handler(
     if qmap.contains_key("legal-hold") {
         return s3_get_object_legal_hold_inner(&bucket, &key, &req).await;
     }
+    if let Some(upload_id) = qmap.get("uploadId") {
+        return s3_list_parts_handler(&bucket, &key, upload_id, &req).await;
+    }
     if let Some(vid) = qmap.get("versionId") {
-        return s3_get_object_version_handler(&bucket, &key, vid, &req).await;
+        return s3_get_object_version_handler(&bucket, &key, vid, &req).await.map(|resp| ResponseFields::of_s3(&req).apply(resp));
     }
     if qmap.contains_key("acl") {
         return s3_get_object_acl_stub(&bucket, &key, &req).await;
@@ -349,7 +504,7 @@ pub async fn s3_get_object_handler(
 
     let meta = db.get_object_full(&bucket, &key)?;
     let total_size = meta.size;
-    let etag = meta.etag.clone().unwrap_or_default();
+    let etag = validators::etag(&meta);
     let content_type = meta.content_type.clone().unwrap_or_else(|| "application/octet-stream".into());
     let last_modified = meta.last_modified.clone().unwrap_or_default();
     let extents = meta.to_offset_size_list();
@@ -380,9 +535,10 @@ pub async fn s3_get_object_handler(
             }
         }
         if let Some(ref inm) = get_if_none_match {
-            if inm == "*" || normalize_etag(inm) == normalize_etag(&etag) {
+            if validators::none_match(inm, &etag) {
                 let mut r = HttpResponse::NotModified();
-                if !etag.is_empty() { r.insert_header(("ETag", etag.as_str())); }
+                r.insert_header(("ETag", etag.as_str()));
+                r.insert_header((GENERATION_HEADER, meta.generation.to_string()));
                 if !last_modified.is_empty() { r.insert_header(("Last-Modified", last_modified.as_str())); }
                 return Ok(r.finish());
             }
@@ -393,7 +549,8 @@ pub async fn s3_get_object_handler(
             {
                 if obj_ts <= hdr_ts {
                     let mut r = HttpResponse::NotModified();
-                    if !etag.is_empty() { r.insert_header(("ETag", etag.as_str())); }
+                    r.insert_header(("ETag", etag.as_str()));
+                    r.insert_header((GENERATION_HEADER, meta.generation.to_string()));
                     if !last_modified.is_empty() { r.insert_header(("Last-Modified", last_modified.as_str())); }
                     return Ok(r.finish());
                 }
@@ -401,58 +558,51 @@ pub async fn s3_get_object_handler(
         }
     }
 
-    let (slices, response_len, range_header) = match parse_range_header(&req, total_size) {
-        RangeResult::Valid(rs, re) => {
+    let range = req.headers().get("range").and_then(|v| v.to_str().ok());
+    let (slices, range_start, response_len, range_header, parts) = match byte_ranges::parse(range, total_size, &RangeConfig::from_env()) {
+        ByteRanges::Single(rs, re) => {
             let s = range_slices(&extents, rs, re);
             let len = re - rs + 1;
             let hdr = format!("bytes {}-{}/{}", rs, re, total_size);
-            (s, len, Some(hdr))
+            (s, rs, len, Some(hdr), None)
         }
-        RangeResult::Unsatisfiable => {
+        ByteRanges::Multiple(ranges) => {
+            let s = ranges.iter().flat_map(|&(rs, re)| range_slices(&extents, rs, re)).collect();
+            let len = ranges.iter().map(|(rs, re)| re - rs + 1).sum();
+            (s, 0, len, None, Some(ranges))
+        }
+        ByteRanges::Unsatisfiable => {
             let resource = format!("/{}/{}", bucket, key);
             return Ok(s3_error(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange",
                                "The requested range is not valid for the request. \
                                 Please try another range.", &resource));
         }
-        RangeResult::None => (stream_slices(&extents), total_size, None),
+        ByteRanges::Full => (stream_slices(&extents), 0, total_size, None, None),
     };
 
     info!("S3 GetObject: bucket={} key={} total={} response_len={}", bucket, key, total_size, response_len);
-
-    let slices = Arc::new(slices);
-    let store = StorageConfig::from_env().create_store();
-    let context = UserContext::with_bucket(auth_result.user_id.clone(), auth_result.bucket.clone());
-    let bucket_log = bucket.clone();
-    let key_log = key.clone();
-
-    let byte_stream = stream::try_unfold(0usize, move |idx| {
-        let slices = Arc::clone(&slices);
-        let ctx = context.clone();
-        let bucket_log = bucket_log.clone();
-        let key_log = key_log.clone();
-        let store = Arc::clone(&store);
-        async move {
-            if idx >= slices.len() {
-                return Ok::<Option<(Bytes, usize)>, Error>(None);
-            }
-            let (off, sz) = slices[idx];
-            let chunk = web::block(move || {
-                store.read(&ctx.user_id, &ctx.bucket, off, sz).map_err(|e| e.to_string())
-            }).await
-            .map_err(|e| {
-                error!("GetObject stream: task failed bucket={} key={} idx={}: {:?}", bucket_log, key_log, idx, e);
-                actix_web::error::ErrorInternalServerError(e)
-            })?
-            .map_err(|msg| {
-                error!("GetObject stream: read error bucket={} key={} idx={}: {}", bucket_log, key_log, idx, msg);
-                actix_web::error::ErrorInternalServerError(msg)
-            })?;
-            Ok(Some((Bytes::from(chunk), idx + 1)))
-        }
-    });
+    access_tracking::record(&auth_result.user_id, &bucket, &key);
+
+    let context = UserContext::with_bucket(auth_result.user_id.clone(), bucket.to_string());
+    StorageService::new().check_readable(&context, &slices)
+        .map_err(|e| s3_read_error(e, &format!("/{}/{}", bucket, key)))?;
+
+    // Fast paths: inline objects are served from the row, single-chunk objects stream
+    // straight from the bucket file
+    let fast_stream = if parts.is_some() {
+        None
+    } else if let Some(data) = &meta.inline_data {
+        let (start, len) = (range_start as usize, response_len as usize);
+        let part = Bytes::copy_from_slice(&data[start..start + len]);
+        Some(stream::once(async move { Ok::<_, Error>(part) }).boxed_local())
+    } else if extents.len() == 1 && !slices.is_empty() {
+        StorageService::new().open_extent_stream(&context, slices[0].0, response_len).await?
+    } else {
+        None
+    };
 
     let resp_content_type = qmap.get("response-content-type").cloned()
-        .unwrap_or(content_type);
+        .unwrap_or_else(|| content_type.clone());
     let resp_content_disposition = qmap.get("response-content-disposition").cloned();
     let resp_content_language = qmap.get("response-content-language").cloned();
     let resp_expires = qmap.get("response-expires").cloned()
@@ -462,11 +612,41 @@ pub async fn s3_get_object_handler(
     let resp_content_encoding = qmap.get("response-content-encoding").cloned()
         .or_else(|| meta.content_encoding.clone());
 
-    let status = if range_header.is_some() { StatusCode::PARTIAL_CONTENT } else { StatusCode::OK };
+    // Objects uploaded with a Content-Encoding are served as stored
+    let compression = CompressionConfig::from_env();
+    let ranged = range_header.is_some() || parts.is_some();
+    let codec = match ranged {
+        false => compression.negotiate(accept_encoding(&req), &content_type, total_size, resp_content_encoding.as_deref()),
+        true => None,
+    };
+
+    // Several ranges are framed as multipart/byteranges, each part typed as the object
+    let multipart = parts.map(|ranges| (Multipart::new(&resp_content_type, total_size), ranges));
+    let status = if ranged { StatusCode::PARTIAL_CONTENT } else { StatusCode::OK };
     let mut resp = HttpResponse::build(status);
-    resp.content_type(resp_content_type.as_str());
-    resp.insert_header(("Content-Length", response_len.to_string()));
+    match &multipart {
+        Some((multipart, ranges)) => {
+            resp.content_type(multipart.content_type());
+            resp.insert_header(("Content-Length", multipart.content_length(ranges).to_string()));
+        }
+        None => {
+            resp.content_type(resp_content_type.as_str());
+        }
+    }
+    match codec {
+        Some(codec) => {
+            resp.insert_header(("Content-Encoding", codec.as_str()));
+        }
+        None if multipart.is_some() => {}
+        None => {
+            resp.insert_header(("Content-Length", response_len.to_string()));
+        }
+    }
+    if resp_content_encoding.is_none() && compression.compressible(&content_type) {
+        resp.insert_header(("Vary", "Accept-Encoding"));
+    }
     resp.insert_header(("ETag", etag));
+    resp.insert_header((GENERATION_HEADER, meta.generation.to_string()));
     resp.insert_header(("Accept-Ranges", "bytes"));
     if let Some(cr) = range_header {
         resp.insert_header(("Content-Range", cr));
@@ -480,6 +660,9 @@ pub async fn s3_get_object_handler(
     if let Some(exp) = resp_expires {
         resp.insert_header(("Expires", exp));
     }
+    if let Some(at) = &meta.expire_at {
+        resp.insert_header(("x-amz-expiration", expiry::s3_expiration(at)));
+    }
     if let Some(enc) = resp_content_encoding {
         resp.insert_header(("Content-Encoding", enc));
     }
@@ -495,10 +678,11 @@ pub async fn s3_get_object_handler(
     if let Some(ref vid) = meta.version_id {
         resp.insert_header(("x-amz-version-id", vid.clone()));
     }
-    // Return checksum headers when x-amz-checksum-mode: ENABLED
+    // Return checksum headers when x-amz-checksum-mode: ENABLED. A range is not what the
+    // whole-object checksum covers, and SDKs validate the body against it, so none is sent.
     let checksum_mode = req.headers().get("x-amz-checksum-mode")
         .and_then(|v| v.to_str().ok()).map(|s| s.to_uppercase());
-    if checksum_mode.as_deref() == Some("ENABLED") {
+    if checksum_mode.as_deref() == Some("ENABLED") && !ranged {
         if let (Some(ref algo_str), Some(ref cksum_val)) = (&meta.checksum_algorithm, &meta.checksum_value) {
             if let Some(algo) = ChecksumAlgorithm::from_str(algo_str) {
                 let header_name = format!("x-amz-checksum-{}", algo.header_suffix());
@@ -511,15 +695,30 @@ pub async fn s3_get_object_handler(
             }
         }
     }
-    // Return object lock headers if present
-    if let Some(ref vid) = meta.version_id {
-        if let Ok(Some(lock)) = db.get_object_lock(&bucket, &key, vid) {
-            if let Some(ref m) = lock.mode { resp.insert_header(("x-amz-object-lock-mode", m.clone())); }
-            if let Some(ref u) = lock.retain_until_date { resp.insert_header(("x-amz-object-lock-retain-until-date", u.clone())); }
-            if lock.legal_hold == "ON" { resp.insert_header(("x-amz-object-lock-legal-hold", "ON")); }
+    insert_object_lock_headers(&mut resp, &db, &bucket, &key, meta.version_id.as_deref().unwrap_or(""));
+    // Chunks are read ahead of the client (GET_READ_AHEAD_CHUNKS) so disk and network overlap
+    let body = match (fast_stream, multipart) {
+        (Some(body), _) => body,
+        (None, Some((multipart, ranges))) => {
+            let storage = StorageService::new();
+            let streams = ranges.iter().map(|&(rs, re)| match &meta.inline_data {
+                Some(data) => {
+                    let part = Bytes::copy_from_slice(&data[rs as usize..=re as usize]);
+                    stream::once(async move { Ok::<_, Error>(part) }).boxed_local()
+                }
+                None => storage.chunk_stream(&context, range_slices(&extents, rs, re), read_ahead_chunks()),
+            }).collect();
+            multipart.stream(&ranges, streams)
         }
-    }
-    Ok(resp.streaming(byte_stream))
+        (None, None) => StorageService::new().chunk_stream(&context, slices, read_ahead_chunks()),
+    };
+    let digest = payload_digest::sample(&req).map(|s| s.digest(&auth_result.user_id, &bucket, &key, Direction::Download));
+    let body = payload_digest::wrap(body, digest);
+    let resp = match codec {
+        Some(codec) => resp.streaming(compress_stream(body, codec)),
+        None => resp.streaming(body),
+    };
+    Ok(ResponseFields::of_s3(&req).apply(resp))
 }
 
 // ---------------------------------------------------------------------------
@@ -531,6 +730,7 @@ pub async fn s3_head_object_handler(
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let (bucket, key) = path.into_inner();
+    if let Err(resp) = validate_key_present(&key, &bucket) { return Ok(resp); }
 
     let qmap: HashMap<String, String> = web::Query::<HashMap<String, String>>::from_query(req.query_string())
         .map(|q| q.into_inner()).unwrap_or_default();
@@ -550,7 +750,7 @@ pub async fn s3_head_object_handler(
             for (name, value) in resp.headers() {
                 head_resp.insert_header((name.clone(), value.clone()));
             }
-            return Ok(head_resp.finish());
+            return Ok(ResponseFields::of_s3(&req).apply(head_resp.finish()));
         }
         return Ok(resp);
     }
@@ -568,7 +768,7 @@ pub async fn s3_head_object_handler(
     }
 
     let meta = db.get_object_full(&bucket, &key)?;
-    let etag = meta.etag.clone().unwrap_or_default();
+    let etag = validators::etag(&meta);
     let content_type = meta.content_type.clone().unwrap_or_else(|| "application/octet-stream".into());
     let last_modified = meta.last_modified.clone().unwrap_or_default();
 
@@ -578,7 +778,9 @@ pub async fn s3_head_object_handler(
     let mut resp = HttpResponse::Ok();
     resp.insert_header(("Content-Type", content_type));
     resp.insert_header(("ETag", etag));
+    resp.insert_header((GENERATION_HEADER, meta.generation.to_string()));
     resp.insert_header(("Accept-Ranges", "bytes"));
+    resp.insert_header((OBJECT_FORMAT_HEADER, ObjectFormat::of(&meta).as_str()));
     if !last_modified.is_empty() {
         resp.insert_header(("Last-Modified", last_modified_for_header(&last_modified)));
     }
@@ -588,6 +790,9 @@ pub async fn s3_head_object_handler(
     if let Some(exp) = &meta.expires {
         resp.insert_header(("Expires", exp.as_str()));
     }
+    if let Some(at) = &meta.expire_at {
+        resp.insert_header(("x-amz-expiration", expiry::s3_expiration(at)));
+    }
     if let Some(enc) = &meta.content_encoding {
         resp.insert_header(("Content-Encoding", enc.as_str()));
     }
@@ -602,6 +807,12 @@ pub async fn s3_head_object_handler(
     if let Some(ref vid) = meta.version_id {
         resp.insert_header(("x-amz-version-id", vid.clone()));
     }
+    if access_tracking::enabled() {
+        if let Some(at) = access_tracking::last_accessed(&auth_result.user_id, &bucket, &[key.as_str()])?.remove(&key) {
+            resp.insert_header((access_tracking::LAST_ACCESSED_HEADER, expiry::header_value(&at)));
+        }
+        access_tracking::record(&auth_result.user_id, &bucket, &key);
+    }
     // Return checksum headers when x-amz-checksum-mode: ENABLED
     let head_checksum_mode = req.headers().get("x-amz-checksum-mode")
         .and_then(|v| v.to_str().ok()).map(|s| s.to_uppercase());
@@ -618,16 +829,8 @@ pub async fn s3_head_object_handler(
             }
         }
     }
-    // Return object lock headers if present
-    let vid_for_lock = meta.version_id.as_deref().unwrap_or("");
-    if !vid_for_lock.is_empty() {
-        if let Ok(Some(lock)) = db.get_object_lock(&bucket, &key, vid_for_lock) {
-            if let Some(ref m) = lock.mode { resp.insert_header(("x-amz-object-lock-mode", m.clone())); }
-            if let Some(ref u) = lock.retain_until_date { resp.insert_header(("x-amz-object-lock-retain-until-date", u.clone())); }
-            if lock.legal_hold == "ON" { resp.insert_header(("x-amz-object-lock-legal-hold", "ON")); }
-        }
-    }
-    Ok(resp.message_body(HeadBody(object_size)).unwrap().map_into_boxed_body())
+    insert_object_lock_headers(&mut resp, &db, &bucket, &key, meta.version_id.as_deref().unwrap_or(""));
+    Ok(ResponseFields::of_s3(&req).apply(resp.message_body(HeadBody(object_size)).unwrap().map_into_boxed_body()))
 }
 
 // ---------------------------------------------------------------------------
@@ -638,6 +841,7 @@ pub async fn s3_delete_object_handler(
     path: web::Path<(String, String)>,
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
+    if let Err(resp) = validate_key_present(&path.1, &path.0) { return Ok(resp); }
     if let Ok(query) = web::Query::<HashMap<String, String>>::from_query(req.query_string()) {
         if query.contains_key("uploadId") {
             return s3_abort_multipart_upload_handler(path, query, req).await;
@@ -681,7 +885,6 @@ pub async fn s3_delete_object_handler(
     let auth_result = authenticate_s3_request(&req).await?;
     let _authenticated_req = create_authenticated_request(&req, &auth_result);
 
-    let context = UserContext::with_bucket(auth_result.user_id.clone(), auth_result.bucket.clone());
     let db = MetadataService::new(&auth_result.user_id)?;
 
     if let Err(resp) = require_bucket(&db, &bucket) { return Ok(resp); }
@@ -725,8 +928,24 @@ pub async fn s3_delete_object_handler(
         }
     }
 
+    // A delete that removes the object rather than hiding it behind a marker honours the
+    // object's own retention and legal hold, even if the bucket has object lock disabled
+    let replaced_lock = in_place_lock(&db, &bucket, &key)?;
+    if let Some((ref vid, _)) = replaced_lock {
+        let (ret_blocked, hold_blocked) = db.check_object_lock_protection(&bucket, &key, vid, bypass_governance(&req))?;
+        if ret_blocked || hold_blocked {
+            return Ok(s3_error(StatusCode::FORBIDDEN, "AccessDenied",
+                               "Object is locked and cannot be deleted", &resource));
+        }
+    }
+
     use crate::metadata::sqlite_store::VersioningDeleteResult;
 
+    // Extents of the object a non-versioned delete removes, read before its row is gone
+    let existing_extents = match db.check_key(&bucket, &key)? {
+        true => db.get_object_full(&bucket, &key)?.to_offset_size_list(),
+        false => Vec::new(),
+    };
     let del_result = db.delete_object_v2(&bucket, &key)?;
 
     info!("S3 DeleteObject: bucket={} key={}", bucket, key);
@@ -738,16 +957,15 @@ pub async fn s3_delete_object_handler(
             resp.insert_header(("x-amz-version-id", version_id));
         }
         VersioningDeleteResult::Deleted => {
-            if db.check_key(&bucket, &key).unwrap_or(false) {
-                if let Ok(existing) = db.get_object_full(&bucket, &key) {
-                    let extents = existing.to_offset_size_list();
-                    if !extents.is_empty() {
-                        db.queue_deletion(&bucket, &key, &extents).ok();
-                    }
-                }
-                StorageService::new().delete_object(&context, &key).ok();
-            }
+            let queued = db.queue_deletion(&bucket, &key, &existing_extents).unwrap_or_else(|e| {
+                warn!("S3 DeleteObject: failed to queue {} chunks of {}/{}: {}", existing_extents.len(), bucket, key, e);
+                DeletionSummary::default()
+            });
+            resp.insert_header((RECLAIMED_BYTES_HEADER, queued.bytes.to_string()));
             db.delete_metadata(&bucket, &key).ok();
+            if let Some((ref vid, _)) = replaced_lock {
+                db.delete_object_lock(&bucket, &key, vid).ok();
+            }
         }
     }
     Ok(resp.insert_header(("Content-Length", "0")).body(""))
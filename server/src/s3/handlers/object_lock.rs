@@ -1,6 +1,7 @@
 // Object Lock handlers — bucket config, per-object retention, legal hold.
-use actix_web::{HttpRequest, HttpResponse, Error, http::StatusCode};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, Error, http::StatusCode};
 
+use crate::metadata::sqlite_store::ObjectLockRow;
 use crate::s3::auth::authenticate_s3_request;
 use crate::service::metadata_service::MetadataService;
 
@@ -22,6 +23,51 @@ pub fn compute_retain_until(days: Option<i64>, years: Option<i64>) -> String {
     dt.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
 }
 
+/// Parse an ISO 8601 retain-until date (`x-amz-object-lock-retain-until-date` or
+/// `<RetainUntilDate>`) into the stored UTC form, which compares correctly as a string.
+pub fn parse_retain_until(value: &str) -> Option<String> {
+    let dt = chrono::DateTime::parse_from_rfc3339(value.trim()).ok()?;
+    Some(dt.with_timezone(&chrono::Utc).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+}
+
+pub fn bypass_governance(req: &HttpRequest) -> bool {
+    req.headers()
+        .get("x-amz-bypass-governance-retention")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// The version id and lock of the object that a PUT or DELETE of `key` replaces in place:
+/// the current object, unless the bucket keeps versions (then the old version survives and
+/// its lock does not get in the way). None when there is no such object or it has no lock.
+pub fn in_place_lock(db: &MetadataService, bucket: &str, key: &str) -> Result<Option<(String, ObjectLockRow)>, Error> {
+    if db.get_versioning_state(bucket)? == "enabled" || !db.check_key(bucket, key)? {
+        return Ok(None);
+    }
+    let vid = db.get_object_full(bucket, key)?.version_id.unwrap_or_default();
+    Ok(db.get_object_lock(bucket, key, &vid)?.map(|lock| (vid, lock)))
+}
+
+/// Retain-until date of `lock` if its retention has not expired yet
+pub fn retained_until(lock: &ObjectLockRow) -> Option<&str> {
+    let now = crate::metadata::last_modified_now();
+    match (&lock.mode, &lock.retain_until_date) {
+        (Some(_), Some(until)) if until.as_str() > now.as_str() => Some(until.as_str()),
+        _ => None,
+    }
+}
+
+/// Add the retention and legal hold of version `version_id` of `key` (`""` for an
+/// unversioned object) to a GET or HEAD response.
+pub fn insert_object_lock_headers(resp: &mut HttpResponseBuilder, db: &MetadataService, bucket: &str, key: &str, version_id: &str) {
+    if let Ok(Some(lock)) = db.get_object_lock(bucket, key, version_id) {
+        if let Some(ref m) = lock.mode { resp.insert_header(("x-amz-object-lock-mode", m.clone())); }
+        if let Some(ref u) = lock.retain_until_date { resp.insert_header(("x-amz-object-lock-retain-until-date", u.clone())); }
+        if lock.legal_hold == "ON" { resp.insert_header(("x-amz-object-lock-legal-hold", "ON")); }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // PUT /{bucket}?object-lock — set bucket-level object lock configuration
 // ---------------------------------------------------------------------------
@@ -148,12 +194,6 @@ pub async fn s3_put_object_retention_inner(bucket: &str, key: &str, body: &[u8],
     let db = MetadataService::new(&auth_result.user_id)?;
     if let Err(resp) = require_bucket(&db, bucket) { return Ok(resp); }
 
-    let lock_enabled = db.get_bucket_object_lock_enabled(bucket)?;
-    if !lock_enabled {
-        return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidRequest",
-                           "Bucket does not have object lock enabled", &format!("/{}/{}", bucket, key)));
-    }
-
     let xml = String::from_utf8_lossy(body);
     let mode = extract_xml_tag(&xml, "Mode").unwrap_or_default();
     if mode != "COMPLIANCE" && mode != "GOVERNANCE" {
@@ -161,21 +201,25 @@ pub async fn s3_put_object_retention_inner(bucket: &str, key: &str, body: &[u8],
                            "Mode must be COMPLIANCE or GOVERNANCE", &format!("/{}/{}", bucket, key)));
     }
 
-    let retain_until = extract_xml_tag(&xml, "RetainUntilDate").unwrap_or_default();
-    if retain_until.is_empty() {
-        return Ok(s3_error(StatusCode::BAD_REQUEST, "MalformedXML",
-                           "RetainUntilDate is required", &format!("/{}/{}", bucket, key)));
-    }
+    let retain_until = match extract_xml_tag(&xml, "RetainUntilDate") {
+        Some(date) if !date.is_empty() => match parse_retain_until(&date) {
+            Some(until) => until,
+            None => return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidArgument",
+                                       "RetainUntilDate must be an ISO 8601 date", &format!("/{}/{}", bucket, key))),
+        },
+        _ => return Ok(s3_error(StatusCode::BAD_REQUEST, "MalformedXML",
+                                "RetainUntilDate is required", &format!("/{}/{}", bucket, key))),
+    };
 
     let qmap = req_query_map(req);
     let version_id = qmap.get("versionId").cloned().unwrap_or_default();
 
-    let bypass_governance = req.headers()
-        .get("x-amz-bypass-governance-retention")
-        .and_then(|v| v.to_str().ok())
-        .map(|v| v.eq_ignore_ascii_case("true"))
-        .unwrap_or(false);
+    let bypass_governance = bypass_governance(req);
 
+    if version_id.is_empty() && !db.check_key(bucket, key)? {
+        return Ok(s3_error(StatusCode::NOT_FOUND, "NoSuchKey",
+                           "The specified key does not exist", &format!("/{}/{}", bucket, key)));
+    }
     let vid = if version_id.is_empty() {
         db.get_object_full(bucket, key)
             .ok()
@@ -187,26 +231,21 @@ pub async fn s3_put_object_retention_inner(bucket: &str, key: &str, body: &[u8],
 
     // Enforce retention protection: check existing lock before overwriting
     if let Some(existing) = db.get_object_lock(bucket, key, &vid)? {
-        if let (Some(ref ex_mode), Some(ref ex_until)) = (&existing.mode, &existing.retain_until_date) {
-            let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-            if ex_until.as_str() > now.as_str() {
-                let shortening = retain_until.as_str() < ex_until.as_str();
-                let mode_changed = mode.as_str() != ex_mode.as_str();
-                let blocked = match ex_mode.as_str() {
-                    "COMPLIANCE" => shortening || mode_changed,
-                    "GOVERNANCE" => !bypass_governance && (shortening || mode_changed),
-                    _ => false,
-                };
-                if blocked {
-                    return Ok(s3_error(StatusCode::FORBIDDEN, "AccessDenied",
-                                       "Object is locked and the retention cannot be changed as requested",
-                                       &format!("/{}/{}", bucket, key)));
-                }
+        // Legal hold alone does not block retention changes
+        if let (Some(ex_until), Some(ex_mode)) = (retained_until(&existing), existing.mode.as_deref()) {
+            let shortening = retain_until.as_str() < ex_until;
+            let mode_changed = mode.as_str() != ex_mode;
+            let blocked = match ex_mode {
+                "COMPLIANCE" => shortening || mode_changed,
+                "GOVERNANCE" => !bypass_governance && (shortening || mode_changed),
+                _ => false,
+            };
+            if blocked {
+                return Ok(s3_error(StatusCode::FORBIDDEN, "AccessDenied",
+                                   "Object is locked and the retention cannot be changed as requested",
+                                   &format!("/{}/{}", bucket, key)));
             }
         }
-        if existing.legal_hold == "ON" && !bypass_governance {
-            // Legal hold alone does NOT block retention changes
-        }
     }
 
     db.put_object_lock(bucket, key, &vid, Some(&mode), Some(&retain_until), None)?;
@@ -222,12 +261,6 @@ pub async fn s3_get_object_retention_inner(bucket: &str, key: &str, req: &HttpRe
     let db = MetadataService::new(&auth_result.user_id)?;
     if let Err(resp) = require_bucket(&db, bucket) { return Ok(resp); }
 
-    let lock_enabled = db.get_bucket_object_lock_enabled(bucket)?;
-    if !lock_enabled {
-        return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidRequest",
-                           "Bucket does not have object lock enabled", &format!("/{}/{}", bucket, key)));
-    }
-
     let qmap = req_query_map(req);
     let version_id = qmap.get("versionId").cloned().unwrap_or_else(|| {
         db.get_object_full(bucket, key)
@@ -268,12 +301,6 @@ pub async fn s3_put_object_legal_hold_inner(bucket: &str, key: &str, body: &[u8]
     let db = MetadataService::new(&auth_result.user_id)?;
     if let Err(resp) = require_bucket(&db, bucket) { return Ok(resp); }
 
-    let lock_enabled = db.get_bucket_object_lock_enabled(bucket)?;
-    if !lock_enabled {
-        return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidRequest",
-                           "Bucket does not have object lock enabled", &format!("/{}/{}", bucket, key)));
-    }
-
     let xml = String::from_utf8_lossy(body);
     let status = extract_xml_tag(&xml, "Status").unwrap_or_default();
     if status != "ON" && status != "OFF" {
@@ -282,6 +309,10 @@ pub async fn s3_put_object_legal_hold_inner(bucket: &str, key: &str, body: &[u8]
     }
 
     let qmap = req_query_map(req);
+    if !qmap.contains_key("versionId") && !db.check_key(bucket, key)? {
+        return Ok(s3_error(StatusCode::NOT_FOUND, "NoSuchKey",
+                           "The specified key does not exist", &format!("/{}/{}", bucket, key)));
+    }
     let vid = qmap.get("versionId").cloned().unwrap_or_else(|| {
         db.get_object_full(bucket, key)
             .ok()
@@ -302,12 +333,6 @@ pub async fn s3_get_object_legal_hold_inner(bucket: &str, key: &str, req: &HttpR
     let db = MetadataService::new(&auth_result.user_id)?;
     if let Err(resp) = require_bucket(&db, bucket) { return Ok(resp); }
 
-    let lock_enabled = db.get_bucket_object_lock_enabled(bucket)?;
-    if !lock_enabled {
-        return Ok(s3_error(StatusCode::BAD_REQUEST, "InvalidRequest",
-                           "Bucket does not have object lock enabled", &format!("/{}/{}", bucket, key)));
-    }
-
     let qmap = req_query_map(req);
     let version_id = qmap.get("versionId").cloned().unwrap_or_else(|| {
         db.get_object_full(bucket, key)
@@ -1,6 +1,8 @@
 // Tag helpers and bucket/object tagging inner handlers.
 use actix_web::{HttpRequest, HttpResponse, Error, http::StatusCode};
 
+use crate::service::object_tags::check_tags;
+
 use super::common::*;
 
 // ---------------------------------------------------------------------------
@@ -26,21 +28,7 @@ pub(super) fn parse_tag_xml(xml: &str) -> Vec<(String, String)> {
 }
 
 pub(super) fn validate_tags(tags: &[(String, String)], resource: &str) -> Result<(), HttpResponse> {
-    if tags.len() > 10 {
-        return Err(s3_error(StatusCode::BAD_REQUEST, "InvalidTag",
-                            "Object tag count cannot be greater than 10", resource));
-    }
-    for (k, v) in tags {
-        if k.len() > 128 {
-            return Err(s3_error(StatusCode::BAD_REQUEST, "InvalidTag",
-                                "The tag key you have provided is invalid", resource));
-        }
-        if v.len() > 256 {
-            return Err(s3_error(StatusCode::BAD_REQUEST, "InvalidTag",
-                                "The tag value you have provided is invalid", resource));
-        }
-    }
-    Ok(())
+    check_tags(tags).map_err(|msg| s3_error(StatusCode::BAD_REQUEST, "InvalidTag", msg, resource))
 }
 
 pub(super) fn tags_to_xml(tags: &[(String, String)]) -> String {
@@ -124,14 +124,14 @@ pub(super) async fn s3_get_object_version_handler(bucket: &str, key: &str, versi
     let etag = meta.etag.clone().unwrap_or_default();
     let content_type = meta.content_type.clone().unwrap_or_else(|| "application/octet-stream".into());
     let last_modified = meta.last_modified.clone().unwrap_or_default();
-    let extents = meta.to_offset_size_list();
+    let stored = meta.clone();
 
     let context = UserContext::with_bucket(
-        auth_result.user_id.clone(), auth_result.bucket.clone()
+        auth_result.user_id.clone(), bucket.to_string()
     );
     let body = web::block(move || {
         StorageService::new()
-            .read_object(&context, &extents, StorageMode::S3)
+            .read_stored(&context, &stored, StorageMode::S3)
             .map_err(|e| e.to_string())
     }).await
     .map_err(actix_web::error::ErrorInternalServerError)?
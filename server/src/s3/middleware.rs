@@ -1,8 +1,13 @@
 // S3 Middleware for request processing
-use actix_web::{HttpRequest, HttpResponse, Error};
-use log::info;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Error};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Uri;
+use actix_web::middleware::Next;
+use log::{debug, info, warn};
+use std::env;
 
-use crate::s3::auth::authenticate_s3_request;
+use crate::s3::auth::{authenticate_s3_request, routed_bucket};
 
 /// Simple S3 request handler that processes requests without middleware complexity
 pub async fn handle_s3_request(req: HttpRequest) -> Result<HttpResponse, Error> {
@@ -10,13 +15,95 @@ pub async fn handle_s3_request(req: HttpRequest) -> Result<HttpResponse, Error>
     
     // Authenticate the request
     let auth_result = authenticate_s3_request(&req).await?;
-    info!("S3 authentication successful for user: {}, bucket: {}", auth_result.user_id, auth_result.bucket);
+    let bucket = routed_bucket(&req);
+    info!("S3 authentication successful for user: {}, bucket: {}", auth_result.user_id, bucket);
     
     // Return success response
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "status": "authenticated",
         "user_id": auth_result.user_id,
-        "bucket": auth_result.bucket,
+        "bucket": bucket,
         "access_key": auth_result.access_key
     })))
 }
+
+/// Virtual-host style addressing (`bucket.s3.example.com/key`)
+///
+/// With `S3_DOMAIN_SUFFIX=s3.example.com`, a request whose Host is `<bucket>.s3.example.com`
+/// is rewritten to the path-style `/s3/<bucket>/<key>` before routing, so the path-style
+/// handlers serve it unchanged. The client signed the path it sent, which is kept in the
+/// request extensions as [`VirtualHostPath`] for signature verification. Requests to the
+/// bare suffix or any other host are left alone.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualHostConfig {
+    pub domain_suffix: Option<String>,
+}
+
+impl VirtualHostConfig {
+    pub fn from_env() -> Self {
+        let domain_suffix = env::var("S3_DOMAIN_SUFFIX")
+            .ok()
+            .map(|v| v.trim().trim_start_matches('.').to_lowercase())
+            .filter(|v| !v.is_empty());
+        Self { domain_suffix }
+    }
+
+    /// Bucket addressed by `host` (port ignored), if it is a subdomain of the suffix.
+    pub fn bucket_from_host(&self, host: &str) -> Option<String> {
+        let suffix = self.domain_suffix.as_deref()?;
+        let host = host.rsplit_once(':').map_or(host, |(name, _)| name).to_lowercase();
+        let bucket = host.strip_suffix(suffix)?.strip_suffix('.')?;
+        (!bucket.is_empty()).then(|| bucket.to_string())
+    }
+}
+
+/// Path the client sent before a virtual-host rewrite
+#[derive(Debug, Clone)]
+pub struct VirtualHostPath(pub String);
+
+/// Rewrite virtual-host style requests to path style; use with `middleware::from_fn`.
+pub async fn virtual_host_rewrite(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let bucket = VirtualHostConfig::from_env().bucket_from_host(req.connection_info().host());
+    if let Some(bucket) = bucket {
+        let original = req.path().to_string();
+        let path = if original == "/" || original.is_empty() {
+            format!("/s3/{}", bucket)
+        } else {
+            format!("/s3/{}{}", bucket, original)
+        };
+        let path_and_query = match req.query_string() {
+            "" => path,
+            query => format!("{}?{}", path, query),
+        };
+        match Uri::try_from(path_and_query.as_str()) {
+            Ok(uri) => {
+                debug!("Virtual-host request {} for bucket {:?} routed as {}", original, bucket, uri);
+                req.match_info_mut().get_mut().update(&uri);
+                req.head_mut().uri = uri;
+                req.extensions_mut().insert(VirtualHostPath(original));
+            }
+            Err(e) => warn!("Cannot rewrite virtual-host request {} for bucket {:?}: {}", original, bucket, e),
+        }
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_from_host() {
+        let config = VirtualHostConfig { domain_suffix: Some("s3.example.com".to_string()) };
+        assert_eq!(config.bucket_from_host("photos.s3.example.com").as_deref(), Some("photos"));
+        assert_eq!(config.bucket_from_host("Photos.S3.Example.com:9710").as_deref(), Some("photos"));
+        assert_eq!(config.bucket_from_host("my.dotted.bucket.s3.example.com").as_deref(), Some("my.dotted.bucket"));
+        assert_eq!(config.bucket_from_host("s3.example.com"), None);
+        assert_eq!(config.bucket_from_host("photos-s3.example.com"), None);
+        assert_eq!(config.bucket_from_host("localhost:9710"), None);
+        assert_eq!(VirtualHostConfig::default().bucket_from_host("photos.s3.example.com"), None);
+    }
+}
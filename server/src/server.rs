@@ -0,0 +1,235 @@
+//! The HTTP application: middleware stack and routes
+//!
+//! `main` serves it with [`http_server`] on `SERVER_PORT`; the S3 compatibility tests serve
+//! the same app on an ephemeral port so real clients see exactly what production answers.
+//! Applications embedding the server register [`routes`] on their own app instead.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{Server, ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::KeepAlive;
+use actix_web::{web, App, HttpServer};
+use log::info;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::admin::{gc, replication_status, repair, tier, create_api_key, revoke_api_key, rotate_bucket_key, rewrap_bucket_keys,
+    get_bucket_access, set_bucket_access, get_bucket_limits, set_bucket_limits, get_bucket_lifecycle, set_bucket_lifecycle, access_top, shadow_report, db_checkpoint, get_maintenance, set_maintenance, dump,
+    get_log_level, set_log_level, trace_user, untrace_user, trace_user_logs, fsck, compact, compaction_plan, backfill_checksums, user_health, inventory, server_stats, io_stats,
+    list_jobs, get_job, cancel_job, list_inflight, create_admin_token, list_admin_tokens, disable_admin_token, list_changes};
+use crate::api::{native_routes, capabilities, healthz, readyz};
+use crate::config::{replica_gate, HttpConfig};
+use crate::logging::request_scope;
+use crate::metrics::{metrics_handler, track_requests};
+use crate::s3::handlers::{
+    s3_put_object_handler,
+    s3_get_object_handler,
+    s3_delete_object_handler,
+    s3_head_object_handler,
+    s3_head_bucket_handler,
+    s3_list_objects_handler,
+    s3_list_buckets_handler,
+    s3_create_bucket_handler,
+    s3_delete_bucket_handler,
+    s3_delete_objects_handler,
+    s3_multipart_router,
+    s3_cors_not_configured_handler,
+    s3_bucket_cors,
+};
+use crate::s3::middleware::virtual_host_rewrite;
+use crate::service::api_version::ApiVersion;
+use crate::service::inflight;
+use crate::service::maintenance::maintenance_gate;
+use crate::ui::ui;
+use crate::util::cors::cors;
+use crate::util::payload::PayloadLimits;
+
+/// The application served by every HTTP worker.
+pub fn app() -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let limits = PayloadLimits::from_env();
+    App::new()
+        // A read-only replica answers writes with 501
+        .wrap(actix_web::middleware::from_fn(replica_gate))
+        // Writes are refused while the server is in read-only maintenance mode
+        .wrap(actix_web::middleware::from_fn(maintenance_gate))
+        // bucket.$S3_DOMAIN_SUFFIX requests become path style before routing
+        .wrap(actix_web::middleware::from_fn(virtual_host_rewrite))
+        // S3 responses carry the bucket's own CORS headers for a matching Origin
+        .wrap(actix_web::middleware::from_fn(s3_bucket_cors))
+        // Preflights from allowed origins are answered here, before any routing
+        .wrap(actix_web::middleware::from_fn(cors))
+        // Request counts and latencies by route template
+        .wrap(actix_web::middleware::from_fn(track_requests))
+        // Every request is listed in /admin/inflight while it runs
+        .wrap(actix_web::middleware::from_fn(inflight::track))
+        // Request ids, and per-user tracing once a handler knows the user
+        .wrap(actix_web::middleware::from_fn(request_scope))
+        .wrap(actix_web::middleware::Logger::default())
+        // Bodies buffered by extractors (admin JSON) are never object data. Object uploads
+        // are streamed by their handlers and checked against MAX_PAYLOAD_BYTES as they
+        // arrive; every other streamed body is capped at MAX_CONTROL_PAYLOAD_BYTES.
+        .app_data(web::PayloadConfig::default().limit(limits.max_control_bytes))
+        .app_data(web::JsonConfig::default().limit(limits.max_control_bytes))
+        // Handlers take their body limits from here (`PayloadLimits::of`), not the environment
+        .app_data(limits)
+        .configure(routes)
+}
+
+/// Every route of the server: native API (by version and unprefixed), admin, metrics, UI
+/// and S3 in both prefixed and root form. Embedders register them on their own `App`; the
+/// S3 root routes match any path, so they should come after the embedder's own.
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        // S3-compatible API — prefixed form (/s3/...)
+        .route("/s3",               web::get().to(s3_list_buckets_handler))
+        .route("/s3/",              web::get().to(s3_list_buckets_handler))
+        // SDKs address bucket operations as /s3/{bucket}/ too; object routes need a non-empty key
+        .service(web::resource(["/s3/{bucket}", "/s3/{bucket}/"])
+            .route(web::put().to(s3_create_bucket_handler))
+            .route(web::delete().to(s3_delete_bucket_handler))
+            .route(web::head().to(s3_head_bucket_handler))
+            .route(web::get().to(s3_list_objects_handler))
+            .route(web::post().to(s3_delete_objects_handler))
+            .route(web::method(actix_web::http::Method::OPTIONS).to(s3_cors_not_configured_handler)))
+        .route("/s3/{bucket}/{key:.+}", web::put().to(s3_put_object_handler))
+        .route("/s3/{bucket}/{key:.+}", web::get().to(s3_get_object_handler))
+        .route("/s3/{bucket}/{key:.+}", web::delete().to(s3_delete_object_handler))
+        .route("/s3/{bucket}/{key:.+}", web::head().to(s3_head_object_handler))
+        .route("/s3/{bucket}/{key:.+}", web::post().to(s3_multipart_router))
+        .route("/s3/{bucket}/{key:.+}", web::method(actix_web::http::Method::OPTIONS).to(s3_cors_not_configured_handler))
+        // Native API (registered before root S3 routes to take priority on conflicts), by
+        // version and unprefixed as the deprecated alias of v1
+        .service(web::scope("/v1").app_data(ApiVersion::V1).configure(native_routes))
+        .service(web::scope("/v2").app_data(ApiVersion::V2).configure(native_routes))
+        .configure(native_routes)
+        .service(capabilities)
+        .service(healthz)
+        .service(readyz)
+        .route("/metrics", web::get().to(metrics_handler))
+        .service(ui)
+        .service(gc)
+        .service(fsck)
+        .service(compact)
+        .service(backfill_checksums)
+        .service(compaction_plan)
+        .service(user_health)
+        .service(server_stats)
+        .service(io_stats)
+        .service(list_jobs)
+        .service(list_changes)
+        .service(get_job)
+        .service(cancel_job)
+        .service(list_inflight)
+        .service(access_top)
+        .service(shadow_report)
+        .service(replication_status)
+        .service(db_checkpoint)
+        .service(get_maintenance)
+        .service(set_maintenance)
+        .service(repair)
+        .service(tier)
+        .service(create_api_key)
+        .service(revoke_api_key)
+        .service(rotate_bucket_key)
+        .service(rewrap_bucket_keys)
+        .service(create_admin_token)
+        .service(list_admin_tokens)
+        .service(disable_admin_token)
+        .service(get_bucket_access)
+        .service(set_bucket_access)
+        .service(get_bucket_limits)
+        .service(set_bucket_limits)
+        .service(get_bucket_lifecycle)
+        .service(set_bucket_lifecycle)
+        .service(dump)
+        .service(inventory)
+        .service(get_log_level)
+        .service(set_log_level)
+        .service(trace_user)
+        .service(untrace_user)
+        .service(trace_user_logs)
+        // S3-compatible API — root form (/{bucket}/...) for standard boto3 / Ceph s3-tests
+        .route("/",                  web::get().to(s3_list_buckets_handler))
+        // SDKs address bucket operations as /{bucket}/ too; object routes need a non-empty key
+        .service(web::resource(["/{bucket}", "/{bucket}/"])
+            .route(web::put().to(s3_create_bucket_handler))
+            .route(web::delete().to(s3_delete_bucket_handler))
+            .route(web::head().to(s3_head_bucket_handler))
+            .route(web::get().to(s3_list_objects_handler))
+            .route(web::post().to(s3_delete_objects_handler))
+            .route(web::method(actix_web::http::Method::OPTIONS).to(s3_cors_not_configured_handler)))
+        .route("/{bucket}/{key:.+}", web::put().to(s3_put_object_handler))
+        .route("/{bucket}/{key:.+}", web::get().to(s3_get_object_handler))
+        .route("/{bucket}/{key:.+}", web::delete().to(s3_delete_object_handler))
+        .route("/{bucket}/{key:.+}", web::head().to(s3_head_object_handler))
+        .route("/{bucket}/{key:.+}", web::post().to(s3_multipart_router))
+        .route("/{bucket}/{key:.+}", web::method(actix_web::http::Method::OPTIONS).to(s3_cors_not_configured_handler));
+}
+
+/// TLS settings from the PEM files of `config`; fails unless both are set and readable.
+pub fn tls_config(config: &HttpConfig) -> io::Result<rustls::ServerConfig> {
+    let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "TLS needs both TLS_CERT_FILE and TLS_KEY_FILE"));
+    };
+    let pem_error = |path: &Path, e: rustls::pki_types::pem::Error| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("cannot read {}: {}", path.display(), e))
+    };
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(cert, e))?;
+    if chain.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no certificate in {}", cert.display())));
+    }
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| pem_error(key, e))?;
+    rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(chain, key))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid TLS certificate or key: {}", e)))
+}
+
+/// Bind an HTTP server for `factory` (one app per worker) as `config` says and start it.
+/// Unusable TLS files or addresses fail here, before any request is accepted.
+pub fn http_server<F, T, B>(factory: F, config: &HttpConfig) -> io::Result<Server>
+where
+    F: Fn() -> App<T> + Send + Clone + 'static,
+    T: ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<B>, Error = actix_web::Error, InitError = ()> + 'static,
+    B: MessageBody + 'static,
+{
+    let mut server = HttpServer::new(factory);
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    if let Some(keep_alive) = config.keep_alive {
+        server = server.keep_alive(if keep_alive.is_zero() { KeepAlive::Disabled } else { KeepAlive::Timeout(keep_alive) });
+    }
+    if let Some(timeout) = config.client_request_timeout {
+        server = server.client_request_timeout(timeout);
+    }
+    if let Some(timeout) = config.client_disconnect_timeout {
+        server = server.client_disconnect_timeout(timeout);
+    }
+    if let Some(max) = config.max_connections {
+        server = server.max_connections(max);
+    }
+    let address = (config.host.as_str(), config.port);
+    let server = if config.tls_enabled() {
+        info!("Serving HTTPS (HTTP/1.1 and h2) on {}:{}", config.host, config.port);
+        server.bind_rustls_0_23(address, tls_config(config)?)?
+    } else if config.h2c {
+        info!("Serving HTTP/1.1 and h2c on {}:{}", config.host, config.port);
+        server.bind_auto_h2c(address)?
+    } else {
+        server.bind(address)?
+    };
+    Ok(server.run())
+}
@@ -0,0 +1,332 @@
+//! Opt-in read tracking and last-access lifecycle rules
+//!
+//! With `TRACK_ACCESS=true`, every native GET, S3 GetObject and S3 HeadObject notes the time
+//! and counts the read in an in-memory map; nothing is written on the read path. A
+//! background task flushes the keys read since the last flush to the `access_times` table
+//! every `ACCESS_FLUSH_INTERVAL_MS` (default 10000), so what the table says lags reads by up
+//! to that long, and reads not yet flushed are lost when the process stops.
+//!
+//! The map holds up to `ACCESS_TRACK_MAX_KEYS` keys (default 100000). When it is full the
+//! least recently read key that has been flushed makes room; if every key still waits for a
+//! flush, the new read is not tracked and counted in
+//! `warpdrive_access_tracking_dropped_total`. The in-memory counts feed
+//! `GET /admin/access/top`, so they cover reads since the key entered the map.
+//!
+//! The last access is reported as `last_accessed_at` by the native listing and as
+//! `X-Warp-Last-Accessed-At` on S3 HeadObject (the read before this one). A bucket rule set
+//! with `PUT /admin/buckets/{user}/{bucket}/lifecycle` expires objects neither written nor
+//! read for `expire_unread_days` days; the deletion worker applies it each pass, after
+//! flushing this process's reads. Rules need tracking on, or every object would look unread.
+//! Settings are read at startup.
+
+use actix_web::Error;
+use chrono::Duration as ChronoDuration;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time;
+
+use crate::metadata::sqlite_store::{AccessUpdate, SQLiteMetadataStore};
+use crate::metrics;
+use crate::service::expiry;
+use crate::util::clock;
+
+/// Response header of S3 HeadObject giving the previous read
+pub const LAST_ACCESSED_HEADER: &str = "x-warp-last-accessed-at";
+
+/// Keys listed by `GET /admin/access/top` when no limit is given
+pub const DEFAULT_TOP_LIMIT: usize = 100;
+
+/// Objects expired per bucket and pass by last-access rules
+pub const SWEEP_BATCH: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessTrackingConfig {
+    pub enabled: bool,
+    /// Keys held in memory at once
+    pub max_keys: usize,
+    /// Time between flushes to `access_times`
+    pub flush_interval: Duration,
+}
+
+impl Default for AccessTrackingConfig {
+    fn default() -> Self {
+        Self { enabled: false, max_keys: 100_000, flush_interval: Duration::from_millis(10_000) }
+    }
+}
+
+impl AccessTrackingConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let enabled = env::var("TRACK_ACCESS")
+            .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "true" | "1" | "yes" | "on"))
+            .unwrap_or(defaults.enabled);
+        let max_keys = env::var("ACCESS_TRACK_MAX_KEYS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_keys);
+        let flush_interval = env::var("ACCESS_FLUSH_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v| *v > 0)
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.flush_interval);
+        Self { enabled, max_keys, flush_interval }
+    }
+}
+
+lazy_static! {
+    static ref CONFIG: AccessTrackingConfig = AccessTrackingConfig::from_env();
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Tracker::default());
+}
+
+type ObjectKey = (String, String, String);
+
+struct Entry {
+    last_accessed_at: String,
+    reads: u64,
+    /// Reads not yet in `access_times`; the entry may only be evicted at 0
+    unflushed: u64,
+    used: u64,
+}
+
+#[derive(Default)]
+struct Tracker {
+    entries: HashMap<ObjectKey, Entry>,
+    /// Keys by the tick of their last read, least recent first
+    order: BTreeMap<u64, ObjectKey>,
+    tick: u64,
+}
+
+impl Tracker {
+    /// Count a read of `key` at `now`. False when the map is full of unflushed keys.
+    fn record(&mut self, key: ObjectKey, now: String, capacity: usize) -> bool {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.order.remove(&entry.used);
+            entry.used = tick;
+            entry.reads += 1;
+            entry.unflushed += 1;
+            entry.last_accessed_at = now;
+            self.order.insert(tick, key);
+            return true;
+        }
+        if self.entries.len() >= capacity && !self.evict_flushed() {
+            return false;
+        }
+        self.order.insert(tick, key.clone());
+        self.entries.insert(key, Entry { last_accessed_at: now, reads: 1, unflushed: 1, used: tick });
+        true
+    }
+
+    /// Drop the least recently read key with nothing left to flush
+    fn evict_flushed(&mut self) -> bool {
+        let coldest = self.order.iter()
+            .find(|(_, key)| self.entries.get(*key).is_some_and(|e| e.unflushed == 0))
+            .map(|(tick, _)| *tick);
+        match coldest.and_then(|tick| self.order.remove(&tick)) {
+            Some(key) => {
+                self.entries.remove(&key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The unflushed reads of every key, which are then counted as flushed
+    fn take_unflushed(&mut self) -> Vec<AccessUpdate> {
+        self.entries.iter_mut()
+            .filter(|(_, entry)| entry.unflushed > 0)
+            .map(|((user_id, bucket, key), entry)| AccessUpdate {
+                user_id: user_id.clone(),
+                bucket: bucket.clone(),
+                key: key.clone(),
+                last_accessed_at: entry.last_accessed_at.clone(),
+                reads: std::mem::take(&mut entry.unflushed),
+            })
+            .collect()
+    }
+}
+
+fn tracker() -> MutexGuard<'static, Tracker> {
+    TRACKER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether reads are tracked (`TRACK_ACCESS`)
+pub fn enabled() -> bool {
+    CONFIG.enabled
+}
+
+/// Note a read of `user_id`'s `bucket`/`key`; does nothing unless tracking is on.
+pub fn record(user_id: &str, bucket: &str, key: &str) {
+    if !CONFIG.enabled {
+        return;
+    }
+    let key = (user_id.to_string(), bucket.to_string(), key.to_string());
+    if !tracker().record(key, expiry::now(), CONFIG.max_keys) {
+        metrics::inc_counter("warpdrive_access_tracking_dropped_total", &[]);
+    }
+}
+
+/// Write the reads noted since the last flush to `access_times`; returns how many keys were
+/// written. On failure the reads are kept for the next flush.
+pub fn flush() -> Result<usize, Error> {
+    let updates = tracker().take_unflushed();
+    if updates.is_empty() {
+        return Ok(0);
+    }
+    if let Err(e) = SQLiteMetadataStore::new().record_accesses(&updates) {
+        let mut tracker = tracker();
+        for update in updates {
+            if let Some(entry) = tracker.entries.get_mut(&(update.user_id, update.bucket, update.key)) {
+                entry.unflushed += update.reads;
+            }
+        }
+        return Err(e);
+    }
+    Ok(updates.len())
+}
+
+/// Last access of each of `keys` that was read: the later of the table and unflushed reads
+pub fn last_accessed(user_id: &str, bucket: &str, keys: &[&str]) -> Result<HashMap<String, String>, Error> {
+    let mut times = SQLiteMetadataStore::new().access_times(user_id, bucket, keys)?;
+    let tracker = tracker();
+    for key in keys {
+        if let Some(entry) = tracker.entries.get(&(user_id.to_string(), bucket.to_string(), key.to_string())) {
+            let time = times.entry(key.to_string()).or_default();
+            if entry.last_accessed_at > *time {
+                *time = entry.last_accessed_at.clone();
+            }
+        }
+    }
+    Ok(times)
+}
+
+/// Reads of one key held in memory
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyAccess {
+    pub user: String,
+    pub bucket: String,
+    pub key: String,
+    pub reads: u64,
+    pub last_accessed_at: String,
+}
+
+/// The `limit` keys in memory with the most reads, most read first
+pub fn top(limit: usize) -> Vec<KeyAccess> {
+    let tracker = tracker();
+    let mut keys: Vec<KeyAccess> = tracker.entries.iter()
+        .map(|((user, bucket, key), entry)| KeyAccess {
+            user: user.clone(),
+            bucket: bucket.clone(),
+            key: key.clone(),
+            reads: entry.reads,
+            last_accessed_at: entry.last_accessed_at.clone(),
+        })
+        .collect();
+    keys.sort_by(|a, b| b.reads.cmp(&a.reads).then_with(|| (&a.user, &a.bucket, &a.key).cmp(&(&b.user, &b.bucket, &b.key))));
+    keys.truncate(limit);
+    keys
+}
+
+/// Keys held in memory
+pub fn tracked_keys() -> usize {
+    tracker().entries.len()
+}
+
+/// Days after which unread objects of `user_id`/`bucket` expire, or None when the bucket
+/// does not exist. The inner None is no rule.
+pub fn expire_unread_days(user_id: &str, bucket: &str) -> Result<Option<Option<u32>>, Error> {
+    SQLiteMetadataStore::new().get_expire_unread_days(user_id, bucket)
+}
+
+/// Set or clear (None) the last-access rule of `user_id`/`bucket`; false when the bucket
+/// does not exist.
+pub fn set_expire_unread_days(user_id: &str, bucket: &str, days: Option<u32>) -> Result<bool, Error> {
+    SQLiteMetadataStore::new().set_expire_unread_days(user_id, bucket, days)
+}
+
+/// Expire up to [`SWEEP_BATCH`] objects per bucket left unread past their bucket's rule;
+/// returns how many were removed. Replicas, and servers not tracking reads, leave them.
+pub fn sweep_unread() -> Result<usize, Error> {
+    if !CONFIG.enabled || crate::config::is_replica() {
+        return Ok(0);
+    }
+    flush()?;
+    let store = SQLiteMetadataStore::new();
+    let mut expired = 0;
+    for (user_id, bucket, days) in store.expire_unread_rules()? {
+        let cutoff = expiry::stored(clock::now() - ChronoDuration::days(i64::from(days)));
+        for (key, version_id) in store.unread_objects(&user_id, &bucket, &cutoff, SWEEP_BATCH)? {
+            let (retained, held) = store.check_object_lock_protection(&bucket, &key, &version_id, false)?;
+            if retained || held {
+                continue;
+            }
+            match store.expire_unread_object(&user_id, &bucket, &key, &cutoff) {
+                Ok(true) => {
+                    info!("Expired key: {} in bucket: {} (user: {}) unread for {} days", key, bucket, user_id, days);
+                    crate::service::prefetch::invalidate(&user_id, &bucket, &[key.as_str()]);
+                    expired += 1;
+                }
+                Ok(false) => {}
+                Err(e) => warn!("Failed to expire unread key: {} in bucket: {} (user: {}): {}", key, bucket, user_id, e),
+            }
+        }
+    }
+    Ok(expired)
+}
+
+/// Start flushing reads in the background; `None` unless tracking is on.
+pub fn start_access_flusher() -> Option<tokio::task::JoinHandle<()>> {
+    if !CONFIG.enabled {
+        return None;
+    }
+    info!("Tracking reads; flushing access times every {}ms", CONFIG.flush_interval.as_millis());
+    Some(tokio::spawn(async move {
+        let mut interval = time::interval(CONFIG.flush_interval);
+        loop {
+            interval.tick().await;
+            // actix errors are not Send; only the message crosses back
+            match tokio::task::spawn_blocking(|| flush().map_err(|e| e.to_string())).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("Failed to flush access times: {}", e),
+                Err(e) => error!("Access time flush task failed: {}", e),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> ObjectKey {
+        ("u".to_string(), "b".to_string(), name.to_string())
+    }
+
+    #[test]
+    fn test_full_tracker_evicts_only_flushed_keys() {
+        let mut tracker = Tracker::default();
+        assert!(tracker.record(key("a"), "t1".to_string(), 2));
+        assert!(tracker.record(key("b"), "t2".to_string(), 2));
+        // Both still wait for a flush
+        assert!(!tracker.record(key("c"), "t3".to_string(), 2));
+
+        let flushed = tracker.take_unflushed();
+        assert_eq!(flushed.len(), 2);
+        assert!(tracker.take_unflushed().is_empty());
+        // Reading "a" again makes "b" the coldest flushed key
+        assert!(tracker.record(key("a"), "t4".to_string(), 2));
+        assert!(tracker.record(key("c"), "t5".to_string(), 2));
+        assert!(tracker.entries.contains_key(&key("a")) && !tracker.entries.contains_key(&key("b")));
+        assert_eq!(tracker.entries[&key("a")].reads, 2);
+        assert_eq!(tracker.entries[&key("a")].last_accessed_at, "t4");
+        assert_eq!(tracker.order.len(), 2);
+    }
+}
@@ -0,0 +1,285 @@
+//! Scoped tokens for the admin API
+//!
+//! Every `/admin` request carries `Authorization: Bearer <token>`. The token is either the
+//! configured `WARPDRIVE_ADMIN_SECRET_KEY`, which holds every scope, or one issued through
+//! `POST /admin/tokens`, which holds the scopes it was issued with and may be restricted to
+//! one user's resources. Unknown and disabled tokens get 401, a token without the scope an
+//! endpoint needs (or restricted to another user) 403. Only the SHA-256 of issued tokens is
+//! stored. On the first start with no tokens a bootstrap token holding every scope is
+//! issued and printed once.
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpMessage, HttpRequest};
+use actix_web::error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized};
+use futures::future::{ready, Ready};
+use log::warn;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::metadata::sqlite_store::{AdminTokenRecord, SQLiteMetadataStore};
+
+/// Prefix of generated tokens, so leaked tokens are easy to recognise
+const TOKEN_PREFIX: &str = "wda_";
+
+/// What an admin token may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Server, replication and access stats, user health checks, job status
+    Stats,
+    /// Garbage collection, compaction, fsck, tiering, repair and job cancellation
+    Gc,
+    /// Bucket limits, access flags and lifecycle rules
+    Quota,
+    /// Maintenance mode, database checkpoints, log levels, tracing, dumps and inventories
+    Maintenance,
+    /// Native API keys and bucket encryption keys
+    Keys,
+    /// Admin tokens themselves
+    Tokens,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 6] = [Scope::Stats, Scope::Gc, Scope::Quota, Scope::Maintenance, Scope::Keys, Scope::Tokens];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Stats => "stats",
+            Scope::Gc => "gc",
+            Scope::Quota => "quota",
+            Scope::Maintenance => "maintenance",
+            Scope::Keys => "keys",
+            Scope::Tokens => "tokens",
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL.into_iter()
+            .find(|scope| scope.as_str() == s.trim())
+            .ok_or_else(|| format!("Unknown scope: {} (expected one of stats, gc, quota, maintenance, keys, tokens)", s))
+    }
+}
+
+fn parse_scopes(scopes: &str) -> Vec<Scope> {
+    scopes.split(',').filter_map(|s| s.parse().ok()).collect()
+}
+
+fn join_scopes(scopes: &[Scope]) -> String {
+    scopes.iter().map(Scope::as_str).collect::<Vec<_>>().join(",")
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// What the bearer of an admin request may do. Extracting it authenticates the request and
+/// attaches the grant to the request's extensions.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AdminGrant {
+    /// None for the configured admin secret
+    pub token_id: Option<String>,
+    pub scopes: Vec<Scope>,
+    /// The only user whose resources the token may touch
+    pub user: Option<String>,
+    /// Description the token was issued with; empty for the admin secret
+    pub description: String,
+}
+
+impl AdminGrant {
+    fn root() -> Self {
+        AdminGrant { token_id: None, scopes: Scope::ALL.to_vec(), user: None, description: String::new() }
+    }
+
+    /// Who made a change, for the change log: the token's description (its id when it has
+    /// none), or "admin secret"
+    pub fn actor(&self) -> String {
+        match &self.token_id {
+            None => "admin secret".to_string(),
+            Some(id) if self.description.trim().is_empty() => format!("token {}", id),
+            Some(_) => self.description.clone(),
+        }
+    }
+
+    /// Fail with 403 unless the grant holds `scope`, whatever the user.
+    pub fn require_scope(&self, scope: Scope) -> Result<(), Error> {
+        if !self.scopes.contains(&scope) {
+            warn!("Admin token {:?} lacks the {} scope", self.token_id, scope.as_str());
+            return Err(ErrorForbidden(format!("Admin token lacks the {} scope", scope.as_str())));
+        }
+        Ok(())
+    }
+
+    /// Fail with 403 unless the grant holds `scope` for `user`'s resources; `None` is a
+    /// server-wide operation, which user-restricted tokens may not perform.
+    pub fn require(&self, scope: Scope, user: Option<&str>) -> Result<(), Error> {
+        self.require_scope(scope)?;
+        match (&self.user, user) {
+            (Some(allowed), Some(user)) if allowed != user => {
+                warn!("Admin token {:?} of user {} used for user {}", self.token_id, allowed, user);
+                Err(ErrorForbidden(format!("Admin token is restricted to user {}", allowed)))
+            }
+            (Some(allowed), None) => Err(ErrorForbidden(format!(
+                "Admin token is restricted to user {} and cannot perform server-wide operations", allowed
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Authenticate the bearer token of `req`.
+pub fn authenticate(req: &HttpRequest) -> Result<AdminGrant, Error> {
+    let presented = req.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| ErrorUnauthorized("Missing admin bearer token"))?;
+    let secret = std::env::var("WARPDRIVE_ADMIN_SECRET_KEY").unwrap_or_default();
+    if !secret.is_empty() && constant_time_eq(presented.as_bytes(), secret.as_bytes()) {
+        return Ok(AdminGrant::root());
+    }
+    match SQLiteMetadataStore::new().admin_token(&hash_token(presented))? {
+        Some(token) if !token.disabled => Ok(AdminGrant {
+            scopes: parse_scopes(&token.scopes),
+            token_id: Some(token.token_id),
+            user: token.user,
+            description: token.description,
+        }),
+        Some(token) => {
+            warn!("Rejected admin request to {} with disabled token {}", req.path(), token.token_id);
+            Err(ErrorUnauthorized("Admin token is disabled"))
+        }
+        None => {
+            warn!("Rejected admin request to {}", req.path());
+            Err(ErrorUnauthorized("Invalid admin token"))
+        }
+    }
+}
+
+impl FromRequest for AdminGrant {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(grant) = req.extensions().get::<AdminGrant>() {
+            return ready(Ok(grant.clone()));
+        }
+        ready(authenticate(req).inspect(|grant| {
+            req.extensions_mut().insert(grant.clone());
+        }))
+    }
+}
+
+/// A freshly issued token; `token` is only ever returned here.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedToken {
+    pub token_id: String,
+    pub token: String,
+    pub description: String,
+    pub scopes: Vec<Scope>,
+    pub user: Option<String>,
+    pub created_at: String,
+}
+
+/// A stored token as listed by `GET /admin/tokens`
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenInfo {
+    pub token_id: String,
+    pub description: String,
+    pub scopes: Vec<Scope>,
+    pub user: Option<String>,
+    pub created_at: String,
+    pub disabled: bool,
+}
+
+impl From<AdminTokenRecord> for TokenInfo {
+    fn from(record: AdminTokenRecord) -> Self {
+        TokenInfo {
+            scopes: parse_scopes(&record.scopes),
+            token_id: record.token_id,
+            description: record.description,
+            user: record.user,
+            created_at: record.created_at,
+            disabled: record.disabled,
+        }
+    }
+}
+
+/// Generate and store a token holding `scopes`, restricted to `user` when set.
+pub fn issue_token(description: &str, scopes: &[Scope], user: Option<&str>) -> Result<IssuedToken, Error> {
+    if scopes.is_empty() {
+        return Err(ErrorBadRequest("A token needs at least one scope"));
+    }
+    let mut secret = [0u8; 32];
+    getrandom::getrandom(&mut secret).map_err(ErrorInternalServerError)?;
+    let token = format!("{}{}", TOKEN_PREFIX, hex::encode(secret));
+    let token_hash = hash_token(&token);
+    let scopes: Vec<Scope> = Scope::ALL.into_iter().filter(|scope| scopes.contains(scope)).collect();
+    let record = AdminTokenRecord {
+        token_id: token_hash[..16].to_string(),
+        description: description.to_string(),
+        scopes: join_scopes(&scopes),
+        user: user.map(str::to_string),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        disabled: false,
+    };
+    SQLiteMetadataStore::new().insert_admin_token(&token_hash, &record)?;
+    Ok(IssuedToken {
+        token_id: record.token_id,
+        token,
+        description: record.description,
+        scopes,
+        user: record.user,
+        created_at: record.created_at,
+    })
+}
+
+pub fn list_tokens() -> Result<Vec<TokenInfo>, Error> {
+    Ok(SQLiteMetadataStore::new().list_admin_tokens()?.into_iter().map(TokenInfo::from).collect())
+}
+
+/// Disable `token_id`. Returns whether an enabled token was disabled.
+pub fn disable_token(token_id: &str) -> Result<bool, Error> {
+    SQLiteMetadataStore::new().disable_admin_token(token_id)
+}
+
+/// Issue a token holding every scope when none exist yet; the caller prints it once.
+pub fn bootstrap() -> Result<Option<IssuedToken>, Error> {
+    if SQLiteMetadataStore::new().admin_token_count()? > 0 {
+        return Ok(None);
+    }
+    issue_token("bootstrap", &Scope::ALL, None).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grant_scopes_and_user_restriction() {
+        let root = AdminGrant::root();
+        assert!(root.require(Scope::Tokens, None).is_ok());
+        assert!(root.require(Scope::Gc, Some("alice")).is_ok());
+
+        let grant = AdminGrant {
+            token_id: Some("t".into()),
+            scopes: vec![Scope::Gc],
+            user: Some("alice".into()),
+            description: String::new(),
+        };
+        assert!(grant.require(Scope::Gc, Some("alice")).is_ok());
+        assert!(grant.require(Scope::Gc, Some("bob")).is_err());
+        assert!(grant.require(Scope::Gc, None).is_err());
+        assert!(grant.require(Scope::Stats, Some("alice")).is_err());
+        assert_eq!(parse_scopes(&join_scopes(&Scope::ALL)), Scope::ALL.to_vec());
+        assert!("root".parse::<Scope>().is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Versions of the native API
+//!
+//! The native routes are mounted three times: under `/v1/` with the behavior they have always
+//! had, under `/v2/`, and unprefixed as aliases of v1. Both versions run the same services;
+//! the version only changes how answers are shaped. In v2 every write answers with a JSON body
+//! (PUT, update, rename and APPEND without an append id answer with text in v1), and GET sends
+//! raw bytes unless `?format=native` asks for FlatBuffers, whatever the object was stored as,
+//! where v1 sends the stored representation. FlatBuffers payloads are always v2 in v2, every
+//! file carrying its chunk index; v1 only sends v2 when file names were recorded. Errors are
+//! the same JSON in every version: 404 for a missing key, 409 for an existing one, 400 for a
+//! malformed request.
+//!
+//! Unprefixed requests, errors included, carry `Deprecation` (RFC 9745: `@<unix time>` of
+//! `NATIVE_API_DEPRECATION_DATE`, or `true` when unset) and, once `NATIVE_API_SUNSET_DATE`
+//! is set, `Sunset` (RFC 8594), so clients learn to move to `/v1/`. Dates are RFC 3339 or
+//! `YYYY-MM-DD` (midnight UTC).
+//!
+//! The scopes take the `/v1/` and `/v2/` paths ahead of the path-style S3 routes, so buckets
+//! named `v1` or `v2` are only reachable through `/s3/`.
+
+use actix_web::dev::Payload;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, FromRequest, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::warn;
+use std::env;
+use std::future::{ready, Ready};
+
+/// Native API versions listed by `/capabilities`
+pub const SUPPORTED: &[&str] = &["v1", "v2"];
+
+/// The native API version a request came in under, set as app data on the `/v1` and `/v2`
+/// scopes; available to handlers as an extractor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// An unprefixed route: v1, with deprecation headers
+    Unversioned,
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub fn of(req: &HttpRequest) -> Self {
+        req.app_data::<ApiVersion>().copied().unwrap_or(ApiVersion::Unversioned)
+    }
+
+    /// Whether writes answer with JSON rather than text
+    pub fn json_bodies(self) -> bool {
+        self == ApiVersion::V2
+    }
+
+    /// Whether GET sends raw bytes when no `?format=` is given
+    pub fn raw_by_default(self) -> bool {
+        self == ApiVersion::V2
+    }
+
+    /// Whether native GET payloads are always v2, with the chunk index of every file
+    pub fn indexed_payloads(self) -> bool {
+        self == ApiVersion::V2
+    }
+
+    /// A handler's answer as sent under this version: unversioned ones, failures included,
+    /// get the deprecation headers
+    pub fn finish(self, result: Result<HttpResponse, Error>) -> Result<HttpResponse, Error> {
+        if self != ApiVersion::Unversioned {
+            return result;
+        }
+        let mut response = result.unwrap_or_else(HttpResponse::from_error);
+        for (name, value) in DeprecationConfig::from_env().headers() {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                response.headers_mut().insert(HeaderName::from_static(name), value);
+            }
+        }
+        Ok(response)
+    }
+}
+
+impl FromRequest for ApiVersion {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(ApiVersion::of(req)))
+    }
+}
+
+/// `path` without its `/v1` or `/v2` prefix, for the middleware that treats native routes by
+/// path (`POST /get_batch` is a read)
+pub fn unversioned_path(path: &str) -> &str {
+    SUPPORTED.iter()
+        .find_map(|version| path.strip_prefix('/').and_then(|p| p.strip_prefix(version)).filter(|rest| rest.starts_with('/')))
+        .unwrap_or(path)
+}
+
+/// Dates announced on unprefixed native routes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeprecationConfig {
+    pub deprecated_at: Option<DateTime<Utc>>,
+    pub sunset_at: Option<DateTime<Utc>>,
+}
+
+impl DeprecationConfig {
+    pub fn from_env() -> Self {
+        Self {
+            deprecated_at: date_var("NATIVE_API_DEPRECATION_DATE"),
+            sunset_at: date_var("NATIVE_API_SUNSET_DATE"),
+        }
+    }
+
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(
+            "deprecation",
+            self.deprecated_at.map(|at| format!("@{}", at.timestamp())).unwrap_or_else(|| "true".to_string()),
+        )];
+        if let Some(sunset) = self.sunset_at {
+            headers.push(("sunset", sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string()));
+        }
+        headers
+    }
+}
+
+fn date_var(name: &str) -> Option<DateTime<Utc>> {
+    let value = env::var(name).ok().filter(|v| !v.trim().is_empty())?;
+    let date = parse_date(value.trim());
+    if date.is_none() {
+        warn!("Ignoring {}={:?}: not an RFC 3339 date or YYYY-MM-DD", name, value);
+    }
+    date
+}
+
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|at| at.with_timezone(&Utc))
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unversioned_path() {
+        assert_eq!(unversioned_path("/v1/get_batch"), "/get_batch");
+        assert_eq!(unversioned_path("/v2/put/a"), "/put/a");
+        assert_eq!(unversioned_path("/get_batch"), "/get_batch");
+        assert_eq!(unversioned_path("/v1beta/x"), "/v1beta/x");
+        assert_eq!(unversioned_path("/v2"), "/v2");
+    }
+
+    #[test]
+    fn test_deprecation_headers() {
+        assert_eq!(DeprecationConfig::default().headers(), vec![("deprecation", "true".to_string())]);
+        let config = DeprecationConfig {
+            deprecated_at: parse_date("2026-11-01"),
+            sunset_at: parse_date("2027-05-01T00:00:00Z"),
+        };
+        assert_eq!(config.headers(), vec![
+            ("deprecation", "@1793491200".to_string()),
+            ("sunset", "Sat, 01 May 2027 00:00:00 GMT".to_string()),
+        ]);
+        assert_eq!(parse_date("May 2027"), None);
+    }
+}
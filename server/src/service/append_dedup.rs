@@ -0,0 +1,98 @@
+//! Idempotent native appends
+//!
+//! A client may tag an append with `X-Warp-Append-Id`. The last few ids applied to a key are
+//! kept in the object's `append.ids` property together with the chunks they produced, so a
+//! retried append (e.g. after a client-side timeout) is answered from the record instead of
+//! writing the payload a second time.
+
+use crate::metadata::Metadata;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Request header carrying the client's id for one logical append
+pub const APPEND_ID_HEADER: &str = "x-warp-append-id";
+
+const APPEND_IDS_PROPERTY: &str = "append.ids";
+
+/// How many append ids are remembered per key
+#[derive(Debug, Clone)]
+pub struct AppendDedupConfig {
+    pub window: usize,
+}
+
+impl Default for AppendDedupConfig {
+    fn default() -> Self {
+        Self { window: 16 }
+    }
+}
+
+impl AppendDedupConfig {
+    pub fn from_env() -> Self {
+        let window = env::var("APPEND_DEDUP_WINDOW")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(Self::default().window);
+        Self { window }
+    }
+}
+
+/// Chunks added to an object by one tagged append
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppendRecord {
+    pub id: String,
+    /// Index of the first chunk this append added
+    pub first_chunk: usize,
+    pub chunks: usize,
+    pub size: u64,
+}
+
+fn records(metadata: &Metadata) -> Vec<AppendRecord> {
+    match metadata.properties.get(APPEND_IDS_PROPERTY) {
+        Some(json) => serde_json::from_str(json).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable {} property: {}", APPEND_IDS_PROPERTY, e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    }
+}
+
+/// The record of an append with `id` already applied to this object, if still in the window.
+pub fn find(metadata: &Metadata, id: &str) -> Option<AppendRecord> {
+    records(metadata).into_iter().find(|r| r.id == id)
+}
+
+/// Remember `record`, keeping only the most recent `window` ids.
+pub fn remember(metadata: &mut Metadata, record: AppendRecord, window: usize) {
+    let mut records = records(metadata);
+    records.push(record);
+    if records.len() > window {
+        records.drain(..records.len() - window);
+    }
+    metadata.properties.insert(
+        APPEND_IDS_PROPERTY.to_string(),
+        serde_json::to_string(&records).unwrap_or_else(|_| "[]".to_string()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, first_chunk: usize) -> AppendRecord {
+        AppendRecord { id: id.to_string(), first_chunk, chunks: 1, size: 4 }
+    }
+
+    #[test]
+    fn test_window_keeps_most_recent_ids() {
+        let mut metadata = Metadata::from_offset_size_list(vec![(0, 4)]);
+        assert!(find(&metadata, "a").is_none());
+
+        for (i, id) in ["a", "b", "c"].iter().enumerate() {
+            remember(&mut metadata, record(id, i + 1), 2);
+        }
+        assert!(find(&metadata, "a").is_none());
+        assert_eq!(find(&metadata, "b"), Some(record("b", 2)));
+        assert_eq!(find(&metadata, "c"), Some(record("c", 3)));
+    }
+}
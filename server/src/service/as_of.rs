@@ -0,0 +1,38 @@
+//! Reads of a bucket as it was at a past time
+//!
+//! `GET /get/{key}?as_of=<RFC 3339>` serves the row of the key that was latest at that time:
+//! the newest one created at or before it, by the time it was written. If that row is a delete
+//! marker the answer is 404 with `X-Warp-Object-State: deleted`; if the key had no row yet,
+//! a plain 404. `GET /list?as_of=` lists the keys live at that time the same way.
+//!
+//! Older rows are only kept in buckets with versioning enabled or suspended; elsewhere a write
+//! replaces the row, so the past is only seen up to the last write of each key. Rows from
+//! before creation times were recorded are never found. Times carry an offset or `Z` and are
+//! compared in UTC at millisecond precision, as rows are stamped; rows stamped to the second
+//! by older versions are rewritten in that form at startup.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::service::error::ServiceError;
+
+/// `value` as a stored timestamp, comparable with row creation times
+pub fn parse(value: &str) -> Result<String, ServiceError> {
+    let time = DateTime::parse_from_rfc3339(value.trim()).map_err(|e| {
+        ServiceError::InvalidRequest(format!("as_of must be an RFC 3339 time such as 2024-06-01T00:00:00Z, not {:?}: {}", value, e))
+    })?;
+    Ok(time.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_normalizes_to_utc_millis() {
+        assert_eq!(parse("2024-06-01T00:00:00Z").unwrap(), "2024-06-01T00:00:00.000Z");
+        assert_eq!(parse("2024-06-01T02:30:00.123456+02:00").unwrap(), "2024-06-01T00:30:00.123Z");
+        for bad in ["2024-06-01", "2024-06-01T00:00:00", "yesterday", ""] {
+            assert!(matches!(parse(bad), Err(ServiceError::InvalidRequest(_))), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Native batch reads (`POST /get_batch`)
+//!
+//! The request body is a JSON array of keys. Every object found is returned as one v2
+//! `FileData` named after its key (chunks concatenated), in request order, its `index` the
+//! key's position in the request. Keys that could not be read are left out of the list and
+//! reported in the `X-Warp-Batch-Errors` header, so one missing key does not fail the whole
+//! batch. Objects are read concurrently, bounded
+//! by `GET_BATCH_CONCURRENCY`.
+
+use actix_web::Error;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use bytes::Bytes;
+use log::warn;
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::service::metadata_service::MetadataService;
+use crate::service::storage_service::{finished_bytes, payload_builder, StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+use crate::util::flatbuffer_store_v2_generated::store_v2;
+
+/// Response header listing the keys that were not returned
+pub const BATCH_ERRORS_HEADER: &str = "x-warp-batch-errors";
+
+/// Limits for one batch request
+#[derive(Debug, Clone)]
+pub struct BatchGetConfig {
+    /// Most keys accepted in one request
+    pub max_keys: usize,
+    /// Objects read at the same time
+    pub concurrency: usize,
+}
+
+impl Default for BatchGetConfig {
+    fn default() -> Self {
+        Self { max_keys: 1000, concurrency: 8 }
+    }
+}
+
+impl BatchGetConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let max_keys = env::var("GET_BATCH_MAX_KEYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_keys);
+        let concurrency = env::var("GET_BATCH_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.concurrency);
+        Self { max_keys, concurrency }
+    }
+}
+
+/// A key left out of the response
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BatchError {
+    pub key: String,
+    pub error: &'static str,
+}
+
+struct BatchObject {
+    key: String,
+    data: Bytes,
+    content_type: Option<String>,
+}
+
+/// Parse and validate the JSON key list.
+pub fn parse_keys(body: &[u8], config: &BatchGetConfig) -> Result<Vec<String>, Error> {
+    let keys: Vec<String> = serde_json::from_slice(body)
+        .map_err(|e| ErrorBadRequest(format!("Body must be a JSON array of keys: {}", e)))?;
+    if keys.is_empty() {
+        return Err(ErrorBadRequest("No keys requested"));
+    }
+    if keys.len() > config.max_keys {
+        return Err(ErrorBadRequest(format!(
+            "Too many keys: {} requested, at most {} allowed", keys.len(), config.max_keys
+        )));
+    }
+    Ok(keys)
+}
+
+fn read_object(context: &UserContext, key: &str) -> Result<Option<BatchObject>, Error> {
+    let db = MetadataService::new(&context.user_id)?;
+    if !db.check_key(&context.bucket, key)? {
+        return Ok(None);
+    }
+    let metadata = db.get_object_full(&context.bucket, key)?;
+    let data = StorageService::new().read_stored(context, &metadata, StorageMode::RAW)?;
+    Ok(Some(BatchObject {
+        key: key.to_string(),
+        data,
+        content_type: metadata.file_attrs().into_iter().find_map(|f| f.content_type),
+    }))
+}
+
+/// Read `keys` from the context's bucket, returning the v2 payload and the per-key errors.
+pub async fn read_batch(context: &UserContext, keys: Vec<String>, config: &BatchGetConfig) -> Result<(Bytes, Vec<BatchError>), Error> {
+    let permits = Arc::new(Semaphore::new(config.concurrency));
+    let reads = keys.into_iter().map(|key| {
+        let permits = permits.clone();
+        let context = context.clone();
+        async move {
+            let _permit = permits.acquire_owned().await.map_err(ErrorInternalServerError)?;
+            let result = tokio::task::spawn_blocking({
+                let key = key.clone();
+                // actix errors are not Send; only the message crosses back
+                move || read_object(&context, &key).map_err(|e| e.to_string())
+            }).await.map_err(ErrorInternalServerError)?;
+            Ok::<_, Error>((key, result))
+        }
+    });
+
+    let reads = futures::future::join_all(reads).await;
+    let objects: Vec<&BatchObject> = reads.iter()
+        .filter_map(|read| match read {
+            Ok((_, Ok(Some(object)))) => Some(object),
+            _ => None,
+        })
+        .collect();
+    let data_bytes = objects.iter()
+        .map(|o| o.data.len() + o.key.len() + o.content_type.as_ref().map_or(0, String::len))
+        .sum();
+    let mut builder = payload_builder(data_bytes, objects.len());
+    let mut file_data_vec = Vec::new();
+    let mut errors = Vec::new();
+    for (position, read) in reads.into_iter().enumerate() {
+        let (key, result) = read?;
+        let object = match result {
+            Ok(Some(object)) => object,
+            Ok(None) => {
+                errors.push(BatchError { key, error: "NoSuchKey" });
+                continue;
+            }
+            Err(e) => {
+                warn!("Batch read of key: {} in bucket: {} failed: {}", key, context.bucket, e);
+                errors.push(BatchError { key, error: "ReadFailed" });
+                continue;
+            }
+        };
+        let data = builder.create_vector(&object.data);
+        let name = builder.create_string(&object.key);
+        let content_type = object.content_type.as_deref().map(|c| builder.create_string(c));
+        file_data_vec.push(store_v2::FileData::create(&mut builder, &store_v2::FileDataArgs {
+            data: Some(data),
+            name: Some(name),
+            content_type,
+            index: position as u32,
+        }));
+    }
+    let files = builder.create_vector(&file_data_vec);
+    let list = store_v2::FileDataList::create(&mut builder, &store_v2::FileDataListArgs { files: Some(files) });
+    store_v2::finish_file_data_list_buffer(&mut builder, list);
+    Ok((finished_bytes(builder), errors))
+}
+
+/// JSON for the errors header, with non-ASCII escaped so any key fits in a header value.
+pub fn errors_header(errors: &[BatchError]) -> String {
+    let json = serde_json::to_string(errors).unwrap_or_else(|_| "[]".to_string());
+    let mut out = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            let mut units = [0u16; 2];
+            for unit in c.encode_utf16(&mut units) {
+                out.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys_enforces_limits() {
+        let config = BatchGetConfig { max_keys: 2, concurrency: 1 };
+        assert_eq!(parse_keys(br#"["a","b"]"#, &config).unwrap(), vec!["a", "b"]);
+        assert!(parse_keys(br#"["a","b","c"]"#, &config).is_err());
+        assert!(parse_keys(b"[]", &config).is_err());
+        assert!(parse_keys(br#"{"keys":["a"]}"#, &config).is_err());
+    }
+
+    #[test]
+    fn test_errors_header_is_ascii_json() {
+        let errors = vec![BatchError { key: "café/🚀".to_string(), error: "NoSuchKey" }];
+        let header = errors_header(&errors);
+        assert!(header.is_ascii());
+        let parsed: serde_json::Value = serde_json::from_str(&header).unwrap();
+        assert_eq!(parsed[0]["key"], "café/🚀");
+    }
+}
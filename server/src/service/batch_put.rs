@@ -0,0 +1,224 @@
+//! Native batch writes (`POST /put_batch`)
+//!
+//! The request body is a v2 `FileDataList`; every `FileData` is one object, stored under its
+//! `name` as a single chunk with its content type. Keys that already exist are reported as
+//! conflicts and not written unless `?overwrite=true`. The remaining payloads are written to
+//! storage and all of their metadata rows are committed in one transaction, so either every
+//! written object becomes visible or none does; chunks that end up without metadata (a failed
+//! commit, a key taken meanwhile) are queued for deletion. The response lists one result per
+//! file, in request order.
+
+use actix_web::Error;
+use actix_web::error::ErrorBadRequest;
+use log::warn;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::env;
+
+use crate::metadata::sqlite_store::BatchRowOutcome;
+use crate::metadata::{DataChunk, FileAttrs};
+use crate::service::key_limits::KeyLimits;
+use crate::service::metadata_service::{BatchObject, MetadataService};
+use crate::service::native_payload::{self, NativePayloadConfig};
+use crate::service::user_context::UserContext;
+use crate::storage::config::StorageConfig;
+use crate::util::flatbuffer_store_v2_generated::store_v2;
+
+/// Limits for one batch request
+#[derive(Debug, Clone)]
+pub struct BatchPutConfig {
+    /// Most files accepted in one request
+    pub max_keys: usize,
+    /// Largest request body, in bytes
+    pub max_bytes: usize,
+}
+
+impl Default for BatchPutConfig {
+    fn default() -> Self {
+        Self { max_keys: 1000, max_bytes: 64 * 1024 * 1024 }
+    }
+}
+
+impl BatchPutConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let max_keys = env::var("PUT_BATCH_MAX_KEYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_keys);
+        let max_bytes = env::var("PUT_BATCH_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_bytes);
+        Self { max_keys, max_bytes }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchPutStatus {
+    Created,
+    /// Written over an existing object (`?overwrite=true`)
+    Replaced,
+    /// The key exists, or appears earlier in the same batch
+    Conflict,
+    Error,
+}
+
+/// Outcome for one file of the batch
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchPutResult {
+    pub key: String,
+    pub status: BatchPutStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchPutResult {
+    fn new(key: &str, status: BatchPutStatus, error: Option<String>) -> Self {
+        Self { key: key.to_string(), status, error }
+    }
+
+    /// Whether the object was stored
+    pub fn stored(&self) -> bool {
+        matches!(self.status, BatchPutStatus::Created | BatchPutStatus::Replaced)
+    }
+}
+
+/// One file of the request
+pub struct BatchItem<'a> {
+    pub key: &'a str,
+    pub data: &'a [u8],
+    pub content_type: Option<&'a str>,
+}
+
+/// Parse and validate the v2 payload, verified as in [`native_payload`] with at most
+/// `max_keys` files.
+pub fn parse_items<'a>(body: &'a [u8], config: &BatchPutConfig) -> Result<Vec<BatchItem<'a>>, Error> {
+    if !store_v2::file_data_list_buffer_has_identifier(body) {
+        return Err(ErrorBadRequest("Body must be a FlatBuffers v2 FileDataList"));
+    }
+    let limits = NativePayloadConfig { max_files: config.max_keys, ..NativePayloadConfig::from_env() };
+    let files = native_payload::parse(body, &limits)?;
+    if files.is_empty() {
+        return Err(ErrorBadRequest("No files found in FlatBuffers data"));
+    }
+    files.iter().enumerate().map(|(i, file)| {
+        let key = file.name.filter(|name| !name.is_empty())
+            .ok_or_else(|| ErrorBadRequest(format!("File {} has no name; every file needs its target key", i)))?;
+        Ok(BatchItem { key, data: file.data.unwrap_or_default(), content_type: file.content_type })
+    }).collect()
+}
+
+/// Store `items` in the context's bucket. Per-file problems are reported in the results;
+/// an error is returned only when the metadata commit fails, after the written chunks have
+/// been queued for deletion.
+pub fn write_batch(context: &UserContext, items: &[BatchItem], overwrite: bool) -> Result<Vec<BatchPutResult>, Error> {
+    let db = MetadataService::new(&context.user_id)?;
+    let key_limits = KeyLimits::from_env();
+    let store = StorageConfig::from_env().create_store();
+
+    let mut results: Vec<Option<BatchPutResult>> = vec![None; items.len()];
+    let mut seen = HashSet::new();
+    let mut written: Vec<(usize, BatchObject)> = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        if let Err(e) = key_limits.check_new_key(item.key) {
+            results[i] = Some(BatchPutResult::new(item.key, BatchPutStatus::Error, Some(e.to_string())));
+            continue;
+        }
+        let repeated = !seen.insert(item.key);
+        if !overwrite && (repeated || db.check_key(&context.bucket, item.key)?) {
+            results[i] = Some(BatchPutResult::new(item.key, BatchPutStatus::Conflict, None));
+            continue;
+        }
+        let extents = if item.data.is_empty() {
+            Vec::new()
+        } else {
+            match store.write(&context.user_id, &context.bucket, item.data) {
+                Ok(extent) => vec![extent],
+                Err(e) => {
+                    warn!("Batch write of key: {} in bucket: {} failed: {}", item.key, context.bucket, e);
+                    results[i] = Some(BatchPutResult::new(item.key, BatchPutStatus::Error, Some(e.to_string())));
+                    continue;
+                }
+            }
+        };
+        let attrs = FileAttrs {
+            name: Some(item.key.to_string()),
+            content_type: item.content_type.map(str::to_string),
+        };
+        written.push((i, (item.key.to_string(), extents, attrs)));
+    }
+
+    let rows: Vec<BatchObject> = written.iter().map(|(_, row)| row.clone()).collect();
+    let outcomes = match db.write_metadata_batch(&context.bucket, &rows, overwrite) {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            warn!("Batch metadata commit in bucket: {} failed, queueing {} written objects for deletion: {}",
+                  context.bucket, written.len(), e);
+            for (_, (key, extents, _)) in &written {
+                queue_orphans(&db, &context.bucket, key, extents);
+            }
+            return Err(e);
+        }
+    };
+    for ((i, (key, extents, _)), outcome) in written.into_iter().zip(outcomes) {
+        let status = match outcome {
+            BatchRowOutcome::Created => BatchPutStatus::Created,
+            BatchRowOutcome::Replaced(previous) => {
+                let previous: Vec<_> = previous.iter().map(DataChunk::extent).collect();
+                queue_orphans(&db, &context.bucket, &key, &previous);
+                BatchPutStatus::Replaced
+            }
+            // Created by another request after the check above
+            BatchRowOutcome::Exists => {
+                queue_orphans(&db, &context.bucket, &key, &extents);
+                BatchPutStatus::Conflict
+            }
+        };
+        results[i] = Some(BatchPutResult::new(&key, status, None));
+    }
+    Ok(results.into_iter().flatten().collect())
+}
+
+fn queue_orphans(db: &MetadataService, bucket: &str, key: &str, extents: &[(u64, u64)]) {
+    if extents.is_empty() {
+        return;
+    }
+    if let Err(e) = db.queue_deletion(bucket, key, extents) {
+        warn!("Failed to queue {} unreferenced extents of key: {} in bucket: {}: {}", extents.len(), key, bucket, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flatbuffers::FlatBufferBuilder;
+
+    fn payload(files: &[(Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut builder = FlatBufferBuilder::new();
+        let files: Vec<_> = files.iter().map(|(name, data)| {
+            let data = builder.create_vector(data);
+            let name = name.map(|n| builder.create_string(n));
+            store_v2::FileData::create(&mut builder, &store_v2::FileDataArgs { data: Some(data), name, ..Default::default() })
+        }).collect();
+        let files = builder.create_vector(&files);
+        let list = store_v2::FileDataList::create(&mut builder, &store_v2::FileDataListArgs { files: Some(files) });
+        store_v2::finish_file_data_list_buffer(&mut builder, list);
+        builder.finished_data().to_vec()
+    }
+
+    #[test]
+    fn test_parse_items_enforces_names_and_limits() {
+        let config = BatchPutConfig { max_keys: 2, max_bytes: 1024 };
+        let body = payload(&[(Some("a"), b"1"), (Some("b"), b"")]);
+        let items = parse_items(&body, &config).unwrap();
+        assert_eq!(items.iter().map(|i| (i.key, i.data)).collect::<Vec<_>>(), vec![("a", &b"1"[..]), ("b", &b""[..])]);
+        assert!(parse_items(&payload(&[(Some("a"), b"1"), (Some("b"), b"2"), (Some("c"), b"3")]), &config).is_err());
+        assert!(parse_items(&payload(&[(Some("a"), b"1"), (None, b"2")]), &config).is_err());
+        assert!(parse_items(&payload(&[]), &config).is_err());
+        assert!(parse_items(b"not flatbuffers", &config).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+//! Per-bucket S3 access flags
+//!
+//! `public_read` lets GET/HEAD through without credentials, acting as the bucket owner;
+//! `read_only` rejects PUT/DELETE/POST even for valid credentials. Flags live in the
+//! `buckets` table and are set through the admin API. Lookups happen on every S3 request,
+//! so answers are cached in memory and dropped whenever `MetadataService` changes the flags
+//! or deletes the bucket, or another process changes the configuration
+//! ([`config_generation`](crate::service::config_generation)).
+
+use actix_web::Error;
+use lazy_static::lazy_static;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::metadata::sqlite_store::{BucketAccess, SQLiteMetadataStore};
+use crate::service::config_generation;
+
+lazy_static! {
+    // (user_id, bucket) -> flags; buckets that do not exist are cached with default flags
+    static ref ACCESS: RwLock<HashMap<(String, String), BucketAccess>> = RwLock::new(HashMap::new());
+    // bucket -> owner serving anonymous reads, if any
+    static ref PUBLIC_OWNERS: RwLock<HashMap<String, Option<String>>> = RwLock::new(HashMap::new());
+}
+
+/// Flags of `user_id`/`bucket` (all off for unknown buckets).
+pub fn access(user_id: &str, bucket: &str) -> Result<BucketAccess, Error> {
+    config_generation::revalidate();
+    let cache_key = (user_id.to_string(), bucket.to_string());
+    if let Some(access) = ACCESS.read().unwrap_or_else(|e| e.into_inner()).get(&cache_key) {
+        return Ok(*access);
+    }
+    let access = SQLiteMetadataStore::new().get_bucket_access(user_id, bucket)?.unwrap_or_default();
+    ACCESS.write().unwrap_or_else(|e| e.into_inner()).insert(cache_key, access);
+    Ok(access)
+}
+
+/// The owner whose `bucket` is public-read. Names shared by several public buckets are
+/// ambiguous for an anonymous caller and resolve to no owner.
+pub fn public_owner(bucket: &str) -> Result<Option<String>, Error> {
+    config_generation::revalidate();
+    if let Some(owner) = PUBLIC_OWNERS.read().unwrap_or_else(|e| e.into_inner()).get(bucket) {
+        return Ok(owner.clone());
+    }
+    let mut owners = SQLiteMetadataStore::new().public_read_owners(bucket)?;
+    if owners.len() > 1 {
+        warn!("Bucket name {} is public-read for {} users; anonymous reads refused", bucket, owners.len());
+    }
+    let owner = if owners.len() == 1 { owners.pop() } else { None };
+    PUBLIC_OWNERS.write().unwrap_or_else(|e| e.into_inner()).insert(bucket.to_string(), owner.clone());
+    Ok(owner)
+}
+
+/// Drop cached answers for every bucket called `bucket`.
+pub(crate) fn invalidate(bucket: &str) {
+    ACCESS.write().unwrap_or_else(|e| e.into_inner()).retain(|(_, b), _| b != bucket);
+    PUBLIC_OWNERS.write().unwrap_or_else(|e| e.into_inner()).remove(bucket);
+}
+
+/// Drop every cached answer.
+pub(crate) fn clear() {
+    ACCESS.write().unwrap_or_else(|e| e.into_inner()).clear();
+    PUBLIC_OWNERS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
@@ -0,0 +1,217 @@
+//! Native bucket grants
+//!
+//! The owner of a bucket can let another user into it without sharing a user namespace:
+//! `PUT /grants/{bucket}/{grantee}?perm=read|write|admin` grants (or changes) access,
+//! `GET /grants/{bucket}` lists the bucket's grants and `DELETE /grants/{bucket}/{grantee}`
+//! revokes one. `read` allows GET, HEAD and batch reads; `write` also every change to the
+//! bucket's objects; `admin` also managing the bucket's grants. Grants live in the
+//! `bucket_grants` table and go with the bucket.
+//!
+//! A native request whose `Bucket` header names a bucket the user does not have but was
+//! granted works in the owner's namespace: it reads and writes the owner's objects and
+//! storage, and nothing is copied. When several owners granted buckets of that name, the
+//! `Bucket-Owner` header picks one; it may also name the owner when the user has a bucket of
+//! the same name. Copies and moves through a grant stay within the granted bucket, and bucket
+//! routes only reach another user's bucket through a grant on it. `GET /buckets` lists the
+//! user's own buckets and those granted to them.
+//!
+//! Grants are cached per grantee. A change drops the cache, and bumps the configuration
+//! generation so other processes drop theirs ([`config_generation`]); a revocation applies to
+//! the next request.
+
+use actix_web::http::Method;
+use actix_web::{Error, HttpRequest};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::metadata::sqlite_store::{BucketGrantRow, SQLiteMetadataStore};
+use crate::service::config_generation;
+use crate::service::error::ServiceError;
+use crate::service::metadata_service::MetadataService;
+use crate::service::user_context::UserContext;
+
+/// Names the owner of the granted bucket a request means
+pub const BUCKET_OWNER_HEADER: &str = "bucket-owner";
+
+/// What a grant allows, each level including the ones below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "read" => Ok(Permission::Read),
+            "write" => Ok(Permission::Write),
+            "admin" => Ok(Permission::Admin),
+            _ => Err(format!("Unknown permission: {} (expected read, write or admin)", s)),
+        }
+    }
+}
+
+lazy_static! {
+    // grantee -> grants to them
+    static ref GRANTS: RwLock<HashMap<String, Vec<BucketGrantRow>>> = RwLock::new(HashMap::new());
+}
+
+/// Grants to `grantee`, by bucket and owner
+pub fn grants_to(grantee: &str) -> Result<Vec<BucketGrantRow>, Error> {
+    config_generation::revalidate();
+    if let Some(grants) = GRANTS.read().unwrap_or_else(|e| e.into_inner()).get(grantee) {
+        return Ok(grants.clone());
+    }
+    let grants = SQLiteMetadataStore::new().grants_to(grantee)?;
+    GRANTS.write().unwrap_or_else(|e| e.into_inner()).insert(grantee.to_string(), grants.clone());
+    Ok(grants)
+}
+
+/// Drop every cached grant.
+pub(crate) fn clear() {
+    GRANTS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Permission a native request needs on its bucket: reads need `read`, anything else `write`
+pub fn required(req: &HttpRequest) -> Permission {
+    let read = matches!(*req.method(), Method::GET | Method::HEAD) || req.path().ends_with("/get_batch");
+    if read { Permission::Read } else { Permission::Write }
+}
+
+fn denied(bucket: &str, message: String) -> Error {
+    ServiceError::AccessDenied { bucket: bucket.to_string(), message }.into()
+}
+
+/// `context` as a request needing `needed` on its bucket works: in its own namespace, or in
+/// the owner's when the bucket is granted to the user (see the module documentation).
+/// `owner` is the `Bucket-Owner` header, if any.
+pub fn resolve(context: UserContext, owner: Option<&str>, needed: Permission) -> Result<UserContext, Error> {
+    let user = context.user_id.clone();
+    if owner == Some(user.as_str()) {
+        return Ok(context);
+    }
+    let mut grants: Vec<BucketGrantRow> = grants_to(&user)?.into_iter()
+        .filter(|grant| grant.bucket == context.bucket && owner.is_none_or(|owner| grant.owner == owner))
+        .collect();
+    let grant = match (owner, grants.len()) {
+        (Some(owner), 0) => {
+            warn!("User {} denied bucket {} of {}: no grant", user, context.bucket, owner);
+            return Err(denied(&context.bucket, format!("Bucket {} of {} is not granted to {}", context.bucket, owner, user)));
+        }
+        (None, 0) => return Ok(context),
+        // The user's own bucket of that name comes first
+        (None, _) if MetadataService::new(&user)?.bucket_exists(&context.bucket)? => return Ok(context),
+        (None, 1) | (Some(_), _) => grants.remove(0),
+        (None, _) => {
+            let owners: Vec<&str> = grants.iter().map(|grant| grant.owner.as_str()).collect();
+            return Err(ServiceError::InvalidRequest(format!(
+                "Bucket {} is granted by {}; name one in the Bucket-Owner header", context.bucket, owners.join(", ")
+            )).into());
+        }
+    };
+    let permission: Permission = grant.permission.parse().map_err(ServiceError::Internal)?;
+    if permission < needed {
+        warn!("User {} denied {} on bucket {} of {}: granted {}", user, needed.as_str(), grant.bucket, grant.owner, permission.as_str());
+        return Err(denied(&grant.bucket, format!(
+            "Bucket {} of {} is granted to {} for {} only", grant.bucket, grant.owner, user, permission.as_str()
+        )));
+    }
+    Ok(context.acting_for(&grant.owner))
+}
+
+/// Refuse, under a grant, a request reaching `bucket` besides the granted one
+pub fn check_same_bucket(context: &UserContext, bucket: &str) -> Result<(), Error> {
+    match &context.granted_to {
+        Some(user) if bucket != context.bucket => Err(denied(bucket, format!(
+            "{} may only use bucket {} of {} through its grant", user, context.bucket, context.user_id
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The owner working on `context`'s bucket: its user, unless acting through a grant, which
+/// must then be `admin`
+fn managed_bucket(context: &UserContext) -> Result<(), Error> {
+    let Some(user) = &context.granted_to else { return Ok(()) };
+    let granted = grants_to(user)?.into_iter()
+        .find(|grant| grant.owner == context.user_id && grant.bucket == context.bucket)
+        .and_then(|grant| grant.permission.parse::<Permission>().ok());
+    if granted != Some(Permission::Admin) {
+        return Err(denied(&context.bucket, format!(
+            "Only the owner or an admin grantee may manage the grants of bucket {}", context.bucket
+        )));
+    }
+    Ok(())
+}
+
+/// Grant `grantee` `permission` on `context`'s bucket
+pub fn grant(context: &UserContext, grantee: &str, permission: Permission) -> Result<(), Error> {
+    managed_bucket(context)?;
+    if grantee.is_empty() || grantee == context.user_id {
+        return Err(ServiceError::InvalidRequest("A bucket can only be granted to another user".to_string()).into());
+    }
+    if !MetadataService::new(&context.user_id)?.bucket_exists(&context.bucket)? {
+        return Err(ServiceError::InvalidRequest(format!("Bucket {} does not exist", context.bucket)).into());
+    }
+    SQLiteMetadataStore::new().put_bucket_grant(&context.user_id, &context.bucket, grantee, permission.as_str())?;
+    clear();
+    info!("User {} granted {} on bucket {} of {} to {}",
+          context.authenticated_user(), permission.as_str(), context.bucket, context.user_id, grantee);
+    Ok(())
+}
+
+/// Revoke `grantee`'s grant on `context`'s bucket; false when there was none
+pub fn revoke(context: &UserContext, grantee: &str) -> Result<bool, Error> {
+    managed_bucket(context)?;
+    let revoked = SQLiteMetadataStore::new().delete_bucket_grant(&context.user_id, &context.bucket, grantee)?;
+    clear();
+    if revoked {
+        info!("User {} revoked the grant on bucket {} of {} to {}", context.authenticated_user(), context.bucket, context.user_id, grantee);
+    }
+    Ok(revoked)
+}
+
+/// Grants on `context`'s bucket
+pub fn list(context: &UserContext) -> Result<Vec<BucketGrantRow>, Error> {
+    managed_bucket(context)?;
+    SQLiteMetadataStore::new().bucket_grants(&context.user_id, &context.bucket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permissions_order_and_parse() {
+        assert!(Permission::Read < Permission::Write && Permission::Write < Permission::Admin);
+        assert_eq!("Write".parse::<Permission>(), Ok(Permission::Write));
+        assert!("owner".parse::<Permission>().is_err());
+    }
+
+    #[test]
+    fn test_check_same_bucket() {
+        let own = UserContext::with_bucket("alice".to_string(), "shared".to_string());
+        assert!(check_same_bucket(&own, "other").is_ok());
+        let granted = UserContext::with_bucket("bob".to_string(), "shared".to_string()).acting_for("alice");
+        assert_eq!((granted.user_id.as_str(), granted.authenticated_user()), ("alice", "bob"));
+        assert!(check_same_bucket(&granted, "shared").is_ok());
+        assert!(check_same_bucket(&granted, "other").is_err());
+    }
+}
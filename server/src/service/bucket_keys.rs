@@ -0,0 +1,353 @@
+//! Per-bucket encryption keys
+//!
+//! With `ENCRYPTION_MASTER_KEY` set, chunks are encrypted at rest by
+//! `storage::encrypted_store` under envelope encryption. Every bucket gets a random 256-bit
+//! data encryption key (DEK) on its first write, stored in `bucket_keys` wrapped by the master
+//! key (the KEK) with AES-256-GCM. Each row records the fingerprint of the master key that
+//! wrapped it, and every encrypted chunk records the DEK version it was written with.
+//!
+//! `POST /admin/keys/rotate?user=&bucket=` adds a DEK version that later writes use. Chunks
+//! keep the version they were written with, so older data stays readable.
+//!
+//! To change the master key, set the new one as `ENCRYPTION_MASTER_KEY` and the old one as
+//! `ENCRYPTION_PREVIOUS_MASTER_KEY`, then `POST /admin/keys/rewrap`. It unwraps every DEK
+//! with the old key and wraps it with the new one without touching object data. Until then,
+//! reading a chunk whose DEK another master key wrapped fails with an error naming both
+//! fingerprints. Undecrypted bytes are never served.
+//!
+//! Keys are given as 64 hex digits or as base64 of 32 bytes.
+
+use actix_web::error::ErrorInternalServerError;
+use actix_web::Error;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use ctr::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use crate::metadata::sqlite_store::{BucketKeyRow, SQLiteMetadataStore};
+
+/// Bytes of a master key or DEK
+pub const KEY_BYTES: usize = 32;
+
+/// Bytes of the IV each encrypted chunk is written with
+pub const IV_BYTES: usize = 16;
+
+const NONCE_BYTES: usize = 12;
+
+/// AES-256 in counter mode: ciphertext is as long as the data, and any range of a chunk can
+/// be decrypted on its own
+type ChunkCipher = ctr::Ctr128BE<aes::Aes256>;
+
+/// A data encryption key, unwrapped
+pub type DataKey = [u8; KEY_BYTES];
+
+lazy_static! {
+    // (user, bucket, version, master key id) -> unwrapped DEK
+    static ref DATA_KEYS: RwLock<HashMap<(String, String, u32, String), DataKey>> = RwLock::new(HashMap::new());
+    static ref KEYS_STORED: AtomicBool = AtomicBool::new(SQLiteMetadataStore::new().has_bucket_keys().unwrap_or_else(|e| {
+        error!("Could not tell whether bucket encryption keys exist: {}", e);
+        true
+    }));
+}
+
+/// A master key (KEK)
+#[derive(Clone)]
+pub struct MasterKey {
+    key: [u8; KEY_BYTES],
+}
+
+impl MasterKey {
+    pub fn new(key: [u8; KEY_BYTES]) -> Self {
+        Self { key }
+    }
+
+    /// Read 64 hex digits or base64 of 32 bytes
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let bytes = hex::decode(value).ok()
+            .or_else(|| base64::engine::general_purpose::STANDARD.decode(value).ok())
+            .ok_or_else(|| "neither hex nor base64".to_string())?;
+        let key = bytes.try_into().map_err(|b: Vec<u8>| format!("{} bytes instead of {}", b.len(), KEY_BYTES))?;
+        Ok(Self { key })
+    }
+
+    /// Fingerprint stored with the keys this one wraps: the first 8 bytes of its SHA-256
+    pub fn id(&self) -> String {
+        hex::encode(&Sha256::digest(self.key)[..8])
+    }
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MasterKey({})", self.id())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EncryptionConfig {
+    /// Key wrapping the DEKs; encryption is off without one
+    pub master_key: Option<MasterKey>,
+    /// Key DEKs were wrapped with before the current one, for `rewrap`
+    pub previous_master_key: Option<MasterKey>,
+    /// Why a configured key was refused
+    pub error: Option<String>,
+}
+
+impl EncryptionConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        let mut read = |name: &str| match env::var(name) {
+            Ok(value) if !value.trim().is_empty() => match MasterKey::parse(&value) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    error!("Ignoring {}: {}", name, e);
+                    config.error.get_or_insert(format!("{} is not a valid key: {}", name, e));
+                    None
+                }
+            },
+            _ => None,
+        };
+        let (master_key, previous_master_key) = (read("ENCRYPTION_MASTER_KEY"), read("ENCRYPTION_PREVIOUS_MASTER_KEY"));
+        Self { master_key, previous_master_key, ..config }
+    }
+
+    /// Whether a master key is configured, usable or not
+    pub fn configured(&self) -> bool {
+        self.master_key.is_some() || self.error.is_some()
+    }
+
+    fn master(&self) -> Result<&MasterKey, Error> {
+        if let Some(e) = &self.error {
+            return Err(ErrorInternalServerError(e.clone()));
+        }
+        self.master_key.as_ref()
+            .ok_or_else(|| ErrorInternalServerError("Bucket data is encrypted but ENCRYPTION_MASTER_KEY is not set"))
+    }
+}
+
+/// Whether storage goes through encryption: a master key is configured or some bucket has
+/// keys, whose chunks must not be read as stored
+pub fn in_use() -> bool {
+    EncryptionConfig::from_env().configured() || KEYS_STORED.load(Ordering::Relaxed)
+}
+
+fn aad(user_id: &str, bucket: &str, version: u32) -> Vec<u8> {
+    format!("{}\0{}\0{}", user_id, bucket, version).into_bytes()
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).expect("OS random number generator unavailable");
+    bytes
+}
+
+/// `key` sealed under `master` as nonce followed by ciphertext and tag
+fn wrap(master: &MasterKey, user_id: &str, bucket: &str, version: u32, key: &DataKey) -> Vec<u8> {
+    let nonce: [u8; NONCE_BYTES] = random();
+    let cipher = Aes256Gcm::new((&master.key).into());
+    let sealed = cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: key, aad: &aad(user_id, bucket, version) })
+        .expect("AES-GCM encryption of a key cannot fail");
+    [nonce.as_slice(), &sealed].concat()
+}
+
+fn unwrap(master: &MasterKey, row: &BucketKeyRow) -> Result<DataKey, Error> {
+    if row.master_key_id != master.id() {
+        return Err(ErrorInternalServerError(format!(
+            "Key version {} of bucket {}/{} is wrapped by master key {}, not the configured {}; \
+             set ENCRYPTION_PREVIOUS_MASTER_KEY and run POST /admin/keys/rewrap",
+            row.version, row.user_id, row.bucket, row.master_key_id, master.id()
+        )));
+    }
+    let (nonce, sealed) = row.wrapped_key.split_at(NONCE_BYTES.min(row.wrapped_key.len()));
+    let cipher = Aes256Gcm::new((&master.key).into());
+    cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: &aad(&row.user_id, &row.bucket, row.version) })
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| ErrorInternalServerError(format!(
+            "Key version {} of bucket {}/{} does not unwrap under master key {}", row.version, row.user_id, row.bucket, master.id()
+        )))
+}
+
+/// Unwrap `row`, remembering the key for later chunks
+fn data_key(master: &MasterKey, row: &BucketKeyRow) -> Result<DataKey, Error> {
+    let cache_key = (row.user_id.clone(), row.bucket.clone(), row.version, master.id());
+    if let Some(key) = DATA_KEYS.read().unwrap_or_else(|e| e.into_inner()).get(&cache_key) {
+        return Ok(*key);
+    }
+    let key = unwrap(master, row)?;
+    DATA_KEYS.write().unwrap_or_else(|e| e.into_inner()).insert(cache_key, key);
+    Ok(key)
+}
+
+/// Store a fresh DEK as `version`; when another writer stored that version first, theirs
+fn create_key(db: &SQLiteMetadataStore, master: &MasterKey, user_id: &str, bucket: &str, version: u32) -> Result<BucketKeyRow, Error> {
+    let row = BucketKeyRow {
+        user_id: user_id.to_string(),
+        bucket: bucket.to_string(),
+        version,
+        wrapped_key: wrap(master, user_id, bucket, version, &random()),
+        master_key_id: master.id(),
+    };
+    if db.insert_bucket_key(&row)? {
+        KEYS_STORED.store(true, Ordering::Relaxed);
+        info!("Created key version {} for bucket {}/{}", version, user_id, bucket);
+        return Ok(row);
+    }
+    db.bucket_keys(user_id, bucket)?.into_iter()
+        .find(|row| row.version == version)
+        .ok_or_else(|| ErrorInternalServerError(format!("Key version {} of bucket {}/{} vanished", version, user_id, bucket)))
+}
+
+/// The version and key new chunks of the bucket are written with, created on the bucket's
+/// first write
+pub fn current_key(user_id: &str, bucket: &str) -> Result<(u32, DataKey), Error> {
+    let config = EncryptionConfig::from_env();
+    let master = config.master()?;
+    let db = SQLiteMetadataStore::new();
+    let row = match db.bucket_keys(user_id, bucket)?.pop() {
+        Some(row) => row,
+        None => create_key(&db, master, user_id, bucket, 1)?,
+    };
+    Ok((row.version, data_key(master, &row)?))
+}
+
+/// Key `version` of the bucket, for reading the chunks written with it
+pub fn key(user_id: &str, bucket: &str, version: u32) -> Result<DataKey, Error> {
+    let config = EncryptionConfig::from_env();
+    let master = config.master()?;
+    let cache_key = (user_id.to_string(), bucket.to_string(), version, master.id());
+    if let Some(key) = DATA_KEYS.read().unwrap_or_else(|e| e.into_inner()).get(&cache_key) {
+        return Ok(*key);
+    }
+    let row = SQLiteMetadataStore::new().bucket_keys(user_id, bucket)?.into_iter()
+        .find(|row| row.version == version)
+        .ok_or_else(|| ErrorInternalServerError(format!("Bucket {}/{} has no key version {}", user_id, bucket, version)))?;
+    data_key(master, &row)
+}
+
+/// Add a key version for the bucket's next writes; returns it
+pub fn rotate(user_id: &str, bucket: &str) -> Result<u32, Error> {
+    let config = EncryptionConfig::from_env();
+    let master = config.master()?;
+    let db = SQLiteMetadataStore::new();
+    let next = db.bucket_keys(user_id, bucket)?.last().map_or(1, |row| row.version + 1);
+    Ok(create_key(&db, master, user_id, bucket, next)?.version)
+}
+
+/// Outcome of [`rewrap`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RewrapReport {
+    pub master_key_id: String,
+    /// Keys moved from the previous master key to the current one
+    pub rewrapped: usize,
+    /// Keys already under the current master key
+    pub current: usize,
+    /// `user/bucket/version` of keys under neither master key, or that did not unwrap
+    pub failed: Vec<String>,
+}
+
+/// Wrap every stored DEK under the previous master key with the current one
+pub fn rewrap() -> Result<RewrapReport, Error> {
+    let config = EncryptionConfig::from_env();
+    let master = config.master()?;
+    let db = SQLiteMetadataStore::new();
+    let mut report = RewrapReport { master_key_id: master.id(), ..RewrapReport::default() };
+    for row in db.all_bucket_keys()? {
+        let name = format!("{}/{}/{}", row.user_id, row.bucket, row.version);
+        if row.master_key_id == master.id() {
+            report.current += 1;
+            continue;
+        }
+        let previous = config.previous_master_key.as_ref().filter(|previous| previous.id() == row.master_key_id);
+        let key = match previous.map(|previous| unwrap(previous, &row)) {
+            Some(Ok(key)) => key,
+            Some(Err(e)) => {
+                warn!("Could not rewrap key {}: {}", name, e);
+                report.failed.push(name);
+                continue;
+            }
+            None => {
+                warn!("Could not rewrap key {}: wrapped by unknown master key {}", name, row.master_key_id);
+                report.failed.push(name);
+                continue;
+            }
+        };
+        if db.rewrap_bucket_key(&row, &wrap(master, &row.user_id, &row.bucket, row.version, &key), &master.id())? {
+            report.rewrapped += 1;
+        } else {
+            report.current += 1;
+        }
+    }
+    info!("Rewrapped {} bucket keys under master key {} ({} already current, {} failed)",
+          report.rewrapped, report.master_key_id, report.current, report.failed.len());
+    Ok(report)
+}
+
+/// Encrypt or decrypt `data` in place, the bytes of a chunk written with `key` and `iv`
+/// starting `position` bytes into it
+pub fn apply_keystream(key: &DataKey, iv: &[u8], position: u64, data: &mut [u8]) -> Result<(), Error> {
+    let iv: &[u8; IV_BYTES] = iv.try_into()
+        .map_err(|_| ErrorInternalServerError(format!("Chunk IV of {} bytes instead of {}", iv.len(), IV_BYTES)))?;
+    let mut cipher = ChunkCipher::new(key.into(), iv.into());
+    cipher.seek(position);
+    cipher.apply_keystream(data);
+    Ok(())
+}
+
+/// A random IV for a new chunk
+pub fn new_iv() -> [u8; IV_BYTES] {
+    random()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_master_key_formats() {
+        let hex_key = MasterKey::parse(&"ab".repeat(32)).unwrap();
+        let base64_key = MasterKey::parse(&base64::engine::general_purpose::STANDARD.encode([0xab; 32])).unwrap();
+        assert_eq!(hex_key.id(), base64_key.id());
+        assert!(MasterKey::parse("abcd").is_err());
+        assert!(MasterKey::parse("not a key").is_err());
+    }
+
+    #[test]
+    fn test_wrapped_keys_only_open_under_their_master_key() {
+        let (master, other) = (MasterKey::new([1; 32]), MasterKey::new([2; 32]));
+        let key: DataKey = [7; 32];
+        let row = |wrapped_key, master_key_id| BucketKeyRow {
+            user_id: "u".to_string(), bucket: "b".to_string(), version: 3, wrapped_key, master_key_id,
+        };
+        let wrapped = wrap(&master, "u", "b", 3, &key);
+        assert_eq!(unwrap(&master, &row(wrapped.clone(), master.id())).unwrap(), key);
+        assert!(unwrap(&other, &row(wrapped.clone(), master.id())).is_err());
+        // A fingerprint that lies does not get past the tag
+        assert!(unwrap(&other, &row(wrapped.clone(), other.id())).is_err());
+        // Nor does a key moved to another bucket or version
+        let moved = BucketKeyRow { version: 4, ..row(wrapped, master.id()) };
+        assert!(unwrap(&master, &moved).is_err());
+    }
+
+    #[test]
+    fn test_ranges_decrypt_on_their_own() {
+        let (key, iv) = ([9; 32], [5; IV_BYTES]);
+        let data: Vec<u8> = (0..100u8).collect();
+        let mut sealed = data.clone();
+        apply_keystream(&key, &iv, 0, &mut sealed).unwrap();
+        assert_ne!(sealed, data);
+        let mut middle = sealed[37..61].to_vec();
+        apply_keystream(&key, &iv, 37, &mut middle).unwrap();
+        assert_eq!(middle, data[37..61]);
+        assert!(apply_keystream(&key, &iv[..8], 0, &mut middle).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Per-bucket quotas
+//!
+//! A bucket may cap its live object count (`max_objects`) and the bytes of its live objects
+//! (`max_bytes`); both are unset by default and set through
+//! `PUT /admin/buckets/{user}/{bucket}/limits`. Usage is counted from the metadata the same
+//! way as the bucket stats, so a delete frees quota as soon as its metadata row is gone, not
+//! when the deletion worker reclaims the bytes. The parts of uploads in progress (S3
+//! multipart uploads and upload sessions) count against `max_bytes` from when they are
+//! stored until the upload is aborted, expires or completes, when the object takes their
+//! place. A write that would take the bucket past a limit fails with 403 `QuotaExceeded`.
+//! Appends and replacements count only the bytes they add. New keys are checked against the
+//! object limit before their data is written; the byte limit is only known after, so the
+//! chunks of a refused write are left for GC. A part is checked again in the transaction
+//! that stores it, so concurrent parts never overshoot; object writes are checked apart
+//! from their commit, so concurrent writers can overshoot a limit by the object writes in
+//! flight. Utilization of the fullest buckets is exported as
+//! `warpdrive_bucket_quota_utilization`.
+
+use actix_web::Error;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Mutex, RwLock};
+
+use crate::metadata::sqlite_store::{BucketLimits, SQLiteMetadataStore};
+use crate::metrics;
+use crate::service::config_generation;
+use crate::service::error::ServiceError;
+
+const UTILIZATION_GAUGE: &str = "warpdrive_bucket_quota_utilization";
+
+/// Buckets exported in the utilization gauge when `BUCKET_QUOTA_METRICS_TOP_N` is unset
+pub const DEFAULT_METRICS_TOP_N: usize = 10;
+
+/// Live objects of a bucket, and their bytes with those of its uploads' parts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+lazy_static! {
+    // (user_id, bucket) -> limits; unknown buckets are cached as unlimited
+    static ref LIMITS: RwLock<HashMap<(String, String), BucketLimits>> = RwLock::new(HashMap::new());
+    // (user_id, bucket, limit) -> fraction of the limit in use
+    static ref UTILIZATION: Mutex<HashMap<(String, String, &'static str), f64>> = Mutex::new(HashMap::new());
+}
+
+fn metrics_top_n() -> usize {
+    env::var("BUCKET_QUOTA_METRICS_TOP_N")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_METRICS_TOP_N)
+}
+
+/// Limits of `user_id`/`bucket` (unlimited for unknown buckets).
+pub fn limits(user_id: &str, bucket: &str) -> Result<BucketLimits, Error> {
+    config_generation::revalidate();
+    let cache_key = (user_id.to_string(), bucket.to_string());
+    if let Some(limits) = LIMITS.read().unwrap_or_else(|e| e.into_inner()).get(&cache_key) {
+        return Ok(*limits);
+    }
+    let limits = SQLiteMetadataStore::new().get_bucket_limits(user_id, bucket)?.unwrap_or_default();
+    LIMITS.write().unwrap_or_else(|e| e.into_inner()).insert(cache_key, limits);
+    Ok(limits)
+}
+
+/// Fail with [`ServiceError::QuotaExceeded`] when adding `added_objects` objects and
+/// `added_bytes` bytes to a bucket at `usage` would pass one of its `limits`. Writes that
+/// add nothing to a dimension are never refused on it, so a bucket over its limit (for
+/// example after the limit was lowered) can still be shrunk.
+pub fn check(bucket: &str, limits: &BucketLimits, usage: Usage, added_objects: u64, added_bytes: i64) -> Result<(), ServiceError> {
+    let exceeded = |limit: &'static str, max: u64| ServiceError::QuotaExceeded {
+        bucket: bucket.to_string(),
+        limit,
+        max,
+        objects: usage.objects,
+        bytes: usage.bytes,
+    };
+    if let Some(max) = limits.max_objects {
+        if added_objects > 0 && usage.objects.saturating_add(added_objects) > max {
+            return Err(exceeded("max_objects", max));
+        }
+    }
+    if let Some(max) = limits.max_bytes {
+        if added_bytes > 0 && usage.bytes.saturating_add(added_bytes as u64) > max {
+            return Err(exceeded("max_bytes", max));
+        }
+    }
+    Ok(())
+}
+
+/// Record how much of its limits `user_id`/`bucket` uses and export the fullest buckets.
+pub fn record_usage(user_id: &str, bucket: &str, limits: &BucketLimits, usage: Usage) {
+    let mut utilization = UTILIZATION.lock().unwrap_or_else(|e| e.into_inner());
+    for (limit, max, used) in [("max_objects", limits.max_objects, usage.objects), ("max_bytes", limits.max_bytes, usage.bytes)] {
+        let series = (user_id.to_string(), bucket.to_string(), limit);
+        match max {
+            Some(max) => utilization.insert(series, if max == 0 { 1.0 } else { used as f64 / max as f64 }),
+            None => utilization.remove(&series),
+        };
+    }
+    export_utilization(&utilization, metrics_top_n());
+}
+
+fn export_utilization(utilization: &HashMap<(String, String, &'static str), f64>, top_n: usize) {
+    let mut fullest: Vec<_> = utilization.iter().collect();
+    fullest.sort_by(|a, b| b.1.total_cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let series: Vec<(Vec<(&str, &str)>, f64)> = fullest
+        .into_iter()
+        .take(top_n)
+        .map(|((user, bucket, limit), used)| (vec![("user", user.as_str()), ("bucket", bucket.as_str()), ("limit", *limit)], *used))
+        .collect();
+    metrics::replace_gauge_series(UTILIZATION_GAUGE, &series);
+}
+
+/// Drop cached limits and exported utilization of every bucket called `bucket`.
+pub(crate) fn invalidate(bucket: &str) {
+    LIMITS.write().unwrap_or_else(|e| e.into_inner()).retain(|(_, b), _| b != bucket);
+    let mut utilization = UTILIZATION.lock().unwrap_or_else(|e| e.into_inner());
+    utilization.retain(|(_, b, _), _| b != bucket);
+    export_utilization(&utilization, metrics_top_n());
+}
+
+/// Drop every cached limit.
+pub(crate) fn clear() {
+    LIMITS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_objects: Option<u64>, max_bytes: Option<u64>) -> BucketLimits {
+        BucketLimits { max_objects, max_bytes }
+    }
+
+    #[test]
+    fn test_object_limit() {
+        let usage = Usage { objects: 3, bytes: 30 };
+        assert!(check("b", &limits(Some(4), None), usage, 1, 10).is_ok());
+        let err = check("b", &limits(Some(3), None), usage, 1, 10).unwrap_err();
+        assert_eq!(err, ServiceError::QuotaExceeded { bucket: "b".into(), limit: "max_objects", max: 3, objects: 3, bytes: 30 });
+        // Replacing or appending adds no object
+        assert!(check("b", &limits(Some(3), None), usage, 0, 10).is_ok());
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let usage = Usage { objects: 1, bytes: 90 };
+        assert!(check("b", &limits(None, Some(100)), usage, 0, 10).is_ok());
+        let err = check("b", &limits(Some(10), Some(100)), usage, 0, 11).unwrap_err();
+        assert!(matches!(err, ServiceError::QuotaExceeded { limit: "max_bytes", max: 100, .. }), "{:?}", err);
+        // Shrinking an object is allowed even over the limit
+        assert!(check("b", &limits(None, Some(50)), usage, 0, -20).is_ok());
+        assert!(check("b", &BucketLimits::default(), usage, 1_000, 1 << 40).is_ok());
+    }
+}
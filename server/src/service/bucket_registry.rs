@@ -0,0 +1,116 @@
+//! Bucket registration checks for the native write path
+//!
+//! By default the first native write into a bucket registers it implicitly. With
+//! `STRICT_BUCKETS=true` writes are only accepted into buckets created explicitly
+//! (`PUT /bucket/{bucket}` or S3 CreateBucket), so a mistyped `Bucket` header fails instead
+//! of scattering data into a new bucket. Known buckets are cached in memory; `MetadataService`
+//! keeps the cache in sync whenever it creates or deletes a bucket, and it is dropped when
+//! another process changes the configuration.
+//!
+//! HEAD on a bucket (`HEAD /bucket/{bucket}`, S3 HeadBucket) answers whether it exists and
+//! reports its size in [`OBJECT_COUNT_HEADER`] and [`BYTES_HEADER`]. Outside strict mode a
+//! bucket holding objects exists even if it was never registered.
+
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use lazy_static::lazy_static;
+use serde_json::json;
+use std::collections::HashSet;
+use std::env;
+use std::sync::RwLock;
+
+use crate::service::config_generation;
+
+/// Response header with the bucket's live object count
+pub const OBJECT_COUNT_HEADER: &str = "x-warp-object-count";
+/// Response header with the bucket's live bytes
+pub const BYTES_HEADER: &str = "x-warp-bytes";
+
+/// Bucket policy for native writes
+#[derive(Debug, Clone, Default)]
+pub struct BucketConfig {
+    /// Reject writes into buckets that were never created explicitly
+    pub strict: bool,
+}
+
+impl BucketConfig {
+    pub fn from_env() -> Self {
+        let strict = env::var("STRICT_BUCKETS")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+        Self { strict }
+    }
+}
+
+lazy_static! {
+    // (user_id, bucket) pairs known to exist. Only positive answers are cached so a bucket
+    // created through any path is picked up by the next lookup.
+    static ref KNOWN_BUCKETS: RwLock<HashSet<(String, String)>> = RwLock::new(HashSet::new());
+}
+
+pub(crate) fn is_known(user_id: &str, bucket: &str) -> bool {
+    config_generation::revalidate();
+    KNOWN_BUCKETS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains(&(user_id.to_string(), bucket.to_string()))
+}
+
+pub(crate) fn remember(user_id: &str, bucket: &str) {
+    KNOWN_BUCKETS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert((user_id.to_string(), bucket.to_string()));
+}
+
+pub(crate) fn forget(user_id: &str, bucket: &str) {
+    KNOWN_BUCKETS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&(user_id.to_string(), bucket.to_string()));
+}
+
+pub(crate) fn clear() {
+    KNOWN_BUCKETS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Strict-mode native write into a bucket that was never created; maps to 400 JSON.
+#[derive(Debug)]
+pub struct NoSuchBucketError {
+    pub bucket: String,
+}
+
+impl std::fmt::Display for NoSuchBucketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The specified bucket does not exist: {}", self.bucket)
+    }
+}
+
+impl ResponseError for NoSuchBucketError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "error": "NoSuchBucket",
+            "message": "The specified bucket does not exist",
+            "bucket": self.bucket,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_remember_forget() {
+        assert!(!is_known("registry_user", "registry-bucket"));
+        remember("registry_user", "registry-bucket");
+        assert!(is_known("registry_user", "registry-bucket"));
+        assert!(!is_known("other_user", "registry-bucket"));
+        forget("registry_user", "registry-bucket");
+        assert!(!is_known("registry_user", "registry-bucket"));
+    }
+}
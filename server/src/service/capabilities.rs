@@ -0,0 +1,179 @@
+//! Capability discovery for client libraries
+//!
+//! `GET /capabilities` (and `GET /s3/?capabilities`) describe what this deployment supports,
+//! so clients can skip features that are missing or switched off instead of probing for them.
+//! The document is built from the same configuration the handlers use: the body limits the
+//! app was started with, and the `*Config::from_env()` readers for the rest, which it follows
+//! without a restart. It never touches the database. HEAD bucket carries the enabled feature
+//! names in `x-warp-features`.
+
+use actix_web::HttpRequest;
+use serde::Serialize;
+
+use crate::metadata::config::MetadataConfig;
+use crate::service::append_dedup::AppendDedupConfig;
+use crate::service::dedup::DedupConfig;
+use crate::service::bucket_keys::EncryptionConfig;
+use crate::service::batch_get::BatchGetConfig;
+use crate::service::batch_put::BatchPutConfig;
+use crate::service::txn::TxnConfig;
+use crate::service::bucket_registry::BucketConfig;
+use crate::service::api_version;
+use crate::service::key_limits::KeyLimits;
+use crate::service::multipart_limits::MultipartLimitConfig;
+use crate::service::native_auth::NativeAuthConfig;
+use crate::storage::config::{StorageBackend, StorageConfig};
+use crate::storage::durability::FsyncPolicy;
+use crate::storage::replicated_store::ReplicationConfig;
+use crate::storage::remote_s3_store::RemoteS3Config;
+use crate::ui::UiConfig;
+use crate::util::payload::PayloadLimits;
+
+/// Response header listing the enabled features on HEAD bucket
+pub const FEATURES_HEADER: &str = "x-warp-features";
+
+/// API revisions this server answers
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiVersions {
+    pub native: Vec<&'static str>,
+    pub s3: Vec<&'static str>,
+}
+
+/// Optional features. Ones this build does not implement are reported as off rather than
+/// left out, so clients can tell "unsupported" from "unknown server".
+#[derive(Debug, Clone, Serialize)]
+pub struct Features {
+    /// Byte ranges on S3 GetObject and chunk ranges on native GET
+    pub range_reads: bool,
+    pub versioning: bool,
+    pub soft_delete: bool,
+    pub encryption: bool,
+    /// Codec applied to stored chunks, `none` when data is stored as sent
+    pub compression: &'static str,
+    /// Native GET returning bare bytes instead of a FileDataList (`?format=raw`, S3 objects)
+    pub raw_format: bool,
+    pub append: bool,
+    /// Append deduplication through `Append-Id`
+    pub append_dedup: bool,
+    pub presign: bool,
+    pub multipart: bool,
+    /// Metadata-only clones (`/clone`, CopyObject with `x-warp-cow`)
+    pub copy_on_write: bool,
+    /// Chunks already stored in the bucket are shared instead of written again
+    pub content_dedup: bool,
+    /// Moving a key to another bucket (`/move`)
+    pub cross_bucket_move: bool,
+    pub strict_buckets: bool,
+    pub native_auth: bool,
+    pub ui: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Limits {
+    pub max_object_size: usize,
+    pub max_control_body: usize,
+    pub max_key_length: usize,
+    pub get_batch_max_keys: usize,
+    pub put_batch_max_keys: usize,
+    pub put_batch_max_bytes: usize,
+    pub txn_max_ops: usize,
+    pub txn_max_bytes: usize,
+    /// Multipart uploads one user may have in progress; None for no limit
+    pub multipart_max_uploads: Option<u64>,
+    /// Total part bytes of one multipart upload
+    pub multipart_max_upload_bytes: u64,
+    /// Fixed chunk size, if the server splits objects itself; chunks are client-defined
+    pub chunk_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub server_version: &'static str,
+    pub api_versions: ApiVersions,
+    pub storage_backend: &'static str,
+    pub metadata_backend: &'static str,
+    pub fsync_policy: &'static str,
+    pub features: Features,
+    pub limits: Limits,
+}
+
+impl Capabilities {
+    /// What the server answering `req` supports: the body limits it was built with, the
+    /// rest from the environment
+    pub fn of(req: &HttpRequest) -> Self {
+        let storage = StorageConfig::from_env();
+        // Replicated without a secondary directory, or mirrored without a remote, runs as a
+        // plain local store
+        let storage_backend = match storage.backend {
+            StorageBackend::Replicated if ReplicationConfig::from_env().is_none() => StorageBackend::LocalXFS,
+            StorageBackend::Mirrored if RemoteS3Config::from_env().is_none() => StorageBackend::LocalXFS,
+            backend => backend,
+        };
+        let payload = PayloadLimits::of(req);
+        let put_batch = BatchPutConfig::from_env();
+        let txn = TxnConfig::from_env();
+        let multipart = MultipartLimitConfig::from_env();
+        Self {
+            server_version: env!("CARGO_PKG_VERSION"),
+            api_versions: ApiVersions { native: api_version::SUPPORTED.to_vec(), s3: vec!["2006-03-01"] },
+            storage_backend: storage_backend.as_str(),
+            metadata_backend: MetadataConfig::from_env().backend.as_str(),
+            fsync_policy: FsyncPolicy::from_env().as_str(),
+            features: Features {
+                range_reads: true,
+                versioning: true,
+                soft_delete: false,
+                encryption: EncryptionConfig::from_env().master_key.is_some(),
+                compression: "none",
+                raw_format: true,
+                append: true,
+                append_dedup: AppendDedupConfig::from_env().window > 0,
+                presign: true,
+                multipart: true,
+                copy_on_write: true,
+                content_dedup: DedupConfig::from_env().enabled,
+                cross_bucket_move: true,
+                strict_buckets: BucketConfig::from_env().strict,
+                native_auth: NativeAuthConfig::from_env().required,
+                ui: UiConfig::from_env().enabled,
+            },
+            limits: Limits {
+                max_object_size: payload.max_bytes,
+                max_control_body: payload.max_control_bytes,
+                max_key_length: KeyLimits::from_env().max_key_bytes,
+                get_batch_max_keys: BatchGetConfig::from_env().max_keys,
+                put_batch_max_keys: put_batch.max_keys,
+                put_batch_max_bytes: put_batch.max_bytes,
+                txn_max_ops: txn.max_ops,
+                txn_max_bytes: txn.max_bytes,
+                multipart_max_uploads: multipart.max_uploads_per_user,
+                multipart_max_upload_bytes: multipart.max_upload_bytes,
+                chunk_size: None,
+            },
+        }
+    }
+
+    /// Names of the enabled boolean features, for `x-warp-features`
+    pub fn enabled_features(&self) -> Vec<&'static str> {
+        let f = &self.features;
+        [
+            ("range_reads", f.range_reads),
+            ("versioning", f.versioning),
+            ("soft_delete", f.soft_delete),
+            ("encryption", f.encryption),
+            ("raw_format", f.raw_format),
+            ("append", f.append),
+            ("append_dedup", f.append_dedup),
+            ("presign", f.presign),
+            ("multipart", f.multipart),
+            ("copy_on_write", f.copy_on_write),
+            ("cross_bucket_move", f.cross_bucket_move),
+            ("strict_buckets", f.strict_buckets),
+            ("native_auth", f.native_auth),
+            ("ui", f.ui),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect()
+    }
+}
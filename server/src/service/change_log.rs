@@ -0,0 +1,224 @@
+//! Append-only audit of schema migrations and configuration changes
+//!
+//! Entries live in the `change_log` table of the metadata database, each with the time, the
+//! actor (the admin token's description, "admin secret" for `WARPDRIVE_ADMIN_SECRET_KEY`, or
+//! "startup"), what changed and its JSON value before and after. Recorded are:
+//!
+//! - `schema`: each column added or backfill run by a migration at startup
+//! - `config`: the server's environment configuration at startup, when it differs from the
+//!   previous snapshot. Variables whose name contains SECRET, PASSWORD or TOKEN are
+//!   redacted; unset ones take their defaults and are left out.
+//! - `maintenance`, `log_level`, `bucket_access`, `bucket_limits`, `bucket_lifecycle` and
+//!   `tier`: changes made through the admin endpoints
+//! - `storage_layout`: a user's files moved by `warp_drive migrate-layout`
+//! - `instance`: the deployment's instance id written everywhere by `warp_drive serve --force-adopt`
+//!
+//! `GET /admin/changes?since=` lists them oldest first. Entries are never updated or
+//! deleted. Recording a runtime change is best effort: the change is already applied, so a
+//! failure to record it is logged rather than returned.
+
+use actix_web::Error;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{info, warn};
+use serde_json::{Map, Value};
+use std::env;
+
+use crate::metadata::sqlite_store::{ChangeRecord, SQLiteMetadataStore};
+
+/// Entries listed when no limit is given
+pub const DEFAULT_LIST_LIMIT: usize = 1000;
+
+/// Actor of changes made while the server starts
+pub const STARTUP_ACTOR: &str = "startup";
+
+/// Value shown in place of a secret
+pub const REDACTED: &str = "<redacted>";
+
+/// Environment variables making up the configuration snapshot
+const CONFIG_VARS: &[&str] = &[
+    "ADMIN_DUMP_ENABLED",
+    "ACCESS_FLUSH_INTERVAL_MS",
+    "ACCESS_TRACK_MAX_KEYS",
+    "ALLOW_EMPTY_APPEND",
+    "APPEND_DEDUP_WINDOW",
+    "BACKEND_RETRY_BACKOFF_MS",
+    "BACKEND_RETRY_MAX_ATTEMPTS",
+    "BUCKET_QUOTA_METRICS_TOP_N",
+    "CHECKSUM_BACKFILL_BATCH_KEYS",
+    "CHECKSUM_BACKFILL_MAX_BYTES_PER_SEC",
+    "COLD_DIRECTORY",
+    "COMPACTION_FREE_RATIO",
+    "COMPACTION_PASS_BYTES",
+    "CONFIG_REVALIDATE_INTERVAL_MS",
+    "CORS_ALLOWED_ORIGINS",
+    "CPU_POOL_MIN_BYTES",
+    "CPU_POOL_THREADS",
+    "DB_FILE",
+    "DB_MAINTENANCE_INTERVAL_SECS",
+    "DB_VACUUM_FREELIST_RATIO",
+    "DB_VACUUM_STEP_PAGES",
+    "DEDUP_ENABLED",
+    "DEDUP_MIN_CHUNK_BYTES",
+    "DELETION_CLEANUP_BATCH",
+    "DELETION_QUEUE_ALERT_THRESHOLD",
+    "DELETION_RETENTION_DAYS",
+    "DIGEST_SAMPLE_RATE",
+    "DOWNLOAD_TOKEN_SECRET",
+    "ENCRYPTION_MASTER_KEY",
+    "ENCRYPTION_PREVIOUS_MASTER_KEY",
+    "FSYNC_INTERVAL_MS",
+    "FSYNC_POLICY",
+    "GET_BATCH_CONCURRENCY",
+    "GET_BATCH_MAX_KEYS",
+    "GET_READ_AHEAD_CHUNKS",
+    "HOT_DIRECTORY",
+    "HTTP_CLIENT_DISCONNECT_TIMEOUT_MS",
+    "HTTP_CLIENT_REQUEST_TIMEOUT_MS",
+    "HTTP_H2C",
+    "HTTP_KEEP_ALIVE_SECS",
+    "HTTP_MAX_CONNECTIONS",
+    "HTTP_WORKERS",
+    "IDEMPOTENCY_WINDOW_SECS",
+    "INFLIGHT_HUNG_SECS",
+    "INLINE_THRESHOLD_BYTES",
+    "INVENTORY_DIRECTORY",
+    "IO_STATS_TOP_USERS",
+    "KEY_FILTER_CAPACITY",
+    "KEY_FILTER_ENABLED",
+    "KEY_FILTER_FP_RATE",
+    "LEASE_DEFAULT_TTL_SECS",
+    "LEASE_MAX_TTL_SECS",
+    "MAX_CONTROL_PAYLOAD_BYTES",
+    "MAX_KEY_LENGTH",
+    "MAX_PAYLOAD_BYTES",
+    "METADATA_BACKEND",
+    "METADATA_GROUP_COMMIT_MS",
+    "METADATA_GROUP_COMMIT_ROWS",
+    "METADATA_SHADOW_BACKEND",
+    "METADATA_SHADOW_QUEUE",
+    "MIRROR_MAX_ATTEMPTS",
+    "MIRROR_QUEUE_CAPACITY",
+    "MIRROR_RETRY_BACKOFF_MS",
+    "MULTIPART_MAX_UPLOADS_PER_USER",
+    "MULTIPART_MAX_UPLOAD_BYTES",
+    "NATIVE_API_DEPRECATION_DATE",
+    "NATIVE_API_SUNSET_DATE",
+    "NATIVE_AUTH",
+    "NATIVE_MAX_FILES",
+    "NATIVE_MAX_FILE_BYTES",
+    "PAYLOAD_IDLE_TIMEOUT_SECS",
+    "PLACEMENT_POLICY",
+    "PLACEMENT_SEGMENTS",
+    "PREALLOCATE_BYTES",
+    "PREALLOCATE_THRESHOLD_BYTES",
+    "PREFETCH_CACHE_ENTRIES",
+    "PREFETCH_CACHE_TTL_MS",
+    "PREFETCH_MAX_KEYS",
+    "PUT_BATCH_MAX_BYTES",
+    "PUT_BATCH_MAX_KEYS",
+    "RANGE_MAX_PARTS",
+    "REMOTE_S3_ACCESS_KEY",
+    "REMOTE_S3_BUCKET",
+    "REMOTE_S3_ENDPOINT",
+    "REMOTE_S3_REGION",
+    "REMOTE_S3_SECRET_KEY",
+    "REMOTE_S3_TIMEOUT_SECS",
+    "REPLICATION_METADATA_INTERVAL_SECS",
+    "REPLICATION_QUEUE_CAPACITY",
+    "REPLICATION_SECONDARY_DIR",
+    "RESPONSE_COMPRESSION",
+    "RESPONSE_COMPRESSION_MIN_BYTES",
+    "RESPONSE_COMPRESSION_TYPES",
+    "S3_AUTH_CACHE_TTL_SECS",
+    "S3_DOMAIN_SUFFIX",
+    "S3_STRICT_COMPAT",
+    "SEGMENT_MAX_BYTES",
+    "SERVER_HOST",
+    "SERVER_PORT",
+    "SERVER_ROLE",
+    "SLOW_REQUEST_MS",
+    "SQLITE_BUSY_TIMEOUT_MS",
+    "SQLITE_CACHE_SIZE",
+    "SQLITE_MMAP_SIZE",
+    "SQLITE_SLOW_QUERY_MS",
+    "SQLITE_TEMP_STORE_MEMORY",
+    "SQLITE_WARMUP",
+    "SQLITE_WARMUP_MAX_MS",
+    "STORAGE_BACKEND",
+    "STORAGE_DIRECTORY",
+    "STORAGE_LAYOUT",
+    "STRICT_BUCKETS",
+    "TLS_CERT_FILE",
+    "TLS_KEY_FILE",
+    "TRACK_ACCESS",
+    "TXN_MAX_BYTES",
+    "TXN_MAX_OPS",
+    "UI_ENABLED",
+    "USER_INFLIGHT_METRICS_TOP_N",
+    "USER_LIMIT_WAIT_MS",
+    "USER_MAX_INFLIGHT_READS",
+    "USER_MAX_INFLIGHT_WRITES",
+    "VITALITY_CONSOLE_URL",
+    "WARPDRIVE_ADMIN_ACCESS_KEY",
+    "WARPDRIVE_ADMIN_SECRET_KEY",
+    "WARPDRIVE_SERVICE_SECRET",
+];
+
+/// Whether the value of variable `name` must not be stored
+pub fn is_secret(name: &str) -> bool {
+    ["SECRET", "PASSWORD", "TOKEN", "MASTER_KEY"].iter().any(|word| name.contains(word))
+}
+
+/// The configured variables of [`CONFIG_VARS`] by name, secrets redacted
+pub fn config_snapshot() -> Value {
+    let mut snapshot = Map::new();
+    for name in CONFIG_VARS {
+        if let Ok(value) = env::var(name) {
+            let value = if is_secret(name) { REDACTED.to_string() } else { value };
+            snapshot.insert(name.to_string(), Value::String(value));
+        }
+    }
+    Value::Object(snapshot)
+}
+
+/// Record the configuration snapshot unless it equals the last one. Returns whether it was
+/// recorded.
+pub fn snapshot_config() -> Result<bool, Error> {
+    let store = SQLiteMetadataStore::new();
+    let snapshot = config_snapshot();
+    let previous = store.last_change("config")?.and_then(|change| change.after);
+    if previous.as_ref() == Some(&snapshot) {
+        return Ok(false);
+    }
+    store.insert_change(STARTUP_ACTOR, "config", "server", previous.as_ref(), Some(&snapshot))?;
+    info!("Configuration changed since the last start; snapshot recorded in the change log");
+    Ok(true)
+}
+
+/// Record a change already applied by `actor`; a failure is logged.
+pub fn record(actor: &str, kind: &str, subject: &str, before: Option<Value>, after: Option<Value>) {
+    if let Err(e) = SQLiteMetadataStore::new().insert_change(actor, kind, subject, before.as_ref(), after.as_ref()) {
+        warn!("Cannot record {} change of {} by {}: {}", kind, subject, actor, e);
+    }
+}
+
+/// Entries from `since`, oldest first
+pub fn list(since: Option<DateTime<Utc>>, limit: usize) -> Result<Vec<ChangeRecord>, Error> {
+    let since = since.map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true));
+    SQLiteMetadataStore::new().list_changes(since.as_deref(), limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_are_recognised_by_name() {
+        assert!(is_secret("WARPDRIVE_ADMIN_SECRET_KEY"));
+        assert!(is_secret("WARPDRIVE_SERVICE_SECRET"));
+        assert!(is_secret("ENCRYPTION_PREVIOUS_MASTER_KEY"));
+        assert!(!is_secret("WARPDRIVE_ADMIN_ACCESS_KEY"));
+        assert!(!is_secret("DB_FILE"));
+        assert!(CONFIG_VARS.iter().filter(|name| is_secret(name)).count() >= 2);
+    }
+}
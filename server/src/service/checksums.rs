@@ -0,0 +1,189 @@
+//! Chunk checksums: checking them on read and backfilling them for older objects
+//!
+//! A chunk record can carry the CRC32C of its stored bytes. Native GETs of whole chunks and
+//! `fsck` compare what they read against it and fail with `ChecksumMismatch` rather than
+//! return corrupt data; chunks without one are read as before.
+//!
+//! Objects written before chunks carried checksums are covered by a backfill
+//! (`POST /admin/checksums/backfill?user=&bucket=`, run as a job). It walks the bucket key by
+//! key, every version included, reads each chunk without a checksum, and stores the chunk
+//! lists with their checksums filled in, `CHECKSUM_BACKFILL_BATCH_KEYS` keys per metadata
+//! transaction. A row changed while its chunks were being read is left for the next run.
+//! The last key of each committed batch is recorded, so an interrupted or cancelled backfill
+//! continues after it; the record is dropped once the whole bucket is done. Reads are held to
+//! `CHECKSUM_BACKFILL_MAX_BYTES_PER_SEC` when set. With `?verify_only=true` the chunks are
+//! read and checksummed but nothing is written, and the report says what would change.
+
+use actix_web::Error;
+use crc::{Crc, CRC_32_ISCSI};
+use log::{error, info};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::env;
+use std::time::{Duration, Instant};
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metrics;
+use crate::service::error::ServiceError;
+use crate::service::jobs::JobHandle;
+use crate::storage::config::StorageConfig;
+
+const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+/// Keys per backfill transaction when `CHECKSUM_BACKFILL_BATCH_KEYS` is unset
+pub const DEFAULT_BATCH_KEYS: usize = 100;
+
+/// CRC32C of `data`, the checksum a chunk record carries
+pub fn crc32c(data: &[u8]) -> u32 {
+    CASTAGNOLI.checksum(data)
+}
+
+/// Fail with `ChecksumMismatch` when `data`, read from `extent` of `bucket`, does not have
+/// the `expected` checksum; a chunk without one always passes.
+pub fn verify(bucket: &str, extent: (u64, u64), expected: Option<u32>, data: &[u8]) -> Result<(), Error> {
+    let Some(expected) = expected else { return Ok(()) };
+    let actual = crc32c(data);
+    if actual == expected {
+        return Ok(());
+    }
+    error!("Chunk ({}, {}) in bucket {} failed its checksum: read {:08x}, recorded {:08x}",
+           extent.0, extent.1, bucket, actual, expected);
+    metrics::inc_counter("warpdrive_checksum_mismatch_total", &[]);
+    Err(ServiceError::ChecksumMismatch { bucket: bucket.to_string(), chunk: extent, expected, actual }.into())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChecksumBackfillConfig {
+    /// Keys whose rows are updated in one metadata transaction
+    pub batch_keys: usize,
+    /// Most chunk bytes read per second; 0 for no limit
+    pub max_bytes_per_sec: u64,
+}
+
+impl Default for ChecksumBackfillConfig {
+    fn default() -> Self {
+        Self { batch_keys: DEFAULT_BATCH_KEYS, max_bytes_per_sec: 0 }
+    }
+}
+
+impl ChecksumBackfillConfig {
+    pub fn from_env() -> Self {
+        let batch_keys = env::var("CHECKSUM_BACKFILL_BATCH_KEYS")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_BATCH_KEYS);
+        let max_bytes_per_sec = env::var("CHECKSUM_BACKFILL_MAX_BYTES_PER_SEC")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        Self { batch_keys, max_bytes_per_sec }
+    }
+}
+
+/// Outcome of one backfill run
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChecksumBackfillReport {
+    pub user: String,
+    pub bucket: String,
+    pub verify_only: bool,
+    /// Key an earlier, interrupted run had completed, which this one started after
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resumed_after: Option<String>,
+    pub keys_scanned: u64,
+    /// Object rows, noncurrent versions included, with at least one chunk lacking a checksum
+    pub objects_missing: u64,
+    /// Of those, rows given their checksums (always 0 with `verify_only`)
+    pub objects_backfilled: u64,
+    pub chunks_checksummed: u64,
+    pub bytes_read: u64,
+    /// Rows that changed while their chunks were read, left for the next run
+    pub objects_skipped: u64,
+}
+
+/// Fill in the missing chunk checksums of `user`/`bucket`, or only count them with `verify_only`
+pub async fn backfill(
+    user: &str, bucket: &str, verify_only: bool, config: &ChecksumBackfillConfig, job: &JobHandle,
+) -> Result<ChecksumBackfillReport, Error> {
+    let db = SQLiteMetadataStore::new();
+    let store = StorageConfig::from_env().create_store();
+    let resumed_after = if verify_only { None } else { db.checksum_backfill_cursor(user, bucket)? };
+    let total_keys = db.chunk_keys_after_count(user, bucket, resumed_after.as_deref())?;
+    let mut report = ChecksumBackfillReport {
+        user: user.to_string(),
+        bucket: bucket.to_string(),
+        verify_only,
+        resumed_after: resumed_after.clone(),
+        ..ChecksumBackfillReport::default()
+    };
+    info!("Checksum backfill of {}/{} started after {:?}, {} keys to scan, verify_only: {}",
+          user, bucket, resumed_after, total_keys, verify_only);
+
+    let started = Instant::now();
+    let mut after = resumed_after;
+    loop {
+        job.checkpoint()?;
+        let rows = db.chunk_rows_after(user, bucket, after.as_deref(), config.batch_keys)?;
+        let Some(last_key) = rows.last().map(|row| row.key.clone()) else { break };
+        let mut updates = Vec::new();
+        for row in &rows {
+            if row.chunks.iter().all(|chunk| chunk.checksum.is_some()) {
+                continue;
+            }
+            report.objects_missing += 1;
+            let mut chunks = row.chunks.clone();
+            for chunk in chunks.iter_mut().filter(|chunk| chunk.checksum.is_none()) {
+                let (offset, size) = chunk.extent();
+                let data = if size == 0 { Vec::new() } else { store.read(user, bucket, offset, size)? };
+                chunk.checksum = Some(crc32c(&data));
+                report.chunks_checksummed += 1;
+                report.bytes_read += data.len() as u64;
+            }
+            updates.push((row, chunks));
+        }
+        report.keys_scanned += rows.iter().map(|row| &row.key).collect::<HashSet<_>>().len() as u64;
+        if !verify_only {
+            let rewritten = db.commit_chunk_checksums(user, bucket, &updates, &last_key)? as u64;
+            report.objects_backfilled += rewritten;
+            report.objects_skipped += updates.len() as u64 - rewritten;
+        }
+        job.progress(report.keys_scanned, total_keys);
+        throttle(started, report.bytes_read, config.max_bytes_per_sec).await;
+        after = Some(last_key);
+    }
+    if !verify_only {
+        db.finish_checksum_backfill(user, bucket)?;
+    }
+    info!("Checksum backfill of {}/{} done: {} keys, {} rows missing checksums, {} backfilled, {} skipped, {} bytes read",
+          user, bucket, report.keys_scanned, report.objects_missing, report.objects_backfilled, report.objects_skipped, report.bytes_read);
+    Ok(report)
+}
+
+/// Wait until `bytes` read since `started` are within `max_bytes_per_sec`
+async fn throttle(started: Instant, bytes: u64, max_bytes_per_sec: u64) {
+    if max_bytes_per_sec == 0 {
+        return;
+    }
+    let due = Duration::from_secs_f64(bytes as f64 / max_bytes_per_sec as f64);
+    if let Some(wait) = due.checked_sub(started.elapsed()) {
+        actix_web::rt::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn test_verify() {
+        assert!(verify("b", (0, 9), None, b"anything").is_ok());
+        assert!(verify("b", (0, 9), Some(0xe306_9283), b"123456789").is_ok());
+        let err = verify("b", (0, 9), Some(0xe306_9283), b"123456780").unwrap_err();
+        assert!(matches!(err.as_error::<ServiceError>(), Some(ServiceError::ChecksumMismatch { expected: 0xe306_9283, .. })));
+    }
+}
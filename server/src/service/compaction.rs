@@ -0,0 +1,393 @@
+//! Compaction planning
+//!
+//! [`plan`] decides what compacting a bucket involves from its segment files, the extents
+//! objects still reference (or are queued for deletion) and the ranges the deletion worker
+//! has freed. It reads nothing itself, so `GET /admin/compaction/plan/{user}/{bucket}` can show
+//! the same plan the compactor acts on without touching any data.
+//!
+//! Sealed segments without live data are always removable: `POST /admin/compact` and the
+//! deletion worker unlink exactly the plan's `removable_segments`. Beyond that, once freed
+//! bytes make up `COMPACTION_FREE_RATIO` (default 0.5) of the bucket's files the plan is
+//! triggered: rewriting every live extent that sits behind a gap in its segment packs each
+//! segment down to its live bytes. The plan lists those moves and the bytes they rewrite.
+//!
+//! [`relocate_bucket`] carries the moves out with the bucket quiesced: it copies the chunks
+//! of every row referencing a moved extent to a new place and swaps in the rows' chunk lists.
+//! Readers see each pass all at once. The rows of a pass (at most `COMPACTION_PASS_BYTES`,
+//! default 64 MiB, of copies) are rewritten in one SQLite transaction that also queues the
+//! old extents for deletion, so a GET or list reads either every offset from before the
+//! pass or every offset after it, never a mix within one multi-chunk object. A reader still
+//! holding pre-pass metadata reads the old extents, which stay intact until the deletion
+//! worker frees them. Rows that share an extent through clones always land in one pass.
+
+use actix_web::Error;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+
+use crate::metadata::sqlite_store::{ExtentMove, ObjectExtentsRow};
+use crate::service::deletion_worker::merge_ranges;
+use crate::service::metadata_service::MetadataService;
+use crate::service::storage_service::StorageService;
+use crate::service::user_context::UserContext;
+use crate::storage::instrumented_store::{self, Traffic};
+use crate::storage::{bucket_lock, segment};
+
+/// Free ratio at which a plan is triggered when `COMPACTION_FREE_RATIO` is unset
+pub const DEFAULT_FREE_RATIO: f64 = 0.5;
+
+/// Bytes copied per relocation pass when `COMPACTION_PASS_BYTES` is unset
+pub const DEFAULT_PASS_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionConfig {
+    /// Share of the bucket's file bytes that must be free before live data is worth moving
+    pub free_ratio: f64,
+    /// Bytes a relocation pass copies before publishing them in one transaction
+    pub pass_bytes: u64,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self { free_ratio: DEFAULT_FREE_RATIO, pass_bytes: DEFAULT_PASS_BYTES }
+    }
+}
+
+impl CompactionConfig {
+    pub fn from_env() -> Self {
+        let free_ratio = env::var("COMPACTION_FREE_RATIO")
+            .ok()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|v| (0.0..=1.0).contains(v))
+            .unwrap_or(DEFAULT_FREE_RATIO);
+        let pass_bytes = env::var("COMPACTION_PASS_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_PASS_BYTES);
+        Self { free_ratio, pass_bytes }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CompactionPlan {
+    /// Bytes of the bucket's segment files
+    pub file_bytes: u64,
+    /// Bytes referenced by objects or still queued for deletion, each shared range once
+    pub live_bytes: u64,
+    /// Bytes the deletion worker has freed
+    pub free_bytes: u64,
+    /// `free_bytes / file_bytes`; 0 for a bucket without files
+    pub free_ratio: f64,
+    pub threshold: f64,
+    /// Whether `free_ratio` reaches `threshold`
+    pub triggered: bool,
+    /// Sealed segments without live data, unlinked whether or not the plan is triggered
+    pub removable_segments: Vec<u32>,
+    /// Live extents behind a gap in their segment, moved when triggered
+    pub moves: Vec<(u64, u64)>,
+    pub bytes_rewritten: u64,
+    /// File bytes once the removable segments are gone and, when triggered, the moves done
+    pub resulting_file_bytes: u64,
+}
+
+/// Plan the compaction of a bucket whose files are `segments` (`(start address, length)`,
+/// oldest first, the last one active), given its `live` extents and `free` ranges. Live
+/// extents that touch are planned as one move.
+pub fn plan(segments: &[(u64, u64)], live: &[(u64, u64)], free: &[(u64, u64)], threshold: f64) -> CompactionPlan {
+    let live = merge_ranges(live);
+    let file_bytes: u64 = segments.iter().map(|(_, len)| len).sum();
+    let live_bytes: u64 = live.iter().map(|(_, size)| size).sum();
+    let free_bytes: u64 = merge_ranges(free).iter().map(|(_, size)| size).sum();
+    let free_ratio = if file_bytes == 0 { 0.0 } else { free_bytes as f64 / file_bytes as f64 };
+    let triggered = file_bytes > 0 && free_ratio >= threshold;
+
+    let mut removable_segments = Vec::new();
+    let mut moves = Vec::new();
+    let mut resulting_file_bytes = 0;
+    for (i, &(start, len)) in segments.iter().enumerate() {
+        let n = segment::segment_of(start);
+        let in_segment: Vec<(u64, u64)> = live.iter().copied().filter(|(offset, _)| segment::segment_of(*offset) == n).collect();
+        let sealed = i + 1 < segments.len();
+        if sealed && in_segment.is_empty() {
+            removable_segments.push(n);
+            continue;
+        }
+        if !triggered {
+            resulting_file_bytes += len;
+            continue;
+        }
+        // Everything from the first gap on slides down to close it
+        let (mut end, mut behind_gap) = (start, false);
+        for &(offset, size) in &in_segment {
+            behind_gap |= offset > end;
+            if behind_gap {
+                moves.push((offset, size));
+            }
+            end = offset + size;
+        }
+        resulting_file_bytes += in_segment.iter().map(|(_, size)| size).sum::<u64>();
+    }
+    let bytes_rewritten = moves.iter().map(|(_, size)| size).sum();
+    CompactionPlan {
+        file_bytes,
+        live_bytes,
+        free_bytes,
+        free_ratio,
+        threshold,
+        triggered,
+        removable_segments,
+        moves,
+        bytes_rewritten,
+        resulting_file_bytes,
+    }
+}
+
+/// [`plan`] for `user_id`/`bucket` as stored now
+pub fn plan_bucket(user_id: &str, bucket: &str, threshold: f64) -> Result<CompactionPlan, Error> {
+    let metadata_service = MetadataService::new(user_id)?;
+    let live = metadata_service.live_extents(bucket)?;
+    let free = metadata_service.free_ranges(bucket)?;
+    let context = UserContext::with_bucket(user_id.to_string(), bucket.to_string());
+    let segments = StorageService::new().segments(&context)?;
+    Ok(plan(&segments, &live, &free, threshold))
+}
+
+/// Outcome of [`relocate_bucket`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RelocationReport {
+    pub user: String,
+    pub bucket: String,
+    /// Passes published, each in one transaction
+    pub passes: usize,
+    pub rows_rewritten: usize,
+    /// Rows of passes dropped because a row changed during the copy
+    pub rows_skipped: usize,
+    pub chunks_moved: usize,
+    pub bytes_moved: u64,
+}
+
+/// Carry out the moves of `user_id`/`bucket`'s plan under `config`, publishing them a pass at
+/// a time. Does nothing unless the plan is triggered.
+pub async fn relocate_bucket(user_id: &str, bucket: &str, config: &CompactionConfig) -> Result<RelocationReport, Error> {
+    let mut report = RelocationReport { user: user_id.to_string(), bucket: bucket.to_string(), ..Default::default() };
+    let _quiesced = bucket_lock::quiesce(user_id, bucket).await;
+    let plan = plan_bucket(user_id, bucket, config.free_ratio)?;
+    if !plan.triggered || plan.moves.is_empty() {
+        return Ok(report);
+    }
+    let metadata_service = MetadataService::new(user_id)?;
+    let rows: Vec<ObjectExtentsRow> = metadata_service.object_extents_in_bucket(bucket)?
+        .into_iter()
+        .filter(|row| row.extents.iter().any(|extent| is_moving(&plan.moves, *extent)))
+        .collect();
+
+    let mut pass: Vec<ObjectExtentsRow> = Vec::new();
+    let mut pass_bytes = 0;
+    for group in group_rows(rows, &plan.moves) {
+        let bytes = moving_bytes(&group, &plan.moves);
+        if !pass.is_empty() && pass_bytes + bytes > config.pass_bytes {
+            publish_pass(&metadata_service, bucket, std::mem::take(&mut pass), &plan.moves, &mut report)?;
+            pass_bytes = 0;
+        }
+        pass.extend(group);
+        pass_bytes += bytes;
+    }
+    if !pass.is_empty() {
+        publish_pass(&metadata_service, bucket, pass, &plan.moves, &mut report)?;
+    }
+    info!("Relocated user {} bucket {}: {} rows in {} passes, {} chunks ({} bytes) moved, {} rows skipped",
+          user_id, bucket, report.rows_rewritten, report.passes, report.chunks_moved, report.bytes_moved, report.rows_skipped);
+    Ok(report)
+}
+
+/// Copy the moving chunks of `rows` and publish their new chunk lists in one transaction
+fn publish_pass(
+    metadata_service: &MetadataService, bucket: &str, rows: Vec<ObjectExtentsRow>, moves: &[(u64, u64)], report: &mut RelocationReport,
+) -> Result<(), Error> {
+    let context = UserContext::with_bucket(report.user.clone(), bucket.to_string());
+    let storage = StorageService::new();
+    let _traffic = instrumented_store::tag(Traffic::Compaction);
+    // A chunk shared by clones is copied once
+    let mut moved: HashMap<(u64, u64), (u64, u64)> = HashMap::new();
+    let mut copied = Vec::with_capacity(rows.len());
+    for row in rows {
+        let mut extents = Vec::with_capacity(row.extents.len());
+        for &extent in &row.extents {
+            if !is_moving(moves, extent) {
+                extents.push(extent);
+                continue;
+            }
+            let copy = match moved.get(&extent) {
+                Some(copy) => *copy,
+                None => {
+                    let copy = storage.move_chunks(&context, &[extent])?[0];
+                    moved.insert(extent, copy);
+                    copy
+                }
+            };
+            extents.push(copy);
+        }
+        copied.push((row, extents));
+    }
+    let moved: Vec<ExtentMove> = moved.into_iter().collect();
+    let rewritten = metadata_service.commit_relocation(bucket, &copied, &moved)?;
+    report.passes += 1;
+    if rewritten < copied.len() {
+        warn!("A row of user {} bucket {} changed during relocation; {} rows keep their chunks", report.user, bucket, copied.len());
+        report.rows_skipped += copied.len();
+        return Ok(());
+    }
+    report.rows_rewritten += rewritten;
+    report.chunks_moved += moved.len();
+    report.bytes_moved += moved.iter().map(|((_, size), _)| size).sum::<u64>();
+    Ok(())
+}
+
+/// Whether `extent` lies within one of the plan's `moves` (sorted, disjoint)
+fn is_moving(moves: &[(u64, u64)], (offset, size): (u64, u64)) -> bool {
+    let i = moves.partition_point(|(start, _)| *start <= offset);
+    i > 0 && {
+        let (start, len) = moves[i - 1];
+        offset + size <= start + len
+    }
+}
+
+/// Bytes of the distinct moving extents of `rows`
+fn moving_bytes(rows: &[ObjectExtentsRow], moves: &[(u64, u64)]) -> u64 {
+    let mut extents: Vec<(u64, u64)> = rows.iter()
+        .flat_map(|row| row.extents.iter().copied())
+        .filter(|extent| is_moving(moves, *extent))
+        .collect();
+    extents.sort_unstable();
+    extents.dedup();
+    extents.iter().map(|(_, size)| size).sum()
+}
+
+/// Split `rows` into groups that share no moving extent, so a pass never rewrites one clone
+/// of a chunk without the others
+fn group_rows(rows: Vec<ObjectExtentsRow>, moves: &[(u64, u64)]) -> Vec<Vec<ObjectExtentsRow>> {
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut parent: Vec<usize> = (0..rows.len()).collect();
+    let mut owner: HashMap<(u64, u64), usize> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        for &extent in row.extents.iter().filter(|extent| is_moving(moves, **extent)) {
+            let j = *owner.entry(extent).or_insert(i);
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a] = b;
+        }
+    }
+    let mut groups: Vec<Vec<ObjectExtentsRow>> = Vec::new();
+    let mut index: HashMap<usize, usize> = HashMap::new();
+    for (i, row) in rows.into_iter().enumerate() {
+        let r = root(&mut parent, i);
+        let g = *index.entry(r).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[g].push(row);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_file() {
+        let plan = plan(&[], &[], &[], 0.5);
+        assert_eq!(plan.file_bytes, 0);
+        assert_eq!(plan.free_ratio, 0.0);
+        assert!(!plan.triggered);
+        assert!(plan.moves.is_empty() && plan.removable_segments.is_empty());
+        assert_eq!(plan.resulting_file_bytes, 0);
+    }
+
+    #[test]
+    fn test_fully_live_file() {
+        let plan = plan(&[(0, 300)], &[(0, 100), (100, 100), (200, 100)], &[], 0.0);
+        assert_eq!((plan.live_bytes, plan.free_bytes), (300, 0));
+        // Triggered at a zero threshold, but nothing sits behind a gap
+        assert!(plan.triggered);
+        assert!(plan.moves.is_empty());
+        assert_eq!(plan.resulting_file_bytes, 300);
+    }
+
+    #[test]
+    fn test_swiss_cheese_file() {
+        let live = [(0, 10), (20, 10), (40, 10), (60, 10)];
+        let free = [(10, 10), (30, 10), (50, 10), (70, 30)];
+        let plan = plan(&[(0, 100)], &live, &free, 0.5);
+        assert_eq!((plan.file_bytes, plan.live_bytes, plan.free_bytes), (100, 40, 60));
+        assert_eq!(plan.free_ratio, 0.6);
+        assert!(plan.triggered);
+        assert_eq!(plan.moves, vec![(20, 10), (40, 10), (60, 10)]);
+        assert_eq!(plan.bytes_rewritten, 30);
+        assert_eq!(plan.resulting_file_bytes, 40);
+
+        // Under a higher threshold nothing moves and the file stays as it is
+        let plan = super::plan(&[(0, 100)], &live, &free, 0.7);
+        assert!(!plan.triggered);
+        assert!(plan.moves.is_empty());
+        assert_eq!(plan.resulting_file_bytes, 100);
+    }
+
+    #[test]
+    fn test_free_space_all_at_end() {
+        let plan = plan(&[(0, 100)], &[(0, 20), (20, 20)], &[(40, 60)], 0.5);
+        assert!(plan.triggered);
+        assert!(plan.moves.is_empty(), "{:?}", plan.moves);
+        assert_eq!(plan.bytes_rewritten, 0);
+        assert_eq!(plan.resulting_file_bytes, 40);
+    }
+
+    #[test]
+    fn test_moving_extents() {
+        let moves = [(20, 30), (60, 10)];
+        assert!(is_moving(&moves, (20, 10)));
+        assert!(is_moving(&moves, (40, 10)));
+        assert!(!is_moving(&moves, (0, 10)));
+        assert!(!is_moving(&moves, (45, 10)), "straddles the end of a move");
+        assert!(is_moving(&moves, (60, 10)));
+        assert!(!is_moving(&moves, (70, 10)));
+    }
+
+    #[test]
+    fn test_clones_share_a_group() {
+        let row = |id, extents: &[(u64, u64)]| ObjectExtentsRow { id, extents: extents.to_vec(), stored: Vec::new() };
+        // 1 and 3 share a moving chunk, 2 shares only one that stays put with 1
+        let rows = vec![row(1, &[(0, 10), (20, 10)]), row(2, &[(0, 10), (40, 10)]), row(3, &[(20, 10)])];
+        let groups = group_rows(rows, &[(20, 30)]);
+        let ids: Vec<Vec<i64>> = groups.iter().map(|g| g.iter().map(|r| r.id).collect()).collect();
+        assert_eq!(ids, vec![vec![1, 3], vec![2]]);
+        assert_eq!(moving_bytes(&groups[0], &[(20, 30)]), 10);
+    }
+
+    #[test]
+    fn test_sealed_segments() {
+        let first = segment::address(0, 0);
+        let second = segment::address(1, 0);
+        let third = segment::address(2, 0);
+        let segments = [(first, 100), (second, 100), (third, 50)];
+        // The first segment is empty, the second holds one extent behind a gap
+        let live = [(second + 60, 40), (third, 50)];
+        let free = [(first, 100), (second, 60)];
+        let plan = plan(&segments, &live, &free, 0.9);
+        assert_eq!(plan.removable_segments, vec![0]);
+        assert!(!plan.triggered);
+        assert_eq!(plan.resulting_file_bytes, 150);
+
+        let plan = super::plan(&segments, &live, &free, 0.5);
+        assert_eq!(plan.removable_segments, vec![0]);
+        assert_eq!(plan.moves, vec![(second + 60, 40)]);
+        assert_eq!(plan.resulting_file_bytes, 90);
+    }
+}
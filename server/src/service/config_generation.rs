@@ -0,0 +1,78 @@
+//! Revalidation of in-memory configuration caches
+//!
+//! Bucket flags, limits and registration, bucket placement, storage layouts and maintenance
+//! mode are cached per process. Every change to them also bumps the single-row
+//! `config_generation` table in the same transaction, so when a second process shares the
+//! database (active/active on one host) its caches can tell they are stale. Cached readers call [`revalidate`] first; at
+//! most once per `CONFIG_REVALIDATE_INTERVAL_MS` (default 1000, 0 checks on every lookup) it
+//! reads the generation and, if it moved, drops every cache so the next lookups reload.
+//! A change made by another process is therefore seen within one interval.
+
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::service::{bucket_access, bucket_grants, bucket_quota, bucket_registry, maintenance};
+use crate::storage::{layout, tiered_store};
+
+/// Interval between generation checks when `CONFIG_REVALIDATE_INTERVAL_MS` is unset
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// No generation observed yet
+const UNKNOWN: u64 = u64::MAX;
+
+lazy_static! {
+    static ref INTERVAL: Duration = env::var("CONFIG_REVALIDATE_INTERVAL_MS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_INTERVAL);
+    static ref EPOCH: Instant = Instant::now();
+}
+
+// Generation the caches were validated against, and when (ms since EPOCH) it was last read
+static OBSERVED: AtomicU64 = AtomicU64::new(UNKNOWN);
+static CHECKED_AT: AtomicU64 = AtomicU64::new(0);
+
+/// Drop every configuration cache if another process changed the configuration since the
+/// last check. Cheap between checks; a failed read keeps the caches and is retried next
+/// interval.
+pub fn revalidate() {
+    let now = EPOCH.elapsed().as_millis() as u64;
+    let checked_at = CHECKED_AT.load(Ordering::Acquire);
+    let due = OBSERVED.load(Ordering::Acquire) == UNKNOWN || now.saturating_sub(checked_at) >= INTERVAL.as_millis() as u64;
+    // One caller per interval does the read
+    if !due || CHECKED_AT.compare_exchange(checked_at, now.max(checked_at + 1), Ordering::AcqRel, Ordering::Acquire).is_err() {
+        return;
+    }
+    let generation = match SQLiteMetadataStore::new().config_generation() {
+        Ok(generation) => generation,
+        Err(e) => {
+            warn!("Cannot read the configuration generation: {}", e);
+            return;
+        }
+    };
+    let previous = OBSERVED.swap(generation, Ordering::AcqRel);
+    if previous != UNKNOWN && previous != generation {
+        debug!("Configuration generation {} -> {}; reloading cached configuration", previous, generation);
+        clear_caches();
+    }
+}
+
+/// Generation the caches were last validated against, if any
+pub fn observed() -> Option<u64> {
+    Some(OBSERVED.load(Ordering::Acquire)).filter(|&g| g != UNKNOWN)
+}
+
+fn clear_caches() {
+    bucket_access::clear();
+    bucket_grants::clear();
+    bucket_quota::clear();
+    bucket_registry::clear();
+    tiered_store::clear_placements();
+    layout::clear();
+    maintenance::clear();
+}
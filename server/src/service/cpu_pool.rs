@@ -0,0 +1,162 @@
+//! A pool of threads for CPU-bound chunk work
+//!
+//! Encrypting, hashing and checksumming a chunk, and compressing a response, take CPU time
+//! in proportion to the bytes. Done on an actix worker, a large upload or download holds up
+//! every other request on that worker. [`run`] hands such work to a fixed pool of
+//! `CPU_POOL_THREADS` threads (default one less than the CPUs, at least one) and awaits it,
+//! so the worker keeps serving while the pool works. Work on fewer than `CPU_POOL_MIN_BYTES`
+//! (default 64 KiB) runs inline, where the handoff would cost more than it saves;
+//! `CPU_POOL_THREADS=0` runs everything inline. Streamed reads (S3 GET, `/download`) already
+//! read each chunk off the worker, on actix's blocking threads, and stay there.
+//!
+//! Jobs start in the order they are handed in and each caller awaits its own, so a request
+//! sees its chunks in order. An error comes back with its status (a `ServiceError` as
+//! itself); a job that panics fails with 500.
+//!
+//! Jobs are counted in `warpdrive_cpu_pool_jobs_total{path}`, `pool` or `inline`.
+
+use actix_web::error::{ErrorInternalServerError, InternalError};
+use actix_web::http::StatusCode;
+use actix_web::Error;
+use lazy_static::lazy_static;
+use log::{error, info};
+use std::env;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::metrics;
+use crate::service::error::ServiceError;
+
+/// Bytes below which work runs inline when `CPU_POOL_MIN_BYTES` is unset
+pub const DEFAULT_MIN_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuPoolConfig {
+    /// Pool threads; 0 runs every job inline
+    pub threads: usize,
+    /// Smallest job, in bytes, handed to the pool
+    pub min_bytes: usize,
+}
+
+impl Default for CpuPoolConfig {
+    fn default() -> Self {
+        let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Self { threads: cpus.saturating_sub(1).max(1), min_bytes: DEFAULT_MIN_BYTES }
+    }
+}
+
+impl CpuPoolConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let threads = env::var("CPU_POOL_THREADS")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .unwrap_or(default.threads);
+        let min_bytes = env::var("CPU_POOL_MIN_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .unwrap_or(default.min_bytes);
+        Self { threads, min_bytes }
+    }
+
+    /// Whether a job on `bytes` goes to the pool
+    pub fn offloads(&self, bytes: usize) -> bool {
+        self.threads > 0 && bytes >= self.min_bytes
+    }
+}
+
+lazy_static! {
+    // Sized once, from the configuration at first use; `None` when the pool is off or could
+    // not be started
+    static ref POOL: Option<Runtime> = start(CpuPoolConfig::from_env().threads);
+}
+
+fn start(threads: usize) -> Option<Runtime> {
+    if threads == 0 {
+        return None;
+    }
+    // Jobs run as blocking tasks, capped at `threads`; the one async worker only drives them
+    let pool = Builder::new_multi_thread()
+        .worker_threads(1)
+        .max_blocking_threads(threads)
+        .thread_name("cpu-pool")
+        .build();
+    match pool {
+        Ok(pool) => {
+            info!("CPU pool started with {} threads", threads);
+            Some(pool)
+        }
+        Err(e) => {
+            error!("Failed to start the CPU pool, running chunk work inline: {}", e);
+            None
+        }
+    }
+}
+
+/// An error crossing back from a pool thread; `actix_web::Error` itself cannot
+enum Failure {
+    Service(ServiceError),
+    Other(StatusCode, String),
+}
+
+impl Failure {
+    fn of(e: Error) -> Self {
+        match e.as_error::<ServiceError>() {
+            Some(service_error) => Failure::Service(service_error.clone()),
+            None => Failure::Other(e.as_response_error().status_code(), e.to_string()),
+        }
+    }
+
+    fn into_error(self) -> Error {
+        match self {
+            Failure::Service(e) => e.into(),
+            Failure::Other(status, message) => InternalError::new(message, status).into(),
+        }
+    }
+}
+
+/// Run `job`, work on `bytes` bytes, on the pool and await its result; small jobs, and all
+/// of them with the pool off, run inline
+pub async fn run<T, F>(bytes: usize, job: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = POOL.as_ref().filter(|_| CpuPoolConfig::from_env().offloads(bytes));
+    let Some(pool) = pool else {
+        metrics::inc_counter("warpdrive_cpu_pool_jobs_total", &[("path", "inline")]);
+        return job();
+    };
+    metrics::inc_counter("warpdrive_cpu_pool_jobs_total", &[("path", "pool")]);
+    match pool.spawn_blocking(move || job().map_err(Failure::of)).await {
+        Ok(result) => result.map_err(Failure::into_error),
+        Err(e) => {
+            error!("CPU pool job failed: {}", e);
+            Err(ErrorInternalServerError("CPU pool job failed"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offloads() {
+        let config = CpuPoolConfig { threads: 2, min_bytes: 100 };
+        assert!(!config.offloads(99));
+        assert!(config.offloads(100));
+        assert!(!CpuPoolConfig { threads: 0, ..config }.offloads(1 << 20));
+        assert!(CpuPoolConfig::default().threads >= 1);
+    }
+
+    #[actix_web::test]
+    async fn test_errors_keep_their_status() {
+        let missing = run(usize::MAX, || -> Result<(), Error> { Err(ServiceError::key_not_found("b", "k").into()) }).await.unwrap_err();
+        assert_eq!(missing.as_error::<ServiceError>(), Some(&ServiceError::key_not_found("b", "k")));
+        let conflict = run(usize::MAX, || -> Result<(), Error> { Err(actix_web::error::ErrorConflict("taken")) }).await.unwrap_err();
+        assert_eq!(conflict.as_response_error().status_code(), StatusCode::CONFLICT);
+        assert_eq!(conflict.to_string(), "taken");
+        let panicked = run(usize::MAX, || -> Result<(), Error> { panic!("job panicked") }).await.unwrap_err();
+        assert_eq!(panicked.as_response_error().status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
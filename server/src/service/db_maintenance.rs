@@ -0,0 +1,158 @@
+//! SQLite checkpoint and vacuum maintenance
+//!
+//! Every `DB_MAINTENANCE_INTERVAL_SECS` (and on `POST /admin/db/checkpoint`) the metadata
+//! database gets a maintenance pass: when free pages make up more than
+//! `DB_VACUUM_FREELIST_RATIO` of the file they are returned to the filesystem with
+//! `PRAGMA incremental_vacuum`, `DB_VACUUM_STEP_PAGES` at a time so requests waiting on the
+//! connection get it between steps; then `PRAGMA wal_checkpoint(TRUNCATE)` folds the WAL
+//! back into the database file and truncates it. File sizes, free pages and the time of the
+//! last complete checkpoint are exported on /metrics.
+//!
+//! Incremental vacuum needs `auto_vacuum=INCREMENTAL`, which SQLite only applies to new
+//! database files; older files are checkpointed but never vacuumed.
+
+use actix_web::Error;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::time;
+
+use crate::metadata::sqlite_store::{CheckpointResult, SQLiteMetadataStore};
+use crate::metrics;
+
+#[derive(Debug, Clone)]
+pub struct DbMaintenanceConfig {
+    /// Time between passes; zero disables the background task
+    pub interval: Duration,
+    /// Free page share of the file above which a pass vacuums
+    pub vacuum_freelist_ratio: f64,
+    /// Pages reclaimed per `incremental_vacuum` step
+    pub vacuum_step_pages: u32,
+}
+
+impl Default for DbMaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(3600),
+            vacuum_freelist_ratio: 0.2,
+            vacuum_step_pages: 256,
+        }
+    }
+}
+
+impl DbMaintenanceConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let interval = env::var("DB_MAINTENANCE_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.interval);
+        let vacuum_freelist_ratio = env::var("DB_VACUUM_FREELIST_RATIO")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v: &f64| (0.0..=1.0).contains(v))
+            .unwrap_or(defaults.vacuum_freelist_ratio);
+        let vacuum_step_pages = env::var("DB_VACUUM_STEP_PAGES")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.vacuum_step_pages);
+        Self { interval, vacuum_freelist_ratio, vacuum_step_pages }
+    }
+}
+
+/// What one maintenance pass did
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceReport {
+    pub checkpoint: CheckpointResult,
+    pub page_count: i64,
+    pub freelist_before: i64,
+    pub freelist_after: i64,
+    pub vacuumed_pages: i64,
+    pub db_file_bytes: u64,
+    pub wal_bytes: u64,
+    pub duration_ms: u128,
+}
+
+/// Vacuum (if the freelist is over the threshold) and checkpoint. Blocking; the connection is
+/// only held for one step at a time.
+pub fn run_maintenance(config: &DbMaintenanceConfig) -> Result<MaintenanceReport, Error> {
+    let start = Instant::now();
+    let store = SQLiteMetadataStore::new();
+
+    let before = store.page_stats()?;
+    let mut after = before;
+    if before.freelist_ratio() > config.vacuum_freelist_ratio {
+        if before.incremental_vacuum {
+            while after.freelist_count > 0 {
+                store.incremental_vacuum(config.vacuum_step_pages)?;
+                let previous = after.freelist_count;
+                after = store.page_stats()?;
+                if after.freelist_count >= previous {
+                    break;
+                }
+                std::thread::yield_now();
+            }
+        } else {
+            warn!(
+                "Metadata database has {} free of {} pages but was created without auto_vacuum=INCREMENTAL; run VACUUM offline to reclaim them",
+                before.freelist_count, before.page_count
+            );
+        }
+    }
+
+    // In WAL mode the vacuumed file is only truncated once the WAL is checkpointed
+    let checkpoint = store.wal_checkpoint()?;
+    if checkpoint.busy {
+        warn!("WAL checkpoint could not complete: {} of {} frames copied", checkpoint.checkpointed_frames, checkpoint.wal_frames);
+    } else {
+        metrics::set_gauge("warpdrive_db_last_checkpoint_timestamp_seconds", &[], chrono::Utc::now().timestamp() as f64);
+    }
+
+    let (db_file_bytes, wal_bytes) = store.file_sizes();
+    metrics::set_gauge("warpdrive_db_file_bytes", &[], db_file_bytes as f64);
+    metrics::set_gauge("warpdrive_db_wal_bytes", &[], wal_bytes as f64);
+    metrics::set_gauge("warpdrive_db_freelist_pages", &[], after.freelist_count as f64);
+
+    let report = MaintenanceReport {
+        checkpoint,
+        page_count: after.page_count,
+        freelist_before: before.freelist_count,
+        freelist_after: after.freelist_count,
+        vacuumed_pages: before.freelist_count - after.freelist_count,
+        db_file_bytes,
+        wal_bytes,
+        duration_ms: start.elapsed().as_millis(),
+    };
+    info!(
+        "Database maintenance: vacuumed {} pages, checkpointed {} frames, db={} bytes, wal={} bytes in {}ms",
+        report.vacuumed_pages, checkpoint.checkpointed_frames, db_file_bytes, wal_bytes, report.duration_ms
+    );
+    Ok(report)
+}
+
+/// Start periodic maintenance as a background task; `None` when the interval is zero.
+pub fn start_db_maintenance() -> Option<tokio::task::JoinHandle<()>> {
+    let config = DbMaintenanceConfig::from_env();
+    if config.interval.is_zero() {
+        info!("Database maintenance disabled (DB_MAINTENANCE_INTERVAL_SECS=0)");
+        return None;
+    }
+    info!("Starting database maintenance with {}s interval", config.interval.as_secs());
+    Some(tokio::spawn(async move {
+        let mut interval = time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            let config = config.clone();
+            // actix errors are not Send; only the message crosses back
+            let pass = move || run_maintenance(&config).map(|_| ()).map_err(|e| e.to_string());
+            match tokio::task::spawn_blocking(pass).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Database maintenance failed: {}", e),
+                Err(e) => error!("Database maintenance task failed: {}", e),
+            }
+        }
+    }))
+}
@@ -0,0 +1,108 @@
+//! Content-addressed deduplication of chunk writes
+//!
+//! With `DEDUP_ENABLED=true` every chunk of at least `DEDUP_MIN_CHUNK_BYTES` (default 4096)
+//! written through [`StorageService`](crate::service::storage_service::StorageService) is
+//! hashed with SHA-256 and looked up in the bucket's `chunk_index`. On a hit nothing is
+//! written: the chunk is the range already stored, which gains a reference in `chunk_refs`
+//! exactly as a clone's ranges do, so deleting any of the objects sharing it only drops a
+//! count and the range is queued for deletion with its last reference. On a miss the chunk
+//! is written as usual and listed under its digest.
+//!
+//! The index is per bucket: chunk addresses are offsets into the bucket's own segment files,
+//! so a range of another bucket or user cannot be referenced. A range leaves the index when
+//! it is queued for deletion or repaired, and the orphan GC pass drops entries no row
+//! references. A reference taken for a write that then fails before its metadata is
+//! committed is released when the caller queues the written extents, as for any chunk, or
+//! by the next GC pass. Lookups that fail fall back to a plain write.
+//!
+//! Hits and the bytes they did not write are counted in `warpdrive_dedup_hits_total` and
+//! `warpdrive_dedup_saved_bytes_total`; `GET /admin/stats` reports each bucket's shared bytes.
+
+use actix_web::Error;
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::env;
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metrics;
+use crate::service::user_context::UserContext;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DedupConfig {
+    pub enabled: bool,
+    /// Smaller chunks are written without a lookup
+    pub min_chunk_bytes: u64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self { enabled: false, min_chunk_bytes: 4096 }
+    }
+}
+
+impl DedupConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let enabled = env::var("DEDUP_ENABLED")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(defaults.enabled);
+        let min_chunk_bytes = env::var("DEDUP_MIN_CHUNK_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(|v| v.max(1))
+            .unwrap_or(defaults.min_chunk_bytes);
+        Self { enabled, min_chunk_bytes }
+    }
+
+    /// Whether a chunk of `len` bytes is looked up
+    pub fn applies(&self, len: usize) -> bool {
+        self.enabled && len as u64 >= self.min_chunk_bytes
+    }
+}
+
+pub fn digest(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// The stored range of `context`'s bucket holding the bytes hashing to `digest`, now
+/// referenced once more by the caller; `None` when there is none or the lookup failed.
+pub fn share(context: &UserContext, digest: &[u8; 32], size: u64) -> Option<(u64, u64)> {
+    match SQLiteMetadataStore::new().share_indexed_chunk(&context.user_id, &context.bucket, digest, size) {
+        Ok(Some(extent)) => {
+            metrics::inc_counter("warpdrive_dedup_hits_total", &[]);
+            metrics::inc_counter_by("warpdrive_dedup_saved_bytes_total", &[], size);
+            Some(extent)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Dedup lookup failed for user {} bucket {}, writing the chunk: {}", context.user_id, context.bucket, e);
+            None
+        }
+    }
+}
+
+/// List the newly written `extent` under `digest`
+pub fn record(context: &UserContext, digest: &[u8; 32], extent: (u64, u64)) {
+    if let Err(e) = SQLiteMetadataStore::new().index_chunk(&context.user_id, &context.bucket, digest, extent) {
+        warn!("Failed to index chunk {:?} of user {} bucket {}: {}", extent, context.user_id, context.bucket, e);
+    }
+}
+
+/// Stop offering `extent` of `context`'s bucket to later writes
+pub fn forget(context: &UserContext, extent: (u64, u64)) -> Result<(), Error> {
+    SQLiteMetadataStore::new().unindex_chunk(&context.user_id, &context.bucket, extent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applies_to_large_enough_chunks_when_enabled() {
+        let config = DedupConfig { enabled: true, min_chunk_bytes: 10 };
+        assert!(config.applies(10));
+        assert!(!config.applies(9));
+        assert!(!DedupConfig::default().applies(1 << 20));
+        assert_ne!(digest(b"a"), digest(b"b"));
+    }
+}
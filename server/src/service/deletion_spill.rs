@@ -0,0 +1,159 @@
+//! Spill file for deletions the queue could not take
+//!
+//! When the deletion queue insert fails (a full disk, a database locked past its retries), the
+//! deletion is appended to `deletion_spill.jsonl` in the storage directory instead, one JSON
+//! object per line, and the delete completes for the client. Nothing the metadata no longer
+//! points at is leaked without a record.
+//!
+//! Each pass of the deletion worker replays the file into the queue and removes it once every
+//! line is in; a line that still cannot be queued stays, with the lines after it, for the next
+//! pass. Lines are identified by the SHA-256 of their text, which the worker's store records
+//! with the events it queues (the `spilled_deletions` table in SQLite), so a line replayed
+//! again after a crash is queued once. The file's size and the age of its oldest line are exported
+//! as `warpdrive_deletion_spill_bytes` and `warpdrive_deletion_spill_age_seconds` and reported
+//! by `/healthz`.
+
+use actix_web::Error;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::metadata::{DeletionSummary, MetadataStorage};
+use crate::metrics;
+use crate::service::expiry;
+use crate::storage::local_store::storage_directory;
+use crate::util::clock;
+
+/// Name of the spill file in the storage directory
+pub const SPILL_FILE: &str = "deletion_spill.jsonl";
+
+/// Serializes appends with replays, which rewrite the file
+static SPILL_LOCK: Mutex<()> = Mutex::new(());
+
+/// One deletion in the spill file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpilledDeletion {
+    pub user_id: String,
+    pub bucket: String,
+    pub key: String,
+    pub extents: Vec<(u64, u64)>,
+    pub spilled_at: String,
+}
+
+/// What `/healthz` reports about the spill file
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SpillStatus {
+    pub bytes: u64,
+    /// Seconds since the oldest line was written; None without a spill file
+    pub age_secs: Option<i64>,
+}
+
+/// Path of the spill file
+pub fn path() -> PathBuf {
+    storage_directory().join(SPILL_FILE)
+}
+
+fn line_hash(line: &str) -> String {
+    hex::encode(Sha256::digest(line.as_bytes()))
+}
+
+/// Record the deletion of `key`'s `extents` in the spill file, after the queue refused it.
+/// Reports the ranges as queued.
+pub fn spill(user_id: &str, bucket: &str, key: &str, extents: &[(u64, u64)]) -> Result<DeletionSummary, Error> {
+    let event = SpilledDeletion {
+        user_id: user_id.to_string(),
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        extents: extents.to_vec(),
+        spilled_at: expiry::now(),
+    };
+    let mut line = serde_json::to_string(&event).map_err(actix_web::error::ErrorInternalServerError)?;
+    line.push('\n');
+    {
+        let _spill = SPILL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = OpenOptions::new().create(true).append(true).open(path())?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+    }
+    warn!("Spilled deletion of key: {} in bucket: {} of user: {} ({} ranges) to {}",
+          key, bucket, user_id, extents.len(), SPILL_FILE);
+    export_metrics();
+    Ok(DeletionSummary {
+        chunks: extents.iter().filter(|(_, size)| *size > 0).count(),
+        bytes: extents.iter().map(|(_, size)| size).sum(),
+    })
+}
+
+/// Queue every deletion in the spill file in `store` and remove the file; returns how many
+/// lines were queued now rather than by an earlier replay. Stops at the first line the queue
+/// refuses, keeping it and the rest for the next call.
+pub fn replay(store: &dyn MetadataStorage) -> Result<usize, Error> {
+    let guard = SPILL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    let mut replayed = 0;
+    let mut done = 0;
+    for line in &lines {
+        // A line torn by a crash mid-append never described a completed delete
+        let Ok(event) = serde_json::from_str::<SpilledDeletion>(line) else {
+            warn!("Dropping unreadable line of {}: {}", SPILL_FILE, line);
+            done += 1;
+            continue;
+        };
+        match store.queue_spilled_deletion(&line_hash(line), &event.user_id, &event.bucket, &event.key, &event.extents) {
+            Ok(Some(_)) => replayed += 1,
+            Ok(None) => info!("Spilled deletion of key: {} in bucket: {} was already queued", event.key, event.bucket),
+            Err(e) => {
+                warn!("Deletion queue still refuses spilled deletions, {} left: {}", lines.len() - done, e);
+                break;
+            }
+        }
+        done += 1;
+    }
+
+    if done == lines.len() {
+        fs::remove_file(&path)?;
+    } else if done > 0 {
+        let rest: String = lines[done..].iter().map(|line| format!("{}\n", line)).collect();
+        let tmp = path.with_extension("jsonl.tmp");
+        fs::write(&tmp, rest)?;
+        fs::File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, &path)?;
+    }
+    if replayed > 0 {
+        info!("Replayed {} spilled deletions into the deletion queue", replayed);
+    }
+    drop(guard);
+    export_metrics();
+    Ok(replayed)
+}
+
+/// Size of the spill file and age of its oldest line
+pub fn status() -> SpillStatus {
+    let _spill = SPILL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Ok(text) = fs::read_to_string(path()) else { return SpillStatus::default() };
+    let oldest = text.lines()
+        .find_map(|line| serde_json::from_str::<SpilledDeletion>(line).ok())
+        .and_then(|event| chrono::DateTime::parse_from_rfc3339(&event.spilled_at).ok());
+    SpillStatus {
+        bytes: text.len() as u64,
+        age_secs: oldest.map(|at| (clock::now() - at.with_timezone(&chrono::Utc)).num_seconds().max(0)),
+    }
+}
+
+/// Export [`status`] as `warpdrive_deletion_spill_bytes` and
+/// `warpdrive_deletion_spill_age_seconds` (0 without a spill file)
+pub fn export_metrics() {
+    let status = status();
+    metrics::set_gauge("warpdrive_deletion_spill_bytes", &[], status.bytes as f64);
+    metrics::set_gauge("warpdrive_deletion_spill_age_seconds", &[], status.age_secs.unwrap_or(0) as f64);
+}
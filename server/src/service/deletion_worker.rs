@@ -3,25 +3,129 @@
 //! This worker runs periodically to process deletion events, free up space,
 //! and trigger compaction when there's enough free space at the top of the file.
 //! Since we're append-only, if there's enough free space at top we compact,
-//! otherwise we leave holes until compaction becomes easier.
+//! otherwise we leave holes until compaction becomes easier. Sealed segment files
+//! left without live data are unlinked whole.
+//!
+//! Each batch is freed per bucket: overlapping and adjacent ranges are merged, ranges already
+//! in the `free_ranges` table are skipped, and the bucket's events are marked processed in the
+//! same transaction that records the newly freed ranges.
+//!
+//! Each pass first replays deletions spilled while the queue refused them into its store (see
+//! [`deletion_spill`]), then expires objects past their TTL (see [`expiry`]) and objects
+//! unread past their bucket's last-access rule (see [`access_tracking`]), queueing their
+//! chunks, and aborts native upload sessions past their TTL (see [`upload_sessions`]).
+//!
+//! The worker reads and completes the queue through the [`MetadataStorage`] it was given,
+//! the one [`crate::app_state::AppStateBuilder`] installed. A bucket whose events fail to
+//! free has each event's attempt count raised and the error kept as its `last_error`; the
+//! events stay pending and are retried by the next pass.
+//!
+//! Processed events are kept for `DELETION_RETENTION_DAYS` after processing and then removed
+//! in batches of `DELETION_CLEANUP_BATCH`. Each pass exports the unprocessed backlog as
+//! `warpdrive_deletion_queue_pending` (events) and `warpdrive_bytes_pending_deletion`, and
+//! warns once it passes `DELETION_QUEUE_ALERT_THRESHOLD`.
 
-use crate::service::metadata_service::MetadataService;
+use crate::metadata::{DeletionEvent, MetadataStorage};
+use crate::metrics;
+use crate::service::access_tracking;
+use crate::service::compaction::{self, CompactionConfig};
+use crate::service::deletion_spill;
+use crate::service::expiry;
+use crate::service::free_ranges;
+use crate::service::jobs::JobHandle;
+use crate::service::maintenance;
+use crate::service::metadata_service;
 use crate::service::storage_service::StorageService;
+use crate::service::upload_sessions;
 use crate::service::user_context::UserContext;
-use crate::metadata::sqlite_store::DeletionEvent;
+use crate::storage::{bucket_lock, segment};
 use log::{info, warn, error};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time;
 
+/// Retention and backlog alerting for the deletion queue
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeletionQueueConfig {
+    /// Days a processed event is kept after processing
+    pub retention_days: u32,
+    /// Rows removed per cleanup statement
+    pub cleanup_batch: usize,
+    /// Unprocessed events above which each pass logs a warning
+    pub alert_threshold: u64,
+}
+
+impl Default for DeletionQueueConfig {
+    fn default() -> Self {
+        Self { retention_days: 7, cleanup_batch: 1000, alert_threshold: 100_000 }
+    }
+}
+
+impl DeletionQueueConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |name: &str| env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok());
+        Self {
+            retention_days: parse("DELETION_RETENTION_DAYS").map(|v| v as u32).unwrap_or(defaults.retention_days),
+            cleanup_batch: parse("DELETION_CLEANUP_BATCH").filter(|v| *v > 0).map(|v| v as usize).unwrap_or(defaults.cleanup_batch),
+            alert_threshold: parse("DELETION_QUEUE_ALERT_THRESHOLD").unwrap_or(defaults.alert_threshold),
+        }
+    }
+}
+
+/// Export the size of the unprocessed backlog as `warpdrive_bytes_pending_deletion`: what the
+/// worker will eventually free. Refreshed by every pass and every scrape of `/metrics`.
+pub fn export_pending_bytes() {
+    export_pending_bytes_of(metadata_service::store().as_ref());
+}
+
+fn export_pending_bytes_of(store: &dyn MetadataStorage) {
+    match store.pending_deletion_bytes() {
+        Ok(bytes) => metrics::set_gauge("warpdrive_bytes_pending_deletion", &[], bytes as f64),
+        Err(e) => warn!("Failed to sum pending deletion bytes: {}", e),
+    }
+}
+
+/// Outcome of [`DeletionWorker::compact_bucket`]
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactReport {
+    pub user: String,
+    pub bucket: String,
+    /// Deletion events processed
+    pub events: u64,
+    /// Events that failed and stay queued
+    pub failed: u64,
+    pub freed_bytes: u64,
+    /// Sealed segment files unlinked
+    pub removed_segments: Vec<u32>,
+}
+
 /// Background deletion worker
 pub struct DeletionWorker {
+    store: Arc<dyn MetadataStorage>,
     batch_size: i32,
     cleanup_interval: Duration,
 }
 
+impl Default for DeletionWorker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DeletionWorker {
+    /// A worker on the store every [`metadata_service::MetadataService`] uses
     pub fn new() -> Self {
+        Self::with_store(metadata_service::store())
+    }
+
+    /// A worker processing the queue of `store`
+    pub fn with_store(store: Arc<dyn MetadataStorage>) -> Self {
         Self {
+            store,
             batch_size: 100, // Process up to 100 deletions at a time
             cleanup_interval: Duration::from_secs(300), // Run every 5 minutes
         }
@@ -36,6 +140,10 @@ impl DeletionWorker {
             
             loop {
                 interval.tick().await;
+                if maintenance::is_read_only() {
+                    info!("Deletion worker paused: server is in read-only maintenance mode");
+                    continue;
+                }
                 
                 if let Err(e) = self.process_deletions().await {
                     error!("Error processing deletions: {}", e);
@@ -45,17 +153,48 @@ impl DeletionWorker {
     }
     
     /// Process pending deletion events
-    async fn process_deletions(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Get pending deletion events through metadata service
-        let metadata_service = match MetadataService::new("system") {
-            Ok(service) => service,
-            Err(e) => {
-                error!("Failed to create metadata service: {}", e);
-                return Err(format!("Failed to create metadata service: {}", e).into());
+    pub async fn process_deletions(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Deletions spilled while the queue refused them go in first
+        if let Err(e) = deletion_spill::replay(self.store.as_ref()) {
+            warn!("Failed to replay spilled deletions: {}", e);
+        }
+
+        // Expired objects queue their chunks first, so this pass frees them
+        match expiry::sweep(expiry::SWEEP_BATCH) {
+            Ok(0) => {}
+            Ok(expired) => info!("Expired {} objects past their TTL", expired),
+            Err(e) => warn!("Failed to expire objects: {}", e),
+        }
+        match access_tracking::sweep_unread() {
+            Ok(0) => {}
+            Ok(expired) => info!("Expired {} objects unread past their bucket's rule", expired),
+            Err(e) => warn!("Failed to expire unread objects: {}", e),
+        }
+        match upload_sessions::sweep(upload_sessions::SWEEP_BATCH) {
+            Ok(0) => {}
+            Ok(aborted) => info!("Aborted {} upload sessions past their TTL", aborted),
+            Err(e) => warn!("Failed to abort stale upload sessions: {}", e),
+        }
+
+        let config = DeletionQueueConfig::from_env();
+        match self.store.pending_deletion_count() {
+            Ok(pending) => {
+                metrics::set_gauge("warpdrive_deletion_queue_pending", &[], pending as f64);
+                if pending > config.alert_threshold {
+                    warn!("Deletion queue backlog: {} unprocessed events (alert threshold {})",
+                          pending, config.alert_threshold);
+                }
             }
-        };
-        
-        let events = match metadata_service.get_pending_deletions(self.batch_size) {
+            Err(e) => warn!("Failed to count pending deletions: {}", e),
+        }
+        export_pending_bytes_of(self.store.as_ref());
+
+        // Clean up old processed events, also on passes with nothing new to process
+        if let Err(e) = self.store.cleanup_old_deletions(config.retention_days, config.cleanup_batch) {
+            warn!("Failed to cleanup old deletion events: {}", e);
+        }
+
+        let events = match self.store.get_pending_deletions(self.batch_size) {
             Ok(events) => events,
             Err(e) => {
                 error!("Failed to get pending deletions: {}", e);
@@ -68,61 +207,199 @@ impl DeletionWorker {
         }
         
         info!("Processing {} deletion events", events.len());
-        
+
+        // Events of one bucket are freed together so ranges queued more than once are
+        // punched once
+        let mut by_bucket: BTreeMap<(String, String), Vec<DeletionEvent>> = BTreeMap::new();
         for event in events {
-            if let Err(e) = self.process_deletion_event(&event).await {
-                error!("Failed to process deletion event {}: {}", event.id, e);
-                // Continue with other events even if one fails
-            } else {
-                // Mark as processed through metadata service
-                if let Err(e) = metadata_service.mark_deletion_processed(event.id) {
-                    error!("Failed to mark deletion event {} as processed: {}", event.id, e);
+            by_bucket.entry((event.user_id.clone(), event.bucket.clone())).or_default().push(event);
+        }
+
+        let mut touched: BTreeSet<(String, String)> = BTreeSet::new();
+        for ((user_id, bucket), events) in by_bucket {
+            match self.free_events(&user_id, &bucket, &events).await {
+                Ok(_) => {
+                    touched.insert((user_id, bucket));
+                }
+                // The events stay pending and are retried on the next pass
+                Err(e) => {
+                    error!("Failed to process {} deletion events of user {} bucket {}: {}",
+                           events.len(), user_id, bucket, e);
+                    self.record_failure(&events, &e.to_string());
                 }
             }
         }
-        
-        // Clean up old processed events
-        if let Err(e) = metadata_service.cleanup_old_deletions() {
-            warn!("Failed to cleanup old deletion events: {}", e);
+
+        // Sealed segments emptied by these deletions are unlinked outright
+        for (user_id, bucket) in touched {
+            if let Err(e) = self.release_segments(&user_id, &bucket).await {
+                warn!("Failed to release segments of user {} bucket {}: {}", user_id, bucket, e);
+            }
         }
         
         Ok(())
     }
     
-    /// Process a single deletion event
-    async fn process_deletion_event(&self, event: &DeletionEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        info!("Processing deletion: user={}, bucket={}, key={}, chunks={}", 
-              event.user_id, event.bucket, event.key, event.offset_size_list.len());
+    /// Free everything queued for `user_id`/`bucket` now instead of waiting for the worker,
+    /// then unlink the sealed segments left without live data. Progress is counted in
+    /// events; a cancelled job stops between batches.
+    pub async fn compact_bucket(&self, user_id: &str, bucket: &str, job: &JobHandle) -> Result<CompactReport, actix_web::Error> {
+        let total = self.store.pending_deletion_count_for_bucket(user_id, bucket)?;
+        let mut report = CompactReport {
+            user: user_id.to_string(),
+            bucket: bucket.to_string(),
+            events: 0,
+            failed: 0,
+            freed_bytes: 0,
+            removed_segments: Vec::new(),
+        };
+        loop {
+            job.checkpoint()?;
+            let events = self.store.get_pending_deletions_for_bucket(user_id, bucket, self.batch_size)?;
+            if events.is_empty() {
+                break;
+            }
+            match self.free_events(user_id, bucket, &events).await {
+                Ok(freed) => {
+                    report.events += events.len() as u64;
+                    report.freed_bytes += freed;
+                }
+                // Failed events stay pending; stop rather than retry them forever
+                Err(e) => {
+                    error!("Failed to process {} deletion events of user {} bucket {}: {}",
+                           events.len(), user_id, bucket, e);
+                    self.record_failure(&events, &e.to_string());
+                    report.failed += events.len() as u64;
+                    break;
+                }
+            }
+            job.progress(report.events, total);
+            if events.len() < self.batch_size as usize {
+                break;
+            }
+        }
+        report.removed_segments = self.release_segments(user_id, bucket).await?;
+        info!("Compacted user {} bucket {}: {} events, {} bytes freed, segments {:?} removed",
+              user_id, bucket, report.events, report.freed_bytes, report.removed_segments);
+        Ok(report)
+    }
 
-        // Create user context for this deletion
-        let context = UserContext::with_bucket(event.user_id.clone(), event.bucket.clone());
+    /// Free the ranges of `events`, all queued for `user_id`/`bucket`, and mark the events
+    /// processed together. Overlapping and adjacent ranges are merged and ranges already
+    /// recorded as free are skipped. Returns the bytes freed.
+    async fn free_events(&self, user_id: &str, bucket: &str, events: &[DeletionEvent]) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let queued: Vec<(u64, u64)> = events.iter().flat_map(|e| e.offset_size_list.iter().copied()).collect();
+        let merged = merge_ranges(&queued);
+        let free = self.store.free_ranges_overlapping(user_id, bucket, &merged).map_err(|e| e.to_string())?;
+        let pending = subtract_ranges(&merged, &free);
+        info!("Processing {} deletions for user {} bucket {}: {} ranges queued, {} left to free",
+              events.len(), user_id, bucket, queued.len(), pending.len());
 
-        // Use storage service to delete the actual chunks (marks them as free)
-        let storage_service = StorageService::new();
-        if let Err(e) = storage_service.delete_chunks(&context, &event.offset_size_list) {
-            return Err(format!("Failed to delete chunks: {}", e).into());
+        if !pending.is_empty() {
+            // Use storage service to delete the actual chunks (marks them as free)
+            let context = UserContext::with_bucket(user_id.to_string(), bucket.to_string());
+            if let Err(e) = StorageService::new().delete_chunks(&context, &pending) {
+                return Err(format!("Failed to delete chunks: {}", e).into());
+            }
         }
+        let ids: Vec<i64> = events.iter().map(|e| e.id).collect();
+        self.store.complete_deletions(user_id, bucket, &pending, &ids).map_err(|e| e.to_string())?;
+        free_ranges::invalidate(user_id, bucket);
 
-        let freed_bytes = self.calculate_total_size(&event.offset_size_list);
-        info!("Freed {} bytes for user {} bucket {}", freed_bytes, event.user_id, event.bucket);
+        let freed_bytes = self.calculate_total_size(&pending);
+        info!("Freed {} bytes for user {} bucket {}", freed_bytes, user_id, bucket);
 
-        // Check if we should trigger compaction
-        // For now, we'll leave holes until there's enough free space at top of file
-        // This is a simplified approach - in the future we can add compaction logic here
-        // that checks if free space at top >= some threshold, then compact
+        // Holes stay until compaction moves live data; compaction::plan already decides when
+        // COMPACTION_FREE_RATIO warrants it and what would move (moving live chunks must
+        // rewrite every row that references them, including clones sharing the range through
+        // chunk_refs)
         
-        Ok(())
+        Ok(freed_bytes)
     }
     
+    /// Unlink the bucket's sealed segments that no longer hold live data
+    async fn release_segments(&self, user_id: &str, bucket: &str) -> Result<Vec<u32>, actix_web::Error> {
+        // Writers could be holding chunks in a just-sealed segment that metadata does not
+        // reference yet, so wait for them as GC does
+        let _quiesced = bucket_lock::quiesce(user_id, bucket).await;
+        // The plan GET /admin/compaction/plan shows, from this worker's store; its removals
+        // do not depend on the threshold
+        let context = UserContext::with_bucket(user_id.to_string(), bucket.to_string());
+        let live = self.store.live_extents(user_id, bucket)?;
+        let free = self.store.free_ranges(user_id, bucket)?;
+        let segments = StorageService::new().segments(&context)?;
+        let plan = compaction::plan(&segments, &live, &free, CompactionConfig::from_env().free_ratio);
+        let removed = StorageService::new().remove_segments(&context, &plan.removable_segments)?;
+        for segment in &removed {
+            self.store.forget_free_ranges(user_id, bucket, *segment)?;
+        }
+        free_ranges::invalidate(user_id, bucket);
+        if !removed.is_empty() {
+            info!("Removed freed segments {:?} of user {} bucket {}", removed, user_id, bucket);
+        }
+        Ok(removed)
+    }
+
+    /// Count a failed attempt on each of `events`, which stay pending
+    fn record_failure(&self, events: &[DeletionEvent], error: &str) {
+        let ids: Vec<i64> = events.iter().map(|e| e.id).collect();
+        if let Err(e) = self.store.record_deletion_failure(&ids, error) {
+            warn!("Failed to record the failure of {} deletion events: {}", ids.len(), e);
+        }
+    }
+
     /// Calculate total size of chunks to be deleted
     fn calculate_total_size(&self, offset_size_list: &[(u64, u64)]) -> u64 {
         offset_size_list.iter().map(|(_, size)| size).sum()
     }
 }
 
-/// Start the deletion worker as a background task (non-blocking)
-pub fn start_deletion_worker() -> tokio::task::JoinHandle<()> {
-    let worker = DeletionWorker::new();
+/// Sort `ranges` and merge the ones that overlap or touch within one segment. Empty ranges
+/// are dropped.
+pub(crate) fn merge_ranges(ranges: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut sorted: Vec<(u64, u64)> = ranges.iter().copied().filter(|(_, size)| *size > 0).collect();
+    sorted.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(sorted.len());
+    for (offset, size) in sorted {
+        if let Some((start, len)) = merged.last_mut() {
+            let end = *start + *len;
+            if offset <= end && segment::segment_of(offset) == segment::segment_of(*start) {
+                *len = end.max(offset + size) - *start;
+                continue;
+            }
+        }
+        merged.push((offset, size));
+    }
+    merged
+}
+
+/// The parts of `ranges` not covered by `free`; both sorted and without overlaps
+fn subtract_ranges(ranges: &[(u64, u64)], free: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut left = Vec::with_capacity(ranges.len());
+    for &(offset, size) in ranges {
+        let (mut start, end) = (offset, offset + size);
+        for &(free_offset, free_size) in free {
+            if free_offset + free_size <= start || free_offset >= end {
+                continue;
+            }
+            if free_offset > start {
+                left.push((start, free_offset - start));
+            }
+            start = start.max(free_offset + free_size);
+            if start >= end {
+                break;
+            }
+        }
+        if start < end {
+            left.push((start, end - start));
+        }
+    }
+    left
+}
+
+/// Start a deletion worker on `store` as a background task (non-blocking)
+pub fn start_deletion_worker(store: Arc<dyn MetadataStorage>) -> tokio::task::JoinHandle<()> {
+    let worker = DeletionWorker::with_store(store);
     worker.start_background()
 }
 
@@ -136,4 +413,24 @@ mod tests {
         assert_eq!(worker.batch_size, 100);
         assert_eq!(worker.cleanup_interval.as_secs(), 300);
     }
+
+    #[test]
+    fn test_merge_ranges() {
+        // Overlapping, adjacent and contained ranges collapse; duplicates and empties vanish
+        assert_eq!(merge_ranges(&[(100, 50), (0, 10), (120, 50), (10, 5), (0, 10), (130, 5), (500, 0)]),
+                   vec![(0, 15), (100, 70)]);
+        assert_eq!(merge_ranges(&[(0, 100), (20, 10)]), vec![(0, 100)]);
+        assert_eq!(merge_ranges(&[(0, 10), (11, 10)]), vec![(0, 10), (11, 10)]);
+        // A range ending at a segment boundary is not joined with the next segment
+        let boundary = segment::address(1, 0);
+        assert_eq!(merge_ranges(&[(boundary - 10, 10), (boundary, 10)]), vec![(boundary - 10, 10), (boundary, 10)]);
+    }
+
+    #[test]
+    fn test_subtract_ranges() {
+        assert_eq!(subtract_ranges(&[(0, 100)], &[]), vec![(0, 100)]);
+        assert_eq!(subtract_ranges(&[(0, 100)], &[(0, 100)]), vec![]);
+        assert_eq!(subtract_ranges(&[(0, 100)], &[(10, 10), (50, 60)]), vec![(0, 10), (20, 30)]);
+        assert_eq!(subtract_ranges(&[(10, 10), (40, 10)], &[(0, 15), (45, 100)]), vec![(15, 5), (40, 5)]);
+    }
 }
@@ -0,0 +1,175 @@
+//! Resumable downloads of large objects
+//!
+//! `GET /download/{key}` streams the raw bytes of an object a slice at a time, reading ahead
+//! at most `GET_READ_AHEAD_CHUNKS` slices of `SLICE_BYTES`, so a multi-GB object never sits in
+//! memory. Every response carries `X-Warp-Resume-Token`: the object's generation and the byte
+//! the body starts at, signed with HMAC-SHA256 under the server key. The MAC also covers the
+//! user, bucket and key, so a token cannot be pointed at another object or another position.
+//!
+//! A client cut off part way comes back with `?resume=<token>`, adding `Range: bytes=N-` for
+//! the N bytes it already holds. The download continues there as long as the object is still
+//! the generation the token names; once it has been rewritten the answer is 409 and the
+//! client starts over. A body the client stops reading before its end is logged with the
+//! byte it reached and counted in `warpdrive_download_interrupted_total`.
+//!
+//! The key is `DOWNLOAD_TOKEN_SECRET`. When unset a random key is drawn at startup, and
+//! tokens only hold until the server restarts.
+
+use actix_web::Error;
+use bytes::Bytes;
+use futures::stream::{self, LocalBoxStream, StreamExt};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::info;
+use sha2::Sha256;
+
+use crate::metrics;
+use crate::service::error::ServiceError;
+use crate::service::object_service::ObjectRead;
+use crate::service::storage_service::{read_ahead_chunks, StorageService};
+use crate::service::user_context::UserContext;
+use crate::util::byte_ranges::{self, ByteRanges, RangeConfig};
+
+/// Response header carrying the token to resume the body from
+pub const RESUME_TOKEN_HEADER: &str = "x-warp-resume-token";
+
+/// Most bytes read from storage for one piece of the body
+pub const SLICE_BYTES: u64 = 1024 * 1024;
+
+lazy_static! {
+    static ref SERVER_KEY: Vec<u8> = match std::env::var("DOWNLOAD_TOKEN_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+        _ => {
+            let mut random = [0u8; 32];
+            getrandom::getrandom(&mut random).expect("OS random number generator unavailable");
+            random.to_vec()
+        }
+    };
+}
+
+/// Where a download of one generation of an object stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeToken {
+    pub generation: u64,
+    pub position: u64,
+}
+
+impl ResumeToken {
+    /// `<generation>.<position>.<hex HMAC>` for `key` in the context's bucket
+    pub fn sign(&self, context: &UserContext, key: &str) -> String {
+        format!("{}.{}.{}", self.generation, self.position, hex::encode(self.mac(context, key).finalize().into_bytes()))
+    }
+
+    /// Read a token back, refusing one that was not signed for `key` in the context's bucket
+    pub fn verify(token: &str, context: &UserContext, key: &str) -> Result<Self, ServiceError> {
+        let invalid = || ServiceError::InvalidRequest(format!("Invalid resume token for key {}", key));
+        let mut fields = token.trim().splitn(3, '.');
+        let mut number = || fields.next().and_then(|f| f.parse::<u64>().ok());
+        let (generation, position) = (number().ok_or_else(invalid)?, number().ok_or_else(invalid)?);
+        let signature = fields.next().and_then(|f| hex::decode(f).ok()).ok_or_else(invalid)?;
+        let parsed = Self { generation, position };
+        parsed.mac(context, key).verify_slice(&signature).map_err(|_| invalid())?;
+        Ok(parsed)
+    }
+
+    fn mac(&self, context: &UserContext, key: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&SERVER_KEY).expect("HMAC accepts any key length");
+        for field in [context.user_id.as_str(), context.bucket.as_str(), key] {
+            mac.update(field.as_bytes());
+            mac.update(&[0]);
+        }
+        mac.update(format!("{}.{}", self.generation, self.position).as_bytes());
+        mac
+    }
+}
+
+/// Bytes `first..=last` to send of an object of `total` bytes, or `None` for all of it.
+/// A resumed download starts at its token's position unless `range` moves it further on.
+pub fn span(resume: Option<&ResumeToken>, range: Option<&str>, total: u64) -> Result<Option<(u64, u64)>, ServiceError> {
+    let start = resume.map_or(0, |token| token.position);
+    let unsatisfiable = || ServiceError::RangeNotSatisfiable { range: range.unwrap_or_default().to_string(), size: total };
+    match byte_ranges::parse(range, total, &RangeConfig::from_env()) {
+        ByteRanges::Full if start == 0 => Ok(None),
+        ByteRanges::Full if start < total => Ok(Some((start, total - 1))),
+        ByteRanges::Full => Err(unsatisfiable()),
+        ByteRanges::Single(first, last) if first >= start => Ok(Some((first, last))),
+        ByteRanges::Single(first, _) => Err(ServiceError::InvalidRequest(format!(
+            "Range starts at {} but the resume token is at {}", first, start
+        ))),
+        ByteRanges::Multiple(_) => Err(ServiceError::InvalidRequest("A download takes a single range".to_string())),
+        ByteRanges::Unsatisfiable => Err(unsatisfiable()),
+    }
+}
+
+/// Stream bytes `first..=last` of `read`, noting where the body stopped if it is dropped early
+pub fn body(context: &UserContext, key: &str, read: &ObjectRead, (first, last): (u64, u64)) -> LocalBoxStream<'static, Result<Bytes, Error>> {
+    let body = if read.metadata.is_inline() {
+        let data = Bytes::from(read.inline.concat()).slice(first as usize..=last as usize);
+        stream::once(async move { Ok(data) }).boxed_local()
+    } else {
+        let slices = byte_ranges::range_slices(&read.extents, first, last, SLICE_BYTES);
+        StorageService::new().chunk_stream(context, slices, read_ahead_chunks())
+    };
+    let mut progress = Progress { context: context.clone(), key: key.to_string(), position: first, end: last + 1 };
+    body.map(move |piece| {
+        if let Ok(data) = &piece {
+            progress.advance(data.len());
+        }
+        piece
+    }).boxed_local()
+}
+
+/// How far a body got; reported when it is dropped before the end
+struct Progress {
+    context: UserContext,
+    key: String,
+    position: u64,
+    end: u64,
+}
+
+impl Progress {
+    fn advance(&mut self, bytes: usize) {
+        self.position += bytes as u64;
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.position < self.end {
+            info!("Download of key: {} in bucket: {} for user: {} stopped at byte {} of {}",
+                  self.key, self.context.bucket, self.context.user_id, self.position, self.end);
+            metrics::inc_counter("warpdrive_download_interrupted_total", &[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_bound_to_their_object() {
+        let context = UserContext::with_bucket("u".to_string(), "b".to_string());
+        let token = ResumeToken { generation: 3, position: 4096 };
+        let signed = token.sign(&context, "big");
+        assert_eq!(ResumeToken::verify(&signed, &context, "big"), Ok(token));
+        assert!(ResumeToken::verify(&signed, &context, "other").is_err());
+        assert!(ResumeToken::verify(&signed, &UserContext::with_bucket("u".to_string(), "c".to_string()), "big").is_err());
+        // Moving the position voids the signature
+        let forged = signed.replacen("4096", "8192", 1);
+        assert!(ResumeToken::verify(&forged, &context, "big").is_err());
+        assert!(ResumeToken::verify("garbage", &context, "big").is_err());
+    }
+
+    #[test]
+    fn test_span() {
+        let at = |position| ResumeToken { generation: 1, position };
+        assert_eq!(span(None, None, 100), Ok(None));
+        assert_eq!(span(Some(&at(0)), None, 100), Ok(None));
+        assert_eq!(span(Some(&at(40)), None, 100), Ok(Some((40, 99))));
+        assert_eq!(span(Some(&at(40)), Some("bytes=60-"), 100), Ok(Some((60, 99))));
+        assert!(span(Some(&at(40)), Some("bytes=10-"), 100).is_err());
+        assert!(span(Some(&at(100)), None, 100).is_err());
+        assert_eq!(span(None, Some("bytes=0-9"), 100), Ok(Some((0, 9))));
+    }
+}
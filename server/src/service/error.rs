@@ -0,0 +1,310 @@
+//! Errors returned by the native API services
+//!
+//! Every native failure maps to one status and a JSON body of the form
+//! `{"error": "<variant>", "message": ..., ...}`: a missing key or upload session is always
+//! 404, an existing one or one rewritten under a resumed download 409, an empty or malformed
+//! body 400, a write past a bucket quota or a request a bucket grant does not allow 403, a
+//! chunk index or byte range past the end 416, a key leased to another writer 423, a user over
+//! their concurrency limit 429, a busy metadata store 503, and a chunk pointing into freed
+//! space or failing its checksum or anything unexpected 500.
+
+use actix_web::{Error, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use serde_json::json;
+
+use crate::metadata::MetadataError;
+use crate::service::object_state::{ObjectState, OBJECT_STATE_HEADER};
+use crate::service::retry::transient_cause;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServiceError {
+    KeyNotFound { bucket: String, key: String },
+    /// Deleted recently; reported as a missing key with `X-Warp-Object-State: deleting`
+    KeyDeleting { bucket: String, key: String },
+    /// Behind a delete marker at `as_of`; reported as a missing key with
+    /// `X-Warp-Object-State: deleted`
+    KeyDeletedAsOf { bucket: String, key: String, as_of: String },
+    KeyExists { bucket: String, key: String },
+    /// `key` is at generation `current`, no longer the `expected` one a resume token names
+    GenerationChanged { bucket: String, key: String, expected: u64, current: u64 },
+    /// Missing from `bucket` but live in `found_in`, other buckets of the user; usually a
+    /// wrong or missing `Bucket` header
+    KeyInOtherBuckets { bucket: String, key: String, found_in: Vec<String> },
+    /// No upload session `session` of the user is in progress for `key`
+    NoSuchUpload { bucket: String, key: String, session: String },
+    /// The request carried no data (or a FlatBuffers list with no files)
+    EmptyPayload(&'static str),
+    /// A FlatBuffers body failed verification
+    InvalidPayload(String),
+    /// A FlatBuffers body is over `limit` (`files` or `file_bytes`) of `max`; `actual` is
+    /// unknown when verification stopped before counting
+    PayloadLimitExceeded { limit: &'static str, max: u64, actual: Option<u64> },
+    /// The request is well-formed but cannot be applied
+    InvalidRequest(String),
+    /// `?chunk=`/`?chunks=` selected indices past the end of the object
+    ChunkOutOfRange { requested: String, chunk_count: usize },
+    /// No range of a `Range: bytes=` header `range` lies within the `size` raw bytes sent
+    RangeNotSatisfiable { range: String, size: u64 },
+    /// The write would take `bucket` past its `limit` of `max`; `objects` and `bytes` are its
+    /// usage before the write
+    QuotaExceeded { bucket: String, limit: &'static str, max: u64, objects: u64, bytes: u64 },
+    /// The user may not do this to another user's bucket: no grant, or one that does not
+    /// allow it
+    AccessDenied { bucket: String, message: String },
+    /// Another writer holds the lease on `key` until `expires_at`
+    Locked { bucket: String, key: String, expires_at: String },
+    /// The metadata store stayed locked; the client should retry
+    BackendUnavailable(String),
+    /// The user has too many requests in flight; the client should back off
+    SlowDown(String),
+    /// The metadata points `chunk` into `freed`, a range the deletion worker has released
+    FreedRangeReference { bucket: String, chunk: (u64, u64), freed: (u64, u64) },
+    /// `chunk` read back with a CRC32C other than the one recorded for it
+    ChecksumMismatch { bucket: String, chunk: (u64, u64), expected: u32, actual: u32 },
+    Internal(String),
+}
+
+impl ServiceError {
+    pub fn key_not_found(bucket: &str, key: &str) -> Self {
+        ServiceError::KeyNotFound { bucket: bucket.to_string(), key: key.to_string() }
+    }
+
+    pub fn key_exists(bucket: &str, key: &str) -> Self {
+        ServiceError::KeyExists { bucket: bucket.to_string(), key: key.to_string() }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ServiceError::KeyNotFound { .. }
+            | ServiceError::KeyDeleting { .. }
+            | ServiceError::KeyDeletedAsOf { .. }
+            | ServiceError::KeyInOtherBuckets { .. } => "KeyNotFound",
+            ServiceError::KeyExists { .. } => "KeyExists",
+            ServiceError::NoSuchUpload { .. } => "NoSuchUpload",
+            ServiceError::GenerationChanged { .. } => "GenerationChanged",
+            ServiceError::EmptyPayload(_) => "EmptyPayload",
+            ServiceError::InvalidRequest(_) => "InvalidRequest",
+            ServiceError::InvalidPayload(_) => "InvalidPayload",
+            ServiceError::PayloadLimitExceeded { .. } => "PayloadLimitExceeded",
+            ServiceError::ChunkOutOfRange { .. } => "InvalidChunkRange",
+            ServiceError::RangeNotSatisfiable { .. } => "InvalidRange",
+            ServiceError::QuotaExceeded { .. } => "QuotaExceeded",
+            ServiceError::AccessDenied { .. } => "AccessDenied",
+            ServiceError::Locked { .. } => "Locked",
+            ServiceError::BackendUnavailable(_) => "BackendUnavailable",
+            ServiceError::SlowDown(_) => "SlowDown",
+            ServiceError::FreedRangeReference { .. } => "FreedRangeReference",
+            ServiceError::ChecksumMismatch { .. } => "ChecksumMismatch",
+            ServiceError::Internal(_) => "InternalError",
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceError::KeyNotFound { bucket, key } => write!(f, "Key does not exist: {} in bucket: {}", key, bucket),
+            ServiceError::KeyDeleting { bucket, key } => write!(f, "Key is being deleted: {} in bucket: {}", key, bucket),
+            ServiceError::KeyDeletedAsOf { bucket, key, as_of } => write!(f, "Key was deleted as of {}: {} in bucket: {}", as_of, key, bucket),
+            ServiceError::KeyExists { bucket, key } => write!(f, "Key already exists: {} in bucket: {}", key, bucket),
+            ServiceError::NoSuchUpload { bucket, key, session } => write!(
+                f, "No upload session {} is in progress for key: {} in bucket: {}", session, key, bucket
+            ),
+            ServiceError::GenerationChanged { bucket, key, expected, current } => write!(
+                f, "Key {} in bucket {} is at generation {}, not {}; restart the download", key, bucket, current, expected
+            ),
+            ServiceError::KeyInOtherBuckets { bucket, key, found_in } => write!(
+                f, "Key does not exist: {} in bucket: {}, but exists in bucket(s): {}; check the Bucket header",
+                key, bucket, found_in.join(", ")
+            ),
+            ServiceError::EmptyPayload(message) => write!(f, "{}", message),
+            ServiceError::ChunkOutOfRange { requested, chunk_count: 0 } => {
+                write!(f, "Chunks {} requested but the object has no chunks", requested)
+            }
+            ServiceError::ChunkOutOfRange { requested, chunk_count } => {
+                write!(f, "Chunks {} requested but valid indices are 0-{}", requested, chunk_count - 1)
+            }
+            ServiceError::RangeNotSatisfiable { range, size } => {
+                write!(f, "Range {} is not satisfiable for {} bytes", range, size)
+            }
+            ServiceError::QuotaExceeded { bucket, limit, max, objects, bytes } => write!(
+                f, "Bucket {} is at its {} quota of {} ({} objects, {} bytes)",
+                bucket, limit, max, objects, bytes
+            ),
+            ServiceError::AccessDenied { message, .. } => write!(f, "{}", message),
+            ServiceError::Locked { bucket, key, expires_at } => write!(
+                f, "Key {} in bucket {} is leased to another writer until {}", key, bucket, expires_at
+            ),
+            ServiceError::FreedRangeReference { bucket, chunk, freed } => write!(
+                f, "Chunk ({}, {}) in bucket {} references freed range ({}, {})",
+                chunk.0, chunk.1, bucket, freed.0, freed.1
+            ),
+            ServiceError::ChecksumMismatch { bucket, chunk, expected, actual } => write!(
+                f, "Chunk ({}, {}) in bucket {} read back with CRC32C {:08x}, recorded {:08x}",
+                chunk.0, chunk.1, bucket, actual, expected
+            ),
+            ServiceError::PayloadLimitExceeded { limit, max, actual: Some(actual) } => write!(
+                f, "Payload is over its {} limit: {} sent, at most {} allowed", limit, actual, max
+            ),
+            ServiceError::PayloadLimitExceeded { limit, max, actual: None } => write!(
+                f, "Payload is over its {} limit of {}", limit, max
+            ),
+            ServiceError::InvalidRequest(message)
+            | ServiceError::InvalidPayload(message)
+            | ServiceError::BackendUnavailable(message)
+            | ServiceError::SlowDown(message)
+            | ServiceError::Internal(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl ResponseError for ServiceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ServiceError::KeyNotFound { .. }
+            | ServiceError::KeyDeleting { .. }
+            | ServiceError::KeyDeletedAsOf { .. }
+            | ServiceError::KeyInOtherBuckets { .. }
+            | ServiceError::NoSuchUpload { .. } => StatusCode::NOT_FOUND,
+            ServiceError::KeyExists { .. } | ServiceError::GenerationChanged { .. } => StatusCode::CONFLICT,
+            ServiceError::EmptyPayload(_)
+            | ServiceError::InvalidRequest(_)
+            | ServiceError::InvalidPayload(_)
+            | ServiceError::PayloadLimitExceeded { .. } => StatusCode::BAD_REQUEST,
+            ServiceError::ChunkOutOfRange { .. } | ServiceError::RangeNotSatisfiable { .. } => StatusCode::RANGE_NOT_SATISFIABLE,
+            ServiceError::QuotaExceeded { .. } | ServiceError::AccessDenied { .. } => StatusCode::FORBIDDEN,
+            ServiceError::Locked { .. } => StatusCode::LOCKED,
+            ServiceError::BackendUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceError::SlowDown(_) => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::FreedRangeReference { .. }
+            | ServiceError::ChecksumMismatch { .. }
+            | ServiceError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({ "error": self.code(), "message": self.to_string() });
+        if let ServiceError::KeyNotFound { bucket, key }
+            | ServiceError::KeyDeleting { bucket, key }
+            | ServiceError::KeyDeletedAsOf { bucket, key, .. }
+            | ServiceError::KeyExists { bucket, key }
+            | ServiceError::GenerationChanged { bucket, key, .. }
+            | ServiceError::KeyInOtherBuckets { bucket, key, .. }
+            | ServiceError::NoSuchUpload { bucket, key, .. }
+            | ServiceError::Locked { bucket, key, .. } = self {
+            body["bucket"] = json!(bucket);
+            body["key"] = json!(key);
+        }
+        if let ServiceError::NoSuchUpload { session, .. } = self {
+            body["session"] = json!(session);
+        }
+        if let ServiceError::KeyInOtherBuckets { found_in, .. } = self {
+            body["found_in"] = json!(found_in);
+        }
+        if let ServiceError::GenerationChanged { expected, current, .. } = self {
+            body["expected_generation"] = json!(expected);
+            body["generation"] = json!(current);
+        }
+        if let ServiceError::Locked { expires_at, .. } = self {
+            body["expires_at"] = json!(expires_at);
+        }
+        if let ServiceError::ChunkOutOfRange { chunk_count, .. } = self {
+            body["chunk_count"] = json!(chunk_count);
+        }
+        if let ServiceError::RangeNotSatisfiable { size, .. } = self {
+            body["size"] = json!(size);
+        }
+        if let ServiceError::QuotaExceeded { bucket, limit, max, objects, bytes } = self {
+            body["bucket"] = json!(bucket);
+            body["limit"] = json!(limit);
+            body["max"] = json!(max);
+            body["objects"] = json!(objects);
+            body["bytes"] = json!(bytes);
+        }
+        if let ServiceError::AccessDenied { bucket, .. } = self {
+            body["bucket"] = json!(bucket);
+        }
+        if let ServiceError::PayloadLimitExceeded { limit, max, actual } = self {
+            body["limit"] = json!(limit);
+            body["max"] = json!(max);
+            body["actual"] = json!(actual);
+        }
+        if let ServiceError::FreedRangeReference { bucket, chunk, freed } = self {
+            body["bucket"] = json!(bucket);
+            body["chunk"] = json!([chunk.0, chunk.1]);
+            body["freed"] = json!([freed.0, freed.1]);
+        }
+        if let ServiceError::ChecksumMismatch { bucket, chunk, .. } = self {
+            body["bucket"] = json!(bucket);
+            body["chunk"] = json!([chunk.0, chunk.1]);
+        }
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            ServiceError::BackendUnavailable(_) | ServiceError::SlowDown(_) => {
+                response.insert_header(("Retry-After", "1"));
+            }
+            ServiceError::KeyDeleting { .. } => {
+                body["state"] = json!(ObjectState::Deleting);
+                response.insert_header((OBJECT_STATE_HEADER, ObjectState::Deleting.as_str()));
+            }
+            ServiceError::KeyDeletedAsOf { as_of, .. } => {
+                body["state"] = json!(ObjectState::Deleted);
+                body["as_of"] = json!(as_of);
+                response.insert_header((OBJECT_STATE_HEADER, ObjectState::Deleted.as_str()));
+            }
+            ServiceError::RangeNotSatisfiable { size, .. } => {
+                response.insert_header(("Content-Range", format!("bytes */{}", size)));
+            }
+            _ => {}
+        }
+        response.json(body)
+    }
+}
+
+/// Replace errors shared with the S3 layer by their native JSON form: a transient backend
+/// failure (busy metadata store, interrupted I/O) becomes `BackendUnavailable`, a racing
+/// insert `KeyExists` and any other 500 `Internal`.
+pub fn native_error(e: Error) -> Error {
+    if transient_cause(&e).is_some() {
+        return ServiceError::BackendUnavailable(e.to_string()).into();
+    }
+    if let Some(MetadataError::AlreadyExists { bucket, key }) = e.as_error::<MetadataError>() {
+        return ServiceError::key_exists(bucket, key).into();
+    }
+    if e.as_response_error().status_code() == StatusCode::INTERNAL_SERVER_ERROR && e.as_error::<ServiceError>().is_none() {
+        return ServiceError::Internal(e.to_string()).into();
+    }
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::sqlite_store::MetadataBusyError;
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(ServiceError::key_not_found("b", "k").status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(ServiceError::key_exists("b", "k").status_code(), StatusCode::CONFLICT);
+        let changed = ServiceError::GenerationChanged { bucket: "b".to_string(), key: "k".to_string(), expected: 1, current: 2 };
+        assert_eq!(changed.status_code(), StatusCode::CONFLICT);
+        assert_eq!(ServiceError::EmptyPayload("No data was uploaded").status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(ServiceError::BackendUnavailable("busy".into()).status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        let locked = ServiceError::Locked { bucket: "b".into(), key: "k".into(), expires_at: "2030-01-01T00:00:00.000Z".into() };
+        assert_eq!(locked.status_code(), StatusCode::LOCKED);
+        assert_eq!(ServiceError::Internal("boom".into()).status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        let corrupt = ServiceError::ChecksumMismatch { bucket: "b".into(), chunk: (0, 4), expected: 1, actual: 2 };
+        assert_eq!(corrupt.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        let out_of_range = ServiceError::ChunkOutOfRange { requested: "3".into(), chunk_count: 3 };
+        assert_eq!(out_of_range.status_code(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(out_of_range.to_string(), "Chunks 3 requested but valid indices are 0-2");
+
+        let busy = native_error(MetadataBusyError { op: "put_metadata", code: rusqlite::ErrorCode::DatabaseBusy }.into());
+        assert_eq!(busy.as_response_error().status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(busy.as_error::<ServiceError>().is_some());
+        let interrupted = native_error(crate::storage::error::io_error(std::io::ErrorKind::Interrupted.into()));
+        assert_eq!(interrupted.as_response_error().status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        let denied = native_error(crate::storage::error::io_error(std::io::ErrorKind::PermissionDenied.into()));
+        assert_eq!(denied.as_response_error().status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,156 @@
+//! Per-object expiry
+//!
+//! A write may set a time-to-live with `X-Warp-Expires: <seconds>` or an absolute
+//! `X-Warp-Expire-At: <RFC 3339>` (native PUT and UPDATE, S3 PutObject). The time is stored
+//! in the object's `expire_at` column, in the form of `last_modified`, and reported on reads
+//! as `X-Warp-Expire-At` (native) or `x-amz-expiration` (S3). Appends keep it; a replacing
+//! write carries only the expiry it was sent with.
+//!
+//! Once the time has passed the object is gone: the first lookup that finds it expired
+//! removes its metadata and queues its chunks, as a delete would, and the deletion worker
+//! sweeps the rest each pass through the `expire_at` index. Objects under retention or a
+//! legal hold are left alone until the lock lapses. Times are read from
+//! [`clock`](crate::util::clock).
+
+use actix_web::{Error, HttpRequest};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use log::{info, warn};
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::service::error::ServiceError;
+use crate::util::clock;
+
+/// Request header giving a time-to-live in seconds
+pub const EXPIRES_HEADER: &str = "x-warp-expires";
+/// Request and response header giving the absolute expiry time
+pub const EXPIRE_AT_HEADER: &str = "x-warp-expire-at";
+/// Rule id reported in `x-amz-expiration` for per-object expiry
+pub const S3_RULE_ID: &str = "object-ttl";
+
+/// Objects expired per sweep when the deletion worker runs
+pub const SWEEP_BATCH: usize = 1000;
+
+/// `time` in the stored form, comparable as text
+pub fn stored(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+/// The current [`clock`] time in the stored form
+pub fn now() -> String {
+    stored(clock::now())
+}
+
+/// The expiry a write asks for, in the stored form, or None. `X-Warp-Expires` must be a
+/// non-negative number of seconds and `X-Warp-Expire-At` an RFC 3339 time; sending both is
+/// an error.
+pub fn requested(req: &HttpRequest) -> Result<Option<String>, ServiceError> {
+    let header = |name: &str| -> Result<Option<&str>, ServiceError> {
+        req.headers().get(name)
+            .map(|v| v.to_str().map(str::trim).map_err(|_| ServiceError::InvalidRequest(format!("Header {} must be visible ASCII", name))))
+            .transpose()
+    };
+    match (header(EXPIRES_HEADER)?, header(EXPIRE_AT_HEADER)?) {
+        (Some(_), Some(_)) => Err(ServiceError::InvalidRequest(format!(
+            "Send either {} or {}, not both", EXPIRES_HEADER, EXPIRE_AT_HEADER
+        ))),
+        (Some(ttl), None) => {
+            let seconds: i64 = ttl.parse().ok().filter(|s| *s >= 0).ok_or_else(|| ServiceError::InvalidRequest(format!(
+                "{} must be a number of seconds, got {:?}", EXPIRES_HEADER, ttl
+            )))?;
+            let ttl = Duration::try_seconds(seconds).ok_or_else(|| ServiceError::InvalidRequest(format!(
+                "{} is too large: {}", EXPIRES_HEADER, seconds
+            )))?;
+            Ok(Some(stored(clock::now() + ttl)))
+        }
+        (None, Some(at)) => {
+            let at = DateTime::parse_from_rfc3339(at).map_err(|_| ServiceError::InvalidRequest(format!(
+                "{} must be an RFC 3339 time, got {:?}", EXPIRE_AT_HEADER, at
+            )))?;
+            Ok(Some(stored(at.with_timezone(&Utc))))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// A stored expiry as RFC 3339 for `X-Warp-Expire-At`
+pub fn header_value(expire_at: &str) -> String {
+    DateTime::parse_from_rfc3339(expire_at)
+        .map(|t| t.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Millis, true))
+        .unwrap_or_else(|_| expire_at.to_string())
+}
+
+/// A stored expiry as the value of S3's `x-amz-expiration`
+pub fn s3_expiration(expire_at: &str) -> String {
+    format!("expiry-date=\"{}\", rule-id=\"{}\"", crate::metadata::http_date(expire_at), S3_RULE_ID)
+}
+
+/// Expire `user_id`'s `bucket`/`key` if it is due and not locked. Returns whether the key is
+/// (now) gone because it expired. On a read-only replica nothing is removed, but due keys
+/// still read as gone.
+pub fn expire_if_due(user_id: &str, bucket: &str, key: &str) -> Result<bool, Error> {
+    let store = SQLiteMetadataStore::new();
+    let now = now();
+    let Some(version_id) = store.expired_version(user_id, bucket, key, &now)? else {
+        return Ok(false);
+    };
+    let (retained, held) = store.check_object_lock_protection(bucket, key, &version_id, false)?;
+    if retained || held {
+        return Ok(false);
+    }
+    if crate::config::is_replica() {
+        return Ok(true);
+    }
+    // False when the key was replaced or expired by someone else since the first look
+    let expired = store.expire_object(user_id, bucket, key, &now)?;
+    if expired {
+        info!("Expired key: {} in bucket: {} (user: {})", key, bucket, user_id);
+    }
+    Ok(expired)
+}
+
+/// Expire up to `limit` due objects; returns how many were removed.
+/// Replicas leave expiry to the primary.
+pub fn sweep(limit: usize) -> Result<usize, Error> {
+    if crate::config::is_replica() {
+        return Ok(0);
+    }
+    let due = SQLiteMetadataStore::new().due_expirations(&now(), limit)?;
+    let mut expired = 0;
+    for (user_id, bucket, key) in due {
+        match expire_if_due(&user_id, &bucket, &key) {
+            Ok(true) => expired += 1,
+            Ok(false) => {}
+            Err(e) => warn!("Failed to expire key: {} in bucket: {} (user: {}): {}", key, bucket, user_id, e),
+        }
+    }
+    Ok(expired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_requested_expiry() {
+        let req = TestRequest::default().insert_header((EXPIRE_AT_HEADER, "2030-01-02T03:04:05+02:00")).to_http_request();
+        assert_eq!(requested(&req).unwrap().as_deref(), Some("2030-01-02T01:04:05.000Z"));
+        assert_eq!(header_value("2030-01-02T01:04:05.000Z"), "2030-01-02T01:04:05.000Z");
+        assert_eq!(s3_expiration("2030-01-02T01:04:05.000Z"), "expiry-date=\"Wed, 02 Jan 2030 01:04:05 GMT\", rule-id=\"object-ttl\"");
+
+        let req = TestRequest::default().insert_header((EXPIRES_HEADER, "60")).to_http_request();
+        let at = requested(&req).unwrap().unwrap();
+        assert!(at > now() && at <= stored(clock::now() + Duration::seconds(60)));
+
+        for (name, value) in [(EXPIRES_HEADER, "-1"), (EXPIRES_HEADER, "soon"), (EXPIRE_AT_HEADER, "tomorrow")] {
+            let req = TestRequest::default().insert_header((name, value)).to_http_request();
+            assert!(requested(&req).is_err(), "{}: {}", name, value);
+        }
+        let req = TestRequest::default()
+            .insert_header((EXPIRES_HEADER, "60"))
+            .insert_header((EXPIRE_AT_HEADER, "2030-01-01T00:00:00Z"))
+            .to_http_request();
+        assert!(requested(&req).is_err());
+        assert_eq!(requested(&TestRequest::default().to_http_request()).unwrap(), None);
+    }
+}
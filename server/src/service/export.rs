@@ -0,0 +1,87 @@
+//! Bucket export as a tar archive (`warp_drive export`)
+//!
+//! Every live object becomes `objects/{key}`, holding its chunks back to back, and its
+//! metadata row (the same JSON as the admin dump, chunk sizes included, so native files can
+//! be split apart again) becomes one line of `metadata.ndjson`, written last. Keys that are
+//! not valid archive paths, such as ones with `..` segments, are skipped and reported.
+
+use actix_web::Error;
+use actix_web::error::ErrorInternalServerError;
+use log::{info, warn};
+use serde::Serialize;
+use std::io::Write;
+
+use crate::service::metadata_service::MetadataService;
+use crate::storage::config::StorageConfig;
+
+/// Rows read per metadata page
+const PAGE_ROWS: usize = 500;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportReport {
+    pub user: String,
+    pub bucket: String,
+    pub objects: u64,
+    pub bytes: u64,
+    /// Keys left out because they cannot be archive paths
+    pub skipped: Vec<String>,
+}
+
+fn header(size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header
+}
+
+/// Write the live objects of `user`/`bucket` to `out` as a tar archive.
+pub fn export_bucket<W: Write>(user: &str, bucket: &str, out: W) -> Result<ExportReport, Error> {
+    let db = MetadataService::new(user)?;
+    if !db.bucket_exists(bucket)? {
+        return Err(actix_web::error::ErrorNotFound(format!("No bucket: {} for user: {}", bucket, user)));
+    }
+    let store = StorageConfig::from_env().create_store();
+    let mut archive = tar::Builder::new(out);
+    let mut report = ExportReport {
+        user: user.to_string(),
+        bucket: bucket.to_string(),
+        objects: 0,
+        bytes: 0,
+        skipped: Vec::new(),
+    };
+    let mut index = Vec::new();
+
+    let mut after = 0i64;
+    loop {
+        let page = db.full_records_page(bucket, after, PAGE_ROWS)?;
+        let Some(last) = page.last().map(|r| r.row_id) else { break };
+        for record in page.iter().filter(|r| r.is_latest && !r.metadata.is_delete_marker) {
+            let mut data = record.metadata.inline_data.clone().unwrap_or_default();
+            data.reserve(record.metadata.size as usize);
+            for (offset, size) in record.metadata.chunks.iter().map(|c| c.extent()).filter(|(_, size)| *size > 0) {
+                data.extend(store.read(user, bucket, offset, size)?);
+            }
+            let path = format!("objects/{}", record.key);
+            if let Err(e) = archive.append_data(&mut header(data.len() as u64), &path, data.as_slice()) {
+                warn!("Export of user {} bucket {} skips key {}: {}", user, bucket, record.key, e);
+                report.skipped.push(record.key.clone());
+                continue;
+            }
+            serde_json::to_writer(&mut index, record).map_err(ErrorInternalServerError)?;
+            index.push(b'\n');
+            report.objects += 1;
+            report.bytes += data.len() as u64;
+        }
+        if page.len() < PAGE_ROWS {
+            break;
+        }
+        after = last;
+    }
+
+    archive.append_data(&mut header(index.len() as u64), "metadata.ndjson", index.as_slice())
+        .map_err(ErrorInternalServerError)?;
+    archive.into_inner().and_then(|mut out| out.flush()).map_err(ErrorInternalServerError)?;
+    info!("Exported {} objects ({} bytes) of user {} bucket {}", report.objects, report.bytes, user, bucket);
+    Ok(report)
+}
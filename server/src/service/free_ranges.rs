@@ -0,0 +1,98 @@
+//! Freed ranges, checked before reads
+//!
+//! The deletion worker records every range it has punched out of a segment file in the
+//! `free_ranges` table. Metadata that still points into such a range (a lost update, a row
+//! restored from an old backup) would read back zeros or, once the space is reused, another
+//! object's bytes; reads check their chunks here first and fail with 500
+//! `FreedRangeReference` instead. Each bucket's ranges are loaded once into a vector sorted
+//! by offset and dropped whenever the worker records or forgets ranges of the bucket.
+
+use actix_web::Error;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::service::error::ServiceError;
+use crate::service::metadata_service::MetadataService;
+
+/// Free ranges of one bucket by offset; ranges never overlap
+type Ranges = Arc<Vec<(u64, u64)>>;
+
+lazy_static! {
+    // (user_id, bucket) -> free ranges
+    static ref FREE: RwLock<HashMap<(String, String), Ranges>> = RwLock::new(HashMap::new());
+}
+
+fn ranges(user_id: &str, bucket: &str) -> Result<Ranges, Error> {
+    let cache_key = (user_id.to_string(), bucket.to_string());
+    if let Some(ranges) = FREE.read().unwrap_or_else(|e| e.into_inner()).get(&cache_key) {
+        return Ok(ranges.clone());
+    }
+    let ranges = Arc::new(MetadataService::new(user_id)?.free_ranges(bucket)?);
+    FREE.write().unwrap_or_else(|e| e.into_inner()).insert(cache_key, ranges.clone());
+    Ok(ranges)
+}
+
+/// The free range of `ranges` overlapping `[offset, offset + size)`, if any
+fn overlapping(ranges: &[(u64, u64)], offset: u64, size: u64) -> Option<(u64, u64)> {
+    if size == 0 {
+        return None;
+    }
+    // Ranges are disjoint, so only the last one starting before the end can overlap
+    let starts_before_end = ranges.partition_point(|&(start, _)| start < offset + size);
+    let &(start, len) = ranges[..starts_before_end].last()?;
+    (start + len > offset).then_some((start, len))
+}
+
+/// Whether any byte of `[offset, offset + size)` of `user_id`/`bucket` has been freed
+pub fn is_free(user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<bool, Error> {
+    Ok(overlapping(&ranges(user_id, bucket)?, offset, size).is_some())
+}
+
+/// Fail with [`ServiceError::FreedRangeReference`] on the first of `chunks` that overlaps a
+/// freed range of `user_id`/`bucket`.
+pub fn check_chunks(user_id: &str, bucket: &str, chunks: &[(u64, u64)]) -> Result<(), Error> {
+    let ranges = ranges(user_id, bucket)?;
+    if ranges.is_empty() {
+        return Ok(());
+    }
+    for &(offset, size) in chunks {
+        if let Some(freed) = overlapping(&ranges, offset, size) {
+            log::error!(
+                "Read of user={} bucket={} chunk ({}, {}) references freed range ({}, {})",
+                user_id, bucket, offset, size, freed.0, freed.1
+            );
+            return Err(ServiceError::FreedRangeReference { bucket: bucket.to_string(), chunk: (offset, size), freed }.into());
+        }
+    }
+    Ok(())
+}
+
+/// A free range of `user_id`/`bucket` overlapping `[offset, offset + size)`, if any
+pub(crate) fn freed_overlap(user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Option<(u64, u64)>, Error> {
+    Ok(overlapping(&ranges(user_id, bucket)?, offset, size))
+}
+
+/// Drop the cached ranges of `user_id`/`bucket`.
+pub(crate) fn invalidate(user_id: &str, bucket: &str) {
+    FREE.write().unwrap_or_else(|e| e.into_inner()).remove(&(user_id.to_string(), bucket.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlapping() {
+        let ranges = [(10, 5), (20, 10), (100, 1)];
+        assert_eq!(overlapping(&ranges, 0, 10), None);
+        assert_eq!(overlapping(&ranges, 0, 11), Some((10, 5)));
+        assert_eq!(overlapping(&ranges, 14, 1), Some((10, 5)));
+        assert_eq!(overlapping(&ranges, 15, 5), None);
+        assert_eq!(overlapping(&ranges, 15, 50), Some((20, 10)));
+        assert_eq!(overlapping(&ranges, 29, 0), None);
+        assert_eq!(overlapping(&ranges, 30, 70), None);
+        assert_eq!(overlapping(&ranges, 100, 1), Some((100, 1)));
+        assert_eq!(overlapping(&[], 0, 1), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! Offline garbage collection of orphaned chunks
+//!
+//! A chunk is orphaned when it was appended to a bucket file but no metadata row ever came
+//! to reference it (write failed before commit, client went away, ...). The collector diffs
+//! the referenced extents against each segment file of the bucket and queues the gaps for
+//! deletion.
+//! Extents shared by clones count as referenced; the pass also rebuilds `chunk_refs` so the
+//! clone reference counts match the object rows again, and drops dedup index entries of
+//! ranges no row references.
+
+use actix_web::Error;
+use log::info;
+use serde::Serialize;
+
+use crate::service::jobs::JobHandle;
+use crate::service::metadata_service::MetadataService;
+use crate::storage::bucket_lock;
+use crate::storage::config::StorageConfig;
+
+/// Outcome of one GC pass over a bucket
+#[derive(Debug, Clone, Serialize)]
+pub struct GcReport {
+    pub user: String,
+    pub bucket: String,
+    pub dry_run: bool,
+    pub bucket_len: u64,
+    pub orphaned_bytes: u64,
+    /// Unreferenced `(offset, size)` ranges
+    pub orphans: Vec<(u64, u64)>,
+    /// Whether the orphans were queued for deletion
+    pub queued: bool,
+}
+
+/// Ranges in `[start, start + len)` not covered by any of `referenced` (which may overlap or
+/// be unsorted).
+pub fn unreferenced_ranges(referenced: &[(u64, u64)], start: u64, len: u64) -> Vec<(u64, u64)> {
+    let mut extents: Vec<(u64, u64)> = referenced.iter().copied().filter(|(_, size)| *size > 0).collect();
+    extents.sort_unstable();
+
+    let end = start + len;
+    let mut gaps = Vec::new();
+    let mut cursor = start;
+    for (offset, size) in extents {
+        if offset >= end {
+            break;
+        }
+        if offset > cursor {
+            gaps.push((cursor, offset - cursor));
+        }
+        cursor = cursor.max(offset.saturating_add(size));
+    }
+    if cursor < end {
+        gaps.push((cursor, end - cursor));
+    }
+    gaps
+}
+
+/// Find orphaned ranges in `user`/`bucket` and, unless `dry_run`, queue them for deletion.
+///
+/// Holds the bucket's exclusive write gate for the whole pass so no in-flight request can
+/// have written chunks that are not yet referenced by metadata. Progress is counted in
+/// segment files; a cancelled job stops before anything is queued.
+pub async fn collect_orphans(user: &str, bucket: &str, dry_run: bool, job: &JobHandle) -> Result<GcReport, Error> {
+    let _quiesced = bucket_lock::quiesce(user, bucket).await;
+    job.checkpoint()?;
+
+    let store = StorageConfig::from_env().create_store();
+    let bucket_len = store.bucket_len(user, bucket)?;
+    let db = MetadataService::new(user)?;
+    let referenced = db.referenced_extents(bucket)?;
+    if !dry_run {
+        // Clone reference counts are derived from the same rows, so resync them while quiesced
+        db.rebuild_chunk_refs(bucket)?;
+    }
+
+    // Segment files are collected one by one; the address space between them is not storage
+    let segments = store.segments(user, bucket)?;
+    let mut orphans: Vec<(u64, u64)> = Vec::new();
+    for (done, (start, len)) in segments.iter().enumerate() {
+        job.checkpoint()?;
+        orphans.extend(unreferenced_ranges(&referenced, *start, *len));
+        job.progress(done as u64 + 1, segments.len() as u64);
+    }
+    let orphaned_bytes = orphans.iter().map(|(_, size)| size).sum();
+    let queued = !dry_run && !orphans.is_empty();
+    if queued {
+        db.queue_deletion(bucket, "", &orphans)?;
+    }
+
+    info!("GC user={} bucket={} dry_run={} len={} orphans={} orphaned_bytes={}",
+          user, bucket, dry_run, bucket_len, orphans.len(), orphaned_bytes);
+    Ok(GcReport {
+        user: user.to_string(),
+        bucket: bucket.to_string(),
+        dry_run,
+        bucket_len,
+        orphaned_bytes,
+        orphans,
+        queued,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unreferenced_ranges() {
+        assert_eq!(unreferenced_ranges(&[], 0, 10), vec![(0, 10)]);
+        assert_eq!(unreferenced_ranges(&[(0, 10)], 0, 10), vec![]);
+        assert_eq!(unreferenced_ranges(&[(6, 4), (0, 2)], 0, 10), vec![(2, 4)]);
+        // Overlapping and duplicate references collapse
+        assert_eq!(unreferenced_ranges(&[(0, 4), (2, 4), (2, 4)], 0, 10), vec![(6, 4)]);
+        // References past the end of the file are ignored
+        assert_eq!(unreferenced_ranges(&[(0, 2), (20, 5)], 0, 10), vec![(2, 8)]);
+        // A later segment only sees its own references
+        assert_eq!(unreferenced_ranges(&[(0, 2), (100, 4)], 100, 10), vec![(104, 6)]);
+    }
+}
@@ -0,0 +1,115 @@
+//! Retry-safe PUTs with client-supplied idempotency keys
+//!
+//! A PUT of a key that already exists is a conflict, which leaves a client that timed out
+//! waiting for its own PUT unable to tell whether it landed. Tagging the PUT with
+//! `X-Warp-Idempotency-Key` records the key and a SHA-256 digest of the body in the object's
+//! `put.idempotency` property. Retrying with the same idempotency key and the same body then
+//! answers 200 with the original outcome (and `X-Warp-Idempotency-Replayed: true`) without
+//! writing anything; a different body, another key or no key at all still conflicts. Records
+//! are honoured for `IDEMPOTENCY_WINDOW_SECS` (default a day, 0 turns replays off).
+//!
+//! S3 PutObject takes the same header: with `If-None-Match: *` a matching retry answers 200
+//! with the stored ETag instead of 412.
+
+use crate::metadata::Metadata;
+use chrono::Utc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+
+/// Request header carrying the client's key for one logical PUT
+pub const IDEMPOTENCY_KEY_HEADER: &str = "x-warp-idempotency-key";
+
+/// Set on a response answered from an earlier PUT's record
+pub const REPLAYED_HEADER: &str = "x-warp-idempotency-replayed";
+
+const IDEMPOTENCY_PROPERTY: &str = "put.idempotency";
+
+/// How long a PUT's idempotency key is honoured
+#[derive(Debug, Clone)]
+pub struct IdempotencyConfig {
+    pub window_secs: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self { window_secs: 86_400 }
+    }
+}
+
+impl IdempotencyConfig {
+    pub fn from_env() -> Self {
+        let window_secs = env::var("IDEMPOTENCY_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(Self::default().window_secs);
+        Self { window_secs }
+    }
+}
+
+/// What the PUT tagged with `key` stored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    /// SHA-256 of the request body, hex
+    pub digest: String,
+    /// Unix seconds the PUT completed
+    pub at: i64,
+    pub size: u64,
+    #[serde(default)]
+    pub files: usize,
+    #[serde(default)]
+    pub inline: bool,
+}
+
+impl IdempotencyRecord {
+    /// A record of a PUT of `body` tagged with `key`, completing now
+    pub fn new(key: &str, body: &[u8]) -> Self {
+        Self { key: key.to_string(), digest: digest(body), at: Utc::now().timestamp(), size: 0, files: 0, inline: false }
+    }
+}
+
+/// Hex SHA-256 of `body`
+pub fn digest(body: &[u8]) -> String {
+    hex::encode(Sha256::digest(body))
+}
+
+/// The record of a PUT tagged with `key` that created this object, if still in the window.
+pub fn find(metadata: &Metadata, key: &str, config: &IdempotencyConfig) -> Option<IdempotencyRecord> {
+    let json = metadata.properties.get(IDEMPOTENCY_PROPERTY)?;
+    let record: IdempotencyRecord = serde_json::from_str(json)
+        .map_err(|e| warn!("Ignoring unreadable {} property: {}", IDEMPOTENCY_PROPERTY, e))
+        .ok()?;
+    let age = Utc::now().timestamp().saturating_sub(record.at);
+    (record.key == key && age >= 0 && (age as u64) < config.window_secs).then_some(record)
+}
+
+/// Remember the PUT that created this object.
+pub fn remember(metadata: &mut Metadata, record: &IdempotencyRecord) {
+    if let Ok(json) = serde_json::to_string(record) {
+        metadata.properties.insert(IDEMPOTENCY_PROPERTY.to_string(), json);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_matches_key_within_window() {
+        let window = IdempotencyConfig { window_secs: 60 };
+        let mut metadata = Metadata::from_offset_size_list(vec![(0, 4)]);
+        assert!(find(&metadata, "k1", &window).is_none());
+
+        let record = IdempotencyRecord { size: 4, files: 1, ..IdempotencyRecord::new("k1", b"body") };
+        remember(&mut metadata, &record);
+        assert_eq!(find(&metadata, "k1", &window), Some(record.clone()));
+        assert_eq!(find(&metadata, "k1", &window).unwrap().digest, digest(b"body"));
+        assert!(find(&metadata, "k2", &window).is_none());
+        assert!(find(&metadata, "k1", &IdempotencyConfig { window_secs: 0 }).is_none());
+
+        remember(&mut metadata, &IdempotencyRecord { at: record.at - 61, ..record });
+        assert!(find(&metadata, "k1", &window).is_none());
+    }
+}
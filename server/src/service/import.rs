@@ -0,0 +1,261 @@
+//! Bulk import of a directory tree (`warp_drive import-dir`)
+//!
+//! Every regular file under the directory becomes one object whose key is its path relative
+//! to the directory, `/`-separated, after an optional prefix. The file is written as one raw
+//! chunk, the native object holding it as its one file (named after the file), the way a
+//! native PUT of a single file would store it; an empty file becomes an empty object.
+//! Symlinks and other special files are skipped, and so are keys already live unless
+//! `overwrite` is set, in which case the replaced object's chunks are queued for deletion.
+//! Up to `concurrency` files are imported at once.
+//!
+//! Each imported key is appended to a manifest, by default `<dir>.warpdrive-import` next to
+//! the directory, as a JSON line naming the user and bucket. Running the import again with
+//! the same manifest skips the keys it lists for that user and bucket, so an interrupted
+//! import resumes where it stopped. Failures are reported per file and do not stop the rest.
+
+use actix_web::Error;
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::metadata::{FileAttrs, Metadata};
+use crate::service::bucket_registry::BucketConfig;
+use crate::service::key_limits::KeyLimits;
+use crate::service::metadata_service::MetadataService;
+use crate::service::size_stats::{self, Api, WriteType};
+use crate::service::storage_service::{StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+use crate::storage::bucket_lock;
+
+/// Files imported at once by default
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Prepended to every key, e.g. `share/`
+    pub prefix: String,
+    pub concurrency: usize,
+    /// Replace live keys instead of skipping them
+    pub overwrite: bool,
+    /// Manifest of imported keys; None for `<dir>.warpdrive-import`
+    pub manifest: Option<PathBuf>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self { prefix: String::new(), concurrency: DEFAULT_CONCURRENCY, overwrite: false, manifest: None }
+    }
+}
+
+/// A file that could not be imported
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub user: String,
+    pub bucket: String,
+    pub manifest: String,
+    /// Files imported by this run
+    pub imported: u64,
+    pub bytes: u64,
+    /// Listed in the manifest by an earlier run
+    pub resumed: u64,
+    /// Live keys left alone (without `overwrite`)
+    pub existing: u64,
+    /// Live keys replaced (with `overwrite`)
+    pub replaced: u64,
+    /// Symlinks and special files
+    pub skipped: Vec<String>,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// One line of the manifest
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    user: String,
+    bucket: String,
+    key: String,
+}
+
+enum Outcome {
+    Imported(u64),
+    Existing,
+    Replaced(u64),
+}
+
+/// Default manifest of an import of `dir`: `<dir>.warpdrive-import`
+pub fn default_manifest(dir: &Path) -> PathBuf {
+    let mut name = dir.file_name().map(|n| n.to_os_string()).unwrap_or_else(|| "import".into());
+    name.push(".warpdrive-import");
+    dir.with_file_name(name)
+}
+
+/// Keys the manifest lists for `user`/`bucket`
+fn completed_keys(manifest: &Path, user: &str, bucket: &str) -> Result<HashSet<String>, Error> {
+    let text = match fs::read_to_string(manifest) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+    // A line torn by an interrupted run names a key that is imported again
+    Ok(text.lines()
+        .filter_map(|line| serde_json::from_str::<ManifestEntry>(line).ok())
+        .filter(|entry| entry.user == user && entry.bucket == bucket)
+        .map(|entry| entry.key)
+        .collect())
+}
+
+/// Regular files under `dir` as (path, key relative to `dir`), sorted; symlinks and special
+/// files go to `skipped`.
+fn walk(dir: &Path, root: &Path, files: &mut Vec<(PathBuf, String)>, skipped: &mut Vec<String>, failed: &mut Vec<ImportFailure>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            failed.push(ImportFailure { path: dir.display().to_string(), error: e.to_string() });
+            return;
+        }
+    };
+    let mut entries: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+    entries.sort();
+    for path in entries {
+        let file_type = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta.file_type(),
+            Err(e) => {
+                failed.push(ImportFailure { path: path.display().to_string(), error: e.to_string() });
+                continue;
+            }
+        };
+        if file_type.is_dir() {
+            walk(&path, root, files, skipped, failed);
+        } else if !file_type.is_file() {
+            skipped.push(path.display().to_string());
+        } else {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let parts: Option<Vec<&str>> = relative.components().map(|c| c.as_os_str().to_str()).collect();
+            match parts {
+                Some(parts) => files.push((path.clone(), parts.join("/"))),
+                None => failed.push(ImportFailure { path: path.display().to_string(), error: "file name is not UTF-8".to_string() }),
+            }
+        }
+    }
+}
+
+/// Import one file as `key`
+async fn import_file(storage: &StorageService, context: &UserContext, path: &Path, key: &str, overwrite: bool) -> Result<Outcome, Error> {
+    KeyLimits::from_env().check_new_key(key)?;
+    let db = MetadataService::new(&context.user_id)?;
+    let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[key]).await;
+    let exists = db.check_key_for_create(&context.bucket, key)?;
+    if exists && !overwrite {
+        return Ok(Outcome::Existing);
+    }
+
+    let bytes = fs::read(path)?;
+    let size = bytes.len() as u64;
+    let (extents, files) = if bytes.is_empty() {
+        (Vec::new(), Vec::new())
+    } else {
+        let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+        let context = context.clone();
+        let extents = storage.offload(bytes.len(), move |storage| storage.write_object(&context, &bytes, StorageMode::RAW)).await?;
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        (extents, vec![FileAttrs { name, content_type: None }])
+    };
+    let metadata = Metadata::from_offset_size_list(extents);
+    let chunk_sizes = size_stats::chunk_sizes(&metadata);
+    let outcome = if exists {
+        let replaced = db.overwrite_native_object(&context.bucket, key, metadata, &files)?;
+        db.queue_deletion(&context.bucket, key, &replaced)?;
+        Outcome::Replaced(size)
+    } else {
+        db.write_native_object(&context.bucket, key, metadata, &files, None)?;
+        Outcome::Imported(size)
+    };
+    size_stats::record(Api::Native, WriteType::Put, size, &chunk_sizes);
+    Ok(outcome)
+}
+
+/// Import the regular files under `dir` into `user`/`bucket`; see the module documentation.
+pub async fn import_dir(user: &str, bucket: &str, dir: &Path, options: &ImportOptions) -> Result<ImportReport, Error> {
+    if !dir.is_dir() {
+        return Err(actix_web::error::ErrorBadRequest(format!("{} is not a directory", dir.display())));
+    }
+    let db = MetadataService::new(user)?;
+    db.ensure_bucket_for_write(bucket, &BucketConfig::from_env())?;
+    let manifest_path = options.manifest.clone().unwrap_or_else(|| default_manifest(dir));
+    let completed = completed_keys(&manifest_path, user, bucket)?;
+    let manifest = Mutex::new(OpenOptions::new().create(true).append(true).open(&manifest_path)?);
+
+    let mut report = ImportReport {
+        user: user.to_string(),
+        bucket: bucket.to_string(),
+        manifest: manifest_path.display().to_string(),
+        ..ImportReport::default()
+    };
+    let mut files: Vec<(PathBuf, String)> = Vec::new();
+    walk(dir, dir, &mut files, &mut report.skipped, &mut report.failed);
+    let (done_before, pending): (Vec<_>, Vec<_>) = files.into_iter()
+        .filter(|(path, _)| *path != manifest_path)
+        .map(|(path, relative)| (path, format!("{}{}", options.prefix, relative)))
+        .partition(|(_, key)| completed.contains(key));
+    report.resumed = done_before.len() as u64;
+    info!("Importing {} files from {} into bucket: {} of user: {} ({} already imported)",
+          pending.len(), dir.display(), bucket, user, report.resumed);
+
+    let storage = StorageService::new();
+    let context = UserContext::with_bucket(user.to_string(), bucket.to_string());
+    let total = pending.len();
+    let mut results = stream::iter(pending)
+        .map(|(path, key)| {
+            let (storage, context) = (&storage, &context);
+            async move {
+                let outcome = import_file(storage, context, &path, &key, options.overwrite).await;
+                (path, key, outcome)
+            }
+        })
+        .buffer_unordered(options.concurrency.max(1));
+
+    let mut done = 0;
+    while let Some((path, key, outcome)) = results.next().await {
+        done += 1;
+        match outcome {
+            Ok(outcome) => {
+                match outcome {
+                    Outcome::Imported(size) => {
+                        report.imported += 1;
+                        report.bytes += size;
+                    }
+                    Outcome::Replaced(size) => {
+                        report.replaced += 1;
+                        report.bytes += size;
+                    }
+                    Outcome::Existing => report.existing += 1,
+                }
+                let entry = ManifestEntry { user: user.to_string(), bucket: bucket.to_string(), key };
+                let line = serde_json::to_string(&entry).map_err(actix_web::error::ErrorInternalServerError)?;
+                let mut manifest = manifest.lock().unwrap_or_else(|e| e.into_inner());
+                writeln!(manifest, "{}", line)?;
+                manifest.flush()?;
+            }
+            Err(e) => {
+                warn!("Failed to import {} as key: {}: {}", path.display(), key, e);
+                report.failed.push(ImportFailure { path: path.display().to_string(), error: e.to_string() });
+            }
+        }
+        if done % 1000 == 0 {
+            info!("Imported {} of {} files ({} bytes, {} failed)", done, total, report.bytes, report.failed.len());
+        }
+    }
+    info!("Imported {} files ({} bytes) into bucket: {}; {} existing, {} replaced, {} skipped, {} failed",
+          report.imported, report.bytes, bucket, report.existing, report.replaced, report.skipped.len(), report.failed.len());
+    Ok(report)
+}
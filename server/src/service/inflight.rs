@@ -0,0 +1,204 @@
+//! In-flight request registry
+//!
+//! The [`track`] middleware registers every request (method, path, start time and, once a
+//! handler calls [`logging::bind_user`](crate::logging::bind_user), the user) and removes it
+//! when the response is ready or the request is dropped. `GET /admin/inflight` lists the
+//! requests still running, oldest first. A request that takes longer than
+//! `SLOW_REQUEST_MS` (default 10000, 0 disables) is logged at warn with its context when it
+//! completes, and the watchdog started by [`start_watchdog`] warns once a minute about
+//! requests running longer than `INFLIGHT_HUNG_SECS` (default 300). Both are read at startup.
+//! The route template is resolved only when a request is listed or logged.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ResourceMap, ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::Error;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::time;
+
+use crate::logging;
+use crate::metrics;
+
+/// Slow-request threshold when `SLOW_REQUEST_MS` is unset
+pub const DEFAULT_SLOW_REQUEST: Duration = Duration::from_secs(10);
+
+/// Age at which the watchdog reports a request when `INFLIGHT_HUNG_SECS` is unset
+pub const DEFAULT_HUNG_AFTER: Duration = Duration::from_secs(300);
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref SLOW_REQUEST: Duration = env::var("SLOW_REQUEST_MS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_SLOW_REQUEST);
+    static ref HUNG_AFTER: Duration = env::var("INFLIGHT_HUNG_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|v| *v > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_HUNG_AFTER);
+    static ref INFLIGHT: Mutex<HashMap<u64, Entry>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    static CURRENT: u64;
+}
+
+struct Entry {
+    request_id: String,
+    method: Method,
+    path: String,
+    user: Option<String>,
+    started: Instant,
+    started_at: SystemTime,
+}
+
+/// A running request as listed by `GET /admin/inflight`
+#[derive(Debug, Clone, Serialize)]
+pub struct InflightRequest {
+    pub id: u64,
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    /// Route template the path matches, e.g. `/get/{key}`, or `unmatched`
+    pub template: String,
+    pub user: Option<String>,
+    pub started_at: String,
+    pub age_ms: u64,
+}
+
+impl Entry {
+    fn describe(&self, id: u64, routes: &ResourceMap) -> InflightRequest {
+        InflightRequest {
+            id,
+            request_id: self.request_id.clone(),
+            method: self.method.to_string(),
+            path: self.path.clone(),
+            template: metrics::route_template(routes.match_pattern(&self.path).as_deref()),
+            user: self.user.clone(),
+            started_at: DateTime::<Utc>::from(self.started_at).to_rfc3339(),
+            age_ms: self.started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+fn registry() -> std::sync::MutexGuard<'static, HashMap<u64, Entry>> {
+    INFLIGHT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Removes the entry however the request ends, cancellation included
+struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        registry().remove(&self.0);
+    }
+}
+
+/// Register the request for its lifetime; use with `middleware::from_fn`, inside
+/// [`logging::request_scope`] so the slow-request line carries the request id.
+pub async fn track(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let started = Instant::now();
+    registry().insert(id, Entry {
+        request_id: logging::request_id(req.request()),
+        method: req.method().clone(),
+        path: req.path().to_string(),
+        user: None,
+        started,
+        started_at: SystemTime::now(),
+    });
+    let registration = Registration(id);
+    // Routing needs the request unshared, so nothing here may hold on to it
+    let res = CURRENT.scope(id, next.call(req)).await;
+
+    let elapsed = started.elapsed();
+    if !SLOW_REQUEST.is_zero() && elapsed >= *SLOW_REQUEST {
+        if let Some(entry) = registry().get(&id) {
+            let (template, outcome) = match &res {
+                Ok(res) => (metrics::route_label(res.request()), res.status().as_u16().to_string()),
+                Err(e) => (metrics::UNMATCHED_ROUTE.to_string(), format!("error ({})", e)),
+            };
+            warn!(
+                "Slow request: {} {} (route {}) for user {} took {}ms -> {}",
+                entry.method,
+                entry.path,
+                template,
+                entry.user.as_deref().unwrap_or("-"),
+                elapsed.as_millis(),
+                outcome
+            );
+        }
+    }
+    drop(registration);
+    res
+}
+
+/// Record `user` against the current request; called from [`logging::bind_user`].
+pub(crate) fn bind_user(user: &str) {
+    if let Ok(id) = CURRENT.try_with(|id| *id) {
+        if let Some(entry) = registry().get_mut(&id) {
+            entry.user = Some(user.to_string());
+        }
+    }
+}
+
+/// Requests running for at least `older_than`, oldest first, not counting the caller's own;
+/// `routes` resolves their templates.
+pub fn list(older_than: Duration, routes: &ResourceMap) -> Vec<InflightRequest> {
+    let own = CURRENT.try_with(|id| *id).ok();
+    let mut requests: Vec<InflightRequest> = registry()
+        .iter()
+        .filter(|(id, entry)| Some(**id) != own && entry.started.elapsed() >= older_than)
+        .map(|(id, entry)| entry.describe(*id, routes))
+        .collect();
+    requests.sort_by(|a, b| b.age_ms.cmp(&a.age_ms).then(a.id.cmp(&b.id)));
+    requests
+}
+
+/// Warn about every request running longer than `INFLIGHT_HUNG_SECS`; returns how many.
+pub fn report_hung() -> usize {
+    let registry = registry();
+    let mut hung = 0;
+    for (id, entry) in registry.iter().filter(|(_, entry)| entry.started.elapsed() >= *HUNG_AFTER) {
+        warn!(
+            "Request {} ({}) has been running for {}s: {} {} for user {}",
+            id,
+            entry.request_id,
+            entry.started.elapsed().as_secs(),
+            entry.method,
+            entry.path,
+            entry.user.as_deref().unwrap_or("-")
+        );
+        hung += 1;
+    }
+    hung
+}
+
+/// Start the hung-request watchdog as a background task.
+pub fn start_watchdog() -> tokio::task::JoinHandle<()> {
+    info!("Starting in-flight request watchdog (hung after {}s)", HUNG_AFTER.as_secs());
+    tokio::spawn(async move {
+        let mut interval = time::interval(WATCHDOG_INTERVAL);
+        loop {
+            interval.tick().await;
+            report_hung();
+        }
+    })
+}
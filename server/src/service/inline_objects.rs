@@ -0,0 +1,174 @@
+//! Small objects stored in their metadata row
+//!
+//! An object written with less than `INLINE_THRESHOLD_BYTES` of data (default 2048, 0 turns
+//! inlining off) keeps its bytes in the row's `inline_data` column and has no chunks, so
+//! neither the write nor later reads touch the storage backend. The files of a native payload
+//! are stored one after another and read back as the same files.
+//!
+//! Appending to an inline object adds the new files to the row while the total stays under
+//! the threshold. Past it, each inline file is written to the bucket file as a chunk and the
+//! append continues as for any chunked object. An inline object owns nothing on disk, so
+//! deleting or replacing it queues nothing for deletion.
+//!
+//! Rows are not encrypted, so nothing is inlined while `service::bucket_keys` is in use.
+
+use actix_web::Error;
+use std::env;
+
+use crate::metadata::{DataChunk, FileAttrs, Metadata};
+use crate::service::bucket_keys;
+use crate::service::native_payload::{self, NativeFile, NativePayloadConfig};
+use crate::service::storage_service::{StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineConfig {
+    /// Objects with fewer bytes of data are stored inline; 0 disables
+    pub threshold: u64,
+}
+
+impl Default for InlineConfig {
+    fn default() -> Self {
+        Self { threshold: 2048 }
+    }
+}
+
+impl InlineConfig {
+    pub fn from_env() -> Self {
+        if bucket_keys::in_use() {
+            return Self { threshold: 0 };
+        }
+        let threshold = env::var("INLINE_THRESHOLD_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(Self::default().threshold);
+        Self { threshold }
+    }
+
+    /// Whether `len` bytes of data are stored inline
+    pub fn fits(&self, len: u64) -> bool {
+        len < self.threshold
+    }
+}
+
+/// Files of a native payload with a data vector; the others are skipped, as on write
+fn data_files(body: &[u8]) -> Result<Vec<NativeFile<'_>>, Error> {
+    if body.is_empty() {
+        return Ok(Vec::new());
+    }
+    let files = native_payload::parse(body, &NativePayloadConfig::from_env())?;
+    Ok(files.into_iter().filter(|f| f.data.is_some()).collect())
+}
+
+fn data_len(files: &[NativeFile]) -> u64 {
+    files.iter().filter_map(|f| f.data).map(|data| data.len() as u64).sum()
+}
+
+/// Metadata of a native payload to store inline, with its files' attributes; `None` when its
+/// data reaches the threshold or it has no files.
+pub fn native_object(body: &[u8], config: &InlineConfig) -> Result<Option<(Metadata, Vec<FileAttrs>)>, Error> {
+    if config.threshold == 0 {
+        return Ok(None);
+    }
+    let files = data_files(body)?;
+    if files.is_empty() || !config.fits(data_len(&files)) {
+        return Ok(None);
+    }
+    let data: Vec<&[u8]> = files.iter().filter_map(|f| f.data).collect();
+    Ok(Some((Metadata::inline_files_of(&data), files.iter().map(NativeFile::attrs).collect())))
+}
+
+/// Files added to an inline object by one append
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InlineAppend {
+    /// Index of the first file added
+    pub first_file: usize,
+    pub files: usize,
+    pub size: u64,
+}
+
+/// Add the files of a native append to `metadata`'s inline data. `None` when the object is
+/// not inline or would reach the threshold; [`spill`] it and append chunks instead.
+pub fn append_native(metadata: &mut Metadata, body: &[u8], config: &InlineConfig) -> Result<Option<InlineAppend>, Error> {
+    if !metadata.is_inline() {
+        return Ok(None);
+    }
+    let files = data_files(body)?;
+    let size = data_len(&files);
+    if !config.fits(metadata.size + size) {
+        return Ok(None);
+    }
+    let mut lengths: Vec<usize> = metadata.inline_files().iter().map(|f| f.len()).collect();
+    let mut attrs = metadata.file_attrs();
+    let appended = InlineAppend { first_file: lengths.len(), files: files.len(), size };
+    let inline = metadata.inline_data.get_or_insert_with(Vec::new);
+    for file in &files {
+        let data = file.data.unwrap_or_default();
+        inline.extend_from_slice(data);
+        lengths.push(data.len());
+        attrs.push(file.attrs());
+    }
+    metadata.set_inline_lengths(lengths);
+    metadata.set_file_attrs(&attrs);
+    metadata.size += size;
+    Ok(Some(appended))
+}
+
+/// Turn an inline object into a chunked one by writing each of its files to the bucket file
+/// as a chunk; no-op for other objects. Metadata is not saved here.
+pub fn spill(storage: &StorageService, context: &UserContext, metadata: &mut Metadata) -> Result<(), Error> {
+    if !metadata.is_inline() {
+        return Ok(());
+    }
+    let mut chunks = Vec::new();
+    for file in metadata.inline_files() {
+        chunks.extend(storage.write_object(context, file, StorageMode::S3)?.into_iter().map(DataChunk::from_extent));
+    }
+    metadata.chunks = chunks;
+    metadata.inline_data = None;
+    metadata.set_inline_lengths(Vec::new());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::flatbuffer_store_generated::store::{FileData, FileDataArgs, FileDataList, FileDataListArgs};
+    use flatbuffers::FlatBufferBuilder;
+
+    fn payload(files: &[&[u8]]) -> Vec<u8> {
+        let mut builder = FlatBufferBuilder::new();
+        let files: Vec<_> = files.iter().map(|data| {
+            let data = builder.create_vector(data);
+            FileData::create(&mut builder, &FileDataArgs { data: Some(data) })
+        }).collect();
+        let files = builder.create_vector(&files);
+        let list = FileDataList::create(&mut builder, &FileDataListArgs { files: Some(files) });
+        builder.finish(list, None);
+        builder.finished_data().to_vec()
+    }
+
+    #[test]
+    fn test_only_small_payloads_are_inlined() {
+        let config = InlineConfig { threshold: 8 };
+        let (metadata, attrs) = native_object(&payload(&[b"sm", b"all"]), &config).unwrap().unwrap();
+        assert_eq!(metadata.inline_files(), [&b"sm"[..], b"all"]);
+        assert_eq!((metadata.size, attrs.len()), (5, 2));
+        assert!(native_object(&payload(&[b"too large"]), &config).unwrap().is_none());
+        assert!(native_object(b"", &config).unwrap().is_none());
+        assert!(native_object(&payload(&[b"small"]), &InlineConfig { threshold: 0 }).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_append_adds_files_until_the_threshold() {
+        let config = InlineConfig { threshold: 8 };
+        let mut metadata = Metadata::inline(b"abc".to_vec());
+        let appended = append_native(&mut metadata, &payload(&[b"de", b"f"]), &config).unwrap();
+        assert_eq!(appended, Some(InlineAppend { first_file: 1, files: 2, size: 3 }));
+        assert_eq!(metadata.inline_files(), [&b"abc"[..], b"de", b"f"]);
+        assert_eq!((metadata.size, metadata.file_attrs().len()), (6, 3));
+        assert_eq!(append_native(&mut metadata, &payload(&[b"gh"]), &config).unwrap(), None);
+        assert_eq!(metadata.size, 6);
+        assert_eq!(append_native(&mut Metadata::from_chunks(Vec::new()), &payload(&[b"g"]), &config).unwrap(), None);
+    }
+}
@@ -0,0 +1,173 @@
+//! Deployment identity shared by the metadata database and its storage roots
+//!
+//! The first start writes a random `instance_id` into the `meta_info` table and into a
+//! [`INSTANCE_FILE`] in every storage root; each later start ([`verify`]) checks that they
+//! still agree, so a database restored from another deployment (or storage mounted from one)
+//! is refused before anything is read or written through it. A root without the file is
+//! given the database's id only while it is empty, and a database without one only while no
+//! root has one and, if it holds no objects, every root is empty: a new database pointed at
+//! storage that already holds data is refused. A deployment from before the check gets its
+//! id on its first start.
+//!
+//! `warp_drive serve --force-adopt` overrides a refusal: the database's id (or else a root's)
+//! is written everywhere and the adoption is recorded in the change log as `instance`. A
+//! replica writes nothing; it refuses only when both sides have ids and they differ.
+
+use log::{info, warn};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::service::change_log::STARTUP_ACTOR;
+use crate::storage::config::StorageConfig;
+
+/// Name of the id in the `meta_info` table
+pub const INSTANCE_ID: &str = "instance_id";
+
+/// File holding the id in each storage root
+pub const INSTANCE_FILE: &str = ".warpdrive_instance";
+
+fn new_instance_id() -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("cannot generate an instance id: {}", e))?;
+    Ok(hex::encode(bytes))
+}
+
+fn read_root_id(root: &Path) -> Result<Option<String>, String> {
+    let path = root.join(INSTANCE_FILE);
+    match fs::read_to_string(&path) {
+        Ok(id) => Ok(Some(id.trim().to_string())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("cannot read {}: {}", path.display(), e)),
+    }
+}
+
+fn write_root_id(root: &Path, id: &str) -> Result<(), String> {
+    let path = root.join(INSTANCE_FILE);
+    fs::write(&path, format!("{}\n", id)).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+}
+
+/// Whether `root` holds nothing but (possibly) its instance file
+fn is_empty_root(root: &Path) -> Result<bool, String> {
+    let entries = fs::read_dir(root).map_err(|e| format!("cannot read storage directory {}: {}", root.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("cannot read storage directory {}: {}", root.display(), e))?;
+        if entry.file_name() != INSTANCE_FILE {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn refusal(db_path: &Path, detail: String) -> String {
+    format!(
+        "{}; the metadata database {} and the storage directories belong to different deployments. \
+         Point DB_FILE and STORAGE_DIRECTORY at the same deployment, or start once with \
+         `warp_drive serve --force-adopt` to make this database's id the deployment's",
+        detail, db_path.display(),
+    )
+}
+
+/// Check that the database at `db_path` and the storage roots carry the same instance id,
+/// assigning one where that is safe; with `force_adopt` make them agree instead of refusing.
+pub fn verify(db_path: &Path, force_adopt: bool) -> Result<(), String> {
+    let store = SQLiteMetadataStore::new();
+    let db_id = store.get_meta_info(INSTANCE_ID).map_err(|e| e.to_string())?;
+    let roots: Vec<(PathBuf, Option<String>)> = StorageConfig::from_env().root_directories().into_iter()
+        .map(|root| read_root_id(&root).map(|id| (root, id)))
+        .collect::<Result<_, _>>()?;
+
+    if config::is_replica() {
+        if let Some(db_id) = &db_id {
+            if let Some((root, root_id)) = roots.iter().find(|(_, id)| id.as_ref().is_some_and(|id| id != db_id)) {
+                return Err(refusal(db_path, format!(
+                    "instance id {} in the database does not match {} in {}",
+                    db_id, root_id.as_deref().unwrap_or_default(), root.join(INSTANCE_FILE).display(),
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    if force_adopt {
+        return adopt(&store, db_id, &roots);
+    }
+
+    match &db_id {
+        Some(db_id) => {
+            for (root, root_id) in &roots {
+                match root_id {
+                    Some(root_id) if root_id == db_id => {}
+                    Some(root_id) => return Err(refusal(db_path, format!(
+                        "instance id {} in the database does not match {} in {}",
+                        db_id, root_id, root.join(INSTANCE_FILE).display(),
+                    ))),
+                    None if is_empty_root(root)? => {
+                        write_root_id(root, db_id)?;
+                        info!("Storage directory {} joined instance {}", root.display(), db_id);
+                    }
+                    None => return Err(refusal(db_path, format!(
+                        "storage directory {} holds data but no {} while the database has instance id {}",
+                        root.display(), INSTANCE_FILE, db_id,
+                    ))),
+                }
+            }
+        }
+        None => {
+            if let Some((root, root_id)) = roots.iter().find(|(_, id)| id.is_some()) {
+                return Err(refusal(db_path, format!(
+                    "the database has no instance id but {} has {}",
+                    root.join(INSTANCE_FILE).display(), root_id.as_deref().unwrap_or_default(),
+                )));
+            }
+            if !store.has_objects().map_err(|e| e.to_string())? {
+                for (root, _) in &roots {
+                    if !is_empty_root(root)? {
+                        return Err(refusal(db_path, format!(
+                            "the database has no objects and no instance id but storage directory {} holds data",
+                            root.display(),
+                        )));
+                    }
+                }
+            }
+            let id = new_instance_id()?;
+            for (root, _) in &roots {
+                write_root_id(root, &id)?;
+            }
+            store.set_meta_info(INSTANCE_ID, &id).map_err(|e| e.to_string())?;
+            info!("Assigned instance id {}", id);
+        }
+    }
+    Ok(())
+}
+
+/// Write one id to the database and every root, recording what each had before.
+fn adopt(store: &SQLiteMetadataStore, db_id: Option<String>, roots: &[(PathBuf, Option<String>)]) -> Result<(), String> {
+    let id = match db_id.clone().or_else(|| roots.iter().find_map(|(_, id)| id.clone())) {
+        Some(id) => id,
+        None => new_instance_id()?,
+    };
+    if db_id.as_ref() == Some(&id) && roots.iter().all(|(_, root_id)| root_id.as_ref() == Some(&id)) {
+        return Ok(());
+    }
+
+    for (root, root_id) in roots {
+        if root_id.as_ref() != Some(&id) {
+            write_root_id(root, &id)?;
+        }
+    }
+    store.set_meta_info(INSTANCE_ID, &id).map_err(|e| e.to_string())?;
+
+    let root_ids: Map<String, Value> = roots.iter()
+        .map(|(root, root_id)| (root.display().to_string(), json!(root_id)))
+        .collect();
+    let before = json!({ "database": db_id, "storage": root_ids });
+    let after = json!({ "instance_id": id });
+    store.insert_change(STARTUP_ACTOR, "instance", "storage", Some(&before), Some(&after))
+        .map_err(|e| e.to_string())?;
+    warn!("Adopted instance id {} for the database and every storage directory (--force-adopt)", id);
+    Ok(())
+}
@@ -0,0 +1,357 @@
+//! Object inventories for analytics (`POST /admin/inventory/{user}/{bucket}`)
+//!
+//! An inventory lists every live object of a bucket with its key, size, chunk count, creation
+//! and last-modified times, ETag and tags, as CSV (with a header row) or NDJSON. It runs as a
+//! job and is written a metadata page at a time, so memory stays bounded whatever the size of
+//! the bucket. The output goes to a file under `INVENTORY_DIRECTORY` (default `inventory`),
+//! written as `.partial` and renamed once complete, or becomes an object of the user's bucket
+//! `target_bucket`, stored in chunks of [`OBJECT_CHUNK_BYTES`]. The job's result is the
+//! [`InventoryReport`].
+//!
+//! `created_at` is empty for objects written before creation times were recorded. Tags are a
+//! JSON object, in CSV as one quoted field.
+
+use actix_web::Error;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound};
+use log::info;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::metadata::{last_modified_now, Metadata, ObjectRecord};
+use crate::service::jobs::JobHandle;
+use crate::service::metadata_service::MetadataService;
+use crate::service::storage_service::{StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+
+/// Metadata rows read per page
+const PAGE_ROWS: usize = 500;
+
+/// Size of each chunk of an inventory written into a bucket
+pub const OBJECT_CHUNK_BYTES: usize = 8 * 1024 * 1024;
+
+/// Columns of a CSV inventory, in order
+pub const CSV_COLUMNS: [&str; 7] = ["key", "size", "chunks", "created_at", "updated_at", "etag", "tags"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InventoryFormat {
+    Csv,
+    Ndjson,
+}
+
+impl InventoryFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InventoryFormat::Csv => "csv",
+            InventoryFormat::Ndjson => "ndjson",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            InventoryFormat::Csv => "text/csv",
+            InventoryFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+impl std::str::FromStr for InventoryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(InventoryFormat::Csv),
+            "ndjson" => Ok(InventoryFormat::Ndjson),
+            _ => Err(format!("Unknown inventory format: {} (expected csv or ndjson)", s)),
+        }
+    }
+}
+
+/// Where inventory files are written
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryConfig {
+    pub directory: PathBuf,
+}
+
+impl Default for InventoryConfig {
+    fn default() -> Self {
+        Self { directory: PathBuf::from("inventory") }
+    }
+}
+
+impl InventoryConfig {
+    pub fn from_env() -> Self {
+        match env::var("INVENTORY_DIRECTORY") {
+            Ok(dir) if !dir.trim().is_empty() => Self { directory: PathBuf::from(dir.trim()) },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// Where one inventory goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum InventoryTarget {
+    /// A file in [`InventoryConfig::directory`]
+    Directory,
+    /// An object `key` in the user's `bucket`
+    Object { bucket: String, key: String },
+}
+
+/// Outcome of [`run_inventory`]
+#[derive(Debug, Clone, Serialize)]
+pub struct InventoryReport {
+    pub user: String,
+    pub bucket: String,
+    pub format: InventoryFormat,
+    /// Objects listed
+    pub objects: u64,
+    /// Sum of their sizes
+    pub bytes: u64,
+    /// Path of the file, or `bucket/key` of the object
+    pub location: String,
+    /// Size of the inventory itself
+    pub inventory_bytes: u64,
+}
+
+/// `value` as one CSV field: quoted, with quotes doubled, when it holds a comma, quote or
+/// line break
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn tags_json(tags: &[(String, String)]) -> Value {
+    Value::Object(tags.iter().map(|(k, v)| (k.clone(), Value::String(v.clone()))).collect::<Map<_, _>>())
+}
+
+/// One inventory line of `record`, newline included
+fn line(format: InventoryFormat, record: &ObjectRecord, tags: &[(String, String)]) -> String {
+    let metadata = &record.metadata;
+    match format {
+        InventoryFormat::Csv => {
+            let tags = if tags.is_empty() { String::new() } else { tags_json(tags).to_string() };
+            let fields = [
+                record.key.clone(),
+                metadata.size.to_string(),
+                metadata.chunks.len().to_string(),
+                record.created_at.clone().unwrap_or_default(),
+                metadata.last_modified.clone().unwrap_or_default(),
+                metadata.etag.clone().unwrap_or_default(),
+                tags,
+            ];
+            let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+            format!("{}\n", fields.join(","))
+        }
+        InventoryFormat::Ndjson => format!("{}\n", json!({
+            "key": record.key,
+            "size": metadata.size,
+            "chunks": metadata.chunks.len(),
+            "created_at": record.created_at,
+            "updated_at": metadata.last_modified,
+            "etag": metadata.etag,
+            "tags": tags_json(tags),
+        })),
+    }
+}
+
+/// Write the inventory of `user`/`bucket` to `out`; returns the objects listed and the sum of
+/// their sizes.
+pub fn write_inventory<W: Write>(user: &str, bucket: &str, format: InventoryFormat, out: &mut W, job: &JobHandle) -> Result<(u64, u64), Error> {
+    let db = MetadataService::new(user)?;
+    let (total, _) = db.bucket_object_stats(bucket)?;
+    if format == InventoryFormat::Csv {
+        writeln!(out, "{}", CSV_COLUMNS.join(",")).map_err(ErrorInternalServerError)?;
+    }
+    let (mut objects, mut bytes) = (0u64, 0u64);
+    let mut after = 0i64;
+    loop {
+        job.checkpoint()?;
+        let page = db.full_records_page(bucket, after, PAGE_ROWS)?;
+        let Some(last) = page.last().map(|r| r.row_id) else { break };
+        let live: Vec<&ObjectRecord> = page.iter().filter(|r| r.is_latest && !r.metadata.is_delete_marker).collect();
+        let keys: Vec<&str> = live.iter().map(|r| r.key.as_str()).collect();
+        let tags = db.get_tags_for_keys(bucket, &keys)?;
+        for record in live {
+            let tags = tags.get(&record.key).map(Vec::as_slice).unwrap_or_default();
+            out.write_all(line(format, record, tags).as_bytes()).map_err(ErrorInternalServerError)?;
+            objects += 1;
+            bytes += record.metadata.size;
+        }
+        job.progress(objects, total);
+        if page.len() < PAGE_ROWS {
+            break;
+        }
+        after = last;
+    }
+    out.flush().map_err(ErrorInternalServerError)?;
+    Ok((objects, bytes))
+}
+
+/// `s` with everything but ASCII letters, digits, `.`, `-` and `_` replaced, for file names
+fn file_component(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' }).collect()
+}
+
+/// Default name of an inventory: `{user}-{bucket}-{time}.{format}`
+pub fn default_name(user: &str, bucket: &str, format: InventoryFormat) -> String {
+    let time = chrono::Utc::now().format("%Y%m%dT%H%M%S%3fZ");
+    format!("{}-{}-{}.{}", file_component(user), file_component(bucket), time, format.as_str())
+}
+
+/// Writer storing what it is given as the chunks of a new object, one
+/// [`OBJECT_CHUNK_BYTES`] chunk at a time
+struct ObjectWriter {
+    storage: StorageService,
+    context: UserContext,
+    buffer: Vec<u8>,
+    extents: Vec<(u64, u64)>,
+    digest: md5::Context,
+}
+
+impl ObjectWriter {
+    fn new(context: UserContext) -> Self {
+        Self {
+            storage: StorageService::new(),
+            context,
+            buffer: Vec::with_capacity(OBJECT_CHUNK_BYTES),
+            extents: Vec::new(),
+            digest: md5::Context::new(),
+        }
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let extents = self.storage.write_object(&self.context, &self.buffer, StorageMode::RAW)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.extents.extend(extents);
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Store the last chunk and the object's row as `key`
+    fn finish(mut self, key: &str, format: InventoryFormat) -> Result<u64, Error> {
+        self.write_chunk().map_err(ErrorInternalServerError)?;
+        let db = MetadataService::new(&self.context.user_id)?;
+        let size = self.extents.iter().map(|(_, size)| size).sum();
+        let mut metadata = Metadata::from_offset_size_list(self.extents.clone());
+        metadata.etag = Some(format!("\"{:x}\"", self.digest.clone().compute()));
+        metadata.size = size;
+        metadata.content_type = Some(format.content_type().to_string());
+        metadata.last_modified = Some(last_modified_now());
+        let written = db.put_object_full(&self.context.bucket, key, metadata);
+        let (_, replaced) = match written {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                self.abandon();
+                return Err(e);
+            }
+        };
+        db.queue_deletion(&self.context.bucket, key, &replaced)?;
+        Ok(size)
+    }
+
+    /// Queue the chunks written so far for deletion
+    fn abandon(&self) {
+        if let Ok(db) = MetadataService::new(&self.context.user_id) {
+            let _ = db.queue_deletion(&self.context.bucket, "", &self.extents);
+        }
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let take = data.len().min(OBJECT_CHUNK_BYTES - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..take]);
+        self.digest.consume(&data[..take]);
+        if self.buffer.len() == OBJECT_CHUNK_BYTES {
+            self.write_chunk()?;
+        }
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Inventory `user`/`bucket` into `target`.
+pub async fn run_inventory(user: &str, bucket: &str, format: InventoryFormat, target: InventoryTarget, job: &JobHandle) -> Result<InventoryReport, Error> {
+    let db = MetadataService::new(user)?;
+    if !db.bucket_exists(bucket)? {
+        return Err(ErrorNotFound(format!("No bucket: {} for user: {}", bucket, user)));
+    }
+    let mut report = InventoryReport {
+        user: user.to_string(),
+        bucket: bucket.to_string(),
+        format,
+        objects: 0,
+        bytes: 0,
+        location: String::new(),
+        inventory_bytes: 0,
+    };
+    match target {
+        InventoryTarget::Directory => {
+            let dir = InventoryConfig::from_env().directory;
+            fs::create_dir_all(&dir)
+                .map_err(|e| ErrorInternalServerError(format!("cannot create inventory directory {}: {}", dir.display(), e)))?;
+            let path = dir.join(default_name(user, bucket, format));
+            let partial = path.with_extension(format!("{}.partial", format.as_str()));
+            let file = File::create(&partial)
+                .map_err(|e| ErrorInternalServerError(format!("cannot create {}: {}", partial.display(), e)))?;
+            let mut out = BufWriter::new(file);
+            let written = write_inventory(user, bucket, format, &mut out, job)
+                .and_then(|counts| out.into_inner().map_err(|e| ErrorInternalServerError(e.to_string())).map(|_| counts))
+                .and_then(|counts| fs::rename(&partial, &path).map_err(ErrorInternalServerError).map(|_| counts));
+            let (objects, bytes) = written.inspect_err(|_| { let _ = fs::remove_file(&partial); })?;
+            report.objects = objects;
+            report.bytes = bytes;
+            report.inventory_bytes = fs::metadata(&path).map(|m| m.len()).unwrap_or_default();
+            report.location = path.display().to_string();
+        }
+        InventoryTarget::Object { bucket: target_bucket, key } => {
+            if !db.bucket_exists(&target_bucket)? {
+                return Err(ErrorBadRequest(format!("No target bucket: {} for user: {}", target_bucket, user)));
+            }
+            let mut out = ObjectWriter::new(UserContext::with_bucket(user.to_string(), target_bucket.clone()));
+            let (objects, bytes) = write_inventory(user, bucket, format, &mut out, job).inspect_err(|_| out.abandon())?;
+            report.objects = objects;
+            report.bytes = bytes;
+            report.inventory_bytes = out.finish(&key, format)?;
+            report.location = format!("{}/{}", target_bucket, key);
+        }
+    }
+    info!("Inventory of user {} bucket {}: {} objects ({} bytes) written to {}",
+          user, bucket, report.objects, report.bytes, report.location);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("plain/key.txt"), "plain/key.txt");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn test_format_and_names() {
+        assert_eq!("CSV".parse::<InventoryFormat>(), Ok(InventoryFormat::Csv));
+        assert!("parquet".parse::<InventoryFormat>().is_err());
+        let name = default_name("user/1", "my-bucket", InventoryFormat::Ndjson);
+        assert!(name.starts_with("user_1-my-bucket-") && name.ends_with(".ndjson"), "{}", name);
+    }
+}
@@ -0,0 +1,235 @@
+//! Background jobs for long-running admin operations
+//!
+//! `POST /admin/gc`, `/admin/compact`, `/admin/tier` and `/admin/inventory` enqueue a job and
+//! answer 202 with it instead of holding the request open for the whole run. Jobs are stored
+//! in the `jobs` table with their state (queued, running, done, failed or cancelled), the
+//! fraction done, and the operation's report or error; `GET /admin/jobs[/{id}]` reads them
+//! back. At most one job runs per user/bucket at a time, the others wait their turn in order.
+//!
+//! The work is given a [`JobHandle`]. `progress` is stored at most every
+//! [`PROGRESS_INTERVAL`], not per item, and `checkpoint` fails once
+//! `POST /admin/jobs/{id}/cancel` was called, so a cancelled job stops at a point where the
+//! bucket is consistent. Jobs a previous process left queued or running are marked failed
+//! when the server starts.
+
+use actix_web::Error;
+use actix_web::error::{ErrorConflict, ErrorInternalServerError};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::metadata::sqlite_store::{JobRecord, SQLiteMetadataStore};
+
+/// Least time between two progress writes of one job
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Jobs listed when `GET /admin/jobs` has no `limit`
+pub const DEFAULT_LIST_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+}
+
+impl std::str::FromStr for JobState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(JobState::Queued),
+            "running" => Ok(JobState::Running),
+            "done" => Ok(JobState::Done),
+            "failed" => Ok(JobState::Failed),
+            "cancelled" => Ok(JobState::Cancelled),
+            _ => Err(format!("Unknown job state: {} (expected queued, running, done, failed or cancelled)", s)),
+        }
+    }
+}
+
+/// (user, bucket) -> turn; held by the job running on that bucket
+type Turns = HashMap<(String, String), Arc<tokio::sync::Mutex<()>>>;
+
+lazy_static! {
+    // Cancellation flags of the jobs this process has queued or is running
+    static ref ACTIVE: Mutex<HashMap<i64, Arc<AtomicBool>>> = Mutex::new(HashMap::new());
+    static ref TURNS: Mutex<Turns> = Mutex::new(HashMap::new());
+}
+
+fn turn(user: &str, bucket: &str) -> Arc<tokio::sync::Mutex<()>> {
+    TURNS.lock().unwrap_or_else(|e| e.into_inner())
+        .entry((user.to_string(), bucket.to_string()))
+        .or_default()
+        .clone()
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339()
+}
+
+/// Progress reporting and cancellation for the work of one job
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    id: Option<i64>,
+    cancelled: Arc<AtomicBool>,
+    // Latest fraction and when it was last stored
+    progress: Arc<Mutex<(f64, Option<Instant>)>>,
+}
+
+impl JobHandle {
+    fn new(id: Option<i64>, cancelled: Arc<AtomicBool>) -> Self {
+        Self { id, cancelled, progress: Arc::new(Mutex::new((0.0, None))) }
+    }
+
+    /// Handle for work run outside a job (the CLI): never cancelled, progress not stored
+    pub fn detached() -> Self {
+        Self::new(None, Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Record that `done` of `total` items are finished
+    pub fn progress(&self, done: u64, total: u64) {
+        let fraction = if total == 0 { 1.0 } else { (done as f64 / total as f64).min(1.0) };
+        let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        progress.0 = fraction;
+        let Some(id) = self.id else { return };
+        if progress.1.is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        progress.1 = Some(Instant::now());
+        if let Err(e) = SQLiteMetadataStore::new().set_job_progress(id, fraction) {
+            warn!("Failed to store progress of job {}: {}", id, e);
+        }
+    }
+
+    fn fraction(&self) -> f64 {
+        self.progress.lock().unwrap_or_else(|e| e.into_inner()).0
+    }
+
+    /// Safe point: fails once the job has been cancelled
+    pub fn checkpoint(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(ErrorConflict(format!("Job {} was cancelled", self.id.unwrap_or_default())));
+        }
+        Ok(())
+    }
+}
+
+/// Store a job of `kind` on `user`/`bucket` and run `work` once no other job holds the
+/// bucket. Returns the queued job; its result is the serialized output of `work`.
+pub fn enqueue<F, Fut, T>(kind: &str, user: &str, bucket: &str, params: Value, work: F) -> Result<JobRecord, Error>
+where
+    F: FnOnce(JobHandle) -> Fut + 'static,
+    Fut: Future<Output = Result<T, Error>> + 'static,
+    T: Serialize,
+{
+    let store = SQLiteMetadataStore::new();
+    let id = store.insert_job(kind, user, bucket, &params, JobState::Queued.as_str(), &now())?;
+    let job = store.get_job(id)?.ok_or_else(|| ErrorInternalServerError(format!("Job {} vanished", id)))?;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).insert(id, cancelled.clone());
+    info!("Queued job {} ({}) for user {} bucket {}", id, kind, user, bucket);
+
+    let handle = JobHandle::new(Some(id), cancelled);
+    let turn = turn(user, bucket);
+    actix_web::rt::spawn(async move {
+        let _turn = turn.lock_owned().await;
+        let outcome = if handle.is_cancelled() {
+            None
+        } else {
+            if let Err(e) = SQLiteMetadataStore::new().start_job(id, JobState::Running.as_str(), &now()) {
+                warn!("Failed to mark job {} running: {}", id, e);
+            }
+            let result = work(handle.clone()).await;
+            Some(result.and_then(|output| serde_json::to_value(output).map_err(ErrorInternalServerError)))
+        };
+        let (state, result, message) = match outcome {
+            Some(Ok(result)) => (JobState::Done, Some(result), None),
+            _ if handle.is_cancelled() => (JobState::Cancelled, None, None),
+            Some(Err(e)) => (JobState::Failed, None, Some(e.to_string())),
+            None => unreachable!("only cancelled jobs are skipped"),
+        };
+        let progress = if state == JobState::Done { 1.0 } else { handle.fraction() };
+        match &message {
+            Some(message) => warn!("Job {} failed: {}", id, message),
+            None => info!("Job {} {}", id, state.as_str()),
+        }
+        if let Err(e) = SQLiteMetadataStore::new().finish_job(id, state.as_str(), progress, result.as_ref(), message.as_deref(), &now()) {
+            error!("Failed to record the end of job {}: {}", id, e);
+        }
+        ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    });
+    Ok(job)
+}
+
+pub fn get(id: i64) -> Result<Option<JobRecord>, Error> {
+    SQLiteMetadataStore::new().get_job(id)
+}
+
+/// Newest first, optionally only those in `state`
+pub fn list(state: Option<JobState>, limit: usize) -> Result<Vec<JobRecord>, Error> {
+    SQLiteMetadataStore::new().list_jobs(state.map(|s| s.as_str()), limit)
+}
+
+/// Ask an unfinished job to stop. A queued job never starts; a running one stops at its next
+/// checkpoint. Fails with 409 when the job has already finished.
+pub fn cancel(id: i64) -> Result<Option<JobRecord>, Error> {
+    let store = SQLiteMetadataStore::new();
+    let Some(job) = store.get_job(id)? else { return Ok(None) };
+    if job.state.parse::<JobState>().map_or(true, |s| s.is_finished()) {
+        return Err(ErrorConflict(format!("Job {} already finished ({})", id, job.state)));
+    }
+    let flag = ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).get(&id).cloned();
+    match flag {
+        Some(flag) => flag.store(true, Ordering::SeqCst),
+        // Not run by this process, so nothing will pick it up
+        None => store.finish_job(id, JobState::Cancelled.as_str(), job.progress, None, None, &now())?,
+    }
+    info!("Cancellation requested for job {}", id);
+    store.get_job(id)
+}
+
+/// Mark the jobs a previous process left unfinished as failed. Call once at server start.
+pub fn recover() -> Result<usize, Error> {
+    let unfinished = [JobState::Queued.as_str(), JobState::Running.as_str()];
+    let abandoned = SQLiteMetadataStore::new()
+        .abandon_jobs(&unfinished, JobState::Failed.as_str(), "Interrupted by a server restart", &now())?;
+    if abandoned > 0 {
+        warn!("{} admin jobs were interrupted by a restart and marked failed", abandoned);
+    }
+    Ok(abandoned)
+}
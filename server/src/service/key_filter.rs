@@ -0,0 +1,288 @@
+//! Approximate key index for the duplicate-key check of native PUT
+//!
+//! Every native PUT asks SQLite whether its key exists, and under heavy ingest that read
+//! competes with the inserts on the same table. With `KEY_FILTER_ENABLED=true` each
+//! (user, bucket) gets a bloom filter of its live keys, so a PUT of a key the filter has
+//! never seen skips the lookup. A "maybe present" answer still goes to SQLite.
+//!
+//! A bucket's filter is built in the background on its first check by paging through its
+//! keys; until it is ready every check goes to SQLite. `MetadataService` records each key it
+//! writes, and keys written while the filter loads are kept aside and added once it is built.
+//! Deleted keys cannot be removed from a bloom filter and stay "maybe present". A filter is
+//! sized for `KEY_FILTER_CAPACITY` keys (default 1,000,000) or twice the bucket's keys at
+//! load, whichever is more, at a false positive rate of `KEY_FILTER_FP_RATE` (default 0.01);
+//! once more keys than that have gone in it is dropped and rebuilt larger.
+//!
+//! Only the PUT check uses the filter: a key written by another process sharing the database
+//! is unknown to it, and the insert then fails on the key constraint, which is a 409
+//! `KeyExists` as for any lost race. Replicas never use it. Checks answered by the filter are
+//! counted in `warpdrive_key_filter_absent_total`, lookups that reached SQLite in
+//! `warpdrive_metadata_key_checks_total`. Settings are read at startup.
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metrics;
+
+/// Keys a filter is sized for when `KEY_FILTER_CAPACITY` is unset
+pub const DEFAULT_CAPACITY: u64 = 1_000_000;
+
+/// False positive rate when `KEY_FILTER_FP_RATE` is unset
+pub const DEFAULT_FP_RATE: f64 = 0.01;
+
+/// Keys read per query while building a filter
+const LOAD_PAGE: usize = 10_000;
+
+/// Settings of the key filters
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyFilterConfig {
+    pub enabled: bool,
+    /// Fewest keys a filter is sized for
+    pub capacity: u64,
+    /// Target false positive rate, in (0, 1)
+    pub fp_rate: f64,
+}
+
+impl Default for KeyFilterConfig {
+    fn default() -> Self {
+        Self { enabled: false, capacity: DEFAULT_CAPACITY, fp_rate: DEFAULT_FP_RATE }
+    }
+}
+
+impl KeyFilterConfig {
+    pub fn from_env() -> Self {
+        let enabled = env::var("KEY_FILTER_ENABLED")
+            .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+        let capacity = env::var("KEY_FILTER_CAPACITY")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_CAPACITY);
+        let fp_rate = env::var("KEY_FILTER_FP_RATE")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v: &f64| *v > 0.0 && *v < 1.0)
+            .unwrap_or(DEFAULT_FP_RATE);
+        Self { enabled, capacity, fp_rate }
+    }
+}
+
+/// Bloom filter over strings with double hashing.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Filter holding `capacity` keys at a false positive rate of about `fp_rate`.
+    pub fn new(capacity: u64, fp_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity.max(1) as f64) * fp_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hashes = ((bits as f64 / capacity.max(1) as f64) * ln2).round().clamp(1.0, 16.0) as u32;
+        Self { bits: vec![0; bits.div_ceil(64) as usize], hashes }
+    }
+
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h1 = hasher.finish();
+        let h2 = h1.rotate_left(32) | 1;
+        let bits = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        for bit in self.positions(key).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// False only for a key that was never inserted.
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.positions(key).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+enum Entry {
+    /// Being built by load `id`; keys written meanwhile wait in `pending`
+    Loading { id: u64, pending: Vec<String> },
+    Ready { filter: BloomFilter, inserted: u64, capacity: u64 },
+}
+
+lazy_static! {
+    static ref CONFIG: KeyFilterConfig = KeyFilterConfig::from_env();
+    static ref FILTERS: Mutex<HashMap<(String, String), Entry>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_LOAD: AtomicU64 = AtomicU64::new(1);
+
+fn filters() -> MutexGuard<'static, HashMap<(String, String), Entry>> {
+    FILTERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn bucket_key(user_id: &str, bucket: &str) -> (String, String) {
+    (user_id.to_string(), bucket.to_string())
+}
+
+/// Whether PUT checks consult the filters
+pub fn enabled() -> bool {
+    CONFIG.enabled && !crate::config::is_replica()
+}
+
+/// True when `key` is certainly not a live key of `bucket`. False means "ask SQLite": the
+/// key may exist, the filter is still loading, or filters are disabled. The first call for
+/// a bucket starts building its filter.
+pub fn definitely_absent(user_id: &str, bucket: &str, key: &str) -> bool {
+    if !enabled() {
+        return false;
+    }
+    let mut filters = filters();
+    let absent = match filters.get(&bucket_key(user_id, bucket)) {
+        Some(Entry::Ready { filter, .. }) => !filter.may_contain(key),
+        Some(Entry::Loading { .. }) => false,
+        None => {
+            // Registered before the scan starts, so a key committed after the scan has
+            // passed its place is still recorded in `pending`
+            let id = NEXT_LOAD.fetch_add(1, Ordering::Relaxed);
+            filters.insert(bucket_key(user_id, bucket), Entry::Loading { id, pending: Vec::new() });
+            spawn_load(user_id, bucket, id);
+            false
+        }
+    };
+    drop(filters);
+    if absent {
+        metrics::inc_counter("warpdrive_key_filter_absent_total", &[]);
+    }
+    absent
+}
+
+/// Whether the filter of `bucket` is built and answering checks.
+pub fn is_loaded(user_id: &str, bucket: &str) -> bool {
+    matches!(filters().get(&bucket_key(user_id, bucket)), Some(Entry::Ready { .. }))
+}
+
+/// Add `keys` of `bucket` after a write to them.
+pub(crate) fn record(user_id: &str, bucket: &str, keys: &[&str]) {
+    if !enabled() {
+        return;
+    }
+    let mut filters = filters();
+    let slot = bucket_key(user_id, bucket);
+    let full = match filters.get_mut(&slot) {
+        Some(Entry::Loading { pending, .. }) => {
+            pending.extend(keys.iter().map(|k| k.to_string()));
+            false
+        }
+        Some(Entry::Ready { filter, inserted, capacity }) => {
+            keys.iter().for_each(|k| filter.insert(k));
+            *inserted += keys.len() as u64;
+            *inserted > *capacity
+        }
+        None => false,
+    };
+    // Rebuilt, larger, on the next check
+    if full {
+        filters.remove(&slot);
+    }
+}
+
+/// Drop the filter of `bucket` after a write that may touch any of its keys.
+pub(crate) fn forget_bucket(user_id: &str, bucket: &str) {
+    filters().remove(&bucket_key(user_id, bucket));
+}
+
+fn spawn_load(user_id: &str, bucket: &str, id: u64) {
+    let (user_id, bucket) = (user_id.to_string(), bucket.to_string());
+    let spawned = std::thread::Builder::new()
+        .name("key-filter-load".to_string())
+        .spawn({
+            let (user_id, bucket) = (user_id.clone(), bucket.clone());
+            move || {
+                if let Err(e) = load(&user_id, &bucket, id) {
+                    warn!("Key filter for user {} bucket {} failed to load: {}", user_id, bucket, e);
+                    discard(&user_id, &bucket, id);
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Key filter for user {} bucket {} not loaded: {}", user_id, bucket, e);
+        discard(&user_id, &bucket, id);
+    }
+}
+
+/// Remove the entry of load `id`, so the next check starts over.
+fn discard(user_id: &str, bucket: &str, id: u64) {
+    let mut filters = filters();
+    let slot = bucket_key(user_id, bucket);
+    if matches!(filters.get(&slot), Some(Entry::Loading { id: current, .. }) if *current == id) {
+        filters.remove(&slot);
+    }
+}
+
+fn load(user_id: &str, bucket: &str, id: u64) -> Result<(), actix_web::Error> {
+    let store = SQLiteMetadataStore::new();
+    let count = store.live_key_count(user_id, bucket)?;
+    let capacity = CONFIG.capacity.max(count.saturating_mul(2));
+    let mut filter = BloomFilter::new(capacity, CONFIG.fp_rate);
+    let mut after: Option<String> = None;
+    let mut loaded = 0u64;
+    loop {
+        let page = store.live_keys_after(user_id, bucket, after.as_deref(), LOAD_PAGE)?;
+        page.iter().for_each(|k| filter.insert(k));
+        loaded += page.len() as u64;
+        match page.into_iter().last() {
+            Some(last) => after = Some(last),
+            None => break,
+        }
+    }
+
+    let mut filters = filters();
+    let slot = bucket_key(user_id, bucket);
+    let pending = match filters.get_mut(&slot) {
+        Some(Entry::Loading { id: current, pending }) if *current == id => std::mem::take(pending),
+        // Dropped while loading: the bucket changed wholesale
+        _ => return Ok(()),
+    };
+    pending.iter().for_each(|k| filter.insert(k));
+    let inserted = loaded + pending.len() as u64;
+    filters.insert(slot, Entry::Ready { filter, inserted, capacity });
+    info!("Key filter for user {} bucket {} loaded with {} keys (capacity {})", user_id, bucket, inserted, capacity);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter_has_no_false_negatives() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        keys.iter().for_each(|k| filter.insert(k));
+        assert!(keys.iter().all(|k| filter.may_contain(k)));
+    }
+
+    #[test]
+    fn test_bloom_filter_false_positive_rate_near_target() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        (0..10_000).for_each(|i| filter.insert(&format!("present-{}", i)));
+        let false_positives = (0..10_000).filter(|i| filter.may_contain(&format!("absent-{}", i))).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_config_defaults_to_disabled() {
+        let config = KeyFilterConfig::default();
+        assert!(!config.enabled);
+        assert_eq!(config.capacity, DEFAULT_CAPACITY);
+        assert_eq!(config.fp_rate, DEFAULT_FP_RATE);
+    }
+}
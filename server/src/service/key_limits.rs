@@ -0,0 +1,104 @@
+//! Maximum object key length
+//!
+//! Keys longer than `MAX_KEY_LENGTH` bytes (default 1024, as in S3) are rejected wherever a
+//! key is created: native put, copy and rename destinations, S3 PutObject, CopyObject and
+//! CreateMultipartUpload. Reads and deletes never check the limit, so keys stored before it
+//! was introduced (or under a higher setting) stay reachable. Keys must also be free of
+//! control characters ([`is_safe_key`]).
+
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use serde_json::json;
+use std::env;
+
+/// S3's key length limit
+pub const DEFAULT_MAX_KEY_LENGTH: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct KeyLimits {
+    /// Longest key accepted on create, in bytes
+    pub max_key_bytes: usize,
+}
+
+impl Default for KeyLimits {
+    fn default() -> Self {
+        Self { max_key_bytes: DEFAULT_MAX_KEY_LENGTH }
+    }
+}
+
+impl KeyLimits {
+    pub fn from_env() -> Self {
+        let max_key_bytes = env::var("MAX_KEY_LENGTH")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_MAX_KEY_LENGTH);
+        Self { max_key_bytes }
+    }
+
+    /// Reject `key` if it is longer than the limit.
+    pub fn check_new_key(&self, key: &str) -> Result<(), KeyTooLongError> {
+        if key.len() > self.max_key_bytes {
+            return Err(KeyTooLongError { key_bytes: key.len(), max_key_bytes: self.max_key_bytes });
+        }
+        Ok(())
+    }
+}
+
+/// False when `key` holds C0 or C1 control characters, which S3 rejects in object keys.
+/// Names of request metadata are held to the same rule.
+pub fn is_safe_key(key: &str) -> bool {
+    !key.chars().any(|c| {
+        let n = c as u32;
+        n < 0x20 || (0x7F..=0x9F).contains(&n)
+    })
+}
+
+/// Key over the configured limit; maps to 400 JSON on the native API.
+#[derive(Debug)]
+pub struct KeyTooLongError {
+    pub key_bytes: usize,
+    pub max_key_bytes: usize,
+}
+
+impl std::fmt::Display for KeyTooLongError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Your key is too long: {} bytes, at most {} allowed", self.key_bytes, self.max_key_bytes)
+    }
+}
+
+impl ResponseError for KeyTooLongError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "error": "KeyTooLongError",
+            "message": self.to_string(),
+            "max_key_length": self.max_key_bytes,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_is_inclusive() {
+        let limits = KeyLimits { max_key_bytes: 4 };
+        assert!(limits.check_new_key("abcd").is_ok());
+        assert!(limits.check_new_key("abcde").is_err());
+        // Counted in bytes, not characters
+        assert!(limits.check_new_key("ééé").is_err());
+    }
+
+    #[test]
+    fn test_control_characters_are_unsafe() {
+        assert!(is_safe_key("photos/2026/é.jpg"));
+        assert!(!is_safe_key("line\nbreak"));
+        assert!(!is_safe_key("del\u{7f}"));
+        assert!(!is_safe_key("c1\u{85}"));
+    }
+}
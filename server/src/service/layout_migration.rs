@@ -0,0 +1,138 @@
+//! Moving a user's bucket files to another storage layout (`warp_drive migrate-layout`)
+//!
+//! The user's row in `storage_layouts` first records the target, so every process resolves
+//! each segment file in whichever layout it is in at the time. Each registered bucket is then
+//! quiesced and its segment files renamed into the target layout in every storage root;
+//! renames keep the files' inodes, so nothing is copied. Once all buckets have moved the
+//! target becomes the user's layout, and every bucket is checked with fsck to prove its
+//! objects still read back. An interrupted migration is finished by running it again.
+//!
+//! Files in the source layout that belong to no registered bucket are left where they are and
+//! listed in the report. The write gate taken per bucket only holds off writers in this
+//! process, so a migration is best run while no server writes the user's buckets; reads stay
+//! correct throughout.
+
+use actix_web::Error;
+use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorInternalServerError};
+use log::info;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::service::change_log;
+use crate::service::fsck::{check_bucket, FsckReport};
+use crate::service::metadata_service::MetadataService;
+use crate::storage::bucket_lock;
+use crate::storage::config::{StorageBackend, StorageConfig};
+use crate::storage::layout::{self, StorageLayout, UserLayout};
+use crate::storage::local_store::LocalXFSBinaryStore;
+use crate::storage::segment;
+
+/// Outcome of a layout migration
+#[derive(Debug, Clone, Serialize)]
+pub struct LayoutMigrationReport {
+    pub user: String,
+    pub from: &'static str,
+    pub to: &'static str,
+    /// Buckets with at least one file moved
+    pub buckets_moved: usize,
+    pub files_moved: usize,
+    /// Files left in the source layout because no registered bucket owns them
+    pub unmoved: Vec<String>,
+    /// Buckets checked after the move
+    pub buckets_verified: usize,
+    /// Fsck reports of buckets that did not read back cleanly
+    pub problems: Vec<FsckReport>,
+}
+
+impl LayoutMigrationReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Segment files under `dir` (a bucket directory of `layout`) not owned by any of `buckets`
+fn unowned_files(dir: &Path, layout: StorageLayout, buckets: &[String], out: &mut Vec<String>) -> std::io::Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            // Hashed subdirectories; a flat user directory only holds files
+            if layout == StorageLayout::Hashed {
+                unowned_files(&path, StorageLayout::Flat, buckets, out)?;
+            }
+            continue;
+        }
+        let name = entry.file_name();
+        let Some(name) = name.to_str().filter(|n| n.ends_with(".bin")) else { continue };
+        if !buckets.iter().any(|b| segment::parse_file_name(b, name).is_some()) {
+            out.push(path.display().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Move every file of `user` to layout `to`, then verify the user's buckets.
+pub async fn migrate_user(user: &str, to: StorageLayout) -> Result<LayoutMigrationReport, Error> {
+    let config = StorageConfig::from_env();
+    if config.backend == StorageBackend::Mock {
+        return Err(ErrorBadRequest("The mock storage backend has no storage layout"));
+    }
+    let current = layout::user_layout(user);
+    if let Some(target) = current.target.filter(|t| *t != to) {
+        return Err(ErrorConflict(format!("User {} is being migrated to the {} layout", user, target.as_str())));
+    }
+    let from = current.layout;
+    let buckets = MetadataService::new(user)?.list_all_buckets()?;
+    let roots = layout::layout_roots(&config);
+    let mut report = LayoutMigrationReport {
+        user: user.to_string(),
+        from: from.as_str(),
+        to: to.as_str(),
+        buckets_moved: 0,
+        files_moved: 0,
+        unmoved: Vec::new(),
+        buckets_verified: 0,
+        problems: Vec::new(),
+    };
+
+    if from != to {
+        info!("Migrating the files of user {} from the {} to the {} layout", user, from.as_str(), to.as_str());
+        layout::set_user_layout(user, UserLayout { layout: from, target: Some(to) })?;
+        for bucket in &buckets {
+            let _quiesced = bucket_lock::quiesce(user, bucket).await;
+            let mut moved = 0;
+            for root in &roots {
+                moved += LocalXFSBinaryStore::with_directory(root.clone())
+                    .move_bucket_files(user, bucket, from, to)
+                    .map_err(|e| ErrorInternalServerError(format!("Cannot move the files of bucket {}: {}", bucket, e)))?
+                    .len();
+            }
+            if moved > 0 {
+                report.buckets_moved += 1;
+                report.files_moved += moved;
+            }
+        }
+        for root in &roots {
+            unowned_files(&root.join(user), from, &buckets, &mut report.unmoved).map_err(ErrorInternalServerError)?;
+        }
+        layout::set_user_layout(user, UserLayout { layout: to, target: None })?;
+        change_log::record("migrate-layout", "storage_layout", user,
+            Some(serde_json::json!(from.as_str())), Some(serde_json::json!(to.as_str())));
+        info!("Moved {} files of {} buckets of user {} to the {} layout ({} left unmoved)",
+              report.files_moved, report.buckets_moved, user, to.as_str(), report.unmoved.len());
+    }
+
+    for bucket in &buckets {
+        let checked = check_bucket(user, bucket)?;
+        report.buckets_verified += 1;
+        if !checked.is_clean() {
+            report.problems.push(checked);
+        }
+    }
+    Ok(report)
+}
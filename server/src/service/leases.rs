@@ -0,0 +1,172 @@
+//! Exclusive write leases on keys
+//!
+//! `POST /lease/{key}?ttl=30` grants the caller a lease on `key` in the request's bucket for
+//! `ttl` seconds (default `LEASE_DEFAULT_TTL_SECS`, 30; at most `LEASE_MAX_TTL_SECS`, 3600)
+//! and answers its token. While the lease is live, native writes of the key (PUT, APPEND,
+//! UPDATE, DELETE, rename, `put_batch` and `txn`) must carry the token in `X-Warp-Lease`;
+//! others get 423 Locked with the lease's expiry. Reads are unaffected. Posting again with
+//! the token renews the lease and `DELETE /lease/{key}` with it releases the lease. The key
+//! need not exist.
+//!
+//! Leases are kept in the `leases` table with their wall-clock expiry, so they outlive a
+//! restart and are seen by every process sharing the database. The process that granted a
+//! lease also keeps its deadline on the monotonic clock and goes by that, so a step of the
+//! system clock neither stretches nor cuts it short. An expired lease is removed by the
+//! first request that finds it.
+
+use actix_web::Error;
+use actix_web::error::ErrorInternalServerError;
+use chrono::Duration as ChronoDuration;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metadata::sqlite_store::{LeaseRecord, SQLiteMetadataStore};
+use crate::service::error::ServiceError;
+use crate::service::expiry;
+use crate::service::user_context::UserContext;
+use crate::util::clock;
+
+/// Request header carrying a lease token
+pub const LEASE_HEADER: &str = "x-warp-lease";
+
+/// Prefix of lease tokens
+const TOKEN_PREFIX: &str = "wdl_";
+
+lazy_static! {
+    /// Monotonic deadline of each lease this process granted, by token
+    static ref DEADLINES: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaseConfig {
+    /// Lease length when the request names none
+    pub default_ttl: Duration,
+    /// Longest lease granted
+    pub max_ttl: Duration,
+}
+
+impl Default for LeaseConfig {
+    fn default() -> Self {
+        Self { default_ttl: Duration::from_secs(30), max_ttl: Duration::from_secs(3600) }
+    }
+}
+
+impl LeaseConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secs = |name: &str, default: Duration| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|s: &u64| *s > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(default)
+        };
+        let max_ttl = secs("LEASE_MAX_TTL_SECS", defaults.max_ttl);
+        let default_ttl = secs("LEASE_DEFAULT_TTL_SECS", defaults.default_ttl).min(max_ttl);
+        Self { default_ttl, max_ttl }
+    }
+}
+
+/// A granted lease
+#[derive(Debug, Clone, Serialize)]
+pub struct Lease {
+    pub bucket: String,
+    pub key: String,
+    pub token: String,
+    pub ttl_secs: u64,
+    pub expires_at: String,
+}
+
+fn is_live(lease: &LeaseRecord) -> bool {
+    if let Some(deadline) = DEADLINES.lock().unwrap().get(&lease.token) {
+        return clock::instant() < *deadline;
+    }
+    // Granted by another process or before a restart
+    expiry::now() < lease.expires_at
+}
+
+/// The live lease on `key`; an expired one is removed
+fn live_lease(store: &SQLiteMetadataStore, context: &UserContext, key: &str) -> Result<Option<LeaseRecord>, Error> {
+    let Some(lease) = store.lease(&context.user_id, &context.bucket, key)? else { return Ok(None) };
+    if is_live(&lease) {
+        return Ok(Some(lease));
+    }
+    store.delete_lease(&context.user_id, &context.bucket, key, &lease.token)?;
+    DEADLINES.lock().unwrap().remove(&lease.token);
+    Ok(None)
+}
+
+fn locked(context: &UserContext, key: &str, lease: LeaseRecord) -> Error {
+    ServiceError::Locked { bucket: context.bucket.clone(), key: key.to_string(), expires_at: lease.expires_at }.into()
+}
+
+/// Refuse with 423 when another writer holds a live lease on one of `keys`
+pub fn check(context: &UserContext, keys: &[&str]) -> Result<(), Error> {
+    let store = SQLiteMetadataStore::new();
+    for key in keys {
+        if let Some(lease) = live_lease(&store, context, key)? {
+            if context.lease.as_deref() != Some(lease.token.as_str()) {
+                return Err(locked(context, key, lease));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Grant a lease on `key` for `ttl_secs` (the default when None), or renew the one whose
+/// token the context presents. The caller holds the key's lock.
+pub fn acquire(context: &UserContext, key: &str, ttl_secs: Option<u64>) -> Result<Lease, Error> {
+    let config = LeaseConfig::from_env();
+    let ttl = match ttl_secs {
+        None => config.default_ttl,
+        Some(secs) if secs == 0 || secs > config.max_ttl.as_secs() => {
+            return Err(ServiceError::InvalidRequest(format!(
+                "ttl must be between 1 and {} seconds, got {}", config.max_ttl.as_secs(), secs
+            )).into());
+        }
+        Some(secs) => Duration::from_secs(secs),
+    };
+    let store = SQLiteMetadataStore::new();
+    let token = match live_lease(&store, context, key)? {
+        Some(held) if context.lease.as_deref() == Some(held.token.as_str()) => held.token,
+        Some(held) => return Err(locked(context, key, held)),
+        None => new_token()?,
+    };
+    let expires_at = expiry::stored(clock::now() + ChronoDuration::from_std(ttl).map_err(ErrorInternalServerError)?);
+    let record = LeaseRecord { token: token.clone(), expires_at: expires_at.clone() };
+    store.put_lease(&context.user_id, &context.bucket, key, &record)?;
+    let now = clock::instant();
+    let mut deadlines = DEADLINES.lock().unwrap();
+    deadlines.retain(|_, deadline| *deadline > now);
+    deadlines.insert(token.clone(), now + ttl);
+    Ok(Lease { bucket: context.bucket.clone(), key: key.to_string(), token, ttl_secs: ttl.as_secs(), expires_at })
+}
+
+/// Release the lease on `key` held with the context's token. Returns whether a live lease
+/// was released; 423 when someone else holds it.
+pub fn release(context: &UserContext, key: &str) -> Result<bool, Error> {
+    let Some(token) = context.lease.as_deref() else {
+        return Err(ServiceError::InvalidRequest(format!("Releasing a lease needs its token in {}", LEASE_HEADER)).into());
+    };
+    let store = SQLiteMetadataStore::new();
+    match live_lease(&store, context, key)? {
+        Some(held) if held.token != token => Err(locked(context, key, held)),
+        Some(_) => {
+            let released = store.delete_lease(&context.user_id, &context.bucket, key, token)?;
+            DEADLINES.lock().unwrap().remove(token);
+            Ok(released)
+        }
+        None => Ok(false),
+    }
+}
+
+fn new_token() -> Result<String, Error> {
+    let mut secret = [0u8; 16];
+    getrandom::getrandom(&mut secret).map_err(ErrorInternalServerError)?;
+    Ok(format!("{}{}", TOKEN_PREFIX, hex::encode(secret)))
+}
@@ -0,0 +1,184 @@
+//! Storage from before buckets (`warp_drive migrate-legacy`)
+//!
+//! Before buckets existed all of a user's data was appended to one `<root>/<user>.bin` and
+//! chunk offsets pointed into it. The first start that finds such a file ([`reconcile`])
+//! moves the chunks of the user's rows in buckets without files of their own into
+//! [`LEGACY_SEGMENT`], whose addresses the local store reads from the old file, so all of
+//! the data stays readable across the upgrade. Pending deletions of those rows are moved
+//! along so they never free space in a bucket file. This happens once per user and is
+//! recorded in the `legacy_files` table.
+//!
+//! `migrate-legacy --user` then copies every legacy chunk into the file of the bucket its row
+//! is in, one bucket at a time with the bucket quiesced, and swaps the bucket's chunk lists in
+//! one transaction. A row that changed while its chunks were copied keeps its old list and is
+//! counted as skipped; with none skipped the old file is renamed to `<user>.bin.retired` and
+//! can be deleted. Running the command again finishes an interrupted migration. Only the
+//! local backend has legacy files.
+
+use actix_web::Error;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::service::change_log;
+use crate::storage::bucket_lock;
+use crate::storage::config::{StorageBackend, StorageConfig};
+use crate::storage::local_store::{self, LocalXFSBinaryStore};
+use crate::storage::segment::{self, LEGACY_SEGMENT};
+use crate::storage::Storage;
+
+/// Outcome of a legacy migration
+#[derive(Debug, Clone, Serialize)]
+pub struct LegacyMigrationReport {
+    pub user: String,
+    /// Buckets with legacy rows
+    pub buckets: usize,
+    pub rows_migrated: usize,
+    /// Rows changed during the copy, still reading from the legacy file
+    pub rows_skipped: usize,
+    pub chunks_copied: usize,
+    pub bytes_copied: u64,
+    /// Where the legacy file was moved, once nothing references it
+    pub retired_file: Option<String>,
+}
+
+/// Users with a `<user>.bin` directly under `root`
+fn legacy_users(root: &Path) -> std::io::Result<Vec<String>> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut users = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(user) = entry.file_name().to_str().and_then(|name| name.strip_suffix(".bin")).filter(|u| !u.is_empty()) {
+            users.push(user.to_string());
+        }
+    }
+    users.sort();
+    Ok(users)
+}
+
+/// Point `user`'s rows in buckets that have no files of their own at the legacy file.
+/// Returns the rows changed.
+fn mark_user(user: &str) -> Result<u64, Error> {
+    let store = LocalXFSBinaryStore::new();
+    let db = SQLiteMetadataStore::new();
+    let mut buckets = Vec::new();
+    for bucket in db.object_buckets(user)? {
+        if store.segments(user, &bucket)?.is_empty() {
+            buckets.push(bucket);
+        }
+    }
+    let rows = db.mark_legacy_rows(user, &buckets)?;
+    info!("Found the pre-bucket file of user {}; {} rows in {} buckets read from it until migrated",
+          user, rows, buckets.len());
+    Ok(rows)
+}
+
+/// At startup: mark the rows of every user whose legacy file was not seen before. Returns
+/// how many users were marked.
+pub fn reconcile(config: &StorageConfig) -> Result<usize, String> {
+    if config.backend != StorageBackend::LocalXFS {
+        return Ok(0);
+    }
+    let root = local_store::storage_directory();
+    let users = legacy_users(&root).map_err(|e| format!("cannot list {}: {}", root.display(), e))?;
+    let db = SQLiteMetadataStore::new();
+    let mut marked = 0;
+    for user in users {
+        if db.legacy_file(&user).map_err(|e| e.to_string())?.is_some() {
+            continue;
+        }
+        mark_user(&user).map_err(|e| format!("cannot mark the legacy rows of user {}: {}", user, e))?;
+        marked += 1;
+    }
+    Ok(marked)
+}
+
+/// Copy `user`'s legacy chunks into their buckets' files and retire the legacy file.
+pub async fn migrate_user(user: &str) -> Result<LegacyMigrationReport, Error> {
+    if StorageConfig::from_env().backend != StorageBackend::LocalXFS {
+        return Err(ErrorBadRequest("Only the local storage backend has pre-bucket files"));
+    }
+    let store = LocalXFSBinaryStore::new();
+    let legacy_file = store.legacy_file_path(user);
+    let db = SQLiteMetadataStore::new();
+    let mut report = LegacyMigrationReport {
+        user: user.to_string(),
+        buckets: 0,
+        rows_migrated: 0,
+        rows_skipped: 0,
+        chunks_copied: 0,
+        bytes_copied: 0,
+        retired_file: None,
+    };
+    match db.legacy_file(user)? {
+        Some(record) if record.retired_at.is_some() => return Ok(report),
+        Some(_) => {}
+        None if legacy_file.is_file() => {
+            mark_user(user)?;
+        }
+        None => return Err(ErrorBadRequest(format!("User {} has no pre-bucket file", user))),
+    }
+
+    for bucket in db.object_buckets(user)? {
+        if db.legacy_rows(user, &bucket)?.is_empty() {
+            continue;
+        }
+        let _quiesced = bucket_lock::quiesce(user, &bucket).await;
+        // Listed again now that no writer is half-way
+        let rows = db.legacy_rows(user, &bucket)?;
+        let mut moves: HashMap<(u64, u64), (u64, u64)> = HashMap::new();
+        let mut copied = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut extents = Vec::with_capacity(row.extents.len());
+            for &(offset, size) in &row.extents {
+                if segment::segment_of(offset) != LEGACY_SEGMENT {
+                    extents.push((offset, size));
+                    continue;
+                }
+                // A chunk shared by clones is copied once
+                if let Some(moved) = moves.get(&(offset, size)) {
+                    extents.push(*moved);
+                    continue;
+                }
+                let data = store.read(user, &bucket, offset, size)?;
+                let moved = store.write(user, &bucket, &data)?;
+                report.chunks_copied += 1;
+                report.bytes_copied += size;
+                moves.insert((offset, size), moved);
+                extents.push(moved);
+            }
+            copied.push((row, extents));
+        }
+        let moves: Vec<_> = moves.into_iter().collect();
+        let migrated = db.commit_legacy_rows(user, &bucket, &copied, &moves)?;
+        report.buckets += 1;
+        report.rows_migrated += migrated;
+        report.rows_skipped += copied.len() - migrated;
+        info!("Migrated {} legacy rows of user {} into bucket {}", migrated, user, bucket);
+    }
+
+    if report.rows_skipped > 0 {
+        warn!("{} rows of user {} changed during the legacy migration; run it again to move them", report.rows_skipped, user);
+        return Ok(report);
+    }
+    if legacy_file.exists() {
+        let retired = PathBuf::from(format!("{}.retired", legacy_file.display()));
+        std::fs::rename(&legacy_file, &retired).map_err(ErrorInternalServerError)?;
+        report.retired_file = Some(retired.display().to_string());
+    }
+    db.retire_legacy_file(user)?;
+    change_log::record("migrate-legacy", "storage_layout", user,
+        Some(serde_json::json!("legacy")), Some(serde_json::json!("buckets")));
+    info!("Retired the pre-bucket file of user {}", user);
+    Ok(report)
+}
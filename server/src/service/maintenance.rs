@@ -0,0 +1,202 @@
+//! Server-wide read-only maintenance mode
+//!
+//! `POST /admin/maintenance?mode=read_only` (optionally with `duration_secs`) makes every
+//! mutating native and S3 request fail with 503 and `Retry-After` while reads keep working,
+//! and pauses the deletion worker; `mode=normal` lifts it. A bounded mode lapses back to
+//! normal on its own. The mode is stored in the metadata database so it survives restarts,
+//! and cached in memory so the per-request check is a read lock.
+
+use actix_web::{Error, HttpResponse};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::RwLock;
+
+use crate::metadata::sqlite_store::{MaintenanceRecord, SQLiteMetadataStore};
+use crate::s3::handlers::xml_escape;
+use crate::service::api_version;
+use crate::service::config_generation;
+
+/// Retry-After when the read-only period has no end
+const DEFAULT_RETRY_AFTER_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceMode {
+    #[default]
+    Normal,
+    ReadOnly,
+}
+
+impl MaintenanceMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MaintenanceMode::Normal => "normal",
+            MaintenanceMode::ReadOnly => "read_only",
+        }
+    }
+}
+
+impl std::str::FromStr for MaintenanceMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(MaintenanceMode::Normal),
+            "read_only" => Ok(MaintenanceMode::ReadOnly),
+            _ => Err(format!("Unknown maintenance mode: {} (expected read_only or normal)", s)),
+        }
+    }
+}
+
+/// Current mode and the change that set it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MaintenanceState {
+    /// Mode as set, before any `until` has passed
+    pub mode: MaintenanceMode,
+    pub changed_at: Option<DateTime<Utc>>,
+    /// Request id of the admin call that made the change
+    pub changed_by: Option<String>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl MaintenanceState {
+    /// Mode in force at `now`
+    pub fn effective_mode(&self, now: DateTime<Utc>) -> MaintenanceMode {
+        match self.until {
+            Some(until) if until <= now => MaintenanceMode::Normal,
+            _ => self.mode,
+        }
+    }
+
+    fn from_record(record: MaintenanceRecord) -> Self {
+        let time = |s: &str| DateTime::parse_from_rfc3339(s).ok().map(|t| t.with_timezone(&Utc));
+        Self {
+            mode: record.mode.parse().unwrap_or_else(|e: String| {
+                warn!("{}; treating the server as normal", e);
+                MaintenanceMode::Normal
+            }),
+            changed_at: time(&record.changed_at),
+            changed_by: Some(record.changed_by),
+            until: record.until.as_deref().and_then(time),
+        }
+    }
+}
+
+lazy_static! {
+    // None until first read from the database
+    static ref STATE: RwLock<Option<MaintenanceState>> = RwLock::new(None);
+}
+
+/// Read the stored mode from the database, bypassing the cache
+pub fn load() -> Result<MaintenanceState, Error> {
+    Ok(SQLiteMetadataStore::new().get_maintenance()?
+        .map(MaintenanceState::from_record)
+        .unwrap_or_default())
+}
+
+/// Cached mode, loaded on first use. A database error reads as normal and is retried on
+/// the next call.
+pub fn current() -> MaintenanceState {
+    config_generation::revalidate();
+    if let Some(state) = STATE.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return state.clone();
+    }
+    match load() {
+        Ok(state) => {
+            *STATE.write().unwrap_or_else(|e| e.into_inner()) = Some(state.clone());
+            state
+        }
+        Err(e) => {
+            warn!("Cannot read maintenance mode: {}", e);
+            MaintenanceState::default()
+        }
+    }
+}
+
+/// Drop the cached mode so the next call reads it again.
+pub(crate) fn clear() {
+    *STATE.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+pub fn is_read_only() -> bool {
+    current().effective_mode(Utc::now()) == MaintenanceMode::ReadOnly
+}
+
+/// Store and apply `mode`, lapsing at `until` if given.
+pub fn set(mode: MaintenanceMode, until: Option<DateTime<Utc>>, changed_by: &str) -> Result<MaintenanceState, Error> {
+    let now = Utc::now();
+    let state = MaintenanceState {
+        mode,
+        changed_at: Some(now),
+        changed_by: Some(changed_by.to_string()),
+        until: until.filter(|_| mode == MaintenanceMode::ReadOnly),
+    };
+    SQLiteMetadataStore::new().set_maintenance(&MaintenanceRecord {
+        mode: mode.as_str().to_string(),
+        changed_at: now.to_rfc3339(),
+        changed_by: changed_by.to_string(),
+        until: state.until.map(|t| t.to_rfc3339()),
+    })?;
+    *STATE.write().unwrap_or_else(|e| e.into_inner()) = Some(state.clone());
+    Ok(state)
+}
+
+pub(crate) fn is_s3_request(req: &ServiceRequest) -> bool {
+    req.path().starts_with("/s3")
+        || req.headers().get("authorization").and_then(|v| v.to_str().ok()).is_some_and(|v| v.starts_with("AWS4-"))
+        || req.query_string().contains("X-Amz-Algorithm")
+}
+
+/// Reject writes while read-only; use with `middleware::from_fn` inside the virtual-host
+/// rewrite. Admin endpoints stay available so the mode can be lifted, and `POST /get_batch`
+/// is a read.
+pub async fn maintenance_gate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let mutating = matches!(*req.method(), Method::PUT | Method::POST | Method::DELETE | Method::PATCH);
+    let exempt = req.path().starts_with("/admin/") || api_version::unversioned_path(req.path()) == "/get_batch";
+    if !mutating || exempt {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let state = current();
+    let now = Utc::now();
+    if state.effective_mode(now) != MaintenanceMode::ReadOnly {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+
+    let retry_after = state.until
+        .map(|until| (until - now).num_seconds().max(1))
+        .unwrap_or(DEFAULT_RETRY_AFTER_SECS);
+    let message = "The server is in read-only maintenance mode";
+    let mut resp = HttpResponse::ServiceUnavailable();
+    resp.insert_header(("Retry-After", retry_after.to_string()));
+    let resp = if is_s3_request(&req) {
+        resp.content_type("application/xml")
+            .insert_header(("x-amz-request-id", "warpdrive"))
+            .body(format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <Error>\n\
+                   <Code>ServiceUnavailable</Code>\n\
+                   <Message>{}</Message>\n\
+                   <Resource>{}</Resource>\n\
+                   <RequestId>warpdrive</RequestId>\n\
+                 </Error>",
+                message, xml_escape(req.path()),
+            ))
+    } else {
+        resp.json(json!({
+            "error": "ReadOnlyMaintenance",
+            "message": message,
+            "until": state.until,
+        }))
+    };
+    Ok(req.into_response(resp).map_into_right_body())
+}
@@ -1,89 +1,361 @@
 //! Metadata service layer bridging handlers with the MetadataStorage trait
-
-use crate::metadata::{MetadataStorage, Metadata, BucketStats, config::MetadataConfig};
-use std::sync::Arc;
+//!
+//! Object reads and writes retry a busy or locked database; see [`crate::service::retry`].
+
+use crate::metadata::{self, MetadataStorage, Metadata, DataChunk, BucketStats, DeletionEvent, DeletionSummary, FileAttrs, ObjectRecord, config::MetadataConfig};
+use crate::metadata::sqlite_store::{BatchRowOutcome, BucketLimits, TxnConflict, TxnMutation};
+use crate::service::bucket_access;
+use crate::service::deletion_spill;
+use crate::service::bucket_quota::{self, Usage};
+use crate::service::bucket_registry::{self, BucketConfig, NoSuchBucketError};
+use crate::service::error::ServiceError;
+use crate::service::expiry;
+use crate::service::free_ranges;
+use crate::service::key_filter;
+use crate::service::object_format::ObjectFormat;
+use crate::service::object_state::ObjectState;
+use crate::service::prefetch;
+use crate::service::retry::with_retry;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use actix_web::Error;
+use actix_web::http::StatusCode;
 use lazy_static::lazy_static;
+use log::{error, info, warn};
+
+/// One object of a batch write: key, storage extents and file attributes
+pub type BatchObject = (String, Vec<(u64, u64)>, FileAttrs);
+
+/// A store supplied with [`install_store`], used instead of the configured backend
+static INSTALLED_STORE: OnceLock<Arc<dyn MetadataStorage>> = OnceLock::new();
+/// Set once [`METADATA_STORE`] is opened; a store installed after that would never be used
+static STORE_OPENED: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
     static ref METADATA_STORE: Arc<dyn MetadataStorage> = {
-        let config = MetadataConfig::from_env();
-        config.create_store()
+        STORE_OPENED.store(true, Ordering::SeqCst);
+        match INSTALLED_STORE.get() {
+            Some(store) => Arc::clone(store),
+            None => MetadataConfig::from_env().create_store(),
+        }
     };
 }
 
+/// Serve object metadata from `store` instead of the backend `METADATA_BACKEND` names. Only
+/// possible before the first metadata access and only once; `store` is handed back otherwise.
+/// Operations outside [`MetadataStorage`] (bucket settings, versions, leases and the like)
+/// still use the SQLite database at `DB_FILE`.
+pub fn install_store(store: Arc<dyn MetadataStorage>) -> Result<(), Arc<dyn MetadataStorage>> {
+    if STORE_OPENED.load(Ordering::SeqCst) {
+        return Err(store);
+    }
+    INSTALLED_STORE.set(store)
+}
+
+/// The store every [`MetadataService`] uses, opening it on first use
+pub fn store() -> Arc<dyn MetadataStorage> {
+    Arc::clone(&METADATA_STORE)
+}
+
 pub struct MetadataService {
     user: String,
+    /// Upload whose parts become the object being written; see [`Self::completing`]
+    completing: Option<String>,
 }
 
 impl MetadataService {
     pub fn new(user: &str) -> Result<Self, Error> {
-        Ok(Self { user: user.to_string() })
+        Ok(Self { user: user.to_string(), completing: None })
+    }
+
+    /// This service for writing the object `upload_id` completes into: the upload's parts
+    /// stop counting against the bucket quota as the object starts to.
+    pub fn completing(&self, upload_id: &str) -> Self {
+        Self { user: self.user.clone(), completing: Some(upload_id.to_string()) }
     }
 
     // --- Object existence / key checks ---
 
+    /// Whether `key` is live; an expired key is expired on the spot and reads as missing.
     pub fn check_key(&self, bucket: &str, key: &str) -> Result<bool, Error> {
-        METADATA_STORE.object_exists(&self.user, bucket, key)
+        crate::metrics::inc_counter("warpdrive_metadata_key_checks_total", &[]);
+        let exists = with_retry("object_exists", || METADATA_STORE.object_exists(&self.user, bucket, key))?;
+        Ok(exists && !expiry::expire_if_due(&self.user, bucket, key)?)
+    }
+
+    /// [`Self::check_key`] for a key about to be created, answered from the bucket's
+    /// [`key_filter`] when it knows the key is absent. A key it misses (written by another
+    /// process) fails the insert instead, as a 409 `KeyExists`.
+    pub fn check_key_for_create(&self, bucket: &str, key: &str) -> Result<bool, Error> {
+        if key_filter::definitely_absent(&self.user, bucket, key) {
+            return Ok(false);
+        }
+        self.check_key(bucket, key)
     }
 
     pub fn check_key_nonexistance(&self, bucket: &str, key: &str) -> Result<(), Error> {
         if !self.check_key(bucket, key)? {
-            return Err(actix_web::error::ErrorNotFound(format!(
-                "No data found for key: {} in bucket: {}, The key does not exist",
-                key, bucket
-            )));
+            return Err(ServiceError::key_not_found(bucket, key).into());
+        }
+        Ok(())
+    }
+
+    /// The latest row of `key`, with a missing key reported as [`ServiceError::KeyNotFound`].
+    fn latest(&self, bucket: &str, key: &str) -> Result<Metadata, Error> {
+        let metadata = with_retry("get_metadata", || METADATA_STORE.get_metadata(&self.user, bucket, key)).map_err(|e| {
+            if e.as_response_error().status_code() == StatusCode::NOT_FOUND {
+                ServiceError::key_not_found(bucket, key).into()
+            } else {
+                e
+            }
+        })?;
+        let due = metadata.expire_at.as_deref().is_some_and(|at| at <= expiry::now().as_str());
+        if due && expiry::expire_if_due(&self.user, bucket, key)? {
+            return Err(ServiceError::key_not_found(bucket, key).into());
+        }
+        Ok(metadata)
+    }
+
+    /// Refuse a write of `writes` (key, new size) that would take `bucket` past its quota.
+    /// With `replaces`, an existing key counts only the bytes it grows by; otherwise existing
+    /// keys count nothing, as the write fails on them anyway.
+    pub fn check_quota(&self, bucket: &str, writes: &[(&str, u64)], replaces: bool) -> Result<(), Error> {
+        let limits = bucket_quota::limits(&self.user, bucket)?;
+        if limits.is_unlimited() {
+            return Ok(());
+        }
+        let (mut added_objects, mut added_bytes) = (0u64, 0i64);
+        for (key, size) in writes {
+            if !self.check_key(bucket, key)? {
+                added_objects += 1;
+                added_bytes += *size as i64;
+            } else if replaces {
+                added_bytes += *size as i64 - self.latest(bucket, key)?.size as i64;
+            }
         }
+        self.check_usage(bucket, &limits, added_objects, added_bytes)
+    }
+
+    /// Refuse part `part_number` of `size` bytes when storing it in `upload_id` would take
+    /// `bucket` past its byte quota; a part sent again counts only the bytes it grows by.
+    /// Checked before the part's data is written; [`Self::upsert_multipart_part`] checks
+    /// again when it is stored.
+    pub fn check_part_quota(&self, bucket: &str, upload_id: &str, part_number: i32, size: u64) -> Result<(), Error> {
+        let limits = bucket_quota::limits(&self.user, bucket)?;
+        if limits.is_unlimited() {
+            return Ok(());
+        }
+        let replaced = self.list_multipart_parts(upload_id)?.into_iter()
+            .find(|part| part.part_number == part_number)
+            .map_or(0, |part| part.size);
+        self.check_usage(bucket, &limits, 0, size as i64 - replaced as i64)
+    }
+
+    /// [`Self::bucket_usage`] checked against `limits` for a write adding `added_objects`
+    /// and `added_bytes`
+    fn check_usage(&self, bucket: &str, limits: &BucketLimits, added_objects: u64, added_bytes: i64) -> Result<(), Error> {
+        let usage = self.bucket_usage(bucket)?;
+        bucket_quota::check(bucket, limits, usage, added_objects, added_bytes)?;
+        let after = Usage { objects: usage.objects + added_objects, bytes: usage.bytes.saturating_add_signed(added_bytes) };
+        bucket_quota::record_usage(&self.user, bucket, limits, after);
         Ok(())
     }
 
+    /// Usage of `bucket` as its quota counts it: live objects, and the parts of its uploads
+    /// in progress other than the one being completed. Summed from the rows each time, so
+    /// an abort or expiry interrupted between its steps leaves nothing to reconcile.
+    pub fn bucket_usage(&self, bucket: &str) -> Result<Usage, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        let (objects, bytes) = self.bucket_object_stats(bucket)?;
+        let multipart = SQLiteMetadataStore::new().multipart_bucket_bytes(&self.user, bucket, self.completing.as_deref())?;
+        Ok(Usage { objects, bytes: bytes + multipart })
+    }
+
     // --- Full-metadata S3 path (includes etag, size, content_type, etc.) ---
 
-    /// Write a fully-populated Metadata object (S3 PUT path).
+    /// Write a fully-populated Metadata object (S3 PUT path), recorded as [`ObjectFormat::Raw`].
     /// Returns (version_id, old_extents_to_gc):
     ///   - version_id: Some(vid) when versioning enabled/suspended, None when disabled.
     ///   - old_extents_to_gc: storage extents of the row that was replaced (queue for GC).
     pub fn put_object_full(
-        &self, bucket: &str, key: &str, metadata: Metadata,
-    ) -> Result<(Option<String>, Vec<(u64, u64)>), Error> {
+        &self, bucket: &str, key: &str, mut metadata: Metadata,
+    ) -> Result<crate::metadata::sqlite_store::PutObjectOutcome, Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().put_object_v2(&self.user, bucket, key, &metadata)
+        self.check_quota(bucket, &[(key, metadata.size)], true)?;
+        metadata.last_modified.get_or_insert_with(metadata::last_modified_now);
+        ObjectFormat::Raw.record(&mut metadata);
+        let outcome = with_retry("put_object", || SQLiteMetadataStore::new().put_object_v2(&self.user, bucket, key, &metadata))?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        key_filter::record(&self.user, bucket, &[key]);
+        Ok(outcome)
+    }
+
+    /// Replace the latest row of `key` with a fully-populated Metadata object, properties included.
+    pub fn update_object_full(&self, bucket: &str, key: &str, metadata: &Metadata) -> Result<(), Error> {
+        self.check_quota(bucket, &[(key, metadata.size)], true)?;
+        with_retry("update_metadata", || METADATA_STORE.update_metadata(&self.user, bucket, key, metadata))?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        Ok(())
     }
 
     /// Read a fully-populated Metadata object (S3 GET / HEAD path).
     pub fn get_object_full(&self, bucket: &str, key: &str) -> Result<Metadata, Error> {
-        METADATA_STORE.get_metadata(&self.user, bucket, key)
+        self.latest(bucket, key)
     }
 
     // --- Legacy bytes-based path (old native API and internal use) ---
 
     pub fn write_metadata(&self, bucket: &str, key: &str, offset_size_bytes: &[u8]) -> Result<(), Error> {
-        use crate::util::serializer::deserialize_offset_size;
-        let offset_size_list = deserialize_offset_size(offset_size_bytes)?;
-        let metadata = Metadata::from_offset_size_list(offset_size_list);
-        METADATA_STORE.put_metadata(&self.user, bucket, key, &metadata)
+        self.write_metadata_with_files(bucket, key, offset_size_bytes, &[], None)
+    }
+
+    /// Like [`Self::write_metadata`], also recording per-chunk file names from a v2 payload
+    /// and the expiry time, if any.
+    pub fn write_metadata_with_files(&self, bucket: &str, key: &str, offset_size_bytes: &[u8], files: &[FileAttrs], expire_at: Option<String>) -> Result<(), Error> {
+        use crate::util::serializer::decode_chunks;
+        self.write_native_object(bucket, key, Metadata::from_chunks(decode_chunks(offset_size_bytes)?), files, expire_at)
+    }
+
+    /// Like [`Self::write_metadata_with_files`] with the data already described by `metadata`,
+    /// e.g. an object stored inline.
+    pub fn write_native_object(&self, bucket: &str, key: &str, mut metadata: Metadata, files: &[FileAttrs], expire_at: Option<String>) -> Result<(), Error> {
+        metadata.set_file_attrs(files);
+        metadata.last_modified = Some(metadata::last_modified_now());
+        metadata.expire_at = expire_at;
+        ObjectFormat::Native.record(&mut metadata);
+        self.check_quota(bucket, &[(key, metadata.size)], false)?;
+        with_retry("put_metadata", || METADATA_STORE.put_metadata(&self.user, bucket, key, &metadata))?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        key_filter::record(&self.user, bucket, &[key]);
+        Ok(())
+    }
+
+    /// Create or replace `key`, returning the extents of the replaced object (queue them for GC).
+    pub fn overwrite_metadata_with_files(&self, bucket: &str, key: &str, offset_size_bytes: &[u8], files: &[FileAttrs]) -> Result<Vec<(u64, u64)>, Error> {
+        use crate::util::serializer::decode_chunks;
+        self.overwrite_native_object(bucket, key, Metadata::from_chunks(decode_chunks(offset_size_bytes)?), files)
+    }
+
+    /// Like [`Self::overwrite_metadata_with_files`] with the data already described by
+    /// `metadata`.
+    pub fn overwrite_native_object(&self, bucket: &str, key: &str, mut metadata: Metadata, files: &[FileAttrs]) -> Result<Vec<(u64, u64)>, Error> {
+        metadata.set_file_attrs(files);
+        metadata.last_modified = Some(metadata::last_modified_now());
+        ObjectFormat::Native.record(&mut metadata);
+        self.check_quota(bucket, &[(key, metadata.size)], true)?;
+        let previous = with_retry("put_metadata_overwrite", || METADATA_STORE.put_metadata_overwrite(&self.user, bucket, key, &metadata))?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        key_filter::record(&self.user, bucket, &[key]);
+        Ok(previous.iter().map(DataChunk::extent).collect())
+    }
+
+    /// Record many native objects, each with its file attributes, in one transaction; see
+    /// [`SQLiteMetadataStore::put_metadata_batch`](crate::metadata::sqlite_store::SQLiteMetadataStore::put_metadata_batch).
+    pub fn write_metadata_batch(&self, bucket: &str, objects: &[BatchObject], overwrite: bool) -> Result<Vec<BatchRowOutcome>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        let now = metadata::last_modified_now();
+        let rows: Vec<(String, Metadata)> = objects.iter().map(|(key, extents, attrs)| {
+            let mut metadata = Metadata::from_offset_size_list(extents.clone());
+            metadata.set_file_attrs(std::slice::from_ref(attrs));
+            metadata.last_modified = Some(now.clone());
+            ObjectFormat::Native.record(&mut metadata);
+            (key.clone(), metadata)
+        }).collect();
+        let writes: Vec<(&str, u64)> = rows.iter().map(|(key, metadata)| (key.as_str(), metadata.size)).collect();
+        self.check_quota(bucket, &writes, overwrite)?;
+        let outcomes = with_retry("put_metadata_batch", || SQLiteMetadataStore::new().put_metadata_batch(&self.user, bucket, &rows, overwrite))?;
+        let keys: Vec<&str> = writes.iter().map(|(key, _)| *key).collect();
+        prefetch::invalidate(&self.user, bucket, &keys);
+        key_filter::record(&self.user, bucket, &keys);
+        Ok(outcomes)
+    }
+
+    /// Apply the mutations of a `POST /txn` in one transaction; see
+    /// [`SQLiteMetadataStore::apply_txn`](crate::metadata::sqlite_store::SQLiteMetadataStore::apply_txn).
+    pub fn apply_txn(&self, bucket: &str, mutations: &[TxnMutation]) -> Result<Result<(), (usize, TxnConflict)>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        let outcome = with_retry("apply_txn", || SQLiteMetadataStore::new().apply_txn(&self.user, bucket, mutations))?;
+        if outcome.is_ok() {
+            let mut touched = Vec::new();
+            let mut created = Vec::new();
+            for mutation in mutations {
+                match mutation {
+                    TxnMutation::Put { key, .. } => {
+                        touched.push(key.as_str());
+                        created.push(key.as_str());
+                    }
+                    TxnMutation::Delete { key } => touched.push(key.as_str()),
+                    TxnMutation::Rename { key, to } => {
+                        touched.extend([key.as_str(), to.as_str()]);
+                        created.push(to.as_str());
+                    }
+                }
+            }
+            prefetch::invalidate(&self.user, bucket, &touched);
+            key_filter::record(&self.user, bucket, &created);
+        }
+        Ok(outcome)
+    }
+
+    /// Per-chunk file attributes recorded for a native object (all empty for v1 objects).
+    pub fn read_file_attrs(&self, bucket: &str, key: &str) -> Result<Vec<FileAttrs>, Error> {
+        Ok(self.latest(bucket, key)?.file_attrs())
     }
 
     pub fn read_metadata(&self, bucket: &str, key: &str) -> Result<Vec<u8>, Error> {
-        use crate::util::serializer::serialize_offset_size;
-        let metadata = METADATA_STORE.get_metadata(&self.user, bucket, key)?;
-        let offset_size_list = metadata.to_offset_size_list();
-        serialize_offset_size(&offset_size_list)
+        use crate::util::serializer::encode_chunks;
+        encode_chunks(&self.latest(bucket, key)?.chunks)
     }
 
     pub fn delete_metadata(&self, bucket: &str, key: &str) -> Result<(), Error> {
-        METADATA_STORE.delete_metadata(&self.user, bucket, key)
+        with_retry("delete_metadata", || METADATA_STORE.delete_metadata(&self.user, bucket, key))?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        Ok(())
+    }
+
+    /// Set the last-modified time of `key` to now without rewriting it; returns the new time.
+    pub fn touch(&self, bucket: &str, key: &str) -> Result<String, Error> {
+        let now = metadata::last_modified_now();
+        METADATA_STORE.touch_metadata(&self.user, bucket, key, &now).map_err(|e| {
+            if e.as_response_error().status_code() == StatusCode::NOT_FOUND {
+                ServiceError::key_not_found(bucket, key).into()
+            } else {
+                e
+            }
+        })?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        Ok(now)
     }
 
     pub fn rename_key(&self, bucket: &str, old_key: &str, new_key: &str) -> Result<(), Error> {
-        METADATA_STORE.update_object_id(&self.user, bucket, old_key, new_key)
+        METADATA_STORE.update_object_id(&self.user, bucket, old_key, new_key)?;
+        prefetch::invalidate(&self.user, bucket, &[old_key, new_key]);
+        key_filter::record(&self.user, bucket, &[new_key]);
+        Ok(())
     }
 
     pub fn update_metadata(&self, bucket: &str, key: &str, offset_size_bytes: &[u8]) -> Result<(), Error> {
-        use crate::util::serializer::deserialize_offset_size;
-        let offset_size_list = deserialize_offset_size(offset_size_bytes)?;
-        let metadata = Metadata::from_offset_size_list(offset_size_list);
-        METADATA_STORE.update_metadata(&self.user, bucket, key, &metadata)
+        self.update_metadata_with_files(bucket, key, offset_size_bytes, &[], None)
+    }
+
+    /// Like [`Self::update_metadata`], replacing the recorded per-chunk file attributes and
+    /// the expiry time.
+    pub fn update_metadata_with_files(&self, bucket: &str, key: &str, offset_size_bytes: &[u8], files: &[FileAttrs], expire_at: Option<String>) -> Result<(), Error> {
+        use crate::util::serializer::decode_chunks;
+        self.update_native_object(bucket, key, Metadata::from_chunks(decode_chunks(offset_size_bytes)?), files, expire_at)
+    }
+
+    /// Like [`Self::update_metadata_with_files`] with the data already described by
+    /// `metadata`.
+    pub fn update_native_object(&self, bucket: &str, key: &str, mut metadata: Metadata, files: &[FileAttrs], expire_at: Option<String>) -> Result<(), Error> {
+        metadata.set_file_attrs(files);
+        metadata.last_modified = Some(metadata::last_modified_now());
+        metadata.expire_at = expire_at;
+        ObjectFormat::Native.record(&mut metadata);
+        self.check_quota(bucket, &[(key, metadata.size)], true)?;
+        with_retry("update_metadata", || METADATA_STORE.update_metadata(&self.user, bucket, key, &metadata))?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        Ok(())
     }
 
     pub fn append_metadata(&self, bucket: &str, key: &str, offset_size_bytes: &[u8]) -> Result<(), Error> {
@@ -97,15 +369,58 @@ impl MetadataService {
     // --- Bucket management ---
 
     pub fn create_bucket(&self, bucket: &str) -> Result<(), Error> {
-        METADATA_STORE.create_bucket(&self.user, bucket)
+        METADATA_STORE.create_bucket(&self.user, bucket)?;
+        bucket_registry::remember(&self.user, bucket);
+        Ok(())
     }
 
     pub fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
-        METADATA_STORE.delete_bucket(&self.user, bucket)
+        bucket_registry::forget(&self.user, bucket);
+        METADATA_STORE.delete_bucket(&self.user, bucket)?;
+        bucket_access::invalidate(bucket);
+        bucket_quota::invalidate(bucket);
+        free_ranges::invalidate(&self.user, bucket);
+        prefetch::invalidate_bucket(&self.user, bucket);
+        key_filter::forget_bucket(&self.user, bucket);
+        Ok(())
     }
 
+    /// Whether `bucket` exists for this user: it was created (explicitly or by a first native
+    /// write), or, unless `STRICT_BUCKETS` is set, it holds objects without having been registered.
     pub fn bucket_exists(&self, bucket: &str) -> Result<bool, Error> {
-        METADATA_STORE.bucket_exists(&self.user, bucket)
+        if self.bucket_registered(bucket)? {
+            return Ok(true);
+        }
+        if BucketConfig::from_env().strict {
+            return Ok(false);
+        }
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().bucket_has_objects(&self.user, bucket)
+    }
+
+    /// Whether `bucket` has a row in the bucket registry
+    pub fn bucket_registered(&self, bucket: &str) -> Result<bool, Error> {
+        if bucket_registry::is_known(&self.user, bucket) {
+            return Ok(true);
+        }
+        let exists = METADATA_STORE.bucket_exists(&self.user, bucket)?;
+        if exists {
+            bucket_registry::remember(&self.user, bucket);
+        }
+        Ok(exists)
+    }
+
+    /// Make sure a native write may go into `bucket`: registers it on first use, or in
+    /// strict mode rejects buckets that were never created.
+    pub fn ensure_bucket_for_write(&self, bucket: &str, config: &BucketConfig) -> Result<(), Error> {
+        if self.bucket_registered(bucket)? {
+            return Ok(());
+        }
+        if config.strict {
+            return Err(NoSuchBucketError { bucket: bucket.to_string() }.into());
+        }
+        info!("Implicitly creating bucket {} for user {} on first write", bucket, self.user);
+        self.create_bucket(bucket)
     }
 
     pub fn list_all_buckets(&self) -> Result<Vec<String>, Error> {
@@ -124,23 +439,203 @@ impl MetadataService {
 
     // --- Deletion WAL ---
 
-    pub fn queue_deletion(&self, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
-        METADATA_STORE.queue_deletion(&self.user, bucket, key, offset_size_list)
+    pub fn queue_deletion(&self, bucket: &str, key: &str, offset_size_list: &[(u64, u64)]) -> Result<DeletionSummary, Error> {
+        // Inline and empty objects own no storage
+        if offset_size_list.is_empty() {
+            return Ok(DeletionSummary::default());
+        }
+        // The metadata may already be gone, so a refused insert is spilled rather than lost
+        METADATA_STORE.queue_deletion(&self.user, bucket, key, offset_size_list).or_else(|e| {
+            warn!("Deletion queue refused key: {} in bucket: {}: {}", key, bucket, e);
+            deletion_spill::spill(&self.user, bucket, key, offset_size_list).map_err(|spill_error| {
+                error!("Failed to spill deletion of key: {} in bucket: {}: {}", key, bucket, spill_error);
+                e
+            })
+        })
+    }
+
+    /// Point `dst_key` at `src_key`'s chunks; returns the extents of a replaced destination
+    pub fn clone_metadata(&self, bucket: &str, src_key: &str, dst_key: &str, overwrite: bool) -> Result<Vec<(u64, u64)>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        let size = self.latest(bucket, src_key)?.size;
+        self.check_quota(bucket, &[(dst_key, size)], overwrite)?;
+        let replaced = SQLiteMetadataStore::new().clone_metadata(&self.user, bucket, src_key, dst_key, overwrite)?;
+        prefetch::invalidate(&self.user, bucket, &[dst_key]);
+        key_filter::record(&self.user, bucket, &[dst_key]);
+        Ok(replaced)
     }
 
-    pub fn get_pending_deletions(&self, limit: i32) -> Result<Vec<crate::metadata::sqlite_store::DeletionEvent>, Error> {
+    /// Move `key` to `dst_bucket`, where its data now lives at `new_list`; see
+    /// [`SQLiteMetadataStore::move_object`](crate::metadata::sqlite_store::SQLiteMetadataStore::move_object).
+    pub fn move_object(&self, src_bucket: &str, key: &str, dst_bucket: &str, expected_list: &[(u64, u64)], new_list: &[(u64, u64)]) -> Result<(), Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().get_pending_deletions(limit)
+        self.check_quota(dst_bucket, &[(key, new_list.iter().map(|(_, size)| size).sum())], false)?;
+        SQLiteMetadataStore::new().move_object(&self.user, src_bucket, key, dst_bucket, expected_list, new_list)?;
+        prefetch::invalidate(&self.user, src_bucket, &[key]);
+        prefetch::invalidate(&self.user, dst_bucket, &[key]);
+        key_filter::record(&self.user, dst_bucket, &[key]);
+        Ok(())
+    }
+
+    /// The user's buckets other than `except` in which `key` is live
+    pub fn buckets_holding(&self, key: &str, except: &str) -> Result<Vec<String>, Error> {
+        let mut found = Vec::new();
+        for bucket in self.list_all_buckets()? {
+            if bucket != except && self.check_key(&bucket, key)? {
+                found.push(bucket);
+            }
+        }
+        Ok(found)
+    }
+
+    pub fn rebuild_chunk_refs(&self, bucket: &str) -> Result<usize, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().rebuild_chunk_refs(&self.user, bucket)
+    }
+
+    /// Ranges offered to deduplicated writes and bytes shared beyond their first reference
+    pub fn sharing_stats(&self, bucket: &str) -> Result<(u64, u64), Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().sharing_stats(&self.user, bucket)
+    }
+
+    pub fn get_pending_deletions(&self, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        METADATA_STORE.get_pending_deletions(limit)
+    }
+
+    pub fn get_pending_deletions_for_bucket(&self, bucket: &str, limit: i32) -> Result<Vec<DeletionEvent>, Error> {
+        METADATA_STORE.get_pending_deletions_for_bucket(&self.user, bucket, limit)
+    }
+
+    /// Deletion events of `bucket` the worker has not processed yet
+    pub fn pending_deletion_count_for_bucket(&self, bucket: &str) -> Result<u64, Error> {
+        METADATA_STORE.pending_deletion_count_for_bucket(&self.user, bucket)
+    }
+
+    /// Extents of `bucket` ever queued for deletion, freed or not
+    pub fn queued_extents(&self, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().queued_extents(&self.user, bucket)
+    }
+
+    /// Every range of `bucket` the deletion worker has freed, by offset
+    pub fn free_ranges(&self, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        METADATA_STORE.free_ranges(&self.user, bucket)
+    }
+
+    /// Ranges of `bucket` already freed that overlap `ranges`
+    pub fn free_ranges_overlapping(&self, bucket: &str, ranges: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error> {
+        METADATA_STORE.free_ranges_overlapping(&self.user, bucket, ranges)
+    }
+
+    /// Record `freed` as free in `bucket` and mark the deletion events `ids` processed
+    pub fn complete_deletions(&self, bucket: &str, freed: &[(u64, u64)], ids: &[i64]) -> Result<(), Error> {
+        METADATA_STORE.complete_deletions(&self.user, bucket, freed, ids)?;
+        free_ranges::invalidate(&self.user, bucket);
+        Ok(())
+    }
+
+    /// Forget the free ranges of an unlinked segment file of `bucket`
+    pub fn forget_free_ranges(&self, bucket: &str, segment: u32) -> Result<(), Error> {
+        METADATA_STORE.forget_free_ranges(&self.user, bucket, segment)?;
+        free_ranges::invalidate(&self.user, bucket);
+        Ok(())
+    }
+
+    /// Every user owning a bucket; not scoped to this service's user
+    pub fn list_users(&self) -> Result<Vec<String>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().list_users()
+    }
+
+    /// Lifecycle state of `key`, or None when there is no trace of it
+    pub fn object_state(&self, bucket: &str, key: &str) -> Result<Option<ObjectState>, Error> {
+        if self.check_key(bucket, key)? {
+            return Ok(Some(ObjectState::Active));
+        }
+        if with_retry("deletion_pending", || METADATA_STORE.deletion_pending(&self.user, bucket, key))? {
+            return Ok(Some(ObjectState::Deleting));
+        }
+        Ok(None)
+    }
+
+    /// Every stored row of `key` with internal columns, newest first (admin dump).
+    pub fn full_record(&self, bucket: &str, key: &str) -> Result<Vec<ObjectRecord>, Error> {
+        METADATA_STORE.get_full_record(&self.user, bucket, key)
+    }
+
+    /// One page of the bucket's rows after `after_row_id`, in row order (admin dump).
+    pub fn full_records_page(&self, bucket: &str, after_row_id: i64, limit: usize) -> Result<Vec<ObjectRecord>, Error> {
+        METADATA_STORE.list_full_records(&self.user, bucket, after_row_id, limit)
     }
 
     pub fn mark_deletion_processed(&self, id: i64) -> Result<(), Error> {
+        METADATA_STORE.mark_deletion_processed(id)
+    }
+
+    pub fn pending_deletion_count(&self) -> Result<u64, Error> {
+        METADATA_STORE.pending_deletion_count()
+    }
+
+    /// Bytes held by deletion events the worker has not processed yet
+    pub fn pending_deletion_bytes(&self) -> Result<u64, Error> {
+        METADATA_STORE.pending_deletion_bytes()
+    }
+
+    pub fn cleanup_old_deletions(&self, retention_days: u32, batch: usize) -> Result<usize, Error> {
+        METADATA_STORE.cleanup_old_deletions(retention_days, batch)
+    }
+
+    /// Extents of `bucket` still referenced by metadata or already queued for deletion.
+    /// Atomically swap one chunk of `key` from `old` to `new`; false if it no longer points at `old`.
+    pub fn replace_chunk(&self, bucket: &str, key: &str, index: usize, old: (u64, u64), new: (u64, u64)) -> Result<bool, Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().mark_deletion_processed(id)
+        let replaced = SQLiteMetadataStore::new().replace_chunk(&self.user, bucket, key, index, old, new)?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        Ok(replaced)
     }
 
-    pub fn cleanup_old_deletions(&self) -> Result<usize, Error> {
+    pub fn referenced_extents(&self, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().cleanup_old_deletions()
+        SQLiteMetadataStore::new().referenced_extents(&self.user, bucket)
+    }
+
+    /// Extents of `bucket` whose bytes are still needed (not yet freed by the deletion worker).
+    pub fn live_extents(&self, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        METADATA_STORE.live_extents(&self.user, bucket)
+    }
+
+    pub fn pending_tier_migration_keys(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().pending_tier_migration_keys(&self.user, bucket)
+    }
+
+    pub fn object_extents_for_key(&self, bucket: &str, key: &str) -> Result<Vec<crate::metadata::sqlite_store::ObjectExtentsRow>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().object_extents_for_key(&self.user, bucket, key)
+    }
+
+    pub fn commit_key_migration(&self, bucket: &str, key: &str, rows: &[(crate::metadata::sqlite_store::ObjectExtentsRow, Vec<(u64, u64)>)]) -> Result<(), Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().commit_key_migration(&self.user, bucket, key, rows)?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        Ok(())
+    }
+
+    pub fn object_extents_in_bucket(&self, bucket: &str) -> Result<Vec<crate::metadata::sqlite_store::ObjectExtentsRow>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().object_extents_in_bucket(&self.user, bucket)
+    }
+
+    /// Publish one compaction pass; see [`SQLiteMetadataStore::commit_relocation`](crate::metadata::sqlite_store::SQLiteMetadataStore::commit_relocation)
+    pub fn commit_relocation(
+        &self, bucket: &str, rows: &[(crate::metadata::sqlite_store::ObjectExtentsRow, Vec<(u64, u64)>)],
+        moves: &[crate::metadata::sqlite_store::ExtentMove],
+    ) -> Result<usize, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        let rewritten = SQLiteMetadataStore::new().commit_relocation(&self.user, bucket, rows, moves)?;
+        prefetch::invalidate_bucket(&self.user, bucket);
+        Ok(rewritten)
     }
 
     // --- CORS ---
@@ -160,6 +655,39 @@ impl MetadataService {
         SQLiteMetadataStore::new().delete_bucket_cors(bucket)
     }
 
+    // --- Bucket access flags ---
+
+    pub fn get_bucket_access(&self, bucket: &str) -> Result<Option<crate::metadata::sqlite_store::BucketAccess>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().get_bucket_access(&self.user, bucket)
+    }
+
+    /// Returns false when the bucket does not exist.
+    pub fn set_bucket_access(&self, bucket: &str, access: &crate::metadata::sqlite_store::BucketAccess) -> Result<bool, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        let updated = SQLiteMetadataStore::new().set_bucket_access(&self.user, bucket, access)?;
+        bucket_access::invalidate(bucket);
+        Ok(updated)
+    }
+
+    // --- Bucket quotas ---
+
+    pub fn get_bucket_limits(&self, bucket: &str) -> Result<Option<crate::metadata::sqlite_store::BucketLimits>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().get_bucket_limits(&self.user, bucket)
+    }
+
+    /// Returns false when the bucket does not exist.
+    pub fn set_bucket_limits(&self, bucket: &str, limits: &crate::metadata::sqlite_store::BucketLimits) -> Result<bool, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        let updated = SQLiteMetadataStore::new().set_bucket_limits(&self.user, bucket, limits)?;
+        bucket_quota::invalidate(bucket);
+        if updated {
+            bucket_quota::record_usage(&self.user, bucket, limits, self.bucket_usage(bucket)?);
+        }
+        Ok(updated)
+    }
+
     // --- Bucket location ---
 
     pub fn set_bucket_location(&self, bucket: &str, location: &str) -> Result<(), Error> {
@@ -188,14 +716,20 @@ impl MetadataService {
         -> Result<crate::metadata::sqlite_store::VersioningDeleteResult, Error>
     {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().delete_object_v2(&self.user, bucket, key)
+        let result = SQLiteMetadataStore::new().delete_object_v2(&self.user, bucket, key)?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        Ok(result)
     }
 
     pub fn delete_specific_version(&self, bucket: &str, key: &str, version_id: &str)
         -> Result<crate::metadata::sqlite_store::DeleteSpecificResult, Error>
     {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().delete_specific_version(&self.user, bucket, key, version_id)
+        let result = SQLiteMetadataStore::new().delete_specific_version(&self.user, bucket, key, version_id)?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        // Removing a delete marker makes the key live again
+        key_filter::record(&self.user, bucket, &[key]);
+        Ok(result)
     }
 
     pub fn get_object_version(&self, bucket: &str, key: &str, version_id: &str)
@@ -205,6 +739,23 @@ impl MetadataService {
         SQLiteMetadataStore::new().get_object_version(&self.user, bucket, key, version_id)
     }
 
+    /// The row of `key` that was latest at `as_of` (a stored timestamp), which may be a delete
+    /// marker; None if the key had no row yet.
+    pub fn object_as_of(&self, bucket: &str, key: &str, as_of: &str) -> Result<Option<crate::metadata::Metadata>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        let store = SQLiteMetadataStore::new();
+        match store.version_as_of(&self.user, bucket, key, as_of)? {
+            Some(version_id) => store.get_object_version(&self.user, bucket, key, &version_id).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// (key, version id) of up to `limit` keys after `after` live at `as_of`, in key order.
+    pub fn versions_as_of(&self, bucket: &str, as_of: &str, after: &str, limit: usize) -> Result<Vec<(String, String)>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().versions_as_of(&self.user, bucket, as_of, after, limit)
+    }
+
     /// Returns the last_modified of the is_latest=1 row, including delete markers.
     pub fn get_latest_last_modified(&self, bucket: &str, key: &str) -> Result<Option<String>, Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
@@ -233,11 +784,22 @@ impl MetadataService {
         SQLiteMetadataStore::new().get_object_tags(&self.user, bucket, key)
     }
 
+    /// Tags of each of `keys` that has any, by key
+    pub fn get_tags_for_keys(&self, bucket: &str, keys: &[&str]) -> Result<std::collections::HashMap<String, Vec<(String, String)>>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().get_tags_for_keys(&self.user, bucket, keys)
+    }
+
     pub fn delete_object_tags(&self, bucket: &str, key: &str) -> Result<(), Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
         SQLiteMetadataStore::new().delete_object_tags(&self.user, bucket, key)
     }
 
+    pub fn list_objects_with_tag(&self, bucket: &str, tag_key: &str, tag_value: &str) -> Result<Vec<String>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().list_objects_with_tag(&self.user, bucket, tag_key, tag_value)
+    }
+
     pub fn get_object_tag_count(&self, bucket: &str, key: &str) -> Result<i64, Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
         SQLiteMetadataStore::new().get_object_tag_count(&self.user, bucket, key)
@@ -270,20 +832,39 @@ impl MetadataService {
 
     // --- Multipart upload management ---
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_multipart_upload(
         &self, upload_id: &str, bucket: &str, key: &str,
         content_type: Option<&str>, metadata_json: &str, initiated_at: &str,
         checksum_algorithm: &str, checksum_type: &str,
         object_lock_mode: &str, object_lock_retain_until: &str, object_lock_legal_hold: &str,
-    ) -> Result<(), Error> {
+        max_in_progress: Option<u64>,
+    ) -> Result<bool, Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
         SQLiteMetadataStore::new().create_multipart_upload(
             upload_id, &self.user, bucket, key, content_type, metadata_json, initiated_at,
             checksum_algorithm, checksum_type,
             object_lock_mode, object_lock_retain_until, object_lock_legal_hold,
+            max_in_progress,
         )
     }
 
+    pub fn multipart_uploads_in_progress(&self) -> Result<Vec<(String, u64)>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().multipart_uploads_in_progress(&self.user)
+    }
+
+    /// Bytes of the parts of in-progress uploads by bucket
+    pub fn multipart_bytes_by_bucket(&self) -> Result<Vec<(String, u64)>, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().multipart_bytes_by_bucket(&self.user)
+    }
+
+    pub fn multipart_part_bytes(&self, upload_id: &str, except_part: i32) -> Result<u64, Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().multipart_part_bytes(upload_id, except_part)
+    }
+
     pub fn get_multipart_upload(&self, upload_id: &str)
         -> Result<Option<crate::metadata::sqlite_store::MultipartUploadRow>, Error>
     {
@@ -313,12 +894,33 @@ impl MetadataService {
         SQLiteMetadataStore::new().list_bucket_multipart_uploads(bucket)
     }
 
+    /// Store part `part_number` of `upload_id`, an upload to `bucket`. The byte quota is
+    /// checked again in the transaction that inserts the part, so concurrent parts cannot
+    /// take the bucket past it together; a refused part is not stored.
+    #[allow(clippy::too_many_arguments)]
     pub fn upsert_multipart_part(
-        &self, upload_id: &str, part_number: i32, etag: &str, size: u64, extents_blob: &[u8],
+        &self, bucket: &str, upload_id: &str, part_number: i32, etag: &str, size: u64, extents_blob: &[u8],
         checksum_value: &str,
     ) -> Result<(), Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().upsert_multipart_part(upload_id, part_number, etag, size, extents_blob, checksum_value)
+        let store = SQLiteMetadataStore::new();
+        let except = self.completing.as_deref();
+        let limits = bucket_quota::limits(&self.user, bucket)?;
+        if limits.is_unlimited() {
+            return store.upsert_multipart_part(upload_id, part_number, etag, size, extents_blob, checksum_value, except, |_, _| Ok(()));
+        }
+        let (objects, object_bytes) = self.bucket_object_stats(bucket)?;
+        let mut after = None;
+        store.upsert_multipart_part(upload_id, part_number, etag, size, extents_blob, checksum_value, except, |other_parts, replaced| {
+            let usage = Usage { objects, bytes: object_bytes + other_parts + replaced };
+            bucket_quota::check(bucket, &limits, usage, 0, size as i64 - replaced as i64)?;
+            after = Some(Usage { objects, bytes: object_bytes + other_parts + size });
+            Ok(())
+        })?;
+        if let Some(after) = after {
+            bucket_quota::record_usage(&self.user, bucket, &limits, after);
+        }
+        Ok(())
     }
 
     pub fn list_multipart_parts(&self, upload_id: &str)
@@ -340,7 +942,9 @@ impl MetadataService {
 
     pub fn set_parts_manifest(&self, bucket: &str, key: &str, manifest: &str) -> Result<(), Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().set_parts_manifest(&self.user, bucket, key, manifest)
+        SQLiteMetadataStore::new().set_parts_manifest(&self.user, bucket, key, manifest)?;
+        prefetch::invalidate(&self.user, bucket, &[key]);
+        Ok(())
     }
 
     // --- Object Lock ---
@@ -357,10 +961,12 @@ impl MetadataService {
 
     pub fn create_bucket_with_lock(&self, bucket: &str, lock_enabled: bool) -> Result<(), Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        SQLiteMetadataStore::new().create_bucket_with_lock(&self.user, bucket, lock_enabled)
+        SQLiteMetadataStore::new().create_bucket_with_lock(&self.user, bucket, lock_enabled)?;
+        bucket_registry::remember(&self.user, bucket);
+        Ok(())
     }
 
-    pub fn get_object_lock_config(&self, bucket: &str) -> Result<Option<(String, Option<i64>, Option<i64>)>, Error> {
+    pub fn get_object_lock_config(&self, bucket: &str) -> Result<Option<crate::metadata::sqlite_store::ObjectLockConfig>, Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
         SQLiteMetadataStore::new().get_object_lock_config(bucket)
     }
@@ -385,6 +991,11 @@ impl MetadataService {
         SQLiteMetadataStore::new().put_object_lock(bucket, key, version_id, mode, retain_until_date, legal_hold)
     }
 
+    pub fn delete_object_lock(&self, bucket: &str, key: &str, version_id: &str) -> Result<(), Error> {
+        use crate::metadata::sqlite_store::SQLiteMetadataStore;
+        SQLiteMetadataStore::new().delete_object_lock(bucket, key, version_id)
+    }
+
     pub fn set_object_legal_hold(&self, bucket: &str, key: &str, version_id: &str, status: &str) -> Result<(), Error> {
         use crate::metadata::sqlite_store::SQLiteMetadataStore;
         SQLiteMetadataStore::new().set_object_legal_hold(bucket, key, version_id, status)
@@ -401,7 +1012,7 @@ impl MetadataService {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::util::serializer::serialize_offset_size;
+    use crate::util::serializer::encode_extents;
     use std::env;
 
     #[test]
@@ -416,7 +1027,7 @@ mod tests {
         assert!(service.check_key_nonexistance("default", key).is_err());
 
         let offset_size_list = vec![(100u64, 200u64), (300, 400)];
-        let offset_size_bytes = serialize_offset_size(&offset_size_list).unwrap();
+        let offset_size_bytes = encode_extents(&offset_size_list).unwrap();
 
         service.write_metadata("default", key, &offset_size_bytes).unwrap();
         assert!(service.check_key("default", key).unwrap());
@@ -3,29 +3,149 @@ pub mod metadata_service;
 pub mod user_context;
 pub mod storage_service;
 pub mod deletion_worker;
+pub mod deletion_spill;
+pub mod free_ranges;
+pub mod gc;
+pub mod fsck;
+pub mod jobs;
+pub mod export;
+pub mod import;
+pub mod stats;
+pub mod bucket_registry;
+pub mod bucket_access;
+pub mod bucket_quota;
+pub mod append_dedup;
+pub mod repair;
+pub mod tiering;
+pub mod native_auth;
+pub mod admin_tokens;
+pub mod batch_get;
+pub mod batch_put;
+pub mod key_limits;
+pub mod db_maintenance;
+pub mod error;
+pub mod object_format;
+pub mod object_state;
+pub mod object_tags;
+pub mod capabilities;
+pub mod maintenance;
+pub mod user_limits;
+pub mod config_generation;
+pub mod expiry;
+pub mod native_payload;
+pub mod inflight;
+pub mod prefetch;
+pub mod key_filter;
+pub mod change_log;
+pub mod layout_migration;
+pub mod legacy_migration;
+pub mod instance_identity;
+pub mod retry;
+pub mod txn;
+pub mod leases;
+pub mod payload_digest;
+pub mod inline_objects;
+pub mod self_test;
+pub mod inventory;
+pub mod dedup;
+pub mod compaction;
+pub mod user_health;
+pub mod access_tracking;
+pub mod object_service;
+pub mod validators;
+pub mod download;
+pub mod bucket_keys;
+pub mod checksums;
+pub mod multipart_limits;
+pub mod api_version;
+pub mod cpu_pool;
+pub mod bucket_grants;
+pub mod size_stats;
+pub mod response_fields;
+pub mod as_of;
+pub mod idempotency;
+pub mod upload_sessions;
 
 use actix_web::{ web, HttpResponse,Error, HttpRequest};
-use futures::StreamExt;
-use bytes::BytesMut;
-use log::{info, error, warn};
-use actix_web::error::{ErrorInternalServerError,ErrorBadRequest};
-use log_mdc;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use std::collections::HashMap;
+use log::{info, warn};
+use serde_json::json;
 
 
-use crate::service::storage_service::{StorageService, StorageMode};
+use crate::logging;
+use crate::service::storage_service::StorageService;
 use crate::service::metadata_service::MetadataService;
-use crate::service::user_context::UserContext;
-use crate::util::serializer::{serialize_offset_size, deserialize_offset_size};
+use crate::service::object_service::{missing_key_error, AppendOutcome, ObjectService};
+use crate::service::user_context::{UserContext, METADATA_DIRECTIVE_HEADER, METADATA_HEADER_PREFIX};
+use crate::util::serializer::{decode_extents, encode_chunks};
+use crate::util::byte_ranges::{self, ByteRanges, Multipart, RangeConfig};
+use crate::util::compression::{self, CompressionConfig};
+use crate::util::payload::{content_length, read_body, read_body_limited, read_control_body, PayloadLimits};
+use crate::storage::bucket_lock;
+use crate::service::bucket_registry::BucketConfig;
+use crate::service::native_auth::NativeAuthConfig;
+use crate::service::batch_get::{BatchGetConfig, BATCH_ERRORS_HEADER};
+use crate::service::batch_put::BatchPutConfig;
+use crate::service::txn::TxnConfig;
+use crate::service::payload_digest::Direction;
+use crate::service::response_fields::{ResponseFields, SIZE_HEADER};
+use crate::service::error::ServiceError;
+use crate::service::object_format::{ObjectFormat, OBJECT_FORMAT_HEADER};
+use crate::service::object_state::{ObjectState, OBJECT_STATE_HEADER};
+use crate::service::download::ResumeToken;
+use crate::service::object_tags::{check_tags, parse_tag_filter};
+use crate::service::key_limits::KeyLimits;
+use crate::service::append_dedup::{AppendRecord, APPEND_ID_HEADER};
+use crate::service::idempotency::{IDEMPOTENCY_KEY_HEADER, REPLAYED_HEADER};
+use crate::service::validators::GENERATION_HEADER;
+use crate::service::api_version::ApiVersion;
+use crate::service::bucket_grants::Permission;
+use crate::metadata::{http_date, DataChunk, Metadata};
 
 
 fn header_handler(req: HttpRequest) -> Result<UserContext, Error> {
-    let user_id = req.headers()
-        .get("User")
-        .ok_or_else(|| ErrorBadRequest("Missing User header"))?
-        .to_str()
-        .map_err(|_| ErrorBadRequest("Invalid User header value"))?
-        .to_string();
-    
+    let context = authenticated_context(&req)?;
+    let needed = bucket_grants::required(&req);
+    granted_context(&req, context, needed)
+}
+
+/// Context of the authenticated user, on the `Bucket` header's bucket
+fn authenticated_context(req: &HttpRequest) -> Result<UserContext, Error> {
+    let user_id = native_auth::request_user(req, &NativeAuthConfig::from_env())?
+        .ok_or_else(|| ServiceError::InvalidRequest("Missing User header".to_string()))?;
+    user_context(req, user_id)
+}
+
+/// `context` working in the owner's namespace when its bucket is granted to the user, if the
+/// grant allows `needed`
+fn granted_context(req: &HttpRequest, context: UserContext, needed: Permission) -> Result<UserContext, Error> {
+    let owner = req.headers()
+        .get(bucket_grants::BUCKET_OWNER_HEADER)
+        .map(|h| h.to_str().map(str::trim))
+        .transpose()
+        .map_err(|_| ServiceError::InvalidRequest("Invalid Bucket-Owner header value".to_string()))?;
+    bucket_grants::resolve(context, owner, needed)
+}
+
+/// `header_handler`, then count the request against the user's concurrency limit.
+async fn admitted_context(req: HttpRequest) -> Result<UserContext, Error> {
+    let context = header_handler(req.clone())?;
+    user_limits::admit(&req, context.authenticated_user(), false).await?;
+    Ok(context)
+}
+
+/// [`admitted_context`] for a route naming `bucket` in its path, which must allow `needed`
+async fn admitted_bucket_context(req: HttpRequest, bucket: &str, needed: Permission) -> Result<UserContext, Error> {
+    let mut context = authenticated_context(&req)?;
+    context.bucket = bucket.to_string();
+    let context = granted_context(&req, context, needed)?;
+    user_limits::admit(&req, context.authenticated_user(), false).await?;
+    Ok(context)
+}
+
+fn user_context(req: &HttpRequest, user_id: String) -> Result<UserContext, Error> {
     // Extract bucket from header, default to "default"
     let bucket = req.headers()
         .get("Bucket")
@@ -33,227 +153,918 @@ fn header_handler(req: HttpRequest) -> Result<UserContext, Error> {
         .unwrap_or("default")
         .to_string();
     
-    log_mdc::insert("user", &user_id);
-    log_mdc::insert("bucket", &bucket);
+    logging::bind_user(&user_id);
     
     let mut context = UserContext::with_bucket(user_id, bucket);
+    context.lease = req.headers()
+        .get(leases::LEASE_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|token| token.trim().to_string());
     
-    // Extract any additional headers as metadata
+    // Only x-warp-meta-* headers become metadata; header names arrive lowercased
     for (header_name, header_value) in req.headers() {
-        if let Ok(value_str) = header_value.to_str() {
-            if header_name.as_str() != "user" && header_name.as_str() != "bucket" {
-                context.set_metadata(header_name.as_str().to_string(), value_str.to_string());
-            }
+        if header_name.as_str() == METADATA_DIRECTIVE_HEADER {
+            continue;
         }
+        let Some(name) = header_name.as_str().strip_prefix(METADATA_HEADER_PREFIX) else { continue };
+        let value = header_value.to_str().map_err(|_| {
+            ServiceError::InvalidRequest(format!("Header {} must be visible ASCII", header_name))
+        })?;
+        context.set_metadata(name.to_string(), value.to_string())?;
     }
-    
+
     Ok(context)
 }
 
-pub async fn put_service(key: String, mut payload: web::Payload, req: HttpRequest) -> Result<HttpResponse, Error>{
+pub async fn put_service(key: String, payload: web::Payload, req: HttpRequest) -> Result<HttpResponse, Error>{
 
-    let context = header_handler(req)?;
+    let expire_at = expiry::requested(&req)?;
+    let expected_len = content_length(&req);
+    let sample = payload_digest::sample(&req);
+    let version = ApiVersion::of(&req);
+    let idempotency_key = req.headers().get(IDEMPOTENCY_KEY_HEADER).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let limits = PayloadLimits::of(&req);
+    let context = admitted_context(req).await?;
     info!("PUT service called for user: {}, bucket: {}, key: {}", context.user_id, context.bucket, key);
 
-    let db = MetadataService::new(&context.user_id)?;
-    info!("MetadataService created for user: {}", context.user_id);
-    
-    let key_exists = db.check_key(&context.bucket, &key).map_err(ErrorInternalServerError)?;
-    info!("Key exists check result: {} for key: {} in bucket: {}", key_exists, key, context.bucket);
-    
-    if key_exists {
-        warn!("Key already exists: {} in bucket: {}", key, context.bucket);
-        return Ok(HttpResponse::BadRequest().body("Key already exists"));
-    }
+    let digest = sample.map(|s| s.digest(&context.user_id, &context.bucket, &key, Direction::Upload));
+    let body = read_body(payload_digest::wrap(payload, digest), expected_len, limits);
+    let outcome = ObjectService::new().put_idempotent(&context, &key, body, expire_at, idempotency_key.as_deref()).await?;
 
-    info!("Starting chunk load for user: {}, bucket: {}", context.user_id, context.bucket);
-    let mut bytes = BytesMut::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.map_err(ErrorInternalServerError)?;
-        bytes.extend_from_slice(&chunk);
+    info!("Data uploaded successfully with key: {} in bucket: {}", key, context.bucket);
+    let mut response = uploaded(version, &context, &key)?;
+    if outcome.replayed {
+        response.headers_mut().insert(HeaderName::from_static(REPLAYED_HEADER), HeaderValue::from_static("true"));
     }
+    Ok(response)
+}
 
-    if bytes.is_empty() {
-        error!("No data uploaded with key: {}", key);
-        return Ok(HttpResponse::BadRequest().body("No data was uploaded"));
+/// The answer to a PUT or update of `key`: text in v1, JSON in v2
+fn uploaded(version: ApiVersion, context: &UserContext, key: &str) -> Result<HttpResponse, Error> {
+    let generation = generation_of(context, key)?;
+    let mut response = HttpResponse::Ok();
+    response.insert_header((GENERATION_HEADER, generation.to_string()));
+    if version.json_bodies() {
+        return Ok(response.json(json!({ "bucket": context.bucket, "key": key, "generation": generation })));
     }
+    Ok(response.body(format!("Data uploaded successfully: key = {}, bucket = {}", key, context.bucket)))
+}
 
-    info!("Total received data size: {} bytes", bytes.len());
-
-    // Write incoming FlatBuffers payload to storage and collect (offset, size)
-    let storage_service = StorageService::new();
-    let offset_size_list = storage_service.write_object(&context, &bytes, StorageMode::Native)?;
-
+/// Generation of `key` as a write just left it, for the response to the write
+fn generation_of(context: &UserContext, key: &str) -> Result<u64, Error> {
+    Ok(MetadataService::new(&context.user_id)?.get_object_full(&context.bucket, key)?.generation)
+}
 
-    if offset_size_list.is_empty()  {
-        error!("No data in data list with key: {}", key);
-        return Ok(HttpResponse::BadRequest().body("No data in data list"));
+/// Chunk indices selected with `?chunk=N` or `?chunks=A-B` (inclusive, zero-based).
+fn chunk_selection(req: &HttpRequest) -> Result<Option<(usize, usize)>, Error> {
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(|e| ServiceError::InvalidRequest(e.to_string()))?;
+    let invalid = |v: &str| ServiceError::InvalidRequest(format!("Invalid chunk selection: {}", v));
+    match (query.get("chunk"), query.get("chunks")) {
+        (None, None) => Ok(None),
+        (Some(_), Some(_)) => Err(ServiceError::InvalidRequest("Use either chunk or chunks, not both".to_string()).into()),
+        (Some(index), None) => {
+            let index = index.parse().map_err(|_| invalid(index))?;
+            Ok(Some((index, index)))
+        }
+        (None, Some(range)) => {
+            let (first, last) = range.split_once('-').ok_or_else(|| invalid(range))?;
+            let first: usize = first.parse().map_err(|_| invalid(range))?;
+            let last: usize = last.parse().map_err(|_| invalid(range))?;
+            if first > last {
+                return Err(invalid(range).into());
+            }
+            Ok(Some((first, last)))
+        }
     }
+}
 
-    info!("Serializing offset and size and uploading");
-
-    info!("Serializing offset_size_list with {} entries", offset_size_list.len());
-    let offset_size_bytes = serialize_offset_size(&offset_size_list)?;
-    info!("Successfully serialized offset_size_bytes, size: {} bytes", offset_size_bytes.len());
+/// Whether `X-Warp-Meta-Directive` asks native update to keep the object's metadata:
+/// `COPY` does, `REPLACE` or no header does not.
+fn metadata_directive(req: &HttpRequest) -> Result<bool, Error> {
+    let Some(value) = req.headers().get(METADATA_DIRECTIVE_HEADER) else {
+        return Ok(false);
+    };
+    match value.to_str().map(|v| v.trim().to_ascii_uppercase()).as_deref() {
+        Ok("COPY") => Ok(true),
+        Ok("REPLACE") => Ok(false),
+        _ => Err(ServiceError::InvalidRequest(format!("Unknown {}: {:?}", METADATA_DIRECTIVE_HEADER, value)).into()),
+    }
+}
 
-    info!("Writing metadata for user: {}, bucket: {}, key: {}", context.user_id, context.bucket, key);
-    db.write_metadata(&context.bucket, &key, &offset_size_bytes)
-        .map_err(|e| {
-            error!("Failed to write metadata for user: {}, bucket: {}, key: {}: {}", context.user_id, context.bucket, key, e);
-            ErrorInternalServerError(e)
-        })?;
-    info!("Successfully wrote metadata for user: {}, bucket: {}, key: {}", context.user_id, context.bucket, key);
+/// `?as_of=` of a request as a stored timestamp (`service::as_of`)
+fn as_of_time(req: &HttpRequest) -> Result<Option<String>, Error> {
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(|e| ServiceError::InvalidRequest(e.to_string()))?;
+    Ok(query.get("as_of").map(|value| as_of::parse(value)).transpose()?)
+}
 
-    info!("Data uploaded successfully with key: {} in bucket: {}", key, context.bucket);
-    Ok(HttpResponse::Ok().body(format!("Data uploaded successfully: key = {}, bucket = {}", key, context.bucket)))
+/// `?format=native|raw` on native GET, overriding the stored representation
+fn format_override(req: &HttpRequest) -> Result<Option<ObjectFormat>, Error> {
+    let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map_err(|e| ServiceError::InvalidRequest(e.to_string()))?;
+    match query.get("format") {
+        Some(format) => Ok(Some(format.parse().map_err(ServiceError::InvalidRequest)?)),
+        None => Ok(None),
+    }
 }
 
 pub async fn get_service(key: String, req: HttpRequest)-> Result<HttpResponse, Error>{
 
-    let context = header_handler(req)?;
+    let selection = chunk_selection(&req)?;
+    let requested_format = format_override(&req)?;
+    let as_of = as_of_time(&req)?;
+    let version = ApiVersion::of(&req);
+    let range = req.headers().get("range").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let if_none_match = req.headers().get("if-none-match").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let accept_encoding = compression::accept_encoding(&req).map(str::to_string);
+    let prefetch_count = prefetch::requested(&req);
+    let sample = payload_digest::sample(&req);
+    let fields = ResponseFields::of(&req);
+    let context = admitted_context(req).await?;
 
-    let db = MetadataService::new(&context.user_id)?;
-    db.check_key_nonexistance(&context.bucket, &key)?;
-    info!("Retrieving data for key: {} in bucket: {}", key, context.bucket);
-
-    // Connect to the SQLite database and retrieve offset and size data
-    let offset_size_bytes = match db.read_metadata(&context.bucket, &key) {
-        Ok(offset_size_bytes) => offset_size_bytes,
-        Err(e) => {
-            warn!("Key does not exist or database error: {}", e);
-            return Ok(HttpResponse::BadRequest().body("Key does not exist"));
+    let objects = ObjectService::new();
+    let mut read = match &as_of {
+        Some(as_of) => objects.get_as_of(&context, &key, as_of, selection)?,
+        None => objects.get(&context, &key, selection)?,
+    };
+    read.indexed = version.indexed_payloads();
+    let metadata = &read.metadata;
+    let format = requested_format.unwrap_or_else(|| if version.raw_by_default() { ObjectFormat::Raw } else { ObjectFormat::of(metadata) });
+    let mut response = HttpResponse::Ok();
+    let etag = validators::etag(metadata);
+    response.insert_header(("ETag", etag.as_str()));
+    response.insert_header((GENERATION_HEADER, metadata.generation.to_string()));
+    if let Some(last_modified) = &metadata.last_modified {
+        response.insert_header(("Last-Modified", http_date(last_modified)));
+    }
+    if if_none_match.as_deref().is_some_and(|tags| validators::none_match(tags, &etag)) {
+        return Ok(fields.apply(response.status(StatusCode::NOT_MODIFIED).finish()));
+    }
+    response.insert_header((SIZE_HEADER, metadata.size.to_string()));
+    // Range applies to raw bytes; a FlatBuffers payload is always sent whole
+    let mut ranges = ByteRanges::Full;
+    let (content_type, mut data) = match format {
+        // Build FlatBuffers payload from stored chunks (v2 when file names were recorded or under the v2 API)
+        ObjectFormat::Native => ("application/octet-stream".to_string(), objects.read_native_pooled(&context, &read).await?),
+        ObjectFormat::Raw => {
+            let content_type = metadata.content_type.clone().unwrap_or_else(|| "application/octet-stream".to_string());
+            let raw_len = read.raw_len();
+            ranges = byte_ranges::parse(range.as_deref(), raw_len, &RangeConfig::from_env());
+            match &ranges {
+                ByteRanges::Full => (content_type, objects.read_raw_pooled(&context, &read, None).await?),
+                ByteRanges::Single(first, last) => {
+                    response.status(StatusCode::PARTIAL_CONTENT);
+                    response.insert_header(("Content-Range", format!("bytes {}-{}/{}", first, last, raw_len)));
+                    (content_type, objects.read_raw_pooled(&context, &read, Some((*first, *last))).await?)
+                }
+                // Several ranges are framed as multipart/byteranges, each part typed as the object
+                ByteRanges::Multiple(ranges) => {
+                    let mut parts = Vec::with_capacity(ranges.len());
+                    for &range in ranges {
+                        parts.push(objects.read_raw_pooled(&context, &read, Some(range)).await?);
+                    }
+                    let multipart = Multipart::new(&content_type, raw_len);
+                    response.status(StatusCode::PARTIAL_CONTENT);
+                    (multipart.content_type(), multipart.body(ranges, &parts))
+                }
+                ByteRanges::Unsatisfiable => {
+                    return Err(ServiceError::RangeNotSatisfiable { range: range.unwrap_or_default(), size: raw_len }.into());
+                }
+            }
         }
     };
+    payload_digest::digest_bytes(sample.map(|s| s.digest(&context.user_id, &context.bucket, &key, Direction::Download)), &data);
 
-    // Deserialize offset and size data
-    let offset_size_list = deserialize_offset_size(&offset_size_bytes)?;
+    if format == ObjectFormat::Raw {
+        response.insert_header(("Accept-Ranges", "bytes"));
+    }
+    if let Some(expire_at) = &metadata.expire_at {
+        response.insert_header((expiry::EXPIRE_AT_HEADER, expiry::header_value(expire_at)));
+    }
+    for (name, value) in metadata.meta_properties() {
+        response.insert_header((format!("{}{}", METADATA_HEADER_PREFIX, name), value));
+    }
+    // Raw objects of text-like types are compressed for clients that accept it; ones
+    // uploaded with a Content-Encoding are sent as stored
+    if format == ObjectFormat::Raw {
+        let config = CompressionConfig::from_env();
+        let stored_encoding = metadata.content_encoding.as_deref().filter(|e| !e.is_empty());
+        let whole = selection.is_none() && ranges == ByteRanges::Full;
+        match (stored_encoding, whole) {
+            (Some(encoding), true) => {
+                response.insert_header(("Content-Encoding", encoding));
+            }
+            (None, true) if config.compressible(&content_type) => {
+                response.insert_header(("Vary", "Accept-Encoding"));
+                if let Some(codec) = config.negotiate(accept_encoding.as_deref(), &content_type, data.len() as u64, None) {
+                    data = cpu_pool::run(data.len(), move || compression::compress(&data, codec).map_err(|e| ServiceError::Internal(e.to_string()).into())).await?.into();
+                    response.insert_header(("Content-Encoding", codec.as_str()));
+                }
+            }
+            _ => {}
+        }
+    }
+    access_tracking::record(&context.user_id, &context.bucket, &key);
+    prefetch::schedule(&context.user_id, &context.bucket, &key, prefetch_count);
+    Ok(fields.apply(response
+        .content_type(content_type)
+        .insert_header((OBJECT_STATE_HEADER, ObjectState::Active.as_str()))
+        .insert_header((OBJECT_FORMAT_HEADER, format.as_str()))
+        .body(data)))
+}
 
-    // Build FlatBuffers payload from stored chunks
-    let storage_service = StorageService::new();
-    let data = storage_service.read_object(&context, &offset_size_list, StorageMode::Native)?;
+pub async fn download_service(key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|q| q.into_inner())
+        .unwrap_or_default();
+    let range = req.headers().get("range").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let context = admitted_context(req).await?;
+    let resume = query.get("resume").map(|token| ResumeToken::verify(token, &context, &key)).transpose()?;
 
-    // Return the FlatBuffers serialized data
+    let objects = ObjectService::new();
+    let read = objects.get(&context, &key, None)?;
+    let metadata = &read.metadata;
+    if let Some(token) = resume.filter(|token| token.generation != metadata.generation) {
+        return Err(ServiceError::GenerationChanged {
+            bucket: context.bucket.clone(), key, expected: token.generation, current: metadata.generation,
+        }.into());
+    }
+    let total = read.raw_len();
+    let span = download::span(resume.as_ref(), range.as_deref(), total)?;
+    let (first, last) = span.unwrap_or((0, total.saturating_sub(1)));
+    info!("DOWNLOAD key: {} in bucket: {} from byte {} of {}", key, context.bucket, first, total);
+
+    let mut response = match span {
+        Some(_) => HttpResponse::PartialContent(),
+        None => HttpResponse::Ok(),
+    };
+    if span.is_some() {
+        response.insert_header(("Content-Range", format!("bytes {}-{}/{}", first, last, total)));
+    }
+    let token = ResumeToken { generation: metadata.generation, position: first };
+    response.insert_header((download::RESUME_TOKEN_HEADER, token.sign(&context, &key)));
+    response.insert_header(("ETag", validators::etag(metadata)));
+    response.insert_header((GENERATION_HEADER, metadata.generation.to_string()));
+    response.insert_header(("Accept-Ranges", "bytes"));
+    let content_type = metadata.content_type.clone().unwrap_or_else(|| "application/octet-stream".to_string());
+    response.content_type(content_type);
+    access_tracking::record(&context.user_id, &context.bucket, &key);
+    if total == 0 {
+        return Ok(response.finish());
+    }
+    response.no_chunking(last - first + 1);
+    Ok(response.streaming(download::body(&context, &key, &read, (first, last))))
+}
+
+pub async fn get_batch_service(payload: web::Payload, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let limits = PayloadLimits::of(&req);
+    let context = admitted_context(req).await?;
+    let config = BatchGetConfig::from_env();
+    let keys = batch_get::parse_keys(&read_control_body(payload, limits).await?, &config)?;
+    info!("GET BATCH called for user: {}, bucket: {}, keys: {}", context.user_id, context.bucket, keys.len());
+
+    let requested = keys.len();
+    let (data, errors) = batch_get::read_batch(&context, keys, &config).await?;
+    if !errors.is_empty() {
+        warn!("GET BATCH in bucket: {}: {} of {} keys not returned", context.bucket, errors.len(), requested);
+    }
     Ok(HttpResponse::Ok()
         .content_type("application/octet-stream")
+        .insert_header((BATCH_ERRORS_HEADER, batch_get::errors_header(&errors)))
         .body(data))
 }
 
-pub async fn append_service(key: String, mut payload: web::Payload, req: HttpRequest ) -> Result<HttpResponse, Error> {
-    let context = header_handler(req)?;
+pub async fn put_batch_service(payload: web::Payload, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let overwrite = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map(|q| q.get("overwrite").map(|v| v == "true").unwrap_or(false))
+        .unwrap_or(false);
+    let limits = PayloadLimits::of(&req);
+    let context = admitted_context(req).await?;
+    let config = BatchPutConfig::from_env();
+    let db = MetadataService::new(&context.user_id)?;
+    db.ensure_bucket_for_write(&context.bucket, &BucketConfig::from_env())?;
+
+    let bytes = read_body_limited(payload, config.max_bytes, limits.idle_timeout).await?;
+    let items = batch_put::parse_items(&bytes, &config)?;
+    info!("PUT BATCH called for user: {}, bucket: {}, files: {}, overwrite: {}",
+          context.user_id, context.bucket, items.len(), overwrite);
 
+    let keys: Vec<&str> = items.iter().map(|item| item.key).collect();
+    let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &keys).await;
+    leases::check(&context, &keys)?;
+    let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+    let results = batch_put::write_batch(&context, &items, overwrite)?;
+    let stored = results.iter().filter(|r| r.stored()).count();
+    if stored < results.len() {
+        warn!("PUT BATCH in bucket: {}: {} of {} files not stored", context.bucket, results.len() - stored, results.len());
+    }
+    let status = if stored == results.len() { StatusCode::OK } else { StatusCode::MULTI_STATUS };
+    Ok(HttpResponse::build(status).json(serde_json::json!({
+        "bucket": context.bucket,
+        "stored": stored,
+        "results": results,
+    })))
+}
+
+pub async fn txn_service(payload: web::Payload, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let limits = PayloadLimits::of(&req);
+    let context = admitted_context(req).await?;
+    let config = TxnConfig::from_env();
     let db = MetadataService::new(&context.user_id)?;
-    db.check_key_nonexistance(&context.bucket, &key)?;
-    info!("Starting chunk load");
-    let mut bytes = BytesMut::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.map_err(ErrorInternalServerError)?;
-        bytes.extend_from_slice(&chunk);
+    db.ensure_bucket_for_write(&context.bucket, &BucketConfig::from_env())?;
+
+    let bytes = read_body_limited(payload, config.max_body_bytes(), limits.idle_timeout).await?;
+    let ops = txn::parse(&bytes, &config)?;
+    info!("TXN called for user: {}, bucket: {}, ops: {}", context.user_id, context.bucket, ops.len());
+
+    let keys = txn::keys(&ops);
+    let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &keys).await;
+    leases::check(&context, &keys)?;
+    let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+    let outcome = txn::run(&context, &ops)?;
+    let status = if outcome.committed { StatusCode::OK } else { StatusCode::CONFLICT };
+    Ok(HttpResponse::build(status).json(json!({
+        "bucket": context.bucket,
+        "committed": outcome.committed,
+        "results": outcome.results,
+    })))
+}
+
+pub async fn lease_service(key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let ttl = match web::Query::<HashMap<String, String>>::from_query(req.query_string()) {
+        Ok(query) => query.get("ttl").map(|v| v.parse::<u64>()).transpose()
+            .map_err(|_| ServiceError::InvalidRequest("ttl must be a number of seconds".to_string()))?,
+        Err(_) => None,
+    };
+    let context = admitted_context(req).await?;
+    let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[&key]).await;
+    let lease = leases::acquire(&context, &key, ttl)?;
+    info!("Leased key: {} in bucket: {} until {}", key, context.bucket, lease.expires_at);
+    Ok(HttpResponse::Ok().json(lease))
+}
+
+pub async fn release_lease_service(key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_context(req).await?;
+    let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[&key]).await;
+    let released = leases::release(&context, &key)?;
+    info!("Lease on key: {} in bucket: {} released: {}", key, context.bucket, released);
+    Ok(HttpResponse::Ok().json(json!({ "bucket": context.bucket, "key": key, "released": released })))
+}
+
+pub async fn append_service(key: String, payload: web::Payload, req: HttpRequest ) -> Result<HttpResponse, Error> {
+    let append_id = req.headers().get(APPEND_ID_HEADER).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let expected_len = content_length(&req);
+    let version = ApiVersion::of(&req);
+    let limits = PayloadLimits::of(&req);
+    let context = admitted_context(req).await?;
+
+    let body = read_body(payload, expected_len, limits);
+    let outcome = ObjectService::new().append(&context, &key, body, append_id.as_deref()).await?;
+    let generation = generation_of(&context, &key)?;
+    let mut response = HttpResponse::Ok();
+    response.insert_header((GENERATION_HEADER, generation.to_string()));
+    match outcome {
+        AppendOutcome::Replayed(record) => Ok(response.json(append_response(&key, &record, true, generation))),
+        AppendOutcome::Appended(record) if append_id.is_some() || version.json_bodies() => {
+            Ok(response.json(append_response(&key, &record, false, generation)))
+        }
+        AppendOutcome::Empty if version.json_bodies() => {
+            Ok(response.json(json!({ "key": key, "chunks": 0, "size": 0, "generation": generation })))
+        }
+        AppendOutcome::Appended(_) | AppendOutcome::Empty => {
+            Ok(response.body(format!("Data appended successfully: key = {}", key)))
+        }
     }
-    if bytes.is_empty() {
-        error!("No data uploaded with key: {}", key);
-        return Ok(HttpResponse::BadRequest().body("No data was uploaded"));
+}
+
+fn append_response(key: &str, record: &AppendRecord, replayed: bool, generation: u64) -> serde_json::Value {
+    json!({
+        "key": key,
+        "append_id": Some(&record.id).filter(|id| !id.is_empty()),
+        "replayed": replayed,
+        "first_chunk": record.first_chunk,
+        "chunks": record.chunks,
+        "size": record.size,
+        "generation": generation,
+    })
+}
+
+pub async fn delete_service(key: String, req: HttpRequest)-> Result<HttpResponse, Error>{
+
+    let context = admitted_context(req).await?;
+    let queued = ObjectService::new().delete(&context, &key).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "bucket": context.bucket,
+        "key": key,
+        "reclaimed_bytes": queued.bytes,
+        "chunks": queued.chunks,
+        "queued": queued.queued(),
+    })))
+}
+
+/// Set the last-modified time of `key` to now without rewriting its data, e.g. to restart a
+/// retention period that counts from it.
+pub async fn touch_service(key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_context(req).await?;
+    let db = MetadataService::new(&context.user_id)?;
+    if !db.check_key(&context.bucket, &key)? {
+        return Err(missing_key_error(&db, &context, &context.bucket, &key).into());
     }
+    let last_modified = db.touch(&context.bucket, &key)?;
+    info!("Touched key: {} in bucket: {}", key, context.bucket);
+    let generation = generation_of(&context, &key)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Last-Modified", http_date(&last_modified)))
+        .insert_header((GENERATION_HEADER, generation.to_string()))
+        .json(json!({ "bucket": context.bucket, "key": key, "last_modified": last_modified, "generation": generation })))
+}
+
+pub async fn update_key_service(old_key: String, new_key: String, req: HttpRequest)->  Result<HttpResponse, Error>{
     
-    info!("Total received data size: {} bytes", bytes.len());
+    let version = ApiVersion::of(&req);
+    let context = admitted_context(req).await?;
+    ObjectService::new().rename(&context, &old_key, &new_key).await?;
+    let generation = generation_of(&context, &new_key)?;
+    let mut response = HttpResponse::Ok();
+    response.insert_header((GENERATION_HEADER, generation.to_string()));
+    if version.json_bodies() {
+        return Ok(response.json(json!({ "bucket": context.bucket, "old_key": old_key, "new_key": new_key, "generation": generation })));
+    }
+    Ok(response.body(format!("Key updated successfully from {} to {} in bucket {}", old_key, new_key, context.bucket)))
+}
 
-    // Write additional FlatBuffers payload chunks to storage
-    let storage_service = StorageService::new();
-    let mut offset_size_list_append = storage_service.write_object(&context, &bytes, StorageMode::Native)?;
+pub async  fn update_service(key: String, payload: web::Payload, req: HttpRequest ) ->  Result<HttpResponse, Error>{
+    let expire_at = expiry::requested(&req)?;
+    let expected_len = content_length(&req);
+    let keep_metadata = metadata_directive(&req)?;
+    let version = ApiVersion::of(&req);
+    let limits = PayloadLimits::of(&req);
+    let context = admitted_context(req).await?;
 
+    let body = read_body(payload, expected_len, limits);
+    ObjectService::new().update(&context, &key, body, expire_at, keep_metadata).await?;
 
-    if offset_size_list_append.is_empty() {
-        error!("No data in data list with key: {}", key);
-        return Ok(HttpResponse::BadRequest().body("No data in data list"));
+    info!("Data uploaded successfully with key: {} in bucket: {}", key, context.bucket);
+    uploaded(version, &context, &key)
+}
+
+pub async fn copy_service(src_key: String, dst_key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let src_bucket = req.headers().get("Src-Bucket").and_then(|h| h.to_str().ok()).map(str::to_string);
+    let dst_bucket = req.headers().get("Dst-Bucket").and_then(|h| h.to_str().ok()).map(str::to_string);
+    let overwrite = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map(|q| q.get("overwrite").map(|v| v == "true").unwrap_or(false))
+        .unwrap_or(false);
+
+    let tagging_directive = req.headers().get("Tagging-Directive")
+        .and_then(|h| h.to_str().ok())
+        .map(|v| v.trim().to_uppercase())
+        .unwrap_or_else(|| "COPY".to_string());
+    if tagging_directive != "COPY" && tagging_directive != "REPLACE" {
+        return Err(ServiceError::InvalidRequest(format!("Unknown Tagging-Directive: {}", tagging_directive)).into());
+    }
+
+    let context = admitted_context(req).await?;
+    let src = UserContext::with_bucket(context.user_id.clone(), src_bucket.unwrap_or_else(|| context.bucket.clone()));
+    let dst = UserContext::with_bucket(context.user_id.clone(), dst_bucket.unwrap_or_else(|| context.bucket.clone()));
+    bucket_grants::check_same_bucket(&context, &src.bucket)?;
+    bucket_grants::check_same_bucket(&context, &dst.bucket)?;
+    info!("COPY service called for user: {}, {}/{} -> {}/{}", context.user_id, src.bucket, src_key, dst.bucket, dst_key);
+
+    KeyLimits::from_env().check_new_key(&dst_key)?;
+    if src.bucket == dst.bucket && src_key == dst_key {
+        return Err(ServiceError::InvalidRequest("Source and destination are the same object".to_string()).into());
     }
-   
-    info!("Serializing offset and size and uploading");
 
-    let offset_size_bytes = match db.read_metadata(&context.bucket, &key) {
-        Ok(offset_size_bytes) => offset_size_bytes,
-        Err(e) => {
-            warn!("Key does not exist or database error: {}", e);
-            return Ok(HttpResponse::BadRequest().body("Key does not exist"));
+    let db = MetadataService::new(&context.user_id)?;
+    db.ensure_bucket_for_write(&dst.bucket, &BucketConfig::from_env())?;
+    db.check_key_nonexistance(&src.bucket, &src_key)?;
+
+    let dst_exists = db.check_key(&dst.bucket, &dst_key)?;
+    if dst_exists && !overwrite {
+        warn!("Copy destination already exists: {} in bucket: {}", dst_key, dst.bucket);
+        return Err(ServiceError::key_exists(&dst.bucket, &dst_key).into());
+    }
+
+    let src_meta = db.get_object_full(&src.bucket, &src_key)?;
+    let files = src_meta.file_attrs();
+    let total_size = src_meta.size;
+
+    let dst_list = if src_meta.is_inline() {
+        // Inline files are copied with the row
+        let dst_meta = Metadata::inline_files_of(&src_meta.inline_files());
+        if overwrite {
+            let old_list = db.overwrite_native_object(&dst.bucket, &dst_key, dst_meta, &files)?;
+            db.queue_deletion(&dst.bucket, &dst_key, &old_list)?;
+        } else {
+            db.write_native_object(&dst.bucket, &dst_key, dst_meta, &files, None)?;
         }
-    };
-    
-        // Deserialize offset and size data
-    let mut offset_size_list = deserialize_offset_size(&offset_size_bytes)?;
+        Vec::new()
+    } else {
+        // Give the destination its own chunks rather than sharing the source's extents
+        let src_list = src_meta.to_offset_size_list();
+        let storage_service = StorageService::new();
+        let _write_guard = bucket_lock::begin_write(&dst.user_id, &dst.bucket).await;
+        let dst_list = storage_service.copy_chunks(&src, &dst, &src_list)?;
+        let dst_chunks: Vec<DataChunk> = src_meta.chunks.iter().zip(&dst_list).map(|(chunk, &extent)| chunk.relocated(extent)).collect();
+        let dst_bytes = encode_chunks(&dst_chunks)?;
 
-    
-    offset_size_list.append(&mut offset_size_list_append);  // Appending offset_list_append to offset_list
+        if overwrite {
+            let old_list = db.overwrite_metadata_with_files(&dst.bucket, &dst_key, &dst_bytes, &files)?;
+            if !old_list.is_empty() {
+                db.queue_deletion(&dst.bucket, &dst_key, &old_list)?;
+            }
+        } else {
+            db.write_metadata_with_files(&dst.bucket, &dst_key, &dst_bytes, &files, None)?;
+        }
+        dst_list
+    };
 
-    let offset_size_bytes_append = serialize_offset_size(&offset_size_list)?;
+    // Tags follow the data unless the caller asks for a clean destination
+    let dst_tags = match tagging_directive.as_str() {
+        "REPLACE" => Vec::new(),
+        _ => db.get_object_tags(&src.bucket, &src_key)?,
+    };
+    db.set_object_tags(&dst.bucket, &dst_key, &dst_tags)?;
 
-    db.append_metadata(&context.bucket, &key, &offset_size_bytes_append)
-            .map_err(ErrorInternalServerError)?;
-    
-    info!("Data apended successfully with key: {}", key);
-    Ok(HttpResponse::Ok().body(format!("Data appended successfully: key = {}", key)))
-    
+    info!("Copied {} chunks ({} bytes) to key: {} in bucket: {}", dst_list.len(), total_size, dst_key, dst.bucket);
+    let generation = generation_of(&dst, &dst_key)?;
+    Ok(HttpResponse::Ok().insert_header((GENERATION_HEADER, generation.to_string())).json(json!({
+        "key": dst_key,
+        "bucket": dst.bucket,
+        "chunks": dst_list.len(),
+        "size": total_size,
+        "generation": generation,
+    })))
 }
 
-pub async fn delete_service(key: String, req: HttpRequest)-> Result<HttpResponse, Error>{
+/// Move `key` from the `Src-Bucket` to the `Dst-Bucket`, keeping its name. Chunks live in
+/// per-bucket files, so the data is copied into the destination, the row and its tags are
+/// re-pointed in one transaction and the source extents are queued for deletion. Both
+/// buckets must be unversioned.
+pub async fn move_service(key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok()).filter(|v| !v.is_empty()).map(str::to_string);
+    let (Some(src_bucket), Some(dst_bucket)) = (header("Src-Bucket"), header("Dst-Bucket")) else {
+        return Err(ServiceError::InvalidRequest("A move needs both Src-Bucket and Dst-Bucket headers".to_string()).into());
+    };
+    let context = admitted_context(req).await?;
+    let src = UserContext::with_bucket(context.user_id.clone(), src_bucket);
+    let dst = UserContext::with_bucket(context.user_id.clone(), dst_bucket);
+    bucket_grants::check_same_bucket(&context, &src.bucket)?;
+    bucket_grants::check_same_bucket(&context, &dst.bucket)?;
+    info!("MOVE service called for user: {}, key: {}, {} -> {}", context.user_id, key, src.bucket, dst.bucket);
 
-    let context = header_handler(req)?;
+    if src.bucket == dst.bucket {
+        return Err(ServiceError::InvalidRequest("Source and destination are the same bucket; use update_key to rename".to_string()).into());
+    }
+    let db = MetadataService::new(&context.user_id)?;
+    if !db.check_key(&src.bucket, &key)? {
+        return Err(missing_key_error(&db, &context, &src.bucket, &key).into());
+    }
+    db.ensure_bucket_for_write(&dst.bucket, &BucketConfig::from_env())?;
+    for bucket in [&src.bucket, &dst.bucket] {
+        if db.get_versioning_state(bucket)? != "disabled" {
+            return Err(ServiceError::InvalidRequest(format!("Moves are only supported between unversioned buckets; {} is versioned", bucket)).into());
+        }
+    }
+    if db.check_key(&dst.bucket, &key)? {
+        return Err(ServiceError::key_exists(&dst.bucket, &key).into());
+    }
+
+    // Lock in name order so concurrent moves between the same buckets cannot deadlock
+    let (first, second) = if src.bucket < dst.bucket { (&src, &dst) } else { (&dst, &src) };
+    let _first_guard = bucket_lock::begin_write(&context.user_id, &first.bucket).await;
+    let _second_guard = bucket_lock::begin_write(&context.user_id, &second.bucket).await;
+
+    let src_list = decode_extents(&db.read_metadata(&src.bucket, &key)?)?;
     let storage_service = StorageService::new();
-    storage_service.delete_object(&context, &key)?;
-    Ok(HttpResponse::Ok().body(format!("File deleted successfully: key = {} in bucket = {}", key, context.bucket)))
+    let dst_list = storage_service.copy_chunks(&src, &dst, &src_list)?;
+    if let Err(e) = db.move_object(&src.bucket, &key, &dst.bucket, &src_list, &dst_list) {
+        warn!("Move of key: {} from bucket: {} to bucket: {} failed: {}", key, src.bucket, dst.bucket, e);
+        if !dst_list.is_empty() {
+            db.queue_deletion(&dst.bucket, &key, &dst_list)?;
+        }
+        return Err(e);
+    }
+    if !src_list.is_empty() {
+        db.queue_deletion(&src.bucket, &key, &src_list)?;
+    }
+    db.delete_completed_uploads_for_key(&src.bucket, &key)?;
+
+    let total_size: u64 = dst_list.iter().map(|(_, size)| size).sum();
+    info!("Moved key: {} ({} chunks, {} bytes) from bucket: {} to bucket: {}", key, dst_list.len(), total_size, src.bucket, dst.bucket);
+    let generation = generation_of(&dst, &key)?;
+    Ok(HttpResponse::Ok().insert_header((GENERATION_HEADER, generation.to_string())).json(json!({
+        "key": key,
+        "bucket": dst.bucket,
+        "from_bucket": src.bucket,
+        "chunks": dst_list.len(),
+        "size": total_size,
+        "generation": generation,
+    })))
 }
 
-pub async fn update_key_service(old_key: String, new_key: String, req: HttpRequest)->  Result<HttpResponse, Error>{
-    
-    let context = header_handler(req)?;
+/// Metadata-only copy within one bucket: the destination shares the source's chunks, which
+/// are reference counted so deleting either key leaves the other readable.
+pub async fn clone_service(src_key: String, dst_key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let overwrite = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .map(|q| q.get("overwrite").map(|v| v == "true").unwrap_or(false))
+        .unwrap_or(false);
+    let context = admitted_context(req).await?;
+    info!("CLONE service called for user: {}, bucket: {}, {} -> {}", context.user_id, context.bucket, src_key, dst_key);
+
+    KeyLimits::from_env().check_new_key(&dst_key)?;
+    if src_key == dst_key {
+        return Err(ServiceError::InvalidRequest("Source and destination are the same object".to_string()).into());
+    }
 
     let db = MetadataService::new(&context.user_id)?;
-    db.check_key_nonexistance(&context.bucket, &old_key)?;
-    // Update the key in the database
-    match db.rename_key(&context.bucket, &old_key, &new_key) {
-        Ok(()) => Ok(HttpResponse::Ok().body(format!("Key updated successfully from {} to {} in bucket {}", old_key, new_key, context.bucket))),
-        Err(e) => Ok(HttpResponse::InternalServerError().body(format!("Failed to update key: {}", e))),
+    db.ensure_bucket_for_write(&context.bucket, &BucketConfig::from_env())?;
+    db.check_key_nonexistance(&context.bucket, &src_key)?;
+    if db.get_versioning_state(&context.bucket)? != "disabled" {
+        return Err(ServiceError::InvalidRequest("Clones are only supported in unversioned buckets".to_string()).into());
     }
+
+    let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+    let replaced = db.clone_metadata(&context.bucket, &src_key, &dst_key, overwrite)?;
+    if !replaced.is_empty() {
+        db.queue_deletion(&context.bucket, &dst_key, &replaced)?;
+    }
+    let tags = db.get_object_tags(&context.bucket, &src_key)?;
+    db.set_object_tags(&context.bucket, &dst_key, &tags)?;
+
+    let cloned = db.get_object_full(&context.bucket, &dst_key)?;
+    info!("Cloned {} chunks ({} bytes) to key: {} in bucket: {}", cloned.chunks.len(), cloned.size, dst_key, context.bucket);
+    let generation = generation_of(&context, &dst_key)?;
+    Ok(HttpResponse::Ok().insert_header((GENERATION_HEADER, generation.to_string())).json(json!({
+        "key": dst_key,
+        "bucket": context.bucket,
+        "chunks": cloned.chunks.len(),
+        "size": cloned.size,
+        "generation": generation,
+    })))
 }
 
-pub async  fn update_service(key: String, mut payload: web::Payload, req: HttpRequest ) ->  Result<HttpResponse, Error>{
-    let context = header_handler(req)?;
+pub async fn put_tags_service(key: String, payload: web::Payload, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let limits = PayloadLimits::of(&req);
+    let context = admitted_context(req).await?;
+    info!("PUT TAGS called for user: {}, key: {} in bucket: {}", context.user_id, key, context.bucket);
+
+    let body = read_control_body(payload, limits).await?;
+    let tags: std::collections::BTreeMap<String, String> = serde_json::from_slice(&body)
+        .map_err(|e| ServiceError::InvalidRequest(format!("Tags must be a JSON object of strings: {}", e)))?;
+    let tags: Vec<(String, String)> = tags.into_iter().collect();
+    check_tags(&tags).map_err(|msg| ServiceError::InvalidRequest(msg.to_string()))?;
 
     let db = MetadataService::new(&context.user_id)?;
     db.check_key_nonexistance(&context.bucket, &key)?;
+    db.set_object_tags(&context.bucket, &key, &tags)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "key": key,
+        "bucket": context.bucket,
+        "tags": tags.into_iter().collect::<std::collections::BTreeMap<_, _>>(),
+    })))
+}
+
+pub async fn get_tags_service(key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_context(req).await?;
+    let db = MetadataService::new(&context.user_id)?;
+    db.check_key_nonexistance(&context.bucket, &key)?;
+    let tags: std::collections::BTreeMap<String, String> = db.get_object_tags(&context.bucket, &key)?.into_iter().collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "key": key,
+        "bucket": context.bucket,
+        "tags": tags,
+    })))
+}
+
 
-    info!("Starting chunk load");
-    let mut bytes = BytesMut::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.map_err(ErrorInternalServerError)?;
-        bytes.extend_from_slice(&chunk);
+/// Keys returned by `/list` when the request doesn't set `limit`
+pub const DEFAULT_LIST_LIMIT: usize = 100;
+/// Upper bound on `limit` for `/list`
+pub const MAX_LIST_LIMIT: usize = 1000;
+
+/// One page of the request bucket's keys in key order, starting after `after`, with each
+/// key's size and last-modified time and the bucket's totals. With `as_of`, the keys live at
+/// that time and the totals of the page. With `tag=key:value`, only the keys carrying that tag,
+/// found with the same join on the tag table as the S3 listing.
+pub async fn list_service(query: HashMap<String, String>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let as_of = query.get("as_of").map(|value| as_of::parse(value)).transpose()?;
+    let tag = query.get("tag").map(|filter| {
+        parse_tag_filter(filter).ok_or_else(|| ServiceError::InvalidRequest("tag filter must be of the form key:value".to_string()))
+    }).transpose()?;
+    if as_of.is_some() && tag.is_some() {
+        return Err(ServiceError::InvalidRequest("tag cannot be combined with as_of".to_string()).into());
     }
+    let context = admitted_context(req).await?;
+    let limit = match query.get("limit") {
+        Some(v) => v.parse::<usize>().ok().filter(|n| (1..=MAX_LIST_LIMIT).contains(n)).ok_or_else(|| {
+            ServiceError::InvalidRequest(format!("limit must be between 1 and {}", MAX_LIST_LIMIT))
+        })?,
+        None => DEFAULT_LIST_LIMIT,
+    };
+    let after = query.get("after").map(String::as_str).unwrap_or("");
+    let page = match &as_of {
+        Some(as_of) => ObjectService::new().list_as_of(&context, as_of, after, limit)?,
+        None => ObjectService::new().list(&context, tag, after, limit)?,
+    };
+    Ok(HttpResponse::Ok().json(json!({
+        "bucket": context.bucket,
+        "objects": page.objects,
+        "next_after": page.next_after,
+        "usage": { "objects": page.object_count, "bytes": page.total_bytes },
+    })))
+}
 
-    if bytes.is_empty() {
-        error!("No data uploaded with key: {}", key);
-        return Ok(HttpResponse::BadRequest().body("No data was uploaded"));
+
+pub async fn create_bucket_service(bucket: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_bucket_context(req, &bucket, Permission::Write).await?;
+    if bucket.is_empty() {
+        return Err(ServiceError::InvalidRequest("Bucket name must not be empty".to_string()).into());
     }
-    
-    info!("Total received data size: {} bytes", bytes.len());
-    info!("Starting deserialization");
-    
-    // Rewrite with provided FlatBuffers payload
-    let storage_service = StorageService::new();
-    let offset_size_list = storage_service.write_object(&context, &bytes, StorageMode::Native)?;
-   
-    if offset_size_list.is_empty()  {
-        error!("No data in data list with key: {}", key);
-        return Ok(HttpResponse::BadRequest().body("No data in data list"));
+    info!("CREATE BUCKET called for user: {}, bucket: {}", context.user_id, bucket);
+
+    let db = MetadataService::new(&context.user_id)?;
+    if db.bucket_registered(&bucket)? {
+        return Ok(HttpResponse::Ok().json(json!({ "bucket": bucket, "created": false })));
     }
-    
+    db.create_bucket(&bucket)?;
+    Ok(HttpResponse::Created().json(json!({ "bucket": bucket, "created": true })))
+}
+
+/// HEAD on a bucket: 200 with its live object count and bytes, or 404 when it does not exist
+/// for this user.
+pub async fn head_bucket_service(bucket: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_bucket_context(req, &bucket, Permission::Read).await?;
+    let db = MetadataService::new(&context.user_id)?;
+    if !db.bucket_exists(&bucket)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let (count, bytes) = db.bucket_object_stats(&bucket)?;
+    Ok(HttpResponse::Ok()
+        .insert_header((bucket_registry::OBJECT_COUNT_HEADER, count.to_string()))
+        .insert_header((bucket_registry::BYTES_HEADER, bytes.to_string()))
+        .finish())
+}
 
-    let offset_size_bytes = serialize_offset_size(&offset_size_list)?;
-    db.update_metadata(&context.bucket, &key, &offset_size_bytes)
-    .map_err(ErrorInternalServerError)?;
+pub async fn delete_bucket_service(bucket: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    // Through a grant only an admin may delete the owner's bucket
+    let context = admitted_bucket_context(req, &bucket, Permission::Admin).await?;
+    info!("DELETE BUCKET called for user: {}, bucket: {}", context.user_id, bucket);
 
-    info!("Data uploaded successfully with key: {} in bucket: {}", key, context.bucket);
-    Ok(HttpResponse::Ok().body(format!("Data uploaded successfully: key = {}, bucket = {}", key, context.bucket)))
+    let db = MetadataService::new(&context.user_id)?;
+    if !db.bucket_exists(&bucket)? {
+        return Ok(HttpResponse::NotFound().json(json!({
+            "error": "NoSuchBucket",
+            "message": "The specified bucket does not exist",
+            "bucket": bucket,
+        })));
+    }
+    if !db.list_objects(&bucket)?.is_empty() {
+        warn!("Refusing to delete non-empty bucket: {} for user: {}", bucket, context.user_id);
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "BucketNotEmpty",
+            "message": "The bucket you tried to delete is not empty",
+            "bucket": bucket,
+        })));
+    }
+    db.delete_bucket(&bucket)?;
+    bucket_grants::clear();
+    Ok(HttpResponse::Ok().json(json!({ "bucket": bucket, "deleted": true })))
+}
+
+/// Grant `grantee` `perm` on `bucket`; the caller must own it or hold an admin grant on it
+pub async fn put_grant_service(bucket: String, grantee: String, query: HashMap<String, String>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let permission: Permission = query.get("perm")
+        .ok_or_else(|| ServiceError::InvalidRequest("Missing perm query parameter (read, write or admin)".to_string()))?
+        .parse()
+        .map_err(ServiceError::InvalidRequest)?;
+    let context = admitted_bucket_context(req, &bucket, Permission::Read).await?;
+    bucket_grants::grant(&context, &grantee, permission)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "owner": context.user_id,
+        "bucket": bucket,
+        "grantee": grantee,
+        "permission": permission,
+    })))
+}
+
+/// Grants on `bucket`, to its owner or an admin grantee
+pub async fn list_grants_service(bucket: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_bucket_context(req, &bucket, Permission::Read).await?;
+    let grants = bucket_grants::list(&context)?;
+    Ok(HttpResponse::Ok().json(json!({ "owner": context.user_id, "bucket": bucket, "grants": grants })))
+}
 
+/// Revoke `grantee`'s grant on `bucket`: 404 when there was none
+pub async fn delete_grant_service(bucket: String, grantee: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_bucket_context(req, &bucket, Permission::Read).await?;
+    if !bucket_grants::revoke(&context, &grantee)? {
+        return Ok(HttpResponse::NotFound().json(json!({
+            "error": "NoSuchGrant",
+            "message": format!("Bucket {} is not granted to {}", bucket, grantee),
+            "bucket": bucket,
+        })));
+    }
+    Ok(HttpResponse::Ok().json(json!({ "owner": context.user_id, "bucket": bucket, "grantee": grantee, "revoked": true })))
+}
 
+/// The user's own buckets, with their usage, and the buckets granted to them
+pub async fn list_buckets_service(req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = authenticated_context(&req)?;
+    user_limits::admit(&req, &context.user_id, false).await?;
+    let owned: Vec<_> = MetadataService::new(&context.user_id)?.list_buckets_with_stats()?.into_iter()
+        .map(|b| json!({ "name": b.name, "created_at": b.created_at, "objects": b.object_count, "bytes": b.total_size }))
+        .collect();
+    let granted: Vec<_> = bucket_grants::grants_to(&context.user_id)?.into_iter()
+        .map(|g| json!({ "name": g.bucket, "owner": g.owner, "permission": g.permission, "granted_at": g.created_at }))
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({ "user": context.user_id, "buckets": owned, "granted": granted })))
 }
 
+/// Bytes the user's buckets hold in live objects and in the parts of uploads in progress,
+/// which both count against a bucket's quota (see [`bucket_quota`]), per bucket and in total
+pub async fn usage_service(req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = authenticated_context(&req)?;
+    user_limits::admit(&req, &context.user_id, false).await?;
+    let db = MetadataService::new(&context.user_id)?;
+    let multipart: HashMap<String, u64> = db.multipart_bytes_by_bucket()?.into_iter().collect();
+    let (mut objects, mut objects_bytes, mut multipart_bytes) = (0, 0, 0);
+    let buckets: Vec<_> = db.list_buckets_with_stats()?.into_iter()
+        .map(|b| {
+            let parts = multipart.get(&b.name).copied().unwrap_or(0);
+            objects += b.object_count;
+            objects_bytes += b.total_size;
+            multipart_bytes += parts;
+            json!({ "name": b.name, "objects": b.object_count, "objects_bytes": b.total_size, "multipart_bytes": parts })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "user": context.user_id,
+        "objects": objects,
+        "objects_bytes": objects_bytes,
+        "multipart_bytes": multipart_bytes,
+        "buckets": buckets,
+    })))
+}
+
+/// Open a resumable upload session for `key` (see [`upload_sessions`])
+pub async fn create_upload_service(key: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_context(req).await?;
+    let session = upload_sessions::create(&context, &key)?;
+    Ok(HttpResponse::Ok().json(json!({ "bucket": context.bucket, "key": key, "session": session })))
+}
+
+pub async fn put_upload_part_service(key: String, session: String, part_number: i32, payload: web::Payload, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let expected_len = content_length(&req);
+    let limits = PayloadLimits::of(&req);
+    let context = admitted_context(req).await?;
+    let body = read_body(payload, expected_len, limits);
+    let part = upload_sessions::put_part(&StorageService::new(), &context, &key, &session, part_number, body).await?;
+    Ok(HttpResponse::Ok().json(part))
+}
+
+pub async fn list_upload_parts_service(key: String, session: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_context(req).await?;
+    Ok(HttpResponse::Ok().json(upload_sessions::list(&context, &key, &session)?))
+}
+
+pub async fn complete_upload_service(key: String, session: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_context(req).await?;
+    let completed = upload_sessions::complete(&context, &key, &session).await?;
+    let generation = generation_of(&context, &key)?;
+    Ok(HttpResponse::Ok()
+        .insert_header((GENERATION_HEADER, generation.to_string()))
+        .json(json!({
+            "bucket": context.bucket,
+            "key": key,
+            "size": completed.size,
+            "parts": completed.parts,
+            "generation": generation,
+        })))
+}
+
+pub async fn abort_upload_service(key: String, session: String, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let context = admitted_context(req).await?;
+    let parts = upload_sessions::abort(&context, &key, &session)?;
+    Ok(HttpResponse::Ok().json(json!({ "bucket": context.bucket, "key": key, "session": session, "aborted_parts": parts })))
+}
+
+pub async fn repair_service(key: String, payload: web::Payload, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let chunk = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|q| q.get("chunk").and_then(|v| v.parse::<usize>().ok()))
+        .ok_or_else(|| ServiceError::InvalidRequest("Missing or invalid chunk query parameter".to_string()))?;
+    // Admin-authenticated: the User header is trusted whatever NATIVE_AUTH says
+    let user_id = native_auth::request_user(&req, &NativeAuthConfig::default())?
+        .ok_or_else(|| ServiceError::InvalidRequest("Missing User header".to_string()))?;
+    let context = user_context(&req, user_id)?;
+    info!("REPAIR service called for user: {}, bucket: {}, key: {}, chunk: {}", context.user_id, context.bucket, key, chunk);
+
+    let bytes = read_body(payload, content_length(&req), PayloadLimits::of(&req)).await?;
+    let report = repair::repair_chunk(&context, &key, chunk, &bytes).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
 
 // All unit tests will currently be here. 
 
@@ -287,6 +1098,82 @@ mod tests {
         println!("Header handler with valid user test passed!");
     }
 
+    #[test]
+    fn test_header_handler_captures_only_prefixed_metadata() {
+        use actix_web::test;
+
+        let req = test::TestRequest::default()
+            .insert_header(("User", "test_user"))
+            .insert_header(("X-Warp-Meta-Source", "camera-3"))
+            .insert_header(("User-Agent", "curl/8.0"))
+            .insert_header(("Accept-Encoding", "gzip"))
+            .insert_header(("Connection", "keep-alive"))
+            .to_http_request();
+        let context = header_handler(req).unwrap();
+        assert_eq!(context.metadata.len(), 1, "{:?}", context.metadata);
+        assert_eq!(context.get_metadata("source").map(String::as_str), Some("camera-3"));
+    }
+
+    #[test]
+    fn test_header_handler_never_captures_credentials() {
+        use actix_web::test;
+
+        let req = test::TestRequest::default()
+            .insert_header(("User", "test_user"))
+            .insert_header(("Authorization", "Bearer secret-token"))
+            .insert_header(("Cookie", "session=secret"))
+            .insert_header(("Proxy-Authorization", "Basic c2VjcmV0"))
+            .to_http_request();
+        let context = header_handler(req).unwrap();
+        assert!(context.metadata.is_empty(), "{:?}", context.metadata);
+        assert!(!format!("{:?}", context).contains("secret"));
+    }
+
+    #[test]
+    fn test_header_handler_rejects_oversized_metadata() {
+        use actix_web::test;
+        use crate::service::user_context::{MAX_METADATA_TOTAL_BYTES, MAX_METADATA_VALUE_BYTES};
+
+        let long_value = "v".repeat(MAX_METADATA_VALUE_BYTES + 1);
+        let req = test::TestRequest::default()
+            .insert_header(("User", "test_user"))
+            .insert_header(("X-Warp-Meta-Note", long_value))
+            .to_http_request();
+        let err = header_handler(req).unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::BAD_REQUEST);
+
+        // Each value fits, together they do not
+        let mut req = test::TestRequest::default().insert_header(("User", "test_user"));
+        for i in 0..=MAX_METADATA_TOTAL_BYTES / MAX_METADATA_VALUE_BYTES {
+            req = req.insert_header((format!("X-Warp-Meta-Part{}", i), "v".repeat(MAX_METADATA_VALUE_BYTES)));
+        }
+        let err = header_handler(req.to_http_request()).unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_set_metadata_validates_names() {
+        let mut context = UserContext::new("test_user".to_string());
+        assert!(context.set_metadata("origin".to_string(), "upload".to_string()).is_ok());
+        assert!(context.set_metadata(String::new(), "x".to_string()).is_err());
+        assert!(context.set_metadata("bad\nname".to_string(), "x".to_string()).is_err());
+        assert_eq!(context.metadata.len(), 1);
+    }
+
+    #[test]
+    fn test_chunk_selection() {
+        use actix_web::test;
+
+        let select = |query: &str| chunk_selection(&test::TestRequest::get().uri(&format!("/get/k?{}", query)).to_http_request());
+        assert_eq!(select("").unwrap(), None);
+        assert_eq!(select("chunk=3").unwrap(), Some((3, 3)));
+        assert_eq!(select("chunks=2-5").unwrap(), Some((2, 5)));
+        assert!(select("chunks=5-2").is_err());
+        assert!(select("chunks=2").is_err());
+        assert!(select("chunk=-1").is_err());
+        assert!(select("chunk=1&chunks=1-2").is_err());
+    }
+
     #[test]
     fn test_header_handler_missing_user_header() {
         use actix_web::test;
@@ -0,0 +1,89 @@
+//! Limits on S3 multipart uploads
+//!
+//! A user may have `MULTIPART_MAX_UPLOADS_PER_USER` uploads in progress (default 1000; 0 for
+//! no limit). CreateMultipartUpload past that fails with 503 `ServiceUnavailable` until one is
+//! completed or aborted; the count is taken from the multipart state table, so completing or
+//! aborting an upload is all it takes to free its place. The parts of one upload may add up
+//! to `MULTIPART_MAX_UPLOAD_BYTES` (default `MAX_PAYLOAD_BYTES`, the largest object a PUT may
+//! store); an UploadPart or UploadPartCopy that would pass it fails with 400
+//! `EntityTooLarge` before anything is written. A part uploaded again is counted once.
+//!
+//! In-progress uploads are listed per bucket in `GET /admin/stats` and exported as the
+//! `warpdrive_multipart_uploads_in_progress` gauge; refusals count in
+//! `warpdrive_multipart_limit_rejections_total{limit}`.
+
+use log::warn;
+use std::env;
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metrics;
+use crate::util::payload::PayloadLimits;
+
+/// In-progress uploads per user when `MULTIPART_MAX_UPLOADS_PER_USER` is unset
+pub const DEFAULT_MAX_UPLOADS_PER_USER: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartLimitConfig {
+    /// In-progress uploads per user; None for no limit
+    pub max_uploads_per_user: Option<u64>,
+    /// Total bytes of the parts of one upload
+    pub max_upload_bytes: u64,
+}
+
+impl Default for MultipartLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_uploads_per_user: Some(DEFAULT_MAX_UPLOADS_PER_USER),
+            max_upload_bytes: PayloadLimits::default().max_bytes as u64,
+        }
+    }
+}
+
+impl MultipartLimitConfig {
+    pub fn from_env() -> Self {
+        let max_uploads_per_user = match env::var("MULTIPART_MAX_UPLOADS_PER_USER").ok().and_then(|v| v.trim().parse::<u64>().ok()) {
+            Some(0) => None,
+            Some(n) => Some(n),
+            None => Some(DEFAULT_MAX_UPLOADS_PER_USER),
+        };
+        let max_upload_bytes = env::var("MULTIPART_MAX_UPLOAD_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(PayloadLimits::from_env().max_bytes as u64);
+        Self { max_uploads_per_user, max_upload_bytes }
+    }
+
+    /// Whether a part of `part_bytes` next to `other_parts` bytes of its upload is over the limit
+    pub fn upload_too_large(&self, other_parts: u64, part_bytes: u64) -> bool {
+        other_parts.saturating_add(part_bytes) > self.max_upload_bytes
+    }
+}
+
+/// Count a refused initiation or part under `limit`
+pub fn record_rejection(limit: &str) {
+    metrics::inc_counter("warpdrive_multipart_limit_rejections_total", &[("limit", limit)]);
+}
+
+/// Export the number of in-progress uploads as `warpdrive_multipart_uploads_in_progress`.
+/// Refreshed by every scrape of `/metrics`.
+pub fn export_in_progress() {
+    match SQLiteMetadataStore::new().multipart_uploads_in_progress_total() {
+        Ok(count) => metrics::set_gauge("warpdrive_multipart_uploads_in_progress", &[], count as f64),
+        Err(e) => warn!("Failed to count in-progress multipart uploads: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upload_too_large() {
+        let config = MultipartLimitConfig { max_uploads_per_user: None, max_upload_bytes: 10 };
+        assert!(!config.upload_too_large(0, 10));
+        assert!(!config.upload_too_large(6, 4));
+        assert!(config.upload_too_large(6, 5));
+        assert!(config.upload_too_large(u64::MAX, 1));
+    }
+}
@@ -0,0 +1,135 @@
+//! API-key authentication for the native API
+//!
+//! In the default open mode the `User` header is trusted as before. With
+//! `NATIVE_AUTH=required` every native request must carry `Authorization: Bearer <key>` for
+//! an enabled key; the key's user becomes the request user, and a `User` header naming
+//! anyone else is rejected. Keys are issued and revoked through the admin API and only
+//! their SHA-256 is stored.
+
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use actix_web::Error;
+use actix_web::error::ErrorInternalServerError;
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::env;
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+
+/// Prefix of generated keys, so leaked keys are easy to recognise
+const KEY_PREFIX: &str = "wd_";
+
+/// Native API authentication mode
+#[derive(Debug, Clone, Default)]
+pub struct NativeAuthConfig {
+    /// Require an API key on every native request
+    pub required: bool,
+}
+
+impl NativeAuthConfig {
+    pub fn from_env() -> Self {
+        let required = env::var("NATIVE_AUTH")
+            .map(|v| v.eq_ignore_ascii_case("required"))
+            .unwrap_or(false);
+        Self { required }
+    }
+}
+
+/// Missing, unknown, revoked or mismatched API key; maps to 401 JSON.
+#[derive(Debug)]
+pub struct NativeAuthError {
+    pub message: String,
+}
+
+impl std::fmt::Display for NativeAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ResponseError for NativeAuthError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNAUTHORIZED
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "error": "Unauthorized",
+            "message": self.message,
+        }))
+    }
+}
+
+fn unauthorized(message: impl Into<String>) -> Error {
+    NativeAuthError { message: message.into() }.into()
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Public identifier of a key, used to revoke it
+fn key_id(key_hash: &str) -> String {
+    key_hash[..16].to_string()
+}
+
+/// A freshly issued key; `key` is only ever returned here.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedKey {
+    pub user: String,
+    pub key_id: String,
+    pub key: String,
+    pub created_at: String,
+}
+
+/// Generate and store a new key for `user_id`.
+pub fn issue_key(user_id: &str) -> Result<IssuedKey, Error> {
+    let mut secret = [0u8; 32];
+    getrandom::getrandom(&mut secret).map_err(ErrorInternalServerError)?;
+    let key = format!("{}{}", KEY_PREFIX, hex::encode(secret));
+    let key_hash = hash_key(&key);
+    let created_at = chrono::Utc::now().to_rfc3339();
+    SQLiteMetadataStore::new().insert_api_key(&key_hash, &key_id(&key_hash), user_id, &created_at)?;
+    Ok(IssuedKey { user: user_id.to_string(), key_id: key_id(&key_hash), key, created_at })
+}
+
+/// Revoke `key_id` of `user_id`. Returns whether an enabled key was revoked.
+pub fn revoke_key(user_id: &str, key_id: &str) -> Result<bool, Error> {
+    SQLiteMetadataStore::new().disable_api_key(user_id, key_id)
+}
+
+/// The user a native request acts as: the `User` header in open mode, the API key's user
+/// when keys are required.
+pub fn request_user(req: &HttpRequest, config: &NativeAuthConfig) -> Result<Option<String>, Error> {
+    let header_user = req.headers()
+        .get("User")
+        .map(|h| h.to_str().map(|s| s.to_string()))
+        .transpose()
+        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid User header value"))?;
+    if !config.required {
+        return Ok(header_user);
+    }
+
+    let key = req.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .ok_or_else(|| unauthorized("Missing API key"))?;
+    let key_user = SQLiteMetadataStore::new()
+        .api_key_user(&hash_key(key))?
+        .ok_or_else(|| {
+            warn!("Rejected native request to {} with unknown or revoked API key", req.path());
+            unauthorized("Invalid or revoked API key")
+        })?;
+    if let Some(header_user) = header_user {
+        if header_user != key_user {
+            warn!("API key of user {} presented for user {}", key_user, header_user);
+            return Err(unauthorized("API key does not belong to the requested user"));
+        }
+    }
+    Ok(Some(key_user))
+}
@@ -0,0 +1,176 @@
+//! Verification of native FlatBuffers payloads
+//!
+//! Every native write body (PUT, APPEND, UPDATE, `put_batch`) is verified with bounded
+//! [`VerifierOptions`] before any of it is read: at most one table per allowed file plus the
+//! root, nesting no deeper than the schema needs, and an apparent size of at most twice the
+//! body so files sharing one data vector cannot multiply what gets written. The file count
+//! (`NATIVE_MAX_FILES`, default 10000) and each file's size (`NATIVE_MAX_FILE_BYTES`, default
+//! `MAX_PAYLOAD_BYTES`) are then checked, so nothing reaches storage until the whole payload
+//! has passed. A body that fails verification is a 400 `InvalidPayload`; one over a limit a
+//! 400 `PayloadLimitExceeded` naming the limit.
+
+use flatbuffers::{InvalidFlatbuffer, VerifierOptions};
+use std::env;
+
+use crate::metadata::FileAttrs;
+use crate::service::error::ServiceError;
+use crate::util::flatbuffer_store_generated::store;
+use crate::util::flatbuffer_store_v2_generated::store_v2;
+use crate::util::payload::PayloadLimits;
+
+/// Most files accepted in one payload when `NATIVE_MAX_FILES` is unset
+pub const DEFAULT_MAX_FILES: usize = 10_000;
+
+/// Deepest table nesting accepted; `FileDataList` → `FileData` needs two
+const MAX_DEPTH: usize = 8;
+
+/// Limits applied to a native payload
+#[derive(Debug, Clone)]
+pub struct NativePayloadConfig {
+    /// Most files in one payload
+    pub max_files: usize,
+    /// Largest single file, in bytes
+    pub max_file_bytes: usize,
+}
+
+impl Default for NativePayloadConfig {
+    fn default() -> Self {
+        Self { max_files: DEFAULT_MAX_FILES, max_file_bytes: PayloadLimits::default().max_bytes }
+    }
+}
+
+impl NativePayloadConfig {
+    pub fn from_env() -> Self {
+        let max_files = env::var("NATIVE_MAX_FILES")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_MAX_FILES);
+        let max_file_bytes = env::var("NATIVE_MAX_FILE_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or_else(|| PayloadLimits::from_env().max_bytes);
+        Self { max_files, max_file_bytes }
+    }
+
+    fn verifier_options(&self, body: &[u8]) -> VerifierOptions {
+        VerifierOptions {
+            max_depth: MAX_DEPTH,
+            max_tables: self.max_files.saturating_add(1),
+            max_apparent_size: body.len().saturating_mul(2),
+            ..VerifierOptions::default()
+        }
+    }
+
+    fn invalid(&self, version: &str, e: InvalidFlatbuffer) -> ServiceError {
+        match e {
+            InvalidFlatbuffer::TooManyTables => limit_exceeded("files", self.max_files, None),
+            // Trace lines follow the first; they vary with the buffer
+            e => ServiceError::InvalidPayload(format!(
+                "FlatBuffers {} payload failed verification: {}",
+                version,
+                e.to_string().lines().next().unwrap_or_default().trim_end_matches('.')
+            )),
+        }
+    }
+
+    fn check(&self, count: usize, mut sizes: impl Iterator<Item = usize>) -> Result<(), ServiceError> {
+        if count > self.max_files {
+            return Err(limit_exceeded("files", self.max_files, Some(count)));
+        }
+        match sizes.find(|size| *size > self.max_file_bytes) {
+            Some(size) => Err(limit_exceeded("file_bytes", self.max_file_bytes, Some(size))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn limit_exceeded(limit: &'static str, max: usize, actual: Option<usize>) -> ServiceError {
+    ServiceError::PayloadLimitExceeded { limit, max: max as u64, actual: actual.map(|a| a as u64) }
+}
+
+/// One file of a verified payload
+#[derive(Debug, Clone, Copy)]
+pub struct NativeFile<'a> {
+    /// None when the file has no data vector
+    pub data: Option<&'a [u8]>,
+    pub name: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+}
+
+impl NativeFile<'_> {
+    pub fn attrs(&self) -> FileAttrs {
+        FileAttrs { name: self.name.map(str::to_string), content_type: self.content_type.map(str::to_string) }
+    }
+}
+
+/// Verify `body`, v1 or v2 by the "WDV2" file identifier, and return its files within the
+/// limits.
+pub fn parse<'a>(body: &'a [u8], config: &NativePayloadConfig) -> Result<Vec<NativeFile<'a>>, ServiceError> {
+    let no_files = || ServiceError::InvalidPayload("No files found in FlatBuffers data".to_string());
+    let opts = config.verifier_options(body);
+    if store_v2::file_data_list_buffer_has_identifier(body) {
+        let list = store_v2::root_as_file_data_list_with_opts(&opts, body).map_err(|e| config.invalid("v2", e))?;
+        let files = list.files().ok_or_else(no_files)?;
+        config.check(files.len(), files.iter().map(|f| f.data().map_or(0, |d| d.len())))?;
+        Ok(files.iter().map(|f| {
+            NativeFile { data: f.data().map(|d| d.bytes()), name: f.name(), content_type: f.content_type() }
+        }).collect())
+    } else {
+        let list = flatbuffers::root_with_opts::<store::FileDataList>(&opts, body).map_err(|e| config.invalid("v1", e))?;
+        let files = list.files().ok_or_else(no_files)?;
+        config.check(files.len(), files.iter().map(|f| f.data().map_or(0, |d| d.len())))?;
+        Ok(files.iter().map(|f| NativeFile { data: f.data().map(|d| d.bytes()), name: None, content_type: None }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flatbuffers::FlatBufferBuilder;
+
+    fn v1(files: &[&[u8]]) -> Vec<u8> {
+        let mut builder = FlatBufferBuilder::new();
+        let files: Vec<_> = files.iter().map(|data| {
+            let data = builder.create_vector(data);
+            store::FileData::create(&mut builder, &store::FileDataArgs { data: Some(data) })
+        }).collect();
+        let files = builder.create_vector(&files);
+        let list = store::FileDataList::create(&mut builder, &store::FileDataListArgs { files: Some(files) });
+        builder.finish(list, None);
+        builder.finished_data().to_vec()
+    }
+
+    #[test]
+    fn test_limits_name_what_was_exceeded() {
+        let config = NativePayloadConfig { max_files: 3, max_file_bytes: 4 };
+        let body = v1(&[b"ab", b"", b"abcd"]);
+        let files = parse(&body, &config).unwrap();
+        assert_eq!(files.iter().map(|f| f.data.unwrap()).collect::<Vec<_>>(), vec![&b"ab"[..], b"", b"abcd"]);
+
+        let err = parse(&v1(&[b"a", b"b", b"c", b"d"]), &config).unwrap_err();
+        assert!(matches!(err, ServiceError::PayloadLimitExceeded { limit: "files", max: 3, .. }), "{:?}", err);
+        let err = parse(&v1(&[b"abcde"]), &config).unwrap_err();
+        assert_eq!(err, ServiceError::PayloadLimitExceeded { limit: "file_bytes", max: 4, actual: Some(5) });
+
+        let err = parse(b"not a flatbuffer", &config).unwrap_err();
+        assert!(matches!(err, ServiceError::InvalidPayload(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_corrupted_bytes_never_read_past_the_body() {
+        let config = NativePayloadConfig { max_files: 8, max_file_bytes: 1024 };
+        let valid = v1(&[b"first file", b"second", b""]);
+        for at in 0..valid.len() {
+            for byte in [0x00, 0x7F, 0xFF] {
+                let mut body = valid.clone();
+                body[at] = byte;
+                if let Ok(files) = parse(&body, &config) {
+                    let total: usize = files.iter().map(|f| f.data.map_or(0, <[u8]>::len)).sum();
+                    assert!(total <= body.len(), "byte {} set to {:#x}", at, byte);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Stored representation of an object
+//!
+//! Native writes keep the payload's file structure, one chunk per file; S3 writes store the
+//! body as raw bytes. Which one an object has is recorded in its `format` property when it is
+//! written, and native GET answers in that representation: a FileDataList for `native`, the
+//! bytes with the stored Content-Type for `raw`. `?format=native|raw` forces either. Objects
+//! written before the property existed read as `native`, as they always have. Native GET and
+//! S3 HEAD report the format in `X-Warp-Object-Format`.
+
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::metadata::Metadata;
+
+/// Response header carrying the [`ObjectFormat`]
+pub const OBJECT_FORMAT_HEADER: &str = "x-warp-object-format";
+
+/// Metadata property holding the format
+const FORMAT_PROPERTY: &str = "format";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectFormat {
+    /// FlatBuffers payload, one chunk per file
+    Native,
+    /// Raw bytes written through S3
+    Raw,
+}
+
+impl ObjectFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectFormat::Native => "native",
+            ObjectFormat::Raw => "raw",
+        }
+    }
+
+    /// Format recorded on `metadata`; `Native` when none was
+    pub fn of(metadata: &Metadata) -> Self {
+        metadata.properties
+            .get(FORMAT_PROPERTY)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(ObjectFormat::Native)
+    }
+
+    /// Record this format on `metadata`
+    pub fn record(&self, metadata: &mut Metadata) {
+        metadata.properties.insert(FORMAT_PROPERTY.to_string(), self.as_str().to_string());
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(ObjectFormat::Native),
+            "raw" => Ok(ObjectFormat::Raw),
+            other => Err(format!("Unknown object format: {} (expected native or raw)", other)),
+        }
+    }
+}
@@ -0,0 +1,560 @@
+//! Native object operations without HTTP
+//!
+//! The native handlers in [`crate::service`] parse headers, drain payloads and format
+//! responses; what each operation does to metadata and storage in between lives here, on
+//! plain types, so it can be exercised without building requests (against the mock metadata
+//! and storage backends in tests). Failures are the [`ServiceError`]s the handlers report.
+//!
+//! Writes take their body as a future rather than bytes: the key is locked and checked
+//! before the body is read, so a PUT of an existing key is refused without draining it.
+
+use actix_web::Error;
+use bytes::Bytes;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+
+use crate::metadata::{last_modified_now, DataChunk, DeletionSummary, FileAttrs, Metadata};
+use crate::service::access_tracking;
+use crate::service::append_dedup::{self, AppendDedupConfig, AppendRecord};
+use crate::service::bucket_registry::BucketConfig;
+use crate::service::error::ServiceError;
+use crate::service::idempotency::{self, IdempotencyConfig, IdempotencyRecord};
+use crate::service::inline_objects::{self, InlineConfig};
+use crate::service::key_limits::KeyLimits;
+use crate::service::leases;
+use crate::service::metadata_service::MetadataService;
+use crate::service::object_state::ObjectState;
+use crate::service::prefetch;
+use crate::service::size_stats::{self, Api, WriteType};
+use crate::service::storage_service::{self, NativeWrite, StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+use crate::storage::bucket_lock;
+use crate::util::byte_ranges;
+use crate::util::payload::PayloadLimits;
+
+/// What a PUT or update stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct PutOutcome {
+    pub size: u64,
+    pub files: usize,
+    /// Kept in the metadata row rather than in storage
+    pub inline: bool,
+    /// The idempotency key matched the PUT that created the object; nothing was written
+    pub replayed: bool,
+}
+
+/// What an append did
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppendOutcome {
+    Appended(AppendRecord),
+    /// The append id was already applied; nothing was written
+    Replayed(AppendRecord),
+    /// The body held no files and empty appends are allowed
+    Empty,
+}
+
+/// A live object looked up for reading, narrowed to the selected chunks
+#[derive(Debug, Clone)]
+pub struct ObjectRead {
+    pub metadata: Metadata,
+    /// Storage extents of the selected chunks; empty for inline objects
+    pub extents: Vec<(u64, u64)>,
+    /// Recorded checksums of the selected chunks, `None` for chunks without one
+    pub checksums: Vec<Option<u32>>,
+    pub files: Vec<FileAttrs>,
+    /// Selected files of an inline object
+    pub inline: Vec<Bytes>,
+    /// Chunk index in the object of the first selected chunk
+    pub first: usize,
+    /// Build native payloads as v2, every file carrying its chunk index, even when no chunk
+    /// has attributes; set for the v2 API
+    pub indexed: bool,
+}
+
+impl ObjectRead {
+    /// Bytes of the selected chunks laid end to end
+    pub fn raw_len(&self) -> u64 {
+        if self.metadata.is_inline() {
+            self.inline.iter().map(|f| f.len() as u64).sum()
+        } else {
+            self.extents.iter().map(|(_, size)| size).sum()
+        }
+    }
+}
+
+/// One object of a listing page
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListedObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+    /// Only present while reads are tracked; null for keys not read since
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_accessed_at: Option<Option<String>>,
+}
+
+/// One page of a bucket listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListPage {
+    pub objects: Vec<ListedObject>,
+    /// Key to list after for the next page, when there is one
+    pub next_after: Option<String>,
+    pub object_count: u64,
+    pub total_bytes: u64,
+}
+
+/// Store a native payload, on the CPU pool when it is large; an empty body is a zero-length
+/// object with no chunks.
+async fn write_native_or_empty(storage_service: &StorageService, context: &UserContext, bytes: &Bytes) -> Result<NativeWrite, Error> {
+    if bytes.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let (context, bytes) = (context.clone(), bytes.clone());
+    storage_service.offload(bytes.len(), move |storage| storage.write_native(&context, &bytes)).await
+}
+
+/// `key` is missing from `bucket`; name the other buckets holding it, if any, since the
+/// usual cause is a wrong or missing `Bucket` header. The owner's other buckets stay unnamed
+/// to a user working in `bucket` through a grant.
+pub(crate) fn missing_key_error(db: &MetadataService, context: &UserContext, bucket: &str, key: &str) -> ServiceError {
+    if context.granted_to.is_some() {
+        return ServiceError::key_not_found(bucket, key);
+    }
+    match db.buckets_holding(key, bucket) {
+        Ok(found_in) if !found_in.is_empty() => ServiceError::KeyInOtherBuckets {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            found_in,
+        },
+        _ => ServiceError::key_not_found(bucket, key),
+    }
+}
+
+/// Native object operations on one storage backend
+#[derive(Default)]
+pub struct ObjectService {
+    storage: StorageService,
+}
+
+impl ObjectService {
+    /// Service on the configured storage backend
+    pub fn new() -> Self {
+        Self { storage: StorageService::new() }
+    }
+
+    pub fn with_storage(storage: StorageService) -> Self {
+        Self { storage }
+    }
+
+    /// Create `key` from the FlatBuffers payload `body` resolves to; KeyExists when it is
+    /// live. Small payloads are stored inline.
+    pub async fn put<B>(&self, context: &UserContext, key: &str, body: B, expire_at: Option<String>) -> Result<PutOutcome, Error>
+    where
+        B: Future<Output = Result<Bytes, Error>>,
+    {
+        self.put_idempotent(context, key, body, expire_at, None).await
+    }
+
+    /// [`Self::put`] tagged with a client's `idempotency_key`: when `key` was created by a PUT
+    /// with the same idempotency key and body, that PUT's outcome is returned instead of
+    /// KeyExists and nothing is written.
+    pub async fn put_idempotent<B>(&self, context: &UserContext, key: &str, body: B, expire_at: Option<String>, idempotency_key: Option<&str>) -> Result<PutOutcome, Error>
+    where
+        B: Future<Output = Result<Bytes, Error>>,
+    {
+        KeyLimits::from_env().check_new_key(key)?;
+        let db = MetadataService::new(&context.user_id)?;
+        db.ensure_bucket_for_write(&context.bucket, &BucketConfig::from_env())?;
+        let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[key]).await;
+        leases::check(context, &[key])?;
+
+        let key_exists = db.check_key_for_create(&context.bucket, key)?;
+        info!("Key exists check result: {} for key: {} in bucket: {}", key_exists, key, context.bucket);
+        if key_exists {
+            // A retry of the PUT that created the key is answered from its record
+            if let Some(id) = idempotency_key {
+                let existing = db.get_object_full(&context.bucket, key)?;
+                if let Some(record) = idempotency::find(&existing, id, &IdempotencyConfig::from_env()) {
+                    if record.digest == idempotency::digest(&body.await?) {
+                        info!("Idempotency key {} already created key: {}, skipping write", id, key);
+                        return Ok(PutOutcome { size: record.size, files: record.files, inline: record.inline, replayed: true });
+                    }
+                }
+            }
+            warn!("Key already exists: {} in bucket: {}", key, context.bucket);
+            return Err(ServiceError::key_exists(&context.bucket, key).into());
+        }
+        // Refuse a new object in a full bucket before any of its data is written
+        db.check_quota(&context.bucket, &[(key, 0)], false)?;
+
+        info!("Starting chunk load for user: {}, bucket: {}", context.user_id, context.bucket);
+        let bytes = body.await?;
+        info!("Total received data size: {} bytes", bytes.len());
+        let tagged = idempotency_key.map(|id| IdempotencyRecord::new(id, &bytes));
+        let remember = |metadata: &mut Metadata, outcome: &PutOutcome| {
+            if let Some(record) = &tagged {
+                let record = IdempotencyRecord { size: outcome.size, files: outcome.files, inline: outcome.inline, ..record.clone() };
+                idempotency::remember(metadata, &record);
+            }
+        };
+
+        // Small payloads are kept in the metadata row and never reach storage
+        let written = match inline_objects::native_object(&bytes, &InlineConfig::from_env())? {
+            Some((mut metadata, files)) => {
+                info!("Storing {} bytes inline for key: {}", metadata.size, key);
+                metadata.set_meta_properties(&context.metadata);
+                let outcome = PutOutcome { size: metadata.size, files: files.len(), inline: true, replayed: false };
+                remember(&mut metadata, &outcome);
+                db.write_native_object(&context.bucket, key, metadata, &files, expire_at).map(|_| (outcome, Vec::new()))
+            }
+            None => {
+                let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+                let (offset_size_list, files) = write_native_or_empty(&self.storage, context, &bytes).await?;
+
+                info!("Writing metadata for user: {}, bucket: {}, key: {}", context.user_id, context.bucket, key);
+                let mut metadata = Metadata::from_offset_size_list(offset_size_list);
+                metadata.set_meta_properties(&context.metadata);
+                let outcome = PutOutcome { size: metadata.size, files: files.len(), inline: false, replayed: false };
+                remember(&mut metadata, &outcome);
+                let chunk_sizes = size_stats::chunk_sizes(&metadata);
+                db.write_native_object(&context.bucket, key, metadata, &files, expire_at).map(|_| (outcome, chunk_sizes))
+            }
+        };
+        let (outcome, chunk_sizes) = written.map_err(|e| {
+            // Lost a race with another PUT of the same key: native_error turns this into KeyExists
+            error!("Failed to write metadata for user: {}, bucket: {}, key: {}: {}", context.user_id, context.bucket, key, e);
+            e
+        })?;
+        info!("Successfully wrote metadata for user: {}, bucket: {}, key: {}", context.user_id, context.bucket, key);
+        size_stats::record(Api::Native, WriteType::Put, outcome.size, &chunk_sizes);
+        Ok(outcome)
+    }
+
+    /// Replace the data of live `key` with `body`. `keep_metadata` keeps the object's
+    /// metadata; otherwise the context's replaces it.
+    pub async fn update<B>(&self, context: &UserContext, key: &str, body: B, expire_at: Option<String>, keep_metadata: bool) -> Result<PutOutcome, Error>
+    where
+        B: Future<Output = Result<Bytes, Error>>,
+    {
+        let db = MetadataService::new(&context.user_id)?;
+        db.ensure_bucket_for_write(&context.bucket, &BucketConfig::from_env())?;
+        let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[key]).await;
+        leases::check(context, &[key])?;
+        db.check_key_nonexistance(&context.bucket, key)?;
+        // COPY keeps the metadata the object has, REPLACE takes the request's (none drops it)
+        let meta_properties: HashMap<String, String> = if keep_metadata {
+            db.get_object_full(&context.bucket, key)?.meta_properties().into_iter().collect()
+        } else {
+            context.metadata.clone()
+        };
+
+        info!("Starting chunk load");
+        let bytes = body.await?;
+        info!("Total received data size: {} bytes", bytes.len());
+
+        // Rewrite with provided FlatBuffers payload (or truncate to zero length)
+        if let Some((mut metadata, files)) = inline_objects::native_object(&bytes, &InlineConfig::from_env())? {
+            metadata.set_meta_properties(&meta_properties);
+            let outcome = PutOutcome { size: metadata.size, files: files.len(), inline: true, replayed: false };
+            db.update_native_object(&context.bucket, key, metadata, &files, expire_at)?;
+            size_stats::record(Api::Native, WriteType::Put, outcome.size, &[]);
+            Ok(outcome)
+        } else {
+            let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+            let (offset_size_list, files) = write_native_or_empty(&self.storage, context, &bytes).await?;
+
+            let mut metadata = Metadata::from_offset_size_list(offset_size_list);
+            metadata.set_meta_properties(&meta_properties);
+            let outcome = PutOutcome { size: metadata.size, files: files.len(), inline: false, replayed: false };
+            let chunk_sizes = size_stats::chunk_sizes(&metadata);
+            db.update_native_object(&context.bucket, key, metadata, &files, expire_at)?;
+            size_stats::record(Api::Native, WriteType::Put, outcome.size, &chunk_sizes);
+            Ok(outcome)
+        }
+    }
+
+    /// Add the files of `body` to the end of live `key`. With an `append_id` already applied
+    /// the stored record is returned and nothing is read or written.
+    pub async fn append<B>(&self, context: &UserContext, key: &str, body: B, append_id: Option<&str>) -> Result<AppendOutcome, Error>
+    where
+        B: Future<Output = Result<Bytes, Error>>,
+    {
+        let db = MetadataService::new(&context.user_id)?;
+        db.ensure_bucket_for_write(&context.bucket, &BucketConfig::from_env())?;
+        let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[key]).await;
+        leases::check(context, &[key])?;
+        db.check_key_nonexistance(&context.bucket, key)?;
+
+        // A retried append with an id we already applied is answered from the stored record
+        if let Some(id) = append_id {
+            let existing = db.get_object_full(&context.bucket, key)?;
+            if let Some(record) = append_dedup::find(&existing, id) {
+                info!("Append id {} already applied to key: {}, skipping write", id, key);
+                return Ok(AppendOutcome::Replayed(record));
+            }
+        }
+
+        info!("Starting chunk load");
+        let bytes = body.await?;
+        info!("Total received data size: {} bytes", bytes.len());
+
+        let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+        let mut metadata = db.get_object_full(&context.bucket, key)?;
+
+        // Appends to an inline object stay in its row while it is small; past that it moves to storage
+        let inline_append = inline_objects::append_native(&mut metadata, &bytes, &InlineConfig::from_env())?;
+        let (mut offset_size_list_append, mut files_append) = match inline_append {
+            Some(_) => (Vec::new(), Vec::new()),
+            None => {
+                inline_objects::spill(&self.storage, context, &mut metadata)?;
+                // Write additional FlatBuffers payload chunks to storage
+                write_native_or_empty(&self.storage, context, &bytes).await?
+            }
+        };
+
+        if offset_size_list_append.is_empty() && inline_append.is_none_or(|appended| appended.files == 0) {
+            if !PayloadLimits::from_env().allow_empty_append {
+                error!("No data to append with key: {}", key);
+                return Err(ServiceError::EmptyPayload("No data was uploaded").into());
+            }
+            info!("Empty append to key: {}, nothing to do", key);
+            return Ok(AppendOutcome::Empty);
+        }
+
+        let chunk_sizes: Vec<u64> = offset_size_list_append.iter().map(|(_, size)| *size).collect();
+        let id = append_id.map(str::to_string).unwrap_or_default();
+        let record = match inline_append {
+            Some(appended) => AppendRecord {
+                id,
+                first_chunk: appended.first_file,
+                chunks: appended.files,
+                size: appended.size,
+            },
+            None => {
+                let mut files = metadata.file_attrs();
+                let record = AppendRecord {
+                    id,
+                    first_chunk: metadata.chunks.len(),
+                    chunks: offset_size_list_append.len(),
+                    size: offset_size_list_append.iter().map(|(_, size)| size).sum(),
+                };
+                metadata.chunks.extend(offset_size_list_append.drain(..).map(DataChunk::from_extent));
+                metadata.size += record.size;
+                files.append(&mut files_append);
+                metadata.set_file_attrs(&files);
+                record
+            }
+        };
+        if append_id.is_some() {
+            append_dedup::remember(&mut metadata, record.clone(), AppendDedupConfig::from_env().window);
+        }
+
+        metadata.last_modified = Some(last_modified_now());
+        db.update_object_full(&context.bucket, key, &metadata)?;
+        info!("Data appended successfully with key: {}", key);
+        size_stats::record(Api::Native, WriteType::Append, record.size, &chunk_sizes);
+        Ok(AppendOutcome::Appended(record))
+    }
+
+    /// Look up live `key` for reading, keeping only chunks `selection` (inclusive indices)
+    /// when given. A key being deleted is KeyDeleting rather than missing.
+    pub fn get(&self, context: &UserContext, key: &str, selection: Option<(usize, usize)>) -> Result<ObjectRead, Error> {
+        // A prefetched entry stands for both lookups
+        let metadata = match prefetch::cached(&context.user_id, &context.bucket, key) {
+            Some(metadata) => metadata,
+            None => {
+                let db = MetadataService::new(&context.user_id)?;
+                match db.object_state(&context.bucket, key)? {
+                    Some(ObjectState::Active) => {}
+                    Some(ObjectState::Deleting) => {
+                        return Err(ServiceError::KeyDeleting { bucket: context.bucket.clone(), key: key.to_string() }.into());
+                    }
+                    Some(ObjectState::Deleted) | None => return Err(ServiceError::key_not_found(&context.bucket, key).into()),
+                }
+                db.get_object_full(&context.bucket, key)?
+            }
+        };
+        info!("Retrieving data for key: {} in bucket: {}", key, context.bucket);
+        Self::select(metadata, selection)
+    }
+
+    /// Like [`Self::get`] for `key` as it was at `as_of`, a stored timestamp
+    /// (`service::as_of`). A key behind a delete marker then is KeyDeletedAsOf.
+    pub fn get_as_of(&self, context: &UserContext, key: &str, as_of: &str, selection: Option<(usize, usize)>) -> Result<ObjectRead, Error> {
+        let db = MetadataService::new(&context.user_id)?;
+        let metadata = match db.object_as_of(&context.bucket, key, as_of)? {
+            Some(metadata) if metadata.is_delete_marker => {
+                return Err(ServiceError::KeyDeletedAsOf { bucket: context.bucket.clone(), key: key.to_string(), as_of: as_of.to_string() }.into());
+            }
+            Some(metadata) => metadata,
+            None => return Err(ServiceError::key_not_found(&context.bucket, key).into()),
+        };
+        info!("Retrieving data for key: {} in bucket: {} as of {}", key, context.bucket, as_of);
+        Self::select(metadata, selection)
+    }
+
+    /// The read of `metadata`'s chunks `selection`, or all of them
+    fn select(metadata: Metadata, selection: Option<(usize, usize)>) -> Result<ObjectRead, Error> {
+        let mut extents = metadata.to_offset_size_list();
+        let mut checksums: Vec<Option<u32>> = metadata.chunks.iter().map(|chunk| chunk.checksum).collect();
+        let mut files = metadata.file_attrs();
+        let mut inline: Vec<Bytes> = metadata.inline_files().into_iter().map(Bytes::copy_from_slice).collect();
+        let mut first_selected = 0;
+
+        // Only read the selected chunks; each inline file counts as one
+        if let Some((first, last)) = selection {
+            let chunk_count = files.len();
+            if last >= chunk_count {
+                let requested = if first == last { first.to_string() } else { format!("{}-{}", first, last) };
+                return Err(ServiceError::ChunkOutOfRange { requested, chunk_count }.into());
+            }
+            if metadata.is_inline() {
+                inline = inline[first..=last].to_vec();
+            } else {
+                extents = extents[first..=last].to_vec();
+                checksums = checksums[first..=last].to_vec();
+            }
+            files = files.get(first..=last).map(<[_]>::to_vec).unwrap_or_default();
+            first_selected = first;
+        }
+        Ok(ObjectRead { metadata, extents, checksums, files, inline, first: first_selected, indexed: false })
+    }
+
+    /// The selected chunks of `read` as a FlatBuffers payload, one file per chunk in chunk order
+    pub fn read_native(&self, context: &UserContext, read: &ObjectRead) -> Result<Bytes, Error> {
+        if read.metadata.is_inline() {
+            let inline: Vec<&[u8]> = read.inline.iter().map(|f| f.as_ref()).collect();
+            storage_service::inline_native(&inline, read.first, &read.files, read.indexed)
+        } else {
+            self.storage.read_native(context, &read.extents, read.first, &read.files, &read.checksums, read.indexed)
+        }
+    }
+
+    /// Raw bytes `first..=last` of the selected chunks of `read`, or all of them
+    pub fn read_raw(&self, context: &UserContext, read: &ObjectRead, range: Option<(u64, u64)>) -> Result<Bytes, Error> {
+        if read.metadata.is_inline() {
+            let data = Bytes::from(read.inline.concat());
+            return Ok(match range {
+                Some((first, last)) => data.slice(first as usize..=last as usize),
+                None => data,
+            });
+        }
+        match range {
+            Some((first, last)) => self.storage.read_object(context, &byte_ranges::range_slices(&read.extents, first, last, u64::MAX), StorageMode::RAW),
+            None => self.storage.read_verified(context, &read.extents, &read.checksums),
+        }
+    }
+
+    /// [`Self::read_native`] with the chunk reads and checks on the CPU pool
+    pub async fn read_native_pooled(&self, context: &UserContext, read: &ObjectRead) -> Result<Bytes, Error> {
+        if read.metadata.is_inline() {
+            return self.read_native(context, read);
+        }
+        let (context, read) = (context.clone(), read.clone());
+        self.storage.offload(read.raw_len() as usize, move |storage| {
+            storage.read_native(&context, &read.extents, read.first, &read.files, &read.checksums, read.indexed)
+        }).await
+    }
+
+    /// [`Self::read_raw`] with the chunk reads and checks on the CPU pool
+    pub async fn read_raw_pooled(&self, context: &UserContext, read: &ObjectRead, range: Option<(u64, u64)>) -> Result<Bytes, Error> {
+        if read.metadata.is_inline() {
+            return self.read_raw(context, read, range);
+        }
+        let bytes = range.map_or(read.raw_len(), |(first, last)| last - first + 1) as usize;
+        let (context, read) = (context.clone(), read.clone());
+        self.storage.offload(bytes, move |storage| {
+            ObjectService::with_storage(storage.clone()).read_raw(&context, &read, range)
+        }).await
+    }
+
+    /// Delete live `key`, queueing its chunks for the deletion worker
+    pub async fn delete(&self, context: &UserContext, key: &str) -> Result<DeletionSummary, Error> {
+        let db = MetadataService::new(&context.user_id)?;
+        let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[key]).await;
+        leases::check(context, &[key])?;
+        if !db.check_key(&context.bucket, key)? {
+            return Err(missing_key_error(&db, context, &context.bucket, key).into());
+        }
+        let queued = self.storage.delete_object(context, key)?;
+        info!("Deleted key: {} in bucket: {}, {} bytes in {} chunks queued for reclaiming",
+              key, context.bucket, queued.bytes, queued.chunks);
+        Ok(queued)
+    }
+
+    /// Rename live `old_key` to `new_key`, which must not be live
+    pub async fn rename(&self, context: &UserContext, old_key: &str, new_key: &str) -> Result<(), Error> {
+        KeyLimits::from_env().check_new_key(new_key)?;
+        let db = MetadataService::new(&context.user_id)?;
+        let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[old_key, new_key]).await;
+        leases::check(context, &[old_key, new_key])?;
+        db.check_key_nonexistance(&context.bucket, old_key)?;
+        if db.check_key(&context.bucket, new_key)? {
+            return Err(ServiceError::key_exists(&context.bucket, new_key).into());
+        }
+        db.rename_key(&context.bucket, old_key, new_key)
+    }
+
+    /// Up to `limit` live objects of the bucket with keys after `after`, in key order, only those
+    /// carrying the `(key, value)` tag when one is given
+    pub fn list(&self, context: &UserContext, tag: Option<(&str, &str)>, after: &str, limit: usize) -> Result<ListPage, Error> {
+        let db = MetadataService::new(&context.user_id)?;
+        let keys = match tag {
+            Some((tag_key, tag_value)) => db.list_objects_with_tag(&context.bucket, tag_key, tag_value)?,
+            None => db.list_objects(&context.bucket)?,
+        };
+        let mut page = keys.iter().filter(|k| k.as_str() > after).peekable();
+        let mut objects = Vec::new();
+        while objects.len() < limit {
+            let Some(key) = page.next() else { break };
+            // Skip keys deleted between the listing and the lookup
+            if let Ok(meta) = db.get_object_full(&context.bucket, key) {
+                objects.push(ListedObject {
+                    key: key.clone(),
+                    size: meta.size,
+                    last_modified: meta.last_modified.clone(),
+                    metadata: meta.meta_properties(),
+                    last_accessed_at: None,
+                });
+            }
+        }
+        if access_tracking::enabled() {
+            let listed: Vec<&str> = objects.iter().map(|o| o.key.as_str()).collect();
+            let times = access_tracking::last_accessed(&context.user_id, &context.bucket, &listed)?;
+            for object in &mut objects {
+                object.last_accessed_at = Some(times.get(&object.key).cloned());
+            }
+        }
+        let next_after = if page.peek().is_some() { objects.last().map(|o| o.key.clone()) } else { None };
+        let (object_count, total_bytes) = db.bucket_object_stats(&context.bucket)?;
+        Ok(ListPage { objects, next_after, object_count, total_bytes })
+    }
+
+    /// Like [`Self::list`] for the objects live at `as_of`, a stored timestamp. The usage
+    /// covers the listed page only, as the bucket's totals describe it now.
+    pub fn list_as_of(&self, context: &UserContext, as_of: &str, after: &str, limit: usize) -> Result<ListPage, Error> {
+        let db = MetadataService::new(&context.user_id)?;
+        let mut versions = db.versions_as_of(&context.bucket, as_of, after, limit + 1)?;
+        let more = versions.len() > limit;
+        versions.truncate(limit);
+        let mut objects = Vec::with_capacity(versions.len());
+        for (key, version_id) in versions {
+            // Skip versions deleted between the listing and the lookup
+            if let Ok(meta) = db.get_object_version(&context.bucket, &key, &version_id) {
+                objects.push(ListedObject {
+                    size: meta.size,
+                    last_modified: meta.last_modified.clone(),
+                    metadata: meta.meta_properties(),
+                    last_accessed_at: None,
+                    key,
+                });
+            }
+        }
+        let next_after = if more { objects.last().map(|o| o.key.clone()) } else { None };
+        let (object_count, total_bytes) = (objects.len() as u64, objects.iter().map(|o| o.size).sum());
+        Ok(ListPage { objects, next_after, object_count, total_bytes })
+    }
+}
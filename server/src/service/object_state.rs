@@ -0,0 +1,33 @@
+//! Object lifecycle state reported on native GET
+//!
+//! Deletes are queued: the metadata row is removed at once and the deletion worker frees the
+//! chunks later. Until it has, GET answers 404 with `X-Warp-Object-State: deleting`, so a
+//! client can tell a key it just deleted from one that never existed (plain 404, no header).
+//! A read as of a time the key was behind a delete marker (`?as_of=`) answers 404 with
+//! `deleted`. Successful reads carry `active`.
+
+use serde::Serialize;
+
+/// Response header carrying the [`ObjectState`]
+pub const OBJECT_STATE_HEADER: &str = "x-warp-object-state";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectState {
+    /// Metadata present
+    Active,
+    /// Metadata gone, chunks still queued for deletion
+    Deleting,
+    /// Behind a delete marker at the time read
+    Deleted,
+}
+
+impl ObjectState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectState::Active => "active",
+            ObjectState::Deleting => "deleting",
+            ObjectState::Deleted => "deleted",
+        }
+    }
+}
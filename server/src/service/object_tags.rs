@@ -0,0 +1,52 @@
+//! Object tag limits shared by the native and S3 tagging APIs
+//!
+//! Tags live in the `object_tags` table keyed by (user, bucket, key), so a tag set follows its
+//! object: it is moved on rename, dropped when the last version is deleted and copied (or
+//! replaced) on copy according to the tagging directive. Both APIs enforce S3's limits: at
+//! most 10 tags per object, keys up to 128 bytes and values up to 256.
+
+pub const MAX_OBJECT_TAGS: usize = 10;
+pub const MAX_TAG_KEY_BYTES: usize = 128;
+pub const MAX_TAG_VALUE_BYTES: usize = 256;
+
+/// Check `tags` against the limits; the error is the message S3 reports for `InvalidTag`.
+pub fn check_tags(tags: &[(String, String)]) -> Result<(), &'static str> {
+    if tags.len() > MAX_OBJECT_TAGS {
+        return Err("Object tag count cannot be greater than 10");
+    }
+    for (k, v) in tags {
+        if k.is_empty() || k.len() > MAX_TAG_KEY_BYTES {
+            return Err("The tag key you have provided is invalid");
+        }
+        if v.len() > MAX_TAG_VALUE_BYTES {
+            return Err("The tag value you have provided is invalid");
+        }
+    }
+    Ok(())
+}
+
+/// Parse a `key:value` tag filter, splitting on the first `:`.
+pub fn parse_tag_filter(filter: &str) -> Option<(&str, &str)> {
+    filter.split_once(':').filter(|(k, _)| !k.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_limits_and_filter() {
+        let tags: Vec<(String, String)> = (0..10).map(|i| (format!("k{}", i), "v".to_string())).collect();
+        assert!(check_tags(&tags).is_ok());
+        let mut too_many = tags.clone();
+        too_many.push(("k10".into(), "v".into()));
+        assert!(check_tags(&too_many).is_err());
+        assert!(check_tags(&[("k".repeat(129), String::new())]).is_err());
+        assert!(check_tags(&[("k".into(), "v".repeat(257))]).is_err());
+
+        assert_eq!(parse_tag_filter("project:alpha"), Some(("project", "alpha")));
+        assert_eq!(parse_tag_filter("url:http://x"), Some(("url", "http://x")));
+        assert_eq!(parse_tag_filter("project"), None);
+        assert_eq!(parse_tag_filter(":alpha"), None);
+    }
+}
@@ -0,0 +1,226 @@
+//! Sampled digests of object data for end-to-end debugging
+//!
+//! With `DIGEST_SAMPLE_RATE` above 0 (default 0, at most 1), that fraction of native and S3
+//! PUT/GET requests hash the object data they carry with SHA-256 as it streams, and log one
+//! line at info with target `payload_digest` once the body has been read or sent:
+//!
+//! `{"request_id":"req-..","user":"..","bucket":"..","key":"..","direction":"upload","bytes":5,"sha256":"..","complete":true}`
+//!
+//! The data is hashed as the client sees it: after aws-chunked decoding on upload, before
+//! response compression on download. A ranged or chunk-selected GET hashes the part sent. A
+//! body that is abandoned before its end (a failed upload, a client that disconnects) is
+//! logged with `"complete":false`. Requests that are not sampled are not wrapped at all.
+
+use actix_web::HttpRequest;
+use bytes::Bytes;
+use futures::Stream;
+use log::info;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::logging;
+
+/// Log target of digest lines
+pub const LOG_TARGET: &str = "payload_digest";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DigestConfig {
+    /// Fraction of requests sampled, 0 to 1
+    pub sample_rate: f64,
+}
+
+impl DigestConfig {
+    pub fn from_env() -> Self {
+        let sample_rate = env::var("DIGEST_SAMPLE_RATE")
+            .ok()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|rate| rate.is_finite())
+            .map(|rate| rate.clamp(0.0, 1.0))
+            .unwrap_or_default();
+        Self { sample_rate }
+    }
+}
+
+/// Which way the data went
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// A request picked for digest logging
+#[derive(Debug, Clone)]
+pub struct Sample {
+    request_id: String,
+}
+
+/// Pick the request for digest logging with probability `DIGEST_SAMPLE_RATE`.
+pub fn sample(req: &HttpRequest) -> Option<Sample> {
+    let rate = DigestConfig::from_env().sample_rate;
+    if rate <= 0.0 {
+        return None;
+    }
+    if rate < 1.0 {
+        let mut bytes = [0u8; 8];
+        getrandom::getrandom(&mut bytes).ok()?;
+        if (u64::from_le_bytes(bytes) as f64 / u64::MAX as f64) >= rate {
+            return None;
+        }
+    }
+    Some(Sample { request_id: logging::request_id(req) })
+}
+
+impl Sample {
+    /// Start hashing the data of `key`
+    pub fn digest(&self, user_id: &str, bucket: &str, key: &str, direction: Direction) -> PayloadDigest {
+        PayloadDigest {
+            line: DigestLine {
+                request_id: self.request_id.clone(),
+                user: user_id.to_string(),
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+                direction,
+                bytes: 0,
+                sha256: String::new(),
+                complete: false,
+            },
+            hasher: Sha256::new(),
+            logged: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DigestLine {
+    request_id: String,
+    user: String,
+    bucket: String,
+    key: String,
+    direction: Direction,
+    bytes: u64,
+    sha256: String,
+    complete: bool,
+}
+
+/// Running SHA-256 of one body; logged by [`PayloadDigest::finish`], or as incomplete when
+/// dropped before
+pub struct PayloadDigest {
+    line: DigestLine,
+    hasher: Sha256,
+    logged: bool,
+}
+
+impl PayloadDigest {
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.line.bytes += data.len() as u64;
+    }
+
+    /// Log the digest of the whole body
+    pub fn finish(mut self) {
+        self.log(true);
+    }
+
+    fn log(&mut self, complete: bool) {
+        if self.logged {
+            return;
+        }
+        self.logged = true;
+        self.line.complete = complete;
+        self.line.sha256 = hex::encode(std::mem::take(&mut self.hasher).finalize());
+        match serde_json::to_string(&self.line) {
+            Ok(line) => info!(target: LOG_TARGET, "{}", line),
+            Err(e) => info!(target: LOG_TARGET, "digest of {} not logged: {}", self.line.key, e),
+        }
+    }
+}
+
+impl Drop for PayloadDigest {
+    fn drop(&mut self) {
+        self.log(false);
+    }
+}
+
+/// Body stream that feeds every chunk it yields to a [`PayloadDigest`]
+pub struct DigestStream<S> {
+    inner: S,
+    digest: Option<PayloadDigest>,
+}
+
+/// Hash `body` as it is consumed; unchanged when `digest` is None.
+pub fn wrap<S>(body: S, digest: Option<PayloadDigest>) -> DigestStream<S> {
+    DigestStream { inner: body, digest }
+}
+
+impl<S, E> Stream for DigestStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = Pin::new(&mut self.inner).poll_next(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(digest) = self.digest.as_mut() {
+                    digest.update(chunk);
+                }
+            }
+            Poll::Ready(None) => {
+                if let Some(digest) = self.digest.take() {
+                    digest.finish();
+                }
+            }
+            _ => {}
+        }
+        polled
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Hash a body that is already in memory
+pub fn digest_bytes(digest: Option<PayloadDigest>, data: &[u8]) {
+    if let Some(mut digest) = digest {
+        digest.update(data);
+        digest.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::Error;
+    use futures::StreamExt;
+
+    #[test]
+    fn test_sample_rate_is_clamped() {
+        std::env::set_var("DIGEST_SAMPLE_RATE", "7");
+        assert_eq!(DigestConfig::from_env().sample_rate, 1.0);
+        std::env::set_var("DIGEST_SAMPLE_RATE", "-1");
+        assert_eq!(DigestConfig::from_env().sample_rate, 0.0);
+        std::env::set_var("DIGEST_SAMPLE_RATE", "NaN");
+        assert_eq!(DigestConfig::from_env().sample_rate, 0.0);
+        std::env::remove_var("DIGEST_SAMPLE_RATE");
+        assert_eq!(DigestConfig::from_env(), DigestConfig::default());
+    }
+
+    #[actix_web::test]
+    async fn test_stream_is_hashed_as_it_passes() {
+        let sample = Sample { request_id: "req-1".to_string() };
+        let chunks: Vec<Result<Bytes, Error>> = vec![Ok(Bytes::from_static(b"hello ")), Ok(Bytes::from_static(b"world"))];
+        let mut stream = wrap(futures::stream::iter(chunks), Some(sample.digest("u", "b", "k", Direction::Upload)));
+        let mut seen = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            seen.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(seen, b"hello world");
+        assert!(stream.digest.is_none());
+    }
+}
@@ -0,0 +1,198 @@
+//! Metadata prefetch for sequential native GETs
+//!
+//! Clients reading `frame_000001`, `frame_000002`, ... one after another pay a metadata
+//! lookup per GET. A GET carrying `X-Warp-Prefetch: N` asks the server to look up the next
+//! N keys of the bucket in key order once it has answered; their metadata lands in a small
+//! in-memory LRU that native GET consults before SQLite. Everything here is best-effort:
+//! an unparseable hint is ignored, N is capped at `PREFETCH_MAX_KEYS` (default 64), at most
+//! `MAX_RUNNING` lookups run at once and further hints are dropped meanwhile.
+//!
+//! The cache holds up to `PREFETCH_CACHE_ENTRIES` objects (default 1024, 0 disables
+//! prefetching). `MetadataService` drops a key whenever it writes it, and a lookup racing
+//! with a write to its bucket keeps nothing. Entries older than `PREFETCH_CACHE_TTL_MS`
+//! (default 5000) are not used, which bounds how long a change made by another process
+//! sharing the database can go unseen. Hits and misses are counted in
+//! `warpdrive_metadata_prefetch_hits_total` / `_misses_total`. Settings are read at startup.
+
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metadata::Metadata;
+use crate::metrics;
+use crate::service::expiry;
+
+/// Request header asking for the metadata of the next N keys
+pub const PREFETCH_HEADER: &str = "x-warp-prefetch";
+
+/// Cap on N when `PREFETCH_MAX_KEYS` is unset
+pub const DEFAULT_MAX_KEYS: usize = 64;
+
+/// Cache capacity when `PREFETCH_CACHE_ENTRIES` is unset
+pub const DEFAULT_CACHE_ENTRIES: usize = 1024;
+
+/// Entry lifetime when `PREFETCH_CACHE_TTL_MS` is unset
+pub const DEFAULT_TTL: Duration = Duration::from_millis(5000);
+
+/// Lookups running at once
+const MAX_RUNNING: usize = 4;
+
+fn env_usize(name: &str, default: usize) -> usize {
+    env::var(name).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+lazy_static! {
+    static ref MAX_KEYS: usize = env_usize("PREFETCH_MAX_KEYS", DEFAULT_MAX_KEYS);
+    static ref CAPACITY: usize = env_usize("PREFETCH_CACHE_ENTRIES", DEFAULT_CACHE_ENTRIES);
+    static ref TTL: Duration = env::var("PREFETCH_CACHE_TTL_MS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_TTL);
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::default());
+}
+
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+type ObjectKey = (String, String, String);
+
+struct Entry {
+    metadata: Metadata,
+    stored: Instant,
+    used: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<ObjectKey, Entry>,
+    /// Bumped by every write to a `(user, bucket)`, so a lookup can tell it raced one
+    generations: HashMap<(String, String), u64>,
+    tick: u64,
+}
+
+impl Cache {
+    fn generation(&self, user_id: &str, bucket: &str) -> u64 {
+        self.generations.get(&(user_id.to_string(), bucket.to_string())).copied().unwrap_or(0)
+    }
+
+    fn bump(&mut self, user_id: &str, bucket: &str) {
+        *self.generations.entry((user_id.to_string(), bucket.to_string())).or_insert(0) += 1;
+    }
+
+    fn insert(&mut self, key: ObjectKey, metadata: Metadata) {
+        if !self.entries.contains_key(&key) && self.entries.len() >= *CAPACITY {
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, e)| e.used).map(|(k, _)| k.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(key, Entry { metadata, stored: Instant::now(), used: self.tick });
+    }
+}
+
+fn cache() -> MutexGuard<'static, Cache> {
+    CACHE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn object_key(user_id: &str, bucket: &str, key: &str) -> ObjectKey {
+    (user_id.to_string(), bucket.to_string(), key.to_string())
+}
+
+/// The number of keys a request's `X-Warp-Prefetch` asks for, capped; 0 without a usable hint
+pub fn requested(req: &actix_web::HttpRequest) -> usize {
+    req.headers()
+        .get(PREFETCH_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .map_or(0, |n| n.min(*MAX_KEYS))
+}
+
+/// Prefetched metadata of `key`, counting the hit or miss. Entries past their TTL or
+/// their expiry time are misses, the latter so the regular lookup expires the object.
+pub fn cached(user_id: &str, bucket: &str, key: &str) -> Option<Metadata> {
+    if *CAPACITY == 0 {
+        return None;
+    }
+    let found = {
+        let mut cache = cache();
+        cache.tick += 1;
+        let tick = cache.tick;
+        let object = object_key(user_id, bucket, key);
+        match cache.entries.get_mut(&object) {
+            Some(entry) if entry.stored.elapsed() < *TTL => {
+                entry.used = tick;
+                Some(entry.metadata.clone())
+            }
+            Some(_) => {
+                cache.entries.remove(&object);
+                None
+            }
+            None => None,
+        }
+    };
+    let found = found.filter(|m| m.expire_at.as_deref().is_none_or(|at| at > expiry::now().as_str()));
+    match found {
+        Some(_) => metrics::inc_counter("warpdrive_metadata_prefetch_hits_total", &[]),
+        None => metrics::inc_counter("warpdrive_metadata_prefetch_misses_total", &[]),
+    }
+    found
+}
+
+/// Whether `key` has a usable prefetched entry; does not count as a lookup.
+pub fn is_cached(user_id: &str, bucket: &str, key: &str) -> bool {
+    cache().entries.get(&object_key(user_id, bucket, key)).is_some_and(|e| e.stored.elapsed() < *TTL)
+}
+
+/// Drop `keys` of `bucket` after a write to them.
+pub(crate) fn invalidate(user_id: &str, bucket: &str, keys: &[&str]) {
+    let mut cache = cache();
+    cache.bump(user_id, bucket);
+    for key in keys {
+        cache.entries.remove(&object_key(user_id, bucket, key));
+    }
+}
+
+/// Drop every key of `bucket` after a write that may touch any of them.
+pub(crate) fn invalidate_bucket(user_id: &str, bucket: &str) {
+    let mut cache = cache();
+    cache.bump(user_id, bucket);
+    cache.entries.retain(|(u, b, _), _| u != user_id || b != bucket);
+}
+
+/// Look up the `count` keys after `key` in the background and cache their metadata.
+pub fn schedule(user_id: &str, bucket: &str, key: &str, count: usize) {
+    if count == 0 || *CAPACITY == 0 {
+        return;
+    }
+    if RUNNING.fetch_add(1, Ordering::AcqRel) >= MAX_RUNNING {
+        RUNNING.fetch_sub(1, Ordering::AcqRel);
+        debug!("Prefetch after {} in bucket {} dropped: lookups busy", key, bucket);
+        return;
+    }
+    let (user_id, bucket, key) = (user_id.to_string(), bucket.to_string(), key.to_string());
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = prefetch(&user_id, &bucket, &key, count.min(*CAPACITY)) {
+            warn!("Prefetch after {} in bucket {} failed: {}", key, bucket, e);
+        }
+        RUNNING.fetch_sub(1, Ordering::AcqRel);
+    });
+}
+
+fn prefetch(user_id: &str, bucket: &str, key: &str, count: usize) -> Result<(), actix_web::Error> {
+    let generation = cache().generation(user_id, bucket);
+    let records = SQLiteMetadataStore::new().latest_records_after(user_id, bucket, key, count)?;
+    let mut cache = cache();
+    if cache.generation(user_id, bucket) != generation {
+        debug!("Prefetch after {} in bucket {} raced a write; discarded", key, bucket);
+        return Ok(());
+    }
+    for record in records {
+        cache.insert(object_key(user_id, bucket, &record.key), record.metadata);
+    }
+    Ok(())
+}
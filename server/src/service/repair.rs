@@ -0,0 +1,109 @@
+//! Chunk-level repair of native and S3 objects
+//!
+//! The client supplies the expected bytes for one chunk. They are checked against the
+//! recorded chunk size and, when the object carries one, its stored checksum, written to a
+//! fresh offset, and swapped into the metadata; the corrupted range is queued for deletion.
+
+use actix_web::Error;
+use actix_web::error::{ErrorBadRequest, ErrorConflict};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::metadata::Metadata;
+use crate::metrics;
+use crate::service::dedup;
+use crate::s3::handlers::checksum::{verify_checksum, ChecksumAlgorithm};
+use crate::service::metadata_service::MetadataService;
+use crate::service::user_context::UserContext;
+use crate::storage::bucket_lock;
+use crate::storage::config::StorageConfig;
+
+/// Outcome of repairing one chunk
+#[derive(Debug, Clone, Serialize)]
+pub struct RepairReport {
+    pub key: String,
+    pub bucket: String,
+    pub chunk: usize,
+    pub old_offset: u64,
+    pub new_offset: u64,
+    pub size: u64,
+    /// Stored checksum the data was verified against (`None` when the object has none)
+    pub checksum_verified: Option<String>,
+    /// Whether the bytes read back from the new offset match the uploaded data
+    pub read_back_verified: bool,
+}
+
+/// Check `data` against any checksum stored for the object. Object-level checksums only
+/// describe a single chunk when the object has exactly one.
+fn verify_stored_checksum(metadata: &Metadata, data: &[u8]) -> Result<Option<String>, Error> {
+    if metadata.chunks.len() != 1 {
+        return Ok(None);
+    }
+    let algorithm = metadata.checksum_algorithm.as_deref().and_then(ChecksumAlgorithm::from_str);
+    let value = metadata.checksum_value.as_deref().filter(|v| !v.is_empty());
+    if let (Some(algorithm), Some(value)) = (algorithm, value) {
+        if metadata.checksum_type.as_deref() != Some("COMPOSITE") {
+            if !verify_checksum(&algorithm, data, value) {
+                return Err(ErrorBadRequest(format!(
+                    "Repair data does not match the stored {} checksum", algorithm.as_str()
+                )));
+            }
+            return Ok(Some(algorithm.as_str().to_string()));
+        }
+    }
+    // A plain (non-multipart) ETag is the MD5 of the object bytes
+    if let Some(etag) = metadata.etag.as_deref().map(|e| e.trim_matches('"')) {
+        if etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()) {
+            if format!("{:x}", md5::compute(data)) != etag.to_ascii_lowercase() {
+                return Err(ErrorBadRequest("Repair data does not match the stored MD5 ETag"));
+            }
+            return Ok(Some("MD5".to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Replace chunk `index` of `key` with `data`.
+pub async fn repair_chunk(context: &UserContext, key: &str, index: usize, data: &[u8]) -> Result<RepairReport, Error> {
+    let db = MetadataService::new(&context.user_id)?;
+    db.check_key_nonexistance(&context.bucket, key)?;
+    let metadata = db.get_object_full(&context.bucket, key)?;
+
+    let old = metadata.chunks.get(index).cloned().ok_or_else(|| ErrorBadRequest(format!(
+        "Chunk index {} is out of range (object has {} chunks)", index, metadata.chunks.len()
+    )))?;
+    if data.len() as u64 != old.size {
+        return Err(ErrorBadRequest(format!(
+            "Repair data is {} bytes but chunk {} is {} bytes", data.len(), index, old.size
+        )));
+    }
+    let checksum_verified = verify_stored_checksum(&metadata, data)?;
+
+    let store = StorageConfig::from_env().create_store();
+    let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+    let (new_offset, size) = store.write(&context.user_id, &context.bucket, data)?;
+    let read_back_verified = store.read(&context.user_id, &context.bucket, new_offset, size)? == data;
+
+    if !db.replace_chunk(&context.bucket, key, index, old.extent(), (new_offset, size))? {
+        warn!("Object changed during repair of key: {} chunk: {}; discarding new copy", key, index);
+        db.queue_deletion(&context.bucket, key, &[(new_offset, size)])?;
+        return Err(ErrorConflict("Object changed while the chunk was being repaired"));
+    }
+    // Other objects may still share the damaged range, but no later write may come to
+    dedup::forget(context, old.extent())?;
+    db.queue_deletion(&context.bucket, key, &[old.extent()])?;
+    metrics::inc_counter("warpdrive_chunk_repairs_total", &[]);
+
+    info!("Repaired chunk {} of key: {} in bucket: {} ({} -> {}, {} bytes)",
+          index, key, context.bucket, old.extent().0, new_offset, size);
+    Ok(RepairReport {
+        key: key.to_string(),
+        bucket: context.bucket.clone(),
+        chunk: index,
+        old_offset: old.extent().0,
+        new_offset,
+        size,
+        checksum_verified,
+        read_back_verified,
+    })
+}
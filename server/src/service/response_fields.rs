@@ -0,0 +1,161 @@
+//! Client-selected metadata headers on object reads
+//!
+//! A native GET, S3 GetObject or S3 HeadObject carrying `X-Warp-Response-Fields` gets only
+//! the optional metadata headers it names, as a comma-separated list of the fields in
+//! [`FIELDS`], or `all` (the default) or `none`. Headers that are not metadata, such as
+//! `Content-Type`, `Content-Length`, `Content-Range`, `Content-Encoding` and `Vary`, are
+//! always sent, so `none` still leaves a well-formed response. Unknown names are ignored and
+//! listed back in `X-Warp-Ignored-Response-Fields`.
+//!
+//! The fields are applied to the finished response, so every read path shares one
+//! definition of which header belongs to which field. With `S3_STRICT_COMPAT=true` S3
+//! requests ignore the header and always get the full set, as an S3 client would expect.
+
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{HttpRequest, HttpResponse};
+use std::env;
+
+use crate::service::access_tracking::LAST_ACCESSED_HEADER;
+use crate::service::expiry::EXPIRE_AT_HEADER;
+use crate::service::object_format::OBJECT_FORMAT_HEADER;
+use crate::service::object_state::OBJECT_STATE_HEADER;
+use crate::service::user_context::METADATA_HEADER_PREFIX;
+use crate::service::validators::GENERATION_HEADER;
+
+/// Request header selecting the fields
+pub const RESPONSE_FIELDS_HEADER: &str = "x-warp-response-fields";
+/// Response header listing the requested fields that do not exist
+pub const IGNORED_FIELDS_HEADER: &str = "x-warp-ignored-response-fields";
+/// Response header of native GET with the object's logical size, whatever part of it is sent
+pub const SIZE_HEADER: &str = "x-warp-size";
+
+/// Each field and the response headers it covers; a name ending in `-` is a prefix
+pub const FIELDS: &[(&str, &[&str])] = &[
+    ("etag", &["etag"]),
+    ("size", &[SIZE_HEADER]),
+    ("generation", &[GENERATION_HEADER]),
+    ("last-modified", &["last-modified"]),
+    ("last-accessed", &[LAST_ACCESSED_HEADER]),
+    ("format", &[OBJECT_FORMAT_HEADER]),
+    ("state", &[OBJECT_STATE_HEADER]),
+    ("expiry", &[EXPIRE_AT_HEADER, "x-amz-expiration"]),
+    ("metadata", &[METADATA_HEADER_PREFIX, "x-amz-meta-"]),
+    ("version", &["x-amz-version-id"]),
+    ("caching", &["cache-control", "expires"]),
+    ("disposition", &["content-disposition", "content-language"]),
+    ("ranges", &["accept-ranges"]),
+    ("checksum", &["x-amz-checksum-"]),
+    ("tags", &["x-amz-tagging-count"]),
+    ("lock", &["x-amz-object-lock-"]),
+];
+
+/// Whether S3 requests follow the S3 API strictly, ignoring warpdrive extensions
+#[derive(Debug, Clone, Default)]
+pub struct S3CompatConfig {
+    pub strict: bool,
+}
+
+impl S3CompatConfig {
+    pub fn from_env() -> Self {
+        let strict = env::var("S3_STRICT_COMPAT")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+        Self { strict }
+    }
+}
+
+/// The fields a request asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseFields {
+    /// Indexes into [`FIELDS`]; `None` for all of them
+    selected: Option<Vec<usize>>,
+    ignored: Vec<String>,
+}
+
+impl ResponseFields {
+    /// Every field, as without the header
+    pub fn all() -> Self {
+        ResponseFields { selected: None, ignored: Vec::new() }
+    }
+
+    /// The fields of a native request
+    pub fn of(req: &HttpRequest) -> Self {
+        match req.headers().get(RESPONSE_FIELDS_HEADER).and_then(|v| v.to_str().ok()) {
+            Some(value) => Self::parse(value),
+            None => Self::all(),
+        }
+    }
+
+    /// The fields of an S3 request: every one in strict compatibility mode
+    pub fn of_s3(req: &HttpRequest) -> Self {
+        match S3CompatConfig::from_env().strict {
+            true => Self::all(),
+            false => Self::of(req),
+        }
+    }
+
+    /// Parse a header value, names compared case-insensitively
+    pub fn parse(value: &str) -> Self {
+        let names: Vec<String> = value.split(',').map(|name| name.trim().to_ascii_lowercase()).filter(|name| !name.is_empty()).collect();
+        if names.iter().any(|name| name == "all") {
+            return Self::all();
+        }
+        let mut selected = Vec::new();
+        let mut ignored = Vec::new();
+        for name in names.into_iter().filter(|name| name != "none") {
+            match FIELDS.iter().position(|(field, _)| *field == name) {
+                Some(index) => selected.push(index),
+                None => ignored.push(name),
+            }
+        }
+        ResponseFields { selected: Some(selected), ignored }
+    }
+
+    /// Whether `header` is sent: it is in a selected field or in none at all
+    fn keeps(&self, header: &str) -> bool {
+        let Some(selected) = &self.selected else { return true };
+        let covers = |names: &[&str]| names.iter().any(|name| match name.ends_with('-') {
+            true => header.starts_with(name),
+            false => header == *name,
+        });
+        match FIELDS.iter().position(|(_, names)| covers(names)) {
+            Some(index) => selected.contains(&index),
+            None => true,
+        }
+    }
+
+    /// Drop the headers of `response` outside the selected fields and report ignored names
+    pub fn apply(&self, mut response: HttpResponse) -> HttpResponse {
+        if self.selected.is_some() {
+            let headers = response.headers_mut();
+            let dropped: Vec<HeaderName> = headers.keys().filter(|name| !self.keeps(name.as_str())).cloned().collect();
+            for name in dropped {
+                headers.remove(name);
+            }
+        }
+        if !self.ignored.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&self.ignored.join(",")) {
+                response.headers_mut().insert(HeaderName::from_static(IGNORED_FIELDS_HEADER), value);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selects_known_fields() {
+        assert_eq!(ResponseFields::parse("ALL, etag"), ResponseFields::all());
+        let fields = ResponseFields::parse(" ETag,size,,bogus ");
+        assert_eq!(fields.ignored, vec!["bogus".to_string()]);
+        assert!(fields.keeps("etag") && fields.keeps(SIZE_HEADER));
+        assert!(!fields.keeps(GENERATION_HEADER) && !fields.keeps("x-amz-meta-color"));
+        // Transport headers belong to no field and are always kept
+        assert!(fields.keeps("content-length") && fields.keeps("content-type"));
+        let none = ResponseFields::parse("none");
+        assert!(!none.keeps("etag") && !none.keeps("x-amz-checksum-crc32") && none.keeps("content-range"));
+    }
+}
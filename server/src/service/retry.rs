@@ -0,0 +1,187 @@
+//! Retry of transient storage and metadata failures
+//!
+//! A failure is transient when trying again shortly is likely to succeed: an interrupted,
+//! would-block or timed-out I/O call on a bucket file, or SQLite reporting the database busy
+//! or locked. `StorageService` and `MetadataService` run their backend calls through
+//! [`with_retry`], which tries a transient failure up to `BACKEND_RETRY_MAX_ATTEMPTS` times
+//! in all (default 3; 1 disables retries), sleeping a random time of up to
+//! `BACKEND_RETRY_BACKOFF_MS` (default 20) doubled for each attempt in between. A transient
+//! failure still there after the last attempt answers 503 with Retry-After, so the client
+//! can retry later; a permanent one answers 500 at once.
+//!
+//! Retries are counted in `warpdrive_backend_retries_total` and failures that outlasted them
+//! in `warpdrive_backend_retries_exhausted_total`, both by operation and cause. A write
+//! retried after failing half-way may leave the partial bytes behind in the bucket file;
+//! nothing references them, as the chunk address comes from the write that succeeded.
+
+use actix_web::Error;
+use log::warn;
+use rusqlite::ErrorCode;
+use std::env;
+use std::io;
+use std::time::Duration;
+
+use crate::metadata::sqlite_store::MetadataBusyError;
+use crate::metrics;
+use crate::storage::error::StorageIoError;
+
+/// Cause of a storage or metadata failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureCause {
+    Io(io::ErrorKind),
+    Sqlite(ErrorCode),
+}
+
+impl FailureCause {
+    /// Cause of `e`; `None` for errors that are not backend failures
+    pub fn of(e: &Error) -> Option<Self> {
+        if let Some(io) = e.as_error::<StorageIoError>() {
+            return Some(FailureCause::Io(io.kind()));
+        }
+        e.as_error::<MetadataBusyError>().map(|busy| FailureCause::Sqlite(busy.code))
+    }
+
+    /// Whether the same call may succeed when tried again
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FailureCause::Io(kind) => {
+                matches!(kind, io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+            }
+            FailureCause::Sqlite(code) => matches!(code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked),
+        }
+    }
+
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureCause::Io(io::ErrorKind::Interrupted) => "interrupted",
+            FailureCause::Io(io::ErrorKind::WouldBlock) => "would_block",
+            FailureCause::Io(io::ErrorKind::TimedOut) => "timed_out",
+            FailureCause::Io(_) => "io",
+            FailureCause::Sqlite(ErrorCode::DatabaseBusy) => "database_busy",
+            FailureCause::Sqlite(ErrorCode::DatabaseLocked) => "database_locked",
+            FailureCause::Sqlite(_) => "sqlite",
+        }
+    }
+}
+
+/// Cause of `e` when it is transient
+pub fn transient_cause(e: &Error) -> Option<FailureCause> {
+    FailureCause::of(e).filter(FailureCause::is_transient)
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Attempts per call, the first included; at least 1
+    pub max_attempts: u32,
+    /// Longest sleep before the second attempt; doubled for each later one
+    pub backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self { max_attempts: 3, backoff: Duration::from_millis(20) }
+    }
+}
+
+impl RetryConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let max_attempts = env::var("BACKEND_RETRY_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .map(|n: u32| n.max(1))
+            .unwrap_or(defaults.max_attempts);
+        let backoff = env::var("BACKEND_RETRY_BACKOFF_MS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.backoff);
+        Self { max_attempts, backoff }
+    }
+
+    /// Sleep before attempt `attempt + 1`: uniformly random up to `backoff * 2^(attempt - 1)`
+    fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self.backoff.saturating_mul(1 << (attempt - 1).min(10));
+        let mut random = [0u8; 8];
+        if getrandom::getrandom(&mut random).is_err() {
+            return ceiling / 2;
+        }
+        ceiling.mul_f64(u64::from_le_bytes(random) as f64 / u64::MAX as f64)
+    }
+}
+
+/// Run `call`, trying it again while it fails transiently, up to the configured attempts.
+/// `op` labels the metrics and log lines.
+pub fn with_retry<T>(op: &'static str, mut call: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+    let config = RetryConfig::from_env();
+    let mut attempt = 1;
+    loop {
+        let e = match call() {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        let Some(cause) = transient_cause(&e) else { return Err(e) };
+        if attempt >= config.max_attempts {
+            if config.max_attempts > 1 {
+                metrics::inc_counter("warpdrive_backend_retries_exhausted_total", &[("op", op), ("cause", cause.as_str())]);
+                warn!("{} still failing after {} attempts: {}", op, attempt, e);
+            }
+            return Err(e);
+        }
+        metrics::inc_counter("warpdrive_backend_retries_total", &[("op", op), ("cause", cause.as_str())]);
+        warn!("{} failed ({}), retrying: {}", op, cause.as_str(), e);
+        std::thread::sleep(config.delay(attempt));
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::error::io_error;
+    use actix_web::http::StatusCode;
+
+    #[test]
+    fn test_transient_failures_are_retried_until_they_clear() {
+        let mut calls = 0;
+        let before = metrics::counter_value("warpdrive_backend_retries_total", &[("op", "retry_test"), ("cause", "database_locked")]);
+        let result = with_retry("retry_test", || {
+            calls += 1;
+            if calls < 3 {
+                return Err(MetadataBusyError { op: "retry_test", code: ErrorCode::DatabaseLocked }.into());
+            }
+            Ok(calls)
+        });
+        assert_eq!(result.unwrap(), 3);
+        let after = metrics::counter_value("warpdrive_backend_retries_total", &[("op", "retry_test"), ("cause", "database_locked")]);
+        assert_eq!(after - before, 2);
+
+        // Exhausted: the last transient error is returned as 503
+        let mut calls = 0;
+        let e = with_retry("retry_test_exhausted", || -> Result<(), Error> {
+            calls += 1;
+            Err(io_error(io::Error::from(io::ErrorKind::Interrupted)))
+        }).unwrap_err();
+        assert_eq!(calls, 3);
+        assert_eq!(e.as_response_error().status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(e.error_response().headers().contains_key("Retry-After"));
+
+        // Permanent: tried once, 500
+        let mut calls = 0;
+        let e = with_retry("retry_test_permanent", || -> Result<(), Error> {
+            calls += 1;
+            Err(io_error(io::Error::from(io::ErrorKind::PermissionDenied)))
+        }).unwrap_err();
+        assert_eq!(calls, 1);
+        assert_eq!(e.as_response_error().status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_backoff_is_bounded_and_grows() {
+        let config = RetryConfig { max_attempts: 5, backoff: Duration::from_millis(10) };
+        for attempt in 1..5 {
+            assert!(config.delay(attempt) <= Duration::from_millis(10 << (attempt - 1)));
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! End-to-end self-test of the write, read and delete paths
+//!
+//! [`run`] points the process at a scratch directory (`<dir>/storage` on the local backend and
+//! `<dir>/metadata.sqlite`), opens the real SQLite and LocalXFS stores there and drives a
+//! scripted scenario through the service layer: several objects are put, one of them across
+//! several chunks, read back and compared, appended to, renamed and deleted, and one pass of
+//! the deletion worker must drain the queue. Each step is timed and reported; the first
+//! failure stops the run. `warp_drive self-test` prints the report.
+//!
+//! The metadata connection is process-global: once a database is open, later runs use it
+//! wherever it is. [`exercise`] is the scenario alone, against the configured stores, for
+//! callers (such as a readiness probe) that already have them.
+
+use log::info;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::metadata::sqlite_store::{self, SQLiteMetadataStore};
+use crate::metadata::DataChunk;
+use crate::service::deletion_worker::DeletionWorker;
+use crate::service::metadata_service::MetadataService;
+use crate::service::storage_service::{inline_native, StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+use crate::startup;
+use crate::util::serializer::encode_extents;
+
+/// Owner of the buckets the self-test writes
+pub const SELF_TEST_USER: &str = "_selftest";
+
+/// Size of each file of the multi-chunk object
+const CHUNK_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepResult {
+    pub step: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub steps: Vec<StepResult>,
+}
+
+impl SelfTestReport {
+    /// The step that failed, if any
+    pub fn failed_step(&self) -> Option<&StepResult> {
+        self.steps.iter().find(|step| !step.ok)
+    }
+
+    /// Record a step that took since `start`; returns whether it passed.
+    fn record(&mut self, step: &'static str, start: Instant, result: Result<(), String>) -> bool {
+        let ok = result.is_ok();
+        match &result {
+            Ok(()) => info!("Self-test step {} passed", step),
+            Err(e) => info!("Self-test step {} failed: {}", step, e),
+        }
+        self.steps.push(StepResult { step, ok, error: result.err(), elapsed_ms: start.elapsed().as_millis() as u64 });
+        self.passed = self.steps.iter().all(|s| s.ok);
+        ok
+    }
+
+    fn step(&mut self, step: &'static str, f: impl FnOnce() -> Result<(), String>) -> bool {
+        let start = Instant::now();
+        let result = f();
+        self.record(step, start, result)
+    }
+}
+
+/// Run the self-test against fresh stores under `dir`.
+pub async fn run(dir: &Path) -> SelfTestReport {
+    let storage = dir.join("storage");
+    std::env::set_var("STORAGE_BACKEND", "localxfs");
+    std::env::set_var("STORAGE_DIRECTORY", &storage);
+    std::env::set_var("DB_FILE", dir.join("metadata.sqlite"));
+
+    let mut report = SelfTestReport::default();
+    if !report.step("storage", || startup::check_storage_root(&storage)) {
+        return report;
+    }
+    if !report.step("metadata", || sqlite_store::initialize().map(|_| ())) {
+        return report;
+    }
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    let context = UserContext::with_bucket(SELF_TEST_USER.to_string(), format!("selftest-{}", nanos));
+    report.steps.extend(exercise(&context).await.steps);
+    report.passed = report.steps.iter().all(|s| s.ok);
+    report
+}
+
+/// Objects the scenario puts: a single small file and one spread over several chunks
+fn objects() -> Vec<(&'static str, Vec<Vec<u8>>)> {
+    let pattern = |seed: u8, len: usize| (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect::<Vec<u8>>();
+    vec![
+        ("small", vec![b"warp drive self-test".to_vec()]),
+        ("multi", (0..4).map(|i| pattern(i, CHUNK_BYTES)).collect()),
+    ]
+}
+
+/// Put `files` as a native object under `key`
+fn put(storage: &StorageService, db: &MetadataService, context: &UserContext, key: &str, files: &[Vec<u8>]) -> Result<(), String> {
+    let slices: Vec<&[u8]> = files.iter().map(Vec::as_slice).collect();
+    let payload = inline_native(&slices, 0, &[], false).map_err(|e| e.to_string())?;
+    let (extents, attrs) = storage.write_native(context, &payload).map_err(|e| e.to_string())?;
+    if extents.len() != files.len() {
+        return Err(format!("{}: wrote {} chunks for {} files", key, extents.len(), files.len()));
+    }
+    let encoded = encode_extents(&extents).map_err(|e| e.to_string())?;
+    db.write_metadata_with_files(&context.bucket, key, &encoded, &attrs, None).map_err(|e| e.to_string())
+}
+
+/// Read `key` back and compare it with `files`
+fn verify(storage: &StorageService, db: &MetadataService, context: &UserContext, key: &str, files: &[Vec<u8>]) -> Result<(), String> {
+    let metadata = db.get_object_full(&context.bucket, key).map_err(|e| format!("{}: {}", key, e))?;
+    if metadata.chunks.len() != files.len() {
+        return Err(format!("{}: {} chunks stored, expected {}", key, metadata.chunks.len(), files.len()));
+    }
+    let data = storage.read_stored(context, &metadata, StorageMode::RAW).map_err(|e| format!("{}: {}", key, e))?;
+    if data != files.concat() {
+        return Err(format!("{}: read {} bytes that differ from the {} written", key, data.len(), files.iter().map(Vec::len).sum::<usize>()));
+    }
+    Ok(())
+}
+
+/// Append `files` to the native object `key`
+fn append(storage: &StorageService, db: &MetadataService, context: &UserContext, key: &str, files: &[Vec<u8>]) -> Result<(), String> {
+    let slices: Vec<&[u8]> = files.iter().map(Vec::as_slice).collect();
+    let payload = inline_native(&slices, 0, &[], false).map_err(|e| e.to_string())?;
+    let (extents, mut attrs) = storage.write_native(context, &payload).map_err(|e| e.to_string())?;
+    let mut metadata = db.get_object_full(&context.bucket, key).map_err(|e| e.to_string())?;
+    let mut files = metadata.file_attrs();
+    files.append(&mut attrs);
+    metadata.size += extents.iter().map(|(_, size)| size).sum::<u64>();
+    metadata.chunks.extend(extents.into_iter().map(DataChunk::from_extent));
+    metadata.set_file_attrs(&files);
+    db.update_object_full(&context.bucket, key, &metadata).map_err(|e| e.to_string())
+}
+
+/// Run the scenario in `context`'s bucket on the configured stores. The bucket is created
+/// first and should not exist yet.
+pub async fn exercise(context: &UserContext) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    let storage = StorageService::new();
+    let db = match MetadataService::new(&context.user_id) {
+        Ok(db) => db,
+        Err(e) => {
+            report.record("bucket", Instant::now(), Err(e.to_string()));
+            return report;
+        }
+    };
+    let mut objects = objects();
+
+    if !report.step("bucket", || db.create_bucket(&context.bucket).map_err(|e| e.to_string())) {
+        return report;
+    }
+    if !report.step("put", || objects.iter().try_for_each(|(key, files)| put(&storage, &db, context, key, files))) {
+        return report;
+    }
+    if !report.step("get", || objects.iter().try_for_each(|(key, files)| verify(&storage, &db, context, key, files))) {
+        return report;
+    }
+
+    let appended = vec![b"appended file".to_vec()];
+    let passed = report.step("append", || {
+        let (key, files) = &mut objects[0];
+        append(&storage, &db, context, key, &appended)?;
+        files.extend(appended.iter().cloned());
+        verify(&storage, &db, context, key, files)
+    });
+    if !passed {
+        return report;
+    }
+
+    let passed = report.step("rename", || {
+        let (key, files) = &mut objects[1];
+        let renamed = "multi-renamed";
+        db.rename_key(&context.bucket, key, renamed).map_err(|e| e.to_string())?;
+        if db.check_key(&context.bucket, key).map_err(|e| e.to_string())? {
+            return Err(format!("{} still exists after the rename", key));
+        }
+        *key = renamed;
+        verify(&storage, &db, context, key, files)
+    });
+    if !passed {
+        return report;
+    }
+
+    let queue = SQLiteMetadataStore::new();
+    let passed = report.step("delete", || {
+        for (key, _) in &objects {
+            storage.delete_object(context, key).map_err(|e| format!("{}: {}", key, e))?;
+            if db.check_key(&context.bucket, key).map_err(|e| e.to_string())? {
+                return Err(format!("{} still exists after the delete", key));
+            }
+            if !queue.deletion_pending(&context.user_id, &context.bucket, key).map_err(|e| e.to_string())? {
+                return Err(format!("{}: no deletion queued", key));
+            }
+        }
+        Ok(())
+    });
+    if !passed {
+        return report;
+    }
+
+    let start = Instant::now();
+    let result = DeletionWorker::new().process_deletions().await.map_err(|e| e.to_string());
+    if !report.record("deletion worker", start, result) {
+        return report;
+    }
+
+    report.step("deletion queue drained", || {
+        for (key, _) in &objects {
+            if queue.deletion_pending(&context.user_id, &context.bucket, key).map_err(|e| e.to_string())? {
+                return Err(format!("{}: deletion still pending after a worker pass", key));
+            }
+        }
+        Ok(())
+    });
+    report
+}
@@ -0,0 +1,171 @@
+//! Object and chunk size distributions
+//!
+//! Each finished write records, labeled by `api` (`native`, `s3`) and `write` (`put`,
+//! `append`, `multipart`), the object's logical size in `warpdrive_object_size_bytes`, its
+//! chunk count in `warpdrive_object_chunks` and the size of every chunk it stored in
+//! `warpdrive_chunk_size_bytes`. For an append the object is the appended data. An object
+//! kept inline stores no chunks and counts as zero of them. Sizes are bucketed on a log scale
+//! from 1 KiB to 10 GiB, chunk counts in powers of two.
+//!
+//! `warp_drive analyze-sizes --user --bucket` reports the same distributions for the data a
+//! bucket already holds, from its metadata: every stored version, but no delete markers. The
+//! API and write type of old objects are not recorded, so the report has no labels.
+
+use actix_web::Error;
+use serde::Serialize;
+
+use crate::metadata::Metadata;
+use crate::metrics;
+use crate::service::metadata_service::MetadataService;
+
+/// Rows read per metadata page
+const PAGE_ROWS: usize = 500;
+
+const KIB: f64 = 1024.0;
+
+/// Upper bounds of the size buckets, in bytes: 1 KiB to 4 GiB by fours, then 10 GiB
+pub const SIZE_BUCKETS: [f64; 13] = [
+    KIB, 4.0 * KIB, 16.0 * KIB, 64.0 * KIB, 256.0 * KIB,
+    KIB * KIB, 4.0 * KIB * KIB, 16.0 * KIB * KIB, 64.0 * KIB * KIB, 256.0 * KIB * KIB,
+    KIB * KIB * KIB, 4.0 * KIB * KIB * KIB, 10.0 * KIB * KIB * KIB,
+];
+
+/// Upper bounds of the chunk count buckets
+pub const CHUNK_COUNT_BUCKETS: [f64; 12] = [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Api {
+    Native,
+    S3,
+}
+
+impl Api {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Api::Native => "native",
+            Api::S3 => "s3",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteType {
+    Put,
+    Append,
+    Multipart,
+}
+
+impl WriteType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WriteType::Put => "put",
+            WriteType::Append => "append",
+            WriteType::Multipart => "multipart",
+        }
+    }
+}
+
+/// Record a finished write of `size` logical bytes stored as chunks of `chunk_sizes`
+pub fn record(api: Api, write: WriteType, size: u64, chunk_sizes: &[u64]) {
+    let labels = [("api", api.as_str()), ("write", write.as_str())];
+    metrics::observe_histogram_in("warpdrive_object_size_bytes", &labels, &SIZE_BUCKETS, size as f64);
+    metrics::observe_histogram_in("warpdrive_object_chunks", &labels, &CHUNK_COUNT_BUCKETS, chunk_sizes.len() as f64);
+    for size in chunk_sizes {
+        metrics::observe_histogram_in("warpdrive_chunk_size_bytes", &labels, &SIZE_BUCKETS, *size as f64);
+    }
+}
+
+/// Sizes of `metadata`'s stored chunks
+pub fn chunk_sizes(metadata: &Metadata) -> Vec<u64> {
+    metadata.chunks.iter().map(|chunk| chunk.size).collect()
+}
+
+/// Values at or below one bucket bound, `le` being `+Inf` for the last
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BucketCount {
+    pub le: String,
+    pub count: u64,
+}
+
+/// One distribution, with cumulative bucket counts as in the Prometheus histograms
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Distribution {
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+    pub buckets: Vec<BucketCount>,
+}
+
+impl Distribution {
+    fn new(bounds: &[f64]) -> Self {
+        let mut buckets: Vec<BucketCount> = bounds.iter().map(|bound| BucketCount { le: bound.to_string(), count: 0 }).collect();
+        buckets.push(BucketCount { le: "+Inf".to_string(), count: 0 });
+        Distribution { count: 0, sum: 0, max: 0, buckets }
+    }
+
+    fn add(&mut self, bounds: &[f64], value: u64) {
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+        for (bucket, bound) in self.buckets.iter_mut().zip(bounds.iter().map(Some).chain([None])) {
+            if bound.is_none_or(|bound| value as f64 <= *bound) {
+                bucket.count += 1;
+            }
+        }
+    }
+}
+
+/// Printed by `warp_drive analyze-sizes`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SizeReport {
+    pub user: String,
+    pub bucket: String,
+    pub object_size: Distribution,
+    pub chunks_per_object: Distribution,
+    pub chunk_size: Distribution,
+}
+
+/// Size distributions of the objects stored in `user`/`bucket`
+pub fn analyze(user: &str, bucket: &str) -> Result<SizeReport, Error> {
+    let db = MetadataService::new(user)?;
+    if !db.bucket_exists(bucket)? {
+        return Err(actix_web::error::ErrorNotFound(format!("No bucket: {} for user: {}", bucket, user)));
+    }
+    let mut report = SizeReport {
+        user: user.to_string(),
+        bucket: bucket.to_string(),
+        object_size: Distribution::new(&SIZE_BUCKETS),
+        chunks_per_object: Distribution::new(&CHUNK_COUNT_BUCKETS),
+        chunk_size: Distribution::new(&SIZE_BUCKETS),
+    };
+    let mut after = 0i64;
+    loop {
+        let page = db.full_records_page(bucket, after, PAGE_ROWS)?;
+        let Some(last) = page.last().map(|r| r.row_id) else { break };
+        for metadata in page.iter().map(|r| &r.metadata).filter(|m| !m.is_delete_marker) {
+            report.object_size.add(&SIZE_BUCKETS, metadata.size);
+            report.chunks_per_object.add(&CHUNK_COUNT_BUCKETS, metadata.chunks.len() as u64);
+            for chunk in &metadata.chunks {
+                report.chunk_size.add(&SIZE_BUCKETS, chunk.size);
+            }
+        }
+        after = last;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_counts_cumulatively() {
+        let mut distribution = Distribution::new(&CHUNK_COUNT_BUCKETS);
+        for value in [0, 1, 3, 5000] {
+            distribution.add(&CHUNK_COUNT_BUCKETS, value);
+        }
+        let count = |le: &str| distribution.buckets.iter().find(|b| b.le == le).unwrap().count;
+        assert_eq!((count("1"), count("2"), count("4"), count("2048"), count("+Inf")), (2, 2, 3, 3, 4));
+        assert_eq!((distribution.count, distribution.sum, distribution.max), (4, 5004, 5000));
+    }
+}
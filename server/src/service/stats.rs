@@ -0,0 +1,80 @@
+//! Server-wide usage (`GET /admin/stats`, `warp_drive stats`)
+//!
+//! Usage comes from metadata; each bucket's file extent count, a fragmentation estimate,
+//! from its segment files.
+
+use actix_web::Error;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::service::metadata_service::MetadataService;
+use crate::service::storage_service::StorageService;
+use crate::service::user_context::UserContext;
+
+/// Live objects and bytes of one bucket
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketUsage {
+    pub user: String,
+    pub bucket: String,
+    pub objects: u64,
+    pub bytes: u64,
+    /// Bytes stored once but referenced again by clones or deduplicated writes
+    pub shared_bytes: u64,
+    /// Chunks listed in the dedup index
+    pub indexed_chunks: u64,
+    /// Multipart uploads initiated and not yet completed or aborted
+    pub uploads_in_progress: u64,
+    /// Extents the bucket's segment files occupy on disk; null where the backend or
+    /// filesystem cannot tell. More extents per byte means a more fragmented bucket.
+    pub file_extents: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStats {
+    pub users: usize,
+    pub buckets: usize,
+    pub objects: u64,
+    pub bytes: u64,
+    pub shared_bytes: u64,
+    /// Deletion events the worker has not processed yet
+    pub pending_deletions: u64,
+    pub uploads_in_progress: u64,
+    pub per_bucket: Vec<BucketUsage>,
+}
+
+/// Usage of every bucket of every user
+pub fn collect() -> Result<ServerStats, Error> {
+    let system = MetadataService::new("system")?;
+    let storage = StorageService::new();
+    let users = system.list_users()?;
+    let mut per_bucket = Vec::new();
+    for user in &users {
+        let db = MetadataService::new(user)?;
+        let uploads: HashMap<String, u64> = db.multipart_uploads_in_progress()?.into_iter().collect();
+        for stats in db.list_buckets_with_stats()? {
+            let (indexed_chunks, shared_bytes) = db.sharing_stats(&stats.name)?;
+            let uploads_in_progress = uploads.get(&stats.name).copied().unwrap_or(0);
+            let file_extents = storage.file_extents(&UserContext::with_bucket(user.clone(), stats.name.clone()))?;
+            per_bucket.push(BucketUsage {
+                user: user.clone(),
+                bucket: stats.name,
+                objects: stats.object_count,
+                bytes: stats.total_size,
+                shared_bytes,
+                indexed_chunks,
+                uploads_in_progress,
+                file_extents,
+            });
+        }
+    }
+    Ok(ServerStats {
+        users: users.len(),
+        buckets: per_bucket.len(),
+        objects: per_bucket.iter().map(|b| b.objects).sum(),
+        bytes: per_bucket.iter().map(|b| b.bytes).sum(),
+        shared_bytes: per_bucket.iter().map(|b| b.shared_bytes).sum(),
+        pending_deletions: system.pending_deletion_count()?,
+        uploads_in_progress: per_bucket.iter().map(|b| b.uploads_in_progress).sum(),
+        per_bucket,
+    })
+}
@@ -1,80 +1,286 @@
 //! StorageService encapsulates business logic for interacting with the storage layer.
 
 use actix_web::Error;
-use actix_web::error::ErrorBadRequest;
-use flatbuffers::{root, FlatBufferBuilder};
+use actix_web::error::ErrorInternalServerError;
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, LocalBoxStream, StreamExt};
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use flatbuffers::FlatBufferBuilder;
+use std::sync::Arc;
+use crate::storage::Storage;
 use crate::storage::config::StorageConfig;
+use crate::storage::instrumented_store::{self, IoOp};
+use crate::storage::placement::{self, PlacementPolicy};
+use crate::storage::preallocate;
+use crate::service::checksums;
+use crate::service::cpu_pool;
+use crate::service::dedup::{self, DedupConfig};
+use crate::service::error::ServiceError;
+use crate::service::free_ranges;
+use crate::service::native_payload::{self, NativePayloadConfig};
+use crate::service::retry;
 use crate::service::user_context::UserContext;
 use crate::service::metadata_service::MetadataService;
-use crate::util::serializer::deserialize_offset_size;
+use crate::util::serializer::decode_extents;
 use crate::util::flatbuffer_store_generated::store::{FileDataList, FileData, FileDataArgs, FileDataListArgs};
+use crate::util::flatbuffer_store_v2_generated::store_v2;
+use crate::metadata::{DeletionSummary, FileAttrs, Metadata};
 
-pub struct StorageService;
+#[derive(Clone)]
+pub struct StorageService {
+    // Fixed backend; None builds the configured one (`STORAGE_BACKEND`) on each call
+    store: Option<Arc<dyn Storage>>,
+    // Where new chunks go; reads follow the chunk addresses instead
+    placement: Arc<dyn PlacementPolicy>,
+}
+
+/// Read size for streaming an extent straight from the bucket file.
+const FILE_STREAM_CHUNK: u64 = 256 * 1024;
+
+/// Chunks read ahead of the one being sent when `GET_READ_AHEAD_CHUNKS` is unset.
+pub const DEFAULT_READ_AHEAD_CHUNKS: usize = 2;
+
+/// How many chunks a streaming GET reads ahead of the one it is sending (`GET_READ_AHEAD_CHUNKS`,
+/// default 2; 0 reads each chunk only once the previous one has been sent).
+pub fn read_ahead_chunks() -> usize {
+    std::env::var("GET_READ_AHEAD_CHUNKS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_READ_AHEAD_CHUNKS)
+}
+
+/// Bytes of FlatBuffers framing reserved per file when sizing a payload builder.
+const FLATBUFFER_FILE_OVERHEAD: usize = 64;
+
+/// The finished payload of `builder`, sharing its buffer instead of copying it out.
+pub(crate) fn finished_bytes(builder: FlatBufferBuilder) -> Bytes {
+    let (buffer, head) = builder.collapse();
+    Bytes::from(buffer).slice(head..)
+}
+
+/// A builder big enough for `data_bytes` of file data and strings in `files` files, so it
+/// never reallocates (and copies) while the payload is built.
+pub(crate) fn payload_builder(data_bytes: usize, files: usize) -> FlatBufferBuilder<'static> {
+    FlatBufferBuilder::with_capacity(data_bytes + (files + 1) * FLATBUFFER_FILE_OVERHEAD)
+}
 
-// Unified mode for storage IO
-pub enum StorageMode { Native, S3 }
+/// Native payload of `count` files, file `i` holding `read_file(i)`: v2 (with names and chunk
+/// indexes) when `indexed` or any of `files` has attributes, otherwise v1.
+fn build_native(data_bytes: usize, count: usize, first: usize, files: &[FileAttrs], indexed: bool, mut read_file: impl FnMut(usize) -> Result<Vec<u8>, Error>) -> Result<Bytes, Error> {
+    let names: usize = files.iter()
+        .map(|f| f.name.as_ref().map_or(0, String::len) + f.content_type.as_ref().map_or(0, String::len))
+        .sum();
+    let mut builder = payload_builder(data_bytes + names, count);
+    if !indexed && files.iter().all(FileAttrs::is_empty) {
+        let mut file_data_vec = Vec::new();
+        for i in 0..count {
+            let data = read_file(i)?;
+            let data_vector = builder.create_vector(&data);
+            let file_data = FileData::create(&mut builder, &FileDataArgs { data: Some(data_vector) });
+            file_data_vec.push(file_data);
+        }
+        let files = builder.create_vector(&file_data_vec);
+        let file_data_list = FileDataList::create(&mut builder, &FileDataListArgs { files: Some(files) });
+        builder.finish(file_data_list, None);
+    } else {
+        let mut file_data_vec = Vec::new();
+        for i in 0..count {
+            let data = read_file(i)?;
+            let attrs = files.get(i).cloned().unwrap_or_default();
+            let data_vector = builder.create_vector(&data);
+            let name = attrs.name.as_deref().map(|n| builder.create_string(n));
+            let content_type = attrs.content_type.as_deref().map(|c| builder.create_string(c));
+            let file_data = store_v2::FileData::create(&mut builder, &store_v2::FileDataArgs {
+                data: Some(data_vector),
+                name,
+                content_type,
+                index: (first + i) as u32,
+            });
+            file_data_vec.push(file_data);
+        }
+        let files = builder.create_vector(&file_data_vec);
+        let file_data_list = store_v2::FileDataList::create(&mut builder, &store_v2::FileDataListArgs { files: Some(files) });
+        store_v2::finish_file_data_list_buffer(&mut builder, file_data_list);
+    }
+    Ok(finished_bytes(builder))
+}
+
+/// Native payload of the `inline` files of an object stored in its row, the first of them
+/// file `first` of the object; v2 when `indexed` as in [`StorageService::read_native`].
+pub fn inline_native(inline: &[&[u8]], first: usize, files: &[FileAttrs], indexed: bool) -> Result<Bytes, Error> {
+    build_native(inline.iter().map(|f| f.len()).sum(), inline.len(), first, files, indexed, |i| Ok(inline[i].to_vec()))
+}
+
+fn total_size(chunks: &[(u64, u64)]) -> usize {
+    chunks.iter().map(|(_, size)| *size as usize).sum()
+}
+
+/// Extents written for a native payload, with the matching per-file attributes.
+pub type NativeWrite = (Vec<(u64, u64)>, Vec<FileAttrs>);
+
+/// How [`StorageService::write_object`] and [`StorageService::read_object`] frame an object's
+/// bytes. The mode only picks the encoding: every mode writes to and reads from the same
+/// backend, so chunks written in one mode read back in the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageMode {
+    /// A FlatBuffers `FileDataList`, one chunk per file
+    Native,
+    /// The bytes as they are, in one chunk
+    S3,
+}
+
+impl StorageMode {
+    /// [`StorageMode::S3`], for callers outside the S3 API that want the bytes as they are
+    pub const RAW: StorageMode = StorageMode::S3;
+}
+
+impl Default for StorageService {
+    fn default() -> Self { Self::new() }
+}
 
 impl StorageService {
-    pub fn new() -> Self { Self }
+    pub fn new() -> Self { Self { store: None, placement: placement::configured() } }
 
-    fn store(&self) -> std::sync::Arc<dyn crate::storage::Storage> {
-        StorageConfig::from_env().create_store()
+    /// Service on `store` instead of the configured backend
+    pub fn with_store(store: Arc<dyn Storage>) -> Self { Self { store: Some(store), placement: placement::configured() } }
+
+    /// Place new chunks with `policy` instead of the configured one (`PLACEMENT_POLICY`)
+    pub fn with_placement(mut self, policy: Arc<dyn PlacementPolicy>) -> Self {
+        self.placement = policy;
+        self
+    }
+
+    /// Write one chunk where the placement policy puts it, retrying transient failures. With
+    /// deduplication on, a chunk the bucket already stores is shared instead of written.
+    fn write_chunk(&self, store: &dyn Storage, context: &UserContext, data: &[u8]) -> Result<(u64, u64), Error> {
+        instrumented_store::record_logical_write(data.len() as u64);
+        let digest = DedupConfig::from_env().applies(data.len()).then(|| dedup::digest(data));
+        if let Some(extent) = digest.as_ref().and_then(|digest| dedup::share(context, digest, data.len() as u64)) {
+            return Ok(extent);
+        }
+        let target = self.placement.place(context, data.len() as u64);
+        let extent = retry::with_retry("storage_write", || store.write_to(&context.user_id, &context.bucket, target, data))?;
+        if let Some(digest) = &digest {
+            dedup::record(context, digest, extent);
+        }
+        Ok(extent)
+    }
+
+    /// Read one chunk, retrying transient failures
+    fn read_chunk(&self, store: &dyn Storage, context: &UserContext, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        retry::with_retry("storage_read", || store.read(&context.user_id, &context.bucket, offset, size))
+    }
+
+    /// [`Self::read_chunk`], checked against the chunk's recorded checksum when it has one
+    fn read_checked(&self, store: &dyn Storage, context: &UserContext, (offset, size): (u64, u64), checksum: Option<u32>) -> Result<Vec<u8>, Error> {
+        let data = self.read_chunk(store, context, offset, size)?;
+        checksums::verify(&context.bucket, (offset, size), checksum, &data)?;
+        Ok(data)
+    }
+
+    /// Run `op` against this service on the CPU pool ([`cpu_pool`]), for calls whose chunk
+    /// work (encryption, digests, checksums) on `bytes` bytes should not hold up the request's
+    /// worker; small ones run inline
+    pub async fn offload<T, F>(&self, bytes: usize, op: F) -> Result<T, Error>
+    where
+        F: FnOnce(&StorageService) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let storage = self.clone();
+        cpu_pool::run(bytes, move || op(&storage)).await
+    }
+
+    fn store(&self) -> Arc<dyn Storage> {
+        match &self.store {
+            Some(store) => store.clone(),
+            None => StorageConfig::from_env().create_store(),
+        }
     }
 
     // Unified write: handles Native (FlatBuffers) and S3 (raw bytes)
     pub fn write_object(&self, context: &UserContext, body: &[u8], mode: StorageMode) -> Result<Vec<(u64, u64)>, Error> {
         match mode {
-            StorageMode::Native => {
-                let file_data_list = root::<FileDataList>(&body)
-                    .map_err(|e| ErrorBadRequest(format!("Failed to parse FlatBuffers data: {:?}", e)))?;
-                let files = file_data_list.files()
-                    .ok_or_else(|| ErrorBadRequest("No files found in FlatBuffers data"))?;
-                let store = self.store();
-                let mut out: Vec<(u64, u64)> = Vec::new();
-                for file_data in files.iter() {
-                    if let Some(data) = file_data.data() {
-                        let (o, s) = store.write(&context.user_id, &context.bucket, data.bytes())?;
-                        out.push((o, s));
-                    }
-                }
-                Ok(out)
-            }
+            StorageMode::Native => Ok(self.write_native(context, body)?.0),
             StorageMode::S3 => {
                 let store = self.store();
-                let (o, s) = store.write(&context.user_id, &context.bucket, body)?;
-                Ok(vec![(o, s)])
+                Ok(vec![self.write_chunk(store.as_ref(), context, body)?])
             }
         }
     }
 
-    // Unified read: returns FlatBuffers (Native) or raw bytes (S3)
-    pub fn read_object(&self, context: &UserContext, chunks: &[(u64, u64)], mode: StorageMode) -> Result<Vec<u8>, Error> {
+    /// Fail with 500 `FreedRangeReference` when one of `chunks` points into space the
+    /// deletion worker has freed. Every read below checks first.
+    pub fn check_readable(&self, context: &UserContext, chunks: &[(u64, u64)]) -> Result<(), Error> {
+        free_ranges::check_chunks(&context.user_id, &context.bucket, chunks)
+    }
+
+    // Unified read: returns FlatBuffers (Native) or raw bytes (S3), in the order of `chunks`
+    pub fn read_object(&self, context: &UserContext, chunks: &[(u64, u64)], mode: StorageMode) -> Result<Bytes, Error> {
         match mode {
-            StorageMode::Native => {
-                let store = self.store();
-                let mut builder = FlatBufferBuilder::new();
-                let mut file_data_vec = Vec::new();
-                for (offset, size) in chunks.iter().copied() {
-                    let data = store.read(&context.user_id, &context.bucket, offset, size)?;
-                    let data_vector = builder.create_vector(&data);
-                    let file_data = FileData::create(&mut builder, &FileDataArgs { data: Some(data_vector) });
-                    file_data_vec.push(file_data);
-                }
-                let files = builder.create_vector(&file_data_vec);
-                let file_data_list = FileDataList::create(&mut builder, &FileDataListArgs { files: Some(files) });
-                builder.finish(file_data_list, None);
-                Ok(builder.finished_data().to_vec())
-            }
-            StorageMode::S3 => {
-                let store = self.store();
-                let mut out = Vec::new();
-                for (offset, size) in chunks.iter().copied() {
-                    let data = store.read(&context.user_id, &context.bucket, offset, size)?;
-                    out.extend_from_slice(&data);
-                }
-                Ok(out)
+            StorageMode::Native => self.read_native(context, chunks, 0, &[], &[], false),
+            StorageMode::S3 => self.read_verified(context, chunks, &[]),
+        }
+    }
+
+    /// Raw bytes of whole `chunks`, each checked against its entry in `checksums`; chunks
+    /// past the end of `checksums` are not checked.
+    pub fn read_verified(&self, context: &UserContext, chunks: &[(u64, u64)], checksums: &[Option<u32>]) -> Result<Bytes, Error> {
+        self.check_readable(context, chunks)?;
+        let store = self.store();
+        let checksum = |i: usize| checksums.get(i).copied().flatten();
+        // A single chunk is returned as read; several are joined into one allocation
+        if let [chunk] = chunks {
+            return Ok(self.read_checked(store.as_ref(), context, *chunk, checksum(0))?.into());
+        }
+        let mut out = Vec::with_capacity(total_size(chunks));
+        for (i, chunk) in chunks.iter().enumerate() {
+            let data = self.read_checked(store.as_ref(), context, *chunk, checksum(i))?;
+            out.extend_from_slice(&data);
+        }
+        Ok(out.into())
+    }
+
+    /// Write a native FlatBuffers payload, detecting v1 vs v2 by the "WDV2" file identifier.
+    /// The whole payload is verified against [`NativePayloadConfig`] before anything is
+    /// written. Returns one extent per file plus the per-file attributes (all empty for v1).
+    pub fn write_native(&self, context: &UserContext, body: &[u8]) -> Result<NativeWrite, Error> {
+        let files = native_payload::parse(body, &NativePayloadConfig::from_env())?;
+        let store = self.store();
+        let mut out: Vec<(u64, u64)> = Vec::with_capacity(files.len());
+        let mut attrs: Vec<FileAttrs> = Vec::with_capacity(files.len());
+        // Files without a data vector are skipped
+        for file in &files {
+            if let Some(data) = file.data {
+                out.push(self.write_chunk(store.as_ref(), context, data)?);
+                attrs.push(file.attrs());
             }
         }
+        Ok((out, attrs))
+    }
+
+    /// Rebuild a native payload: v2 (with names) when `indexed` or any chunk has attributes,
+    /// otherwise v1. Chunks are checked against `checksums` as in [`Self::read_verified`].
+    /// File `i` of the payload is always `chunks[i]`, whatever order the chunks sit in on
+    /// disk; v2 files carry their chunk index in the object, counting from `first`, the index
+    /// of `chunks[0]`.
+    pub fn read_native(&self, context: &UserContext, chunks: &[(u64, u64)], first: usize, files: &[FileAttrs], checksums: &[Option<u32>], indexed: bool) -> Result<Bytes, Error> {
+        self.check_readable(context, chunks)?;
+        let store = self.store();
+        build_native(total_size(chunks), chunks.len(), first, files, indexed, |i| {
+            self.read_checked(store.as_ref(), context, chunks[i], checksums.get(i).copied().flatten())
+        })
+    }
+
+    /// [`Self::read_object`] of a stored object; data kept inline in its row is returned
+    /// without touching the backend.
+    pub fn read_stored(&self, context: &UserContext, metadata: &Metadata, mode: StorageMode) -> Result<Bytes, Error> {
+        match (&metadata.inline_data, mode) {
+            (Some(_), StorageMode::Native) => inline_native(&metadata.inline_files(), 0, &metadata.file_attrs(), false),
+            (Some(data), StorageMode::S3) => Ok(Bytes::copy_from_slice(data)),
+            (None, mode) => self.read_object(context, &metadata.to_offset_size_list(), mode),
+        }
     }
 
     /// One contiguous extent on the backing store (S3 object byte range).
@@ -85,20 +291,131 @@ impl StorageService {
         offset: u64,
         size: u64,
     ) -> Result<Vec<u8>, Error> {
-        self.store()
-            .read(&context.user_id, &context.bucket, offset, size)
+        self.check_readable(context, &[(offset, size)])?;
+        self.read_chunk(self.store().as_ref(), context, offset, size)
+    }
+
+    /// Re-read each chunk from `src` and write it into `dst`, preserving chunk boundaries.
+    /// The returned extents are independent of the source, so either side can be deleted alone.
+    pub fn copy_chunks(&self, src: &UserContext, dst: &UserContext, chunks: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error> {
+        self.check_readable(src, chunks)?;
+        let store = self.store();
+        let mut out: Vec<(u64, u64)> = Vec::with_capacity(chunks.len());
+        for (offset, size) in chunks.iter().copied() {
+            let data = self.read_chunk(store.as_ref(), src, offset, size)?;
+            out.push(self.write_chunk(store.as_ref(), dst, &data)?);
+        }
+        Ok(out)
+    }
+
+    /// Copy each of `chunks` to a new place in `context`'s bucket for compaction. Unlike
+    /// [`copy_chunks`](Self::copy_chunks) the copies never go through dedup, which would
+    /// hand back the chunk being moved.
+    pub fn move_chunks(&self, context: &UserContext, chunks: &[(u64, u64)]) -> Result<Vec<(u64, u64)>, Error> {
+        self.check_readable(context, chunks)?;
+        let store = self.store();
+        let mut out: Vec<(u64, u64)> = Vec::with_capacity(chunks.len());
+        for (offset, size) in chunks.iter().copied() {
+            let data = self.read_chunk(store.as_ref(), context, offset, size)?;
+            let target = self.placement.place(context, size);
+            out.push(retry::with_retry("storage_write", || store.write_to(&context.user_id, &context.bucket, target, &data))?);
+        }
+        Ok(out)
+    }
+
+    /// Stream `[offset, offset + len)` directly from the segment file holding it, skipping the
+    /// read-into-Vec copy. Returns `None` when the backend has no backing file.
+    pub async fn open_extent_stream(
+        &self,
+        context: &UserContext,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<LocalBoxStream<'static, Result<Bytes, Error>>>, Error> {
+        self.check_readable(context, &[(offset, len)])?;
+        let (path, position) = match self.store().backing_file(&context.user_id, &context.bucket, offset) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let mut file = tokio::fs::File::open(&path).await.map_err(ErrorInternalServerError)?;
+        file.seek(SeekFrom::Start(position)).await.map_err(ErrorInternalServerError)?;
+        crate::metrics::inc_counter("warpdrive_get_fast_path_total", &[]);
+
+        let traffic = instrumented_store::current();
+        let user_id = context.user_id.clone();
+        let body = stream::try_unfold((file.take(len), len), move |(mut reader, remaining)| {
+            let user_id = user_id.clone();
+            async move {
+                if remaining == 0 {
+                    return Ok(None);
+                }
+                let mut buf = BytesMut::with_capacity(remaining.min(FILE_STREAM_CHUNK) as usize);
+                let n = reader.read_buf(&mut buf).await.map_err(ErrorInternalServerError)?;
+                if n == 0 {
+                    return Err(ErrorInternalServerError("Bucket file ended before the end of the extent"));
+                }
+                // Bypasses the store, so its bytes are counted here
+                instrumented_store::record(IoOp::Read, traffic, &user_id, n as u64);
+                Ok(Some((buf.freeze(), (reader, remaining - n as u64))))
+            }
+        });
+        Ok(Some(body.boxed_local()))
+    }
+
+    /// Stream `slices` of the context's bucket in order, reading up to `read_ahead` slices
+    /// beyond the one last handed out so disk reads overlap sending. Reads start only as the
+    /// consumer pulls, so a slow client holds at most `read_ahead` slices in memory. Slices are
+    /// handed out by their index in `slices`, never in the order their reads finish.
+    pub fn chunk_stream(
+        &self,
+        context: &UserContext,
+        slices: Vec<(u64, u64)>,
+        read_ahead: usize,
+    ) -> LocalBoxStream<'static, Result<Bytes, Error>> {
+        let store = self.store();
+        let context = context.clone();
+        let mut next = 0;
+        stream::iter(slices.into_iter().enumerate())
+            .map(move |(index, (offset, size))| {
+                let store = store.clone();
+                let ctx = context.clone();
+                async move {
+                    let (user_id, bucket) = (ctx.user_id.clone(), ctx.bucket.clone());
+                    let data = actix_web::web::block(move || {
+                        retry::with_retry("storage_read", || store.read(&ctx.user_id, &ctx.bucket, offset, size))
+                            .map_err(|e| e.to_string())
+                    }).await.map_err(ErrorInternalServerError)?;
+                    data.map(|data| (index, Bytes::from(data))).map_err(|msg| {
+                        log::error!("Stream read failed for user {} bucket {} at ({}, {}): {}", user_id, bucket, offset, size, msg);
+                        ErrorInternalServerError(msg)
+                    })
+                }
+            })
+            // Once a slice is handed out, up to `read_ahead` more are being read or waiting
+            .buffered(read_ahead + 1)
+            .map(move |read| {
+                let (index, data) = read?;
+                // `buffered` yields in submission order; a reordering combinator would corrupt the body
+                if index != next {
+                    log::error!("Chunk stream out of order: slice {} handed out where {} belongs", index, next);
+                    return Err(ServiceError::Internal(format!("chunk stream out of order: slice {} where {} belongs", index, next)).into());
+                }
+                next += 1;
+                Ok(data)
+            })
+            .boxed_local()
     }
 
     // Delete an object: queue storage bytes for GC, remove metadata immediately.
-    pub fn delete_object(&self, context: &UserContext, key: &str) -> Result<(), Error> {
+    // Returns what was queued.
+    pub fn delete_object(&self, context: &UserContext, key: &str) -> Result<DeletionSummary, Error> {
         let metadata = MetadataService::new(&context.user_id)?;
         metadata.check_key_nonexistance(&context.bucket, key)?;
-        let offset_size_bytes = metadata.read_metadata(&context.bucket, key)
-            .map_err(|_| ErrorBadRequest("Key does not exist"))?;
-        let offset_size_list = deserialize_offset_size(&offset_size_bytes)?;
-        metadata.queue_deletion(&context.bucket, key, &offset_size_list)?;
+        let offset_size_bytes = metadata.read_metadata(&context.bucket, key)?;
+        let offset_size_list = decode_extents(&offset_size_bytes)?;
+        let queued = metadata.queue_deletion(&context.bucket, key, &offset_size_list)?;
         metadata.delete_metadata(&context.bucket, key)?;
-        metadata.delete_completed_uploads_for_key(&context.bucket, key)
+        metadata.delete_completed_uploads_for_key(&context.bucket, key)?;
+        Ok(queued)
     }
 
     /// Delete storage chunks directly (used by deletion worker)
@@ -106,6 +423,244 @@ impl StorageService {
         let store = self.store();
         store.delete(&context.user_id, &context.bucket, offset_size_list)
     }
+
+    /// The bucket's segment files as `(start address, length)`, oldest first; the last one is
+    /// the active segment
+    pub fn segments(&self, context: &UserContext) -> Result<Vec<(u64, u64)>, Error> {
+        self.store().segments(&context.user_id, &context.bucket)
+    }
+
+    /// Path of the bucket's active segment file, if the bucket has one and the backend keeps
+    /// it in a plain file
+    pub fn active_segment_file(&self, context: &UserContext) -> Result<Option<PathBuf>, Error> {
+        let store = self.store();
+        let segments = store.segments(&context.user_id, &context.bucket)?;
+        Ok(segments.last().and_then(|(start, _)| store.backing_file(&context.user_id, &context.bucket, *start)).map(|(path, _)| path))
+    }
+
+    /// Extents the bucket's segment files occupy on disk, summed; None when the backend has no
+    /// plain files or the filesystem cannot tell (`storage::preallocate::extent_count`)
+    pub fn file_extents(&self, context: &UserContext) -> Result<Option<u64>, Error> {
+        let store = self.store();
+        let mut total = 0;
+        for (start, _) in store.segments(&context.user_id, &context.bucket)? {
+            let Some((path, _)) = store.backing_file(&context.user_id, &context.bucket, start) else { return Ok(None) };
+            match preallocate::extent_count(&path) {
+                Ok(Some(extents)) => total += extents,
+                Ok(None) => return Ok(None),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(ErrorInternalServerError(e)),
+            }
+        }
+        Ok(Some(total))
+    }
+
+    /// Unlink the bucket's segment files `segments`, returning the ones removed. The caller
+    /// must have checked that no live data, including chunks written but not yet in metadata,
+    /// points into them.
+    pub fn remove_segments(&self, context: &UserContext, segments: &[u32]) -> Result<Vec<u32>, Error> {
+        let store = self.store();
+        let mut removed = Vec::new();
+        for n in segments {
+            if store.remove_segment(&context.user_id, &context.bucket, *n)? {
+                removed.push(*n);
+            }
+        }
+        Ok(removed)
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::segment;
+    use crate::storage::local_store::LocalXFSBinaryStore;
+    use crate::storage::mock_store::MockBinaryStore;
+    use crate::storage::tiered_store::TieredStore;
+
+    fn backends() -> Vec<(&'static str, Arc<dyn Storage>)> {
+        let root = crate::util::scratch::root().join("mode-matrix");
+        vec![
+            ("mock", Arc::new(MockBinaryStore::new())),
+            ("local", Arc::new(LocalXFSBinaryStore::with_directory(root.join("local")))),
+            ("tiered", Arc::new(TieredStore::new(
+                LocalXFSBinaryStore::with_directory(root.join("hot")),
+                LocalXFSBinaryStore::with_directory(root.join("cold")),
+            ))),
+        ]
+    }
 
+    fn native_payload(files: &[&[u8]]) -> Vec<u8> {
+        let mut builder = FlatBufferBuilder::new();
+        let files: Vec<_> = files.iter().map(|data| {
+            let data = builder.create_vector(data);
+            FileData::create(&mut builder, &FileDataArgs { data: Some(data) })
+        }).collect();
+        let files = builder.create_vector(&files);
+        let file_list = FileDataList::create(&mut builder, &FileDataListArgs { files: Some(files) });
+        builder.finish(file_list, None);
+        builder.finished_data().to_vec()
+    }
+
+    fn native_files(payload: &[u8]) -> Vec<Vec<u8>> {
+        flatbuffers::root::<FileDataList>(payload).unwrap().files().unwrap().iter()
+            .map(|f| f.data().unwrap().bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_modes_round_trip_on_every_backend() {
+        let files: [&[u8]; 3] = [b"first file", b"", b"third"];
+        let joined: Vec<u8> = files.concat();
+        for (backend, store) in backends() {
+            let context = UserContext::with_bucket("mode_user".to_string(), format!("mode-{}", backend));
+            let service = StorageService::with_store(store);
+
+            let native = service.write_object(&context, &native_payload(&files), StorageMode::Native).unwrap();
+            assert_eq!(native.len(), files.len(), "{}", backend);
+            let raw = service.write_object(&context, &joined, StorageMode::RAW).unwrap();
+            assert_eq!(raw.len(), 1, "{}", backend);
+
+            for mode in [StorageMode::Native, StorageMode::S3] {
+                let (from_native, from_raw) = (
+                    service.read_object(&context, &native, mode).unwrap(),
+                    service.read_object(&context, &raw, mode).unwrap(),
+                );
+                match mode {
+                    StorageMode::Native => {
+                        assert_eq!(native_files(&from_native), files.map(<[u8]>::to_vec), "{} {:?}", backend, mode);
+                        assert_eq!(native_files(&from_raw), vec![joined.clone()], "{} {:?}", backend, mode);
+                    }
+                    StorageMode::S3 => {
+                        assert_eq!(from_native, joined, "{} {:?}", backend, mode);
+                        assert_eq!(from_raw, joined, "{} {:?}", backend, mode);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_robin_placement_alternates_segments() {
+        use crate::storage::placement::RoundRobinSegments;
+
+        let root = crate::util::scratch::root().join("round-robin");
+        let store: Arc<dyn Storage> = Arc::new(LocalXFSBinaryStore::with_directory(root));
+        let context = UserContext::with_bucket("placement_user".to_string(), "round-robin".to_string());
+        let service = StorageService::with_store(store.clone())
+            .with_placement(Arc::new(RoundRobinSegments::new(2)));
+
+        let bodies: Vec<Vec<u8>> = (0..6u8).map(|i| vec![i; 100 + i as usize]).collect();
+        let mut extents = Vec::new();
+        for body in &bodies {
+            extents.extend(service.write_object(&context, body, StorageMode::S3).unwrap());
+        }
+        let files: [&[u8]; 2] = [b"native one", b"native two"];
+        let native = service.write_object(&context, &native_payload(&files), StorageMode::Native).unwrap();
+
+        let segments: Vec<u32> = extents.iter().chain(&native).map(|(offset, _)| segment::segment_of(*offset)).collect();
+        assert_eq!(segments, [0, 1, 0, 1, 0, 1, 0, 1]);
+
+        // Reads follow the chunk addresses, whatever policy the reader has
+        let reader = StorageService::with_store(store);
+        for (extent, body) in extents.iter().zip(&bodies) {
+            assert_eq!(&reader.read_object(&context, &[*extent], StorageMode::S3).unwrap(), body);
+        }
+        let payload = reader.read_object(&context, &native, StorageMode::Native).unwrap();
+        assert_eq!(native_files(&payload), files.map(<[u8]>::to_vec));
+    }
+
+    /// Mock store whose first `failures` writes fail with `kind`
+    struct FlakyStore {
+        inner: MockBinaryStore,
+        kind: std::io::ErrorKind,
+        failures: usize,
+        writes: std::sync::atomic::AtomicUsize,
+    }
+
+    impl FlakyStore {
+        fn new(kind: std::io::ErrorKind, failures: usize) -> Self {
+            Self { inner: MockBinaryStore::new(), kind, failures, writes: Default::default() }
+        }
+    }
+
+    impl Storage for FlakyStore {
+        fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+            if self.writes.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < self.failures {
+                return Err(crate::storage::error::io_error(std::io::Error::from(self.kind)));
+            }
+            self.inner.write(user_id, bucket, data)
+        }
+        fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+            self.inner.read(user_id, bucket, offset, size)
+        }
+        fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+            self.inner.delete(user_id, bucket, offset_size_list)
+        }
+        fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+            self.inner.verify(user_id, bucket, offset, size, checksum)
+        }
+        fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+            self.inner.bucket_len(user_id, bucket)
+        }
+    }
+
+    #[test]
+    fn test_transient_write_failures_are_retried() {
+        use actix_web::http::StatusCode;
+        let context = UserContext::with_bucket("retry_user".to_string(), "retry-bucket".to_string());
+        let labels = [("op", "storage_write"), ("cause", "interrupted")];
+        let before = crate::metrics::counter_value("warpdrive_backend_retries_total", &labels);
+
+        let store = Arc::new(FlakyStore::new(std::io::ErrorKind::Interrupted, 2));
+        let service = StorageService::with_store(store.clone());
+        let files: [&[u8]; 1] = [b"survives two EINTRs"];
+        let extents = service.write_object(&context, &native_payload(&files), StorageMode::Native).unwrap();
+        assert_eq!(store.writes.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert!(crate::metrics::counter_value("warpdrive_backend_retries_total", &labels) >= before + 2);
+        let payload = service.read_object(&context, &extents, StorageMode::Native).unwrap();
+        assert_eq!(native_files(&payload), files.map(<[u8]>::to_vec));
+
+        // A permanent failure is returned at once as a 500
+        let store = Arc::new(FlakyStore::new(std::io::ErrorKind::PermissionDenied, 1));
+        let e = StorageService::with_store(store.clone()).write_object(&context, b"denied", StorageMode::S3).unwrap_err();
+        assert_eq!(store.writes.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(e.as_response_error().status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // A transient one outlasting every attempt is a 503
+        let store = Arc::new(FlakyStore::new(std::io::ErrorKind::WouldBlock, usize::MAX));
+        let e = StorageService::with_store(store.clone()).write_object(&context, b"blocked", StorageMode::S3).unwrap_err();
+        assert_eq!(store.writes.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert_eq!(e.as_response_error().status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn test_extent_stream_matches_read_path() {
+        let context = UserContext::with_bucket("fast_path_user".to_string(), "fast-path-bucket".to_string());
+        let service = StorageService::new();
+        let body: Vec<u8> = (0..(24 * 1024 * 1024u32)).map(|i| (i % 251) as u8).collect();
+        let extents = service.write_object(&context, &body, StorageMode::S3).unwrap();
+        let (offset, size) = extents[0];
+
+        let copied = service.read_object(&context, &extents, StorageMode::S3).unwrap();
+
+        let before = crate::metrics::counter_value("warpdrive_get_fast_path_total", &[]);
+        let mut streamed = Vec::with_capacity(body.len());
+        let mut chunks = service.open_extent_stream(&context, offset, size).await.unwrap().expect("local store has a backing file");
+        while let Some(chunk) = chunks.next().await {
+            streamed.extend_from_slice(&chunk.unwrap());
+        }
+
+        assert!(crate::metrics::counter_value("warpdrive_get_fast_path_total", &[]) > before);
+        assert_eq!(streamed, body);
+        assert_eq!(copied, body);
+
+        // A sub-range of the extent
+        let mut ranged = Vec::new();
+        let mut chunks = service.open_extent_stream(&context, offset + 10, 100).await.unwrap().unwrap();
+        while let Some(chunk) = chunks.next().await {
+            ranged.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(ranged, &body[10..110]);
+    }
+}
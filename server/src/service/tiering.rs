@@ -0,0 +1,152 @@
+//! Moving buckets between storage tiers
+//!
+//! Only available with `STORAGE_BACKEND=tiered`. A migration copies every chunk of the
+//! bucket into the target tier key by key, swapping each key's chunk lists in one metadata
+//! transaction and recording the key as done. Progress is kept in SQLite, so an interrupted
+//! migration (or one run in batches with `max_keys`) picks up where it stopped. Once every
+//! key is moved the bucket is placed on the target tier and the source file is removed.
+
+use actix_web::Error;
+use actix_web::error::{ErrorBadRequest, ErrorConflict};
+use log::info;
+use serde::Serialize;
+
+use crate::metrics;
+use crate::service::jobs::JobHandle;
+use crate::service::metadata_service::MetadataService;
+use crate::storage::Storage;
+use crate::storage::bucket_lock;
+use crate::storage::config::{StorageBackend, StorageConfig};
+use crate::storage::tiered_store::{self, Placement, Tier, TieredStore};
+
+/// Outcome of one migration call
+#[derive(Debug, Clone, Serialize)]
+pub struct TierMigrationReport {
+    pub user: String,
+    pub bucket: String,
+    pub from: &'static str,
+    pub to: &'static str,
+    /// Keys moved by this call
+    pub keys_migrated: usize,
+    /// Keys still to move; the migration is complete when this is 0
+    pub keys_remaining: usize,
+    pub bytes_copied: u64,
+    pub complete: bool,
+    /// Whether a source bucket file was deleted
+    pub source_removed: bool,
+}
+
+fn other(tier: Tier) -> Tier {
+    match tier {
+        Tier::Hot => Tier::Cold,
+        Tier::Cold => Tier::Hot,
+    }
+}
+
+/// Reject a migration request [`migrate_bucket`] would refuse, before a job is queued for it
+pub fn check_migration(user: &str, bucket: &str, to: Tier) -> Result<(), Error> {
+    if StorageConfig::from_env().backend != StorageBackend::Tiered {
+        return Err(ErrorBadRequest("Bucket tiering requires STORAGE_BACKEND=tiered"));
+    }
+    match tiered_store::placement(user, bucket)?.migration {
+        Some((target, _)) if target != to => {
+            Err(ErrorConflict(format!("Bucket is being migrated to the {} tier", target.as_str())))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Move `user`/`bucket` to tier `to`, copying at most `max_keys` keys in this call.
+///
+/// Holds the bucket's exclusive write gate throughout, so no new chunks are written while
+/// keys are being moved. A cancelled job stops between keys and can be resumed like a
+/// `max_keys` batch.
+pub async fn migrate_bucket(user: &str, bucket: &str, to: Tier, max_keys: Option<usize>, job: &JobHandle) -> Result<TierMigrationReport, Error> {
+    if StorageConfig::from_env().backend != StorageBackend::Tiered {
+        return Err(ErrorBadRequest("Bucket tiering requires STORAGE_BACKEND=tiered"));
+    }
+    let _quiesced = bucket_lock::quiesce(user, bucket).await;
+
+    let store = TieredStore::from_env();
+    let db = MetadataService::new(user)?;
+    let placement = tiered_store::placement(user, bucket)?;
+    let from = placement.tier;
+    let mut report = TierMigrationReport {
+        user: user.to_string(),
+        bucket: bucket.to_string(),
+        from: from.as_str(),
+        to: to.as_str(),
+        keys_migrated: 0,
+        keys_remaining: 0,
+        bytes_copied: 0,
+        complete: false,
+        source_removed: false,
+    };
+
+    let boundary = match placement.migration {
+        None if from == to => {
+            // Already placed; clears a source file left behind by an interrupted finish
+            report.source_removed = store.tier_store(other(to)).remove_bucket_file(user, bucket)?;
+            report.complete = true;
+            return Ok(report);
+        }
+        None => {
+            if db.list_multipart_uploads_for_bucket(bucket)?.iter().any(|u| u.user_id == user) {
+                return Err(ErrorConflict("Bucket has multipart uploads in progress"));
+            }
+            let boundary = store.tier_store(from).bucket_len(user, bucket)?
+                .max(store.tier_store(to).bucket_len(user, bucket)?);
+            store.tier_store(to).extend_to(user, bucket, boundary)?;
+            tiered_store::set_placement(user, bucket, Placement { tier: from, migration: Some((to, boundary)) })?;
+            info!("Tier migration of {}/{} from {} to {} started at boundary {}",
+                  user, bucket, from.as_str(), to.as_str(), boundary);
+            boundary
+        }
+        Some((target, boundary)) if target == to => boundary,
+        Some((target, _)) => {
+            return Err(ErrorConflict(format!("Bucket is being migrated to the {} tier", target.as_str())));
+        }
+    };
+
+    let (source, target) = (store.tier_store(from), store.tier_store(to));
+    let pending = db.pending_tier_migration_keys(bucket)?;
+    let batch = max_keys.unwrap_or(pending.len()).min(pending.len());
+    for key in &pending[..batch] {
+        job.checkpoint()?;
+        let mut rows = Vec::new();
+        for row in db.object_extents_for_key(bucket, key)? {
+            let mut moved = Vec::with_capacity(row.extents.len());
+            for &(offset, size) in &row.extents {
+                if offset >= boundary {
+                    moved.push((offset, size));
+                    continue;
+                }
+                let data = source.read(user, bucket, offset, size)?;
+                moved.push(target.write(user, bucket, &data)?);
+                report.bytes_copied += size;
+            }
+            if moved != row.extents {
+                rows.push((row, moved));
+            }
+        }
+        db.commit_key_migration(bucket, key, &rows)?;
+        report.keys_migrated += 1;
+        job.progress(report.keys_migrated as u64, batch as u64);
+    }
+    report.keys_remaining = pending.len() - batch;
+    if report.keys_remaining > 0 {
+        info!("Tier migration of {}/{}: {} keys moved, {} remaining",
+              user, bucket, report.keys_migrated, report.keys_remaining);
+        return Ok(report);
+    }
+
+    tiered_store::set_placement(user, bucket, Placement { tier: to, migration: None })?;
+    // Keys are moved one by one, so clones no longer share their chunks in the new tier
+    db.rebuild_chunk_refs(bucket)?;
+    report.source_removed = source.remove_bucket_file(user, bucket)?;
+    report.complete = true;
+    metrics::inc_counter("warpdrive_tier_migrations_total", &[("to", to.as_str())]);
+    info!("Tier migration of {}/{} to {} complete ({} keys, {} bytes copied)",
+          user, bucket, to.as_str(), report.keys_migrated, report.bytes_copied);
+    Ok(report)
+}
@@ -0,0 +1,302 @@
+//! Native multi-key transactions (`POST /txn`)
+//!
+//! The body is JSON `{"ops": [...]}`, each op one of
+//!
+//! - `{"op": "put", "key": k, "data": base64, "content_type": t, "overwrite": false}`
+//! - `{"op": "delete", "key": k}`
+//! - `{"op": "rename", "key": k, "to": new_key}`
+//!
+//! applied in order to the request's bucket: either all of them take effect or none does.
+//! Every key involved is locked first ([`crate::storage::bucket_lock::lock_keys`]), then the payloads of all
+//! puts are written to storage, then every metadata change is made in one SQLite
+//! transaction. When an op conflicts (a put or rename onto a live key, a delete or rename of
+//! a missing one) the transaction is rolled back and the chunks already written are queued
+//! for deletion. Puts are stored as native objects of one chunk, like `put_batch` files.
+//! At most `TXN_MAX_OPS` ops (default 16) with `TXN_MAX_BYTES` of decoded payload in all
+//! (default 64 MiB) are accepted.
+
+use actix_web::Error;
+use actix_web::error::ErrorBadRequest;
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+use crate::metadata::{self, FileAttrs, Metadata};
+use crate::metadata::sqlite_store::{TxnConflict, TxnMutation};
+use crate::service::key_limits::KeyLimits;
+use crate::service::metadata_service::MetadataService;
+use crate::service::object_format::ObjectFormat;
+use crate::service::storage_service::{StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+
+/// Limits for one transaction
+#[derive(Debug, Clone)]
+pub struct TxnConfig {
+    /// Most ops in one transaction
+    pub max_ops: usize,
+    /// Most payload bytes of all puts together, after base64 decoding
+    pub max_bytes: usize,
+}
+
+impl Default for TxnConfig {
+    fn default() -> Self {
+        Self { max_ops: 16, max_bytes: 64 * 1024 * 1024 }
+    }
+}
+
+impl TxnConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let max_ops = env::var("TXN_MAX_OPS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_ops);
+        let max_bytes = env::var("TXN_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_bytes);
+        Self { max_ops, max_bytes }
+    }
+
+    /// Largest request body: the payloads base64-encoded, plus room for the JSON around them
+    pub fn max_body_bytes(&self) -> usize {
+        self.max_bytes.div_ceil(3) * 4 + 64 * 1024
+    }
+}
+
+/// One operation as sent
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TxnOp {
+    Put {
+        key: String,
+        /// Base64 payload
+        #[serde(default)]
+        data: String,
+        #[serde(default)]
+        content_type: Option<String>,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    Delete { key: String },
+    Rename { key: String, to: String },
+}
+
+impl TxnOp {
+    fn name(&self) -> &'static str {
+        match self {
+            TxnOp::Put { .. } => "put",
+            TxnOp::Delete { .. } => "delete",
+            TxnOp::Rename { .. } => "rename",
+        }
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            TxnOp::Put { key, .. } | TxnOp::Delete { key } | TxnOp::Rename { key, .. } => key,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TxnRequest {
+    ops: Vec<TxnOp>,
+}
+
+/// An op with its payload decoded
+pub struct ParsedOp {
+    pub op: TxnOp,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxnOpStatus {
+    Applied,
+    /// The key (or rename target) is held by a live object
+    Conflict,
+    /// The key to delete or rename does not exist
+    NotFound,
+    /// Not applied because another op of the transaction failed
+    Aborted,
+}
+
+/// Outcome for one op, in request order
+#[derive(Debug, Clone, Serialize)]
+pub struct TxnOpResult {
+    pub op: &'static str,
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    pub status: TxnOpStatus,
+}
+
+/// Outcome of the whole transaction
+#[derive(Debug, Clone, Serialize)]
+pub struct TxnOutcome {
+    pub committed: bool,
+    pub results: Vec<TxnOpResult>,
+}
+
+/// Parse and validate the body: `max_ops` at most, payloads decoded and within `max_bytes`,
+/// and every key a put or rename creates within the key limits.
+pub fn parse(body: &[u8], config: &TxnConfig) -> Result<Vec<ParsedOp>, Error> {
+    let request: TxnRequest = serde_json::from_slice(body)
+        .map_err(|e| ErrorBadRequest(format!("Invalid transaction: {}", e)))?;
+    if request.ops.is_empty() {
+        return Err(ErrorBadRequest("A transaction needs at least one op"));
+    }
+    if request.ops.len() > config.max_ops {
+        return Err(ErrorBadRequest(format!("A transaction holds at most {} ops, got {}", config.max_ops, request.ops.len())));
+    }
+    let key_limits = KeyLimits::from_env();
+    let mut total = 0usize;
+    let mut parsed = Vec::with_capacity(request.ops.len());
+    for (i, op) in request.ops.into_iter().enumerate() {
+        let data = match &op {
+            TxnOp::Put { key, data, .. } => {
+                key_limits.check_new_key(key)?;
+                B64.decode(data).map_err(|e| ErrorBadRequest(format!("Op {}: data is not base64: {}", i, e)))?
+            }
+            TxnOp::Rename { to, .. } => {
+                key_limits.check_new_key(to)?;
+                Vec::new()
+            }
+            TxnOp::Delete { .. } => Vec::new(),
+        };
+        total += data.len();
+        if total > config.max_bytes {
+            return Err(ErrorBadRequest(format!("Transaction payloads exceed {} bytes", config.max_bytes)));
+        }
+        parsed.push(ParsedOp { op, data });
+    }
+    Ok(parsed)
+}
+
+/// Every key the ops read or change, for [`crate::storage::bucket_lock::lock_keys`]
+pub fn keys(ops: &[ParsedOp]) -> Vec<&str> {
+    let mut keys = Vec::with_capacity(ops.len());
+    for parsed in ops {
+        keys.push(parsed.op.key());
+        if let TxnOp::Rename { to, .. } = &parsed.op {
+            keys.push(to.as_str());
+        }
+    }
+    keys
+}
+
+/// Apply `ops` to the context's bucket. The caller holds the locks of [`keys`] and the
+/// bucket's write gate. A conflict is an outcome with `committed: false`; an error is
+/// returned when storage or the metadata commit fails, after the written chunks have been
+/// queued for deletion.
+pub fn run(context: &UserContext, ops: &[ParsedOp]) -> Result<TxnOutcome, Error> {
+    let db = MetadataService::new(&context.user_id)?;
+    let writes: Vec<(&str, u64)> = ops.iter()
+        .filter(|parsed| matches!(parsed.op, TxnOp::Put { .. }))
+        .map(|parsed| (parsed.op.key(), parsed.data.len() as u64))
+        .collect();
+    let replaces = ops.iter().any(|parsed| matches!(parsed.op, TxnOp::Put { overwrite: true, .. }));
+    db.check_quota(&context.bucket, &writes, replaces)?;
+
+    let storage = StorageService::new();
+    let now = metadata::last_modified_now();
+    let mut written: Vec<(&str, Vec<(u64, u64)>)> = Vec::new();
+    let mut mutations = Vec::with_capacity(ops.len());
+    for parsed in ops {
+        let mutation = match &parsed.op {
+            TxnOp::Put { key, content_type, overwrite, .. } => {
+                let extents = if parsed.data.is_empty() {
+                    Vec::new()
+                } else {
+                    match storage.write_object(context, &parsed.data, StorageMode::S3) {
+                        Ok(extents) => extents,
+                        Err(e) => {
+                            warn!("Transaction write of key: {} in bucket: {} failed: {}", key, context.bucket, e);
+                            queue_orphans(&db, &context.bucket, &written);
+                            return Err(e);
+                        }
+                    }
+                };
+                written.push((key, extents.clone()));
+                let mut metadata = Metadata::from_offset_size_list(extents);
+                metadata.set_file_attrs(&[FileAttrs { name: Some(key.clone()), content_type: content_type.clone() }]);
+                metadata.last_modified = Some(now.clone());
+                ObjectFormat::Native.record(&mut metadata);
+                TxnMutation::Put { key: key.clone(), metadata: Box::new(metadata), overwrite: *overwrite }
+            }
+            TxnOp::Delete { key } => TxnMutation::Delete { key: key.clone() },
+            TxnOp::Rename { key, to } => TxnMutation::Rename { key: key.clone(), to: to.clone() },
+        };
+        mutations.push(mutation);
+    }
+
+    let failed = match db.apply_txn(&context.bucket, &mutations) {
+        Ok(Ok(())) => None,
+        Ok(Err(conflict)) => Some(conflict),
+        Err(e) => {
+            warn!("Transaction commit in bucket: {} failed, queueing {} written objects for deletion: {}",
+                  context.bucket, written.len(), e);
+            queue_orphans(&db, &context.bucket, &written);
+            return Err(e);
+        }
+    };
+    if let Some((index, conflict)) = failed {
+        info!("Transaction in bucket: {} rolled back at op {} ({:?} on key: {})",
+              context.bucket, index, conflict, ops[index].op.key());
+        queue_orphans(&db, &context.bucket, &written);
+    }
+
+    let results = ops.iter().enumerate().map(|(i, parsed)| {
+        let status = match failed {
+            None => TxnOpStatus::Applied,
+            Some((index, TxnConflict::Exists)) if index == i => TxnOpStatus::Conflict,
+            Some((index, TxnConflict::NotFound)) if index == i => TxnOpStatus::NotFound,
+            Some(_) => TxnOpStatus::Aborted,
+        };
+        let to = match &parsed.op {
+            TxnOp::Rename { to, .. } => Some(to.clone()),
+            _ => None,
+        };
+        TxnOpResult { op: parsed.op.name(), key: parsed.op.key().to_string(), to, status }
+    }).collect();
+    Ok(TxnOutcome { committed: failed.is_none(), results })
+}
+
+fn queue_orphans(db: &MetadataService, bucket: &str, written: &[(&str, Vec<(u64, u64)>)]) {
+    for (key, extents) in written.iter().filter(|(_, extents)| !extents.is_empty()) {
+        if let Err(e) = db.queue_deletion(bucket, key, extents) {
+            warn!("Failed to queue {} unreferenced extents of key: {} in bucket: {}: {}", extents.len(), key, bucket, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_validates_ops_and_limits() {
+        let config = TxnConfig { max_ops: 3, max_bytes: 8 };
+        let body = br#"{"ops": [
+            {"op": "put", "key": "a", "data": "aGVsbG8="},
+            {"op": "delete", "key": "b"},
+            {"op": "rename", "key": "c", "to": "d"}
+        ]}"#;
+        let ops = parse(body, &config).unwrap();
+        assert_eq!(ops[0].data, b"hello");
+        assert_eq!(keys(&ops), ["a", "b", "c", "d"]);
+
+        assert!(parse(br#"{"ops": []}"#, &config).is_err());
+        assert!(parse(br#"{"ops": [{"op": "put", "key": "a", "data": "not base64!"}]}"#, &config).is_err());
+        assert!(parse(br#"{"ops": [{"op": "copy", "key": "a"}]}"#, &config).is_err());
+        // 9 decoded bytes over the 8 allowed
+        assert!(parse(br#"{"ops": [{"op": "put", "key": "a", "data": "MTIzNDU2Nzg5"}]}"#, &config).is_err());
+        let four = br#"{"ops": [{"op": "delete", "key": "a"}, {"op": "delete", "key": "b"},
+                                {"op": "delete", "key": "c"}, {"op": "delete", "key": "d"}]}"#;
+        assert!(parse(four, &config).is_err());
+    }
+}
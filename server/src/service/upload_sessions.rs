@@ -0,0 +1,297 @@
+//! Resumable native uploads
+//!
+//! A large object can be sent in parts over several requests, each retried on its own.
+//! `POST /upload/{key}` opens a session and returns its id. `PUT /upload/{key}/{session}/{n}`
+//! stores the raw bytes of part `n` (1 to 10000); sending a part again replaces it.
+//! `GET /upload/{key}/{session}` lists the parts received with their sizes and SHA-256
+//! digests, so a client can tell what is still missing after a broken link.
+//! `POST /upload/{key}/{session}/complete` creates `key` from the parts in part order, one
+//! file per part, and `DELETE /upload/{key}/{session}` aborts. Completing fails with 409 like
+//! a PUT when `key` exists, and the session stays open.
+//!
+//! Sessions are kept in the S3 multipart tables under ids of `ups-` and 128 random bits in
+//! hex, which S3 calls never accept or list. They count against `MULTIPART_MAX_UPLOADS_PER_USER`
+//! and `MULTIPART_MAX_UPLOAD_BYTES` like S3 uploads, and their parts against the bucket's byte
+//! quota until the session completes or is aborted. Parts go to storage as they arrive. A
+//! session still open `UPLOAD_SESSION_TTL_SECS` after it was opened (default 7 days, 0 for
+//! never) is aborted by the deletion worker, which queues its parts' chunks like a delete.
+
+use actix_web::Error;
+use bytes::Bytes;
+use chrono::Duration;
+use log::{info, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+
+use crate::metadata::sqlite_store::{MultipartUploadRow, SQLiteMetadataStore};
+use crate::metadata::{FileAttrs, Metadata};
+use crate::service::bucket_registry::BucketConfig;
+use crate::service::error::ServiceError;
+use crate::service::expiry;
+use crate::service::key_limits::KeyLimits;
+use crate::service::leases;
+use crate::service::metadata_service::MetadataService;
+use crate::service::multipart_limits::{self, MultipartLimitConfig};
+use crate::service::size_stats::{self, Api, WriteType};
+use crate::service::storage_service::{StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+use crate::storage::bucket_lock;
+use crate::util::clock;
+use crate::util::serializer::{decode_extents, encode_extents};
+
+/// Start of every session id
+pub const SESSION_PREFIX: &str = "ups-";
+/// Highest part number
+pub const MAX_PART_NUMBER: i32 = 10_000;
+/// Sessions aborted per sweep when the deletion worker runs
+pub const SWEEP_BATCH: usize = 100;
+
+/// How long an upload session may stay open
+#[derive(Debug, Clone)]
+pub struct UploadSessionConfig {
+    /// Seconds from opening; 0 keeps sessions until completed or aborted
+    pub ttl_secs: u64,
+}
+
+impl Default for UploadSessionConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 7 * 24 * 3600 }
+    }
+}
+
+impl UploadSessionConfig {
+    pub fn from_env() -> Self {
+        let ttl_secs = env::var("UPLOAD_SESSION_TTL_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(Self::default().ttl_secs);
+        Self { ttl_secs }
+    }
+}
+
+/// One part received by a session
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PartInfo {
+    pub part_number: i32,
+    pub size: u64,
+    /// SHA-256 of the part, hex
+    pub sha256: String,
+}
+
+/// An open session and the parts it has
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub bucket: String,
+    pub key: String,
+    pub session: String,
+    pub initiated_at: String,
+    pub parts: Vec<PartInfo>,
+}
+
+/// What completing a session stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletedUpload {
+    pub size: u64,
+    pub parts: usize,
+}
+
+fn new_session_id() -> Result<String, Error> {
+    let mut id = [0u8; 16];
+    getrandom::getrandom(&mut id).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(format!("{}{}", SESSION_PREFIX, hex::encode(id)))
+}
+
+/// Whether `id` has the shape of a session id
+pub fn is_session_id(id: &str) -> bool {
+    id.strip_prefix(SESSION_PREFIX)
+        .is_some_and(|h| h.len() == 32 && h.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)))
+}
+
+/// The open session `session` of the context's user for `key`; sessions of other users,
+/// keys or buckets are missing.
+fn find(db: &MetadataService, context: &UserContext, key: &str, session: &str) -> Result<MultipartUploadRow, Error> {
+    let row = match is_session_id(session) {
+        true => db.get_multipart_upload(session)?,
+        false => None,
+    };
+    row.filter(|row| row.user_id == context.user_id && row.bucket == context.bucket && row.key == key && row.status == "in_progress")
+        .ok_or_else(|| ServiceError::NoSuchUpload {
+            bucket: context.bucket.clone(),
+            key: key.to_string(),
+            session: session.to_string(),
+        }.into())
+}
+
+/// Open a session for creating `key`; KeyExists when it is live.
+pub fn create(context: &UserContext, key: &str) -> Result<String, Error> {
+    KeyLimits::from_env().check_new_key(key)?;
+    let db = MetadataService::new(&context.user_id)?;
+    db.ensure_bucket_for_write(&context.bucket, &BucketConfig::from_env())?;
+    if db.check_key(&context.bucket, key)? {
+        return Err(ServiceError::key_exists(&context.bucket, key).into());
+    }
+
+    let session = new_session_id()?;
+    let metadata_json = serde_json::to_string(&context.metadata).unwrap_or_else(|_| "{}".to_string());
+    let limits = MultipartLimitConfig::from_env();
+    let created = db.create_multipart_upload(
+        &session, &context.bucket, key, None, &metadata_json, &expiry::now(),
+        "", "", "", "", "", limits.max_uploads_per_user,
+    )?;
+    if !created {
+        multipart_limits::record_rejection("uploads_per_user");
+        return Err(ServiceError::SlowDown(format!(
+            "You have {} uploads in progress, the most allowed; complete or abort one first",
+            limits.max_uploads_per_user.unwrap_or_default()
+        )).into());
+    }
+    info!("Opened upload session {} for key: {} in bucket: {}", session, key, context.bucket);
+    Ok(session)
+}
+
+/// Store `body` as part `part_number` of `session`, replacing a part sent before.
+pub async fn put_part<B>(storage: &StorageService, context: &UserContext, key: &str, session: &str, part_number: i32, body: B) -> Result<PartInfo, Error>
+where
+    B: Future<Output = Result<Bytes, Error>>,
+{
+    if !(1..=MAX_PART_NUMBER).contains(&part_number) {
+        return Err(ServiceError::InvalidRequest(format!("Part numbers run from 1 to {}, not {}", MAX_PART_NUMBER, part_number)).into());
+    }
+    let db = MetadataService::new(&context.user_id)?;
+    find(&db, context, key, session)?;
+
+    let bytes = body.await?;
+    if bytes.is_empty() {
+        return Err(ServiceError::EmptyPayload("No data was uploaded").into());
+    }
+    let size = bytes.len() as u64;
+    let limits = MultipartLimitConfig::from_env();
+    let other_parts = db.multipart_part_bytes(session, part_number)?;
+    if limits.upload_too_large(other_parts, size) {
+        multipart_limits::record_rejection("upload_bytes");
+        return Err(ServiceError::PayloadLimitExceeded {
+            limit: "upload_bytes",
+            max: limits.max_upload_bytes,
+            actual: Some(other_parts.saturating_add(size)),
+        }.into());
+    }
+    db.check_part_quota(&context.bucket, session, part_number, size)?;
+    let replaced = db.list_multipart_parts(session)?.into_iter().find(|part| part.part_number == part_number);
+    let sha256 = hex::encode(Sha256::digest(&bytes));
+
+    let extents = {
+        let _write_guard = bucket_lock::begin_write(&context.user_id, &context.bucket).await;
+        let (context, bytes) = (context.clone(), bytes.clone());
+        storage.offload(bytes.len(), move |storage| storage.write_object(&context, &bytes, StorageMode::RAW)).await?
+    };
+    if let Err(e) = db.upsert_multipart_part(&context.bucket, session, part_number, &sha256, size, &encode_extents(&extents)?, "") {
+        db.queue_deletion(&context.bucket, key, &extents)?;
+        return Err(e);
+    }
+    if let Some(replaced) = replaced {
+        db.queue_deletion(&context.bucket, key, &decode_extents(&replaced.extents_blob)?)?;
+    }
+    info!("Upload session {} received part {} ({} bytes) for key: {}", session, part_number, size, key);
+    Ok(PartInfo { part_number, size, sha256 })
+}
+
+/// The parts `session` has received, in part order
+pub fn list(context: &UserContext, key: &str, session: &str) -> Result<SessionInfo, Error> {
+    let db = MetadataService::new(&context.user_id)?;
+    let row = find(&db, context, key, session)?;
+    let parts = db.list_multipart_parts(session)?.into_iter()
+        .map(|part| PartInfo { part_number: part.part_number, size: part.size, sha256: part.etag })
+        .collect();
+    Ok(SessionInfo { bucket: row.bucket, key: row.key, session: row.upload_id, initiated_at: row.initiated_at, parts })
+}
+
+/// Create `key` from the parts of `session` in part order and close it. KeyExists when
+/// `key` is live, leaving the session open.
+pub async fn complete(context: &UserContext, key: &str, session: &str) -> Result<CompletedUpload, Error> {
+    let db = MetadataService::new(&context.user_id)?;
+    db.ensure_bucket_for_write(&context.bucket, &BucketConfig::from_env())?;
+    let _key_guards = bucket_lock::lock_keys(&context.user_id, &context.bucket, &[key]).await;
+    leases::check(context, &[key])?;
+    let row = find(&db, context, key, session)?;
+    if db.check_key_for_create(&context.bucket, key)? {
+        return Err(ServiceError::key_exists(&context.bucket, key).into());
+    }
+    let parts = db.list_multipart_parts(session)?;
+    if parts.is_empty() {
+        return Err(ServiceError::InvalidRequest(format!("Upload session {} has no parts to complete", session)).into());
+    }
+
+    let mut extents = Vec::new();
+    for part in &parts {
+        extents.extend(decode_extents(&part.extents_blob)?);
+    }
+    let mut metadata = Metadata::from_offset_size_list(extents);
+    let meta_properties: HashMap<String, String> = serde_json::from_str(&row.metadata_json).unwrap_or_default();
+    metadata.set_meta_properties(&meta_properties);
+    let files = vec![FileAttrs::default(); metadata.chunks.len()];
+    let completed = CompletedUpload { size: metadata.size, parts: parts.len() };
+    let chunk_sizes = size_stats::chunk_sizes(&metadata);
+    db.completing(session).write_native_object(&context.bucket, key, metadata, &files, None)?;
+    db.delete_parts_for_upload(session)?;
+    db.delete_multipart_upload(session)?;
+    size_stats::record(Api::Native, WriteType::Multipart, completed.size, &chunk_sizes);
+    info!("Completed upload session {} into key: {} in bucket: {} ({} parts, {} bytes)",
+        session, key, context.bucket, completed.parts, completed.size);
+    Ok(completed)
+}
+
+/// Close `session` without creating its key; returns how many parts were dropped.
+pub fn abort(context: &UserContext, key: &str, session: &str) -> Result<usize, Error> {
+    let db = MetadataService::new(&context.user_id)?;
+    let row = find(&db, context, key, session)?;
+    let parts = discard(&db, &row)?;
+    info!("Aborted upload session {} for key: {} in bucket: {} ({} parts)", session, key, context.bucket, parts);
+    Ok(parts)
+}
+
+/// Queue the chunks of every part of `row` for deletion and forget the session.
+fn discard(db: &MetadataService, row: &MultipartUploadRow) -> Result<usize, Error> {
+    let parts = db.list_multipart_parts(&row.upload_id)?;
+    for part in &parts {
+        db.queue_deletion(&row.bucket, &row.key, &decode_extents(&part.extents_blob)?)?;
+    }
+    db.delete_parts_for_upload(&row.upload_id)?;
+    db.delete_multipart_upload(&row.upload_id)?;
+    Ok(parts.len())
+}
+
+/// Abort up to `limit` sessions open longer than `UPLOAD_SESSION_TTL_SECS`. Called by the
+/// deletion worker each pass; a replica leaves them to the primary.
+pub fn sweep(limit: usize) -> Result<usize, Error> {
+    let config = UploadSessionConfig::from_env();
+    if config.ttl_secs == 0 || crate::config::is_replica() {
+        return Ok(0);
+    }
+    let cutoff = expiry::stored(clock::now() - Duration::seconds(config.ttl_secs as i64));
+    let stale = SQLiteMetadataStore::new().stale_multipart_uploads(SESSION_PREFIX, &cutoff, limit)?;
+    let mut aborted = 0;
+    for row in stale {
+        match MetadataService::new(&row.user_id).and_then(|db| discard(&db, &row)) {
+            Ok(_) => aborted += 1,
+            Err(e) => warn!("Failed to abort upload session {} of user: {}: {}", row.upload_id, row.user_id, e),
+        }
+    }
+    Ok(aborted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_ids() {
+        let id = new_session_id().unwrap();
+        assert!(is_session_id(&id), "{}", id);
+        for bad in ["", "ups-", "mpu-0123456789abcdef0123456789abcdef", "ups-0123456789ABCDEF0123456789ABCDEF", "ups-0123"] {
+            assert!(!is_session_id(bad), "{}", bad);
+        }
+    }
+}
@@ -2,16 +2,37 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::service::error::ServiceError;
+use crate::service::key_limits::is_safe_key;
+
+/// Only request headers with this prefix are captured into [`UserContext::metadata`], named
+/// by the rest of the header name (lowercase). Standard and hop-by-hop headers never are.
+pub const METADATA_HEADER_PREFIX: &str = "x-warp-meta-";
+/// `COPY` keeps an object's metadata on native update, `REPLACE` (the default) takes the
+/// request's; never captured as metadata itself
+pub const METADATA_DIRECTIVE_HEADER: &str = "x-warp-meta-directive";
+/// Longest value of one metadata entry, in bytes
+pub const MAX_METADATA_VALUE_BYTES: usize = 1024;
+/// Most bytes of names and values one context may hold
+pub const MAX_METADATA_TOTAL_BYTES: usize = 8 * 1024;
+
 /// User context containing all user-related information
 /// This struct makes it easy to add new fields without changing function signatures
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct UserContext {
-    /// User ID
+    /// User whose namespace the request works in: the authenticated user, or the effective
+    /// owner of a bucket granted to them ([`crate::service::bucket_grants`])
     pub user_id: String,
     /// Bucket name (defaults to "default" if not specified)
     pub bucket: String,
     /// Optional additional metadata that can be extended in the future
     pub metadata: std::collections::HashMap<String, String>,
+    /// Lease token presented in `X-Warp-Lease`, see [`crate::service::leases`]
+    #[serde(default)]
+    pub lease: Option<String>,
+    /// The user who made the request, when they act in another user's bucket through a grant
+    #[serde(default)]
+    pub granted_to: Option<String>,
 }
 
 impl UserContext {
@@ -20,6 +41,8 @@ impl UserContext {
         Self {
             bucket: "default".to_string(),
             metadata: std::collections::HashMap::new(),
+            lease: None,
+            granted_to: None,
             user_id,
         }
     }
@@ -29,13 +52,45 @@ impl UserContext {
         Self {
             bucket,
             metadata: std::collections::HashMap::new(),
+            lease: None,
+            granted_to: None,
             user_id,
         }
     }
+
+    /// This context working in `owner`'s namespace on behalf of its user, through a grant
+    pub fn acting_for(mut self, owner: &str) -> Self {
+        self.granted_to = Some(std::mem::replace(&mut self.user_id, owner.to_string()));
+        self
+    }
+
+    /// The user who made the request, distinct from [`Self::user_id`] under a grant
+    pub fn authenticated_user(&self) -> &str {
+        self.granted_to.as_deref().unwrap_or(&self.user_id)
+    }
     
-    /// Set a metadata field
-    pub fn set_metadata(&mut self, key: String, value: String) {
+    /// Set a metadata field. Names follow the object key rules and must not be empty; values
+    /// and the total are capped at [`MAX_METADATA_VALUE_BYTES`] and [`MAX_METADATA_TOTAL_BYTES`].
+    pub fn set_metadata(&mut self, key: String, value: String) -> Result<(), ServiceError> {
+        if key.is_empty() || !is_safe_key(&key) {
+            return Err(ServiceError::InvalidRequest(format!("Invalid metadata name: {:?}", key)));
+        }
+        if value.len() > MAX_METADATA_VALUE_BYTES {
+            return Err(ServiceError::InvalidRequest(format!(
+                "Metadata {} is {} bytes, at most {} allowed", key, value.len(), MAX_METADATA_VALUE_BYTES
+            )));
+        }
+        let total: usize = self.metadata.iter()
+            .filter(|(k, _)| **k != key)
+            .map(|(k, v)| k.len() + v.len())
+            .sum::<usize>() + key.len() + value.len();
+        if total > MAX_METADATA_TOTAL_BYTES {
+            return Err(ServiceError::InvalidRequest(format!(
+                "Request metadata is {} bytes, at most {} allowed", total, MAX_METADATA_TOTAL_BYTES
+            )));
+        }
         self.metadata.insert(key, value);
+        Ok(())
     }
     
     /// Get a metadata field
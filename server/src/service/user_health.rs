@@ -0,0 +1,180 @@
+//! Deep health check of one user's data path
+//!
+//! `/readyz` only shows that the metadata database answers; a tenant whose bucket file came
+//! back from a restore with the wrong permissions still looks ready. [`check_user`] walks the
+//! path that user's requests take, for one bucket, and times each step:
+//!
+//! - `metadata`: an existence query and the object lookup below, in one transaction scoped to
+//!   the user and bucket
+//! - `read`: the first chunk of the bucket's first object with data in the bucket file, or,
+//!   when there is none, `probe`: a small object put under `_health`, read back and deleted
+//!   (skipped while the server is read-only)
+//! - `write`: the bucket's active segment file grants write permission and opens for
+//!   appending (nothing is written), and its filesystem is writable with space available
+//!
+//! A step failing does not stop the ones after it, except that nothing runs without metadata.
+
+use log::{info, warn};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::config;
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metadata::Metadata;
+use crate::service::maintenance;
+use crate::service::metadata_service::MetadataService;
+use crate::service::storage_service::{StorageMode, StorageService};
+use crate::service::user_context::UserContext;
+
+/// Key the write probe puts its object under in an otherwise empty bucket
+pub const PROBE_KEY: &str = "_health";
+
+const PROBE_DATA: &[u8] = b"warp drive health probe";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthCheck {
+    pub check: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserHealthReport {
+    pub user: String,
+    pub bucket: String,
+    pub healthy: bool,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl UserHealthReport {
+    /// Time `f` as `check`; its `Ok` value is the check's detail
+    fn run(&mut self, check: &'static str, f: impl FnOnce() -> Result<String, String>) -> bool {
+        let start = Instant::now();
+        let result = f();
+        let ok = result.is_ok();
+        if let Err(e) = &result {
+            warn!("Health check {} failed for user {} bucket {}: {}", check, self.user, self.bucket, e);
+        }
+        let (detail, error) = match result {
+            Ok(detail) => (Some(detail), None),
+            Err(e) => (None, Some(e)),
+        };
+        self.checks.push(HealthCheck { check, ok, detail, error, elapsed_ms: start.elapsed().as_millis() as u64 });
+        self.healthy = self.checks.iter().all(|c| c.ok);
+        ok
+    }
+}
+
+/// Check `user_id`'s data path through `bucket`; `None` when metadata answers that the bucket
+/// does not exist.
+pub fn check_user(user_id: &str, bucket: &str) -> Option<UserHealthReport> {
+    let mut report = UserHealthReport { user: user_id.to_string(), bucket: bucket.to_string(), healthy: true, checks: Vec::new() };
+    let mut probe = None;
+    let passed = report.run("metadata", || {
+        let (exists, key) = SQLiteMetadataStore::new().health_probe(user_id, bucket).map_err(|e| e.to_string())?;
+        probe = Some((exists, key));
+        Ok("bucket lookup and object lookup committed".to_string())
+    });
+    if !passed {
+        return Some(report);
+    }
+    let (exists, key) = probe?;
+    if !exists {
+        return None;
+    }
+
+    let context = UserContext::with_bucket(user_id.to_string(), bucket.to_string());
+    let storage = StorageService::new();
+    match key {
+        Some(key) => report.run("read", || read_first_chunk(&storage, &context, &key)),
+        None => report.run("probe", || probe_object(&storage, &context)),
+    };
+    report.run("write", || check_bucket_file(&storage, &context));
+    info!("Health check of user {} bucket {}: {}", user_id, bucket, if report.healthy { "healthy" } else { "failing" });
+    Some(report)
+}
+
+/// Read the first chunk of `key`
+fn read_first_chunk(storage: &StorageService, context: &UserContext, key: &str) -> Result<String, String> {
+    let db = MetadataService::new(&context.user_id).map_err(|e| e.to_string())?;
+    let metadata = db.get_object_full(&context.bucket, key).map_err(|e| format!("{}: {}", key, e))?;
+    let chunk = metadata.chunks.first().ok_or_else(|| format!("{}: no chunks stored", key))?;
+    let data = storage.read_object(context, &[chunk.extent()], StorageMode::RAW).map_err(|e| format!("{}: {}", key, e))?;
+    if data.len() as u64 != chunk.size {
+        return Err(format!("{}: read {} bytes of a {}-byte chunk", key, data.len(), chunk.size));
+    }
+    Ok(format!("read {} bytes of {}", data.len(), key))
+}
+
+/// Put a small object under [`PROBE_KEY`], read it back and delete it
+fn probe_object(storage: &StorageService, context: &UserContext) -> Result<String, String> {
+    if config::is_replica() || maintenance::is_read_only() {
+        return Ok("skipped: the server is read-only".to_string());
+    }
+    let db = MetadataService::new(&context.user_id).map_err(|e| e.to_string())?;
+    if db.check_key(&context.bucket, PROBE_KEY).map_err(|e| e.to_string())? {
+        return Err(format!("an object {} already exists; not overwriting it", PROBE_KEY));
+    }
+    let extents = storage.write_object(context, PROBE_DATA, StorageMode::RAW).map_err(|e| format!("write: {}", e))?;
+    let mut metadata = Metadata::from_offset_size_list(extents);
+    metadata.size = PROBE_DATA.len() as u64;
+    db.put_object_full(&context.bucket, PROBE_KEY, metadata.clone()).map_err(|e| format!("put: {}", e))?;
+    let read = storage.read_stored(context, &metadata, StorageMode::RAW);
+    // Delete before judging the read so a failed probe leaves nothing behind
+    storage.delete_object(context, PROBE_KEY).map_err(|e| format!("delete: {}", e))?;
+    let data = read.map_err(|e| format!("read: {}", e))?;
+    if data.as_ref() != PROBE_DATA {
+        return Err(format!("read {} bytes that differ from the {} written", data.len(), PROBE_DATA.len()));
+    }
+    Ok(format!("wrote, read back and deleted {}", PROBE_KEY))
+}
+
+/// The active segment file is writable and its filesystem has room
+fn check_bucket_file(storage: &StorageService, context: &UserContext) -> Result<String, String> {
+    let path = match storage.active_segment_file(context).map_err(|e| e.to_string())? {
+        Some(path) => path,
+        None => return Ok("no local bucket file".to_string()),
+    };
+    let meta = std::fs::metadata(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    // Checked on the mode bits too: opening would succeed anyway for root
+    if meta.permissions().readonly() {
+        return Err(format!("{} is not writable: no write permission", path.display()));
+    }
+    OpenOptions::new().append(true).open(&path).map_err(|e| format!("{} is not writable: {}", path.display(), e))?;
+    let available = available_bytes(&path)?;
+    if available == Some(0) {
+        return Err(format!("no space left on the filesystem of {}", path.display()));
+    }
+    Ok(match available {
+        Some(bytes) => format!("{} writable, {} bytes available", path.display(), bytes),
+        None => format!("{} writable", path.display()),
+    })
+}
+
+/// Bytes available to unprivileged writers on `path`'s filesystem; fails when it is mounted
+/// read-only
+#[cfg(unix)]
+fn available_bytes(path: &Path) -> Result<Option<u64>, String> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    // SAFETY: statvfs only writes into the zeroed struct passed to it
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(format!("statvfs {}: {}", path.display(), std::io::Error::last_os_error()));
+    }
+    if stat.f_flag & libc::ST_RDONLY != 0 {
+        return Err(format!("the filesystem of {} is mounted read-only", path.display()));
+    }
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available_bytes(_path: &Path) -> Result<Option<u64>, String> {
+    Ok(None)
+}
@@ -0,0 +1,228 @@
+//! Per-user request concurrency limits
+//!
+//! `USER_MAX_INFLIGHT_READS` and `USER_MAX_INFLIGHT_WRITES` cap how many requests one user
+//! may have in progress (unset or 0: unlimited). A request takes a permit once it is
+//! authenticated, so the user is known, and holds it until the request is dropped. When the
+//! user is at the limit the request waits up to `USER_LIMIT_WAIT_MS` for a permit and then
+//! fails with 429 `SlowDown` instead of occupying a worker behind the user's other requests.
+//! Other users are never affected. In-flight counts of the busiest users are exported as
+//! `warpdrive_user_inflight_requests`.
+
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse};
+use actix_web::http::Method;
+use lazy_static::lazy_static;
+use log::warn;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::metrics;
+use crate::service::api_version;
+use crate::service::error::ServiceError;
+
+const INFLIGHT_GAUGE: &str = "warpdrive_user_inflight_requests";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    Read,
+    Write,
+}
+
+impl RequestKind {
+    /// GET and HEAD read, as does `POST /get_batch` (under any API version); everything else writes.
+    pub fn of(req: &HttpRequest) -> Self {
+        if matches!(*req.method(), Method::GET | Method::HEAD) || api_version::unversioned_path(req.path()) == "/get_batch" {
+            RequestKind::Read
+        } else {
+            RequestKind::Write
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestKind::Read => "read",
+            RequestKind::Write => "write",
+        }
+    }
+}
+
+/// Per-user limits
+#[derive(Debug, Clone)]
+pub struct UserLimitConfig {
+    /// In-flight reads per user; `None` is unlimited
+    pub max_reads: Option<usize>,
+    /// In-flight writes per user; `None` is unlimited
+    pub max_writes: Option<usize>,
+    /// How long a request over the limit waits for a permit before failing
+    pub wait: Duration,
+    /// Users exported in the in-flight gauge, busiest first
+    pub metrics_top_n: usize,
+}
+
+impl Default for UserLimitConfig {
+    fn default() -> Self {
+        Self { max_reads: None, max_writes: None, wait: Duration::from_millis(100), metrics_top_n: 10 }
+    }
+}
+
+impl UserLimitConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let limit = |var: &str| env::var(var).ok().and_then(|v| v.trim().parse().ok()).filter(|v| *v > 0);
+        let wait = env::var("USER_LIMIT_WAIT_MS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.wait);
+        let metrics_top_n = env::var("USER_INFLIGHT_METRICS_TOP_N")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(defaults.metrics_top_n);
+        Self {
+            max_reads: limit("USER_MAX_INFLIGHT_READS"),
+            max_writes: limit("USER_MAX_INFLIGHT_WRITES"),
+            wait,
+            metrics_top_n,
+        }
+    }
+
+    pub fn limit(&self, kind: RequestKind) -> Option<usize> {
+        match kind {
+            RequestKind::Read => self.max_reads,
+            RequestKind::Write => self.max_writes,
+        }
+    }
+}
+
+/// (user, kind) -> (limit the semaphore was sized for, semaphore)
+type Limiters = HashMap<(String, RequestKind), (usize, Arc<Semaphore>)>;
+
+lazy_static! {
+    static ref LIMITERS: Mutex<Limiters> = Mutex::new(HashMap::new());
+    // (user, kind) -> requests holding a permit, for the gauge
+    static ref IN_FLIGHT: Mutex<HashMap<(String, RequestKind), usize>> = Mutex::new(HashMap::new());
+}
+
+fn semaphore(user: &str, kind: RequestKind, limit: usize) -> Arc<Semaphore> {
+    let mut limiters = LIMITERS.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = limiters
+        .entry((user.to_string(), kind))
+        .or_insert_with(|| (limit, Arc::new(Semaphore::new(limit))));
+    // A changed limit takes effect for new permits; ones already held drain from the old one
+    if entry.0 != limit {
+        *entry = (limit, Arc::new(Semaphore::new(limit)));
+    }
+    Arc::clone(&entry.1)
+}
+
+/// Refresh the gauge with the `top_n` users that have the most requests in flight.
+fn export_in_flight(in_flight: &HashMap<(String, RequestKind), usize>, top_n: usize) {
+    let mut busiest: Vec<_> = in_flight.iter().filter(|(_, n)| **n > 0).collect();
+    busiest.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.0.cmp(&b.0.0)));
+    let series: Vec<(Vec<(&str, &str)>, f64)> = busiest
+        .into_iter()
+        .take(top_n)
+        .map(|((user, kind), n)| (vec![("user", user.as_str()), ("kind", kind.as_str())], *n as f64))
+        .collect();
+    metrics::replace_gauge_series(INFLIGHT_GAUGE, &series);
+}
+
+fn track(user: &str, kind: RequestKind, delta: isize) {
+    let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+    let count = in_flight.entry((user.to_string(), kind)).or_default();
+    *count = count.saturating_add_signed(delta);
+    if *count == 0 {
+        in_flight.remove(&(user.to_string(), kind));
+    }
+    export_in_flight(&in_flight, UserLimitConfig::from_env().metrics_top_n);
+}
+
+/// A request counted against its user's limit until dropped
+pub struct UserPermit {
+    user: String,
+    kind: RequestKind,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for UserPermit {
+    fn drop(&mut self) {
+        track(&self.user, self.kind, -1);
+    }
+}
+
+/// Take a permit for `user`, waiting up to the configured time. `None` means the user is
+/// at the limit.
+pub async fn acquire(user: &str, kind: RequestKind, config: &UserLimitConfig) -> Option<UserPermit> {
+    let permit = match config.limit(kind) {
+        Some(limit) => {
+            // A free permit is taken without waiting, even with a zero wait
+            let waiting = semaphore(user, kind, limit).acquire_owned();
+            Some(tokio::time::timeout(config.wait, waiting).await.ok()?.ok()?)
+        }
+        None => None,
+    };
+    track(user, kind, 1);
+    Some(UserPermit { user: user.to_string(), kind, _permit: permit })
+}
+
+/// Count `req` against `user`'s limit for the rest of the request; the permit is kept in the
+/// request extensions, so a request admitted once is not counted again. On a full limit
+/// returns the 429 in the caller's format: S3 XML when `s3`, native JSON otherwise.
+pub async fn admit(req: &HttpRequest, user: &str, s3: bool) -> Result<(), Error> {
+    if req.extensions().contains::<UserPermit>() {
+        return Ok(());
+    }
+    let kind = RequestKind::of(req);
+    let config = UserLimitConfig::from_env();
+    match acquire(user, kind, &config).await {
+        Some(permit) => {
+            req.extensions_mut().insert(permit);
+            Ok(())
+        }
+        None => {
+            metrics::inc_counter("warpdrive_user_throttled_total", &[("kind", kind.as_str())]);
+            warn!("User {} is over the in-flight {} limit; rejecting {} {}", user, kind.as_str(), req.method(), req.path());
+            let message = format!("Too many concurrent {} requests for this user", kind.as_str());
+            Err(if s3 { s3_slow_down(&message) } else { ServiceError::SlowDown(message).into() })
+        }
+    }
+}
+
+fn s3_slow_down(message: &str) -> Error {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <Error>\n\
+           <Code>SlowDown</Code>\n\
+           <Message>{}</Message>\n\
+           <RequestId>warpdrive</RequestId>\n\
+         </Error>",
+        message
+    );
+    actix_web::error::InternalError::from_response(
+        message.to_string(),
+        HttpResponse::TooManyRequests()
+            .content_type("application/xml")
+            .insert_header(("x-amz-request-id", "warpdrive"))
+            .insert_header(("Retry-After", "1"))
+            .body(body),
+    ).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limit_is_per_user_and_kind() {
+        let config = UserLimitConfig { max_reads: Some(1), max_writes: None, wait: Duration::from_millis(10), metrics_top_n: 10 };
+        let held = acquire("limits_user_a", RequestKind::Read, &config).await.unwrap();
+        assert!(acquire("limits_user_a", RequestKind::Read, &config).await.is_none());
+        assert!(acquire("limits_user_b", RequestKind::Read, &config).await.is_some());
+        assert!(acquire("limits_user_a", RequestKind::Write, &config).await.is_some());
+
+        drop(held);
+        assert!(acquire("limits_user_a", RequestKind::Read, &config).await.is_some());
+    }
+}
@@ -0,0 +1,67 @@
+//! Cache validators of an object
+//!
+//! GET and HEAD answer with the validators a caching proxy needs: `Last-Modified`, an `ETag`
+//! and `X-Warp-Generation`, the number of writes the key has seen. The store assigns the
+//! generation: 1 on the first write, one more on every put, update or append, kept across a
+//! rename. A key deleted and written again continues from where it was, so a generation is
+//! never reused for other data.
+//!
+//! The ETag is strong when the object carries one (S3 uploads) or a stored checksum.
+//! Objects with neither, such as native uploads and objects from before checksums, get the
+//! weak validator `W/"<generation>"`. `If-None-Match` is compared weakly against whichever
+//! the object has, as RFC 9110 requires for GET and HEAD.
+
+use crate::metadata::Metadata;
+
+/// Response header carrying the object's generation
+pub const GENERATION_HEADER: &str = "x-warp-generation";
+
+/// The ETag sent for `metadata`
+pub fn etag(metadata: &Metadata) -> String {
+    if let Some(etag) = metadata.etag.as_deref().filter(|e| !e.is_empty()) {
+        return etag.to_string();
+    }
+    match metadata.checksum_value.as_deref().filter(|v| !v.is_empty()) {
+        Some(value) => format!("\"{}\"", value),
+        None => weak_etag(metadata.generation),
+    }
+}
+
+/// Weak validator derived from a generation
+pub fn weak_etag(generation: u64) -> String {
+    format!("W/\"{}\"", generation)
+}
+
+/// Whether the `If-None-Match` value `header` matches `etag`: `*`, or a listed tag with the
+/// same opaque value whether either side is weak or not
+pub fn none_match(header: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    header.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_etag_prefers_stored_values() {
+        let mut metadata = Metadata::from_offset_size_list(vec![(0, 3)]);
+        metadata.generation = 4;
+        assert_eq!(etag(&metadata), "W/\"4\"");
+        metadata.checksum_value = Some("AAAAAA==".to_string());
+        assert_eq!(etag(&metadata), "\"AAAAAA==\"");
+        metadata.etag = Some("\"d41d8cd98f00b204e9800998ecf8427e\"".to_string());
+        assert_eq!(etag(&metadata), "\"d41d8cd98f00b204e9800998ecf8427e\"");
+    }
+
+    #[test]
+    fn test_none_match_compares_weakly() {
+        assert!(none_match("W/\"4\"", "W/\"4\""));
+        assert!(none_match("\"4\"", "W/\"4\""));
+        assert!(none_match("\"1\", W/\"4\"", "W/\"4\""));
+        assert!(none_match("*", "\"abc\""));
+        assert!(!none_match("W/\"3\"", "W/\"4\""));
+        assert!(!none_match("W/\"40\"", "W/\"4\""));
+    }
+}
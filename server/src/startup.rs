@@ -0,0 +1,144 @@
+//! Startup initialization
+//!
+//! Runs before the listener is bound so the first requests don't pay for (or race on) lazy
+//! setup: the metadata database is opened and migrated (and with `SQLITE_WARMUP=true` its key
+//! index read into the page cache), each storage root is created and
+//! checked for writability with a probe file, the database and storage are checked to belong
+//! to the same deployment (`service::instance_identity`), a change of `STORAGE_LAYOUT` is reconciled with
+//! the users already in storage, rows of users still on a pre-bucket `{user}.bin` are pointed
+//! at it, writes are started past the extents metadata holds in each segment file
+//! (`storage::local_store`), and the `_health` bucket is registered. Any failure is
+//! returned with the step that failed so `main` can exit with a clear message. The effective
+//! configuration is recorded in the change log when it differs from the last start's. A
+//! replica (`SERVER_ROLE=replica`) writes nothing: the database is opened read-only, storage
+//! roots only have to exist, and the `_health` bucket, layout, instance ids and configuration
+//! snapshot are left to the primary.
+
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::metadata::sqlite_store::{self, SQLiteMetadataStore};
+use crate::metrics;
+use crate::service::change_log;
+use crate::service::instance_identity;
+use crate::service::legacy_migration;
+use crate::service::metadata_service::MetadataService;
+use crate::storage::config::{StorageBackend, StorageConfig};
+use crate::storage::local_store::LocalXFSBinaryStore;
+use crate::storage::layout;
+
+/// Bucket kept for health checks
+pub const HEALTH_BUCKET: &str = "_health";
+/// Owner of internal buckets such as [`HEALTH_BUCKET`]
+pub const SYSTEM_USER: &str = "_system";
+
+#[derive(Debug)]
+pub struct StartupError {
+    pub step: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "startup failed during {}: {}", self.step, self.message)
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+fn step_error(step: &'static str) -> impl Fn(String) -> StartupError {
+    move |message| StartupError { step, message }
+}
+
+/// Create `root` if needed and prove it is writable by writing and removing a probe file.
+pub(crate) fn check_storage_root(root: &Path) -> Result<(), String> {
+    fs::create_dir_all(root)
+        .map_err(|e| format!("cannot create storage directory {}: {}", root.display(), e))?;
+    let probe = root.join(format!(".warpdrive-probe-{}", std::process::id()));
+    fs::write(&probe, b"probe")
+        .map_err(|e| format!("storage directory {} is not writable: {}", root.display(), e))?;
+    fs::remove_file(&probe)
+        .map_err(|e| format!("cannot remove probe file {}: {}", probe.display(), e))?;
+    Ok(())
+}
+
+/// Check that a replica's `root` is a readable directory, without writing to it.
+fn check_replica_storage_root(root: &Path) -> Result<(), String> {
+    fs::read_dir(root)
+        .map(|_| ())
+        .map_err(|e| format!("cannot read storage directory {}: {}", root.display(), e))
+}
+
+/// Start writes to every segment file past the extents metadata holds in it, warning about
+/// files that end before them; returns how many do.
+fn check_write_offsets(storage: &StorageConfig) -> Result<usize, String> {
+    if storage.backend != StorageBackend::LocalXFS || storage.root_directories().is_empty() {
+        return Ok(0);
+    }
+    let store = LocalXFSBinaryStore::new();
+    let db = SQLiteMetadataStore::new();
+    let mut short = 0;
+    for user in db.list_users().map_err(|e| e.to_string())? {
+        let buckets = MetadataService::new(&user).and_then(|m| m.list_all_buckets()).map_err(|e| e.to_string())?;
+        for bucket in buckets {
+            let extents = db.referenced_extents(&user, &bucket).map_err(|e| e.to_string())?;
+            for (segment, len, end) in store.check_reserved_offsets(&user, &bucket, &extents).map_err(|e| e.to_string())? {
+                warn!("Segment {} of user {} bucket {} is {} bytes but metadata references up to {}; \
+                       writes start past that, run fsck on the bucket", segment, user, bucket, len, end);
+                short += 1;
+            }
+        }
+    }
+    Ok(short)
+}
+
+/// Prepare metadata and storage; returns how long it took.
+pub fn initialize() -> Result<Duration, StartupError> {
+    initialize_with(false)
+}
+
+/// As [`initialize`]; with `force_adopt` a database and storage carrying different instance
+/// ids are made to agree instead of failing the start.
+pub fn initialize_with(force_adopt: bool) -> Result<Duration, StartupError> {
+    let start = Instant::now();
+    let replica = config::is_replica();
+
+    let db_path = sqlite_store::initialize().map_err(step_error("metadata"))?;
+    info!("Metadata database ready at {}{}", db_path.display(), if replica { " (read-only)" } else { "" });
+    if let Some(report) = sqlite_store::warm_up_database().map_err(step_error("metadata warm-up"))? {
+        info!("Metadata page cache warmed in {}ms ({} index entries{})",
+            report.elapsed.as_millis(), report.rows, if report.complete { "" } else { ", stopped at SQLITE_WARMUP_MAX_MS" });
+    }
+
+    for root in StorageConfig::from_env().root_directories() {
+        if replica {
+            check_replica_storage_root(&root).map_err(step_error("storage"))?;
+        } else {
+            check_storage_root(&root).map_err(step_error("storage"))?;
+        }
+        info!("Storage directory ready at {}", root.display());
+    }
+    instance_identity::verify(&db_path, force_adopt).map_err(step_error("instance identity"))?;
+    if !replica {
+        let storage = StorageConfig::from_env();
+        layout::reconcile(&storage).map_err(step_error("storage layout"))?;
+        legacy_migration::reconcile(&storage).map_err(step_error("legacy storage"))?;
+        check_write_offsets(&storage).map_err(step_error("write offsets"))?;
+    }
+
+    if !replica {
+        MetadataService::new(SYSTEM_USER)
+            .and_then(|db| db.create_bucket(HEALTH_BUCKET))
+            .map_err(|e| StartupError { step: "health bucket", message: e.to_string() })?;
+        change_log::snapshot_config()
+            .map_err(|e| StartupError { step: "change log", message: e.to_string() })?;
+    }
+
+    let elapsed = start.elapsed();
+    metrics::set_gauge("warpdrive_startup_init_seconds", &[], elapsed.as_secs_f64());
+    info!("Startup initialization completed in {}ms", elapsed.as_millis());
+    Ok(elapsed)
+}
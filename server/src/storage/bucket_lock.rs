@@ -0,0 +1,112 @@
+//! Per-bucket write gate and per-key locks
+//!
+//! Requests that append chunks hold a shared guard from before the first chunk write until
+//! their metadata is committed. Maintenance jobs that need a quiesced bucket (orphan GC)
+//! take the exclusive guard, which waits for in-flight writers and blocks new ones.
+//!
+//! Native writes that change keys (PUT, UPDATE, APPEND, DELETE, rename, `put_batch` and
+//! `txn`) also lock each key they touch, so a transaction's keys do not change under it.
+//! Keys hash onto a fixed set of stripes that are always taken in ascending order, so two
+//! requests locking overlapping keys cannot deadlock. Key locks are taken before the write
+//! gate.
+
+use lazy_static::lazy_static;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+/// Held by a request while it has chunks written but not yet referenced by metadata.
+pub type BucketWriteGuard = OwnedRwLockReadGuard<()>;
+
+/// Held by maintenance jobs that must not race in-flight writes.
+pub type BucketExclusiveGuard = OwnedRwLockWriteGuard<()>;
+
+/// Held while a request changes the keys it locked.
+pub type KeyGuards = Vec<OwnedMutexGuard<()>>;
+
+/// (user_id, bucket) -> gate
+type BucketLocks = HashMap<(String, String), Arc<RwLock<()>>>;
+
+/// Key lock stripes; unrelated keys sharing one only wait for each other
+const KEY_LOCK_STRIPES: usize = 1024;
+
+lazy_static! {
+    static ref BUCKET_LOCKS: Mutex<BucketLocks> = Mutex::new(HashMap::new());
+    static ref KEY_LOCKS: Vec<Arc<AsyncMutex<()>>> = (0..KEY_LOCK_STRIPES).map(|_| Arc::new(AsyncMutex::new(()))).collect();
+}
+
+fn key_stripe(user_id: &str, bucket: &str, key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    (user_id, bucket, key).hash(&mut hasher);
+    (hasher.finish() % KEY_LOCK_STRIPES as u64) as usize
+}
+
+fn bucket_lock(user_id: &str, bucket: &str) -> Arc<RwLock<()>> {
+    let mut locks = BUCKET_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    locks
+        .entry((user_id.to_string(), bucket.to_string()))
+        .or_insert_with(|| Arc::new(RwLock::new(())))
+        .clone()
+}
+
+/// Shared guard for a request that writes chunks into `user_id`/`bucket`.
+pub async fn begin_write(user_id: &str, bucket: &str) -> BucketWriteGuard {
+    bucket_lock(user_id, bucket).read_owned().await
+}
+
+/// Exclusive guard: waits for in-flight writers to finish and blocks new ones until dropped.
+pub async fn quiesce(user_id: &str, bucket: &str) -> BucketExclusiveGuard {
+    bucket_lock(user_id, bucket).write_owned().await
+}
+
+/// Lock `keys` of `user_id`/`bucket` until the guards are dropped. Take them before
+/// [`begin_write`].
+pub async fn lock_keys(user_id: &str, bucket: &str, keys: &[&str]) -> KeyGuards {
+    let mut stripes: Vec<usize> = keys.iter().map(|key| key_stripe(user_id, bucket, key)).collect();
+    stripes.sort_unstable();
+    stripes.dedup();
+    let mut guards = Vec::with_capacity(stripes.len());
+    for stripe in stripes {
+        guards.push(KEY_LOCKS[stripe].clone().lock_owned().await);
+    }
+    guards
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_quiesce_waits_for_writers() {
+        let writer = begin_write("lock_user", "lock_bucket").await;
+        let pending = tokio::time::timeout(Duration::from_millis(50), quiesce("lock_user", "lock_bucket")).await;
+        assert!(pending.is_err());
+
+        drop(writer);
+        let exclusive = tokio::time::timeout(Duration::from_millis(50), quiesce("lock_user", "lock_bucket")).await;
+        assert!(exclusive.is_ok());
+
+        // Other buckets are unaffected
+        let other = tokio::time::timeout(Duration::from_millis(50), begin_write("lock_user", "other_bucket")).await;
+        assert!(other.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_key_locks_in_any_order_do_not_deadlock() {
+        let held = lock_keys("lock_user", "keys", &["b", "a"]).await;
+        let pending = tokio::time::timeout(Duration::from_millis(50), lock_keys("lock_user", "keys", &["a"])).await;
+        assert!(pending.is_err());
+        drop(held);
+
+        let tasks: Vec<_> = (0..8).map(|i| tokio::spawn(async move {
+            let keys = if i % 2 == 0 { ["x", "y", "z"] } else { ["z", "y", "x"] };
+            let _guards = lock_keys("lock_user", "keys", &keys).await;
+            tokio::task::yield_now().await;
+        })).collect();
+        let all = tokio::time::timeout(Duration::from_secs(5), futures::future::join_all(tasks)).await;
+        assert!(all.is_ok());
+    }
+}
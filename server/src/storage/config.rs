@@ -1,21 +1,35 @@
 //! Configuration for binary storage backends
 
-use crate::storage::{Storage, local_store::LocalXFSBinaryStore, mock_store::MockBinaryStore};
-use std::sync::Arc;
+use crate::service::bucket_keys;
+use crate::storage::{Storage, encrypted_store::EncryptedStore, instrumented_store::InstrumentedStore, local_store::{self, LocalXFSBinaryStore}, mirrored_store::MirroredStore, mock_store::MockBinaryStore,
+    read_only_store::ReadOnlyStore, replicated_store::{ReplicatedStore, ReplicationConfig}, tiered_store::{TieredConfig, TieredStore}};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 use std::env;
 use log::{debug, warn};
 
+/// A backend supplied with [`install_backend`], used instead of `STORAGE_BACKEND`'s
+static INSTALLED_BACKEND: OnceLock<Arc<dyn Storage>> = OnceLock::new();
+
+/// Store chunks in `backend` instead of the backend `STORAGE_BACKEND` names, from the next
+/// [`StorageConfig::create_store`] on. Only possible once; `backend` is handed back otherwise.
+/// Like any backend it is still counted, encrypted and made read-only as configured.
+pub fn install_backend(backend: Arc<dyn Storage>) -> Result<(), Arc<dyn Storage>> {
+    INSTALLED_BACKEND.set(backend)
+}
+
 /// Available binary storage backends
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub enum StorageBackend {
+    #[default]
     LocalXFS,
     Mock,
-}
-
-impl Default for StorageBackend {
-    fn default() -> Self {
-        StorageBackend::LocalXFS
-    }
+    /// LocalXFS primary mirrored to `REPLICATION_SECONDARY_DIR`
+    Replicated,
+    /// Buckets placed on `HOT_DIRECTORY` or `COLD_DIRECTORY`
+    Tiered,
+    /// LocalXFS backed up to the S3-compatible `REMOTE_S3_BUCKET`
+    Mirrored,
 }
 
 impl std::str::FromStr for StorageBackend {
@@ -25,25 +39,32 @@ impl std::str::FromStr for StorageBackend {
         match s.to_lowercase().as_str() {
             "localxfs" | "local" | "xfs" => Ok(StorageBackend::LocalXFS),
             "mock" => Ok(StorageBackend::Mock),
+            "replicated" => Ok(StorageBackend::Replicated),
+            "tiered" => Ok(StorageBackend::Tiered),
+            "mirrored" => Ok(StorageBackend::Mirrored),
             _ => Err(format!("Unknown storage backend: {}", s))
         }
     }
 }
 
+impl StorageBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageBackend::LocalXFS => "localxfs",
+            StorageBackend::Mock => "mock",
+            StorageBackend::Replicated => "replicated",
+            StorageBackend::Tiered => "tiered",
+            StorageBackend::Mirrored => "mirrored",
+        }
+    }
+}
+
 /// Configuration for binary storage
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct StorageConfig {
     pub backend: StorageBackend,
 }
 
-impl Default for StorageConfig {
-    fn default() -> Self {
-        Self {
-            backend: StorageBackend::default(),
-        }
-    }
-}
-
 impl StorageConfig {
     /// Create a new storage configuration from environment variables
     pub fn from_env() -> Self {
@@ -69,11 +90,57 @@ impl StorageConfig {
         Self { backend }
     }
     
-    /// Create a storage instance based on the configuration
+    /// Directories the configured backend writes bucket files under; none for an installed
+    /// backend, which looks after its own
+    pub fn root_directories(&self) -> Vec<PathBuf> {
+        if INSTALLED_BACKEND.get().is_some() {
+            return vec![];
+        }
+        match self.backend {
+            StorageBackend::LocalXFS | StorageBackend::Mirrored => vec![local_store::storage_directory()],
+            StorageBackend::Mock => vec![],
+            StorageBackend::Replicated => {
+                let mut roots = vec![local_store::storage_directory()];
+                roots.extend(ReplicationConfig::from_env().map(|c| c.secondary_dir));
+                roots
+            }
+            StorageBackend::Tiered => {
+                let config = TieredConfig::from_env();
+                vec![config.hot_directory, config.cold_directory]
+            }
+        }
+    }
+
+    /// Create a storage instance based on the configuration; the backend's traffic is
+    /// counted, chunks are encrypted while bucket keys are in use, and on a replica it
+    /// refuses writes.
     pub fn create_store(&self) -> Arc<dyn Storage> {
+        let mut store: Arc<dyn Storage> = Arc::new(InstrumentedStore::new(self.create_backend()));
+        if bucket_keys::in_use() {
+            store = Arc::new(EncryptedStore::new(store));
+        }
+        if crate::config::is_replica() {
+            return Arc::new(ReadOnlyStore::new(store));
+        }
+        store
+    }
+
+    fn create_backend(&self) -> Arc<dyn Storage> {
+        if let Some(backend) = INSTALLED_BACKEND.get() {
+            return Arc::clone(backend);
+        }
         match self.backend {
             StorageBackend::LocalXFS => Arc::new(LocalXFSBinaryStore::new()),
             StorageBackend::Mock => Arc::new(MockBinaryStore::new()),
+            StorageBackend::Replicated => match ReplicatedStore::from_env() {
+                Some(store) => Arc::new(store),
+                None => Arc::new(LocalXFSBinaryStore::new()),
+            },
+            StorageBackend::Tiered => Arc::new(TieredStore::from_env()),
+            StorageBackend::Mirrored => match MirroredStore::from_env() {
+                Some(store) => Arc::new(store),
+                None => Arc::new(LocalXFSBinaryStore::new()),
+            },
         }
     }
 }
@@ -90,6 +157,9 @@ mod tests {
         assert_eq!("xfs".parse::<StorageBackend>().unwrap(), StorageBackend::LocalXFS);
         assert_eq!("mock".parse::<StorageBackend>().unwrap(), StorageBackend::Mock);
         assert_eq!("MOCK".parse::<StorageBackend>().unwrap(), StorageBackend::Mock);
+        assert_eq!("replicated".parse::<StorageBackend>().unwrap(), StorageBackend::Replicated);
+        assert_eq!("tiered".parse::<StorageBackend>().unwrap(), StorageBackend::Tiered);
+        assert_eq!("mirrored".parse::<StorageBackend>().unwrap(), StorageBackend::Mirrored);
         
         assert!("invalid".parse::<StorageBackend>().is_err());
     }
@@ -0,0 +1,157 @@
+//! Fsync policy for bucket files and the metadata database
+//!
+//! `FSYNC_POLICY` trades durability for write throughput:
+//!
+//! - `always`: every chunk write is followed by `sync_data()` before it is acknowledged, so an
+//!   acknowledged write survives power loss. SQLite runs with `synchronous=FULL`.
+//! - `interval`: writes are acknowledged once they reach the page cache and the bucket file is
+//!   marked dirty; a background task syncs dirty files every `FSYNC_INTERVAL_MS`. A power
+//!   failure can lose up to one interval of acknowledged writes. SQLite runs with
+//!   `synchronous=NORMAL` (the WAL is synced at checkpoints).
+//! - `never` (default): data is left to the kernel's writeback, as before this setting
+//!   existed; a power failure can lose any write not yet written back. SQLite also runs with
+//!   `synchronous=NORMAL`. `OFF` is never used because it can corrupt the database on an OS
+//!   crash, not just lose recent commits.
+//!
+//! A process crash alone never loses acknowledged writes under any policy: the data is
+//! already in the page cache.
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time;
+
+use crate::metrics;
+
+lazy_static! {
+    // Bucket files written since the last interval sync
+    static ref DIRTY_FILES: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FsyncPolicy {
+    Always,
+    Interval(Duration),
+    #[default]
+    Never,
+}
+
+impl FsyncPolicy {
+    /// Read `FSYNC_POLICY` and, for `interval`, `FSYNC_INTERVAL_MS` (default 1000).
+    pub fn from_env() -> Self {
+        match env::var("FSYNC_POLICY").map(|v| v.trim().to_lowercase()) {
+            Ok(policy) => match policy.as_str() {
+                "always" => FsyncPolicy::Always,
+                "interval" => {
+                    let millis = env::var("FSYNC_INTERVAL_MS")
+                        .ok()
+                        .and_then(|v| v.trim().parse().ok())
+                        .filter(|v| *v > 0)
+                        .unwrap_or(1000);
+                    FsyncPolicy::Interval(Duration::from_millis(millis))
+                }
+                "never" | "" => FsyncPolicy::Never,
+                other => {
+                    warn!("Unknown FSYNC_POLICY {}, using never", other);
+                    FsyncPolicy::Never
+                }
+            },
+            Err(_) => FsyncPolicy::Never,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::Interval(_) => "interval",
+            FsyncPolicy::Never => "never",
+        }
+    }
+
+    /// Matching SQLite `synchronous` setting
+    pub fn sqlite_synchronous(&self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "FULL",
+            FsyncPolicy::Interval(_) | FsyncPolicy::Never => "NORMAL",
+        }
+    }
+}
+
+fn sync_data(file: &File, policy: &'static str) -> io::Result<()> {
+    file.sync_data()?;
+    metrics::inc_counter("warpdrive_fsync_total", &[("policy", policy)]);
+    Ok(())
+}
+
+/// Apply `policy` to `file` (at `path`) right after a write, before it is acknowledged.
+pub fn after_write(policy: &FsyncPolicy, file: &File, path: &Path) -> io::Result<()> {
+    match policy {
+        FsyncPolicy::Always => sync_data(file, "always"),
+        FsyncPolicy::Interval(_) => {
+            DIRTY_FILES.lock().unwrap_or_else(|e| e.into_inner()).insert(path.to_path_buf());
+            Ok(())
+        }
+        FsyncPolicy::Never => Ok(()),
+    }
+}
+
+/// Sync every file written since the last call; returns how many were synced. Files that
+/// fail to sync stay dirty for the next pass.
+pub fn sync_dirty_files() -> usize {
+    let dirty: Vec<PathBuf> = DIRTY_FILES.lock().unwrap_or_else(|e| e.into_inner()).drain().collect();
+    let mut synced = 0;
+    for path in dirty {
+        match File::open(&path).and_then(|file| sync_data(&file, "interval")) {
+            Ok(()) => synced += 1,
+            // Removed since it was written: nothing left to sync
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!("Failed to sync {}: {}", path.display(), e);
+                DIRTY_FILES.lock().unwrap_or_else(|e| e.into_inner()).insert(path);
+            }
+        }
+    }
+    synced
+}
+
+/// Number of files waiting for the next interval sync
+pub fn dirty_file_count() -> usize {
+    DIRTY_FILES.lock().unwrap_or_else(|e| e.into_inner()).len()
+}
+
+/// Publish the policy on /metrics and, for `interval`, start the background sync task.
+pub fn start_fsync_worker() -> Option<tokio::task::JoinHandle<()>> {
+    let policy = FsyncPolicy::from_env();
+    metrics::set_gauge("warpdrive_fsync_policy", &[("policy", policy.as_str())], 1.0);
+    info!("Fsync policy: {} (SQLite synchronous={})", policy.as_str(), policy.sqlite_synchronous());
+    let FsyncPolicy::Interval(period) = policy else {
+        return None;
+    };
+    Some(tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = tokio::task::spawn_blocking(sync_dirty_files).await {
+                warn!("Fsync task failed: {}", e);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_mapping() {
+        assert_eq!(FsyncPolicy::Always.sqlite_synchronous(), "FULL");
+        assert_eq!(FsyncPolicy::Interval(Duration::from_millis(10)).sqlite_synchronous(), "NORMAL");
+        assert_eq!(FsyncPolicy::default(), FsyncPolicy::Never);
+    }
+}
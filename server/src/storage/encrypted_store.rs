@@ -0,0 +1,96 @@
+//! Encryption at rest
+//!
+//! `EncryptedStore` wraps the configured backend while `service::bucket_keys` is in use.
+//! Every chunk is encrypted on its way to the backend with AES-256-CTR under the bucket's
+//! current key and a fresh IV, and the `chunk_keys` table records the key version and IV at
+//! the chunk's offset. The ciphertext is as long as the data, so offsets, sizes and ranges
+//! above this layer are unchanged.
+//!
+//! A read decrypts each recorded chunk it overlaps with the key version that chunk was
+//! written with. Bytes with no record were written before encryption was turned on and are
+//! returned as stored. Freeing a range drops the records of the chunks in it.
+//!
+//! The backend's files hold ciphertext, so there is no backing file to stream from.
+
+use actix_web::Error;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::metadata::sqlite_store::{ChunkKeyRow, SQLiteMetadataStore};
+use crate::service::bucket_keys;
+use crate::storage::placement::PlacementTarget;
+use crate::storage::{segment, Storage};
+
+pub struct EncryptedStore {
+    inner: Arc<dyn Storage>,
+}
+
+impl EncryptedStore {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        Self { inner }
+    }
+
+    /// Write `data` encrypted through `write` and record the key it was written with
+    fn write_sealed<F>(&self, user_id: &str, bucket: &str, data: &[u8], write: F) -> Result<(u64, u64), Error>
+    where
+        F: FnOnce(&[u8]) -> Result<(u64, u64), Error>,
+    {
+        let (key_version, key) = bucket_keys::current_key(user_id, bucket)?;
+        let iv = bucket_keys::new_iv();
+        let mut sealed = data.to_vec();
+        bucket_keys::apply_keystream(&key, &iv, 0, &mut sealed)?;
+        let (offset, size) = write(&sealed)?;
+        let chunk = ChunkKeyRow { offset, size, key_version, iv: iv.to_vec() };
+        SQLiteMetadataStore::new().record_chunk_key(user_id, bucket, &chunk)?;
+        Ok((offset, size))
+    }
+}
+
+impl Storage for EncryptedStore {
+    fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        self.write_sealed(user_id, bucket, data, |sealed| self.inner.write(user_id, bucket, sealed))
+    }
+
+    fn write_to(&self, user_id: &str, bucket: &str, target: PlacementTarget, data: &[u8]) -> Result<(u64, u64), Error> {
+        self.write_sealed(user_id, bucket, data, |sealed| self.inner.write_to(user_id, bucket, target, sealed))
+    }
+
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let mut data = self.inner.read(user_id, bucket, offset, size)?;
+        let end = offset + data.len() as u64;
+        for chunk in SQLiteMetadataStore::new().chunk_keys_in(user_id, bucket, offset, end)? {
+            let key = bucket_keys::key(user_id, bucket, chunk.key_version)?;
+            let (from, to) = (offset.max(chunk.offset), end.min(chunk.offset + chunk.size));
+            let range = (from - offset) as usize..(to - offset) as usize;
+            bucket_keys::apply_keystream(&key, &chunk.iv, from - chunk.offset, &mut data[range])?;
+        }
+        Ok(data)
+    }
+
+    fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        self.inner.delete(user_id, bucket, offset_size_list)?;
+        SQLiteMetadataStore::new().forget_chunk_keys(user_id, bucket, offset_size_list)
+    }
+
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        let data = self.read(user_id, bucket, offset, size)?;
+        Ok(Sha256::digest(&data).as_slice() == checksum)
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.inner.bucket_len(user_id, bucket)
+    }
+
+    fn segments(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.inner.segments(user_id, bucket)
+    }
+
+    fn remove_segment(&self, user_id: &str, bucket: &str, segment: u32) -> Result<bool, Error> {
+        let removed = self.inner.remove_segment(user_id, bucket, segment)?;
+        if removed {
+            let span = (segment::address(segment, 0), segment::SEGMENT_SPAN);
+            SQLiteMetadataStore::new().forget_chunk_keys(user_id, bucket, &[span])?;
+        }
+        Ok(removed)
+    }
+}
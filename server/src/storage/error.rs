@@ -0,0 +1,47 @@
+//! I/O errors of the storage backends
+
+use actix_web::{Error, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use std::io;
+
+use crate::service::retry::FailureCause;
+
+/// An I/O error of a storage backend, kept whole so [`FailureCause`] can tell a transient
+/// failure (retried, then 503 with Retry-After) from a permanent one (500).
+#[derive(Debug)]
+pub struct StorageIoError(pub io::Error);
+
+impl StorageIoError {
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+}
+
+impl std::fmt::Display for StorageIoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ResponseError for StorageIoError {
+    fn status_code(&self) -> StatusCode {
+        if FailureCause::Io(self.kind()).is_transient() {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if self.status_code() == StatusCode::SERVICE_UNAVAILABLE {
+            response.insert_header(("Retry-After", "1"));
+        }
+        response.content_type("text/plain; charset=utf-8").body(self.to_string())
+    }
+}
+
+/// `map_err` adapter for I/O errors of a backend
+pub fn io_error(e: io::Error) -> Error {
+    StorageIoError(e).into()
+}
@@ -0,0 +1,303 @@
+//! Byte accounting at the storage boundary
+//!
+//! `InstrumentedStore` wraps the configured backend, beneath encryption, and counts the
+//! operations and bytes of every write, read, verify and delete that reaches it. Each is
+//! counted under the traffic it carries: `client` unless the calling thread is [`tag`]ged,
+//! `compaction` while the compactor copies live chunks, and `replication` for the copies the
+//! replication and S3 mirror threads make, which they record themselves. Reads streamed
+//! straight from a segment file are counted as they are sent.
+//!
+//! `StorageService` counts the logical bytes clients hand it to store, before deduplication,
+//! so write amplification is the physical bytes written by every kind of traffic over the
+//! logical client bytes. Bytes are also kept per user; the busiest users, by bytes written
+//! and read, are listed.
+//!
+//! Counters are `warpdrive_storage_ops_total{op,traffic}`,
+//! `warpdrive_storage_bytes_total{op,traffic}` and
+//! `warpdrive_storage_logical_bytes_written_total`; each scrape also sets
+//! `warpdrive_storage_write_amplification` and `warpdrive_storage_user_bytes{user,op}` for the
+//! `IO_STATS_TOP_USERS` (default 10) busiest users. `GET /admin/stats/io` reports the same.
+//! Everything is kept in memory and starts from zero when the process does.
+
+use actix_web::Error;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::metrics;
+use crate::storage::placement::PlacementTarget;
+use crate::storage::Storage;
+
+/// Users listed when `IO_STATS_TOP_USERS` is unset
+pub const DEFAULT_TOP_USERS: usize = 10;
+
+/// What a storage operation is done for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Traffic {
+    Client,
+    Compaction,
+    Replication,
+}
+
+impl Traffic {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Traffic::Client => "client",
+            Traffic::Compaction => "compaction",
+            Traffic::Replication => "replication",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IoOp {
+    Write,
+    Read,
+    Verify,
+    Delete,
+}
+
+impl IoOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IoOp::Write => "write",
+            IoOp::Read => "read",
+            IoOp::Verify => "verify",
+            IoOp::Delete => "delete",
+        }
+    }
+}
+
+thread_local! {
+    static TRAFFIC: Cell<Traffic> = const { Cell::new(Traffic::Client) };
+}
+
+/// Counts the calling thread's storage operations as `traffic` until dropped
+pub struct TrafficTag {
+    previous: Traffic,
+}
+
+impl Drop for TrafficTag {
+    fn drop(&mut self) {
+        TRAFFIC.with(|t| t.set(self.previous));
+    }
+}
+
+/// Tag the calling thread's storage operations as `traffic` while the returned guard lives.
+/// Hold it only across code that does not await, which could resume on another thread.
+pub fn tag(traffic: Traffic) -> TrafficTag {
+    TrafficTag { previous: TRAFFIC.with(|t| t.replace(traffic)) }
+}
+
+/// Traffic the calling thread's storage operations count as
+pub fn current() -> Traffic {
+    TRAFFIC.with(|t| t.get())
+}
+
+#[derive(Default)]
+struct UserBytes {
+    written: u64,
+    read: u64,
+}
+
+#[derive(Default)]
+struct IoState {
+    // (ops, bytes) per operation and traffic
+    totals: BTreeMap<(IoOp, Traffic), (u64, u64)>,
+    logical_written: u64,
+    users: HashMap<String, UserBytes>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<IoState> = Mutex::new(IoState::default());
+}
+
+/// Count one `op` on `bytes` bytes of `user_id`'s data as `traffic`
+pub fn record(op: IoOp, traffic: Traffic, user_id: &str, bytes: u64) {
+    let labels = [("op", op.as_str()), ("traffic", traffic.as_str())];
+    metrics::inc_counter("warpdrive_storage_ops_total", &labels);
+    metrics::inc_counter_by("warpdrive_storage_bytes_total", &labels, bytes);
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let total = state.totals.entry((op, traffic)).or_default();
+    total.0 += 1;
+    total.1 += bytes;
+    match op {
+        IoOp::Write => state.users.entry(user_id.to_string()).or_default().written += bytes,
+        IoOp::Read => state.users.entry(user_id.to_string()).or_default().read += bytes,
+        IoOp::Verify | IoOp::Delete => {}
+    }
+}
+
+/// Count `bytes` a client handed the storage layer to store, before deduplication
+pub fn record_logical_write(bytes: u64) {
+    metrics::inc_counter_by("warpdrive_storage_logical_bytes_written_total", &[], bytes);
+    STATE.lock().unwrap_or_else(|e| e.into_inner()).logical_written += bytes;
+}
+
+/// Users listed by [`snapshot`] and the per-user gauge, from `IO_STATS_TOP_USERS`
+pub fn top_users_from_env() -> usize {
+    env::var("IO_STATS_TOP_USERS")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_TOP_USERS)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OpBytes {
+    pub op: &'static str,
+    pub traffic: &'static str,
+    pub ops: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserIo {
+    pub user: String,
+    pub written_bytes: u64,
+    pub read_bytes: u64,
+}
+
+/// Reported by `GET /admin/stats/io`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IoStats {
+    /// Bytes clients handed the storage layer to store
+    pub logical_written_bytes: u64,
+    /// Bytes written to storage by every kind of traffic
+    pub physical_written_bytes: u64,
+    /// `physical_written_bytes / logical_written_bytes`; none before any client write
+    pub write_amplification: Option<f64>,
+    pub operations: Vec<OpBytes>,
+    /// Users with the most bytes written and read, most first
+    pub top_users: Vec<UserIo>,
+}
+
+/// Counters so far, with the `top` busiest users
+pub fn snapshot(top: usize) -> IoStats {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let physical_written_bytes = state.totals.iter()
+        .filter(|((op, _), _)| *op == IoOp::Write)
+        .map(|(_, (_, bytes))| bytes)
+        .sum::<u64>();
+    let operations = state.totals.iter()
+        .map(|((op, traffic), (ops, bytes))| OpBytes { op: op.as_str(), traffic: traffic.as_str(), ops: *ops, bytes: *bytes })
+        .collect();
+    let mut top_users: Vec<UserIo> = state.users.iter()
+        .map(|(user, bytes)| UserIo { user: user.clone(), written_bytes: bytes.written, read_bytes: bytes.read })
+        .collect();
+    top_users.sort_by(|a, b| (b.written_bytes + b.read_bytes).cmp(&(a.written_bytes + a.read_bytes)).then_with(|| a.user.cmp(&b.user)));
+    top_users.truncate(top);
+    IoStats {
+        logical_written_bytes: state.logical_written,
+        physical_written_bytes,
+        write_amplification: (state.logical_written > 0).then(|| physical_written_bytes as f64 / state.logical_written as f64),
+        operations,
+        top_users,
+    }
+}
+
+/// Set the write amplification and per-user gauges from the counters; called on each scrape
+pub fn export() {
+    let stats = snapshot(top_users_from_env());
+    metrics::set_gauge("warpdrive_storage_write_amplification", &[], stats.write_amplification.unwrap_or(0.0));
+    let series: Vec<(Vec<(&str, &str)>, f64)> = stats.top_users.iter()
+        .flat_map(|u| [
+            (vec![("user", u.user.as_str()), ("op", "write")], u.written_bytes as f64),
+            (vec![("user", u.user.as_str()), ("op", "read")], u.read_bytes as f64),
+        ])
+        .collect();
+    metrics::replace_gauge_series("warpdrive_storage_user_bytes", &series);
+}
+
+pub struct InstrumentedStore<S: Storage + ?Sized> {
+    inner: Arc<S>,
+}
+
+impl<S: Storage + ?Sized> InstrumentedStore<S> {
+    pub fn new(inner: Arc<S>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S: Storage + ?Sized> Storage for InstrumentedStore<S> {
+    fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        let extent = self.inner.write(user_id, bucket, data)?;
+        record(IoOp::Write, current(), user_id, data.len() as u64);
+        Ok(extent)
+    }
+
+    fn write_to(&self, user_id: &str, bucket: &str, target: PlacementTarget, data: &[u8]) -> Result<(u64, u64), Error> {
+        let extent = self.inner.write_to(user_id, bucket, target, data)?;
+        record(IoOp::Write, current(), user_id, data.len() as u64);
+        Ok(extent)
+    }
+
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let data = self.inner.read(user_id, bucket, offset, size)?;
+        record(IoOp::Read, current(), user_id, data.len() as u64);
+        Ok(data)
+    }
+
+    fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        self.inner.delete(user_id, bucket, offset_size_list)?;
+        record(IoOp::Delete, current(), user_id, offset_size_list.iter().map(|(_, size)| size).sum());
+        Ok(())
+    }
+
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        let verified = self.inner.verify(user_id, bucket, offset, size, checksum)?;
+        record(IoOp::Verify, current(), user_id, size);
+        Ok(verified)
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.inner.bucket_len(user_id, bucket)
+    }
+
+    fn backing_file(&self, user_id: &str, bucket: &str, offset: u64) -> Option<(PathBuf, u64)> {
+        self.inner.backing_file(user_id, bucket, offset)
+    }
+
+    fn segments(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.inner.segments(user_id, bucket)
+    }
+
+    fn remove_segment(&self, user_id: &str, bucket: &str, segment: u32) -> Result<bool, Error> {
+        self.inner.remove_segment(user_id, bucket, segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::mock_store::MockBinaryStore;
+
+    fn bytes(user: &str) -> (u64, u64) {
+        let stats = snapshot(usize::MAX);
+        let user = stats.top_users.iter().find(|u| u.user == user);
+        user.map_or((0, 0), |u| (u.written_bytes, u.read_bytes))
+    }
+
+    #[test]
+    fn test_counts_by_user_and_traffic() {
+        let store = InstrumentedStore::new(Arc::new(MockBinaryStore::new()));
+        let compaction = || metrics::counter_value("warpdrive_storage_bytes_total", &[("op", "write"), ("traffic", "compaction")]);
+        let before = compaction();
+
+        let (offset, size) = store.write("instrumented_user", "b", &[1; 100]).unwrap();
+        assert_eq!(store.read("instrumented_user", "b", offset, size).unwrap().len(), 100);
+        {
+            let _compaction = tag(Traffic::Compaction);
+            assert_eq!(current(), Traffic::Compaction);
+            store.write("instrumented_user", "b", &[2; 30]).unwrap();
+        }
+        assert_eq!(current(), Traffic::Client);
+        assert!(store.read("instrumented_user", "b", offset + size + 1000, 1).is_err());
+
+        assert_eq!(bytes("instrumented_user"), (130, 100));
+        assert_eq!(compaction() - before, 30);
+    }
+}
@@ -0,0 +1,212 @@
+//! Directory layout of bucket files
+//!
+//! With the default `STORAGE_LAYOUT=flat` every segment file of a user sits in
+//! `<root>/<user>/`. A user with tens of thousands of buckets then has that many files in one
+//! directory, so `STORAGE_LAYOUT=hashed` fans them out to `<root>/<user>/<xx>/`, where `xx`
+//! is the first two hex digits of the SHA-256 of the bucket name (256 subdirectories).
+//!
+//! The layout of existing files is recorded in the `storage_layouts` table: a user with a row
+//! uses its layout, any other user `STORAGE_LAYOUT`. When a start finds `STORAGE_LAYOUT`
+//! changed, [`reconcile`] pins every user already present in storage to the previous layout,
+//! so changing the setting only affects new users until `warp_drive migrate-layout --user`
+//! moves a user's files. While a migration runs the user's row names its target, and each
+//! segment file is looked up in the target layout first and then where it was, so reads see
+//! every file whether or not it has moved yet. Rows are cached per process and reloaded
+//! when the configuration generation moves.
+
+use actix_web::Error;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::service::config_generation;
+use crate::storage::config::{StorageBackend, StorageConfig};
+use crate::storage::local_store;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageLayout {
+    /// `<user>/<bucket>.bin`
+    #[default]
+    Flat,
+    /// `<user>/<xx>/<bucket>.bin`
+    Hashed,
+}
+
+impl StorageLayout {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageLayout::Flat => "flat",
+            StorageLayout::Hashed => "hashed",
+        }
+    }
+
+    /// `STORAGE_LAYOUT`, flat when unset or invalid
+    pub fn from_env() -> Self {
+        match env::var("STORAGE_LAYOUT") {
+            Ok(v) => v.parse().unwrap_or_else(|e| {
+                warn!("{}; using the flat layout", e);
+                StorageLayout::Flat
+            }),
+            Err(_) => StorageLayout::Flat,
+        }
+    }
+}
+
+impl std::str::FromStr for StorageLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "flat" => Ok(StorageLayout::Flat),
+            "hashed" => Ok(StorageLayout::Hashed),
+            _ => Err(format!("Unknown storage layout: {} (expected flat or hashed)", s)),
+        }
+    }
+}
+
+/// Layout of one user's files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserLayout {
+    pub layout: StorageLayout,
+    /// Layout files are being moved to
+    pub target: Option<StorageLayout>,
+}
+
+/// Subdirectory of `bucket` in the hashed layout
+pub fn hash_prefix(bucket: &str) -> String {
+    hex::encode(&Sha256::digest(bucket.as_bytes())[..1])
+}
+
+/// Directory holding the files of `bucket` under `user_dir`
+pub fn bucket_directory(user_dir: &Path, bucket: &str, layout: StorageLayout) -> PathBuf {
+    match layout {
+        StorageLayout::Flat => user_dir.to_path_buf(),
+        StorageLayout::Hashed => user_dir.join(hash_prefix(bucket)),
+    }
+}
+
+lazy_static! {
+    // user_id -> recorded (layout, target); None caches "no row"
+    static ref LAYOUTS: RwLock<HashMap<String, Option<UserLayout>>> = RwLock::new(HashMap::new());
+}
+
+fn parse_row(user_id: &str, (layout, target): (String, Option<String>)) -> UserLayout {
+    let layout = layout.parse().unwrap_or_else(|e| {
+        warn!("{} for user {}; assuming flat", e, user_id);
+        StorageLayout::Flat
+    });
+    UserLayout { layout, target: target.and_then(|t| t.parse().ok()) }
+}
+
+/// Layout of `user_id`'s files. A database error falls back to `STORAGE_LAYOUT` and is
+/// retried on the next call.
+pub fn user_layout(user_id: &str) -> UserLayout {
+    config_generation::revalidate();
+    let recorded = LAYOUTS.read().unwrap_or_else(|e| e.into_inner()).get(user_id).copied();
+    let recorded = match recorded {
+        Some(recorded) => recorded,
+        None => match SQLiteMetadataStore::new().get_storage_layout(user_id) {
+            Ok(row) => {
+                let recorded = row.map(|row| parse_row(user_id, row));
+                LAYOUTS.write().unwrap_or_else(|e| e.into_inner()).insert(user_id.to_string(), recorded);
+                recorded
+            }
+            Err(e) => {
+                warn!("Cannot read the storage layout of user {}: {}", user_id, e);
+                None
+            }
+        },
+    };
+    recorded.unwrap_or(UserLayout { layout: StorageLayout::from_env(), target: None })
+}
+
+/// Persist `layout` for `user_id` and refresh the cache.
+pub fn set_user_layout(user_id: &str, layout: UserLayout) -> Result<(), Error> {
+    SQLiteMetadataStore::new().set_storage_layout(user_id, layout.layout.as_str(), layout.target.map(|t| t.as_str()))?;
+    LAYOUTS.write().unwrap_or_else(|e| e.into_inner()).insert(user_id.to_string(), Some(layout));
+    Ok(())
+}
+
+/// Drop every cached layout.
+pub(crate) fn clear() {
+    LAYOUTS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Roots holding the bucket files of the configured backend; a replication secondary keeps
+/// the flat layout and is not included.
+pub fn layout_roots(config: &StorageConfig) -> Vec<PathBuf> {
+    match config.backend {
+        StorageBackend::Replicated => vec![local_store::storage_directory()],
+        _ => config.root_directories(),
+    }
+}
+
+/// Users with a directory under any of `roots`
+pub fn users_in(roots: &[PathBuf]) -> std::io::Result<Vec<String>> {
+    let mut users = Vec::new();
+    for root in roots {
+        let entries = match std::fs::read_dir(root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                users.extend(entry.file_name().to_str().map(str::to_string));
+            }
+        }
+    }
+    users.sort();
+    users.dedup();
+    Ok(users)
+}
+
+/// At startup: when `STORAGE_LAYOUT` differs from the layout the storage was last started
+/// with, pin the users already in storage to the previous one. Returns how many were pinned.
+pub fn reconcile(config: &StorageConfig) -> Result<usize, String> {
+    let current = StorageLayout::from_env();
+    let db = SQLiteMetadataStore::new();
+    let recorded = db.get_storage_layout("").map_err(|e| e.to_string())?.map(|row| parse_row("", row).layout);
+    if recorded == Some(current) {
+        return Ok(0);
+    }
+    // Storage from before layouts were recorded is flat
+    let previous = recorded.unwrap_or(StorageLayout::Flat);
+    let users = if previous == current {
+        Vec::new()
+    } else {
+        users_in(&layout_roots(config)).map_err(|e| format!("cannot list storage users: {}", e))?
+    };
+    let pinned = db.pin_storage_layouts(&users, previous.as_str(), current.as_str()).map_err(|e| e.to_string())?;
+    clear();
+    if previous != current {
+        info!("STORAGE_LAYOUT changed from {} to {}; {} existing users keep the {} layout until migrated",
+              previous.as_str(), current.as_str(), pinned, previous.as_str());
+    }
+    Ok(pinned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_directory_is_stable_two_hex_digits() {
+        let prefix = hash_prefix("photos");
+        assert_eq!(prefix.len(), 2);
+        assert!(prefix.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(prefix, hash_prefix("photos"));
+
+        let user_dir = Path::new("/data/alice");
+        assert_eq!(bucket_directory(user_dir, "photos", StorageLayout::Flat), user_dir);
+        assert_eq!(bucket_directory(user_dir, "photos", StorageLayout::Hashed), user_dir.join(prefix));
+        assert_eq!("Hashed".parse::<StorageLayout>(), Ok(StorageLayout::Hashed));
+        assert!("nested".parse::<StorageLayout>().is_err());
+    }
+}
@@ -1,19 +1,79 @@
 //! Local XFS binary storage implementation
+//!
+//! Each bucket is a set of segment files under `<STORAGE_DIRECTORY>/<user>/`, or a hashed
+//! subdirectory of it (`storage::layout`); see `storage::segment` for the segments and how
+//! chunk offsets address them. Chunks in [`segment::LEGACY_SEGMENT`] are read from the
+//! user's `<STORAGE_DIRECTORY>/<user>.bin` of the layout from before buckets.
+//!
+//! Writers of a bucket do not wait for each other, and buckets never wait for one another.
+//! Each bucket keeps, in memory, its active segment and the next free offset of every segment
+//! it has written to, started from the file's length on first touch. A write reserves its
+//! range with one atomic update and then writes it with `write_all_at` outside any lock, so
+//! writes of one bucket land concurrently at different offsets. Rolling over to a new segment
+//! seals the old one in the same update, so nothing more is reserved in it. Offsets are never
+//! handed out twice; a write that fails leaves its range unreferenced, punched out at once
+//! and found again by orphan GC. Removing or moving a bucket's segment files waits for the
+//! bucket's writes in flight; removal resets the bucket's write state in place, so writes
+//! queued behind it reserve from the emptied bucket like any later one. At startup
+//! ([`LocalXFSBinaryStore::check_reserved_offsets`]) the next free offset of a segment file
+//! shorter than the extents metadata holds in it is started past them instead, so they are
+//! never handed out again; the file itself is left for fsck to report. With
+//! `PREALLOCATE_BYTES` set, blocks are reserved ahead of the writes of each segment
+//! (`storage::preallocate`).
 
 use crate::storage::Storage;
+use crate::metrics;
+use crate::storage::durability::{self, FsyncPolicy};
+use crate::storage::error::io_error;
+use crate::storage::layout::{self, StorageLayout};
+use crate::storage::placement::PlacementTarget;
+use crate::storage::preallocate::{self, PreallocateConfig};
+use crate::storage::segment::{self, SegmentConfig};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{OpenOptions, File};
-use std::io::{self, Read, Write, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
 use std::env;
 use actix_web::Error;
-use actix_web::error::ErrorInternalServerError;
 use log::{debug, trace, warn};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use lazy_static::lazy_static;
 
-// Global mutex to synchronize concurrent writes to storage files
+/// Flag of a segment end: the active segment has moved past it and no more chunks are
+/// reserved in it. Offsets stay below `SEGMENT_SPAN`, far from this bit.
+const SEALED: u64 = 1 << 63;
+
+/// Write state of one bucket
+struct BucketFiles {
+    /// Held shared by each write, exclusively while segment files are removed or moved
+    files: RwLock<()>,
+    /// Segment new chunks are appended to
+    active: AtomicU32,
+    /// Next free offset of each segment written to, with [`SEALED`] once the active segment
+    /// moved past it
+    ends: Mutex<HashMap<u32, Arc<AtomicU64>>>,
+    /// End of the blocks preallocated in each segment, as far as this process knows
+    allocated: Mutex<HashMap<u32, u64>>,
+}
+
 lazy_static! {
-    static ref STORAGE_WRITE_LOCK: Mutex<()> = Mutex::new(());
+    // (root, user, bucket) -> write state, filled on first use
+    static ref BUCKET_FILES: Mutex<HashMap<(PathBuf, String, String), Arc<BucketFiles>>> = Mutex::new(HashMap::new());
+}
+
+/// Root used when `STORAGE_DIRECTORY` is unset: `storage`, or the scratch directory in unit tests.
+pub(crate) fn default_storage_directory() -> PathBuf {
+    #[cfg(test)]
+    return crate::util::scratch::root().join("storage");
+    #[cfg(not(test))]
+    PathBuf::from("storage")
+}
+
+/// Root directory from `STORAGE_DIRECTORY` (default `storage`), without creating it.
+pub fn storage_directory() -> PathBuf {
+    env::var("STORAGE_DIRECTORY").map(PathBuf::from).unwrap_or_else(|_| default_storage_directory())
 }
 
 fn get_storage_directory() -> PathBuf {
@@ -26,7 +86,7 @@ fn get_storage_directory() -> PathBuf {
         Err(_) => {
             warn!("Storage directory not defined in environment");
             // Use default directory "./storage"            
-            let default_path = PathBuf::from("storage");
+            let default_path = default_storage_directory();
             if !default_path.exists() {
                 std::fs::create_dir_all(&default_path)
                     .expect("Failed to create default storage directory");
@@ -37,87 +97,414 @@ fn get_storage_directory() -> PathBuf {
     }
 }
 
+#[cfg(target_os = "linux")]
+const EOPNOTSUPP: i32 = libc::EOPNOTSUPP;
+#[cfg(not(target_os = "linux"))]
+const EOPNOTSUPP: i32 = 95;
+
+/// Deallocate `len` bytes at `offset` without changing the file size; reads of the range
+/// return zeros afterwards.
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: fallocate only acts on the open descriptor; no memory is shared with the kernel.
+    let rc = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if rc == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
 /// Local XFS binary storage implementation
-pub struct LocalXFSBinaryStore;
+#[derive(Default)]
+pub struct LocalXFSBinaryStore {
+    /// Root directory; `None` means `STORAGE_DIRECTORY`
+    root: Option<PathBuf>,
+}
 
 impl LocalXFSBinaryStore {
-    pub fn new() -> Self { Self }
+    pub fn new() -> Self { Self::default() }
+
+    /// Store rooted at `root` instead of `STORAGE_DIRECTORY`
+    pub fn with_directory(root: PathBuf) -> Self {
+        Self { root: Some(root) }
+    }
     
-    /// Get the file path for a user's bucket binary file
-    fn get_bucket_file_path(&self, user_id: &str, bucket: &str) -> PathBuf {
-        let storage_dir = get_storage_directory();
-        let user_dir = storage_dir.join(user_id);
+    fn root_directory(&self) -> PathBuf {
+        match &self.root {
+            Some(root) => root.clone(),
+            None => get_storage_directory(),
+        }
+    }
+
+    /// The single file holding all of a user's data before buckets existed
+    pub fn legacy_file_path(&self, user_id: &str) -> PathBuf {
+        self.root_directory().join(format!("{}.bin", user_id))
+    }
+
+    /// Get the directory holding a user's bucket files, creating it if needed
+    fn get_user_directory(&self, user_id: &str) -> PathBuf {
+        let user_dir = self.root_directory().join(user_id);
         
         // Create user directory if it doesn't exist
         if !user_dir.exists() {
             std::fs::create_dir_all(&user_dir)
                 .expect("Failed to create user directory");
         }
-        
-        // Return path as user/bucket-name.bin
-        user_dir.join(format!("{}.bin", bucket))
+        user_dir
+    }
+
+    /// Directory of `bucket`'s files in `layout`, creating it if needed
+    fn get_bucket_directory(&self, user_dir: &Path, bucket: &str, layout: StorageLayout) -> PathBuf {
+        let dir = layout::bucket_directory(user_dir, bucket, layout);
+        if !dir.exists() {
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                warn!("Failed to create bucket directory {}: {}", dir.display(), e);
+            }
+        }
+        dir
+    }
+
+    /// Get the file path of one segment of a user's bucket. While the user's files move to
+    /// another layout, a segment not moved yet is found where it was.
+    fn get_segment_file_path(&self, user_id: &str, bucket: &str, segment: u32) -> PathBuf {
+        if segment == segment::LEGACY_SEGMENT {
+            return self.legacy_file_path(user_id);
+        }
+        let user_dir = self.get_user_directory(user_id);
+        let name = segment::file_name(bucket, segment);
+        let current = layout::user_layout(user_id);
+        match current.target {
+            None => self.get_bucket_directory(&user_dir, bucket, current.layout).join(name),
+            Some(target) => {
+                let source = layout::bucket_directory(&user_dir, bucket, current.layout).join(&name);
+                let moved = layout::bucket_directory(&user_dir, bucket, target).join(&name);
+                if moved.exists() || !source.exists() {
+                    self.get_bucket_directory(&user_dir, bucket, target).join(name)
+                } else {
+                    source
+                }
+            }
+        }
+    }
+
+    /// Segments of a bucket present on disk, in order
+    fn list_segments(&self, user_id: &str, bucket: &str) -> io::Result<Vec<u32>> {
+        let user_dir = self.get_user_directory(user_id);
+        let current = layout::user_layout(user_id);
+        let mut segments = Vec::new();
+        for layout in std::iter::once(current.layout).chain(current.target) {
+            let entries = match std::fs::read_dir(layout::bucket_directory(&user_dir, bucket, layout)) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let name = entry?.file_name();
+                if let Some(segment) = name.to_str().and_then(|name| segment::parse_file_name(bucket, name)) {
+                    segments.push(segment);
+                }
+            }
+        }
+        segments.sort_unstable();
+        segments.dedup();
+        Ok(segments)
+    }
+
+    /// Move the segment files of `bucket` from layout `from` to `to`; returns the segments
+    /// moved. Callers keep the bucket quiesced.
+    pub fn move_bucket_files(&self, user_id: &str, bucket: &str, from: StorageLayout, to: StorageLayout) -> io::Result<Vec<u32>> {
+        let state = self.bucket_files(user_id, bucket)?;
+        let _files = state.files.write().unwrap_or_else(|e| e.into_inner());
+        let user_dir = self.get_user_directory(user_id);
+        let source_dir = layout::bucket_directory(&user_dir, bucket, from);
+        let target_dir = layout::bucket_directory(&user_dir, bucket, to);
+        let mut moved = Vec::new();
+        let entries = match std::fs::read_dir(&source_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(moved),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let name = entry?.file_name();
+            let Some(segment) = name.to_str().and_then(|name| segment::parse_file_name(bucket, name)) else {
+                continue;
+            };
+            std::fs::create_dir_all(&target_dir)?;
+            let target = target_dir.join(&name);
+            if target.exists() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", target.display())));
+            }
+            std::fs::rename(source_dir.join(&name), &target)?;
+            moved.push(segment);
+        }
+        moved.sort_unstable();
+        Ok(moved)
+    }
+
+    fn state_key(&self, user_id: &str, bucket: &str) -> (PathBuf, String, String) {
+        (self.root_directory(), user_id.to_string(), bucket.to_string())
+    }
+
+    /// Write state of the bucket; on first use its active segment is the last one on disk
+    fn bucket_files(&self, user_id: &str, bucket: &str) -> io::Result<Arc<BucketFiles>> {
+        let key = self.state_key(user_id, bucket);
+        if let Some(state) = BUCKET_FILES.lock().unwrap_or_else(|e| e.into_inner()).get(&key) {
+            return Ok(state.clone());
+        }
+        let active = self.list_segments(user_id, bucket)?.last().copied().unwrap_or(0);
+        let state = Arc::new(BucketFiles {
+            files: RwLock::new(()),
+            active: AtomicU32::new(active),
+            ends: Mutex::new(HashMap::new()),
+            allocated: Mutex::new(HashMap::new()),
+        });
+        Ok(BUCKET_FILES.lock().unwrap_or_else(|e| e.into_inner()).entry(key).or_insert(state).clone())
+    }
+
+    /// Next free offset of `segment`, from the file's length on first use
+    fn segment_end(&self, state: &BucketFiles, user_id: &str, bucket: &str, segment: u32) -> io::Result<Arc<AtomicU64>> {
+        let mut ends = state.ends.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(end) = ends.get(&segment) {
+            return Ok(end.clone());
+        }
+        let len = match std::fs::metadata(self.get_segment_file_path(user_id, bucket, segment)) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(ends.entry(segment).or_insert_with(|| Arc::new(AtomicU64::new(len))).clone())
+    }
+
+    /// Reserve `len` bytes where `target` says: `(segment, offset)` of the range, which no
+    /// other write is given
+    fn reserve(&self, state: &BucketFiles, user_id: &str, bucket: &str, target: PlacementTarget, len: u64) -> io::Result<(u32, u64)> {
+        if let PlacementTarget::Segment(n) = target {
+            // A segment the placement policy picked; it is appended to whatever its size
+            let end = self.segment_end(state, user_id, bucket, n)?;
+            state.active.fetch_max(n, Ordering::AcqRel);
+            return Ok((n, end.fetch_add(len, Ordering::AcqRel) & !SEALED));
+        }
+        let config = SegmentConfig::from_env();
+        loop {
+            let active = state.active.load(Ordering::Acquire);
+            let end = self.segment_end(state, user_id, bucket, active)?;
+            let current = end.load(Ordering::Acquire);
+            if current & SEALED != 0 {
+                // Sealed by a racing write that has yet to move the active segment on
+                state.active.fetch_max(active + 1, Ordering::AcqRel);
+                continue;
+            }
+            // Seal the segment if this chunk would overfill it, and append to the next
+            if config.rolls_over(current, len) {
+                if end.compare_exchange(current, current | SEALED, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                    state.active.fetch_max(active + 1, Ordering::AcqRel);
+                    metrics::inc_counter("warpdrive_segment_rollovers_total", &[]);
+                    debug!("Sealed segment {} of user {} bucket {} at {} bytes", active, user_id, bucket, current);
+                }
+                continue;
+            }
+            if end.compare_exchange(current, current + len, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                return Ok((active, current));
+            }
+        }
+    }
+
+    /// Write `data` to its reserved range of the segment file at `path`, returning the file
+    fn write_reserved(path: &Path, offset: u64, data: &[u8]) -> io::Result<File> {
+        let file = Self::open_file_for_write(path)?;
+        file.write_all_at(data, offset)?;
+        // Sync (or schedule a sync) per FSYNC_POLICY before acknowledging
+        durability::after_write(&FsyncPolicy::from_env(), &file, path)?;
+        Ok(file)
+    }
+
+    /// Reserve blocks of `segment` past `written`, the end of a write, when fewer than the
+    /// threshold are left ahead of it
+    fn preallocate(state: &BucketFiles, segment: u32, file: &File, path: &Path, written: u64) {
+        let config = PreallocateConfig::from_env();
+        if !config.enabled() {
+            return;
+        }
+        let limit = SegmentConfig::from_env().max_bytes;
+        let (start, end) = {
+            let mut allocated = state.allocated.lock().unwrap_or_else(|e| e.into_inner());
+            let ahead = allocated.entry(segment).or_insert(0);
+            if ahead.saturating_sub(written) >= config.threshold {
+                return;
+            }
+            let start = (*ahead).max(written);
+            let end = written.saturating_add(config.bytes).min(limit);
+            if end <= start {
+                return;
+            }
+            // Claimed before allocating, so racing writes do not allocate the same blocks
+            *ahead = end;
+            (start, end)
+        };
+        if preallocate::allocate_ahead(file, path, start, end - start) {
+            metrics::inc_counter_by("warpdrive_storage_preallocated_bytes_total", &[], end - start);
+        }
+    }
+
+    /// Give back the blocks of a range whose write failed. Its offsets are never reserved
+    /// again; orphan GC finds the range too, should this fail.
+    fn abandon(path: &Path, offset: u64, len: u64) {
+        metrics::inc_counter_by("warpdrive_storage_abandoned_bytes_total", &[], len);
+        let punched = OpenOptions::new().write(true).open(path).and_then(|file| punch_hole(&file, offset, len));
+        if let Err(e) = punched {
+            debug!("Could not free the {} bytes at {} of {} after a failed write: {}", len, offset, path.display(), e);
+        }
     }
     
-    /// Open or create a user's bucket binary file for writing
-    fn open_bucket_file_for_write(&self, user_id: &str, bucket: &str) -> io::Result<File> {
-        let file_path = self.get_bucket_file_path(user_id, bucket);
+    /// Open a segment file for reading. A file moved by a layout migration between resolving
+    /// its path and opening it is looked up again.
+    fn open_segment_for_read(&self, user_id: &str, bucket: &str, segment: u32) -> io::Result<File> {
+        let path = self.get_segment_file_path(user_id, bucket, segment);
+        match File::open(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let again = self.get_segment_file_path(user_id, bucket, segment);
+                if again == path { Err(e) } else { File::open(again) }
+            }
+            result => result,
+        }
+    }
+
+    /// Open or create a segment file for writing
+    fn open_file_for_write(path: &Path) -> io::Result<File> {
         OpenOptions::new()
             .create(true)
             .read(true)
             .write(true)
+            .truncate(false)
             .append(false)  // Don't use append mode to allow seeking
-            .open(&file_path)
+            .open(path)
     }
 
-    /// Open a user's bucket binary file for reading
-    fn open_bucket_file_for_read(&self, user_id: &str, bucket: &str) -> io::Result<File> {
-        let file_path = self.get_bucket_file_path(user_id, bucket);
-        OpenOptions::new()
-            .read(true)
-            .open(&file_path)
+    /// Grow the bucket to at least `len` (a bucket address, sparse), so the next write lands
+    /// at or beyond `len`.
+    pub fn extend_to(&self, user_id: &str, bucket: &str, len: u64) -> Result<(), Error> {
+        let state = self.bucket_files(user_id, bucket).map_err(io_error)?;
+        let _files = state.files.read().unwrap_or_else(|e| e.into_inner());
+        let target = segment::segment_of(len);
+        if target < state.active.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let path = self.get_segment_file_path(user_id, bucket, target);
+        let file = Self::open_file_for_write(&path).map_err(io_error)?;
+        let within = segment::offset_in_segment(len);
+        let current = file.metadata().map_err(io_error)?.len();
+        if current < within {
+            file.set_len(within).map_err(io_error)?;
+        }
+        self.segment_end(&state, user_id, bucket, target).map_err(io_error)?.fetch_max(within, Ordering::AcqRel);
+        state.active.fetch_max(target, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Remove every segment file of the bucket. Returns whether there was one.
+    pub fn remove_bucket_file(&self, user_id: &str, bucket: &str) -> Result<bool, Error> {
+        let state = self.bucket_files(user_id, bucket).map_err(io_error)?;
+        let _files = state.files.write().unwrap_or_else(|e| e.into_inner());
+        let segments = self.list_segments(user_id, bucket).map_err(io_error)?;
+        // Writes waiting on `files` hold this state, so it is emptied rather than replaced:
+        // they reserve from offset 0 of segment 0 along with every later write
+        state.ends.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        state.allocated.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        state.active.store(0, Ordering::Release);
+        let mut removed = false;
+        for n in segments {
+            match std::fs::remove_file(self.get_segment_file_path(user_id, bucket, n)) {
+                Ok(()) => removed = true,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Start the next free offset of each segment past the `referenced` extents in it. A file
+    /// cut short by a crash or a restore ends before them, and offsets started from its length
+    /// would hand them out again. Returns `(segment, file length, end of the extents)` of each
+    /// segment whose file is short.
+    pub fn check_reserved_offsets(&self, user_id: &str, bucket: &str, referenced: &[(u64, u64)]) -> Result<Vec<(u32, u64, u64)>, Error> {
+        let mut needed: BTreeMap<u32, u64> = BTreeMap::new();
+        for &(address, size) in referenced {
+            let segment = segment::segment_of(address);
+            if segment != segment::LEGACY_SEGMENT && size > 0 {
+                let end = needed.entry(segment).or_insert(0);
+                *end = (*end).max(segment::offset_in_segment(address) + size);
+            }
+        }
+        let last = match needed.keys().next_back() {
+            Some(last) => *last,
+            None => return Ok(Vec::new()),
+        };
+        let state = self.bucket_files(user_id, bucket).map_err(io_error)?;
+        let _files = state.files.write().unwrap_or_else(|e| e.into_inner());
+        let mut short = Vec::new();
+        for (segment, end) in needed {
+            let path = self.get_segment_file_path(user_id, bucket, segment);
+            let len = match std::fs::metadata(&path) {
+                Ok(meta) => meta.len(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(io_error(e)),
+            };
+            if len < end {
+                short.push((segment, len, end));
+            }
+            self.segment_end(&state, user_id, bucket, segment).map_err(io_error)?.fetch_max(end, Ordering::AcqRel);
+        }
+        state.active.fetch_max(last, Ordering::AcqRel);
+        Ok(short)
     }
 }
 
 impl Storage for LocalXFSBinaryStore {
     fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
-        // Acquire global lock to synchronize concurrent writes
-        let _lock = STORAGE_WRITE_LOCK.lock().unwrap();
-        
-        // Write data to the bucket binary file and return real offset/size
-        let mut file = self.open_bucket_file_for_write(user_id, bucket)
-            .map_err(ErrorInternalServerError)?;
-        
-        let offset = file.seek(SeekFrom::End(0))
-            .map_err(ErrorInternalServerError)?;
-        
-        
-        file.write_all(data)
-            .map_err(ErrorInternalServerError)?;
-        
-        // Flush to ensure data is written
-        file.flush()
-            .map_err(ErrorInternalServerError)?;
-        
+        self.write_to(user_id, bucket, PlacementTarget::Active, data)
+    }
+
+    fn write_to(&self, user_id: &str, bucket: &str, target: PlacementTarget, data: &[u8]) -> Result<(u64, u64), Error> {
+        let state = self.bucket_files(user_id, bucket).map_err(io_error)?;
+        let _files = state.files.read().unwrap_or_else(|e| e.into_inner());
         let size = data.len() as u64;
-        
-        debug!("Wrote data for user {} bucket {} at offset {} with size {}", 
-              user_id, bucket, offset, size);
-        
-        // Lock is automatically released when _lock goes out of scope
-        Ok((offset, size))
+        let (chosen, offset) = self.reserve(&state, user_id, bucket, target, size).map_err(io_error)?;
+        let path = self.get_segment_file_path(user_id, bucket, chosen);
+        match Self::write_reserved(&path, offset, data) {
+            Ok(file) => Self::preallocate(&state, chosen, &file, &path, offset + size),
+            Err(e) => {
+                warn!("Write of {} bytes at {} of segment {} of user {} bucket {} failed: {}", size, offset, chosen, user_id, bucket, e);
+                Self::abandon(&path, offset, size);
+                return Err(io_error(e));
+            }
+        }
+
+        debug!("Wrote data for user {} bucket {} segment {} at offset {} with size {}",
+              user_id, bucket, chosen, offset, size);
+        Ok((segment::address(chosen, offset), size))
     }
     
     fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
-        // Read data from the bucket binary file at specific offset/size
-        let mut file = self.open_bucket_file_for_read(user_id, bucket)
-            .map_err(ErrorInternalServerError)?;
+        // Read data from the segment file holding `offset`
+        let mut file = self.open_segment_for_read(user_id, bucket, segment::segment_of(offset))
+            .map_err(io_error)?;
         
-        file.seek(SeekFrom::Start(offset))
-            .map_err(ErrorInternalServerError)?;
+        file.seek(SeekFrom::Start(segment::offset_in_segment(offset)))
+            .map_err(io_error)?;
         
         let mut buffer = vec![0u8; size as usize];
         file.read_exact(&mut buffer)
-            .map_err(ErrorInternalServerError)?;
+            .map_err(io_error)?;
         
         
         trace!("Read data for user {} bucket {} from offset {} with size {}", 
@@ -127,14 +514,36 @@ impl Storage for LocalXFSBinaryStore {
     }
     
     fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
-        // Queue deletion event in SQLite for background worker to process
-        use crate::metadata::sqlite_store::SQLiteMetadataStore;
-        let metadata_store = SQLiteMetadataStore::new();
-        // Key is not part of the low-level contract anymore; deletion is range-based
-        metadata_store.queue_deletion(user_id, bucket, "", offset_size_list)?;
-        
-        debug!("Queued deletion event for user {} bucket {} with {} chunks", 
-              user_id, bucket, offset_size_list.len());
+        // Called by the deletion worker once the ranges are unreferenced: give their blocks
+        // back to the filesystem. Files keep their length so later offsets stay valid.
+        let mut by_segment: HashMap<u32, Vec<(u64, u64)>> = HashMap::new();
+        for (offset, size) in offset_size_list.iter().copied().filter(|(_, size)| *size > 0) {
+            by_segment.entry(segment::segment_of(offset)).or_default()
+                .push((segment::offset_in_segment(offset), size));
+        }
+
+        let mut freed = 0u64;
+        for (n, ranges) in by_segment {
+            let path = self.get_segment_file_path(user_id, bucket, n);
+            let file = match OpenOptions::new().write(true).open(&path) {
+                Ok(file) => file,
+                // Segment already removed: nothing left to free
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(io_error(e)),
+            };
+            for (offset, size) in ranges {
+                match punch_hole(&file, offset, size) {
+                    Ok(()) => freed += size,
+                    Err(e) if e.kind() == io::ErrorKind::Unsupported || e.raw_os_error() == Some(EOPNOTSUPP) => {
+                        debug!("Hole punching not supported for {}; {} bytes stay allocated", path.display(), size);
+                    }
+                    Err(e) => return Err(io_error(e)),
+                }
+            }
+        }
+        metrics::inc_counter_by("warpdrive_storage_freed_bytes_total", &[], freed);
+        debug!("Freed {} bytes in {} ranges for user {} bucket {}",
+              freed, offset_size_list.len(), user_id, bucket);
         Ok(())
     }
 
@@ -147,6 +556,53 @@ impl Storage for LocalXFSBinaryStore {
         let calculated = hasher.finalize();
         Ok(calculated.as_slice() == checksum)
     }
+
+    fn backing_file(&self, user_id: &str, bucket: &str, offset: u64) -> Option<(PathBuf, u64)> {
+        let path = self.get_segment_file_path(user_id, bucket, segment::segment_of(offset));
+        Some((path, segment::offset_in_segment(offset)))
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        // Up to the last reservation of the active segment, written or still in flight
+        let state = self.bucket_files(user_id, bucket).map_err(io_error)?;
+        let active = state.active.load(Ordering::Acquire);
+        let end = self.segment_end(&state, user_id, bucket, active).map_err(io_error)?;
+        Ok(segment::address(active, end.load(Ordering::Acquire) & !SEALED))
+    }
+
+    fn segments(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        let mut out = Vec::new();
+        for n in self.list_segments(user_id, bucket).map_err(io_error)? {
+            match std::fs::metadata(self.get_segment_file_path(user_id, bucket, n)) {
+                Ok(meta) => out.push((segment::address(n, 0), meta.len())),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        Ok(out)
+    }
+
+    fn remove_segment(&self, user_id: &str, bucket: &str, segment: u32) -> Result<bool, Error> {
+        let state = self.bucket_files(user_id, bucket).map_err(io_error)?;
+        // Writes in flight finish first; any to this segment were reserved before it was sealed
+        let _files = state.files.write().unwrap_or_else(|e| e.into_inner());
+        // The active segment is still being appended to
+        if segment >= state.active.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+        let path = self.get_segment_file_path(user_id, bucket, segment);
+        let len = match std::fs::metadata(&path) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(io_error(e)),
+        };
+        std::fs::remove_file(&path).map_err(io_error)?;
+        state.ends.lock().unwrap_or_else(|e| e.into_inner()).remove(&segment);
+        state.allocated.lock().unwrap_or_else(|e| e.into_inner()).remove(&segment);
+        metrics::inc_counter("warpdrive_segments_removed_total", &[]);
+        debug!("Removed segment {} ({} bytes) of user {} bucket {}", segment, len, user_id, bucket);
+        Ok(true)
+    }
 }
 
 #[cfg(test)]
@@ -178,6 +634,74 @@ mod tests {
         store.delete(user_id, bucket, &[(offset, size)]).unwrap();
     }
     
+    #[test]
+    fn test_write_in_flight_blocks_no_other_write() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let store = Arc::new(LocalXFSBinaryStore::new());
+        // What a slow write holds while its bytes go to disk: the bucket's shared guard and
+        // its reserved range
+        let state = store.bucket_files("test_user_slow", "test_bucket").unwrap();
+        let _in_flight = state.files.read().unwrap();
+        let (segment, offset) = store.reserve(&state, "test_user_slow", "test_bucket", PlacementTarget::Active, 1000).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let writer = store.clone();
+        std::thread::spawn(move || {
+            let other_user = writer.write("test_user_fast", "test_bucket", b"other user").unwrap();
+            let same_bucket = writer.write("test_user_slow", "test_bucket", b"same bucket").unwrap();
+            tx.send((other_user, same_bucket)).unwrap();
+        });
+        let (other_user, same_bucket) = rx.recv_timeout(Duration::from_secs(10)).expect("writes waited for the write in flight");
+        assert_eq!(store.read("test_user_fast", "test_bucket", other_user.0, other_user.1).unwrap(), b"other user");
+        assert_eq!(same_bucket.0, segment::address(segment, offset + 1000));
+        assert_eq!(store.read("test_user_slow", "test_bucket", same_bucket.0, same_bucket.1).unwrap(), b"same bucket");
+    }
+
+    #[test]
+    fn test_writes_waiting_on_a_removal_do_not_overlap_later_ones() {
+        let store = LocalXFSBinaryStore::new();
+        let (user_id, bucket) = ("test_user_removal", "test_bucket");
+        store.write(user_id, bucket, &[1; 4096]).unwrap();
+        // Writes that looked the bucket up before the removal and waited for it to finish
+        let waiting: Vec<Arc<BucketFiles>> = (0..8).map(|_| store.bucket_files(user_id, bucket).unwrap()).collect();
+        assert!(store.remove_bucket_file(user_id, bucket).unwrap());
+
+        let store = &store;
+        let mut ranges: Vec<(u64, u64)> = std::thread::scope(|scope| {
+            let stale: Vec<_> = waiting.into_iter().map(|state| scope.spawn(move || {
+                let _files = state.files.read().unwrap();
+                let (segment, offset) = store.reserve(&state, user_id, bucket, PlacementTarget::Active, 1024).unwrap();
+                (segment::address(segment, offset), 1024)
+            })).collect();
+            let fresh: Vec<_> = (0..8).map(|_| scope.spawn(move || store.write(user_id, bucket, &[2; 1024]).unwrap())).collect();
+            stale.into_iter().chain(fresh).map(|writer| writer.join().unwrap()).collect()
+        });
+        ranges.sort_unstable();
+        for pair in ranges.windows(2) {
+            assert!(pair[0].0 + pair[0].1 <= pair[1].0, "ranges overlap: {:?} and {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_writes_start_past_the_extents_of_a_short_segment() {
+        let store = LocalXFSBinaryStore::new();
+        let (user_id, bucket) = ("test_user_short", "test_bucket");
+        let (offset, size) = store.write(user_id, bucket, &[3; 2048]).unwrap();
+        // The file lost its tail, as after a crash before the blocks reached disk
+        let path = store.get_segment_file_path(user_id, bucket, segment::segment_of(offset));
+        File::options().write(true).open(&path).unwrap().set_len(1024).unwrap();
+        BUCKET_FILES.lock().unwrap().remove(&store.state_key(user_id, bucket));
+
+        let short = store.check_reserved_offsets(user_id, bucket, &[(offset, size)]).unwrap();
+        assert_eq!(short, vec![(segment::segment_of(offset), 1024, segment::offset_in_segment(offset) + size)]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1024);
+        let (next, _) = store.write(user_id, bucket, b"after").unwrap();
+        assert_eq!(next, offset + size);
+        assert!(store.check_reserved_offsets(user_id, bucket, &[(offset, size)]).unwrap().is_empty());
+    }
+
     #[test]
     fn test_local_xfs_binary_store_error_cases() {
         let store = LocalXFSBinaryStore::new();
@@ -0,0 +1,423 @@
+//! Local storage backed up to a remote S3-compatible bucket
+//!
+//! `MirroredStore` writes every chunk to the local store first and answers as soon as that
+//! succeeds. A background thread then copies the chunk to the `RemoteS3Store` at the same
+//! offset, retrying with backoff, so the remote can serve any range the metadata points at.
+//! Freed ranges are deleted remotely through the same queue, after the copies queued before
+//! them. Reads go to the local store and fall back to the remote when the local read fails.
+//!
+//! Every queued operation is also recorded in the `mirror_pending` table until the remote has
+//! applied it; a copy keeps only its range. When a mirror starts, the operations recorded for
+//! its remote are queued ahead of new ones, in their original order: those still queued when
+//! the process stopped, and those given up after `MIRROR_MAX_ATTEMPTS` (counted as `failed`,
+//! kept with their attempts and last error). A replayed copy reads its range from the local
+//! store again; a range no longer readable there is skipped, as a delete recorded after it
+//! removes it remotely. So every write reaches the remote eventually, as long as the local
+//! copy survives until it does and the remote is reachable at some start; an operation the
+//! journal could not record (a failing database) is only mirrored by the running process.
+
+use actix_web::Error;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::env;
+use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metrics;
+use crate::storage::{Storage, instrumented_store::{self, IoOp, Traffic}, local_store::LocalXFSBinaryStore, placement::PlacementTarget};
+use crate::storage::config::{StorageBackend, StorageConfig};
+use crate::storage::remote_s3_store::RemoteS3Store;
+
+/// Longest wait between two attempts of an operation
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Mirror queue settings
+#[derive(Debug, Clone)]
+pub struct MirrorConfig {
+    /// Maximum number of operations waiting for the remote before writers block
+    pub queue_capacity: usize,
+    /// Attempts per operation before it is given up and counted as an error
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled after each failed attempt, up to 30 s
+    pub retry_backoff: Duration,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self { queue_capacity: 1024, max_attempts: 5, retry_backoff: Duration::from_millis(200) }
+    }
+}
+
+impl MirrorConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            queue_capacity: env::var("MIRROR_QUEUE_CAPACITY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.queue_capacity),
+            max_attempts: env::var("MIRROR_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&n: &u32| n > 0)
+                .unwrap_or(defaults.max_attempts),
+            retry_backoff: env::var("MIRROR_RETRY_BACKOFF_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.retry_backoff),
+        }
+    }
+}
+
+enum MirrorOp {
+    Put { offset: u64, data: Vec<u8> },
+    /// A copy replayed from the journal, read from the local store when it is applied
+    Recopy { offset: u64, size: u64 },
+    Delete { ranges: Vec<(u64, u64)> },
+}
+
+impl MirrorOp {
+    fn kind(&self) -> &'static str {
+        match self {
+            MirrorOp::Put { .. } | MirrorOp::Recopy { .. } => "put",
+            MirrorOp::Delete { .. } => "delete",
+        }
+    }
+
+    fn ranges(&self) -> Vec<(u64, u64)> {
+        match self {
+            MirrorOp::Put { offset, data } => vec![(*offset, data.len() as u64)],
+            MirrorOp::Recopy { offset, size } => vec![(*offset, *size)],
+            MirrorOp::Delete { ranges } => ranges.clone(),
+        }
+    }
+}
+
+struct MirrorJob {
+    user_id: String,
+    bucket: String,
+    op: MirrorOp,
+    /// Its row in `mirror_pending`, if it could be recorded
+    row: Option<i64>,
+}
+
+#[derive(Default)]
+struct MirrorState {
+    /// Enqueue time of every operation not handled yet, oldest first
+    queued: VecDeque<Instant>,
+    mirrored_writes: u64,
+    mirrored_bytes: u64,
+    retries: u64,
+    failed: u64,
+    last_error: Option<String>,
+}
+
+impl MirrorState {
+    /// How long the oldest pending operation has waited (0 when drained)
+    fn lag_seconds(&self) -> f64 {
+        self.queued.front().map(|t| t.elapsed().as_secs_f64()).unwrap_or(0.0)
+    }
+
+    fn publish(&self) {
+        metrics::set_gauge("warpdrive_mirror_queue_depth", &[], self.queued.len() as f64);
+        metrics::set_gauge("warpdrive_mirror_lag_seconds", &[], self.lag_seconds());
+    }
+}
+
+/// Snapshot of the mirror queue
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MirrorStatus {
+    pub queue_capacity: usize,
+    pub queue_depth: usize,
+    pub mirrored_writes: u64,
+    pub mirrored_bytes: u64,
+    pub retries: u64,
+    /// Operations given up after `max_attempts`; they stay in `mirror_pending` for the next
+    /// start
+    pub failed: u64,
+    /// How long the oldest pending operation has waited (0 when drained)
+    pub lag_seconds: f64,
+    pub last_error: Option<String>,
+}
+
+/// Bounded queue of operations for the remote plus the thread draining it
+pub struct Mirror {
+    config: MirrorConfig,
+    /// The remote's name in `mirror_pending`
+    remote: String,
+    sender: SyncSender<MirrorJob>,
+    state: Arc<Mutex<MirrorState>>,
+}
+
+impl Mirror {
+    /// Start the mirror thread copying to `remote`, first replaying the operations recorded
+    /// for it in `mirror_pending`.
+    pub fn start(remote: Arc<RemoteS3Store>, config: MirrorConfig) -> Arc<Self> {
+        let location = remote.location();
+        let recovered = recover(&location);
+        let (sender, receiver) = sync_channel(config.queue_capacity.max(1));
+        let mut state = MirrorState::default();
+        state.queued.extend(recovered.iter().map(|_| Instant::now()));
+        state.publish();
+        let state = Arc::new(Mutex::new(state));
+        let worker_state = Arc::clone(&state);
+        let worker_config = config.clone();
+        std::thread::Builder::new()
+            .name("s3-mirror".to_string())
+            .spawn(move || mirror_loop(&remote, &worker_config, recovered.into_iter().chain(receiver), &worker_state))
+            .expect("Failed to spawn S3 mirror thread");
+        info!("S3 mirror enabled: queue_capacity={} max_attempts={}", config.queue_capacity, config.max_attempts);
+        Arc::new(Self { config, remote: location, sender, state })
+    }
+
+    /// Record an operation and queue it. Blocks while the queue is full.
+    fn enqueue(&self, user_id: &str, bucket: &str, op: MirrorOp) {
+        let row = SQLiteMetadataStore::new().insert_mirror_pending(&self.remote, user_id, bucket, op.kind(), &op.ranges())
+            .map_err(|e| warn!("Cannot record S3 mirror {} for user={} bucket={}; it is lost if the process stops first: {}",
+                               op.kind(), user_id, bucket, e))
+            .ok();
+        {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.queued.push_back(Instant::now());
+            state.publish();
+        }
+        let job = MirrorJob { user_id: user_id.to_string(), bucket: bucket.to_string(), op, row };
+        if self.sender.send(job).is_err() {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.queued.pop_back();
+            state.last_error = Some("S3 mirror thread is not running".to_string());
+            error!("S3 mirror thread is gone; dropped an operation for user={} bucket={}", user_id, bucket);
+        }
+    }
+
+    /// Wait until every queued operation has been handled; false on timeout.
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.state.lock().unwrap_or_else(|e| e.into_inner()).queued.is_empty() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    pub fn status(&self) -> MirrorStatus {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        MirrorStatus {
+            queue_capacity: self.config.queue_capacity,
+            queue_depth: state.queued.len(),
+            mirrored_writes: state.mirrored_writes,
+            mirrored_bytes: state.mirrored_bytes,
+            retries: state.retries,
+            failed: state.failed,
+            lag_seconds: state.lag_seconds(),
+            last_error: state.last_error.clone(),
+        }
+    }
+}
+
+/// The operations recorded for `remote`, as jobs
+fn recover(remote: &str) -> Vec<MirrorJob> {
+    let rows = match SQLiteMetadataStore::new().list_mirror_pending(remote) {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Cannot read the S3 mirror journal of {}; its pending operations wait for the next start: {}", remote, e);
+            return Vec::new();
+        }
+    };
+    if !rows.is_empty() {
+        info!("Replaying {} S3 mirror operations recorded for {}", rows.len(), remote);
+    }
+    rows.into_iter().filter_map(|row| {
+        let op = match (row.kind.as_str(), row.ranges.as_slice()) {
+            ("put", &[(offset, size)]) => MirrorOp::Recopy { offset, size },
+            ("delete", _) => MirrorOp::Delete { ranges: row.ranges },
+            _ => {
+                warn!("Skipping S3 mirror journal entry {} of unknown kind {}", row.id, row.kind);
+                return None;
+            }
+        };
+        Some(MirrorJob { user_id: row.user_id, bucket: row.bucket, op, row: Some(row.id) })
+    }).collect()
+}
+
+/// Apply `job` to the remote; returns the bytes copied, None for a replayed copy skipped
+fn apply(remote: &RemoteS3Store, job: &MirrorJob) -> Result<Option<u64>, Error> {
+    match &job.op {
+        MirrorOp::Put { offset, data } => remote.put_chunk(&job.user_id, &job.bucket, *offset, data).map(|()| Some(data.len() as u64)),
+        MirrorOp::Recopy { offset, size } => match LocalXFSBinaryStore::new().read(&job.user_id, &job.bucket, *offset, *size) {
+            Ok(data) => remote.put_chunk(&job.user_id, &job.bucket, *offset, &data).map(|()| Some(*size)),
+            Err(e) => {
+                warn!("Skipping S3 mirror copy of user={} bucket={} offset={}: no longer readable locally ({})",
+                      job.user_id, job.bucket, offset, e);
+                Ok(None)
+            }
+        },
+        MirrorOp::Delete { ranges } => remote.delete_chunks(&job.user_id, &job.bucket, ranges).map(|()| Some(0)),
+    }
+}
+
+/// Update the journal row of a job the mirror is done with
+fn settle(job: &MirrorJob, result: &Result<Option<u64>, Error>) {
+    let Some(id) = job.row else { return };
+    let store = SQLiteMetadataStore::new();
+    let recorded = match result {
+        Ok(_) => store.delete_mirror_pending(id),
+        Err(e) => store.record_mirror_failure(id, &e.to_string()),
+    };
+    if let Err(e) = recorded {
+        warn!("Cannot update S3 mirror journal entry {}: {}", id, e);
+    }
+}
+
+fn mirror_loop(remote: &RemoteS3Store, config: &MirrorConfig, jobs: impl Iterator<Item = MirrorJob>, state: &Mutex<MirrorState>) {
+    for job in jobs {
+        let mut backoff = config.retry_backoff;
+        let mut result = apply(remote, &job);
+        for attempt in 2..=config.max_attempts {
+            let Err(e) = &result else { break };
+            warn!("S3 mirror attempt {} for user={} bucket={} failed: {}", attempt - 1, job.user_id, job.bucket, e);
+            {
+                let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+                state.retries += 1;
+                state.publish();
+            }
+            metrics::inc_counter("warpdrive_mirror_retries_total", &[]);
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+            result = apply(remote, &job);
+        }
+        settle(&job, &result);
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.queued.pop_front();
+        match (&job.op, result) {
+            (_, Ok(None)) => {}
+            (MirrorOp::Put { .. } | MirrorOp::Recopy { .. }, Ok(Some(bytes))) => {
+                state.mirrored_writes += 1;
+                state.mirrored_bytes += bytes;
+                instrumented_store::record(IoOp::Write, Traffic::Replication, &job.user_id, bytes);
+            }
+            (MirrorOp::Delete { ranges }, Ok(Some(_))) => {
+                instrumented_store::record(IoOp::Delete, Traffic::Replication, &job.user_id, ranges.iter().map(|(_, size)| size).sum());
+            }
+            (op, Err(e)) => {
+                let kind = op.kind();
+                state.failed += 1;
+                state.last_error = Some(format!("{} {}/{}: {}", kind, job.user_id, job.bucket, e));
+                metrics::inc_counter("warpdrive_mirror_errors_total", &[("kind", kind)]);
+                error!("S3 mirror gave up on {} for user={} bucket={} after {} attempts: {}",
+                       kind, job.user_id, job.bucket, config.max_attempts, e);
+            }
+        }
+        state.publish();
+    }
+}
+
+lazy_static! {
+    static ref MIRROR: Option<(Arc<RemoteS3Store>, Arc<Mirror>)> = match StorageConfig::from_env().backend {
+        StorageBackend::Mirrored => RemoteS3Store::from_env().map(|remote| {
+            if let Err(e) = remote.create_bucket() {
+                warn!("Cannot create the remote S3 bucket ({}); copies are retried until it exists", e);
+            }
+            let remote = Arc::new(remote);
+            let mirror = Mirror::start(Arc::clone(&remote), MirrorConfig::from_env());
+            (remote, mirror)
+        }),
+        _ => None,
+    };
+}
+
+/// Status of the process-wide mirror, if `STORAGE_BACKEND=mirrored` and a remote is configured.
+pub fn status() -> Option<MirrorStatus> {
+    MIRROR.as_ref().map(|(_, mirror)| mirror.status())
+}
+
+/// Storage that writes locally and mirrors each chunk to a remote S3-compatible bucket.
+pub struct MirroredStore {
+    local: LocalXFSBinaryStore,
+    remote: Arc<RemoteS3Store>,
+    mirror: Arc<Mirror>,
+}
+
+impl MirroredStore {
+    pub fn new(local: LocalXFSBinaryStore, remote: Arc<RemoteS3Store>, mirror: Arc<Mirror>) -> Self {
+        Self { local, remote, mirror }
+    }
+
+    /// Mirrored store on the process-wide mirror, or `None` when no remote is configured.
+    pub fn from_env() -> Option<Self> {
+        match MIRROR.as_ref() {
+            Some((remote, mirror)) => Some(Self::new(LocalXFSBinaryStore::new(), Arc::clone(remote), Arc::clone(mirror))),
+            None => {
+                warn!("STORAGE_BACKEND=mirrored but REMOTE_S3_ENDPOINT or REMOTE_S3_BUCKET is not set");
+                None
+            }
+        }
+    }
+}
+
+impl Storage for MirroredStore {
+    fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        let (offset, size) = self.local.write(user_id, bucket, data)?;
+        self.mirror.enqueue(user_id, bucket, MirrorOp::Put { offset, data: data.to_vec() });
+        Ok((offset, size))
+    }
+
+    fn write_to(&self, user_id: &str, bucket: &str, target: PlacementTarget, data: &[u8]) -> Result<(u64, u64), Error> {
+        let (offset, size) = self.local.write_to(user_id, bucket, target, data)?;
+        self.mirror.enqueue(user_id, bucket, MirrorOp::Put { offset, data: data.to_vec() });
+        Ok((offset, size))
+    }
+
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        match self.local.read(user_id, bucket, offset, size) {
+            Ok(data) => Ok(data),
+            Err(local_error) => {
+                warn!("Local read of user={} bucket={} offset={} size={} failed ({}); reading the remote copy",
+                      user_id, bucket, offset, size, local_error);
+                metrics::inc_counter("warpdrive_mirror_fallback_reads_total", &[]);
+                self.remote.read(user_id, bucket, offset, size).map_err(|remote_error| {
+                    error!("Remote read of user={} bucket={} offset={} failed too: {}", user_id, bucket, offset, remote_error);
+                    local_error
+                })
+            }
+        }
+    }
+
+    fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        self.local.delete(user_id, bucket, offset_size_list)?;
+        self.mirror.enqueue(user_id, bucket, MirrorOp::Delete { ranges: offset_size_list.to_vec() });
+        Ok(())
+    }
+
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        self.local.verify(user_id, bucket, offset, size, checksum)
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.local.bucket_len(user_id, bucket)
+    }
+
+    fn backing_file(&self, user_id: &str, bucket: &str, offset: u64) -> Option<(PathBuf, u64)> {
+        // Without the local file, readers must go through `read` to reach the remote
+        self.local.backing_file(user_id, bucket, offset).filter(|(path, _)| path.exists())
+    }
+
+    fn segments(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.local.segments(user_id, bucket)
+    }
+
+    fn remove_segment(&self, user_id: &str, bucket: &str, segment: u32) -> Result<bool, Error> {
+        // Its chunks were freed, and so deleted remotely, before the segment could be removed
+        self.local.remove_segment(user_id, bucket, segment)
+    }
+}
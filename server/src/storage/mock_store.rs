@@ -5,12 +5,37 @@ use actix_web::Error;
 use actix_web::error::ErrorNotFound;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use lazy_static::lazy_static;
 use log::info;
 
+/// In-memory storage: user_id -> bucket -> offset -> data
+type UserBucketData = HashMap<String, HashMap<String, HashMap<u64, Vec<u8>>>>;
+
+/// Backend calls served by mock stores for one user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MockCalls {
+    pub writes: u64,
+    pub reads: u64,
+}
+
+lazy_static! {
+    static ref CALLS: Mutex<HashMap<String, MockCalls>> = Mutex::new(HashMap::new());
+}
+
+/// Writes and reads every mock store of the process has served for `user_id`. The configured
+/// backend is built afresh for each request, so tests count calls here rather than on one
+/// instance.
+pub fn calls(user_id: &str) -> MockCalls {
+    CALLS.lock().unwrap().get(user_id).copied().unwrap_or_default()
+}
+
+fn count(user_id: &str, record: impl FnOnce(&mut MockCalls)) {
+    record(CALLS.lock().unwrap().entry(user_id.to_string()).or_default());
+}
+
 /// Mock implementation of Storage for testing
 pub struct MockBinaryStore {
-    // In-memory storage: user_id -> bucket -> offset -> data
-    data: Arc<Mutex<HashMap<String, HashMap<String, HashMap<u64, Vec<u8>>>>>>,
+    data: Arc<Mutex<UserBucketData>>,
 }
 
 impl MockBinaryStore {
@@ -61,18 +86,26 @@ impl Default for MockBinaryStore {
 
 impl Storage for MockBinaryStore {
     fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        count(user_id, |calls| calls.writes += 1);
         let mut store = self.data.lock().unwrap();
-        let user_entry = store.entry(user_id.to_string()).or_insert_with(HashMap::new);
-        let bucket_entry = user_entry.entry(bucket.to_string()).or_insert_with(HashMap::new);
-        let next_offset = bucket_entry.keys().copied().max().unwrap_or(0)
-            + bucket_entry.get(&bucket_entry.keys().copied().max().unwrap_or(0)).map(|v| v.len() as u64).unwrap_or(0);
+        let user_entry = store.entry(user_id.to_string()).or_default();
+        let bucket_entry = user_entry.entry(bucket.to_string()).or_default();
+        let next_offset = bucket_entry.iter().map(|(offset, data)| offset + data.len() as u64).max().unwrap_or(0);
         let size = data.len() as u64;
-        bucket_entry.insert(next_offset, data.to_vec());
+        // Like a bucket file, an empty chunk takes no space: the next write lands at the same
+        // offset, and reading zero bytes anywhere succeeds
+        if size > 0 {
+            bucket_entry.insert(next_offset, data.to_vec());
+        }
         info!("Mock: Wrote data for user {} bucket {} at offset {} size {}", user_id, bucket, next_offset, size);
         Ok((next_offset, size))
     }
 
     fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        count(user_id, |calls| calls.reads += 1);
+        if size == 0 {
+            return Ok(Vec::new());
+        }
         let store = self.data.lock().unwrap();
         if let Some(user_entry) = store.get(user_id) {
             if let Some(bucket_entry) = user_entry.get(bucket) {
@@ -117,6 +150,14 @@ impl Storage for MockBinaryStore {
         let calculated = hasher.finalize();
         Ok(calculated.as_slice() == checksum)
     }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        let store = self.data.lock().unwrap();
+        Ok(store.get(user_id)
+            .and_then(|buckets| buckets.get(bucket))
+            .and_then(|entries| entries.iter().map(|(offset, data)| offset + data.len() as u64).max())
+            .unwrap_or(0))
+    }
 }
 
 #[cfg(test)]
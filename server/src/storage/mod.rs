@@ -5,20 +5,68 @@
 pub mod local_store;
 pub mod mock_store;
 pub mod config;
+pub mod bucket_lock;
+pub mod replicated_store;
+pub mod read_only_store;
+pub mod tiered_store;
+pub mod remote_s3_store;
+pub mod mirrored_store;
+pub mod encrypted_store;
+pub mod instrumented_store;
+pub mod durability;
+pub mod segment;
+pub mod placement;
+pub mod preallocate;
+pub mod layout;
+pub mod error;
 
 use actix_web::Error;
+use std::path::PathBuf;
+
+use placement::PlacementTarget;
 
 /// Trait defining the minimal binary storage interface
 pub trait Storage: Send + Sync {
     /// Write `data` for a `user_id` and `bucket`, returning (offset, size)
     fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error>;
 
+    /// Write `data` to `target`, returning (offset, size) like `write`. Backends that cannot
+    /// honour a target append as `write` does; the offset records where the chunk went.
+    fn write_to(&self, user_id: &str, bucket: &str, _target: PlacementTarget, data: &[u8]) -> Result<(u64, u64), Error> {
+        self.write(user_id, bucket, data)
+    }
+
     /// Read `size` bytes from `offset` for a `user_id` and `bucket`
     fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error>;
 
-    /// Delete previously written ranges by queuing/logging deletion for background processing
+    /// Free ranges no object references any more. Only the deletion worker calls this, after
+    /// taking the ranges off the deletion queue; it must not queue anything itself.
     fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error>;
 
     /// Verify data integrity for the specified range
     fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error>;
+
+    /// End of everything ever appended for a `user_id` and `bucket` (0 if nothing was
+    /// written); the next write lands at or beyond it
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error>;
+
+    /// Plain file holding the byte at `offset` and the position of that byte in it, if the
+    /// backend has one. Lets readers stream a range straight from disk instead of going
+    /// through `read`.
+    fn backing_file(&self, _user_id: &str, _bucket: &str, _offset: u64) -> Option<(PathBuf, u64)> {
+        None
+    }
+
+    /// `(start offset, length)` of each file holding the bucket's bytes, oldest first; the
+    /// last one is still appended to. Backends without segments report a single span.
+    fn segments(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        let len = self.bucket_len(user_id, bucket)?;
+        Ok(if len > 0 { vec![(0, len)] } else { Vec::new() })
+    }
+
+    /// Unlink a sealed segment nothing references any more, returning whether a file was
+    /// removed. The active segment is never removed.
+    fn remove_segment(&self, _user_id: &str, _bucket: &str, _segment: u32) -> Result<bool, Error> {
+        Ok(false)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,115 @@
+//! Chunk placement
+//!
+//! A [`PlacementPolicy`] decides where each new chunk goes: today which segment file of the
+//! bucket, later which node. The decision only steers the write. Where the chunk actually
+//! landed is recorded in its address (see `storage::segment`), so reads never consult a
+//! policy and chunks written under one policy keep reading under another.
+//!
+//! `PLACEMENT_POLICY` selects the policy at startup: `single-file` (default) appends to the
+//! active segment as the store always has; `round-robin` spreads chunks over the first
+//! `PLACEMENT_SEGMENTS` (default 2) segments of each bucket. Round-robin segments are all
+//! appended to, so none of them is ever sealed by size.
+
+use lazy_static::lazy_static;
+use log::warn;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+
+use crate::service::user_context::UserContext;
+
+/// Segments `round-robin` spreads over when `PLACEMENT_SEGMENTS` is unset
+pub const DEFAULT_ROUND_ROBIN_SEGMENTS: u32 = 2;
+
+/// Where a chunk is to be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlacementTarget {
+    /// Wherever the backend appends next
+    Active,
+    /// A given segment of the bucket
+    Segment(u32),
+}
+
+/// Picks the target of each chunk written for a user's bucket
+pub trait PlacementPolicy: Send + Sync {
+    fn place(&self, ctx: &UserContext, size: u64) -> PlacementTarget;
+}
+
+/// Every chunk goes to the bucket's active segment
+#[derive(Debug, Default)]
+pub struct SingleFilePolicy;
+
+impl PlacementPolicy for SingleFilePolicy {
+    fn place(&self, _ctx: &UserContext, _size: u64) -> PlacementTarget {
+        PlacementTarget::Active
+    }
+}
+
+/// Chunks of a bucket cycle through its segments `0..segments`
+#[derive(Debug)]
+pub struct RoundRobinSegments {
+    segments: u32,
+    // (user, bucket) -> segment the next chunk goes to
+    next: Mutex<HashMap<(String, String), u32>>,
+}
+
+impl RoundRobinSegments {
+    pub fn new(segments: u32) -> Self {
+        Self { segments: segments.max(1), next: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl PlacementPolicy for RoundRobinSegments {
+    fn place(&self, ctx: &UserContext, _size: u64) -> PlacementTarget {
+        let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+        let slot = next.entry((ctx.user_id.clone(), ctx.bucket.clone())).or_insert(0);
+        let segment = *slot;
+        *slot = (segment + 1) % self.segments;
+        PlacementTarget::Segment(segment)
+    }
+}
+
+/// The policy named by `PLACEMENT_POLICY`
+pub fn from_env() -> Arc<dyn PlacementPolicy> {
+    match env::var("PLACEMENT_POLICY").unwrap_or_default().trim().to_lowercase().as_str() {
+        "" | "single-file" | "single" => Arc::new(SingleFilePolicy),
+        "round-robin" | "round_robin" => {
+            let segments = env::var("PLACEMENT_SEGMENTS")
+                .ok()
+                .and_then(|v| v.trim().parse::<u32>().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(DEFAULT_ROUND_ROBIN_SEGMENTS);
+            Arc::new(RoundRobinSegments::new(segments))
+        }
+        other => {
+            warn!("Unknown PLACEMENT_POLICY {:?}; using single-file", other);
+            Arc::new(SingleFilePolicy)
+        }
+    }
+}
+
+lazy_static! {
+    // Built once so round-robin keeps its position across requests
+    static ref CONFIGURED: Arc<dyn PlacementPolicy> = from_env();
+}
+
+/// The policy the server was started with
+pub fn configured() -> Arc<dyn PlacementPolicy> {
+    CONFIGURED.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_robin_cycles_per_bucket() {
+        let policy = RoundRobinSegments::new(3);
+        let a = UserContext::with_bucket("placement_user".to_string(), "a".to_string());
+        let b = UserContext::with_bucket("placement_user".to_string(), "b".to_string());
+        let placed: Vec<_> = (0..4).map(|_| policy.place(&a, 10)).collect();
+        assert_eq!(placed, [0, 1, 2, 0].map(PlacementTarget::Segment));
+        assert_eq!(policy.place(&b, 10), PlacementTarget::Segment(0));
+        assert_eq!(SingleFilePolicy.place(&a, 10), PlacementTarget::Active);
+    }
+}
@@ -0,0 +1,149 @@
+//! Preallocation of segment files ahead of writes
+//!
+//! Growing a segment file a chunk at a time leaves XFS to place each write's blocks on its
+//! own, and old buckets end up in many small extents that read slower than fresh ones. With
+//! `PREALLOCATE_BYTES` set (`1GB`, `256MB`, or plain bytes; units are powers of 1024), the
+//! local store reserves blocks past the end of each write with `fallocate` in keep-size mode
+//! whenever less than `PREALLOCATE_THRESHOLD_BYTES` (default a quarter of it) is left
+//! reserved ahead. The file's length does not change and nothing is recorded in metadata; it
+//! is only a hint to the filesystem. Reservations stop at `SEGMENT_MAX_BYTES`, so a sealed
+//! segment keeps at most one chunk's worth of blocks it never uses. Where `fallocate` is
+//! missing or unsupported by the filesystem nothing is reserved.
+//!
+//! [`extent_count`] reports how many extents a file occupies (FIEMAP, Linux only), which
+//! `GET /admin/stats` sums per bucket as a fragmentation estimate.
+
+use log::{debug, warn};
+use std::env;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// How far ahead of writes segment files are allocated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreallocateConfig {
+    /// Bytes reserved past a write; 0 turns preallocation off
+    pub bytes: u64,
+    /// Reserve again once less than this is left ahead of a write
+    pub threshold: u64,
+}
+
+impl PreallocateConfig {
+    /// Read `PREALLOCATE_BYTES` (default 0, off) and `PREALLOCATE_THRESHOLD_BYTES` (default a
+    /// quarter of it).
+    pub fn from_env() -> Self {
+        let size = |name: &str| env::var(name).ok().and_then(|v| {
+            let parsed = parse_size(&v);
+            if parsed.is_none() {
+                warn!("Ignoring {}={:?}: not a size such as 1GB, 256MB or a number of bytes", name, v);
+            }
+            parsed
+        });
+        let bytes = size("PREALLOCATE_BYTES").unwrap_or(0);
+        let threshold = size("PREALLOCATE_THRESHOLD_BYTES").unwrap_or(bytes / 4).min(bytes);
+        Self { bytes, threshold }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.bytes > 0
+    }
+}
+
+/// `1GB`, `64 KiB`, `512m` or `1048576` in bytes; units are powers of 1024
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let shift = match unit.trim().to_ascii_lowercase().trim_end_matches('b').trim_end_matches('i') {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        "t" => 40,
+        _ => return None,
+    };
+    number.checked_mul(1 << shift)
+}
+
+/// Allocate `len` bytes of `file` at `offset` without changing its length
+#[cfg(target_os = "linux")]
+pub fn allocate(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: fallocate only acts on the open descriptor; no memory is shared with the kernel.
+    let rc = unsafe {
+        libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, offset as libc::off_t, len as libc::off_t)
+    };
+    if rc == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allocate(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// [`allocate`], logging instead of failing: a write never depends on it
+pub fn allocate_ahead(file: &File, path: &Path, offset: u64, len: u64) -> bool {
+    match allocate(file, offset, len) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("Could not preallocate {} bytes at {} of {}: {}", len, offset, path.display(), e);
+            false
+        }
+    }
+}
+
+/// Extents the data of the file at `path` occupies, None where the platform or filesystem
+/// cannot tell
+#[cfg(target_os = "linux")]
+pub fn extent_count(path: &Path) -> io::Result<Option<u64>> {
+    use std::os::unix::io::AsRawFd;
+
+    // struct fiemap of linux/fiemap.h with no room for extents: the kernel only counts them
+    #[repr(C)]
+    #[derive(Default)]
+    struct Fiemap {
+        fm_start: u64,
+        fm_length: u64,
+        fm_flags: u32,
+        fm_mapped_extents: u32,
+        fm_extent_count: u32,
+        fm_reserved: u32,
+    }
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xC020_660B;
+    const FIEMAP_FLAG_SYNC: u32 = 1;
+
+    let file = File::open(path)?;
+    let mut map = Fiemap { fm_length: u64::MAX, fm_flags: FIEMAP_FLAG_SYNC, ..Default::default() };
+    // SAFETY: `map` is a valid fiemap header with fm_extent_count 0, so the kernel writes
+    // nothing past it.
+    let rc = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut map as *mut Fiemap) };
+    if rc == 0 {
+        return Ok(Some(u64::from(map.fm_mapped_extents)));
+    }
+    match io::Error::last_os_error() {
+        e if matches!(e.raw_os_error(), Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY) | Some(libc::EINVAL)) => Ok(None),
+        e => Err(e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn extent_count(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size_units() {
+        assert_eq!(parse_size("1048576"), Some(1 << 20));
+        assert_eq!(parse_size("1GB"), Some(1 << 30));
+        assert_eq!(parse_size(" 256 MiB "), Some(256 << 20));
+        assert_eq!(parse_size("64k"), Some(64 << 10));
+        for bad in ["", "GB", "1.5GB", "12 bytes", "-1"] {
+            assert_eq!(parse_size(bad), None, "{}", bad);
+        }
+    }
+}
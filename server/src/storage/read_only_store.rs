@@ -0,0 +1,78 @@
+//! Read-only view of another backend
+//!
+//! A replica wraps its backend in `ReadOnlyStore` so that a write which gets past the request
+//! gate fails instead of appending to, or freeing ranges in, the snapshot it serves.
+
+use crate::storage::Storage;
+use actix_web::Error;
+use actix_web::error::ErrorNotImplemented;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub struct ReadOnlyStore {
+    inner: Arc<dyn Storage>,
+}
+
+impl ReadOnlyStore {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        Self { inner }
+    }
+}
+
+fn refused(op: &str, user_id: &str, bucket: &str) -> Error {
+    log::error!("Refused storage {} on a read-only replica (user: {}, bucket: {})", op, user_id, bucket);
+    ErrorNotImplemented("Storage is read-only on a replica")
+}
+
+impl Storage for ReadOnlyStore {
+    fn write(&self, user_id: &str, bucket: &str, _data: &[u8]) -> Result<(u64, u64), Error> {
+        Err(refused("write", user_id, bucket))
+    }
+
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        self.inner.read(user_id, bucket, offset, size)
+    }
+
+    fn delete(&self, user_id: &str, bucket: &str, _offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        Err(refused("delete", user_id, bucket))
+    }
+
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        self.inner.verify(user_id, bucket, offset, size, checksum)
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.inner.bucket_len(user_id, bucket)
+    }
+
+    fn backing_file(&self, user_id: &str, bucket: &str, offset: u64) -> Option<(PathBuf, u64)> {
+        self.inner.backing_file(user_id, bucket, offset)
+    }
+
+    fn segments(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.inner.segments(user_id, bucket)
+    }
+
+    fn remove_segment(&self, user_id: &str, bucket: &str, _segment: u32) -> Result<bool, Error> {
+        Err(refused("remove_segment", user_id, bucket))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::mock_store::MockBinaryStore;
+
+    #[test]
+    fn test_reads_pass_through_and_writes_are_refused() {
+        let inner = Arc::new(MockBinaryStore::new());
+        let (offset, size) = inner.write("u", "b", b"snapshot").unwrap();
+        let store = ReadOnlyStore::new(inner);
+
+        assert_eq!(store.read("u", "b", offset, size).unwrap(), b"snapshot");
+        assert!(store.write("u", "b", b"more").is_err());
+        assert!(store.delete("u", "b", &[(offset, size)]).is_err());
+        assert!(store.remove_segment("u", "b", 0).is_err());
+        assert_eq!(store.read("u", "b", offset, size).unwrap(), b"snapshot");
+    }
+}
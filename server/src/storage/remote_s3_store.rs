@@ -0,0 +1,450 @@
+//! Chunks kept as objects in an S3-compatible bucket
+//!
+//! `RemoteS3Store` stores every chunk as one object named `{user}/{bucket}/{offset}-{size}-{uuid}`
+//! in `REMOTE_S3_BUCKET` at `REMOTE_S3_ENDPOINT`, signing requests with SigV4. The offsets are
+//! synthetic: a chunk is placed at the end of what the bucket already holds, just as the local
+//! store appends. Because the key carries the range, the index of a bucket's chunks is rebuilt
+//! by listing its prefix, so nothing besides the remote itself needs to survive a restart.
+//!
+//! The `Storage` trait is synchronous; requests run on a small runtime of their own and the
+//! caller blocks until they finish.
+
+use actix_web::Error;
+use lazy_static::lazy_static;
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::future::Future;
+use std::io;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use crate::storage::Storage;
+use crate::storage::error::io_error;
+
+/// Chunks of one user's bucket by offset: `offset -> (size, object key)`
+type ChunkIndex = BTreeMap<u64, (u64, String)>;
+
+lazy_static! {
+    /// Runs the HTTP requests of every remote store; never dropped, so it can be shared with
+    /// code running inside another runtime
+    static ref RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("remote-s3")
+        .enable_all()
+        .build()
+        .expect("Failed to start the remote S3 runtime");
+}
+
+/// Where the remote bucket is and how to sign for it; `None` unless `REMOTE_S3_ENDPOINT` and
+/// `REMOTE_S3_BUCKET` are set.
+#[derive(Debug, Clone)]
+pub struct RemoteS3Config {
+    /// Base URL objects are addressed under path-style, e.g. `https://s3.example.com`
+    pub endpoint: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub region: String,
+    /// Limit on each request, including the body
+    pub timeout: Duration,
+}
+
+impl RemoteS3Config {
+    pub fn from_env() -> Option<Self> {
+        let endpoint = env::var("REMOTE_S3_ENDPOINT").ok().filter(|s| !s.is_empty())?;
+        let bucket = env::var("REMOTE_S3_BUCKET").ok().filter(|s| !s.is_empty())?;
+        let timeout_secs = env::var("REMOTE_S3_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        Some(Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            bucket,
+            access_key: env::var("REMOTE_S3_ACCESS_KEY").unwrap_or_default(),
+            secret_key: env::var("REMOTE_S3_SECRET_KEY").unwrap_or_default(),
+            region: env::var("REMOTE_S3_REGION").ok().filter(|s| !s.is_empty()).unwrap_or_else(|| "us-east-1".to_string()),
+            timeout: Duration::from_secs(timeout_secs),
+        })
+    }
+}
+
+/// Run `future` on the remote runtime and wait for it
+fn block_on<F>(future: F) -> F::Output
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    futures::executor::block_on(RUNTIME.spawn(future)).expect("remote S3 request panicked")
+}
+
+fn remote_error(kind: io::ErrorKind, message: String) -> Error {
+    io_error(io::Error::new(kind, message))
+}
+
+fn request_error(e: reqwest::Error) -> Error {
+    let kind = if e.is_timeout() { io::ErrorKind::TimedOut } else { io::ErrorKind::Other };
+    remote_error(kind, format!("remote S3: {}", e))
+}
+
+/// Percent-encode everything but the unreserved characters (and `/` when `keep_slash`)
+fn uri_encode(s: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if keep_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Random UUID (version 4) naming a chunk object
+fn chunk_uuid() -> String {
+    let mut b = [0u8; 16];
+    getrandom::getrandom(&mut b).expect("OS random number generator unavailable");
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let h = hex::encode(b);
+    format!("{}-{}-{}-{}-{}", &h[..8], &h[8..12], &h[12..16], &h[16..20], &h[20..])
+}
+
+/// `(offset, size)` encoded in the last segment of a chunk key
+fn parse_chunk_name(name: &str) -> Option<(u64, u64)> {
+    let mut parts = name.splitn(3, '-');
+    let offset = parts.next()?.parse().ok()?;
+    let size = parts.next()?.parse().ok()?;
+    parts.next()?;
+    Some((offset, size))
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/// Text of every `<tag>` element in `xml`
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else { break };
+        values.push(xml_unescape(&rest[..end]));
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+/// Storage backed by objects in a remote S3-compatible bucket
+pub struct RemoteS3Store {
+    config: RemoteS3Config,
+    client: reqwest::Client,
+    /// Chunk index of each `(user, bucket)` listed so far
+    chunks: RwLock<HashMap<(String, String), ChunkIndex>>,
+    /// Serializes choosing the offset of a new chunk
+    write_lock: Mutex<()>,
+}
+
+impl RemoteS3Store {
+    pub fn new(config: RemoteS3Config) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .expect("failed to create reqwest client");
+        info!("Remote S3 store: endpoint={} bucket={}", config.endpoint, config.bucket);
+        Self { config, client, chunks: RwLock::new(HashMap::new()), write_lock: Mutex::new(()) }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        RemoteS3Config::from_env().map(Self::new)
+    }
+
+    /// Endpoint and bucket, naming this remote in the mirror journal
+    pub fn location(&self) -> String {
+        format!("{}/{}", self.config.endpoint, self.config.bucket)
+    }
+
+    fn prefix(user_id: &str, bucket: &str) -> String {
+        format!("{}/{}/", user_id, bucket)
+    }
+
+    /// Signed request for `key` in the remote bucket (the bucket itself when `key` is empty)
+    fn request(&self, method: reqwest::Method, key: &str, query: &[(&str, String)], body: Vec<u8>) -> Result<reqwest::RequestBuilder, Error> {
+        let path = format!("{}/{}/{}", self.path_prefix(), uri_encode(&self.config.bucket, false), uri_encode(key, true));
+        let mut pairs: Vec<(String, String)> = query.iter().map(|(k, v)| (uri_encode(k, false), uri_encode(v, false))).collect();
+        pairs.sort();
+        let query_string = pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+        let mut url = format!("{}{}", self.origin(), path);
+        if !query_string.is_empty() {
+            url = format!("{}?{}", url, query_string);
+        }
+        let parsed = reqwest::Url::parse(&url).map_err(|e| remote_error(io::ErrorKind::InvalidInput, format!("remote S3 URL {}: {}", url, e)))?;
+        let host = match parsed.port() {
+            Some(port) => format!("{}:{}", parsed.host_str().unwrap_or_default(), port),
+            None => parsed.host_str().unwrap_or_default().to_string(),
+        };
+
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query_string, host, payload_hash, amz_date, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut signing_key = hmac(format!("AWS4{}", self.config.secret_key).as_bytes(), date);
+        for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part);
+        }
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key, scope, signed_headers, hex::encode(hmac(&signing_key, &string_to_sign))
+        );
+
+        Ok(self.client.request(method, parsed)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization)
+            .body(body))
+    }
+
+    /// Scheme and authority of the endpoint
+    fn origin(&self) -> &str {
+        let endpoint = &self.config.endpoint;
+        let authority_start = endpoint.find("://").map(|i| i + 3).unwrap_or(0);
+        match endpoint[authority_start..].find('/') {
+            Some(i) => &endpoint[..authority_start + i],
+            None => endpoint,
+        }
+    }
+
+    /// Path of the endpoint objects are addressed under (empty for most services)
+    fn path_prefix(&self) -> &str {
+        &self.config.endpoint[self.origin().len()..]
+    }
+
+    /// Send a request, failing on any status but 2xx
+    fn send(&self, request: reqwest::RequestBuilder, what: String) -> Result<(reqwest::StatusCode, Vec<u8>), Error> {
+        let (status, body) = block_on(async move {
+            let response = request.send().await?;
+            let status = response.status();
+            let body = response.bytes().await?;
+            Ok::<_, reqwest::Error>((status, body.to_vec()))
+        }).map_err(request_error)?;
+        if !status.is_success() {
+            let kind = match status {
+                reqwest::StatusCode::NOT_FOUND => io::ErrorKind::NotFound,
+                reqwest::StatusCode::REQUEST_TIMEOUT | reqwest::StatusCode::TOO_MANY_REQUESTS => io::ErrorKind::TimedOut,
+                s if s.is_server_error() => io::ErrorKind::TimedOut,
+                _ => io::ErrorKind::Other,
+            };
+            return Err(remote_error(kind, format!("remote S3 {}: HTTP {}", what, status)));
+        }
+        Ok((status, body))
+    }
+
+    /// Create the remote bucket; one that already exists is left as it is.
+    pub fn create_bucket(&self) -> Result<(), Error> {
+        let request = self.request(reqwest::Method::PUT, "", &[], Vec::new())?;
+        match self.send(request, format!("create bucket {}", self.config.bucket)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.to_string().contains("HTTP 409") => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// List every chunk object of a user's bucket
+    fn list_chunks(&self, user_id: &str, bucket: &str) -> Result<ChunkIndex, Error> {
+        let prefix = Self::prefix(user_id, bucket);
+        let mut index = ChunkIndex::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2".to_string()), ("prefix", prefix.clone())];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.clone()));
+            }
+            let request = self.request(reqwest::Method::GET, "", &query, Vec::new())?;
+            let (_, body) = self.send(request, format!("list {}", prefix))?;
+            let xml = String::from_utf8_lossy(&body);
+            for key in xml_values(&xml, "Key") {
+                if let Some((offset, size)) = key.strip_prefix(&prefix).and_then(parse_chunk_name) {
+                    index.insert(offset, (size, key));
+                }
+            }
+            token = xml_values(&xml, "NextContinuationToken").into_iter().next();
+            if xml_values(&xml, "IsTruncated").first().map(String::as_str) != Some("true") || token.is_none() {
+                break;
+            }
+        }
+        debug!("Remote S3 index of {}: {} chunks", prefix, index.len());
+        Ok(index)
+    }
+
+    /// Run `f` on the bucket's chunk index, listing the remote first if it was not loaded yet
+    fn with_index<T>(&self, user_id: &str, bucket: &str, f: impl FnOnce(&mut ChunkIndex) -> T) -> Result<T, Error> {
+        let id = (user_id.to_string(), bucket.to_string());
+        if let Some(index) = self.chunks.write().unwrap_or_else(|e| e.into_inner()).get_mut(&id) {
+            return Ok(f(index));
+        }
+        let listed = self.list_chunks(user_id, bucket)?;
+        let mut chunks = self.chunks.write().unwrap_or_else(|e| e.into_inner());
+        Ok(f(chunks.entry(id).or_insert(listed)))
+    }
+
+    /// Forget the bucket's index so the next access lists the remote again
+    fn invalidate(&self, user_id: &str, bucket: &str) {
+        self.chunks.write().unwrap_or_else(|e| e.into_inner()).remove(&(user_id.to_string(), bucket.to_string()));
+    }
+
+    /// Store `data` as the chunk at `offset` of a user's bucket. The mirror uses it to keep the
+    /// offsets the local store chose.
+    pub fn put_chunk(&self, user_id: &str, bucket: &str, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let key = format!("{}{}-{}-{}", Self::prefix(user_id, bucket), offset, data.len(), chunk_uuid());
+        let request = self.request(reqwest::Method::PUT, &key, &[], data.to_vec())?;
+        self.send(request, format!("put {}", key))?;
+        let size = data.len() as u64;
+        self.with_index(user_id, bucket, |index| {
+            index.insert(offset, (size, key));
+        })
+    }
+
+    /// Bytes `[start, start + len)` of the chunk object `key`
+    fn get_range(&self, key: &str, start: u64, len: u64, chunk_size: u64) -> Result<Vec<u8>, Error> {
+        let request = self.request(reqwest::Method::GET, key, &[], Vec::new())?;
+        let request = if start == 0 && len == chunk_size {
+            request
+        } else {
+            request.header("range", format!("bytes={}-{}", start, start + len - 1))
+        };
+        let (status, body) = self.send(request, format!("get {}", key))?;
+        // A service ignoring Range sends the whole chunk
+        let data = if status == reqwest::StatusCode::PARTIAL_CONTENT {
+            body
+        } else {
+            body.get(start as usize..(start + len) as usize).map(<[u8]>::to_vec).unwrap_or_default()
+        };
+        if data.len() as u64 != len {
+            return Err(remote_error(io::ErrorKind::UnexpectedEof,
+                format!("remote S3 get {}: expected {} bytes, got {}", key, len, data.len())));
+        }
+        Ok(data)
+    }
+
+    /// Chunks covering `[offset, offset + size)` as `(key, start in chunk, length, chunk size)`
+    fn pieces(index: &ChunkIndex, offset: u64, size: u64) -> Option<Vec<(String, u64, u64, u64)>> {
+        let end = offset + size;
+        let mut pieces = Vec::new();
+        let mut at = offset;
+        while at < end {
+            let (&chunk_offset, (chunk_size, key)) = index.range(..=at).next_back()?;
+            let chunk_end = chunk_offset + chunk_size;
+            if chunk_end <= at {
+                return None;
+            }
+            let len = chunk_end.min(end) - at;
+            pieces.push((key.clone(), at - chunk_offset, len, *chunk_size));
+            at += len;
+        }
+        Some(pieces)
+    }
+
+    /// Remove the chunk objects lying wholly inside the ranges
+    pub fn delete_chunks(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        let doomed = self.with_index(user_id, bucket, |index| {
+            let mut doomed = Vec::new();
+            for &(offset, size) in offset_size_list {
+                for (&chunk_offset, (chunk_size, key)) in index.range(offset..offset + size) {
+                    if chunk_offset + chunk_size <= offset + size {
+                        doomed.push((chunk_offset, key.clone()));
+                    }
+                }
+            }
+            doomed
+        })?;
+        for (chunk_offset, key) in doomed {
+            let request = self.request(reqwest::Method::DELETE, &key, &[], Vec::new())?;
+            self.send(request, format!("delete {}", key))?;
+            self.with_index(user_id, bucket, |index| index.remove(&chunk_offset))?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage for RemoteS3Store {
+    fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        let _lock = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let offset = self.bucket_len(user_id, bucket)?;
+        self.put_chunk(user_id, bucket, offset, data)?;
+        Ok((offset, data.len() as u64))
+    }
+
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let mut pieces = self.with_index(user_id, bucket, |index| Self::pieces(index, offset, size))?;
+        if pieces.is_none() {
+            // Another writer may have added chunks since the bucket was listed
+            self.invalidate(user_id, bucket);
+            pieces = self.with_index(user_id, bucket, |index| Self::pieces(index, offset, size))?;
+        }
+        let pieces = pieces.ok_or_else(|| remote_error(io::ErrorKind::NotFound,
+            format!("remote S3 holds no chunk for {}/{} offset {} size {}", user_id, bucket, offset, size)))?;
+        let mut data = Vec::with_capacity(size as usize);
+        for (key, start, len, chunk_size) in pieces {
+            data.extend(self.get_range(&key, start, len, chunk_size)?);
+        }
+        Ok(data)
+    }
+
+    fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        self.delete_chunks(user_id, bucket, offset_size_list)
+    }
+
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        let data = self.read(user_id, bucket, offset, size)?;
+        Ok(Sha256::digest(&data).as_slice() == checksum)
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.with_index(user_id, bucket, |index| {
+            index.iter().next_back().map(|(offset, (size, _))| offset + size).unwrap_or(0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_names_round_trip() {
+        let name = format!("{}-{}-{}", 4096, 17, chunk_uuid());
+        assert_eq!(parse_chunk_name(&name), Some((4096, 17)));
+        assert_eq!(parse_chunk_name("4096-17"), None);
+        assert_eq!(parse_chunk_name("x-17-id"), None);
+
+        let mut index = ChunkIndex::new();
+        index.insert(0, (10, "a".to_string()));
+        index.insert(10, (5, "b".to_string()));
+        assert_eq!(RemoteS3Store::pieces(&index, 8, 4).unwrap(),
+                   vec![("a".to_string(), 8, 2, 10), ("b".to_string(), 0, 2, 5)]);
+        assert!(RemoteS3Store::pieces(&index, 12, 10).is_none());
+    }
+}
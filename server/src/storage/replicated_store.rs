@@ -0,0 +1,369 @@
+//! Warm-standby replication to a secondary directory
+//!
+//! `ReplicatedStore` writes through to the primary `LocalXFSBinaryStore` and hands a copy of
+//! every appended chunk to a background mirror thread, which writes it at the same offset of
+//! the same segment file under `<REPLICATION_SECONDARY_DIR>/<user>/`. Reads, deletes and
+//! verification always go to the primary (freed ranges stay allocated on the secondary). The
+//! SQLite metadata file is copied to `<REPLICATION_SECONDARY_DIR>/metadata.sqlite` periodically with the online
+//! backup API.
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::metrics;
+use crate::storage::{Storage, instrumented_store::{self, IoOp, Traffic}, local_store::LocalXFSBinaryStore, placement::PlacementTarget, segment};
+use crate::storage::config::{StorageBackend, StorageConfig};
+use actix_web::Error;
+use actix_web::error::ErrorInternalServerError;
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time;
+
+/// Replication settings; replication is off unless `REPLICATION_SECONDARY_DIR` is set.
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+    pub secondary_dir: PathBuf,
+    /// Maximum number of chunks waiting to be mirrored before writers block
+    pub queue_capacity: usize,
+    /// How often the metadata database is copied to the secondary
+    pub metadata_interval: Duration,
+}
+
+impl ReplicationConfig {
+    pub fn from_env() -> Option<Self> {
+        let secondary_dir = env::var("REPLICATION_SECONDARY_DIR").ok().filter(|s| !s.is_empty())?;
+        let queue_capacity = env::var("REPLICATION_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1024);
+        let metadata_interval_secs = env::var("REPLICATION_METADATA_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+        Some(Self {
+            secondary_dir: PathBuf::from(secondary_dir),
+            queue_capacity,
+            metadata_interval: Duration::from_secs(metadata_interval_secs),
+        })
+    }
+}
+
+struct MirrorJob {
+    user_id: String,
+    bucket: String,
+    offset: u64,
+    data: Vec<u8>,
+    enqueued_at: Instant,
+}
+
+#[derive(Default)]
+struct ReplicationState {
+    queue_depth: usize,
+    mirrored_writes: u64,
+    mirrored_bytes: u64,
+    lag_seconds: f64,
+    last_error: Option<String>,
+    last_metadata_backup: Option<String>,
+}
+
+/// Snapshot reported by `GET /admin/replication/status`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplicationStatus {
+    pub enabled: bool,
+    pub secondary_dir: Option<String>,
+    pub queue_capacity: usize,
+    pub queue_depth: usize,
+    pub mirrored_writes: u64,
+    pub mirrored_bytes: u64,
+    /// Time the most recently mirrored chunk spent in the queue (0 when drained)
+    pub lag_seconds: f64,
+    pub last_error: Option<String>,
+    /// RFC 3339 time of the last successful metadata backup
+    pub last_metadata_backup: Option<String>,
+}
+
+/// Bounded mirror queue plus the thread draining it
+pub struct Replicator {
+    config: ReplicationConfig,
+    sender: SyncSender<MirrorJob>,
+    state: Arc<Mutex<ReplicationState>>,
+}
+
+impl Replicator {
+    /// Start the mirror thread for `config`.
+    pub fn start(config: ReplicationConfig) -> Arc<Self> {
+        let (sender, receiver) = sync_channel(config.queue_capacity.max(1));
+        let state = Arc::new(Mutex::new(ReplicationState::default()));
+        let worker_state = Arc::clone(&state);
+        let secondary_dir = config.secondary_dir.clone();
+        std::thread::Builder::new()
+            .name("replication-mirror".to_string())
+            .spawn(move || mirror_loop(&secondary_dir, receiver, &worker_state))
+            .expect("Failed to spawn replication mirror thread");
+        info!("Replication enabled: secondary={} queue_capacity={}",
+              config.secondary_dir.display(), config.queue_capacity);
+        Arc::new(Self { config, sender, state })
+    }
+
+    /// Queue a copy of a chunk the primary just wrote. Blocks while the queue is full.
+    fn enqueue(&self, user_id: &str, bucket: &str, offset: u64, data: &[u8]) {
+        let depth = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.queue_depth += 1;
+            state.queue_depth
+        };
+        metrics::set_gauge("warpdrive_replication_queue_depth", &[], depth as f64);
+        let job = MirrorJob {
+            user_id: user_id.to_string(),
+            bucket: bucket.to_string(),
+            offset,
+            data: data.to_vec(),
+            enqueued_at: Instant::now(),
+        };
+        if self.sender.send(job).is_err() {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.queue_depth -= 1;
+            state.last_error = Some("Replication mirror thread is not running".to_string());
+            error!("Replication mirror thread is gone; dropped chunk user={} bucket={} offset={}", user_id, bucket, offset);
+        }
+    }
+
+    /// Wait until every queued chunk has been mirrored; false on timeout.
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.state.lock().unwrap_or_else(|e| e.into_inner()).queue_depth == 0 {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Copy the metadata database to the secondary directory now.
+    pub fn backup_metadata(&self) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.config.secondary_dir).map_err(ErrorInternalServerError)?;
+        let dest = self.config.secondary_dir.join("metadata.sqlite");
+        let result = SQLiteMetadataStore::new().backup_to(&dest);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match &result {
+            Ok(()) => {
+                state.last_metadata_backup = Some(chrono::Utc::now().to_rfc3339());
+                debug!("Metadata backed up to {}", dest.display());
+            }
+            Err(e) => {
+                state.last_error = Some(format!("metadata backup: {}", e));
+                metrics::inc_counter("warpdrive_replication_errors_total", &[("kind", "metadata")]);
+                error!("Metadata backup to {} failed: {}", dest.display(), e);
+            }
+        }
+        result
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        ReplicationStatus {
+            enabled: true,
+            secondary_dir: Some(self.config.secondary_dir.display().to_string()),
+            queue_capacity: self.config.queue_capacity,
+            queue_depth: state.queue_depth,
+            mirrored_writes: state.mirrored_writes,
+            mirrored_bytes: state.mirrored_bytes,
+            lag_seconds: state.lag_seconds,
+            last_error: state.last_error.clone(),
+            last_metadata_backup: state.last_metadata_backup.clone(),
+        }
+    }
+}
+
+fn mirror_chunk(secondary_dir: &Path, job: &MirrorJob) -> std::io::Result<()> {
+    let user_dir = secondary_dir.join(&job.user_id);
+    std::fs::create_dir_all(&user_dir)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(user_dir.join(segment::file_name(&job.bucket, segment::segment_of(job.offset))))?;
+    file.seek(SeekFrom::Start(segment::offset_in_segment(job.offset)))?;
+    file.write_all(&job.data)?;
+    file.flush()
+}
+
+fn mirror_loop(secondary_dir: &Path, receiver: Receiver<MirrorJob>, state: &Mutex<ReplicationState>) {
+    for job in receiver {
+        let result = mirror_chunk(secondary_dir, &job);
+        let lag = job.enqueued_at.elapsed().as_secs_f64();
+
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.queue_depth -= 1;
+        match result {
+            Ok(()) => {
+                state.mirrored_writes += 1;
+                state.mirrored_bytes += job.data.len() as u64;
+                instrumented_store::record(IoOp::Write, Traffic::Replication, &job.user_id, job.data.len() as u64);
+            }
+            Err(e) => {
+                state.last_error = Some(format!("mirror {}/{} offset {}: {}", job.user_id, job.bucket, job.offset, e));
+                metrics::inc_counter("warpdrive_replication_errors_total", &[("kind", "data")]);
+                error!("Failed to mirror user={} bucket={} offset={} size={}: {}",
+                       job.user_id, job.bucket, job.offset, job.data.len(), e);
+            }
+        }
+        state.lag_seconds = if state.queue_depth == 0 { 0.0 } else { lag };
+        metrics::set_gauge("warpdrive_replication_queue_depth", &[], state.queue_depth as f64);
+        metrics::set_gauge("warpdrive_replication_lag_seconds", &[], state.lag_seconds);
+    }
+}
+
+lazy_static! {
+    static ref REPLICATOR: Option<Arc<Replicator>> = match StorageConfig::from_env().backend {
+        StorageBackend::Replicated => ReplicationConfig::from_env().map(Replicator::start),
+        _ => None,
+    };
+}
+
+/// Process-wide replicator, if `STORAGE_BACKEND=replicated` and a secondary is configured.
+pub fn replicator() -> Option<Arc<Replicator>> {
+    REPLICATOR.clone()
+}
+
+/// Status of the process-wide replicator (`enabled: false` when not configured).
+pub fn status() -> ReplicationStatus {
+    replicator().map(|r| r.status()).unwrap_or_default()
+}
+
+/// Start periodic metadata backups as a background task; `None` when replication is off.
+pub fn start_metadata_replication() -> Option<tokio::task::JoinHandle<()>> {
+    let replicator = replicator()?;
+    let period = replicator.config.metadata_interval;
+    info!("Starting metadata replication with {}s interval", period.as_secs());
+    Some(tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+            let replicator = Arc::clone(&replicator);
+            // Failures are recorded in the replication status by backup_metadata itself
+            let backup = move || { let _ = replicator.backup_metadata(); };
+            if let Err(e) = tokio::task::spawn_blocking(backup).await {
+                error!("Metadata replication task failed: {}", e);
+            }
+        }
+    }))
+}
+
+/// Storage that writes to the local primary and mirrors each chunk to the secondary.
+pub struct ReplicatedStore {
+    primary: LocalXFSBinaryStore,
+    replicator: Arc<Replicator>,
+}
+
+impl ReplicatedStore {
+    pub fn new(replicator: Arc<Replicator>) -> Self {
+        Self { primary: LocalXFSBinaryStore::new(), replicator }
+    }
+
+    /// Replicated store on the process-wide replicator, or `None` when replication is off.
+    pub fn from_env() -> Option<Self> {
+        match replicator() {
+            Some(replicator) => Some(Self::new(replicator)),
+            None => {
+                warn!("STORAGE_BACKEND=replicated but REPLICATION_SECONDARY_DIR is not set");
+                None
+            }
+        }
+    }
+}
+
+impl Storage for ReplicatedStore {
+    fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        let (offset, size) = self.primary.write(user_id, bucket, data)?;
+        self.replicator.enqueue(user_id, bucket, offset, data);
+        Ok((offset, size))
+    }
+
+    fn write_to(&self, user_id: &str, bucket: &str, target: PlacementTarget, data: &[u8]) -> Result<(u64, u64), Error> {
+        let (offset, size) = self.primary.write_to(user_id, bucket, target, data)?;
+        self.replicator.enqueue(user_id, bucket, offset, data);
+        Ok((offset, size))
+    }
+
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        self.primary.read(user_id, bucket, offset, size)
+    }
+
+    fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        self.primary.delete(user_id, bucket, offset_size_list)
+    }
+
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        self.primary.verify(user_id, bucket, offset, size, checksum)
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.primary.bucket_len(user_id, bucket)
+    }
+
+    fn backing_file(&self, user_id: &str, bucket: &str, offset: u64) -> Option<(PathBuf, u64)> {
+        self.primary.backing_file(user_id, bucket, offset)
+    }
+
+    fn segments(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        self.primary.segments(user_id, bucket)
+    }
+
+    fn remove_segment(&self, user_id: &str, bucket: &str, segment: u32) -> Result<bool, Error> {
+        // Like freed ranges, removed segments stay on the secondary
+        self.primary.remove_segment(user_id, bucket, segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secondary_matches_primary() {
+        let secondary_dir = env::temp_dir().join(format!("warpdrive-replica-{}", std::process::id()));
+        let replicator = Replicator::start(ReplicationConfig {
+            secondary_dir: secondary_dir.clone(),
+            queue_capacity: 4,
+            metadata_interval: Duration::from_secs(60),
+        });
+        let store = ReplicatedStore::new(Arc::clone(&replicator));
+        let bucket = format!("replica-bucket-{}", chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0));
+
+        for i in 0..32u32 {
+            let chunk: Vec<u8> = (0..(1000 + i * 517)).map(|b| (b.wrapping_mul(i + 1) % 251) as u8).collect();
+            let (offset, size) = store.write("replica_user", &bucket, &chunk).unwrap();
+            assert_eq!(store.read("replica_user", &bucket, offset, size).unwrap(), chunk);
+        }
+        assert!(replicator.wait_idle(Duration::from_secs(10)));
+
+        let primary = std::fs::read(store.backing_file("replica_user", &bucket, 0).unwrap().0).unwrap();
+        let secondary = std::fs::read(secondary_dir.join("replica_user").join(format!("{}.bin", bucket))).unwrap();
+        assert_eq!(primary, secondary);
+
+        let status = replicator.status();
+        assert_eq!(status.queue_depth, 0);
+        assert_eq!(status.mirrored_writes, 32);
+        assert_eq!(status.mirrored_bytes, primary.len() as u64);
+        assert!(status.last_error.is_none());
+
+        replicator.backup_metadata().unwrap();
+        let backup = rusqlite::Connection::open(secondary_dir.join("metadata.sqlite")).unwrap();
+        let tables: i64 = backup
+            .query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'objects'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tables, 1);
+
+        let _ = std::fs::remove_dir_all(&secondary_dir);
+    }
+}
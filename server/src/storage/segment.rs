@@ -0,0 +1,123 @@
+//! Segmented bucket files
+//!
+//! A bucket's bytes are spread over segment files so no single file grows without bound.
+//! Segment 0 is the original `{bucket}.bin`; later segments are `{bucket}.{segment:06}.bin`.
+//! Appends go to the highest segment, which is sealed once the next chunk would take it past
+//! `SEGMENT_MAX_BYTES`. Sealed segments are never written again, so once nothing references
+//! them the whole file can be unlinked instead of punching holes in it.
+//!
+//! Chunk extents keep their `(offset, size)` shape: the offset is a bucket address with the
+//! segment number in the bits above `SEGMENT_SHIFT` and the position inside the segment file
+//! below. Legacy offsets are all below `SEGMENT_SPAN`, so they keep reading from segment 0.
+//! The highest segment number, [`LEGACY_SEGMENT`], is reserved for chunks still in a user's
+//! single `{user}.bin` from before buckets existed (`service::legacy_migration`).
+
+use log::warn;
+use std::env;
+
+/// Bits of a bucket address holding the position inside a segment file
+pub const SEGMENT_SHIFT: u32 = 40;
+
+/// Address space of one segment (1 TiB), an upper bound for `SEGMENT_MAX_BYTES`
+pub const SEGMENT_SPAN: u64 = 1 << SEGMENT_SHIFT;
+
+/// Segment number of chunks in the user's pre-bucket `{user}.bin`; never a bucket file
+pub const LEGACY_SEGMENT: u32 = (1 << (64 - SEGMENT_SHIFT)) - 1;
+
+const DEFAULT_SEGMENT_MAX_BYTES: u64 = 8 * 1024 * 1024 * 1024;
+
+/// Segment holding bucket address `address`
+pub fn segment_of(address: u64) -> u32 {
+    (address >> SEGMENT_SHIFT) as u32
+}
+
+/// Position of `address` inside its segment file
+pub fn offset_in_segment(address: u64) -> u64 {
+    address & (SEGMENT_SPAN - 1)
+}
+
+/// Bucket address of `offset` in `segment`
+pub fn address(segment: u32, offset: u64) -> u64 {
+    ((segment as u64) << SEGMENT_SHIFT) | offset
+}
+
+/// File name of `segment` of `bucket`
+pub fn file_name(bucket: &str, segment: u32) -> String {
+    match segment {
+        0 => format!("{}.bin", bucket),
+        n => format!("{}.{:06}.bin", bucket, n),
+    }
+}
+
+/// Segment number of a file named by `file_name` for `bucket`, if it is one
+pub fn parse_file_name(bucket: &str, name: &str) -> Option<u32> {
+    let rest = name.strip_prefix(bucket)?.strip_suffix(".bin")?;
+    if rest.is_empty() {
+        return Some(0);
+    }
+    let digits = rest.strip_prefix('.')?;
+    if digits.len() < 6 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok().filter(|n| *n > 0)
+}
+
+/// Segment rollover settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentConfig {
+    /// Size past which the active segment is sealed
+    pub max_bytes: u64,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self { max_bytes: DEFAULT_SEGMENT_MAX_BYTES }
+    }
+}
+
+impl SegmentConfig {
+    /// Read `SEGMENT_MAX_BYTES` (default 8 GiB, at most `SEGMENT_SPAN`).
+    pub fn from_env() -> Self {
+        let max_bytes = env::var("SEGMENT_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_SEGMENT_MAX_BYTES);
+        if max_bytes > SEGMENT_SPAN {
+            warn!("SEGMENT_MAX_BYTES {} exceeds the segment address space; using {}", max_bytes, SEGMENT_SPAN);
+        }
+        Self { max_bytes: max_bytes.min(SEGMENT_SPAN) }
+    }
+
+    /// Whether appending `len` bytes to a segment of `current` bytes must start a new one.
+    /// An empty segment always takes the chunk, however large.
+    pub fn rolls_over(&self, current: u64, len: u64) -> bool {
+        current > 0 && current.saturating_add(len) > self.max_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses_and_file_names() {
+        let addr = address(3, 4096);
+        assert_eq!(segment_of(addr), 3);
+        assert_eq!(offset_in_segment(addr), 4096);
+        // Legacy offsets stay in segment 0
+        assert_eq!(segment_of(8 * 1024 * 1024 * 1024), 0);
+
+        assert_eq!(file_name("photos", 0), "photos.bin");
+        assert_eq!(file_name("photos", 12), "photos.000012.bin");
+        assert_eq!(parse_file_name("photos", "photos.bin"), Some(0));
+        assert_eq!(parse_file_name("photos", "photos.000012.bin"), Some(12));
+        assert_eq!(parse_file_name("photos", "photos.raw.bin"), None);
+        assert_eq!(parse_file_name("photos", "photos2.bin"), None);
+
+        let config = SegmentConfig { max_bytes: 100 };
+        assert!(!config.rolls_over(0, 500));
+        assert!(!config.rolls_over(60, 40));
+        assert!(config.rolls_over(60, 41));
+    }
+}
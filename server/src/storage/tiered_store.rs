@@ -0,0 +1,247 @@
+//! Tiered storage backend
+//!
+//! `TieredStore` keeps each bucket's file in one of two local stores, a hot tier
+//! (`HOT_DIRECTORY`) and a cold tier (`COLD_DIRECTORY`). The placement of every bucket is
+//! recorded in the `bucket_tiers` table; buckets without a row live on the hot tier.
+//!
+//! A bucket is moved with `service::tiering::migrate_bucket`. Before the first chunk is
+//! copied, the target file is grown to a boundary past the end of both files. Every copied
+//! chunk therefore lands at an offset >= boundary, while chunks not yet moved stay below it.
+//! That lets reads route by offset while a migration is in progress or was interrupted.
+
+use actix_web::Error;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::metadata::sqlite_store::SQLiteMetadataStore;
+use crate::service::config_generation;
+use crate::storage::{Storage, local_store::{default_storage_directory, LocalXFSBinaryStore}};
+
+/// Storage tier of a bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    Hot,
+    Cold,
+}
+
+impl Tier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Hot => "hot",
+            Tier::Cold => "cold",
+        }
+    }
+}
+
+impl std::str::FromStr for Tier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hot" => Ok(Tier::Hot),
+            "cold" => Ok(Tier::Cold),
+            _ => Err(format!("Unknown storage tier: {}", s)),
+        }
+    }
+}
+
+/// Where a bucket's bytes live
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub tier: Tier,
+    /// `(target, boundary)` while a migration is in progress: offsets >= boundary are
+    /// already in the target tier.
+    pub migration: Option<(Tier, u64)>,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self { tier: Tier::Hot, migration: None }
+    }
+}
+
+impl Placement {
+    /// Tier holding the chunk at `offset`
+    pub fn tier_for(&self, offset: u64) -> Tier {
+        match self.migration {
+            Some((target, boundary)) if offset >= boundary => target,
+            _ => self.tier,
+        }
+    }
+
+    /// Tier new chunks are appended to
+    pub fn write_tier(&self) -> Tier {
+        self.migration.map(|(target, _)| target).unwrap_or(self.tier)
+    }
+}
+
+lazy_static! {
+    // (user_id, bucket) -> placement. Placement only changes through `set_placement`, so the
+    // cache is refreshed there; changes made by another process clear it.
+    static ref PLACEMENTS: RwLock<HashMap<(String, String), Placement>> = RwLock::new(HashMap::new());
+}
+
+/// Current placement of `user_id`/`bucket`
+pub fn placement(user_id: &str, bucket: &str) -> Result<Placement, Error> {
+    config_generation::revalidate();
+    let cache_key = (user_id.to_string(), bucket.to_string());
+    if let Some(placement) = PLACEMENTS.read().unwrap_or_else(|e| e.into_inner()).get(&cache_key) {
+        return Ok(*placement);
+    }
+    let placement = match SQLiteMetadataStore::new().get_bucket_tier(user_id, bucket)? {
+        Some(row) => {
+            let tier = row.tier.parse().unwrap_or_else(|e| {
+                warn!("{} for bucket {}/{}; assuming hot", e, user_id, bucket);
+                Tier::Hot
+            });
+            let migration = row.target_tier
+                .and_then(|t| t.parse().ok())
+                .map(|target| (target, row.boundary));
+            Placement { tier, migration }
+        }
+        None => Placement::default(),
+    };
+    PLACEMENTS.write().unwrap_or_else(|e| e.into_inner()).insert(cache_key, placement);
+    Ok(placement)
+}
+
+/// Persist a new placement for `user_id`/`bucket` and refresh the cache.
+pub fn set_placement(user_id: &str, bucket: &str, placement: Placement) -> Result<(), Error> {
+    let db = SQLiteMetadataStore::new();
+    match placement.migration {
+        Some((target, boundary)) => {
+            db.begin_tier_migration(user_id, bucket, placement.tier.as_str(), target.as_str(), boundary)?
+        }
+        None => db.finish_tier_migration(user_id, bucket, placement.tier.as_str())?,
+    }
+    PLACEMENTS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert((user_id.to_string(), bucket.to_string()), placement);
+    debug!("Bucket {}/{} placement: {:?}", user_id, bucket, placement);
+    Ok(())
+}
+
+/// Drop every cached placement.
+pub(crate) fn clear_placements() {
+    PLACEMENTS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Directories of the two tiers
+#[derive(Debug, Clone)]
+pub struct TieredConfig {
+    pub hot_directory: PathBuf,
+    pub cold_directory: PathBuf,
+}
+
+impl TieredConfig {
+    pub fn from_env() -> Self {
+        let dir = |var: &str, default: &str| env::var(var).map(PathBuf::from)
+            .unwrap_or_else(|_| default_storage_directory().join(default));
+        Self {
+            hot_directory: dir("HOT_DIRECTORY", "hot"),
+            cold_directory: dir("COLD_DIRECTORY", "cold"),
+        }
+    }
+}
+
+/// Local store per tier, routed by bucket placement
+pub struct TieredStore {
+    hot: LocalXFSBinaryStore,
+    cold: LocalXFSBinaryStore,
+}
+
+impl TieredStore {
+    pub fn new(hot: LocalXFSBinaryStore, cold: LocalXFSBinaryStore) -> Self {
+        Self { hot, cold }
+    }
+
+    pub fn from_env() -> Self {
+        let config = TieredConfig::from_env();
+        Self::new(
+            LocalXFSBinaryStore::with_directory(config.hot_directory),
+            LocalXFSBinaryStore::with_directory(config.cold_directory),
+        )
+    }
+
+    /// The store backing `tier`
+    pub fn tier_store(&self, tier: Tier) -> &LocalXFSBinaryStore {
+        match tier {
+            Tier::Hot => &self.hot,
+            Tier::Cold => &self.cold,
+        }
+    }
+
+    fn store_for(&self, user_id: &str, bucket: &str, offset: u64) -> Result<&LocalXFSBinaryStore, Error> {
+        Ok(self.tier_store(placement(user_id, bucket)?.tier_for(offset)))
+    }
+}
+
+impl Storage for TieredStore {
+    fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        let tier = placement(user_id, bucket)?.write_tier();
+        self.tier_store(tier).write(user_id, bucket, data)
+    }
+
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        self.store_for(user_id, bucket, offset)?.read(user_id, bucket, offset, size)
+    }
+
+    fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        // Each range is freed in the tier that holds it
+        for range in offset_size_list {
+            self.store_for(user_id, bucket, range.0)?.delete(user_id, bucket, std::slice::from_ref(range))?;
+        }
+        Ok(())
+    }
+
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        self.store_for(user_id, bucket, offset)?.verify(user_id, bucket, offset, size, checksum)
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        let tier = placement(user_id, bucket)?.write_tier();
+        self.tier_store(tier).bucket_len(user_id, bucket)
+    }
+
+    fn backing_file(&self, user_id: &str, bucket: &str, offset: u64) -> Option<(PathBuf, u64)> {
+        self.store_for(user_id, bucket, offset).ok()?.backing_file(user_id, bucket, offset)
+    }
+
+    fn segments(&self, user_id: &str, bucket: &str) -> Result<Vec<(u64, u64)>, Error> {
+        let tier = placement(user_id, bucket)?.write_tier();
+        self.tier_store(tier).segments(user_id, bucket)
+    }
+
+    fn remove_segment(&self, user_id: &str, bucket: &str, segment: u32) -> Result<bool, Error> {
+        // Mid-migration a segment number can exist in both tiers; wait until it settles
+        match placement(user_id, bucket)? {
+            Placement { tier, migration: None } => self.tier_store(tier).remove_segment(user_id, bucket, segment),
+            _ => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placement_routes_by_boundary() {
+        let settled = Placement { tier: Tier::Cold, migration: None };
+        assert_eq!(settled.tier_for(0), Tier::Cold);
+        assert_eq!(settled.write_tier(), Tier::Cold);
+
+        let migrating = Placement { tier: Tier::Hot, migration: Some((Tier::Cold, 100)) };
+        assert_eq!(migrating.tier_for(99), Tier::Hot);
+        assert_eq!(migrating.tier_for(100), Tier::Cold);
+        assert_eq!(migrating.write_tier(), Tier::Cold);
+
+        assert_eq!("COLD".parse::<Tier>().unwrap(), Tier::Cold);
+        assert!("warm".parse::<Tier>().is_err());
+    }
+}
@@ -0,0 +1,43 @@
+//! Embedded operator UI
+//!
+//! `GET /ui` serves a single static page that lists a bucket through `/list` and deletes keys
+//! through `/delete/{key}`, sending the `User`, `Bucket` and optional `Authorization` headers
+//! from its form. The page only talks to its own origin, so no CORS headers are involved, and
+//! its Content-Security-Policy keeps it from loading or posting anywhere else. The endpoint
+//! answers 404 unless `UI_ENABLED` is set.
+
+use actix_web::HttpResponse;
+use std::env;
+
+const INDEX_HTML: &str = include_str!("index.html");
+
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; script-src 'unsafe-inline'; style-src 'unsafe-inline'; connect-src 'self'; form-action 'none'; frame-ancestors 'none'";
+
+/// Whether the UI is served
+#[derive(Debug, Clone, Default)]
+pub struct UiConfig {
+    pub enabled: bool,
+}
+
+impl UiConfig {
+    pub fn from_env() -> Self {
+        let enabled = env::var("UI_ENABLED")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+        Self { enabled }
+    }
+}
+
+#[actix_web::get("/ui")]
+async fn ui() -> HttpResponse {
+    if !UiConfig::from_env().enabled {
+        return HttpResponse::NotFound().finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header(("Content-Security-Policy", CONTENT_SECURITY_POLICY))
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        .insert_header(("Cache-Control", "no-store"))
+        .body(INDEX_HTML)
+}
@@ -0,0 +1,285 @@
+//! `Range` requests on GET (S3 GetObject and native raw GET).
+//!
+//! `Range: bytes=X-Y`, `bytes=X-` and `bytes=-N` are accepted, comma-separated. Ranges past
+//! the end of the object are dropped; when none is left the request is unsatisfiable (416).
+//! Overlapping and adjacent ranges are coalesced, so a request that names one span of bytes
+//! gets a plain 206 with Content-Range. Several disjoint spans get a 206
+//! `multipart/byteranges` body, one part per span, each with its own Content-Range.
+//!
+//! Following RFC 7233 §6.1 the Range header is ignored (a full 200) when it names more than
+//! `RANGE_MAX_PARTS` ranges (default 16) or several ranges that add up to more than the
+//! object itself (overlap) or to most of it, where a multipart body buys nothing over the
+//! whole object. A single range is always honored.
+
+use bytes::Bytes;
+use futures::stream::{self, LocalBoxStream, StreamExt};
+use std::env;
+
+/// Ranges honored in one request when `RANGE_MAX_PARTS` is unset
+pub const DEFAULT_MAX_PARTS: usize = 16;
+
+/// Share of the object, in percent, past which several ranges are served as the whole object
+const MAX_COVERAGE_PERCENT: u64 = 80;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeConfig {
+    /// Most ranges one Range header may name before it is ignored
+    pub max_parts: usize,
+}
+
+impl Default for RangeConfig {
+    fn default() -> Self {
+        Self { max_parts: DEFAULT_MAX_PARTS }
+    }
+}
+
+impl RangeConfig {
+    pub fn from_env() -> Self {
+        let max_parts = env::var("RANGE_MAX_PARTS")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_MAX_PARTS);
+        Self { max_parts }
+    }
+}
+
+/// What a GET answers for its Range header; ranges are inclusive `(first, last)` byte indices
+#[derive(Debug, Clone, PartialEq)]
+pub enum ByteRanges {
+    /// No Range header, or one ignored: the whole object
+    Full,
+    Single(u64, u64),
+    /// Disjoint, ascending, at least two
+    Multiple(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+/// One `X-Y`, `X-` or `-N`; `Err` when malformed, `Ok(None)` when past the end of a
+/// `total`-byte object.
+fn parse_spec(spec: &str, total: u64) -> Result<Option<(u64, u64)>, ()> {
+    if let Some(suffix) = spec.strip_prefix('-') {
+        let n: u64 = suffix.parse().map_err(|_| ())?;
+        if n == 0 || total == 0 {
+            return Ok(None);
+        }
+        return Ok(Some((total.saturating_sub(n), total - 1)));
+    }
+    let (start, end) = spec.split_once('-').ok_or(())?;
+    let start: u64 = start.parse().map_err(|_| ())?;
+    let end: u64 = if end.is_empty() { u64::MAX } else { end.parse().map_err(|_| ())? };
+    if start > end {
+        return Err(());
+    }
+    if start >= total {
+        return Ok(None);
+    }
+    Ok(Some((start, end.min(total - 1))))
+}
+
+/// Sort `ranges` and merge the ones that overlap or touch
+fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut out: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match out.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => out.push((start, end)),
+        }
+    }
+    out
+}
+
+/// Parse a Range header `header` for an object of `total` bytes
+pub fn parse(header: Option<&str>, total: u64, config: &RangeConfig) -> ByteRanges {
+    let Some(header) = header else {
+        return ByteRanges::Full;
+    };
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return ByteRanges::Unsatisfiable;
+    };
+    let specs: Vec<&str> = specs.split(',').map(str::trim).filter(|s| !s.is_empty()).take(config.max_parts + 1).collect();
+    if specs.len() > config.max_parts {
+        return ByteRanges::Full;
+    }
+    let mut ranges = Vec::with_capacity(specs.len());
+    for spec in &specs {
+        match parse_spec(spec, total) {
+            Ok(Some(range)) => ranges.push(range),
+            Ok(None) => {}
+            Err(()) => return ByteRanges::Unsatisfiable,
+        }
+    }
+    let requested: u64 = ranges.iter().map(|(start, end)| end - start + 1).sum();
+    let ranges = coalesce(ranges);
+    match ranges.as_slice() {
+        [] => ByteRanges::Unsatisfiable,
+        [(start, end)] if specs.len() == 1 => ByteRanges::Single(*start, *end),
+        _ if requested > total || requested * 100 > total * MAX_COVERAGE_PERCENT => ByteRanges::Full,
+        [(start, end)] => ByteRanges::Single(*start, *end),
+        _ => ByteRanges::Multiple(ranges),
+    }
+}
+
+/// Map the logical bytes `first..=last` of an object stored as `chunks` onto storage extents
+/// of at most `slice` bytes.
+pub fn range_slices(chunks: &[(u64, u64)], first: u64, last: u64, slice: u64) -> Vec<(u64, u64)> {
+    let mut out = Vec::new();
+    let mut logical = 0u64;
+    for &(storage_off, chunk_size) in chunks {
+        let chunk_end = logical + chunk_size;
+        if chunk_end <= first {
+            logical = chunk_end;
+            continue;
+        }
+        if logical > last {
+            break;
+        }
+        let read_start = first.max(logical);
+        let read_end = (last + 1).min(chunk_end);
+        let mut off = storage_off + (read_start - logical);
+        let mut rem = read_end - read_start;
+        while rem > 0 {
+            let n = rem.min(slice);
+            out.push((off, n));
+            off += n;
+            rem -= n;
+        }
+        logical = chunk_end;
+    }
+    out
+}
+
+/// A multipart/byteranges body of an object of `total` bytes of `content_type`
+pub struct Multipart {
+    boundary: String,
+    content_type: String,
+    total: u64,
+}
+
+impl Multipart {
+    /// A body with a freshly generated boundary
+    pub fn new(content_type: &str, total: u64) -> Self {
+        let mut random = [0u8; 16];
+        getrandom::getrandom(&mut random).expect("OS random number generator unavailable");
+        Self { boundary: format!("warpdrive-{}", hex::encode(random)), content_type: content_type.to_string(), total }
+    }
+
+    /// The response's Content-Type
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    fn part_header(&self, (first, last): (u64, u64)) -> String {
+        format!("--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                self.boundary, self.content_type, first, last, self.total)
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+
+    /// Bytes of the whole body for `ranges`
+    pub fn content_length(&self, ranges: &[(u64, u64)]) -> u64 {
+        let parts: u64 = ranges.iter()
+            .map(|&range| self.part_header(range).len() as u64 + range.1 - range.0 + 1 + 2)
+            .sum();
+        parts + self.closing().len() as u64
+    }
+
+    /// Frame the parts: `parts[i]` streams the bytes of `ranges[i]`
+    pub fn stream<E: 'static>(
+        &self,
+        ranges: &[(u64, u64)],
+        parts: Vec<LocalBoxStream<'static, Result<Bytes, E>>>,
+    ) -> LocalBoxStream<'static, Result<Bytes, E>> {
+        let framed: Vec<_> = ranges.iter().zip(parts).map(|(&range, part)| {
+            let header = Bytes::from(self.part_header(range));
+            stream::iter([Ok(header)]).chain(part).chain(stream::iter([Ok(Bytes::from_static(b"\r\n"))])).boxed_local()
+        }).collect();
+        let closing = Bytes::from(self.closing());
+        stream::iter(framed).flatten().chain(stream::iter([Ok(closing)])).boxed_local()
+    }
+
+    /// The whole body in memory: `parts[i]` holds the bytes of `ranges[i]`
+    pub fn body(&self, ranges: &[(u64, u64)], parts: &[Bytes]) -> Bytes {
+        let mut out = Vec::with_capacity(self.content_length(ranges) as usize);
+        for (&range, part) in ranges.iter().zip(parts) {
+            out.extend_from_slice(self.part_header(range).as_bytes());
+            out.extend_from_slice(part);
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(self.closing().as_bytes());
+        out.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_default(header: &str, total: u64) -> ByteRanges {
+        parse(Some(header), total, &RangeConfig::default())
+    }
+
+    #[test]
+    fn test_single_ranges() {
+        assert_eq!(parse(None, 100, &RangeConfig::default()), ByteRanges::Full);
+        assert_eq!(parse_default("bytes=0-9", 100), ByteRanges::Single(0, 9));
+        assert_eq!(parse_default("bytes=90-", 100), ByteRanges::Single(90, 99));
+        assert_eq!(parse_default("bytes=-10", 100), ByteRanges::Single(90, 99));
+        assert_eq!(parse_default("bytes=50-500", 100), ByteRanges::Single(50, 99));
+        // A single range is honored even when it is the whole object
+        assert_eq!(parse_default("bytes=0-", 100), ByteRanges::Single(0, 99));
+        assert_eq!(parse_default("bytes=100-", 100), ByteRanges::Unsatisfiable);
+        assert_eq!(parse_default("bytes=9-0", 100), ByteRanges::Unsatisfiable);
+        assert_eq!(parse_default("items=0-9", 100), ByteRanges::Unsatisfiable);
+        assert_eq!(parse_default("bytes=-0", 100), ByteRanges::Unsatisfiable);
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        assert_eq!(parse_default("bytes=0-9, 50-59", 100), ByteRanges::Multiple(vec![(0, 9), (50, 59)]));
+        // Sorted, with the ones past the end dropped
+        assert_eq!(parse_default("bytes=50-59,0-9,200-300", 100), ByteRanges::Multiple(vec![(0, 9), (50, 59)]));
+        // Overlapping and adjacent ranges are merged
+        assert_eq!(parse_default("bytes=0-9,5-14,15-19,60-69", 100), ByteRanges::Multiple(vec![(0, 19), (60, 69)]));
+        assert_eq!(parse_default("bytes=0-9,10-19", 100), ByteRanges::Single(0, 19));
+        assert_eq!(parse_default("bytes=200-300,400-", 100), ByteRanges::Unsatisfiable);
+        assert_eq!(parse_default("bytes=0-9,x", 100), ByteRanges::Unsatisfiable);
+    }
+
+    #[test]
+    fn test_abusive_ranges_fall_back_to_the_whole_object() {
+        // Too many
+        let many: Vec<String> = (0..17).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+        assert_eq!(parse_default(&format!("bytes={}", many.join(",")), 100), ByteRanges::Full);
+        let config = RangeConfig { max_parts: 2 };
+        assert_eq!(parse(Some("bytes=0-0,2-2,4-4"), 100, &config), ByteRanges::Full);
+        // Most of the object, or the same bytes over and over
+        assert_eq!(parse_default("bytes=0-44,50-94", 100), ByteRanges::Full);
+        assert_eq!(parse_default("bytes=0-59,0-59", 100), ByteRanges::Full);
+    }
+
+    #[test]
+    fn test_range_slices() {
+        let chunks = [(1000, 10), (2000, 10), (3000, 10)];
+        assert_eq!(range_slices(&chunks, 5, 24, u64::MAX), vec![(1005, 5), (2000, 10), (3000, 5)]);
+        assert_eq!(range_slices(&chunks, 10, 19, 4), vec![(2000, 4), (2004, 4), (2008, 2)]);
+    }
+
+    #[test]
+    fn test_multipart_body() {
+        let multipart = Multipart::new("text/plain", 10);
+        let ranges = [(0, 1), (8, 9)];
+        let body = multipart.body(&ranges, &[Bytes::from_static(b"01"), Bytes::from_static(b"89")]);
+        assert_eq!(body.len() as u64, multipart.content_length(&ranges));
+        let boundary = multipart.content_type().split_once("boundary=").unwrap().1.to_string();
+        let expected = format!(
+            "--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+             --{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(body, expected.as_bytes());
+    }
+}
@@ -0,0 +1,27 @@
+//! Clocks for object expiry and leases
+//!
+//! Expiry times are compared against [`now`] rather than `Utc::now()` directly, and lease
+//! deadlines against [`instant`] rather than `Instant::now()`, so tests can move time forward
+//! with [`advance`] instead of sleeping through a TTL.
+
+use chrono::{DateTime, Duration, Utc};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Instant;
+
+// Milliseconds added to the system time
+static OFFSET_MS: AtomicI64 = AtomicI64::new(0);
+
+/// The current time
+pub fn now() -> DateTime<Utc> {
+    Utc::now() + Duration::milliseconds(OFFSET_MS.load(Ordering::Relaxed))
+}
+
+/// The current monotonic time
+pub fn instant() -> Instant {
+    Instant::now() + std::time::Duration::from_millis(OFFSET_MS.load(Ordering::Relaxed).max(0) as u64)
+}
+
+/// Move [`now`] and [`instant`] forward by `by` for the rest of the process.
+pub fn advance(by: Duration) {
+    OFFSET_MS.fetch_add(by.num_milliseconds(), Ordering::Relaxed);
+}
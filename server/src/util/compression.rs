@@ -0,0 +1,242 @@
+//! Content-aware compression of GET responses (native raw GET and S3 GetObject).
+//!
+//! A response is compressed only when the client asks for it (`Accept-Encoding: gzip` or
+//! `zstd`), the stored Content-Type is one of `RESPONSE_COMPRESSION_TYPES`, the object is at
+//! least `RESPONSE_COMPRESSION_MIN_BYTES` and the whole object is returned (no range). An
+//! object stored with a Content-Encoding (uploaded already compressed) is never compressed
+//! again: its stored bytes are served with the stored encoding. `RESPONSE_COMPRESSION=off`
+//! disables compression. Compressed responses carry no Content-Length and keep the ETag
+//! of the stored object.
+
+use actix_web::{web, Error, HttpRequest};
+use actix_web::error::ErrorInternalServerError;
+use bytes::Bytes;
+use futures::stream::{self, LocalBoxStream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::Write;
+
+/// Smallest object compressed when `RESPONSE_COMPRESSION_MIN_BYTES` is unset
+pub const DEFAULT_MIN_BYTES: u64 = 1024;
+
+/// Content types compressed when `RESPONSE_COMPRESSION_TYPES` is unset; `type/*` matches
+/// every subtype.
+pub const DEFAULT_TYPES: &str = "text/*,application/json,application/xml,application/javascript,application/x-ndjson,image/svg+xml";
+
+/// Content codings the server can produce, in order of preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+    Gzip,
+}
+
+impl Codec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Gzip => "gzip",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub min_bytes: u64,
+    /// Lowercase content types, `type/*` for a whole top-level type
+    pub types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self { enabled: true, min_bytes: DEFAULT_MIN_BYTES, types: parse_types(DEFAULT_TYPES) }
+    }
+}
+
+fn parse_types(list: &str) -> Vec<String> {
+    list.split(',').map(|t| t.trim().to_ascii_lowercase()).filter(|t| !t.is_empty()).collect()
+}
+
+impl CompressionConfig {
+    /// Read `RESPONSE_COMPRESSION`, `RESPONSE_COMPRESSION_MIN_BYTES` and
+    /// `RESPONSE_COMPRESSION_TYPES`, falling back to defaults.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let enabled = env::var("RESPONSE_COMPRESSION")
+            .map(|v| !(v.eq_ignore_ascii_case("off") || v.eq_ignore_ascii_case("false") || v == "0"))
+            .unwrap_or(defaults.enabled);
+        let min_bytes = env::var("RESPONSE_COMPRESSION_MIN_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(defaults.min_bytes);
+        let types = env::var("RESPONSE_COMPRESSION_TYPES")
+            .map(|v| parse_types(&v))
+            .unwrap_or(defaults.types);
+        Self { enabled, min_bytes, types }
+    }
+
+    /// Whether objects of `content_type` are worth compressing
+    pub fn compressible(&self, content_type: &str) -> bool {
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        self.types.iter().any(|t| match t.strip_suffix("/*") {
+            Some(top) => mime.split('/').next() == Some(top),
+            None => *t == mime,
+        })
+    }
+
+    /// The coding to apply to a full GET of an object of `content_type` and `size` stored
+    /// with `stored_encoding`, for a request that sent `accept_encoding`, or None to send it
+    /// as stored. Call only for whole-object responses.
+    pub fn negotiate(&self, accept_encoding: Option<&str>, content_type: &str, size: u64, stored_encoding: Option<&str>) -> Option<Codec> {
+        if !self.enabled || size < self.min_bytes || stored_encoding.is_some_and(|e| !e.is_empty()) {
+            return None;
+        }
+        if !self.compressible(content_type) {
+            return None;
+        }
+        preferred_codec(accept_encoding?)
+    }
+}
+
+/// The request's Accept-Encoding header
+pub fn accept_encoding(req: &HttpRequest) -> Option<&str> {
+    req.headers().get("Accept-Encoding").and_then(|v| v.to_str().ok())
+}
+
+/// The supported coding `accept` (an Accept-Encoding value) rates highest; ties go to the
+/// server's preference. `identity` and unknown codings are never picked.
+fn preferred_codec(accept: &str) -> Option<Codec> {
+    let mut wildcard = None;
+    let mut rated: Vec<(Codec, f32)> = Vec::new();
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()))
+            .next()
+            .unwrap_or(1.0);
+        match coding.as_str() {
+            "zstd" => rated.push((Codec::Zstd, q)),
+            "gzip" | "x-gzip" => rated.push((Codec::Gzip, q)),
+            "*" => wildcard = Some(q),
+            _ => {}
+        }
+    }
+    [Codec::Zstd, Codec::Gzip].into_iter()
+        .filter_map(|codec| {
+            let q = rated.iter().find(|(c, _)| *c == codec).map(|(_, q)| *q).or(wildcard)?;
+            (q > 0.0).then_some((codec, q))
+        })
+        .fold(None, |best: Option<(Codec, f32)>, (codec, q)| match best {
+            Some((_, best_q)) if best_q >= q => best,
+            _ => Some((codec, q)),
+        })
+        .map(|(codec, _)| codec)
+}
+
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(codec: Codec) -> std::io::Result<Self> {
+        Ok(match codec {
+            Codec::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default())),
+            Codec::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), zstd::DEFAULT_COMPRESSION_LEVEL)?),
+        })
+    }
+
+    /// Compress `data`, returning whatever output the codec has produced so far
+    fn write(&mut self, data: &[u8]) -> std::io::Result<Bytes> {
+        let out = match self {
+            Encoder::Gzip(e) => {
+                e.write_all(data)?;
+                e.get_mut()
+            }
+            Encoder::Zstd(e) => {
+                e.write_all(data)?;
+                e.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(out)))
+    }
+
+    fn finish(self) -> std::io::Result<Bytes> {
+        Ok(Bytes::from(match self {
+            Encoder::Gzip(e) => e.finish()?,
+            Encoder::Zstd(e) => e.finish()?,
+        }))
+    }
+}
+
+/// `data` compressed with `codec`
+pub fn compress(data: &[u8], codec: Codec) -> std::io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(codec)?;
+    let mut out = encoder.write(data)?.to_vec();
+    out.extend_from_slice(&encoder.finish()?);
+    Ok(out)
+}
+
+/// `body` compressed with `codec` as it streams; each chunk is compressed on the blocking
+/// pool.
+pub fn compress_stream<S>(body: S, codec: Codec) -> LocalBoxStream<'static, Result<Bytes, Error>>
+where
+    S: Stream<Item = Result<Bytes, Error>> + 'static,
+{
+    let body: LocalBoxStream<'static, Result<Bytes, Error>> = body.boxed_local();
+    stream::try_unfold(Some((body, None::<Encoder>)), move |state| async move {
+        let Some((mut body, encoder)) = state else { return Ok(None) };
+        let mut encoder = match encoder {
+            Some(encoder) => encoder,
+            None => Encoder::new(codec).map_err(ErrorInternalServerError)?,
+        };
+        loop {
+            match body.next().await {
+                Some(chunk) => {
+                    let chunk = chunk?;
+                    let (returned, out) = web::block(move || {
+                        let out = encoder.write(&chunk);
+                        (encoder, out)
+                    }).await?;
+                    encoder = returned;
+                    let out = out.map_err(ErrorInternalServerError)?;
+                    if !out.is_empty() {
+                        return Ok(Some((out, Some((body, Some(encoder))))));
+                    }
+                }
+                None => {
+                    let out = web::block(move || encoder.finish()).await?.map_err(ErrorInternalServerError)?;
+                    return Ok(Some((out, None)));
+                }
+            }
+        }
+    }).boxed_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferred_codec() {
+        assert_eq!(preferred_codec("gzip, zstd"), Some(Codec::Zstd));
+        assert_eq!(preferred_codec("gzip"), Some(Codec::Gzip));
+        assert_eq!(preferred_codec("zstd;q=0.5, gzip"), Some(Codec::Gzip));
+        assert_eq!(preferred_codec("gzip;q=0, br"), None);
+        assert_eq!(preferred_codec("identity"), None);
+        assert_eq!(preferred_codec("*"), Some(Codec::Zstd));
+        assert_eq!(preferred_codec("zstd;q=0, *"), Some(Codec::Gzip));
+    }
+
+    #[test]
+    fn test_compressible_types() {
+        let config = CompressionConfig::default();
+        assert!(config.compressible("application/json"));
+        assert!(config.compressible("text/plain; charset=utf-8"));
+        assert!(config.compressible("Text/CSV"));
+        assert!(!config.compressible("application/octet-stream"));
+        assert!(!config.compressible("image/png"));
+    }
+}
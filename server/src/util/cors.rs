@@ -0,0 +1,120 @@
+//! Server-wide CORS for browser clients
+//!
+//! Off unless `CORS_ALLOWED_ORIGINS` is set to a comma-separated list of origins (or `*`).
+//! Preflight `OPTIONS` requests from an allowed origin are answered here for every native
+//! and S3 route; other requests from an allowed origin get `Access-Control-Allow-Origin`
+//! and the custom response headers listed in `Access-Control-Expose-Headers`. Requests from
+//! any other origin pass through untouched, so the browser withholds the response from the
+//! page, and S3 preflights can still be answered by the bucket's own CORS configuration.
+
+use actix_web::{Error, HttpResponse};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::http::header::HeaderValue;
+use actix_web::middleware::Next;
+use std::env;
+
+use crate::service::append_dedup::APPEND_ID_HEADER;
+use crate::service::batch_get::BATCH_ERRORS_HEADER;
+use crate::service::capabilities::FEATURES_HEADER;
+use crate::service::idempotency::REPLAYED_HEADER;
+use crate::service::object_format::OBJECT_FORMAT_HEADER;
+use crate::service::object_state::OBJECT_STATE_HEADER;
+use crate::service::download::RESUME_TOKEN_HEADER;
+use crate::service::validators::GENERATION_HEADER;
+
+const ALLOWED_METHODS: &str = "GET, PUT, POST, DELETE, HEAD, OPTIONS";
+
+/// How long browsers may cache a preflight answer
+const PREFLIGHT_MAX_AGE_SECS: u32 = 600;
+
+/// Origins allowed to call the API from a browser
+#[derive(Debug, Clone, Default)]
+pub struct CorsConfig {
+    /// Empty when CORS is off; `*` allows any origin
+    pub allowed_origins: Vec<String>,
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Self {
+        let allowed_origins = env::var("CORS_ALLOWED_ORIGINS")
+            .map(|v| v.split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect())
+            .unwrap_or_default();
+        Self { allowed_origins }
+    }
+
+    /// `Access-Control-Allow-Origin` value for a request from `origin`, if it is allowed
+    pub fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.allowed_origins.iter().any(|o| o == "*") {
+            return Some("*".to_string());
+        }
+        self.allowed_origins.iter().any(|o| o.eq_ignore_ascii_case(origin)).then(|| origin.to_string())
+    }
+}
+
+/// Response headers a page may read
+fn expose_headers() -> String {
+    [
+        "User", "Bucket", "X-Request-Id", "ETag", "Last-Modified",
+        "x-amz-request-id", "x-amz-version-id",
+        APPEND_ID_HEADER, BATCH_ERRORS_HEADER, OBJECT_STATE_HEADER, OBJECT_FORMAT_HEADER,
+        FEATURES_HEADER, GENERATION_HEADER, RESUME_TOKEN_HEADER, REPLAYED_HEADER,
+    ].join(", ")
+}
+
+/// Answer preflights and add CORS headers for allowed origins; use with `middleware::from_fn`.
+pub async fn cors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let allow_origin = req.headers().get(header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|origin| CorsConfig::from_env().allow_origin(origin));
+    let Some(allow_origin) = allow_origin else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+
+    let preflight = req.method() == Method::OPTIONS
+        && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if preflight {
+        let mut resp = HttpResponse::NoContent();
+        resp.insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin))
+            .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS))
+            .insert_header((header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE_SECS.to_string()))
+            .insert_header((header::VARY, "Origin"));
+        if let Some(requested) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            resp.insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, requested.clone()));
+        }
+        return Ok(req.into_response(resp.finish()).map_into_right_body());
+    }
+
+    let mut res = next.call(req).await?;
+    let headers = res.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&allow_origin) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&expose_headers()) {
+        headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, value);
+    }
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    Ok(res.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_origin() {
+        let config = CorsConfig { allowed_origins: vec!["https://app.example.com".to_string()] };
+        assert_eq!(config.allow_origin("https://app.example.com").as_deref(), Some("https://app.example.com"));
+        assert_eq!(config.allow_origin("https://evil.example.com"), None);
+        let any = CorsConfig { allowed_origins: vec!["*".to_string()] };
+        assert_eq!(any.allow_origin("https://evil.example.com").as_deref(), Some("*"));
+        assert_eq!(CorsConfig::default().allow_origin("https://app.example.com"), None);
+    }
+}
@@ -0,0 +1,347 @@
+// automatically generated by the FlatBuffers compiler, do not modify
+
+
+// @generated
+
+
+extern crate flatbuffers;
+
+#[allow(unused_imports, dead_code)]
+pub mod store_v2 {
+
+  use core::mem;
+  use core::cmp::Ordering;
+
+  extern crate flatbuffers;
+  use self::flatbuffers::{EndianScalar, Follow};
+
+pub enum FileDataOffset {}
+#[derive(Copy, Clone, PartialEq)]
+
+pub struct FileData<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for FileData<'a> {
+  type Inner = FileData<'a>;
+  #[inline]
+  unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+    Self { _tab: flatbuffers::Table::new(buf, loc) }
+  }
+}
+
+impl<'a> FileData<'a> {
+  pub const VT_DATA: flatbuffers::VOffsetT = 4;
+  pub const VT_NAME: flatbuffers::VOffsetT = 6;
+  pub const VT_CONTENT_TYPE: flatbuffers::VOffsetT = 8;
+  pub const VT_INDEX: flatbuffers::VOffsetT = 10;
+
+  #[inline]
+  pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+    FileData { _tab: table }
+  }
+  #[allow(unused_mut)]
+  pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr, A: flatbuffers::Allocator + 'bldr>(
+    _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr, A>,
+    args: &'args FileDataArgs<'args>
+  ) -> flatbuffers::WIPOffset<FileData<'bldr>> {
+    let mut builder = FileDataBuilder::new(_fbb);
+    builder.add_index(args.index);
+    if let Some(x) = args.content_type { builder.add_content_type(x); }
+    if let Some(x) = args.name { builder.add_name(x); }
+    if let Some(x) = args.data { builder.add_data(x); }
+    builder.finish()
+  }
+
+
+  #[inline]
+  pub fn data(&self) -> Option<flatbuffers::Vector<'a, u8>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, u8>>>(FileData::VT_DATA, None)}
+  }
+  #[inline]
+  pub fn name(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(FileData::VT_NAME, None)}
+  }
+  #[inline]
+  pub fn content_type(&self) -> Option<&'a str> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(FileData::VT_CONTENT_TYPE, None)}
+  }
+  #[inline]
+  pub fn index(&self) -> u32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<u32>(FileData::VT_INDEX, Some(0)).unwrap()}
+  }
+}
+
+impl flatbuffers::Verifiable for FileData<'_> {
+  #[inline]
+  fn run_verifier(
+    v: &mut flatbuffers::Verifier, pos: usize
+  ) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+    use self::flatbuffers::Verifiable;
+    v.visit_table(pos)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, u8>>>("data", Self::VT_DATA, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("name", Self::VT_NAME, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<&str>>("content_type", Self::VT_CONTENT_TYPE, false)?
+     .visit_field::<u32>("index", Self::VT_INDEX, false)?
+     .finish();
+    Ok(())
+  }
+}
+pub struct FileDataArgs<'a> {
+    pub data: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, u8>>>,
+    pub name: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub content_type: Option<flatbuffers::WIPOffset<&'a str>>,
+    pub index: u32,
+}
+impl<'a> Default for FileDataArgs<'a> {
+  #[inline]
+  fn default() -> Self {
+    FileDataArgs {
+      data: None,
+      name: None,
+      content_type: None,
+      index: 0,
+    }
+  }
+}
+
+pub struct FileDataBuilder<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a, A>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> FileDataBuilder<'a, 'b, A> {
+  #[inline]
+  pub fn add_data(&mut self, data: flatbuffers::WIPOffset<flatbuffers::Vector<'b , u8>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(FileData::VT_DATA, data);
+  }
+  #[inline]
+  pub fn add_name(&mut self, name: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(FileData::VT_NAME, name);
+  }
+  #[inline]
+  pub fn add_content_type(&mut self, content_type: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(FileData::VT_CONTENT_TYPE, content_type);
+  }
+  #[inline]
+  pub fn add_index(&mut self, index: u32) {
+    self.fbb_.push_slot::<u32>(FileData::VT_INDEX, index, 0);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>) -> FileDataBuilder<'a, 'b, A> {
+    let start = _fbb.start_table();
+    FileDataBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<FileData<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
+impl core::fmt::Debug for FileData<'_> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let mut ds = f.debug_struct("FileData");
+      ds.field("data", &self.data());
+      ds.field("name", &self.name());
+      ds.field("content_type", &self.content_type());
+      ds.field("index", &self.index());
+      ds.finish()
+  }
+}
+pub enum FileDataListOffset {}
+#[derive(Copy, Clone, PartialEq)]
+
+pub struct FileDataList<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for FileDataList<'a> {
+  type Inner = FileDataList<'a>;
+  #[inline]
+  unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+    Self { _tab: flatbuffers::Table::new(buf, loc) }
+  }
+}
+
+impl<'a> FileDataList<'a> {
+  pub const VT_FILES: flatbuffers::VOffsetT = 4;
+
+  #[inline]
+  pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+    FileDataList { _tab: table }
+  }
+  #[allow(unused_mut)]
+  pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr, A: flatbuffers::Allocator + 'bldr>(
+    _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr, A>,
+    args: &'args FileDataListArgs<'args>
+  ) -> flatbuffers::WIPOffset<FileDataList<'bldr>> {
+    let mut builder = FileDataListBuilder::new(_fbb);
+    if let Some(x) = args.files { builder.add_files(x); }
+    builder.finish()
+  }
+
+
+  #[inline]
+  pub fn files(&self) -> Option<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<FileData<'a>>>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<FileData>>>>(FileDataList::VT_FILES, None)}
+  }
+}
+
+impl flatbuffers::Verifiable for FileDataList<'_> {
+  #[inline]
+  fn run_verifier(
+    v: &mut flatbuffers::Verifier, pos: usize
+  ) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+    use self::flatbuffers::Verifiable;
+    v.visit_table(pos)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<FileData>>>>("files", Self::VT_FILES, false)?
+     .finish();
+    Ok(())
+  }
+}
+pub struct FileDataListArgs<'a> {
+    pub files: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<FileData<'a>>>>>,
+}
+impl<'a> Default for FileDataListArgs<'a> {
+  #[inline]
+  fn default() -> Self {
+    FileDataListArgs {
+      files: None,
+    }
+  }
+}
+
+pub struct FileDataListBuilder<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a, A>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> FileDataListBuilder<'a, 'b, A> {
+  #[inline]
+  pub fn add_files(&mut self, files: flatbuffers::WIPOffset<flatbuffers::Vector<'b , flatbuffers::ForwardsUOffset<FileData<'b >>>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(FileDataList::VT_FILES, files);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>) -> FileDataListBuilder<'a, 'b, A> {
+    let start = _fbb.start_table();
+    FileDataListBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<FileDataList<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
+impl core::fmt::Debug for FileDataList<'_> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let mut ds = f.debug_struct("FileDataList");
+      ds.field("files", &self.files());
+      ds.finish()
+  }
+}
+#[inline]
+/// Verifies that a buffer of bytes contains a `FileDataList`
+/// and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_file_data_list_unchecked`.
+pub fn root_as_file_data_list(buf: &[u8]) -> Result<FileDataList<'_>, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::root::<FileDataList<'_>>(buf)
+}
+#[inline]
+/// Verifies that a buffer of bytes contains a size prefixed
+/// `FileDataList` and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `size_prefixed_root_as_file_data_list_unchecked`.
+pub fn size_prefixed_root_as_file_data_list(buf: &[u8]) -> Result<FileDataList<'_>, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::size_prefixed_root::<FileDataList<'_>>(buf)
+}
+#[inline]
+/// Verifies, with the given options, that a buffer of bytes
+/// contains a `FileDataList` and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_file_data_list_unchecked`.
+pub fn root_as_file_data_list_with_opts<'b, 'o>(
+  opts: &'o flatbuffers::VerifierOptions,
+  buf: &'b [u8],
+) -> Result<FileDataList<'b>, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::root_with_opts::<FileDataList<'b>>(opts, buf)
+}
+#[inline]
+/// Verifies, with the given verifier options, that a buffer of
+/// bytes contains a size prefixed `FileDataList` and returns
+/// it. Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_file_data_list_unchecked`.
+pub fn size_prefixed_root_as_file_data_list_with_opts<'b, 'o>(
+  opts: &'o flatbuffers::VerifierOptions,
+  buf: &'b [u8],
+) -> Result<FileDataList<'b>, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::size_prefixed_root_with_opts::<FileDataList<'b>>(opts, buf)
+}
+#[inline]
+/// Assumes, without verification, that a buffer of bytes contains a FileDataList and returns it.
+/// # Safety
+/// Callers must trust the given bytes do indeed contain a valid `FileDataList`.
+pub unsafe fn root_as_file_data_list_unchecked(buf: &[u8]) -> FileDataList<'_> {
+  flatbuffers::root_unchecked::<FileDataList<'_>>(buf)
+}
+#[inline]
+/// Assumes, without verification, that a buffer of bytes contains a size prefixed FileDataList and returns it.
+/// # Safety
+/// Callers must trust the given bytes do indeed contain a valid size prefixed `FileDataList`.
+pub unsafe fn size_prefixed_root_as_file_data_list_unchecked(buf: &[u8]) -> FileDataList<'_> {
+  flatbuffers::size_prefixed_root_unchecked::<FileDataList<'_>>(buf)
+}
+pub const FILE_DATA_LIST_IDENTIFIER: &str = "WDV2";
+
+#[inline]
+pub fn file_data_list_buffer_has_identifier(buf: &[u8]) -> bool {
+  flatbuffers::buffer_has_identifier(buf, FILE_DATA_LIST_IDENTIFIER, false)
+}
+
+#[inline]
+pub fn file_data_list_size_prefixed_buffer_has_identifier(buf: &[u8]) -> bool {
+  flatbuffers::buffer_has_identifier(buf, FILE_DATA_LIST_IDENTIFIER, true)
+}
+
+#[inline]
+pub fn finish_file_data_list_buffer<'a, 'b, A: flatbuffers::Allocator + 'a>(
+    fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>,
+    root: flatbuffers::WIPOffset<FileDataList<'a>>) {
+  fbb.finish(root, Some(FILE_DATA_LIST_IDENTIFIER));
+}
+
+#[inline]
+pub fn finish_size_prefixed_file_data_list_buffer<'a, 'b, A: flatbuffers::Allocator + 'a>(fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>, root: flatbuffers::WIPOffset<FileDataList<'a>>) {
+  fbb.finish_size_prefixed(root, Some(FILE_DATA_LIST_IDENTIFIER));
+}
+}  // pub mod StoreV2
+
@@ -1,2 +1,12 @@
 pub mod serializer; 
-pub mod flatbuffer_store_generated;
\ No newline at end of file
+pub mod payload;
+pub mod cors;
+pub mod compression;
+pub mod byte_ranges;
+pub mod clock;
+#[cfg(test)]
+pub(crate) mod scratch;
+#[allow(clippy::missing_safety_doc)]
+pub mod flatbuffer_store_generated;
+#[allow(clippy::missing_safety_doc)]
+pub mod flatbuffer_store_v2_generated;
\ No newline at end of file
@@ -0,0 +1,266 @@
+//! Bounded request-body consumption shared by the native and S3 handlers.
+//!
+//! Every handler that drains a `web::Payload` goes through here so a stalled client
+//! cannot hold a worker forever (408) and a body without Content-Length cannot grow
+//! without bound (413).
+//!
+//! Object data (native PUT/APPEND/UPDATE/repair, S3 PutObject and UploadPart) may be up to
+//! `MAX_PAYLOAD_BYTES`. Every other body (batch key lists, tag sets, multipart completion and
+//! DeleteObjects XML, bucket configuration, admin JSON) is read with [`read_control_body`]
+//! and capped at `MAX_CONTROL_PAYLOAD_BYTES`, so a misdirected upload is refused after 1 MiB
+//! instead of being buffered in memory.
+//!
+//! The limits are read from the environment once, when [`crate::server::app`] is built, and
+//! handed to handlers as app data ([`PayloadLimits::of`]).
+//!
+//! Empty bodies are valid objects (S3 directory markers, placeholder files) on PUT and
+//! update. An empty APPEND is rejected with 400 unless `ALLOW_EMPTY_APPEND=true`, since it
+//! changes nothing and usually means the client lost its data.
+
+use actix_web::{Error, HttpRequest};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::error::{ErrorPayloadTooLarge, ErrorRequestTimeout};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use log::warn;
+use std::env;
+use std::time::Duration;
+
+/// Default body cap (5 GiB), matching the historical `PayloadConfig` limit.
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 5 * 1024 * 1024 * 1024;
+
+/// Default cap (1 MiB) for request bodies that are not object data.
+pub const DEFAULT_MAX_CONTROL_PAYLOAD_BYTES: usize = 1024 * 1024;
+
+/// Default time to wait for the next body chunk before giving up.
+pub const DEFAULT_PAYLOAD_IDLE_TIMEOUT_SECS: u64 = 30;
+
+/// Most bytes reserved up front from a client's announced length; bigger bodies grow from there
+/// so a false Content-Length cannot reserve `MAX_PAYLOAD_BYTES` per request.
+const MAX_PREALLOCATED_BYTES: usize = 256 * 1024 * 1024;
+
+/// Size and idle-time limits applied while reading a request body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayloadLimits {
+    pub max_bytes: usize,
+    /// Cap for bodies that are not object data
+    pub max_control_bytes: usize,
+    pub idle_timeout: Duration,
+    /// Accept an APPEND with no data as a no-op instead of rejecting it
+    pub allow_empty_append: bool,
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+            max_control_bytes: DEFAULT_MAX_CONTROL_PAYLOAD_BYTES,
+            idle_timeout: Duration::from_secs(DEFAULT_PAYLOAD_IDLE_TIMEOUT_SECS),
+            allow_empty_append: false,
+        }
+    }
+}
+
+impl PayloadLimits {
+    /// Read `MAX_PAYLOAD_BYTES`, `MAX_CONTROL_PAYLOAD_BYTES`, `PAYLOAD_IDLE_TIMEOUT_SECS` and
+    /// `ALLOW_EMPTY_APPEND`, falling back to defaults.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let max_bytes = env::var("MAX_PAYLOAD_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_bytes);
+        let max_control_bytes = env::var("MAX_CONTROL_PAYLOAD_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_control_bytes);
+        let idle_timeout = env::var("PAYLOAD_IDLE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.idle_timeout);
+        let allow_empty_append = env::var("ALLOW_EMPTY_APPEND")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(defaults.allow_empty_append);
+        Self { max_bytes, max_control_bytes, idle_timeout, allow_empty_append }
+    }
+
+    /// The limits the app serving `req` was built with; [`Self::from_env`] for an `App`
+    /// assembled without them
+    pub fn of(req: &HttpRequest) -> Self {
+        req.app_data::<PayloadLimits>().copied().unwrap_or_else(Self::from_env)
+    }
+}
+
+/// Pull the next chunk, enforcing the idle timeout and the running size cap.
+///
+/// `received` is the number of bytes already consumed from this body and is advanced
+/// by the size of the returned chunk. Returns `Ok(None)` at end of stream.
+pub async fn next_chunk_limited<S, E>(
+    payload: &mut S,
+    received: &mut usize,
+    max_bytes: usize,
+    idle_timeout: Duration,
+) -> Result<Option<Bytes>, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    let next = match tokio::time::timeout(idle_timeout, payload.next()).await {
+        Ok(next) => next,
+        Err(_) => {
+            warn!("Request body stalled: no data for {}s after {} bytes", idle_timeout.as_secs(), received);
+            return Err(ErrorRequestTimeout("Request body not received within the idle timeout"));
+        }
+    };
+    match next {
+        None => Ok(None),
+        Some(Err(e)) => Err(e.into()),
+        Some(Ok(chunk)) => {
+            *received = received.saturating_add(chunk.len());
+            if *received > max_bytes {
+                warn!("Request body exceeds limit of {} bytes", max_bytes);
+                return Err(ErrorPayloadTooLarge(format!(
+                    "Request body exceeds the maximum allowed size of {} bytes", max_bytes
+                )));
+            }
+            Ok(Some(chunk))
+        }
+    }
+}
+
+/// Drain a request body into memory, failing with 408 on a stalled stream and 413 over `max_bytes`.
+pub async fn read_body_limited<S, E>(payload: S, max_bytes: usize, idle_timeout: Duration) -> Result<Bytes, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    read_body_sized(payload, 0, max_bytes, idle_timeout).await
+}
+
+/// [`read_body_limited`] into a buffer sized for `expected` bytes up front, so a large body is
+/// not copied each time the buffer grows. A body that arrives in one chunk is returned
+/// without copying it.
+pub async fn read_body_sized<S, E>(
+    mut payload: S,
+    expected: usize,
+    max_bytes: usize,
+    idle_timeout: Duration,
+) -> Result<Bytes, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    let mut received = 0usize;
+    let first = match next_chunk_limited(&mut payload, &mut received, max_bytes, idle_timeout).await? {
+        Some(chunk) => chunk,
+        None => return Ok(Bytes::new()),
+    };
+    let second = match next_chunk_limited(&mut payload, &mut received, max_bytes, idle_timeout).await? {
+        Some(chunk) => chunk,
+        None => return Ok(first),
+    };
+    let mut bytes = BytesMut::with_capacity(initial_capacity(expected as u64, max_bytes).max(first.len() + second.len()));
+    bytes.extend_from_slice(&first);
+    bytes.extend_from_slice(&second);
+    while let Some(chunk) = next_chunk_limited(&mut payload, &mut received, max_bytes, idle_timeout).await? {
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.freeze())
+}
+
+/// Buffer capacity to reserve for a body announced as `expected` bytes.
+pub fn initial_capacity(expected: u64, max_bytes: usize) -> usize {
+    usize::try_from(expected).unwrap_or(usize::MAX).min(max_bytes).min(MAX_PREALLOCATED_BYTES)
+}
+
+/// Length the client announced in `Content-Length`, if any.
+pub fn content_length(req: &HttpRequest) -> Option<u64> {
+    req.headers().get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// [`read_body_sized`] under `limits`, for an object body of `expected` bytes (its
+/// [`content_length`]).
+pub async fn read_body<S, E>(payload: S, expected: Option<u64>, limits: PayloadLimits) -> Result<Bytes, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    let expected = usize::try_from(expected.unwrap_or(0)).unwrap_or(usize::MAX);
+    read_body_sized(payload, expected, limits.max_bytes, limits.idle_timeout).await
+}
+
+/// [`read_body_limited`] capped at `max_control_bytes`, for bodies that are not object data.
+pub async fn read_control_body<S, E>(payload: S, limits: PayloadLimits) -> Result<Bytes, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    read_body_limited(payload, limits.max_control_bytes, limits.idle_timeout).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::error::PayloadError;
+    use actix_web::http::StatusCode;
+    use futures::stream;
+
+    #[tokio::test]
+    async fn test_read_body_limited_collects_chunks() {
+        let chunks: Vec<Result<Bytes, PayloadError>> = vec![
+            Ok(Bytes::from_static(b"hello ")),
+            Ok(Bytes::from_static(b"world")),
+        ];
+        let body = read_body_limited(stream::iter(chunks), 64, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(&body[..], b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_read_body_limited_keeps_single_chunk() {
+        let chunk = Bytes::from(vec![7u8; 32]);
+        let body = read_body_limited(stream::iter(vec![Ok::<_, PayloadError>(chunk.clone())]), 64, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(body.as_ptr(), chunk.as_ptr());
+    }
+
+    #[tokio::test]
+    async fn test_read_body_sized_ignores_short_content_length() {
+        let chunks: Vec<Result<Bytes, PayloadError>> = vec![
+            Ok(Bytes::from_static(b"more than ")),
+            Ok(Bytes::from_static(b"announced")),
+        ];
+        let body = read_body_sized(stream::iter(chunks), 4, 64, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(&body[..], b"more than announced");
+    }
+
+    #[tokio::test]
+    async fn test_read_body_limited_times_out_on_stalled_stream() {
+        let stalled = stream::iter(vec![Ok::<_, PayloadError>(Bytes::from_static(b"partial"))])
+            .chain(stream::pending());
+        let err = read_body_limited(Box::pin(stalled), 64, Duration::from_millis(50)).await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_read_body_limited_rejects_oversized_body() {
+        let chunks: Vec<Result<Bytes, PayloadError>> = vec![
+            Ok(Bytes::from(vec![0u8; 40])),
+            Ok(Bytes::from(vec![0u8; 40])),
+        ];
+        let err = read_body_limited(stream::iter(chunks), 64, Duration::from_secs(1)).await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn test_payload_limits_default() {
+        let limits = PayloadLimits::default();
+        assert_eq!(limits.max_bytes, DEFAULT_MAX_PAYLOAD_BYTES);
+        assert_eq!(limits.max_control_bytes, DEFAULT_MAX_CONTROL_PAYLOAD_BYTES);
+        assert_eq!(limits.idle_timeout, Duration::from_secs(DEFAULT_PAYLOAD_IDLE_TIMEOUT_SECS));
+    }
+}
@@ -0,0 +1,24 @@
+//! Per-process scratch directory for unit tests
+//!
+//! Under `cfg(test)` the default storage and metadata locations resolve here instead of
+//! `./storage` and `./metadata`, so `cargo test` never writes into the working tree. The
+//! directory is removed when the test binary exits.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+extern "C" fn remove_root() {
+    if let Some(root) = ROOT.get() {
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
+
+pub(crate) fn root() -> &'static Path {
+    ROOT.get_or_init(|| {
+        // SAFETY: registers a function without captured state; it runs once at process exit.
+        unsafe { libc::atexit(remove_root) };
+        std::env::temp_dir().join(format!("warpdrive-unit-{}", std::process::id()))
+    })
+}
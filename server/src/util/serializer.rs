@@ -1,15 +1,243 @@
-use bincode;
+//! Encoding of the chunk lists stored in metadata blobs
+//!
+//! A blob starts with a one-byte version:
+//!
+//! - v1: the original layout, bincode of the `(offset, size)` extent list. Blobs written
+//!   before versioning carry no version byte; they are recognised by their shape, an 8-byte
+//!   count followed by exactly that many 16-byte extents.
+//! - v2: bincode of a list of chunk records whose optional fields (segment, checksum,
+//!   encoding, raw size) take one byte when unset.
+//!
+//! No versioned blob has the legacy shape: read as a legacy count, its first eight bytes
+//! claim at least two extents plus 256 per record it holds, and records stay far below
+//! 4 KiB. Blobs are upgraded lazily: every reader accepts all versions and every write,
+//! including the next update of an object written as v1, stores v2.
+
 use actix_web::error::ErrorInternalServerError;
 use actix_web::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::DataChunk;
+use crate::util::compression::Codec;
+
+/// Version byte of the `(offset, size)` extent layout
+pub const VERSION_1: u8 = 1;
+
+/// Version byte of the chunk record layout, the one written
+pub const VERSION_2: u8 = 2;
+
+/// One chunk in a v2 blob
+#[derive(Debug, Serialize, Deserialize)]
+struct ChunkRecord {
+    offset: u64,
+    size: u64,
+    segment: Option<u32>,
+    checksum: Option<u32>,
+    encoding: Option<u8>,
+    raw_size: Option<u64>,
+}
+
+fn encoding_code(codec: Codec) -> u8 {
+    match codec {
+        Codec::Zstd => 1,
+        Codec::Gzip => 2,
+    }
+}
+
+fn encoding_of(code: u8) -> Result<Codec, Error> {
+    match code {
+        1 => Ok(Codec::Zstd),
+        2 => Ok(Codec::Gzip),
+        other => Err(ErrorInternalServerError(format!("Unknown chunk encoding {}", other))),
+    }
+}
 
+impl From<&DataChunk> for ChunkRecord {
+    fn from(chunk: &DataChunk) -> Self {
+        Self {
+            offset: chunk.offset,
+            size: chunk.size,
+            segment: Some(chunk.segment).filter(|s| *s != 0),
+            checksum: chunk.checksum,
+            encoding: chunk.encoding.map(encoding_code),
+            raw_size: chunk.raw_size,
+        }
+    }
+}
+
+impl ChunkRecord {
+    fn into_chunk(self) -> Result<DataChunk, Error> {
+        Ok(DataChunk {
+            offset: self.offset,
+            size: self.size,
+            segment: self.segment.unwrap_or(0),
+            checksum: self.checksum,
+            encoding: self.encoding.map(encoding_of).transpose()?,
+            raw_size: self.raw_size,
+        })
+    }
+}
+
+/// True for a blob written before versioning: a bare bincode extent list
+fn is_legacy(bytes: &[u8]) -> bool {
+    match bytes.get(..8) {
+        Some(count) => {
+            let count = u64::from_le_bytes(count.try_into().unwrap());
+            count.checked_mul(16) == Some(bytes.len() as u64 - 8)
+        }
+        None => false,
+    }
+}
 
-pub fn serialize_offset_size(offset_size_list: &Vec<(u64, u64)>) -> Result<Vec<u8>, actix_web::Error> {
-    bincode::serialize(&offset_size_list)
-        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to serialize size list: {}", e)))
-    
+/// Version of the layout `bytes` are stored in; unversioned legacy blobs are v1.
+pub fn version(bytes: &[u8]) -> Option<u8> {
+    if is_legacy(bytes) {
+        return Some(VERSION_1);
+    }
+    bytes.first().copied()
 }
 
-pub fn deserialize_offset_size(bytes: &[u8]) -> Result<Vec<(u64, u64)>, Error> {
+fn decode_extent_list(bytes: &[u8]) -> Result<Vec<(u64, u64)>, Error> {
     bincode::deserialize(bytes)
         .map_err(|e| ErrorInternalServerError(format!("Failed to deserialize offset list: {}", e)))
-}
\ No newline at end of file
+}
+
+/// Encode `chunks` in the current layout.
+pub fn encode_chunks(chunks: &[DataChunk]) -> Result<Vec<u8>, Error> {
+    let records: Vec<ChunkRecord> = chunks.iter().map(ChunkRecord::from).collect();
+    let mut bytes = vec![VERSION_2];
+    bincode::serialize_into(&mut bytes, &records)
+        .map_err(|e| ErrorInternalServerError(format!("Failed to serialize chunk list: {}", e)))?;
+    Ok(bytes)
+}
+
+/// Decode a chunk list stored in any layout.
+pub fn decode_chunks(bytes: &[u8]) -> Result<Vec<DataChunk>, Error> {
+    if is_legacy(bytes) {
+        return Ok(decode_extent_list(bytes)?.into_iter().map(DataChunk::from_extent).collect());
+    }
+    match bytes.first() {
+        Some(&VERSION_1) => Ok(decode_extent_list(&bytes[1..])?.into_iter().map(DataChunk::from_extent).collect()),
+        Some(&VERSION_2) => {
+            let records: Vec<ChunkRecord> = bincode::deserialize(&bytes[1..])
+                .map_err(|e| ErrorInternalServerError(format!("Failed to deserialize chunk list: {}", e)))?;
+            records.into_iter().map(ChunkRecord::into_chunk).collect()
+        }
+        Some(other) => Err(ErrorInternalServerError(format!("Unknown chunk list version {}", other))),
+        None => Err(ErrorInternalServerError("Failed to deserialize chunk list: empty blob")),
+    }
+}
+
+/// Encode storage `(offset, size)` extents as a chunk list without per-chunk attributes.
+pub fn encode_extents(extents: &[(u64, u64)]) -> Result<Vec<u8>, Error> {
+    let chunks: Vec<DataChunk> = extents.iter().copied().map(DataChunk::from_extent).collect();
+    encode_chunks(&chunks)
+}
+
+/// The storage `(offset, size)` extents of a chunk list stored in any layout.
+pub fn decode_extents(bytes: &[u8]) -> Result<Vec<(u64, u64)>, Error> {
+    Ok(decode_chunks(bytes)?.iter().map(DataChunk::extent).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::segment;
+
+    /// `bincode::serialize(&vec![(0u64, 10u64), (10, 5)])` as stored before versioning
+    const LEGACY_TWO_EXTENTS: [u8; 40] = [
+        2, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0,
+        10, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0,
+    ];
+
+    /// A legacy extent in segment 3 at offset 4096, 100 bytes long
+    const LEGACY_SEGMENT_EXTENT: [u8; 24] = [
+        1, 0, 0, 0, 0, 0, 0, 0,
+        0, 16, 0, 0, 0, 3, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0,
+    ];
+
+    /// An empty legacy list
+    const LEGACY_EMPTY: [u8; 8] = [0; 8];
+
+    fn chunk(offset: u64, size: u64) -> DataChunk {
+        DataChunk::from_extent((offset, size))
+    }
+
+    #[test]
+    fn test_legacy_fixtures_decode_as_v1() {
+        assert_eq!(version(&LEGACY_TWO_EXTENTS), Some(VERSION_1));
+        assert_eq!(decode_chunks(&LEGACY_TWO_EXTENTS).unwrap(), vec![chunk(0, 10), chunk(10, 5)]);
+        assert_eq!(decode_extents(&LEGACY_TWO_EXTENTS).unwrap(), vec![(0, 10), (10, 5)]);
+
+        let chunks = decode_chunks(&LEGACY_SEGMENT_EXTENT).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!((chunks[0].segment, chunks[0].offset, chunks[0].size), (3, 4096, 100));
+        assert_eq!(decode_extents(&LEGACY_SEGMENT_EXTENT).unwrap(), vec![(segment::address(3, 4096), 100)]);
+
+        assert_eq!(version(&LEGACY_EMPTY), Some(VERSION_1));
+        assert!(decode_chunks(&LEGACY_EMPTY).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_legacy_fixture_matches_old_serializer() {
+        assert_eq!(bincode::serialize(&vec![(0u64, 10u64), (10, 5)]).unwrap(), LEGACY_TWO_EXTENTS);
+    }
+
+    #[test]
+    fn test_prefixed_v1_decodes() {
+        let mut bytes = vec![VERSION_1];
+        bytes.extend_from_slice(&LEGACY_TWO_EXTENTS);
+        assert_eq!(decode_extents(&bytes).unwrap(), vec![(0, 10), (10, 5)]);
+    }
+
+    #[test]
+    fn test_v2_round_trip_with_optional_fields() {
+        let chunks = vec![
+            chunk(0, 10),
+            DataChunk {
+                offset: 4096,
+                size: 40,
+                segment: 7,
+                checksum: Some(0xdead_beef),
+                encoding: Some(Codec::Zstd),
+                raw_size: Some(100),
+            },
+            DataChunk { encoding: Some(Codec::Gzip), ..chunk(50, 1) },
+        ];
+        let bytes = encode_chunks(&chunks).unwrap();
+        assert_eq!(bytes[0], VERSION_2);
+        assert_eq!(version(&bytes), Some(VERSION_2));
+        assert_eq!(decode_chunks(&bytes).unwrap(), chunks);
+    }
+
+    #[test]
+    fn test_v2_never_looks_legacy() {
+        for n in 0..64u64 {
+            let extents: Vec<(u64, u64)> = (0..n).map(|i| (i * 16, 16)).collect();
+            let bytes = encode_extents(&extents).unwrap();
+            assert_eq!(version(&bytes), Some(VERSION_2), "{} extents", n);
+            assert_eq!(decode_extents(&bytes).unwrap(), extents);
+        }
+    }
+
+    #[test]
+    fn test_legacy_rewritten_as_v2() {
+        let chunks = decode_chunks(&LEGACY_TWO_EXTENTS).unwrap();
+        let upgraded = encode_chunks(&chunks).unwrap();
+        assert_eq!(version(&upgraded), Some(VERSION_2));
+        assert_eq!(decode_chunks(&upgraded).unwrap(), chunks);
+    }
+
+    #[test]
+    fn test_unknown_version_and_encoding_rejected() {
+        assert!(decode_chunks(&[9, 0, 0]).is_err());
+        assert!(decode_chunks(&[]).is_err());
+        let mut bytes = encode_chunks(&[DataChunk { encoding: Some(Codec::Zstd), ..chunk(0, 1) }]).unwrap();
+        // The encoding code follows the offset, size, unset segment and unset checksum
+        let at = 1 + 8 + 8 + 8 + 1 + 1 + 1;
+        assert_eq!(bytes[at], 1);
+        bytes[at] = 200;
+        assert!(decode_chunks(&bytes).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+// Opt-in access tracking: reads are noted in memory, flushed to `access_times` and reported by
+// the listing and /admin/access/top; a bucket's last-access rule expires what nobody read.
+
+use actix_web::{test, App, http::StatusCode};
+use rusqlite::{Connection, OptionalExtension};
+use std::sync::Once;
+use warp_drive::admin::{access_top, get_bucket_lifecycle, set_bucket_lifecycle};
+use warp_drive::api::{get, list, put};
+use warp_drive::service::access_tracking;
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::clock;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "access_user";
+const SECRET_KEY: &str = "access-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+        std::env::set_var("TRACK_ACCESS", "true");
+    });
+}
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket))
+}
+
+fn admin(req: test::TestRequest, uri: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("Authorization", format!("Bearer {}", SECRET_KEY)))
+}
+
+/// (last_accessed_at, reads) of the key's row in `access_times`
+fn access_row(bucket: &str, key: &str) -> Option<(String, i64)> {
+    let conn = Connection::open(common::isolate().join("metadata").join("metadata.sqlite")).unwrap();
+    conn.query_row(
+        "SELECT last_accessed_at, reads FROM access_times WHERE user_id = ?1 AND bucket = ?2 AND key = ?3",
+        rusqlite::params![USER, bucket, key],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional().unwrap()
+}
+
+#[actix_web::test]
+async fn test_reads_are_flushed_to_access_times() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(list).service(access_top)).await;
+    let bucket = unique("access");
+    for key in ["hot", "cold"] {
+        let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", key), &bucket)
+            .set_payload(payload_of(key.as_bytes())).to_request()).await;
+        assert!(resp.status().is_success(), "{}", resp.status());
+    }
+    for _ in 0..3 {
+        let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/hot", &bucket).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    // The listing already sees reads the table does not have yet
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/list", &bucket).to_request()).await;
+    let listing: serde_json::Value = test::read_body_json(resp).await;
+    let objects = listing["objects"].as_array().unwrap();
+    assert_eq!(objects[0]["key"], "cold");
+    assert!(objects[0]["last_accessed_at"].is_null());
+    assert_eq!(objects[1]["key"], "hot");
+    let listed = objects[1]["last_accessed_at"].as_str().unwrap().to_string();
+
+    access_tracking::flush().unwrap();
+    let (flushed, reads) = access_row(&bucket, "hot").expect("flushed row");
+    assert_eq!((flushed.as_str(), reads), (listed.as_str(), 3));
+    assert!(access_row(&bucket, "cold").is_none());
+    // Counts add up across flushes
+    test::call_service(&app, native(test::TestRequest::get(), "/get/hot", &bucket).to_request()).await;
+    access_tracking::flush().unwrap();
+    assert_eq!(access_row(&bucket, "hot").unwrap().1, 4);
+
+    let resp = test::call_service(&app, admin(test::TestRequest::get(), "/admin/access/top?limit=1000").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let top: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(top["enabled"], true);
+    let hot = top["keys"].as_array().unwrap().iter()
+        .find(|k| k["bucket"] == bucket.as_str() && k["key"] == "hot")
+        .expect("hot key listed");
+    assert_eq!(hot["reads"], 4);
+    let resp = test::call_service(&app, admin(test::TestRequest::get(), "/admin/access/top?limit=x").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_lifecycle_rule_expires_unread_objects() {
+    setup();
+    let app = test::init_service(
+        App::new().service(put).service(get).service(get_bucket_lifecycle).service(set_bucket_lifecycle)
+    ).await;
+    let bucket = unique("lifecycle");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    for key in ["read", "unread"] {
+        let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", key), &bucket)
+            .set_payload(payload_of(key.as_bytes())).to_request()).await;
+        assert!(resp.status().is_success(), "{}", resp.status());
+    }
+    let lifecycle = format!("/admin/buckets/{}/{}/lifecycle", USER, bucket);
+    let resp = test::call_service(&app, admin(test::TestRequest::put(), &lifecycle)
+        .set_json(serde_json::json!({ "expire_unread_days": 0 })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, admin(test::TestRequest::put(), &lifecycle)
+        .set_json(serde_json::json!({ "expire_unread_days": 5 })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, admin(test::TestRequest::get(), &lifecycle).to_request()).await;
+    let rule: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(rule["expire_unread_days"], 5);
+
+    // Freshly written objects count as recently used
+    DeletionWorker::new().process_deletions().await.unwrap();
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/unread", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    clock::advance(chrono::Duration::days(10));
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/read", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    // The worker flushes the read itself before applying the rule
+    DeletionWorker::new().process_deletions().await.unwrap();
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/unread", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/read", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(access_row(&bucket, "unread").is_none());
+}
@@ -0,0 +1,112 @@
+// Admin metadata dump: a bucket written through the native API and S3 is dumped as NDJSON and
+// per key, and the rows are checked against what GET and HEAD report. The endpoint is gated
+// by ADMIN_DUMP_ENABLED, toggled inside the single test.
+
+use actix_web::{test, web, App, http::StatusCode};
+use actix_web::body::{BodySize, MessageBody};
+use warp_drive::admin::dump;
+use warp_drive::api::{put, get};
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_head_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{AMZ_DATE, authorization, payload, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "dump-test-access";
+const SECRET_KEY: &str = "dump-test-secret";
+
+#[actix_web::test]
+async fn test_dump_matches_get_and_head() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    let app = test::init_service(
+        App::new()
+            .service(dump)
+            .service(put)
+            .service(get)
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::head().to(s3_head_object_handler))
+    ).await;
+    let bucket = unique("dump");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let admin = |uri: String| test::TestRequest::get().uri(&uri)
+        .insert_header(("Authorization", format!("Bearer {}", SECRET_KEY)))
+        .to_request();
+
+    // A native object of two chunks and an S3 object with headers
+    let resp = test::call_service(&app, test::TestRequest::post().uri("/put/native")
+        .insert_header(("user", "admin")).insert_header(("bucket", bucket.clone()))
+        .set_payload(payload(&[b"first chunk", b"second"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let path = format!("/s3/{}/s3-object", bucket);
+    let signed = |req: test::TestRequest, method: &str| req.uri(&path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, &path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+        .to_request();
+    let resp = test::call_service(&app, signed(test::TestRequest::put()
+        .insert_header(("Content-Type", "text/plain"))
+        .insert_header(("x-amz-meta-origin", "dump-test"))
+        .set_payload("hello dump"), "PUT")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Disabled unless configured, and always behind the admin token
+    let resp = test::call_service(&app, admin(format!("/admin/dump/admin/{}", bucket))).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    std::env::set_var("ADMIN_DUMP_ENABLED", "true");
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("/admin/dump/admin/{}", bucket)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = test::call_service(&app, admin(format!("/admin/dump/admin/{}", bucket))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/x-ndjson");
+    let body = test::read_body(resp).await;
+    let rows: Vec<serde_json::Value> = std::str::from_utf8(&body).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 2, "{}", String::from_utf8_lossy(&body));
+    assert!(rows[0]["row_id"].as_i64().unwrap() < rows[1]["row_id"].as_i64().unwrap());
+    let native = rows.iter().find(|r| r["key"] == "native").unwrap();
+    let s3 = rows.iter().find(|r| r["key"] == "s3-object").unwrap();
+
+    // The native row lists the chunks GET returns
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/get/native")
+        .insert_header(("user", "admin")).insert_header(("bucket", bucket.clone())).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    let files: Vec<u64> = root_as_file_data_list(&body).unwrap().files().unwrap()
+        .iter().map(|f| f.data().unwrap().len() as u64).collect();
+    let chunk_sizes: Vec<u64> = native["chunks"].as_array().unwrap()
+        .iter().map(|c| c["size"].as_u64().unwrap()).collect();
+    assert_eq!(chunk_sizes, files);
+    assert_eq!(native["size"].as_u64().unwrap(), files.iter().sum::<u64>());
+    assert_eq!(native["chunks"][0]["segment"], 0);
+    assert_eq!(native["is_latest"], true);
+    assert_eq!(native["is_delete_marker"], false);
+
+    // The S3 row carries what HEAD reports
+    let resp = test::call_service(&app, signed(test::TestRequest::default().method(actix_web::http::Method::HEAD), "HEAD")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let header = |name: &str| resp.headers().get(name).unwrap().to_str().unwrap().to_string();
+    assert_eq!(s3["etag"].as_str().unwrap(), header("etag"));
+    assert_eq!(BodySize::Sized(s3["size"].as_u64().unwrap()), resp.response().body().size());
+    assert_eq!(s3["content_type"].as_str().unwrap(), header("content-type"));
+    assert_eq!(s3["user_metadata"]["origin"].as_str().unwrap(), header("x-amz-meta-origin"));
+
+    // One key: its rows plus lifecycle state
+    let resp = test::call_service(&app, admin(format!("/admin/dump/admin/{}?key=s3-object", bucket))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let record: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(record["state"], "active");
+    assert_eq!(record["versions"].as_array().unwrap().len(), 1);
+    assert_eq!(&record["versions"][0], s3);
+
+    let resp = test::call_service(&app, admin(format!("/admin/dump/admin/{}?key=missing", bucket))).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, admin("/admin/dump/admin/no-such-bucket".to_string())).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    std::env::remove_var("ADMIN_DUMP_ENABLED");
+}
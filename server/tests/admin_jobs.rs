@@ -0,0 +1,149 @@
+// Admin jobs: state transitions as seen through /admin/jobs, one job at a time per bucket,
+// and cooperative cancellation.
+
+use actix_web::{test, App, http::StatusCode};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use warp_drive::admin::{cancel_job, get_job, list_jobs};
+use warp_drive::service::jobs::{self, JobHandle};
+
+mod common;
+
+const SECRET: &str = "jobs-test-secret";
+
+fn setup() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+}
+
+/// A job that takes `steps` steps of 20ms, counting them in `done`
+fn slow_job(bucket: &str, steps: u64, done: Arc<AtomicU64>) -> i64 {
+    jobs::enqueue("test_slow", "jobs_user", bucket, serde_json::json!({ "steps": steps }), move |job: JobHandle| async move {
+        for step in 0..steps {
+            job.checkpoint()?;
+            actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+            done.store(step + 1, Ordering::SeqCst);
+            job.progress(step + 1, steps);
+        }
+        Ok(serde_json::json!({ "steps": steps }))
+    }).unwrap().id
+}
+
+fn job_request(uri: &str) -> test::TestRequest {
+    test::TestRequest::get().uri(uri).insert_header(("Authorization", format!("Bearer {}", SECRET)))
+}
+
+#[actix_web::test]
+async fn test_jobs_run_one_at_a_time_per_bucket() {
+    setup();
+    let app = test::init_service(App::new().service(list_jobs).service(get_job)).await;
+    let bucket = common::unique("jobs");
+    let (first_done, second_done) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    let first = slow_job(&bucket, 5, first_done.clone());
+    let second = slow_job(&bucket, 5, second_done.clone());
+
+    let state = |id: i64| {
+        let request = job_request(&format!("/admin/jobs/{}", id)).to_request();
+        let app = &app;
+        async move {
+            let resp = test::call_service(app, request).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let job: serde_json::Value = test::read_body_json(resp).await;
+            job["state"].as_str().unwrap().to_string()
+        }
+    };
+    // Nothing has run yet: the runner only gets a turn once this test awaits
+    let mut seen = vec![(jobs::get(first).unwrap().unwrap().state, jobs::get(second).unwrap().unwrap().state)];
+    for _ in 0..500 {
+        let states = (state(first).await, state(second).await);
+        assert_ne!(states, ("running".to_string(), "running".to_string()), "both jobs ran at once");
+        if seen.last() != Some(&states) {
+            seen.push(states.clone());
+        }
+        if states.1 == "done" {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+    }
+    let history = |states: Vec<&String>| {
+        let mut history: Vec<String> = states.into_iter().cloned().collect();
+        history.dedup();
+        history
+    };
+    let expected = vec!["queued".to_string(), "running".to_string(), "done".to_string()];
+    assert_eq!(history(seen.iter().map(|s| &s.0).collect()), expected, "{:?}", seen);
+    assert_eq!(history(seen.iter().map(|s| &s.1).collect()), expected, "{:?}", seen);
+    assert_eq!((first_done.load(Ordering::SeqCst), second_done.load(Ordering::SeqCst)), (5, 5));
+
+    let resp = test::call_service(&app, job_request(&format!("/admin/jobs/{}", first)).to_request()).await;
+    let job: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(job["kind"], "test_slow");
+    assert_eq!(job["bucket"], bucket.as_str());
+    assert_eq!(job["params"]["steps"], 5);
+    assert_eq!(job["progress"], 1.0);
+    assert_eq!(job["result"]["steps"], 5);
+    assert!(job["started_at"].is_string() && job["finished_at"].is_string(), "{}", job);
+
+    let resp = test::call_service(&app, job_request("/admin/jobs?state=done&limit=1000").to_request()).await;
+    let list: serde_json::Value = test::read_body_json(resp).await;
+    let ids: Vec<i64> = list["jobs"].as_array().unwrap().iter().map(|j| j["id"].as_i64().unwrap()).collect();
+    let (second_at, first_at) = (ids.iter().position(|id| *id == second), ids.iter().position(|id| *id == first));
+    assert!(second_at.unwrap() < first_at.unwrap(), "newest first: {:?}", ids);
+
+    let resp = test::call_service(&app, job_request("/admin/jobs/999999999").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, job_request("/admin/jobs?state=sleeping").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/admin/jobs").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_cancel_stops_a_running_job() {
+    setup();
+    let app = test::init_service(App::new().service(get_job).service(cancel_job)).await;
+    let bucket = common::unique("cancel");
+    let done = Arc::new(AtomicU64::new(0));
+    let id = slow_job(&bucket, 1000, done.clone());
+    let cancel = || test::TestRequest::post()
+        .uri(&format!("/admin/jobs/{}/cancel", id))
+        .insert_header(("Authorization", format!("Bearer {}", SECRET)))
+        .to_request();
+
+    while done.load(Ordering::SeqCst) < 3 {
+        actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+    }
+    let resp = test::call_service(&app, cancel()).await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+
+    let job = common::finished_job(&serde_json::json!({ "id": id })).await;
+    assert_eq!(job["state"], "cancelled", "{}", job);
+    assert!(job["result"].is_null() && job["error"].is_null(), "{}", job);
+    let progress = job["progress"].as_f64().unwrap();
+    assert!(progress > 0.0 && progress < 1.0, "{}", job);
+    let stopped_at = done.load(Ordering::SeqCst);
+    assert!(stopped_at < 1000);
+    actix_web::rt::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(done.load(Ordering::SeqCst), stopped_at, "no steps after the cancellation");
+
+    // Finished jobs cannot be cancelled again
+    let resp = test::call_service(&app, cancel()).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+}
+
+#[actix_web::test]
+async fn test_cancelled_queued_job_never_starts() {
+    setup();
+    let bucket = common::unique("queued");
+    let (running, waiting) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    let first = slow_job(&bucket, 5, running);
+    let second = slow_job(&bucket, 5, waiting.clone());
+    assert_eq!(jobs::cancel(second).unwrap().unwrap().state, "queued");
+
+    assert_eq!(common::finished_job(&serde_json::json!({ "id": first })).await["state"], "done");
+    let job = common::finished_job(&serde_json::json!({ "id": second })).await;
+    assert_eq!(job["state"], "cancelled");
+    assert!(job["started_at"].is_null(), "{}", job);
+    assert_eq!(waiting.load(Ordering::SeqCst), 0);
+}
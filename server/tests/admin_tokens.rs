@@ -0,0 +1,111 @@
+// Scoped admin tokens issued through /admin/tokens: scopes gate endpoints, user-restricted
+// tokens only reach their user, disabled tokens are rejected and only hashes are stored.
+
+use actix_web::{test, App, http::StatusCode};
+use rusqlite::Connection;
+use warp_drive::admin::{create_admin_token, disable_admin_token, gc, get_bucket_limits, list_admin_tokens, server_stats};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::unique;
+
+const SECRET: &str = "tokens-test-secret";
+
+fn setup() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+}
+
+fn bearer(req: test::TestRequest, token: &str) -> test::TestRequest {
+    req.insert_header(("Authorization", format!("Bearer {}", token)))
+}
+
+macro_rules! app {
+    () => {
+        test::init_service(
+            App::new()
+                .service(create_admin_token)
+                .service(list_admin_tokens)
+                .service(disable_admin_token)
+                .service(server_stats)
+                .service(gc)
+                .service(get_bucket_limits)
+        ).await
+    };
+}
+
+#[actix_web::test]
+async fn test_token_scopes_gate_endpoints() {
+    setup();
+    let app = app!();
+    let issue = |token: &str, body: serde_json::Value| {
+        let req = bearer(test::TestRequest::post().uri("/admin/tokens"), token).set_json(body).to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await }
+    };
+
+    let resp = issue(SECRET, serde_json::json!({ "scopes": ["stats"], "description": "dashboards" })).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let issued: serde_json::Value = test::read_body_json(resp).await;
+    let stats_token = issued["token"].as_str().unwrap().to_string();
+    assert_eq!(issued["scopes"], serde_json::json!(["stats"]));
+
+    let req = bearer(test::TestRequest::get().uri("/admin/stats"), &stats_token).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = bearer(test::TestRequest::post().uri(&format!("/admin/gc/u/{}", unique("gc"))), &stats_token).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+    // Token management needs the tokens scope, and a token cannot hand out scopes it lacks
+    assert_eq!(issue(&stats_token, serde_json::json!({ "scopes": ["stats"] })).await.status(), StatusCode::FORBIDDEN);
+    let resp = issue(SECRET, serde_json::json!({ "scopes": ["tokens", "stats"] })).await;
+    let issued: serde_json::Value = test::read_body_json(resp).await;
+    let manager = issued["token"].as_str().unwrap().to_string();
+    assert_eq!(issue(&manager, serde_json::json!({ "scopes": ["gc"] })).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(issue(&manager, serde_json::json!({ "scopes": ["root"] })).await.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(issue(&manager, serde_json::json!({ "scopes": ["stats"] })).await.status(), StatusCode::CREATED);
+
+    // Only hashes are stored
+    let conn = Connection::open(common::isolate().join("metadata").join("metadata.sqlite")).unwrap();
+    let stored: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM admin_tokens WHERE token_hash = ?1 OR token_id = ?1", [&stats_token], |row| row.get(0),
+    ).unwrap();
+    assert_eq!(stored, 0);
+}
+
+#[actix_web::test]
+async fn test_user_restricted_and_disabled_tokens() {
+    setup();
+    let app = app!();
+    let (alice, bob) = (unique("alice"), unique("bob"));
+    let bucket = unique("limits");
+    MetadataService::new(&alice).unwrap().create_bucket(&bucket).unwrap();
+    MetadataService::new(&bob).unwrap().create_bucket(&bucket).unwrap();
+
+    let req = bearer(test::TestRequest::post().uri("/admin/tokens"), SECRET)
+        .set_json(serde_json::json!({ "scopes": ["quota", "tokens"], "user": alice }))
+        .to_request();
+    let issued: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    let (token, token_id) = (issued["token"].as_str().unwrap().to_string(), issued["token_id"].as_str().unwrap().to_string());
+
+    let limits = |user: &str| bearer(test::TestRequest::get().uri(&format!("/admin/buckets/{}/{}/limits", user, bucket)), &token).to_request();
+    assert_eq!(test::call_service(&app, limits(&alice)).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, limits(&bob)).await.status(), StatusCode::FORBIDDEN);
+    let req = bearer(test::TestRequest::get().uri("/admin/stats"), &token).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    // It may not issue tokens for anyone else either
+    let req = bearer(test::TestRequest::post().uri("/admin/tokens"), &token)
+        .set_json(serde_json::json!({ "scopes": ["quota"], "user": bob }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    let req = bearer(test::TestRequest::get().uri("/admin/tokens"), &token).to_request();
+    let listed: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    let listed = listed["tokens"].as_array().unwrap();
+    assert!(listed.iter().all(|t| t["user"] == alice.as_str()), "{:?}", listed);
+    assert!(listed.iter().all(|t| t.get("token").is_none() && t.get("token_hash").is_none()));
+
+    let req = bearer(test::TestRequest::delete().uri(&format!("/admin/tokens/{}", token_id)), SECRET).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, limits(&alice)).await.status(), StatusCode::UNAUTHORIZED);
+    let req = bearer(test::TestRequest::get().uri("/admin/stats"), "wda_unknown").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,93 @@
+// Native API versions: the routes answer under /v1/ and /v2/ and unprefixed. Unprefixed
+// answers, errors included, carry Deprecation and Sunset; v2 answers writes with JSON and GET
+// with raw bytes by default. A duplicate PUT is 409 with a JSON body in every version.
+
+use actix_web::{test, web, App, http::StatusCode};
+use warp_drive::api::{capabilities, native_routes};
+use warp_drive::service::api_version::ApiVersion;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "versions_user";
+
+macro_rules! app {
+    () => {
+        test::init_service(
+            App::new()
+                .service(web::scope("/v1").app_data(ApiVersion::V1).configure(native_routes))
+                .service(web::scope("/v2").app_data(ApiVersion::V2).configure(native_routes))
+                .configure(native_routes)
+                .service(capabilities)
+        ).await
+    };
+}
+
+// One test: the deprecation dates are read from the environment
+#[actix_web::test]
+async fn test_versions_shape_answers_of_the_same_services() {
+    common::isolate();
+    std::env::set_var("NATIVE_API_DEPRECATION_DATE", "2026-11-01");
+    std::env::set_var("NATIVE_API_SUNSET_DATE", "2027-05-01");
+    let app = app!();
+    let bucket = unique("versions");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    let put = |prefix: &str, key: &str| test::TestRequest::post()
+        .uri(&format!("{}/put/{}", prefix, key))
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket.as_str()))
+        .set_payload(payload_of(b"versioned data"))
+        .to_request();
+
+    for prefix in ["", "/v1", "/v2"] {
+        let key = format!("dup{}", prefix.replace('/', "_"));
+        let created = test::call_service(&app, put(prefix, &key)).await;
+        assert_eq!(created.status(), StatusCode::OK, "{}", prefix);
+        let deprecation = created.headers().get("deprecation").map(|v| v.to_str().unwrap().to_string());
+        let body = test::read_body(created).await;
+        if prefix == "/v2" {
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!((body["key"].as_str(), body["bucket"].as_str()), (Some(key.as_str()), Some(bucket.as_str())));
+            assert_eq!(body["generation"], 1);
+        } else {
+            assert!(String::from_utf8_lossy(&body).starts_with("Data uploaded successfully"), "{:?}", body);
+        }
+        assert_eq!(deprecation.is_some(), prefix.is_empty(), "{}", prefix);
+
+        let duplicate = test::call_service(&app, put(prefix, &key)).await;
+        assert_eq!(duplicate.status(), StatusCode::CONFLICT, "{}", prefix);
+        if prefix.is_empty() {
+            assert_eq!(duplicate.headers().get("deprecation").unwrap(), "@1793491200");
+            assert_eq!(duplicate.headers().get("sunset").unwrap(), "Sat, 01 May 2027 00:00:00 GMT");
+        } else {
+            assert!(duplicate.headers().get("deprecation").is_none(), "{}", prefix);
+        }
+        let body: serde_json::Value = test::read_body_json(duplicate).await;
+        assert_eq!(body["error"], "KeyExists", "{}", prefix);
+    }
+
+    // The object was stored as FlatBuffers: v1 sends it so, v2 sends its bytes
+    let get = |prefix: &str| test::TestRequest::get()
+        .uri(&format!("{}/get/dup_v1", prefix))
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket.as_str()))
+        .to_request();
+    let v1 = test::call_service(&app, get("/v1")).await;
+    assert_eq!(v1.headers().get("x-warp-object-format").unwrap(), "native");
+    assert_eq!(test::read_body(v1).await, payload_of(b"versioned data"));
+    let v2 = test::call_service(&app, get("/v2")).await;
+    assert_eq!(v2.headers().get("x-warp-object-format").unwrap(), "raw");
+    assert_eq!(test::read_body(v2).await, &b"versioned data"[..]);
+
+    let missing = test::call_service(&app, test::TestRequest::get()
+        .uri("/v2/get/nothing-here")
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket.as_str()))
+        .to_request()).await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/capabilities").to_request()).await;
+    let document: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(document["api_versions"]["native"], serde_json::json!(["v1", "v2"]));
+}
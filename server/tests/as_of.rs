@@ -0,0 +1,111 @@
+// Reads as of a time: three versions and a delete marker written through S3 into a versioned
+// bucket, then native GET and list with `as_of` between each pair see the version current at
+// that time, a 404 before the first, and a 404 with `X-Warp-Object-State: deleted` after the
+// marker. Bad timestamps are 400. Requests to S3 are signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use chrono::{SecondsFormat, Utc};
+use std::sync::Once;
+use std::time::Duration;
+use warp_drive::api::native_routes;
+use warp_drive::s3::handlers::{s3_delete_object_handler, s3_put_object_handler};
+use warp_drive::service::api_version::ApiVersion;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "as-of-test-access";
+const SECRET_KEY: &str = "as-of-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() -> String {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    let bucket = unique("as-of");
+    let db = MetadataService::new("admin").unwrap();
+    db.create_bucket(&bucket).unwrap();
+    db.set_versioning_state(&bucket, "enabled").unwrap();
+    bucket
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str) -> test::TestRequest {
+    req.uri(path)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, "", UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+/// The current time, between rows written before and after
+async fn instant() -> String {
+    actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    now
+}
+
+#[actix_web::test]
+async fn test_get_and_list_as_of_each_version() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::delete().to(s3_delete_object_handler))
+            .service(web::scope("/v2").app_data(ApiVersion::V2).configure(native_routes))
+    ).await;
+    let path = format!("/s3/{}/doc", bucket);
+
+    let before = instant().await;
+    let mut times = Vec::new();
+    for body in ["first", "second", "third"] {
+        let req = signed(test::TestRequest::put(), "PUT", &path).set_payload(body).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        times.push(instant().await);
+    }
+    let req = signed(test::TestRequest::delete(), "DELETE", &path).to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let deleted = instant().await;
+
+    let get = |as_of: &str| test::TestRequest::get()
+        .uri(&format!("/v2/get/doc?format=raw&as_of={}", as_of))
+        .insert_header(("user", "admin"))
+        .insert_header(("bucket", bucket.as_str()))
+        .to_request();
+    let list = |as_of: &str| test::TestRequest::get()
+        .uri(&format!("/v2/list?as_of={}", as_of))
+        .insert_header(("user", "admin"))
+        .insert_header(("bucket", bucket.as_str()))
+        .to_request();
+
+    for (time, body) in times.iter().zip(["first", "second", "third"]) {
+        let resp = test::call_service(&app, get(time)).await;
+        assert_eq!(resp.status(), StatusCode::OK, "as of {}", time);
+        assert_eq!(test::read_body(resp).await, body.as_bytes(), "as of {}", time);
+        let listing: serde_json::Value = test::read_body_json(test::call_service(&app, list(time)).await).await;
+        assert_eq!(listing["objects"][0]["key"], "doc");
+        assert_eq!(listing["objects"][0]["size"], body.len());
+    }
+
+    // Before the first version the key did not exist
+    let resp = test::call_service(&app, get(&before)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(resp.headers().get("x-warp-object-state").is_none());
+
+    // After the delete marker it was deleted
+    let resp = test::call_service(&app, get(&deleted)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers().get("x-warp-object-state").unwrap(), "deleted");
+    for time in [&before, &deleted] {
+        let listing: serde_json::Value = test::read_body_json(test::call_service(&app, list(time)).await).await;
+        assert_eq!(listing["objects"], serde_json::json!([]), "as of {}", time);
+    }
+
+    for bad in ["2024-06-01", "2024-13-01T00:00:00Z", "soon"] {
+        assert_eq!(test::call_service(&app, get(bad)).await.status(), StatusCode::BAD_REQUEST, "{}", bad);
+        assert_eq!(test::call_service(&app, list(bad)).await.status(), StatusCode::BAD_REQUEST, "{}", bad);
+    }
+}
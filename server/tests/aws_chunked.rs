@@ -0,0 +1,111 @@
+// Streaming (aws-chunked) S3 uploads, signed with the admin access key so the requests pass
+// SigV4 verification. Admin credentials are process-wide, hence a binary of its own.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_get_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization, AMZ_DATE};
+
+const ACCESS_KEY: &str = "chunked-test-access";
+const SECRET_KEY: &str = "chunked-test-secret";
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+// Framing as sent by `aws s3 cp` for a 13-byte file: one signed data chunk, then the final
+// zero-length chunk (chunk signatures are not verified)
+const CAPTURED_BODY: &[u8] = b"d;chunk-signature=b474d8862b1487a5145d686f57f013e54db672cee1c953b3010fb58501ef5aa2\r\n\
+warpdrive!!!\n\r\n\
+0;chunk-signature=5be0b1c4f7ba5ad1d3e35f2d1d5ee3f0ec8f80b9a2a88b9ed9b9a0f1a1a4b1c2\r\n\
+\r\n";
+
+static SETUP: Once = Once::new();
+
+fn setup(bucket: &str) {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    MetadataService::new("admin").unwrap().create_bucket(bucket).ok();
+}
+
+fn chunked_put(path: &str, content_sha256: &str, decoded_len: usize, body: &[u8]) -> test::TestRequest {
+    test::TestRequest::put()
+        .uri(path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, "PUT", path, content_sha256)))
+        .insert_header(("x-amz-content-sha256", content_sha256))
+        .insert_header(("x-amz-date", AMZ_DATE))
+        .insert_header(("Content-Encoding", "aws-chunked"))
+        .insert_header(("x-amz-decoded-content-length", decoded_len.to_string()))
+        .set_payload(body.to_vec())
+}
+
+fn signed_get(path: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, "GET", path, EMPTY_PAYLOAD_HASH)))
+        .insert_header(("x-amz-content-sha256", EMPTY_PAYLOAD_HASH))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_put_stores_decoded_payload() {
+    setup("chunked-bucket");
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let path = "/s3/chunked-bucket/captured";
+
+    let req = chunked_put(path, "STREAMING-AWS4-HMAC-SHA256-PAYLOAD", 13, CAPTURED_BODY).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+    assert_eq!(etag, format!("\"{:x}\"", md5::compute(b"warpdrive!!!\n")));
+
+    let resp = test::call_service(&app, signed_get(path).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(&test::read_body(resp).await[..], b"warpdrive!!!\n");
+}
+
+#[actix_web::test]
+async fn test_put_verifies_trailing_checksum_and_length() {
+    setup("chunked-bucket");
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let path = "/s3/chunked-bucket/trailer";
+    let trailer_body = |crc: &str| format!(
+        "7\r\nstream \r\n5\r\nbody!\r\n0\r\nx-amz-checksum-crc32:{}\r\n\r\n", crc
+    ).into_bytes();
+    let crc = {
+        use base64::Engine as _;
+        base64::engine::general_purpose::STANDARD.encode(crc32fast::hash(b"stream body!").to_be_bytes())
+    };
+
+    let req = chunked_put(path, "STREAMING-UNSIGNED-PAYLOAD-TRAILER", 12, &trailer_body("AAAAAA=="))
+        .insert_header(("x-amz-trailer", "x-amz-checksum-crc32"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8_lossy(&test::read_body(resp).await).contains("BadDigest"));
+
+    let req = chunked_put(path, "STREAMING-UNSIGNED-PAYLOAD-TRAILER", 20, &trailer_body(&crc)).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8_lossy(&test::read_body(resp).await).contains("IncompleteBody"));
+
+    let req = chunked_put(path, "STREAMING-UNSIGNED-PAYLOAD-TRAILER", 12, &trailer_body(&crc))
+        .insert_header(("x-amz-trailer", "x-amz-checksum-crc32"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, signed_get(path).to_request()).await;
+    assert_eq!(&test::read_body(resp).await[..], b"stream body!");
+}
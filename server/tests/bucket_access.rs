@@ -0,0 +1,115 @@
+// Public-read and read-only bucket flags on the S3 API. Requests are signed with the admin
+// access key, which is process-wide configuration, hence a binary of its own.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use warp_drive::admin::{get_bucket_access, set_bucket_access};
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_get_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "access-test-access";
+const SECRET_KEY: &str = "access-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str) -> test::TestRequest {
+    req.uri(path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+fn set_access(bucket: &str, body: serde_json::Value) -> test::TestRequest {
+    test::TestRequest::put()
+        .uri(&format!("/admin/buckets/admin/{}/access", bucket))
+        .insert_header(("Authorization", format!("Bearer {}", SECRET_KEY)))
+        .set_json(body)
+}
+
+#[actix_web::test]
+async fn test_anonymous_reads_only_on_public_buckets() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .service(set_bucket_access)
+            .service(get_bucket_access)
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let (public, private) = (unique("public"), unique("private"));
+    let db = MetadataService::new("admin").unwrap();
+    for bucket in [&public, &private] {
+        db.create_bucket(bucket).unwrap();
+        let path = format!("/s3/{}/asset.css", bucket);
+        let req = signed(test::TestRequest::put(), "PUT", &path).set_payload("body{}").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    // Private until flagged
+    let req = test::TestRequest::get().uri(&format!("/s3/{}/asset.css", public)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+    let resp = test::call_service(&app, set_access(&public, serde_json::json!({ "public_read": true })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let flags: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(flags, serde_json::json!({ "public_read": true, "read_only": false }));
+
+    let req = test::TestRequest::get().uri(&format!("/s3/{}/asset.css", public)).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(&test::read_body(resp).await[..], b"body{}");
+
+    // Anonymous writes to the public bucket and reads elsewhere stay denied
+    let req = test::TestRequest::put().uri(&format!("/s3/{}/other.css", public)).set_payload("x").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    let req = test::TestRequest::get().uri(&format!("/s3/{}/asset.css", private)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+    let req = set_access("no-such-bucket-anywhere", serde_json::json!({ "public_read": true })).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_read_only_bucket_rejects_writes() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .service(set_bucket_access)
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let bucket = unique("frozen");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let path = format!("/s3/{}/report.csv", bucket);
+    let req = signed(test::TestRequest::put(), "PUT", &path).set_payload("a,b").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, set_access(&bucket, serde_json::json!({ "read_only": true })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = signed(test::TestRequest::put(), "PUT", &path).set_payload("c,d").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(String::from_utf8_lossy(&test::read_body(resp).await).contains("AccessDenied"));
+
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(&test::read_body(resp).await[..], b"a,b");
+
+    // Clearing the flag takes effect immediately
+    let resp = test::call_service(&app, set_access(&bucket, serde_json::json!({ "read_only": false })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = signed(test::TestRequest::put(), "PUT", &path).set_payload("c,d").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
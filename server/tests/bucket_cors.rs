@@ -0,0 +1,124 @@
+// S3 bucket CORS: PutBucketCors / GetBucketCors / DeleteBucketCors, preflights answered from
+// the bucket's rules, and Access-Control-* headers on actual requests from a matching origin.
+// S3 requests are signed with the admin access key, whose buckets belong to user "admin".
+
+use actix_web::{test, web, App, http::{Method, StatusCode}};
+use actix_web::dev::ServiceResponse;
+use std::sync::Once;
+use warp_drive::s3::handlers::{
+    s3_bucket_cors, s3_cors_not_configured_handler, s3_create_bucket_handler, s3_delete_bucket_handler,
+    s3_get_object_handler, s3_list_objects_handler, s3_put_object_handler,
+};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "cors-test-access";
+const SECRET_KEY: &str = "cors-test-secret";
+
+const APP_ORIGIN: &str = "https://app.example.com";
+const OTHER_ORIGIN: &str = "https://other.example.com";
+
+const CORS_XML: &str = "<CORSConfiguration><CORSRule>\
+    <AllowedOrigin>https://app.example.com</AllowedOrigin>\
+    <AllowedMethod>GET</AllowedMethod><AllowedMethod>PUT</AllowedMethod>\
+    <AllowedHeader>*</AllowedHeader>\
+    <ExposeHeader>ETag</ExposeHeader>\
+    <MaxAgeSeconds>300</MaxAgeSeconds>\
+    </CORSRule></CORSConfiguration>";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+fn preflight(path: &str, origin: &str, method: &str) -> test::TestRequest {
+    test::TestRequest::default().method(Method::OPTIONS).uri(path)
+        .insert_header(("Origin", origin))
+        .insert_header(("Access-Control-Request-Method", method))
+}
+
+fn header<B>(resp: &ServiceResponse<B>, name: &str) -> Option<String> {
+    resp.headers().get(name).map(|v| v.to_str().unwrap().to_string())
+}
+
+#[actix_web::test]
+async fn test_bucket_cors_rules_apply_to_matching_origins_only() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .wrap(actix_web::middleware::from_fn(s3_bucket_cors))
+            .service(web::resource("/s3/{bucket}")
+                .route(web::put().to(s3_create_bucket_handler))
+                .route(web::delete().to(s3_delete_bucket_handler))
+                .route(web::get().to(s3_list_objects_handler)))
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::get().to(s3_get_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::method(Method::OPTIONS).to(s3_cors_not_configured_handler))
+    ).await;
+    let bucket = unique("cors");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let bucket_path = format!("/s3/{}", bucket);
+    let object_path = format!("/s3/{}/upload.txt", bucket);
+
+    // Rules with a method S3 does not support are rejected
+    let bad = CORS_XML.replace("<AllowedMethod>PUT</AllowedMethod>", "<AllowedMethod>PATCH</AllowedMethod>");
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &bucket_path, "cors=").set_payload(bad).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8_lossy(&test::read_body(resp).await).contains("<Code>InvalidRequest</Code>"));
+
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &bucket_path, "cors=").set_payload(CORS_XML).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &bucket_path, "cors=").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(String::from_utf8_lossy(&test::read_body(resp).await).contains("<AllowedOrigin>https://app.example.com</AllowedOrigin>"));
+
+    // Preflight from the configured origin
+    let resp = test::call_service(&app, preflight(&object_path, APP_ORIGIN, "PUT").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "access-control-allow-origin").as_deref(), Some(APP_ORIGIN));
+    assert_eq!(header(&resp, "access-control-allow-methods").as_deref(), Some("GET, PUT"));
+    assert_eq!(header(&resp, "access-control-max-age").as_deref(), Some("300"));
+    let resp = test::call_service(&app, preflight(&object_path, OTHER_ORIGIN, "PUT").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(header(&resp, "access-control-allow-origin"), None);
+
+    // Actual requests
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &object_path, "")
+        .insert_header(("Origin", APP_ORIGIN)).set_payload("hello").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "access-control-allow-origin").as_deref(), Some(APP_ORIGIN));
+    assert_eq!(header(&resp, "access-control-expose-headers").as_deref(), Some("ETag"));
+    assert_eq!(header(&resp, "vary").as_deref(), Some("Origin"));
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &object_path, "")
+        .insert_header(("Origin", OTHER_ORIGIN)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "access-control-allow-origin"), None);
+
+    // Without a configuration there is no CORS at all
+    let resp = test::call_service(&app, signed(test::TestRequest::delete(), "DELETE", &bucket_path, "cors=").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &bucket_path, "cors=").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, preflight(&object_path, APP_ORIGIN, "PUT").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(header(&resp, "access-control-allow-origin"), None);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &object_path, "")
+        .insert_header(("Origin", APP_ORIGIN)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "access-control-allow-origin"), None);
+}
@@ -0,0 +1,124 @@
+// Native bucket grants: a read grantee can GET the owner's objects but not PUT (403), a write
+// grantee's PUT lands in the owner's bucket, and a revocation applies to the next request.
+// Grants are listed on the bucket and in the grantee's GET /buckets.
+
+use actix_web::{test, web, App, http::StatusCode};
+use warp_drive::api::native_routes;
+use warp_drive::service::api_version::ApiVersion;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{payload_of, unique};
+
+const OWNER: &str = "grants_owner";
+const READER: &str = "grants_reader";
+const WRITER: &str = "grants_writer";
+
+macro_rules! app {
+    () => {
+        test::init_service(App::new().service(web::scope("/v2").app_data(ApiVersion::V2).configure(native_routes))).await
+    };
+}
+
+fn put(user: &str, bucket: &str, key: &str, data: &[u8]) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri(&format!("/v2/put/{}", key))
+        .insert_header(("user", user))
+        .insert_header(("bucket", bucket))
+        .set_payload(payload_of(data))
+}
+
+fn get(user: &str, bucket: &str, key: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(&format!("/v2/get/{}", key))
+        .insert_header(("user", user))
+        .insert_header(("bucket", bucket))
+}
+
+fn grant(user: &str, bucket: &str, grantee: &str, perm: &str) -> test::TestRequest {
+    test::TestRequest::put()
+        .uri(&format!("/v2/grants/{}/{}?perm={}", bucket, grantee, perm))
+        .insert_header(("user", user))
+}
+
+fn revoke(user: &str, bucket: &str, grantee: &str) -> test::TestRequest {
+    test::TestRequest::delete()
+        .uri(&format!("/v2/grants/{}/{}", bucket, grantee))
+        .insert_header(("user", user))
+}
+
+#[actix_web::test]
+async fn test_read_grantee_can_get_but_not_put() {
+    common::isolate();
+    let app = app!();
+    let bucket = unique("shared_read");
+    MetadataService::new(OWNER).unwrap().create_bucket(&bucket).unwrap();
+    assert_eq!(test::call_service(&app, put(OWNER, &bucket, "doc", b"owner data").to_request()).await.status(), StatusCode::OK);
+
+    // Nothing granted yet: the reader's own namespace has no such object
+    assert_eq!(test::call_service(&app, get(READER, &bucket, "doc").to_request()).await.status(), StatusCode::NOT_FOUND);
+
+    assert_eq!(test::call_service(&app, grant(OWNER, &bucket, READER, "read").to_request()).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, get(READER, &bucket, "doc").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "owner data".as_bytes());
+
+    let resp = test::call_service(&app, put(READER, &bucket, "mine", b"reader data").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!((body["error"].as_str(), body["bucket"].as_str()), (Some("AccessDenied"), Some(bucket.as_str())));
+
+    // A read grantee cannot manage the bucket's grants or delete it
+    assert_eq!(test::call_service(&app, grant(READER, &bucket, WRITER, "write").to_request()).await.status(), StatusCode::FORBIDDEN);
+    let req = test::TestRequest::delete().uri(&format!("/v2/bucket/{}", bucket)).insert_header(("user", READER)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn test_write_grant_puts_into_owner_bucket_until_revoked() {
+    common::isolate();
+    let app = app!();
+    let bucket = unique("shared_write");
+    let other = unique("private");
+    let owner = MetadataService::new(OWNER).unwrap();
+    owner.create_bucket(&bucket).unwrap();
+    owner.create_bucket(&other).unwrap();
+
+    assert_eq!(test::call_service(&app, grant(OWNER, &bucket, WRITER, "write").to_request()).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, put(WRITER, &bucket, "report", b"from the writer").to_request()).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, get(OWNER, &bucket, "report").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "from the writer".as_bytes());
+    assert!(!MetadataService::new(WRITER).unwrap().bucket_exists(&bucket).unwrap());
+
+    // Copies stay within the granted bucket
+    let req = test::TestRequest::post()
+        .uri("/v2/copy/report/leak")
+        .insert_header(("user", WRITER))
+        .insert_header(("bucket", bucket.as_str()))
+        .insert_header(("dst-bucket", other.as_str()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+    let req = test::TestRequest::get().uri(&format!("/v2/grants/{}", bucket)).insert_header(("user", OWNER)).to_request();
+    let grants: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    assert_eq!(grants["grants"][0]["grantee"], WRITER);
+    assert_eq!(grants["grants"][0]["permission"], "write");
+
+    let req = test::TestRequest::get().uri("/v2/buckets").insert_header(("user", WRITER)).to_request();
+    let buckets: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    let granted = buckets["granted"].as_array().unwrap().iter().find(|b| b["name"] == bucket.as_str()).unwrap();
+    assert_eq!((granted["owner"].as_str(), granted["permission"].as_str()), (Some(OWNER), Some("write")));
+
+    assert_eq!(test::call_service(&app, revoke(OWNER, &bucket, WRITER).to_request()).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, revoke(OWNER, &bucket, WRITER).to_request()).await.status(), StatusCode::NOT_FOUND);
+    // Back in the writer's own namespace: the owner's object is gone from view
+    assert_eq!(test::call_service(&app, get(WRITER, &bucket, "report").to_request()).await.status(), StatusCode::NOT_FOUND);
+    let req = test::TestRequest::get()
+        .uri("/v2/get/report")
+        .insert_header(("user", WRITER))
+        .insert_header(("bucket", bucket.as_str()))
+        .insert_header(("bucket-owner", OWNER))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+}
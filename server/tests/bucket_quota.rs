@@ -0,0 +1,231 @@
+// Per-bucket object count and byte quotas, set through the admin API and enforced on native
+// and S3 writes. S3 requests are signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use warp_drive::admin::{get_bucket_limits, set_bucket_limits};
+use warp_drive::api::{append, delete, put};
+use warp_drive::s3::handlers::s3_put_object_handler;
+use warp_drive::metadata::sqlite_store::BucketLimits;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{AMZ_DATE, authorization, payload, unique, UNSIGNED_PAYLOAD};
+
+const USER: &str = "quota_user";
+const ACCESS_KEY: &str = "quota-test-access";
+const SECRET_KEY: &str = "quota-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+}
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket))
+}
+
+fn limits_request(req: test::TestRequest, user: &str, bucket: &str) -> test::TestRequest {
+    req.uri(&format!("/admin/buckets/{}/{}/limits", user, bucket))
+        .insert_header(("Authorization", format!("Bearer {}", SECRET_KEY)))
+}
+
+#[actix_web::test]
+async fn test_object_limit_frees_up_on_delete() {
+    setup();
+    let app = test::init_service(
+        App::new().service(put).service(delete).service(set_bucket_limits).service(get_bucket_limits)
+    ).await;
+    let bucket = unique("quota");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    let resp = test::call_service(&app, limits_request(test::TestRequest::put(), USER, &bucket)
+        .set_json(serde_json::json!({ "max_objects": 3 })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let put_key = |key: &str| native(test::TestRequest::post(), &format!("/put/{}", key), &bucket)
+        .set_payload(payload(&[b"12345"])).to_request();
+    for key in ["a", "b", "c"] {
+        assert_eq!(test::call_service(&app, put_key(key)).await.status(), StatusCode::OK);
+    }
+
+    let resp = test::call_service(&app, put_key("d")).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let error: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(error["error"], "QuotaExceeded");
+    assert_eq!(error["bucket"], bucket.as_str());
+    assert_eq!(error["limit"], "max_objects");
+    assert_eq!((error["max"].as_u64(), error["objects"].as_u64(), error["bytes"].as_u64()), (Some(3), Some(3), Some(15)), "{}", error);
+
+    // A delete frees its slot at once, before the deletion worker has run
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), "/delete/a", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, put_key("d")).await.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, limits_request(test::TestRequest::get(), USER, &bucket).to_request()).await;
+    let limits: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(limits, serde_json::json!({ "max_objects": 3, "max_bytes": null, "objects": 3, "bytes": 15 }));
+
+    // null lifts the limit
+    let resp = test::call_service(&app, limits_request(test::TestRequest::put(), USER, &bucket)
+        .set_json(serde_json::json!({ "max_objects": null })).to_request()).await;
+    let limits: serde_json::Value = test::read_body_json(resp).await;
+    assert!(limits["max_objects"].is_null(), "{}", limits);
+    assert_eq!(test::call_service(&app, put_key("e")).await.status(), StatusCode::OK);
+
+    let req = limits_request(test::TestRequest::get(), USER, "no-such-bucket-anywhere").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_appends_count_bytes_not_objects() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(append).service(set_bucket_limits)).await;
+    let bucket = unique("quota-bytes");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    let resp = test::call_service(&app, limits_request(test::TestRequest::put(), USER, &bucket)
+        .set_json(serde_json::json!({ "max_objects": 1, "max_bytes": 10 })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = native(test::TestRequest::post(), "/put/log", &bucket).set_payload(payload(&[b"1234"])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let append_data = |data: &'static [u8]| native(test::TestRequest::post(), "/append/log", &bucket)
+        .set_payload(payload(&[data])).to_request();
+    assert_eq!(test::call_service(&app, append_data(b"56789")).await.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, append_data(b"ab")).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let error: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(error["limit"], "max_bytes");
+    assert_eq!((error["objects"].as_u64(), error["bytes"].as_u64()), (Some(1), Some(9)), "{}", error);
+    assert_eq!(test::call_service(&app, append_data(b"0")).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_s3_put_over_quota_is_an_xml_error() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .service(set_bucket_limits)
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+    ).await;
+    let bucket = unique("quota-s3");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let resp = test::call_service(&app, limits_request(test::TestRequest::put(), "admin", &bucket)
+        .set_json(serde_json::json!({ "max_objects": 1 })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let put_object = |key: &str| {
+        let path = format!("/s3/{}/{}", bucket, key);
+        test::TestRequest::put().uri(&path)
+            .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, "PUT", &path, UNSIGNED_PAYLOAD)))
+            .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+            .insert_header(("x-amz-date", AMZ_DATE))
+            .set_payload("data")
+            .to_request()
+    };
+    assert_eq!(test::call_service(&app, put_object("one")).await.status(), StatusCode::OK);
+    // Overwriting an existing key adds no object
+    assert_eq!(test::call_service(&app, put_object("one")).await.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, put_object("two")).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let body = String::from_utf8_lossy(&test::read_body(resp).await).into_owned();
+    assert!(body.contains("<Code>QuotaExceeded</Code>"), "{}", body);
+}
+
+#[actix_web::test]
+async fn test_upload_parts_count_until_aborted_or_completed() {
+    setup();
+    let app = test::init_service(warp_drive::server::app()).await;
+    let bucket = unique("quota-parts");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&bucket).unwrap();
+    let usage = || async {
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/usage").insert_header(("user", USER)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let row = body["buckets"].as_array().unwrap().iter().find(|b| b["name"] == bucket.as_str()).unwrap().clone();
+        (row["objects_bytes"].as_u64().unwrap(), row["multipart_bytes"].as_u64().unwrap())
+    };
+    let open = |key: &str| {
+        let req = native(test::TestRequest::post(), &format!("/upload/{}", key), &bucket).to_request();
+        async {
+            let body: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+            body["session"].as_str().unwrap().to_string()
+        }
+    };
+    let part = |key: &str, session: &str, n: u32, size: usize| native(test::TestRequest::put(), &format!("/upload/{}/{}/{}", key, session, n), &bucket)
+        .set_payload(vec![1u8; size]).to_request();
+
+    // Parts count as they are stored and stop counting on abort
+    let session = open("dropped").await;
+    assert_eq!(test::call_service(&app, part("dropped", &session, 1, 100)).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, part("dropped", &session, 2, 50)).await.status(), StatusCode::OK);
+    assert_eq!(usage().await, (0, 150));
+    // Sending a part again counts its new size only
+    assert_eq!(test::call_service(&app, part("dropped", &session, 2, 30)).await.status(), StatusCode::OK);
+    assert_eq!(usage().await, (0, 130));
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), &format!("/upload/dropped/{}", session), &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(usage().await, (0, 0));
+
+    // Completing moves the bytes from the parts to the object
+    let session = open("kept").await;
+    assert_eq!(test::call_service(&app, part("kept", &session, 1, 100)).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, part("kept", &session, 2, 50)).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/upload/kept/{}/complete", session), &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(usage().await, (150, 0));
+
+    // Parts are refused past the byte quota, and completing does not count them twice
+    db.set_bucket_limits(&bucket, &BucketLimits { max_objects: None, max_bytes: Some(200) }).unwrap();
+    let session = open("last").await;
+    let resp = test::call_service(&app, part("last", &session, 1, 60)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let error: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!((error["error"].as_str(), error["limit"].as_str(), error["bytes"].as_u64()), (Some("QuotaExceeded"), Some("max_bytes"), Some(150)));
+    assert_eq!(test::call_service(&app, part("last", &session, 1, 40)).await.status(), StatusCode::OK);
+    assert_eq!(usage().await, (150, 40));
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/upload/last/{}/complete", session), &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(usage().await, (190, 0));
+}
+
+#[actix_web::test]
+async fn test_concurrent_parts_cannot_pass_the_byte_quota() {
+    setup();
+    let app = test::init_service(warp_drive::server::app()).await;
+    let bucket = unique("quota-race");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&bucket).unwrap();
+    db.set_bucket_limits(&bucket, &BucketLimits { max_objects: None, max_bytes: Some(200) }).unwrap();
+    let mut sessions = Vec::new();
+    for n in 0..4 {
+        let req = native(test::TestRequest::post(), &format!("/upload/key{}", n), &bucket).to_request();
+        let body: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+        sessions.push(body["session"].as_str().unwrap().to_string());
+    }
+
+    // Each part fits on its own, all four together do not
+    let parts = sessions.iter().enumerate().map(|(n, session)| {
+        let req = native(test::TestRequest::put(), &format!("/upload/key{}/{}/1", n, session), &bucket)
+            .set_payload(vec![1u8; 60]).to_request();
+        test::call_service(&app, req)
+    });
+    let statuses: Vec<StatusCode> = futures::future::join_all(parts).await.iter().map(|resp| resp.status()).collect();
+    assert_eq!(statuses.iter().filter(|s| **s == StatusCode::OK).count(), 3, "{:?}", statuses);
+    assert_eq!(statuses.iter().filter(|s| **s == StatusCode::FORBIDDEN).count(), 1, "{:?}", statuses);
+    assert_eq!(db.bucket_usage(&bucket).unwrap().bytes, 180);
+
+    // The insert refuses a part the check before its write let through
+    let err = db.upsert_multipart_part(&bucket, &sessions[0], 2, "etag", 30, &[], "").unwrap_err();
+    assert!(err.to_string().contains("max_bytes"), "{}", err);
+    assert_eq!(db.list_multipart_parts(&sessions[0]).unwrap().len(), 1);
+    db.upsert_multipart_part(&bucket, &sessions[0], 2, "etag", 20, &[], "").unwrap();
+    assert_eq!(db.bucket_usage(&bucket).unwrap().bytes, 200);
+}
@@ -0,0 +1,108 @@
+// Capability discovery: /capabilities, GET /s3/?capabilities and x-warp-features on HEAD
+// bucket. The document is read from the environment per request, except for the body limits
+// the app was built with, so tests that toggle settings hold ENV_LOCK; S3 requests are signed
+// with the admin access key.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::{Mutex, Once};
+use warp_drive::api::capabilities;
+use warp_drive::server::app;
+use warp_drive::s3::handlers::{s3_head_bucket_handler, s3_list_buckets_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "caps-test-access";
+const SECRET_KEY: &str = "caps-test-secret";
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    std::env::remove_var("STRICT_BUCKETS");
+    std::env::remove_var("MAX_PAYLOAD_BYTES");
+}
+
+#[actix_web::test]
+async fn test_document_follows_config() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(
+        App::new()
+            .service(capabilities)
+            .route("/s3/", web::get().to(s3_list_buckets_handler))
+    ).await;
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/capabilities").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let before: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(before["storage_backend"], "localxfs");
+    assert_eq!(before["metadata_backend"], "sqlite");
+    assert_eq!(before["api_versions"]["s3"][0], "2006-03-01");
+    assert_eq!(before["features"]["strict_buckets"], false);
+    assert_eq!(before["features"]["compression"], "none");
+
+    std::env::set_var("STRICT_BUCKETS", "true");
+    std::env::set_var("MAX_PAYLOAD_BYTES", "4096");
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/capabilities").to_request()).await;
+    let after: serde_json::Value = test::read_body_json(resp).await;
+    // The S3 form returns the same document, without credentials
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/s3/?capabilities").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let s3: serde_json::Value = test::read_body_json(resp).await;
+    setup();
+
+    assert_eq!(after["features"]["strict_buckets"], true);
+    assert_eq!(after["limits"]["max_object_size"], 4096);
+    assert_ne!(before["limits"]["max_object_size"], after["limits"]["max_object_size"]);
+    assert_eq!(s3, after);
+}
+
+#[actix_web::test]
+async fn test_body_limits_are_read_when_the_app_is_built() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    std::env::set_var("MAX_PAYLOAD_BYTES", "4096");
+    let app = test::init_service(app()).await;
+    std::env::set_var("MAX_PAYLOAD_BYTES", "8192");
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/capabilities").to_request()).await;
+    setup();
+    let document: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(document["limits"]["max_object_size"], 4096);
+}
+
+#[actix_web::test]
+async fn test_head_bucket_lists_enabled_features() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(
+        App::new().route("/s3/{bucket}", web::head().to(s3_head_bucket_handler))
+    ).await;
+    let bucket = unique("caps");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let path = format!("/s3/{}", bucket);
+    let head = || test::TestRequest::default().method(actix_web::http::Method::HEAD).uri(&path)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, "HEAD", &path, "", UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+        .to_request();
+
+    let resp = test::call_service(&app, head()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let features = resp.headers().get("x-warp-features").unwrap().to_str().unwrap().to_string();
+    assert!(features.split(',').any(|f| f == "range_reads"), "{}", features);
+    assert!(!features.split(',').any(|f| f == "strict_buckets"), "{}", features);
+
+    std::env::set_var("STRICT_BUCKETS", "true");
+    let resp = test::call_service(&app, head()).await;
+    setup();
+    let features = resp.headers().get("x-warp-features").unwrap().to_str().unwrap().to_string();
+    assert!(features.split(',').any(|f| f == "strict_buckets"), "{}", features);
+}
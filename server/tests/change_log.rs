@@ -0,0 +1,105 @@
+// Change log at /admin/changes: schema migrations applied at startup, configuration
+// snapshots and admin configuration changes. The database is created with an old schema
+// before first use, so this binary has a single test.
+
+use actix_web::{test, App, http::StatusCode};
+use rusqlite::Connection;
+use warp_drive::admin::{create_admin_token, list_changes, set_bucket_limits};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::startup;
+
+mod common;
+use common::unique;
+
+const SECRET: &str = "change-log-test-secret";
+
+/// The buckets table as it was before per-bucket quotas
+fn create_old_schema() {
+    let dir = common::isolate().join("metadata");
+    std::fs::create_dir_all(&dir).unwrap();
+    let conn = Connection::open(dir.join("metadata.sqlite")).unwrap();
+    conn.execute(
+        "CREATE TABLE buckets (
+            user                TEXT NOT NULL,
+            name                TEXT NOT NULL,
+            created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S.000Z', 'now')),
+            versioning_state    TEXT NOT NULL DEFAULT 'disabled',
+            location            TEXT DEFAULT '',
+            object_lock_enabled INTEGER NOT NULL DEFAULT 0,
+            public_read         INTEGER NOT NULL DEFAULT 0,
+            read_only           INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (user, name)
+        )",
+        [],
+    ).unwrap();
+}
+
+fn bearer(req: test::TestRequest, token: &str) -> test::TestRequest {
+    req.insert_header(("Authorization", format!("Bearer {}", token)))
+}
+
+fn find<'a>(changes: &'a [serde_json::Value], kind: &str, subject: &str) -> Vec<&'a serde_json::Value> {
+    changes.iter().filter(|c| c["kind"] == kind && c["subject"] == subject).collect()
+}
+
+#[actix_web::test]
+async fn test_migrations_config_and_quota_changes_are_logged() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+    create_old_schema();
+    startup::initialize().unwrap();
+    // An unchanged configuration is not snapshotted again
+    startup::initialize().unwrap();
+
+    let app = test::init_service(
+        App::new().service(create_admin_token).service(set_bucket_limits).service(list_changes)
+    ).await;
+    let req = bearer(test::TestRequest::post().uri("/admin/tokens"), SECRET)
+        .set_json(serde_json::json!({ "scopes": ["quota"], "description": "quota-bot" }))
+        .to_request();
+    let issued: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    let token = issued["token"].as_str().unwrap().to_string();
+
+    let (user, bucket) = (unique("owner"), unique("quota"));
+    MetadataService::new(&user).unwrap().create_bucket(&bucket).unwrap();
+    let req = bearer(test::TestRequest::put().uri(&format!("/admin/buckets/{}/{}/limits", user, bucket)), &token)
+        .set_json(serde_json::json!({ "max_objects": 10 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = bearer(test::TestRequest::get().uri("/admin/changes"), SECRET).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    assert!(!String::from_utf8_lossy(&body).contains(SECRET), "master key in the change log");
+    let changes: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+
+    let migration = find(&changes, "schema", "buckets.max_objects");
+    assert_eq!(migration.len(), 1, "{:?}", changes);
+    assert_eq!(migration[0]["actor"], "startup");
+    assert_eq!(migration[0]["before"], serde_json::Value::Null);
+    assert_eq!(migration[0]["after"]["sql"], "ALTER TABLE buckets ADD COLUMN max_objects INTEGER");
+    // Columns already present are not migrations
+    assert!(find(&changes, "schema", "buckets.public_read").is_empty());
+
+    let quota = find(&changes, "bucket_limits", &format!("{}/{}", user, bucket));
+    assert_eq!(quota.len(), 1, "{:?}", changes);
+    assert_eq!(quota[0]["actor"], "quota-bot");
+    assert_eq!(quota[0]["before"], serde_json::json!({ "max_objects": null, "max_bytes": null }));
+    assert_eq!(quota[0]["after"], serde_json::json!({ "max_objects": 10, "max_bytes": null }));
+    assert!(quota[0]["id"].as_i64() > migration[0]["id"].as_i64());
+
+    let config = find(&changes, "config", "server");
+    assert_eq!(config.len(), 1, "{:?}", changes);
+    assert_eq!(config[0]["after"]["WARPDRIVE_ADMIN_SECRET_KEY"], "<redacted>");
+    assert_eq!(config[0]["after"]["STORAGE_DIRECTORY"], common::isolate().join("storage").to_str().unwrap());
+
+    // `since` skips earlier entries
+    let since = quota[0]["changed_at"].as_str().unwrap().replace('+', "%2B");
+    let req = bearer(test::TestRequest::get().uri(&format!("/admin/changes?since={}", since)), SECRET).to_request();
+    let later: Vec<serde_json::Value> = test::read_body_json(test::call_service(&app, req).await).await;
+    assert!(later.iter().all(|c| c["id"].as_i64() >= quota[0]["id"].as_i64()));
+    assert!(!later.is_empty());
+    let req = bearer(test::TestRequest::get().uri("/admin/changes?since=yesterday"), SECRET).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
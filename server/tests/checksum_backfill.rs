@@ -0,0 +1,162 @@
+// Chunk checksum backfill: POST /admin/checksums/backfill fills in the CRC32C of chunks stored
+// without one, reports what it would do with verify_only, and continues after the last key an
+// interrupted run finished. Once backfilled, GET and fsck catch a chunk corrupted on disk.
+
+use actix_web::{test, App, http::StatusCode};
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::Once;
+use warp_drive::admin::backfill_checksums;
+use warp_drive::api::get;
+use warp_drive::metadata::{Metadata, MetadataStorage};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::service::checksums::crc32c;
+use warp_drive::service::fsck::check_bucket;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::storage_service::{StorageMode, StorageService};
+use warp_drive::service::user_context::UserContext;
+use warp_drive::storage::config::StorageConfig;
+
+mod common;
+use common::{finished_job, unique};
+
+const SECRET: &str = "checksum-test-secret";
+const USER: &str = "checksum_user";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+    });
+}
+
+/// A bucket whose objects were stored without chunk checksums: `a` and `c` in one chunk,
+/// `b` in three
+fn fixture() -> String {
+    let bucket = unique("checksums");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&bucket).unwrap();
+    let storage = StorageService::new();
+    let context = UserContext::with_bucket(USER.to_string(), bucket.clone());
+    let objects: [(&str, &[&[u8]]); 3] = [
+        ("a", &[b"first object"]),
+        ("b", &[b"chunk zero of b", b"chunk one of b", b"chunk two of b"]),
+        ("c", &[b"third object"]),
+    ];
+    for (key, chunks) in objects {
+        let mut extents = Vec::new();
+        for data in chunks {
+            extents.extend(storage.write_object(&context, data, StorageMode::RAW).unwrap());
+        }
+        let mut metadata = Metadata::from_offset_size_list(extents);
+        metadata.size = chunks.iter().map(|data| data.len() as u64).sum();
+        db.put_object_full(&bucket, key, metadata).unwrap();
+    }
+    bucket
+}
+
+fn checksums(bucket: &str, key: &str) -> Vec<Option<u32>> {
+    let metadata = SQLiteMetadataStore::new().get_metadata(USER, bucket, key).unwrap();
+    metadata.chunks.iter().map(|chunk| chunk.checksum).collect()
+}
+
+macro_rules! app {
+    () => {
+        test::init_service(App::new().service(backfill_checksums).service(get)).await
+    };
+}
+
+macro_rules! backfill {
+    ($app:expr, $query:expr) => {{
+        let req = test::TestRequest::post()
+            .uri(&format!("/admin/checksums/backfill?{}", $query))
+            .insert_header(("Authorization", format!("Bearer {}", SECRET)))
+            .to_request();
+        let resp = test::call_service(&$app, req).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let job = finished_job(&test::read_body_json(resp).await).await;
+        assert_eq!(job["state"], "done", "{}", job);
+        job["result"].clone()
+    }};
+}
+
+#[actix_web::test]
+async fn test_backfilled_checksums_are_checked_on_read() {
+    setup();
+    let app = app!();
+    let bucket = fixture();
+
+    let report = backfill!(app, format!("user={}&bucket={}&verify_only=true", USER, bucket));
+    assert_eq!((report["objects_missing"].as_u64(), report["chunks_checksummed"].as_u64()), (Some(3), Some(5)), "{}", report);
+    assert_eq!(report["objects_backfilled"], 0);
+    assert_eq!(checksums(&bucket, "b"), vec![None, None, None], "verify_only wrote checksums");
+
+    let report = backfill!(app, format!("user={}&bucket={}", USER, bucket));
+    assert_eq!((report["objects_missing"].as_u64(), report["objects_backfilled"].as_u64()), (Some(3), Some(3)), "{}", report);
+    assert_eq!(report["keys_scanned"], 3);
+    let store = StorageConfig::from_env().create_store();
+    for key in ["a", "b", "c"] {
+        let metadata = SQLiteMetadataStore::new().get_metadata(USER, &bucket, key).unwrap();
+        for chunk in &metadata.chunks {
+            let (offset, size) = chunk.extent();
+            assert_eq!(chunk.checksum, Some(crc32c(&store.read(USER, &bucket, offset, size).unwrap())), "{} {:?}", key, chunk);
+        }
+    }
+    // Nothing is left for another run
+    let report = backfill!(app, format!("user={}&bucket={}", USER, bucket));
+    assert_eq!((report["objects_missing"].as_u64(), report["bytes_read"].as_u64()), (Some(0), Some(0)));
+
+    // Corrupt the middle chunk of b in place
+    let (offset, _) = SQLiteMetadataStore::new().get_metadata(USER, &bucket, "b").unwrap().chunks[1].extent();
+    let (path, position) = store.backing_file(USER, &bucket, offset).unwrap();
+    let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(position)).unwrap();
+    file.write_all(b"XXXX").unwrap();
+    drop(file);
+
+    let get_req = |key: &str| test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket.as_str()))
+        .to_request();
+    let resp = test::call_service(&app, get_req("b")).await;
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "ChecksumMismatch");
+    assert_eq!(test::call_service(&app, get_req("a")).await.status(), StatusCode::OK);
+
+    let fsck = check_bucket(USER, &bucket).unwrap();
+    assert_eq!(fsck.problems.len(), 1, "{:?}", fsck.problems);
+    assert_eq!((fsck.problems[0].key.as_str(), fsck.problems[0].chunk), ("b", Some(1)));
+    assert!(fsck.problems[0].problem.contains("checksum"), "{}", fsck.problems[0].problem);
+}
+
+#[actix_web::test]
+async fn test_interrupted_backfill_resumes_after_the_last_key() {
+    setup();
+    let app = app!();
+    let bucket = fixture();
+    // A run that stopped after committing the batch ending at `a`
+    SQLiteMetadataStore::new().commit_chunk_checksums(USER, &bucket, &[], "a").unwrap();
+
+    let report = backfill!(app, format!("user={}&bucket={}", USER, bucket));
+    assert_eq!(report["resumed_after"], "a");
+    assert_eq!((report["keys_scanned"].as_u64(), report["objects_backfilled"].as_u64()), (Some(2), Some(2)), "{}", report);
+    assert_eq!(checksums(&bucket, "a"), vec![None]);
+    assert!(checksums(&bucket, "c")[0].is_some());
+
+    // Having reached the end, the next run starts from the first key again
+    let report = backfill!(app, format!("user={}&bucket={}", USER, bucket));
+    assert!(report.get("resumed_after").is_none(), "{}", report);
+    assert_eq!((report["keys_scanned"].as_u64(), report["objects_backfilled"].as_u64()), (Some(3), Some(1)), "{}", report);
+    assert!(checksums(&bucket, "a")[0].is_some());
+
+    let req = |query: String| test::TestRequest::post()
+        .uri(&format!("/admin/checksums/backfill?{}", query))
+        .insert_header(("Authorization", format!("Bearer {}", SECRET)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req(format!("user={}", USER))).await.status(), StatusCode::BAD_REQUEST);
+    let missing = test::call_service(&app, req(format!("user={}&bucket={}", USER, unique("missing")))).await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+}
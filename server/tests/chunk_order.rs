@@ -0,0 +1,157 @@
+// Chunk order is part of the GET contract: an object built by appends comes back with one
+// FlatBuffers file per chunk in append order, each exactly once and (v2) carrying its chunk
+// index, and raw reads lay the chunks end to end in the same order. Checked through the
+// buffered native and raw GETs, the read-ahead streams of `/download`, and again after
+// compaction has moved the chunks elsewhere in the bucket file. Under the v2 API native
+// payloads are v2 even for objects stored without names, so the indexes are always there.
+
+use actix_web::{test, http::Method, http::StatusCode};
+use flatbuffers::FlatBufferBuilder;
+use futures::StreamExt;
+use warp_drive::server::app;
+use warp_drive::service::compaction::{self, CompactionConfig};
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::object_service::ObjectService;
+use warp_drive::service::storage_service::StorageService;
+use warp_drive::service::user_context::UserContext;
+use warp_drive::util::flatbuffer_store_generated::store;
+use warp_drive::util::flatbuffer_store_v2_generated::store_v2;
+
+mod common;
+use common::unique;
+
+const USER: &str = "chunk_order_user";
+const CHUNKS: usize = 6;
+
+fn v2_payload(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut builder = FlatBufferBuilder::new();
+    let data = builder.create_vector(data);
+    let name = builder.create_string(name);
+    let file = store_v2::FileData::create(&mut builder, &store_v2::FileDataArgs {
+        data: Some(data),
+        name: Some(name),
+        ..Default::default()
+    });
+    let files = builder.create_vector(&[file]);
+    let list = store_v2::FileDataList::create(&mut builder, &store_v2::FileDataListArgs { files: Some(files) });
+    store_v2::finish_file_data_list_buffer(&mut builder, list);
+    builder.finished_data().to_vec()
+}
+
+fn v1_payload(data: &[u8]) -> Vec<u8> {
+    let mut builder = FlatBufferBuilder::new();
+    let data = builder.create_vector(data);
+    let file = store::FileData::create(&mut builder, &store::FileDataArgs { data: Some(data) });
+    let files = builder.create_vector(&[file]);
+    let list = store::FileDataList::create(&mut builder, &store::FileDataListArgs { files: Some(files) });
+    builder.finish(list, None);
+    builder.finished_data().to_vec()
+}
+
+/// Chunk `i`: its own fill byte and its own length, so a swap or a repeat shows
+fn chunk(i: usize) -> Vec<u8> {
+    vec![b'a' + i as u8; 8 * 1024 + i * 517]
+}
+
+fn native(method: Method, uri: &str, bucket: &str) -> test::TestRequest {
+    test::TestRequest::default().method(method).uri(uri)
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket))
+}
+
+macro_rules! body {
+    ($app:expr, $uri:expr, $bucket:expr) => {{
+        let resp = test::call_service($app, native(Method::GET, $uri, $bucket).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{}", $uri);
+        test::read_body(resp).await
+    }};
+}
+
+/// Names, chunk indexes and data of the files of a v2 payload, in payload order
+fn files(body: &[u8]) -> Vec<(String, u32, Vec<u8>)> {
+    store_v2::root_as_file_data_list(body).unwrap().files().unwrap().iter()
+        .map(|file| (file.name().unwrap().to_string(), file.index(), file.data().unwrap().bytes().to_vec()))
+        .collect()
+}
+
+macro_rules! assert_in_append_order {
+    ($app:expr, $bucket:expr, $context:expr) => {{
+        let expected: Vec<(String, u32, Vec<u8>)> = (0..CHUNKS).map(|i| (format!("part-{}", i), i as u32, chunk(i))).collect();
+        let concatenated: Vec<u8> = (0..CHUNKS).flat_map(chunk).collect();
+
+        assert_eq!(files(&body!($app, "/get/log", $bucket)), expected);
+        assert_eq!(files(&body!($app, "/get/log?chunks=2-4", $bucket)), expected[2..=4].to_vec());
+        assert_eq!(body!($app, "/get/log?format=raw", $bucket).to_vec(), concatenated);
+        assert_eq!(body!($app, "/download/log", $bucket).to_vec(), concatenated);
+
+        // The stream hands chunks out in order however far it reads ahead
+        let extents = ObjectService::new().get($context, "log", None).unwrap().extents;
+        for read_ahead in [0, 1, CHUNKS] {
+            let streamed: Vec<Vec<u8>> = StorageService::new().chunk_stream($context, extents.clone(), read_ahead)
+                .map(|data| data.unwrap().to_vec())
+                .collect().await;
+            assert_eq!(streamed, (0..CHUNKS).map(chunk).collect::<Vec<_>>(), "read ahead {}", read_ahead);
+        }
+        extents
+    }};
+}
+
+#[actix_web::test]
+async fn test_appended_chunks_come_back_in_append_order() {
+    common::isolate();
+    let app = test::init_service(app()).await;
+    let bucket = unique("chunk-order");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    let context = UserContext::with_bucket(USER.to_string(), bucket.clone());
+
+    // Other objects written between the appends leave gaps for compaction to close
+    for i in 0..CHUNKS {
+        let filler = native(Method::POST, &format!("/put/filler-{}", i), &bucket).set_payload(v2_payload("filler", &[0u8; 4096]));
+        assert_eq!(test::call_service(&app, filler.to_request()).await.status(), StatusCode::OK);
+        let route = if i == 0 { "put" } else { "append" };
+        let req = native(Method::POST, &format!("/{}/log", route), &bucket).set_payload(v2_payload(&format!("part-{}", i), &chunk(i)));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+    let before = assert_in_append_order!(&app, &bucket, &context);
+
+    for i in 0..CHUNKS {
+        let req = native(Method::DELETE, &format!("/delete/filler-{}", i), &bucket);
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+    DeletionWorker::new().process_deletions().await.unwrap();
+    let config = CompactionConfig { free_ratio: 0.0, ..CompactionConfig::default() };
+    let report = compaction::relocate_bucket(USER, &bucket, &config).await.unwrap();
+    assert_eq!(report.chunks_moved, CHUNKS, "{:?}", report);
+
+    let after = assert_in_append_order!(&app, &bucket, &context);
+    assert_ne!(after, before);
+}
+
+#[actix_web::test]
+async fn test_v2_native_payloads_carry_indexes_of_unnamed_chunks() {
+    common::isolate();
+    let app = test::init_service(app()).await;
+    let bucket = unique("chunk-index");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    for i in 0..3 {
+        let route = if i == 0 { "put" } else { "append" };
+        let req = native(Method::POST, &format!("/{}/plain", route), &bucket).set_payload(v1_payload(&chunk(i)));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+
+    // v1 keeps sending the v1 payload the object was stored as
+    let v1 = body!(&app, "/v1/get/plain", &bucket);
+    assert!(!store_v2::file_data_list_buffer_has_identifier(&v1));
+    assert_eq!(store::root_as_file_data_list(&v1).unwrap().files().unwrap().len(), 3);
+
+    let indexed = |body: &[u8]| -> Vec<(u32, Vec<u8>)> {
+        store_v2::root_as_file_data_list(body).unwrap().files().unwrap().iter()
+            .map(|file| (file.index(), file.data().unwrap().bytes().to_vec()))
+            .collect()
+    };
+    let v2 = body!(&app, "/v2/get/plain?format=native", &bucket);
+    assert!(store_v2::file_data_list_buffer_has_identifier(&v2));
+    assert_eq!(indexed(&v2), (0..3).map(|i| (i as u32, chunk(i))).collect::<Vec<_>>());
+    assert_eq!(indexed(&body!(&app, "/v2/get/plain?format=native&chunks=1-2", &bucket)), vec![(1, chunk(1)), (2, chunk(2))]);
+}
@@ -0,0 +1,207 @@
+// Single-shot CLI subcommands, run as the real binary against this test's scratch database
+// and storage: JSON on stdout and the exit status.
+
+use actix_web::{test, App, http::StatusCode};
+use std::path::PathBuf;
+use std::process::Command;
+use warp_drive::api::{get, put};
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{payload, unique};
+
+const USER: &str = "cli_user";
+
+/// Run the binary with `args`; returns the exit status and the parsed stdout.
+fn warp_drive(args: &[&str]) -> (i32, serde_json::Value) {
+    let scratch = common::isolate();
+    let output = Command::new(env!("CARGO_BIN_EXE_warp_drive"))
+        .args(args)
+        .current_dir(scratch)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let json = serde_json::from_str(&stdout)
+        .unwrap_or_else(|e| panic!("{:?}: stdout is not JSON ({}): {}\nstderr: {}", args, e, stdout, String::from_utf8_lossy(&output.stderr)));
+    (output.status.code().unwrap(), json)
+}
+
+/// Segment files of `bucket`
+fn bucket_files(bucket: &str) -> Vec<PathBuf> {
+    fn walk(dir: &std::path::Path, bucket: &str, out: &mut Vec<PathBuf>) {
+        for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                walk(&path, bucket, out);
+            } else if path.file_name().unwrap().to_string_lossy().starts_with(bucket) {
+                out.push(path);
+            }
+        }
+    }
+    let mut out = Vec::new();
+    walk(&common::isolate().join("storage"), bucket, &mut out);
+    out
+}
+
+#[actix_web::test]
+async fn test_stats_and_fsck_subcommands() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put)).await;
+    let bucket = unique("cli");
+    for (key, files) in [("one", vec![&b"first"[..], &b"second"[..]]), ("two", vec![&b"third"[..]])] {
+        let resp = test::call_service(&app, test::TestRequest::post().uri(&format!("/put/{}", key))
+            .insert_header(("user", USER)).insert_header(("bucket", bucket.clone()))
+            .set_payload(payload(&files)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let (code, stats) = warp_drive(&["stats"]);
+    assert_eq!(code, 0);
+    let usage = stats["per_bucket"].as_array().unwrap().iter()
+        .find(|b| b["user"] == USER && b["bucket"] == bucket.as_str())
+        .unwrap_or_else(|| panic!("{} missing from {}", bucket, stats));
+    assert_eq!(usage["objects"], 2);
+    assert_eq!(usage["bytes"], 16);
+    assert!(stats["objects"].as_u64().unwrap() >= 2);
+
+    let (code, report) = warp_drive(&["fsck", "--user", USER, "--bucket", &bucket]);
+    assert_eq!(code, 0, "{}", report);
+    assert_eq!(report["rows"], 2);
+    assert_eq!(report["chunks"], 3);
+    assert_eq!(report["bytes"], 16);
+    assert_eq!(report["problems"], serde_json::json!([]));
+
+    let archive = common::isolate().join(format!("{}.tar", bucket));
+    let (code, report) = warp_drive(&["export", "--user", USER, "--bucket", &bucket, "--out", archive.to_str().unwrap()]);
+    assert_eq!(code, 0, "{}", report);
+    assert_eq!(report["objects"], 2);
+    let mut entries: Vec<(String, Vec<u8>)> = tar::Archive::new(std::fs::File::open(&archive).unwrap()).entries().unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut data).unwrap();
+            (entry.path().unwrap().to_string_lossy().into_owned(), data)
+        })
+        .collect();
+    let index = entries.pop().unwrap();
+    assert_eq!(index.0, "metadata.ndjson");
+    assert_eq!(String::from_utf8_lossy(&index.1).lines().count(), 2);
+    entries.sort();
+    assert_eq!(entries, vec![
+        ("objects/one".to_string(), b"firstsecond".to_vec()),
+        ("objects/two".to_string(), b"third".to_vec()),
+    ]);
+
+    // Cut the bucket file short: the chunks past the end are reported
+    let files = bucket_files(&bucket);
+    assert_eq!(files.len(), 1, "{:?}", files);
+    std::fs::OpenOptions::new().write(true).open(&files[0]).unwrap().set_len(8).unwrap();
+    let (code, report) = warp_drive(&["fsck", "--user", USER, "--bucket", &bucket]);
+    assert_eq!(code, 4, "{}", report);
+    let problems = report["problems"].as_array().unwrap();
+    assert_eq!(problems.len(), 2, "{}", report);
+    assert!(problems.iter().all(|p| p["problem"].as_str().unwrap().contains("unreadable")), "{}", report);
+
+    // A failed command still answers in JSON
+    let (code, report) = warp_drive(&["fsck", "--user", USER, "--bucket", "no-such-bucket"]);
+    assert_eq!(code, 1);
+    assert_eq!(report["command"], "fsck");
+    assert!(report["error"].as_str().unwrap().contains("no-such-bucket"), "{}", report);
+}
+
+#[actix_web::test]
+async fn test_analyze_sizes_subcommand() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put)).await;
+    let bucket = unique("cli_sizes");
+    let (small, large) = (vec![1u8; 2 * 1024], vec![2u8; 40 * 1024]);
+    for (key, files) in [("small", vec![&small[..]]), ("large", vec![&large[..], &large[..]])] {
+        let resp = test::call_service(&app, test::TestRequest::post().uri(&format!("/put/{}", key))
+            .insert_header(("user", USER)).insert_header(("bucket", bucket.clone()))
+            .set_payload(payload(&files)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let (code, report) = warp_drive(&["analyze-sizes", "--user", USER, "--bucket", &bucket]);
+    assert_eq!(code, 0, "{}", report);
+    let count = |distribution: &str, le: &str| report[distribution]["buckets"].as_array().unwrap().iter()
+        .find(|b| b["le"] == le)
+        .unwrap_or_else(|| panic!("no bucket {} in {}", le, report))["count"]
+        .as_u64()
+        .unwrap();
+    assert_eq!((report["object_size"]["count"].as_u64(), report["object_size"]["sum"].as_u64()), (Some(2), Some(82 * 1024)));
+    assert_eq!((count("object_size", "4096"), count("object_size", "65536"), count("object_size", "262144")), (1, 1, 2));
+    assert_eq!((count("chunks_per_object", "1"), count("chunks_per_object", "2")), (1, 2));
+    assert_eq!((report["chunk_size"]["count"].as_u64(), report["chunk_size"]["max"].as_u64()), (Some(3), Some(40 * 1024)));
+    assert_eq!((count("chunk_size", "4096"), count("chunk_size", "65536"), count("chunk_size", "+Inf")), (1, 3, 3));
+
+    let (code, report) = warp_drive(&["analyze-sizes", "--user", USER, "--bucket", "no-such-bucket"]);
+    assert_eq!((code, report["command"].as_str()), (1, Some("analyze-sizes")));
+}
+
+#[actix_web::test]
+async fn test_import_dir_subcommand_resumes_from_its_manifest() {
+    common::isolate();
+    let app = test::init_service(App::new().service(get)).await;
+    let bucket = unique("cli_import");
+    let tree = common::isolate().join(&bucket);
+    let files: [(&str, &[u8]); 4] = [
+        ("top.txt", b"top level"),
+        ("docs/readme.md", b"# nested"),
+        ("docs/deep/er/data.bin", &[7u8; 3000]),
+        ("docs/empty", b""),
+    ];
+    for (path, data) in files {
+        let path = tree.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+    std::os::unix::fs::symlink(tree.join("top.txt"), tree.join("docs/link")).unwrap();
+
+    let args = ["import-dir", "--user", USER, "--bucket", &bucket, "--path", tree.to_str().unwrap(), "--prefix", "share/", "--concurrency", "2"];
+    let (code, report) = warp_drive(&args);
+    assert_eq!(code, 0, "{}", report);
+    assert_eq!(report["imported"], 4, "{}", report);
+    assert_eq!(report["bytes"], 3000 + 9 + 8);
+    assert_eq!(report["skipped"].as_array().unwrap().len(), 1, "{}", report);
+    assert!(report["skipped"][0].as_str().unwrap().ends_with("docs/link"), "{}", report);
+    assert_eq!(report["failed"], serde_json::json!([]));
+    assert!(std::path::Path::new(report["manifest"].as_str().unwrap()).exists());
+
+    for (path, data) in files {
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("/get/share/{}", path))
+            .insert_header(("user", USER)).insert_header(("bucket", bucket.clone())).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{}", path);
+        let body = test::read_body(resp).await;
+        let stored: Vec<u8> = root_as_file_data_list(&body).unwrap().files().map(|files| files.iter()
+            .flat_map(|file| file.data().map(|d| d.bytes().to_vec()).unwrap_or_default())
+            .collect()).unwrap_or_default();
+        assert_eq!(stored, data, "{}", path);
+    }
+
+    // A second run skips what the manifest lists and imports only the new file
+    std::fs::write(tree.join("docs/later.txt"), b"added later").unwrap();
+    let (code, report) = warp_drive(&args);
+    assert_eq!(code, 0, "{}", report);
+    assert_eq!(report["resumed"], 4, "{}", report);
+    assert_eq!(report["imported"], 1, "{}", report);
+    assert_eq!(report["existing"], 0, "{}", report);
+}
+
+#[actix_web::test]
+async fn test_self_test_subcommand() {
+    let dir = common::isolate().join("cli-self-test");
+    let (code, report) = warp_drive(&["self-test", "--dir", dir.to_str().unwrap()]);
+    assert_eq!(code, 0, "{}", report);
+    assert_eq!(report["passed"], true);
+    assert!(report["steps"].as_array().unwrap().iter().all(|s| s["ok"] == true), "{}", report);
+
+    // Storage that can't be created fails at that step, with the report on stdout
+    let file = common::isolate().join("cli-self-test-file");
+    std::fs::write(&file, b"").unwrap();
+    let (code, report) = warp_drive(&["self-test", "--dir", file.join("dir").to_str().unwrap()]);
+    assert_eq!(code, 1, "{}", report);
+    assert_eq!(report["passed"], false);
+    assert_eq!(report["steps"][0]["step"], "storage");
+    assert_eq!(report["steps"][0]["ok"], false);
+}
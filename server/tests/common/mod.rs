@@ -0,0 +1,120 @@
+// Helpers shared by the test binaries: a scratch directory for storage and metadata, SigV4
+// signing for requests made with the admin access key, FlatBuffers upload bodies, and waiting
+// for admin jobs.
+#![allow(dead_code)]
+
+use flatbuffers::FlatBufferBuilder;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use warp_drive::util::flatbuffer_store_generated::store::{FileData, FileDataArgs, FileDataList, FileDataListArgs};
+
+static SCRATCH: OnceLock<PathBuf> = OnceLock::new();
+
+extern "C" fn remove_scratch() {
+    if let Some(root) = SCRATCH.get() {
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
+
+/// Point `STORAGE_DIRECTORY` and `DB_FILE` into a scratch directory owned by this test binary,
+/// so nothing is written into the working tree. Call it before the first storage or metadata
+/// access; later calls return the same directory. The directory is removed at exit.
+pub fn isolate() -> &'static Path {
+    SCRATCH.get_or_init(|| {
+        let name = std::env::current_exe().ok()
+            .and_then(|exe| exe.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let root = std::env::temp_dir().join(format!("warpdrive-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(root.join("storage")).unwrap();
+        std::env::set_var("STORAGE_DIRECTORY", root.join("storage"));
+        std::env::set_var("DB_FILE", root.join("metadata").join("metadata.sqlite"));
+        // Tests look at what small objects leave in storage; inline_objects turns this back on
+        std::env::set_var("INLINE_THRESHOLD_BYTES", "0");
+        // SAFETY: registers a function without captured state; it runs once at process exit.
+        unsafe { libc::atexit(remove_scratch) };
+        root
+    })
+}
+
+pub const AMZ_DATE: &str = "20240601T120000Z";
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// SigV4 Authorization header over `x-amz-content-sha256` and `x-amz-date`.
+pub fn authorization(access_key: &str, secret_key: &str, method: &str, path: &str, payload_hash: &str) -> String {
+    authorization_with_query(access_key, secret_key, method, path, "", payload_hash)
+}
+
+/// As [`authorization`], for a request whose canonical query string is `query`
+/// (e.g. `uploads=` for `?uploads`).
+pub fn authorization_with_query(
+    access_key: &str,
+    secret_key: &str,
+    method: &str,
+    path: &str,
+    query: &str,
+    payload_hash: &str,
+) -> String {
+    let signed_headers = "x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{}\n{}\n{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        method, path, query, payload_hash, AMZ_DATE, signed_headers, payload_hash
+    );
+    let scope = format!("{}/us-east-1/s3/aws4_request", &AMZ_DATE[..8]);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        AMZ_DATE, scope, hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = hmac(format!("AWS4{}", secret_key).as_bytes(), &AMZ_DATE[..8]);
+    for part in ["us-east-1", "s3", "aws4_request"] {
+        key = hmac(&key, part);
+    }
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, signed_headers, hex::encode(hmac(&key, &string_to_sign))
+    )
+}
+
+/// FlatBuffers `FileDataList` body holding `files` in order, as `/put` and the object service
+/// take it.
+pub fn payload(files: &[&[u8]]) -> Vec<u8> {
+    let mut builder = FlatBufferBuilder::new();
+    let files: Vec<_> = files.iter().map(|data| {
+        let data_bytes = builder.create_vector(data);
+        FileData::create(&mut builder, &FileDataArgs { data: Some(data_bytes) })
+    }).collect();
+    let files = builder.create_vector(&files);
+    let file_list = FileDataList::create(&mut builder, &FileDataListArgs { files: Some(files) });
+    builder.finish(file_list, None);
+    builder.finished_data().to_vec()
+}
+
+/// [`payload`] of a single file.
+pub fn payload_of(data: &[u8]) -> Vec<u8> {
+    payload(&[data])
+}
+
+pub fn unique(prefix: &str) -> String {
+    format!("{}-{}", prefix, std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos())
+}
+
+/// Wait for the admin job in a 202 `accepted` body to finish and return it as JSON; its
+/// `result` holds the operation's report.
+pub async fn finished_job(accepted: &serde_json::Value) -> serde_json::Value {
+    let id = accepted["id"].as_i64().unwrap_or_else(|| panic!("not a job: {}", accepted));
+    for _ in 0..1000 {
+        let job = warp_drive::service::jobs::get(id).unwrap().unwrap();
+        if job.state != "queued" && job.state != "running" {
+            return serde_json::to_value(job).unwrap();
+        }
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("job {} did not finish", id);
+}
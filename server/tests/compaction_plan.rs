@@ -0,0 +1,90 @@
+// Compaction plan dry-run: after half a bucket's objects are deleted and freed, the plan
+// reports the freed share of the file, the live extents that would move and the resulting
+// size, and leaves the bucket's file and objects as they were.
+
+use actix_web::{test, App, http::StatusCode};
+use warp_drive::admin::compaction_plan;
+use warp_drive::metadata::Metadata;
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::storage_service::{StorageMode, StorageService};
+use warp_drive::service::user_context::UserContext;
+use warp_drive::storage::config::StorageConfig;
+
+mod common;
+use common::unique;
+
+const SECRET: &str = "compaction-plan-secret";
+const USER: &str = "compaction_plan_user";
+const OBJECTS: usize = 10;
+const SIZE: u64 = 2048;
+
+fn plan_request(uri: &str) -> test::TestRequest {
+    test::TestRequest::get().uri(uri).insert_header(("Authorization", format!("Bearer {}", SECRET)))
+}
+
+#[actix_web::test]
+async fn test_plan_after_deleting_half_the_objects() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+    let app = test::init_service(App::new().service(compaction_plan)).await;
+    let context = UserContext::with_bucket(USER.to_string(), unique("compaction-plan"));
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&context.bucket).unwrap();
+    let storage = StorageService::new();
+
+    let mut extents = Vec::new();
+    for i in 0..OBJECTS {
+        let data = vec![i as u8; SIZE as usize];
+        let written = storage.write_object(&context, &data, StorageMode::RAW).unwrap();
+        let mut metadata = Metadata::from_offset_size_list(written.clone());
+        metadata.size = SIZE;
+        db.put_object_full(&context.bucket, &format!("object-{}", i), metadata).unwrap();
+        extents.extend(written);
+    }
+    for i in (0..OBJECTS).step_by(2) {
+        storage.delete_object(&context, &format!("object-{}", i)).unwrap();
+    }
+    DeletionWorker::new().process_deletions().await.unwrap();
+
+    let uri = format!("/admin/compaction/plan/{}/{}", USER, context.bucket);
+    let resp = test::call_service(&app, plan_request(&uri).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let plan: serde_json::Value = test::read_body_json(resp).await;
+    let file_bytes = OBJECTS as u64 * SIZE;
+    assert_eq!(plan["file_bytes"], file_bytes);
+    assert_eq!(plan["free_bytes"], file_bytes / 2);
+    assert_eq!(plan["live_bytes"], file_bytes / 2);
+    assert_eq!(plan["free_ratio"], 0.5);
+    assert_eq!(plan["threshold"], 0.5);
+    assert_eq!(plan["triggered"], true);
+    // The first object is gone, so every remaining one sits behind a gap
+    let live: Vec<(u64, u64)> = extents.iter().copied().skip(1).step_by(2).collect();
+    assert_eq!(plan["moves"], serde_json::json!(live));
+    assert_eq!(plan["bytes_rewritten"], file_bytes / 2);
+    assert_eq!(plan["resulting_file_bytes"], file_bytes / 2);
+
+    // A stricter threshold is not reached: nothing would move
+    let resp = test::call_service(&app, plan_request(&format!("{}?threshold=0.9", uri)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let plan: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(plan["triggered"], false);
+    assert_eq!(plan["moves"], serde_json::json!([]));
+    assert_eq!(plan["bytes_rewritten"], 0);
+    assert_eq!(plan["resulting_file_bytes"], file_bytes);
+
+    // Nothing was touched
+    let store = StorageConfig::from_env().create_store();
+    assert_eq!(store.bucket_len(USER, &context.bucket).unwrap(), file_bytes);
+    for i in (1..OBJECTS).step_by(2) {
+        let metadata = db.get_object_full(&context.bucket, &format!("object-{}", i)).unwrap();
+        assert_eq!(storage.read_stored(&context, &metadata, StorageMode::RAW).unwrap(), vec![i as u8; SIZE as usize]);
+    }
+
+    for query in ["threshold=1.5", "threshold=half"] {
+        let resp = test::call_service(&app, plan_request(&format!("{}?{}", uri, query)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+    let resp = test::call_service(&app, plan_request(&format!("/admin/compaction/plan/{}/{}", USER, unique("missing"))).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
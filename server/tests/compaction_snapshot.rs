@@ -0,0 +1,106 @@
+// Compaction moving live data while it is being read: a 3-chunk object is relocated several
+// times while threads GET it in a loop. Every pass is published in one transaction, so each
+// GET sees all three chunks before a move or all three after it and reads the same bytes.
+
+use bytes::Bytes;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use warp_drive::service::compaction::{self, CompactionConfig};
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::object_service::ObjectService;
+use warp_drive::service::user_context::UserContext;
+
+mod common;
+use common::{payload, payload_of, unique};
+
+const USER: &str = "snapshot_user";
+
+fn chunk(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+#[actix_web::test]
+async fn test_gets_see_whole_passes_while_compaction_moves_an_object() {
+    common::isolate();
+    let context = UserContext::with_bucket(USER.to_string(), unique("snapshot"));
+    let objects = ObjectService::new();
+    objects.put(&context, "filler", async { Ok(payload_of(&chunk(1, 64 * 1024)).into()) }, None).await.unwrap();
+    let chunks: Vec<Vec<u8>> = (2..5u8).map(|seed| chunk(seed, 16 * 1024)).collect();
+    let expected = Bytes::from(chunks.concat());
+    objects.put(&context, "moving", async { Ok(payload(&chunks.iter().map(Vec::as_slice).collect::<Vec<_>>()).into()) }, None).await.unwrap();
+    // Freeing the filler leaves the object behind a gap
+    objects.delete(&context, "filler").await.unwrap();
+    DeletionWorker::new().process_deletions().await.unwrap();
+    let before = objects.get(&context, "moving", None).unwrap().extents;
+    assert_eq!(before.len(), 3);
+
+    let done = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicUsize::new(0));
+    let readers: Vec<_> = (0..4).map(|_| {
+        let (context, expected, done, reads) = (context.clone(), expected.clone(), Arc::clone(&done), Arc::clone(&reads));
+        std::thread::spawn(move || {
+            let objects = ObjectService::new();
+            while !done.load(Ordering::SeqCst) {
+                let read = objects.get(&context, "moving", None).unwrap();
+                let body = objects.read_raw(&context, &read, None).unwrap();
+                assert_eq!(body, expected, "GET mixed extents {:?}", read.extents);
+                reads.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+    }).collect();
+    // Let the readers get going before anything moves; a reader that failed never counts
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while reads.load(Ordering::SeqCst) == 0 {
+        assert!(Instant::now() < deadline, "no GET finished within 10s");
+        std::thread::yield_now();
+    }
+
+    // Each round finds the object behind the extents the previous one queued
+    let config = CompactionConfig { free_ratio: 0.0, ..CompactionConfig::default() };
+    let mut seen = vec![before.clone()];
+    for _ in 0..5 {
+        let report = compaction::relocate_bucket(USER, &context.bucket, &config).await.unwrap();
+        assert_eq!((report.passes, report.rows_rewritten, report.rows_skipped), (1, 1, 0), "{:?}", report);
+        assert_eq!((report.chunks_moved, report.bytes_moved), (3, 48 * 1024));
+        seen.push(objects.get(&context, "moving", None).unwrap().extents);
+    }
+    done.store(true, Ordering::SeqCst);
+    for reader in readers {
+        reader.join().expect("a GET returned other bytes");
+    }
+    for pair in seen.windows(2) {
+        assert_ne!(pair[0], pair[1], "every round moves the object");
+    }
+
+    // The extents left behind are queued and freed like any other deletion
+    DeletionWorker::new().process_deletions().await.unwrap();
+    let read = objects.get(&context, "moving", None).unwrap();
+    assert_eq!(objects.read_raw(&context, &read, None).unwrap(), expected);
+}
+
+#[actix_web::test]
+async fn test_passes_are_bounded() {
+    common::isolate();
+    let context = UserContext::with_bucket(USER.to_string(), unique("passes"));
+    let objects = ObjectService::new();
+    objects.put(&context, "filler", async { Ok(payload_of(&chunk(1, 32 * 1024)).into()) }, None).await.unwrap();
+    for (i, key) in ["a", "b", "c"].iter().enumerate() {
+        let (first, second) = (chunk(i as u8 + 2, 8 * 1024), chunk(i as u8 + 5, 8 * 1024));
+        objects.put(&context, key, async { Ok(payload(&[&first, &second]).into()) }, None).await.unwrap();
+    }
+    objects.delete(&context, "filler").await.unwrap();
+    DeletionWorker::new().process_deletions().await.unwrap();
+    let bodies: Vec<Bytes> = ["a", "b", "c"].iter()
+        .map(|key| objects.read_raw(&context, &objects.get(&context, key, None).unwrap(), None).unwrap())
+        .collect();
+
+    // Each object alone fills a pass
+    let config = CompactionConfig { free_ratio: 0.0, pass_bytes: 16 * 1024 };
+    let report = compaction::relocate_bucket(USER, &context.bucket, &config).await.unwrap();
+    assert_eq!((report.passes, report.rows_rewritten, report.chunks_moved), (3, 3, 6), "{:?}", report);
+    for (key, body) in ["a", "b", "c"].iter().zip(&bodies) {
+        let read = objects.get(&context, key, None).unwrap();
+        assert_eq!(&objects.read_raw(&context, &read, None).unwrap(), body);
+    }
+}
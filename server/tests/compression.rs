@@ -0,0 +1,152 @@
+// Accept-Encoding negotiation on S3 GetObject and native raw GET: text-like objects are
+// compressed for clients that ask, binary ones and ranges are not, and objects uploaded
+// compressed are served as stored. S3 requests are signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::io::Read;
+use std::sync::Once;
+use warp_drive::api::get;
+use warp_drive::s3::handlers::{s3_get_object_handler, s3_put_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "compression-test-access";
+const SECRET_KEY: &str = "compression-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() -> String {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    let bucket = unique("compress");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    bucket
+}
+
+fn s3(req: test::TestRequest, method: &str, bucket: &str, key: &str) -> test::TestRequest {
+    let path = format!("/s3/{}/{}", bucket, key);
+    req.uri(&path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, &path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+/// A JSON document of a few KiB
+fn document() -> Vec<u8> {
+    let rows: Vec<_> = (0..200).map(|i| serde_json::json!({ "id": i, "name": format!("row {}", i) })).collect();
+    serde_json::to_vec(&rows).unwrap()
+}
+
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    flate2::read::GzDecoder::new(data).read_to_end(&mut out).unwrap();
+    out
+}
+
+macro_rules! app {
+    () => {
+        test::init_service(
+            App::new()
+                .service(get)
+                .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+                .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+        ).await
+    };
+}
+
+#[actix_web::test]
+async fn test_json_is_gzipped_only_when_requested() {
+    let bucket = setup();
+    let app = app!();
+    let json = document();
+    for (key, content_type) in [("doc.json", "application/json"), ("blob.bin", "application/octet-stream")] {
+        let req = s3(test::TestRequest::put(), "PUT", &bucket, key)
+            .insert_header(("Content-Type", content_type))
+            .set_payload(json.clone())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    let get_json = |accept: Option<&str>| {
+        let mut req = s3(test::TestRequest::get(), "GET", &bucket, "doc.json");
+        if let Some(accept) = accept {
+            req = req.insert_header(("Accept-Encoding", accept.to_string()));
+        }
+        let req = req.to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await }
+    };
+    let resp = get_json(Some("gzip")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "gzip");
+    assert_eq!(resp.headers().get("Vary").unwrap(), "Accept-Encoding");
+    let body = test::read_body(resp).await;
+    assert!(body.len() < json.len());
+    assert_eq!(gunzip(&body), json);
+
+    for accept in [None, Some("identity"), Some("br")] {
+        let resp = get_json(accept).await;
+        assert!(resp.headers().get("Content-Encoding").is_none(), "{:?}", accept);
+        assert_eq!(test::read_body(resp).await, json);
+    }
+
+    // Ranges and binary types are sent as stored
+    let req = s3(test::TestRequest::get(), "GET", &bucket, "doc.json")
+        .insert_header(("Accept-Encoding", "gzip"))
+        .insert_header(("Range", "bytes=0-9"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert!(resp.headers().get("Content-Encoding").is_none());
+    let req = s3(test::TestRequest::get(), "GET", &bucket, "blob.bin").insert_header(("Accept-Encoding", "gzip")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get("Content-Encoding").is_none());
+    assert_eq!(test::read_body(resp).await, json);
+
+    // Native GET answers raw objects the same way
+    let req = test::TestRequest::get().uri("/get/doc.json")
+        .insert_header(("user", "admin")).insert_header(("bucket", bucket.clone()))
+        .insert_header(("Accept-Encoding", "gzip, deflate"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "gzip");
+    assert_eq!(gunzip(&test::read_body(resp).await), json);
+}
+
+#[actix_web::test]
+async fn test_zstd_stored_object_is_served_without_recompression() {
+    let bucket = setup();
+    let app = app!();
+    let json = document();
+    let stored = zstd::encode_all(&json[..], 3).unwrap();
+    let req = s3(test::TestRequest::put(), "PUT", &bucket, "doc.json.zst")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Content-Encoding", "zstd"))
+        .set_payload(stored.clone())
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = s3(test::TestRequest::get(), "GET", &bucket, "doc.json.zst")
+        .insert_header(("Accept-Encoding", "gzip, zstd"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "zstd");
+    let body = test::read_body(resp).await;
+    assert_eq!(body, stored, "the stored bytes, not a second compression");
+    assert_eq!(zstd::decode_all(&body[..]).unwrap(), json);
+
+    let req = test::TestRequest::get().uri("/get/doc.json.zst")
+        .insert_header(("user", "admin")).insert_header(("bucket", bucket.clone()))
+        .insert_header(("Accept-Encoding", "zstd"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "zstd");
+    assert_eq!(test::read_body(resp).await, stored);
+}
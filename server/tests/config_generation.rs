@@ -0,0 +1,93 @@
+// Configuration changes bump the generation, and changes another process makes to the shared
+// database (emulated with a second SQLite connection) reach this process's caches within one
+// revalidation interval.
+
+use actix_web::{test, App};
+use rusqlite::{params, Connection};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use warp_drive::api::healthz;
+use warp_drive::metadata::sqlite_store::BucketAccess;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::{bucket_access, maintenance};
+
+mod common;
+use common::unique;
+
+const INTERVAL: Duration = Duration::from_millis(100);
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("CONFIG_REVALIDATE_INTERVAL_MS", INTERVAL.as_millis().to_string());
+    });
+}
+
+/// A connection of its own to the metadata database, as a second process would open
+fn other_process() -> Connection {
+    let conn = Connection::open(common::isolate().join("metadata").join("metadata.sqlite")).unwrap();
+    conn.busy_timeout(Duration::from_secs(5)).unwrap();
+    conn
+}
+
+fn generation(conn: &Connection) -> i64 {
+    conn.query_row("SELECT generation FROM config_generation WHERE id = 1", [], |row| row.get(0)).unwrap()
+}
+
+/// Run `statement` and bump the generation in one transaction, as the store does
+fn change(conn: &mut Connection, statement: &str, values: impl rusqlite::Params) {
+    let tx = conn.transaction().unwrap();
+    tx.execute(statement, values).unwrap();
+    tx.execute("UPDATE config_generation SET generation = generation + 1 WHERE id = 1", []).unwrap();
+    tx.commit().unwrap();
+}
+
+/// Poll `observed` until it holds, failing after a few revalidation intervals
+fn eventually(what: &str, observed: impl Fn() -> bool) -> Duration {
+    let start = Instant::now();
+    while !observed() {
+        assert!(start.elapsed() < INTERVAL * 10, "{} not observed after {:?}", what, start.elapsed());
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    start.elapsed()
+}
+
+#[actix_web::test]
+async fn test_changes_from_another_process_reach_cached_readers() {
+    setup();
+    let user = unique("owner");
+    let bucket = unique("shared");
+    let db = MetadataService::new(&user).unwrap();
+    db.create_bucket(&bucket).unwrap();
+    let mut other = other_process();
+
+    // Changes through this process's store bump the generation
+    let before = generation(&other);
+    assert!(db.set_bucket_access(&bucket, &BucketAccess { public_read: false, read_only: false }).unwrap());
+    assert_eq!(generation(&other), before + 1);
+    assert!(!bucket_access::access(&user, &bucket).unwrap().read_only);
+
+    // The other process locks the bucket; the cached flags follow within the window
+    change(&mut other, "UPDATE buckets SET read_only = 1 WHERE user = ?1 AND name = ?2", params![user, bucket]);
+    let waited = eventually("read_only flag", || bucket_access::access(&user, &bucket).unwrap().read_only);
+    assert!(waited <= INTERVAL * 5, "took {:?}", waited);
+
+    // ...and maintenance mode
+    assert!(!maintenance::is_read_only());
+    change(
+        &mut other,
+        "INSERT OR REPLACE INTO maintenance (id, mode, changed_at, changed_by, until) VALUES (1, 'read_only', ?1, 'other', NULL)",
+        params![chrono::Utc::now().to_rfc3339()],
+    );
+    eventually("maintenance mode", maintenance::is_read_only);
+    change(&mut other, "DELETE FROM maintenance WHERE id = 1", []);
+    eventually("maintenance lifted", || !maintenance::is_read_only());
+
+    // /healthz reports the generation the caches were validated against
+    let app = test::init_service(App::new().service(healthz)).await;
+    let expected = generation(&other);
+    let body: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/healthz").to_request()).await;
+    assert_eq!(body["config_generation"], expected);
+}
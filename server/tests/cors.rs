@@ -0,0 +1,77 @@
+// Server-wide CORS from CORS_ALLOWED_ORIGINS. The setting is read per request, so tests hold
+// ENV_LOCK while it is set.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{middleware, test, App, http::{Method, StatusCode}};
+use std::sync::Mutex;
+use warp_drive::api::{get, put};
+use warp_drive::util::cors::cors;
+
+mod common;
+use common::payload_of;
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+const ALLOWED: &str = "https://app.example.com";
+
+fn preflight(origin: &str, uri: &str) -> test::TestRequest {
+    test::TestRequest::default().method(Method::OPTIONS).uri(uri)
+        .insert_header(("Origin", origin))
+        .insert_header(("Access-Control-Request-Method", "GET"))
+        .insert_header(("Access-Control-Request-Headers", "user, bucket"))
+}
+
+#[actix_web::test]
+async fn test_preflight_only_for_allowed_origins() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    let app = test::init_service(App::new().wrap(middleware::from_fn(cors)).service(get)).await;
+
+    // Off by default
+    std::env::remove_var("CORS_ALLOWED_ORIGINS");
+    let resp = test::call_service(&app, preflight(ALLOWED, "/get/key").to_request()).await;
+    assert!(!resp.headers().contains_key("access-control-allow-origin"));
+
+    std::env::set_var("CORS_ALLOWED_ORIGINS", format!("{}, https://other.example.com", ALLOWED));
+    let allowed = test::call_service(&app, preflight(ALLOWED, "/get/key").to_request()).await;
+    let denied = test::call_service(&app, preflight("https://evil.example.com", "/get/key").to_request()).await;
+    // S3 routes are covered too, even when nothing is registered for OPTIONS
+    let s3 = test::call_service(&app, preflight(ALLOWED, "/s3/bucket/key").to_request()).await;
+    std::env::remove_var("CORS_ALLOWED_ORIGINS");
+
+    assert_eq!(allowed.status(), StatusCode::NO_CONTENT);
+    let headers = allowed.headers();
+    assert_eq!(headers.get("access-control-allow-origin").unwrap(), ALLOWED);
+    assert!(headers.get("access-control-allow-methods").unwrap().to_str().unwrap().contains("GET"));
+    assert_eq!(headers.get("access-control-allow-headers").unwrap(), "user, bucket");
+    assert_eq!(s3.status(), StatusCode::NO_CONTENT);
+
+    assert!(denied.headers().keys().all(|name| !name.as_str().starts_with("access-control-")),
+            "{:?}", denied.headers());
+}
+
+#[actix_web::test]
+async fn test_response_carries_allow_and_expose_headers() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    let app = test::init_service(App::new().wrap(middleware::from_fn(cors)).service(put).service(get)).await;
+    let key = common::unique("cors");
+    let resp = test::call_service(&app, test::TestRequest::post().uri(&format!("/put/{}", key))
+        .insert_header(("user", "cors_user")).set_payload(payload_of(b"data")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    std::env::set_var("CORS_ALLOWED_ORIGINS", ALLOWED);
+    let get_from = |origin: &str| test::TestRequest::get().uri(&format!("/get/{}", key))
+        .insert_header(("user", "cors_user")).insert_header(("Origin", origin.to_string())).to_request();
+    let allowed = test::call_service(&app, get_from(ALLOWED)).await;
+    let denied = test::call_service(&app, get_from("https://evil.example.com")).await;
+    std::env::remove_var("CORS_ALLOWED_ORIGINS");
+
+    assert_eq!(allowed.status(), StatusCode::OK);
+    assert_eq!(allowed.headers().get("access-control-allow-origin").unwrap(), ALLOWED);
+    let exposed = allowed.headers().get("access-control-expose-headers").unwrap().to_str().unwrap().to_lowercase();
+    for name in ["user", "bucket", "x-request-id", "etag", "x-warp-object-state"] {
+        assert!(exposed.split(", ").any(|h| h == name), "{} missing from {}", name, exposed);
+    }
+    assert!(!denied.headers().contains_key("access-control-allow-origin"));
+}
@@ -0,0 +1,136 @@
+// The CPU pool: while a large read works through a slow transform on the pool, small reads on
+// the same (single-threaded) worker keep being answered; run inline, the large read holds
+// them up. Objects written and read through the pool with encryption, deduplication and
+// chunk checksums stacked come back intact, and a failed checksum keeps its error.
+
+use actix_web::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use warp_drive::service::checksums;
+use warp_drive::service::error::ServiceError;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::object_service::{ObjectRead, ObjectService};
+use warp_drive::service::storage_service::StorageService;
+use warp_drive::service::user_context::UserContext;
+use warp_drive::storage::mock_store::MockBinaryStore;
+use warp_drive::storage::Storage;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{payload, unique};
+
+const USER: &str = "cpu_pool_user";
+const MASTER_KEY: &str = "4444444444444444444444444444444444444444444444444444444444444444";
+const SLOW: Duration = Duration::from_millis(800);
+const LARGE: usize = 1 << 20;
+
+fn files_of(payload: &[u8]) -> Vec<Vec<u8>> {
+    let list = root_as_file_data_list(payload).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+/// A store whose reads of large chunks take `SLOW`, standing in for an expensive transform
+struct SlowStore {
+    inner: MockBinaryStore,
+}
+
+impl Storage for SlowStore {
+    fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        self.inner.write(user_id, bucket, data)
+    }
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if size as usize >= LARGE {
+            std::thread::sleep(SLOW);
+        }
+        self.inner.read(user_id, bucket, offset, size)
+    }
+    fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        self.inner.delete(user_id, bucket, offset_size_list)
+    }
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        self.inner.verify(user_id, bucket, offset, size, checksum)
+    }
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.inner.bucket_len(user_id, bucket)
+    }
+}
+
+/// How long the large read and twenty small ones started alongside it took
+async fn race(objects: &ObjectService, context: &UserContext, large: &ObjectRead, small: &ObjectRead) -> (Duration, Duration) {
+    let started = Instant::now();
+    let large_read = async {
+        assert_eq!(objects.read_raw_pooled(context, large, None).await.unwrap().len(), LARGE);
+        started.elapsed()
+    };
+    let small_reads = async {
+        for _ in 0..20 {
+            assert_eq!(&objects.read_raw_pooled(context, small, None).await.unwrap()[..], b"small object");
+        }
+        started.elapsed()
+    };
+    futures::join!(large_read, small_reads)
+}
+
+// One test: the pool threshold and the encryption key are read from the environment
+#[actix_web::test]
+async fn test_pool_keeps_small_reads_moving_and_round_trips() {
+    common::isolate();
+    std::env::set_var("INLINE_THRESHOLD_BYTES", "0");
+    std::env::set_var("CPU_POOL_THREADS", "2");
+    let context = UserContext::with_bucket(USER.to_string(), unique("pool"));
+    MetadataService::new(USER).unwrap().create_bucket(&context.bucket).unwrap();
+    let objects = ObjectService::with_storage(StorageService::with_store(Arc::new(SlowStore { inner: MockBinaryStore::new() })));
+    let large_data = vec![7u8; LARGE];
+    objects.put(&context, "large", async { Ok(payload(&[&large_data]).into()) }, None).await.unwrap();
+    objects.put(&context, "small", async { Ok(payload(&[b"small object"]).into()) }, None).await.unwrap();
+    let large = objects.get(&context, "large", None).unwrap();
+    let small = objects.get(&context, "small", None).unwrap();
+
+    let (large_took, small_took) = race(&objects, &context, &large, &small).await;
+    assert!(large_took >= SLOW, "{:?}", large_took);
+    assert!(small_took < SLOW / 2, "small reads waited {:?} behind the large one", small_took);
+
+    // With the large read inline the small ones wait for it
+    std::env::set_var("CPU_POOL_MIN_BYTES", usize::MAX.to_string());
+    let (_, small_took) = race(&objects, &context, &large, &small).await;
+    assert!(small_took >= SLOW, "{:?}", small_took);
+
+    // Everything through the pool, with encryption and deduplication on the write path
+    std::env::set_var("CPU_POOL_MIN_BYTES", "0");
+    std::env::set_var("ENCRYPTION_MASTER_KEY", MASTER_KEY);
+    std::env::set_var("DEDUP_ENABLED", "true");
+    std::env::set_var("DEDUP_MIN_CHUNK_BYTES", "1");
+    let context = UserContext::with_bucket(USER.to_string(), unique("stacked"));
+    let objects = ObjectService::new();
+    let files: Vec<Vec<u8>> = (0..4u8).map(|i| (0..200_000u32).map(|n| (n % 251) as u8 ^ i).collect()).collect();
+    let file_refs: Vec<&[u8]> = files.iter().map(Vec::as_slice).collect();
+    for key in ["first", "copy"] {
+        let body = payload(&file_refs).into();
+        objects.put(&context, key, async move { Ok(body) }, None).await.unwrap();
+    }
+    let db = MetadataService::new(USER).unwrap();
+    let (first, copy) = (db.get_object_full(&context.bucket, "first").unwrap(), db.get_object_full(&context.bucket, "copy").unwrap());
+    assert_eq!(first.to_offset_size_list(), copy.to_offset_size_list(), "the copy was not deduplicated");
+
+    // Record each chunk's checksum so reads verify it
+    let mut checked = first.clone();
+    for (chunk, data) in checked.chunks.iter_mut().zip(&files) {
+        chunk.checksum = Some(checksums::crc32c(data));
+    }
+    db.update_object_full(&context.bucket, "first", &checked).unwrap();
+
+    let read = objects.get(&context, "first", None).unwrap();
+    assert_eq!(files_of(&objects.read_native_pooled(&context, &read).await.unwrap()), files);
+    assert_eq!(objects.read_raw_pooled(&context, &read, None).await.unwrap(), files.concat());
+    let ranged = objects.read_raw_pooled(&context, &read, Some((199_990, 200_009))).await.unwrap();
+    assert_eq!(ranged, files.concat()[199_990..200_010]);
+    let selected = objects.get(&context, "copy", Some((2, 3))).unwrap();
+    assert_eq!(files_of(&objects.read_native_pooled(&context, &selected).await.unwrap()), files[2..].to_vec());
+
+    // A checksum failure on a pool thread comes back as itself
+    checked.chunks[1].checksum = Some(checksums::crc32c(b"something else"));
+    db.update_object_full(&context.bucket, "first", &checked).unwrap();
+    let read = objects.get(&context, "first", None).unwrap();
+    let e = objects.read_raw_pooled(&context, &read, None).await.unwrap_err();
+    assert!(matches!(e.as_error::<ServiceError>(), Some(ServiceError::ChecksumMismatch { .. })), "{}", e);
+}
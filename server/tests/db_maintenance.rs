@@ -0,0 +1,56 @@
+// Incremental vacuum only works on a database file created with auto_vacuum=INCREMENTAL, so
+// this binary points DB_FILE at a fresh scratch file before the metadata store is first opened.
+
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::metadata::{Metadata, MetadataStorage};
+use warp_drive::service::db_maintenance::{run_maintenance, DbMaintenanceConfig};
+
+mod common;
+
+fn db_file() -> PathBuf {
+    common::isolate().join("metadata").join("metadata.sqlite")
+}
+
+fn freelist_count() -> i64 {
+    let conn = Connection::open(db_file()).unwrap();
+    conn.query_row("PRAGMA freelist_count", [], |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn test_maintenance_reclaims_free_pages_without_losing_rows() {
+    common::isolate();
+    let store = SQLiteMetadataStore::new();
+    let user = "maintenance_user";
+    let bucket = "maintenance-bucket";
+    let padding = "x".repeat(2048);
+
+    for i in 0..500 {
+        let mut metadata = Metadata::from_offset_size_list(vec![(i, 1)]);
+        metadata.user_metadata = HashMap::from([("padding".to_string(), padding.clone())]);
+        store.put_metadata(user, bucket, &format!("key-{}", i), &metadata).unwrap();
+    }
+    for i in 0..500 {
+        if i % 10 != 0 {
+            store.delete_metadata(user, bucket, &format!("key-{}", i)).unwrap();
+        }
+    }
+    let before = freelist_count();
+    assert!(before > 100, "expected deletes to leave free pages, got {}", before);
+
+    let config = DbMaintenanceConfig { vacuum_freelist_ratio: 0.0, vacuum_step_pages: 16, ..Default::default() };
+    let report = run_maintenance(&config).unwrap();
+    assert!(!report.checkpoint.busy);
+    assert!(report.vacuumed_pages > 0);
+    assert_eq!(report.wal_bytes, 0);
+
+    assert!(freelist_count() < before, "freelist did not shrink from {}", before);
+    for i in (0..500).step_by(10) {
+        let metadata = store.get_metadata(user, bucket, &format!("key-{}", i)).unwrap();
+        assert_eq!(metadata.to_offset_size_list(), vec![(i, 1)]);
+        assert_eq!(metadata.user_metadata["padding"], padding);
+    }
+    assert!(!store.object_exists(user, bucket, "key-1").unwrap());
+}
@@ -0,0 +1,110 @@
+// Content deduplication: identical chunks written under several keys are stored once, read
+// back under every key, and freed only once the last key referencing them is deleted.
+
+use std::sync::{Arc, Once};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::metadata::Metadata;
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::storage_service::{StorageMode, StorageService};
+use warp_drive::service::user_context::UserContext;
+use warp_drive::storage::config::StorageConfig;
+use warp_drive::storage::mock_store::{self, MockBinaryStore};
+
+mod common;
+use common::unique;
+
+const KEYS: [&str; 3] = ["layer-a", "layer-b", "layer-c"];
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("DEDUP_ENABLED", "true");
+        std::env::set_var("DEDUP_MIN_CHUNK_BYTES", "1024");
+    });
+}
+
+fn payload(seed: u8) -> Vec<u8> {
+    (0..16 * 1024).map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed)).collect()
+}
+
+/// Put `data` as an S3 object under each of `KEYS`; returns the extents of each
+fn put_all(storage: &StorageService, context: &UserContext, data: &[u8]) -> Vec<Vec<(u64, u64)>> {
+    let db = MetadataService::new(&context.user_id).unwrap();
+    KEYS.iter().map(|key| {
+        let extents = storage.write_object(context, data, StorageMode::RAW).unwrap();
+        let mut metadata = Metadata::from_offset_size_list(extents.clone());
+        metadata.size = data.len() as u64;
+        db.put_object_full(&context.bucket, key, metadata).unwrap();
+        extents
+    }).collect()
+}
+
+#[test]
+fn test_identical_payloads_are_written_once() {
+    setup();
+    let user = unique("dedup_mock_user");
+    let context = UserContext::with_bucket(user.clone(), unique("dedup_mock"));
+    let db = MetadataService::new(&user).unwrap();
+    db.create_bucket(&context.bucket).unwrap();
+    let storage = StorageService::with_store(Arc::new(MockBinaryStore::new()));
+    let data = payload(1);
+
+    let extents = put_all(&storage, &context, &data);
+    assert_eq!(mock_store::calls(&user).writes, 1);
+    assert!(extents.iter().all(|e| *e == extents[0]), "{:?}", extents);
+    for key in KEYS {
+        let metadata = db.get_object_full(&context.bucket, key).unwrap();
+        assert_eq!(storage.read_stored(&context, &metadata, StorageMode::RAW).unwrap(), data, "{}", key);
+    }
+    assert_eq!(db.sharing_stats(&context.bucket).unwrap(), (1, 2 * data.len() as u64));
+
+    // Different bytes, and identical bytes in another bucket, are written
+    storage.write_object(&context, &payload(2), StorageMode::RAW).unwrap();
+    let other = UserContext::with_bucket(user.clone(), unique("dedup_mock_other"));
+    db.create_bucket(&other.bucket).unwrap();
+    storage.write_object(&other, &data, StorageMode::RAW).unwrap();
+    assert_eq!(mock_store::calls(&user).writes, 3);
+
+    // Only the last delete queues the shared range
+    let queue = SQLiteMetadataStore::new();
+    for (i, key) in KEYS.iter().enumerate() {
+        let queued = storage.delete_object(&context, key).unwrap();
+        let last = i == KEYS.len() - 1;
+        assert_eq!(queued.bytes, if last { data.len() as u64 } else { 0 }, "{}", key);
+        assert_eq!(queue.deletion_pending(&user, &context.bucket, key).unwrap(), last, "{}", key);
+    }
+    // A freed range is no longer offered to new writes; the other payload's chunk still is
+    assert_eq!(db.sharing_stats(&context.bucket).unwrap(), (1, 0));
+    storage.write_object(&context, &data, StorageMode::RAW).unwrap();
+    assert_eq!(mock_store::calls(&user).writes, 4);
+}
+
+#[actix_web::test]
+async fn test_shared_range_freed_after_last_delete() {
+    setup();
+    let user = "dedup_local_user";
+    let context = UserContext::with_bucket(user.to_string(), unique("dedup_local"));
+    let db = MetadataService::new(user).unwrap();
+    db.create_bucket(&context.bucket).unwrap();
+    let storage = StorageService::new();
+    let store = StorageConfig::from_env().create_store();
+    let data = payload(3);
+
+    let extents = put_all(&storage, &context, &data);
+    let range = extents[0][0];
+    assert_eq!(store.bucket_len(user, &context.bucket).unwrap(), data.len() as u64);
+
+    for (i, key) in KEYS.iter().enumerate() {
+        storage.delete_object(&context, key).unwrap();
+        DeletionWorker::new().process_deletions().await.unwrap();
+        let freed = !db.free_ranges_overlapping(&context.bucket, &[range]).unwrap().is_empty();
+        assert_eq!(freed, i == KEYS.len() - 1, "after deleting {}", key);
+        for remaining in &KEYS[i + 1..] {
+            let metadata = db.get_object_full(&context.bucket, remaining).unwrap();
+            assert_eq!(storage.read_stored(&context, &metadata, StorageMode::RAW).unwrap(), data, "{}", remaining);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// Deletion queue retention: processed events age by processing time and are removed in
+// bounded batches, and the worker exports the unprocessed backlog. Rows are seeded straight
+// into the scratch database. Deletes report the bytes they queue, and the pending-bytes gauge
+// follows the queue; the tests share the queue, so they serialize on QUEUE_LOCK.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, web, App, http::StatusCode};
+use rusqlite::{params, Connection};
+use std::sync::Mutex;
+use warp_drive::api::{put, delete};
+use warp_drive::metadata::sqlite_store;
+use warp_drive::metrics;
+use warp_drive::s3::handlers::{s3_delete_object_handler, s3_put_object_handler, RECLAIMED_BYTES_HEADER};
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::serializer::encode_extents;
+
+mod common;
+use common::{AMZ_DATE, authorization, payload, unique, UNSIGNED_PAYLOAD};
+
+const USER: &str = "queue_user";
+const ACCESS_KEY: &str = "queue-test-access";
+const SECRET_KEY: &str = "queue-test-secret";
+
+static QUEUE_LOCK: Mutex<()> = Mutex::new(());
+
+/// `warpdrive_bytes_pending_deletion` as a scrape of `/metrics` reports it
+async fn pending_bytes_gauge() -> u64 {
+    let body = actix_web::body::to_bytes(metrics::metrics_handler().await.into_body()).await.unwrap();
+    let text = String::from_utf8_lossy(&body).to_string();
+    text.lines()
+        .find_map(|line| line.strip_prefix("warpdrive_bytes_pending_deletion "))
+        .unwrap_or_else(|| panic!("gauge missing from {}", text))
+        .parse()
+        .unwrap()
+}
+
+fn open_db() -> Connection {
+    common::isolate();
+    let path = sqlite_store::initialize().unwrap();
+    Connection::open(path).unwrap()
+}
+
+#[actix_web::test]
+async fn test_cleanup_runs_in_batches_and_keeps_recent_work() {
+    let _queue = QUEUE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut conn = open_db();
+    let indexes: Vec<String> = conn.prepare("PRAGMA index_list('deletion_queue')").unwrap()
+        .query_map([], |row| row.get(1)).unwrap()
+        .collect::<Result<_, _>>().unwrap();
+    assert!(indexes.contains(&"idx_deletion_queue_processed".to_string()), "{:?}", indexes);
+
+    let extents = encode_extents(&[(0, 16)]).unwrap();
+    let tx = conn.transaction().unwrap();
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO deletion_queue (user_id, bucket, key, offset_size_list, created_at, processed, processed_at)
+             VALUES ('queue_user', 'queue-bucket', ?1, ?2, datetime('now', ?3), ?4, datetime('now', ?5))",
+        ).unwrap();
+        // Processed long ago
+        for i in 0..10_000 {
+            insert.execute(params![format!("old-{}", i), extents, "-30 days", true, "-10 days"]).unwrap();
+        }
+        // Queued long ago but only just processed: still within retention
+        for i in 0..5 {
+            insert.execute(params![format!("late-{}", i), extents, "-30 days", true, "-1 hours"]).unwrap();
+        }
+    }
+    for i in 0..3 {
+        tx.execute(
+            "INSERT INTO deletion_queue (user_id, bucket, key, offset_size_list, created_at)
+             VALUES ('queue_user', 'queue-bucket', ?1, ?2, datetime('now', '-30 days'))",
+            params![format!("pending-{}", i), extents],
+        ).unwrap();
+    }
+    tx.commit().unwrap();
+
+    let batches_before = metrics::counter_value("warpdrive_deletion_cleanup_batches_total", &[]);
+    let removed = MetadataService::new("system").unwrap().cleanup_old_deletions(7, 1000).unwrap();
+    assert_eq!(removed, 10_000);
+    assert_eq!(metrics::counter_value("warpdrive_deletion_cleanup_batches_total", &[]) - batches_before, 10);
+
+    let remaining: Vec<String> = conn.prepare("SELECT key FROM deletion_queue ORDER BY key").unwrap()
+        .query_map([], |row| row.get(0)).unwrap()
+        .collect::<Result<_, _>>().unwrap();
+    assert_eq!(remaining.len(), 8, "{:?}", remaining);
+    assert!(remaining.iter().all(|k| k.starts_with("late-") || k.starts_with("pending-")));
+
+    // The worker reports the backlog before working through it
+    std::env::set_var("DELETION_QUEUE_ALERT_THRESHOLD", "2");
+    DeletionWorker::new().process_deletions().await.unwrap();
+    assert!(metrics::render().contains("warpdrive_deletion_queue_pending 3"), "{}", metrics::render());
+    let processed_at: Option<String> = conn.query_row(
+        "SELECT processed_at FROM deletion_queue WHERE key = 'pending-0'", [], |row| row.get(0),
+    ).unwrap();
+    assert!(processed_at.is_some(), "processing time is recorded");
+}
+
+#[actix_web::test]
+async fn test_delete_reports_reclaimed_bytes() {
+    let _queue = QUEUE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    open_db();
+    std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    let app = test::init_service(App::new().service(put).service(delete)
+        .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+        .route("/s3/{bucket}/{key}", web::delete().to(s3_delete_object_handler))).await;
+    let bucket = unique("reclaim");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    DeletionWorker::new().process_deletions().await.unwrap();
+    assert_eq!(pending_bytes_gauge().await, 0);
+
+    let native = |req: test::TestRequest| req.insert_header(("user", USER)).insert_header(("bucket", bucket.clone()));
+    let data = [vec![b'a'; 1000], vec![b'b'; 24]];
+    let resp = test::call_service(&app, native(test::TestRequest::post().uri("/put/doc"))
+        .set_payload(payload(&[&data[0], &data[1]])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::delete().uri("/delete/doc")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["reclaimed_bytes"], 1024, "{}", body);
+    assert_eq!(body["chunks"], 2, "{}", body);
+    assert_eq!(body["queued"], true, "{}", body);
+    assert_eq!(pending_bytes_gauge().await, 1024);
+
+    // S3 deletes answer with the same figure in a header
+    let s3_bucket = unique("reclaim-s3");
+    MetadataService::new("admin").unwrap().create_bucket(&s3_bucket).unwrap();
+    let path = format!("/s3/{}/object", s3_bucket);
+    let s3 = |req: test::TestRequest, method: &str| req.uri(&path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, &path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE));
+    let resp = test::call_service(&app, s3(test::TestRequest::put(), "PUT").set_payload(vec![b'c'; 4096]).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, s3(test::TestRequest::delete(), "DELETE").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.headers().get(RECLAIMED_BYTES_HEADER).unwrap(), "4096");
+    assert_eq!(pending_bytes_gauge().await, 1024 + 4096);
+
+    DeletionWorker::new().process_deletions().await.unwrap();
+    assert_eq!(pending_bytes_gauge().await, 0);
+}
@@ -0,0 +1,114 @@
+// With METADATA_BACKEND=mock, a delete is queued, freed by the deletion worker and its range
+// recorded as free space entirely in the mock store; the SQLite queue never sees it. A worker
+// given its own store works that store's queue, counts failed attempts on its events and
+// replays spilled deletions into it. Every worker pass replays the one spill file, so the
+// tests hold WORKER_LOCK.
+#![allow(clippy::await_holding_lock)]
+
+use std::sync::{Arc, Mutex};
+use warp_drive::metadata::MetadataStorage;
+use warp_drive::metadata::mock_store::MockMetadataStore;
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::service::deletion_spill;
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::storage_service::{StorageMode, StorageService};
+use warp_drive::service::user_context::UserContext;
+use warp_drive::util::serializer::encode_extents;
+
+mod common;
+
+static WORKER_LOCK: Mutex<()> = Mutex::new(());
+
+#[actix_web::test]
+async fn test_delete_flows_through_worker_on_mock_backend() {
+    let _worker = WORKER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    // Before the first MetadataService call picks the backend
+    std::env::set_var("METADATA_BACKEND", "mock");
+    let user = "mock_queue_user";
+    let bucket = common::unique("mock-queue");
+    let db = MetadataService::new(user).unwrap();
+    db.create_bucket(&bucket).unwrap();
+
+    let context = UserContext::with_bucket(user.to_string(), bucket.clone());
+    let storage = StorageService::new();
+    let extents = storage.write_object(&context, &[7u8; 8192], StorageMode::S3).unwrap();
+    db.write_metadata(&bucket, "doomed", &encode_extents(&extents).unwrap()).unwrap();
+
+    let queued = storage.delete_object(&context, "doomed").unwrap();
+    assert_eq!(queued.bytes, 8192);
+    assert_eq!(db.pending_deletion_count_for_bucket(&bucket).unwrap(), 1);
+    assert_eq!(SQLiteMetadataStore::new().pending_deletion_count_for_bucket(user, &bucket).unwrap(), 0);
+
+    DeletionWorker::new().process_deletions().await.unwrap();
+    assert_eq!(db.pending_deletion_count_for_bucket(&bucket).unwrap(), 0);
+    assert_eq!(db.free_ranges(&bucket).unwrap(), extents);
+    assert!(db.object_state(&bucket, "doomed").unwrap().is_none());
+}
+
+#[actix_web::test]
+async fn test_worker_on_its_own_store_counts_failed_attempts() {
+    let _worker = WORKER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    // Whichever test opens the global store first, it is the mock
+    std::env::set_var("METADATA_BACKEND", "mock");
+    let user = "own_store_user";
+    let bucket = common::unique("own-store");
+    let context = UserContext::with_bucket(user.to_string(), bucket.clone());
+    let storage = StorageService::new();
+    let extents = storage.write_object(&context, &[7u8; 4096], StorageMode::S3).unwrap();
+    // A segment the worker cannot open for writing fails every attempt to free its ranges
+    let path = storage.active_segment_file(&context).unwrap().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::create_dir(&path).unwrap();
+
+    let store = Arc::new(MockMetadataStore::new());
+    store.create_bucket(user, &bucket).unwrap();
+    store.queue_deletion(user, &bucket, "stuck", &extents).unwrap();
+    let worker = DeletionWorker::with_store(store.clone());
+    for attempt in 1..=2 {
+        worker.process_deletions().await.unwrap();
+        let events = store.get_pending_deletions_for_bucket(user, &bucket, 10).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].attempts, attempt);
+        assert!(events[0].last_error.as_deref().is_some_and(|e| e.contains("Failed to delete chunks")), "{:?}", events[0]);
+    }
+    // The store every service uses never saw the event
+    assert_eq!(MetadataService::new(user).unwrap().pending_deletion_count_for_bucket(&bucket).unwrap(), 0);
+
+    std::fs::remove_dir(&path).unwrap();
+    std::fs::write(&path, [7u8; 4096]).unwrap();
+    worker.process_deletions().await.unwrap();
+    assert_eq!(store.pending_deletion_count_for_bucket(user, &bucket).unwrap(), 0);
+    assert_eq!(store.free_ranges(user, &bucket).unwrap(), extents);
+}
+
+#[actix_web::test]
+async fn test_spilled_deletions_are_replayed_into_the_workers_store() {
+    let _worker = WORKER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    std::env::set_var("METADATA_BACKEND", "mock");
+    let user = "spill_replay_user";
+    let bucket = common::unique("spill-replay");
+    let context = UserContext::with_bucket(user.to_string(), bucket.clone());
+    let storage = StorageService::new();
+    let extents = storage.write_object(&context, &[7u8; 4096], StorageMode::S3).unwrap();
+    deletion_spill::spill(user, &bucket, "spilled", &extents).unwrap();
+    let text = std::fs::read_to_string(deletion_spill::path()).unwrap();
+
+    let store = Arc::new(MockMetadataStore::new());
+    store.create_bucket(user, &bucket).unwrap();
+    let worker = DeletionWorker::with_store(store.clone());
+    worker.process_deletions().await.unwrap();
+    assert!(!deletion_spill::path().exists());
+    assert_eq!(store.free_ranges(user, &bucket).unwrap(), extents);
+    assert_eq!(store.pending_deletion_count_for_bucket(user, &bucket).unwrap(), 0);
+    assert_eq!(SQLiteMetadataStore::new().pending_deletion_count_for_bucket(user, &bucket).unwrap(), 0);
+
+    // Replayed again, as after a crash before the file was removed, it is not queued twice
+    std::fs::write(deletion_spill::path(), &text).unwrap();
+    assert_eq!(deletion_spill::replay(store.as_ref()).unwrap(), 0);
+    assert!(!deletion_spill::path().exists());
+    assert_eq!(store.pending_deletion_count_for_bucket(user, &bucket).unwrap(), 0);
+}
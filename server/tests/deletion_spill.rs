@@ -0,0 +1,88 @@
+// Deletes while the deletion queue refuses inserts: the delete still succeeds, the deletion
+// lands in the spill file, and the worker replays it into the queue once the database takes
+// inserts again, queueing and freeing it exactly once. The refusal is a trigger on the
+// scratch database's queue table.
+
+use actix_web::{test, http::Method, http::StatusCode};
+use rusqlite::Connection;
+use serde_json::Value;
+use std::fs;
+use warp_drive::metadata::sqlite_store;
+use warp_drive::metrics;
+use warp_drive::server::app;
+use warp_drive::service::deletion_spill::{self, SpilledDeletion};
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::{self, MetadataService};
+use warp_drive::util::serializer::decode_extents;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "spill_user";
+
+fn native(method: Method, uri: &str, bucket: &str) -> test::TestRequest {
+    test::TestRequest::default().method(method).uri(uri)
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket))
+}
+
+#[actix_web::test]
+async fn test_refused_deletion_is_spilled_and_replayed_once() {
+    common::isolate();
+    let conn = Connection::open(sqlite_store::initialize().unwrap()).unwrap();
+    let app = test::init_service(app()).await;
+    let bucket = unique("spill");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&bucket).unwrap();
+
+    let req = native(Method::POST, "/put/doomed", &bucket).set_payload(payload_of(&[9u8; 4096]));
+    assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    let extents = decode_extents(&db.read_metadata(&bucket, "doomed").unwrap()).unwrap();
+
+    conn.execute_batch(
+        "CREATE TRIGGER refuse_deletions BEFORE INSERT ON deletion_queue
+         BEGIN SELECT RAISE(ABORT, 'database or disk is full'); END;",
+    ).unwrap();
+
+    // The delete completes for the client and the deletion waits in the spill file
+    let resp = test::call_service(&app, native(Method::DELETE, "/delete/doomed", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["queued"], true);
+    assert_eq!(body["reclaimed_bytes"], extents.iter().map(|(_, size)| size).sum::<u64>());
+    let resp = test::call_service(&app, native(Method::GET, "/get/doomed", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(db.pending_deletion_count_for_bucket(&bucket).unwrap(), 0);
+
+    let text = fs::read_to_string(deletion_spill::path()).unwrap();
+    let spilled: Vec<SpilledDeletion> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(spilled.len(), 1);
+    assert_eq!((spilled[0].user_id.as_str(), spilled[0].bucket.as_str(), spilled[0].key.as_str()), (USER, bucket.as_str(), "doomed"));
+    assert_eq!(spilled[0].extents, extents);
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/healthz").to_request()).await;
+    let health: Value = test::read_body_json(resp).await;
+    assert_eq!(health["deletion_spill"]["bytes"], text.len());
+    assert!(health["deletion_spill"]["age_secs"].is_i64());
+    assert!(metrics::render().contains(&format!("warpdrive_deletion_spill_bytes {}", text.len())), "{}", metrics::render());
+
+    // Still refused: the line stays for the next pass
+    assert_eq!(deletion_spill::replay(metadata_service::store().as_ref()).unwrap(), 0);
+    assert_eq!(fs::read_to_string(deletion_spill::path()).unwrap(), text);
+
+    // Once the queue takes inserts, the worker queues and frees it
+    conn.execute_batch("DROP TRIGGER refuse_deletions;").unwrap();
+    DeletionWorker::new().process_deletions().await.unwrap();
+    assert!(!deletion_spill::path().exists());
+    assert_eq!(deletion_spill::status().bytes, 0);
+    assert_eq!(db.queued_extents(&bucket).unwrap(), extents);
+    assert_eq!(db.pending_deletion_count_for_bucket(&bucket).unwrap(), 0);
+    assert_eq!(db.free_ranges(&bucket).unwrap(), extents);
+
+    // The same line replayed again, as after a crash before the file was removed, is skipped
+    fs::write(deletion_spill::path(), &text).unwrap();
+    assert_eq!(deletion_spill::replay(metadata_service::store().as_ref()).unwrap(), 0);
+    assert!(!deletion_spill::path().exists());
+    assert_eq!(db.queued_extents(&bucket).unwrap(), extents);
+    assert_eq!(db.pending_deletion_count_for_bucket(&bucket).unwrap(), 0);
+}
@@ -0,0 +1,137 @@
+// The deletion worker frees queued ranges in the local store instead of queueing them again.
+// Storage and metadata live in the binary's scratch directory.
+
+use actix_web::{test, App, http::StatusCode};
+use rusqlite::Connection;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use warp_drive::api::{get, put};
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::fsck;
+use warp_drive::service::jobs::JobHandle;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::storage_service::{StorageMode, StorageService};
+use warp_drive::service::user_context::UserContext;
+use warp_drive::storage::config::StorageConfig;
+
+mod common;
+use common::payload;
+
+fn root() -> &'static Path {
+    common::isolate()
+}
+
+fn queue_counts() -> (i64, i64) {
+    let conn = Connection::open(root().join("metadata").join("metadata.sqlite")).unwrap();
+    conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(processed = 0), 0) FROM deletion_queue",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).unwrap()
+}
+
+#[actix_web::test]
+async fn test_worker_frees_queued_ranges_without_requeueing() {
+    common::isolate();
+    let context = UserContext::with_bucket("deletion_user".to_string(), "deletion-bucket".to_string());
+    let storage = StorageService::new();
+    let kept = storage.write_object(&context, &[1u8; 4096], StorageMode::S3).unwrap();
+    let freed = storage.write_object(&context, &[2u8; 4 * 1024 * 1024], StorageMode::S3).unwrap();
+    let file = root().join("storage").join("deletion_user").join("deletion-bucket.bin");
+    let before = std::fs::metadata(&file).unwrap();
+
+    MetadataService::new("deletion_user").unwrap().queue_deletion("deletion-bucket", "gone", &freed).unwrap();
+    assert_eq!(queue_counts(), (1, 1));
+
+    let worker = DeletionWorker::new();
+    worker.process_deletions().await.unwrap();
+    assert_eq!(queue_counts(), (1, 0), "worker must not queue the event again");
+    // A second pass finds nothing to do
+    worker.process_deletions().await.unwrap();
+    assert_eq!(queue_counts(), (1, 0));
+
+    let after = std::fs::metadata(&file).unwrap();
+    assert_eq!(after.len(), before.len(), "offsets of later chunks must stay valid");
+    assert!(after.blocks() < before.blocks(), "allocated blocks {} -> {}", before.blocks(), after.blocks());
+    assert_eq!(storage.read_object(&context, &kept, StorageMode::S3).unwrap(), vec![1u8; 4096]);
+    // The hole reads back as zeros, but reads through the service refuse the freed range
+    let (offset, size) = freed[0];
+    let raw = StorageConfig::from_env().create_store().read("deletion_user", "deletion-bucket", offset, size).unwrap();
+    assert!(raw.iter().all(|b| *b == 0));
+    assert!(storage.read_object(&context, &freed, StorageMode::S3).is_err());
+}
+
+fn free_ranges(user: &str, bucket: &str) -> Vec<(i64, i64)> {
+    let conn = Connection::open(root().join("metadata").join("metadata.sqlite")).unwrap();
+    let mut stmt = conn.prepare("SELECT offset, size FROM free_ranges WHERE user_id = ?1 AND bucket = ?2 ORDER BY offset").unwrap();
+    stmt.query_map([user, bucket], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(|r| r.unwrap()).collect()
+}
+
+#[actix_web::test]
+async fn test_worker_merges_duplicate_and_adjacent_ranges() {
+    common::isolate();
+    let (user, bucket) = ("dedup_user", "dedup-bucket");
+    let context = UserContext::with_bucket(user.to_string(), bucket.to_string());
+    let storage = StorageService::new();
+    let chunks: Vec<Vec<(u64, u64)>> = (0..3u8)
+        .map(|i| storage.write_object(&context, &[i + 1; 4096], StorageMode::S3).unwrap())
+        .collect();
+    let kept = storage.write_object(&context, &[9u8; 4096], StorageMode::S3).unwrap();
+    let (start, _) = chunks[0][0];
+    let (last, last_size) = *chunks[2].last().unwrap();
+
+    let db = MetadataService::new(user).unwrap();
+    db.queue_deletion(bucket, "a", &[chunks[0].clone(), chunks[1].clone()].concat()).unwrap();
+    db.queue_deletion(bucket, "b", &[chunks[1].clone(), chunks[2].clone()].concat()).unwrap();
+    db.queue_deletion(bucket, "c", &chunks[0]).unwrap();
+
+    let report = DeletionWorker::new().compact_bucket(user, bucket, &JobHandle::detached()).await.unwrap();
+    assert_eq!((report.events, report.failed), (3, 0));
+    assert_eq!(report.freed_bytes, 3 * 4096, "each byte is freed once");
+    assert_eq!(free_ranges(user, bucket), vec![(start as i64, (last + last_size - start) as i64)]);
+
+    // Queued again after it was freed: recorded as processed, nothing freed twice
+    db.queue_deletion(bucket, "d", &chunks[1]).unwrap();
+    let report = DeletionWorker::new().compact_bucket(user, bucket, &JobHandle::detached()).await.unwrap();
+    assert_eq!((report.events, report.freed_bytes), (1, 0));
+    assert_eq!(free_ranges(user, bucket).len(), 1);
+    assert!(db.get_pending_deletions_for_bucket(bucket, 100).unwrap().is_empty());
+    assert_eq!(storage.read_object(&context, &kept, StorageMode::S3).unwrap(), vec![9u8; 4096]);
+}
+
+#[actix_web::test]
+async fn test_reads_and_fsck_reject_chunks_in_freed_ranges() {
+    common::isolate();
+    let (user, bucket) = ("freed_user", "freed-bucket");
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let native = |req: test::TestRequest, uri: &str| req.uri(uri).insert_header(("user", user)).insert_header(("bucket", bucket));
+    for key in ["kept", "stale"] {
+        let req = native(test::TestRequest::post(), &format!("/put/{}", key)).set_payload(payload(&[key.as_bytes()])).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+    let fetch = |key: &str| {
+        let req = native(test::TestRequest::get(), &format!("/get/{}", key)).to_request();
+        let app = &app;
+        async move { test::call_service(app, req).await }
+    };
+    assert_eq!(fetch("stale").await.status(), StatusCode::OK);
+
+    // Free the range under "stale" while its metadata still points at it
+    let db = MetadataService::new(user).unwrap();
+    let (offset, size) = db.get_object_full(bucket, "stale").unwrap().to_offset_size_list()[0];
+    db.complete_deletions(bucket, &[(offset, size)], &[]).unwrap();
+
+    let resp = fetch("stale").await;
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let error: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(error["error"], "FreedRangeReference", "{}", error);
+    assert_eq!(error["chunk"], serde_json::json!([offset, size]));
+    assert_eq!(error["freed"], serde_json::json!([offset, size]));
+    assert_eq!(fetch("kept").await.status(), StatusCode::OK);
+
+    let report = fsck::check_bucket(user, bucket).unwrap();
+    assert_eq!(report.problems.len(), 1, "{:?}", report.problems);
+    assert_eq!(report.problems[0].key, "stale");
+    assert_eq!(report.problems[0].chunk, Some(0));
+    assert!(report.problems[0].problem.contains("references freed range"), "{:?}", report.problems);
+}
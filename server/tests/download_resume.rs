@@ -0,0 +1,89 @@
+// Resumable downloads: a client reads part of GET /download/{key} and drops the connection,
+// then continues with the resume token and a Range for what it holds. The pieces put together
+// are the object; once the object is rewritten the same token is refused with 409.
+
+use actix_web::body::MessageBody;
+use actix_web::{test, App, http::StatusCode};
+use warp_drive::api::{download, put, update};
+use warp_drive::metrics;
+use warp_drive::service::download::SLICE_BYTES;
+
+mod common;
+use common::{payload, payload_of, unique};
+
+const USER: &str = "download_user";
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket))
+}
+
+fn chunk(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(13).wrapping_add(seed)).collect()
+}
+
+fn header(resp: &actix_web::dev::ServiceResponse, name: &str) -> String {
+    resp.headers().get(name).unwrap().to_str().unwrap().to_string()
+}
+
+#[actix_web::test]
+async fn test_cut_download_resumes_where_it_stopped() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(update).service(download)).await;
+    let bucket = unique("download");
+    let files = [chunk(1, SLICE_BYTES as usize + 1000), chunk(2, SLICE_BYTES as usize / 2), chunk(3, 70_000)];
+    let object = files.concat();
+    let req = native(test::TestRequest::post(), "/put/big", &bucket).set_payload(payload(&files.iter().map(Vec::as_slice).collect::<Vec<_>>())).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    // Take the first piece of the body and hang up
+    let cut = metrics::counter_value("warpdrive_download_interrupted_total", &[]);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/download/big", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "content-length"), object.len().to_string());
+    let token = header(&resp, "x-warp-resume-token");
+    let mut body = Box::pin(resp.into_body());
+    let first = futures::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await.unwrap().unwrap();
+    assert!(!first.is_empty() && first.len() < object.len());
+    drop(body);
+    assert!(metrics::counter_value("warpdrive_download_interrupted_total", &[]) > cut);
+
+    let req = native(test::TestRequest::get(), &format!("/download/big?resume={}", token), &bucket)
+        .insert_header(("range", format!("bytes={}-", first.len())))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(header(&resp, "content-range"), format!("bytes {}-{}/{}", first.len(), object.len() - 1, object.len()));
+    let rest = test::read_body(resp).await;
+    assert_eq!([first.as_ref(), rest.as_ref()].concat(), object);
+
+    // A token for another key, or with its position moved, is refused
+    let req = native(test::TestRequest::get(), &format!("/download/other?resume={}", token), &bucket).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    let (generation, _) = token.split_once('.').unwrap();
+    let forged = format!("{}.{}.{}", generation, 4096, token.rsplit('.').next().unwrap());
+    let req = native(test::TestRequest::get(), &format!("/download/big?resume={}", forged), &bucket).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_resume_after_a_rewrite_is_a_conflict() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(update).service(download)).await;
+    let bucket = unique("rewritten");
+    let req = native(test::TestRequest::post(), "/put/doc", &bucket).set_payload(payload_of(&chunk(4, 300_000))).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/download/doc", &bucket).to_request()).await;
+    let token = header(&resp, "x-warp-resume-token");
+    drop(resp);
+
+    let req = native(test::TestRequest::post(), "/update/doc", &bucket).set_payload(payload_of(&chunk(5, 300_000))).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = native(test::TestRequest::get(), &format!("/download/doc?resume={}", token), &bucket)
+        .insert_header(("range", "bytes=1000-"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "GenerationChanged");
+    assert_eq!((body["expected_generation"].as_u64(), body["generation"].as_u64()), (Some(1), Some(2)));
+}
@@ -0,0 +1,61 @@
+// FSYNC_POLICY is read from the environment on every write, so these tests run in their own
+// binary and serialize on ENV_LOCK.
+
+use std::sync::Mutex;
+use warp_drive::metadata::config::SqliteConfig;
+use warp_drive::metadata::sqlite_store::open_connection;
+use warp_drive::metrics;
+use warp_drive::storage::Storage;
+use warp_drive::storage::durability;
+use warp_drive::storage::local_store::LocalXFSBinaryStore;
+
+mod common;
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn store(name: &str) -> LocalXFSBinaryStore {
+    LocalXFSBinaryStore::with_directory(common::isolate().join(name))
+}
+
+fn fsyncs(policy: &str) -> u64 {
+    metrics::counter_value("warpdrive_fsync_total", &[("policy", policy)])
+}
+
+#[test]
+fn test_always_syncs_each_write() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    std::env::set_var("FSYNC_POLICY", "always");
+    let store = store("always");
+
+    let before = fsyncs("always");
+    store.write("fsync_user", "bucket", b"first").unwrap();
+    store.write("fsync_user", "bucket", b"second").unwrap();
+    assert_eq!(fsyncs("always"), before + 2);
+
+    // SQLite follows the policy too
+    let path = common::isolate().join("fsync.sqlite");
+    let conn = open_connection(&path, &SqliteConfig::from_env()).unwrap();
+    let synchronous: i64 = conn.query_row("PRAGMA synchronous", [], |row| row.get(0)).unwrap();
+    assert_eq!(synchronous, 2); // FULL
+    std::env::remove_var("FSYNC_POLICY");
+}
+
+#[test]
+fn test_interval_defers_sync_to_background_pass() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    std::env::set_var("FSYNC_POLICY", "interval");
+    let store = store("interval");
+
+    let (interval_before, always_before) = (fsyncs("interval"), fsyncs("always"));
+    store.write("fsync_user", "bucket-a", b"one").unwrap();
+    store.write("fsync_user", "bucket-a", b"two").unwrap();
+    store.write("fsync_user", "bucket-b", b"three").unwrap();
+    // Acknowledged without syncing; the files are only marked dirty
+    assert_eq!((fsyncs("interval"), fsyncs("always")), (interval_before, always_before));
+    assert!(durability::dirty_file_count() >= 2);
+
+    assert!(durability::sync_dirty_files() >= 2);
+    assert_eq!(durability::dirty_file_count(), 0);
+    assert!(fsyncs("interval") >= interval_before + 2);
+    std::env::remove_var("FSYNC_POLICY");
+}
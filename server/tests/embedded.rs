@@ -0,0 +1,55 @@
+// Embedding: the app of `examples/embedded.rs`, built with mock metadata and storage
+// backends, serves its own route next to warpdrive's. A native PUT and GET round-trip
+// through the installed backends, leaving nothing in the storage directory, and a second
+// store cannot be installed once one is in use.
+
+use actix_web::{test, http::StatusCode};
+use std::sync::Arc;
+use warp_drive::app_state::AppStateBuilder;
+use warp_drive::metadata::mock_store::MockMetadataStore;
+
+mod common;
+use common::payload_of;
+
+#[path = "../examples/embedded.rs"]
+#[allow(dead_code)]
+mod embedded;
+
+#[actix_web::test]
+async fn test_embedded_app_serves_from_installed_backends() {
+    let scratch = common::isolate();
+    let state = embedded::state().unwrap();
+    assert_eq!(state.worker_count(), 0);
+    assert_eq!(state.http.port, 8090);
+    let app = test::init_service(embedded::app()).await;
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/edge/status").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "edge node up".as_bytes());
+
+    let req = test::TestRequest::post()
+        .uri("/v2/put/hello")
+        .insert_header(("user", "edge"))
+        .set_payload(payload_of(b"embedded"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = test::TestRequest::get()
+        .uri("/v2/get/hello?format=raw")
+        .insert_header(("user", "edge"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "embedded".as_bytes());
+
+    // The chunks went to the mock backend, not to the storage directory
+    assert_eq!(std::fs::read_dir(scratch.join("storage")).unwrap().count(), 0);
+
+    let err = AppStateBuilder::new()
+        .metadata_store(Arc::new(MockMetadataStore::new()))
+        .initialize(false)
+        .background_workers(false)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.step, "metadata");
+}
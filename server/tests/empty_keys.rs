@@ -0,0 +1,127 @@
+// Empty keys and trailing slashes on the routes of the full app: an object key is never
+// empty on either API, `dir/` is an ordinary key, and `/{bucket}/` stays a bucket operation.
+// S3 requests are signed with the admin access key.
+
+use actix_web::{test, web, App, http::Method, http::StatusCode};
+use std::sync::Once;
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_multipart_router};
+use warp_drive::server::app;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{AMZ_DATE, authorization_with_query, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "empty-key-access";
+const SECRET_KEY: &str = "empty-key-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+}
+
+fn native(method: Method, uri: &str, bucket: &str) -> test::TestRequest {
+    test::TestRequest::default().method(method).uri(uri)
+        .insert_header(("user", "empty_key_user"))
+        .insert_header(("bucket", bucket))
+}
+
+fn signed(method: Method, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    test::TestRequest::default().method(method.clone()).uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method.as_str(), path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_empty_key_is_rejected_on_both_apis() {
+    setup();
+    let app = test::init_service(app()).await;
+    let bucket = unique("empty-key");
+    MetadataService::new("empty_key_user").unwrap().create_bucket(&bucket).unwrap();
+
+    for (method, uri) in [(Method::POST, "/put/"), (Method::GET, "/get/"), (Method::DELETE, "/delete/"),
+                          (Method::POST, "/copy//target"), (Method::PUT, "/update_key/source/")] {
+        let resp = test::call_service(&app, native(method, uri, &bucket).set_payload(payload_of(b"data")).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "InvalidRequest", "{}", uri);
+    }
+    let db = MetadataService::new("empty_key_user").unwrap();
+    assert!(!db.check_key(&bucket, "").unwrap());
+
+    // The object handlers refuse an empty key even when a route lets one through
+    let s3 = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::post().to(s3_multipart_router))
+    ).await;
+    let s3_bucket = unique("empty-key-s3");
+    MetadataService::new("admin").unwrap().create_bucket(&s3_bucket).unwrap();
+    let path = format!("/s3/{}/", s3_bucket);
+    for (method, query) in [(Method::PUT, ""), (Method::POST, "uploads=")] {
+        let resp = test::call_service(&s3, signed(method.clone(), &path, query).set_payload("data").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{} {}", method, query);
+        let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+        assert!(xml.contains("<Code>InvalidRequest</Code>"), "{}", xml);
+    }
+    assert!(!MetadataService::new("admin").unwrap().check_key(&s3_bucket, "").unwrap());
+}
+
+#[actix_web::test]
+async fn test_directory_marker_round_trips() {
+    setup();
+    let app = test::init_service(app()).await;
+    let bucket = unique("empty-key-marker");
+    MetadataService::new("empty_key_user").unwrap().create_bucket(&bucket).unwrap();
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+
+    // Native: the rest of the path is the key, trailing slash included
+    let resp = test::call_service(&app, native(Method::POST, "/put/photos/", &bucket).set_payload(payload_of(b"marker")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(MetadataService::new("empty_key_user").unwrap().check_key(&bucket, "photos/").unwrap());
+    let resp = test::call_service(&app, native(Method::GET, "/get/photos/", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    let files = root_as_file_data_list(&body).unwrap().files().unwrap();
+    assert_eq!(files.get(0).data().unwrap().bytes(), b"marker");
+
+    // S3: a zero-byte marker is listed under its own key
+    let path = format!("/s3/{}/photos/", bucket);
+    let resp = test::call_service(&app, signed(Method::PUT, &path, "").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(Method::HEAD, &path, "").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(Method::GET, &format!("/s3/{}/", bucket), "").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Key>photos/</Key>"), "{}", xml);
+}
+
+#[actix_web::test]
+async fn test_trailing_slash_reaches_bucket_handlers() {
+    setup();
+    let app = test::init_service(app()).await;
+    let bucket = unique("empty-key-bucket");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+
+    for prefix in ["/s3/", "/"] {
+        let path = format!("{}{}/", prefix, bucket);
+        let resp = test::call_service(&app, signed(Method::GET, &path, "").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "GET {}", path);
+        let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+        assert!(xml.contains("<ListBucketResult"), "{}", xml);
+
+        let resp = test::call_service(&app, signed(Method::HEAD, &path, "").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "HEAD {}", path);
+        let resp = test::call_service(&app, signed(Method::HEAD, &format!("{}{}/", prefix, unique("missing")), "").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND, "HEAD missing bucket under {}", prefix);
+    }
+    assert!(!MetadataService::new("admin").unwrap().check_key(&bucket, "").unwrap());
+}
@@ -0,0 +1,133 @@
+// Zero-length objects over S3 and the native API, and the ALLOW_EMPTY_APPEND override.
+// ALLOW_EMPTY_APPEND is read per request, so tests serialize on ENV_LOCK; S3 requests are
+// signed with the admin access key.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, web, App, http::StatusCode};
+use actix_web::body::{BodySize, MessageBody};
+use std::sync::{Mutex, Once};
+use warp_drive::api::{put, get, append};
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_get_object_handler, s3_head_object_handler, s3_list_objects_handler};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{AMZ_DATE, authorization_with_query, payload, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "empty-test-access";
+const SECRET_KEY: &str = "empty-test-secret";
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    std::env::remove_var("ALLOW_EMPTY_APPEND");
+}
+
+fn stored_files(body: &[u8]) -> Vec<Vec<u8>> {
+    let list = root_as_file_data_list(body).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+fn native(req: test::TestRequest, uri: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", "empty_user"))
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_s3_zero_byte_object_and_directory_marker() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}", web::get().to(s3_list_objects_handler))
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::get().to(s3_get_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::head().to(s3_head_object_handler))
+    ).await;
+    let bucket = unique("empty");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+
+    // rclone-style directory marker alongside a plain empty file
+    for key in ["empty.txt", "photos/"] {
+        let path = format!("/s3/{}/{}", bucket, key);
+        let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &path, "").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "PutObject {}", key);
+
+        let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path, "").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "GetObject {}", key);
+        assert_eq!(resp.headers().get("content-length").unwrap(), "0");
+        assert!(test::read_body(resp).await.is_empty());
+
+        let resp = test::call_service(&app, signed(test::TestRequest::default().method(actix_web::http::Method::HEAD), "HEAD", &path, "").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "HeadObject {}", key);
+        // Content-Length is derived from the body size when the response is written
+        assert_eq!(resp.response().body().size(), BodySize::Sized(0));
+    }
+
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &format!("/s3/{}", bucket), "").to_request()).await;
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Key>photos/</Key>"), "{}", xml);
+    assert!(xml.contains("<Size>0</Size>"), "{}", xml);
+}
+
+#[actix_web::test]
+async fn test_native_empty_put_then_append() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(append)).await;
+    let key = unique("empty_put");
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", key)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), &format!("/get/{}", key)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(stored_files(&test::read_body(resp).await).is_empty());
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/append/{}", key)).set_payload(payload(&[b"abc"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), &format!("/get/{}", key)).to_request()).await;
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![b"abc".to_vec()]);
+
+    // A file with no bytes is kept as a zero-size chunk
+    let key = unique("empty_file");
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", key)).set_payload(payload(&[b"", b"x"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), &format!("/get/{}", key)).to_request()).await;
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![Vec::new(), b"x".to_vec()]);
+}
+
+#[actix_web::test]
+async fn test_empty_append_rejected_unless_allowed() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(append)).await;
+    let key = unique("empty_append");
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", key)).set_payload(payload(&[b"abc"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let empty_append = || native(test::TestRequest::post(), &format!("/append/{}", key)).to_request();
+    let resp = test::call_service(&app, empty_append()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "EmptyPayload");
+
+    std::env::set_var("ALLOW_EMPTY_APPEND", "true");
+    let resp = test::call_service(&app, empty_append()).await;
+    std::env::remove_var("ALLOW_EMPTY_APPEND");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), &format!("/get/{}", key)).to_request()).await;
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![b"abc".to_vec()]);
+}
@@ -0,0 +1,99 @@
+// Cache validators on native objects: X-Warp-Generation counts the writes of a key and
+// survives a rename, and the weak ETag derived from it answers If-None-Match with 304.
+
+use actix_web::{test, App, http::StatusCode};
+use warp_drive::api::{append, delete, get, put, update, update_key};
+
+mod common;
+use common::{payload, unique};
+
+const USER: &str = "generation_user";
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket))
+}
+
+fn header(resp: &actix_web::dev::ServiceResponse, name: &str) -> Option<String> {
+    resp.headers().get(name).map(|v| v.to_str().unwrap().to_string())
+}
+
+#[actix_web::test]
+async fn test_generation_counts_writes_and_survives_rename() {
+    common::isolate();
+    let app = test::init_service(
+        App::new().service(put).service(get).service(append).service(update).service(update_key).service(delete)
+    ).await;
+    let bucket = unique("generation");
+    let generation_of = |key: &str| {
+        let req = native(test::TestRequest::get(), &format!("/get/{}", key), &bucket).to_request();
+        let app = &app;
+        async move {
+            let resp = test::call_service(app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let generation = header(&resp, "x-warp-generation").expect("X-Warp-Generation on GET");
+            assert_eq!(header(&resp, "etag").unwrap(), format!("W/\"{}\"", generation));
+            assert!(header(&resp, "last-modified").is_some());
+            generation.parse::<u64>().unwrap()
+        }
+    };
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/doc", &bucket).set_payload(payload(&[b"v1"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "x-warp-generation").as_deref(), Some("1"));
+    assert_eq!(generation_of("doc").await, 1);
+
+    // The JSON answer to an append tells the client the new generation
+    let req = native(test::TestRequest::post(), "/append/doc", &bucket)
+        .insert_header(("x-warp-append-id", "a1"))
+        .set_payload(payload(&[b"v2"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "x-warp-generation").as_deref(), Some("2"));
+    let appended: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(appended["generation"], 2);
+    assert_eq!(generation_of("doc").await, 2);
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/update/doc", &bucket).set_payload(payload(&[b"v3"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "x-warp-generation").as_deref(), Some("3"));
+    assert_eq!(generation_of("doc").await, 3);
+
+    let resp = test::call_service(&app, native(test::TestRequest::put(), "/update_key/doc/renamed", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "x-warp-generation").as_deref(), Some("3"));
+    assert_eq!(generation_of("renamed").await, 3, "rename keeps the generation");
+
+    // A key written again after a delete continues from its last generation
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), "/delete/renamed", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/renamed", &bucket).set_payload(payload(&[b"v4"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(generation_of("renamed").await, 4);
+}
+
+#[actix_web::test]
+async fn test_if_none_match_on_the_weak_validator() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(append)).await;
+    let bucket = unique("validator");
+    let req = native(test::TestRequest::post(), "/put/doc", &bucket).set_payload(payload(&[b"cached"])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let conditional = |tags: &str| native(test::TestRequest::get(), "/get/doc", &bucket).insert_header(("if-none-match", tags)).to_request();
+    let resp = test::call_service(&app, conditional("W/\"1\"")).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(header(&resp, "etag").as_deref(), Some("W/\"1\""));
+    assert_eq!(header(&resp, "x-warp-generation").as_deref(), Some("1"));
+    assert!(test::read_body(resp).await.is_empty());
+    // Compared weakly, and any tag of a list will do
+    assert_eq!(test::call_service(&app, conditional("\"0\", \"1\"")).await.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(test::call_service(&app, conditional("*")).await.status(), StatusCode::NOT_MODIFIED);
+
+    // Once the object changes the old validator no longer matches
+    let req = native(test::TestRequest::post(), "/append/doc", &bucket).set_payload(payload(&[b"more"])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, conditional("W/\"1\"")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "etag").as_deref(), Some("W/\"2\""));
+}
@@ -0,0 +1,88 @@
+// Group commit of metadata inserts. METADATA_GROUP_COMMIT_MS is read once per process, so this
+// binary enables it in setup and every put_metadata goes through the committer; writers run
+// on plain threads because each one blocks until its batch commits.
+
+use std::sync::{Arc, Barrier, Once};
+use std::thread;
+use actix_web::http::StatusCode;
+use warp_drive::metadata::{Metadata, MetadataStorage};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::metrics;
+
+mod common;
+use common::unique;
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("METADATA_GROUP_COMMIT_MS", "50");
+        std::env::set_var("METADATA_GROUP_COMMIT_ROWS", "64");
+    });
+}
+
+/// Put `keys` from one thread each, all released at once; returns each put's status.
+fn put_concurrently(bucket: &str, keys: Vec<String>) -> Vec<(String, Result<(), StatusCode>)> {
+    let barrier = Arc::new(Barrier::new(keys.len()));
+    let writers: Vec<_> = keys.into_iter().enumerate().map(|(i, key)| {
+        let (barrier, bucket) = (Arc::clone(&barrier), bucket.to_string());
+        thread::spawn(move || {
+            let metadata = Metadata::from_offset_size_list(vec![(i as u64 * 100, 100)]);
+            barrier.wait();
+            let result = SQLiteMetadataStore::new().put_metadata("group_user", &bucket, &key, &metadata);
+            (key, result.map_err(|e| e.as_response_error().status_code()))
+        })
+    }).collect();
+    writers.into_iter().map(|w| w.join().unwrap()).collect()
+}
+
+#[test]
+fn test_concurrent_puts_all_land_in_few_batches() {
+    setup();
+    let bucket = unique("group");
+    let batches_before = metrics::counter_value("warpdrive_metadata_group_commits_total", &[]);
+
+    let keys: Vec<String> = (0..200).map(|i| format!("object-{}", i)).collect();
+    let results = put_concurrently(&bucket, keys);
+    assert!(results.iter().all(|(_, r)| r.is_ok()), "{:?}", results);
+
+    let store = SQLiteMetadataStore::new();
+    assert_eq!(store.list_objects("group_user", &bucket).unwrap().len(), 200);
+    for (i, key) in (0..200).map(|i| (i, format!("object-{}", i))) {
+        let metadata = store.get_metadata("group_user", &bucket, &key).unwrap();
+        assert_eq!(metadata.to_offset_size_list(), vec![(i * 100, 100)]);
+    }
+    let batches = metrics::counter_value("warpdrive_metadata_group_commits_total", &[]) - batches_before;
+    assert!(batches < 200, "200 rows should share transactions, got {} batches", batches);
+}
+
+#[test]
+fn test_duplicate_key_fails_alone() {
+    setup();
+    let bucket = unique("group-dup");
+    let store = SQLiteMetadataStore::new();
+    store.put_metadata("group_user", &bucket, "taken", &Metadata::from_offset_size_list(vec![(0, 1)])).unwrap();
+
+    // One existing key and one key put twice, among fresh keys in the same batch
+    let mut keys: Vec<String> = (0..20).map(|i| format!("fresh-{}", i)).collect();
+    keys.push("taken".to_string());
+    keys.push("twice".to_string());
+    keys.push("twice".to_string());
+    let results = put_concurrently(&bucket, keys);
+
+    for (key, result) in &results {
+        if key.starts_with("fresh-") {
+            assert_eq!(*result, Ok(()), "{}", key);
+            assert!(store.object_exists("group_user", &bucket, key).unwrap());
+        }
+    }
+    let taken: Vec<_> = results.iter().filter(|(k, _)| k == "taken").collect();
+    assert_eq!(taken[0].1, Err(StatusCode::CONFLICT));
+    assert_eq!(store.get_metadata("group_user", &bucket, "taken").unwrap().to_offset_size_list(), vec![(0, 1)]);
+
+    let twice: Vec<_> = results.iter().filter(|(k, _)| k == "twice").map(|(_, r)| *r).collect();
+    assert_eq!(twice.iter().filter(|r| r.is_ok()).count(), 1, "{:?}", twice);
+    assert!(twice.contains(&Err(StatusCode::CONFLICT)));
+    assert_eq!(store.list_objects("group_user", &bucket).unwrap().len(), 22);
+}
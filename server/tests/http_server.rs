@@ -0,0 +1,89 @@
+// HTTP server tuning: the builder main uses starts the configured number of workers, serves
+// plaintext HTTP/2 when asked, and refuses unusable TLS files before accepting connections.
+
+use actix_web::{web, App, HttpResponse};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use warp_drive::config::HttpConfig;
+use warp_drive::server::http_server;
+
+mod common;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn local_config() -> HttpConfig {
+    HttpConfig { host: "127.0.0.1".to_string(), port: free_port(), ..HttpConfig::default() }
+}
+
+#[actix_web::test]
+async fn test_builder_applies_worker_count() {
+    let instances = Arc::new(AtomicUsize::new(0));
+    let counted = instances.clone();
+    let factory = move || {
+        counted.fetch_add(1, Ordering::SeqCst);
+        App::new().route("/", web::get().to(|| async { HttpResponse::Ok().body("up") }))
+    };
+    let config = HttpConfig {
+        workers: Some(3),
+        keep_alive: Some(Duration::from_secs(30)),
+        client_request_timeout: Some(Duration::from_millis(2500)),
+        max_connections: Some(64),
+        ..local_config()
+    };
+    let server = http_server(factory, &config).unwrap();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    // Each worker builds its own app
+    let body = reqwest::get(format!("http://127.0.0.1:{}/", config.port)).await.unwrap().text().await.unwrap();
+    assert_eq!(body, "up");
+    for _ in 0..200 {
+        if instances.load(Ordering::SeqCst) >= 3 {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+    }
+    actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(instances.load(Ordering::SeqCst), 3);
+    handle.stop(true).await;
+}
+
+#[actix_web::test]
+async fn test_h2c_accepts_prior_knowledge() {
+    let config = HttpConfig { workers: Some(1), h2c: true, ..local_config() };
+    let server = http_server(|| App::new().route("/", web::get().to(|| async { HttpResponse::Ok().finish() })), &config).unwrap();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let client = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+    let resp = client.get(format!("http://127.0.0.1:{}/", config.port)).send().await.unwrap();
+    assert_eq!(resp.version(), reqwest::Version::HTTP_2);
+    assert!(resp.status().is_success());
+    handle.stop(true).await;
+}
+
+#[actix_web::test]
+async fn test_unusable_tls_files_fail_at_startup() {
+    let dir = common::isolate().join("tls");
+    std::fs::create_dir_all(&dir).unwrap();
+    let garbage = dir.join("garbage.pem");
+    std::fs::write(&garbage, "not a certificate").unwrap();
+    let app = || App::new();
+
+    let cases = [
+        (Some(dir.join("missing-cert.pem")), Some(dir.join("missing-key.pem")), "missing-cert.pem"),
+        (Some(garbage.clone()), Some(dir.join("missing-key.pem")), "no certificate"),
+        (Some(garbage.clone()), None, "TLS_KEY_FILE"),
+    ];
+    for (tls_cert, tls_key, expected) in cases {
+        let config = HttpConfig { workers: Some(1), tls_cert, tls_key, ..local_config() };
+        let error = http_server(app, &config).err().expect("TLS configuration accepted");
+        assert!(error.to_string().contains(expected), "{}", error);
+        // Nothing was left listening
+        TcpListener::bind(("127.0.0.1", config.port)).unwrap();
+    }
+}
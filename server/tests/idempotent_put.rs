@@ -0,0 +1,115 @@
+// Idempotent PUTs: a native PUT retried with the same `X-Warp-Idempotency-Key` and body is
+// answered 200 with the original outcome, while a different body, another key or a third
+// party's PUT without the header still gets 409. S3 PutObject with `If-None-Match: *` takes
+// the header the same way instead of answering 412. Requests to S3 are signed with the admin
+// access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use warp_drive::api::native_routes;
+use warp_drive::s3::handlers::{s3_get_object_handler, s3_put_object_handler};
+use warp_drive::service::api_version::ApiVersion;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{AMZ_DATE, authorization_with_query, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const USER: &str = "idempotent_user";
+const ACCESS_KEY: &str = "idempotent-test-access";
+const SECRET_KEY: &str = "idempotent-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str) -> test::TestRequest {
+    req.uri(path)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, "", UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_native_retry_is_idempotent() {
+    setup();
+    let app = test::init_service(App::new().service(web::scope("/v2").app_data(ApiVersion::V2).configure(native_routes))).await;
+    let bucket = unique("idempotent");
+    let put = |idempotency_key: Option<&str>, body: &[u8]| {
+        let req = test::TestRequest::post()
+            .uri("/v2/put/doc")
+            .insert_header(("user", USER))
+            .insert_header(("bucket", bucket.as_str()))
+            .set_payload(payload_of(body));
+        match idempotency_key {
+            Some(id) => req.insert_header(("x-warp-idempotency-key", id)).to_request(),
+            None => req.to_request(),
+        }
+    };
+
+    let resp = test::call_service(&app, put(Some("attempt-1"), b"original")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("x-warp-idempotency-replayed").is_none());
+    let first: serde_json::Value = test::read_body_json(resp).await;
+
+    // The retry gets the original answer and writes nothing
+    let resp = test::call_service(&app, put(Some("attempt-1"), b"original")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("x-warp-idempotency-replayed").unwrap(), "true");
+    let retried: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(retried, first);
+
+    // A different body under the same key, another key, or none at all still conflict
+    for (id, body) in [(Some("attempt-1"), &b"changed"[..]), (Some("attempt-2"), b"original"), (None, b"original")] {
+        assert_eq!(test::call_service(&app, put(id, body)).await.status(), StatusCode::CONFLICT, "{:?}", id);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/v2/get/doc?format=raw")
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket.as_str()))
+        .to_request();
+    assert_eq!(test::read_body(test::call_service(&app, req).await).await, "original".as_bytes());
+}
+
+#[actix_web::test]
+async fn test_s3_conditional_retry_is_idempotent() {
+    setup();
+    let bucket = unique("idempotent-s3");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let path = format!("/s3/{}/doc", bucket);
+    let put = |idempotency_key: Option<&str>, body: &'static [u8]| {
+        let req = signed(test::TestRequest::put(), "PUT", &path)
+            .insert_header(("if-none-match", "*"))
+            .set_payload(body);
+        match idempotency_key {
+            Some(id) => req.insert_header(("x-warp-idempotency-key", id)).to_request(),
+            None => req.to_request(),
+        }
+    };
+
+    let resp = test::call_service(&app, put(Some("attempt-1"), b"s3 original")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers().get("etag").unwrap().clone();
+
+    let resp = test::call_service(&app, put(Some("attempt-1"), b"s3 original")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("etag").unwrap(), &etag);
+    assert_eq!(resp.headers().get("x-warp-idempotency-replayed").unwrap(), "true");
+
+    for (id, body) in [(Some("attempt-1"), &b"s3 changed"[..]), (None, b"s3 original")] {
+        assert_eq!(test::call_service(&app, put(id, body)).await.status(), StatusCode::PRECONDITION_FAILED, "{:?}", id);
+    }
+    let req = signed(test::TestRequest::get(), "GET", &path).to_request();
+    assert_eq!(test::read_body(test::call_service(&app, req).await).await, "s3 original".as_bytes());
+}
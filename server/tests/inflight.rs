@@ -0,0 +1,101 @@
+// In-flight request registry and slow-request logging. The logger and SLOW_REQUEST_MS are
+// process-wide, so this binary installs the logger once, with no appenders, and reads the
+// slow-request line from the handling user's trace buffer.
+
+use actix_web::{test, web, App, HttpRequest, HttpResponse, http::StatusCode};
+use log::LevelFilter;
+use std::sync::Once;
+use std::time::Duration;
+use warp_drive::admin::list_inflight;
+use warp_drive::logging::{self, request_scope};
+use warp_drive::service::inflight;
+
+mod common;
+use common::unique;
+
+const ADMIN_SECRET: &str = "inflight-test-admin";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", ADMIN_SECRET);
+        std::env::set_var("SLOW_REQUEST_MS", "200");
+        let config = log4rs::Config::builder()
+            .build(log4rs::config::Root::builder().build(LevelFilter::Info))
+            .unwrap();
+        logging::install(config).unwrap();
+    });
+}
+
+/// Takes `?ms=` milliseconds, on behalf of the user in the `user` header
+async fn slow(req: HttpRequest, query: web::Query<std::collections::HashMap<String, String>>) -> HttpResponse {
+    if let Some(user) = req.headers().get("user").and_then(|v| v.to_str().ok()) {
+        logging::bind_user(user);
+    }
+    let ms = query.get("ms").and_then(|v| v.parse().ok()).unwrap_or(0);
+    tokio::time::sleep(Duration::from_millis(ms)).await;
+    HttpResponse::Ok().finish()
+}
+
+#[actix_web::test]
+async fn test_slow_request_is_listed_while_running_and_logged_after() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .wrap(actix_web::middleware::from_fn(inflight::track))
+            .wrap(actix_web::middleware::from_fn(request_scope))
+            .route("/slow/{name}", web::get().to(slow))
+            .service(list_inflight)
+    ).await;
+    let user = unique("slow_user");
+    logging::start_trace(&user, Duration::from_secs(60));
+
+    let pending = test::call_service(&app, test::TestRequest::get().uri("/slow/report?ms=400")
+        .insert_header(("user", user.clone())).insert_header(("x-request-id", "slow-1")).to_request());
+    let listing = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let req = test::TestRequest::get().uri("/admin/inflight?older_than_ms=50")
+            .insert_header(("Authorization", format!("Bearer {}", ADMIN_SECRET)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        body
+    };
+    let (resp, listed) = futures::join!(pending, listing);
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // While pending: listed with its route, user and age; the listing request is not
+    let requests = listed["requests"].as_array().unwrap();
+    assert_eq!(requests.len(), 1, "{}", listed);
+    let request = &requests[0];
+    assert_eq!(request["request_id"], "slow-1");
+    assert_eq!(request["method"], "GET");
+    assert_eq!(request["path"], "/slow/report");
+    assert_eq!(request["template"], "/slow/{name}");
+    assert_eq!(request["user"], user.as_str());
+    assert!(request["age_ms"].as_u64().unwrap() >= 50);
+
+    // Once done: gone from the registry and logged as slow with its context
+    let req = test::TestRequest::get().uri("/admin/inflight")
+        .insert_header(("Authorization", format!("Bearer {}", ADMIN_SECRET)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(body["requests"].as_array().unwrap().is_empty(), "{}", body);
+
+    let lines = logging::trace_snapshot(&user).unwrap().lines;
+    let slow_line = lines.iter().find(|l| l.message.starts_with("Slow request")).expect("no slow-request line");
+    assert_eq!(slow_line.level, "WARN");
+    assert_eq!(slow_line.request_id, "slow-1");
+    for part in ["GET /slow/report", "route /slow/{name}", user.as_str(), "-> 200"] {
+        assert!(slow_line.message.contains(part), "{:?} missing from {}", part, slow_line.message);
+    }
+
+    // Fast requests are not logged
+    let req = test::TestRequest::get().uri("/slow/quick").insert_header(("user", user.clone())).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let lines = logging::trace_snapshot(&user).unwrap().lines;
+    assert_eq!(lines.iter().filter(|l| l.message.starts_with("Slow request")).count(), 1);
+}
@@ -0,0 +1,96 @@
+// Small objects kept in the metadata row. tests/common turns inlining off for the other
+// binaries; these tests set INLINE_THRESHOLD_BYTES and STORAGE_BACKEND, which are read per
+// request, so they serialize on ENV_LOCK.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, App, http::StatusCode};
+use std::sync::{Mutex, Once};
+use warp_drive::api::{put, get, append, delete};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::storage::mock_store::{self, MockCalls};
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{payload, unique};
+
+const USER: &str = "inline_user";
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+static SETUP: Once = Once::new();
+
+fn setup(threshold: &str) {
+    SETUP.call_once(|| {
+        common::isolate();
+    });
+    std::env::set_var("INLINE_THRESHOLD_BYTES", threshold);
+    std::env::remove_var("STORAGE_BACKEND");
+}
+
+fn stored_files(body: &[u8]) -> Vec<Vec<u8>> {
+    let list = root_as_file_data_list(body).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket.to_string()))
+}
+
+#[actix_web::test]
+async fn test_small_object_never_reaches_storage() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup("2048");
+    std::env::set_var("STORAGE_BACKEND", "mock");
+    let app = test::init_service(App::new().service(put).service(get).service(delete)).await;
+    let bucket = unique("inline_mock");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/small", &bucket).set_payload(payload(&[b"hello", b"world!"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/small", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![b"hello".to_vec(), b"world!".to_vec()]);
+
+    assert_eq!(MetadataService::new(USER).unwrap().bucket_object_stats(&bucket).unwrap(), (1, 11));
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), "/delete/small", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    std::env::remove_var("STORAGE_BACKEND");
+    assert_eq!(mock_store::calls(USER), MockCalls::default());
+}
+
+#[actix_web::test]
+async fn test_appends_spill_past_the_threshold() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup("64");
+    let app = test::init_service(App::new().service(put).service(get).service(append).service(delete)).await;
+    let bucket = unique("inline_append");
+    let service = MetadataService::new(USER).unwrap();
+    service.create_bucket(&bucket).unwrap();
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/log", &bucket).set_payload(payload(&[b"first"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/append/log", &bucket).set_payload(payload(&[b"second"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let metadata = service.get_object_full(&bucket, "log").unwrap();
+    assert!(metadata.chunks.is_empty());
+    assert_eq!(metadata.inline_files(), [&b"first"[..], b"second"]);
+
+    // Crossing the threshold moves every file to storage, keeping the file boundaries
+    let large = vec![b'x'; 64];
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/append/log", &bucket).set_payload(payload(&[&large])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let metadata = service.get_object_full(&bucket, "log").unwrap();
+    assert!(metadata.inline_data.is_none());
+    assert_eq!(metadata.chunks.len(), 3);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/log", &bucket).to_request()).await;
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![b"first".to_vec(), b"second".to_vec(), large.clone()]);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/log?format=raw", &bucket).to_request()).await;
+    assert_eq!(test::read_body(resp).await, [&b"firstsecond"[..], &large].concat());
+
+    // An inline object has nothing to free
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/tiny", &bucket).set_payload(payload(&[b"tiny"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), "/delete/tiny", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!SQLiteMetadataStore::new().deletion_pending(USER, &bucket, "tiny").unwrap());
+}
@@ -1,14 +1,22 @@
 use actix_web::{test, App, http::StatusCode};
-use warp_drive::api::{put, get, append, delete, update_key, update};
+use warp_drive::api::{put, get, get_batch, append, delete, update_key, update, copy};
+use warp_drive::admin::{gc, repair};
+use warp_drive::metadata::MetadataStorage;
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::storage::config::StorageConfig;
 
 // bring in your generated flatbuffers schema
 use warp_drive::util::flatbuffer_store_generated::store::{
     FileData, FileDataArgs, FileDataList, FileDataListArgs,
 };
+use warp_drive::util::flatbuffer_store_v2_generated::store_v2;
 use flatbuffers::FlatBufferBuilder;
 
+mod common;
+
 #[actix_web::test]
 async fn test_basic_api_endpoints() {
+    common::isolate();
     // 1. Build a flatbuffer payload
     let mut builder = FlatBufferBuilder::new();
 
@@ -37,8 +45,7 @@ async fn test_basic_api_endpoints() {
     ).await;
 
     // 3. Send request with actix-web test client
-    // Use a unique key to avoid "Key already exists" error
-    let unique_key = format!("testkey_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let unique_key = "testkey".to_string();
     let uri = format!("/put/{}", unique_key);
     println!("Using key: {}", unique_key);
     
@@ -101,6 +108,7 @@ async fn test_basic_api_endpoints() {
 
 #[actix_web::test]
 async fn test_user_isolation() {
+    common::isolate();
     // Test that different users can have the same key names
     // This verifies the behavior found by TLA+ model checker
     
@@ -129,7 +137,7 @@ async fn test_user_isolation() {
     ).await;
 
     // 3. Test user1 storing data with key "shared_key"
-    let unique_key = format!("shared_key_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let unique_key = "shared_key".to_string();
     println!("Testing user isolation with key: {}", unique_key);
     
     // User1 PUT
@@ -197,6 +205,7 @@ async fn test_user_isolation() {
 
 #[actix_web::test]
 async fn test_append_endpoint() {
+    common::isolate();
     // Test the append functionality
     let mut builder = FlatBufferBuilder::new();
     let data_bytes = builder.create_vector(&[1u8, 2, 3, 4]);
@@ -220,7 +229,7 @@ async fn test_append_endpoint() {
             .service(update)
     ).await;
 
-    let unique_key = format!("append_test_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let unique_key = "append_test".to_string();
     println!("Testing append with key: {}", unique_key);
 
     // 1. First PUT some data
@@ -275,6 +284,7 @@ async fn test_append_endpoint() {
 
 #[actix_web::test]
 async fn test_delete_endpoint() {
+    common::isolate();
     // Test the delete functionality
     let mut builder = FlatBufferBuilder::new();
     let data_bytes = builder.create_vector(&[1u8, 2, 3, 4]);
@@ -298,7 +308,7 @@ async fn test_delete_endpoint() {
             .service(update)
     ).await;
 
-    let unique_key = format!("delete_test_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let unique_key = "delete_test".to_string();
     println!("Testing delete with key: {}", unique_key);
 
     // 1. PUT some data
@@ -347,6 +357,7 @@ async fn test_delete_endpoint() {
 
 #[actix_web::test]
 async fn test_update_key_endpoint() {
+    common::isolate();
     // Test the update_key functionality
     let mut builder = FlatBufferBuilder::new();
     let data_bytes = builder.create_vector(&[1u8, 2, 3, 4]);
@@ -370,8 +381,8 @@ async fn test_update_key_endpoint() {
             .service(update)
     ).await;
 
-    let old_key = format!("old_key_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
-    let new_key = format!("new_key_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let old_key = "old_key".to_string();
+    let new_key = "new_key".to_string();
     println!("Testing update_key: {} -> {}", old_key, new_key);
 
     // 1. PUT data with old key
@@ -429,6 +440,7 @@ async fn test_update_key_endpoint() {
 
 #[actix_web::test]
 async fn test_update_endpoint() {
+    common::isolate();
     // Test the update functionality
     let mut builder = FlatBufferBuilder::new();
     let data_bytes = builder.create_vector(&[1u8, 2, 3, 4]);
@@ -452,7 +464,7 @@ async fn test_update_endpoint() {
             .service(update)
     ).await;
 
-    let unique_key = format!("update_test_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let unique_key = "update_test".to_string();
     println!("Testing update with key: {}", unique_key);
 
     // 1. PUT initial data
@@ -523,6 +535,7 @@ async fn test_update_endpoint() {
 
 #[actix_web::test]
 async fn test_error_cases() {
+    common::isolate();
     // Test various error cases
     let app = test::init_service(
         App::new()
@@ -534,7 +547,7 @@ async fn test_error_cases() {
             .service(update)
     ).await;
 
-    let non_existent_key = format!("non_existent_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let non_existent_key = "non_existent".to_string();
 
     // 1. GET non-existent key
     let get_req = test::TestRequest::get()
@@ -545,6 +558,9 @@ async fn test_error_cases() {
     let get_resp = test::call_service(&app, get_req).await;
     println!("GET non-existent key Status: {:?}", get_resp.status());
     assert_eq!(get_resp.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = test::read_body_json(get_resp).await;
+    assert_eq!(body["error"], "KeyNotFound");
+    assert_eq!(body["key"], non_existent_key.as_str());
 
     // 2. DELETE non-existent key
     let delete_req = test::TestRequest::delete()
@@ -589,10 +605,47 @@ async fn test_error_cases() {
     let append_resp = test::call_service(&app, append_req).await;
     println!("APPEND to non-existent key Status: {:?}", append_resp.status());
     assert_eq!(append_resp.status(), StatusCode::NOT_FOUND);
+
+    // 6. PUT of an existing key conflicts
+    let mut builder = FlatBufferBuilder::new();
+    let data_bytes = builder.create_vector(&[1u8, 2, 3]);
+    let file = FileData::create(&mut builder, &FileDataArgs { data: Some(data_bytes) });
+    let files = builder.create_vector(&[file]);
+    let file_list = FileDataList::create(&mut builder, &FileDataListArgs { files: Some(files) });
+    builder.finish(file_list, None);
+    let existing_key = format!("existing_{}", non_existent_key);
+    let put_req = || test::TestRequest::post()
+        .uri(&format!("/put/{}", existing_key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(builder.finished_data().to_vec())
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req()).await.status(), StatusCode::OK);
+    let put_resp = test::call_service(&app, put_req()).await;
+    assert_eq!(put_resp.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = test::read_body_json(put_resp).await;
+    assert_eq!(body["error"], "KeyExists");
+
+    // 7. UPDATE_KEY onto an existing key conflicts too
+    let rename_req = test::TestRequest::put()
+        .uri(&format!("/update_key/{}/{}", existing_key, existing_key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    assert_eq!(test::call_service(&app, rename_req).await.status(), StatusCode::CONFLICT);
+
+    // 8. APPEND without a body (an empty PUT is a valid zero-length object)
+    let empty_req = test::TestRequest::post()
+        .uri(&format!("/append/{}", existing_key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let empty_resp = test::call_service(&app, empty_req).await;
+    assert_eq!(empty_resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(empty_resp).await;
+    assert_eq!(body["error"], "EmptyPayload");
 }
 
 #[actix_web::test]
 async fn test_bucket_feature() {
+    common::isolate();
     // Test the bucket functionality - verify user/bucket.bin file structure
     let mut builder = FlatBufferBuilder::new();
     let data_bytes = builder.create_vector(&[1u8, 2, 3, 4, 5]);
@@ -618,7 +671,7 @@ async fn test_bucket_feature() {
 
     let test_user = "bucket_test_user";
     let test_bucket = "test_bucket";
-    let test_key = format!("bucket_test_key_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let test_key = "bucket_test_key".to_string();
     
     println!("Testing bucket feature with user: {}, bucket: {}, key: {}", test_user, test_bucket, test_key);
 
@@ -636,13 +689,13 @@ async fn test_bucket_feature() {
     assert_eq!(put_resp.status(), StatusCode::OK);
 
     // 2. Verify the bucket file was created
-    let expected_bucket_file = format!("storage/{}/{}.bin", test_user, test_bucket);
-    println!("Checking for bucket file: {}", expected_bucket_file);
+    let expected_bucket_file = common::isolate().join("storage").join(test_user).join(format!("{}.bin", test_bucket));
+    println!("Checking for bucket file: {}", expected_bucket_file.display());
     
     // Check if the bucket file exists
-    let bucket_file_exists = std::path::Path::new(&expected_bucket_file).exists();
+    let bucket_file_exists = expected_bucket_file.exists();
     println!("Bucket file exists: {}", bucket_file_exists);
-    assert!(bucket_file_exists, "Bucket file should be created at: {}", expected_bucket_file);
+    assert!(bucket_file_exists, "Bucket file should be created at: {}", expected_bucket_file.display());
 
     // 3. GET data with bucket header
     let get_req = test::TestRequest::get()
@@ -660,7 +713,7 @@ async fn test_bucket_feature() {
     assert!(!get_body.is_empty(), "GET should return data");
 
     // 4. Test default bucket behavior (no bucket header)
-    let default_key = format!("default_bucket_key_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let default_key = "default_bucket_key".to_string();
     
     let put_default_req = test::TestRequest::post()
         .uri(&format!("/put/{}", default_key))
@@ -675,15 +728,15 @@ async fn test_bucket_feature() {
     assert_eq!(put_default_resp.status(), StatusCode::OK);
 
     // 5. Verify default bucket file was created
-    let expected_default_file = format!("storage/{}/default.bin", test_user);
-    println!("Checking for default bucket file: {}", expected_default_file);
+    let expected_default_file = common::isolate().join("storage").join(test_user).join("default.bin");
+    println!("Checking for default bucket file: {}", expected_default_file.display());
     
-    let default_file_exists = std::path::Path::new(&expected_default_file).exists();
+    let default_file_exists = expected_default_file.exists();
     println!("Default bucket file exists: {}", default_file_exists);
-    assert!(default_file_exists, "Default bucket file should be created at: {}", expected_default_file);
+    assert!(default_file_exists, "Default bucket file should be created at: {}", expected_default_file.display());
 
     // 6. Test bucket isolation - same key in different buckets
-    let isolated_key = format!("isolated_key_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let isolated_key = "isolated_key".to_string();
     let bucket1 = "bucket1";
     let bucket2 = "bucket2";
 
@@ -714,11 +767,11 @@ async fn test_bucket_feature() {
     assert_eq!(put_bucket2_resp.status(), StatusCode::OK);
 
     // 7. Verify both bucket files exist
-    let bucket1_file = format!("storage/{}/{}.bin", test_user, bucket1);
-    let bucket2_file = format!("storage/{}/{}.bin", test_user, bucket2);
+    let bucket1_file = common::isolate().join("storage").join(test_user).join(format!("{}.bin", bucket1));
+    let bucket2_file = common::isolate().join("storage").join(test_user).join(format!("{}.bin", bucket2));
     
-    assert!(std::path::Path::new(&bucket1_file).exists(), "Bucket1 file should exist: {}", bucket1_file);
-    assert!(std::path::Path::new(&bucket2_file).exists(), "Bucket2 file should exist: {}", bucket2_file);
+    assert!(bucket1_file.exists(), "Bucket1 file should exist: {}", bucket1_file.display());
+    assert!(bucket2_file.exists(), "Bucket2 file should exist: {}", bucket2_file.display());
 
     // 8. Test GET from specific buckets
     let get_bucket1_req = test::TestRequest::get()
@@ -746,3 +799,588 @@ async fn test_bucket_feature() {
     println!("✅ Verified bucket isolation");
     println!("✅ Verified default bucket behavior");
 }
+
+fn copy_test_payload(data: &[u8]) -> Vec<u8> {
+    let mut builder = FlatBufferBuilder::new();
+    let data_bytes = builder.create_vector(data);
+    let file = FileData::create(&mut builder, &FileDataArgs {
+        data: Some(data_bytes),
+    });
+    let files = builder.create_vector(&[file]);
+    let file_list = FileDataList::create(&mut builder, &FileDataListArgs {
+        files: Some(files),
+    });
+    builder.finish(file_list, None);
+    builder.finished_data().to_vec()
+}
+
+#[actix_web::test]
+async fn test_copy_within_bucket() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(delete).service(copy)).await;
+    let src_key = "copy_src".to_string();
+    let dst_key = "copy_dst".to_string();
+
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", src_key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(copy_test_payload(&[1, 2, 3, 4]))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+
+    let copy_req = test::TestRequest::post()
+        .uri(&format!("/copy/{}/{}", src_key, dst_key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let copy_resp = test::call_service(&app, copy_req).await;
+    assert_eq!(copy_resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(copy_resp).await;
+    assert_eq!(body["chunks"], 1);
+    assert_eq!(body["size"], 4);
+
+    // The copy must survive deletion of the source
+    let delete_req = test::TestRequest::delete()
+        .uri(&format!("/delete/{}", src_key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    assert_eq!(test::call_service(&app, delete_req).await.status(), StatusCode::OK);
+
+    let get_req = test::TestRequest::get()
+        .uri(&format!("/get/{}", dst_key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(get_resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(get_resp).await.to_vec(), copy_test_payload(&[1, 2, 3, 4]));
+}
+
+#[actix_web::test]
+async fn test_copy_across_buckets() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(copy)).await;
+    let key = "copy_xbucket".to_string();
+
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .insert_header(("bucket", "copy-src-bucket"))
+        .set_payload(copy_test_payload(&[9, 8, 7]))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+
+    let copy_req = test::TestRequest::post()
+        .uri(&format!("/copy/{}/{}", key, key))
+        .insert_header(("user", "testuser1"))
+        .insert_header(("Src-Bucket", "copy-src-bucket"))
+        .insert_header(("Dst-Bucket", "copy-dst-bucket"))
+        .to_request();
+    assert_eq!(test::call_service(&app, copy_req).await.status(), StatusCode::OK);
+
+    let get_req = test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "testuser1"))
+        .insert_header(("bucket", "copy-dst-bucket"))
+        .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(get_resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(get_resp).await.to_vec(), copy_test_payload(&[9, 8, 7]));
+}
+
+#[actix_web::test]
+async fn test_copy_missing_source() {
+    common::isolate();
+    let app = test::init_service(App::new().service(copy)).await;
+    let copy_req = test::TestRequest::post()
+        .uri("/copy/copy_missing/copy_missing_dst")
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    assert_eq!(test::call_service(&app, copy_req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_copy_destination_exists() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(copy)).await;
+    let src_key = "copy_conflict_src".to_string();
+    let dst_key = "copy_conflict_dst".to_string();
+
+    for (key, data) in [(&src_key, [1u8, 1]), (&dst_key, [2u8, 2])] {
+        let put_req = test::TestRequest::post()
+            .uri(&format!("/put/{}", key))
+            .insert_header(("user", "testuser1"))
+            .set_payload(copy_test_payload(&data))
+            .to_request();
+        assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+    }
+
+    let copy_req = test::TestRequest::post()
+        .uri(&format!("/copy/{}/{}", src_key, dst_key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    assert_eq!(test::call_service(&app, copy_req).await.status(), StatusCode::CONFLICT);
+
+    let overwrite_req = test::TestRequest::post()
+        .uri(&format!("/copy/{}/{}?overwrite=true", src_key, dst_key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    assert_eq!(test::call_service(&app, overwrite_req).await.status(), StatusCode::OK);
+
+    let get_req = test::TestRequest::get()
+        .uri(&format!("/get/{}", dst_key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(test::read_body(get_resp).await.to_vec(), copy_test_payload(&[1, 1]));
+}
+
+fn v2_payload(files: &[(&[u8], Option<&str>)]) -> Vec<u8> {
+    let mut builder = FlatBufferBuilder::new();
+    let mut entries = Vec::new();
+    for (data, name) in files {
+        let data = builder.create_vector(data);
+        let name = name.map(|n| builder.create_string(n));
+        entries.push(store_v2::FileData::create(&mut builder, &store_v2::FileDataArgs {
+            data: Some(data),
+            name,
+            ..Default::default()
+        }));
+    }
+    let files = builder.create_vector(&entries);
+    let list = store_v2::FileDataList::create(&mut builder, &store_v2::FileDataListArgs { files: Some(files) });
+    store_v2::finish_file_data_list_buffer(&mut builder, list);
+    builder.finished_data().to_vec()
+}
+
+#[actix_web::test]
+async fn test_v2_round_trip_preserves_names() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let key = "v2_names".to_string();
+
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(v2_payload(&[(b"first", Some("a.txt")), (b"second", Some("b.txt"))]))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+
+    let get_req = test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let body = test::read_body(test::call_service(&app, get_req).await).await;
+    assert!(store_v2::file_data_list_buffer_has_identifier(&body));
+    let list = store_v2::root_as_file_data_list(&body).unwrap();
+    let files = list.files().unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files.get(0).name(), Some("a.txt"));
+    assert_eq!(files.get(0).data().unwrap().bytes(), b"first");
+    assert_eq!(files.get(1).name(), Some("b.txt"));
+    assert_eq!(files.get(1).data().unwrap().bytes(), b"second");
+}
+
+#[actix_web::test]
+async fn test_v1_round_trip_unchanged() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let key = "v1_plain".to_string();
+    let payload = copy_test_payload(&[4, 3, 2, 1]);
+
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(payload.clone())
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+
+    let get_req = test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let body = test::read_body(test::call_service(&app, get_req).await).await;
+    assert!(!store_v2::file_data_list_buffer_has_identifier(&body));
+    assert_eq!(body.to_vec(), payload);
+}
+
+#[actix_web::test]
+async fn test_append_v1_onto_v2_object() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(append)).await;
+    let key = "v2_mixed_append".to_string();
+
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(v2_payload(&[(b"named", Some("named.txt"))]))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+
+    let append_req = test::TestRequest::post()
+        .uri(&format!("/append/{}", key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(copy_test_payload(b"plain"))
+        .to_request();
+    assert_eq!(test::call_service(&app, append_req).await.status(), StatusCode::OK);
+
+    // Names survive the append; the v1 chunk comes back unnamed in the same v2 buffer
+    let get_req = test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let body = test::read_body(test::call_service(&app, get_req).await).await;
+    let list = store_v2::root_as_file_data_list(&body).unwrap();
+    let files = list.files().unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files.get(0).name(), Some("named.txt"));
+    assert_eq!(files.get(1).name(), None);
+    assert_eq!(files.get(1).data().unwrap().bytes(), b"plain");
+}
+
+#[actix_web::test]
+async fn test_gc_reports_and_queues_orphans() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", "gc-test-secret");
+    let app = test::init_service(App::new().service(put).service(gc)).await;
+    let user = "gc_user".to_string();
+    let bucket = "gc-bucket";
+
+    // One referenced object, then a chunk written straight to the store with no metadata
+    let put_req = test::TestRequest::post()
+        .uri("/put/referenced")
+        .insert_header(("user", user.as_str()))
+        .insert_header(("bucket", bucket))
+        .set_payload(copy_test_payload(&[1, 2, 3, 4]))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+    let orphan = StorageConfig::from_env().create_store().write(&user, bucket, b"orphaned bytes").unwrap();
+
+    let unauthenticated = test::TestRequest::post()
+        .uri(&format!("/admin/gc/{}/{}?dry_run=true", user, bucket))
+        .to_request();
+    assert_eq!(test::call_service(&app, unauthenticated).await.status(), StatusCode::UNAUTHORIZED);
+
+    let dry_run = test::TestRequest::post()
+        .uri(&format!("/admin/gc/{}/{}?dry_run=true", user, bucket))
+        .insert_header(("Authorization", "Bearer gc-test-secret"))
+        .to_request();
+    let resp = test::call_service(&app, dry_run).await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    let job = common::finished_job(&test::read_body_json(resp).await).await;
+    assert_eq!(job["state"], "done", "{}", job);
+    let report = &job["result"];
+    assert_eq!(report["orphans"], serde_json::json!([[orphan.0, orphan.1]]));
+    assert_eq!(report["queued"], false);
+
+    let queued_for = |user: &str| SQLiteMetadataStore::new().get_pending_deletions(i32::MAX).unwrap()
+        .into_iter()
+        .filter(|e| e.user_id == user && e.bucket == bucket)
+        .flat_map(|e| e.offset_size_list)
+        .collect::<Vec<_>>();
+    assert!(queued_for(&user).is_empty());
+
+    let real_run = test::TestRequest::post()
+        .uri(&format!("/admin/gc/{}/{}", user, bucket))
+        .insert_header(("Authorization", "Bearer gc-test-secret"))
+        .to_request();
+    let job = common::finished_job(&test::read_body_json(test::call_service(&app, real_run).await).await).await;
+    assert_eq!(job["result"]["queued"], true);
+    assert_eq!(queued_for(&user), vec![orphan]);
+
+    // Queued ranges count as accounted for, so a second pass finds nothing new
+    let again = test::TestRequest::post()
+        .uri(&format!("/admin/gc/{}/{}?dry_run=true", user, bucket))
+        .insert_header(("Authorization", "Bearer gc-test-secret"))
+        .to_request();
+    let job = common::finished_job(&test::read_body_json(test::call_service(&app, again).await).await).await;
+    assert_eq!(job["result"]["orphans"], serde_json::json!([]));
+}
+
+fn append_with_id_request(key: &str, data: &[u8], append_id: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri(&format!("/append/{}", key))
+        .insert_header(("user", "testuser1"))
+        .insert_header(("x-warp-append-id", append_id))
+        .set_payload(copy_test_payload(data))
+}
+
+fn stored_files(body: &[u8]) -> Vec<Vec<u8>> {
+    let list = warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list(body).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+#[actix_web::test]
+async fn test_append_with_repeated_id_is_applied_once() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(append)).await;
+    let key = "append_dedup_same".to_string();
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(copy_test_payload(&[1, 2]))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, append_with_id_request(&key, &[3, 4, 5], "0b6e3c4e-retry").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let first: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(first["replayed"], false);
+    let resp = test::call_service(&app, append_with_id_request(&key, &[3, 4, 5], "0b6e3c4e-retry").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let retry: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(retry["replayed"], true);
+    assert_eq!(retry["first_chunk"], first["first_chunk"]);
+    assert_eq!(retry["size"], 3);
+
+    let get_req = test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let body = test::read_body(test::call_service(&app, get_req).await).await;
+    assert_eq!(stored_files(&body), vec![vec![1, 2], vec![3, 4, 5]]);
+}
+
+#[actix_web::test]
+async fn test_append_with_distinct_ids_is_applied_each_time() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(append)).await;
+    let key = "append_dedup_distinct".to_string();
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(copy_test_payload(&[1, 2]))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, append_with_id_request(&key, &[3, 4, 5], "append-one").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, append_with_id_request(&key, &[3, 4, 5], "append-two").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let second: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(second["replayed"], false);
+    assert_eq!(second["first_chunk"], 2);
+
+    let get_req = test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let body = test::read_body(test::call_service(&app, get_req).await).await;
+    assert_eq!(stored_files(&body), vec![vec![1, 2], vec![3, 4, 5], vec![3, 4, 5]]);
+}
+
+#[actix_web::test]
+async fn test_get_selected_chunks() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(append)).await;
+    let key = "chunk_select".to_string();
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(copy_test_payload(b"monday"))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+    for segment in [&b"tuesday"[..], b"wednesday"] {
+        let append_req = test::TestRequest::post()
+            .uri(&format!("/append/{}", key))
+            .insert_header(("user", "testuser1"))
+            .set_payload(copy_test_payload(segment))
+            .to_request();
+        assert_eq!(test::call_service(&app, append_req).await.status(), StatusCode::OK);
+    }
+
+    let get_req = |query: &str| test::TestRequest::get()
+        .uri(&format!("/get/{}?{}", key, query))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    for (index, segment) in [&b"monday"[..], b"tuesday", b"wednesday"].into_iter().enumerate() {
+        let resp = test::call_service(&app, get_req(&format!("chunk={}", index))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(stored_files(&test::read_body(resp).await), vec![segment.to_vec()]);
+    }
+    let resp = test::call_service(&app, get_req("chunks=1-2")).await;
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![b"tuesday".to_vec(), b"wednesday".to_vec()]);
+
+    let resp = test::call_service(&app, get_req("chunks=2-3")).await;
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["chunk_count"], 3);
+    assert_eq!(body["message"], "Chunks 2-3 requested but valid indices are 0-2");
+}
+
+#[actix_web::test]
+async fn test_get_reports_deleting_state_until_worker_runs() {
+    common::isolate();
+    use warp_drive::service::deletion_worker::DeletionWorker;
+
+    let app = test::init_service(App::new().service(put).service(get).service(delete)).await;
+    let key = "object_state".to_string();
+    let get_req = || test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .set_payload(copy_test_payload(&[7, 7, 7]))
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, get_req()).await;
+    assert_eq!(resp.headers().get("x-warp-object-state").unwrap(), "active");
+
+    let delete_req = test::TestRequest::delete()
+        .uri(&format!("/delete/{}", key))
+        .insert_header(("user", "testuser1"))
+        .to_request();
+    assert_eq!(test::call_service(&app, delete_req).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, get_req()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers().get("x-warp-object-state").unwrap(), "deleting");
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["state"], "deleting");
+
+    // Other tests queue deletions too; drain until ours has been processed
+    let worker = DeletionWorker::new();
+    for _ in 0..20 {
+        worker.process_deletions().await.unwrap();
+        let resp = test::call_service(&app, get_req()).await;
+        if resp.headers().get("x-warp-object-state").is_none() {
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            return;
+        }
+    }
+    panic!("deletion of {} was never processed", key);
+}
+
+#[actix_web::test]
+async fn test_repair_rewrites_corrupted_chunk() {
+    common::isolate();
+    use std::io::{Seek, SeekFrom, Write};
+
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", "gc-test-secret");
+    let app = test::init_service(App::new().service(put).service(get).service(repair)).await;
+    let key = "repair".to_string();
+    let bucket = "repair-bucket";
+
+    let mut builder = FlatBufferBuilder::new();
+    let first = builder.create_vector(b"first chunk");
+    let second = builder.create_vector(b"second chunk");
+    let files = [
+        FileData::create(&mut builder, &FileDataArgs { data: Some(first) }),
+        FileData::create(&mut builder, &FileDataArgs { data: Some(second) }),
+    ];
+    let files = builder.create_vector(&files);
+    let list = FileDataList::create(&mut builder, &FileDataListArgs { files: Some(files) });
+    builder.finish(list, None);
+    let original = builder.finished_data().to_vec();
+
+    let put_req = test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "testuser1"))
+        .insert_header(("bucket", bucket))
+        .set_payload(original.clone())
+        .to_request();
+    assert_eq!(test::call_service(&app, put_req).await.status(), StatusCode::OK);
+
+    // Corrupt the second chunk in place
+    let chunks = SQLiteMetadataStore::new().get_metadata("testuser1", bucket, &key).unwrap().to_offset_size_list();
+    let (bad_offset, bad_size) = chunks[1];
+    let (path, position) = StorageConfig::from_env().create_store().backing_file("testuser1", bucket, bad_offset).unwrap();
+    let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(position)).unwrap();
+    file.write_all(b"XXXXXXXXXXXX").unwrap();
+    drop(file);
+
+    let get_req = || test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "testuser1"))
+        .insert_header(("bucket", bucket))
+        .to_request();
+    let body = test::read_body(test::call_service(&app, get_req()).await).await;
+    assert_eq!(stored_files(&body)[1], b"XXXXXXXXXXXX".to_vec());
+
+    let repair_req = |data: &[u8]| test::TestRequest::put()
+        .uri(&format!("/repair/{}?chunk=1", key))
+        .insert_header(("user", "testuser1"))
+        .insert_header(("bucket", bucket))
+        .insert_header(("Authorization", "Bearer gc-test-secret"))
+        .set_payload(data.to_vec())
+        .to_request();
+    let resp = test::call_service(&app, repair_req(b"too short")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = test::call_service(&app, repair_req(b"second chunk")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(report["old_offset"], bad_offset);
+    assert_ne!(report["new_offset"], bad_offset);
+    assert_eq!(report["read_back_verified"], true);
+
+    let body = test::read_body(test::call_service(&app, get_req()).await).await;
+    assert_eq!(stored_files(&body), stored_files(&original));
+
+    let queued: Vec<(u64, u64)> = SQLiteMetadataStore::new().get_pending_deletions(i32::MAX).unwrap()
+        .into_iter()
+        .filter(|e| e.user_id == "testuser1" && e.bucket == bucket && e.key == key)
+        .flat_map(|e| e.offset_size_list)
+        .collect();
+    assert_eq!(queued, vec![(bad_offset, bad_size)]);
+}
+
+#[actix_web::test]
+async fn test_get_batch_reports_missing_keys() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get_batch)).await;
+    let bucket = "batch".to_string();
+    let (first, second) = ("batch_a".to_string(), "batch_b".to_string());
+
+    let objects: [(&str, Vec<u8>); 2] = [
+        (&first, copy_test_payload(b"alpha")),
+        (&second, v2_payload(&[(b"be", Some("part1")), (b"ta", Some("part2"))])),
+    ];
+    for (key, payload) in objects {
+        let req = test::TestRequest::post()
+            .uri(&format!("/put/{}", key))
+            .insert_header(("user", "testuser1"))
+            .insert_header(("bucket", bucket.as_str()))
+            .set_payload(payload)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    let keys = serde_json::json!([first, "missing_one", second, "missing_two"]);
+    let req = test::TestRequest::post()
+        .uri("/get_batch")
+        .insert_header(("user", "testuser1"))
+        .insert_header(("bucket", bucket.as_str()))
+        .set_payload(keys.to_string())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let errors: serde_json::Value = serde_json::from_str(
+        resp.headers().get("x-warp-batch-errors").unwrap().to_str().unwrap()
+    ).unwrap();
+    assert_eq!(errors, serde_json::json!([
+        { "key": "missing_one", "error": "NoSuchKey" },
+        { "key": "missing_two", "error": "NoSuchKey" },
+    ]));
+
+    let body = test::read_body(resp).await;
+    let list = store_v2::root_as_file_data_list(&body).unwrap();
+    let files = list.files().unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files.get(0).name(), Some(first.as_str()));
+    assert_eq!(files.get(0).data().unwrap().bytes(), b"alpha");
+    assert_eq!(files.get(1).name(), Some(second.as_str()));
+    assert_eq!(files.get(1).data().unwrap().bytes(), b"beta");
+    // Each file says which requested key it answers
+    assert_eq!((files.get(0).index(), files.get(1).index()), (0, 2));
+
+    // Malformed or empty key lists are rejected outright
+    for body in ["{\"keys\":[]}", "[]"] {
+        let req = test::TestRequest::post()
+            .uri("/get_batch")
+            .insert_header(("user", "testuser1"))
+            .set_payload(body)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,181 @@
+// Bucket inventories: POST /admin/inventory runs a job listing a bucket's live objects as CSV or
+// NDJSON into INVENTORY_DIRECTORY or into an object of a target bucket; the rows are checked
+// against the metadata store.
+
+use actix_web::{test, App, http::StatusCode};
+use std::collections::HashMap;
+use std::sync::Once;
+use warp_drive::admin::inventory;
+use warp_drive::metadata::Metadata;
+use warp_drive::service::inventory::CSV_COLUMNS;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::storage_service::{StorageMode, StorageService};
+use warp_drive::service::user_context::UserContext;
+
+mod common;
+use common::{finished_job, unique};
+
+const SECRET: &str = "inventory-test-secret";
+const USER: &str = "inventory_user";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        let root = common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+        std::env::set_var("INVENTORY_DIRECTORY", root.join("inventory"));
+    });
+}
+
+/// A bucket of objects with awkward keys, tags on some, and one deleted object
+fn fixture() -> String {
+    let bucket = unique("inventory");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&bucket).unwrap();
+    let storage = StorageService::new();
+    let context = UserContext::with_bucket(USER.to_string(), bucket.clone());
+    let objects: [(&str, &[u8]); 5] = [
+        ("plain.txt", b"plain contents"),
+        ("with,comma", b"comma"),
+        ("say \"hi\"", b"quoted key"),
+        ("two\nlines", b"line break"),
+        ("deleted", b"gone"),
+    ];
+    for (key, data) in objects {
+        let extents = storage.write_object(&context, data, StorageMode::RAW).unwrap();
+        let mut metadata = Metadata::from_offset_size_list(extents);
+        metadata.size = data.len() as u64;
+        metadata.etag = Some(format!("\"{:x}\"", md5::compute(data)));
+        db.put_object_full(&bucket, key, metadata).unwrap();
+    }
+    db.set_object_tags(&bucket, "plain.txt", &[("team".to_string(), "a,b".to_string()), ("env".to_string(), "prod".to_string())]).unwrap();
+    db.set_object_tags(&bucket, "with,comma", &[("k".to_string(), "say \"v\"".to_string())]).unwrap();
+    storage.delete_object(&context, "deleted").unwrap();
+    bucket
+}
+
+/// Parse RFC 4180 CSV
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let (mut rows, mut row, mut field) = (Vec::new(), Vec::new(), String::new());
+    let (mut quoted, mut chars) = (false, text.chars().peekable());
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    assert!(!quoted && field.is_empty() && row.is_empty(), "unterminated CSV: {:?}", text);
+    rows
+}
+
+fn request(uri: &str) -> test::TestRequest {
+    test::TestRequest::post().uri(uri).insert_header(("Authorization", format!("Bearer {}", SECRET)))
+}
+
+/// Run an inventory with `query` and return the finished job
+async fn run(bucket: &str, query: &str) -> serde_json::Value {
+    let app = test::init_service(App::new().service(inventory)).await;
+    let resp = test::call_service(&app, request(&format!("/admin/inventory/{}/{}?{}", USER, bucket, query)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    let job = finished_job(&test::read_body_json(resp).await).await;
+    assert_eq!(job["state"], "done", "{}", job);
+    job
+}
+
+/// Tags of `key` as stored, as the JSON object the inventory writes
+fn stored_tags(db: &MetadataService, bucket: &str, key: &str) -> serde_json::Value {
+    let tags: serde_json::Map<_, _> = db.get_object_tags(bucket, key).unwrap().into_iter()
+        .map(|(k, v)| (k, serde_json::Value::String(v)))
+        .collect();
+    serde_json::Value::Object(tags)
+}
+
+#[actix_web::test]
+async fn test_csv_inventory_matches_metadata() {
+    setup();
+    let bucket = fixture();
+    let job = run(&bucket, "format=csv").await;
+    let report = &job["result"];
+    assert_eq!(report["objects"], 4);
+    let path = report["location"].as_str().unwrap();
+    assert!(path.ends_with(".csv"), "{}", path);
+    let text = std::fs::read_to_string(path).unwrap();
+    assert_eq!(report["inventory_bytes"], text.len());
+
+    let rows = parse_csv(&text);
+    assert_eq!(rows[0], CSV_COLUMNS);
+    assert_eq!(rows.len(), 5, "{:?}", rows);
+    let db = MetadataService::new(USER).unwrap();
+    let mut seen = HashMap::new();
+    let mut total = 0;
+    for row in &rows[1..] {
+        assert_eq!(row.len(), CSV_COLUMNS.len(), "{:?}", row);
+        let key = &row[0];
+        let metadata = db.get_object_full(&bucket, key).unwrap();
+        assert_eq!(row[1], metadata.size.to_string(), "{}", key);
+        assert_eq!(row[2], metadata.chunks.len().to_string(), "{}", key);
+        assert!(!row[3].is_empty(), "{}: no created_at", key);
+        assert_eq!(Some(&row[4]), metadata.last_modified.as_ref(), "{}", key);
+        assert_eq!(Some(&row[5]), metadata.etag.as_ref(), "{}", key);
+        let tags = stored_tags(&db, &bucket, key);
+        if tags.as_object().unwrap().is_empty() {
+            assert_eq!(row[6], "", "{}", key);
+        } else {
+            assert_eq!(serde_json::from_str::<serde_json::Value>(&row[6]).unwrap(), tags, "{}", key);
+        }
+        total += metadata.size;
+        seen.insert(key.clone(), row.clone());
+    }
+    assert_eq!(report["bytes"], total);
+    let mut keys: Vec<&String> = seen.keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["plain.txt", "say \"hi\"", "two\nlines", "with,comma"]);
+    assert_eq!(seen["with,comma"][6], r#"{"k":"say \"v\""}"#);
+}
+
+#[actix_web::test]
+async fn test_ndjson_inventory_into_bucket() {
+    setup();
+    let bucket = fixture();
+    let target = unique("inventory_target");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&target).unwrap();
+    let job = run(&bucket, &format!("format=ndjson&target_bucket={}&target_key=reports/list.ndjson", target)).await;
+    assert_eq!(job["result"]["location"], format!("{}/reports/list.ndjson", target));
+
+    let metadata = db.get_object_full(&target, "reports/list.ndjson").unwrap();
+    assert_eq!(metadata.content_type.as_deref(), Some("application/x-ndjson"));
+    let context = UserContext::with_bucket(USER.to_string(), target.clone());
+    let data = StorageService::new().read_stored(&context, &metadata, StorageMode::RAW).unwrap();
+    assert_eq!(metadata.etag, Some(format!("\"{:x}\"", md5::compute(&data))));
+    let lines: Vec<serde_json::Value> = String::from_utf8(data.to_vec()).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4);
+    for line in &lines {
+        let key = line["key"].as_str().unwrap();
+        let stored = db.get_object_full(&bucket, key).unwrap();
+        assert_eq!(line["size"], stored.size);
+        assert_eq!(line["etag"].as_str(), stored.etag.as_deref());
+        assert_eq!(line["tags"], stored_tags(&db, &bucket, key));
+    }
+
+    // Unknown formats and buckets are refused before a job is queued
+    let app = test::init_service(App::new().service(inventory)).await;
+    let resp = test::call_service(&app, request(&format!("/admin/inventory/{}/{}?format=parquet", USER, bucket)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, request(&format!("/admin/inventory/{}/missing-bucket", USER)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,103 @@
+// Storage I/O accounting: known-size writes and reads, then a compaction, are counted exactly
+// in the /metrics scrape and GET /admin/stats/io, the compactor's copies under their own
+// traffic label. One test, since the counters are process-wide.
+
+use actix_web::{test, App, http::StatusCode};
+use warp_drive::admin::io_stats;
+use warp_drive::metrics;
+use warp_drive::service::compaction::{self, CompactionConfig};
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::object_service::ObjectService;
+use warp_drive::service::user_context::UserContext;
+
+mod common;
+use common::{payload, payload_of, unique};
+
+const USER: &str = "io_stats_user";
+const SECRET: &str = "io-stats-secret";
+const KIB: u64 = 1024;
+
+/// Every sample of `name` in a scrape of `/metrics`, as `(labels, value)`
+async fn scrape(name: &str) -> Vec<(String, f64)> {
+    let body = actix_web::body::to_bytes(metrics::metrics_handler().await.into_body()).await.unwrap();
+    String::from_utf8_lossy(&body).lines()
+        .filter_map(|line| line.strip_prefix(name))
+        .filter(|rest| rest.starts_with('{') || rest.starts_with(' '))
+        .map(|rest| {
+            let (labels, value) = rest.rsplit_once(' ').unwrap();
+            (labels.to_string(), value.parse().unwrap())
+        })
+        .collect()
+}
+
+async fn sample(name: &str, labels: &str) -> f64 {
+    scrape(name).await.into_iter()
+        .find(|(l, _)| l == labels)
+        .map_or(0.0, |(_, value)| value)
+}
+
+/// `name{op, traffic}` in a scrape of `/metrics`
+async fn by_traffic(name: &str, op: &str, traffic: &str) -> f64 {
+    sample(name, &format!("{{op=\"{}\",traffic=\"{}\"}}", op, traffic)).await
+}
+
+#[actix_web::test]
+async fn test_io_counters_attribute_client_and_compaction_bytes() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+    let context = UserContext::with_bucket(USER.to_string(), unique("io"));
+    let objects = ObjectService::new();
+
+    // 64 KiB that will be freed, then three 16 KiB chunks left behind the gap
+    objects.put(&context, "filler", async { Ok(payload_of(&[1; 64 * 1024]).into()) }, None).await.unwrap();
+    let chunks: Vec<Vec<u8>> = (2..5u8).map(|seed| vec![seed; 16 * 1024]).collect();
+    let expected = chunks.concat();
+    objects.put(&context, "moving", async move { Ok(payload(&chunks.iter().map(Vec::as_slice).collect::<Vec<_>>()).into()) }, None).await.unwrap();
+    let read = objects.get(&context, "moving", None).unwrap();
+    assert_eq!(objects.read_raw(&context, &read, None).unwrap(), expected);
+
+    objects.delete(&context, "filler").await.unwrap();
+    DeletionWorker::new().process_deletions().await.unwrap();
+    let config = CompactionConfig { free_ratio: 0.0, ..CompactionConfig::default() };
+    let report = compaction::relocate_bucket(USER, &context.bucket, &config).await.unwrap();
+    assert_eq!((report.chunks_moved, report.bytes_moved), (3, 48 * KIB));
+
+    let bytes = |op, traffic| by_traffic("warpdrive_storage_bytes_total", op, traffic);
+    let ops = |op, traffic| by_traffic("warpdrive_storage_ops_total", op, traffic);
+    assert_eq!(bytes("write", "client").await, (112 * KIB) as f64);
+    assert_eq!(ops("write", "client").await, 4.0);
+    assert_eq!(bytes("read", "client").await, (48 * KIB) as f64);
+    assert_eq!(bytes("delete", "client").await, (64 * KIB) as f64);
+    assert_eq!(bytes("write", "compaction").await, (48 * KIB) as f64);
+    assert_eq!(bytes("read", "compaction").await, (48 * KIB) as f64);
+    assert_eq!(ops("write", "compaction").await, 3.0);
+    assert_eq!(bytes("write", "replication").await, 0.0);
+    assert_eq!(sample("warpdrive_storage_logical_bytes_written_total", "").await, (112 * KIB) as f64);
+    let amplification = (160 * KIB) as f64 / (112 * KIB) as f64;
+    assert_eq!(sample("warpdrive_storage_write_amplification", "").await, amplification);
+    assert_eq!(sample("warpdrive_storage_user_bytes", &format!("{{user=\"{}\",op=\"write\"}}", USER)).await, (160 * KIB) as f64);
+    assert_eq!(sample("warpdrive_storage_user_bytes", &format!("{{user=\"{}\",op=\"read\"}}", USER)).await, (96 * KIB) as f64);
+
+    let app = test::init_service(App::new().service(io_stats)).await;
+    let req = test::TestRequest::get().uri("/admin/stats/io?top=1")
+        .insert_header(("Authorization", format!("Bearer {}", SECRET)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let stats: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(stats["logical_written_bytes"], 112 * KIB);
+    assert_eq!(stats["physical_written_bytes"], 160 * KIB);
+    assert_eq!(stats["write_amplification"], amplification);
+    assert_eq!(stats["top_users"], serde_json::json!([{ "user": USER, "written_bytes": 160 * KIB, "read_bytes": 96 * KIB }]));
+    let compaction_writes = stats["operations"].as_array().unwrap().iter()
+        .find(|o| o["op"] == "write" && o["traffic"] == "compaction")
+        .unwrap();
+    assert_eq!((compaction_writes["ops"].as_u64(), compaction_writes["bytes"].as_u64()), (Some(3), Some(48 * KIB)));
+
+    let req = test::TestRequest::get().uri("/admin/stats/io?top=many")
+        .insert_header(("Authorization", format!("Bearer {}", SECRET)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    let req = test::TestRequest::get().uri("/admin/stats/io").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+}
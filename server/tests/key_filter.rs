@@ -0,0 +1,113 @@
+// Key existence filter for native PUT (KEY_FILTER_ENABLED). Settings are read once per
+// process, so every test here runs with the filter on; the rest of the suite runs with it off.
+// Tests are serialized because they compare the process-wide key check counter.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, App, http::StatusCode};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+use warp_drive::api::{delete, get, put, update_key};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::metadata::{Metadata, MetadataStorage};
+use warp_drive::metrics;
+use warp_drive::service::key_filter;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::serializer::encode_extents;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "key_filter_user";
+
+static SETUP: Once = Once::new();
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("KEY_FILTER_ENABLED", "true");
+        std::env::set_var("KEY_FILTER_CAPACITY", "10000");
+    });
+}
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket.to_string()))
+}
+
+fn put_req(bucket: &str, key: &str) -> test::TestRequest {
+    native(test::TestRequest::post(), &format!("/put/{}", key), bucket).set_payload(payload_of(key.as_bytes()))
+}
+
+fn key_checks() -> u64 {
+    metrics::counter_value("warpdrive_metadata_key_checks_total", &[])
+}
+
+async fn wait_loaded(bucket: &str) {
+    let start = Instant::now();
+    while !key_filter::is_loaded(USER, bucket) {
+        assert!(start.elapsed() < Duration::from_secs(5), "filter of {} never loaded", bucket);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[actix_web::test]
+async fn test_new_key_puts_skip_the_database_check() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let bucket = unique("ingest");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    // Keys written before the filter exists are picked up when it loads
+    for i in 0..50 {
+        MetadataService::new(USER).unwrap().write_metadata(&bucket, &format!("old-{}", i), &encode_extents(&[]).unwrap()).unwrap();
+    }
+
+    // The first check starts the load and goes to the database
+    assert_eq!(test::call_service(&app, put_req(&bucket, "first").to_request()).await.status(), StatusCode::OK);
+    wait_loaded(&bucket).await;
+
+    let before = key_checks();
+    for i in 0..500 {
+        assert_eq!(test::call_service(&app, put_req(&bucket, &format!("new-{:04}", i)).to_request()).await.status(), StatusCode::OK);
+    }
+    // Only false positives reach SQLite
+    let checks = key_checks() - before;
+    assert!(checks <= 25, "{} database checks for 500 new keys", checks);
+
+    // Known keys still go to the database and are refused
+    for key in ["first", "old-7", "new-0042"] {
+        let before = key_checks();
+        assert_eq!(test::call_service(&app, put_req(&bucket, key).to_request()).await.status(), StatusCode::CONFLICT, "{}", key);
+        assert_eq!(key_checks() - before, 1);
+    }
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/new-0499", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_filter_stays_correct_across_deletes_renames_and_outside_writes() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(App::new().service(put).service(delete).service(update_key)).await;
+    let bucket = unique("churn");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+
+    // A cold filter answers nothing: the duplicate is caught by the database check
+    assert_eq!(test::call_service(&app, put_req(&bucket, "a").to_request()).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, put_req(&bucket, "a").to_request()).await.status(), StatusCode::CONFLICT);
+    wait_loaded(&bucket).await;
+
+    // A deleted key stays "maybe present" and can be written again
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), "/delete/a", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, put_req(&bucket, "a").to_request()).await.status(), StatusCode::OK);
+
+    // A rename destination is recorded
+    let resp = test::call_service(&app, native(test::TestRequest::put(), "/update_key/a/b", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, put_req(&bucket, "b").to_request()).await.status(), StatusCode::CONFLICT);
+
+    // A key written behind the filter's back fails on the key constraint, still a 409
+    SQLiteMetadataStore::new().put_metadata(USER, &bucket, "outside", &Metadata::from_offset_size_list(vec![])).unwrap();
+    assert_eq!(test::call_service(&app, put_req(&bucket, "outside").to_request()).await.status(), StatusCode::CONFLICT);
+}
@@ -0,0 +1,124 @@
+// Key length limit on the create paths (native and S3). MAX_KEY_LENGTH is read per request,
+// so tests serialize on ENV_LOCK; S3 requests are signed with the admin access key.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::{Mutex, Once};
+use warp_drive::api::{put, get, delete, update_key};
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_create_multipart_upload_handler};
+use warp_drive::service::key_limits::DEFAULT_MAX_KEY_LENGTH;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{AMZ_DATE, authorization_with_query, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "keylen-test-access";
+const SECRET_KEY: &str = "keylen-test-secret";
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    std::env::remove_var("MAX_KEY_LENGTH");
+}
+
+/// A unique key of exactly `len` bytes
+fn key_of_len(len: usize) -> String {
+    let prefix = unique("k");
+    format!("{}{}", prefix, "x".repeat(len - prefix.len()))
+}
+
+fn native_put(key: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", "keylen_user"))
+        .set_payload(payload_of(b"data"))
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_native_put_and_rename_at_the_limit() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(App::new().service(put).service(update_key)).await;
+
+    let at_limit = key_of_len(DEFAULT_MAX_KEY_LENGTH);
+    assert_eq!(test::call_service(&app, native_put(&at_limit).to_request()).await.status(), StatusCode::OK);
+
+    let over = key_of_len(DEFAULT_MAX_KEY_LENGTH + 1);
+    let resp = test::call_service(&app, native_put(&over).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "KeyTooLongError");
+
+    let rename = |new_key: &str| test::TestRequest::put()
+        .uri(&format!("/update_key/{}/{}", at_limit, new_key))
+        .insert_header(("user", "keylen_user"))
+        .to_request();
+    let resp = test::call_service(&app, rename(&key_of_len(DEFAULT_MAX_KEY_LENGTH + 1))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, rename(&key_of_len(DEFAULT_MAX_KEY_LENGTH))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_s3_put_and_multipart_initiate_at_the_limit() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::post().to(s3_create_multipart_upload_handler))
+    ).await;
+    let bucket = unique("keylen");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+
+    for (len, status) in [(DEFAULT_MAX_KEY_LENGTH, StatusCode::OK), (DEFAULT_MAX_KEY_LENGTH + 1, StatusCode::BAD_REQUEST)] {
+        let path = format!("/s3/{}/{}", bucket, key_of_len(len));
+        let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &path, "").set_payload("x").to_request()).await;
+        assert_eq!(resp.status(), status, "PutObject with {}-byte key", len);
+        if status == StatusCode::BAD_REQUEST {
+            assert!(String::from_utf8_lossy(&test::read_body(resp).await).contains("<Code>KeyTooLongError</Code>"));
+        }
+
+        let path = format!("/s3/{}/{}", bucket, key_of_len(len));
+        let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, "uploads=").to_request()).await;
+        assert_eq!(resp.status(), status, "CreateMultipartUpload with {}-byte key", len);
+    }
+}
+
+#[actix_web::test]
+async fn test_existing_overlong_keys_stay_readable_and_deletable() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(delete)).await;
+
+    // Stored while a higher limit was configured
+    let key = key_of_len(DEFAULT_MAX_KEY_LENGTH + 100);
+    std::env::set_var("MAX_KEY_LENGTH", "2048");
+    assert_eq!(test::call_service(&app, native_put(&key).to_request()).await.status(), StatusCode::OK);
+    std::env::remove_var("MAX_KEY_LENGTH");
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", "keylen_user"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = test::TestRequest::delete()
+        .uri(&format!("/delete/{}", key))
+        .insert_header(("user", "keylen_user"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
@@ -0,0 +1,71 @@
+// Last-Modified on native reads: stable across reads, bumped by touch and append, kept by
+// rename.
+
+use actix_web::{test, App, http::StatusCode};
+use std::time::Duration;
+use warp_drive::api::{append, get, list, put, touch, update_key};
+
+mod common;
+use common::{payload, unique};
+
+const USER: &str = "mtime_user";
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket))
+}
+
+/// `Last-Modified` has one-second resolution: wait long enough for it to change
+async fn next_second() {
+    actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+}
+
+#[actix_web::test]
+async fn test_last_modified_follows_writes_and_touch() {
+    common::isolate();
+    let app = test::init_service(
+        App::new().service(put).service(get).service(append).service(touch).service(update_key).service(list)
+    ).await;
+    let bucket = unique("mtime");
+    let last_modified = |key: &str| {
+        let req = native(test::TestRequest::get(), &format!("/get/{}", key), &bucket).to_request();
+        let app = &app;
+        async move {
+            let resp = test::call_service(app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let header = resp.headers().get("Last-Modified").expect("Last-Modified on GET");
+            let value = header.to_str().unwrap().to_string();
+            assert!(value.ends_with(" GMT"), "{}", value);
+            value
+        }
+    };
+
+    let req = native(test::TestRequest::post(), "/put/doc", &bucket).set_payload(payload(&[b"v1"])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let written = last_modified("doc").await;
+    next_second().await;
+    assert_eq!(last_modified("doc").await, written, "reads must not change Last-Modified");
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/touch/doc", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let touched_header = resp.headers().get("Last-Modified").unwrap().to_str().unwrap().to_string();
+    let touched: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(last_modified("doc").await, touched_header);
+    assert_ne!(touched_header, written);
+
+    // The listing shows the stored time, not the time of the request
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/list", &bucket).to_request()).await;
+    let listing: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(listing["objects"][0]["last_modified"], touched["last_modified"]);
+
+    next_second().await;
+    let req = native(test::TestRequest::put(), "/update_key/doc/renamed", &bucket).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    assert_eq!(last_modified("renamed").await, touched_header, "rename keeps Last-Modified");
+
+    let req = native(test::TestRequest::post(), "/append/renamed", &bucket).set_payload(payload(&[b"v2"])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    assert_ne!(last_modified("renamed").await, touched_header, "append bumps Last-Modified");
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/touch/missing", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
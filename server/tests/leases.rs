@@ -0,0 +1,94 @@
+// Write leases: a key leased to one worker refuses the writes of others with 423 until the
+// lease is released or expires. Expiry is reached by moving the clock, not by sleeping.
+
+use actix_web::{test, App, http::StatusCode};
+use serde_json::Value;
+use warp_drive::api::{delete, get, lease, put, release_lease, update};
+use warp_drive::util::clock;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "lease_user";
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str, token: Option<&str>) -> test::TestRequest {
+    let req = req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket.to_string()));
+    match token {
+        Some(token) => req.insert_header(("X-Warp-Lease", token.to_string())),
+        None => req,
+    }
+}
+
+macro_rules! write {
+    ($app:expr, $uri:expr, $bucket:expr, $token:expr, $data:expr) => {{
+        let req = native(test::TestRequest::post(), $uri, $bucket, $token).set_payload(payload_of($data));
+        test::call_service($app, req.to_request()).await
+    }};
+}
+
+#[actix_web::test]
+async fn test_lease_excludes_other_writers_until_it_expires() {
+    common::isolate();
+    let app = test::init_service(
+        App::new().service(put).service(get).service(update).service(delete).service(lease).service(release_lease)
+    ).await;
+    let bucket = unique("leases");
+    assert_eq!(write!(&app, "/put/doc", &bucket, None, b"v1").status(), StatusCode::OK);
+
+    // Worker A takes the lease
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/lease/doc?ttl=30", &bucket, None).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let granted: Value = test::read_body_json(resp).await;
+    let token = granted["token"].as_str().unwrap().to_string();
+    assert_eq!(granted["ttl_secs"], 30);
+
+    // Worker B is locked out of writes but can still read
+    let resp = write!(&app, "/update/doc", &bucket, None, b"from B");
+    assert_eq!(resp.status(), StatusCode::LOCKED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Locked");
+    assert_eq!(body["expires_at"], granted["expires_at"]);
+    let resp = write!(&app, "/update/doc", &bucket, Some("wdl_not-the-token"), b"from B");
+    assert_eq!(resp.status(), StatusCode::LOCKED);
+    let delete_req = native(test::TestRequest::delete(), "/delete/doc", &bucket, None);
+    assert_eq!(test::call_service(&app, delete_req.to_request()).await.status(), StatusCode::LOCKED);
+    let take = native(test::TestRequest::post(), "/lease/doc", &bucket, None);
+    assert_eq!(test::call_service(&app, take.to_request()).await.status(), StatusCode::LOCKED);
+    let get_req = native(test::TestRequest::get(), "/get/doc", &bucket, None);
+    assert_eq!(test::call_service(&app, get_req.to_request()).await.status(), StatusCode::OK);
+
+    // A writes with its token
+    assert_eq!(write!(&app, "/update/doc", &bucket, Some(&token), b"from A").status(), StatusCode::OK);
+
+    // Once the lease has expired B can write again
+    clock::advance(chrono::Duration::seconds(31));
+    assert_eq!(write!(&app, "/update/doc", &bucket, None, b"from B").status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/doc", &bucket, None).to_request()).await;
+    let body = test::read_body(resp).await;
+    assert!(body.windows(6).any(|w| w == b"from B"));
+}
+
+#[actix_web::test]
+async fn test_released_lease_frees_the_key() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(lease).service(release_lease)).await;
+    let bucket = unique("leases");
+
+    // A key can be leased before it exists; the long ttl outlasts the clock moved by the test above
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/lease/new?ttl=600", &bucket, None).to_request()).await;
+    let granted: Value = test::read_body_json(resp).await;
+    let token = granted["token"].as_str().unwrap().to_string();
+    assert_eq!(write!(&app, "/put/new", &bucket, None, b"x").status(), StatusCode::LOCKED);
+
+    // Renewing keeps the token; ttl past the maximum is refused
+    let renew = native(test::TestRequest::post(), "/lease/new?ttl=60", &bucket, Some(&token));
+    let renewed: Value = test::read_body_json(test::call_service(&app, renew.to_request()).await).await;
+    assert_eq!(renewed["token"], token.as_str());
+    let too_long = native(test::TestRequest::post(), "/lease/new?ttl=999999", &bucket, Some(&token));
+    assert_eq!(test::call_service(&app, too_long.to_request()).await.status(), StatusCode::BAD_REQUEST);
+
+    let release = native(test::TestRequest::delete(), "/lease/new", &bucket, Some(&token));
+    let released: Value = test::read_body_json(test::call_service(&app, release.to_request()).await).await;
+    assert_eq!(released["released"], true);
+    assert_eq!(write!(&app, "/put/new", &bucket, None, b"x").status(), StatusCode::OK);
+}
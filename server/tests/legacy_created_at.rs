@@ -0,0 +1,93 @@
+// Creation times of rows written before they were stamped with milliseconds. The database is
+// created with such rows and the old trigger before first use, so this binary has a single
+// test: startup rewrites the stamps as UTC with milliseconds, so reads as of a time order
+// them with newer rows, records that so later starts skip the rewrite, and the trigger stamps
+// new rows the same way.
+
+use rusqlite::{params, Connection};
+use warp_drive::metadata::sqlite_store::{self, SQLiteMetadataStore};
+use warp_drive::service::as_of;
+
+mod common;
+
+const BUCKET: &str = "legacy-created-at";
+
+/// The objects table with the trigger that copied last_modified as written, holding a row
+/// stamped to the second and a later one stamped with milliseconds
+fn create_legacy_rows() {
+    let dir = common::isolate().join("metadata");
+    std::fs::create_dir_all(&dir).unwrap();
+    let conn = Connection::open(dir.join("metadata.sqlite")).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE objects (
+            id                 INTEGER PRIMARY KEY AUTOINCREMENT,
+            user               TEXT NOT NULL,
+            bucket             TEXT NOT NULL,
+            key                TEXT NOT NULL,
+            version_id         TEXT NOT NULL DEFAULT '',
+            is_delete_marker   INTEGER NOT NULL DEFAULT 0,
+            is_latest          INTEGER NOT NULL DEFAULT 1,
+            offset_size_list   BLOB,
+            etag               TEXT,
+            size               INTEGER NOT NULL DEFAULT 0,
+            content_type       TEXT,
+            last_modified      TEXT,
+            user_metadata      TEXT,
+            cache_control      TEXT,
+            expires            TEXT,
+            content_encoding   TEXT,
+            parts_manifest     TEXT,
+            checksum_algorithm TEXT NOT NULL DEFAULT '',
+            checksum_value     TEXT NOT NULL DEFAULT '',
+            checksum_type      TEXT NOT NULL DEFAULT '',
+            properties         TEXT,
+            expire_at          TEXT,
+            inline_data        BLOB,
+            created_at         TEXT,
+            UNIQUE(user, bucket, key, version_id)
+        );
+        CREATE TRIGGER objects_created_at AFTER INSERT ON objects
+        WHEN NEW.created_at IS NULL
+        BEGIN
+            UPDATE objects SET created_at = COALESCE(NEW.last_modified, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+            WHERE id = NEW.id;
+        END;",
+    ).unwrap();
+    for (version, last_modified, latest) in [("v1", "2024-06-01T00:00:00Z", 0), ("v2", "2024-06-01T00:00:00.500Z", 1)] {
+        conn.execute(
+            "INSERT INTO objects (user, bucket, key, version_id, is_latest, size, last_modified) VALUES ('admin', ?1, 'doc', ?2, ?3, 5, ?4)",
+            params![BUCKET, version, latest, last_modified],
+        ).unwrap();
+    }
+}
+
+#[test]
+fn test_legacy_stamps_order_with_millisecond_ones() {
+    create_legacy_rows();
+    let path = sqlite_store::initialize().unwrap();
+    let store = SQLiteMetadataStore::new();
+    let version_as_of = |time: &str| store.version_as_of("admin", BUCKET, "doc", &as_of::parse(time).unwrap()).unwrap();
+
+    // Compared as text, "...00Z" sorts after "...00.250Z" and "...00.500Z"
+    assert_eq!(version_as_of("2024-06-01T00:00:00.250Z").as_deref(), Some("v1"));
+    assert_eq!(version_as_of("2024-06-01T00:00:01Z").as_deref(), Some("v2"));
+    assert_eq!(version_as_of("2024-05-31T23:59:59Z"), None);
+    let listed = store.versions_as_of("admin", BUCKET, &as_of::parse("2024-06-01T00:00:00.250Z").unwrap(), "", 10).unwrap();
+    assert_eq!(listed, vec![("doc".to_string(), "v1".to_string())]);
+
+    // The rewrite is recorded once, with the one row it changed
+    let conn = Connection::open(path).unwrap();
+    let rows: Vec<i64> = conn.prepare("SELECT json_extract(after_value, '$.rows') FROM change_log WHERE kind = 'schema' AND subject = 'objects.created_at.utc'")
+        .unwrap().query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows, vec![1]);
+
+    // New rows are stamped in UTC with milliseconds whatever form last_modified has
+    conn.execute(
+        "INSERT INTO objects (user, bucket, key, version_id, size, last_modified) VALUES ('admin', ?1, 'other', 'v1', 5, '2024-06-01T02:00:02+02:00')",
+        params![BUCKET],
+    ).unwrap();
+    let created_at: String = conn.query_row(
+        "SELECT created_at FROM objects WHERE bucket = ?1 AND key = 'other'", params![BUCKET], |row| row.get(0),
+    ).unwrap();
+    assert_eq!(created_at, "2024-06-01T00:00:02.000Z");
+}
@@ -0,0 +1,80 @@
+// Storage from before buckets: a user's data in one `<root>/<user>.bin` stays readable after
+// the upgrade and `migrate-legacy` moves it into the bucket files.
+
+use actix_web::{test, App, http::StatusCode};
+use warp_drive::api::{get, put};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::service::legacy_migration;
+use warp_drive::startup;
+use warp_drive::storage::local_store::LocalXFSBinaryStore;
+use warp_drive::storage::Storage;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "legacy_user";
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket.to_string()))
+}
+
+macro_rules! assert_reads {
+    ($app:expr, $bucket:expr, $keys:expr) => {{
+        for key in $keys {
+            let req = native(test::TestRequest::get(), &format!("/get/{}", key), $bucket);
+            let resp = test::call_service($app, req.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", key);
+            let body = test::read_body(resp).await;
+            let expected = format!("contents of {}", key);
+            assert!(body.windows(expected.len()).any(|w| w == expected.as_bytes()), "{}", key);
+        }
+    }};
+}
+
+#[actix_web::test]
+async fn test_legacy_file_is_read_then_migrated_and_retired() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let bucket = unique("legacy");
+    let keys = ["a", "b", "c"];
+    for key in keys {
+        let req = native(test::TestRequest::post(), &format!("/put/{}", key), &bucket)
+            .set_payload(payload_of(format!("contents of {}", key).as_bytes()));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+
+    // Fixture: the bucket's bytes only exist in the user's pre-bucket file, at the same offsets
+    let store = LocalXFSBinaryStore::new();
+    let (bucket_file, _) = store.backing_file(USER, &bucket, 0).unwrap();
+    let legacy_file = store.legacy_file_path(USER);
+    std::fs::copy(&bucket_file, &legacy_file).unwrap();
+    assert!(store.remove_bucket_file(USER, &bucket).unwrap());
+
+    // Startup points the rows at the legacy file and reads fall back to it
+    startup::initialize().unwrap();
+    let db = SQLiteMetadataStore::new();
+    assert_eq!(db.legacy_file(USER).unwrap().unwrap().rows, 3);
+    assert_eq!(db.legacy_rows(USER, &bucket).unwrap().len(), 3);
+    assert_reads!(&app, &bucket, keys);
+    // A second start leaves the rows alone
+    startup::initialize().unwrap();
+    assert_eq!(db.legacy_rows(USER, &bucket).unwrap().len(), 3);
+
+    let report = legacy_migration::migrate_user(USER).await.unwrap();
+    assert_eq!((report.buckets, report.rows_migrated, report.rows_skipped), (1, 3, 0));
+    assert_eq!(report.chunks_copied, 3);
+    assert!(db.legacy_rows(USER, &bucket).unwrap().is_empty());
+    assert!(!legacy_file.exists());
+    assert!(!store.segments(USER, &bucket).unwrap().is_empty());
+    assert_reads!(&app, &bucket, keys);
+
+    // Nothing reads the retired file any more
+    let retired = report.retired_file.unwrap();
+    std::fs::remove_file(&retired).unwrap();
+    assert!(db.legacy_file(USER).unwrap().unwrap().retired_at.is_some());
+    assert_reads!(&app, &bucket, keys);
+
+    // Migrating again is a no-op
+    let again = legacy_migration::migrate_user(USER).await.unwrap();
+    assert_eq!(again.rows_migrated, 0);
+}
@@ -0,0 +1,101 @@
+// ListObjects V1 (no list-type) next to V2 on GET /s3/{bucket}. Requests are signed with the
+// admin access key, whose user is "admin".
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_list_objects_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "list-test-access";
+const SECRET_KEY: &str = "list-test-secret";
+const KEYS: [&str; 5] = ["a.txt", "docs/1", "docs/2", "logs/x", "z.txt"];
+
+static SETUP: Once = Once::new();
+
+fn setup() -> String {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    let bucket = unique("listing");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    bucket
+}
+
+/// `query` is the canonical query string: sorted, values percent-encoded
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+fn values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    xml.split(&open).skip(1).map(|rest| rest[..rest.find(&close).unwrap()].to_string()).collect()
+}
+
+macro_rules! list {
+    ($app:expr, $bucket:expr, $query:expr) => {{
+        let req = signed(test::TestRequest::get(), "GET", &format!("/s3/{}", $bucket), $query);
+        let resp = test::call_service($app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{}", $query);
+        String::from_utf8(test::read_body(resp).await.to_vec()).unwrap()
+    }};
+}
+
+#[actix_web::test]
+async fn test_list_objects_v1_and_v2() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}", web::get().to(s3_list_objects_handler))
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+    ).await;
+    for key in KEYS {
+        let req = signed(test::TestRequest::put(), "PUT", &format!("/s3/{}/{}", bucket, key), "").set_payload(key);
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+
+    // V1 pages with marker; without a delimiter there is no NextMarker
+    let xml = list!(&app, &bucket, "max-keys=2");
+    assert_eq!(values(&xml, "Key"), ["a.txt", "docs/1"], "{}", xml);
+    assert_eq!(values(&xml, "IsTruncated"), ["true"]);
+    assert_eq!(values(&xml, "Marker"), [""]);
+    assert!(!xml.contains("<NextMarker>") && !xml.contains("<KeyCount>"), "{}", xml);
+    let xml = list!(&app, &bucket, "marker=docs%2F1&max-keys=2");
+    assert_eq!(values(&xml, "Key"), ["docs/2", "logs/x"], "{}", xml);
+    assert_eq!(values(&xml, "Marker"), ["docs/1"]);
+    let xml = list!(&app, &bucket, "marker=logs%2Fx&max-keys=2");
+    assert_eq!(values(&xml, "Key"), ["z.txt"], "{}", xml);
+    assert_eq!(values(&xml, "IsTruncated"), ["false"]);
+
+    // With a delimiter, keys roll up into CommonPrefixes and a truncated page has NextMarker
+    let xml = list!(&app, &bucket, "delimiter=%2F&max-keys=2");
+    assert_eq!(values(&xml, "Key"), ["a.txt"], "{}", xml);
+    assert_eq!(values(&xml, "Prefix"), ["", "docs/"], "{}", xml);
+    assert_eq!(values(&xml, "NextMarker"), ["docs/"]);
+    let xml = list!(&app, &bucket, "delimiter=%2F&marker=docs%2F&max-keys=2");
+    assert_eq!(values(&xml, "Key"), ["z.txt"], "{}", xml);
+    assert_eq!(values(&xml, "Prefix"), ["", "logs/"], "{}", xml);
+    assert_eq!(values(&xml, "IsTruncated"), ["false"]);
+    assert!(!xml.contains("<NextMarker>"), "{}", xml);
+    let xml = list!(&app, &bucket, "delimiter=%2F&prefix=docs%2F");
+    assert_eq!(values(&xml, "Key"), ["docs/1", "docs/2"], "{}", xml);
+
+    // V2 is unchanged: KeyCount and continuation tokens, no Marker
+    let xml = list!(&app, &bucket, "list-type=2&max-keys=2");
+    assert_eq!(values(&xml, "Key"), ["a.txt", "docs/1"], "{}", xml);
+    assert_eq!(values(&xml, "KeyCount"), ["2"]);
+    assert_eq!(values(&xml, "NextContinuationToken"), ["docs/1"]);
+    assert!(!xml.contains("<Marker>"), "{}", xml);
+    let xml = list!(&app, &bucket, "continuation-token=docs%2F1&list-type=2&max-keys=10");
+    assert_eq!(values(&xml, "Key"), ["docs/2", "logs/x", "z.txt"], "{}", xml);
+    assert_eq!(values(&xml, "KeyCount"), ["3"]);
+    assert_eq!(values(&xml, "IsTruncated"), ["false"]);
+}
@@ -0,0 +1,113 @@
+// Runtime log level and per-user tracing. The logger is process-wide, so this binary installs
+// it once, with no appenders: captured trace lines are what the tests look at.
+
+use actix_web::{test, App, http::StatusCode};
+use log::LevelFilter;
+use std::sync::Once;
+use std::time::Duration;
+use warp_drive::admin::{get_log_level, set_log_level, trace_user, untrace_user, trace_user_logs};
+use warp_drive::api::{put, get};
+use warp_drive::logging::{self, request_scope};
+
+mod common;
+use common::unique;
+
+const ADMIN_SECRET: &str = "trace-test-admin";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", ADMIN_SECRET);
+        let config = log4rs::Config::builder()
+            .build(log4rs::config::Root::builder().build(LevelFilter::Info))
+            .unwrap();
+        logging::install(config).unwrap();
+    });
+}
+
+fn admin(req: test::TestRequest) -> test::TestRequest {
+    req.insert_header(("Authorization", format!("Bearer {}", ADMIN_SECRET)))
+}
+
+#[actix_web::test]
+async fn test_log_level_changes_at_runtime() {
+    setup();
+    let app = test::init_service(App::new().service(get_log_level).service(set_log_level)).await;
+
+    let resp = test::call_service(&app, admin(test::TestRequest::post().uri("/admin/log_level?level=debug")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(logging::level(), LevelFilter::Debug);
+    assert!(log::log_enabled!(log::Level::Debug));
+    let body: serde_json::Value = test::read_body_json(test::call_service(&app,
+        admin(test::TestRequest::get().uri("/admin/log_level")).to_request()).await).await;
+    assert_eq!(body["level"], "debug");
+
+    let resp = test::call_service(&app, admin(test::TestRequest::post().uri("/admin/log_level?level=loud")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, test::TestRequest::post().uri("/admin/log_level?level=trace").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = test::call_service(&app, admin(test::TestRequest::post().uri("/admin/log_level?level=info")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(logging::level(), LevelFilter::Info);
+}
+
+#[actix_web::test]
+async fn test_traced_user_requests_are_captured_until_expiry() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .wrap(actix_web::middleware::from_fn(request_scope))
+            .service(put)
+            .service(get)
+            .service(trace_user)
+            .service(untrace_user)
+            .service(trace_user_logs)
+    ).await;
+    let (user, other) = (unique("traced"), unique("untraced"));
+    let request = |user: &str, key: &str, request_id: &str| test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", user.to_string()))
+        .insert_header(("bucket", "trace-bucket"))
+        .insert_header(("x-request-id", request_id.to_string()))
+        .set_payload("not a flatbuffer")
+        .to_request();
+    let logs = |user: &str| admin(test::TestRequest::get().uri(&format!("/admin/trace_user/{}/logs", user))).to_request();
+
+    let resp = test::call_service(&app, logs(&user)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, admin(test::TestRequest::post()
+        .uri(&format!("/admin/trace_user/{}?ttl=0", user))).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, admin(test::TestRequest::post()
+        .uri(&format!("/admin/trace_user/{}?ttl=1", user))).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Requests carry their id back, and only the traced user's are captured
+    let resp = test::call_service(&app, request(&user, "first", "trace-req-1")).await;
+    assert_eq!(resp.headers().get("x-request-id").unwrap(), "trace-req-1");
+    test::call_service(&app, request(&other, "first", "other-req-1")).await;
+
+    let snapshot: serde_json::Value = test::read_body_json(test::call_service(&app, logs(&user)).await).await;
+    assert_eq!(snapshot["active"], true);
+    let lines = snapshot["lines"].as_array().unwrap();
+    assert!(!lines.is_empty());
+    assert!(lines.iter().all(|l| l["request_id"] == "trace-req-1"), "{:?}", lines);
+    assert!(lines.iter().any(|l| l["level"] == "DEBUG"), "debug lines are captured: {:?}", lines);
+    let captured = lines.len();
+
+    // Once the trace lapses the buffer stops growing but stays readable
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let resp = test::call_service(&app, request(&user, "second", "trace-req-2")).await;
+    assert_eq!(resp.headers().get("x-request-id").unwrap(), "trace-req-2");
+    let snapshot: serde_json::Value = test::read_body_json(test::call_service(&app, logs(&user)).await).await;
+    assert_eq!(snapshot["active"], false);
+    assert_eq!(snapshot["lines"].as_array().unwrap().len(), captured);
+
+    let resp = test::call_service(&app, admin(test::TestRequest::delete().uri(&format!("/admin/trace_user/{}", user))).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = test::call_service(&app, logs(&user)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
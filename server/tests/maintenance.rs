@@ -0,0 +1,122 @@
+// Read-only maintenance mode. The mode is process-wide, so tests serialize on STATE_LOCK;
+// admin calls use the admin secret and S3 requests the admin access key.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{middleware, test, web, App, http::StatusCode};
+use std::sync::{Mutex, Once};
+use warp_drive::admin::{get_maintenance, set_maintenance};
+use warp_drive::api::{get, put};
+use warp_drive::s3::handlers::{s3_get_object_handler, s3_put_object_handler};
+use warp_drive::service::maintenance::{self, maintenance_gate, MaintenanceMode};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{AMZ_DATE, authorization, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "maintenance-test-access";
+const SECRET_KEY: &str = "maintenance-test-secret";
+
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+}
+
+fn admin(req: test::TestRequest, uri: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("Authorization", format!("Bearer {}", SECRET_KEY)))
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str) -> test::TestRequest {
+    req.uri(path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_read_only_blocks_writes_and_persists() {
+    let _state = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(
+        App::new()
+            .wrap(middleware::from_fn(maintenance_gate))
+            .service(get_maintenance)
+            .service(set_maintenance)
+            .service(put)
+            .service(get)
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let native = |req: test::TestRequest, uri: &str| req.uri(uri).insert_header(("user", "maintenance_user"));
+    let bucket = unique("maintenance");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let s3_path = format!("/s3/{}/object", bucket);
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/existing").set_payload(payload_of(b"v1")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &s3_path).set_payload("v1").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, admin(test::TestRequest::post(), "/admin/maintenance?mode=read_only")
+        .insert_header(("X-Request-Id", "migration-42")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Native and S3 writes are refused, reads keep working
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/new").set_payload(payload_of(b"v2")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(resp.headers().contains_key("retry-after"));
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "ReadOnlyMaintenance");
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &s3_path).set_payload("v2").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Code>ServiceUnavailable</Code>"), "{}", xml);
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/existing").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &s3_path).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, admin(test::TestRequest::get(), "/admin/maintenance").to_request()).await;
+    let report: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(report["mode"], "read_only");
+    assert_eq!(report["changed_by"], "migration-42");
+    assert!(report["changed_at"].is_string());
+
+    // A restart rebuilds the same state from the database
+    let stored = maintenance::load().unwrap();
+    assert_eq!(stored, maintenance::current());
+    assert_eq!(stored.mode, MaintenanceMode::ReadOnly);
+
+    let resp = test::call_service(&app, admin(test::TestRequest::post(), "/admin/maintenance?mode=normal").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/new").set_payload(payload_of(b"v2")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(maintenance::load().unwrap().mode, MaintenanceMode::Normal);
+}
+
+#[actix_web::test]
+async fn test_bounded_read_only_lapses() {
+    let _state = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(App::new().service(set_maintenance)).await;
+
+    let resp = test::call_service(&app, admin(test::TestRequest::post(), "/admin/maintenance?mode=read_write").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, test::TestRequest::post().uri("/admin/maintenance?mode=read_only").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = test::call_service(&app, admin(test::TestRequest::post(), "/admin/maintenance?mode=read_only&duration_secs=3600").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(maintenance::is_read_only());
+    let state = maintenance::current();
+    let until = state.until.unwrap();
+    assert_eq!(state.effective_mode(until - chrono::Duration::seconds(1)), MaintenanceMode::ReadOnly);
+    assert_eq!(state.effective_mode(until), MaintenanceMode::Normal);
+
+    maintenance::set(MaintenanceMode::Normal, None, "test").unwrap();
+    assert!(!maintenance::is_read_only());
+}
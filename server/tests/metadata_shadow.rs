@@ -0,0 +1,91 @@
+// Metadata shadowing: a ShadowingStore answers from its primary and replays every call on the
+// shadow, and where the shadow answers differently (here a mock store changed behind its back)
+// the difference is recorded and listed by GET /admin/shadow/report.
+
+use actix_web::{test, App, http::StatusCode};
+use std::sync::Arc;
+use warp_drive::admin::shadow_report;
+use warp_drive::metadata::{DataChunk, Metadata, MetadataStorage};
+use warp_drive::metadata::mock_store::MockMetadataStore;
+use warp_drive::metadata::shadow_store::ShadowingStore;
+use warp_drive::metadata::sqlite_store::{SQLiteMetadataStore, ShadowDivergence};
+use warp_drive::metrics;
+
+mod common;
+use common::unique;
+
+const SECRET: &str = "shadow-test-secret";
+
+fn object(extents: Vec<(u64, u64)>) -> Metadata {
+    let mut metadata = Metadata::from_offset_size_list(extents);
+    metadata.size = metadata.chunks.iter().map(|chunk| chunk.extent().1).sum();
+    metadata
+}
+
+fn divergences(user: &str) -> Vec<ShadowDivergence> {
+    let mut found: Vec<_> = SQLiteMetadataStore::new().recent_shadow_divergences(1000).unwrap()
+        .into_iter()
+        .filter(|divergence| divergence.user_id == user)
+        .collect();
+    found.sort_by_key(|divergence| divergence.id);
+    found
+}
+
+#[actix_web::test]
+async fn test_shadow_divergences_are_recorded_without_changing_responses() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+    let user = unique("shadow_user");
+    let bucket = "shadowed";
+    let primary = Arc::new(MockMetadataStore::new());
+    let shadow = Arc::new(MockMetadataStore::new());
+    let store = ShadowingStore::new(primary.clone(), shadow.clone(), 100);
+
+    // Writes reach both backends, and agreeing reads record nothing
+    store.create_bucket(&user, bucket).unwrap();
+    for (key, extents) in [("a", vec![(0, 10)]), ("b", vec![(10, 5), (15, 5)]), ("c", vec![(20, 7)])] {
+        store.put_metadata(&user, bucket, key, &object(extents)).unwrap();
+    }
+    assert_eq!(store.get_metadata(&user, bucket, "b").unwrap().chunks.len(), 2);
+    assert!(store.bucket_exists(&user, bucket).unwrap());
+    store.flush();
+    assert_eq!(shadow.get_metadata(&user, bucket, "b").unwrap().chunks, primary.get_metadata(&user, bucket, "b").unwrap().chunks);
+    assert!(divergences(&user).is_empty(), "{:?}", divergences(&user));
+
+    // Faults in the shadow: a different chunk list, a lost object and a key the put will find
+    shadow.update_metadata(&user, bucket, "a", &object(vec![(100, 10)])).unwrap();
+    shadow.delete_metadata(&user, bucket, "b").unwrap();
+    shadow.put_metadata(&user, bucket, "d", &object(vec![(200, 1)])).unwrap();
+
+    // Responses still come from the primary
+    assert_eq!(store.get_metadata(&user, bucket, "a").unwrap().chunks, vec![DataChunk::from_extent((0, 10))]);
+    assert!(store.object_exists(&user, bucket, "b").unwrap());
+    let mut keys = store.list_objects(&user, bucket).unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["a", "b", "c"]);
+    store.put_metadata(&user, bucket, "d", &object(vec![(30, 3)])).unwrap();
+    store.flush();
+
+    let found = divergences(&user);
+    let ops: Vec<&str> = found.iter().map(|divergence| divergence.op.as_str()).collect();
+    assert_eq!(ops, vec!["get_metadata", "object_exists", "list_objects", "put_metadata"], "{:?}", found);
+    assert_eq!((found[1].primary_result.as_str(), found[1].shadow_result.as_str()), ("Ok(true)", "Ok(false)"));
+    assert_eq!((found[3].primary_result.as_str(), found[3].shadow_result.as_str()), ("Ok(())", "Err(409)"));
+    assert_eq!(found[0].key.as_deref(), Some("a"));
+    assert!(metrics::counter_value("warpdrive_shadow_divergence_total", &[("op", "put_metadata")]) >= 1);
+
+    let app = test::init_service(App::new().service(shadow_report)).await;
+    let req = test::TestRequest::get()
+        .uri("/admin/shadow/report?limit=2")
+        .insert_header(("Authorization", format!("Bearer {}", SECRET)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: serde_json::Value = test::read_body_json(resp).await;
+    assert!(report["total"].as_u64().unwrap() >= 4, "{}", report);
+    assert!(report["by_op"]["get_metadata"].as_u64().unwrap() >= 1, "{}", report);
+    assert_eq!(report["recent"].as_array().unwrap().len(), 2, "{}", report);
+
+    let req = test::TestRequest::get().uri("/admin/shadow/report").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+}
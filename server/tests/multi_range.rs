@@ -0,0 +1,134 @@
+// Multi-range GETs: several disjoint ranges come back as a multipart/byteranges body whose
+// parts carry the requested slices, on S3 GetObject and native raw GET alike; overlapping
+// ranges are merged and a header naming thousands of ranges gets the whole object. S3
+// requests are signed with the admin access key, whose objects belong to user "admin".
+
+use actix_web::{test, web, App, http::StatusCode};
+use warp_drive::api::get;
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_get_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "multi-range-access";
+const SECRET_KEY: &str = "multi-range-secret";
+const SIZE: usize = 10_000;
+
+/// The `(Content-Range, data)` of each part of a multipart/byteranges `body`
+fn parse_parts(content_type: &str, body: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").expect(content_type);
+    let delimiter = format!("--{}", boundary);
+    let closing = format!("{}--\r\n", delimiter);
+    assert!(body.ends_with(closing.as_bytes()), "body does not end with the closing delimiter");
+    let mut rest = &body[..body.len() - closing.len()];
+    let mut parts = Vec::new();
+    while !rest.is_empty() {
+        rest = rest.strip_prefix(format!("{}\r\n", delimiter).as_bytes()).expect("part delimiter");
+        let end_of_headers = rest.windows(4).position(|w| w == b"\r\n\r\n").expect("part headers");
+        let headers = std::str::from_utf8(&rest[..end_of_headers]).unwrap();
+        let range = headers.lines()
+            .find_map(|line| line.strip_prefix("Content-Range: bytes "))
+            .expect("part Content-Range").to_string();
+        assert!(headers.contains("Content-Type: application/octet-stream"), "{}", headers);
+        let (span, total) = range.split_once('/').unwrap();
+        assert_eq!(total, SIZE.to_string());
+        let (first, last) = span.split_once('-').unwrap();
+        let len = last.parse::<usize>().unwrap() - first.parse::<usize>().unwrap() + 1;
+        let data_start = end_of_headers + 4;
+        parts.push((span.to_string(), rest[data_start..data_start + len].to_vec()));
+        rest = rest[data_start + len..].strip_prefix(b"\r\n").expect("CRLF after part data");
+    }
+    parts
+}
+
+#[actix_web::test]
+async fn test_multi_range_get() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    let app = test::init_service(
+        App::new()
+            .service(get)
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let bucket = unique("multi-range");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let signed = |req: test::TestRequest, method: &str| {
+        let path = format!("/s3/{}/media", bucket);
+        req.uri(&path)
+            .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, &path, UNSIGNED_PAYLOAD)))
+            .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+            .insert_header(("x-amz-date", AMZ_DATE))
+    };
+    let native = || test::TestRequest::get().uri("/get/media")
+        .insert_header(("user", "admin")).insert_header(("bucket", bucket.clone()));
+
+    let data: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT")
+        .insert_header(("Content-Type", "application/octet-stream"))
+        .set_payload(data.clone()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    for route in ["s3", "native"] {
+        let request = |range: &str| {
+            let req = if route == "s3" { signed(test::TestRequest::get(), "GET") } else { native() };
+            req.insert_header(("Range", range.to_string())).to_request()
+        };
+
+        // Two disjoint ranges: one part each, with the requested bytes
+        let resp = test::call_service(&app, request("bytes=0-99,5000-5999")).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT, "{}", route);
+        assert!(resp.headers().get("content-range").is_none());
+        let content_type = resp.headers().get("content-type").unwrap().to_str().unwrap().to_string();
+        let content_length = resp.headers().get("content-length").map(|v| v.to_str().unwrap().to_string());
+        let body = test::read_body(resp).await;
+        if let Some(content_length) = content_length {
+            assert_eq!(content_length, body.len().to_string(), "{}", route);
+        }
+        assert_eq!(parse_parts(&content_type, &body), vec![
+            ("0-99".to_string(), data[0..100].to_vec()),
+            ("5000-5999".to_string(), data[5000..6000].to_vec()),
+        ], "{}", route);
+
+        // Overlapping ranges are merged into one part, out-of-order ones sorted
+        let resp = test::call_service(&app, request("bytes=9000-9099,50-149,0-99")).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT, "{}", route);
+        let content_type = resp.headers().get("content-type").unwrap().to_str().unwrap().to_string();
+        assert_eq!(parse_parts(&content_type, &test::read_body(resp).await), vec![
+            ("0-149".to_string(), data[0..150].to_vec()),
+            ("9000-9099".to_string(), data[9000..9100].to_vec()),
+        ], "{}", route);
+
+        // Ranges that merge into one span are a plain single-range response
+        let resp = test::call_service(&app, request("bytes=10-19,20-29")).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT, "{}", route);
+        assert_eq!(resp.headers().get("content-range").unwrap(), "bytes 10-29/10000");
+        assert_eq!(test::read_body(resp).await, data[10..30]);
+
+        // 10,000 one-byte ranges are not served one part at a time: the whole object is
+        let many: Vec<String> = (0..10_000).map(|i| format!("{}-{}", i, i)).collect();
+        let resp = test::call_service(&app, request(&format!("bytes={}", many.join(",")))).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{}", route);
+        assert_eq!(test::read_body(resp).await, data, "{}", route);
+
+        // So are several ranges covering most of the object
+        let resp = test::call_service(&app, request("bytes=0-4999,5100-9999")).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{}", route);
+        assert_eq!(test::read_body(resp).await, data, "{}", route);
+
+        let resp = test::call_service(&app, request("bytes=20000-20099,30000-")).await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE, "{}", route);
+    }
+
+    // Native raw GET answers a single range too, and 416 names the size
+    let resp = test::call_service(&app, native().insert_header(("Range", "bytes=-10")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes 9990-9999/10000");
+    assert_eq!(resp.headers().get("accept-ranges").unwrap(), "bytes");
+    assert_eq!(test::read_body(resp).await, data[9990..]);
+    let resp = test::call_service(&app, native().insert_header(("Range", "bytes=10000-")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */10000");
+}
@@ -0,0 +1,88 @@
+// Multipart upload limits: a user may only have MULTIPART_MAX_UPLOADS_PER_USER uploads in
+// progress, aborting or completing one frees its place, and the parts of one upload may not add
+// up to more than MULTIPART_MAX_UPLOAD_BYTES. Requests are signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use warp_drive::s3::handlers::{s3_abort_multipart_upload_handler, s3_multipart_router, s3_upload_part_handler};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::stats;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "mpu-limit-access";
+const SECRET_KEY: &str = "mpu-limit-secret";
+
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+fn upload_id(xml: &str) -> String {
+    let start = xml.find("<UploadId>").unwrap() + "<UploadId>".len();
+    xml[start..start + xml[start..].find('<').unwrap()].to_string()
+}
+
+fn in_progress(bucket: &str) -> u64 {
+    let stats = stats::collect().unwrap();
+    stats.per_bucket.iter().find(|b| b.bucket == bucket).map(|b| b.uploads_in_progress).unwrap()
+}
+
+// One test: the limits are read from the environment and count every upload of the user
+#[actix_web::test]
+async fn test_upload_count_and_size_limits() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    std::env::set_var("MULTIPART_MAX_UPLOADS_PER_USER", "3");
+    std::env::set_var("MULTIPART_MAX_UPLOAD_BYTES", "10");
+    let bucket = unique("mpu-limits");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key:.*}", web::post().to(s3_multipart_router))
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_upload_part_handler))
+            .route("/s3/{bucket}/{key:.*}", web::delete().to(s3_abort_multipart_upload_handler))
+    ).await;
+    let path = format!("/s3/{}/object", bucket);
+
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, "uploads=").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        ids.push(upload_id(&String::from_utf8_lossy(&test::read_body(resp).await)));
+    }
+    assert_eq!(in_progress(&bucket), 3);
+
+    let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, "uploads=").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Code>ServiceUnavailable</Code>"), "{}", xml);
+
+    // Aborting one makes room for another
+    let query = format!("uploadId={}", ids.remove(0));
+    let resp = test::call_service(&app, signed(test::TestRequest::delete(), "DELETE", &path, &query).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(in_progress(&bucket), 2);
+    let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, "uploads=").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Parts may add up to 10 bytes; uploading a part again replaces its bytes
+    let part = |number: u32, data: &'static str| {
+        let query = format!("partNumber={}&uploadId={}", number, ids[0]);
+        signed(test::TestRequest::put(), "PUT", &path, &query).set_payload(data).to_request()
+    };
+    assert_eq!(test::call_service(&app, part(1, "123456")).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, part(2, "1234")).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, part(2, "12")).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, part(3, "123")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Code>EntityTooLarge</Code>"), "{}", xml);
+    assert_eq!(test::call_service(&app, part(3, "12")).await.status(), StatusCode::OK);
+    let parts = MetadataService::new("admin").unwrap().list_multipart_parts(&ids[0]).unwrap();
+    assert_eq!(parts.iter().map(|p| p.size).sum::<u64>(), 10);
+}
@@ -0,0 +1,101 @@
+// Multipart upload IDs: random and unique, validated on later calls and bound to the object
+// they were initiated for. Requests are signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::collections::HashSet;
+use std::sync::Once;
+use warp_drive::s3::handlers::{s3_multipart_router, s3_upload_part_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "mpu-test-access";
+const SECRET_KEY: &str = "mpu-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() -> String {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    let bucket = unique("mpu");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    bucket
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+fn upload_id(xml: &str) -> String {
+    let start = xml.find("<UploadId>").unwrap() + "<UploadId>".len();
+    xml[start..start + xml[start..].find('<').unwrap()].to_string()
+}
+
+const COMPLETE_ONE_PART: &str = "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>\"x\"</ETag></Part></CompleteMultipartUpload>";
+
+#[actix_web::test]
+async fn test_simultaneous_initiations_get_distinct_ids() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new().route("/s3/{bucket}/{key:.*}", web::post().to(s3_multipart_router))
+    ).await;
+    let path = format!("/s3/{}/object", bucket);
+
+    let responses = futures::future::join_all((0..16).map(|_| {
+        test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, "uploads=").to_request())
+    })).await;
+    let mut ids = HashSet::new();
+    for resp in responses {
+        assert_eq!(resp.status(), StatusCode::OK);
+        let id = upload_id(&String::from_utf8_lossy(&test::read_body(resp).await));
+        assert!(id.starts_with("mpu-") && id.len() == 36, "{}", id);
+        ids.insert(id);
+    }
+    assert_eq!(ids.len(), 16);
+}
+
+#[actix_web::test]
+async fn test_unknown_foreign_and_empty_uploads_are_rejected() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key:.*}", web::post().to(s3_multipart_router))
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_upload_part_handler))
+    ).await;
+    let path = format!("/s3/{}/object", bucket);
+
+    // Unknown and malformed IDs
+    for id in ["mpu-0123456789abcdef0123456789abcdef", "mpu-1700000000000", "mpu-0123456789ABCDEF0123456789ABCDEF"] {
+        let query = format!("uploadId={}", id);
+        let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, &query)
+            .set_payload(COMPLETE_ONE_PART).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", id);
+        let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+        assert!(xml.contains("<Code>NoSuchUpload</Code>"), "{}", xml);
+    }
+
+    let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, "uploads=").to_request()).await;
+    let id = upload_id(&String::from_utf8_lossy(&test::read_body(resp).await));
+
+    // The ID only works for the key it was initiated for
+    let other = format!("/s3/{}/other", bucket);
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &other, &format!("partNumber=1&uploadId={}", id))
+        .set_payload("data").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Completing with no recorded parts fails and writes nothing
+    let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, &format!("uploadId={}", id))
+        .set_payload(COMPLETE_ONE_PART).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Code>InvalidPart</Code>"), "{}", xml);
+    assert!(!MetadataService::new("admin").unwrap().check_key(&bucket, "object").unwrap());
+}
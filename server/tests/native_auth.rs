@@ -0,0 +1,127 @@
+// NATIVE_AUTH is read from the environment on every request, so these tests live in their
+// own binary and serialize on ENV_LOCK while toggling it.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, App, http::StatusCode};
+use std::sync::{Mutex, Once};
+use warp_drive::admin::{create_api_key, revoke_api_key};
+use warp_drive::api::{put, get};
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{payload_of, unique};
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+static SETUP: Once = Once::new();
+
+const ADMIN_SECRET: &str = "native-auth-test-secret";
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", ADMIN_SECRET);
+    });
+}
+
+fn put_request(bucket: &str, key: &str, data: &[u8]) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("bucket", bucket))
+        .set_payload(payload_of(data))
+}
+
+fn get_request(bucket: &str, key: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("bucket", bucket))
+}
+
+fn admin(req: test::TestRequest) -> test::TestRequest {
+    req.insert_header(("Authorization", format!("Bearer {}", ADMIN_SECRET)))
+}
+
+#[actix_web::test]
+async fn test_key_binds_its_user() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    std::env::set_var("NATIVE_AUTH", "required");
+    let app = test::init_service(App::new().service(put).service(get).service(create_api_key)).await;
+    let (user, bucket) = (unique("key_user"), unique("keyed"));
+
+    let req = admin(test::TestRequest::post().uri(&format!("/admin/users/{}/keys", user))).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let issued: serde_json::Value = test::read_body_json(resp).await;
+    let key = issued["key"].as_str().unwrap().to_string();
+    assert!(key.starts_with("wd_"));
+
+    // No User header needed: the key names the user
+    let req = put_request(&bucket, "k", b"keyed data")
+        .insert_header(("Authorization", format!("Bearer {}", key)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    // Readable as the key's user once auth is back to open
+    std::env::set_var("NATIVE_AUTH", "open");
+    let req = get_request(&bucket, "k").insert_header(("user", user.as_str())).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    let list = root_as_file_data_list(&body).unwrap();
+    assert_eq!(list.files().unwrap().get(0).data().unwrap().bytes(), b"keyed data");
+
+    // A User header naming someone else is rejected
+    std::env::set_var("NATIVE_AUTH", "required");
+    let req = get_request(&bucket, "k")
+        .insert_header(("user", "someone_else"))
+        .insert_header(("Authorization", format!("Bearer {}", key)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+    std::env::remove_var("NATIVE_AUTH");
+}
+
+#[actix_web::test]
+async fn test_revoked_key_is_rejected() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    std::env::set_var("NATIVE_AUTH", "required");
+    let app = test::init_service(
+        App::new().service(put).service(create_api_key).service(revoke_api_key)
+    ).await;
+    let (user, bucket) = (unique("revoked_user"), unique("revoked"));
+
+    let req = admin(test::TestRequest::post().uri(&format!("/admin/users/{}/keys", user))).to_request();
+    let issued: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    let (key, key_id) = (issued["key"].as_str().unwrap(), issued["key_id"].as_str().unwrap());
+
+    let req = admin(test::TestRequest::delete().uri(&format!("/admin/users/{}/keys/{}", user, key_id))).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = admin(test::TestRequest::delete().uri(&format!("/admin/users/{}/keys/{}", user, key_id))).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+
+    let req = put_request(&bucket, "k", b"x")
+        .insert_header(("Authorization", format!("Bearer {}", key)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Unauthorized");
+    std::env::remove_var("NATIVE_AUTH");
+}
+
+#[actix_web::test]
+async fn test_missing_key_depends_on_mode() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(App::new().service(put)).await;
+    let bucket = unique("open");
+
+    std::env::set_var("NATIVE_AUTH", "required");
+    let req = put_request(&bucket, "k", b"x").insert_header(("user", "open_user")).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+    std::env::set_var("NATIVE_AUTH", "open");
+    let req = put_request(&bucket, "k", b"x").insert_header(("user", "open_user")).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    std::env::remove_var("NATIVE_AUTH");
+}
@@ -0,0 +1,71 @@
+// X-Warp-Meta-* headers on the native API: PUT stores them with the object, GET returns them
+// as headers and the listing as JSON, and update replaces or keeps them per
+// X-Warp-Meta-Directive.
+
+use actix_web::{test, App, http::StatusCode};
+use warp_drive::api::{put, get, update, list};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "native_meta_user";
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket))
+}
+
+#[actix_web::test]
+async fn test_meta_headers_round_trip_and_follow_directive() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(update).service(list)).await;
+    let bucket = unique("native-meta");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    let key = "report";
+    // The origin and owner metadata GET returns
+    macro_rules! get_headers {
+        ($app:expr) => {{
+            let resp = test::call_service($app, native(test::TestRequest::get(), "/get/report", &bucket).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let header = |name: &str| resp.headers().get(name).map(|v| v.to_str().unwrap().to_string());
+            (header("x-warp-meta-origin"), header("x-warp-meta-owner"))
+        }};
+    }
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", key), &bucket)
+        .insert_header(("X-Warp-Meta-Origin", "camera-7"))
+        .insert_header(("X-Warp-Meta-Owner", "team-a"))
+        .set_payload(payload_of(b"v1")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(get_headers!(&app), (Some("camera-7".to_string()), Some("team-a".to_string())));
+
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/list", &bucket).to_request()).await;
+    let listing: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(listing["objects"][0]["key"], key);
+    assert_eq!(listing["objects"][0]["metadata"], serde_json::json!({ "origin": "camera-7", "owner": "team-a" }));
+
+    // COPY keeps the stored metadata whatever the request carries
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/update/{}", key), &bucket)
+        .insert_header(("X-Warp-Meta-Directive", "COPY"))
+        .insert_header(("X-Warp-Meta-Owner", "team-b"))
+        .set_payload(payload_of(b"v2")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(get_headers!(&app), (Some("camera-7".to_string()), Some("team-a".to_string())));
+
+    // REPLACE (the default) takes the request's, dropping the rest
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/update/{}", key), &bucket)
+        .insert_header(("X-Warp-Meta-Directive", "REPLACE"))
+        .insert_header(("X-Warp-Meta-Owner", "team-b"))
+        .set_payload(payload_of(b"v3")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(get_headers!(&app), (None, Some("team-b".to_string())));
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/update/{}", key), &bucket)
+        .set_payload(payload_of(b"v4")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(get_headers!(&app), (None, None));
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/update/{}", key), &bucket)
+        .insert_header(("X-Warp-Meta-Directive", "MERGE"))
+        .set_payload(payload_of(b"v5")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
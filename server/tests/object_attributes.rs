@@ -0,0 +1,157 @@
+// S3 upload checksums and GetObjectAttributes: x-amz-checksum-* values are verified against
+// the body, stored and echoed back, and ?attributes returns the requested subset. Requests
+// are signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use sha2::{Digest, Sha256};
+use std::sync::Once;
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_get_object_handler, s3_head_object_handler, s3_multipart_router, s3_upload_part_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "attributes-test-access";
+const SECRET_KEY: &str = "attributes-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() -> String {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    let bucket = unique("attrs");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    bucket
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+fn sha256_b64(data: &[u8]) -> String {
+    B64.encode(Sha256::digest(data))
+}
+
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = xml.find(&open)? + open.len();
+    Some(&xml[start..start + xml[start..].find(&format!("</{}>", name))?])
+}
+
+#[actix_web::test]
+async fn test_checksum_is_verified_stored_and_returned() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+            .route("/s3/{bucket}/{key}", web::head().to(s3_head_object_handler))
+    ).await;
+    let path = format!("/s3/{}/summed", bucket);
+    let body = b"checksummed body";
+
+    // A mismatching value is rejected and nothing is stored
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &path, "")
+        .insert_header(("x-amz-checksum-sha256", sha256_b64(b"something else")))
+        .set_payload(body.to_vec()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8_lossy(&test::read_body(resp).await).contains("<Code>BadDigest</Code>"));
+    let resp = test::call_service(&app, signed(test::TestRequest::default().method(actix_web::http::Method::HEAD), "HEAD", &path, "").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // The value header alone names the algorithm
+    let checksum = sha256_b64(body);
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &path, "")
+        .insert_header(("x-amz-checksum-sha256", checksum.clone()))
+        .set_payload(body.to_vec()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("x-amz-checksum-sha256").unwrap().to_str().unwrap(), checksum);
+
+    // GET and HEAD return it in checksum mode
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path, "")
+        .insert_header(("x-amz-checksum-mode", "ENABLED")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("x-amz-checksum-sha256").unwrap().to_str().unwrap(), checksum);
+    let resp = test::call_service(&app, signed(test::TestRequest::default().method(actix_web::http::Method::HEAD), "HEAD", &path, "")
+        .insert_header(("x-amz-checksum-mode", "ENABLED")).to_request()).await;
+    assert_eq!(resp.headers().get("x-amz-checksum-sha256").unwrap().to_str().unwrap(), checksum);
+
+    // CRC32 is accepted the same way
+    let crc_path = format!("/s3/{}/crc", bucket);
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &crc_path, "")
+        .insert_header(("x-amz-checksum-crc32", B64.encode(crc32fast::hash(body).to_be_bytes())))
+        .set_payload(body.to_vec()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &crc_path, "")
+        .insert_header(("x-amz-checksum-crc32", "AAAAAA=="))
+        .set_payload(body.to_vec()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Only the requested attributes come back; unknown names are ignored
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path, "attributes=")
+        .insert_header(("x-amz-object-attributes", "ObjectSize,Checksum,NoSuchAttribute")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert_eq!(element(&xml, "ObjectSize"), Some(body.len().to_string().as_str()), "{}", xml);
+    assert_eq!(element(&xml, "ChecksumSHA256"), Some(checksum.as_str()), "{}", xml);
+    assert!(element(&xml, "ETag").is_none() && element(&xml, "StorageClass").is_none(), "{}", xml);
+    assert!(element(&xml, "ObjectParts").is_none(), "{}", xml);
+
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path, "attributes=")
+        .insert_header(("x-amz-object-attributes", "ETag")).to_request()).await;
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert_eq!(element(&xml, "ETag"), Some(format!("{:x}", md5::compute(body)).as_str()), "{}", xml);
+    assert!(element(&xml, "ObjectSize").is_none() && element(&xml, "Checksum").is_none(), "{}", xml);
+}
+
+#[actix_web::test]
+async fn test_attributes_list_parts_of_multipart_objects() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::post().to(s3_multipart_router))
+            .route("/s3/{bucket}/{key}", web::put().to(s3_upload_part_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let path = format!("/s3/{}/assembled", bucket);
+
+    let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, "uploads=").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    let upload_id = element(&xml, "UploadId").unwrap().to_string();
+
+    let parts = [vec![b'a'; 5 * 1024 * 1024], b"tail".to_vec()];
+    let mut complete = String::from("<CompleteMultipartUpload>");
+    for (i, data) in parts.iter().enumerate() {
+        let query = format!("partNumber={}&uploadId={}", i + 1, upload_id);
+        let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &path, &query)
+            .set_payload(data.clone()).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+        complete.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag));
+    }
+    complete.push_str("</CompleteMultipartUpload>");
+    let query = format!("uploadId={}", upload_id);
+    let resp = test::call_service(&app, signed(test::TestRequest::post(), "POST", &path, &query)
+        .set_payload(complete).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path, "attributes=")
+        .append_header(("x-amz-object-attributes", "ObjectParts"))
+        .append_header(("x-amz-object-attributes", "ObjectSize")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert_eq!(element(&xml, "ObjectSize"), Some((5 * 1024 * 1024 + 4).to_string().as_str()), "{}", xml);
+    assert_eq!(element(&xml, "TotalPartsCount"), Some("2"), "{}", xml);
+    let sizes: Vec<&str> = xml.split("<Part>").skip(1).map(|p| element(p, "Size").unwrap()).collect();
+    assert_eq!(sizes, vec!["5242880", "4"]);
+    assert!(element(&xml, "ETag").is_none(), "{}", xml);
+}
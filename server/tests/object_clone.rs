@@ -0,0 +1,127 @@
+// Copy-on-write clones: /clone and CopyObject with x-warp-cow share the source's chunks, and
+// a shared range is only queued for deletion once no object references it. S3 requests are
+// signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use rusqlite::Connection;
+use std::sync::Once;
+use warp_drive::api::{put, get, delete, clone};
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_get_object_handler};
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{AMZ_DATE, authorization_with_query, payload, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "clone-test-access";
+const SECRET_KEY: &str = "clone-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+}
+
+fn stored_files(body: &[u8]) -> Vec<Vec<u8>> {
+    let list = root_as_file_data_list(body).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+/// Queued deletion events for `bucket`, as (key, number of ranges)
+fn queued(bucket: &str) -> Vec<(String, usize)> {
+    let conn = Connection::open(common::isolate().join("metadata").join("metadata.sqlite")).unwrap();
+    let mut stmt = conn.prepare("SELECT key, offset_size_list FROM deletion_queue WHERE bucket = ?1 ORDER BY id").unwrap();
+    let rows = stmt.query_map([bucket], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))).unwrap();
+    rows.map(|row| {
+        let (key, bytes) = row.unwrap();
+        (key, warp_drive::util::serializer::decode_extents(&bytes).unwrap().len())
+    }).collect()
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str) -> test::TestRequest {
+    req.uri(path)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, "", UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_clone_outlives_original_and_frees_chunks_last() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(delete).service(clone)).await;
+    let bucket = unique("clone");
+    let native = |req: test::TestRequest, uri: &str| req.uri(uri).insert_header(("user", "clone_user")).insert_header(("bucket", bucket.clone()));
+
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/original").set_payload(payload(&[b"first", b"second"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/clone/original/copy").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["chunks"], 2);
+    assert_eq!(body["size"], 11);
+
+    // Same extents, no new data
+    let db = MetadataService::new("clone_user").unwrap();
+    assert_eq!(db.read_metadata(&bucket, "original").unwrap(), db.read_metadata(&bucket, "copy").unwrap());
+
+    // An existing destination needs ?overwrite=true
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/clone/original/copy").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), "/delete/original").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(queued(&bucket).is_empty(), "chunks are still referenced by the clone");
+
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/copy").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![b"first".to_vec(), b"second".to_vec()]);
+
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), "/delete/copy").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(queued(&bucket), vec![("copy".to_string(), 2)]);
+    DeletionWorker::new().process_deletions().await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_s3_copy_with_cow_header_shares_chunks() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::get().to(s3_get_object_handler))
+    ).await;
+    let bucket = unique("clone");
+    let db = MetadataService::new("admin").unwrap();
+    db.create_bucket(&bucket).unwrap();
+
+    let src = format!("/s3/{}/src", bucket);
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &src).set_payload("shared bytes").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let cow = format!("/s3/{}/cow", bucket);
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &cow)
+        .insert_header(("x-amz-copy-source", format!("{}/src", bucket)))
+        .insert_header(("x-warp-cow", "true")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(db.read_metadata(&bucket, "cow").unwrap(), db.read_metadata(&bucket, "src").unwrap());
+
+    // Without the header the destination gets its own chunks
+    let full = format!("/s3/{}/full", bucket);
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &full)
+        .insert_header(("x-amz-copy-source", format!("{}/src", bucket))).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_ne!(db.read_metadata(&bucket, "full").unwrap(), db.read_metadata(&bucket, "src").unwrap());
+
+    // Overwriting the source only drops its reference to the shared range
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &src).set_payload("replaced").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(queued(&bucket).is_empty());
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &cow).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "shared bytes");
+}
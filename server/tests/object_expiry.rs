@@ -0,0 +1,125 @@
+// Per-object expiry set with X-Warp-Expires / X-Warp-Expire-At. Time is moved forward with
+// the injectable clock rather than slept through, so tests in this binary must not depend on
+// the current time staying put; S3 requests are signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use warp_drive::api::{append, get, put};
+use warp_drive::s3::handlers::{s3_get_object_handler, s3_head_object_handler, s3_put_object_handler};
+use warp_drive::service::expiry;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::clock;
+
+mod common;
+use common::{AMZ_DATE, authorization, payload, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "expiry-test-access";
+const SECRET_KEY: &str = "expiry-test-secret";
+const USER: &str = "expiry_user";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+}
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket.to_string()))
+}
+
+fn s3(req: test::TestRequest, method: &str, bucket: &str, key: &str) -> test::TestRequest {
+    let path = format!("/s3/{}/{}", bucket, key);
+    req.uri(&path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, &path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_native_object_expires_after_ttl() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(append)).await;
+    let bucket = unique("ttl");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&bucket).unwrap();
+
+    let put_object = |key: &str, ttl: Option<&str>| {
+        let mut req = native(test::TestRequest::post(), &format!("/put/{}", key), &bucket).set_payload(payload(&[b"abc", b"def"]));
+        if let Some(ttl) = ttl {
+            req = req.insert_header((expiry::EXPIRES_HEADER, ttl.to_string()));
+        }
+        req.to_request()
+    };
+    for (key, ttl) in [("short", Some("60")), ("swept", Some("60")), ("kept", None)] {
+        assert_eq!(test::call_service(&app, put_object(key, ttl)).await.status(), StatusCode::OK);
+    }
+    let resp = test::call_service(&app, put_object("bad", Some("soon"))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Before expiry the object reads normally and reports when it goes
+    let get_object = |key: &str| native(test::TestRequest::get(), &format!("/get/{}", key), &bucket).to_request();
+    let resp = test::call_service(&app, get_object("short")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let expire_at = resp.headers().get(expiry::EXPIRE_AT_HEADER).unwrap().to_str().unwrap().to_string();
+    assert!(chrono::DateTime::parse_from_rfc3339(&expire_at).is_ok(), "{}", expire_at);
+    assert!(test::call_service(&app, get_object("kept")).await.headers().get(expiry::EXPIRE_AT_HEADER).is_none());
+
+    // Appending keeps the expiry
+    let req = native(test::TestRequest::post(), "/append/short", &bucket).set_payload(payload(&[b"ghi"])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, get_object("short")).await;
+    assert_eq!(resp.headers().get(expiry::EXPIRE_AT_HEADER).unwrap().to_str().unwrap(), expire_at);
+    assert_eq!(db.pending_deletion_count_for_bucket(&bucket).unwrap(), 0);
+
+    clock::advance(chrono::Duration::seconds(61));
+
+    // A lookup past the expiry removes the object and queues its chunks
+    assert_eq!(test::call_service(&app, get_object("short")).await.status(), StatusCode::NOT_FOUND);
+    assert!(!db.check_key(&bucket, "short").unwrap());
+    let queued = db.pending_deletion_count_for_bucket(&bucket).unwrap();
+    assert!(queued > 0);
+
+    // The worker's sweep takes the rest
+    assert!(expiry::sweep(expiry::SWEEP_BATCH).unwrap() >= 1);
+    assert!(db.pending_deletion_count_for_bucket(&bucket).unwrap() > queued);
+    assert_eq!(test::call_service(&app, get_object("swept")).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(test::call_service(&app, get_object("kept")).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_s3_put_reports_expiration() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+            .route("/s3/{bucket}/{key}", web::head().to(s3_head_object_handler))
+    ).await;
+    let bucket = unique("ttl_s3");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+
+    let req = s3(test::TestRequest::put(), "PUT", &bucket, "report.csv")
+        .insert_header((expiry::EXPIRE_AT_HEADER, "2099-01-02T03:04:05Z"))
+        .set_payload("a,b\n1,2\n")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let expected = "expiry-date=\"Fri, 02 Jan 2099 03:04:05 GMT\", rule-id=\"object-ttl\"";
+    assert_eq!(resp.headers().get("x-amz-expiration").unwrap(), expected);
+
+    let resp = test::call_service(&app, s3(test::TestRequest::get(), "GET", &bucket, "report.csv").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("x-amz-expiration").unwrap(), expected);
+    let head = s3(test::TestRequest::default().method(actix_web::http::Method::HEAD), "HEAD", &bucket, "report.csv");
+    let resp = test::call_service(&app, head.to_request()).await;
+    assert_eq!(resp.headers().get("x-amz-expiration").unwrap(), expected);
+
+    let req = s3(test::TestRequest::put(), "PUT", &bucket, "bad.csv")
+        .insert_header((expiry::EXPIRE_AT_HEADER, "tomorrow"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
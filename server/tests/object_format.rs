@@ -0,0 +1,86 @@
+// Stored object format: native GET answers FlatBuffers for natively written objects and raw
+// bytes for S3 ones unless ?format= overrides it, and S3 HEAD reports the format. S3 requests
+// are signed with the admin access key, whose objects belong to user "admin".
+
+use actix_web::{test, web, App, http::StatusCode};
+use warp_drive::api::{put, get};
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_head_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{AMZ_DATE, authorization, payload, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "format-test-access";
+const SECRET_KEY: &str = "format-test-secret";
+
+fn stored_files(body: &[u8]) -> Vec<Vec<u8>> {
+    let list = root_as_file_data_list(body).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+#[actix_web::test]
+async fn test_native_get_follows_stored_format() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    let app = test::init_service(
+        App::new()
+            .service(put)
+            .service(get)
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::head().to(s3_head_object_handler))
+    ).await;
+    let bucket = unique("format");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let native = |uri: &str| test::TestRequest::get().uri(uri)
+        .insert_header(("user", "admin")).insert_header(("bucket", bucket.clone()))
+        .to_request();
+    let signed = |req: test::TestRequest, method: &str, key: &str| {
+        let path = format!("/s3/{}/{}", bucket, key);
+        req.uri(&path)
+            .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, &path, UNSIGNED_PAYLOAD)))
+            .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+            .insert_header(("x-amz-date", AMZ_DATE))
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, test::TestRequest::post().uri("/put/files")
+        .insert_header(("user", "admin")).insert_header(("bucket", bucket.clone()))
+        .set_payload(payload(&[b"one", b"two"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(test::TestRequest::put()
+        .insert_header(("Content-Type", "text/plain"))
+        .set_payload("plain text body"), "PUT", "document")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Each object comes back in the representation it was written in
+    let resp = test::call_service(&app, native("/get/files")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("x-warp-object-format").unwrap(), "native");
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/octet-stream");
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![b"one".to_vec(), b"two".to_vec()]);
+
+    let resp = test::call_service(&app, native("/get/document")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("x-warp-object-format").unwrap(), "raw");
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain");
+    assert_eq!(test::read_body(resp).await, "plain text body");
+
+    // ?format= forces either representation
+    let resp = test::call_service(&app, native("/get/files?format=raw")).await;
+    assert_eq!(resp.headers().get("x-warp-object-format").unwrap(), "raw");
+    assert_eq!(test::read_body(resp).await, "onetwo");
+    let resp = test::call_service(&app, native("/get/document?format=native")).await;
+    assert_eq!(resp.headers().get("x-warp-object-format").unwrap(), "native");
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![b"plain text body".to_vec()]);
+    let resp = test::call_service(&app, native("/get/document?format=xml")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // HEAD reports the stored format
+    for (key, format) in [("files", "native"), ("document", "raw")] {
+        let resp = test::call_service(&app, signed(test::TestRequest::default().method(actix_web::http::Method::HEAD), "HEAD", key)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("x-warp-object-format").unwrap(), format, "{}", key);
+    }
+}
@@ -0,0 +1,127 @@
+// Per-object retention and legal hold set with x-amz-object-lock-* headers, enforced on S3
+// deletes and overwrites whether or not the bucket has object lock enabled. S3 requests are
+// signed with the admin access key.
+
+use actix_web::{test, web, App, http::{Method, StatusCode}};
+use std::sync::Once;
+use warp_drive::s3::handlers::{s3_delete_object_handler, s3_get_object_handler, s3_head_object_handler, s3_put_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "lock-test-access";
+const SECRET_KEY: &str = "lock-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() -> String {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    let bucket = unique("lock");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    bucket
+}
+
+/// A signed request for `/s3/{bucket}/{key}`, with `query` as the canonical query string
+fn s3(req: test::TestRequest, method: &str, bucket: &str, key: &str, query: &str) -> test::TestRequest {
+    let path = format!("/s3/{}/{}", bucket, key);
+    let uri = if query.is_empty() { path.clone() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, &path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+fn head() -> test::TestRequest {
+    test::TestRequest::default().method(Method::HEAD)
+}
+
+macro_rules! app {
+    () => {
+        test::init_service(
+            App::new()
+                .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+                .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+                .route("/s3/{bucket}/{key}", web::head().to(s3_head_object_handler))
+                .route("/s3/{bucket}/{key}", web::delete().to(s3_delete_object_handler))
+        ).await
+    };
+}
+
+#[actix_web::test]
+async fn test_future_retention_blocks_delete_and_overwrite() {
+    let bucket = setup();
+    let app = app!();
+    let put = |until: &str, bypass: &str| s3(test::TestRequest::put(), "PUT", &bucket, "report", "")
+        .insert_header(("x-amz-object-lock-mode", "GOVERNANCE"))
+        .insert_header(("x-amz-object-lock-retain-until-date", until.to_string()))
+        .insert_header(("x-amz-bypass-governance-retention", bypass.to_string()))
+        .set_payload("v1")
+        .to_request();
+
+    let resp = test::call_service(&app, put("not-a-date", "false")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, put("2099-01-01T00:00:00+01:00", "false")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    for (method, req) in [("GET", test::TestRequest::get()), ("HEAD", head())] {
+        let resp = test::call_service(&app, s3(req, method, &bucket, "report", "").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let header = |name: &str| resp.headers().get(name).map(|v| v.to_str().unwrap().to_string());
+        assert_eq!(header("x-amz-object-lock-mode").as_deref(), Some("GOVERNANCE"), "{}", method);
+        assert_eq!(header("x-amz-object-lock-retain-until-date").as_deref(), Some("2098-12-31T23:00:00.000Z"), "{}", method);
+        assert_eq!(header("x-amz-object-lock-legal-hold"), None, "{}", method);
+    }
+
+    let resp = test::call_service(&app, s3(test::TestRequest::delete(), "DELETE", &bucket, "report", "").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let body = String::from_utf8_lossy(&test::read_body(resp).await).into_owned();
+    assert!(body.contains("<Code>AccessDenied</Code>"), "{}", body);
+
+    let req = s3(test::TestRequest::put(), "PUT", &bucket, "report", "").set_payload("v2").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+    // Shortening needs the governance bypass, and even then an overwrite may not shorten it
+    let retention = |until: &str| format!("<Retention><Mode>GOVERNANCE</Mode><RetainUntilDate>{}</RetainUntilDate></Retention>", until);
+    let req = s3(test::TestRequest::put(), "PUT", &bucket, "report", "retention=").set_payload(retention("2098-01-01T00:00:00Z")).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(test::call_service(&app, put("2098-01-01T00:00:00Z", "true")).await.status(), StatusCode::FORBIDDEN);
+
+    let resp = test::call_service(&app, s3(test::TestRequest::get(), "GET", &bucket, "report", "").to_request()).await;
+    assert_eq!(test::read_body(resp).await, "v1");
+}
+
+#[actix_web::test]
+async fn test_legal_hold_off_and_expired_retention_allow_delete() {
+    let bucket = setup();
+    let app = app!();
+    let req = s3(test::TestRequest::put(), "PUT", &bucket, "evidence", "")
+        .insert_header(("x-amz-object-lock-mode", "COMPLIANCE"))
+        .insert_header(("x-amz-object-lock-retain-until-date", "2001-01-01T00:00:00Z"))
+        .insert_header(("x-amz-object-lock-legal-hold", "ON"))
+        .set_payload("data")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, s3(head(), "HEAD", &bucket, "evidence", "").to_request()).await;
+    assert_eq!(resp.headers().get("x-amz-object-lock-legal-hold").unwrap(), "ON");
+    let delete = || s3(test::TestRequest::delete(), "DELETE", &bucket, "evidence", "").to_request();
+    assert_eq!(test::call_service(&app, delete()).await.status(), StatusCode::FORBIDDEN);
+
+    let req = s3(test::TestRequest::put(), "PUT", &bucket, "evidence", "legal-hold=")
+        .set_payload("<LegalHold><Status>OFF</Status></LegalHold>")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, delete()).await.status(), StatusCode::NO_CONTENT);
+
+    // The lock went with the object: a new object under the key starts unlocked
+    let req = s3(test::TestRequest::put(), "PUT", &bucket, "evidence", "").set_payload("new").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let resp = test::call_service(&app, s3(head(), "HEAD", &bucket, "evidence", "").to_request()).await;
+    assert!(resp.headers().get("x-amz-object-lock-mode").is_none());
+    assert_eq!(test::call_service(&app, delete()).await.status(), StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,120 @@
+// Moving a key between buckets with /move, and the native delete 404 naming the bucket
+// searched and any other bucket holding the key.
+
+use actix_web::{test, App, http::StatusCode};
+use rusqlite::Connection;
+use warp_drive::api::{put, get, delete, move_key};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{payload, unique};
+
+const USER: &str = "move_user";
+
+fn stored_files(body: &[u8]) -> Vec<Vec<u8>> {
+    let list = root_as_file_data_list(body).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+fn native(req: test::TestRequest, bucket: &str) -> test::TestRequest {
+    req.insert_header(("user", USER)).insert_header(("bucket", bucket.to_string()))
+}
+
+fn move_request(key: &str, src: &str, dst: &str) -> test::TestRequest {
+    test::TestRequest::put()
+        .uri(&format!("/move/{}", key))
+        .insert_header(("user", USER))
+        .insert_header(("Src-Bucket", src.to_string()))
+        .insert_header(("Dst-Bucket", dst.to_string()))
+}
+
+/// Queued deletion events for `bucket`, as (key, number of ranges)
+fn queued(bucket: &str) -> Vec<(String, usize)> {
+    let conn = Connection::open(common::isolate().join("metadata").join("metadata.sqlite")).unwrap();
+    let mut stmt = conn.prepare("SELECT key, offset_size_list FROM deletion_queue WHERE bucket = ?1 ORDER BY id").unwrap();
+    let rows = stmt.query_map([bucket], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))).unwrap();
+    rows.map(|row| {
+        let (key, bytes) = row.unwrap();
+        (key, warp_drive::util::serializer::decode_extents(&bytes).unwrap().len())
+    }).collect()
+}
+
+#[actix_web::test]
+async fn test_move_between_buckets() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(get).service(delete).service(move_key)).await;
+    let (src, dst) = (unique("move-src"), unique("move-dst"));
+    let files: [&[u8]; 2] = [b"first chunk", b"second chunk"];
+
+    let resp = test::call_service(&app, native(test::TestRequest::post().uri("/put/moved"), &src)
+        .set_payload(payload(&files)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    MetadataService::new(USER).unwrap().set_object_tags(&src, "moved", &[("team".to_string(), "data".to_string())]).unwrap();
+
+    // Both headers are required, and the buckets must differ
+    let resp = test::call_service(&app, native(test::TestRequest::put().uri("/move/moved"), &src)
+        .insert_header(("Dst-Bucket", dst.clone())).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, move_request("moved", &src, &src).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = test::call_service(&app, move_request("moved", &src, &dst).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["bucket"], dst.as_str());
+    assert_eq!(body["from_bucket"], src.as_str());
+    assert_eq!(body["chunks"], 2);
+
+    // Readable from the destination only, with its tags; the source ranges are queued
+    let resp = test::call_service(&app, native(test::TestRequest::get().uri("/get/moved"), &dst).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(stored_files(&test::read_body(resp).await), files.iter().map(|f| f.to_vec()).collect::<Vec<_>>());
+    let resp = test::call_service(&app, native(test::TestRequest::get().uri("/get/moved"), &src).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let db = MetadataService::new(USER).unwrap();
+    assert_eq!(db.get_object_tags(&dst, "moved").unwrap(), vec![("team".to_string(), "data".to_string())]);
+    assert!(db.get_object_tags(&src, "moved").unwrap().is_empty());
+    assert_eq!(queued(&src), vec![("moved".to_string(), 2)]);
+    assert!(queued(&dst).is_empty());
+
+    // A missing source and a taken destination are refused
+    let resp = test::call_service(&app, move_request("moved", &src, &dst).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, native(test::TestRequest::post().uri("/put/moved"), &src)
+        .set_payload(payload(&[b"newer"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, move_request("moved", &src, &dst).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+}
+
+#[actix_web::test]
+async fn test_misdirected_delete_names_the_buckets() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(delete)).await;
+    let (home, other) = (unique("delete-home"), unique("delete-other"));
+
+    let resp = test::call_service(&app, native(test::TestRequest::post().uri("/put/stray"), &home)
+        .set_payload(payload(&[b"data"])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    MetadataService::new(USER).unwrap().create_bucket(&other).unwrap();
+
+    let resp = test::call_service(&app, native(test::TestRequest::delete().uri("/delete/stray"), &other).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "KeyNotFound");
+    assert_eq!(body["bucket"], other.as_str());
+    assert_eq!(body["found_in"], serde_json::json!([home]));
+    let message = body["message"].as_str().unwrap();
+    assert!(message.contains(&other) && message.contains(&home), "{}", message);
+
+    // Nowhere else: the plain 404, still naming the bucket searched
+    let resp = test::call_service(&app, native(test::TestRequest::delete().uri("/delete/nowhere"), &other).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["bucket"], other.as_str());
+    assert!(body.get("found_in").is_none());
+
+    let resp = test::call_service(&app, native(test::TestRequest::delete().uri("/delete/stray"), &home).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
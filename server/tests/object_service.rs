@@ -0,0 +1,120 @@
+// ObjectService against the mock metadata and storage backends: the checks a handler relies on
+// (duplicate put, missing key, empty body) and an append merging into the stored object.
+
+use bytes::Bytes;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+use warp_drive::service::error::ServiceError;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::object_service::{AppendOutcome, ObjectService};
+use warp_drive::service::storage_service::StorageService;
+use warp_drive::service::user_context::UserContext;
+use warp_drive::storage::mock_store::MockBinaryStore;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{payload, unique};
+
+const USER: &str = "object_service_user";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("METADATA_BACKEND", "mock");
+    });
+}
+
+/// A fresh bucket and a service over an in-memory store
+fn fixture(name: &str) -> (ObjectService, UserContext) {
+    setup();
+    let context = UserContext::with_bucket(USER.to_string(), unique(name));
+    MetadataService::new(USER).unwrap().create_bucket(&context.bucket).unwrap();
+    let objects = ObjectService::with_storage(StorageService::with_store(Arc::new(MockBinaryStore::new())));
+    (objects, context)
+}
+
+fn files_of(native: &[u8]) -> Vec<Vec<u8>> {
+    root_as_file_data_list(native).unwrap().files().unwrap().iter()
+        .map(|f| f.data().unwrap().bytes().to_vec())
+        .collect()
+}
+
+fn service_error(err: &actix_web::Error) -> &ServiceError {
+    err.as_error::<ServiceError>().expect("a ServiceError")
+}
+
+#[actix_web::test]
+async fn test_duplicate_put_is_rejected_before_the_body_is_read() {
+    let (objects, context) = fixture("dup");
+    let stored = objects.put(&context, "k", async { Ok(payload(&[b"first"]).into()) }, None).await.unwrap();
+    assert_eq!(stored.files, 1);
+    assert_eq!(stored.size, 5);
+
+    let drained = AtomicBool::new(false);
+    let err = objects.put(&context, "k", async {
+        drained.store(true, Ordering::SeqCst);
+        Ok(payload(&[b"second"]).into())
+    }, None).await.unwrap_err();
+    assert!(matches!(service_error(&err), ServiceError::KeyExists { .. }), "{}", err);
+    assert!(!drained.load(Ordering::SeqCst), "body of a duplicate put was read");
+
+    let read = objects.get(&context, "k", None).unwrap();
+    assert_eq!(files_of(&objects.read_native(&context, &read).unwrap()), vec![b"first".to_vec()]);
+}
+
+#[actix_web::test]
+async fn test_missing_key() {
+    let (objects, context) = fixture("missing");
+    let not_found = |err: actix_web::Error| {
+        assert!(matches!(service_error(&err), ServiceError::KeyNotFound { .. }), "{}", err);
+    };
+    not_found(objects.get(&context, "nope", None).unwrap_err());
+    not_found(objects.delete(&context, "nope").await.unwrap_err());
+    not_found(objects.rename(&context, "nope", "other").await.unwrap_err());
+    not_found(objects.append(&context, "nope", async { Ok(payload(&[b"x"]).into()) }, None).await.unwrap_err());
+    not_found(objects.update(&context, "nope", async { Ok(payload(&[b"x"]).into()) }, None, false).await.unwrap_err());
+}
+
+#[actix_web::test]
+async fn test_empty_body() {
+    let (objects, context) = fixture("empty");
+    let stored = objects.put(&context, "k", async { Ok(Bytes::new()) }, None).await.unwrap();
+    assert_eq!((stored.size, stored.files), (0, 0));
+    let read = objects.get(&context, "k", None).unwrap();
+    assert_eq!(read.raw_len(), 0);
+    assert!(read.files.is_empty());
+
+    let err = objects.append(&context, "k", async { Ok(payload(&[]).into()) }, None).await.unwrap_err();
+    assert!(matches!(service_error(&err), ServiceError::EmptyPayload(_)), "{}", err);
+}
+
+#[actix_web::test]
+async fn test_append_merges_into_the_object() {
+    let (objects, context) = fixture("append");
+    objects.put(&context, "k", async { Ok(payload(&[b"one"]).into()) }, None).await.unwrap();
+
+    let outcome = objects.append(&context, "k", async { Ok(payload(&[b"two", b"three"]).into()) }, Some("a1")).await.unwrap();
+    let record = match outcome {
+        AppendOutcome::Appended(record) => record,
+        other => panic!("expected an append, got {:?}", other),
+    };
+    assert_eq!((record.first_chunk, record.chunks, record.size), (1, 2, 8));
+
+    let read = objects.get(&context, "k", None).unwrap();
+    assert_eq!(read.files.len(), 3);
+    assert_eq!(read.metadata.size, 11);
+    assert_eq!(
+        files_of(&objects.read_native(&context, &read).unwrap()),
+        vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+    );
+    assert_eq!(&objects.read_raw(&context, &read, None).unwrap()[..], b"onetwothree");
+
+    let replay = objects.append(&context, "k", async { Ok(payload(&[b"two", b"three"]).into()) }, Some("a1")).await.unwrap();
+    assert_eq!(replay, AppendOutcome::Replayed(record));
+    assert_eq!(objects.get(&context, "k", None).unwrap().files.len(), 3);
+
+    let selected = objects.get(&context, "k", Some((1, 1))).unwrap();
+    assert_eq!(&objects.read_raw(&context, &selected, None).unwrap()[..], b"two");
+}
@@ -0,0 +1,201 @@
+// Object tags through the native /tag endpoints and S3 ?tagging, the ListObjects and /list tag
+// filters, and tags following objects through copy, rename and delete. S3 requests are signed with the
+// admin access key, whose user is "admin"; native requests use the same user.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use warp_drive::api::{put, delete, update_key, copy, put_tags, get_tags, list};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::s3::handlers::{s3_put_object_handler, s3_get_object_handler, s3_list_objects_handler};
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{AMZ_DATE, authorization_with_query, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "tags-test-access";
+const SECRET_KEY: &str = "tags-test-secret";
+const USER: &str = "admin";
+
+static SETUP: Once = Once::new();
+
+fn setup() -> String {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    let bucket = unique("tags");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    bucket
+}
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket))
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+fn stored_tags(bucket: &str, key: &str) -> Vec<(String, String)> {
+    SQLiteMetadataStore::new().get_object_tags(USER, bucket, key).unwrap()
+}
+
+#[actix_web::test]
+async fn test_native_and_s3_tags_round_trip() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new()
+            .service(put)
+            .service(put_tags)
+            .service(get_tags)
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::get().to(s3_get_object_handler))
+    ).await;
+    let key = unique("tagged");
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", key), &bucket).set_payload(payload_of(b"data")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, native(test::TestRequest::put(), &format!("/tag/{}", key), &bucket)
+        .set_json(serde_json::json!({ "project": "alpha", "team": "storage" })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let path = format!("/s3/{}/{}", bucket, key);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path, "tagging=").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Tag><Key>project</Key><Value>alpha</Value></Tag>"), "{}", xml);
+    assert!(xml.contains("<Tag><Key>team</Key><Value>storage</Value></Tag>"), "{}", xml);
+
+    // S3 PUT ?tagging replaces the whole set
+    let body = "<Tagging><TagSet><Tag><Key>project</Key><Value>beta</Value></Tag></TagSet></Tagging>";
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &path, "tagging=").set_payload(body).to_request()).await;
+    assert!(resp.status().is_success());
+    let resp = test::call_service(&app, native(test::TestRequest::get(), &format!("/tag/{}", key), &bucket).to_request()).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["tags"], serde_json::json!({ "project": "beta" }));
+
+    let too_many: serde_json::Map<String, serde_json::Value> = (0..11).map(|i| (format!("k{}", i), "v".into())).collect();
+    let resp = test::call_service(&app, native(test::TestRequest::put(), &format!("/tag/{}", key), &bucket)
+        .set_json(too_many).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "InvalidRequest");
+
+    let resp = test::call_service(&app, native(test::TestRequest::put(), &format!("/tag/{}", unique("missing")), &bucket)
+        .set_json(serde_json::json!({ "project": "alpha" })).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_list_objects_filtered_by_tag() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}", web::get().to(s3_list_objects_handler))
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+    ).await;
+    for (key, tagging) in [("a", "project=alpha"), ("b", "project=beta"), ("c", "project=alpha&team=x"), ("d", "")] {
+        let mut req = signed(test::TestRequest::put(), "PUT", &format!("/s3/{}/{}", bucket, key), "").set_payload("x");
+        if !tagging.is_empty() {
+            req = req.insert_header(("x-amz-tagging", tagging));
+        }
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+
+    let path = format!("/s3/{}", bucket);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path, "tag=project%3Aalpha").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Key>a</Key>") && xml.contains("<Key>c</Key>"), "{}", xml);
+    assert!(!xml.contains("<Key>b</Key>") && !xml.contains("<Key>d</Key>"), "{}", xml);
+
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path, "tag=project").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_native_list_filtered_by_tag() {
+    let bucket = setup();
+    let app = test::init_service(App::new().service(put).service(put_tags).service(list)).await;
+    for (key, tags) in [("a", vec![("project", "alpha")]), ("b", vec![("project", "beta")]), ("c", vec![("project", "alpha"), ("team", "x")]), ("d", vec![])] {
+        test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", key), &bucket).set_payload(payload_of(b"x")).to_request()).await;
+        let tags: serde_json::Map<String, serde_json::Value> = tags.into_iter().map(|(k, v)| (k.to_string(), v.into())).collect();
+        let resp = test::call_service(&app, native(test::TestRequest::put(), &format!("/tag/{}", key), &bucket).set_json(tags).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let listed = |body: serde_json::Value| -> Vec<String> {
+        body["objects"].as_array().unwrap().iter().map(|o| o["key"].as_str().unwrap().to_string()).collect()
+    };
+
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/list?tag=project%3Aalpha", &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(listed(test::read_body_json(resp).await), vec!["a", "c"]);
+
+    // Paging applies to the filtered keys
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/list?tag=project%3Aalpha&limit=1", &bucket).to_request()).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!((listed(body.clone()), body["next_after"].as_str()), (vec!["a".to_string()], Some("a")));
+
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/list", &bucket).to_request()).await;
+    assert_eq!(listed(test::read_body_json(resp).await), vec!["a", "b", "c", "d"]);
+
+    for query in ["tag=project", "tag=project%3Aalpha&as_of=2024-06-01T00%3A00%3A00Z"] {
+        let resp = test::call_service(&app, native(test::TestRequest::get(), &format!("/list?{}", query), &bucket).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[actix_web::test]
+async fn test_tags_follow_copy_rename_and_delete() {
+    let bucket = setup();
+    let app = test::init_service(
+        App::new().service(put).service(put_tags).service(copy).service(update_key).service(delete)
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+    ).await;
+    let src = unique("src");
+    test::call_service(&app, native(test::TestRequest::post(), &format!("/put/{}", src), &bucket).set_payload(payload_of(b"data")).to_request()).await;
+    test::call_service(&app, native(test::TestRequest::put(), &format!("/tag/{}", src), &bucket)
+        .set_json(serde_json::json!({ "project": "alpha" })).to_request()).await;
+    let alpha = vec![("project".to_string(), "alpha".to_string())];
+
+    // Native copy keeps tags by default and drops them with Tagging-Directive: REPLACE
+    let copied = unique("copied");
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/copy/{}/{}", src, copied), &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(stored_tags(&bucket, &copied), alpha);
+    let bare = unique("bare");
+    let resp = test::call_service(&app, native(test::TestRequest::post(), &format!("/copy/{}/{}", src, bare), &bucket)
+        .insert_header(("Tagging-Directive", "REPLACE")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(stored_tags(&bucket, &bare).is_empty());
+
+    // S3 CopyObject: COPY by default, REPLACE takes x-amz-tagging
+    let s3_copy = |dst: &str| signed(test::TestRequest::put(), "PUT", &format!("/s3/{}/{}", bucket, dst), "")
+        .insert_header(("x-amz-copy-source", format!("/{}/{}", bucket, src)));
+    let s3_copied = unique("s3copied");
+    assert_eq!(test::call_service(&app, s3_copy(&s3_copied).to_request()).await.status(), StatusCode::OK);
+    assert_eq!(stored_tags(&bucket, &s3_copied), alpha);
+    let s3_replaced = unique("s3replaced");
+    let resp = test::call_service(&app, s3_copy(&s3_replaced)
+        .insert_header(("x-amz-tagging-directive", "REPLACE"))
+        .insert_header(("x-amz-tagging", "team=storage"))
+        .to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(stored_tags(&bucket, &s3_replaced), vec![("team".to_string(), "storage".to_string())]);
+
+    // Rename moves the tags; delete removes them
+    let renamed = unique("renamed");
+    let resp = test::call_service(&app, native(test::TestRequest::put(), &format!("/update_key/{}/{}", src, renamed), &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(stored_tags(&bucket, &src).is_empty());
+    assert_eq!(stored_tags(&bucket, &renamed), alpha);
+
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), &format!("/delete/{}", renamed), &bucket).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(stored_tags(&bucket, &renamed).is_empty());
+}
@@ -0,0 +1,93 @@
+// Parallel writers: many threads putting objects into one bucket, across segment rollovers,
+// and into several users' buckets at once, end with no two chunks sharing a byte in
+// metadata and every object reading back as written.
+
+use std::sync::Once;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::object_service::ObjectService;
+use warp_drive::service::user_context::UserContext;
+
+mod common;
+use common::{payload, unique};
+
+const WRITERS: usize = 8;
+const OBJECTS_PER_WRITER: usize = 20;
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("SEGMENT_MAX_BYTES", "65536");
+    });
+}
+
+/// Files of object `i` of `writer`: distinct bytes and sizes
+fn files_of(writer: usize, i: usize) -> Vec<Vec<u8>> {
+    let fill = (writer * OBJECTS_PER_WRITER + i) as u8;
+    vec![vec![fill; 1000 + 97 * i], vec![fill.wrapping_add(1); 3000 + 131 * writer]]
+}
+
+/// Put every writer's objects from its own thread, one runtime each
+fn put_concurrently(contexts: &[UserContext]) {
+    std::thread::scope(|scope| {
+        for (writer, context) in contexts.iter().enumerate() {
+            scope.spawn(move || {
+                actix_web::rt::System::new().block_on(async move {
+                    let objects = ObjectService::new();
+                    for i in 0..OBJECTS_PER_WRITER {
+                        let files = files_of(writer, i);
+                        let body = payload(&files.iter().map(Vec::as_slice).collect::<Vec<_>>()).into();
+                        objects.put(context, &format!("w{}-{}", writer, i), async move { Ok(body) }, None).await.unwrap();
+                    }
+                });
+            });
+        }
+    });
+}
+
+/// Chunks of the objects in `contexts` do not overlap within a bucket, and read back as written
+fn check(contexts: &[UserContext]) {
+    let objects = ObjectService::new();
+    let mut extents: Vec<(String, String, u64, u64)> = Vec::new();
+    for (writer, context) in contexts.iter().enumerate() {
+        let db = MetadataService::new(&context.user_id).unwrap();
+        for i in 0..OBJECTS_PER_WRITER {
+            let key = format!("w{}-{}", writer, i);
+            let metadata = db.get_object_full(&context.bucket, &key).unwrap();
+            extents.extend(metadata.chunks.iter().map(|c| (context.user_id.clone(), context.bucket.clone(), c.extent().0, c.size)));
+            let read = objects.get(context, &key, None).unwrap();
+            assert_eq!(objects.read_raw(context, &read, None).unwrap(), files_of(writer, i).concat(), "{}", key);
+        }
+    }
+    extents.sort();
+    for pair in extents.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        if (&a.0, &a.1) == (&b.0, &b.1) {
+            assert!(a.2 + a.3 <= b.2, "chunks overlap: {:?} and {:?}", a, b);
+        }
+    }
+    assert_eq!(extents.len(), contexts.len() * OBJECTS_PER_WRITER * 2);
+}
+
+#[test]
+fn test_concurrent_writers_to_one_bucket() {
+    setup();
+    let bucket = unique("parallel");
+    let contexts: Vec<UserContext> = (0..WRITERS)
+        .map(|_| UserContext::with_bucket("parallel_user".to_string(), bucket.clone()))
+        .collect();
+    put_concurrently(&contexts);
+    check(&contexts);
+}
+
+#[test]
+fn test_concurrent_writers_across_users() {
+    setup();
+    let bucket = unique("tenants");
+    let contexts: Vec<UserContext> = (0..WRITERS)
+        .map(|writer| UserContext::with_bucket(format!("tenant_{}", writer % 3), bucket.clone()))
+        .collect();
+    put_concurrently(&contexts);
+    check(&contexts);
+}
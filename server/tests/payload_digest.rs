@@ -0,0 +1,106 @@
+// Sampled payload digests: with DIGEST_SAMPLE_RATE=1 every PUT and GET logs the SHA-256 of the
+// object data it carried. This binary installs a logger that keeps the digest lines. S3
+// requests are signed with the admin access key, whose objects belong to user "admin".
+
+use actix_web::{test, web, App, http::StatusCode};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::{Mutex, Once};
+use warp_drive::logging::request_scope;
+use warp_drive::s3::handlers::{s3_get_object_handler, s3_put_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::payload_digest::LOG_TARGET;
+
+mod common;
+use common::{authorization, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "digest-test-access";
+const SECRET_KEY: &str = "digest-test-secret";
+
+static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static SETUP: Once = Once::new();
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == LOG_TARGET
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            LINES.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+        log::set_boxed_logger(Box::new(Capture)).unwrap();
+        log::set_max_level(LevelFilter::Info);
+    });
+}
+
+/// Digest lines logged for `key`
+fn digest_lines(key: &str) -> Vec<Value> {
+    LINES.lock().unwrap().iter()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|line| line["key"] == key)
+        .collect()
+}
+
+#[actix_web::test]
+async fn test_put_and_get_digests_match_the_payload() {
+    setup();
+    std::env::set_var("DIGEST_SAMPLE_RATE", "1.0");
+    let app = test::init_service(
+        App::new()
+            .wrap(actix_web::middleware::from_fn(request_scope))
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let bucket = unique("digest");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let path = format!("/s3/{}/object", bucket);
+    let signed = |req: test::TestRequest, method: &str, request_id: &str| req.uri(&path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, &path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+        .insert_header(("x-request-id", request_id.to_string()))
+        .to_request();
+
+    let payload: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let expected = hex::encode(Sha256::digest(&payload));
+    let resp = test::call_service(&app, signed(test::TestRequest::put().set_payload(payload.clone()), "PUT", "digest-put")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", "digest-get")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await.as_ref(), payload.as_slice());
+
+    let lines = digest_lines("object");
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    let (upload, download) = (&lines[0], &lines[1]);
+    assert_eq!(upload["direction"], "upload");
+    assert_eq!(upload["request_id"], "digest-put");
+    assert_eq!(download["direction"], "download");
+    assert_eq!(download["request_id"], "digest-get");
+    for line in [upload, download] {
+        assert_eq!(line["user"], "admin");
+        assert_eq!(line["bucket"], bucket.as_str());
+        assert_eq!(line["bytes"], payload.len());
+        assert_eq!(line["sha256"], expected.as_str());
+        assert_eq!(line["complete"], true);
+    }
+
+    // Nothing is logged at rate 0
+    std::env::set_var("DIGEST_SAMPLE_RATE", "0");
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", "digest-off")).await;
+    assert_eq!(test::read_body(resp).await.len(), payload.len());
+    assert_eq!(digest_lines("object").len(), 2);
+}
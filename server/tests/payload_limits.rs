@@ -0,0 +1,153 @@
+// Object bodies and control bodies have separate caps, and native FlatBuffers bodies are
+// verified and held to file count and size limits. This binary lowers all of them
+// (MAX_PAYLOAD_BYTES=8192, MAX_CONTROL_PAYLOAD_BYTES=1024, NATIVE_MAX_FILES=4,
+// NATIVE_MAX_FILE_BYTES=4096) before the first request; S3 requests are signed with the admin
+// access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use warp_drive::api::{put, get_batch, put_tags};
+use warp_drive::s3::handlers::s3_put_object_handler;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{AMZ_DATE, authorization_with_query, payload, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "limits-test-access";
+const SECRET_KEY: &str = "limits-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+        std::env::set_var("MAX_PAYLOAD_BYTES", "8192");
+        std::env::set_var("MAX_CONTROL_PAYLOAD_BYTES", "1024");
+        std::env::set_var("NATIVE_MAX_FILES", "4");
+        std::env::set_var("NATIVE_MAX_FILE_BYTES", "4096");
+    });
+}
+
+/// Whether anything was written to `bucket`'s storage files
+fn bucket_written(bucket: &str) -> bool {
+    let dir = common::isolate().join("storage").join("limits_user");
+    std::fs::read_dir(dir).map(|entries| {
+        entries.flatten().any(|e| e.file_name().to_string_lossy().starts_with(bucket))
+    }).unwrap_or(false)
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    req.uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_native_control_bodies_use_the_small_limit() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(get_batch).service(put_tags)).await;
+    let body = payload_of(&[7u8; 2048]);
+
+    // The same 2 KiB body is object data for /put but far too much for a key list
+    let key = unique("limits");
+    let resp = test::call_service(&app, test::TestRequest::post().uri(&format!("/put/{}", key))
+        .insert_header(("user", "limits_user")).set_payload(body.clone()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, test::TestRequest::post().uri("/get_batch")
+        .insert_header(("user", "limits_user")).set_payload(body.clone()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let tags = serde_json::json!({ "note": "x".repeat(2048) }).to_string();
+    let resp = test::call_service(&app, test::TestRequest::put().uri(&format!("/tag/{}", key))
+        .insert_header(("user", "limits_user")).set_payload(tags).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Object data is still bounded by MAX_PAYLOAD_BYTES
+    let resp = test::call_service(&app, test::TestRequest::post().uri(&format!("/put/{}", unique("limits_big")))
+        .insert_header(("user", "limits_user")).set_payload(payload_of(&[7u8; 16384])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[actix_web::test]
+async fn test_s3_subresource_bodies_use_the_small_limit() {
+    setup();
+    let app = test::init_service(
+        App::new().route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+    ).await;
+    let bucket = unique("limits");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let path = format!("/s3/{}/object", bucket);
+    let body = vec![b'a'; 2048];
+
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &path, "").set_payload(body.clone()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", &path, "tagging=").set_payload(body).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[actix_web::test]
+async fn test_crafted_vector_length_is_rejected_without_reading_it() {
+    setup();
+    let app = test::init_service(App::new().service(put)).await;
+    let bucket = unique("crafted");
+    let marker = b"crafted-file-data";
+    let valid = payload_of(marker);
+    let at = valid.windows(marker.len()).position(|w| w == marker).unwrap();
+
+    // The data vector's length prefix claims 2 GiB, the files vector's a billion entries
+    let mut huge_data = valid.clone();
+    huge_data[at - 4..at].copy_from_slice(&0x7FFF_FFF0u32.to_le_bytes());
+    let mut huge_files = valid.clone();
+    let files_len = huge_files.windows(4).position(|w| w == 1u32.to_le_bytes()).unwrap();
+    huge_files[files_len..files_len + 4].copy_from_slice(&1_000_000_000u32.to_le_bytes());
+
+    for body in [huge_data, huge_files, b"\xff\xff\xff\x7f garbage".to_vec()] {
+        let started = Instant::now();
+        let resp = test::call_service(&app, test::TestRequest::post().uri(&format!("/put/{}", unique("crafted")))
+            .insert_header(("user", "limits_user")).insert_header(("bucket", bucket.clone()))
+            .set_payload(body).to_request()).await;
+        assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(matches!(body["error"].as_str(), Some("InvalidPayload" | "PayloadLimitExceeded")), "{}", body);
+    }
+    assert!(!bucket_written(&bucket));
+}
+
+#[actix_web::test]
+async fn test_native_file_limits_are_checked_before_any_write() {
+    setup();
+    let app = test::init_service(App::new().service(put)).await;
+    let bucket = unique("file_limits");
+    let put_files = |files: &[&[u8]]| test::TestRequest::post().uri(&format!("/put/{}", unique("files")))
+        .insert_header(("user", "limits_user")).insert_header(("bucket", bucket.clone()))
+        .set_payload(payload(files)).to_request();
+
+    let resp = test::call_service(&app, put_files(&[b"a", b"b", b"c", b"d", b"e"])).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "PayloadLimitExceeded");
+    assert_eq!(body["limit"], "files");
+    assert_eq!(body["max"], 4);
+    assert!(!bucket_written(&bucket), "a rejected payload wrote data");
+
+    // The oversized file is last; the ones before it are not written either
+    let resp = test::call_service(&app, put_files(&[b"small", &[1u8; 5000]])).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["limit"], "file_bytes");
+    assert_eq!(body["max"], 4096);
+    assert_eq!(body["actual"], 5000);
+    assert!(!bucket_written(&bucket), "a rejected payload wrote data");
+
+    let resp = test::call_service(&app, put_files(&[b"a", b"b", b"c", b"d"])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(bucket_written(&bucket));
+}
@@ -0,0 +1,55 @@
+// Segment file preallocation (Linux): with PREALLOCATE_BYTES set, a segment's allocated
+// blocks run ahead of its length, writes into the preallocated region read back as written,
+// and /admin/stats reports the bucket's file extents.
+#![cfg(target_os = "linux")]
+
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use warp_drive::service::stats;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::storage::local_store::LocalXFSBinaryStore;
+use warp_drive::storage::Storage;
+
+mod common;
+use common::unique;
+
+const USER: &str = "prealloc_user";
+const MIB: u64 = 1 << 20;
+
+/// (length, allocated bytes) of `path`
+fn sizes(path: &PathBuf) -> (u64, u64) {
+    let meta = std::fs::metadata(path).unwrap();
+    (meta.len(), meta.blocks() * 512)
+}
+
+#[test]
+fn test_blocks_are_allocated_ahead_of_writes() {
+    common::isolate();
+    std::env::set_var("PREALLOCATE_BYTES", "4MB");
+    let bucket = unique("prealloc");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    let store = LocalXFSBinaryStore::new();
+
+    let first: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let (offset, size) = store.write(USER, &bucket, &first).unwrap();
+    let (path, _) = store.backing_file(USER, &bucket, offset).unwrap();
+    let (len, allocated) = sizes(&path);
+    assert_eq!(len, size);
+    assert!(allocated >= 4 * MIB, "{} bytes allocated for a {} byte file", allocated, len);
+
+    // Later writes land in the preallocated blocks without allocating more
+    let mut written = vec![(offset, first)];
+    for i in 0..10u8 {
+        let data = vec![i; 64 * 1024];
+        let (offset, _) = store.write(USER, &bucket, &data).unwrap();
+        written.push((offset, data));
+    }
+    assert_eq!(sizes(&path).1, allocated);
+    for (offset, data) in &written {
+        assert_eq!(&store.read(USER, &bucket, *offset, data.len() as u64).unwrap(), data);
+    }
+
+    let stats = stats::collect().unwrap();
+    let usage = stats.per_bucket.iter().find(|b| b.bucket == bucket).unwrap();
+    assert!(usage.file_extents.is_some_and(|extents| extents >= 1), "{:?}", usage.file_extents);
+}
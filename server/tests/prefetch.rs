@@ -0,0 +1,102 @@
+// Metadata prefetch on native GET with X-Warp-Prefetch. Prefetching runs after the response,
+// so the tests wait for the keys to show up in the cache before reading them.
+
+use actix_web::{test, App, http::StatusCode};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use warp_drive::api::{get, put, update};
+use warp_drive::metrics;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::prefetch::{self, PREFETCH_HEADER};
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "prefetch_user";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+    });
+}
+
+fn native(req: test::TestRequest, uri: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", USER)).insert_header(("bucket", bucket.to_string()))
+}
+
+fn hits() -> u64 {
+    metrics::counter_value("warpdrive_metadata_prefetch_hits_total", &[])
+}
+
+async fn wait_cached(bucket: &str, key: &str) {
+    let start = Instant::now();
+    while !prefetch::is_cached(USER, bucket, key) {
+        assert!(start.elapsed() < Duration::from_secs(5), "{} was never prefetched", key);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[actix_web::test]
+async fn test_prefetched_keys_hit_the_cache_until_written() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(update)).await;
+    let bucket = unique("frames");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+    for i in 0..8 {
+        let key = format!("frame_{:06}", i);
+        let req = native(test::TestRequest::post(), &format!("/put/{}", key), &bucket)
+            .set_payload(payload_of(key.as_bytes())).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+    let get_frame = |i: usize, hint: Option<&str>| {
+        let mut req = native(test::TestRequest::get(), &format!("/get/frame_{:06}", i), &bucket);
+        if let Some(hint) = hint {
+            req = req.insert_header((PREFETCH_HEADER, hint.to_string()));
+        }
+        req.to_request()
+    };
+
+    // Reading frame 0 with a hint of 4 prefetches frames 1-4 and no further
+    let before = hits();
+    let resp = test::call_service(&app, get_frame(0, Some("4"))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    wait_cached(&bucket, "frame_000004").await;
+    assert!(!prefetch::is_cached(USER, &bucket, "frame_000005"));
+    assert_eq!(hits(), before);
+
+    for i in 1..=4 {
+        let body = test::call_and_read_body(&app, get_frame(i, None)).await;
+        assert!(contains(&body, format!("frame_{:06}", i).as_bytes()));
+    }
+    assert_eq!(hits(), before + 4);
+
+    // A write drops the prefetched entry, and the next read sees the new data
+    let resp = test::call_service(&app, get_frame(4, Some("2"))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    wait_cached(&bucket, "frame_000006").await;
+    let req = native(test::TestRequest::post(), "/update/frame_000005", &bucket)
+        .set_payload(payload_of(b"rewritten")).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    assert!(!prefetch::is_cached(USER, &bucket, "frame_000005"));
+    assert!(prefetch::is_cached(USER, &bucket, "frame_000006"));
+
+    let hits_before_read = hits();
+    let body = test::call_and_read_body(&app, get_frame(5, None)).await;
+    assert!(contains(&body, b"rewritten"));
+    assert_eq!(hits(), hits_before_read);
+    let body = test::call_and_read_body(&app, get_frame(6, None)).await;
+    assert!(contains(&body, b"frame_000006"));
+    assert_eq!(hits(), hits_before_read + 1);
+
+    // An unusable hint is ignored
+    let resp = test::call_service(&app, get_frame(6, Some("lots"))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!prefetch::is_cached(USER, &bucket, "frame_000007"));
+}
@@ -0,0 +1,116 @@
+// Native batch PUT: many keys in one v2 payload, committed in one metadata transaction, with
+// per-key results. A failed commit is provoked with a trigger on the scratch database.
+
+use actix_web::{test, App, http::StatusCode};
+use flatbuffers::FlatBufferBuilder;
+use rusqlite::Connection;
+use warp_drive::api::{put_batch, get};
+use warp_drive::metadata::sqlite_store;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::flatbuffer_store_v2_generated::store_v2;
+
+mod common;
+use common::unique;
+
+const USER: &str = "batch_user";
+
+fn payload(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut builder = FlatBufferBuilder::new();
+    let files: Vec<_> = files.iter().map(|(key, data)| {
+        let data = builder.create_vector(data);
+        let name = builder.create_string(key);
+        let content_type = builder.create_string("text/plain");
+        store_v2::FileData::create(&mut builder, &store_v2::FileDataArgs {
+            data: Some(data), name: Some(name), content_type: Some(content_type), ..Default::default()
+        })
+    }).collect();
+    let files = builder.create_vector(&files);
+    let list = store_v2::FileDataList::create(&mut builder, &store_v2::FileDataListArgs { files: Some(files) });
+    store_v2::finish_file_data_list_buffer(&mut builder, list);
+    builder.finished_data().to_vec()
+}
+
+fn batch_request(bucket: &str, query: &str, files: &[(String, Vec<u8>)]) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri(&format!("/put_batch{}", query))
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket.to_string()))
+        .set_payload(payload(files))
+}
+
+fn files(keys: impl IntoIterator<Item = String>) -> Vec<(String, Vec<u8>)> {
+    keys.into_iter().map(|key| { let data = format!("data of {}", key).into_bytes(); (key, data) }).collect()
+}
+
+#[actix_web::test]
+async fn test_batch_lands_in_one_commit() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put_batch).service(get)).await;
+    let bucket = unique("batch");
+
+    let resp = test::call_service(&app, batch_request(&bucket, "", &files((0..100).map(|i| format!("key-{:03}", i)))).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["stored"], 100);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 100);
+    assert!(results.iter().all(|r| r["status"] == "created"), "{:?}", results);
+    assert_eq!(results[42]["key"], "key-042");
+
+    let db = MetadataService::new(USER).unwrap();
+    assert_eq!(db.list_objects(&bucket).unwrap().len(), 100);
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/get/key-042?format=raw")
+        .insert_header(("user", USER)).insert_header(("bucket", bucket.clone())).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "data of key-042");
+
+    // A commit that fails part way records nothing and queues every written chunk
+    let conn = Connection::open(sqlite_store::initialize().unwrap()).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER fail_batch BEFORE INSERT ON objects WHEN NEW.key = 'poison'
+         BEGIN SELECT RAISE(ABORT, 'injected failure'); END;",
+    ).unwrap();
+    let pending_before = db.pending_deletion_count().unwrap();
+    let mut keys: Vec<String> = (0..10).map(|i| format!("late-{}", i)).collect();
+    keys.insert(5, "poison".to_string());
+    let resp = test::call_service(&app, batch_request(&bucket, "", &files(keys)).to_request()).await;
+    assert!(resp.status().is_server_error(), "{}", resp.status());
+    conn.execute_batch("DROP TRIGGER fail_batch").unwrap();
+    assert_eq!(db.list_objects(&bucket).unwrap().len(), 100);
+    assert_eq!(db.pending_deletion_count().unwrap() - pending_before, 11);
+}
+
+#[actix_web::test]
+async fn test_conflicting_key_is_reported_alone() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put_batch).service(get)).await;
+    let bucket = unique("batch-conflict");
+
+    let resp = test::call_service(&app, batch_request(&bucket, "", &files(["taken".to_string()])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let mut keys: Vec<String> = (0..9).map(|i| format!("fresh-{}", i)).collect();
+    keys.insert(3, "taken".to_string());
+    let mut batch = files(keys);
+    batch[3].1 = b"replacement".to_vec();
+    let resp = test::call_service(&app, batch_request(&bucket, "", &batch).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["stored"], 9);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[3]["key"], "taken");
+    assert_eq!(results[3]["status"], "conflict");
+    assert!(results.iter().enumerate().all(|(i, r)| i == 3 || r["status"] == "created"), "{:?}", results);
+
+    let get_raw = |key: &str| test::TestRequest::get().uri(&format!("/get/{}?format=raw", key))
+        .insert_header(("user", USER)).insert_header(("bucket", bucket.clone())).to_request();
+    assert_eq!(test::read_body(test::call_service(&app, get_raw("taken")).await).await, "data of taken");
+    assert_eq!(MetadataService::new(USER).unwrap().list_objects(&bucket).unwrap().len(), 10);
+
+    // ?overwrite=true replaces it
+    let resp = test::call_service(&app, batch_request(&bucket, "?overwrite=true", &batch[3..4]).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["results"][0]["status"], "replaced");
+    assert_eq!(test::read_body(test::call_service(&app, get_raw("taken")).await).await, "replacement");
+}
@@ -0,0 +1,88 @@
+// Streaming GET read-ahead: with a store that takes a while per read and a client that takes
+// a while per chunk, reading ahead overlaps the two and finishes well before the sequential
+// stream, with the same bytes and no more than the configured chunks read ahead of the client.
+
+use actix_web::Error;
+use bytes::Bytes;
+use futures::StreamExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use warp_drive::service::storage_service::StorageService;
+use warp_drive::service::user_context::UserContext;
+use warp_drive::storage::mock_store::MockBinaryStore;
+use warp_drive::storage::Storage;
+
+const CHUNKS: usize = 8;
+const READ_DELAY: Duration = Duration::from_millis(40);
+const SEND_DELAY: Duration = Duration::from_millis(40);
+
+/// A store whose reads each take `READ_DELAY`, counting the reads started
+struct SlowStore {
+    inner: MockBinaryStore,
+    reads: AtomicUsize,
+}
+
+impl Storage for SlowStore {
+    fn write(&self, user_id: &str, bucket: &str, data: &[u8]) -> Result<(u64, u64), Error> {
+        self.inner.write(user_id, bucket, data)
+    }
+
+    fn read(&self, user_id: &str, bucket: &str, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(READ_DELAY);
+        self.inner.read(user_id, bucket, offset, size)
+    }
+
+    fn delete(&self, user_id: &str, bucket: &str, offset_size_list: &[(u64, u64)]) -> Result<(), Error> {
+        self.inner.delete(user_id, bucket, offset_size_list)
+    }
+
+    fn verify(&self, user_id: &str, bucket: &str, offset: u64, size: u64, checksum: &[u8]) -> Result<bool, Error> {
+        self.inner.verify(user_id, bucket, offset, size, checksum)
+    }
+
+    fn bucket_len(&self, user_id: &str, bucket: &str) -> Result<u64, Error> {
+        self.inner.bucket_len(user_id, bucket)
+    }
+}
+
+/// Stream `CHUNKS` chunks from a slow store to a client taking `SEND_DELAY` per chunk; returns
+/// the bytes received, the bytes stored, the elapsed time and the most reads ever started
+/// ahead of the chunks received
+async fn drain(read_ahead: usize) -> (Vec<u8>, Vec<u8>, Duration, usize) {
+    let store = Arc::new(SlowStore { inner: MockBinaryStore::new(), reads: AtomicUsize::new(0) });
+    let context = UserContext::with_bucket("read_ahead_user".to_string(), "read_ahead".to_string());
+    let mut expected = Vec::new();
+    let slices = (0..CHUNKS).map(|i| {
+        let data: Vec<u8> = (0..4096).map(|j| (i * 31 + j) as u8).collect();
+        expected.extend_from_slice(&data);
+        store.write(&context.user_id, &context.bucket, &data).unwrap()
+    }).collect();
+
+    let started = Instant::now();
+    let mut body = StorageService::with_store(store.clone()).chunk_stream(&context, slices, read_ahead);
+    let (mut received, mut chunks, mut most_ahead) = (Vec::new(), 0, 0);
+    while let Some(chunk) = body.next().await {
+        let chunk: Bytes = chunk.unwrap();
+        received.extend_from_slice(&chunk);
+        chunks += 1;
+        actix_web::rt::time::sleep(SEND_DELAY).await;
+        most_ahead = most_ahead.max(store.reads.load(Ordering::SeqCst) - chunks);
+    }
+    (received, expected, started.elapsed(), most_ahead)
+}
+
+#[actix_web::test]
+async fn test_read_ahead_overlaps_reads_with_sending() {
+    let (sequential, expected, sequential_time, sequential_ahead) = drain(0).await;
+    assert_eq!(sequential, expected);
+    assert_eq!(sequential_ahead, 0);
+    assert!(sequential_time >= (READ_DELAY + SEND_DELAY) * CHUNKS as u32, "{:?}", sequential_time);
+
+    let (pipelined, expected, pipelined_time, pipelined_ahead) = drain(2).await;
+    assert_eq!(pipelined, expected);
+    assert!(pipelined_ahead <= 2, "{} reads ahead of the client", pipelined_ahead);
+    assert!(pipelined_time * 4 < sequential_time * 3,
+        "read-ahead took {:?}, sequential {:?}", pipelined_time, sequential_time);
+}
@@ -0,0 +1,209 @@
+// Mirroring to an S3-compatible remote: the remote is a second instance of our own app serving
+// the S3 API on a local port. Chunks written through the mirrored store reach it at the local
+// offsets, a read falls back to it once the local file is gone, a queue that built up while
+// the remote was down drains once it is reachable, and operations given up on are replayed by
+// the next mirror started for the same remote.
+
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use warp_drive::config::HttpConfig;
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::metrics;
+use warp_drive::server::{app, http_server};
+use warp_drive::storage::Storage;
+use warp_drive::storage::local_store::LocalXFSBinaryStore;
+use warp_drive::storage::mirrored_store::{Mirror, MirrorConfig, MirroredStore};
+use warp_drive::storage::remote_s3_store::{RemoteS3Config, RemoteS3Store};
+
+mod common;
+use common::unique;
+
+const ACCESS_KEY: &str = "mirror-test-access";
+const SECRET_KEY: &str = "mirror-test-secret";
+const USER: &str = "mirror_user";
+
+fn setup() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Serve the app on `port`; the returned handle stops it. The workers run on threads of their
+/// own, so the test may block on the stores once they are up.
+async fn start_fake_s3(port: u16) -> actix_web::dev::ServerHandle {
+    let config = HttpConfig { host: "127.0.0.1".to_string(), port, workers: Some(1), ..HttpConfig::default() };
+    let server = http_server(app, &config).unwrap();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+    handle
+}
+
+fn remote_config(port: u16, bucket: &str) -> RemoteS3Config {
+    RemoteS3Config {
+        endpoint: format!("http://127.0.0.1:{}/s3", port),
+        bucket: bucket.to_string(),
+        access_key: ACCESS_KEY.to_string(),
+        secret_key: SECRET_KEY.to_string(),
+        region: "us-east-1".to_string(),
+        timeout: Duration::from_secs(5),
+    }
+}
+
+fn chunk(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(seed).wrapping_add(seed)).collect()
+}
+
+/// A mirrored store over a fresh local store, with the remote store it copies to
+fn mirrored(port: u16, remote_bucket: &str, config: MirrorConfig) -> (MirroredStore, Arc<Mirror>, Arc<RemoteS3Store>) {
+    let remote = Arc::new(RemoteS3Store::new(remote_config(port, remote_bucket)));
+    let mirror = Mirror::start(Arc::clone(&remote), config);
+    (MirroredStore::new(LocalXFSBinaryStore::new(), Arc::clone(&remote), Arc::clone(&mirror)), mirror, remote)
+}
+
+#[actix_web::test]
+async fn test_write_through_and_delete() {
+    setup();
+    let port = free_port();
+    let server = start_fake_s3(port).await;
+    let remote_bucket = unique("backup");
+    let (store, mirror, remote) = mirrored(port, &remote_bucket, MirrorConfig::default());
+    remote.create_bucket().unwrap();
+    let bucket = unique("through");
+
+    let chunks: Vec<Vec<u8>> = (1..=5u8).map(|i| chunk(i, 1000 + i as usize * 300)).collect();
+    let extents: Vec<(u64, u64)> = chunks.iter().map(|c| store.write(USER, &bucket, c).unwrap()).collect();
+    assert!(mirror.wait_idle(Duration::from_secs(10)), "{:?}", mirror.status());
+    let status = mirror.status();
+    assert_eq!(status.mirrored_writes, 5);
+    assert_eq!(status.failed, 0, "{:?}", status.last_error);
+
+    // A store that never saw the writes finds every chunk by listing the remote
+    let fresh = RemoteS3Store::new(remote_config(port, &remote_bucket));
+    assert_eq!(fresh.bucket_len(USER, &bucket).unwrap(), store.bucket_len(USER, &bucket).unwrap());
+    for (data, &(offset, size)) in chunks.iter().zip(&extents) {
+        assert_eq!(&fresh.read(USER, &bucket, offset, size).unwrap(), data);
+    }
+    // Ranges inside a chunk and across two
+    let (offset, _) = extents[1];
+    assert_eq!(fresh.read(USER, &bucket, offset + 10, 20).unwrap(), chunks[1][10..30].to_vec());
+    let (second_end, third) = (extents[1].0 + extents[1].1, &chunks[2]);
+    let across = fresh.read(USER, &bucket, second_end - 5, 10).unwrap();
+    assert_eq!(&across[..5], &chunks[1][chunks[1].len() - 5..]);
+    assert_eq!(&across[5..], &third[..5]);
+
+    // Freed ranges are deleted remotely after the copies
+    store.delete(USER, &bucket, &[extents[0]]).unwrap();
+    assert!(mirror.wait_idle(Duration::from_secs(10)));
+    let fresh = RemoteS3Store::new(remote_config(port, &remote_bucket));
+    assert!(fresh.read(USER, &bucket, extents[0].0, extents[0].1).is_err());
+    assert_eq!(&fresh.read(USER, &bucket, extents[4].0, extents[4].1).unwrap(), &chunks[4]);
+
+    // The remote also works as a store of its own, appending after what it holds
+    let other = unique("direct");
+    let (first, second) = (chunk(7, 64), chunk(9, 32));
+    assert_eq!(fresh.write(USER, &other, &first).unwrap(), (0, 64));
+    assert_eq!(fresh.write(USER, &other, &second).unwrap(), (64, 32));
+    assert_eq!(fresh.read(USER, &other, 60, 8).unwrap(), [&first[60..], &second[..4]].concat());
+
+    server.stop(false).await;
+}
+
+#[actix_web::test]
+async fn test_read_falls_back_to_remote() {
+    setup();
+    let port = free_port();
+    let server = start_fake_s3(port).await;
+    let (store, mirror, remote) = mirrored(port, &unique("backup"), MirrorConfig::default());
+    remote.create_bucket().unwrap();
+    let bucket = unique("fallback");
+
+    let data = chunk(3, 4096);
+    let (offset, size) = store.write(USER, &bucket, &data).unwrap();
+    assert!(mirror.wait_idle(Duration::from_secs(10)));
+
+    let (path, _) = store.backing_file(USER, &bucket, offset).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(store.backing_file(USER, &bucket, offset).is_none());
+
+    let fallbacks = metrics::counter_value("warpdrive_mirror_fallback_reads_total", &[]);
+    assert_eq!(store.read(USER, &bucket, offset, size).unwrap(), data);
+    assert_eq!(store.read(USER, &bucket, offset + 100, 50).unwrap(), data[100..150].to_vec());
+    assert_eq!(metrics::counter_value("warpdrive_mirror_fallback_reads_total", &[]), fallbacks + 2);
+
+    server.stop(false).await;
+}
+
+#[actix_web::test]
+async fn test_lag_drains_once_remote_is_reachable() {
+    setup();
+    // Nothing listens on the port until the chunks are queued
+    let port = free_port();
+    let config = MirrorConfig { queue_capacity: 16, max_attempts: 50, retry_backoff: Duration::from_millis(20) };
+    let remote_bucket = unique("backup");
+    let (store, mirror, remote) = mirrored(port, &remote_bucket, config);
+    let bucket = unique("lag");
+
+    let chunks: Vec<Vec<u8>> = (1..=4u8).map(|i| chunk(i, 512)).collect();
+    let extents: Vec<(u64, u64)> = chunks.iter().map(|c| store.write(USER, &bucket, c).unwrap()).collect();
+    std::thread::sleep(Duration::from_millis(100));
+    let status = mirror.status();
+    assert_eq!((status.queue_depth, status.mirrored_writes), (4, 0));
+    assert!(status.lag_seconds >= 0.1, "{:?}", status);
+    assert!(status.retries > 0);
+
+    let server = start_fake_s3(port).await;
+    remote.create_bucket().unwrap();
+    assert!(mirror.wait_idle(Duration::from_secs(20)), "{:?}", mirror.status());
+    let status = mirror.status();
+    assert_eq!((status.queue_depth, status.mirrored_writes, status.failed), (0, 4, 0));
+    assert_eq!(status.lag_seconds, 0.0);
+
+    let fresh = RemoteS3Store::new(remote_config(port, &remote_bucket));
+    for (data, &(offset, size)) in chunks.iter().zip(&extents) {
+        assert_eq!(&fresh.read(USER, &bucket, offset, size).unwrap(), data);
+    }
+    server.stop(false).await;
+}
+
+#[actix_web::test]
+async fn test_given_up_operations_are_replayed_at_start() {
+    setup();
+    // Nothing listens on the port, so every operation is given up at once
+    let port = free_port();
+    let config = MirrorConfig { queue_capacity: 16, max_attempts: 1, retry_backoff: Duration::from_millis(1) };
+    let remote_bucket = unique("backup");
+    let (store, mirror, remote) = mirrored(port, &remote_bucket, config);
+    let bucket = unique("restart");
+
+    let chunks: Vec<Vec<u8>> = (1..=3u8).map(|i| chunk(i, 700)).collect();
+    let extents: Vec<(u64, u64)> = chunks.iter().map(|c| store.write(USER, &bucket, c).unwrap()).collect();
+    store.delete(USER, &bucket, &[extents[2]]).unwrap();
+    assert!(mirror.wait_idle(Duration::from_secs(10)), "{:?}", mirror.status());
+    assert_eq!(mirror.status().failed, 4);
+    let journal = SQLiteMetadataStore::new().list_mirror_pending(&remote.location()).unwrap();
+    let kinds: Vec<(&str, u32)> = journal.iter().map(|row| (row.kind.as_str(), row.attempts)).collect();
+    assert_eq!(kinds, vec![("put", 1), ("put", 1), ("put", 1), ("delete", 1)]);
+    assert!(journal.iter().all(|row| row.last_error.is_some()));
+
+    // The next start with the remote reachable copies the writes, then applies the delete
+    let server = start_fake_s3(port).await;
+    remote.create_bucket().unwrap();
+    let (_, restarted, _) = mirrored(port, &remote_bucket, MirrorConfig::default());
+    assert!(restarted.wait_idle(Duration::from_secs(10)), "{:?}", restarted.status());
+    let status = restarted.status();
+    assert_eq!((status.mirrored_writes, status.failed), (3, 0), "{:?}", status.last_error);
+    assert!(SQLiteMetadataStore::new().list_mirror_pending(&remote.location()).unwrap().is_empty());
+
+    let fresh = RemoteS3Store::new(remote_config(port, &remote_bucket));
+    for (data, &(offset, size)) in chunks.iter().zip(&extents).take(2) {
+        assert_eq!(&fresh.read(USER, &bucket, offset, size).unwrap(), data);
+    }
+    assert!(fresh.read(USER, &bucket, extents[2].0, extents[2].1).is_err());
+    server.stop(false).await;
+}
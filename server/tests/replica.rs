@@ -0,0 +1,116 @@
+// SERVER_ROLE=replica, run as the real binary against a copy of this test's database and
+// storage whose metadata directory is made read-only: reads work, writes get 501.
+
+use actix_web::{test, App, http::StatusCode};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use warp_drive::api::put;
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+
+mod common;
+use common::{payload, unique};
+
+const USER: &str = "replica_user";
+
+fn copy_dir(from: &Path, to: &Path) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap().flatten() {
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            std::fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+fn set_mode(path: &Path, mode: u32) {
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+}
+
+/// Kills the server when the test ends, pass or fail
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+#[actix_web::test]
+async fn test_replica_serves_reads_and_refuses_writes() {
+    let scratch = common::isolate();
+    let app = test::init_service(App::new().service(put)).await;
+    let bucket = unique("replica");
+    let req = test::TestRequest::post().uri("/put/doc")
+        .insert_header(("user", USER)).insert_header(("bucket", bucket.clone()))
+        .set_payload(payload(&[b"from the primary"])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    // Snapshot: the database with its WAL folded in, and the bucket files
+    assert!(!SQLiteMetadataStore::new().wal_checkpoint().unwrap().busy);
+    let fixture = scratch.join("replica");
+    let metadata_dir = fixture.join("metadata");
+    std::fs::create_dir_all(&metadata_dir).unwrap();
+    std::fs::copy(scratch.join("metadata").join("metadata.sqlite"), metadata_dir.join("metadata.sqlite")).unwrap();
+    copy_dir(&scratch.join("storage"), &fixture.join("storage"));
+    std::fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("server_log.yaml"), fixture.join("server_log.yaml")).unwrap();
+    let snapshot = std::fs::read(metadata_dir.join("metadata.sqlite")).unwrap();
+    set_mode(&metadata_dir.join("metadata.sqlite"), 0o444);
+    set_mode(&metadata_dir, 0o555);
+
+    let port = free_port();
+    let _server = Server(Command::new(env!("CARGO_BIN_EXE_warp_drive"))
+        .current_dir(&fixture)
+        .env("SERVER_ROLE", "replica")
+        .env("SERVER_PORT", port.to_string())
+        .env("DB_FILE", metadata_dir.join("metadata.sqlite"))
+        .env("STORAGE_DIRECTORY", fixture.join("storage"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap());
+
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("http://127.0.0.1:{}{}", port, path);
+    let mut health = None;
+    for _ in 0..200 {
+        if let Ok(resp) = client.get(url("/healthz")).send().await {
+            health = Some(resp.json::<serde_json::Value>().await.unwrap());
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(health.expect("replica did not start")["role"], "replica");
+    let ready: serde_json::Value = client.get(url("/readyz")).send().await.unwrap().json().await.unwrap();
+    assert_eq!((ready["status"].as_str(), ready["read_only"].as_bool()), (Some("ready"), Some(true)), "{}", ready);
+
+    let native = |req: reqwest::RequestBuilder| req.header("user", USER).header("bucket", bucket.as_str());
+    let resp = native(client.get(url("/get/doc"))).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = resp.bytes().await.unwrap();
+    assert!(body.windows(16).any(|w| w == b"from the primary"));
+
+    let resp = native(client.post(url("/put/new"))).body(payload(&[b"x"])).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 501);
+    let error: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(error["error"], "NotImplemented");
+    let resp = native(client.delete(url("/delete/doc"))).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 501);
+
+    let resp = client.put(url(&format!("/s3/{}/new", bucket))).body("x").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 501);
+    assert!(resp.text().await.unwrap().contains("<Code>NotImplemented</Code>"));
+
+    // The snapshot is untouched (permissions do not stop root, the read-only connection does)
+    assert!(std::fs::read(metadata_dir.join("metadata.sqlite")).unwrap() == snapshot);
+    set_mode(&metadata_dir, 0o755);
+    set_mode(&metadata_dir.join("metadata.sqlite"), 0o644);
+}
@@ -0,0 +1,116 @@
+// Response field selection: a native GET with `X-Warp-Response-Fields: none` gets only the
+// transport headers and the body, a list gets exactly the metadata headers named, unknown
+// names are reported back, and S3 GetObject ignores the header in strict S3 compatibility
+// mode. Requests to S3 are signed with the admin access key.
+
+use actix_web::{test, web, App, http::StatusCode};
+use std::sync::Once;
+use warp_drive::api::native_routes;
+use warp_drive::s3::handlers::{s3_get_object_handler, s3_put_object_handler};
+use warp_drive::service::api_version::ApiVersion;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{AMZ_DATE, authorization_with_query, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const USER: &str = "fields_user";
+const ACCESS_KEY: &str = "fields-test-access";
+const SECRET_KEY: &str = "fields-test-secret";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+        // Native requests are not affected by it
+        std::env::set_var("S3_STRICT_COMPAT", "true");
+    });
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str) -> test::TestRequest {
+    req.uri(path)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method, path, "", UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+/// Names of the response headers, sorted
+fn header_names(resp: &actix_web::dev::ServiceResponse) -> Vec<String> {
+    let mut names: Vec<String> = resp.headers().keys().map(|name| name.as_str().to_string()).collect();
+    names.sort();
+    names
+}
+
+#[actix_web::test]
+async fn test_native_get_sends_selected_fields() {
+    setup();
+    let app = test::init_service(App::new().service(web::scope("/v2").app_data(ApiVersion::V2).configure(native_routes))).await;
+    let bucket = unique("fields");
+    let req = test::TestRequest::post()
+        .uri("/v2/put/doc")
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket.as_str()))
+        .insert_header(("x-warp-meta-color", "blue"))
+        .set_payload(payload_of(b"selected fields"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let get = |fields: Option<&str>| {
+        let req = test::TestRequest::get()
+            .uri("/v2/get/doc?format=raw")
+            .insert_header(("user", USER))
+            .insert_header(("bucket", bucket.as_str()));
+        match fields {
+            Some(fields) => req.insert_header(("x-warp-response-fields", fields)).to_request(),
+            None => req.to_request(),
+        }
+    };
+
+    // By default every metadata header is sent
+    let resp = test::call_service(&app, get(None)).await;
+    for name in ["etag", "x-warp-generation", "x-warp-size", "x-warp-meta-color", "x-warp-object-format"] {
+        assert!(resp.headers().contains_key(name), "{}", name);
+    }
+    assert_eq!(resp.headers().get("x-warp-size").unwrap(), "15");
+
+    let resp = test::call_service(&app, get(Some("none"))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header_names(&resp), vec!["content-type"]);
+    assert_eq!(test::read_body(resp).await, "selected fields".as_bytes());
+
+    let resp = test::call_service(&app, get(Some("etag,size"))).await;
+    assert_eq!(header_names(&resp), vec!["content-type", "etag", "x-warp-size"]);
+
+    // Unknown names are listed back; the known ones still apply
+    let resp = test::call_service(&app, get(Some("Generation, colour, sizes"))).await;
+    assert_eq!(header_names(&resp), vec!["content-type", "x-warp-generation", "x-warp-ignored-response-fields"]);
+    assert_eq!(resp.headers().get("x-warp-ignored-response-fields").unwrap(), "colour,sizes");
+}
+
+#[actix_web::test]
+async fn test_strict_s3_get_ignores_fields() {
+    setup();
+    let bucket = unique("fields-s3");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let path = format!("/s3/{}/doc", bucket);
+    let req = signed(test::TestRequest::put(), "PUT", &path)
+        .insert_header(("x-amz-meta-color", "blue"))
+        .set_payload(b"s3 body".to_vec())
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = signed(test::TestRequest::get(), "GET", &path).insert_header(("x-warp-response-fields", "none")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    for name in ["etag", "last-modified", "x-amz-meta-color", "content-length"] {
+        assert!(resp.headers().contains_key(name), "{}", name);
+    }
+    assert!(!resp.headers().contains_key("x-warp-ignored-response-fields"));
+    assert_eq!(test::read_body(resp).await, "s3 body".as_bytes());
+}
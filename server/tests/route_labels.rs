@@ -0,0 +1,59 @@
+// Request metrics are labeled by route template, never by path: requests to many keys on
+// the full app leave one series per template and status, and no series names a key.
+
+use actix_web::{test, http::Method, http::StatusCode};
+use std::collections::HashSet;
+use warp_drive::metrics;
+use warp_drive::server::app;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "route_label_user";
+
+fn native(method: Method, uri: &str, bucket: &str) -> test::TestRequest {
+    test::TestRequest::default().method(method).uri(uri)
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket))
+}
+
+#[actix_web::test]
+async fn test_request_series_are_per_route_template() {
+    common::isolate();
+    let app = test::init_service(app()).await;
+    let bucket = unique("route-labels");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+
+    let keys: Vec<String> = (0..5).map(|i| format!("label-key-{}/part-{}", i, i)).collect();
+    for key in &keys {
+        let req = native(Method::POST, &format!("/put/{}", key), &bucket).set_payload(payload_of(b"data"));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        let req = native(Method::GET, &format!("/v1/get/{}", key), &bucket);
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        let req = native(Method::GET, &format!("/get/{}-missing", key), &bucket);
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    let text = metrics::render();
+    let series: Vec<&str> = text.lines()
+        .filter(|line| line.starts_with("warpdrive_http_requests_total{"))
+        .collect();
+    assert!(series.iter().all(|line| !line.contains("label-key")), "{:#?}", series);
+    let labeled = |route: &str, status: &str| format!(
+        "warpdrive_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}}",
+        if route.starts_with("/put") { "POST" } else { "GET" }, route, status,
+    );
+    for (route, status) in [("/put/{key}", "200"), ("/v1/get/{key}", "200"), ("/get/{key}", "404")] {
+        let prefix = labeled(route, status);
+        let matching: Vec<&&str> = series.iter().filter(|line| line.starts_with(&prefix)).collect();
+        assert_eq!(matching.len(), 1, "{} in {:#?}", prefix, series);
+        let count: u64 = matching[0].rsplit(' ').next().unwrap().parse().unwrap();
+        assert!(count >= keys.len() as u64, "{}", matching[0]);
+    }
+    // One series per label set, as rendered
+    let unique_series: HashSet<&str> = series.iter().map(|line| line.rsplit_once(' ').unwrap().0).collect();
+    assert_eq!(unique_series.len(), series.len());
+    assert!(text.lines().filter(|line| line.starts_with("warpdrive_http_request_duration_seconds_count{"))
+        .all(|line| !line.contains("label-key")));
+}
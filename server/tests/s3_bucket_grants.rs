@@ -0,0 +1,120 @@
+// Per-credential bucket grants on the S3 API. A stand-in for Vitality Console answers
+// s3-credentials with one registered bucket; every request is authorized for the bucket of its
+// routed path, and a copy source is authorized on its own.
+
+use actix_web::{test, http::{Method, StatusCode}};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::Once;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization_with_query, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "grant-test-access";
+const SECRET_KEY: &str = "grant-test-secret";
+const USER: &str = "grant_user";
+
+static SETUP: Once = Once::new();
+
+/// Serve `POST /api/auth/s3-credentials` with a bundle registering only `bucket`
+fn start_console(bucket: String) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            reader.read_exact(&mut vec![0; content_length]).unwrap();
+            let body = serde_json::json!({
+                "owner_id": USER,
+                "secret_key": SECRET_KEY,
+                "registered_buckets": [bucket],
+            }).to_string();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(), body).unwrap();
+        }
+    });
+    url
+}
+
+fn signed(method: Method, path: &str, query: &str) -> test::TestRequest {
+    let uri = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.trim_end_matches('=')) };
+    test::TestRequest::default().method(method.clone()).uri(&uri)
+        .insert_header(("Authorization", authorization_with_query(ACCESS_KEY, SECRET_KEY, method.as_str(), path, query, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_credential_only_reaches_its_buckets() {
+    let (granted, other) = (unique("granted"), unique("other"));
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::remove_var("WARPDRIVE_ADMIN_ACCESS_KEY");
+        std::env::set_var("VITALITY_CONSOLE_URL", start_console(granted.clone()));
+        std::env::set_var("WARPDRIVE_SERVICE_SECRET", "grant-test-service");
+    });
+    let app = test::init_service(warp_drive::server::app()).await;
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&granted).unwrap();
+    db.create_bucket(&other).unwrap();
+
+    let object = format!("/s3/{}/object", granted);
+    let resp = test::call_service(&app, signed(Method::PUT, &object, "").set_payload("granted data").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, signed(Method::GET, &object, "").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Every verb on the other bucket is refused with an S3 error document
+    let other_object = format!("/s3/{}/object", other);
+    let other_bucket = format!("/s3/{}", other);
+    let denied = [
+        (Method::PUT, other_object.as_str(), ""),
+        (Method::GET, other_object.as_str(), ""),
+        (Method::HEAD, other_object.as_str(), ""),
+        (Method::DELETE, other_object.as_str(), ""),
+        (Method::POST, other_object.as_str(), "uploads="),
+        (Method::GET, other_bucket.as_str(), ""),
+        (Method::HEAD, other_bucket.as_str(), ""),
+        (Method::PUT, other_bucket.as_str(), "cors="),
+        (Method::DELETE, other_bucket.as_str(), ""),
+        (Method::POST, other_bucket.as_str(), "delete="),
+    ];
+    for (method, path, query) in denied {
+        let resp = test::call_service(&app, signed(method.clone(), path, query).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{} {}?{}", method, path, query);
+        if method != Method::HEAD {
+            let body = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+            assert!(body.contains("<Code>AccessDenied</Code>"), "{} {}: {}", method, path, body);
+        }
+    }
+
+    // ListBuckets only shows the granted bucket
+    let resp = test::call_service(&app, signed(Method::GET, "/s3", "").to_request()).await;
+    let body = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(body.contains(&granted) && !body.contains(&other), "{}", body);
+
+    // The routed path decides the bucket: a bucket header is ignored, and a copy source in
+    // another bucket is authorized on its own
+    let resp = test::call_service(&app, signed(Method::PUT, &object, "")
+        .insert_header(("bucket", other.clone())).set_payload("still granted").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(db.bucket_object_stats(&other).unwrap().0, 0);
+    let resp = test::call_service(&app, signed(Method::PUT, &format!("/s3/{}/copy", granted), "")
+        .insert_header(("x-amz-copy-source", format!("/{}/object", other))).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,210 @@
+// S3 compatibility against the official AWS SDK. The server runs in-process on an ephemeral
+// port, serving the same app as `main`; the SDK signs with the admin static credentials and
+// uses path-style addressing. Run with: cargo test --features s3-compat-tests
+#![cfg(feature = "s3-compat-tests")]
+
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use std::sync::Once;
+use warp_drive::server;
+
+mod common;
+use common::unique;
+
+const ACCESS_KEY: &str = "compat-test-access";
+const SECRET_KEY: &str = "compat-test-secret";
+
+static SETUP: Once = Once::new();
+
+/// Serve the app on an ephemeral port and return its endpoint.
+fn serve() -> String {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+        warp_drive::startup::initialize().unwrap();
+    });
+    let server = actix_web::HttpServer::new(server::app)
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    format!("http://{}", addr)
+}
+
+fn sdk_client(endpoint: &str, secret_key: &str) -> Client {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .endpoint_url(endpoint)
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new(ACCESS_KEY, secret_key, None, None, "static"))
+        .force_path_style(true)
+        .build();
+    Client::from_conf(config)
+}
+
+fn start() -> Client {
+    sdk_client(&serve(), SECRET_KEY)
+}
+
+async fn create_bucket(client: &Client, prefix: &str) -> String {
+    let bucket = unique(prefix);
+    client.create_bucket().bucket(&bucket).send().await.unwrap();
+    bucket
+}
+
+async fn body(client: &Client, bucket: &str, key: &str) -> Vec<u8> {
+    let object = client.get_object().bucket(bucket).key(key).send().await.unwrap();
+    object.body.collect().await.unwrap().into_bytes().to_vec()
+}
+
+fn md5_etag(data: &[u8]) -> String {
+    format!("\"{:x}\"", md5::compute(data))
+}
+
+#[actix_web::test]
+async fn test_put_get_head_delete_round_trip() {
+    let client = start();
+    let bucket = create_bucket(&client, "compat").await;
+    let data = b"hello from the sdk".to_vec();
+
+    let put = client.put_object().bucket(&bucket).key("dir/hello.txt")
+        .content_type("text/plain")
+        .body(ByteStream::from(data.clone()))
+        .send().await.unwrap();
+    assert_eq!(put.e_tag(), Some(md5_etag(&data).as_str()));
+
+    let object = client.get_object().bucket(&bucket).key("dir/hello.txt").send().await.unwrap();
+    assert_eq!(object.e_tag(), Some(md5_etag(&data).as_str()));
+    assert_eq!(object.content_length(), Some(data.len() as i64));
+    assert_eq!(object.body.collect().await.unwrap().into_bytes().as_ref(), data.as_slice());
+
+    let head = client.head_object().bucket(&bucket).key("dir/hello.txt").send().await.unwrap();
+    assert_eq!(head.e_tag(), Some(md5_etag(&data).as_str()));
+    assert_eq!(head.content_length(), Some(data.len() as i64));
+    assert!(head.last_modified().is_some());
+
+    let ranged = client.get_object().bucket(&bucket).key("dir/hello.txt").range("bytes=6-9").send().await.unwrap();
+    assert_eq!(ranged.body.collect().await.unwrap().into_bytes().as_ref(), b"from");
+
+    client.delete_object().bucket(&bucket).key("dir/hello.txt").send().await.unwrap();
+    let err = client.get_object().bucket(&bucket).key("dir/hello.txt").send().await.unwrap_err();
+    assert!(err.into_service_error().is_no_such_key());
+}
+
+#[actix_web::test]
+async fn test_list_objects_v2_with_prefix_and_delimiter() {
+    let client = start();
+    let bucket = create_bucket(&client, "compat-list").await;
+    for key in ["a/1", "a/2", "b/1", "top"] {
+        client.put_object().bucket(&bucket).key(key).body(ByteStream::from_static(b"x")).send().await.unwrap();
+    }
+
+    let all = client.list_objects_v2().bucket(&bucket).send().await.unwrap();
+    let keys: Vec<&str> = all.contents().iter().filter_map(|o| o.key()).collect();
+    assert_eq!(keys, ["a/1", "a/2", "b/1", "top"]);
+    assert_eq!(all.contents()[0].e_tag(), Some(md5_etag(b"x").as_str()));
+    assert_eq!(all.contents()[0].size(), Some(1));
+
+    let grouped = client.list_objects_v2().bucket(&bucket).delimiter("/").send().await.unwrap();
+    let prefixes: Vec<&str> = grouped.common_prefixes().iter().filter_map(|p| p.prefix()).collect();
+    assert_eq!(prefixes, ["a/", "b/"]);
+    let keys: Vec<&str> = grouped.contents().iter().filter_map(|o| o.key()).collect();
+    assert_eq!(keys, ["top"]);
+
+    let page = client.list_objects_v2().bucket(&bucket).prefix("a/").max_keys(1).send().await.unwrap();
+    assert_eq!(page.contents().len(), 1);
+    assert_eq!(page.is_truncated(), Some(true));
+    let rest = client.list_objects_v2().bucket(&bucket).prefix("a/")
+        .continuation_token(page.next_continuation_token().unwrap())
+        .send().await.unwrap();
+    let keys: Vec<&str> = rest.contents().iter().filter_map(|o| o.key()).collect();
+    assert_eq!(keys, ["a/2"]);
+
+    let buckets = client.list_buckets().send().await.unwrap();
+    assert!(buckets.buckets().iter().any(|b| b.name() == Some(bucket.as_str())));
+}
+
+#[actix_web::test]
+async fn test_copy_object() {
+    let client = start();
+    let bucket = create_bucket(&client, "compat-copy").await;
+    let data = b"copy me".to_vec();
+    client.put_object().bucket(&bucket).key("source").body(ByteStream::from(data.clone())).send().await.unwrap();
+
+    let copied = client.copy_object().bucket(&bucket).key("target")
+        .copy_source(format!("{}/source", bucket))
+        .send().await.unwrap();
+    let etag = copied.copy_object_result().and_then(|r| r.e_tag()).map(str::to_string);
+    assert_eq!(etag, Some(md5_etag(&data)));
+    assert_eq!(body(&client, &bucket, "target").await, data);
+    assert_eq!(body(&client, &bucket, "source").await, data);
+}
+
+#[actix_web::test]
+async fn test_multipart_upload() {
+    let client = start();
+    let bucket = create_bucket(&client, "compat-mpu").await;
+    let first = vec![b'a'; 5 * 1024 * 1024];
+    let second = b"tail".to_vec();
+
+    let created = client.create_multipart_upload().bucket(&bucket).key("big").send().await.unwrap();
+    let upload_id = created.upload_id().unwrap().to_string();
+    let mut parts = Vec::new();
+    for (number, data) in [(1, &first), (2, &second)] {
+        let part = client.upload_part().bucket(&bucket).key("big").upload_id(&upload_id)
+            .part_number(number)
+            .body(ByteStream::from(data.clone()))
+            .send().await.unwrap();
+        assert_eq!(part.e_tag(), Some(md5_etag(data).as_str()));
+        parts.push(CompletedPart::builder().part_number(number).e_tag(part.e_tag().unwrap()).build());
+    }
+
+    let listed = client.list_parts().bucket(&bucket).key("big").upload_id(&upload_id).send().await.unwrap();
+    assert_eq!(listed.parts().len(), 2);
+
+    let completed = client.complete_multipart_upload().bucket(&bucket).key("big").upload_id(&upload_id)
+        .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+        .send().await.unwrap();
+    // Multipart ETags are the MD5 of the part digests, suffixed with the part count
+    let mut digests = md5::compute(&first).0.to_vec();
+    digests.extend_from_slice(&md5::compute(&second).0);
+    let expected = format!("\"{:x}-2\"", md5::compute(&digests));
+    assert_eq!(completed.e_tag(), Some(expected.as_str()));
+
+    let mut data = first;
+    data.extend_from_slice(&second);
+    assert_eq!(body(&client, &bucket, "big").await, data);
+
+    // An aborted upload is gone
+    let created = client.create_multipart_upload().bucket(&bucket).key("aborted").send().await.unwrap();
+    let upload_id = created.upload_id().unwrap();
+    client.abort_multipart_upload().bucket(&bucket).key("aborted").upload_id(upload_id).send().await.unwrap();
+    let err = client.list_parts().bucket(&bucket).key("aborted").upload_id(upload_id).send().await.unwrap_err();
+    assert_eq!(err.into_service_error().meta().code(), Some("NoSuchUpload"));
+}
+
+#[actix_web::test]
+async fn test_errors_map_to_sdk_error_codes() {
+    let endpoint = serve();
+    let client = sdk_client(&endpoint, SECRET_KEY);
+    let bucket = create_bucket(&client, "compat-err").await;
+
+    let err = client.get_object().bucket(&bucket).key("missing").send().await.unwrap_err();
+    assert!(err.into_service_error().is_no_such_key());
+
+    let err = client.head_object().bucket(&bucket).key("missing").send().await.unwrap_err();
+    assert!(err.into_service_error().is_not_found());
+
+    let err = client.list_objects_v2().bucket(unique("compat-nobucket")).send().await.unwrap_err();
+    assert!(err.into_service_error().is_no_such_bucket());
+
+    let err = client.head_bucket().bucket(unique("compat-nobucket")).send().await.unwrap_err();
+    assert!(err.into_service_error().is_not_found());
+
+    let err = sdk_client(&endpoint, "wrong-secret").get_object().bucket(&bucket).key("missing").send().await.unwrap_err();
+    assert_eq!(err.into_service_error().meta().code(), Some("SignatureDoesNotMatch"));
+}
@@ -1,4 +1,6 @@
 // S3-compatible API integration tests
+// ENV_LOCK is held for the whole test on purpose so env mutations never interleave.
+#![allow(clippy::await_holding_lock)]
 use actix_web::{test, web, App, http::StatusCode};
 use std::sync::Mutex;
 use warp_drive::s3::handlers::{
@@ -13,8 +15,11 @@ use warp_drive::s3::handlers::{
 // them so parallel test threads don't race on VITALITY_CONSOLE_URL / WARPDRIVE_SERVICE_SECRET.
 static ENV_LOCK: Mutex<()> = Mutex::new(());
 
+mod common;
+
 /// Ensure Console auth is not configured so S3 requests return 401 (Warpdrive only supports Console auth).
 fn ensure_no_console_config() {
+    common::isolate();
     std::env::remove_var("VITALITY_CONSOLE_URL");
     std::env::remove_var("WARPDRIVE_SERVICE_SECRET");
 }
@@ -250,6 +255,7 @@ async fn test_s3_curl_simulation() {
 #[actix_web::test]
 async fn test_s3_console_url_without_service_secret_returns_401() {
     let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
     std::env::set_var("VITALITY_CONSOLE_URL", "http://localhost:9999");
     std::env::remove_var("WARPDRIVE_SERVICE_SECRET");
 
@@ -275,6 +281,7 @@ async fn test_s3_console_url_without_service_secret_returns_401() {
 #[actix_web::test]
 async fn test_s3_console_auth_unreachable_returns_401() {
     let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
     std::env::set_var("VITALITY_CONSOLE_URL", "http://127.0.0.1:0");
     std::env::set_var("WARPDRIVE_SERVICE_SECRET", "test-secret");
 
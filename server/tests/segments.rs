@@ -0,0 +1,85 @@
+// Segmented bucket files: with a tiny SEGMENT_MAX_BYTES the bucket rolls over to new segment
+// files, reads follow each chunk to its segment, and a sealed segment left without live data
+// is unlinked by the deletion worker. Storage and metadata live in the binary's scratch
+// directory.
+
+use actix_web::{test, App, http::StatusCode};
+use std::path::PathBuf;
+use std::sync::Once;
+use warp_drive::api::{put, get, delete};
+use warp_drive::service::deletion_worker::DeletionWorker;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{payload, unique};
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("SEGMENT_MAX_BYTES", "4096");
+    });
+}
+
+fn stored_files(body: &[u8]) -> Vec<Vec<u8>> {
+    let list = root_as_file_data_list(body).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+fn segment_file(bucket: &str, name: &str) -> PathBuf {
+    common::isolate().join("storage").join("segment_user").join(format!("{}{}", bucket, name))
+}
+
+#[actix_web::test]
+async fn test_rollover_reads_and_segment_release() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(delete)).await;
+    let bucket = unique("segments");
+    let native = |req: test::TestRequest, uri: &str| req.uri(uri).insert_header(("user", "segment_user")).insert_header(("bucket", bucket.clone()));
+    let (head, a, b, c, tail) = (vec![1u8; 1000], vec![2u8; 3000], vec![3u8; 3000], vec![4u8; 3000], vec![5u8; 100]);
+
+    // head and a fill segment 0, b cannot fit and starts segment 1, c starts segment 2
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/head").set_payload(payload(&[&head])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/spread").set_payload(payload(&[&a, &b, &c])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/tail").set_payload(payload(&[&tail])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let (seg0, seg1, seg2) = (segment_file(&bucket, ".bin"), segment_file(&bucket, ".000001.bin"), segment_file(&bucket, ".000002.bin"));
+    assert_eq!(std::fs::metadata(&seg0).unwrap().len(), 4000);
+    assert_eq!(std::fs::metadata(&seg1).unwrap().len(), 3000);
+    assert_eq!(std::fs::metadata(&seg2).unwrap().len(), 3100);
+    let spread = MetadataService::new("segment_user").unwrap().get_object_full(&bucket, "spread").unwrap();
+    let segments: Vec<u32> = spread.chunks.iter().map(|c| c.segment).collect();
+    assert_eq!(segments, vec![0, 1, 2]);
+    assert_eq!(spread.chunks[1].offset, 0);
+
+    // One object read back across all three segments
+    let resp = test::call_service(&app, native(test::TestRequest::get(), "/get/spread").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(stored_files(&test::read_body(resp).await), vec![a, b, c]);
+
+    // Freeing spread empties segment 1; segment 0 still holds head and segment 2 is active
+    let resp = test::call_service(&app, native(test::TestRequest::delete(), "/delete/spread").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(seg1.exists(), "segments are only released once the deletion worker frees them");
+    DeletionWorker::new().process_deletions().await.unwrap();
+    assert!(!seg1.exists(), "segment 1 should have been unlinked");
+    assert!(seg0.exists());
+    assert!(seg2.exists());
+
+    for (key, data) in [("head", head), ("tail", tail)] {
+        let resp = test::call_service(&app, native(test::TestRequest::get(), &format!("/get/{}", key)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(stored_files(&test::read_body(resp).await), vec![data]);
+    }
+
+    // New writes keep going to the active segment
+    let resp = test::call_service(&app, native(test::TestRequest::post(), "/put/more").set_payload(payload(&[&[6u8; 500]])).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(std::fs::metadata(&seg2).unwrap().len(), 3600);
+    assert!(!seg1.exists());
+}
@@ -0,0 +1,37 @@
+// The self-test scenario run in-process against scratch stores, and a run whose storage
+// directory cannot be created. Both point the process's environment at their directory, so
+// they serialize on ENV_LOCK.
+#![allow(clippy::await_holding_lock)]
+
+use std::sync::Mutex;
+use warp_drive::service::self_test;
+
+mod common;
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+#[actix_web::test]
+async fn test_self_test_passes_on_fresh_stores() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = common::isolate().join("self-test");
+    let report = self_test::run(&dir).await;
+    assert!(report.passed, "{:?}", report);
+    let steps: Vec<&str> = report.steps.iter().map(|s| s.step).collect();
+    assert_eq!(steps, ["storage", "metadata", "bucket", "put", "get", "append", "rename", "delete",
+        "deletion worker", "deletion queue drained"]);
+    assert!(dir.join("metadata.sqlite").exists());
+}
+
+#[actix_web::test]
+async fn test_self_test_reports_unwritable_storage() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // A directory can't be created below a regular file
+    let file = common::isolate().join("not-a-directory");
+    std::fs::write(&file, b"").unwrap();
+    let report = self_test::run(&file.join("self-test")).await;
+    assert!(!report.passed);
+    assert_eq!(report.steps.len(), 1);
+    let failed = report.failed_step().unwrap();
+    assert_eq!(failed.step, "storage");
+    assert!(failed.error.as_deref().unwrap().contains("cannot create storage directory"), "{:?}", failed);
+}
@@ -0,0 +1,59 @@
+// Size distributions: native puts and appends of known sizes land in the expected buckets of
+// the object size, chunk count and chunk size histograms in the /metrics scrape. One test,
+// since the histograms are process-wide.
+
+use warp_drive::metrics;
+use warp_drive::service::object_service::ObjectService;
+use warp_drive::service::user_context::UserContext;
+
+mod common;
+use common::{payload, payload_of, unique};
+
+const USER: &str = "size_stats_user";
+const KIB: usize = 1024;
+
+/// `name_suffix{api="native",write,le}` (or without `le`) in a scrape of `/metrics`
+async fn sample(name: &str, write: &str, le: Option<&str>) -> f64 {
+    let labels = match le {
+        Some(le) => format!("{{api=\"native\",write=\"{}\",le=\"{}\"}}", write, le),
+        None => format!("{{api=\"native\",write=\"{}\"}}", write),
+    };
+    let body = actix_web::body::to_bytes(metrics::metrics_handler().await.into_body()).await.unwrap();
+    String::from_utf8_lossy(&body).lines()
+        .filter_map(|line| line.strip_prefix(name))
+        .filter_map(|rest| rest.strip_prefix(labels.as_str()))
+        .map(|value| value.trim().parse().unwrap())
+        .next()
+        .unwrap_or(0.0)
+}
+
+#[actix_web::test]
+async fn test_writes_land_in_size_buckets() {
+    common::isolate();
+    let context = UserContext::with_bucket(USER.to_string(), unique("sizes"));
+    let objects = ObjectService::new();
+
+    // 2 KiB in one chunk, and 100 KiB in two of 50 KiB
+    objects.put(&context, "small", async { Ok(payload_of(&[1; 2 * KIB]).into()) }, None).await.unwrap();
+    objects.put(&context, "large", async { Ok(payload(&[&[2; 50 * KIB], &[3; 50 * KIB]]).into()) }, None).await.unwrap();
+    objects.append(&context, "small", async { Ok(payload_of(&[4; 300 * KIB]).into()) }, None).await.unwrap();
+
+    let object_size = |le| sample("warpdrive_object_size_bytes_bucket", "put", Some(le));
+    assert_eq!(object_size("1024").await, 0.0);
+    assert_eq!(object_size("4096").await, 1.0);
+    assert_eq!(object_size("65536").await, 1.0);
+    assert_eq!(object_size("262144").await, 2.0);
+    assert_eq!(sample("warpdrive_object_size_bytes_sum", "put", None).await, (102 * KIB) as f64);
+
+    let chunks = |le| sample("warpdrive_object_chunks_bucket", "put", Some(le));
+    assert_eq!((chunks("1").await, chunks("2").await), (1.0, 2.0));
+
+    let chunk_size = |le| sample("warpdrive_chunk_size_bytes_bucket", "put", Some(le));
+    assert_eq!((chunk_size("4096").await, chunk_size("65536").await), (1.0, 3.0));
+    assert_eq!(sample("warpdrive_chunk_size_bytes_count", "put", None).await, 3.0);
+
+    // The append is counted on its own, by the data it added
+    let appended = |le| sample("warpdrive_object_size_bytes_bucket", "append", Some(le));
+    assert_eq!((appended("262144").await, appended("1048576").await), (0.0, 1.0));
+    assert_eq!(sample("warpdrive_object_chunks_count", "append", None).await, 1.0);
+}
@@ -0,0 +1,146 @@
+// Startup initialization reads STORAGE_DIRECTORY, so these tests run in their own binary and
+// serialize on ENV_LOCK.
+
+use std::sync::Mutex;
+use warp_drive::metadata::sqlite_store::{self, SQLiteMetadataStore};
+use warp_drive::service::change_log;
+use warp_drive::service::instance_identity::{INSTANCE_FILE, INSTANCE_ID};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::startup::{self, HEALTH_BUCKET, SYSTEM_USER};
+use warp_drive::storage::Storage;
+use warp_drive::storage::local_store::LocalXFSBinaryStore;
+use warp_drive::util::serializer::encode_extents;
+
+mod common;
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn scratch(name: &str) -> std::path::PathBuf {
+    let dir = common::isolate().join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_unwritable_storage_directory_fails_initialization() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // A path under a regular file can't be created, whatever the process's privileges
+    let blocker = scratch("blocked").join("not-a-directory");
+    std::fs::write(&blocker, b"").unwrap();
+    std::env::set_var("STORAGE_DIRECTORY", blocker.join("storage"));
+
+    let err = startup::initialize().unwrap_err();
+    assert_eq!(err.step, "storage");
+    assert!(err.to_string().contains("not-a-directory"), "{}", err);
+    std::env::set_var("STORAGE_DIRECTORY", common::isolate().join("storage"));
+}
+
+#[test]
+fn test_initialization_prepares_storage_and_health_bucket() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let root = scratch("ok").join("storage");
+    std::env::set_var("STORAGE_DIRECTORY", &root);
+
+    startup::initialize().unwrap();
+    assert!(root.is_dir());
+    // The probe file is cleaned up; the instance file is all that is left
+    let entries: Vec<_> = std::fs::read_dir(&root).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(entries, vec![INSTANCE_FILE]);
+    assert!(MetadataService::new(SYSTEM_USER).unwrap().bucket_exists(HEALTH_BUCKET).unwrap());
+    std::env::set_var("STORAGE_DIRECTORY", common::isolate().join("storage"));
+}
+
+/// Initialize on a fresh `root` so the database has an instance id; returns it.
+fn instance_id(root: &std::path::Path) -> String {
+    std::env::set_var("STORAGE_DIRECTORY", root);
+    startup::initialize().unwrap();
+    let id = SQLiteMetadataStore::new().get_meta_info(INSTANCE_ID).unwrap().unwrap();
+    assert_eq!(std::fs::read_to_string(root.join(INSTANCE_FILE)).unwrap().trim(), id);
+    id
+}
+
+#[test]
+fn test_storage_of_another_deployment_refuses_to_start_until_adopted() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let id = instance_id(&scratch("identity-fresh").join("storage"));
+    // Starting again on the same storage is fine
+    startup::initialize().unwrap();
+
+    let foreign = scratch("identity-foreign").join("storage");
+    std::fs::create_dir_all(&foreign).unwrap();
+    std::fs::write(foreign.join(INSTANCE_FILE), "0123456789abcdef\n").unwrap();
+    std::env::set_var("STORAGE_DIRECTORY", &foreign);
+    let err = startup::initialize().unwrap_err();
+    assert_eq!(err.step, "instance identity");
+    let message = err.to_string();
+    assert!(message.contains(&id) && message.contains("0123456789abcdef") && message.contains("--force-adopt"), "{}", message);
+
+    startup::initialize_with(true).unwrap();
+    assert_eq!(std::fs::read_to_string(foreign.join(INSTANCE_FILE)).unwrap().trim(), id);
+    let adoption = change_log::list(None, usize::MAX).unwrap().into_iter().rev()
+        .find(|change| change.kind == "instance").unwrap();
+    assert_eq!(adoption.actor, change_log::STARTUP_ACTOR);
+    assert_eq!(adoption.before.unwrap()["storage"][foreign.display().to_string()], "0123456789abcdef");
+    assert_eq!(adoption.after.unwrap()["instance_id"], id);
+    startup::initialize().unwrap();
+    std::env::set_var("STORAGE_DIRECTORY", common::isolate().join("storage"));
+}
+
+#[test]
+fn test_storage_with_data_but_no_instance_file_refuses_to_start() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    instance_id(&scratch("identity-first").join("storage"));
+
+    let root = scratch("identity-unmarked").join("storage");
+    std::fs::create_dir_all(root.join("someone")).unwrap();
+    std::env::set_var("STORAGE_DIRECTORY", &root);
+    let err = startup::initialize().unwrap_err();
+    assert_eq!(err.step, "instance identity");
+    assert!(!root.join(INSTANCE_FILE).exists());
+    std::env::set_var("STORAGE_DIRECTORY", common::isolate().join("storage"));
+}
+
+#[test]
+fn test_empty_database_refuses_storage_that_holds_data() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let root = scratch("identity-populated").join("storage");
+    // An empty database without an instance id, as when DB_FILE points at a new file
+    let conn = rusqlite::Connection::open(sqlite_store::initialize().unwrap()).unwrap();
+    conn.execute("DELETE FROM meta_info WHERE name = ?1", [INSTANCE_ID]).unwrap();
+    conn.execute("DELETE FROM objects", []).unwrap();
+    assert!(!SQLiteMetadataStore::new().has_objects().unwrap());
+
+    std::fs::create_dir_all(root.join("someone")).unwrap();
+    std::fs::write(root.join("someone").join("bucket.bin"), b"segment data").unwrap();
+    std::env::set_var("STORAGE_DIRECTORY", &root);
+    let err = startup::initialize().unwrap_err();
+    assert_eq!(err.step, "instance identity");
+    assert!(err.to_string().contains("holds data") && err.to_string().contains("--force-adopt"), "{}", err);
+    assert!(!root.join(INSTANCE_FILE).exists());
+    assert_eq!(SQLiteMetadataStore::new().get_meta_info(INSTANCE_ID).unwrap(), None);
+
+    startup::initialize_with(true).unwrap();
+    let id = SQLiteMetadataStore::new().get_meta_info(INSTANCE_ID).unwrap().unwrap();
+    assert_eq!(std::fs::read_to_string(root.join(INSTANCE_FILE)).unwrap().trim(), id);
+    std::env::set_var("STORAGE_DIRECTORY", common::isolate().join("storage"));
+}
+
+#[test]
+fn test_startup_starts_writes_past_the_extents_of_short_segment_files() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let root = scratch("offsets").join("storage");
+    instance_id(&root);
+    let store = LocalXFSBinaryStore::new();
+    let db = MetadataService::new("offsets_user").unwrap();
+    db.create_bucket("short").unwrap();
+    let (offset, _) = store.write("offsets_user", "short", &[7; 1024]).unwrap();
+    // Metadata references more of the file than made it to disk
+    db.write_metadata("short", "cut", &encode_extents(&[(offset, 8192)]).unwrap()).unwrap();
+
+    startup::initialize().unwrap();
+    // The file is left short for fsck to report
+    assert_eq!(store.segments("offsets_user", "short").unwrap(), vec![(offset, 1024)]);
+    let (next, _) = store.write("offsets_user", "short", b"after").unwrap();
+    assert_eq!(next, offset + 8192);
+    std::env::set_var("STORAGE_DIRECTORY", common::isolate().join("storage"));
+}
@@ -0,0 +1,136 @@
+// Flat and hashed storage layouts (STORAGE_LAYOUT) and `warp_drive migrate-layout`. The
+// layout is read per call, so the tests serialize on ENV_LOCK and, as a server restart
+// would, run startup initialization after every change of STORAGE_LAYOUT.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, App, http::StatusCode};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+use warp_drive::api::{get, put};
+use warp_drive::startup;
+use warp_drive::storage::layout::{self, hash_prefix, StorageLayout, UserLayout};
+use warp_drive::storage::local_store::LocalXFSBinaryStore;
+
+mod common;
+use common::{payload_of, unique};
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn set_layout(layout: &str) {
+    common::isolate();
+    // Another process's layout changes are seen on the next lookup
+    std::env::set_var("CONFIG_REVALIDATE_INTERVAL_MS", "0");
+    std::env::set_var("STORAGE_LAYOUT", layout);
+    startup::initialize().unwrap();
+}
+
+fn storage() -> PathBuf {
+    common::isolate().join("storage")
+}
+
+fn native(req: test::TestRequest, uri: &str, user: &str, bucket: &str) -> test::TestRequest {
+    req.uri(uri).insert_header(("user", user.to_string())).insert_header(("bucket", bucket.to_string()))
+}
+
+macro_rules! put_object {
+    ($app:expr, $user:expr, $bucket:expr, $key:expr) => {{
+        let req = native(test::TestRequest::post(), &format!("/put/{}", $key), $user, $bucket)
+            .set_payload(payload_of(format!("data of {}", $key).as_bytes()));
+        assert_eq!(test::call_service($app, req.to_request()).await.status(), StatusCode::OK);
+    }};
+}
+
+macro_rules! assert_readable {
+    ($app:expr, $user:expr, $bucket:expr, $key:expr) => {{
+        let resp = test::call_service($app, native(test::TestRequest::get(), &format!("/get/{}", $key), $user, $bucket).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{}/{}", $bucket, $key);
+        let body = test::read_body(resp).await;
+        let expected = format!("data of {}", $key);
+        assert!(body.windows(expected.len()).any(|w| w == expected.as_bytes()), "{}/{}", $bucket, $key);
+    }};
+}
+
+#[actix_web::test]
+async fn test_hashed_layout_fans_out_bucket_files() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    set_layout("hashed");
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let user = unique("fanout");
+    let buckets: Vec<String> = (0..20).map(|i| format!("customer-{}", i)).collect();
+    for bucket in &buckets {
+        put_object!(&app, &user, bucket, "invoice");
+    }
+    for bucket in &buckets {
+        assert_readable!(&app, &user, bucket, "invoice");
+        let hashed = storage().join(&user).join(hash_prefix(bucket)).join(format!("{}.bin", bucket));
+        assert!(hashed.is_file(), "{} missing", hashed.display());
+    }
+    // Only the hashed subdirectories are directly under the user
+    let flat_files = std::fs::read_dir(storage().join(&user)).unwrap()
+        .filter(|e| e.as_ref().unwrap().file_type().unwrap().is_file())
+        .count();
+    assert_eq!(flat_files, 0);
+    set_layout("flat");
+}
+
+#[actix_web::test]
+async fn test_migrate_flat_user_to_hashed_layout() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    set_layout("flat");
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let user = unique("tenant");
+    let buckets = ["alpha", "beta", "gamma"];
+    for bucket in buckets {
+        for key in ["a", "b", "c"] {
+            put_object!(&app, &user, bucket, key);
+        }
+        assert!(storage().join(&user).join(format!("{}.bin", bucket)).is_file());
+    }
+    std::fs::write(storage().join(&user).join("stray.bin"), b"not a bucket").unwrap();
+
+    // Switching the setting keeps existing users where they are
+    set_layout("hashed");
+    assert_eq!(layout::user_layout(&user), UserLayout { layout: StorageLayout::Flat, target: None });
+    assert_readable!(&app, &user, "beta", "b");
+
+    // Half-way state of an interrupted migration: one bucket moved, reads find both
+    layout::set_user_layout(&user, UserLayout { layout: StorageLayout::Flat, target: Some(StorageLayout::Hashed) }).unwrap();
+    LocalXFSBinaryStore::with_directory(storage())
+        .move_bucket_files(&user, "alpha", StorageLayout::Flat, StorageLayout::Hashed)
+        .unwrap();
+    for bucket in buckets {
+        assert_readable!(&app, &user, bucket, "a");
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_warp_drive"))
+        .args(["migrate-layout", "--user", &user])
+        .current_dir(common::isolate())
+        .output()
+        .unwrap();
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .unwrap_or_else(|e| panic!("stdout is not JSON ({}): {}", e, String::from_utf8_lossy(&output.stderr)));
+    assert_eq!(output.status.code(), Some(0), "{}", report);
+    assert_eq!(report["from"], "flat");
+    assert_eq!(report["to"], "hashed");
+    assert_eq!(report["buckets_moved"], 2);
+    assert_eq!(report["buckets_verified"], 3);
+    assert_eq!(report["problems"], serde_json::json!([]));
+    let unmoved = report["unmoved"].as_array().unwrap();
+    assert_eq!(unmoved.len(), 1, "{}", report);
+    assert!(unmoved[0].as_str().unwrap().ends_with("stray.bin"));
+
+    // This process picks up the finished migration through the configuration generation
+    assert_eq!(layout::user_layout(&user), UserLayout { layout: StorageLayout::Hashed, target: None });
+    for bucket in buckets {
+        assert!(!storage().join(&user).join(format!("{}.bin", bucket)).exists());
+        assert!(storage().join(&user).join(hash_prefix(bucket)).join(format!("{}.bin", bucket)).is_file());
+        for key in ["a", "b", "c"] {
+            assert_readable!(&app, &user, bucket, key);
+        }
+    }
+    // New writes go to the hashed layout
+    put_object!(&app, &user, "beta", "d");
+    assert_readable!(&app, &user, "beta", "d");
+    set_layout("flat");
+}
@@ -0,0 +1,203 @@
+// STRICT_BUCKETS is read from the environment on every request, so these tests live in their
+// own binary and serialize on ENV_LOCK instead of racing the other integration tests.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, web, App, http::{Method, StatusCode}};
+use std::sync::Mutex;
+use warp_drive::api::{put, get, delete, create_bucket, head_bucket, delete_bucket};
+use warp_drive::metadata::{Metadata, MetadataStorage};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::s3::handlers::s3_head_bucket_handler;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{AMZ_DATE, authorization, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "strict-test-access";
+const SECRET_KEY: &str = "strict-test-secret";
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+#[actix_web::test]
+async fn test_strict_mode_rejects_unknown_bucket() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    std::env::set_var("STRICT_BUCKETS", "true");
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let bucket = "prod-bakcups".to_string();
+
+    let req = test::TestRequest::post()
+        .uri("/put/strict_key")
+        .insert_header(("user", "strict_user"))
+        .insert_header(("bucket", bucket.as_str()))
+        .set_payload(payload_of(b"strict"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "NoSuchBucket");
+    assert_eq!(body["bucket"], bucket.as_str());
+    assert!(!MetadataService::new("strict_user").unwrap().bucket_exists(&bucket).unwrap());
+
+    // Reads of unknown buckets keep the usual not-found
+    let req = test::TestRequest::get()
+        .uri("/get/strict_key")
+        .insert_header(("user", "strict_user"))
+        .insert_header(("bucket", bucket.as_str()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    std::env::remove_var("STRICT_BUCKETS");
+}
+
+#[actix_web::test]
+async fn test_permissive_mode_creates_bucket_on_first_write() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    std::env::remove_var("STRICT_BUCKETS");
+    let app = test::init_service(App::new().service(put)).await;
+    let bucket = "implicit".to_string();
+
+    let req = test::TestRequest::post()
+        .uri("/put/implicit_key")
+        .insert_header(("user", "strict_user"))
+        .insert_header(("bucket", bucket.as_str()))
+        .set_payload(payload_of(b"implicit"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(MetadataService::new("strict_user").unwrap().bucket_exists(&bucket).unwrap());
+}
+
+#[actix_web::test]
+async fn test_strict_mode_sees_bucket_lifecycle() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    std::env::set_var("STRICT_BUCKETS", "true");
+    let app = test::init_service(
+        App::new().service(put).service(delete).service(create_bucket).service(delete_bucket)
+    ).await;
+    let bucket = "lifecycle".to_string();
+    let put_req = || test::TestRequest::post()
+        .uri("/put/lifecycle_key")
+        .insert_header(("user", "strict_user"))
+        .insert_header(("bucket", bucket.as_str()))
+        .set_payload(payload_of(b"lifecycle"))
+        .to_request();
+
+    let resp = test::call_service(&app, put_req()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/bucket/{}", bucket))
+        .insert_header(("user", "strict_user"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let resp = test::call_service(&app, put_req()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Non-empty buckets cannot be deleted
+    let delete_bucket_req = || test::TestRequest::delete()
+        .uri(&format!("/bucket/{}", bucket))
+        .insert_header(("user", "strict_user"))
+        .to_request();
+    let resp = test::call_service(&app, delete_bucket_req()).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let req = test::TestRequest::delete()
+        .uri("/delete/lifecycle_key")
+        .insert_header(("user", "strict_user"))
+        .insert_header(("bucket", bucket.as_str()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = test::call_service(&app, delete_bucket_req()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // The cached entry is dropped with the bucket
+    let resp = test::call_service(&app, put_req()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    std::env::remove_var("STRICT_BUCKETS");
+}
+
+#[actix_web::test]
+async fn test_head_bucket_reports_existence_and_stats() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    std::env::remove_var("STRICT_BUCKETS");
+    let app = test::init_service(
+        App::new()
+            .service(put)
+            .service(head_bucket)
+            .route("/s3/{bucket}", web::head().to(s3_head_bucket_handler))
+    ).await;
+    let native_head = |user: &str, bucket: &str| test::TestRequest::default()
+        .method(Method::HEAD)
+        .uri(&format!("/bucket/{}", bucket))
+        .insert_header(("user", user.to_string()))
+        .to_request();
+    let s3_head = |bucket: &str| {
+        let path = format!("/s3/{}", bucket);
+        test::TestRequest::default()
+            .method(Method::HEAD)
+            .uri(&path)
+            .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, "HEAD", &path, UNSIGNED_PAYLOAD)))
+            .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+            .insert_header(("x-amz-date", AMZ_DATE))
+            .to_request()
+    };
+    let header = |resp: &actix_web::dev::ServiceResponse, name: &str| {
+        resp.headers().get(name).map(|v| v.to_str().unwrap().to_string())
+    };
+
+    // Existing bucket with two objects, reported to its owner on both APIs
+    let bucket = unique("head");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    for (key, data) in [("a", &b"abc"[..]), ("b", &b"defgh"[..])] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/put/{}", key))
+            .insert_header(("user", "admin"))
+            .insert_header(("bucket", bucket.as_str()))
+            .set_payload(payload_of(data))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+    for resp in [test::call_service(&app, native_head("admin", &bucket)).await, test::call_service(&app, s3_head(&bucket)).await] {
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(header(&resp, "x-warp-object-count").as_deref(), Some("2"));
+        assert_eq!(header(&resp, "x-warp-bytes").as_deref(), Some("8"));
+    }
+
+    // Missing bucket
+    let missing = unique("missing");
+    assert_eq!(test::call_service(&app, native_head("admin", &missing)).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(test::call_service(&app, s3_head(&missing)).await.status(), StatusCode::NOT_FOUND);
+
+    // A bucket of the same name belonging to another user does not count
+    let theirs = unique("theirs");
+    MetadataService::new("strict_user").unwrap().create_bucket(&theirs).unwrap();
+    assert_eq!(test::call_service(&app, native_head("strict_user", &theirs)).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, native_head("admin", &theirs)).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(test::call_service(&app, s3_head(&theirs)).await.status(), StatusCode::NOT_FOUND);
+
+    // Objects in a bucket that was never registered make it exist, except in strict mode
+    let unregistered = unique("unregistered");
+    SQLiteMetadataStore::new().put_metadata("admin", &unregistered, "legacy", &Metadata::from_offset_size_list(vec![(0, 4)])).unwrap();
+    let resp = test::call_service(&app, s3_head(&unregistered)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(header(&resp, "x-warp-bytes").as_deref(), Some("4"));
+    assert_eq!(test::call_service(&app, native_head("admin", &unregistered)).await.status(), StatusCode::OK);
+    std::env::set_var("STRICT_BUCKETS", "true");
+    assert_eq!(test::call_service(&app, native_head("admin", &unregistered)).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(test::call_service(&app, s3_head(&unregistered)).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(test::call_service(&app, native_head("admin", &bucket)).await.status(), StatusCode::OK);
+
+    std::env::remove_var("STRICT_BUCKETS");
+}
@@ -0,0 +1,157 @@
+// STORAGE_BACKEND is read from the environment on every request, so the tiered backend is
+// exercised in its own binary where every test runs against it.
+
+use actix_web::{test, App, http::StatusCode};
+use std::path::PathBuf;
+use std::sync::Once;
+use warp_drive::api::{put, get};
+use warp_drive::admin::tier;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::payload;
+
+static SETUP: Once = Once::new();
+
+fn tier_root() -> PathBuf {
+    common::isolate().join("tiers")
+}
+
+fn setup() {
+    SETUP.call_once(|| {
+        std::env::set_var("STORAGE_BACKEND", "tiered");
+        std::env::set_var("HOT_DIRECTORY", tier_root().join("hot"));
+        std::env::set_var("COLD_DIRECTORY", tier_root().join("cold"));
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", "tier-test-secret");
+    });
+}
+
+fn bucket_file(tier_dir: &str, user: &str, bucket: &str) -> PathBuf {
+    tier_root().join(tier_dir).join(user).join(format!("{}.bin", bucket))
+}
+
+fn stored_files(body: &[u8]) -> Vec<Vec<u8>> {
+    let list = root_as_file_data_list(body).unwrap();
+    list.files().unwrap().iter().map(|f| f.data().unwrap().bytes().to_vec()).collect()
+}
+
+fn put_request(user: &str, bucket: &str, key: &str, files: &[&[u8]]) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri(&format!("/put/{}", key))
+        .insert_header(("user", user))
+        .insert_header(("bucket", bucket))
+        .set_payload(payload(files))
+}
+
+fn get_request(user: &str, bucket: &str, key: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(&format!("/get/{}", key))
+        .insert_header(("user", user))
+        .insert_header(("bucket", bucket))
+}
+
+fn tier_request(user: &str, bucket: &str, query: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri(&format!("/admin/tier/{}/{}?{}", user, bucket, query))
+        .insert_header(("Authorization", "Bearer tier-test-secret"))
+}
+
+/// Report of the migration job a tier request queued
+async fn tier_report(accepted: serde_json::Value) -> serde_json::Value {
+    let job = common::finished_job(&accepted).await;
+    assert_eq!(job["state"], "done", "{}", job);
+    job["result"].clone()
+}
+
+#[actix_web::test]
+async fn test_migrate_bucket_to_cold_and_back() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(tier)).await;
+    let (user, bucket) = ("tier_user", &format!("roundtrip-{}", std::process::id()));
+
+    let objects: [(&str, &[&[u8]]); 2] = [
+        ("multi", &[b"first chunk", b"second chunk"]),
+        ("single", &[b"only chunk"]),
+    ];
+    for (key, files) in objects {
+        let resp = test::call_service(&app, put_request(user, bucket, key, files).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    assert!(bucket_file("hot", user, bucket).exists());
+
+    let report =
+        tier_report(test::read_body_json(test::call_service(&app, tier_request(user, bucket, "to=cold").to_request()).await).await).await;
+    assert_eq!(report["complete"], true);
+    assert_eq!(report["keys_migrated"], 2);
+    assert_eq!(report["source_removed"], true);
+    assert!(!bucket_file("hot", user, bucket).exists());
+    assert!(bucket_file("cold", user, bucket).exists());
+
+    for (key, files) in objects {
+        let body = test::read_body(test::call_service(&app, get_request(user, bucket, key).to_request()).await).await;
+        assert_eq!(stored_files(&body), files.iter().map(|f| f.to_vec()).collect::<Vec<_>>());
+    }
+
+    // New writes follow the bucket to its tier
+    let resp = test::call_service(&app, put_request(user, bucket, "late", &[b"written cold"]).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!bucket_file("hot", user, bucket).exists());
+
+    // Migrating to the current tier is a no-op
+    let report =
+        tier_report(test::read_body_json(test::call_service(&app, tier_request(user, bucket, "to=cold").to_request()).await).await).await;
+    assert_eq!(report["complete"], true);
+    assert_eq!(report["keys_migrated"], 0);
+
+    let report =
+        tier_report(test::read_body_json(test::call_service(&app, tier_request(user, bucket, "to=hot").to_request()).await).await).await;
+    assert_eq!(report["complete"], true);
+    assert_eq!(report["keys_migrated"], 3);
+    assert!(!bucket_file("cold", user, bucket).exists());
+
+    let body = test::read_body(test::call_service(&app, get_request(user, bucket, "late").to_request()).await).await;
+    assert_eq!(stored_files(&body), vec![b"written cold".to_vec()]);
+    let body = test::read_body(test::call_service(&app, get_request(user, bucket, "multi").to_request()).await).await;
+    assert_eq!(stored_files(&body), vec![b"first chunk".to_vec(), b"second chunk".to_vec()]);
+}
+
+#[actix_web::test]
+async fn test_migration_resumes_in_batches() {
+    setup();
+    let app = test::init_service(App::new().service(put).service(get).service(tier)).await;
+    let (user, bucket) = ("tier_user", &format!("batched-{}", std::process::id()));
+
+    for key in ["a", "b", "c"] {
+        let resp = test::call_service(&app, put_request(user, bucket, key, &[key.as_bytes()]).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let report =
+        tier_report(test::read_body_json(test::call_service(&app, tier_request(user, bucket, "to=cold&max_keys=1").to_request()).await).await).await;
+    assert_eq!(report["complete"], false);
+    assert_eq!(report["keys_migrated"], 1);
+    assert_eq!(report["keys_remaining"], 2);
+    assert!(bucket_file("hot", user, bucket).exists());
+
+    // Reads span both tiers mid-migration
+    for key in ["a", "b", "c"] {
+        let body = test::read_body(test::call_service(&app, get_request(user, bucket, key).to_request()).await).await;
+        assert_eq!(stored_files(&body), vec![key.as_bytes().to_vec()]);
+    }
+
+    let resp = test::call_service(&app, tier_request(user, bucket, "to=hot").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = test::call_service(&app, tier_request(user, bucket, "to=warm").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let report =
+        tier_report(test::read_body_json(test::call_service(&app, tier_request(user, bucket, "to=cold").to_request()).await).await).await;
+    assert_eq!(report["complete"], true);
+    assert_eq!(report["keys_migrated"], 2);
+    assert!(!bucket_file("hot", user, bucket).exists());
+
+    for key in ["a", "b", "c"] {
+        let body = test::read_body(test::call_service(&app, get_request(user, bucket, key).to_request()).await).await;
+        assert_eq!(stored_files(&body), vec![key.as_bytes().to_vec()]);
+    }
+}
@@ -0,0 +1,116 @@
+// Native multi-key transactions: POST /txn applies puts, deletes and renames all-or-nothing.
+
+use actix_web::{test, App, http::StatusCode};
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use serde_json::{json, Value};
+use warp_drive::api::{get, txn};
+use warp_drive::metadata::sqlite_store::SQLiteMetadataStore;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::unique;
+
+const USER: &str = "txn_user";
+
+fn txn_request(bucket: &str, ops: Value) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/txn")
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket.to_string()))
+        .set_payload(json!({ "ops": ops }).to_string())
+}
+
+fn put_op(key: &str) -> Value {
+    json!({ "op": "put", "key": key, "data": B64.encode(format!("data of {}", key)) })
+}
+
+macro_rules! apply {
+    ($app:expr, $bucket:expr, $ops:expr, $status:expr) => {{
+        let resp = test::call_service($app, txn_request($bucket, $ops).to_request()).await;
+        assert_eq!(resp.status(), $status);
+        let body: Value = test::read_body_json(resp).await;
+        body
+    }};
+}
+
+macro_rules! get_body {
+    ($app:expr, $bucket:expr, $key:expr) => {{
+        let req = test::TestRequest::get().uri(&format!("/get/{}", $key))
+            .insert_header(("user", USER))
+            .insert_header(("bucket", $bucket.to_string()));
+        let resp = test::call_service($app, req.to_request()).await;
+        (resp.status(), test::read_body(resp).await)
+    }};
+}
+
+fn contains(body: &[u8], text: &str) -> bool {
+    body.windows(text.len()).any(|w| w == text.as_bytes())
+}
+
+#[actix_web::test]
+async fn test_conflicting_last_op_leaves_no_trace() {
+    common::isolate();
+    let app = test::init_service(App::new().service(txn).service(get)).await;
+    let bucket = unique("txn");
+    apply!(&app, &bucket, json!([put_op("existing"), put_op("doomed"), put_op("old")]), StatusCode::OK);
+
+    let ops = json!([
+        put_op("fresh"),
+        { "op": "delete", "key": "doomed" },
+        { "op": "rename", "key": "old", "to": "new" },
+        put_op("existing"),
+    ]);
+    let body = apply!(&app, &bucket, ops, StatusCode::CONFLICT);
+    assert_eq!(body["committed"], false);
+    let statuses: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["status"].as_str().unwrap()).collect();
+    assert_eq!(statuses, ["aborted", "aborted", "aborted", "conflict"]);
+
+    let db = MetadataService::new(USER).unwrap();
+    assert!(!db.check_key(&bucket, "fresh").unwrap());
+    assert!(!db.check_key(&bucket, "new").unwrap());
+    for key in ["existing", "doomed", "old"] {
+        let (status, data) = get_body!(&app, &bucket, key);
+        assert_eq!(status, StatusCode::OK, "{}", key);
+        assert!(contains(&data, &format!("data of {}", key)), "{}", key);
+    }
+    // The chunk written for the rolled-back put is queued for deletion
+    assert!(SQLiteMetadataStore::new().deletion_pending(USER, &bucket, "fresh").unwrap());
+}
+
+#[actix_web::test]
+async fn test_mixed_txn_is_fully_visible() {
+    common::isolate();
+    let app = test::init_service(App::new().service(txn).service(get)).await;
+    let bucket = unique("txn");
+    apply!(&app, &bucket, json!([put_op("doomed"), put_op("old")]), StatusCode::OK);
+
+    let ops = json!([
+        put_op("fresh"),
+        { "op": "delete", "key": "doomed" },
+        { "op": "rename", "key": "old", "to": "new" },
+    ]);
+    let body = apply!(&app, &bucket, ops, StatusCode::OK);
+    assert_eq!(body["committed"], true);
+    assert!(body["results"].as_array().unwrap().iter().all(|r| r["status"] == "applied"), "{}", body);
+    assert_eq!(body["results"][2]["to"], "new");
+
+    let (status, data) = get_body!(&app, &bucket, "fresh");
+    assert_eq!(status, StatusCode::OK);
+    assert!(contains(&data, "data of fresh"));
+    let (status, data) = get_body!(&app, &bucket, "new");
+    assert_eq!(status, StatusCode::OK);
+    assert!(contains(&data, "data of old"));
+    assert_eq!(get_body!(&app, &bucket, "doomed").0, StatusCode::NOT_FOUND);
+    assert_eq!(get_body!(&app, &bucket, "old").0, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_limits_are_enforced_before_anything_is_written() {
+    common::isolate();
+    let app = test::init_service(App::new().service(txn).service(get)).await;
+    let bucket = unique("txn");
+    let ops: Vec<Value> = (0..17).map(|i| put_op(&format!("key-{}", i))).collect();
+    let resp = test::call_service(&app, txn_request(&bucket, Value::Array(ops)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(!MetadataService::new(USER).unwrap().check_key(&bucket, "key-0").unwrap());
+}
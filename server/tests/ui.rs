@@ -0,0 +1,62 @@
+// The operator UI and the /list endpoint it reads. UI_ENABLED is read per request, so the
+// UI test holds ENV_LOCK while toggling it.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, App, http::StatusCode};
+use std::sync::Mutex;
+use warp_drive::api::{put, list};
+use warp_drive::ui::ui;
+
+mod common;
+use common::payload_of;
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+#[actix_web::test]
+async fn test_ui_served_only_when_enabled() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    common::isolate();
+    let app = test::init_service(App::new().service(ui)).await;
+
+    std::env::remove_var("UI_ENABLED");
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/ui").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    std::env::set_var("UI_ENABLED", "true");
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/ui").to_request()).await;
+    std::env::remove_var("UI_ENABLED");
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("content-type").unwrap().to_str().unwrap().starts_with("text/html"));
+    assert!(resp.headers().contains_key("content-security-policy"));
+    let body = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(body.contains("/list?"), "the page reads /list");
+}
+
+#[actix_web::test]
+async fn test_list_pages_through_bucket() {
+    common::isolate();
+    let app = test::init_service(App::new().service(put).service(list)).await;
+    let native = |req: test::TestRequest| req.insert_header(("user", "ui_user")).insert_header(("bucket", "ui-bucket"));
+    for key in ["a", "b", "c"] {
+        let req = native(test::TestRequest::post().uri(&format!("/put/{}", key))).set_payload(payload_of(b"12345"));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+
+    let resp = test::call_service(&app, native(test::TestRequest::get().uri("/list?limit=2")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let keys: Vec<_> = body["objects"].as_array().unwrap().iter().map(|o| o["key"].as_str().unwrap().to_string()).collect();
+    assert_eq!(keys, vec!["a", "b"]);
+    assert_eq!(body["objects"][0]["size"], 5);
+    assert_eq!(body["next_after"], "b");
+    assert_eq!(body["usage"], serde_json::json!({ "objects": 3, "bytes": 15 }));
+
+    let resp = test::call_service(&app, native(test::TestRequest::get().uri("/list?limit=2&after=b")).to_request()).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["objects"].as_array().unwrap().len(), 1);
+    assert_eq!(body["objects"][0]["key"], "c");
+    assert!(body["next_after"].is_null());
+
+    let resp = test::call_service(&app, native(test::TestRequest::get().uri("/list?limit=0")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,184 @@
+// Resumable native uploads through the full app: parts sent in any order, listed, and
+// completed into one object in part order; aborted and expired sessions drop their parts.
+// The TTL sweep moves the clock past every session of this binary, so the tests serialize
+// on SESSION_LOCK.
+
+use actix_web::{test, http::Method, http::StatusCode};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use warp_drive::server::app;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::upload_sessions;
+use warp_drive::util::clock;
+use warp_drive::util::flatbuffer_store_generated::store::root_as_file_data_list;
+
+mod common;
+use common::{payload_of, unique};
+
+const USER: &str = "upload_session_user";
+
+static SESSION_LOCK: Mutex<()> = Mutex::const_new(());
+
+fn native(method: Method, uri: &str, bucket: &str) -> test::TestRequest {
+    test::TestRequest::default().method(method).uri(uri)
+        .insert_header(("user", USER))
+        .insert_header(("bucket", bucket))
+}
+
+macro_rules! call {
+    ($app:expr, $req:expr) => {
+        test::call_service($app, $req.to_request()).await
+    };
+}
+
+macro_rules! open {
+    ($app:expr, $key:expr, $bucket:expr) => {{
+        let resp = call!($app, native(Method::POST, &format!("/upload/{}", $key), $bucket));
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(resp).await;
+        body["session"].as_str().unwrap().to_string()
+    }};
+}
+
+#[actix_web::test]
+async fn test_parts_out_of_order_complete_in_part_order() {
+    common::isolate();
+    let _sessions = SESSION_LOCK.lock().await;
+    let app = test::init_service(app()).await;
+    let bucket = unique("upload-sessions");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+
+    let session = open!(&app, "dir/big.bin", &bucket);
+    assert!(upload_sessions::is_session_id(&session), "{}", session);
+    let parts: [&[u8]; 3] = [b"first part ", b"second part, longer ", b"third"];
+    for n in [3, 1, 2] {
+        let uri = format!("/upload/dir/big.bin/{}/{}", session, n);
+        let resp = call!(&app, native(Method::PUT, &uri, &bucket).set_payload(parts[n - 1]));
+        assert_eq!(resp.status(), StatusCode::OK);
+        let part: Value = test::read_body_json(resp).await;
+        assert_eq!(part["part_number"], n);
+        assert_eq!(part["size"], parts[n - 1].len());
+    }
+    // Sending a part again replaces it
+    let uri = format!("/upload/dir/big.bin/{}/2", session);
+    assert_eq!(call!(&app, native(Method::PUT, &uri, &bucket).set_payload(&b"second part"[..])).status(), StatusCode::OK);
+    let parts: [&[u8]; 3] = [b"first part ", b"second part", b"third"];
+
+    let resp = call!(&app, native(Method::GET, &format!("/upload/dir/big.bin/{}", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::OK);
+    let listed: Value = test::read_body_json(resp).await;
+    assert_eq!(listed["session"], session.as_str());
+    let listed = listed["parts"].as_array().unwrap();
+    assert_eq!(listed.len(), 3);
+    for (i, part) in listed.iter().enumerate() {
+        assert_eq!(part["part_number"], i + 1);
+        assert_eq!(part["size"], parts[i].len());
+        assert_eq!(part["sha256"], hex::encode(Sha256::digest(parts[i])));
+    }
+
+    // Not visible before it is completed
+    assert_eq!(call!(&app, native(Method::GET, "/get/dir/big.bin", &bucket)).status(), StatusCode::NOT_FOUND);
+    let resp = call!(&app, native(Method::POST, &format!("/upload/dir/big.bin/{}/complete", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::OK);
+    let completed: Value = test::read_body_json(resp).await;
+    assert_eq!(completed["parts"], 3);
+    assert_eq!(completed["size"], parts.concat().len());
+
+    let resp = call!(&app, native(Method::GET, "/get/dir/big.bin", &bucket));
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    let data: Vec<u8> = root_as_file_data_list(&body).unwrap().files().unwrap().iter()
+        .flat_map(|file| file.data().unwrap().bytes().to_vec())
+        .collect();
+    assert_eq!(data, parts.concat());
+
+    // The session is gone with its parts' rows
+    let resp = call!(&app, native(Method::GET, &format!("/upload/dir/big.bin/{}", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "NoSuchUpload");
+    assert!(MetadataService::new(USER).unwrap().list_multipart_parts(&session).unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn test_complete_respects_existing_key() {
+    common::isolate();
+    let _sessions = SESSION_LOCK.lock().await;
+    let app = test::init_service(app()).await;
+    let bucket = unique("upload-exists");
+    MetadataService::new(USER).unwrap().create_bucket(&bucket).unwrap();
+
+    let session = open!(&app, "taken", &bucket);
+    let uri = format!("/upload/taken/{}/1", session);
+    assert_eq!(call!(&app, native(Method::PUT, &uri, &bucket).set_payload(&b"late"[..])).status(), StatusCode::OK);
+
+    // Created meanwhile by a PUT: completing fails like a PUT would and keeps the session
+    let resp = call!(&app, native(Method::POST, "/put/taken", &bucket).set_payload(payload_of(b"first")));
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = call!(&app, native(Method::POST, &format!("/upload/taken/{}/complete", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "KeyExists");
+    let resp = call!(&app, native(Method::GET, &format!("/upload/taken/{}", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // A live key cannot get a new session either
+    assert_eq!(call!(&app, native(Method::POST, "/upload/taken", &bucket)).status(), StatusCode::CONFLICT);
+    // Nor is a session found under another key
+    let resp = call!(&app, native(Method::GET, &format!("/upload/other/{}", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_abort_drops_parts() {
+    common::isolate();
+    let _sessions = SESSION_LOCK.lock().await;
+    let app = test::init_service(app()).await;
+    let bucket = unique("upload-abort");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&bucket).unwrap();
+
+    let session = open!(&app, "dropped", &bucket);
+    for n in 1..=2 {
+        let uri = format!("/upload/dropped/{}/{}", session, n);
+        assert_eq!(call!(&app, native(Method::PUT, &uri, &bucket).set_payload(&b"part data"[..])).status(), StatusCode::OK);
+    }
+    let queued = db.pending_deletion_count_for_bucket(&bucket).unwrap();
+
+    let resp = call!(&app, native(Method::DELETE, &format!("/upload/dropped/{}", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["aborted_parts"], 2);
+    assert!(db.list_multipart_parts(&session).unwrap().is_empty());
+    assert!(db.get_multipart_upload(&session).unwrap().is_none());
+    assert_eq!(db.pending_deletion_count_for_bucket(&bucket).unwrap(), queued + 2);
+
+    let uri = format!("/upload/dropped/{}/3", session);
+    assert_eq!(call!(&app, native(Method::PUT, &uri, &bucket).set_payload(&b"late"[..])).status(), StatusCode::NOT_FOUND);
+    let resp = call!(&app, native(Method::POST, &format!("/upload/dropped/{}/complete", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(!db.check_key(&bucket, "dropped").unwrap());
+}
+
+#[actix_web::test]
+async fn test_sweep_aborts_expired_sessions() {
+    common::isolate();
+    let _sessions = SESSION_LOCK.lock().await;
+    let app = test::init_service(app()).await;
+    let bucket = unique("upload-ttl");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&bucket).unwrap();
+
+    let session = open!(&app, "stale", &bucket);
+    let uri = format!("/upload/stale/{}/1", session);
+    assert_eq!(call!(&app, native(Method::PUT, &uri, &bucket).set_payload(&b"part data"[..])).status(), StatusCode::OK);
+    assert_eq!(upload_sessions::sweep(upload_sessions::SWEEP_BATCH).unwrap(), 0);
+
+    clock::advance(chrono::Duration::seconds(upload_sessions::UploadSessionConfig::default().ttl_secs as i64 + 1));
+    assert!(upload_sessions::sweep(upload_sessions::SWEEP_BATCH).unwrap() >= 1);
+    assert!(db.get_multipart_upload(&session).unwrap().is_none());
+    assert!(db.list_multipart_parts(&session).unwrap().is_empty());
+    let resp = call!(&app, native(Method::GET, &format!("/upload/stale/{}", session), &bucket));
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,98 @@
+// Per-user health deep-check: a healthy bucket passes every check, an empty one is probed with
+// a `_health` object that is deleted again, and a bucket file restored read-only fails the
+// write check while its data still reads.
+
+use actix_web::{test, App, http::StatusCode};
+use std::os::unix::fs::PermissionsExt;
+use warp_drive::admin::user_health;
+use warp_drive::metadata::Metadata;
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::storage_service::{StorageMode, StorageService};
+use warp_drive::service::user_context::UserContext;
+
+mod common;
+use common::unique;
+
+const SECRET: &str = "user-health-secret";
+const USER: &str = "user_health_user";
+
+fn setup() {
+    common::isolate();
+    std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET);
+}
+
+fn health_request(uri: &str) -> test::TestRequest {
+    test::TestRequest::get().uri(uri).insert_header(("Authorization", format!("Bearer {}", SECRET)))
+}
+
+/// A bucket holding one object; returns its context
+fn fixture() -> UserContext {
+    let context = UserContext::with_bucket(USER.to_string(), unique("user-health"));
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&context.bucket).unwrap();
+    let data = vec![7u8; 4096];
+    let extents = StorageService::new().write_object(&context, &data, StorageMode::RAW).unwrap();
+    let mut metadata = Metadata::from_offset_size_list(extents);
+    metadata.size = data.len() as u64;
+    db.put_object_full(&context.bucket, "object", metadata).unwrap();
+    context
+}
+
+/// `(name, ok)` of each check in the report
+fn checks(report: &serde_json::Value) -> Vec<(String, bool)> {
+    report["checks"].as_array().unwrap().iter()
+        .map(|c| (c["check"].as_str().unwrap().to_string(), c["ok"].as_bool().unwrap()))
+        .collect()
+}
+
+#[actix_web::test]
+async fn test_healthy_and_empty_buckets_pass() {
+    setup();
+    let app = test::init_service(App::new().service(user_health)).await;
+    let context = fixture();
+
+    let resp = test::call_service(&app, health_request(&format!("/admin/health/user/{}?bucket={}", USER, context.bucket)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(report["healthy"], true);
+    let expected = vec![("metadata".to_string(), true), ("read".to_string(), true), ("write".to_string(), true)];
+    assert_eq!(checks(&report), expected);
+    assert!(report["checks"].as_array().unwrap().iter().all(|c| c["elapsed_ms"].is_u64()));
+
+    // An empty bucket is probed and left empty
+    let empty = unique("user-health-empty");
+    let db = MetadataService::new(USER).unwrap();
+    db.create_bucket(&empty).unwrap();
+    let resp = test::call_service(&app, health_request(&format!("/admin/health/user/{}?bucket={}", USER, empty)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report: serde_json::Value = test::read_body_json(resp).await;
+    let expected = vec![("metadata".to_string(), true), ("probe".to_string(), true), ("write".to_string(), true)];
+    assert_eq!(checks(&report), expected);
+    assert!(!db.check_key(&empty, "_health").unwrap());
+
+    let resp = test::call_service(&app, health_request(&format!("/admin/health/user/{}?bucket={}", USER, unique("missing"))).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, health_request(&format!("/admin/health/user/{}", USER)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("/admin/health/user/{}?bucket={}", USER, context.bucket)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_read_only_bucket_file_fails_write_check() {
+    setup();
+    let app = test::init_service(App::new().service(user_health)).await;
+    let context = fixture();
+    let path = StorageService::new().active_segment_file(&context).unwrap().unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o444)).unwrap();
+
+    let resp = test::call_service(&app, health_request(&format!("/admin/health/user/{}?bucket={}", USER, context.bucket)).to_request()).await;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let report: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(report["healthy"], false);
+    let expected = vec![("metadata".to_string(), true), ("read".to_string(), true), ("write".to_string(), false)];
+    assert_eq!(checks(&report), expected);
+    let error = report["checks"][2]["error"].as_str().unwrap();
+    assert!(error.contains("not writable"), "{}", error);
+}
@@ -0,0 +1,132 @@
+// Per-user concurrency limits. Limits are read from the environment per request, so tests
+// serialize on ENV_LOCK; slow request bodies keep native PUTs in flight while others arrive.
+// S3 requests are signed with the admin access key.
+#![allow(clippy::await_holding_lock)]
+
+use actix_web::{test, web, App, http::StatusCode};
+use actix_web::dev::{Payload, Service};
+use actix_web::error::PayloadError;
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+use warp_drive::api::{get, put};
+use warp_drive::metrics;
+use warp_drive::s3::handlers::{s3_get_object_handler, s3_put_object_handler};
+use warp_drive::service::metadata_service::MetadataService;
+use warp_drive::service::user_limits::{self, RequestKind, UserLimitConfig};
+
+mod common;
+use common::{AMZ_DATE, authorization, payload_of, unique, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "limits-test-access";
+const SECRET_KEY: &str = "limits-test-secret";
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+    });
+    std::env::remove_var("USER_MAX_INFLIGHT_READS");
+    std::env::remove_var("USER_MAX_INFLIGHT_WRITES");
+    std::env::set_var("USER_LIMIT_WAIT_MS", "20");
+}
+
+/// Request body that only arrives after `delay`, keeping the request in flight meanwhile
+fn slow_body(data: Vec<u8>, delay: Duration) -> Payload {
+    let body = stream::once(async move {
+        tokio::time::sleep(delay).await;
+        Ok::<_, PayloadError>(Bytes::from(data))
+    });
+    Payload::from(body.boxed_local())
+}
+
+#[actix_web::test]
+async fn test_excess_requests_get_429_per_user() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    std::env::set_var("USER_MAX_INFLIGHT_WRITES", "2");
+    let app = test::init_service(App::new().service(put).service(get)).await;
+    let bucket = unique("limits");
+
+    let slow_put = |user: &str, key: String| {
+        let req = test::TestRequest::post()
+            .uri(&format!("/put/{}", key))
+            .insert_header(("user", user))
+            .insert_header(("bucket", bucket.clone()))
+            .to_request();
+        let (req, _) = req.replace_payload(slow_body(payload_of(key.as_bytes()), Duration::from_millis(200)));
+        app.call(req)
+    };
+    let busy: Vec<_> = (0..5).map(|i| slow_put("limits_busy", format!("busy-{}", i))).collect();
+    let quiet = slow_put("limits_quiet", "quiet".to_string());
+    let (busy, quiet) = futures::join!(futures::future::join_all(busy), quiet);
+
+    let mut statuses: Vec<StatusCode> = Vec::new();
+    for resp in busy {
+        let resp = resp.unwrap();
+        statuses.push(resp.status());
+        if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+            assert_eq!(resp.headers().get("retry-after").unwrap(), "1");
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["error"], "SlowDown");
+        }
+    }
+    assert_eq!(statuses.iter().filter(|s| **s == StatusCode::OK).count(), 2, "{:?}", statuses);
+    assert_eq!(statuses.iter().filter(|s| **s == StatusCode::TOO_MANY_REQUESTS).count(), 3, "{:?}", statuses);
+    assert_eq!(quiet.unwrap().status(), StatusCode::OK, "another user must not be throttled");
+
+    // Permits are returned when the requests finish; reads have their own, unlimited, budget
+    let resp = test::call_service(&app, test::TestRequest::post().uri("/put/busy-after")
+        .insert_header(("user", "limits_busy")).insert_header(("bucket", bucket.clone()))
+        .set_payload(payload_of(b"after")).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/get/busy-after")
+        .insert_header(("user", "limits_busy")).insert_header(("bucket", bucket.clone())).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    setup();
+}
+
+#[actix_web::test]
+async fn test_s3_reads_slow_down_and_export_in_flight() {
+    let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    setup();
+    let app = test::init_service(
+        App::new()
+            .route("/s3/{bucket}/{key}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key}", web::get().to(s3_get_object_handler))
+    ).await;
+    let bucket = unique("limits-s3");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let path = format!("/s3/{}/object", bucket);
+    let signed = |req: test::TestRequest, method: &str| req.uri(&path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, &path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+        .to_request();
+    let resp = test::call_service(&app, signed(test::TestRequest::put().set_payload("data"), "PUT")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // A long-running read by the same user holds the only read permit
+    std::env::set_var("USER_MAX_INFLIGHT_READS", "1");
+    let held = user_limits::acquire("admin", RequestKind::Read, &UserLimitConfig::from_env()).await.unwrap();
+    let rendered = metrics::render();
+    assert!(rendered.contains("warpdrive_user_inflight_requests{user=\"admin\",kind=\"read\"} 1"), "{}", rendered);
+
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET")).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains("<Code>SlowDown</Code>"), "{}", xml);
+
+    drop(held);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    // The permit goes back once the request is dropped
+    drop(resp);
+    assert!(!metrics::render().contains("warpdrive_user_inflight_requests{user=\"admin\",kind=\"read\"}"));
+    setup();
+}
@@ -0,0 +1,97 @@
+// ListBuckets and virtual-host style addressing. S3_DOMAIN_SUFFIX is set once for this
+// binary; requests are signed with the admin access key over the path the client sends.
+
+use actix_web::{test, web, App, http::StatusCode, middleware::from_fn};
+use std::sync::Once;
+use warp_drive::s3::handlers::{
+    s3_list_buckets_handler, s3_list_objects_handler, s3_put_object_handler, s3_get_object_handler,
+};
+use warp_drive::s3::middleware::virtual_host_rewrite;
+use warp_drive::service::metadata_service::MetadataService;
+
+mod common;
+use common::{authorization, unique, AMZ_DATE, UNSIGNED_PAYLOAD};
+
+const ACCESS_KEY: &str = "vhost-test-access";
+const SECRET_KEY: &str = "vhost-test-secret";
+const DOMAIN: &str = "s3.test.local";
+
+static SETUP: Once = Once::new();
+
+fn setup() {
+    SETUP.call_once(|| {
+        common::isolate();
+        std::env::set_var("WARPDRIVE_ADMIN_ACCESS_KEY", ACCESS_KEY);
+        std::env::set_var("WARPDRIVE_ADMIN_SECRET_KEY", SECRET_KEY);
+        std::env::set_var("S3_DOMAIN_SUFFIX", DOMAIN);
+    });
+}
+
+fn signed(req: test::TestRequest, method: &str, path: &str) -> test::TestRequest {
+    req.uri(path)
+        .insert_header(("Authorization", authorization(ACCESS_KEY, SECRET_KEY, method, path, UNSIGNED_PAYLOAD)))
+        .insert_header(("x-amz-content-sha256", UNSIGNED_PAYLOAD))
+        .insert_header(("x-amz-date", AMZ_DATE))
+}
+
+#[actix_web::test]
+async fn test_list_buckets() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(virtual_host_rewrite))
+            .route("/s3/", web::get().to(s3_list_buckets_handler))
+            .route("/", web::get().to(s3_list_buckets_handler))
+    ).await;
+    let db = MetadataService::new("admin").unwrap();
+    let (first, second) = (unique("vhost-a"), unique("vhost-b"));
+    db.create_bucket(&first).unwrap();
+    db.create_bucket(&second).unwrap();
+
+    // Path style, and the bare domain of a virtual-host deployment
+    for (path, host) in [("/s3/", "localhost"), ("/", DOMAIN)] {
+        let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", path).insert_header(("host", host)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+        assert!(xml.contains("<ListAllMyBucketsResult"), "{}", xml);
+        assert!(xml.contains(&format!("<Name>{}</Name>", first)), "{}", xml);
+        assert!(xml.contains(&format!("<Name>{}</Name>", second)), "{}", xml);
+    }
+}
+
+#[actix_web::test]
+async fn test_virtual_host_request_reaches_bucket_and_key() {
+    setup();
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(virtual_host_rewrite))
+            .route("/s3/{bucket}", web::get().to(s3_list_objects_handler))
+            .route("/s3/{bucket}/{key:.*}", web::put().to(s3_put_object_handler))
+            .route("/s3/{bucket}/{key:.*}", web::get().to(s3_get_object_handler))
+    ).await;
+    let bucket = unique("vhost");
+    MetadataService::new("admin").unwrap().create_bucket(&bucket).unwrap();
+    let host = format!("{}.{}:9710", bucket, DOMAIN);
+
+    let resp = test::call_service(&app, signed(test::TestRequest::put(), "PUT", "/docs/readme.txt")
+        .insert_header(("host", host.as_str())).set_payload("hello").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Path style sees the object the virtual-host PUT stored
+    let path = format!("/s3/{}/docs/readme.txt", bucket);
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", &path).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(&test::read_body(resp).await[..], b"hello");
+
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", "/docs/readme.txt")
+        .insert_header(("host", host.as_str())).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(&test::read_body(resp).await[..], b"hello");
+
+    let resp = test::call_service(&app, signed(test::TestRequest::get(), "GET", "/")
+        .insert_header(("host", host.as_str())).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let xml = String::from_utf8_lossy(&test::read_body(resp).await).to_string();
+    assert!(xml.contains(&format!("<Name>{}</Name>", bucket)), "{}", xml);
+    assert!(xml.contains("<Key>docs/readme.txt</Key>"), "{}", xml);
+}